@@ -0,0 +1,557 @@
+// A module to implement the subcommands of the command-line tool
+
+// Written by Amin Alam in 2024
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use clap::{Parser, Subcommand};
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use crate::processing::checkpoint::{parse_flat_object, quote};
+
+/// The exit code of invalid arguments, missing files or unsupported formats
+const EXIT_USER_ERROR: u8 = 2;
+
+/// The exit code of files that cannot be parsed or written
+const EXIT_DATA_ERROR: u8 = 65;
+
+/// Inspects and converts tabular data files, writing to stdout by default
+#[derive(Parser)]
+#[command(name = "neurorust", version, about)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+    /// The number of channels of binary inputs, which have no header
+    #[arg(long, global = true)]
+    channels: Option<usize>,
+}
+
+/// The subcommands of the tool
+///
+/// # Arguments
+///
+/// * `Info` - Prints the format, columns, row count and duration of a file
+/// * `Head` - Prints the header and the first rows of a file
+/// * `Stats` - Prints the count, mean, standard deviation, minimum and maximum of each column
+/// * `Convert` - Converts a file to the format of the output extension
+/// * `Select` - Prints some columns and a range of rows of a file
+#[derive(Subcommand)]
+enum Command {
+    /// Prints the format, columns, row count and duration of a file
+    Info {
+        /// The input file, or `-` for CSV on stdin
+        file: String,
+    },
+    /// Prints the header and the first rows of a file
+    Head {
+        /// The input file, or `-` for CSV on stdin
+        file: String,
+        /// The number of rows to print
+        #[arg(short = 'n', default_value_t = 10)]
+        rows: usize,
+    },
+    /// Prints the count, mean, standard deviation, minimum and maximum of each column
+    Stats {
+        /// The input file, or `-` for CSV on stdin
+        file: String,
+    },
+    /// Converts a file to the format of the output extension
+    Convert {
+        /// The input file, or `-` for CSV on stdin
+        input: String,
+        /// The output file, or `-` for stdout
+        #[arg(default_value = "-")]
+        output: String,
+        /// The output format when writing to stdout, csv by default: csv, tsv, jsonl or bin
+        #[arg(long = "to")]
+        format: Option<String>,
+    },
+    /// Prints some columns and a range of rows of a file
+    Select {
+        /// The input file, or `-` for CSV on stdin
+        file: String,
+        /// The comma-separated names of the columns to keep, all by default
+        #[arg(long, value_delimiter = ',')]
+        columns: Vec<String>,
+        /// The zero-based range of data rows to keep, e.g. `1000..2000` or `1000..`
+        #[arg(long)]
+        rows: Option<String>,
+        /// The output file, or `-` for stdout
+        #[arg(short, long, default_value = "-")]
+        output: String,
+    },
+}
+
+/// The errors of the tool, which decide its exit code
+///
+/// # Arguments
+///
+/// * `User` - The arguments are invalid, a file is missing or a format is not supported
+/// * `Data` - A file cannot be parsed or the output cannot be written
+/// * `Closed` - The reader of stdout stopped early, e.g. `| head`, which is not an error
+enum CliError {
+    User(String),
+    Data(String),
+    Closed,
+}
+
+impl From<csv::Error> for CliError {
+    fn from(error: csv::Error) -> Self {
+        match error.kind() {
+            csv::ErrorKind::Io(io_error) if io_error.kind() == io::ErrorKind::BrokenPipe => CliError::Closed,
+            _ => CliError::Data(error.to_string()),
+        }
+    }
+}
+
+impl From<io::Error> for CliError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::BrokenPipe => CliError::Closed,
+            _ => CliError::Data(error.to_string()),
+        }
+    }
+}
+
+/// The file formats the tool reads or writes, chosen by extension
+///
+/// # Arguments
+///
+/// * `Csv` - Comma-separated values with a header row
+/// * `Tsv` - Tab-separated values with a header row
+/// * `Jsonl` - One flat JSON object per row, keyed by column
+/// * `Binary` - Headerless interleaved little-endian f64 samples, as written by the pipeline
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Csv,
+    Tsv,
+    Jsonl,
+    Binary,
+}
+
+impl Format {
+    /// Returns the format named by an extension or a `--to` value
+    fn from_name(name: &str) -> Result<Self, CliError> {
+        match name.to_ascii_lowercase().as_str() {
+            "csv" => Ok(Format::Csv),
+            "tsv" | "tab" => Ok(Format::Tsv),
+            "jsonl" | "ndjson" => Ok(Format::Jsonl),
+            "bin" | "dat" | "raw" => Ok(Format::Binary),
+            "parquet" => Err(CliError::User("Parquet is not supported, convert the file to CSV first".to_string())),
+            other => Err(CliError::User(format!("Unknown format `{}`, expected csv, tsv, jsonl or bin", other))),
+        }
+    }
+
+    /// Returns the format of a path from its extension, CSV for stdin and stdout
+    fn from_path(path: &str) -> Result<Self, CliError> {
+        if path == "-" {
+            return Ok(Format::Csv);
+        }
+        match Path::new(path).extension().and_then(|extension| extension.to_str()) {
+            Some(extension) => Format::from_name(extension),
+            None => Err(CliError::User(format!("Cannot tell the format of `{}` without an extension", path))),
+        }
+    }
+
+    /// Returns the name of the format, as accepted by `from_name`
+    fn name(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Tsv => "tsv",
+            Format::Jsonl => "jsonl",
+            Format::Binary => "bin",
+        }
+    }
+}
+
+/// Streams the records of a file, whatever its format
+struct RecordReader {
+    format: Format,
+    headers: StringRecord,
+    source: RecordSource,
+}
+
+/// Where a `RecordReader` reads its records from
+///
+/// # Arguments
+///
+/// * `Delimited` - A CSV or TSV reader
+/// * `Jsonl` - The lines of a JSON Lines file, the number of the last line read and the first
+///   record, which was read to find the headers
+/// * `Binary` - The bytes of a binary file and a buffer of one sample of every channel
+enum RecordSource {
+    Delimited(csv::Reader<Box<dyn Read>>),
+    Jsonl { lines: Box<dyn BufRead>, line: usize, first: Option<StringRecord> },
+    Binary { bytes: Box<dyn Read>, sample: Vec<u8> },
+}
+
+impl RecordReader {
+    /// Opens a file, or stdin for `-`, and reads its header row
+    ///
+    /// JSONL files take their columns from the keys of their first object, and binary files,
+    /// which need `channels`, name theirs `field_1`, `field_2` and so on
+    fn open(path: &str, channels: Option<usize>) -> Result<Self, CliError> {
+        let format = Format::from_path(path)?;
+        let input: Box<dyn BufRead> = if path == "-" {
+            Box::new(io::stdin().lock())
+        } else {
+            Box::new(BufReader::new(File::open(path).map_err(|error| CliError::User(format!("Cannot open `{}`: {}", path, error)))?))
+        };
+        match format {
+            Format::Csv | Format::Tsv => {
+                let delimiter = if format == Format::Tsv { b'\t' } else { b',' };
+                let mut reader = ReaderBuilder::new().delimiter(delimiter).from_reader(Box::new(input) as Box<dyn Read>);
+                let headers = reader.headers()?.clone();
+                Ok(Self { format, headers, source: RecordSource::Delimited(reader) })
+            }
+            Format::Jsonl => {
+                let mut reader = Self { format, headers: StringRecord::new(), source: RecordSource::Jsonl { lines: input, line: 0, first: None } };
+                let mut first = StringRecord::new();
+                if reader.next(&mut first)? {
+                    if let RecordSource::Jsonl { first: pending, .. } = &mut reader.source {
+                        *pending = Some(first);
+                    }
+                }
+                Ok(reader)
+            }
+            Format::Binary => match channels {
+                Some(channels) if channels > 0 => {
+                    let headers = (1..=channels).map(|channel| format!("field_{}", channel)).collect();
+                    Ok(Self { format, headers, source: RecordSource::Binary { bytes: Box::new(input), sample: vec![0; channels * 8] } })
+                }
+                _ => Err(CliError::User(format!("Reading the binary file `{}` needs its number of channels, e.g. --channels 8", path))),
+            },
+        }
+    }
+
+    /// Reads the next record into `record`, returning false at the end of the file
+    fn next(&mut self, record: &mut StringRecord) -> Result<bool, CliError> {
+        match &mut self.source {
+            RecordSource::Delimited(reader) => Ok(reader.read_record(record)?),
+            RecordSource::Jsonl { lines, line, first } => {
+                if let Some(first) = first.take() {
+                    *record = first;
+                    return Ok(true);
+                }
+                let mut text = String::new();
+                loop {
+                    text.clear();
+                    if lines.read_line(&mut text)? == 0 {
+                        return Ok(false);
+                    }
+                    *line += 1;
+                    if !text.trim().is_empty() {
+                        break;
+                    }
+                }
+                let fields = parse_flat_object(&text, &format!("line {}", line)).map_err(|error| CliError::Data(error.to_string()))?;
+                if self.headers.is_empty() {
+                    self.headers = fields.iter().map(|(key, _)| key.as_str()).collect();
+                }
+                let mut values = vec![""; self.headers.len()];
+                for (key, value) in &fields {
+                    let index = self
+                        .headers
+                        .iter()
+                        .position(|header| header == key)
+                        .ok_or_else(|| CliError::Data(format!("Line {} has the key {}, which the first object does not", line, quote(key))))?;
+                    values[index] = value.as_deref().unwrap_or("");
+                }
+                record.clear();
+                values.into_iter().for_each(|value| record.push_field(value));
+                Ok(true)
+            }
+            RecordSource::Binary { bytes, sample } => {
+                let mut filled = 0;
+                while filled < sample.len() {
+                    match bytes.read(&mut sample[filled..]) {
+                        Ok(0) => break,
+                        Ok(n) => filled += n,
+                        Err(error) if error.kind() == io::ErrorKind::Interrupted => {}
+                        Err(error) => return Err(error.into()),
+                    }
+                }
+                if filled == 0 {
+                    return Ok(false);
+                }
+                if filled < sample.len() {
+                    return Err(CliError::Data(format!("The binary input ends {} bytes into a sample of {} channels", filled, sample.len() / 8)));
+                }
+                record.clear();
+                for value in sample.chunks_exact(8) {
+                    record.push_field(&f64::from_le_bytes(value.try_into().unwrap()).to_string());
+                }
+                Ok(true)
+            }
+        }
+    }
+}
+
+/// Writes records as delimited rows, JSON objects or binary samples
+enum RecordWriter {
+    Delimited(Box<csv::Writer<Box<dyn Write>>>),
+    Jsonl { out: Box<dyn Write>, headers: Vec<String> },
+    Binary(Box<dyn Write>),
+}
+
+impl RecordWriter {
+    /// Creates a file, or writes to stdout for `-`, and writes the header row of delimited formats
+    fn create(path: &str, format: Format, headers: &StringRecord) -> Result<Self, CliError> {
+        let sink: Box<dyn Write> = if path == "-" {
+            Box::new(BufWriter::new(io::stdout().lock()))
+        } else {
+            Box::new(BufWriter::new(File::create(path).map_err(|error| CliError::User(format!("Cannot create `{}`: {}", path, error)))?))
+        };
+        let mut writer = match format {
+            Format::Csv | Format::Tsv => {
+                let delimiter = if format == Format::Tsv { b'\t' } else { b',' };
+                RecordWriter::Delimited(Box::new(WriterBuilder::new().delimiter(delimiter).from_writer(sink)))
+            }
+            Format::Jsonl => return Ok(RecordWriter::Jsonl { out: sink, headers: headers.iter().map(str::to_string).collect() }),
+            Format::Binary => return Ok(RecordWriter::Binary(sink)),
+        };
+        writer.write(headers)?;
+        Ok(writer)
+    }
+
+    /// Writes one record
+    ///
+    /// Binary outputs write empty fields as NaN and reject any field that is not a number
+    fn write(&mut self, record: &StringRecord) -> Result<(), CliError> {
+        match self {
+            RecordWriter::Delimited(writer) => writer.write_record(record)?,
+            RecordWriter::Jsonl { out, headers } => {
+                let fields: Vec<String> = headers
+                    .iter()
+                    .zip(record.iter())
+                    .map(|(key, value)| format!("{}:{}", quote(key), json_value(value)))
+                    .collect();
+                writeln!(out, "{{{}}}", fields.join(","))?;
+            }
+            RecordWriter::Binary(out) => {
+                for field in record.iter() {
+                    let value: f64 = match field.trim() {
+                        "" => f64::NAN,
+                        field => field.parse().map_err(|_| CliError::Data(format!("Cannot write `{}` to a binary file, which holds only numbers", field)))?,
+                    };
+                    out.write_all(&value.to_le_bytes())?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Flushes the output
+    fn finish(self) -> Result<(), CliError> {
+        match self {
+            RecordWriter::Delimited(mut writer) => writer.flush()?,
+            RecordWriter::Jsonl { mut out, .. } | RecordWriter::Binary(mut out) => out.flush()?,
+        }
+        Ok(())
+    }
+}
+
+/// The running statistics of one column, updated with Welford's algorithm
+#[derive(Default)]
+struct ColumnStats {
+    count: usize,
+    missing: usize,
+    mean: f64,
+    m2: f64,
+    min: f64,
+    max: f64,
+}
+
+impl ColumnStats {
+    /// Adds a field, counting it as missing unless it parses as a finite number
+    fn push(&mut self, field: &str) {
+        let value: f64 = match field.trim().parse() {
+            Ok(value) if f64::is_finite(value) => value,
+            _ => {
+                self.missing += 1;
+                return;
+            }
+        };
+        if self.count == 0 {
+            self.min = value;
+            self.max = value;
+        }
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+    }
+}
+
+/// Parses the arguments, runs the subcommand and returns the exit code
+pub fn run() -> ExitCode {
+    let cli = Cli::parse();
+    let channels = cli.channels;
+    let result = match cli.command {
+        Command::Info { file } => info(&file, channels),
+        Command::Head { file, rows } => select(&file, channels, &[], 0, Some(rows), "-", None),
+        Command::Stats { file } => stats(&file, channels),
+        Command::Convert { input, output, format } => {
+            let format = match &format {
+                Some(name) => Format::from_name(name).map(Some),
+                None => Format::from_path(&output).map(Some),
+            };
+            format.and_then(|format| select(&input, channels, &[], 0, None, &output, format))
+        }
+        Command::Select { file, columns, rows, output } => parse_rows(rows.as_deref()).and_then(|(start, count)| {
+            let format = if output == "-" { None } else { Some(Format::from_path(&output)?) };
+            select(&file, channels, &columns, start, count, &output, format)
+        }),
+    };
+    match result {
+        Ok(()) | Err(CliError::Closed) => ExitCode::SUCCESS,
+        Err(CliError::User(message)) => {
+            eprintln!("error: {}", message);
+            ExitCode::from(EXIT_USER_ERROR)
+        }
+        Err(CliError::Data(message)) => {
+            eprintln!("error: {}", message);
+            ExitCode::from(EXIT_DATA_ERROR)
+        }
+    }
+}
+
+/// Prints the format, columns, row count and duration of a file
+fn info(path: &str, channels: Option<usize>) -> Result<(), CliError> {
+    let mut reader = RecordReader::open(path, channels)?;
+    let time_index = reader.headers.iter().position(|header| header.eq_ignore_ascii_case("time"));
+    let (mut rows, mut first, mut last) = (0usize, None, None);
+    let mut record = StringRecord::new();
+    while reader.next(&mut record)? {
+        rows += 1;
+        if let Some(time) = time_index.and_then(|index| record.get(index)).and_then(|field| field.trim().parse::<f64>().ok()) {
+            first.get_or_insert(time);
+            last = Some(time);
+        }
+    }
+    let mut out = io::stdout().lock();
+    writeln!(out, "format: {}", reader.format.name())?;
+    writeln!(out, "columns: {}", reader.headers.len())?;
+    for header in reader.headers.iter() {
+        writeln!(out, "  {}", header)?;
+    }
+    writeln!(out, "rows: {}", rows)?;
+    if let (Some(first), Some(last)) = (first, last) {
+        let duration = last - first;
+        writeln!(out, "start: {}", first)?;
+        writeln!(out, "duration: {}", duration)?;
+        if rows > 1 && duration > 0.0 {
+            writeln!(out, "sampling_rate: {}", (rows - 1) as f64 / duration)?;
+        }
+    }
+    Ok(())
+}
+
+/// Prints the statistics of every column as `column,count,missing,mean,std,min,max` rows
+fn stats(path: &str, channels: Option<usize>) -> Result<(), CliError> {
+    let mut reader = RecordReader::open(path, channels)?;
+    let mut columns: Vec<ColumnStats> = (0..reader.headers.len()).map(|_| ColumnStats::default()).collect();
+    let mut record = StringRecord::new();
+    while reader.next(&mut record)? {
+        for (column, field) in columns.iter_mut().zip(record.iter()) {
+            column.push(field);
+        }
+    }
+    let header = StringRecord::from(vec!["column", "count", "missing", "mean", "std", "min", "max"]);
+    let mut writer = RecordWriter::create("-", Format::Csv, &header)?;
+    for (name, column) in reader.headers.iter().zip(&columns) {
+        let (mean, std, min, max) = match column.count {
+            0 => (f64::NAN, f64::NAN, f64::NAN, f64::NAN),
+            1 => (column.mean, f64::NAN, column.min, column.max),
+            count => (column.mean, (column.m2 / (count - 1) as f64).sqrt(), column.min, column.max),
+        };
+        writer.write(&StringRecord::from(vec![
+            name.to_string(),
+            column.count.to_string(),
+            column.missing.to_string(),
+            mean.to_string(),
+            std.to_string(),
+            min.to_string(),
+            max.to_string(),
+        ]))?;
+    }
+    writer.finish()
+}
+
+/// Streams some columns and rows of a file to an output, in the input format unless one is given
+///
+/// Binary inputs are written as CSV unless asked otherwise, so that `head` prints text
+fn select(
+    path: &str,
+    channels: Option<usize>,
+    columns: &[String],
+    start: usize,
+    count: Option<usize>,
+    output: &str,
+    format: Option<Format>,
+) -> Result<(), CliError> {
+    let mut reader = RecordReader::open(path, channels)?;
+    let indices: Vec<usize> = if columns.is_empty() {
+        (0..reader.headers.len()).collect()
+    } else {
+        columns
+            .iter()
+            .map(|column| {
+                reader
+                    .headers
+                    .iter()
+                    .position(|header| header == column)
+                    .ok_or_else(|| CliError::User(format!("No column named `{}`", column)))
+            })
+            .collect::<Result<_, _>>()?
+    };
+    let pick = |record: &StringRecord| -> StringRecord { indices.iter().map(|&index| record.get(index).unwrap_or("")).collect() };
+
+    let format = format.unwrap_or(match reader.format {
+        Format::Binary => Format::Csv,
+        format => format,
+    });
+    let mut writer = RecordWriter::create(output, format, &pick(&reader.headers))?;
+    let mut record = StringRecord::new();
+    let mut row = 0;
+    while count.is_none_or(|count| row < start + count) && reader.next(&mut record)? {
+        if row >= start {
+            writer.write(&pick(&record))?;
+        }
+        row += 1;
+    }
+    writer.finish()
+}
+
+/// Parses a `start..end` or `start..` row range into its start and its number of rows
+fn parse_rows(rows: Option<&str>) -> Result<(usize, Option<usize>), CliError> {
+    let rows = match rows {
+        Some(rows) => rows,
+        None => return Ok((0, None)),
+    };
+    let invalid = || CliError::User(format!("Invalid row range `{}`, expected e.g. 1000..2000 or 1000..", rows));
+    let (start, end) = rows.split_once("..").ok_or_else(invalid)?;
+    let start: usize = if start.is_empty() { 0 } else { start.parse().map_err(|_| invalid())? };
+    match end {
+        "" => Ok((start, None)),
+        end => {
+            let end: usize = end.parse().map_err(|_| invalid())?;
+            if end < start {
+                return Err(invalid());
+            }
+            Ok((start, Some(end - start)))
+        }
+    }
+}
+
+/// Writes a field as a JSON number if it parses as a finite one, otherwise as a string
+fn json_value(field: &str) -> String {
+    match field.trim().parse::<f64>() {
+        Ok(value) if value.is_finite() => value.to_string(),
+        _ => quote(field),
+    }
+}
+
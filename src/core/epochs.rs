@@ -0,0 +1,641 @@
+// A module to cut the continuous signals of a recording into trials around events
+
+// Written by Amin Alam in 2024
+
+use std::collections::BTreeMap;
+use crate::core::events::Events;
+use crate::core::memory::{matrix_bytes, MemoryBudget};
+use crate::core::recording::Recording;
+use crate::core::session::SessionInfo;
+use crate::processing::error::ProcessingError;
+use crate::processing::evoked::{average, average_by_label, EvokedResponse};
+
+/// The largest relative difference between the sampling rates of signals epoched together
+const RATE_TOLERANCE: f64 = 1e-9;
+
+/// What `Epochs::from_events_with` does with the trials that extend past the signals
+///
+/// # Arguments
+///
+/// * `Drop` - Leaves them out and lists them in `Epochs::dropped`, as is always done with the trials that span a seam of the recording
+/// * `Pad` - Keeps them with NaN for the samples outside the signals, which `average` leaves out
+///
+/// # Examples
+///
+/// ```
+/// let epochs = Epochs::from_events_with(&recording, &stimuli, 0.2, 0.8, EdgeTrials::Pad)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EdgeTrials {
+    Drop,
+    Pad,
+}
+
+/// The trials cut from the channels of a recording around events
+///
+/// # Arguments
+///
+/// * `data` - The samples of each trial, indexed as `data[trial][channel][sample]`
+/// * `names` - The name of each channel
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample of each trial in seconds relative to its event, e.g. `-0.2`
+/// * `n_samples` - The number of samples of each channel of a trial
+/// * `events` - The event of each trial kept, in the order of the trials
+/// * `dropped` - The positions among the events given of the trials dropped at the edges of the signals or across a seam
+/// * `session` - The session metadata of the recording, or None if it had none
+///
+/// # Examples
+///
+/// ```
+/// let epochs = Epochs::from_events(&recording, &stimuli, 0.2, 0.8)?.baseline_correct((-0.2, 0.0))?;
+/// let erp = epochs.average()?;
+/// println!("{} of {} trials kept", epochs.n_trials(), stimuli.len());
+/// ```
+///
+/// # Note
+///
+/// The data is laid out as the `trials[trial][channel][time]` arrays taken by `average`,
+/// `epochs_to_bids` and the trial-based measures, so `data()` can be passed to them directly
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Epochs {
+    data: Vec<Vec<Vec<f64>>>,
+    names: Vec<String>,
+    sampling_rate: f64,
+    start_time: f64,
+    n_samples: usize,
+    events: Events,
+    dropped: Vec<usize>,
+    session: Option<SessionInfo>,
+}
+
+/// Implementation of the Epochs struct
+///
+/// # Methods
+///
+/// * `from_events` - Cuts the channels of a recording into trials around events, dropping the trials at the edges
+/// * `from_events_with` - Cuts the channels of a recording into trials around events
+/// * `baseline_correct` - Subtracts the mean of a baseline window from every channel of every trial
+/// * `data` - Returns the samples of every trial
+/// * `trial` - Returns the samples of one trial
+/// * `n_trials` - Returns the number of trials
+/// * `n_channels` - Returns the number of channels
+/// * `n_samples` - Returns the number of samples of each channel of a trial
+/// * `names` - Returns the names of the channels
+/// * `sampling_rate` - Returns the sampling rate
+/// * `start_time` - Returns the time of the first sample relative to the event
+/// * `times` - Returns the time of every sample relative to the event
+/// * `events` - Returns the event of each trial
+/// * `dropped` - Returns the positions of the events whose trials were dropped
+/// * `session` - Returns the session metadata of the recording
+/// * `average` - Averages the trials into an evoked response
+/// * `average_by_label` - Averages the trials into one evoked response per event label
+/// * `into_data` - Returns the samples of every trial, consuming the Epochs
+impl Epochs {
+    /// Cuts the channels of a recording into trials around events, dropping the trials at the edges
+    ///
+    /// # Arguments
+    ///
+    /// * `recording` - The recording, with every signal at the same sampling rate
+    /// * `events` - The events the trials are time-locked to
+    /// * `pre` - The time before each event the trials start, in seconds, e.g. `0.2`
+    /// * `post` - The time after each event the trials end, in seconds, e.g. `0.8`
+    ///
+    /// # Returns
+    ///
+    /// The Epochs of the trials that lie within every signal and do not span a seam, or an
+    /// error as for `from_events_with`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let epochs = Epochs::from_events(&recording, &recording.events("stimulus").cloned().unwrap_or_default(), 0.2, 0.8)?;
+    /// ```
+    ///
+    pub fn from_events(recording: &Recording, events: &Events, pre: f64, post: f64) -> Result<Self, ProcessingError> {
+        Self::from_events_with(recording, events, pre, post, EdgeTrials::Drop)
+    }
+
+    /// Cuts the channels of a recording into trials around events
+    ///
+    /// # Arguments
+    ///
+    /// * `recording` - The recording, with every signal at the same sampling rate
+    /// * `events` - The events the trials are time-locked to
+    /// * `pre` - The time before each event the trials start, in seconds, negative to start after the event
+    /// * `post` - The time after each event the trials end, in seconds
+    /// * `edges` - What to do with the trials that extend past the signals
+    ///
+    /// # Returns
+    ///
+    /// The Epochs, or an error if the recording has no channels, its signals differ in
+    /// sampling rate, its seams are not valid, the trials would have no samples, or the
+    /// trials would exceed the global memory budget
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let recording = recording.select(&["Fz", "Cz", "Pz"])?;
+    /// let epochs = Epochs::from_events_with(&recording, &stimuli.with_label("target"), 0.2, 0.8, EdgeTrials::Pad)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Each trial starts `round(pre * sampling_rate)` samples before the sample nearest to
+    /// its event and has `round(pre * sampling_rate) + round(post * sampling_rate)` samples,
+    /// so it covers `[-pre, post)`. Signals with different start times are aligned on their
+    /// own time base. Use `Recording::select` to epoch the channels of one sampling rate.
+    ///
+    /// A trial that has samples on both sides of a seam listed by `Recording::seams` is
+    /// dropped whatever `edges` is, since its samples are not continuous; clear the seams
+    /// with `Recording::set_seams` to keep it. The session metadata of the recording is
+    /// kept with the trials unless it is empty.
+    ///
+    pub fn from_events_with(recording: &Recording, events: &Events, pre: f64, post: f64, edges: EdgeTrials) -> Result<Self, ProcessingError> {
+        let signals = recording.signals();
+        let sampling_rate = signals
+            .first()
+            .map(|signal| signal.sampling_rate())
+            .ok_or_else(|| ProcessingError::InvalidParameter("The recording has no channels to epoch".to_string()))?;
+        if let Some(signal) = signals.iter().find(|signal| (signal.sampling_rate() - sampling_rate).abs() > RATE_TOLERANCE * sampling_rate) {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Signals sampled at {} and {} Hz cannot be epoched together, select the channels of one rate first",
+                sampling_rate,
+                signal.sampling_rate()
+            )));
+        }
+        if !(pre.is_finite() && post.is_finite()) {
+            return Err(ProcessingError::InvalidParameter(format!("Epoch window must be finite, got {} s before and {} s after", pre, post)));
+        }
+        let n_pre = (pre * sampling_rate).round() as i64;
+        let n_samples = n_pre + (post * sampling_rate).round() as i64;
+        if n_samples <= 0 {
+            return Err(ProcessingError::InvalidParameter(format!("Epochs from {} s to {} s around the events have no samples", -pre, post)));
+        }
+        let n_samples = n_samples as usize;
+        let n_channels = recording.n_channels();
+        let seams = recording.seams()?;
+
+        let mut data = Vec::new();
+        let mut dropped = Vec::new();
+        for (event, &time) in events.times().iter().enumerate() {
+            // The first sample of the trial in each signal, which may be before or after the signal
+            let firsts: Vec<i64> = signals.iter().map(|signal| ((time - signal.start_time()) * sampling_rate).round() as i64 - n_pre).collect();
+            let inside = signals.iter().zip(&firsts).all(|(signal, &first)| first >= 0 && first + n_samples as i64 <= signal.len() as i64);
+            // A seam at sample s splits the trial if the trial has samples before and from s
+            let spans_seam = seams.iter().any(|&seam| {
+                signals.iter().zip(&firsts).any(|(signal, &first)| {
+                    let sample = ((seam - signal.start_time()) * sampling_rate).round() as i64;
+                    first < sample && sample < first + n_samples as i64
+                })
+            });
+            if spans_seam || (!inside && edges == EdgeTrials::Drop) {
+                dropped.push(event);
+                continue;
+            }
+            let needed = (data.len() + 1).saturating_mul(matrix_bytes(n_channels, n_samples));
+            MemoryBudget::global()
+                .check(needed)
+                .map_err(|error| ProcessingError::MemoryBudgetExceeded { needed: error.needed, budget: error.budget })?;
+            let mut trial = Vec::with_capacity(n_channels);
+            for (signal, &first) in signals.iter().zip(&firsts) {
+                for samples in signal.channels() {
+                    trial.push(
+                        (0..n_samples as i64)
+                            .map(|offset| usize::try_from(first + offset).ok().and_then(|index| samples.get(index)).copied().unwrap_or(f64::NAN))
+                            .collect(),
+                    );
+                }
+            }
+            data.push(trial);
+        }
+
+        Ok(Epochs {
+            data,
+            names: recording.channel_names().into_iter().map(str::to_string).collect(),
+            sampling_rate,
+            start_time: -(n_pre as f64) / sampling_rate,
+            n_samples,
+            events: events.filtered(|event| dropped.binary_search(&event).is_err()),
+            dropped,
+            session: (recording.session != SessionInfo::default()).then(|| recording.session.clone()),
+        })
+    }
+
+    /// Subtracts the mean of a baseline window from every channel of every trial
+    ///
+    /// # Arguments
+    ///
+    /// * `window` - The start and end of the baseline window in seconds relative to the events, inclusive, e.g. `(-0.2, 0.0)`
+    ///
+    /// # Returns
+    ///
+    /// The Epochs with every channel of every trial corrected, or an error if no sample of
+    /// the trials falls within the window
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let corrected = epochs.baseline_correct((-0.2, 0.0))?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// NaN samples within the window are ignored when computing the mean, as for
+    /// `detrend::baseline_correct`. A channel of a trial with no valid sample in the window,
+    /// e.g. of a padded edge trial, is set to NaN, so `average` leaves it out.
+    ///
+    pub fn baseline_correct(mut self, window: (f64, f64)) -> Result<Self, ProcessingError> {
+        let in_window: Vec<usize> = self.times().iter().enumerate().filter(|(_, &time)| time >= window.0 && time <= window.1).map(|(index, _)| index).collect();
+        if in_window.is_empty() {
+            return Err(ProcessingError::InvalidParameter(format!("No sample of the epochs falls within the baseline window {:?}", window)));
+        }
+        for samples in self.data.iter_mut().flatten() {
+            let (sum, count) = in_window
+                .iter()
+                .map(|&index| samples[index])
+                .filter(|sample| !sample.is_nan())
+                .fold((0.0, 0usize), |(sum, count), sample| (sum + sample, count + 1));
+            let baseline = match count {
+                0 => f64::NAN,
+                _ => sum / count as f64,
+            };
+            samples.iter_mut().for_each(|sample| *sample -= baseline);
+        }
+        Ok(self)
+    }
+
+    /// Returns the samples of every trial
+    ///
+    /// # Returns
+    ///
+    /// The samples, indexed as `data[trial][channel][sample]`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (tsv, json) = epochs_to_bids(epochs.data(), epochs.names(), epochs.start_time(), &sidecar, "derivatives/sub-01/eeg", &entities)?;
+    /// ```
+    ///
+    pub fn data(&self) -> &[Vec<Vec<f64>>] {
+        &self.data
+    }
+
+    /// Returns the samples of one trial
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The position of the trial
+    ///
+    /// # Returns
+    ///
+    /// The samples of each channel of the trial, or None if there is no trial at the position
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let first = epochs.trial(0);
+    /// ```
+    ///
+    pub fn trial(&self, index: usize) -> Option<&[Vec<f64>]> {
+        self.data.get(index).map(Vec::as_slice)
+    }
+
+    /// Returns the number of trials
+    ///
+    /// # Returns
+    ///
+    /// The number of trials kept
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let n_trials = epochs.n_trials();
+    /// ```
+    ///
+    pub fn n_trials(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Returns the number of channels
+    ///
+    /// # Returns
+    ///
+    /// The number of channels of each trial
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let n_channels = epochs.n_channels();
+    /// ```
+    ///
+    pub fn n_channels(&self) -> usize {
+        self.names.len()
+    }
+
+    /// Returns the number of samples of each channel of a trial
+    ///
+    /// # Returns
+    ///
+    /// The number of samples
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let n_samples = epochs.n_samples();
+    /// ```
+    ///
+    pub fn n_samples(&self) -> usize {
+        self.n_samples
+    }
+
+    /// Returns the names of the channels
+    ///
+    /// # Returns
+    ///
+    /// The names, in the order of the channels of each trial
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let names = epochs.names();
+    /// ```
+    ///
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Returns the sampling rate
+    ///
+    /// # Returns
+    ///
+    /// The sampling rate in Hz
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let sampling_rate = epochs.sampling_rate();
+    /// ```
+    ///
+    pub fn sampling_rate(&self) -> f64 {
+        self.sampling_rate
+    }
+
+    /// Returns the time of the first sample relative to the event
+    ///
+    /// # Returns
+    ///
+    /// The time in seconds, e.g. `-0.2`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let start_time = epochs.start_time();
+    /// ```
+    ///
+    pub fn start_time(&self) -> f64 {
+        self.start_time
+    }
+
+    /// Returns the time of every sample relative to the event
+    ///
+    /// # Returns
+    ///
+    /// The times in seconds
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let times = epochs.times();
+    /// ```
+    ///
+    pub fn times(&self) -> Vec<f64> {
+        (0..self.n_samples()).map(|sample| self.start_time + sample as f64 / self.sampling_rate).collect()
+    }
+
+    /// Returns the event of each trial
+    ///
+    /// # Returns
+    ///
+    /// The events of the trials kept, in the order of the trials
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let conditions = epochs.events().labels();
+    /// ```
+    ///
+    pub fn events(&self) -> &Events {
+        &self.events
+    }
+
+    /// Returns the positions of the events whose trials were dropped
+    ///
+    /// # Returns
+    ///
+    /// The positions among the events given to `from_events`, of the trials that extend
+    /// past the signals, empty for `EdgeTrials::Pad`, and of the trials that span a seam
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// for &event in epochs.dropped() {
+    ///     println!("Trial at {} s dropped at the edge of the recording", stimuli.times()[event]);
+    /// }
+    /// ```
+    ///
+    pub fn dropped(&self) -> &[usize] {
+        &self.dropped
+    }
+
+    /// Returns the session metadata of the recording
+    ///
+    /// # Returns
+    ///
+    /// The SessionInfo of the recording the trials were cut from, or None if it was empty
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let subject = epochs.session().and_then(|session| session.subject_id.as_deref()).unwrap_or("unknown");
+    /// ```
+    ///
+    pub fn session(&self) -> Option<&SessionInfo> {
+        self.session.as_ref()
+    }
+
+    /// Averages the trials into an evoked response
+    ///
+    /// # Returns
+    ///
+    /// The EvokedResponse of `evoked::average`, or an error if there are no trials
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let erp = epochs.average()?;
+    /// ```
+    ///
+    pub fn average(&self) -> Result<EvokedResponse, ProcessingError> {
+        average(&self.data, &self.names, self.sampling_rate, self.start_time)
+    }
+
+    /// Averages the trials into one evoked response per event label
+    ///
+    /// # Returns
+    ///
+    /// The EvokedResponse of each label of the events, or an error if there are no trials
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let by_condition = epochs.average_by_label()?;
+    /// let mismatch_negativity = by_condition["deviant"].difference(&by_condition["standard"])?;
+    /// ```
+    ///
+    pub fn average_by_label(&self) -> Result<BTreeMap<String, EvokedResponse>, ProcessingError> {
+        average_by_label(&self.data, self.events.labels(), &self.names, self.sampling_rate, self.start_time)
+    }
+
+    /// Returns the samples of every trial, consuming the Epochs
+    ///
+    /// # Returns
+    ///
+    /// The samples, indexed as `data[trial][channel][sample]`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let trials = epochs.into_data();
+    /// ```
+    ///
+    pub fn into_data(self) -> Vec<Vec<Vec<f64>>> {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::timeseries::TimeSeries;
+    use crate::processing::concat::{concatenate, GapPolicy, RecordingPart};
+
+    /// A recording of 10 s at 100 Hz whose channels hold the index of each sample and its negative
+    fn ramp_recording(session: SessionInfo) -> Recording {
+        let ramp: Vec<f64> = (0..1000).map(|i| i as f64).collect();
+        let negative = ramp.iter().map(|sample| -sample).collect();
+        let mut recording = Recording::new(session);
+        recording.add_signal(TimeSeries::new(vec![ramp, negative], vec!["a".to_string(), "b".to_string()], 100.0, 0.0).unwrap()).unwrap();
+        recording
+    }
+
+    fn events(times: &[f64]) -> Events {
+        Events::new(times.to_vec(), times.iter().map(|time| format!("at {}", time)).collect(), None).unwrap()
+    }
+
+    #[test]
+    fn trials_are_cut_around_the_events_and_edge_trials_dropped() {
+        let recording = ramp_recording(SessionInfo::new());
+        let epochs = Epochs::from_events(&recording, &events(&[0.05, 1.0, 5.0, 9.95]), 0.2, 0.3).unwrap();
+        assert_eq!((epochs.n_trials(), epochs.n_channels(), epochs.n_samples()), (2, 2, 50));
+        assert_eq!(epochs.data().len(), 2);
+        assert!(epochs.data().iter().all(|trial| trial.len() == 2 && trial.iter().all(|channel| channel.len() == 50)));
+        assert_eq!(epochs.trial(0).unwrap()[0], (80..130).map(|i| i as f64).collect::<Vec<_>>());
+        assert_eq!(epochs.trial(1).unwrap()[1], (480..530).map(|i| -(i as f64)).collect::<Vec<_>>());
+        assert_eq!(epochs.dropped(), &[0, 3]);
+        assert_eq!(epochs.events().times(), &[1.0, 5.0]);
+        assert_eq!(epochs.events().labels(), &["at 1", "at 5"]);
+        assert!((epochs.start_time() + 0.2).abs() < 1e-12);
+        assert!((epochs.times()[49] - 0.29).abs() < 1e-12);
+        assert_eq!(epochs.names(), &["a", "b"]);
+        assert!(epochs.session().is_none());
+    }
+
+    #[test]
+    fn padded_edge_trials_are_nan_outside_the_signals_and_baselines_skip_nan() {
+        let recording = ramp_recording(SessionInfo::new());
+        let epochs = Epochs::from_events_with(&recording, &events(&[0.05, 1.0, 9.95]), 0.2, 0.3, EdgeTrials::Pad).unwrap();
+        assert_eq!(epochs.n_trials(), 3);
+        assert!(epochs.dropped().is_empty());
+        let early = &epochs.trial(0).unwrap()[0];
+        assert!(early[..15].iter().all(|sample| sample.is_nan()));
+        assert_eq!(early[15..], (0..35).map(|i| i as f64).collect::<Vec<_>>()[..]);
+        let late = &epochs.trial(2).unwrap()[0];
+        assert_eq!(late[..25], (975..1000).map(|i| i as f64).collect::<Vec<_>>()[..]);
+        assert!(late[25..].iter().all(|sample| sample.is_nan()));
+
+        // The window from -0.2 s to 0 s holds 21 samples
+        let corrected = epochs.baseline_correct((-0.2, 0.0)).unwrap();
+        assert_eq!(corrected.trial(1).unwrap()[0], (80..130).map(|i| i as f64 - 90.0).collect::<Vec<_>>());
+        assert_eq!(corrected.trial(1).unwrap()[1], (80..130).map(|i| 90.0 - i as f64).collect::<Vec<_>>());
+        assert_eq!(corrected.trial(0).unwrap()[0][15], -2.5);
+        let average = corrected.average().unwrap();
+        assert_eq!((average.n[0][0], average.n[0][20], average.n[0][30]), (2, 3, 2));
+        assert!(Epochs::from_events(&recording, &events(&[1.0]), 0.2, 0.3).unwrap().baseline_correct((0.5, 0.6)).is_err());
+    }
+
+    #[test]
+    fn invalid_recordings_and_windows_are_errors() {
+        assert!(Epochs::from_events(&Recording::new(SessionInfo::new()), &events(&[1.0]), 0.2, 0.3).is_err());
+        let recording = ramp_recording(SessionInfo::new());
+        assert!(Epochs::from_events(&recording, &events(&[1.0]), -0.3, 0.2).is_err());
+        assert!(Epochs::from_events(&recording, &events(&[1.0]), f64::NAN, 0.2).is_err());
+        let mut mixed = ramp_recording(SessionInfo::new());
+        mixed.add_signal(TimeSeries::new(vec![vec![0.0; 100]], vec!["slow".to_string()], 10.0, 0.0).unwrap()).unwrap();
+        assert!(Epochs::from_events(&mixed, &events(&[1.0]), 0.2, 0.3).is_err());
+    }
+
+    #[test]
+    fn the_session_of_the_recording_is_kept() {
+        let mut session = SessionInfo::new();
+        session.subject_id = Some("mouse-12".to_string());
+        session.set_extra("implant", "left CA1");
+        let epochs = Epochs::from_events(&ramp_recording(session.clone()), &events(&[1.0]), 0.2, 0.3).unwrap();
+        assert_eq!(epochs.session(), Some(&session));
+        assert_eq!(epochs.session().and_then(|session| session.subject_id.as_deref()), Some("mouse-12"));
+    }
+
+    #[test]
+    fn trials_across_a_seam_are_dropped() {
+        // Two parts of 5 s at 100 Hz, with 2 s missing between them
+        let names = vec!["a".to_string()];
+        let first = vec![(0..500).map(|i| i as f64).collect::<Vec<_>>()];
+        let second = vec![(500..1000).map(|i| i as f64).collect::<Vec<_>>()];
+        let parts = [
+            RecordingPart { channels: &first, names: &names, sampling_rate: 100.0, start_time: 0.0 },
+            RecordingPart { channels: &second, names: &names, sampling_rate: 100.0, start_time: 7.0 },
+        ];
+        let mut session = SessionInfo::new();
+        session.subject_id = Some("mouse-12".to_string());
+        let recording = concatenate(&parts, GapPolicy::Stitch).unwrap().into_recording(session).unwrap();
+        assert_eq!(recording.seams().unwrap(), vec![5.0]);
+        assert_eq!(recording.session.subject_id.as_deref(), Some("mouse-12"));
+
+        // The trial of 4.9 s covers 4.7 to 5.2 s, and that of 5.2 s starts at the seam
+        let stimuli = events(&[2.0, 4.9, 5.2, 8.0]);
+        for edges in [EdgeTrials::Drop, EdgeTrials::Pad] {
+            let epochs = Epochs::from_events_with(&recording, &stimuli, 0.2, 0.3, edges).unwrap();
+            assert_eq!(epochs.dropped(), &[1], "{:?}", edges);
+            assert_eq!(epochs.events().times(), &[2.0, 5.2, 8.0]);
+            assert_eq!(epochs.trial(1).unwrap()[0][0], 500.0);
+            assert!(epochs.session().is_some_and(|session| session.get(crate::core::recording::SEAMS_KEY) == Some("5")));
+        }
+
+        let mut kept = recording.clone();
+        kept.set_seams(&[]);
+        assert!(kept.seams().unwrap().is_empty());
+        assert_eq!(Epochs::from_events(&kept, &stimuli, 0.2, 0.3).unwrap().n_trials(), 4);
+
+        // A filled gap has a seam at each end
+        let filled = concatenate(&parts, GapPolicy::FillNan).unwrap().into_recording(SessionInfo::new()).unwrap();
+        assert_eq!(filled.seams().unwrap(), vec![5.0, 7.0]);
+        let epochs = Epochs::from_events(&filled, &events(&[4.9, 6.0, 7.1, 9.0]), 0.2, 0.3).unwrap();
+        assert_eq!(epochs.dropped(), &[0, 2]);
+
+        let mut broken = recording;
+        broken.session.set_extra(crate::core::recording::SEAMS_KEY, "5;later");
+        assert!(broken.seams().is_err());
+        assert!(Epochs::from_events(&broken, &stimuli, 0.2, 0.3).is_err());
+    }
+}
@@ -0,0 +1,598 @@
+// A module to hold timestamped events such as stimulus onsets, TTL pulses and behavioral markers
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::core::sample::Sample;
+use crate::core::timeseries::TimeSeries;
+use crate::data_io::bids::BidsEvent;
+use crate::data_io::csv::{column_index, CsvIO};
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::triggers::TriggerEvent;
+
+/// Events with a time, a label and optionally a duration, sorted by time
+///
+/// # Arguments
+///
+/// * `times` - The onset of each event in seconds
+/// * `labels` - The label of each event, e.g. `stimulus` or `lick`
+/// * `durations` - The duration of each event in seconds, or None if it has none
+///
+/// # Examples
+///
+/// ```
+/// let mut csv_io = CsvIO::open_read("behavior.csv")?;
+/// let licks = Events::from_csv(&mut csv_io, "lick_time", None, None)?.shift(-clock_offset);
+/// let samples = licks.align(&lfp);
+/// ```
+///
+/// # Note
+///
+/// Events with the same onset keep the order they were given in. `times()` can be passed
+/// to the functions taking event times, e.g. `psth::compute`.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Events {
+    times: Vec<f64>,
+    labels: Vec<String>,
+    durations: Vec<Option<f64>>,
+}
+
+/// Implementation of the Events struct
+///
+/// # Methods
+///
+/// * `new` - Creates Events from onsets in any order
+/// * `from_csv` - Reads Events from a time column of the remaining records of a CsvIO object
+/// * `from_triggers` - Creates Events from decoded trigger pulses
+/// * `from_bids` - Creates Events from the events of a BIDS `events.tsv` file
+/// * `to_csv` - Writes the Events as `time,label,duration` rows
+/// * `len` - Returns the number of events
+/// * `is_empty` - Checks whether there are no events
+/// * `times` - Returns the onsets
+/// * `labels` - Returns the labels
+/// * `durations` - Returns the durations
+/// * `with_label` - Keeps the events with a label
+/// * `slice_time` - Keeps the events with an onset within a time range
+/// * `shift` - Adds an offset to every onset
+/// * `merge` - Joins several sets of events into one
+/// * `align` - Returns the sample of a TimeSeries nearest to each onset
+impl Events {
+    /// Creates Events from onsets in any order
+    ///
+    /// # Arguments
+    ///
+    /// * `times` - The onset of each event in seconds
+    /// * `labels` - The label of each event
+    /// * `durations` - The duration of each event in seconds, or None if the events have none
+    ///
+    /// # Returns
+    ///
+    /// The Events sorted by onset, or an error if there is not one label and duration per
+    /// onset, or an onset or duration is not finite
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let stimuli = Events::new(vec![1.0, 4.0], vec!["go".to_string(), "nogo".to_string()], Some(vec![0.5, 0.5]))?;
+    /// ```
+    ///
+    pub fn new(times: Vec<f64>, labels: Vec<String>, durations: Option<Vec<f64>>) -> Result<Self, ProcessingError> {
+        let durations = match durations {
+            Some(durations) => durations.into_iter().map(Some).collect(),
+            None => vec![None; times.len()],
+        };
+        Self::sorted(times, labels, durations)
+    }
+
+    /// Reads Events from a time column of the remaining records of a CsvIO object
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object
+    /// * `time_column` - The name of the column holding the onsets in seconds
+    /// * `label_column` - The name of the column holding the labels, or None to label every event with the name of the time column
+    /// * `duration_column` - The name of the column holding the durations in seconds, or None if the events have none
+    ///
+    /// # Returns
+    ///
+    /// The Events of the rows with an onset, or a `ColumnNotFound` error if a column is not
+    /// found, a `Parse` error if an onset or duration is not a number, or an error if a
+    /// record cannot be read
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut csv_io = CsvIO::open_read("trials.csv")?;
+    /// let stimuli = Events::from_csv(&mut csv_io, "stimulus_onset", Some("condition"), Some("stimulus_duration"))?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Rows whose onset is empty, `n/a` or `NaN` are skipped, so a column of a trial table
+    /// that only some trials have, such as a reward time, can be read directly. An empty or
+    /// `n/a` duration is read as None. This method consumes the remaining records of the reader.
+    ///
+    pub fn from_csv(csv_io: &mut CsvIO, time_column: &str, label_column: Option<&str>, duration_column: Option<&str>) -> Result<Self, DataIoError> {
+        let headers = csv_io.reader_mut()?.headers().clone();
+        let index = |name: &str| column_index(&headers, name).map_err(|_| DataIoError::ColumnNotFound(name.to_string()));
+        let time_index = index(time_column)?;
+        let label_index = label_column.map(index).transpose()?;
+        let duration_index = duration_column.map(index).transpose()?;
+        let (mut times, mut labels, mut durations) = (Vec::new(), Vec::new(), Vec::new());
+        for record in csv_io.records() {
+            let record = record?;
+            let Some(time) = parse_optional(&record, time_index, &headers)? else {
+                continue;
+            };
+            times.push(time);
+            labels.push(label_index.map_or(time_column, |index| record.get(index).unwrap_or("")).to_string());
+            durations.push(duration_index.map(|index| parse_optional(&record, index, &headers)).transpose()?.flatten());
+        }
+        Ok(Self::sorted(times, labels, durations)?)
+    }
+
+    /// Creates Events from decoded trigger pulses
+    ///
+    /// # Arguments
+    ///
+    /// * `triggers` - The pulses decoded with `triggers::decode`
+    ///
+    /// # Returns
+    ///
+    /// The Events with the time, label and duration of each pulse
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let events = Events::from_triggers(&decode(&ttl, 30000.0, 0.0, &options)?);
+    /// ```
+    ///
+    pub fn from_triggers(triggers: &[TriggerEvent]) -> Self {
+        let times = triggers.iter().map(|trigger| trigger.time).collect();
+        let labels = triggers.iter().map(|trigger| trigger.label.clone()).collect();
+        let durations = triggers.iter().map(|trigger| trigger.duration).collect();
+        // The times and durations of decoded pulses are finite, so the fields always pass the checks
+        Self::sorted(times, labels, durations).unwrap_or_default()
+    }
+
+    /// Creates Events from the events of a BIDS `events.tsv` file
+    ///
+    /// # Arguments
+    ///
+    /// * `events` - The events read with `events_from_bids`
+    ///
+    /// # Returns
+    ///
+    /// The Events with the onset, trial type and duration of each event, or an error if an
+    /// onset or duration is not finite
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let events = Events::from_bids(&events_from_bids("sub-01_task-oddball_events.tsv")?)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// An event without a trial type gets an empty label
+    ///
+    pub fn from_bids(events: &[BidsEvent]) -> Result<Self, ProcessingError> {
+        let times = events.iter().map(|event| event.onset).collect();
+        let labels = events.iter().map(|event| event.trial_type.clone().unwrap_or_default()).collect();
+        let durations = events.iter().map(|event| event.duration).collect();
+        Self::sorted(times, labels, durations)
+    }
+
+    /// Writes the Events as `time,label,duration` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object the rows are written to
+    ///
+    /// # Returns
+    ///
+    /// An error if a record cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut csv_io = CsvIO::open_write("events.csv")?;
+    /// events.to_csv(&mut csv_io)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. Missing durations are written as empty fields, which
+    /// `from_csv` reads back as None.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["time", "label", "duration"]))?;
+        for ((time, label), duration) in self.times.iter().zip(&self.labels).zip(&self.durations) {
+            csv_io.write_record(StringRecord::from(vec![
+                float_format.format(*time),
+                label.clone(),
+                duration.map(|duration| float_format.format(duration)).unwrap_or_default(),
+            ]))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of events
+    ///
+    /// # Returns
+    ///
+    /// The number of events
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// println!("{} trials", stimuli.len());
+    /// ```
+    ///
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    /// Checks whether there are no events
+    ///
+    /// # Returns
+    ///
+    /// True if there are no events
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// if events.with_label("reward").is_empty() {
+    ///     println!("No rewarded trials");
+    /// }
+    /// ```
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    /// Returns the onsets
+    ///
+    /// # Returns
+    ///
+    /// The onset of each event in seconds, sorted
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let psth = compute(&spike_times, events.times(), (-0.2, 0.5), 0.01, (0.0, 600.0))?;
+    /// ```
+    ///
+    pub fn times(&self) -> &[f64] {
+        &self.times
+    }
+
+    /// Returns the labels
+    ///
+    /// # Returns
+    ///
+    /// The label of each event, in the order of `times`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let labels = events.labels();
+    /// ```
+    ///
+    pub fn labels(&self) -> &[String] {
+        &self.labels
+    }
+
+    /// Returns the durations
+    ///
+    /// # Returns
+    ///
+    /// The duration of each event in seconds, or None if it has none, in the order of `times`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let offsets: Vec<f64> = events.times().iter().zip(events.durations()).filter_map(|(time, duration)| duration.map(|duration| time + duration)).collect();
+    /// ```
+    ///
+    pub fn durations(&self) -> &[Option<f64>] {
+        &self.durations
+    }
+
+    /// Keeps the events with a label
+    ///
+    /// # Arguments
+    ///
+    /// * `label` - The label of the events kept
+    ///
+    /// # Returns
+    ///
+    /// The Events with the label
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let go = events.with_label("go");
+    /// ```
+    ///
+    pub fn with_label(&self, label: &str) -> Events {
+        self.filtered(|event| self.labels[event] == label)
+    }
+
+    /// Keeps the events with an onset within a time range
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - The start of the range in seconds, included
+    /// * `end` - The end of the range in seconds, excluded
+    ///
+    /// # Returns
+    ///
+    /// The Events with an onset in the range
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let first_block = events.slice_time(0.0, 300.0);
+    /// ```
+    ///
+    pub fn slice_time(&self, start: f64, end: f64) -> Events {
+        self.filtered(|event| self.times[event] >= start && self.times[event] < end)
+    }
+
+    /// Adds an offset to every onset
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The offset in seconds, e.g. the time of the recording clock at which the behavioral clock was zero
+    ///
+    /// # Returns
+    ///
+    /// The Events with the onsets shifted and the labels and durations unchanged
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let on_recording_clock = behavior_events.shift(12.75);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Clocks that also drift apart are mapped with `ClockMapping` instead
+    ///
+    pub fn shift(&self, offset: f64) -> Events {
+        Events { times: self.times.iter().map(|time| time + offset).collect(), ..self.clone() }
+    }
+
+    /// Joins several sets of events into one
+    ///
+    /// # Arguments
+    ///
+    /// * `events` - The sets of events, e.g. stimuli and responses
+    ///
+    /// # Returns
+    ///
+    /// The Events of every set sorted by onset, events with the same onset in the order of the sets
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let all = Events::merge(&[stimuli, responses]);
+    /// ```
+    ///
+    pub fn merge(events: &[Events]) -> Events {
+        let times = events.iter().flat_map(|events| events.times.iter().copied()).collect();
+        let labels = events.iter().flat_map(|events| events.labels.iter().cloned()).collect();
+        let durations = events.iter().flat_map(|events| events.durations.iter().copied()).collect();
+        // Every set was checked when it was created, so the joined fields always pass the checks
+        Self::sorted(times, labels, durations).unwrap_or_default()
+    }
+
+    /// Returns the sample of a TimeSeries nearest to each onset
+    ///
+    /// # Arguments
+    ///
+    /// * `series` - The TimeSeries whose time base the events are aligned to
+    ///
+    /// # Returns
+    ///
+    /// The index of the sample nearest to each onset, in the order of `times`, or None for
+    /// an onset more than half a sample before the first sample or after the last one
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// for (onset, sample) in stimuli.times().iter().zip(stimuli.align(&lfp)) {
+    ///     match sample {
+    ///         Some(sample) => println!("{} s is sample {}", onset, sample),
+    ///         None => println!("{} s is outside the recording", onset),
+    ///     }
+    /// }
+    /// ```
+    ///
+    pub fn align<T: Sample>(&self, series: &TimeSeries<T>) -> Vec<Option<usize>> {
+        self.times
+            .iter()
+            .map(|time| {
+                let position = ((time - series.start_time()) * series.sampling_rate()).round();
+                (position >= 0.0 && position < series.len() as f64).then_some(position as usize)
+            })
+            .collect()
+    }
+
+    /// Checks and sorts the fields of the events by onset
+    fn sorted(times: Vec<f64>, labels: Vec<String>, durations: Vec<Option<f64>>) -> Result<Self, ProcessingError> {
+        if labels.len() != times.len() || durations.len() != times.len() {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Got {} onsets but {} labels and {} durations",
+                times.len(),
+                labels.len(),
+                durations.len()
+            )));
+        }
+        if let Some(time) = times.iter().find(|time| !time.is_finite()) {
+            return Err(ProcessingError::InvalidParameter(format!("Event onset must be finite, got {}", time)));
+        }
+        if let Some(duration) = durations.iter().flatten().find(|duration| !duration.is_finite()) {
+            return Err(ProcessingError::InvalidParameter(format!("Event duration must be finite, got {}", duration)));
+        }
+        let mut order: Vec<usize> = (0..times.len()).collect();
+        order.sort_by(|&a, &b| times[a].total_cmp(&times[b]));
+        Ok(Events {
+            times: order.iter().map(|&event| times[event]).collect(),
+            labels: order.iter().map(|&event| labels[event].clone()).collect(),
+            durations: order.iter().map(|&event| durations[event]).collect(),
+        })
+    }
+
+    /// Keeps the events at the positions a predicate accepts
+    pub(crate) fn filtered<F: Fn(usize) -> bool>(&self, keep: F) -> Events {
+        let kept: Vec<usize> = (0..self.times.len()).filter(|&event| keep(event)).collect();
+        Events {
+            times: kept.iter().map(|&event| self.times[event]).collect(),
+            labels: kept.iter().map(|&event| self.labels[event].clone()).collect(),
+            durations: kept.iter().map(|&event| self.durations[event]).collect(),
+        }
+    }
+}
+
+/// Parses a field that may be missing, reading an empty, `n/a` or `NaN` field as None
+fn parse_optional(record: &StringRecord, index: usize, headers: &StringRecord) -> Result<Option<f64>, DataIoError> {
+    let field = record.get(index).unwrap_or("").trim();
+    if field.is_empty() || field.eq_ignore_ascii_case("n/a") || field.eq_ignore_ascii_case("nan") {
+        return Ok(None);
+    }
+    field.parse().map(Some).map_err(|_| DataIoError::Parse {
+        line: record.position().map_or(0, |position| position.line()),
+        column: headers.get(index).unwrap_or("").to_string(),
+        value: field.to_string(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(name: &str) -> String {
+        std::env::temp_dir().join(format!("neurorust-events-{}-{}", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    fn labels(events: &Events) -> Vec<&str> {
+        events.labels().iter().map(String::as_str).collect()
+    }
+
+    fn stimuli() -> Events {
+        let labels = ["tone", "light", "tone", "reward"].map(str::to_string).to_vec();
+        Events::new(vec![2.0, 0.5, 1.0, 3.5], labels, Some(vec![0.1, 0.2, 0.1, 0.0])).unwrap()
+    }
+
+    #[test]
+    fn events_are_sorted_by_onset_with_their_labels_and_durations() {
+        let events = stimuli();
+        assert_eq!(events.len(), 4);
+        assert_eq!(events.times(), &[0.5, 1.0, 2.0, 3.5]);
+        assert_eq!(labels(&events), vec!["light", "tone", "tone", "reward"]);
+        assert_eq!(events.durations(), &[Some(0.2), Some(0.1), Some(0.1), Some(0.0)]);
+
+        let pulses = Events::new(vec![1.0, 2.0], vec!["ttl".to_string(); 2], None).unwrap();
+        assert_eq!(pulses.durations(), &[None, None]);
+        assert!(Events::default().is_empty());
+
+        let message = |result: Result<Events, ProcessingError>| result.unwrap_err().to_string();
+        assert_eq!(message(Events::new(vec![1.0, 2.0], vec!["ttl".to_string()], None)), "Invalid parameter: Got 2 onsets but 1 labels and 2 durations");
+        assert_eq!(message(Events::new(vec![1.0], vec!["ttl".to_string()], Some(Vec::new()))), "Invalid parameter: Got 1 onsets but 1 labels and 0 durations");
+        assert_eq!(message(Events::new(vec![f64::NAN], vec!["ttl".to_string()], None)), "Invalid parameter: Event onset must be finite, got NaN");
+        assert_eq!(message(Events::new(vec![1.0], vec!["ttl".to_string()], Some(vec![f64::INFINITY]))), "Invalid parameter: Event duration must be finite, got inf");
+    }
+
+    #[test]
+    fn events_are_read_from_csv_columns() {
+        let file = path("read.csv");
+        std::fs::write(&file, "onset,trial_type,duration,other\n2.5,tone,0.1,x\n,missing,0.1,x\n0.5,light,n/a,x\n1.25,tone,NaN,x\n").unwrap();
+        let events = Events::from_csv(&mut CsvIO::open_read(&file).unwrap(), "onset", Some("trial_type"), Some("duration")).unwrap();
+        assert_eq!(events.times(), &[0.5, 1.25, 2.5]);
+        assert_eq!(labels(&events), vec!["light", "tone", "tone"]);
+        assert_eq!(events.durations(), &[None, None, Some(0.1)]);
+
+        // Without a label column every event is named after the time column
+        let onsets = Events::from_csv(&mut CsvIO::open_read(&file).unwrap(), "onset", None, None).unwrap();
+        assert_eq!(labels(&onsets), vec!["onset"; 3]);
+        assert_eq!(onsets.durations(), &[None; 3]);
+
+        let Err(error) = Events::from_csv(&mut CsvIO::open_read(&file).unwrap(), "onset", Some("label"), None) else { panic!("the label column is missing") };
+        assert_eq!(error.to_string(), "Column 'label' not found");
+        std::fs::write(&file, "onset,duration\n1.0,0.1\n2.0,long\n").unwrap();
+        let Err(error) = Events::from_csv(&mut CsvIO::open_read(&file).unwrap(), "onset", None, Some("duration")) else { panic!("the duration cannot be parsed") };
+        assert_eq!(error.to_string(), "Value 'long' of column 'duration' at line 3 cannot be parsed");
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn events_round_trip_through_csv() {
+        let file = path("written.csv");
+        std::fs::write(&file, "").unwrap();
+        let events = Events::new(vec![0.5, 1.0], vec!["light".to_string(), "ttl".to_string()], None).unwrap();
+        let events = Events::merge(&[events, Events::new(vec![2.0], vec!["tone".to_string()], Some(vec![0.25])).unwrap()]);
+        let mut output = CsvIO::open_write(&file).unwrap();
+        events.to_csv(&mut output).unwrap();
+        output.save().unwrap();
+        assert_eq!(std::fs::read_to_string(&file).unwrap(), "time,label,duration\n0.5,light,\n1,ttl,\n2,tone,0.25\n");
+        let read = Events::from_csv(&mut CsvIO::open_read(&file).unwrap(), "time", Some("label"), Some("duration")).unwrap();
+        assert_eq!(read, events);
+        std::fs::remove_file(&file).unwrap();
+    }
+
+    #[test]
+    fn triggers_and_bids_rows_become_events() {
+        let trigger = |time: f64, label: &str| TriggerEvent { sample: (time * 1000.0) as usize, time, duration: Some(0.01), code: 1, label: label.to_string(), line: None };
+        let events = Events::from_triggers(&[trigger(1.5, "stim"), trigger(0.5, "cue")]);
+        assert_eq!(events.times(), &[0.5, 1.5]);
+        assert_eq!(labels(&events), vec!["cue", "stim"]);
+        assert_eq!(events.durations(), &[Some(0.01); 2]);
+
+        let rows = [BidsEvent { onset: 3.0, duration: None, trial_type: Some("go".to_string()) }, BidsEvent { onset: 1.0, duration: Some(0.5), trial_type: None }];
+        let events = Events::from_bids(&rows).unwrap();
+        assert_eq!(events.times(), &[1.0, 3.0]);
+        assert_eq!(labels(&events), vec!["", "go"]);
+        assert_eq!(events.durations(), &[Some(0.5), None]);
+        assert!(Events::from_bids(&[BidsEvent { onset: f64::NAN, duration: None, trial_type: None }]).is_err());
+    }
+
+    #[test]
+    fn events_are_filtered_shifted_and_merged() {
+        let events = stimuli();
+        let tones = events.with_label("tone");
+        assert_eq!(tones.times(), &[1.0, 2.0]);
+        assert!(events.with_label("shock").is_empty());
+
+        let window = events.slice_time(1.0, 3.5);
+        assert_eq!(window.times(), &[1.0, 2.0]);
+        assert_eq!(labels(&window), vec!["tone", "tone"]);
+
+        let shifted = events.shift(-0.5);
+        assert_eq!(shifted.times(), &[0.0, 0.5, 1.5, 3.0]);
+        assert_eq!(shifted.labels(), events.labels());
+        assert_eq!(shifted.durations(), events.durations());
+
+        let merged = Events::merge(&[events.with_label("reward"), events.with_label("light"), Events::default()]);
+        assert_eq!(merged.times(), &[0.5, 3.5]);
+        assert_eq!(labels(&merged), vec!["light", "reward"]);
+        assert_eq!(merged.durations(), &[Some(0.2), Some(0.0)]);
+    }
+
+    #[test]
+    fn events_are_aligned_to_the_nearest_sample() {
+        // 10 samples at 100 Hz starting at 1 s cover 1.00 to 1.09 s
+        let series = TimeSeries::new(vec![vec![0.0; 10]], vec!["lfp".to_string()], 100.0, 1.0).unwrap();
+        let labels = vec!["ttl".to_string(); 6];
+        let events = Events::new(vec![0.99, 0.996, 1.0, 1.034, 1.046, 1.096], labels, None).unwrap();
+        assert_eq!(events.align(&series), vec![None, Some(0), Some(0), Some(3), Some(5), None]);
+
+        let shifted = events.shift(0.02);
+        assert_eq!(shifted.align(&series), vec![Some(1), Some(2), Some(2), Some(5), Some(7), None]);
+        assert!(Events::default().align(&series).is_empty());
+    }
+}
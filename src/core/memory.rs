@@ -0,0 +1,290 @@
+// A module to bound the memory of operations that hold large intermediate results
+
+// Written by Amin Alam in 2024
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::sync::RwLock;
+use csv::StringRecord;
+
+/// The budget used by operations that are not given one, unlimited until `MemoryBudget::set_global` is called
+static GLOBAL_BUDGET: RwLock<Option<MemoryBudget>> = RwLock::new(None);
+
+/// The largest amount of memory an operation may hold for its intermediate results
+///
+/// # Arguments
+///
+/// * `limit` - The budget in bytes, or None for no limit
+/// * `spill_dir` - The directory where operations that can spill write their temporary files, or None for the system temporary directory
+///
+/// # Examples
+///
+/// ```
+/// MemoryBudget::set_global(MemoryBudget::new(2 << 30).with_spill_dir("/scratch/tmp"));
+/// let mut reader = CsvReader::open("events.csv")?.with_memory_budget(MemoryBudget::new(256 << 20));
+/// ```
+///
+/// # Note
+///
+/// The sizes checked against the budget are estimates of the heap memory of the values
+/// held, not measurements, so leave headroom below the memory actually available.
+/// Operations that can spill, such as `CsvReader::sort`, write the excess to temporary
+/// files; the others return a `MemoryBudgetExceeded` error instead of growing further.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MemoryBudget {
+    pub limit: Option<usize>,
+    pub spill_dir: Option<PathBuf>,
+}
+
+/// The error returned when an operation that cannot spill would exceed its memory budget
+///
+/// # Arguments
+///
+/// * `needed` - The estimated number of bytes the operation needed when it stopped
+/// * `budget` - The budget in bytes
+///
+/// # Examples
+///
+/// ```
+/// if let Err(error) = MemoryBudget::global().check(matrix_bytes(64, n_samples)) {
+///     eprintln!("Needs {} of {} bytes, reading in chunks", error.needed, error.budget);
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryBudgetExceeded {
+    pub needed: usize,
+    pub budget: usize,
+}
+
+/// Implementation of the MemoryBudget struct
+///
+/// # Methods
+///
+/// * `new` - Creates a budget of a number of bytes
+/// * `unlimited` - Creates a budget without a limit
+/// * `with_spill_dir` - Sets the directory of the temporary files
+/// * `spill_dir` - Returns the directory of the temporary files
+/// * `check` - Checks an amount of memory against the budget
+/// * `global` - Returns the budget used by operations that are not given one
+/// * `set_global` - Sets the budget used by operations that are not given one
+impl MemoryBudget {
+    /// Creates a budget of a number of bytes
+    ///
+    /// # Arguments
+    ///
+    /// * `limit` - The budget in bytes
+    ///
+    /// # Returns
+    ///
+    /// The MemoryBudget, spilling to the system temporary directory
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let budget = MemoryBudget::new(512 << 20);
+    /// ```
+    ///
+    pub fn new(limit: usize) -> Self {
+        Self { limit: Some(limit), spill_dir: None }
+    }
+
+    /// Creates a budget without a limit
+    ///
+    /// # Returns
+    ///
+    /// The MemoryBudget, with which nothing is spilled or refused
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// MemoryBudget::set_global(MemoryBudget::unlimited());
+    /// ```
+    ///
+    pub fn unlimited() -> Self {
+        Self::default()
+    }
+
+    /// Sets the directory of the temporary files
+    ///
+    /// # Arguments
+    ///
+    /// * `spill_dir` - The directory, which must exist
+    ///
+    /// # Returns
+    ///
+    /// The MemoryBudget with the directory
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let budget = MemoryBudget::new(1 << 30).with_spill_dir("/scratch/tmp");
+    /// ```
+    ///
+    pub fn with_spill_dir<P: Into<PathBuf>>(mut self, spill_dir: P) -> Self {
+        self.spill_dir = Some(spill_dir.into());
+        self
+    }
+
+    /// Returns the directory of the temporary files
+    ///
+    /// # Returns
+    ///
+    /// The directory set with `with_spill_dir`, or the system temporary directory
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// println!("Spilling to {}", budget.spill_dir().display());
+    /// ```
+    ///
+    pub fn spill_dir(&self) -> PathBuf {
+        self.spill_dir.clone().unwrap_or_else(std::env::temp_dir)
+    }
+
+    /// Checks an amount of memory against the budget
+    ///
+    /// # Arguments
+    ///
+    /// * `needed` - The estimated number of bytes
+    ///
+    /// # Returns
+    ///
+    /// Ok if the amount fits in the budget, or the MemoryBudgetExceeded error
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // Check before reading 64 channels of 10 minutes at 30 kHz
+    /// MemoryBudget::global().check(matrix_bytes(64, 18_000_000))?;
+    /// ```
+    ///
+    pub fn check(&self, needed: usize) -> Result<(), MemoryBudgetExceeded> {
+        match self.limit {
+            Some(budget) if needed > budget => Err(MemoryBudgetExceeded { needed, budget }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the budget used by operations that are not given one
+    ///
+    /// # Returns
+    ///
+    /// A copy of the budget set with `set_global`, unlimited by default
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let budget = MemoryBudget::global();
+    /// ```
+    ///
+    pub fn global() -> Self {
+        GLOBAL_BUDGET.read().map(|budget| budget.clone().unwrap_or_default()).unwrap_or_default()
+    }
+
+    /// Sets the budget used by operations that are not given one
+    ///
+    /// # Arguments
+    ///
+    /// * `budget` - The budget for the whole process
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// MemoryBudget::set_global(MemoryBudget::new(8 << 30));
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Operations already running keep the budget they started with
+    ///
+    pub fn set_global(budget: MemoryBudget) {
+        if let Ok(mut global) = GLOBAL_BUDGET.write() {
+            *global = Some(budget);
+        }
+    }
+}
+
+impl fmt::Display for MemoryBudgetExceeded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "The operation needs more than {} bytes but the memory budget is {} bytes", self.needed, self.budget)
+    }
+}
+
+impl Error for MemoryBudgetExceeded {}
+
+impl From<MemoryBudgetExceeded> for io::Error {
+    fn from(error: MemoryBudgetExceeded) -> Self {
+        io::Error::new(io::ErrorKind::OutOfMemory, error)
+    }
+}
+
+/// Estimates the memory of a matrix of `f64` values held as one `Vec` per row
+///
+/// # Arguments
+///
+/// * `n_rows` - The number of rows, e.g. channels
+/// * `n_columns` - The number of values of each row, e.g. samples
+///
+/// # Returns
+///
+/// The estimated number of bytes
+///
+/// # Examples
+///
+/// ```
+/// let needed = matrix_bytes(names.len(), n_samples);
+/// ```
+///
+pub fn matrix_bytes(n_rows: usize, n_columns: usize) -> usize {
+    n_rows.saturating_mul(n_columns.saturating_mul(size_of::<f64>()).saturating_add(size_of::<Vec<f64>>()))
+}
+
+/// Estimates the memory of a record held in a collection
+pub(crate) fn record_bytes(record: &StringRecord) -> usize {
+    size_of::<StringRecord>() + record.as_slice().len() + record.len() * size_of::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn budgets_check_the_bytes_needed() {
+        assert_eq!(MemoryBudget::new(100).check(100), Ok(()));
+        assert_eq!(MemoryBudget::new(100).check(101), Err(MemoryBudgetExceeded { needed: 101, budget: 100 }));
+        assert_eq!(MemoryBudget::unlimited().check(usize::MAX), Ok(()));
+        assert_eq!(MemoryBudget::new(1).spill_dir(), std::env::temp_dir());
+        assert_eq!(MemoryBudget::new(1).with_spill_dir("/scratch").spill_dir(), PathBuf::from("/scratch"));
+    }
+
+    #[test]
+    fn exceeded_budgets_become_out_of_memory_errors() {
+        let exceeded = MemoryBudgetExceeded { needed: 2048, budget: 1024 };
+        assert_eq!(exceeded.to_string(), "The operation needs more than 2048 bytes but the memory budget is 1024 bytes");
+        let error = io::Error::from(exceeded);
+        assert_eq!(error.kind(), io::ErrorKind::OutOfMemory);
+        assert_eq!(error.get_ref().and_then(|inner| inner.downcast_ref::<MemoryBudgetExceeded>()), Some(&exceeded));
+    }
+
+    #[test]
+    fn the_global_budget_applies_until_replaced() {
+        // Other tests read the global budget, so it is only ever set without a limit here
+        let budget = MemoryBudget::unlimited().with_spill_dir(std::env::temp_dir().join("neurorust-memory-global"));
+        MemoryBudget::set_global(budget.clone());
+        assert_eq!(MemoryBudget::global(), budget);
+        MemoryBudget::set_global(MemoryBudget::unlimited());
+        assert_eq!(MemoryBudget::global(), MemoryBudget::unlimited());
+    }
+
+    #[test]
+    fn byte_estimates_count_values_and_rows() {
+        assert_eq!(matrix_bytes(10, 4), 10 * (4 * 8 + size_of::<Vec<f64>>()));
+        assert_eq!(matrix_bytes(0, 1000), 0);
+        assert_eq!(matrix_bytes(usize::MAX, 2), usize::MAX);
+        let record = StringRecord::from(vec!["ab", "cde"]);
+        assert_eq!(record_bytes(&record), size_of::<StringRecord>() + 5 + 2 * size_of::<usize>());
+    }
+}
@@ -0,0 +1,8 @@
+pub mod epochs;
+pub mod events;
+pub mod memory;
+pub mod recording;
+pub mod sample;
+pub mod session;
+pub mod spike_train;
+pub mod timeseries;
\ No newline at end of file
@@ -0,0 +1,1127 @@
+// A module to group the signals, event channels and session metadata of a recording
+
+// Written by Amin Alam in 2024
+
+use std::collections::BTreeMap;
+use crate::core::events::Events;
+use crate::core::sample::Sample;
+use crate::core::session::SessionInfo;
+use crate::core::timeseries::TimeSeries;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::data_io::float_format::FloatFormat;
+use crate::processing::detrend::DetrendMethod;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::{butterworth, FilterKind};
+use crate::processing::resample::ResampleMethod;
+use crate::processing::spectral::Spectrum;
+use crate::processing::timing::{validate_timing, TimingPolicy, TimingReport};
+use crate::processing::window::Window;
+
+/// The key of the session extra holding the probe the recording was made with
+pub const PROBE_KEY: &str = "probe";
+
+/// The key of the session extra holding the seams of a recording joined from several parts
+pub const SEAMS_KEY: &str = "seams";
+
+/// The signals, event channels and session metadata of one recording
+///
+/// # Arguments
+///
+/// * `signals` - The groups of channels, each sampled at its own rate from its own start time, all stored as the same Sample type
+/// * `events` - The events of each event channel
+/// * `session` - The subject, date, experimenter, probe and other metadata of the session
+///
+/// # Examples
+///
+/// ```
+/// let mut recording = Recording::new(SessionInfo::load("session.csv")?);
+/// recording.add_signal(TimeSeries::from_csv(&mut CsvIO::open_read("lfp.csv")?, "time", None)?)?;
+/// recording.add_events("stimulus", Events::from_csv(&mut CsvIO::open_read("trials.csv")?, "stimulus_onset", Some("condition"), None)?);
+/// for channel in recording.channels() {
+///     println!("{} at {} Hz", channel.name, channel.sampling_rate);
+/// }
+/// ```
+///
+/// # Note
+///
+/// Channel names are unique over all signals, so a channel is found by its name alone
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(bound = "T: Sample"))]
+pub struct Recording<T = f64> {
+    signals: Vec<TimeSeries<T>>,
+    events: BTreeMap<String, Events>,
+    pub session: SessionInfo,
+}
+
+/// One channel of a Recording, with the time base of its signal
+///
+/// # Arguments
+///
+/// * `name` - The name of the channel
+/// * `samples` - The samples of the channel
+/// * `unit` - The unit of the channel, or None if unknown
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample in seconds
+///
+/// # Examples
+///
+/// ```
+/// let channel = recording.channel("ch3").expect("No channel ch3");
+/// let spikes = spikes::detect(channel.samples, channel.sampling_rate, channel.start_time, &options)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordingChannel<'a, T = f64> {
+    pub name: &'a str,
+    pub samples: &'a [T],
+    pub unit: Option<&'a str>,
+    pub sampling_rate: f64,
+    pub start_time: f64,
+}
+
+/// Implementation of the Recording struct
+///
+/// # Methods
+///
+/// * `new` - Creates a Recording with no signals or events
+/// * `from_csv_with_time` - Reads a Recording from a csv file with a time column, checking that the rows are evenly spaced
+/// * `add_signal` - Adds a group of channels
+/// * `add_events` - Adds events to an event channel
+/// * `signals` - Returns the groups of channels
+/// * `events` - Returns the events of an event channel
+/// * `event_channels` - Returns the names of the event channels
+/// * `n_channels` - Returns the number of channels over all signals
+/// * `channel_names` - Returns the names of the channels over all signals
+/// * `channels` - Iterates over the channels of every signal
+/// * `channel` - Returns a channel by name
+/// * `probe` - Returns the probe the recording was made with
+/// * `set_probe` - Sets the probe the recording was made with
+/// * `seams` - Returns the times at which the samples are not continuous
+/// * `set_seams` - Sets the times at which the samples are not continuous
+/// * `time_range` - Returns the time covered by the signals
+/// * `select` - Keeps some channels and every event channel
+/// * `merge` - Joins the recordings read from several files of the same session
+/// * `to_f32` - Returns a copy with the samples of every signal stored as f32
+/// * `to_f64` - Returns a copy with the samples of every signal stored as f64
+/// * `map_signals` - Processes every signal, keeping the events and session
+/// * `filter` - Filters every signal forward with a Butterworth filter designed for its sampling rate
+/// * `filtfilt` - Filters every signal forward and backward with a Butterworth filter designed for its sampling rate
+/// * `notch` - Removes a narrow band from every signal with a zero-phase notch filter
+/// * `remove_line_noise` - Removes the line frequency and its harmonics from every signal
+/// * `detrend` - Removes a trend from every channel
+/// * `decimate` - Decimates every signal by an integer factor after anti-aliasing
+/// * `resample` - Resamples every signal to the same sampling rate
+/// * `envelope` - Returns the amplitude envelope of every channel
+/// * `psd` - Estimates the power spectral density of every channel with Welch's method
+impl<T: Sample> Recording<T> {
+    /// Creates a Recording with no signals or events
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - The metadata of the session
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let recording = Recording::new(SessionInfo::from_bids_path("sub-01_ses-02_task-oddball_eeg.csv"));
+    /// ```
+    ///
+    pub fn new(session: SessionInfo) -> Self {
+        Self { signals: Vec::new(), events: BTreeMap::new(), session }
+    }
+
+    /// Reads a Recording from a csv file with a time column, checking that the rows are evenly spaced
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object, with a time column and one column per channel
+    /// * `time_column` - The name of the column holding the time of each row in seconds
+    /// * `tolerance_fraction` - The tolerated deviation from the nominal interval, as a fraction of it, e.g. `0.1`
+    /// * `policy` - What to do with gaps and overlaps in the times
+    ///
+    /// # Returns
+    ///
+    /// The Recording of one signal with every column but the time column, sampled at the
+    /// nominal rate from the first time, and the TimingReport of the times, or an error if
+    /// the time column is not found, a field is not a number, the nominal interval cannot
+    /// be found, or the times are irregular and the policy does not allow it
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut csv_io = CsvIO::open_read("lfp.csv")?;
+    /// let (mut recording, report) = Recording::<f32>::from_csv_with_time(&mut csv_io, "time", 0.1, TimingPolicy::FillNan)?;
+    /// recording.session = SessionInfo::load("session.csv")?;
+    /// println!("{} samples filled with NaN", report.total_missing_samples());
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The nominal interval is the median step of the times, see `validate_timing`. With
+    /// `FillNan` the NaN samples of a gap are inserted before its first sample, so sample `i`
+    /// of the result is at `start + i / rate`; overlaps and times that go backwards cannot
+    /// be filled and are an error. The session is empty. This method consumes the
+    /// remaining records of the reader.
+    ///
+    pub fn from_csv_with_time(csv_io: &mut CsvIO, time_column: &str, tolerance_fraction: f64, policy: TimingPolicy) -> Result<(Self, TimingReport), DataIoError> {
+        let headers = csv_io.reader_mut()?.headers().clone();
+        let mut names: Vec<String> = headers.iter().map(str::to_string).collect();
+        let time_index = names.iter().position(|name| name == time_column).ok_or_else(|| DataIoError::ColumnNotFound(time_column.to_string()))?;
+        names.remove(time_index);
+        let (mut channels, times) = csv_io.read_columns_split::<T>(Some(time_index))?;
+        let report = validate_timing(&times, tolerance_fraction);
+        let invalid = |message: String| DataIoError::Processing(ProcessingError::InvalidParameter(message));
+        if !report.has_nominal_interval() {
+            return Err(invalid(format!("The {} times of column '{}' have no positive nominal interval", times.len(), time_column)));
+        }
+        if !report.is_regular() {
+            let first = report.gaps.iter().chain(&report.overlaps).min_by_key(|irregularity| irregularity.index);
+            let description = first.map_or_else(String::new, |irregularity| {
+                format!(", the first a step of {} s to {} s at sample {}", irregularity.interval, irregularity.time, irregularity.index)
+            });
+            let summary = format!(
+                "Column '{}' has {} gaps and {} overlaps beyond {} of the nominal interval of {} s{}",
+                time_column,
+                report.gaps.len(),
+                report.overlaps.len(),
+                tolerance_fraction,
+                report.nominal_interval,
+                description
+            );
+            match policy {
+                TimingPolicy::Error => return Err(invalid(summary)),
+                TimingPolicy::FillNan if !report.overlaps.is_empty() => return Err(invalid(format!("{}; overlaps cannot be filled", summary))),
+                TimingPolicy::Warn => eprintln!("Warning: {}", summary),
+                TimingPolicy::FillNan => {
+                    for samples in &mut channels {
+                        let mut filled = Vec::with_capacity(samples.len() + report.total_missing_samples());
+                        let mut start = 0;
+                        for gap in &report.gaps {
+                            filled.extend_from_slice(&samples[start..gap.index]);
+                            filled.extend(std::iter::repeat_n(T::from_f64(f64::NAN), gap.missing_samples));
+                            start = gap.index;
+                        }
+                        filled.extend_from_slice(&samples[start..]);
+                        *samples = filled;
+                    }
+                }
+            }
+        }
+        let mut recording = Self::new(SessionInfo::new());
+        recording.add_signal(TimeSeries::new(channels, names, report.nominal_rate, times[0])?)?;
+        Ok((recording, report))
+    }
+
+    /// Adds a group of channels
+    ///
+    /// # Arguments
+    ///
+    /// * `signal` - The channels, sampled at their own rate from their own start time
+    ///
+    /// # Returns
+    ///
+    /// An error if one of its channels has the name of a channel of the Recording
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// recording.add_signal(lfp)?;
+    /// recording.add_signal(accelerometer)?;
+    /// ```
+    ///
+    pub fn add_signal(&mut self, signal: TimeSeries<T>) -> Result<(), ProcessingError> {
+        if let Some(name) = signal.names().iter().find(|name| self.find(name).is_some()) {
+            return Err(ProcessingError::InvalidParameter(format!("Channel '{}' is already in the recording", name)));
+        }
+        self.signals.push(signal);
+        Ok(())
+    }
+
+    /// Adds events to an event channel
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The name of the event channel, created if it does not exist
+    /// * `events` - The events
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// recording.add_events("ttl", Events::from_triggers(&decode(&ttl, 30000.0, 0.0, &options)?));
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Events added to an existing channel are merged with its events, after them among
+    /// events with the same onset
+    ///
+    pub fn add_events(&mut self, channel: &str, events: Events) {
+        let channel = self.events.entry(channel.to_string()).or_default();
+        *channel = Events::merge(&[std::mem::take(channel), events]);
+    }
+
+    /// Returns the groups of channels
+    ///
+    /// # Returns
+    ///
+    /// The signals, in the order they were added
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// for signal in recording.signals() {
+    ///     println!("{} channels at {} Hz", signal.n_channels(), signal.sampling_rate());
+    /// }
+    /// ```
+    ///
+    pub fn signals(&self) -> &[TimeSeries<T>] {
+        &self.signals
+    }
+
+    /// Returns the events of an event channel
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The name of the event channel
+    ///
+    /// # Returns
+    ///
+    /// The events, or None if there is no such event channel
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let go = recording.events("stimulus").map(|events| events.with_label("go"));
+    /// ```
+    ///
+    pub fn events(&self, channel: &str) -> Option<&Events> {
+        self.events.get(channel)
+    }
+
+    /// Returns the names of the event channels
+    ///
+    /// # Returns
+    ///
+    /// The names, sorted
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// for name in recording.event_channels() {
+    ///     println!("{}: {} events", name, recording.events(name).map_or(0, Events::len));
+    /// }
+    /// ```
+    ///
+    pub fn event_channels(&self) -> Vec<&str> {
+        self.events.keys().map(String::as_str).collect()
+    }
+
+    /// Returns the number of channels over all signals
+    ///
+    /// # Returns
+    ///
+    /// The number of channels, not counting the event channels
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let n_channels = recording.n_channels();
+    /// ```
+    ///
+    pub fn n_channels(&self) -> usize {
+        self.signals.iter().map(TimeSeries::n_channels).sum()
+    }
+
+    /// Returns the names of the channels over all signals
+    ///
+    /// # Returns
+    ///
+    /// The names, in the order of `channels`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let names = recording.channel_names();
+    /// ```
+    ///
+    pub fn channel_names(&self) -> Vec<&str> {
+        self.signals.iter().flat_map(|signal| signal.names().iter().map(String::as_str)).collect()
+    }
+
+    /// Iterates over the channels of every signal
+    ///
+    /// # Returns
+    ///
+    /// An iterator over the channels of the first signal, then of the second, and so on
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// for channel in recording.channels().filter(|channel| channel.unit == Some("uV")) {
+    ///     println!("{}: {} samples", channel.name, channel.samples.len());
+    /// }
+    /// ```
+    ///
+    pub fn channels(&self) -> impl Iterator<Item = RecordingChannel<'_, T>> {
+        self.signals.iter().flat_map(|signal| (0..signal.n_channels()).map(move |index| channel_of(signal, index)))
+    }
+
+    /// Returns a channel by name
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the channel
+    ///
+    /// # Returns
+    ///
+    /// The channel with the time base of its signal, or None if it is not found
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let ecg = recording.channel("ecg");
+    /// ```
+    ///
+    pub fn channel(&self, name: &str) -> Option<RecordingChannel<'_, T>> {
+        self.find(name).map(|(signal, index)| channel_of(&self.signals[signal], index))
+    }
+
+    /// Returns the probe the recording was made with
+    ///
+    /// # Returns
+    ///
+    /// The session extra stored under `PROBE_KEY`, if any
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let probe = recording.probe().unwrap_or("unknown");
+    /// ```
+    ///
+    pub fn probe(&self) -> Option<&str> {
+        self.session.get(PROBE_KEY)
+    }
+
+    /// Sets the probe the recording was made with
+    ///
+    /// # Arguments
+    ///
+    /// * `probe` - A description of the probe, e.g. `Neuropixels 1.0, serial 18194814`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// recording.set_probe("NeuroNexus A1x32-Poly2");
+    /// ```
+    ///
+    pub fn set_probe(&mut self, probe: &str) {
+        self.session.set_extra(PROBE_KEY, probe);
+    }
+
+    /// Returns the times at which the samples are not continuous
+    ///
+    /// # Returns
+    ///
+    /// The times in seconds stored under `SEAMS_KEY`, empty if there are none, or an error
+    /// if one of them is not a number
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let recording = concatenate(&parts, GapPolicy::Stitch)?.into_recording(session)?;
+    /// for seam in recording.seams()? {
+    ///     println!("seam at {} s", seam);
+    /// }
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// `Epochs::from_events` drops the trials that span a seam, so filtering or spectral
+    /// analysis of the trials does not cross one
+    ///
+    pub fn seams(&self) -> Result<Vec<f64>, ProcessingError> {
+        let text = self.session.get(SEAMS_KEY).unwrap_or("");
+        text.split(';')
+            .map(str::trim)
+            .filter(|seam| !seam.is_empty())
+            .map(|seam| seam.parse::<f64>().ok().filter(|seam| seam.is_finite()))
+            .map(|seam| seam.ok_or_else(|| ProcessingError::InvalidParameter(format!("The session extra '{}' holds '{}', which are not times", SEAMS_KEY, text))))
+            .collect()
+    }
+
+    /// Sets the times at which the samples are not continuous
+    ///
+    /// # Arguments
+    ///
+    /// * `seams` - The times in seconds, written to the session extra `SEAMS_KEY` separated by `;`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // Keep the trials across the seams after checking the parts line up
+    /// recording.set_seams(&[]);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// An empty list removes the extra
+    ///
+    pub fn set_seams(&mut self, seams: &[f64]) {
+        match seams.is_empty() {
+            true => {
+                self.session.extras.remove(SEAMS_KEY);
+            }
+            false => {
+                let float_format = FloatFormat::default();
+                self.session.set_extra(SEAMS_KEY, &seams.iter().map(|&seam| float_format.format(seam)).collect::<Vec<_>>().join(";"));
+            }
+        }
+    }
+
+    /// Returns the time covered by the signals
+    ///
+    /// # Returns
+    ///
+    /// The earliest start time and the latest end time over the signals with samples, in
+    /// seconds, or None if no signal has samples
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// if let Some((start, end)) = recording.time_range() {
+    ///     println!("{:.1} s to {:.1} s", start, end);
+    /// }
+    /// ```
+    ///
+    pub fn time_range(&self) -> Option<(f64, f64)> {
+        self.signals
+            .iter()
+            .filter(|signal| !signal.is_empty())
+            .map(|signal| (signal.start_time(), signal.end_time()))
+            .reduce(|a, b| (a.0.min(b.0), a.1.max(b.1)))
+    }
+
+    /// Keeps some channels and every event channel
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - The names of the channels
+    ///
+    /// # Returns
+    ///
+    /// The Recording of the channels with the events and session of this one, or an error if
+    /// a channel is not found or is given twice
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let hippocampus = recording.select(&["ch3", "ch4", "ch7"])?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The signals keep their order and a signal with none of the channels is left out.
+    /// Within a signal the channels are in the order given.
+    ///
+    pub fn select(&self, channels: &[&str]) -> Result<Recording<T>, ProcessingError> {
+        let mut per_signal: Vec<Vec<&str>> = vec![Vec::new(); self.signals.len()];
+        for (position, channel) in channels.iter().enumerate() {
+            if channels[..position].contains(channel) {
+                return Err(ProcessingError::InvalidParameter(format!("Channel '{}' is given twice", channel)));
+            }
+            let (signal, _) = self.find(channel).ok_or_else(|| ProcessingError::InvalidParameter(format!("Channel '{}' not found", channel)))?;
+            per_signal[signal].push(channel);
+        }
+        let signals = self
+            .signals
+            .iter()
+            .zip(&per_signal)
+            .filter(|(_, names)| !names.is_empty())
+            .map(|(signal, names)| signal.select(names))
+            .collect::<Result<Vec<TimeSeries<T>>, ProcessingError>>()?;
+        Ok(Recording { signals, events: self.events.clone(), session: self.session.clone() })
+    }
+
+    /// Joins the recordings read from several files of the same session
+    ///
+    /// # Arguments
+    ///
+    /// * `recordings` - The recordings, e.g. one per probe or acquisition device
+    ///
+    /// # Returns
+    ///
+    /// The Recording with the signals of every recording in order, the events of the event
+    /// channels of the same name joined, and the metadata of every session, or an error if
+    /// two recordings have a channel of the same name or different values for the same
+    /// metadata key
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let recording = Recording::merge(vec![probe_a, probe_b, behavior])?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The signals are kept side by side with their own time bases. Parts of one recording
+    /// split in time, e.g. by restarts of the acquisition, are joined with `concatenate` instead.
+    ///
+    pub fn merge(recordings: Vec<Recording<T>>) -> Result<Recording<T>, ProcessingError> {
+        let mut merged = Self::default();
+        for recording in recordings {
+            for (key, value) in recording.session.entries() {
+                match merged.session.get(key) {
+                    Some(existing) if existing != value => {
+                        return Err(ProcessingError::InvalidParameter(format!(
+                            "Recordings disagree on the session key '{}': '{}' and '{}'",
+                            key, existing, value
+                        )));
+                    }
+                    Some(_) => {}
+                    None => merged.session.set(key, value),
+                }
+            }
+            for signal in recording.signals {
+                merged.add_signal(signal)?;
+            }
+            for (channel, events) in recording.events {
+                merged.add_events(&channel, events);
+            }
+        }
+        Ok(merged)
+    }
+
+    /// Returns a copy with the samples of every signal stored as f32
+    ///
+    /// # Returns
+    ///
+    /// The Recording with every sample rounded to the nearest f32, and the same events and
+    /// session
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let compact = Recording::merge(parts)?.to_f32();
+    /// ```
+    ///
+    pub fn to_f32(&self) -> Recording<f32> {
+        Recording { signals: self.signals.iter().map(TimeSeries::to_f32).collect(), events: self.events.clone(), session: self.session.clone() }
+    }
+
+    /// Returns a copy with the samples of every signal stored as f64
+    ///
+    /// # Returns
+    ///
+    /// The Recording with every sample widened to f64, exactly, and the same events and
+    /// session
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let epochs = Epochs::from_events(&compact.select(&["ch1", "ch2"])?.to_f64(), &onsets, 0.2, 0.8)?;
+    /// ```
+    ///
+    pub fn to_f64(&self) -> Recording<f64> {
+        Recording { signals: self.signals.iter().map(TimeSeries::to_f64).collect(), events: self.events.clone(), session: self.session.clone() }
+    }
+
+    /// Processes every signal, keeping the events and session
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - The function applied to each signal
+    ///
+    /// # Returns
+    ///
+    /// The Recording of the processed signals with the events and session of this one, or
+    /// the first error of `f`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let trimmed = recording.map_signals(|signal| signal.slice_time(10.0, 70.0))?;
+    /// ```
+    ///
+    pub fn map_signals<F>(&self, f: F) -> Result<Recording<T>, ProcessingError>
+    where
+        F: Fn(&TimeSeries<T>) -> Result<TimeSeries<T>, ProcessingError>,
+    {
+        let signals = self.signals.iter().map(f).collect::<Result<Vec<TimeSeries<T>>, ProcessingError>>()?;
+        Ok(Recording { signals, events: self.events.clone(), session: self.session.clone() })
+    }
+
+    /// Filters every signal forward with a Butterworth filter designed for its sampling rate
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - The order of the filter
+    /// * `kind` - The kind of the filter and its cutoff frequencies in Hz
+    ///
+    /// # Returns
+    ///
+    /// The filtered Recording, or an error as in `butterworth` for one of the sampling rates
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let causal = recording.filter(2, FilterKind::Highpass(0.5))?;
+    /// ```
+    ///
+    pub fn filter(&self, order: usize, kind: FilterKind) -> Result<Recording<T>, ProcessingError> {
+        self.map_signals(|signal| signal.filter(&butterworth(order, kind, signal.sampling_rate())?))
+    }
+
+    /// Filters every signal forward and backward with a Butterworth filter designed for its sampling rate
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - The order of the filter
+    /// * `kind` - The kind of the filter and its cutoff frequencies in Hz
+    ///
+    /// # Returns
+    ///
+    /// The filtered Recording, or an error as in `butterworth` and `IirFilter::filtfilt`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let lfp_band = recording.filtfilt(4, FilterKind::Bandpass(1.0, 300.0))?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The signals of a Recording joined by `concatenate` are filtered across the seams, so
+    /// parts that must stay apart are filtered before they are joined. The same holds for
+    /// the other methods that filter or resample.
+    ///
+    pub fn filtfilt(&self, order: usize, kind: FilterKind) -> Result<Recording<T>, ProcessingError> {
+        self.map_signals(|signal| signal.filtfilt(&butterworth(order, kind, signal.sampling_rate())?))
+    }
+
+    /// Removes a narrow band from every signal with a zero-phase notch filter
+    ///
+    /// # Arguments
+    ///
+    /// * `frequency` - The center of the removed band in Hz
+    /// * `q_factor` - The ratio of the center frequency to the width of the band, e.g. `30.0`
+    ///
+    /// # Returns
+    ///
+    /// The filtered Recording, or an error as in `TimeSeries::notch`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let clean = recording.notch(50.0, 30.0)?;
+    /// ```
+    ///
+    pub fn notch(&self, frequency: f64, q_factor: f64) -> Result<Recording<T>, ProcessingError> {
+        self.map_signals(|signal| signal.notch(frequency, q_factor))
+    }
+
+    /// Removes the line frequency and its harmonics from every signal
+    ///
+    /// # Arguments
+    ///
+    /// * `base_frequency` - The line frequency in Hz, e.g. `50.0` or `60.0`
+    /// * `n_harmonics` - The number of multiples of the base frequency removed, including it
+    ///
+    /// # Returns
+    ///
+    /// The filtered Recording, or an error as in `remove_line_noise`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let clean = recording.remove_line_noise(60.0, 3)?;
+    /// ```
+    ///
+    pub fn remove_line_noise(&self, base_frequency: f64, n_harmonics: usize) -> Result<Recording<T>, ProcessingError> {
+        self.map_signals(|signal| signal.remove_line_noise(base_frequency, n_harmonics))
+    }
+
+    /// Removes a trend from every channel
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The trend removed, a constant, a line or a polynomial
+    ///
+    /// # Returns
+    ///
+    /// The detrended Recording, or an error as in `detrend`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let flat = recording.detrend(DetrendMethod::Constant)?;
+    /// ```
+    ///
+    pub fn detrend(&self, method: DetrendMethod) -> Result<Recording<T>, ProcessingError> {
+        self.map_signals(|signal| signal.detrend(method))
+    }
+
+    /// Decimates every signal by an integer factor after anti-aliasing
+    ///
+    /// # Arguments
+    ///
+    /// * `factor` - The ratio between the old and the new sampling rate of every signal
+    ///
+    /// # Returns
+    ///
+    /// The decimated Recording, with the same events, or an error as in `decimate`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let compact = recording.decimate(4)?;
+    /// ```
+    ///
+    pub fn decimate(&self, factor: usize) -> Result<Recording<T>, ProcessingError> {
+        self.map_signals(|signal| signal.decimate(factor))
+    }
+
+    /// Resamples every signal to the same sampling rate
+    ///
+    /// # Arguments
+    ///
+    /// * `new_rate` - The requested sampling rate in Hz
+    /// * `method` - The resampling method
+    ///
+    /// # Returns
+    ///
+    /// The resampled Recording, with the same events, or an error as in `resample`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let common = Recording::merge(vec![probe, eye_tracker])?.resample(500.0, ResampleMethod::Sinc)?;
+    /// ```
+    ///
+    pub fn resample(&self, new_rate: f64, method: ResampleMethod) -> Result<Recording<T>, ProcessingError> {
+        self.map_signals(|signal| signal.resample(new_rate, method))
+    }
+
+    /// Returns the amplitude envelope of every channel
+    ///
+    /// # Returns
+    ///
+    /// The Recording of the envelopes, with the same events and session
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let gamma_envelope = recording.filtfilt(4, FilterKind::Bandpass(30.0, 80.0))?.envelope();
+    /// ```
+    ///
+    pub fn envelope(&self) -> Recording<T> {
+        Recording { signals: self.signals.iter().map(TimeSeries::envelope).collect(), events: self.events.clone(), session: self.session.clone() }
+    }
+
+    /// Estimates the power spectral density of every channel with Welch's method
+    ///
+    /// # Arguments
+    ///
+    /// * `segment_duration` - The duration of each segment in seconds, rounded to whole samples at the rate of each signal
+    /// * `overlap_fraction` - The overlap of consecutive segments, from 0 up to but excluding 1
+    /// * `window` - The window applied to each segment
+    ///
+    /// # Returns
+    ///
+    /// The Spectrum of every channel, in the order of `channel_names`, or an error as in `welch`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let spectra = recording.psd(2.0, 0.5, Window::Hann)?;
+    /// for (name, spectrum) in recording.channel_names().iter().zip(&spectra) {
+    ///     println!("{}: alpha {}", name, spectrum.band_power(8.0, 12.0));
+    /// }
+    /// ```
+    ///
+    pub fn psd(&self, segment_duration: f64, overlap_fraction: f64, window: Window) -> Result<Vec<Spectrum>, ProcessingError> {
+        let mut spectra = Vec::with_capacity(self.n_channels());
+        for signal in &self.signals {
+            let segment_len = (segment_duration * signal.sampling_rate()).round() as usize;
+            spectra.extend(signal.psd(segment_len, overlap_fraction, window)?);
+        }
+        Ok(spectra)
+    }
+
+    /// Returns the position of the signal holding a channel and of the channel within it
+    fn find(&self, name: &str) -> Option<(usize, usize)> {
+        self.signals.iter().enumerate().find_map(|(signal, series)| series.channel_index(name).map(|index| (signal, index)))
+    }
+}
+
+/// Returns a channel of a signal with the time base of the signal
+fn channel_of<T: Sample>(signal: &TimeSeries<T>, index: usize) -> RecordingChannel<'_, T> {
+    let name = signal.names()[index].as_str();
+    RecordingChannel {
+        name,
+        samples: &signal[index],
+        unit: signal.unit(name),
+        sampling_rate: signal.sampling_rate(),
+        start_time: signal.start_time(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Writes 1 s at 1 kHz of a channel holding the sample index, without the rows of `dropped`
+    fn write_recording(name: &str, dropped: &[std::ops::Range<usize>]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("neurorust-recording-{}-{}", std::process::id(), name));
+        let mut text = "time,lfp,emg\n".to_string();
+        for i in (0..1000).filter(|i| !dropped.iter().any(|range| range.contains(i))) {
+            text.push_str(&format!("{},{},{}\n", 10.0 + i as f64 / 1000.0, i, -(i as f64) / 2.0));
+        }
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn read(path: &std::path::Path, policy: TimingPolicy) -> Result<(Recording, TimingReport), DataIoError> {
+        Recording::from_csv_with_time(&mut CsvIO::open_read(path.to_str().unwrap()).unwrap(), "time", 0.1, policy)
+    }
+
+    #[test]
+    fn two_dropouts_of_50_ms_are_found_at_their_samples() {
+        let path = write_recording("dropouts.csv", &[200..250, 600..650]);
+        let report = CsvIO::open_read(path.to_str().unwrap()).unwrap().validate_time_column("time", 0.1).unwrap();
+        assert_eq!(report.gaps.iter().map(|gap| (gap.index, gap.missing_samples)).collect::<Vec<_>>(), vec![(200, 50), (550, 50)]);
+        assert!((report.total_missing_duration - 0.1).abs() < 1e-9);
+
+        let (filled, report) = read(&path, TimingPolicy::FillNan).unwrap();
+        assert_eq!(report.gaps.len(), 2);
+        let signal = &filled.signals()[0];
+        assert!((signal.sampling_rate() - 1000.0).abs() < 1e-6);
+        assert_eq!(signal.start_time(), 10.0);
+        assert_eq!(signal.names(), &["lfp", "emg"]);
+        let lfp = &signal.channels()[0];
+        assert_eq!(lfp.len(), 1000);
+        for (i, &sample) in lfp.iter().enumerate() {
+            match (200..250).contains(&i) || (600..650).contains(&i) {
+                true => assert!(sample.is_nan(), "sample {} is {}", i, sample),
+                false => assert_eq!(sample, i as f64),
+            }
+        }
+        assert_eq!(signal.channels()[1][999], -499.5);
+
+        let (kept, _) = read(&path, TimingPolicy::Warn).unwrap();
+        assert_eq!(kept.signals()[0].len(), 900);
+        assert_eq!(kept.signals()[0].channels()[0][200], 250.0);
+
+        let error = read(&path, TimingPolicy::Error).unwrap_err().to_string();
+        assert!(error.contains("2 gaps and 0 overlaps") && error.contains("at sample 200"), "{}", error);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn regular_files_are_read_as_they_are_and_overlaps_are_not_filled() {
+        let path = write_recording("regular.csv", &[]);
+        for policy in [TimingPolicy::Error, TimingPolicy::Warn, TimingPolicy::FillNan] {
+            let (recording, report) = read(&path, policy).unwrap();
+            assert!(report.is_regular());
+            assert_eq!(recording.signals()[0].len(), 1000);
+            assert!(recording.session == SessionInfo::new());
+        }
+        let (single, _) = Recording::<f32>::from_csv_with_time(&mut CsvIO::open_read(path.to_str().unwrap()).unwrap(), "time", 0.1, TimingPolicy::Error).unwrap();
+        assert_eq!(single.signals()[0].channels()[1][3], -1.5f32);
+        assert!(matches!(
+            Recording::<f64>::from_csv_with_time(&mut CsvIO::open_read(path.to_str().unwrap()).unwrap(), "t", 0.1, TimingPolicy::Warn),
+            Err(DataIoError::ColumnNotFound(_))
+        ));
+
+        let text = std::fs::read_to_string(&path).unwrap();
+        let mut lines: Vec<&str> = text.lines().collect();
+        lines.swap(101, 102);
+        std::fs::write(&path, lines.join("\n") + "\n").unwrap();
+        let error = read(&path, TimingPolicy::FillNan).unwrap_err().to_string();
+        assert!(error.contains("overlaps cannot be filled"), "{}", error);
+        assert_eq!(read(&path, TimingPolicy::Warn).unwrap().0.signals()[0].len(), 1000);
+        std::fs::write(&path, "time,lfp\n1,0\n").unwrap();
+        assert!(read(&path, TimingPolicy::Warn).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    fn two_rate_recording() -> Recording {
+        let signal = |rate: f64, name: &str| {
+            let samples = (0..(4.0 * rate) as usize).map(|k| (2.0 * std::f64::consts::PI * 10.0 * k as f64 / rate).sin() + (k % 7) as f64 * 0.01).collect();
+            TimeSeries::new(vec![samples], vec![name.to_string()], rate, 1.0).unwrap()
+        };
+        let mut recording = Recording::new(SessionInfo::new());
+        recording.add_signal(signal(1000.0, "lfp")).unwrap();
+        recording.add_signal(signal(250.0, "eye")).unwrap();
+        recording.add_events("stimulus", Events::new(vec![1.5, 2.5], vec!["a".to_string(), "b".to_string()], None).unwrap());
+        recording.set_probe("A1x32");
+        recording.set_seams(&[3.0]);
+        recording
+    }
+
+    #[test]
+    fn wrappers_design_the_filter_for_each_signal_and_keep_events_and_session() {
+        let recording = two_rate_recording();
+        let kind = FilterKind::Bandpass(5.0, 20.0);
+        let filtered = recording.filtfilt(4, kind).unwrap();
+        for (processed, signal) in filtered.signals().iter().zip(recording.signals()) {
+            assert_eq!(processed, &signal.filtfilt(&butterworth(4, kind, signal.sampling_rate()).unwrap()).unwrap());
+        }
+        assert_eq!(filtered.events("stimulus"), recording.events("stimulus"));
+        assert_eq!((filtered.probe(), filtered.seams().unwrap()), (Some("A1x32"), vec![3.0]));
+        assert_eq!(recording.filter(2, FilterKind::Highpass(1.0)).unwrap().signals()[1], recording.signals()[1].filter(&butterworth(2, FilterKind::Highpass(1.0), 250.0).unwrap()).unwrap());
+        assert_eq!(recording.notch(50.0, 30.0).unwrap().signals()[0], recording.signals()[0].notch(50.0, 30.0).unwrap());
+        assert_eq!(recording.envelope().signals()[1], recording.signals()[1].envelope());
+        // A cutoff above the Nyquist frequency of one signal is an error for the whole Recording
+        assert!(recording.filtfilt(4, FilterKind::Lowpass(200.0)).is_err());
+    }
+
+    #[test]
+    fn resampling_wrappers_change_every_rate_and_psd_segments_share_a_duration() {
+        let recording = two_rate_recording();
+        let decimated = recording.decimate(2).unwrap();
+        assert_eq!(decimated.signals().iter().map(|signal| (signal.sampling_rate(), signal.len(), signal.start_time())).collect::<Vec<_>>(), vec![(500.0, 2000, 1.0), (125.0, 500, 1.0)]);
+        let resampled = recording.resample(200.0, ResampleMethod::Linear).unwrap();
+        assert!(resampled.signals().iter().all(|signal| signal.sampling_rate() == 200.0 && signal.len() == 800));
+        assert_eq!(resampled.events("stimulus"), recording.events("stimulus"));
+        assert_eq!(recording.detrend(DetrendMethod::Constant).unwrap().signals()[0], recording.signals()[0].detrend(DetrendMethod::Constant).unwrap());
+
+        let spectra = recording.psd(1.0, 0.5, Window::Hann).unwrap();
+        assert_eq!(spectra.len(), recording.n_channels());
+        assert_eq!(spectra[0], recording.signals()[0].psd(1000, 0.5, Window::Hann).unwrap()[0]);
+        assert_eq!(spectra[1], recording.signals()[1].psd(250, 0.5, Window::Hann).unwrap()[0]);
+        for spectrum in &spectra {
+            assert!((spectrum.resolution() - 1.0).abs() < 1e-12);
+            assert!((spectrum.peak().0 - 10.0).abs() < 1e-9);
+        }
+        let trimmed = recording.map_signals(|signal| signal.slice_time(2.0, 3.0)).unwrap();
+        assert!(trimmed.signals().iter().all(|signal| (signal.duration() - 1.0).abs() < 1e-9));
+    }
+
+    /// A recording from one file: two signals at different rates, a stimulus channel and a subject
+    fn session_recording(subject: &str, prefix: &str) -> Recording {
+        let mut session = SessionInfo::new();
+        session.subject_id = Some(subject.to_string());
+        session.experimenter = Some("Amin".to_string());
+        let mut recording = Recording::new(session);
+        let lfp = TimeSeries::new(vec![vec![1.0, 2.0, 3.0, 4.0], vec![5.0, 6.0, 7.0, 8.0]], vec![format!("{}lfp1", prefix), format!("{}lfp2", prefix)], 2.0, 1.0)
+            .unwrap()
+            .with_units(&["uV", "uV"])
+            .unwrap();
+        let emg = TimeSeries::new(vec![vec![-1.0; 10]], vec![format!("{}emg", prefix)], 10.0, 0.5).unwrap();
+        recording.add_signal(lfp).unwrap();
+        recording.add_signal(emg).unwrap();
+        recording.add_events("stim", Events::new(vec![1.5, 2.5], vec!["on".to_string(), "off".to_string()], None).unwrap());
+        recording
+    }
+
+    #[test]
+    fn signals_events_and_session_are_grouped() {
+        let mut recording = session_recording("m01", "");
+        assert_eq!(recording.signals().len(), 2);
+        assert_eq!(recording.n_channels(), 3);
+        assert_eq!(recording.channel_names(), vec!["lfp1", "lfp2", "emg"]);
+        assert_eq!(recording.session.get("subject_id"), Some("m01"));
+        assert_eq!(recording.session.get("experimenter"), Some("Amin"));
+
+        let clash = TimeSeries::new(vec![vec![0.0; 4]], vec!["lfp2".to_string()], 2.0, 1.0).unwrap();
+        assert_eq!(recording.add_signal(clash).unwrap_err().to_string(), "Invalid parameter: Channel 'lfp2' is already in the recording");
+        assert_eq!(recording.n_channels(), 3);
+
+        recording.add_events("stim", Events::new(vec![0.5], vec!["on".to_string()], None).unwrap());
+        recording.add_events("licks", Events::new(vec![3.0], vec!["lick".to_string()], None).unwrap());
+        assert_eq!(recording.event_channels(), vec!["licks", "stim"]);
+        let stim = recording.events("stim").unwrap();
+        assert_eq!(stim.times(), &[0.5, 1.5, 2.5]);
+        assert_eq!(stim.labels(), &["on", "on", "off"]);
+        assert!(recording.events("reward").is_none());
+    }
+
+    #[test]
+    fn channels_are_iterated_in_order_and_found_by_name() {
+        let recording = session_recording("m01", "");
+        let channels: Vec<RecordingChannel> = recording.channels().collect();
+        assert_eq!(channels.iter().map(|channel| channel.name).collect::<Vec<_>>(), vec!["lfp1", "lfp2", "emg"]);
+        assert_eq!(channels[1].samples, &[5.0, 6.0, 7.0, 8.0]);
+        assert_eq!(channels[1].unit, Some("uV"));
+        assert_eq!((channels[1].sampling_rate, channels[1].start_time), (2.0, 1.0));
+        assert_eq!(channels[2].unit, None);
+        assert_eq!((channels[2].sampling_rate, channels[2].start_time), (10.0, 0.5));
+
+        assert_eq!(recording.channel("emg"), Some(channels[2]));
+        assert!(recording.channel("eeg").is_none());
+        assert_eq!(recording.time_range(), Some((0.5, 3.0)));
+        assert_eq!(Recording::<f64>::new(SessionInfo::new()).time_range(), None);
+    }
+
+    #[test]
+    fn select_keeps_the_asked_channels_with_events_and_session() {
+        let recording = session_recording("m01", "");
+        let subset = recording.select(&["emg", "lfp2"]).unwrap();
+        assert_eq!(subset.channel_names(), vec!["lfp2", "emg"]);
+        assert_eq!(subset.signals().len(), 2);
+        assert_eq!(subset.channel("lfp2").unwrap().samples, &[5.0, 6.0, 7.0, 8.0]);
+        assert_eq!(subset.channel("lfp2").unwrap().unit, Some("uV"));
+        assert_eq!(subset.event_channels(), vec!["stim"]);
+        assert_eq!(subset.session, recording.session);
+
+        let lfp_only = recording.select(&["lfp1"]).unwrap();
+        assert_eq!(lfp_only.signals().len(), 1);
+        assert_eq!(lfp_only.channel_names(), vec!["lfp1"]);
+
+        assert_eq!(recording.select(&["lfp1", "eeg"]).unwrap_err().to_string(), "Invalid parameter: Channel 'eeg' not found");
+        assert_eq!(recording.select(&["emg", "emg"]).unwrap_err().to_string(), "Invalid parameter: Channel 'emg' is given twice");
+    }
+
+    #[test]
+    fn recordings_from_several_files_merge_into_one() {
+        let morning = session_recording("m01", "a_");
+        let afternoon = session_recording("m01", "b_");
+        let merged = Recording::merge(vec![morning.clone(), afternoon]).unwrap();
+        assert_eq!(merged.channel_names(), vec!["a_lfp1", "a_lfp2", "a_emg", "b_lfp1", "b_lfp2", "b_emg"]);
+        assert_eq!(merged.session, morning.session);
+        assert_eq!(merged.event_channels(), vec!["stim"]);
+        assert_eq!(merged.events("stim").unwrap().times(), &[1.5, 1.5, 2.5, 2.5]);
+
+        let other_subject = session_recording("m02", "b_");
+        assert_eq!(
+            Recording::merge(vec![morning.clone(), other_subject]).unwrap_err().to_string(),
+            "Invalid parameter: Recordings disagree on the session key 'subject_id': 'm01' and 'm02'"
+        );
+        assert_eq!(
+            Recording::merge(vec![morning.clone(), morning]).unwrap_err().to_string(),
+            "Invalid parameter: Channel 'a_lfp1' is already in the recording"
+        );
+        assert_eq!(Recording::<f64>::merge(Vec::new()).unwrap(), Recording::default());
+    }
+
+    #[test]
+    fn probe_and_seams_round_trip_through_the_session() {
+        let mut recording = session_recording("m01", "");
+        assert_eq!(recording.probe(), None);
+        recording.set_probe("NeuroNexus A1x16");
+        assert_eq!(recording.probe(), Some("NeuroNexus A1x16"));
+        assert_eq!(recording.session.get(PROBE_KEY), Some("NeuroNexus A1x16"));
+
+        assert_eq!(recording.seams().unwrap(), Vec::<f64>::new());
+        recording.set_seams(&[0.5, 12.25]);
+        assert_eq!(recording.seams().unwrap(), vec![0.5, 12.25]);
+        recording.set_seams(&[]);
+        assert!(recording.session.get(SEAMS_KEY).is_none());
+
+        recording.session.set_extra(SEAMS_KEY, "0.5;soon");
+        assert_eq!(recording.seams().unwrap_err().to_string(), "Invalid parameter: The session extra 'seams' holds '0.5;soon', which are not times");
+        recording.session.set_extra(SEAMS_KEY, "inf");
+        assert!(recording.seams().is_err());
+    }
+
+    #[test]
+    fn to_f32_keeps_the_layout() {
+        let recording = session_recording("m01", "");
+        let single = recording.to_f32();
+        assert_eq!(single.channel_names(), recording.channel_names());
+        assert_eq!(single.channel("lfp1").unwrap().samples, &[1.0f32, 2.0, 3.0, 4.0]);
+        assert_eq!(single.event_channels(), vec!["stim"]);
+        assert_eq!(single.session, recording.session);
+    }
+}
@@ -0,0 +1,89 @@
+// A module to define the sample types a TimeSeries can store
+
+// Written by Amin Alam in 2024
+
+use std::fmt::Debug;
+use std::str::FromStr;
+use crate::data_io::float_format::{FloatFormat, FloatNotation};
+
+/// A floating-point type the samples of a TimeSeries are stored as
+///
+/// # Examples
+///
+/// ```
+/// // Half the memory of the default f64 storage for a long high-density recording
+/// let probe = TimeSeries::<f32>::from_csv(&mut CsvIO::open_read("probe.csv")?, "time", Some(30000.0))?;
+/// let filtered = probe.apply_f64(|samples| bandpass.filtfilt(samples))?;
+/// ```
+///
+/// # Note
+///
+/// Only storage is generic. Times, sampling rates and every processing function stay in
+/// f64, and `TimeSeries::apply_f64` widens one channel at a time to pass f32 samples to
+/// them. This matters most where errors build up over many samples: the state of IIR
+/// filters (`IirFilter`, `notch`, `remove_line_noise`), the sums of `detrend`, `smooth`
+/// and the running statistics of `StreamingStats`, and the FFTs of `welch` and
+/// `spectrogram`. An f32 keeps about 7 significant digits, far below the noise of
+/// recorded signals, but results computed from f32 samples differ in the last digits
+/// from those computed from the original f64 values.
+pub trait Sample: Copy + Default + PartialEq + PartialOrd + Debug + FromStr + Send + Sync + 'static {
+    /// Converts an f64 to this type, rounding to the nearest value
+    fn from_f64(value: f64) -> Self;
+
+    /// Converts the sample to an f64, exactly
+    fn to_f64(self) -> f64;
+
+    /// Formats the sample with a FloatFormat, as `FloatFormat::format` formats an f64
+    fn format(self, format: &FloatFormat) -> String;
+}
+
+impl Sample for f64 {
+    fn from_f64(value: f64) -> Self {
+        value
+    }
+
+    fn to_f64(self) -> f64 {
+        self
+    }
+
+    fn format(self, format: &FloatFormat) -> String {
+        format.format(self)
+    }
+}
+
+impl Sample for f32 {
+    fn from_f64(value: f64) -> Self {
+        value as f32
+    }
+
+    fn to_f64(self) -> f64 {
+        self as f64
+    }
+
+    fn format(self, format: &FloatFormat) -> String {
+        // The shortest text of the f32 itself, not of its exact f64 value, e.g. 0.1 and not 0.10000000149011612
+        let value = match format.notation {
+            FloatNotation::Shortest => self.to_string().parse().unwrap_or(self as f64),
+            _ => self as f64,
+        };
+        format.format(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn f32_samples_format_with_their_own_shortest_digits() {
+        let format = FloatFormat::default();
+        assert_eq!(0.1f32.format(&format), "0.1");
+        assert_eq!(1e-7f32.format(&format), "1e-7");
+        assert_eq!(f32::NAN.format(&format), "NaN");
+        assert_eq!(0.1f32.format(&FloatFormat::significant(12)), "0.10000000149");
+        assert_eq!(0.1f64.format(&format), "0.1");
+        for value in [0.1f32, 3.4028235e38, 1.1754944e-38, -2.5e-3] {
+            assert_eq!(value.format(&format).parse::<f32>().unwrap(), value);
+        }
+    }
+}
@@ -0,0 +1,436 @@
+// A module to describe, load and save recording session metadata
+
+// Written by Amin Alam in 2024
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use csv::{Reader, Writer};
+use crate::processing::checkpoint::quote;
+
+/// Metadata describing a single recording session
+///
+/// # Arguments
+///
+/// * `subject_id` - The identifier of the recorded subject
+/// * `session_date` - The date of the session, kept as written by the user (e.g. `2024-03-01`)
+/// * `experimenter` - The person who ran the session
+/// * `rig` - The setup or rig the session was recorded on
+/// * `notes` - Free-form notes about the session
+/// * `extras` - Arbitrary additional key-value pairs
+///
+/// # Examples
+///
+/// ```
+/// let mut info = SessionInfo::new();
+/// info.subject_id = Some("mouse-12".to_string());
+/// info.set_extra("implant", "left CA1");
+/// info.save("session.csv").expect("Could not save session info");
+/// ```
+///
+/// # Note
+///
+/// The sidecar format is chosen by the file extension: `.json` files are written as a
+/// flat JSON object, everything else as a two-column `key,value` CSV file
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionInfo {
+    pub subject_id: Option<String>,
+    pub session_date: Option<String>,
+    pub experimenter: Option<String>,
+    pub rig: Option<String>,
+    pub notes: Option<String>,
+    pub extras: BTreeMap<String, String>,
+}
+
+/// The keys under which the named fields are stored in the sidecar files
+const SUBJECT_ID_KEY: &str = "subject_id";
+const SESSION_DATE_KEY: &str = "session_date";
+const EXPERIMENTER_KEY: &str = "experimenter";
+const RIG_KEY: &str = "rig";
+const NOTES_KEY: &str = "notes";
+
+/// Implementation of the SessionInfo struct
+///
+/// # Methods
+///
+/// * `new` - Creates an empty SessionInfo object
+/// * `get` - Looks up a named field or an extra by key
+/// * `set` - Sets a named field or an extra by key
+/// * `set_extra` - Sets an extra key-value pair
+/// * `entries` - Lists all non-empty key-value pairs
+/// * `load` - Loads a SessionInfo object from a CSV or JSON sidecar
+/// * `save` - Saves the SessionInfo object to a CSV or JSON sidecar
+/// * `from_bids_path` - Parses the BIDS entities of a file name
+///
+/// # Examples
+///
+/// ```
+/// let info = SessionInfo::load("sub-01_ses-02_sessions.csv").expect("Could not load session info");
+/// ```
+impl SessionInfo {
+    /// Creates an empty SessionInfo object
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let info = SessionInfo::new();
+    /// ```
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Looks up a value by key
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of a named field (e.g. `subject_id`) or of an extra
+    ///
+    /// # Returns
+    ///
+    /// The value stored under the key, if any
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let subject = info.get("subject_id");
+    /// ```
+    ///
+    pub fn get(&self, key: &str) -> Option<&str> {
+        match key {
+            SUBJECT_ID_KEY => self.subject_id.as_deref(),
+            SESSION_DATE_KEY => self.session_date.as_deref(),
+            EXPERIMENTER_KEY => self.experimenter.as_deref(),
+            RIG_KEY => self.rig.as_deref(),
+            NOTES_KEY => self.notes.as_deref(),
+            _ => self.extras.get(key).map(|value| value.as_str()),
+        }
+    }
+
+    /// Sets a value by key
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of a named field (e.g. `subject_id`) or of an extra
+    /// * `value` - The value to store
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// info.set("experimenter", "A. Alam");
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Keys that do not match a named field are stored in `extras`
+    ///
+    pub fn set(&mut self, key: &str, value: &str) {
+        let value = value.to_string();
+        match key {
+            SUBJECT_ID_KEY => self.subject_id = Some(value),
+            SESSION_DATE_KEY => self.session_date = Some(value),
+            EXPERIMENTER_KEY => self.experimenter = Some(value),
+            RIG_KEY => self.rig = Some(value),
+            NOTES_KEY => self.notes = Some(value),
+            _ => {
+                self.extras.insert(key.to_string(), value);
+            }
+        }
+    }
+
+    /// Sets an extra key-value pair
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - The key of the extra
+    /// * `value` - The value to store
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// info.set_extra("implant", "left CA1");
+    /// ```
+    ///
+    pub fn set_extra(&mut self, key: &str, value: &str) {
+        self.extras.insert(key.to_string(), value.to_string());
+    }
+
+    /// Lists all non-empty key-value pairs
+    ///
+    /// # Returns
+    ///
+    /// The named fields in declaration order, followed by the extras sorted by key
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// for (key, value) in info.entries() {
+    ///     println!("{}: {}", key, value);
+    /// }
+    /// ```
+    ///
+    pub fn entries(&self) -> Vec<(&str, &str)> {
+        let named = [
+            (SUBJECT_ID_KEY, &self.subject_id),
+            (SESSION_DATE_KEY, &self.session_date),
+            (EXPERIMENTER_KEY, &self.experimenter),
+            (RIG_KEY, &self.rig),
+            (NOTES_KEY, &self.notes),
+        ];
+        let mut entries: Vec<(&str, &str)> = named
+            .iter()
+            .filter_map(|(key, value)| value.as_deref().map(|value| (*key, value)))
+            .collect();
+        for (key, value) in &self.extras {
+            entries.push((key.as_str(), value.as_str()));
+        }
+        entries
+    }
+
+    /// Loads a SessionInfo object from a sidecar file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to a `.json` file or a two-column `key,value` CSV file
+    ///
+    /// # Returns
+    ///
+    /// The loaded SessionInfo object, or an error if the file cannot be read or parsed
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let info = SessionInfo::load("session.csv").expect("Could not load session info");
+    /// ```
+    ///
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref();
+        let entries = if is_json(path) {
+            let mut text = String::new();
+            File::open(path)?.read_to_string(&mut text)?;
+            parse_flat_json(&text)?
+        } else {
+            read_key_value_csv(path)?
+        };
+
+        let mut info = Self::new();
+        for (key, value) in entries {
+            info.set(&key, &value);
+        }
+        Ok(info)
+    }
+
+    /// Saves the SessionInfo object to a sidecar file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to a `.json` file or a two-column `key,value` CSV file
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// info.save("session.json").expect("Could not save session info");
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Fields that are not set are not written
+    ///
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let entries = self.entries();
+        if is_json(path) {
+            let mut writer = BufWriter::new(File::create(path)?);
+            writer.write_all(format_flat_json(&entries).as_bytes())?;
+            writer.flush()
+        } else {
+            let mut writer = Writer::from_writer(BufWriter::new(File::create(path)?));
+            writer.write_record(["key", "value"])?;
+            for (key, value) in entries {
+                writer.write_record([key, value])?;
+            }
+            writer.flush()
+        }
+    }
+
+    /// Parses the BIDS entities of a file name into a SessionInfo object
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - A BIDS-style path such as `sub-01/ses-02/eeg/sub-01_ses-02_task-oddball_eeg.csv`
+    ///
+    /// # Returns
+    ///
+    /// A SessionInfo object with `subject_id` set from the `sub` entity and every other
+    /// entity (`ses`, `task`, `run`, ...) stored as an extra under its BIDS key
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let info = SessionInfo::from_bids_path("sub-01_ses-02_task-oddball_eeg.csv");
+    /// assert_eq!(info.subject_id.as_deref(), Some("01"));
+    /// assert_eq!(info.get("task"), Some("oddball"));
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The trailing suffix without a value (e.g. `eeg`) is stored under the `suffix` key.
+    /// Entities missing from the file name are looked up in the parent directories.
+    ///
+    pub fn from_bids_path<P: AsRef<Path>>(path: P) -> Self {
+        let path = path.as_ref();
+        let mut info = Self::new();
+
+        let file_name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        let stem = file_name.split('.').next().unwrap_or("");
+        for token in stem.split('_').filter(|token| !token.is_empty()) {
+            match token.split_once('-') {
+                Some((key, value)) => info.set_bids_entity(key, value),
+                None => info.set_extra("suffix", token),
+            }
+        }
+
+        let parents = path.parent().into_iter().flat_map(|parent| parent.components());
+        for component in parents {
+            let name = component.as_os_str().to_str().unwrap_or("");
+            if let Some((key, value)) = name.split_once('-') {
+                if (key == "sub" || key == "ses") && info.get_bids_entity(key).is_none() {
+                    info.set_bids_entity(key, value);
+                }
+            }
+        }
+        info
+    }
+
+    fn set_bids_entity(&mut self, key: &str, value: &str) {
+        if key == "sub" {
+            self.subject_id = Some(value.to_string());
+        } else {
+            self.set_extra(key, value);
+        }
+    }
+
+    fn get_bids_entity(&self, key: &str) -> Option<&str> {
+        if key == "sub" {
+            self.subject_id.as_deref()
+        } else {
+            self.extras.get(key).map(|value| value.as_str())
+        }
+    }
+}
+
+fn is_json(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.eq_ignore_ascii_case("json"))
+        .unwrap_or(false)
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_key_value_csv(path: &Path) -> io::Result<Vec<(String, String)>> {
+    let mut reader = Reader::from_reader(BufReader::new(File::open(path)?));
+    let headers = reader.headers()?.clone();
+    if headers.len() != 2 || &headers[0] != "key" || &headers[1] != "value" {
+        return Err(invalid_data(format!(
+            "Expected a `key,value` header in {}, found {:?}",
+            path.display(),
+            headers
+        )));
+    }
+
+    let mut entries = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        entries.push((record[0].to_string(), record[1].to_string()));
+    }
+    Ok(entries)
+}
+
+fn format_flat_json(entries: &[(&str, &str)]) -> String {
+    let mut json = String::from("{\n");
+    for (i, (key, value)) in entries.iter().enumerate() {
+        let separator = if i + 1 < entries.len() { "," } else { "" };
+        json.push_str(&format!("  {}: {}{}\n", quote(key), quote(value), separator));
+    }
+    json.push_str("}\n");
+    json
+}
+
+
+/// Parses a JSON object whose values are all strings, as written by `format_flat_json`
+fn parse_flat_json(text: &str) -> io::Result<Vec<(String, String)>> {
+    let mut chars = text.chars().peekable();
+    let mut entries = Vec::new();
+
+    skip_whitespace(&mut chars);
+    expect_char(&mut chars, '{')?;
+    skip_whitespace(&mut chars);
+    if chars.peek() == Some(&'}') {
+        return Ok(entries);
+    }
+    loop {
+        skip_whitespace(&mut chars);
+        let key = parse_json_string(&mut chars)?;
+        skip_whitespace(&mut chars);
+        expect_char(&mut chars, ':')?;
+        skip_whitespace(&mut chars);
+        let value = parse_json_string(&mut chars)?;
+        entries.push((key, value));
+        skip_whitespace(&mut chars);
+        match chars.next() {
+            Some(',') => continue,
+            Some('}') => break,
+            other => return Err(invalid_data(format!("Expected `,` or `}}` in session JSON, found {:?}", other))),
+        }
+    }
+    Ok(entries)
+}
+
+fn skip_whitespace(chars: &mut std::iter::Peekable<std::str::Chars>) {
+    while chars.peek().map(|c| c.is_whitespace()).unwrap_or(false) {
+        chars.next();
+    }
+}
+
+fn expect_char(chars: &mut std::iter::Peekable<std::str::Chars>, expected: char) -> io::Result<()> {
+    match chars.next() {
+        Some(c) if c == expected => Ok(()),
+        other => Err(invalid_data(format!("Expected `{}` in session JSON, found {:?}", expected, other))),
+    }
+}
+
+fn parse_json_string(chars: &mut std::iter::Peekable<std::str::Chars>) -> io::Result<String> {
+    expect_char(chars, '"')?;
+    let mut text = String::new();
+    loop {
+        match chars.next() {
+            Some('"') => return Ok(text),
+            Some('\\') => match chars.next() {
+                Some('"') => text.push('"'),
+                Some('\\') => text.push('\\'),
+                Some('/') => text.push('/'),
+                Some('n') => text.push('\n'),
+                Some('r') => text.push('\r'),
+                Some('t') => text.push('\t'),
+                Some('b') => text.push('\u{8}'),
+                Some('f') => text.push('\u{c}'),
+                Some('u') => {
+                    let code: String = chars.by_ref().take(4).collect();
+                    let c = u32::from_str_radix(&code, 16)
+                        .ok()
+                        .and_then(char::from_u32)
+                        .ok_or_else(|| invalid_data(format!("Invalid unicode escape `\\u{}` in session JSON", code)))?;
+                    text.push(c);
+                }
+                other => return Err(invalid_data(format!("Invalid escape {:?} in session JSON", other))),
+            },
+            Some(c) => text.push(c),
+            None => return Err(invalid_data("Unterminated string in session JSON".to_string())),
+        }
+    }
+}
@@ -0,0 +1,550 @@
+// A module to hold the spike times and waveforms of a unit over the interval it was recorded in
+
+// Written by Amin Alam in 2024
+
+use crate::processing::error::ProcessingError;
+use crate::processing::rate::{bin_counts, binned_rate, FiringRate};
+use crate::processing::spikes::SpikeDetectionResult;
+
+/// The spikes of a unit or channel over the interval they were recorded in
+///
+/// # Arguments
+///
+/// * `times` - The time of each spike in seconds, sorted
+/// * `unit_id` - The unit the spikes were sorted into, or None if they are unsorted
+/// * `waveforms` - The snippet of each spike, all of the same length, or None if not kept
+/// * `t_start` - The start of the recording interval in seconds
+/// * `t_stop` - The end of the recording interval in seconds
+///
+/// # Examples
+///
+/// ```
+/// let train = SpikeTrain::new(times, None, 0.0, 600.0)?.with_unit_id(3);
+/// let counts = train.slice_time(120.0, 180.0)?.binned_counts(0.05)?;
+/// println!("{:.2} spikes/s", train.firing_rate());
+/// ```
+///
+/// # Note
+///
+/// The interval is kept apart from the spikes so that rates count the silent periods at
+/// its edges. `times()` can be passed to the spike statistics functions directly.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpikeTrain {
+    times: Vec<f64>,
+    unit_id: Option<usize>,
+    waveforms: Option<Vec<Vec<f64>>>,
+    t_start: f64,
+    t_stop: f64,
+}
+
+/// Implementation of the SpikeTrain struct
+///
+/// # Methods
+///
+/// * `new` - Creates a SpikeTrain from spike times in any order
+/// * `from_detection` - Creates a SpikeTrain from the spikes detected in a signal
+/// * `with_unit_id` - Sets the unit the spikes were sorted into
+/// * `times` - Returns the spike times
+/// * `unit_id` - Returns the unit the spikes were sorted into
+/// * `waveforms` - Returns the waveform of every spike
+/// * `t_start` - Returns the start of the recording interval
+/// * `t_stop` - Returns the end of the recording interval
+/// * `duration` - Returns the length of the recording interval
+/// * `len` - Returns the number of spikes
+/// * `is_empty` - Checks whether there are no spikes
+/// * `slice_time` - Keeps the spikes within a time range
+/// * `merge` - Joins several spike trains into one
+/// * `firing_rate` - Returns the mean firing rate over the recording interval
+/// * `binned_counts` - Counts the spikes in consecutive bins over the recording interval
+/// * `binned_rate` - Estimates the firing rate in consecutive bins over the recording interval
+impl SpikeTrain {
+    /// Creates a SpikeTrain from spike times in any order
+    ///
+    /// # Arguments
+    ///
+    /// * `times` - The time of each spike in seconds
+    /// * `waveforms` - The snippet of each spike in the order of `times`, or None
+    /// * `t_start` - The start of the recording interval in seconds
+    /// * `t_stop` - The end of the recording interval in seconds
+    ///
+    /// # Returns
+    ///
+    /// The SpikeTrain with the spikes sorted by time, or an error if the interval ends
+    /// before it starts, a spike is outside of it, or there is not one waveform of the same
+    /// length per spike
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let train = SpikeTrain::new(vec![0.12, 0.05, 0.61], None, 0.0, 1.0)?;
+    /// assert_eq!(train.times(), &[0.05, 0.12, 0.61]);
+    /// ```
+    ///
+    pub fn new(times: Vec<f64>, waveforms: Option<Vec<Vec<f64>>>, t_start: f64, t_stop: f64) -> Result<Self, ProcessingError> {
+        if !(t_start.is_finite() && t_stop.is_finite() && t_start <= t_stop) {
+            return Err(ProcessingError::InvalidParameter(format!("Recording must not end before it starts, got {} to {} s", t_start, t_stop)));
+        }
+        if let Some(time) = times.iter().find(|time| !(t_start..=t_stop).contains(*time)) {
+            return Err(ProcessingError::InvalidParameter(format!("Spike at {} s is outside the recording from {} to {} s", time, t_start, t_stop)));
+        }
+        let mut order: Vec<usize> = (0..times.len()).collect();
+        order.sort_by(|&a, &b| times[a].total_cmp(&times[b]));
+        let waveforms = match waveforms {
+            Some(waveforms) => {
+                if waveforms.len() != times.len() {
+                    return Err(ProcessingError::InvalidParameter(format!("{} waveforms were given for {} spikes", waveforms.len(), times.len())));
+                }
+                if waveforms.iter().any(|waveform| waveform.len() != waveforms[0].len()) {
+                    return Err(ProcessingError::InvalidParameter("Waveforms must all have the same length".to_string()));
+                }
+                Some(order.iter().map(|&spike| waveforms[spike].clone()).collect())
+            }
+            None => None,
+        };
+        let times = order.iter().map(|&spike| times[spike]).collect();
+        Ok(Self { times, unit_id: None, waveforms, t_start, t_stop })
+    }
+
+    /// Creates a SpikeTrain from the spikes detected in a signal
+    ///
+    /// # Arguments
+    ///
+    /// * `result` - The spikes detected with `spikes::detect`
+    /// * `t_start` - The start of the recording interval in seconds, usually the start of the signal
+    /// * `t_stop` - The end of the recording interval in seconds, usually the end of the signal
+    ///
+    /// # Returns
+    ///
+    /// The SpikeTrain with the times and waveforms of the spikes, or an error as for `new`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let result = detect(&filtered, 30000.0, 0.0, &SpikeDetectionOptions::default())?;
+    /// let train = SpikeTrain::from_detection(&result, 0.0, filtered.len() as f64 / 30000.0)?;
+    /// ```
+    ///
+    pub fn from_detection(result: &SpikeDetectionResult, t_start: f64, t_stop: f64) -> Result<Self, ProcessingError> {
+        Self::new(result.times.clone(), Some(result.waveforms.clone()), t_start, t_stop)
+    }
+
+    /// Sets the unit the spikes were sorted into
+    ///
+    /// # Arguments
+    ///
+    /// * `unit_id` - The identifier of the unit, e.g. a label of `kmeans`
+    ///
+    /// # Returns
+    ///
+    /// The SpikeTrain with the unit ID
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let units: Vec<SpikeTrain> = split_by_labels(&times, &labels)?
+    ///     .into_iter()
+    ///     .enumerate()
+    ///     .map(|(unit, times)| Ok(SpikeTrain::new(times, None, 0.0, 600.0)?.with_unit_id(unit)))
+    ///     .collect::<Result<_, ProcessingError>>()?;
+    /// ```
+    ///
+    pub fn with_unit_id(mut self, unit_id: usize) -> Self {
+        self.unit_id = Some(unit_id);
+        self
+    }
+
+    /// Returns the spike times
+    ///
+    /// # Returns
+    ///
+    /// The time of each spike in seconds, sorted
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (n_violations, fraction) = refractory_violations(train.times(), DEFAULT_REFRACTORY_PERIOD);
+    /// ```
+    ///
+    pub fn times(&self) -> &[f64] {
+        &self.times
+    }
+
+    /// Returns the unit the spikes were sorted into
+    ///
+    /// # Returns
+    ///
+    /// The unit ID, or None if the spikes are unsorted
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let unit = train.unit_id().map_or("unsorted".to_string(), |unit| unit.to_string());
+    /// ```
+    ///
+    pub fn unit_id(&self) -> Option<usize> {
+        self.unit_id
+    }
+
+    /// Returns the waveform of every spike
+    ///
+    /// # Returns
+    ///
+    /// The snippet of each spike in the order of `times`, or None if they were not kept
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// if let Some(waveforms) = train.waveforms() {
+    ///     let features = pca(waveforms, 3)?;
+    /// }
+    /// ```
+    ///
+    pub fn waveforms(&self) -> Option<&[Vec<f64>]> {
+        self.waveforms.as_deref()
+    }
+
+    /// Returns the start of the recording interval
+    ///
+    /// # Returns
+    ///
+    /// The time in seconds
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let t_start = train.t_start();
+    /// ```
+    ///
+    pub fn t_start(&self) -> f64 {
+        self.t_start
+    }
+
+    /// Returns the end of the recording interval
+    ///
+    /// # Returns
+    ///
+    /// The time in seconds
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let t_stop = train.t_stop();
+    /// ```
+    ///
+    pub fn t_stop(&self) -> f64 {
+        self.t_stop
+    }
+
+    /// Returns the length of the recording interval
+    ///
+    /// # Returns
+    ///
+    /// The duration in seconds
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// println!("{} spikes in {:.1} s", train.len(), train.duration());
+    /// ```
+    ///
+    pub fn duration(&self) -> f64 {
+        self.t_stop - self.t_start
+    }
+
+    /// Returns the number of spikes
+    ///
+    /// # Returns
+    ///
+    /// The number of spikes
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let n_spikes = train.len();
+    /// ```
+    ///
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    /// Checks whether there are no spikes
+    ///
+    /// # Returns
+    ///
+    /// True if there are no spikes
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let silent: Vec<&SpikeTrain> = units.iter().filter(|train| train.is_empty()).collect();
+    /// ```
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    /// Keeps the spikes within a time range
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - The start of the range in seconds, included
+    /// * `end` - The end of the range in seconds, excluded
+    ///
+    /// # Returns
+    ///
+    /// The SpikeTrain of the spikes in the range, over the part of the recording interval
+    /// within the range, or an error if the start is after the end
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let first_minute = train.slice_time(0.0, 60.0)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The unit ID and the waveforms of the spikes kept are kept. A range outside of the
+    /// recording interval gives a train with no spikes and an interval of zero length at
+    /// its nearest edge.
+    ///
+    pub fn slice_time(&self, start: f64, end: f64) -> Result<SpikeTrain, ProcessingError> {
+        if start.is_nan() || end.is_nan() || start > end {
+            return Err(ProcessingError::InvalidParameter(format!("Start time {} must not be after end time {}", start, end)));
+        }
+        let t_start = start.clamp(self.t_start, self.t_stop);
+        let t_stop = end.clamp(self.t_start, self.t_stop);
+        let first = self.times.partition_point(|&time| time < start);
+        let last = self.times.partition_point(|&time| time < end).max(first);
+        Ok(SpikeTrain {
+            times: self.times[first..last].to_vec(),
+            unit_id: self.unit_id,
+            waveforms: self.waveforms.as_ref().map(|waveforms| waveforms[first..last].to_vec()),
+            t_start,
+            t_stop,
+        })
+    }
+
+    /// Joins several spike trains into one
+    ///
+    /// # Arguments
+    ///
+    /// * `trains` - The spike trains, e.g. of units merged after sorting, or of several electrodes
+    ///
+    /// # Returns
+    ///
+    /// The SpikeTrain of every spike sorted by time, over the interval from the earliest start
+    /// to the latest end, or an error if no train is given
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let multi_unit = SpikeTrain::merge(&units)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The unit ID is kept if every train has the same one. The waveforms are kept if every
+    /// train has waveforms of the same length.
+    ///
+    pub fn merge(trains: &[SpikeTrain]) -> Result<SpikeTrain, ProcessingError> {
+        let first = trains.first().ok_or_else(|| ProcessingError::InvalidParameter("At least one spike train must be merged".to_string()))?;
+        let mut lengths = trains.iter().filter_map(|train| train.waveforms.as_ref().and_then(|waveforms| waveforms.first()).map(Vec::len));
+        let same_length = match lengths.next() {
+            Some(len) => lengths.all(|other| other == len),
+            None => true,
+        };
+        let keep_waveforms = same_length && trains.iter().all(|train| train.waveforms.is_some());
+        let times = trains.iter().flat_map(|train| train.times.iter().copied()).collect();
+        let waveforms = keep_waveforms.then(|| trains.iter().flat_map(|train| train.waveforms.iter().flatten().cloned()).collect());
+        let t_start = trains.iter().map(|train| train.t_start).fold(f64::INFINITY, f64::min);
+        let t_stop = trains.iter().map(|train| train.t_stop).fold(f64::NEG_INFINITY, f64::max);
+        let mut merged = Self::new(times, waveforms, t_start, t_stop)?;
+        merged.unit_id = first.unit_id.filter(|&unit_id| trains.iter().all(|train| train.unit_id == Some(unit_id)));
+        Ok(merged)
+    }
+
+    /// Returns the mean firing rate over the recording interval
+    ///
+    /// # Returns
+    ///
+    /// The number of spikes divided by the duration in spikes per second, or NaN if the
+    /// interval has zero length
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let active: Vec<&SpikeTrain> = units.iter().filter(|train| train.firing_rate() > 0.5).collect();
+    /// ```
+    ///
+    pub fn firing_rate(&self) -> f64 {
+        match self.duration() > 0.0 {
+            true => self.times.len() as f64 / self.duration(),
+            false => f64::NAN,
+        }
+    }
+
+    /// Counts the spikes in consecutive bins over the recording interval
+    ///
+    /// # Arguments
+    ///
+    /// * `bin_size` - The width of each bin in seconds
+    ///
+    /// # Returns
+    ///
+    /// The number of spikes in each bin from `t_start`, or an error if the bin size is not
+    /// positive or the interval has zero length
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let counts = train.binned_counts(0.01)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The bins are those of `binned_rate`, so the last bin may extend past `t_stop` and the
+    /// counts add up to the number of spikes
+    ///
+    pub fn binned_counts(&self, bin_size: f64) -> Result<Vec<usize>, ProcessingError> {
+        bin_counts(&self.times, self.t_start, self.t_stop, bin_size)
+    }
+
+    /// Estimates the firing rate in consecutive bins over the recording interval
+    ///
+    /// # Arguments
+    ///
+    /// * `bin_size` - The width of each bin in seconds
+    ///
+    /// # Returns
+    ///
+    /// The FiringRate of `rate::binned_rate`, or an error if the bin size is not positive
+    /// or the interval has zero length
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// train.binned_rate(0.05)?.to_csv(&mut csv_io)?;
+    /// ```
+    ///
+    pub fn binned_rate(&self, bin_size: f64) -> Result<FiringRate, ProcessingError> {
+        binned_rate(&self.times, self.t_start, self.t_stop, bin_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn waveform(peak: f64) -> Vec<f64> {
+        vec![0.0, peak, 0.0]
+    }
+
+    #[test]
+    fn spikes_are_sorted_with_their_waveforms() {
+        let train = SpikeTrain::new(vec![0.7, 0.1, 0.4], Some(vec![waveform(7.0), waveform(1.0), waveform(4.0)]), 0.0, 1.0).unwrap().with_unit_id(3);
+        assert_eq!(train.times(), &[0.1, 0.4, 0.7]);
+        assert_eq!(train.waveforms().unwrap(), &[waveform(1.0), waveform(4.0), waveform(7.0)]);
+        assert_eq!(train.unit_id(), Some(3));
+        assert_eq!((train.t_start(), train.t_stop(), train.duration()), (0.0, 1.0, 1.0));
+        assert_eq!(train.len(), 3);
+        assert!(!train.is_empty());
+
+        let bare = SpikeTrain::new(Vec::new(), None, 2.0, 2.0).unwrap();
+        assert!(bare.is_empty());
+        assert_eq!(bare.unit_id(), None);
+        assert!(bare.waveforms().is_none());
+        assert!(bare.firing_rate().is_nan());
+    }
+
+    #[test]
+    fn invalid_trains_are_rejected() {
+        let message = |result: Result<SpikeTrain, ProcessingError>| result.unwrap_err().to_string();
+        assert_eq!(message(SpikeTrain::new(Vec::new(), None, 2.0, 1.0)), "Invalid parameter: Recording must not end before it starts, got 2 to 1 s");
+        assert!(SpikeTrain::new(Vec::new(), None, 0.0, f64::INFINITY).is_err());
+        assert_eq!(message(SpikeTrain::new(vec![0.5, 1.5], None, 0.0, 1.0)), "Invalid parameter: Spike at 1.5 s is outside the recording from 0 to 1 s");
+        assert_eq!(message(SpikeTrain::new(vec![0.5, 0.6], Some(vec![waveform(1.0)]), 0.0, 1.0)), "Invalid parameter: 1 waveforms were given for 2 spikes");
+        assert_eq!(
+            message(SpikeTrain::new(vec![0.5, 0.6], Some(vec![waveform(1.0), vec![1.0]]), 0.0, 1.0)),
+            "Invalid parameter: Waveforms must all have the same length"
+        );
+        // Spikes on either edge of the interval belong to it
+        assert_eq!(SpikeTrain::new(vec![0.0, 1.0], None, 0.0, 1.0).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn a_detection_becomes_a_train() {
+        let result = SpikeDetectionResult {
+            times: vec![0.25, 0.5],
+            indices: vec![250, 500],
+            waveforms: vec![waveform(-40.0), waveform(-55.0)],
+            pre_samples: 1,
+            threshold: -30.0,
+            noise: 6.0,
+            dropped_at_edges: 0,
+        };
+        let train = SpikeTrain::from_detection(&result, 0.0, 1.0).unwrap();
+        assert_eq!(train.times(), &[0.25, 0.5]);
+        assert_eq!(train.waveforms().unwrap(), &[waveform(-40.0), waveform(-55.0)]);
+        assert!(SpikeTrain::from_detection(&result, 0.3, 1.0).is_err());
+    }
+
+    #[test]
+    fn slicing_keeps_the_spikes_in_a_half_open_window() {
+        let train = SpikeTrain::new(vec![0.1, 0.2, 0.3, 0.4, 0.5], Some((1..=5).map(|peak| waveform(peak as f64)).collect()), 0.0, 1.0).unwrap().with_unit_id(1);
+        let window = train.slice_time(0.2, 0.4).unwrap();
+        assert_eq!(window.times(), &[0.2, 0.3]);
+        assert_eq!(window.waveforms().unwrap(), &[waveform(2.0), waveform(3.0)]);
+        assert_eq!((window.t_start(), window.t_stop()), (0.2, 0.4));
+        assert_eq!(window.unit_id(), Some(1));
+        assert!((window.firing_rate() - 10.0).abs() < 1e-9);
+
+        let clipped = train.slice_time(-5.0, 0.15).unwrap();
+        assert_eq!(clipped.times(), &[0.1]);
+        assert_eq!((clipped.t_start(), clipped.t_stop()), (0.0, 0.15));
+
+        let outside = train.slice_time(2.0, 3.0).unwrap();
+        assert!(outside.is_empty());
+        assert_eq!((outside.t_start(), outside.t_stop()), (1.0, 1.0));
+
+        assert_eq!(train.slice_time(0.4, 0.2).unwrap_err().to_string(), "Invalid parameter: Start time 0.4 must not be after end time 0.2");
+        assert!(train.slice_time(f64::NAN, 0.2).is_err());
+    }
+
+    #[test]
+    fn merging_interleaves_spikes_and_keeps_what_all_trains_agree_on() {
+        let a = SpikeTrain::new(vec![0.1, 0.5], Some(vec![waveform(1.0), waveform(5.0)]), 0.0, 1.0).unwrap().with_unit_id(2);
+        let b = SpikeTrain::new(vec![1.3], Some(vec![waveform(13.0)]), 1.0, 2.0).unwrap().with_unit_id(2);
+        let merged = SpikeTrain::merge(&[b.clone(), a.clone()]).unwrap();
+        assert_eq!(merged.times(), &[0.1, 0.5, 1.3]);
+        assert_eq!(merged.waveforms().unwrap(), &[waveform(1.0), waveform(5.0), waveform(13.0)]);
+        assert_eq!((merged.t_start(), merged.t_stop()), (0.0, 2.0));
+        assert_eq!(merged.unit_id(), Some(2));
+        assert!((merged.firing_rate() - 1.5).abs() < 1e-12);
+
+        let other_unit = b.clone().with_unit_id(4);
+        assert_eq!(SpikeTrain::merge(&[a.clone(), other_unit]).unwrap().unit_id(), None);
+        let without_waveforms = SpikeTrain::new(vec![1.3], None, 1.0, 2.0).unwrap().with_unit_id(2);
+        let merged = SpikeTrain::merge(&[a.clone(), without_waveforms]).unwrap();
+        assert!(merged.waveforms().is_none());
+        assert_eq!(merged.unit_id(), Some(2));
+        let longer = SpikeTrain::new(vec![1.3], Some(vec![vec![0.0; 5]]), 1.0, 2.0).unwrap();
+        assert!(SpikeTrain::merge(&[a, longer]).unwrap().waveforms().is_none());
+
+        assert_eq!(SpikeTrain::merge(&[]).unwrap_err().to_string(), "Invalid parameter: At least one spike train must be merged");
+    }
+
+    #[test]
+    fn counts_and_rates_are_binned_over_the_interval() {
+        let train = SpikeTrain::new(vec![0.05, 0.1, 0.15, 0.6, 1.0], None, 0.0, 1.0).unwrap();
+        assert!((train.firing_rate() - 5.0).abs() < 1e-12);
+        assert_eq!(train.binned_counts(0.25).unwrap(), vec![3, 0, 1, 1]);
+        let rate = train.binned_rate(0.25).unwrap();
+        assert_eq!(rate.rates, vec![12.0, 0.0, 4.0, 4.0]);
+        assert_eq!((rate.start_time, rate.sampling_rate), (0.0, 4.0));
+        assert_eq!(rate.rates, train.binned_counts(0.25).unwrap().iter().map(|&count| count as f64 / 0.25).collect::<Vec<_>>());
+        // A last partial bin is kept
+        assert_eq!(train.binned_counts(0.4).unwrap(), vec![3, 1, 1]);
+        assert!(train.binned_counts(0.0).is_err());
+    }
+}
@@ -0,0 +1,1210 @@
+// A module to hold the regularly sampled channels of a recording together with their time base
+
+// Written by Amin Alam in 2024
+
+use std::ops::Index;
+use csv::StringRecord;
+use crate::core::sample::Sample;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::detrend::{detrend, DetrendMethod};
+use crate::processing::filter::{notch, remove_line_noise, validate_sampling_rate, FirFilter, IirFilter};
+use crate::processing::hilbert::envelope;
+use crate::processing::resample::{decimate, resample, ResampleMethod};
+use crate::processing::spectral::{band_power, spectrogram, welch, BandPowerMethod, FrequencyBand, Spectrogram, Spectrum};
+use crate::processing::window::Window;
+
+/// Channels sampled at the same regular rate from the same start time
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel, indexed as `channels[channel][sample]`, stored as f64 unless another Sample type is chosen
+/// * `names` - The name of each channel
+/// * `units` - The unit of each channel, e.g. `uV`, or None if unknown
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample in seconds
+///
+/// # Examples
+///
+/// ```
+/// let lfp = TimeSeries::new(channels, names, 1000.0, 0.0)?.with_units(&["uV"; 4])?;
+/// let trial = lfp.slice_time(12.5, 13.0)?;
+/// let psd = welch(&trial["ch2"], trial.sampling_rate(), 256, 0.5, Window::Hann)?;
+/// ```
+///
+/// # Note
+///
+/// Sample `k` is at time `start_time + k / sampling_rate`. The channels are laid out as the
+/// channels × samples matrices taken by the processing functions, so `channels()` can be
+/// passed to them directly. A `TimeSeries<f32>` halves the memory of the samples; see
+/// `Sample` for what stays in f64.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize), serde(bound = "T: Sample"))]
+pub struct TimeSeries<T = f64> {
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    channels: Vec<Vec<T>>,
+    names: Vec<String>,
+    units: Vec<Option<String>>,
+    sampling_rate: f64,
+    start_time: f64,
+}
+
+/// Implementation of the TimeSeries struct
+///
+/// # Methods
+///
+/// * `new` - Creates a TimeSeries from the samples of its channels
+/// * `with_units` - Sets the unit of every channel
+/// * `set_unit` - Sets the unit of one channel
+/// * `from_csv` - Reads a TimeSeries from the remaining records of a CsvIO object
+/// * `to_csv` - Writes the TimeSeries as a time column followed by one column per channel
+/// * `len` - Returns the number of samples of each channel
+/// * `is_empty` - Checks whether the channels have no samples
+/// * `n_channels` - Returns the number of channels
+/// * `sampling_rate` - Returns the sampling rate
+/// * `start_time` - Returns the time of the first sample
+/// * `end_time` - Returns the time one sample after the last one
+/// * `duration` - Returns the time covered by the samples
+/// * `times` - Returns the time of every sample
+/// * `names` - Returns the names of the channels
+/// * `unit` - Returns the unit of a channel
+/// * `channels` - Returns the samples of every channel
+/// * `channel` - Returns the samples of a channel by position
+/// * `channel_by_name` - Returns the samples of a channel by name
+/// * `channel_mut` - Returns the samples of a channel by position, to be modified in place
+/// * `channel_index` - Returns the position of a channel
+/// * `sample_index` - Returns the index of the first sample at or after a time
+/// * `slice_time` - Keeps the samples within a time range
+/// * `select` - Keeps some channels, in the order given
+/// * `into_channels` - Returns the samples of every channel, consuming the TimeSeries
+/// * `to_f32` - Returns a copy with the samples stored as f32
+/// * `to_f64` - Returns a copy with the samples stored as f64
+/// * `apply_f64` - Processes every channel in f64 and stores the result back in the sample type
+/// * `filter` - Filters every channel forward with an IIR filter
+/// * `filtfilt` - Filters every channel forward and backward with an IIR filter, without phase delay
+/// * `filter_fir` - Filters every channel with an FIR filter, compensating its delay
+/// * `notch` - Removes a narrow band from every channel with a zero-phase notch filter
+/// * `remove_line_noise` - Removes the line frequency and its harmonics from every channel
+/// * `detrend` - Removes a trend from every channel
+/// * `decimate` - Decimates every channel by an integer factor after anti-aliasing
+/// * `resample` - Resamples every channel to another sampling rate
+/// * `envelope` - Returns the amplitude envelope of every channel
+/// * `psd` - Estimates the power spectral density of every channel with Welch's method
+/// * `spectrogram` - Computes the spectrogram of every channel
+/// * `band_power` - Estimates the power of every channel within several frequency bands
+impl<T: Sample> TimeSeries<T> {
+    /// Creates a TimeSeries from the samples of its channels
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - The samples of each channel, all of the same length
+    /// * `names` - The name of each channel
+    /// * `sampling_rate` - The sampling rate in Hz
+    /// * `start_time` - The time of the first sample in seconds
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries with no units, or an error if the channels differ in length, there is
+    /// not one name per channel, a name is repeated, the sampling rate is not positive or the
+    /// start time is not finite
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let lfp = TimeSeries::new(vec![ch1, ch2], vec!["ch1".to_string(), "ch2".to_string()], 1000.0, 0.0)?;
+    /// ```
+    ///
+    pub fn new(channels: Vec<Vec<T>>, names: Vec<String>, sampling_rate: f64, start_time: f64) -> Result<Self, ProcessingError> {
+        validate_sampling_rate(sampling_rate)?;
+        if !start_time.is_finite() {
+            return Err(ProcessingError::InvalidParameter(format!("Start time must be finite, got {}", start_time)));
+        }
+        if names.len() != channels.len() {
+            return Err(ProcessingError::InvalidParameter(format!("{} names were given for {} channels", names.len(), channels.len())));
+        }
+        if let Some(channel) = channels.iter().position(|samples| samples.len() != channels[0].len()) {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Channel '{}' has {} samples but channel '{}' has {}",
+                names[channel],
+                channels[channel].len(),
+                names[0],
+                channels[0].len()
+            )));
+        }
+        if let Some((index, name)) = names.iter().enumerate().find(|(index, name)| names[..*index].contains(name)) {
+            return Err(ProcessingError::InvalidParameter(format!("Channel name '{}' is repeated at position {}", name, index)));
+        }
+        let units = vec![None; channels.len()];
+        Ok(Self { channels, names, units, sampling_rate, start_time })
+    }
+
+    /// Sets the unit of every channel
+    ///
+    /// # Arguments
+    ///
+    /// * `units` - The unit of each channel, in the order of the channels
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries with the units, or an error if there is not one unit per channel
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let lfp = TimeSeries::new(channels, names, 1000.0, 0.0)?.with_units(&["uV", "uV", "mV"])?;
+    /// ```
+    ///
+    pub fn with_units(mut self, units: &[&str]) -> Result<Self, ProcessingError> {
+        if units.len() != self.channels.len() {
+            return Err(ProcessingError::InvalidParameter(format!("{} units were given for {} channels", units.len(), self.channels.len())));
+        }
+        self.units = units.iter().map(|unit| Some(unit.to_string())).collect();
+        Ok(self)
+    }
+
+    /// Sets the unit of one channel
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The name of the channel
+    /// * `unit` - The unit of the channel
+    ///
+    /// # Returns
+    ///
+    /// An error if the channel is not found
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// lfp.set_unit("ecg", "mV")?;
+    /// ```
+    ///
+    pub fn set_unit(&mut self, channel: &str, unit: &str) -> Result<(), ProcessingError> {
+        let index = self.require_channel(channel)?;
+        self.units[index] = Some(unit.to_string());
+        Ok(())
+    }
+
+    /// Reads a TimeSeries from the remaining records of a CsvIO object
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object, with one column per channel and optionally a time column
+    /// * `time_column` - The name of the column holding the time of each row in seconds, always parsed as f64
+    /// * `sampling_rate` - The sampling rate in Hz, or None to estimate it from the time column
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries of every column but the time column, starting at the first time, or a
+    /// `ColumnNotFound` error if there is no time column and no sampling rate is given, a
+    /// `Parse` error if a field is not a number, or a `Processing` error if the sampling rate
+    /// cannot be estimated
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut csv_io = CsvIO::open_read("lfp.csv")?;
+    /// let lfp = TimeSeries::from_csv(&mut csv_io, "time", None)?;
+    /// let probe = TimeSeries::<f32>::from_csv(&mut CsvIO::open_read("probe.csv")?, "time", Some(30000.0))?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Without a time column the series starts at 0 s. The sampling rate is estimated from
+    /// the first and last times, so use `CsvIO::validate_time_column` first to check that
+    /// the rows are evenly spaced. This method consumes the remaining records of the reader.
+    ///
+    pub fn from_csv(csv_io: &mut CsvIO, time_column: &str, sampling_rate: Option<f64>) -> Result<Self, DataIoError> {
+        let headers = csv_io.reader_mut()?.headers().clone();
+        let mut names: Vec<String> = headers.iter().map(str::to_string).collect();
+        let time_index = names.iter().position(|name| name == time_column);
+        // The times stay in f64 whatever the sample type, a long recording needs more than the 24 bits of an f32
+        let (columns, times) = csv_io.read_columns_split::<T>(time_index)?;
+        let times = time_index.map(|index| {
+            names.remove(index);
+            times
+        });
+        let (sampling_rate, start_time) = match (&times, sampling_rate) {
+            (Some(times), Some(sampling_rate)) => (sampling_rate, times.first().copied().unwrap_or(0.0)),
+            (Some(times), None) => (estimate_sampling_rate(times)?, times[0]),
+            (None, Some(sampling_rate)) => (sampling_rate, 0.0),
+            (None, None) => return Err(DataIoError::ColumnNotFound(time_column.to_string())),
+        };
+        Ok(Self::new(columns, names, sampling_rate, start_time)?)
+    }
+
+    /// Writes the TimeSeries as a time column followed by one column per channel
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object the rows are written to
+    /// * `time_column` - The header of the time column
+    ///
+    /// # Returns
+    ///
+    /// An error if a record cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut csv_io = CsvIO::open_write("lfp_trial.csv")?;
+    /// lfp.slice_time(12.5, 13.0)?.to_csv(&mut csv_io, "time")?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The units are not written; keep them in the SessionInfo sidecar of the file
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO, time_column: &str) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(std::iter::once(time_column).chain(self.names.iter().map(String::as_str)).collect())?;
+        for (sample, time) in self.times().into_iter().enumerate() {
+            let record: StringRecord = std::iter::once(float_format.format(time))
+                .chain(self.channels.iter().map(|samples| samples[sample].format(&float_format)))
+                .collect();
+            csv_io.write_record(record)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of samples of each channel
+    ///
+    /// # Returns
+    ///
+    /// The number of samples, 0 if there are no channels
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let n_samples = lfp.len();
+    /// ```
+    ///
+    pub fn len(&self) -> usize {
+        self.channels.first().map_or(0, Vec::len)
+    }
+
+    /// Checks whether the channels have no samples
+    ///
+    /// # Returns
+    ///
+    /// True if there are no samples
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// if trial.is_empty() {
+    ///     return Ok(None);
+    /// }
+    /// ```
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of channels
+    ///
+    /// # Returns
+    ///
+    /// The number of channels
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let n_channels = lfp.n_channels();
+    /// ```
+    ///
+    pub fn n_channels(&self) -> usize {
+        self.channels.len()
+    }
+
+    /// Returns the sampling rate
+    ///
+    /// # Returns
+    ///
+    /// The sampling rate in Hz
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let nyquist = lfp.sampling_rate() / 2.0;
+    /// ```
+    ///
+    pub fn sampling_rate(&self) -> f64 {
+        self.sampling_rate
+    }
+
+    /// Returns the time of the first sample
+    ///
+    /// # Returns
+    ///
+    /// The time in seconds
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let onset = lfp.start_time();
+    /// ```
+    ///
+    pub fn start_time(&self) -> f64 {
+        self.start_time
+    }
+
+    /// Returns the time one sample after the last one
+    ///
+    /// # Returns
+    ///
+    /// The time in seconds, equal to the start time if there are no samples
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let whole = lfp.slice_time(lfp.start_time(), lfp.end_time())?;
+    /// ```
+    ///
+    pub fn end_time(&self) -> f64 {
+        self.start_time + self.duration()
+    }
+
+    /// Returns the time covered by the samples
+    ///
+    /// # Returns
+    ///
+    /// The number of samples divided by the sampling rate, in seconds
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// println!("{:.1} s recorded", lfp.duration());
+    /// ```
+    ///
+    pub fn duration(&self) -> f64 {
+        self.len() as f64 / self.sampling_rate
+    }
+
+    /// Returns the time of every sample
+    ///
+    /// # Returns
+    ///
+    /// The times in seconds
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let times = lfp.times();
+    /// ```
+    ///
+    pub fn times(&self) -> Vec<f64> {
+        (0..self.len()).map(|sample| self.start_time + sample as f64 / self.sampling_rate).collect()
+    }
+
+    /// Returns the names of the channels
+    ///
+    /// # Returns
+    ///
+    /// The names, in the order of the channels
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let names = lfp.names();
+    /// ```
+    ///
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Returns the unit of a channel
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The name of the channel
+    ///
+    /// # Returns
+    ///
+    /// The unit, or None if the channel is not found or has no unit
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let unit = lfp.unit("ch1").unwrap_or("a.u.");
+    /// ```
+    ///
+    pub fn unit(&self, channel: &str) -> Option<&str> {
+        self.channel_index(channel).and_then(|index| self.units[index].as_deref())
+    }
+
+    /// Returns the samples of every channel
+    ///
+    /// # Returns
+    ///
+    /// The samples, indexed as `channels[channel][sample]`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (car, names) = rereference(lfp.channels(), lfp.names(), &[], &Reference::CommonAverage, false)?;
+    /// ```
+    ///
+    pub fn channels(&self) -> &[Vec<T>] {
+        &self.channels
+    }
+
+    /// Returns the samples of a channel by position
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The position of the channel
+    ///
+    /// # Returns
+    ///
+    /// The samples, or None if there is no channel at the position
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let first = lfp.channel(0);
+    /// ```
+    ///
+    pub fn channel(&self, index: usize) -> Option<&[T]> {
+        self.channels.get(index).map(Vec::as_slice)
+    }
+
+    /// Returns the samples of a channel by name
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the channel
+    ///
+    /// # Returns
+    ///
+    /// The samples, or None if the channel is not found
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let ecg = lfp.channel_by_name("ecg");
+    /// ```
+    ///
+    pub fn channel_by_name(&self, name: &str) -> Option<&[T]> {
+        self.channel_index(name).map(|index| self.channels[index].as_slice())
+    }
+
+    /// Returns the samples of a channel by position, to be modified in place
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - The position of the channel
+    ///
+    /// # Returns
+    ///
+    /// The samples, or None if there is no channel at the position
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// if let Some(samples) = lfp.channel_mut(2) {
+    ///     samples.iter_mut().for_each(|sample| *sample *= 1e-3);
+    /// }
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The samples are returned as a slice so that the channels keep the same length
+    ///
+    pub fn channel_mut(&mut self, index: usize) -> Option<&mut [T]> {
+        self.channels.get_mut(index).map(Vec::as_mut_slice)
+    }
+
+    /// Returns the position of a channel
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the channel
+    ///
+    /// # Returns
+    ///
+    /// The position, or None if the channel is not found
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let index = lfp.channel_index("ch3");
+    /// ```
+    ///
+    pub fn channel_index(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|channel| channel == name)
+    }
+
+    /// Returns the index of the first sample at or after a time
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - The time in seconds
+    ///
+    /// # Returns
+    ///
+    /// The index, clamped between 0 and the number of samples
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let onset = lfp.sample_index(event_time);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A time within a billionth of a sample of a sample time is taken as that sample, so
+    /// rounding errors in the times do not shift the index
+    ///
+    pub fn sample_index(&self, time: f64) -> usize {
+        let position = ((time - self.start_time) * self.sampling_rate - 1e-9).ceil();
+        position.clamp(0.0, self.len() as f64) as usize
+    }
+
+    /// Keeps the samples within a time range
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - The start of the range in seconds, included
+    /// * `end` - The end of the range in seconds, excluded
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries of the samples in the range, clipped to the samples there are, or an
+    /// error if the start is after the end
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let baseline = lfp.slice_time(onset - 0.2, onset)?;
+    /// ```
+    ///
+    pub fn slice_time(&self, start: f64, end: f64) -> Result<TimeSeries<T>, ProcessingError> {
+        if start.is_nan() || end.is_nan() || start > end {
+            return Err(ProcessingError::InvalidParameter(format!("Start time {} must not be after end time {}", start, end)));
+        }
+        let first = self.sample_index(start);
+        let last = self.sample_index(end).max(first);
+        Ok(TimeSeries {
+            channels: self.channels.iter().map(|samples| samples[first..last].to_vec()).collect(),
+            names: self.names.clone(),
+            units: self.units.clone(),
+            sampling_rate: self.sampling_rate,
+            start_time: self.start_time + first as f64 / self.sampling_rate,
+        })
+    }
+
+    /// Keeps some channels, in the order given
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - The names of the channels
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries of the channels, or an error if a channel is not found or is given twice
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let hippocampus = lfp.select(&["ch3", "ch4", "ch7"])?;
+    /// ```
+    ///
+    pub fn select(&self, channels: &[&str]) -> Result<TimeSeries<T>, ProcessingError> {
+        let indices = channels.iter().map(|channel| self.require_channel(channel)).collect::<Result<Vec<usize>, ProcessingError>>()?;
+        let selected = TimeSeries::new(
+            indices.iter().map(|&index| self.channels[index].clone()).collect(),
+            indices.iter().map(|&index| self.names[index].clone()).collect(),
+            self.sampling_rate,
+            self.start_time,
+        )?;
+        Ok(TimeSeries { units: indices.iter().map(|&index| self.units[index].clone()).collect(), ..selected })
+    }
+
+    /// Returns the samples of every channel, consuming the TimeSeries
+    ///
+    /// # Returns
+    ///
+    /// The samples, indexed as `channels[channel][sample]`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let channels = lfp.into_channels();
+    /// ```
+    ///
+    pub fn into_channels(self) -> Vec<Vec<T>> {
+        self.channels
+    }
+
+    /// Returns a copy with the samples stored as f32
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries with every sample rounded to the nearest f32, and the same names,
+    /// units and time base
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let compact = lfp.to_f32();
+    /// drop(lfp);
+    /// ```
+    ///
+    pub fn to_f32(&self) -> TimeSeries<f32> {
+        self.convert()
+    }
+
+    /// Returns a copy with the samples stored as f64
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries with every sample widened to f64, exactly, and the same names, units
+    /// and time base
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let trial = probe.slice_time(12.5, 13.0)?.to_f64();
+    /// let psd = welch(&trial["ch2"], trial.sampling_rate(), 256, 0.5, Window::Hann)?;
+    /// ```
+    ///
+    pub fn to_f64(&self) -> TimeSeries<f64> {
+        self.convert()
+    }
+
+    /// Processes every channel in f64 and stores the result back in the sample type
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - The function applied to the f64 samples of each channel, returning as many samples
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries of the processed channels, with the same names, units and time base, or
+    /// the first error of `f`, or an error if `f` changes the number of samples
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use neurorust::{butterworth, FilterKind, TimeSeries};
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let probe = TimeSeries::new(vec![vec![0.5f32; 3000]], vec!["ch1".to_string()], 30000.0, 0.0)?;
+    /// let bandpass = butterworth(4, FilterKind::Bandpass(300.0, 6000.0), probe.sampling_rate())?;
+    /// let filtered = probe.apply_f64(|samples| bandpass.filtfilt(samples))?;
+    /// # assert_eq!(filtered.len(), probe.len());
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Only one channel is held in f64 at a time, so f32 storage keeps its memory advantage
+    /// on functions that would otherwise need every channel widened at once
+    ///
+    pub fn apply_f64<F>(&self, f: F) -> Result<TimeSeries<T>, ProcessingError>
+    where
+        F: Fn(&[f64]) -> Result<Vec<f64>, ProcessingError>,
+    {
+        let mut channels = Vec::with_capacity(self.channels.len());
+        for (samples, name) in self.channels.iter().zip(&self.names) {
+            let processed = f(&widen(samples))?;
+            if processed.len() != samples.len() {
+                return Err(ProcessingError::InvalidParameter(format!(
+                    "Processing channel '{}' turned {} samples into {}",
+                    name,
+                    samples.len(),
+                    processed.len()
+                )));
+            }
+            channels.push(processed.into_iter().map(T::from_f64).collect());
+        }
+        Ok(TimeSeries { channels, ..self.without_channels() })
+    }
+
+    /// Filters every channel forward with an IIR filter
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The filter, designed for the sampling rate of the TimeSeries
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries of the filtered channels, with the same names, units and time base, or
+    /// an error if the filter was designed for another sampling rate
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let highpass = butterworth(2, FilterKind::Highpass(1.0), eeg.sampling_rate())?;
+    /// let causal = eeg.filter(&highpass)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The output is delayed by the phase of the filter, as in `IirFilter::apply`
+    ///
+    pub fn filter(&self, filter: &IirFilter) -> Result<TimeSeries<T>, ProcessingError> {
+        self.check_filter_rate(filter.sampling_rate())?;
+        self.apply_f64(|samples| Ok(filter.apply(samples)))
+    }
+
+    /// Filters every channel forward and backward with an IIR filter, without phase delay
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The filter, designed for the sampling rate of the TimeSeries
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries of the filtered channels, with the same names, units and time base, or
+    /// an error if the filter was designed for another sampling rate or a channel is too
+    /// short for its padding
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let bandpass = butterworth(4, FilterKind::Bandpass(300.0, 6000.0), probe.sampling_rate())?;
+    /// let spikes_band = probe.filtfilt(&bandpass)?;
+    /// ```
+    ///
+    pub fn filtfilt(&self, filter: &IirFilter) -> Result<TimeSeries<T>, ProcessingError> {
+        self.check_filter_rate(filter.sampling_rate())?;
+        self.apply_f64(|samples| filter.filtfilt(samples))
+    }
+
+    /// Filters every channel with an FIR filter, compensating its delay
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The filter, designed for the sampling rate of the TimeSeries
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries of the filtered channels, with the same names, units and time base, or
+    /// an error if the filter was designed for another sampling rate
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let lowpass = fir_design(101, FilterKind::Lowpass(40.0), Window::Hamming, eeg.sampling_rate())?;
+    /// let smoothed = eeg.filter_fir(&lowpass)?;
+    /// ```
+    ///
+    pub fn filter_fir(&self, filter: &FirFilter) -> Result<TimeSeries<T>, ProcessingError> {
+        self.check_filter_rate(filter.sampling_rate())?;
+        self.apply_f64(|samples| Ok(filter.apply_zero_delay(samples)))
+    }
+
+    /// Removes a narrow band from every channel with a zero-phase notch filter
+    ///
+    /// # Arguments
+    ///
+    /// * `frequency` - The center of the removed band in Hz
+    /// * `q_factor` - The ratio of the center frequency to the width of the band, e.g. `30.0`
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries of the filtered channels, with the same names, units and time base, or
+    /// an error as in `notch` and `IirFilter::filtfilt`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let clean = eeg.notch(50.0, 30.0)?;
+    /// ```
+    ///
+    pub fn notch(&self, frequency: f64, q_factor: f64) -> Result<TimeSeries<T>, ProcessingError> {
+        self.filtfilt(&notch(frequency, q_factor, self.sampling_rate)?)
+    }
+
+    /// Removes the line frequency and its harmonics from every channel
+    ///
+    /// # Arguments
+    ///
+    /// * `base_frequency` - The line frequency in Hz, e.g. `50.0` or `60.0`
+    /// * `n_harmonics` - The number of multiples of the base frequency removed, including it
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries of the filtered channels, with the same names, units and time base, or
+    /// an error as in `remove_line_noise`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let clean = eeg.remove_line_noise(60.0, 3)?;
+    /// ```
+    ///
+    pub fn remove_line_noise(&self, base_frequency: f64, n_harmonics: usize) -> Result<TimeSeries<T>, ProcessingError> {
+        self.apply_f64(|samples| remove_line_noise(samples, self.sampling_rate, base_frequency, n_harmonics))
+    }
+
+    /// Removes a trend from every channel
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The trend removed, a constant, a line or a polynomial
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries of the detrended channels, with the same names, units and time base, or
+    /// an error as in `detrend`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let flat = lfp.detrend(DetrendMethod::Linear)?;
+    /// ```
+    ///
+    pub fn detrend(&self, method: DetrendMethod) -> Result<TimeSeries<T>, ProcessingError> {
+        self.apply_f64(|samples| detrend(samples, method))
+    }
+
+    /// Decimates every channel by an integer factor after anti-aliasing
+    ///
+    /// # Arguments
+    ///
+    /// * `factor` - The ratio between the old and the new sampling rate
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries at the new sampling rate, with the same names, units and start time, or
+    /// an error as in `decimate`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let lfp = wideband.decimate(30)?;
+    /// assert_eq!(lfp.sampling_rate(), 1000.0);
+    /// ```
+    ///
+    pub fn decimate(&self, factor: usize) -> Result<TimeSeries<T>, ProcessingError> {
+        self.apply_resampled(|samples| decimate(samples, self.sampling_rate, factor))
+    }
+
+    /// Resamples every channel to another sampling rate
+    ///
+    /// # Arguments
+    ///
+    /// * `new_rate` - The requested sampling rate in Hz
+    /// * `method` - The resampling method
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries at the new sampling rate, with the same names, units and start time, or
+    /// an error as in `resample`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let eye_aligned = neural.resample(1100.0, ResampleMethod::Polyphase)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The sampling rate is the one returned by `resample`, which `Polyphase` can round marginally
+    ///
+    pub fn resample(&self, new_rate: f64, method: ResampleMethod) -> Result<TimeSeries<T>, ProcessingError> {
+        self.apply_resampled(|samples| resample(samples, self.sampling_rate, new_rate, method))
+    }
+
+    /// Returns the amplitude envelope of every channel
+    ///
+    /// # Returns
+    ///
+    /// The TimeSeries of the magnitudes of the analytic signals of the channels, with the same
+    /// names, units and time base
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let gamma_envelope = lfp.filtfilt(&gamma_band)?.envelope();
+    /// ```
+    ///
+    pub fn envelope(&self) -> TimeSeries<T> {
+        let channels = self
+            .channels
+            .iter()
+            .map(|samples| envelope(&widen(samples)).into_iter().map(T::from_f64).collect())
+            .collect();
+        TimeSeries { channels, ..self.without_channels() }
+    }
+
+    /// Estimates the power spectral density of every channel with Welch's method
+    ///
+    /// # Arguments
+    ///
+    /// * `segment_len` - The number of samples per segment
+    /// * `overlap_fraction` - The overlap of consecutive segments, from 0 up to but excluding 1
+    /// * `window` - The window applied to each segment
+    ///
+    /// # Returns
+    ///
+    /// The Spectrum of every channel, in the order of the channels, or an error as in `welch`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let spectra = eeg.psd(1024, 0.5, Window::Hann)?;
+    /// let (peak_frequency, _) = spectra[eeg.channel_index("O1").unwrap()].peak();
+    /// ```
+    ///
+    pub fn psd(&self, segment_len: usize, overlap_fraction: f64, window: Window) -> Result<Vec<Spectrum>, ProcessingError> {
+        self.channels.iter().map(|samples| welch(&widen(samples), self.sampling_rate, segment_len, overlap_fraction, window)).collect()
+    }
+
+    /// Computes the spectrogram of every channel
+    ///
+    /// # Arguments
+    ///
+    /// * `window_len` - The number of samples per window
+    /// * `hop` - The number of samples between the starts of consecutive windows
+    /// * `window` - The window applied to each segment
+    ///
+    /// # Returns
+    ///
+    /// The Spectrogram of every channel, in the order of the channels, or an error as in
+    /// `spectrogram`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let tf = lfp.spectrogram(256, 64, Window::Hann)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Unlike the times of `spectrogram`, which start from the first sample, the times of
+    /// the windows are in seconds on the time base of the TimeSeries
+    ///
+    pub fn spectrogram(&self, window_len: usize, hop: usize, window: Window) -> Result<Vec<Spectrogram>, ProcessingError> {
+        self.channels
+            .iter()
+            .map(|samples| {
+                let mut tf = spectrogram(&widen(samples), self.sampling_rate, window_len, hop, window)?;
+                tf.times.iter_mut().for_each(|time| *time += self.start_time);
+                Ok(tf)
+            })
+            .collect()
+    }
+
+    /// Estimates the power of every channel within several frequency bands
+    ///
+    /// # Arguments
+    ///
+    /// * `bands` - The frequency bands
+    /// * `method` - How the power is estimated
+    /// * `relative` - Whether each power is divided by the total power of the channel
+    ///
+    /// # Returns
+    ///
+    /// The power of every band of every channel, indexed as `power[channel][band]`, or an
+    /// error as in `band_power`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let power = eeg.band_power(&FrequencyBand::canonical(), BandPowerMethod::FilterHilbert { order: 4 }, true)?;
+    /// ```
+    ///
+    pub fn band_power(&self, bands: &[FrequencyBand], method: BandPowerMethod, relative: bool) -> Result<Vec<Vec<f64>>, ProcessingError> {
+        self.channels.iter().map(|samples| band_power(&widen(samples), self.sampling_rate, bands, method, relative)).collect()
+    }
+
+    /// Processes every channel in f64 with a function that changes the sampling rate
+    fn apply_resampled<F>(&self, f: F) -> Result<TimeSeries<T>, ProcessingError>
+    where
+        F: Fn(&[f64]) -> Result<(Vec<f64>, f64), ProcessingError>,
+    {
+        let mut channels = Vec::with_capacity(self.channels.len());
+        let mut sampling_rate = None;
+        for samples in &self.channels {
+            let (processed, rate) = f(&widen(samples))?;
+            sampling_rate = Some(rate);
+            channels.push(processed.into_iter().map(T::from_f64).collect());
+        }
+        let sampling_rate = match sampling_rate {
+            Some(rate) => rate,
+            None => f(&[])?.1,
+        };
+        Ok(TimeSeries { channels, sampling_rate, ..self.without_channels() })
+    }
+
+    /// Checks that a filter was designed for the sampling rate of the TimeSeries
+    fn check_filter_rate(&self, filter_rate: f64) -> Result<(), ProcessingError> {
+        if (filter_rate - self.sampling_rate).abs() > 1e-9 * self.sampling_rate {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "The filter was designed for {} Hz but the signal is sampled at {} Hz",
+                filter_rate, self.sampling_rate
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns a copy with every sample converted to another sample type
+    fn convert<U: Sample>(&self) -> TimeSeries<U> {
+        let channels = self.channels.iter().map(|samples| samples.iter().map(|&sample| U::from_f64(sample.to_f64())).collect()).collect();
+        TimeSeries { channels, ..self.without_channels() }
+    }
+
+    /// Returns a TimeSeries with the names, units and time base of this one and no channels
+    fn without_channels<U>(&self) -> TimeSeries<U> {
+        TimeSeries {
+            channels: Vec::new(),
+            names: self.names.clone(),
+            units: self.units.clone(),
+            sampling_rate: self.sampling_rate,
+            start_time: self.start_time,
+        }
+    }
+
+    /// Returns the position of a channel, or an error naming it if it is not found
+    fn require_channel(&self, name: &str) -> Result<usize, ProcessingError> {
+        self.channel_index(name).ok_or_else(|| ProcessingError::InvalidParameter(format!("Channel '{}' not found", name)))
+    }
+}
+
+impl<T: Sample> Index<usize> for TimeSeries<T> {
+    type Output = [T];
+
+    fn index(&self, index: usize) -> &[T] {
+        &self.channels[index]
+    }
+}
+
+impl<T: Sample> Index<&str> for TimeSeries<T> {
+    type Output = [T];
+
+    fn index(&self, name: &str) -> &[T] {
+        match self.channel_index(name) {
+            Some(index) => &self.channels[index],
+            None => panic!("Channel '{}' not found", name),
+        }
+    }
+}
+
+/// Widens samples to f64, exactly
+fn widen<T: Sample>(samples: &[T]) -> Vec<f64> {
+    samples.iter().map(|&sample| sample.to_f64()).collect()
+}
+
+/// Estimates the sampling rate of evenly spaced times from the first and last one
+fn estimate_sampling_rate(times: &[f64]) -> Result<f64, ProcessingError> {
+    if times.len() < 2 {
+        return Err(ProcessingError::SignalTooShort { length: times.len(), required: 2 });
+    }
+    let span = times[times.len() - 1] - times[0];
+    let sampling_rate = (times.len() - 1) as f64 / span;
+    validate_sampling_rate(sampling_rate).map(|_| sampling_rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::filter::butterworth;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("neurorust-timeseries-{}-{}", std::process::id(), name)).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn f32_storage_keeps_times_in_f64_and_writes_short_samples() {
+        let input = temp_path("input.csv");
+        // At 7200 s an f32 time is only precise to about 0.5 ms, more than a 30 kHz sample period
+        let rows: String = (0..4).map(|k| format!("{},0.1,{}\n", 7200.0 + k as f64 / 30000.0, 1.0 - 2.5 * k as f64)).collect();
+        std::fs::write(&input, format!("time,a,b\n{}", rows)).unwrap();
+        let series = TimeSeries::<f32>::from_csv(&mut CsvIO::open_read(&input).unwrap(), "time", None).unwrap();
+        std::fs::remove_file(&input).unwrap();
+        assert!((series.sampling_rate() - 30000.0).abs() < 1e-3, "{}", series.sampling_rate());
+        assert_eq!(series.start_time(), 7200.0);
+        assert_eq!(series.names(), ["a", "b"]);
+        assert_eq!(series["b"], [1.0, -1.5, -4.0, -6.5]);
+
+        let output = temp_path("output.csv");
+        let mut csv_io = CsvIO::open_write(&output).unwrap();
+        series.slice_time(7200.0, 7200.00005).unwrap().to_csv(&mut csv_io, "time").unwrap();
+        csv_io.save().unwrap();
+        drop(csv_io);
+        let written = std::fs::read_to_string(&output).unwrap();
+        std::fs::remove_file(&output).unwrap();
+        assert_eq!(written, format!("time,a,b\n7200,0.1,1\n{},0.1,-1.5\n", series.times()[1]));
+    }
+
+    #[test]
+    fn conversions_between_precisions_are_explicit_and_per_channel() {
+        let series = TimeSeries::new(vec![vec![0.1, 1e-50, 3.0]], vec!["x".to_string()], 10.0, 1.0).unwrap().with_units(&["uV"]).unwrap();
+        let compact = series.to_f32();
+        assert_eq!(compact[0], [0.1f32, 0.0, 3.0]);
+        assert_eq!((compact.unit("x"), compact.sampling_rate(), compact.start_time()), (Some("uV"), 10.0, 1.0));
+        assert_eq!(compact.to_f64()[0], [0.1f32 as f64, 0.0, 3.0]);
+
+        let doubled = compact.apply_f64(|samples| Ok(samples.iter().map(|sample| 2.0 * sample).collect())).unwrap();
+        assert_eq!(doubled[0], [0.2f32, 0.0, 6.0]);
+        assert!(compact.apply_f64(|samples| Ok(samples[1..].to_vec())).is_err());
+    }
+
+    fn noisy_probe<T: Sample>() -> TimeSeries<T> {
+        let mut rng = crate::processing::random::SeededRng::new(258);
+        let channels = (0..2)
+            .map(|channel| {
+                (0..4000)
+                    .map(|k| {
+                        let t = k as f64 / 1000.0;
+                        T::from_f64((2.0 * std::f64::consts::PI * (10.0 + 30.0 * channel as f64) * t).sin() + 0.5 * (2.0 * std::f64::consts::PI * 50.0 * t).sin() + 0.1 * rng.next_gaussian() + t)
+                    })
+                    .collect()
+            })
+            .collect();
+        TimeSeries::new(channels, vec!["a".to_string(), "b".to_string()], 1000.0, 12.5).unwrap().with_units(&["uV", "mV"]).unwrap()
+    }
+
+    #[test]
+    fn wrappers_apply_the_slice_functions_to_every_channel() {
+        let probe = noisy_probe::<f64>();
+        let lowpass = butterworth(4, crate::processing::filter::FilterKind::Lowpass(40.0), 1000.0).unwrap();
+        let fir = crate::processing::filter::fir_design(51, crate::processing::filter::FilterKind::Lowpass(40.0), Window::Hamming, 1000.0).unwrap();
+        let check = |processed: TimeSeries, f: &dyn Fn(&[f64]) -> Vec<f64>| {
+            assert_eq!((processed.names(), processed.unit("b"), processed.sampling_rate(), processed.start_time()), (probe.names(), Some("mV"), 1000.0, 12.5));
+            for (channel, samples) in probe.channels().iter().enumerate() {
+                assert_eq!(processed[channel], f(samples)[..]);
+            }
+        };
+        check(probe.filter(&lowpass).unwrap(), &|samples| lowpass.apply(samples));
+        check(probe.filtfilt(&lowpass).unwrap(), &|samples| lowpass.filtfilt(samples).unwrap());
+        check(probe.filter_fir(&fir).unwrap(), &|samples| fir.apply_zero_delay(samples));
+        check(probe.notch(50.0, 30.0).unwrap(), &|samples| notch(50.0, 30.0, 1000.0).unwrap().filtfilt(samples).unwrap());
+        check(probe.remove_line_noise(50.0, 2).unwrap(), &|samples| remove_line_noise(samples, 1000.0, 50.0, 2).unwrap());
+        check(probe.detrend(DetrendMethod::Linear).unwrap(), &|samples| detrend(samples, DetrendMethod::Linear).unwrap());
+        check(probe.envelope(), &envelope);
+        let spectra = probe.psd(512, 0.5, Window::Hann).unwrap();
+        let bands = FrequencyBand::canonical();
+        let power = probe.band_power(&bands, BandPowerMethod::FilterHilbert { order: 4 }, true).unwrap();
+        for (channel, samples) in probe.channels().iter().enumerate() {
+            assert_eq!(spectra[channel], welch(samples, 1000.0, 512, 0.5, Window::Hann).unwrap());
+            assert_eq!(power[channel], band_power(samples, 1000.0, &bands, BandPowerMethod::FilterHilbert { order: 4 }, true).unwrap());
+        }
+        assert!((spectra[1].peak().0 - 40.0).abs() <= spectra[1].resolution());
+    }
+
+    #[test]
+    fn wrappers_keep_the_time_base_and_refuse_filters_of_another_rate() {
+        let probe = noisy_probe::<f32>();
+        let decimated = probe.decimate(4).unwrap();
+        assert_eq!((decimated.len(), decimated.sampling_rate(), decimated.start_time(), decimated.unit("a")), (1000, 250.0, 12.5, Some("uV")));
+        let expected = decimate(&widen(&probe[1]), 1000.0, 4).unwrap().0;
+        assert_eq!(decimated[1], expected.iter().map(|&value| value as f32).collect::<Vec<_>>()[..]);
+        let resampled = probe.resample(300.0, ResampleMethod::Sinc).unwrap();
+        assert_eq!((resampled.len(), resampled.sampling_rate(), resampled.start_time()), (1200, 300.0, 12.5));
+
+        let tf = probe.spectrogram(256, 128, Window::Hann).unwrap();
+        let unshifted = spectrogram(&widen(&probe[0]), 1000.0, 256, 128, Window::Hann).unwrap();
+        assert_eq!(tf[0].times, unshifted.times.iter().map(|time| time + 12.5).collect::<Vec<_>>());
+        assert_eq!((tf[0].times[0], unshifted.times[0]), (12.5 + 0.128, 0.128));
+
+        let other_rate = butterworth(2, crate::processing::filter::FilterKind::Highpass(1.0), 2000.0).unwrap();
+        assert!(matches!(probe.filtfilt(&other_rate), Err(ProcessingError::InvalidParameter(message)) if message.contains("2000 Hz")));
+        assert!(probe.filter(&other_rate).is_err());
+    }
+}
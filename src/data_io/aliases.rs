@@ -0,0 +1,601 @@
+// A module to map the column names of different rigs to canonical names when reading
+
+// Written by Amin Alam in 2024
+
+use std::io;
+use csv::{ReaderBuilder, StringRecord};
+use crate::data_io::csv::{CsvReader, CsvWriter};
+
+/// The bundled alias table of `ColumnAliases::neuroscience`, as `canonical,alias,kind` rows
+pub const DEFAULT_ALIASES: &str = "canonical,alias,kind
+time,time_s,column
+time,times,column
+time,time_sec,column
+time,timestamp,column
+time,timestamps,column
+time,seconds,column
+time,t,column
+trial,trial_id,column
+trial,trial_index,column
+trial,trial_number,column
+trial,trial_no,column
+trial,trialnum,column
+subject,subject_id,column
+subject,subj,column
+subject,sub,column
+subject,participant,column
+subject,participant_id,column
+subject,animal,column
+subject,animal_id,column
+session,session_id,column
+session,ses,column
+condition,cond,column
+condition,trial_type,column
+condition,stimulus_type,column
+condition,stim_type,column
+ch,channel,prefix
+ch,chan,prefix
+ch,electrode,prefix
+ch,elec,prefix
+";
+
+/// The separators allowed between a channel prefix and its number
+const PREFIX_SEPARATORS: [char; 4] = [' ', '_', '-', '.'];
+
+/// A table of the names under which canonical columns appear in files
+///
+/// # Examples
+///
+/// ```
+/// let aliases = ColumnAliases::new(&[("time", &["Time", "time_s", "timestamp", "t"])]);
+/// let reader = CsvReader::open("rig_b.csv")?.with_column_aliases(&aliases)?;
+/// ```
+///
+/// # Note
+///
+/// A column alias matches a whole header, ignoring ASCII case. A prefix alias matches the
+/// start of a header followed by a number, optionally after a space, `_`, `-` or `.`, so
+/// that with the prefix `ch` and the alias `channel` the header `Channel_07` becomes `ch07`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColumnAliases {
+    columns: Vec<(String, Vec<String>)>,
+    prefixes: Vec<(String, Vec<String>)>,
+}
+
+/// A header renamed to its canonical name
+///
+/// # Arguments
+///
+/// * `column` - The index of the column
+/// * `header` - The header as it appears in the file
+/// * `canonical` - The canonical name it is read as
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AppliedAlias {
+    pub column: usize,
+    pub header: String,
+    pub canonical: String,
+}
+
+/// The renames applied to the headers of one file
+///
+/// # Arguments
+///
+/// * `applied` - The renamed headers, in the order of the columns
+///
+/// # Examples
+///
+/// ```
+/// if let Some(mapping) = reader.column_mapping() {
+///     mapping.to_csv(&mut CsvWriter::create("aliases_applied.csv")?)?;
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ColumnMapping {
+    pub applied: Vec<AppliedAlias>,
+}
+
+/// Implementation of the ColumnAliases struct
+///
+/// # Methods
+///
+/// * `new` - Creates a table of column aliases
+/// * `neuroscience` - Returns the bundled table of common neuroscience names
+/// * `column` - Adds aliases of a canonical column
+/// * `prefix` - Adds aliases of a canonical channel prefix
+/// * `extend_from_csv` - Adds the aliases of a `canonical,alias,kind` csv file
+/// * `resolve` - Finds the canonical name of every header of a file
+impl ColumnAliases {
+    /// Creates a table of column aliases
+    ///
+    /// # Arguments
+    ///
+    /// * `aliases` - The canonical names, each with the aliases it may appear under
+    ///
+    /// # Returns
+    ///
+    /// The ColumnAliases
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let aliases = ColumnAliases::new(&[("time", &["time_s", "timestamp"]), ("trial", &["trial_id"])]);
+    /// ```
+    ///
+    pub fn new(aliases: &[(&str, &[&str])]) -> Self {
+        aliases.iter().fold(Self::default(), |table, (canonical, names)| table.column(canonical, names))
+    }
+
+    /// Returns the bundled table of common neuroscience names
+    ///
+    /// # Returns
+    ///
+    /// The ColumnAliases of `DEFAULT_ALIASES`, for the `time`, `trial`, `subject`, `session`
+    /// and `condition` columns and the `ch` channel prefix
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let reader = CsvReader::open("rig_a.csv")?.with_column_aliases(&ColumnAliases::neuroscience())?;
+    /// ```
+    ///
+    pub fn neuroscience() -> Self {
+        let mut table = Self::default();
+        let mut reader = ReaderBuilder::new().from_reader(DEFAULT_ALIASES.as_bytes());
+        for record in reader.records() {
+            table.add_record(&record.expect("The bundled alias table is valid csv")).expect("The bundled alias table is valid");
+        }
+        table
+    }
+
+    /// Adds aliases of a canonical column
+    ///
+    /// # Arguments
+    ///
+    /// * `canonical` - The canonical name
+    /// * `aliases` - The names it may appear under
+    ///
+    /// # Returns
+    ///
+    /// The ColumnAliases with the aliases added after those already known for the name
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let aliases = ColumnAliases::neuroscience().column("lfp", &["LFP_uV", "lfp_raw"]);
+    /// ```
+    ///
+    pub fn column(mut self, canonical: &str, aliases: &[&str]) -> Self {
+        add_aliases(&mut self.columns, canonical, aliases);
+        self
+    }
+
+    /// Adds aliases of a canonical channel prefix
+    ///
+    /// # Arguments
+    ///
+    /// * `canonical` - The canonical prefix, e.g. `ch`
+    /// * `aliases` - The prefixes it may appear under, e.g. `channel`
+    ///
+    /// # Returns
+    ///
+    /// The ColumnAliases with the aliases added after those already known for the prefix
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let aliases = ColumnAliases::default().prefix("unit", &["cluster", "clu"]);
+    /// ```
+    ///
+    pub fn prefix(mut self, canonical: &str, aliases: &[&str]) -> Self {
+        add_aliases(&mut self.prefixes, canonical, aliases);
+        self
+    }
+
+    /// Adds the aliases of a `canonical,alias,kind` csv file
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The reader of the table, at its first record
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if the `canonical` or `alias` column is missing, a kind is not
+    /// `column` or `prefix`, or the records cannot be read
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut aliases = ColumnAliases::neuroscience();
+    /// aliases.extend_from_csv(&mut CsvReader::open("lab_aliases.csv")?)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The `kind` column is optional and an empty kind means `column`, so a file of
+    /// `canonical,alias` rows adds column aliases
+    ///
+    pub fn extend_from_csv(&mut self, reader: &mut CsvReader) -> io::Result<()> {
+        let headers = reader.headers().clone();
+        let find = |name: &str| headers.iter().position(|header| header == name);
+        let missing = |name: &str| io::Error::new(io::ErrorKind::InvalidInput, format!("Column '{}' not found", name));
+        let canonical = find("canonical").ok_or_else(|| missing("canonical"))?;
+        let alias = find("alias").ok_or_else(|| missing("alias"))?;
+        let kind = find("kind");
+        for record in reader.records() {
+            let record = record?;
+            let field = |column: Option<usize>| column.and_then(|column| record.get(column)).unwrap_or("").trim();
+            self.add_record(&StringRecord::from(vec![field(Some(canonical)), field(Some(alias)), field(kind)]))
+                .map_err(|error| io::Error::new(error.kind(), format!("Line {}: {}", record.position().map_or(0, |position| position.line()), error)))?;
+        }
+        Ok(())
+    }
+
+    /// Finds the canonical name of every header of a file
+    ///
+    /// # Arguments
+    ///
+    /// * `headers` - The header row of the file
+    ///
+    /// # Returns
+    ///
+    /// The ColumnMapping of the headers that are renamed, or an error naming the headers if
+    /// two of them are aliases of the same canonical name, one header is an alias of two
+    /// canonical names, or two renamed headers end up with the same name
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mapping = aliases.resolve(reader.headers())?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header equal to a canonical name, including its case, is kept, and the aliases of
+    /// that name are then not looked for, so a file with both `time` and `timestamp` reads
+    /// `time` as the time column and keeps `timestamp` as it is. Otherwise exactly one
+    /// header may match the canonical name or its aliases without regard to case.
+    ///
+    pub fn resolve(&self, headers: &StringRecord) -> io::Result<ColumnMapping> {
+        let mut names: Vec<Option<String>> = vec![None; headers.len()];
+        let exact = |name: &str| headers.iter().any(|header| header == name);
+        for (canonical, aliases) in &self.columns {
+            if exact(canonical) {
+                continue;
+            }
+            let matches: Vec<usize> = (0..headers.len())
+                .filter(|&column| {
+                    let header = &headers[column];
+                    header.eq_ignore_ascii_case(canonical) || aliases.iter().any(|alias| header.eq_ignore_ascii_case(alias))
+                })
+                .collect();
+            match matches.as_slice() {
+                [] => {}
+                [column] => {
+                    if let Some(other) = &names[*column] {
+                        return Err(ambiguous(format!("Column '{}' is an alias of both '{}' and '{}'", &headers[*column], other, canonical)));
+                    }
+                    names[*column] = Some(canonical.clone());
+                }
+                several => {
+                    let found: Vec<String> = several.iter().map(|&column| format!("'{}'", &headers[column])).collect();
+                    return Err(ambiguous(format!("Columns {} all match '{}' or its aliases", found.join(", "), canonical)));
+                }
+            }
+        }
+        for (column, header) in headers.iter().enumerate() {
+            if names[column].is_some() || self.columns.iter().any(|(canonical, _)| canonical == header) {
+                continue;
+            }
+            names[column] = self.prefixed_name(header).filter(|name| name != header);
+        }
+
+        let mut applied = Vec::new();
+        for (column, name) in names.iter().enumerate() {
+            let Some(canonical) = name else {
+                continue;
+            };
+            let clash = (0..headers.len()).find(|&other| other != column && names[other].as_deref().unwrap_or(&headers[other]) == canonical);
+            if let Some(other) = clash {
+                return Err(ambiguous(format!("Columns '{}' and '{}' would both be read as '{}'", &headers[column], &headers[other], canonical)));
+            }
+            applied.push(AppliedAlias { column, header: headers[column].to_string(), canonical: canonical.clone() });
+        }
+        Ok(ColumnMapping { applied })
+    }
+
+    /// Returns the name a header takes through the longest matching prefix alias, if any
+    fn prefixed_name(&self, header: &str) -> Option<String> {
+        let lower = header.to_ascii_lowercase();
+        let mut best: Option<(usize, &str)> = None;
+        for (canonical, aliases) in &self.prefixes {
+            for alias in std::iter::once(canonical).chain(aliases) {
+                let Some(rest) = lower.strip_prefix(&alias.to_ascii_lowercase()) else {
+                    continue;
+                };
+                let number = rest.strip_prefix(PREFIX_SEPARATORS).unwrap_or(rest);
+                if number.starts_with(|c: char| c.is_ascii_digit()) && best.is_none_or(|(length, _)| alias.len() > length) {
+                    best = Some((alias.len(), canonical));
+                }
+            }
+        }
+        best.map(|(length, canonical)| {
+            let rest = &header[length..];
+            format!("{}{}", canonical, rest.strip_prefix(PREFIX_SEPARATORS).unwrap_or(rest))
+        })
+    }
+
+    /// Adds one `canonical,alias,kind` row of an alias table
+    fn add_record(&mut self, record: &StringRecord) -> io::Result<()> {
+        let (canonical, alias) = (record.get(0).unwrap_or(""), record.get(1).unwrap_or(""));
+        if canonical.is_empty() || alias.is_empty() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "The canonical name and the alias must not be empty"));
+        }
+        match record.get(2).unwrap_or("") {
+            "" | "column" => add_aliases(&mut self.columns, canonical, &[alias]),
+            "prefix" => add_aliases(&mut self.prefixes, canonical, &[alias]),
+            other => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Alias kind '{}' is not 'column' or 'prefix'", other))),
+        }
+        Ok(())
+    }
+}
+
+/// Implementation of the ColumnMapping struct
+///
+/// # Methods
+///
+/// * `canonical_headers` - Returns the headers of the file with the renames applied
+/// * `original` - Returns the header a canonical name was read from
+/// * `to_csv` - Writes the renames as `column,header,canonical` rows
+impl ColumnMapping {
+    /// Returns the headers of the file with the renames applied
+    ///
+    /// # Arguments
+    ///
+    /// * `headers` - The header row of the file the mapping was resolved for
+    ///
+    /// # Returns
+    ///
+    /// The header row with the canonical names
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let canonical = aliases.resolve(&headers)?.canonical_headers(&headers);
+    /// ```
+    ///
+    pub fn canonical_headers(&self, headers: &StringRecord) -> StringRecord {
+        headers
+            .iter()
+            .enumerate()
+            .map(|(column, header)| self.applied.iter().find(|alias| alias.column == column).map_or(header, |alias| alias.canonical.as_str()))
+            .collect()
+    }
+
+    /// Returns the header a canonical name was read from
+    ///
+    /// # Arguments
+    ///
+    /// * `canonical` - The canonical name
+    ///
+    /// # Returns
+    ///
+    /// The header as it appears in the file, or None if no header was renamed to the name
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let time_header = mapping.original("time").unwrap_or("time");
+    /// ```
+    ///
+    pub fn original(&self, canonical: &str) -> Option<&str> {
+        self.applied.iter().find(|alias| alias.canonical == canonical).map(|alias| alias.header.as_str())
+    }
+
+    /// Writes the renames as `column,header,canonical` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - The writer of the csv file
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if the file cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// mapping.to_csv(&mut CsvWriter::create("aliases_applied.csv")?)?;
+    /// ```
+    ///
+    pub fn to_csv(&self, output: &mut CsvWriter) -> io::Result<()> {
+        output.write_record(&StringRecord::from(vec!["column", "header", "canonical"]))?;
+        for alias in &self.applied {
+            output.write_record(&StringRecord::from(vec![alias.column.to_string(), alias.header.clone(), alias.canonical.clone()]))?;
+        }
+        output.flush()
+    }
+}
+
+/// Appends aliases to the entry of a canonical name, creating it if needed
+fn add_aliases(entries: &mut Vec<(String, Vec<String>)>, canonical: &str, aliases: &[&str]) {
+    let index = match entries.iter().position(|(name, _)| name == canonical) {
+        Some(index) => index,
+        None => {
+            entries.push((canonical.to_string(), Vec::new()));
+            entries.len() - 1
+        }
+    };
+    let known = &mut entries[index].1;
+    for alias in aliases {
+        if !known.iter().any(|name| name == alias) {
+            known.push(alias.to_string());
+        }
+    }
+}
+
+fn ambiguous(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_io::csv::CsvIO;
+
+    fn headers(names: &[&str]) -> StringRecord {
+        StringRecord::from(names.to_vec())
+    }
+
+    fn renames(aliases: &ColumnAliases, names: &[&str]) -> Vec<(String, String)> {
+        aliases.resolve(&headers(names)).unwrap().applied.into_iter().map(|alias| (alias.header, alias.canonical)).collect()
+    }
+
+    fn pairs(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected.iter().map(|(header, canonical)| (header.to_string(), canonical.to_string())).collect()
+    }
+
+    fn error(aliases: &ColumnAliases, names: &[&str]) -> String {
+        let error = aliases.resolve(&headers(names)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        error.to_string()
+    }
+
+    fn fixture(name: &str, contents: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("neurorust-aliases-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn aliases_match_whole_headers_without_regard_to_case() {
+        let aliases = ColumnAliases::new(&[("time", &["Time", "time_s", "timestamp", "t"]), ("trial", &["trial_id"])]);
+        assert_eq!(renames(&aliases, &["TIMESTAMP", "Trial_ID", "lfp"]), pairs(&[("TIMESTAMP", "time"), ("Trial_ID", "trial")]));
+        // The canonical name in another case is a match too, and part of a header is not
+        assert_eq!(renames(&aliases, &["TIME", "trials", "t_start"]), pairs(&[("TIME", "time")]));
+        let mapping = aliases.resolve(&headers(&["t", "trial_id", "lfp"])).unwrap();
+        assert_eq!(mapping.canonical_headers(&headers(&["t", "trial_id", "lfp"])), headers(&["time", "trial", "lfp"]));
+        assert_eq!((mapping.original("trial"), mapping.original("lfp")), (Some("trial_id"), None));
+        assert_eq!(mapping.applied[1], AppliedAlias { column: 1, header: "trial_id".to_string(), canonical: "trial".to_string() });
+    }
+
+    #[test]
+    fn an_exact_canonical_header_wins_over_its_aliases() {
+        let aliases = ColumnAliases::neuroscience();
+        // With time present, timestamp is kept as it is
+        assert_eq!(renames(&aliases, &["timestamp", "time", "subj"]), pairs(&[("subj", "subject")]));
+        assert_eq!(renames(&aliases, &["trial", "trial_id"]), pairs(&[]));
+        // Any other pair of matches is ambiguous
+        assert_eq!(error(&aliases, &["Time", "t"]), "Columns 'Time', 't' all match 'time' or its aliases");
+        assert_eq!(error(&aliases, &["sub", "participant_id", "x"]), "Columns 'sub', 'participant_id' all match 'subject' or its aliases");
+
+        let overlapping = ColumnAliases::new(&[("time", &["t"]), ("trial", &["T"])]);
+        assert_eq!(error(&overlapping, &["t"]), "Column 't' is an alias of both 'time' and 'trial'");
+    }
+
+    #[test]
+    fn prefixes_rename_numbered_channels() {
+        let aliases = ColumnAliases::neuroscience();
+        assert_eq!(
+            renames(&aliases, &["Channel_07", "Electrode-3", "elec12", "chan 2", "ch.5", "ch9", "channel", "electrodes4", "ch_x"]),
+            pairs(&[("Channel_07", "ch07"), ("Electrode-3", "ch3"), ("elec12", "ch12"), ("chan 2", "ch2"), ("ch.5", "ch5")])
+        );
+        let clashing = error(&aliases, &["ch1", "channel1"]);
+        assert_eq!(clashing, "Columns 'channel1' and 'ch1' would both be read as 'ch1'");
+        assert_eq!(error(&aliases, &["time_s", "Seconds"]), "Columns 'time_s', 'Seconds' all match 'time' or its aliases");
+
+        // The longest matching alias decides, whichever prefix it belongs to
+        let nested = ColumnAliases::default().prefix("unit", &["u"]).prefix("ch", &["uch"]);
+        assert_eq!(renames(&nested, &["u3", "uch5"]), pairs(&[("u3", "unit3"), ("uch5", "ch5")]));
+        // A column canonical name is never renamed as a channel
+        let mixed = ColumnAliases::new(&[("ch1", &["reference"])]).prefix("ch", &["channel"]);
+        assert_eq!(renames(&mixed, &["ch1", "channel2"]), pairs(&[("channel2", "ch2")]));
+    }
+
+    #[test]
+    fn the_bundled_table_can_be_extended_from_a_csv() {
+        let mut aliases = ColumnAliases::neuroscience();
+        assert_eq!(aliases, ColumnAliases::neuroscience());
+        assert_eq!(renames(&aliases, &["Timestamp", "Trial_No", "Animal_ID", "ses", "Trial_Type"]).len(), 5);
+        let path = fixture("lab.csv", "alias,canonical,kind\nLFP_uV,lfp,\nlfp_raw,lfp,column\nclu,unit,prefix\nsecs,time,\n");
+        aliases.extend_from_csv(&mut CsvReader::open(&path).unwrap()).unwrap();
+        assert_eq!(renames(&aliases, &["secs", "lfp_raw", "clu_4"]), pairs(&[("secs", "time"), ("lfp_raw", "lfp"), ("clu_4", "unit4")]));
+        assert_eq!(aliases, ColumnAliases::neuroscience().column("lfp", &["LFP_uV", "lfp_raw"]).prefix("unit", &["clu"]).column("time", &["secs"]));
+
+        // Kind is optional, and every row is checked
+        std::fs::write(&path, "canonical,alias\ntrial,attempt\n").unwrap();
+        let mut plain = ColumnAliases::default();
+        plain.extend_from_csv(&mut CsvReader::open(&path).unwrap()).unwrap();
+        assert_eq!(plain, ColumnAliases::new(&[("trial", &["attempt"])]));
+        for (contents, message, kind) in [
+            ("canonical,alias,kind\ntime,t,column\ntrial,tr,suffix\n", "Line 3: Alias kind 'suffix' is not 'column' or 'prefix'", io::ErrorKind::InvalidData),
+            ("canonical,alias\ntime,\n", "Line 2: The canonical name and the alias must not be empty", io::ErrorKind::InvalidData),
+            ("name,alias\ntime,t\n", "Column 'canonical' not found", io::ErrorKind::InvalidInput),
+        ] {
+            std::fs::write(&path, contents).unwrap();
+            let error = ColumnAliases::default().extend_from_csv(&mut CsvReader::open(&path).unwrap()).unwrap_err();
+            assert_eq!((error.kind(), error.to_string()), (kind, message.to_string()));
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn readers_look_up_and_write_the_canonical_names() {
+        let path = fixture("rig_b.csv", "Timestamp,Trial_ID,stim_type,channel_1\n0.5,2,go,1.5\n0.1,1,nogo,2.5\n0.9,3,go,3.5\n");
+        let mut reader = CsvReader::open(&path).unwrap().with_column_aliases(&ColumnAliases::neuroscience()).unwrap();
+        assert_eq!(*reader.headers(), headers(&["time", "trial", "condition", "ch1"]));
+        assert_eq!(reader.column_mapping().unwrap().original("condition"), Some("stim_type"));
+        assert_eq!(reader.try_clone().unwrap().headers(), reader.headers());
+        let counts = reader.try_clone().unwrap().value_counts("condition", None).unwrap();
+        assert_eq!(counts.counts, [("go".to_string(), 2), ("nogo".to_string(), 1)]);
+
+        let sorted = path.with_extension("sorted.csv");
+        reader.sort(&["trial"], true, &mut CsvWriter::create(&sorted).unwrap()).unwrap();
+        assert_eq!(std::fs::read_to_string(&sorted).unwrap(), "time,trial,condition,ch1\n0.1,1,nogo,2.5\n0.5,2,go,1.5\n0.9,3,go,3.5\n");
+
+        // A second table renames the already renamed headers and its renames join the mapping
+        let mut reader = CsvReader::open(&path).unwrap().with_column_aliases(&ColumnAliases::neuroscience()).unwrap();
+        reader = reader.with_column_aliases(&ColumnAliases::new(&[("onset", &["time"]), ("stimulus", &["stim_type"])])).unwrap();
+        assert_eq!(*reader.headers(), headers(&["onset", "trial", "condition", "ch1"]));
+        let applied: Vec<(usize, &str, &str)> = reader.column_mapping().unwrap().applied.iter().map(|alias| (alias.column, alias.header.as_str(), alias.canonical.as_str())).collect();
+        assert_eq!(applied, [(0, "Timestamp", "time"), (1, "Trial_ID", "trial"), (2, "stim_type", "condition"), (3, "channel_1", "ch1"), (0, "time", "onset")]);
+
+        // An ambiguous file fails before anything is read
+        std::fs::write(&path, "time_s,timestamp\n1,2\n").unwrap();
+        let Err(error) = CsvReader::open(&path).unwrap().with_column_aliases(&ColumnAliases::neuroscience()) else {
+            panic!("Expected the aliases to be ambiguous");
+        };
+        assert_eq!(error.to_string(), "Columns 'time_s', 'timestamp' all match 'time' or its aliases");
+        assert!(CsvReader::open(&path).unwrap().column_mapping().is_none());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&sorted).unwrap();
+    }
+
+    #[test]
+    fn the_applied_mapping_can_be_logged() {
+        let path = fixture("rig_a.csv", "t,subj\n0,s1\n");
+        let mut csv_io = CsvIO::open_read(path.to_str().unwrap()).unwrap();
+        assert!(csv_io.column_mapping().is_none());
+        csv_io.set_column_aliases(&ColumnAliases::neuroscience()).unwrap();
+        let log = path.with_extension("mapping.csv");
+        csv_io.column_mapping().unwrap().to_csv(&mut CsvWriter::create(&log).unwrap()).unwrap();
+        assert_eq!(std::fs::read_to_string(&log).unwrap(), "column,header,canonical\n0,t,time\n1,subj,subject\n");
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&log).unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn typed_reads_use_the_canonical_names() {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Trial {
+            trial: u32,
+            condition: String,
+        }
+        let path = fixture("typed.csv", "Trial_Number,cond\n1,go\n2,nogo\n");
+        let mut csv_io = CsvIO::open_read(path.to_str().unwrap()).unwrap();
+        csv_io.set_column_aliases(&ColumnAliases::neuroscience()).unwrap();
+        let trials: Vec<Trial> = csv_io.read_into().unwrap();
+        assert_eq!(trials, [Trial { trial: 1, condition: "go".to_string() }, Trial { trial: 2, condition: "nogo".to_string() }]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
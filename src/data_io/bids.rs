@@ -0,0 +1,720 @@
+// A module to export events, epochs and evoked responses as BIDS tsv files with json sidecars
+
+// Written by Amin Alam in 2024
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use csv::{ReaderBuilder, StringRecord, Writer, WriterBuilder};
+use crate::data_io::float_format::FloatFormat;
+use crate::processing::checkpoint::quote;
+use crate::processing::evoked::EvokedResponse;
+use crate::processing::filter::FilterKind;
+
+/// The value BIDS tsv files hold in place of a missing value
+pub const BIDS_NA: &str = "n/a";
+
+/// The entities that name a BIDS file, e.g. `sub-01_ses-02_task-oddball_run-1_desc-clean_epochs.tsv`
+///
+/// # Arguments
+///
+/// * `subject` - The label of the subject, the `sub` entity
+/// * `session` - The label of the session, the `ses` entity, if any
+/// * `task` - The label of the task, the `task` entity, if any
+/// * `run` - The index of the run, the `run` entity, if any
+/// * `description` - The label of the derivative, the `desc` entity, if any
+///
+/// # Examples
+///
+/// ```
+/// let entities = BidsEntities { task: Some("oddball".to_string()), run: Some(1), ..BidsEntities::new("01") };
+/// assert_eq!(entities.filename("events", "tsv")?, "sub-01_task-oddball_run-1_events.tsv");
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BidsEntities {
+    pub subject: String,
+    pub session: Option<String>,
+    pub task: Option<String>,
+    pub run: Option<u32>,
+    pub description: Option<String>,
+}
+
+/// Implementation of the BidsEntities struct
+///
+/// # Methods
+///
+/// * `new` - Creates the entities of a subject, without session, task, run or description
+/// * `filename` - Builds the name of a file from the entities, a suffix and an extension
+impl BidsEntities {
+    /// Creates the entities of a subject, without session, task, run or description
+    ///
+    /// # Arguments
+    ///
+    /// * `subject` - The label of the subject, without the `sub-` prefix
+    ///
+    /// # Returns
+    ///
+    /// The BidsEntities
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let entities = BidsEntities::new("01");
+    /// ```
+    ///
+    pub fn new(subject: &str) -> Self {
+        Self { subject: subject.to_string(), session: None, task: None, run: None, description: None }
+    }
+
+    /// Builds the name of a file from the entities, a suffix and an extension
+    ///
+    /// # Arguments
+    ///
+    /// * `suffix` - The suffix, e.g. `events`
+    /// * `extension` - The extension without its dot, e.g. `tsv`
+    ///
+    /// # Returns
+    ///
+    /// The file name with the entities in the order of the BIDS specification, or an error if
+    /// a label or the suffix is empty or not alphanumeric
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let sidecar = entities.filename("epochs", "json")?;
+    /// ```
+    ///
+    pub fn filename(&self, suffix: &str, extension: &str) -> io::Result<String> {
+        let mut parts = vec![("sub", self.subject.clone())];
+        parts.extend(self.session.clone().map(|session| ("ses", session)));
+        parts.extend(self.task.clone().map(|task| ("task", task)));
+        parts.extend(self.run.map(|run| ("run", run.to_string())));
+        parts.extend(self.description.clone().map(|description| ("desc", description)));
+        for (key, label) in parts.iter().chain([&("suffix", suffix.to_string())]) {
+            if label.is_empty() || !label.chars().all(|c| c.is_ascii_alphanumeric()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("The BIDS {} '{}' must be a non-empty alphanumeric label", key, label),
+                ));
+            }
+        }
+        let entities: Vec<String> = parts.iter().map(|(key, label)| format!("{}-{}", key, label)).collect();
+        Ok(format!("{}_{}.{}", entities.join("_"), suffix, extension))
+    }
+}
+
+/// An event of a BIDS `events.tsv` file
+///
+/// # Arguments
+///
+/// * `onset` - The onset in seconds from the start of the recording
+/// * `duration` - The duration in seconds, or None if it is not known (`n/a`)
+/// * `trial_type` - The category of the event, or None if it has none (`n/a`)
+///
+/// # Examples
+///
+/// ```
+/// let events: Vec<BidsEvent> = triggers
+///     .iter()
+///     .map(|event| BidsEvent { onset: event.time, duration: event.duration, trial_type: Some(event.label.clone()) })
+///     .collect();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BidsEvent {
+    pub onset: f64,
+    pub duration: Option<f64>,
+    pub trial_type: Option<String>,
+}
+
+/// A processing step listed under `SoftwareFilters` in a sidecar
+///
+/// # Arguments
+///
+/// * `name` - The name of the filter, e.g. `Highpass`
+/// * `parameters` - The name and value of each parameter, e.g. `("FrequencyCutoff", "0.1")`; values that are numbers or `true`/`false` are written as JSON numbers or booleans
+///
+/// # Examples
+///
+/// ```
+/// let highpass = SoftwareFilter { name: "Highpass".to_string(), parameters: vec![("FrequencyCutoff".to_string(), "0.1".to_string())] };
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SoftwareFilter {
+    pub name: String,
+    pub parameters: Vec<(String, String)>,
+}
+
+/// Implementation of the SoftwareFilter struct
+///
+/// # Methods
+///
+/// * `butterworth` - Describes a Butterworth filter designed with `butterworth`
+impl SoftwareFilter {
+    /// Describes a Butterworth filter designed with `butterworth`
+    ///
+    /// # Arguments
+    ///
+    /// * `order` - The order the filter was designed with
+    /// * `kind` - The kind and cutoff frequencies of the filter
+    /// * `zero_phase` - Whether the filter was applied with `filtfilt`
+    ///
+    /// # Returns
+    ///
+    /// The SoftwareFilter, named after the kind of the filter
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let highpass = butterworth(4, FilterKind::Highpass(0.1), 500.0)?;
+    /// let cleaned = highpass.filtfilt(&eeg)?;
+    /// sidecar.software_filters.push(SoftwareFilter::butterworth(4, FilterKind::Highpass(0.1), true));
+    /// ```
+    ///
+    pub fn butterworth(order: usize, kind: FilterKind, zero_phase: bool) -> Self {
+        let (name, mut parameters) = match kind {
+            FilterKind::Lowpass(cutoff) => ("Lowpass", vec![("FrequencyCutoff", cutoff.to_string())]),
+            FilterKind::Highpass(cutoff) => ("Highpass", vec![("FrequencyCutoff", cutoff.to_string())]),
+            FilterKind::Bandpass(low, high) => ("Bandpass", vec![("LowCutoff", low.to_string()), ("HighCutoff", high.to_string())]),
+        };
+        parameters.push(("FilterType", "Butterworth".to_string()));
+        parameters.push(("FilterOrder", order.to_string()));
+        parameters.push(("ZeroPhase", zero_phase.to_string()));
+        Self {
+            name: name.to_string(),
+            parameters: parameters.into_iter().map(|(key, value)| (key.to_string(), value)).collect(),
+        }
+    }
+}
+
+/// The metadata written to the json sidecar of a data file
+///
+/// # Arguments
+///
+/// * `sampling_rate` - The sampling rate in Hz, written as `SamplingFrequency`
+/// * `units` - The units of the channel values, e.g. `uV`
+/// * `description` - A free-text description of the file, written as `Description`, if any
+/// * `software_filters` - The processing applied to the data in order, written as `SoftwareFilters` (`n/a` if empty)
+///
+/// # Examples
+///
+/// ```
+/// let sidecar = BidsSidecar { software_filters: vec![highpass], ..BidsSidecar::new(500.0, "uV") };
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BidsSidecar {
+    pub sampling_rate: f64,
+    pub units: String,
+    pub description: Option<String>,
+    pub software_filters: Vec<SoftwareFilter>,
+}
+
+/// Implementation of the BidsSidecar struct
+///
+/// # Methods
+///
+/// * `new` - Creates the metadata of unfiltered data
+impl BidsSidecar {
+    /// Creates the metadata of unfiltered data
+    ///
+    /// # Arguments
+    ///
+    /// * `sampling_rate` - The sampling rate in Hz
+    /// * `units` - The units of the channel values
+    ///
+    /// # Returns
+    ///
+    /// The BidsSidecar, without description or filters
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let sidecar = BidsSidecar::new(500.0, "uV");
+    /// ```
+    ///
+    pub fn new(sampling_rate: f64, units: &str) -> Self {
+        Self { sampling_rate, units: units.to_string(), description: None, software_filters: Vec::new() }
+    }
+}
+
+/// Writes events as a BIDS `events.tsv` file with `onset`, `duration` and `trial_type` columns
+///
+/// # Arguments
+///
+/// * `events` - The events to write
+/// * `tsv_path` - The path of the tsv file, e.g. built with `BidsEntities::filename("events", "tsv")`
+///
+/// # Returns
+///
+/// Nothing, or an error if the file cannot be written
+///
+/// # Examples
+///
+/// ```
+/// let path = Path::new("sub-01/eeg").join(entities.filename("events", "tsv")?);
+/// events_to_bids(&events, &path)?;
+/// ```
+///
+/// # Note
+///
+/// Missing durations and trial types are written as `n/a`. Numbers are written in their
+/// shortest exact form, so `events_from_bids` reads back the same events.
+///
+pub fn events_to_bids<P: AsRef<Path>>(events: &[BidsEvent], tsv_path: P) -> io::Result<()> {
+    let float_format = FloatFormat::default();
+    let mut writer = tsv_writer(tsv_path.as_ref())?;
+    writer.write_record(["onset", "duration", "trial_type"])?;
+    for event in events {
+        writer.write_record([
+            format_value(event.onset, &float_format),
+            event.duration.map_or(BIDS_NA.to_string(), |duration| format_value(duration, &float_format)),
+            event.trial_type.clone().unwrap_or_else(|| BIDS_NA.to_string()),
+        ])?;
+    }
+    writer.flush()
+}
+
+/// Reads the events of a BIDS `events.tsv` file
+///
+/// # Arguments
+///
+/// * `tsv_path` - The path of the tsv file
+///
+/// # Returns
+///
+/// The events in the order of the file, or an error if the file cannot be read, has no
+/// `onset` or `duration` column, or an onset or duration is not a number
+///
+/// # Examples
+///
+/// ```
+/// let events = events_from_bids("sub-01/eeg/sub-01_task-oddball_events.tsv")?;
+/// ```
+///
+/// # Note
+///
+/// `n/a` is read as a missing duration or trial type, and a file without a `trial_type`
+/// column gives events without one. Other columns are ignored.
+///
+pub fn events_from_bids<P: AsRef<Path>>(tsv_path: P) -> io::Result<Vec<BidsEvent>> {
+    let mut reader = ReaderBuilder::new().delimiter(b'\t').from_path(tsv_path).map_err(io::Error::from)?;
+    let headers = reader.headers().map_err(io::Error::from)?.clone();
+    let column = |name: &str| headers.iter().position(|header| header == name);
+    let (Some(onset), Some(duration)) = (column("onset"), column("duration")) else {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "A BIDS events file needs onset and duration columns"));
+    };
+    let trial_type = column("trial_type");
+    let mut events = Vec::new();
+    for record in reader.records() {
+        let record = record.map_err(io::Error::from)?;
+        let line = record.position().map_or(0, |position| position.line());
+        let field = |index: usize| record.get(index).unwrap_or(BIDS_NA).trim();
+        let number = |text: &str| {
+            text.parse::<f64>()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Line {}: '{}' is not a number", line, text)))
+        };
+        events.push(BidsEvent {
+            onset: number(field(onset))?,
+            duration: match field(duration) {
+                BIDS_NA => None,
+                text => Some(number(text)?),
+            },
+            trial_type: trial_type.map(field).filter(|&text| text != BIDS_NA).map(str::to_string),
+        });
+    }
+    Ok(events)
+}
+
+/// Writes epoched trials as a BIDS-style `epochs.tsv` file and its json sidecar
+///
+/// # Arguments
+///
+/// * `trials` - The samples of each trial, indexed as `trials[trial][channel][time]`
+/// * `names` - The name of each channel
+/// * `start_time` - The time of the first sample of each trial in seconds relative to the event, e.g. `-0.2`
+/// * `sidecar` - The sampling rate, units, description and processing history
+/// * `directory` - The directory the files are written to, created if missing
+/// * `entities` - The entities that name the files
+///
+/// # Returns
+///
+/// The paths of the tsv file and the sidecar, or an error if the trials differ in their
+/// number of channels or samples, the entities are invalid or the files cannot be written
+///
+/// # Examples
+///
+/// ```
+/// let entities = BidsEntities { task: Some("oddball".to_string()), description: Some("clean".to_string()), ..BidsEntities::new("01") };
+/// let (tsv, json) = epochs_to_bids(&trials, &names, -0.2, &BidsSidecar::new(500.0, "uV"), "derivatives/neurorust/sub-01/eeg", &entities)?;
+/// ```
+///
+/// # Note
+///
+/// The tsv file has the columns `epoch,time,<channel names>`, with one row per trial and
+/// sample, and NaN samples written as `n/a`. The sidecar holds `SamplingFrequency`,
+/// `StartTime`, `SoftwareFilters` and a description and the units of every column.
+///
+pub fn epochs_to_bids<P: AsRef<Path>>(
+    trials: &[Vec<Vec<f64>>],
+    names: &[String],
+    start_time: f64,
+    sidecar: &BidsSidecar,
+    directory: P,
+    entities: &BidsEntities,
+) -> io::Result<(PathBuf, PathBuf)> {
+    let n_samples = trials.first().and_then(|trial| trial.first()).map_or(0, Vec::len);
+    if trials.iter().any(|trial| trial.len() != names.len() || trial.iter().any(|channel| channel.len() != n_samples)) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Every trial must have {} channels of {} samples", names.len(), n_samples),
+        ));
+    }
+    let (tsv_path, json_path) = bids_paths(directory.as_ref(), entities, "epochs")?;
+    let float_format = FloatFormat::default();
+    let mut writer = tsv_writer(&tsv_path)?;
+    let mut header = StringRecord::from(vec!["epoch", "time"]);
+    names.iter().for_each(|name| header.push_field(name));
+    writer.write_record(&header)?;
+    for (epoch, trial) in trials.iter().enumerate() {
+        for t in 0..n_samples {
+            let mut record = vec![epoch.to_string(), format_value(start_time + t as f64 / sidecar.sampling_rate, &float_format)];
+            record.extend(trial.iter().map(|channel| format_value(channel[t], &float_format)));
+            writer.write_record(&record)?;
+        }
+    }
+    writer.flush()?;
+
+    let mut columns = vec![
+        column_description("epoch", "The index of the epoch", None),
+        column_description("time", "The time relative to the event", Some("s")),
+    ];
+    columns.extend(names.iter().map(|name| column_description(name, "The samples of the channel", Some(&sidecar.units))));
+    write_sidecar(&json_path, sidecar, start_time, &columns)?;
+    Ok((tsv_path, json_path))
+}
+
+/// Writes an evoked response as a BIDS-style `evoked.tsv` file and its json sidecar
+///
+/// # Arguments
+///
+/// * `evoked` - The evoked response
+/// * `sidecar` - The sampling rate, units, description and processing history
+/// * `directory` - The directory the files are written to, created if missing
+/// * `entities` - The entities that name the files
+///
+/// # Returns
+///
+/// The paths of the tsv file and the sidecar, or an error if the entities are invalid or
+/// the files cannot be written
+///
+/// # Examples
+///
+/// ```
+/// let erp = average(&trials, &names, 500.0, -0.2)?;
+/// evoked_to_bids(&erp, &BidsSidecar::new(500.0, "uV"), "derivatives/neurorust/sub-01/eeg", &entities)?;
+/// ```
+///
+/// # Note
+///
+/// The tsv file has the columns `time,channel,mean,std,sem,n`, like
+/// `EvokedResponse::to_csv_long`, with NaN values written as `n/a`. The sidecar holds
+/// `SamplingFrequency`, `StartTime` (the first time of the response), `SoftwareFilters`
+/// and a description and the units of every column.
+///
+pub fn evoked_to_bids<P: AsRef<Path>>(evoked: &EvokedResponse, sidecar: &BidsSidecar, directory: P, entities: &BidsEntities) -> io::Result<(PathBuf, PathBuf)> {
+    let (tsv_path, json_path) = bids_paths(directory.as_ref(), entities, "evoked")?;
+    let float_format = FloatFormat::default();
+    let mut writer = tsv_writer(&tsv_path)?;
+    writer.write_record(["time", "channel", "mean", "std", "sem", "n"])?;
+    for (t, time) in evoked.times.iter().enumerate() {
+        for (c, name) in evoked.names.iter().enumerate() {
+            writer.write_record([
+                format_value(*time, &float_format),
+                name.clone(),
+                format_value(evoked.mean[c][t], &float_format),
+                format_value(evoked.std[c][t], &float_format),
+                format_value(evoked.sem[c][t], &float_format),
+                evoked.n[c][t].to_string(),
+            ])?;
+        }
+    }
+    writer.flush()?;
+
+    let columns = [
+        column_description("time", "The time relative to the event", Some("s")),
+        column_description("channel", "The name of the channel", None),
+        column_description("mean", "The mean over trials", Some(&sidecar.units)),
+        column_description("std", "The sample standard deviation over trials", Some(&sidecar.units)),
+        column_description("sem", "The standard error of the mean", Some(&sidecar.units)),
+        column_description("n", "The number of trials with a value", None),
+    ];
+    write_sidecar(&json_path, sidecar, evoked.times.first().copied().unwrap_or(0.0), &columns)?;
+    Ok((tsv_path, json_path))
+}
+
+/// Creates the directory and returns the paths of a tsv file and its sidecar
+fn bids_paths(directory: &Path, entities: &BidsEntities, suffix: &str) -> io::Result<(PathBuf, PathBuf)> {
+    let tsv_path = directory.join(entities.filename(suffix, "tsv")?);
+    let json_path = directory.join(entities.filename(suffix, "json")?);
+    fs::create_dir_all(directory)?;
+    Ok((tsv_path, json_path))
+}
+
+/// Creates a tab-separated writer
+fn tsv_writer(path: &Path) -> io::Result<Writer<File>> {
+    WriterBuilder::new().delimiter(b'\t').from_path(path).map_err(io::Error::from)
+}
+
+/// Formats a number, with NaN as `n/a`
+fn format_value(value: f64, float_format: &FloatFormat) -> String {
+    if value.is_nan() {
+        BIDS_NA.to_string()
+    } else {
+        float_format.format(value)
+    }
+}
+
+/// Formats a number as JSON, with non-finite numbers as null
+fn json_number(value: f64) -> String {
+    if value.is_finite() {
+        FloatFormat::default().format(value)
+    } else {
+        "null".to_string()
+    }
+}
+
+/// Returns the sidecar entry that describes a column
+fn column_description(name: &str, description: &str, units: Option<&str>) -> (String, String) {
+    let mut fields = vec![format!("\"Description\": {}", quote(description))];
+    fields.extend(units.map(|units| format!("\"Units\": {}", quote(units))));
+    (name.to_string(), format!("{{{}}}", fields.join(", ")))
+}
+
+/// Writes a json sidecar with the metadata and the column descriptions
+fn write_sidecar(path: &Path, sidecar: &BidsSidecar, start_time: f64, columns: &[(String, String)]) -> io::Result<()> {
+    let mut entries: Vec<(String, String)> = Vec::new();
+    entries.extend(sidecar.description.as_deref().map(|description| ("Description".to_string(), quote(description))));
+    entries.push(("SamplingFrequency".to_string(), json_number(sidecar.sampling_rate)));
+    entries.push(("StartTime".to_string(), json_number(start_time)));
+    let filters = if sidecar.software_filters.is_empty() {
+        quote(BIDS_NA)
+    } else {
+        let filters: Vec<String> = sidecar
+            .software_filters
+            .iter()
+            .map(|filter| {
+                let parameters: Vec<String> = filter
+                    .parameters
+                    .iter()
+                    .map(|(name, value)| {
+                        let value = match value.trim() {
+                            literal @ ("true" | "false") => literal.to_string(),
+                            text => text.parse::<f64>().ok().filter(|number| number.is_finite()).map_or_else(|| quote(value), json_number),
+                        };
+                        format!("      {}: {}", quote(name), value)
+                    })
+                    .collect();
+                if parameters.is_empty() {
+                    format!("    {}: {{}}", quote(&filter.name))
+                } else {
+                    format!("    {}: {{\n{}\n    }}", quote(&filter.name), parameters.join(",\n"))
+                }
+            })
+            .collect();
+        format!("{{\n{}\n  }}", filters.join(",\n"))
+    };
+    entries.push(("SoftwareFilters".to_string(), filters));
+    entries.extend(columns.iter().cloned());
+
+    let body: Vec<String> = entries.iter().map(|(key, value)| format!("  {}: {}", quote(key), value)).collect();
+    let mut writer = BufWriter::new(File::create(path)?);
+    writeln!(writer, "{{\n{}\n}}", body.join(",\n"))?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("neurorust-bids-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&directory);
+        directory
+    }
+
+    fn entities() -> BidsEntities {
+        BidsEntities { task: Some("oddball".to_string()), run: Some(1), description: Some("clean".to_string()), ..BidsEntities::new("01") }
+    }
+
+    #[test]
+    fn filenames_follow_the_order_of_the_entities() {
+        let mut entities = BidsEntities::new("01");
+        assert_eq!(entities.filename("events", "tsv").unwrap(), "sub-01_events.tsv");
+        entities.description = Some("clean".to_string());
+        entities.run = Some(2);
+        entities.task = Some("oddball".to_string());
+        entities.session = Some("02".to_string());
+        assert_eq!(entities.filename("epochs", "json").unwrap(), "sub-01_ses-02_task-oddball_run-2_desc-clean_epochs.json");
+
+        for invalid in [
+            BidsEntities::new(""),
+            BidsEntities::new("01_a"),
+            BidsEntities { task: Some("odd-ball".to_string()), ..BidsEntities::new("01") },
+            BidsEntities { description: Some(String::new()), ..BidsEntities::new("01") },
+        ] {
+            assert_eq!(invalid.filename("events", "tsv").unwrap_err().kind(), io::ErrorKind::InvalidInput, "{:?}", invalid);
+        }
+        assert!(BidsEntities::new("01").filename("ev_ents", "tsv").is_err());
+    }
+
+    #[test]
+    fn events_match_the_expected_file_and_read_back_unchanged() {
+        let directory = test_dir("events");
+        fs::create_dir_all(&directory).unwrap();
+        let path = directory.join(BidsEntities { task: Some("oddball".to_string()), ..BidsEntities::new("01") }.filename("events", "tsv").unwrap());
+        assert!(path.ends_with("sub-01_task-oddball_events.tsv"));
+        let events = vec![
+            BidsEvent { onset: 0.1, duration: Some(0.5), trial_type: Some("standard".to_string()) },
+            BidsEvent { onset: 1.2345678901234567, duration: None, trial_type: Some("deviant".to_string()) },
+            BidsEvent { onset: 3.0, duration: Some(0.0), trial_type: None },
+            BidsEvent { onset: 1e-7, duration: Some(2.5e20), trial_type: Some("two words".to_string()) },
+        ];
+        events_to_bids(&events, &path).unwrap();
+        let expected = "onset\tduration\ttrial_type\n\
+                        0.1\t0.5\tstandard\n\
+                        1.2345678901234567\tn/a\tdeviant\n\
+                        3\t0\tn/a\n\
+                        1e-7\t2.5e20\ttwo words\n";
+        assert_eq!(fs::read_to_string(&path).unwrap(), expected);
+        assert_eq!(events_from_bids(&path).unwrap(), events);
+
+        let reordered = directory.join("reordered.tsv");
+        fs::write(&reordered, "trial_type\tsample\tonset\tduration\nstandard\t50\t0.1\tn/a\nn/a\t600\t1.2\t 0.25\n").unwrap();
+        assert_eq!(
+            events_from_bids(&reordered).unwrap(),
+            vec![
+                BidsEvent { onset: 0.1, duration: None, trial_type: Some("standard".to_string()) },
+                BidsEvent { onset: 1.2, duration: Some(0.25), trial_type: None },
+            ]
+        );
+        fs::write(&reordered, "onset\tduration\n0.1\t0.5\n").unwrap();
+        assert_eq!(events_from_bids(&reordered).unwrap(), vec![BidsEvent { onset: 0.1, duration: Some(0.5), trial_type: None }]);
+        fs::write(&reordered, "onset\ttrial_type\n0.1\tstandard\n").unwrap();
+        assert_eq!(events_from_bids(&reordered).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::write(&reordered, "onset\tduration\n0.1\t0.5\nsoon\t0.5\n").unwrap();
+        let error = events_from_bids(&reordered).unwrap_err();
+        assert!(error.to_string().contains("Line 3") && error.to_string().contains("'soon'"), "{}", error);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn epochs_match_the_expected_tsv_and_sidecar() {
+        let directory = test_dir("epochs");
+        let trials = vec![
+            vec![vec![1.0, 2.0, 3.0], vec![-0.5, f64::NAN, 0.25]],
+            vec![vec![4.0, 5.0, 6.0], vec![0.0, 1.5, -2.0]],
+        ];
+        let names = vec!["Cz".to_string(), "Pz".to_string()];
+        let sidecar = BidsSidecar {
+            description: Some("Epochs around \"deviant\" tones".to_string()),
+            software_filters: vec![
+                SoftwareFilter::butterworth(4, FilterKind::Highpass(0.1), true),
+                SoftwareFilter { name: "Rereference".to_string(), parameters: vec![("Reference".to_string(), "average".to_string())] },
+            ],
+            ..BidsSidecar::new(4.0, "uV")
+        };
+        let (tsv, json) = epochs_to_bids(&trials, &names, -0.5, &sidecar, &directory, &entities()).unwrap();
+        assert_eq!(tsv, directory.join("sub-01_task-oddball_run-1_desc-clean_epochs.tsv"));
+        assert_eq!(json, directory.join("sub-01_task-oddball_run-1_desc-clean_epochs.json"));
+
+        let expected_tsv = "epoch\ttime\tCz\tPz\n\
+                            0\t-0.5\t1\t-0.5\n\
+                            0\t-0.25\t2\tn/a\n\
+                            0\t0\t3\t0.25\n\
+                            1\t-0.5\t4\t0\n\
+                            1\t-0.25\t5\t1.5\n\
+                            1\t0\t6\t-2\n";
+        assert_eq!(fs::read_to_string(&tsv).unwrap(), expected_tsv);
+        let expected_json = r#"{
+  "Description": "Epochs around \"deviant\" tones",
+  "SamplingFrequency": 4,
+  "StartTime": -0.5,
+  "SoftwareFilters": {
+    "Highpass": {
+      "FrequencyCutoff": 0.1,
+      "FilterType": "Butterworth",
+      "FilterOrder": 4,
+      "ZeroPhase": true
+    },
+    "Rereference": {
+      "Reference": "average"
+    }
+  },
+  "epoch": {"Description": "The index of the epoch"},
+  "time": {"Description": "The time relative to the event", "Units": "s"},
+  "Cz": {"Description": "The samples of the channel", "Units": "uV"},
+  "Pz": {"Description": "The samples of the channel", "Units": "uV"}
+}
+"#;
+        assert_eq!(fs::read_to_string(&json).unwrap(), expected_json);
+
+        let ragged = vec![vec![vec![1.0, 2.0, 3.0], vec![0.0, 0.0]]];
+        assert_eq!(epochs_to_bids(&ragged, &names, -0.5, &sidecar, &directory, &entities()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        let missing_channel = vec![vec![vec![1.0, 2.0, 3.0]]];
+        assert!(epochs_to_bids(&missing_channel, &names, -0.5, &sidecar, &directory, &entities()).is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn evoked_matches_the_expected_tsv_and_sidecar() {
+        let directory = test_dir("evoked");
+        let evoked = EvokedResponse {
+            times: vec![-0.5, 0.0],
+            names: vec!["Cz".to_string(), "Pz".to_string()],
+            mean: vec![vec![1.5, 2.5], vec![-1.0, f64::NAN]],
+            std: vec![vec![0.5, 0.25], vec![2.0, f64::NAN]],
+            sem: vec![vec![0.25, 0.125], vec![1.0, f64::NAN]],
+            n: vec![vec![4, 4], vec![4, 0]],
+        };
+        let sidecar = BidsSidecar::new(2.0, "uV");
+        let (tsv, json) = evoked_to_bids(&evoked, &sidecar, &directory, &entities()).unwrap();
+        assert!(tsv.ends_with("sub-01_task-oddball_run-1_desc-clean_evoked.tsv"));
+        let expected_tsv = "time\tchannel\tmean\tstd\tsem\tn\n\
+                            -0.5\tCz\t1.5\t0.5\t0.25\t4\n\
+                            -0.5\tPz\t-1\t2\t1\t4\n\
+                            0\tCz\t2.5\t0.25\t0.125\t4\n\
+                            0\tPz\tn/a\tn/a\tn/a\t0\n";
+        assert_eq!(fs::read_to_string(&tsv).unwrap(), expected_tsv);
+        let expected_json = r#"{
+  "SamplingFrequency": 2,
+  "StartTime": -0.5,
+  "SoftwareFilters": "n/a",
+  "time": {"Description": "The time relative to the event", "Units": "s"},
+  "channel": {"Description": "The name of the channel"},
+  "mean": {"Description": "The mean over trials", "Units": "uV"},
+  "std": {"Description": "The sample standard deviation over trials", "Units": "uV"},
+  "sem": {"Description": "The standard error of the mean", "Units": "uV"},
+  "n": {"Description": "The number of trials with a value"}
+}
+"#;
+        assert_eq!(fs::read_to_string(&json).unwrap(), expected_json);
+
+        let invalid = BidsEntities::new("0 1");
+        assert_eq!(evoked_to_bids(&evoked, &sidecar, &directory, &invalid).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn butterworth_filters_are_described_by_kind() {
+        let bandpass = SoftwareFilter::butterworth(2, FilterKind::Bandpass(1.0, 40.0), false);
+        assert_eq!(bandpass.name, "Bandpass");
+        let keys: Vec<&str> = bandpass.parameters.iter().map(|(key, _)| key.as_str()).collect();
+        assert_eq!(keys, ["LowCutoff", "HighCutoff", "FilterType", "FilterOrder", "ZeroPhase"]);
+        assert_eq!(bandpass.parameters[1].1, "40");
+        assert_eq!(bandpass.parameters[4].1, "false");
+        assert_eq!(SoftwareFilter::butterworth(4, FilterKind::Lowpass(30.0), true).name, "Lowpass");
+    }
+}
@@ -0,0 +1,725 @@
+// A module to cache results on disk as versioned JSON or bincode files
+
+// Written by Amin Alam in 2024
+
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use crate::core::session::SessionInfo;
+use crate::processing::artifacts::ArtifactRejection;
+use crate::processing::bad_channels::BadChannelReport;
+use crate::processing::cluster::KMeansResult;
+use crate::processing::correlogram::Correlogram;
+use crate::processing::decomposition::{IcaResult, PcaResult};
+use crate::processing::evoked::EvokedResponse;
+use crate::processing::features::FeatureTable;
+use crate::processing::filter::{FirFilter, IirFilter};
+use crate::processing::histogram::Histogram;
+use crate::processing::interpolate::GapReport;
+use crate::processing::normalize::Normalizer;
+use crate::processing::pac::PacResult;
+use crate::processing::psth::Psth;
+use crate::processing::qc::QcReport;
+use crate::processing::rate::FiringRate;
+use crate::processing::spectral::{Coherence, CoherenceMatrix, Spectrogram, Spectrum};
+use crate::processing::spikes::SpikeDetectionResult;
+use crate::processing::stability::StabilityReport;
+use crate::processing::streaming::{StatsTable, StreamingStats};
+use crate::processing::timing::TimingReport;
+use crate::processing::wavelet::TimeFrequency;
+use crate::processing::xcorr::CorrelationResult;
+
+/// The version of the serialized form, increased whenever a cached type changes its fields
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// A type that can be cached on disk between pipeline stages
+///
+/// # Methods
+///
+/// * `save_json` - Writes the value as a versioned JSON file
+/// * `load_json` - Reads a value written by `save_json`
+/// * `save_bincode` - Writes the value as a versioned bincode file, smaller and faster for large arrays
+/// * `load_bincode` - Reads a value written by `save_bincode`
+///
+/// # Examples
+///
+/// ```
+/// let psd = welch(&samples, 1000.0, 1024, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW)?;
+/// psd.save_json("psd.json")?;
+/// let cached = Spectrum::load_json("psd.json")?;
+/// ```
+///
+/// # Note
+///
+/// Every file starts with `SCHEMA_VERSION` and the name of the type, and loading fails with
+/// `InvalidData` if either differs, so a stale or mismatched cache is never silently read
+/// as something else. Matrices are stored as a shape and a flat row-major array. JSON has no
+/// NaN or infinity: they are written as the strings `NaN`, `inf` and `-inf` inside matrices,
+/// and `save_json` fails with `InvalidData` on a non-finite number anywhere else, so use
+/// bincode for results that may hold them.
+pub trait Persist: Serialize + DeserializeOwned {
+    /// The name of the type written into every file
+    const KIND: &'static str;
+
+    /// Writes the value as a versioned JSON file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the JSON file
+    ///
+    /// # Returns
+    ///
+    /// Nothing, an `InvalidData` error naming the field if the value holds a non-finite
+    /// number outside a matrix, or the error of creating or writing the file
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// erp.save_json("erp.json")?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The value is checked before the file is created, so a failed save leaves no file behind
+    ///
+    fn save_json<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.serialize(finite::FiniteCheck).map_err(|error| io::Error::new(io::ErrorKind::InvalidData, error.to_string()))?;
+        let envelope = Envelope { schema_version: SCHEMA_VERSION, kind: Self::KIND.to_string(), data: self };
+        serde_json::to_writer(BufWriter::new(File::create(path)?), &envelope).map_err(io::Error::from)
+    }
+
+    /// Reads a value written by `save_json`
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the JSON file
+    ///
+    /// # Returns
+    ///
+    /// The value, or an error if the file cannot be read, was written with another schema
+    /// version or for another type, or does not hold a valid value
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let erp = EvokedResponse::load_json("erp.json")?;
+    /// ```
+    ///
+    fn load_json<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let envelope: Envelope<serde_json::Value> = serde_json::from_reader(BufReader::new(File::open(path)?)).map_err(io::Error::from)?;
+        check_header(envelope.schema_version, &envelope.kind, Self::KIND)?;
+        serde_json::from_value(envelope.data).map_err(io::Error::from)
+    }
+
+    /// Writes the value as a versioned bincode file, smaller and faster for large arrays
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the bincode file
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or the error of creating or writing the file
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// spectrogram.save_bincode("session-3.spectrogram")?;
+    /// ```
+    ///
+    fn save_bincode<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let envelope = Envelope { schema_version: SCHEMA_VERSION, kind: Self::KIND.to_string(), data: self };
+        bincode::serialize_into(BufWriter::new(File::create(path)?), &envelope).map_err(|error| bincode_error(*error))
+    }
+
+    /// Reads a value written by `save_bincode`
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the bincode file
+    ///
+    /// # Returns
+    ///
+    /// The value, or an error if the file cannot be read, was written with another schema
+    /// version or for another type, or does not hold a valid value
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let spectrogram = Spectrogram::load_bincode("session-3.spectrogram")?;
+    /// ```
+    ///
+    fn load_bincode<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        // The header is read on its own first, so a file of another version is rejected
+        // before its data is interpreted
+        let schema_version: u32 = bincode::deserialize_from(&mut reader).map_err(|error| bincode_error(*error))?;
+        let kind: String = bincode::deserialize_from(&mut reader).map_err(|error| bincode_error(*error))?;
+        check_header(schema_version, &kind, Self::KIND)?;
+        bincode::deserialize_from(&mut reader).map_err(|error| bincode_error(*error))
+    }
+}
+
+impl Persist for SessionInfo {
+    const KIND: &'static str = "SessionInfo";
+}
+
+impl Persist for ArtifactRejection {
+    const KIND: &'static str = "ArtifactRejection";
+}
+
+impl Persist for BadChannelReport {
+    const KIND: &'static str = "BadChannelReport";
+}
+
+impl Persist for KMeansResult {
+    const KIND: &'static str = "KMeansResult";
+}
+
+impl Persist for Correlogram {
+    const KIND: &'static str = "Correlogram";
+}
+
+impl Persist for PcaResult {
+    const KIND: &'static str = "PcaResult";
+}
+
+impl Persist for IcaResult {
+    const KIND: &'static str = "IcaResult";
+}
+
+impl Persist for EvokedResponse {
+    const KIND: &'static str = "EvokedResponse";
+}
+
+impl Persist for FeatureTable {
+    const KIND: &'static str = "FeatureTable";
+}
+
+impl Persist for IirFilter {
+    const KIND: &'static str = "IirFilter";
+}
+
+impl Persist for FirFilter {
+    const KIND: &'static str = "FirFilter";
+}
+
+impl Persist for Histogram {
+    const KIND: &'static str = "Histogram";
+}
+
+impl Persist for GapReport {
+    const KIND: &'static str = "GapReport";
+}
+
+impl Persist for Normalizer {
+    const KIND: &'static str = "Normalizer";
+}
+
+impl Persist for PacResult {
+    const KIND: &'static str = "PacResult";
+}
+
+impl Persist for Psth {
+    const KIND: &'static str = "Psth";
+}
+
+impl Persist for FiringRate {
+    const KIND: &'static str = "FiringRate";
+}
+
+impl Persist for QcReport {
+    const KIND: &'static str = "QcReport";
+}
+
+impl Persist for Spectrum {
+    const KIND: &'static str = "Spectrum";
+}
+
+impl Persist for Spectrogram {
+    const KIND: &'static str = "Spectrogram";
+}
+
+impl Persist for Coherence {
+    const KIND: &'static str = "Coherence";
+}
+
+impl Persist for CoherenceMatrix {
+    const KIND: &'static str = "CoherenceMatrix";
+}
+
+impl Persist for SpikeDetectionResult {
+    const KIND: &'static str = "SpikeDetectionResult";
+}
+
+impl Persist for StabilityReport {
+    const KIND: &'static str = "StabilityReport";
+}
+
+impl Persist for StatsTable {
+    const KIND: &'static str = "StatsTable";
+}
+
+impl Persist for StreamingStats {
+    const KIND: &'static str = "StreamingStats";
+}
+
+impl Persist for TimingReport {
+    const KIND: &'static str = "TimingReport";
+}
+
+impl Persist for TimeFrequency {
+    const KIND: &'static str = "TimeFrequency";
+}
+
+impl Persist for CorrelationResult {
+    const KIND: &'static str = "CorrelationResult";
+}
+
+/// The header written before the data of every cached file
+#[derive(Serialize, Deserialize)]
+struct Envelope<T> {
+    schema_version: u32,
+    kind: String,
+    data: T,
+}
+
+fn check_header(schema_version: u32, kind: &str, expected: &str) -> io::Result<()> {
+    if schema_version != SCHEMA_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Cache has schema version {} but version {} is expected", schema_version, SCHEMA_VERSION),
+        ));
+    }
+    if kind != expected {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Cache holds a {} but a {} is expected", kind, expected)));
+    }
+    Ok(())
+}
+
+fn bincode_error(error: bincode::ErrorKind) -> io::Error {
+    match error {
+        bincode::ErrorKind::Io(error) => error,
+        other => io::Error::new(io::ErrorKind::InvalidData, other.to_string()),
+    }
+}
+
+/// Checks that a value holds no number JSON cannot represent, which serde_json would write as null
+mod finite {
+    use std::fmt;
+    use serde::ser::{self, Serialize, Serializer};
+
+    /// A serializer that writes nothing and fails on the first non-finite number
+    ///
+    /// It is human-readable like JSON, so the non-finite values of matrices, which are
+    /// written as strings, pass.
+    #[derive(Clone, Copy)]
+    pub(super) struct FiniteCheck;
+
+    /// The error of a non-finite number, with the path of the field holding it
+    #[derive(Debug)]
+    pub(super) struct NonFinite {
+        path: Vec<String>,
+        message: String,
+    }
+
+    impl NonFinite {
+        fn within(mut self, field: String) -> Self {
+            self.path.push(field);
+            self
+        }
+    }
+
+    impl fmt::Display for NonFinite {
+        fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            if self.path.is_empty() {
+                return formatter.write_str(&self.message);
+            }
+            let path: Vec<&str> = self.path.iter().rev().map(String::as_str).collect();
+            write!(formatter, "Field `{}`: {}", path.join("."), self.message)
+        }
+    }
+
+    impl std::error::Error for NonFinite {}
+
+    impl ser::Error for NonFinite {
+        fn custom<T: fmt::Display>(message: T) -> Self {
+            Self { path: Vec::new(), message: message.to_string() }
+        }
+    }
+
+    fn check(value: f64) -> Result<(), NonFinite> {
+        if value.is_finite() {
+            Ok(())
+        } else {
+            Err(ser::Error::custom(format!(
+                "{} cannot be written to JSON outside a matrix, save the value with bincode instead",
+                value
+            )))
+        }
+    }
+
+    impl Serializer for FiniteCheck {
+        type Ok = ();
+        type Error = NonFinite;
+        type SerializeSeq = Self;
+        type SerializeTuple = Self;
+        type SerializeTupleStruct = Self;
+        type SerializeTupleVariant = Self;
+        type SerializeMap = Self;
+        type SerializeStruct = Self;
+        type SerializeStructVariant = Self;
+
+        fn serialize_bool(self, _: bool) -> Result<(), NonFinite> {
+            Ok(())
+        }
+        fn serialize_i8(self, _: i8) -> Result<(), NonFinite> {
+            Ok(())
+        }
+        fn serialize_i16(self, _: i16) -> Result<(), NonFinite> {
+            Ok(())
+        }
+        fn serialize_i32(self, _: i32) -> Result<(), NonFinite> {
+            Ok(())
+        }
+        fn serialize_i64(self, _: i64) -> Result<(), NonFinite> {
+            Ok(())
+        }
+        fn serialize_u8(self, _: u8) -> Result<(), NonFinite> {
+            Ok(())
+        }
+        fn serialize_u16(self, _: u16) -> Result<(), NonFinite> {
+            Ok(())
+        }
+        fn serialize_u32(self, _: u32) -> Result<(), NonFinite> {
+            Ok(())
+        }
+        fn serialize_u64(self, _: u64) -> Result<(), NonFinite> {
+            Ok(())
+        }
+        fn serialize_f32(self, value: f32) -> Result<(), NonFinite> {
+            check(value as f64)
+        }
+        fn serialize_f64(self, value: f64) -> Result<(), NonFinite> {
+            check(value)
+        }
+        fn serialize_char(self, _: char) -> Result<(), NonFinite> {
+            Ok(())
+        }
+        fn serialize_str(self, _: &str) -> Result<(), NonFinite> {
+            Ok(())
+        }
+        fn serialize_bytes(self, _: &[u8]) -> Result<(), NonFinite> {
+            Ok(())
+        }
+        fn serialize_none(self) -> Result<(), NonFinite> {
+            Ok(())
+        }
+        fn serialize_some<T: ?Sized + Serialize>(self, value: &T) -> Result<(), NonFinite> {
+            value.serialize(self)
+        }
+        fn serialize_unit(self) -> Result<(), NonFinite> {
+            Ok(())
+        }
+        fn serialize_unit_struct(self, _: &'static str) -> Result<(), NonFinite> {
+            Ok(())
+        }
+        fn serialize_unit_variant(self, _: &'static str, _: u32, _: &'static str) -> Result<(), NonFinite> {
+            Ok(())
+        }
+        fn serialize_newtype_struct<T: ?Sized + Serialize>(self, _: &'static str, value: &T) -> Result<(), NonFinite> {
+            value.serialize(self)
+        }
+        fn serialize_newtype_variant<T: ?Sized + Serialize>(self, _: &'static str, _: u32, variant: &'static str, value: &T) -> Result<(), NonFinite> {
+            value.serialize(self).map_err(|error| error.within(variant.to_string()))
+        }
+        fn serialize_seq(self, _: Option<usize>) -> Result<Self, NonFinite> {
+            Ok(self)
+        }
+        fn serialize_tuple(self, _: usize) -> Result<Self, NonFinite> {
+            Ok(self)
+        }
+        fn serialize_tuple_struct(self, _: &'static str, _: usize) -> Result<Self, NonFinite> {
+            Ok(self)
+        }
+        fn serialize_tuple_variant(self, _: &'static str, _: u32, _: &'static str, _: usize) -> Result<Self, NonFinite> {
+            Ok(self)
+        }
+        fn serialize_map(self, _: Option<usize>) -> Result<Self, NonFinite> {
+            Ok(self)
+        }
+        fn serialize_struct(self, _: &'static str, _: usize) -> Result<Self, NonFinite> {
+            Ok(self)
+        }
+        fn serialize_struct_variant(self, _: &'static str, _: u32, _: &'static str, _: usize) -> Result<Self, NonFinite> {
+            Ok(self)
+        }
+    }
+
+    impl ser::SerializeSeq for FiniteCheck {
+        type Ok = ();
+        type Error = NonFinite;
+        fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), NonFinite> {
+            value.serialize(*self)
+        }
+        fn end(self) -> Result<(), NonFinite> {
+            Ok(())
+        }
+    }
+
+    impl ser::SerializeTuple for FiniteCheck {
+        type Ok = ();
+        type Error = NonFinite;
+        fn serialize_element<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), NonFinite> {
+            value.serialize(*self)
+        }
+        fn end(self) -> Result<(), NonFinite> {
+            Ok(())
+        }
+    }
+
+    impl ser::SerializeTupleStruct for FiniteCheck {
+        type Ok = ();
+        type Error = NonFinite;
+        fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), NonFinite> {
+            value.serialize(*self)
+        }
+        fn end(self) -> Result<(), NonFinite> {
+            Ok(())
+        }
+    }
+
+    impl ser::SerializeTupleVariant for FiniteCheck {
+        type Ok = ();
+        type Error = NonFinite;
+        fn serialize_field<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), NonFinite> {
+            value.serialize(*self)
+        }
+        fn end(self) -> Result<(), NonFinite> {
+            Ok(())
+        }
+    }
+
+    impl ser::SerializeMap for FiniteCheck {
+        type Ok = ();
+        type Error = NonFinite;
+        fn serialize_key<T: ?Sized + Serialize>(&mut self, key: &T) -> Result<(), NonFinite> {
+            key.serialize(*self)
+        }
+        fn serialize_value<T: ?Sized + Serialize>(&mut self, value: &T) -> Result<(), NonFinite> {
+            value.serialize(*self)
+        }
+        fn end(self) -> Result<(), NonFinite> {
+            Ok(())
+        }
+    }
+
+    impl ser::SerializeStruct for FiniteCheck {
+        type Ok = ();
+        type Error = NonFinite;
+        fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<(), NonFinite> {
+            value.serialize(*self).map_err(|error| error.within(key.to_string()))
+        }
+        fn end(self) -> Result<(), NonFinite> {
+            Ok(())
+        }
+    }
+
+    impl ser::SerializeStructVariant for FiniteCheck {
+        type Ok = ();
+        type Error = NonFinite;
+        fn serialize_field<T: ?Sized + Serialize>(&mut self, key: &'static str, value: &T) -> Result<(), NonFinite> {
+            value.serialize(*self).map_err(|error| error.within(key.to_string()))
+        }
+        fn end(self) -> Result<(), NonFinite> {
+            Ok(())
+        }
+    }
+}
+
+/// Serializes matrices, i.e. `Vec<Vec<T>>` fields, as a shape and a flat row-major array
+///
+/// # Examples
+///
+/// ```
+/// #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+/// pub power: Vec<Vec<f64>>,
+/// ```
+pub(crate) mod matrix {
+    use serde::de::{self, Deserializer, Visitor};
+    use serde::ser::Serializer;
+    use serde::{Deserialize, Serialize};
+
+    /// An element of a matrix and the form it is stored in
+    pub(crate) trait Element: Sized + Copy {
+        type Stored: Serialize + for<'de> Deserialize<'de>;
+        fn store(self) -> Self::Stored;
+        fn restore(stored: Self::Stored) -> Self;
+    }
+
+    impl Element for usize {
+        type Stored = u64;
+        fn store(self) -> u64 {
+            self as u64
+        }
+        fn restore(stored: u64) -> Self {
+            stored as usize
+        }
+    }
+
+    impl<T: crate::core::sample::Sample> Element for T {
+        type Stored = Float;
+        fn store(self) -> Float {
+            Float(self.to_f64())
+        }
+        fn restore(stored: Float) -> Self {
+            T::from_f64(stored.0)
+        }
+    }
+
+    /// A float written as a string in human-readable formats when it is not finite
+    pub(crate) struct Float(f64);
+
+    impl Serialize for Float {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            if self.0.is_finite() || !serializer.is_human_readable() {
+                serializer.serialize_f64(self.0)
+            } else if self.0.is_nan() {
+                serializer.serialize_str("NaN")
+            } else if self.0 > 0.0 {
+                serializer.serialize_str("inf")
+            } else {
+                serializer.serialize_str("-inf")
+            }
+        }
+    }
+
+    impl<'de> Deserialize<'de> for Float {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            struct FloatVisitor;
+
+            impl Visitor<'_> for FloatVisitor {
+                type Value = Float;
+
+                fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                    formatter.write_str("a number or one of \"NaN\", \"inf\" and \"-inf\"")
+                }
+
+                fn visit_f64<E: de::Error>(self, value: f64) -> Result<Float, E> {
+                    Ok(Float(value))
+                }
+
+                fn visit_i64<E: de::Error>(self, value: i64) -> Result<Float, E> {
+                    Ok(Float(value as f64))
+                }
+
+                fn visit_u64<E: de::Error>(self, value: u64) -> Result<Float, E> {
+                    Ok(Float(value as f64))
+                }
+
+                fn visit_str<E: de::Error>(self, value: &str) -> Result<Float, E> {
+                    match value {
+                        "NaN" => Ok(Float(f64::NAN)),
+                        "inf" => Ok(Float(f64::INFINITY)),
+                        "-inf" => Ok(Float(f64::NEG_INFINITY)),
+                        other => Err(E::invalid_value(de::Unexpected::Str(other), &self)),
+                    }
+                }
+            }
+
+            if deserializer.is_human_readable() {
+                deserializer.deserialize_any(FloatVisitor)
+            } else {
+                deserializer.deserialize_f64(FloatVisitor)
+            }
+        }
+    }
+
+    #[derive(Serialize, Deserialize)]
+    struct Flat<T> {
+        shape: [usize; 2],
+        data: Vec<T>,
+    }
+
+    #[allow(clippy::ptr_arg)]
+    pub(crate) fn serialize<T: Element, S: Serializer>(rows: &Vec<Vec<T>>, serializer: S) -> Result<S::Ok, S::Error> {
+        let n_columns = rows.first().map_or(0, |row| row.len());
+        if rows.iter().any(|row| row.len() != n_columns) {
+            return Err(serde::ser::Error::custom("Matrix rows differ in length"));
+        }
+        let data = rows.iter().flatten().map(|&value| value.store()).collect();
+        Flat { shape: [rows.len(), n_columns], data }.serialize(serializer)
+    }
+
+    pub(crate) fn deserialize<'de, T: Element, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Vec<T>>, D::Error> {
+        let flat: Flat<T::Stored> = Flat::deserialize(deserializer)?;
+        let [n_rows, n_columns] = flat.shape;
+        if n_rows.checked_mul(n_columns) != Some(flat.data.len()) {
+            return Err(de::Error::custom(format!(
+                "Matrix of shape {}x{} holds {} values",
+                n_rows,
+                n_columns,
+                flat.data.len()
+            )));
+        }
+        let mut values = flat.data.into_iter().map(T::restore);
+        Ok((0..n_rows).map(|_| values.by_ref().take(n_columns).collect()).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("neurorust-cache-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn json_refuses_non_finite_values_outside_matrices() {
+        let spectrum = Spectrum { frequencies: vec![0.0, 1.0], power: vec![1.0, f64::NAN], amplitude: None, phase: None };
+        let path = temp_path("spectrum.json");
+        let error = spectrum.save_json(&path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("`power`"), "{}", error);
+        assert!(!path.exists());
+
+        let bincode_path = temp_path("spectrum.bin");
+        spectrum.save_bincode(&bincode_path).unwrap();
+        let loaded = Spectrum::load_bincode(&bincode_path).unwrap();
+        std::fs::remove_file(&bincode_path).unwrap();
+        assert!(loaded.power[1].is_nan());
+    }
+
+    #[test]
+    fn json_round_trips_non_finite_matrix_values() {
+        let result = KMeansResult {
+            labels: vec![0, 1],
+            centroids: vec![vec![f64::NAN, 1.0], vec![f64::INFINITY, f64::NEG_INFINITY]],
+            inertia: 0.5,
+            counts: vec![1, 1],
+            iterations: 3,
+        };
+        let path = temp_path("kmeans.json");
+        result.save_json(&path).unwrap();
+        let loaded = KMeansResult::load_json(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert!(loaded.centroids[0][0].is_nan());
+        assert_eq!(loaded.centroids[1], vec![f64::INFINITY, f64::NEG_INFINITY]);
+        assert_eq!(loaded.labels, result.labels);
+        assert_eq!(loaded.inertia, result.inertia);
+    }
+
+    #[test]
+    fn loading_rejects_another_kind() {
+        let histogram = Histogram { bin_edges: vec![0.0, 1.0], counts: vec![4] };
+        let path = temp_path("histogram.json");
+        histogram.save_json(&path).unwrap();
+        let error = Psth::load_json(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
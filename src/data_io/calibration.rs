@@ -0,0 +1,454 @@
+// A module to correct channel gains, offsets and units from a calibration csv file
+
+// Written by Amin Alam in 2024
+
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+use csv::StringRecord;
+use crate::core::session::SessionInfo;
+use crate::data_io::csv::{column_index, parse_number, CsvReader, CsvWriter};
+use crate::data_io::pseudonym::sha256;
+
+/// The session key listing the identifiers of the calibrations applied, separated by commas
+pub const CALIBRATION_KEY: &str = "calibration";
+
+/// The prefix of the session keys holding the unit of each calibrated channel, e.g. `unit_ch1`
+pub const UNIT_KEY_PREFIX: &str = "unit_";
+
+/// The correction of one channel
+///
+/// # Arguments
+///
+/// * `channel` - The name of the channel
+/// * `gain_correction` - The factor the samples are multiplied by
+/// * `offset` - The value added to the samples after the multiplication
+/// * `unit` - The unit of the corrected samples, or None to keep the unit
+///
+/// # Examples
+///
+/// ```
+/// let correction = ChannelCalibration { channel: "ch3".to_string(), gain_correction: 0.5, offset: 0.0, unit: Some("uV".to_string()) };
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChannelCalibration {
+    pub channel: String,
+    pub gain_correction: f64,
+    pub offset: f64,
+    pub unit: Option<String>,
+}
+
+/// The corrections of a set of channels, read from a `channel,gain_correction,offset,unit` csv file
+///
+/// # Arguments
+///
+/// * `channels` - The correction of each channel
+///
+/// # Examples
+///
+/// ```
+/// let calibration = Calibration::load("calibration_2024-03.csv")?;
+/// let report = calibration.apply(&mut channels, &names, &mut session, false)?;
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Calibration {
+    pub channels: Vec<ChannelCalibration>,
+}
+
+/// What applying a calibration changed
+///
+/// # Arguments
+///
+/// * `calibration_id` - The identifier of the calibration recorded in the session
+/// * `applied` - The channels corrected, in the order of the data
+/// * `missing_from_data` - The calibrated channels that are not in the data
+/// * `uncalibrated` - The channels of the data without a correction, left as they are
+///
+/// # Examples
+///
+/// ```
+/// let report = calibration.apply(&mut channels, &names, &mut session, false)?;
+/// if !report.missing_from_data.is_empty() { println!("Not in the data: {:?}", report.missing_from_data); }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CalibrationReport {
+    pub calibration_id: String,
+    pub applied: Vec<String>,
+    pub missing_from_data: Vec<String>,
+    pub uncalibrated: Vec<String>,
+}
+
+/// Implementation of the Calibration struct
+///
+/// # Methods
+///
+/// * `load` - Reads a calibration csv file
+/// * `from_reader` - Reads the remaining records of a calibration csv file
+/// * `id` - Returns the identifier of the calibration
+/// * `apply` - Corrects channels in memory and records the calibration in the session
+impl Calibration {
+    /// Reads a calibration csv file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to a csv file with `channel`, `gain_correction`, `offset` and `unit` columns
+    ///
+    /// # Returns
+    ///
+    /// The Calibration, or an error if the file cannot be read, a column is missing, a
+    /// number is not valid or a channel repeats
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let calibration = Calibration::load("calibration.csv")?;
+    /// ```
+    ///
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Self::from_reader(&mut CsvReader::open(path)?)
+    }
+
+    /// Reads the remaining records of a calibration csv file
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The reader of a csv file with `channel`, `gain_correction`, `offset` and `unit` columns
+    ///
+    /// # Returns
+    ///
+    /// The Calibration, or an error if a column is missing, a number is not valid or a channel repeats
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let calibration = Calibration::from_reader(&mut CsvReader::open("calibration.csv")?)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// An empty `offset` is 0 and an empty `unit` keeps the unit of the channel. The `unit`
+    /// column may be left out altogether.
+    ///
+    pub fn from_reader(reader: &mut CsvReader) -> io::Result<Self> {
+        let headers = reader.headers().clone();
+        let (channel, gain, offset) = (column_index(&headers, "channel")?, column_index(&headers, "gain_correction")?, column_index(&headers, "offset")?);
+        let unit = column_index(&headers, "unit").ok();
+        let mut channels: Vec<ChannelCalibration> = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            let line = record.position().map_or(0, |position| position.line());
+            let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("Line {}: {}", line, message));
+            let name = record.get(channel).unwrap_or("").trim().to_string();
+            if name.is_empty() || channels.iter().any(|other| other.channel == name) {
+                return Err(invalid(format!("The channel '{}' is empty or repeats", name)));
+            }
+            let number = |column: usize, default: Option<f64>| {
+                let text = record.get(column).unwrap_or("").trim();
+                match default {
+                    Some(default) if text.is_empty() => Ok(default),
+                    _ => parse_number(text).ok().filter(|value| value.is_finite()).ok_or_else(|| invalid(format!("'{}' is not a finite number", text))),
+                }
+            };
+            let gain_correction = number(gain, None)?;
+            let offset = number(offset, Some(0.0))?;
+            let unit = unit.and_then(|column| record.get(column)).map(str::trim).filter(|unit| !unit.is_empty()).map(str::to_string);
+            channels.push(ChannelCalibration { channel: name, gain_correction, offset, unit });
+        }
+        Ok(Self { channels })
+    }
+
+    /// Returns the identifier of the calibration
+    ///
+    /// # Returns
+    ///
+    /// 16 hexadecimal digits of the SHA-256 of the corrections, the same for the same corrections in any order
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// println!("Calibration {}", calibration.id());
+    /// ```
+    ///
+    pub fn id(&self) -> String {
+        let mut lines: Vec<String> = self
+            .channels
+            .iter()
+            .map(|channel| format!("{}\t{:e}\t{:e}\t{}\n", channel.channel, channel.gain_correction, channel.offset, channel.unit.as_deref().unwrap_or("")))
+            .collect();
+        lines.sort();
+        sha256(lines.concat().as_bytes())[..8].iter().map(|byte| format!("{:02x}", byte)).collect()
+    }
+
+    /// Corrects channels in memory and records the calibration in the session
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - The samples of each channel, corrected in place
+    /// * `names` - The name of each channel
+    /// * `session` - The metadata of the recording, where the calibration and the units are recorded
+    /// * `force` - Applies the calibration even if the session records it as applied already
+    ///
+    /// # Returns
+    ///
+    /// The CalibrationReport, or an `AlreadyExists` error if the calibration was applied
+    /// before and `force` is false, or an `InvalidInput` error if the numbers of channels
+    /// and names differ
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut session = SessionInfo::load("session.json")?;
+    /// let report = calibration.apply(&mut channels, &names, &mut session, false)?;
+    /// session.save("session.json")?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Each sample becomes `sample * gain_correction + offset`. The identifier of the
+    /// calibration is added to the `calibration` key of the session and the unit of each
+    /// corrected channel is set under `unit_<channel>`, so saving the session alongside the
+    /// data keeps the correction from being applied twice. Nothing is changed on error.
+    ///
+    pub fn apply(&self, channels: &mut [Vec<f64>], names: &[String], session: &mut SessionInfo, force: bool) -> io::Result<CalibrationReport> {
+        if channels.len() != names.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Got {} channels but {} channel names", channels.len(), names.len())));
+        }
+        let (corrections, report) = self.prepare(names.iter().map(String::as_str), session, force)?;
+        for (samples, correction) in channels.iter_mut().zip(&corrections) {
+            if let Some(correction) = correction {
+                samples.iter_mut().for_each(|sample| *sample = *sample * correction.gain_correction + correction.offset);
+            }
+        }
+        self.record(session, &report);
+        Ok(report)
+    }
+
+    /// Matches the corrections to the columns and checks the session for an earlier application
+    fn prepare<'a>(&self, names: impl Iterator<Item = &'a str>, session: &SessionInfo, force: bool) -> io::Result<(Vec<Option<&ChannelCalibration>>, CalibrationReport)> {
+        let id = self.id();
+        if !force && session.get(CALIBRATION_KEY).is_some_and(|applied| applied.split(',').any(|other| other == id)) {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("Calibration {} has already been applied; pass force to apply it again", id),
+            ));
+        }
+        let by_name: HashMap<&str, &ChannelCalibration> = self.channels.iter().map(|channel| (channel.channel.as_str(), channel)).collect();
+        let mut report = CalibrationReport { calibration_id: id, ..CalibrationReport::default() };
+        let mut corrections = Vec::new();
+        for name in names {
+            let correction = by_name.get(name).copied();
+            match correction {
+                Some(_) => report.applied.push(name.to_string()),
+                None => report.uncalibrated.push(name.to_string()),
+            }
+            corrections.push(correction);
+        }
+        report.missing_from_data = self.channels.iter().map(|channel| channel.channel.clone()).filter(|name| !report.applied.contains(name)).collect();
+        Ok((corrections, report))
+    }
+
+    /// Adds the calibration and the new units to the session
+    fn record(&self, session: &mut SessionInfo, report: &CalibrationReport) {
+        let applied = match session.get(CALIBRATION_KEY) {
+            Some(applied) if !applied.is_empty() => format!("{},{}", applied, report.calibration_id),
+            _ => report.calibration_id.clone(),
+        };
+        session.set_extra(CALIBRATION_KEY, &applied);
+        for channel in self.channels.iter().filter(|channel| report.applied.contains(&channel.channel)) {
+            if let Some(unit) = &channel.unit {
+                session.set_extra(&format!("{}{}", UNIT_KEY_PREFIX, channel.channel), unit);
+            }
+        }
+    }
+}
+
+/// Copies the remaining records with the calibrated columns corrected
+pub(crate) fn apply_calibration_copy(
+    reader: &mut CsvReader,
+    calibration: &Calibration,
+    output: &mut CsvWriter,
+    session: &mut SessionInfo,
+    force: bool,
+) -> io::Result<CalibrationReport> {
+    let headers = reader.headers().clone();
+    let (corrections, report) = calibration.prepare(headers.iter(), session, force)?;
+    let float_format = output.float_format();
+    output.write_record(&headers)?;
+    let mut row = StringRecord::new();
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, |position| position.line());
+        row.clear();
+        for (field, correction) in record.iter().zip(&corrections) {
+            match correction {
+                Some(correction) if !field.trim().is_empty() => {
+                    let value: f64 = field
+                        .trim()
+                        .parse()
+                        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Line {}: '{}' is not a number", line, field)))?;
+                    row.push_field(&float_format.format(value * correction.gain_correction + correction.offset));
+                }
+                _ => row.push_field(field),
+            }
+        }
+        output.write_record(&row)?;
+    }
+    calibration.record(session, &report);
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("neurorust-calibration-{}-{}", std::process::id(), name))
+    }
+
+    fn calibration() -> Calibration {
+        Calibration {
+            channels: vec![
+                ChannelCalibration { channel: "ch1".to_string(), gain_correction: 2.0, offset: 0.0, unit: Some("uV".to_string()) },
+                ChannelCalibration { channel: "ch3".to_string(), gain_correction: 0.5, offset: -1.0, unit: None },
+                ChannelCalibration { channel: "ch9".to_string(), gain_correction: 10.0, offset: 0.0, unit: Some("mV".to_string()) },
+            ],
+        }
+    }
+
+    fn names() -> Vec<String> {
+        ["ch1", "ch2", "ch3"].iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn loads_a_calibration_file_with_defaults() {
+        let path = temp_path("load.csv");
+        fs::write(&path, "channel,gain_correction,offset,unit\nch1,2,,uV\n ch3 ,0.5,-1,\nch9,1e1,0,mV\n").unwrap();
+        let loaded = Calibration::load(&path).unwrap();
+        assert_eq!(loaded, calibration());
+
+        fs::write(&path, "offset,gain_correction,channel\n0.25,1.5,Cz\n").unwrap();
+        let loaded = Calibration::load(&path).unwrap();
+        assert_eq!(loaded.channels, vec![ChannelCalibration { channel: "Cz".to_string(), gain_correction: 1.5, offset: 0.25, unit: None }]);
+
+        for (contents, message) in [
+            ("channel,gain_correction,offset\nch1,2,0\nch1,3,0\n", "Line 3"),
+            ("channel,gain_correction,offset\n,2,0\n", "empty or repeats"),
+            ("channel,gain_correction,offset\nch1,,0\n", "not a finite number"),
+            ("channel,gain_correction,offset\nch1,inf,0\n", "not a finite number"),
+            ("channel,gain_correction,offset\nch1,2,zero\n", "'zero'"),
+        ] {
+            fs::write(&path, contents).unwrap();
+            let error = Calibration::load(&path).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert!(error.to_string().contains(message), "{}: {}", contents, error);
+        }
+        fs::write(&path, "channel,gain\nch1,2\n").unwrap();
+        assert!(Calibration::load(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ids_depend_on_the_corrections_but_not_their_order() {
+        let id = calibration().id();
+        assert_eq!(id.len(), 16);
+        assert!(id.chars().all(|c| c.is_ascii_hexdigit()));
+        let mut reversed = calibration();
+        reversed.channels.reverse();
+        assert_eq!(reversed.id(), id);
+        let mut changed = calibration();
+        changed.channels[1].gain_correction = 0.25;
+        assert_ne!(changed.id(), id);
+        let mut relabelled = calibration();
+        relabelled.channels[1].unit = Some("uV".to_string());
+        assert_ne!(relabelled.id(), id);
+    }
+
+    #[test]
+    fn applies_once_and_reports_unmatched_channels() {
+        let mut channels = vec![vec![1.0, -2.0], vec![5.0, 6.0], vec![4.0, 0.0]];
+        let mut session = SessionInfo::new();
+        let report = calibration().apply(&mut channels, &names(), &mut session, false).unwrap();
+        assert_eq!(channels, vec![vec![2.0, -4.0], vec![5.0, 6.0], vec![1.0, -1.0]]);
+        assert_eq!(report.calibration_id, calibration().id());
+        assert_eq!(report.applied, ["ch1", "ch3"]);
+        assert_eq!(report.uncalibrated, ["ch2"]);
+        assert_eq!(report.missing_from_data, ["ch9"]);
+        assert_eq!(session.get(CALIBRATION_KEY), Some(report.calibration_id.as_str()));
+        assert_eq!(session.get("unit_ch1"), Some("uV"));
+        assert_eq!(session.get("unit_ch3"), None);
+        assert_eq!(session.get("unit_ch9"), None);
+
+        let before = channels.clone();
+        let error = calibration().apply(&mut channels, &names(), &mut session, false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        assert!(error.to_string().contains("force"), "{}", error);
+        assert_eq!(channels, before);
+
+        let forced = calibration().apply(&mut channels, &names(), &mut session, true).unwrap();
+        assert_eq!(channels, vec![vec![4.0, -8.0], vec![5.0, 6.0], vec![-0.5, -1.5]]);
+        assert_eq!(session.get(CALIBRATION_KEY).unwrap(), format!("{0},{0}", forced.calibration_id));
+
+        let mismatched = calibration().apply(&mut channels[..2], &names(), &mut SessionInfo::new(), false).unwrap_err();
+        assert_eq!(mismatched.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn protection_survives_a_saved_session_and_allows_other_calibrations() {
+        let path = temp_path("session.json");
+        let mut session = SessionInfo::new();
+        session.set("subject_id", "s01");
+        let mut channels = vec![vec![1.0], vec![1.0], vec![1.0]];
+        calibration().apply(&mut channels, &names(), &mut session, false).unwrap();
+        session.save(&path).unwrap();
+
+        let mut reloaded = SessionInfo::load(&path).unwrap();
+        assert_eq!(calibration().apply(&mut channels, &names(), &mut reloaded, false).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(channels, vec![vec![2.0], vec![1.0], vec![-0.5]]);
+
+        let second = Calibration { channels: vec![ChannelCalibration { channel: "ch2".to_string(), gain_correction: 3.0, offset: 0.0, unit: Some("nV".to_string()) }] };
+        let report = second.apply(&mut channels, &names(), &mut reloaded, false).unwrap();
+        assert_eq!(channels[1], vec![3.0]);
+        assert_eq!(reloaded.get(CALIBRATION_KEY).unwrap(), format!("{},{}", calibration().id(), report.calibration_id));
+        assert_eq!(reloaded.get("unit_ch1"), Some("uV"));
+        assert_eq!(reloaded.get("unit_ch2"), Some("nV"));
+        assert_eq!(second.apply(&mut channels, &names(), &mut reloaded, false).unwrap_err().kind(), io::ErrorKind::AlreadyExists);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn streaming_copy_corrects_the_named_columns() {
+        let input = temp_path("input.csv");
+        let output = temp_path("output.csv");
+        fs::write(&input, "time,ch1,ch2,ch3\n0,1,5,4\n0.5,-2,6,\n1,0.25,7,3\n").unwrap();
+        let mut session = SessionInfo::new();
+        let mut writer = CsvWriter::create(&output).unwrap();
+        let report = CsvReader::open(&input).unwrap().apply_calibration_copy(&calibration(), &mut writer, &mut session, false).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), "time,ch1,ch2,ch3\n0,2,5,1\n0.5,-4,6,\n1,0.5,7,0.5\n");
+        assert_eq!(report.applied, ["ch1", "ch3"]);
+        assert_eq!(report.uncalibrated, ["time", "ch2"]);
+        assert_eq!(report.missing_from_data, ["ch9"]);
+        assert_eq!(session.get("unit_ch1"), Some("uV"));
+
+        let mut writer = CsvWriter::create(temp_path("again.csv")).unwrap();
+        let error = CsvReader::open(&input).unwrap().apply_calibration_copy(&calibration(), &mut writer, &mut session, false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        drop(writer);
+        assert_eq!(fs::read_to_string(temp_path("again.csv")).unwrap(), "");
+
+        fs::write(&input, "ch1,ch3\n1,x\n").unwrap();
+        let mut fresh = SessionInfo::new();
+        let mut writer = CsvWriter::create(&output).unwrap();
+        let error = CsvReader::open(&input).unwrap().apply_calibration_copy(&calibration(), &mut writer, &mut fresh, false).unwrap_err();
+        assert!(error.to_string().contains("Line 2") && error.to_string().contains("'x'"), "{}", error);
+        assert_eq!(fresh.get(CALIBRATION_KEY), None);
+        for path in [input, output, temp_path("again.csv")] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
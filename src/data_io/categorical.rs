@@ -0,0 +1,548 @@
+// A module to encode the values of label columns of csv files as integer codes
+
+// Written by Amin Alam in 2024
+
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::io;
+use csv::StringRecord;
+use crate::data_io::csv::{column_index, CsvReader, CsvWriter};
+
+/// The value of the row of `ValueCounts::to_csv` counting the values outside the top k
+pub const OTHER_LABEL: &str = "__other__";
+
+/// How `encode_categorical` numbers the values of a column
+///
+/// # Arguments
+///
+/// * `FirstAppearance` - The values are numbered from 0 in the order in which they first appear
+/// * `Sorted` - The values are numbered from 0 in byte order, which costs a second pass over the records
+///
+/// # Examples
+///
+/// ```
+/// let mapping = reader.encode_categorical(&["condition"], CodeOrder::Sorted, &mut output, &mut mapping_output)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CodeOrder {
+    #[default]
+    FirstAppearance,
+    Sorted,
+}
+
+/// The values behind the integer codes of the columns of an encoded file
+///
+/// # Examples
+///
+/// ```
+/// let mapping = reader.encode_categorical(&["condition", "electrode"], CodeOrder::FirstAppearance, &mut output, &mut mapping_output)?;
+/// let mapping = CategoricalMapping::from_csv(&mut CsvReader::open("mapping.csv")?)?;
+/// ```
+///
+/// # Note
+///
+/// The code of a value is its index in the dictionary of its column, so the codes of a
+/// column always run from 0 without gaps
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CategoricalMapping {
+    columns: Vec<String>,
+    values: Vec<Vec<String>>,
+}
+
+/// The frequency of the values of a column
+///
+/// # Arguments
+///
+/// * `column` - The name of the column
+/// * `counts` - The values and their number of rows, the most frequent first, ties in order of first appearance
+/// * `other` - The number of rows holding a value outside `counts`
+/// * `n_distinct` - The number of distinct values, including those outside `counts`
+/// * `n_empty` - The number of rows where the column is empty or missing, counted in no bucket
+///
+/// # Examples
+///
+/// ```
+/// let counts = reader.value_counts("electrode", Some(10))?;
+/// println!("{} electrodes, {} rows outside the top 10", counts.n_distinct, counts.other);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ValueCounts {
+    pub column: String,
+    pub counts: Vec<(String, usize)>,
+    pub other: usize,
+    pub n_distinct: usize,
+    pub n_empty: usize,
+}
+
+/// Implementation of the CategoricalMapping struct
+///
+/// # Methods
+///
+/// * `columns` - Returns the names of the encoded columns
+/// * `values` - Returns the values of a column, indexed by code
+/// * `code` - Returns the code of a value of a column
+/// * `value` - Returns the value of a code of a column
+/// * `to_csv` - Writes the mapping as `column,code,value` rows
+/// * `from_csv` - Reads a mapping written by `to_csv`
+impl CategoricalMapping {
+    /// Returns the names of the encoded columns
+    ///
+    /// # Returns
+    ///
+    /// The columns, in the order given to `encode_categorical`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let columns = mapping.columns();
+    /// ```
+    ///
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Returns the values of a column, indexed by code
+    ///
+    /// # Arguments
+    ///
+    /// * `column` - The name of the column
+    ///
+    /// # Returns
+    ///
+    /// The values, or None if the column is not encoded
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let n_conditions = mapping.values("condition").map_or(0, |values| values.len());
+    /// ```
+    ///
+    pub fn values(&self, column: &str) -> Option<&[String]> {
+        self.columns.iter().position(|name| name == column).map(|index| self.values[index].as_slice())
+    }
+
+    /// Returns the code of a value of a column
+    ///
+    /// # Arguments
+    ///
+    /// * `column` - The name of the column
+    /// * `value` - The original value
+    ///
+    /// # Returns
+    ///
+    /// The code, or None if the column is not encoded or the value was not seen
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let target = mapping.code("condition", "target");
+    /// ```
+    ///
+    pub fn code(&self, column: &str, value: &str) -> Option<usize> {
+        self.values(column)?.iter().position(|known| known == value)
+    }
+
+    /// Returns the value of a code of a column
+    ///
+    /// # Arguments
+    ///
+    /// * `column` - The name of the column
+    /// * `code` - The integer code
+    ///
+    /// # Returns
+    ///
+    /// The original value, or None if the column is not encoded or the code is unknown
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let label = mapping.value("electrode", 3);
+    /// ```
+    ///
+    pub fn value(&self, column: &str, code: usize) -> Option<&str> {
+        self.values(column)?.get(code).map(String::as_str)
+    }
+
+    /// Writes the mapping as `column,code,value` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - The writer of the mapping file
+    ///
+    /// # Returns
+    ///
+    /// The number of rows written after the header row, or an error if the rows cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut mapping_output = CsvWriter::create("trials_codes.csv")?;
+    /// mapping.to_csv(&mut mapping_output)?;
+    /// mapping_output.flush()?;
+    /// ```
+    ///
+    pub fn to_csv(&self, output: &mut CsvWriter) -> io::Result<usize> {
+        output.write_record(&StringRecord::from(vec!["column", "code", "value"]))?;
+        let mut n_rows = 0;
+        for (column, values) in self.columns.iter().zip(&self.values) {
+            for (code, value) in values.iter().enumerate() {
+                output.write_record(&StringRecord::from(vec![column.as_str(), &code.to_string(), value.as_str()]))?;
+                n_rows += 1;
+            }
+        }
+        Ok(n_rows)
+    }
+
+    /// Reads a mapping written by `to_csv`
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The reader of the mapping file, at its first record
+    ///
+    /// # Returns
+    ///
+    /// The CategoricalMapping, or an error if a `column`, `code` or `value` column is missing,
+    /// the codes of a column do not run from 0 in order, or the records cannot be read
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mapping = CategoricalMapping::from_csv(&mut CsvReader::open("trials_codes.csv")?)?;
+    /// ```
+    ///
+    pub fn from_csv(reader: &mut CsvReader) -> io::Result<Self> {
+        let headers = reader.headers().clone();
+        let (column_column, code_column, value_column) = (column_index(&headers, "column")?, column_index(&headers, "code")?, column_index(&headers, "value")?);
+        let mut mapping = CategoricalMapping::default();
+        for record in reader.records() {
+            let record = record?;
+            let line = record.position().map_or(0, |position| position.line());
+            let column = record.get(column_column).unwrap_or("");
+            let index = match mapping.columns.iter().position(|name| name == column) {
+                Some(index) => index,
+                None => {
+                    mapping.columns.push(column.to_string());
+                    mapping.values.push(Vec::new());
+                    mapping.values.len() - 1
+                }
+            };
+            let values = &mut mapping.values[index];
+            let code = record.get(code_column).unwrap_or("");
+            if code.trim().parse::<usize>().ok() != Some(values.len()) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Expected code {} of column '{}' at line {} but found '{}'", values.len(), column, line, code),
+                ));
+            }
+            values.push(record.get(value_column).unwrap_or("").to_string());
+        }
+        Ok(mapping)
+    }
+}
+
+/// Implementation of the ValueCounts struct
+///
+/// # Methods
+///
+/// * `to_csv` - Writes the counts as `value,count` rows
+impl ValueCounts {
+    /// Writes the counts as `value,count` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - The writer of the csv file
+    ///
+    /// # Returns
+    ///
+    /// Ok, or an error if the rows cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut output = CsvWriter::create("electrode_counts.csv")?;
+    /// counts.to_csv(&mut output)?;
+    /// output.flush()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. The rows outside the top k are written last as one
+    /// row with the value `OTHER_LABEL`, if there are any.
+    ///
+    pub fn to_csv(&self, output: &mut CsvWriter) -> io::Result<()> {
+        output.write_record(&StringRecord::from(vec!["value", "count"]))?;
+        for (value, count) in &self.counts {
+            output.write_record(&StringRecord::from(vec![value.as_str(), &count.to_string()]))?;
+        }
+        if self.other > 0 {
+            output.write_record(&StringRecord::from(vec![OTHER_LABEL, &self.other.to_string()]))?;
+        }
+        Ok(())
+    }
+}
+
+/// Copies the remaining records with the values of the columns replaced by their codes
+pub(crate) fn encode(reader: &mut CsvReader, columns: &[&str], order: CodeOrder, output: &mut CsvWriter) -> io::Result<CategoricalMapping> {
+    let indices = columns.iter().map(|name| column_index(reader.headers(), name)).collect::<io::Result<Vec<usize>>>()?;
+    let mut codes: Vec<HashMap<String, usize>> = vec![HashMap::new(); indices.len()];
+    let mut values: Vec<Vec<String>> = vec![Vec::new(); indices.len()];
+    if order == CodeOrder::Sorted {
+        for record in reader.try_clone_at_position()?.records() {
+            let record = record?;
+            for (i, &column) in indices.iter().enumerate() {
+                add_value(&mut codes[i], &mut values[i], record.get(column).unwrap_or(""));
+            }
+        }
+        for (codes, values) in codes.iter_mut().zip(values.iter_mut()) {
+            values.sort_unstable();
+            codes.iter_mut().for_each(|(value, code)| *code = values.binary_search(value).unwrap_or_default());
+        }
+    }
+    output.write_record(reader.headers())?;
+    let mut row = StringRecord::new();
+    for record in reader.records() {
+        let record = record?;
+        row.clear();
+        for (column, field) in record.iter().enumerate() {
+            match indices.iter().position(|&index| index == column) {
+                Some(i) if !field.is_empty() => row.push_field(&add_value(&mut codes[i], &mut values[i], field).to_string()),
+                _ => row.push_field(field),
+            }
+        }
+        output.write_record(&row)?;
+    }
+    Ok(CategoricalMapping { columns: columns.iter().map(|name| name.to_string()).collect(), values })
+}
+
+/// Returns the code of a value, giving it the next code if it is new; empty values are skipped
+fn add_value(codes: &mut HashMap<String, usize>, values: &mut Vec<String>, value: &str) -> usize {
+    if value.is_empty() {
+        return 0;
+    }
+    if let Some(&code) = codes.get(value) {
+        return code;
+    }
+    codes.insert(value.to_string(), values.len());
+    values.push(value.to_string());
+    values.len() - 1
+}
+
+/// Copies the remaining records with the codes of the columns of the mapping replaced by their values
+pub(crate) fn decode(reader: &mut CsvReader, mapping: &CategoricalMapping, output: &mut CsvWriter) -> io::Result<usize> {
+    let indices = mapping.columns.iter().map(|name| column_index(reader.headers(), name)).collect::<io::Result<Vec<usize>>>()?;
+    output.write_record(reader.headers())?;
+    let mut row = StringRecord::new();
+    let mut n_rows = 0;
+    for record in reader.records() {
+        let record = record?;
+        row.clear();
+        for (column, field) in record.iter().enumerate() {
+            match indices.iter().position(|&index| index == column) {
+                Some(i) if !field.is_empty() => {
+                    let value = field.trim().parse::<usize>().ok().and_then(|code| mapping.values[i].get(code)).ok_or_else(|| {
+                        io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "Code '{}' of column '{}' at line {} is not in the mapping",
+                                field,
+                                mapping.columns[i],
+                                record.position().map_or(0, |position| position.line())
+                            ),
+                        )
+                    })?;
+                    row.push_field(value);
+                }
+                _ => row.push_field(field),
+            }
+        }
+        output.write_record(&row)?;
+        n_rows += 1;
+    }
+    Ok(n_rows)
+}
+
+/// Counts the values of a column over the remaining records
+pub(crate) fn value_counts(reader: &mut CsvReader, column: &str, top_k: Option<usize>) -> io::Result<ValueCounts> {
+    let index = column_index(reader.headers(), column)?;
+    let mut codes: HashMap<String, usize> = HashMap::new();
+    let mut values: Vec<String> = Vec::new();
+    let mut counts: Vec<usize> = Vec::new();
+    let mut n_empty = 0;
+    for record in reader.records() {
+        let record = record?;
+        match record.get(index).unwrap_or("") {
+            "" => n_empty += 1,
+            value => {
+                let code = add_value(&mut codes, &mut values, value);
+                if code == counts.len() {
+                    counts.push(0);
+                }
+                counts[code] += 1;
+            }
+        }
+    }
+    let n_distinct = values.len();
+    let mut counts: Vec<(String, usize)> = values.into_iter().zip(counts).collect();
+    // The sort is stable, so equal counts keep their order of first appearance
+    counts.sort_by_key(|(_, count)| Reverse(*count));
+    let kept = top_k.unwrap_or(counts.len()).min(counts.len());
+    let other = counts.drain(kept..).map(|(_, count)| count).sum();
+    Ok(ValueCounts { column: column.to_string(), counts, other, n_distinct, n_empty })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    // The file is in the form the csv writer produces, so an unchanged record is written back byte for byte
+    const TRIALS: &str = "\
+trial,condition,electrode,note,rt
+1,go,Cz,\"fast, clean\",0.412
+2,no-go,Pz,,0.388
+3,go,\"C3, left\",\"said \"\"oops\"\"\",
+4,,Cz,late,0.901
+5,oddball,Pz,,0.455
+6,no-go,Oz,again,0.377
+";
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("neurorust-categorical-{}-{}", std::process::id(), name))
+    }
+
+    fn fields(text: &str) -> Vec<StringRecord> {
+        csv::ReaderBuilder::new().from_reader(text.as_bytes()).records().map(Result::unwrap).collect()
+    }
+
+    /// Encodes the trials file, returning the mapping, the encoded text and the mapping text
+    fn encode_trials(name: &str, columns: &[&str], order: CodeOrder) -> (CategoricalMapping, String, String) {
+        let (input, output, mapping_path) = (temp_path(&format!("{}.csv", name)), temp_path(&format!("{}_encoded.csv", name)), temp_path(&format!("{}_mapping.csv", name)));
+        fs::write(&input, TRIALS).unwrap();
+        let (mut writer, mut mapping_writer) = (CsvWriter::create(&output).unwrap(), CsvWriter::create(&mapping_path).unwrap());
+        let mapping = CsvReader::open(&input).unwrap().encode_categorical(columns, order, &mut writer, &mut mapping_writer).unwrap();
+        writer.flush().unwrap();
+        mapping_writer.flush().unwrap();
+        let texts = (fs::read_to_string(&output).unwrap(), fs::read_to_string(&mapping_path).unwrap());
+        for path in [input, output, mapping_path] {
+            fs::remove_file(path).unwrap();
+        }
+        (mapping, texts.0, texts.1)
+    }
+
+    fn decode_text(name: &str, encoded: &str, mapping: &CategoricalMapping) -> io::Result<String> {
+        let (input, output) = (temp_path(&format!("{}_in.csv", name)), temp_path(&format!("{}_out.csv", name)));
+        fs::write(&input, encoded).unwrap();
+        let mut writer = CsvWriter::create(&output).unwrap();
+        let result = CsvReader::open(&input).unwrap().decode_categorical(mapping, &mut writer);
+        writer.flush().unwrap();
+        let text = fs::read_to_string(&output).unwrap();
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&output).unwrap();
+        result.map(|_| text)
+    }
+
+    #[test]
+    fn encoding_changes_only_the_chosen_columns_and_decodes_back() {
+        for order in [CodeOrder::FirstAppearance, CodeOrder::Sorted] {
+            let (mapping, encoded, _) = encode_trials("roundtrip", &["condition", "electrode"], order);
+            let (original, coded) = (fields(TRIALS), fields(&encoded));
+            assert_eq!(encoded.lines().next(), TRIALS.lines().next());
+            assert_eq!(coded.len(), original.len());
+            for (before, after) in original.iter().zip(&coded) {
+                for column in [0, 3, 4] {
+                    assert_eq!(before[column], after[column]);
+                }
+                for column in [1, 2] {
+                    assert!(after[column].is_empty() == before[column].is_empty() && (after[column].is_empty() || after[column].parse::<usize>().is_ok()));
+                }
+            }
+            if order == CodeOrder::FirstAppearance {
+                let expected = "\
+trial,condition,electrode,note,rt
+1,0,0,\"fast, clean\",0.412
+2,1,1,,0.388
+3,0,2,\"said \"\"oops\"\"\",
+4,,0,late,0.901
+5,2,1,,0.455
+6,1,3,again,0.377
+";
+                assert_eq!(encoded, expected);
+            }
+            assert_eq!(decode_text("roundtrip", &encoded, &mapping).unwrap(), TRIALS, "{:?}", order);
+        }
+    }
+
+    #[test]
+    fn codes_follow_first_appearance_or_sorted_order() {
+        let (first, encoded, mapping_text) = encode_trials("order", &["condition"], CodeOrder::FirstAppearance);
+        assert_eq!(first.values("condition").unwrap(), ["go", "no-go", "oddball"]);
+        assert_eq!(fields(&encoded).iter().map(|record| record[1].to_string()).collect::<Vec<_>>(), ["0", "1", "0", "", "2", "1"]);
+        assert_eq!(mapping_text, "column,code,value\ncondition,0,go\ncondition,1,no-go\ncondition,2,oddball\n");
+
+        let (sorted, encoded, _) = encode_trials("order_sorted", &["electrode", "condition"], CodeOrder::Sorted);
+        assert_eq!(sorted.columns(), ["electrode", "condition"]);
+        assert_eq!(sorted.values("electrode").unwrap(), ["C3, left", "Cz", "Oz", "Pz"]);
+        assert_eq!(fields(&encoded).iter().map(|record| record[2].to_string()).collect::<Vec<_>>(), ["1", "3", "0", "1", "3", "2"]);
+        assert_eq!((sorted.code("electrode", "Oz"), sorted.value("condition", 2)), (Some(2), Some("oddball")));
+        assert_eq!((sorted.code("electrode", "Fz"), sorted.value("condition", 3), sorted.values("rt")), (None, None, None));
+    }
+
+    #[test]
+    fn mappings_read_back_from_their_csv() {
+        let (mapping, encoded, mapping_text) = encode_trials("mapping", &["condition", "electrode"], CodeOrder::FirstAppearance);
+        let path = temp_path("mapping_file.csv");
+        fs::write(&path, &mapping_text).unwrap();
+        let loaded = CategoricalMapping::from_csv(&mut CsvReader::open(&path).unwrap()).unwrap();
+        assert_eq!(loaded, mapping);
+        assert_eq!(decode_text("mapping", &encoded, &loaded).unwrap(), TRIALS);
+
+        fs::write(&path, "column,code,value\ncondition,0,go\ncondition,2,oddball\n").unwrap();
+        let error = CategoricalMapping::from_csv(&mut CsvReader::open(&path).unwrap()).unwrap_err();
+        assert_eq!(error.to_string(), "Expected code 1 of column 'condition' at line 3 but found '2'");
+        fs::write(&path, "column,value\ncondition,go\n").unwrap();
+        assert!(CategoricalMapping::from_csv(&mut CsvReader::open(&path).unwrap()).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn unseen_codes_name_the_value_and_line() {
+        let (mapping, _, _) = encode_trials("unseen", &["condition"], CodeOrder::FirstAppearance);
+        let error = decode_text("unseen", "trial,condition\n1,0\n2,7\n", &mapping).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "Code '7' of column 'condition' at line 3 is not in the mapping");
+        let error = decode_text("unseen", "trial,condition\n1,go\n", &mapping).unwrap_err();
+        assert!(error.to_string().contains("Code 'go'") && error.to_string().contains("line 2"), "{}", error);
+        assert!(decode_text("unseen", "trial,label\n1,0\n", &mapping).is_err());
+    }
+
+    #[test]
+    fn value_counts_keep_the_top_values_and_bucket_the_rest() {
+        let path = temp_path("counts.csv");
+        fs::write(&path, TRIALS).unwrap();
+        let counts = CsvReader::open(&path).unwrap().value_counts("condition", None).unwrap();
+        assert_eq!(
+            counts,
+            ValueCounts {
+                column: "condition".to_string(),
+                counts: vec![("go".to_string(), 2), ("no-go".to_string(), 2), ("oddball".to_string(), 1)],
+                other: 0,
+                n_distinct: 3,
+                n_empty: 1,
+            }
+        );
+        let top = CsvReader::open(&path).unwrap().value_counts("electrode", Some(2)).unwrap();
+        assert_eq!(top.counts, [("Cz".to_string(), 2), ("Pz".to_string(), 2)]);
+        assert_eq!((top.other, top.n_distinct, top.n_empty), (2, 4, 0));
+
+        let output = temp_path("counts_out.csv");
+        let mut writer = CsvWriter::create(&output).unwrap();
+        top.to_csv(&mut writer).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(fs::read_to_string(&output).unwrap(), format!("value,count\nCz,2\nPz,2\n{},2\n", OTHER_LABEL));
+        assert_eq!(CsvReader::open(&path).unwrap().value_counts("condition", Some(10)).unwrap().other, 0);
+        assert!(CsvReader::open(&path).unwrap().value_counts("missing", None).is_err());
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&output).unwrap();
+    }
+}
@@ -0,0 +1,1013 @@
+// A module to read and write gzip and Zstandard compressed csv files and seekable Zstandard files
+
+// Written by Amin Alam in 2024
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+#[cfg(feature = "zstd")]
+use std::io::{Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Numbers the temporary files of the decompressions of this process
+static SPILL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// How a csv file is compressed
+///
+/// # Arguments
+///
+/// * `None` - The file is plain text
+/// * `Gzip` - The file is gzip compressed, usually with a `.gz` extension; needs the `gzip` feature
+/// * `Zstd` - The file is Zstandard compressed, usually with a `.zst` extension; needs the `zstd` feature
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(Compression::from_extension("behavior.csv.gz"), Compression::Gzip);
+/// let mut csv_io = CsvIO::builder().compression(Compression::Zstd).open_write("lfp.csv.zst")?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+/// A temporary file holding the decompressed records of a compressed csv file, removed when dropped
+///
+/// # Arguments
+///
+/// * `path` - The path to the temporary file
+#[derive(Debug)]
+pub(crate) struct Spill {
+    path: PathBuf,
+}
+
+/// The file a CsvWriter writes to, through a compressor if the file is compressed
+pub(crate) enum Sink {
+    Plain(BufWriter<File>),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<BufWriter<File>>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::stream::write::Encoder<'static, BufWriter<File>>),
+}
+
+/// Implementation of the Compression enum
+///
+/// # Methods
+///
+/// * `from_path` - Returns the compression of a file from its first bytes, or from its extension
+/// * `from_extension` - Returns the compression implied by the extension of a file
+/// * `from_magic_bytes` - Returns the compression whose stream starts with some bytes
+/// * `name` - Returns the name of the compression
+impl Compression {
+    /// Returns the compression of a file from its first bytes, or from its extension
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path to the file
+    ///
+    /// # Returns
+    ///
+    /// The compression whose magic bytes the file starts with, or the one implied by its
+    /// extension if the file does not exist, is empty or starts with other bytes
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // An export that lost its extension is still read as Zstandard
+    /// let compression = Compression::from_path("session_03/lfp");
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// This is how files are read and appended to. A file that is created or truncated is
+    /// compressed after its extension, since its old bytes are replaced.
+    ///
+    pub fn from_path<P: AsRef<Path>>(file_path: P) -> Self {
+        let mut magic = [0u8; 4];
+        let n_read = File::open(file_path.as_ref()).and_then(|mut file| {
+            let mut n_read = 0;
+            while n_read < magic.len() {
+                match file.read(&mut magic[n_read..])? {
+                    0 => break,
+                    n => n_read += n,
+                }
+            }
+            Ok(n_read)
+        });
+        n_read.ok().and_then(|n_read| Self::from_magic_bytes(&magic[..n_read])).unwrap_or_else(|| Self::from_extension(file_path))
+    }
+
+    /// Returns the compression implied by the extension of a file
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path to the file
+    ///
+    /// # Returns
+    ///
+    /// `Gzip` for a `.gz` extension, `Zstd` for a `.zst` extension, ignoring case, and `None`
+    /// otherwise
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(Compression::from_extension("session_03/lfp.csv.zst"), Compression::Zstd);
+    /// ```
+    ///
+    pub fn from_extension<P: AsRef<Path>>(file_path: P) -> Self {
+        match file_path.as_ref().extension().and_then(|extension| extension.to_str()).map(str::to_ascii_lowercase).as_deref() {
+            Some("gz") => Compression::Gzip,
+            Some("zst") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Returns the compression whose stream starts with some bytes
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The first bytes of a file, at least 2 for gzip and 4 for Zstandard
+    ///
+    /// # Returns
+    ///
+    /// `Gzip` after `1f 8b`, `Zstd` after the magic number of a Zstandard frame or of a
+    /// skippable frame, and None otherwise
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(Compression::from_magic_bytes(&[0x28, 0xb5, 0x2f, 0xfd]), Some(Compression::Zstd));
+    /// ```
+    ///
+    pub fn from_magic_bytes(bytes: &[u8]) -> Option<Self> {
+        match bytes {
+            [0x1f, 0x8b, ..] => Some(Compression::Gzip),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Some(Compression::Zstd),
+            [first, 0x2a, 0x4d, 0x18, ..] if first & 0xf0 == 0x50 => Some(Compression::Zstd),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of the compression
+    ///
+    /// # Returns
+    ///
+    /// `none`, `gzip` or `zstd`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// println!("compression: {}", Compression::from_path(&path).name());
+    /// ```
+    ///
+    pub fn name(&self) -> &'static str {
+        match self {
+            Compression::None => "none",
+            Compression::Gzip => "gzip",
+            Compression::Zstd => "zstd",
+        }
+    }
+}
+
+impl Spill {
+    /// Takes ownership of a temporary file, which need not exist yet
+    pub(crate) fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    /// Returns the path to the temporary file
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Sink {
+    /// Creates, truncates or opens to append a file, writing through a compressor at a level, or its default, if it is compressed
+    pub(crate) fn open(file_path: &Path, append: bool, compression: Compression, level: Option<i32>) -> io::Result<Self> {
+        if let Some(level) = level {
+            let (min, max) = match compression {
+                Compression::None => (level, level),
+                Compression::Gzip => (0, 9),
+                #[cfg(feature = "zstd")]
+                Compression::Zstd => (*zstd::compression_level_range().start(), *zstd::compression_level_range().end()),
+                #[cfg(not(feature = "zstd"))]
+                Compression::Zstd => (level, level),
+            };
+            if level < min || level > max {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("The {} compression level must be from {} to {}, got {}", compression.name(), min, max, level)));
+            }
+        }
+        let open = || -> io::Result<BufWriter<File>> {
+            let file = match append {
+                true => OpenOptions::new().append(true).open(file_path)?,
+                false => File::create(file_path)?,
+            };
+            Ok(BufWriter::new(file))
+        };
+        // An appended gzip member or Zstandard frame is read after the earlier ones
+        Ok(match compression {
+            Compression::None => Sink::Plain(open()?),
+            #[cfg(feature = "gzip")]
+            Compression::Gzip => Sink::Gzip(flate2::write::GzEncoder::new(open()?, level.map_or_else(flate2::Compression::default, |level| flate2::Compression::new(level as u32)))),
+            #[cfg(not(feature = "gzip"))]
+            Compression::Gzip => return Err(unsupported(compression)),
+            #[cfg(feature = "zstd")]
+            Compression::Zstd => Sink::Zstd(zstd::stream::write::Encoder::new(open()?, level.unwrap_or(0))?),
+            #[cfg(not(feature = "zstd"))]
+            Compression::Zstd => return Err(unsupported(compression)),
+        })
+    }
+
+    /// Writes the end of the compressed stream, after which the file is complete
+    pub(crate) fn finish(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Plain(file) => file.flush(),
+            #[cfg(feature = "gzip")]
+            Sink::Gzip(encoder) => encoder.try_finish().and_then(|_| encoder.get_mut().flush()),
+            #[cfg(feature = "zstd")]
+            Sink::Zstd(encoder) => encoder.do_finish().and_then(|_| encoder.get_mut().flush()),
+        }
+    }
+}
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Sink::Plain(file) => file.write(buf),
+            #[cfg(feature = "gzip")]
+            Sink::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            Sink::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Sink::Plain(file) => file.flush(),
+            #[cfg(feature = "gzip")]
+            Sink::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            Sink::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl Drop for Sink {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Decompresses a file into a temporary file, which is removed when the Spill is dropped
+pub(crate) fn decompress(file_path: &Path, compression: Compression) -> io::Result<Spill> {
+    let input = BufReader::new(File::open(file_path)?);
+    // A file just created to be appended to is empty rather than an empty compressed stream
+    let mut decoder: Box<dyn Read> = match compression {
+        _ if input.get_ref().metadata()?.len() == 0 => Box::new(io::empty()),
+        Compression::None => Box::new(input),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Box::new(flate2::bufread::MultiGzDecoder::new(input)),
+        #[cfg(not(feature = "gzip"))]
+        Compression::Gzip => return Err(unsupported(compression)),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Box::new(zstd::stream::read::Decoder::with_buffer(input)?),
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => return Err(unsupported(compression)),
+    };
+    let spill = Spill::new(std::env::temp_dir().join(format!("neurorust-{}-{}.csv", std::process::id(), SPILL_COUNTER.fetch_add(1, Ordering::Relaxed))));
+    let mut output = BufWriter::new(File::create(spill.path())?);
+    io::copy(&mut decoder, &mut output)?;
+    output.flush()?;
+    Ok(spill)
+}
+
+/// Returns the error of a compression whose feature is not enabled
+#[cfg(not(all(feature = "gzip", feature = "zstd")))]
+fn unsupported(compression: Compression) -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, format!("Reading and writing {} compressed files needs the `{}` feature", compression.name(), compression.name()))
+}
+
+/// The magic number of the skippable frame holding the seek table of a seekable Zstandard file
+#[cfg(feature = "zstd")]
+const SEEK_TABLE_MAGIC: u32 = 0x184d_2a5e;
+
+/// The magic number ending a seekable Zstandard file
+#[cfg(feature = "zstd")]
+const SEEKABLE_MAGIC: u32 = 0x8f92_eab1;
+
+/// The number of bytes of the footer of a seek table: the number of frames, the descriptor and the magic number
+#[cfg(feature = "zstd")]
+const SEEK_TABLE_FOOTER: usize = 9;
+
+/// The largest decompressed size of a frame of a seekable Zstandard file
+#[cfg(feature = "zstd")]
+pub const MAX_SEEKABLE_FRAME: usize = 1 << 30;
+
+/// A frame of a seekable Zstandard file
+#[cfg(feature = "zstd")]
+#[derive(Debug, Clone, Copy)]
+struct SeekFrame {
+    compressed_offset: u64,
+    compressed_size: usize,
+    offset: u64,
+    size: usize,
+}
+
+/// A Zstandard file read at any offset of its decompressed data if it has a seek table
+///
+/// # Arguments
+///
+/// * `file` - The compressed file
+/// * `path` - The path to the compressed file
+/// * `frames` - The frames listed in the seek table, or None if the file is not in the seekable format
+/// * `cached` - The index and decompressed data of the frame read last
+///
+/// # Examples
+///
+/// ```
+/// let mut samples = SeekableZstd::open("lfp.i16.zst")?;
+/// let mut record = vec![0u8; 2 * n_channels];
+/// samples.read_exact_at(row * record.len() as u64, &mut record)?;
+/// ```
+///
+/// # Note
+///
+/// The seekable format of the Zstandard project is a series of independent frames followed
+/// by a skippable frame listing their sizes. Any Zstandard decoder, including the `zstd`
+/// command line tool, reads such a file as an ordinary one. A file without a seek table,
+/// such as the output of `zstd` itself, can only be read from the start with `read_to_end`,
+/// and `read_at` returns an `Unsupported` error. The frame checksums a seek table may hold
+/// are skipped rather than checked, as Zstandard frames check their own data.
+#[cfg(feature = "zstd")]
+#[derive(Debug)]
+pub struct SeekableZstd {
+    file: File,
+    path: PathBuf,
+    frames: Option<Vec<SeekFrame>>,
+    cached: Option<(usize, Vec<u8>)>,
+}
+
+/// A writer of Zstandard files in the seekable format
+///
+/// # Arguments
+///
+/// * `output` - The compressed file
+/// * `level` - The compression level of the frames
+/// * `frame_size` - The decompressed size of every frame but the last
+/// * `buffer` - The data of the frame being filled
+/// * `entries` - The compressed and decompressed sizes of the frames written
+/// * `finished` - Whether the seek table was written
+///
+/// # Examples
+///
+/// ```
+/// let mut writer = SeekableZstdWriter::create("lfp.i16.zst", 1 << 20, 3)?;
+/// for sample in &samples {
+///     writer.write_all(&sample.to_le_bytes())?;
+/// }
+/// writer.finish()?;
+/// ```
+#[cfg(feature = "zstd")]
+pub struct SeekableZstdWriter {
+    output: BufWriter<File>,
+    level: i32,
+    frame_size: usize,
+    buffer: Vec<u8>,
+    entries: Vec<(u32, u32)>,
+    finished: bool,
+}
+
+/// Implementation of the SeekableZstd struct
+///
+/// # Methods
+///
+/// * `open` - Opens a Zstandard file and reads its seek table, if it has one
+/// * `is_seekable` - Returns whether the file has a seek table
+/// * `len` - Returns the size of the decompressed data
+/// * `read_at` - Reads decompressed data from an offset
+/// * `read_exact_at` - Reads exactly enough decompressed data from an offset to fill a buffer
+/// * `read_to_end` - Decompresses the whole file
+#[cfg(feature = "zstd")]
+impl SeekableZstd {
+    /// Opens a Zstandard file and reads its seek table, if it has one
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path to the file
+    ///
+    /// # Returns
+    ///
+    /// The SeekableZstd, or an error if the file cannot be read or ends with a seek table
+    /// that does not match its size
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let samples = SeekableZstd::open("lfp.i16.zst")?;
+    /// ```
+    ///
+    pub fn open<P: AsRef<Path>>(file_path: P) -> io::Result<Self> {
+        let path = file_path.as_ref().to_path_buf();
+        let mut file = File::open(&path)?;
+        let frames = read_seek_table(&mut file, &path)?;
+        Ok(Self { file, path, frames, cached: None })
+    }
+
+    /// Returns whether the file has a seek table
+    ///
+    /// # Returns
+    ///
+    /// True if the file was written in the seekable format, so `read_at` can be used
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// if !samples.is_seekable() {
+    ///     let data = samples.read_to_end()?;
+    /// }
+    /// ```
+    ///
+    pub fn is_seekable(&self) -> bool {
+        self.frames.is_some()
+    }
+
+    /// Returns the size of the decompressed data
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes, or None if the file has no seek table
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let n_samples = samples.len().map(|len| len / 2);
+    /// ```
+    ///
+    #[allow(clippy::len_without_is_empty)]
+    pub fn len(&self) -> Option<u64> {
+        self.frames.as_ref().map(|frames| frames.last().map_or(0, |frame| frame.offset + frame.size as u64))
+    }
+
+    /// Reads decompressed data from an offset
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The offset in the decompressed data
+    /// * `buffer` - The buffer to fill
+    ///
+    /// # Returns
+    ///
+    /// The number of bytes read, which is less than the length of the buffer only at the
+    /// end of the data, or an `Unsupported` error if the file has no seek table
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let n_read = samples.read_at(4096, &mut buffer)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Only the frames holding the requested bytes are decompressed, and the last of them
+    /// is kept for the next read
+    ///
+    pub fn read_at(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<usize> {
+        let frames = match &self.frames {
+            Some(frames) => frames,
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Unsupported,
+                    format!("{} is not in the Zstandard seekable format, so it can only be read from the start with `read_to_end`", self.path.display()),
+                ))
+            }
+        };
+        let mut index = frames.partition_point(|frame| frame.offset + frame.size as u64 <= offset);
+        let mut n_read = 0;
+        while n_read < buffer.len() && index < frames.len() {
+            let frame = frames[index];
+            if self.cached.as_ref().map(|(cached, _)| *cached) != Some(index) {
+                let mut compressed = vec![0u8; frame.compressed_size];
+                self.file.seek(SeekFrom::Start(frame.compressed_offset))?;
+                self.file.read_exact(&mut compressed)?;
+                let data = zstd::bulk::decompress(&compressed, frame.size)?;
+                if data.len() != frame.size {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Frame {} of {} holds {} bytes, but its seek table entry says {}", index, self.path.display(), data.len(), frame.size),
+                    ));
+                }
+                self.cached = Some((index, data));
+            }
+            let data = self.cached.as_ref().map_or(&[][..], |(_, data)| data.as_slice());
+            let start = (offset + n_read as u64 - frame.offset) as usize;
+            let n = (data.len() - start).min(buffer.len() - n_read);
+            buffer[n_read..n_read + n].copy_from_slice(&data[start..start + n]);
+            n_read += n;
+            index += 1;
+        }
+        Ok(n_read)
+    }
+
+    /// Reads exactly enough decompressed data from an offset to fill a buffer
+    ///
+    /// # Arguments
+    ///
+    /// * `offset` - The offset in the decompressed data
+    /// * `buffer` - The buffer to fill
+    ///
+    /// # Returns
+    ///
+    /// Nothing, an `UnexpectedEof` error if the data ends before the buffer is full, or the
+    /// errors of `read_at`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// samples.read_exact_at(row * record.len() as u64, &mut record)?;
+    /// ```
+    ///
+    pub fn read_exact_at(&mut self, offset: u64, buffer: &mut [u8]) -> io::Result<()> {
+        let n_read = self.read_at(offset, buffer)?;
+        if n_read < buffer.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("{} holds {} bytes from offset {}, not {}", self.path.display(), n_read, offset, buffer.len()),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Decompresses the whole file
+    ///
+    /// # Returns
+    ///
+    /// The decompressed data, or an error if the file cannot be read or decompressed
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let data = SeekableZstd::open("archive.bin.zst")?.read_to_end()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// This works with or without a seek table, which a decoder skips as a skippable frame
+    ///
+    pub fn read_to_end(&mut self) -> io::Result<Vec<u8>> {
+        self.file.seek(SeekFrom::Start(0))?;
+        let mut data = Vec::with_capacity(self.len().unwrap_or(0) as usize);
+        zstd::stream::read::Decoder::new(&mut self.file)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+}
+
+/// Implementation of the SeekableZstdWriter struct
+///
+/// # Methods
+///
+/// * `create` - Creates or truncates a seekable Zstandard file
+/// * `finish` - Compresses the last frame and writes the seek table
+#[cfg(feature = "zstd")]
+impl SeekableZstdWriter {
+    /// Creates or truncates a seekable Zstandard file
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path to the file
+    /// * `frame_size` - The decompressed size of every frame but the last, from 1 byte to `MAX_SEEKABLE_FRAME`
+    /// * `level` - The compression level, 0 for the Zstandard default
+    ///
+    /// # Returns
+    ///
+    /// The SeekableZstdWriter, or an error if the frame size or level is out of range or
+    /// the file cannot be created
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let writer = SeekableZstdWriter::create("lfp.i16.zst", 1 << 20, 3)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Smaller frames make reads at an offset cheaper and compress worse, since every frame
+    /// is compressed on its own
+    ///
+    pub fn create<P: AsRef<Path>>(file_path: P, frame_size: usize, level: i32) -> io::Result<Self> {
+        if frame_size == 0 || frame_size > MAX_SEEKABLE_FRAME {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("The frame size must be from 1 to {} bytes, got {}", MAX_SEEKABLE_FRAME, frame_size)));
+        }
+        if !zstd::compression_level_range().contains(&level) {
+            let range = zstd::compression_level_range();
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("The zstd compression level must be from {} to {}, got {}", range.start(), range.end(), level)));
+        }
+        let output = BufWriter::new(File::create(file_path)?);
+        Ok(Self { output, level, frame_size, buffer: Vec::with_capacity(frame_size.min(1 << 20)), entries: Vec::new(), finished: false })
+    }
+
+    /// Compresses the buffered data as a frame
+    fn write_frame(&mut self) -> io::Result<()> {
+        let compressed = zstd::bulk::compress(&self.buffer, self.level)?;
+        let compressed_size = u32::try_from(compressed.len()).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "A compressed frame is larger than 4 GiB"))?;
+        self.output.write_all(&compressed)?;
+        self.entries.push((compressed_size, self.buffer.len() as u32));
+        self.buffer.clear();
+        Ok(())
+    }
+
+    /// Compresses the last frame and writes the seek table
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if the file cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// writer.finish()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Dropping the writer also finishes the file but ignores errors. Calling `finish` again
+    /// does nothing.
+    ///
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+        if !self.buffer.is_empty() {
+            self.write_frame()?;
+        }
+        let n_frames = self.entries.len() as u32;
+        self.output.write_all(&SEEK_TABLE_MAGIC.to_le_bytes())?;
+        self.output.write_all(&(self.entries.len() as u32 * 8 + SEEK_TABLE_FOOTER as u32).to_le_bytes())?;
+        for (compressed_size, size) in &self.entries {
+            self.output.write_all(&compressed_size.to_le_bytes())?;
+            self.output.write_all(&size.to_le_bytes())?;
+        }
+        self.output.write_all(&n_frames.to_le_bytes())?;
+        self.output.write_all(&[0])?;
+        self.output.write_all(&SEEKABLE_MAGIC.to_le_bytes())?;
+        self.output.flush()?;
+        self.finished = true;
+        Ok(())
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Write for SeekableZstdWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.finished {
+            return Err(io::Error::other("The seekable Zstandard file is finished"));
+        }
+        let n = buf.len().min(self.frame_size - self.buffer.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        if self.buffer.len() == self.frame_size {
+            self.write_frame()?;
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Drop for SeekableZstdWriter {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// Reads the seek table at the end of a Zstandard file, or None if it ends with something else
+#[cfg(feature = "zstd")]
+fn read_seek_table(file: &mut File, path: &Path) -> io::Result<Option<Vec<SeekFrame>>> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), message));
+    let file_len = file.metadata()?.len();
+    if file_len < (8 + SEEK_TABLE_FOOTER) as u64 {
+        return Ok(None);
+    }
+    let mut footer = [0u8; SEEK_TABLE_FOOTER];
+    file.seek(SeekFrom::End(-(SEEK_TABLE_FOOTER as i64)))?;
+    file.read_exact(&mut footer)?;
+    let word = |bytes: &[u8]| u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    if word(&footer[5..9]) != SEEKABLE_MAGIC {
+        return Ok(None);
+    }
+    let n_frames = word(&footer[0..4]) as u64;
+    let descriptor = footer[4];
+    if descriptor & 0x7c != 0 {
+        return Err(invalid(format!("the seek table descriptor {:#04x} sets reserved bits", descriptor)));
+    }
+    let entry_len: u64 = if descriptor & 0x80 != 0 { 12 } else { 8 };
+    let table_len = 8 + n_frames * entry_len + SEEK_TABLE_FOOTER as u64;
+    if table_len > file_len {
+        return Err(invalid(format!("the seek table of {} frames is longer than the file", n_frames)));
+    }
+    let mut table = vec![0u8; table_len as usize];
+    file.seek(SeekFrom::Start(file_len - table_len))?;
+    file.read_exact(&mut table)?;
+    if word(&table[0..4]) != SEEK_TABLE_MAGIC || word(&table[4..8]) as u64 != table_len - 8 {
+        return Err(invalid("the seek table does not start with its skippable frame header".to_string()));
+    }
+    let mut frames = Vec::with_capacity(n_frames as usize);
+    let (mut compressed_offset, mut offset) = (0u64, 0u64);
+    for entry in table[8..table.len() - SEEK_TABLE_FOOTER].chunks_exact(entry_len as usize) {
+        let (compressed_size, size) = (word(&entry[0..4]) as usize, word(&entry[4..8]) as usize);
+        frames.push(SeekFrame { compressed_offset, compressed_size, offset, size });
+        compressed_offset += compressed_size as u64;
+        offset += size as u64;
+    }
+    if compressed_offset != file_len - table_len {
+        return Err(invalid(format!("the seek table lists {} compressed bytes before it, but there are {}", compressed_offset, file_len - table_len)));
+    }
+    Ok(Some(frames))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::StringRecord;
+    use crate::data_io::csv::{CsvReader, CsvWriter};
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("neurorust-compression-{}-{}", std::process::id(), name))
+    }
+
+    fn row(fields: &[&str]) -> StringRecord {
+        StringRecord::from(fields.to_vec())
+    }
+
+    /// Writes a header and two rows to a new file, then two more rows through `CsvWriter::append`
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    fn write_in_two_sessions(path: &Path) {
+        let mut writer = CsvWriter::create(path).unwrap();
+        writer.write_records(&[row(&["time", "lfp"]), row(&["0", "0.5"]), row(&["0.001", "-1.25"])]).unwrap();
+        writer.finish().unwrap();
+        let mut writer = CsvWriter::append(path).unwrap();
+        writer.write_records(&[row(&["0.002", "3e-7"]), row(&["0.003", "\"quoted, value\""])]).unwrap();
+        writer.finish().unwrap();
+    }
+
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    fn check_round_trip(name: &str, magic: &[u8]) {
+        let path = temp_path(name);
+        write_in_two_sessions(&path);
+        let bytes = fs::read(&path).unwrap();
+        assert!(bytes.starts_with(magic), "{} does not start with the magic bytes", name);
+        // The appended rows are a second gzip member or Zstandard frame
+        assert!(bytes[magic.len()..].windows(magic.len()).any(|window| window == magic), "{} has one stream only", name);
+        let mut reader = CsvReader::open(&path).unwrap();
+        assert_eq!(reader.headers(), &row(&["time", "lfp"]));
+        let records = reader.read_records().unwrap();
+        assert_eq!(records, vec![row(&["0", "0.5"]), row(&["0.001", "-1.25"]), row(&["0.002", "3e-7"]), row(&["0.003", "\"quoted, value\""])]);
+        drop(reader);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn gzip_round_trips_and_appends_a_member() {
+        check_round_trip("trials.csv.gz", &[0x1f, 0x8b]);
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn zstd_round_trips_and_appends_a_frame() {
+        check_round_trip("lfp.csv.zst", &[0x28, 0xb5, 0x2f, 0xfd]);
+    }
+
+    #[test]
+    fn extensions_pick_the_compression_and_plain_files_stay_plain() {
+        assert_eq!(Compression::from_path("behavior.csv.gz"), Compression::Gzip);
+        assert_eq!(Compression::from_path("session_03/LFP.CSV.ZST"), Compression::Zstd);
+        assert_eq!(Compression::from_path("trials.csv"), Compression::None);
+        assert_eq!(Compression::from_path("gz"), Compression::None);
+
+        let path = temp_path("plain.csv");
+        let mut writer = CsvWriter::create(&path).unwrap();
+        writer.write_records(&[row(&["a"]), row(&["1"])]).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "a\n1\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    #[cfg(not(all(feature = "gzip", feature = "zstd")))]
+    fn a_compression_without_its_feature_is_unsupported() {
+        #[cfg(not(feature = "gzip"))]
+        let path = temp_path("unsupported.csv.gz");
+        #[cfg(all(feature = "gzip", not(feature = "zstd")))]
+        let path = temp_path("unsupported.csv.zst");
+        let error = CsvWriter::create(&path).map(|_| ()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+        assert!(error.to_string().contains("feature"), "{}", error);
+        fs::write(&path, b"not compressed").unwrap();
+        assert_eq!(CsvReader::open(&path).map(|_| ()).unwrap_err().kind(), io::ErrorKind::Unsupported);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn magic_bytes_win_over_the_extension_of_existing_files() {
+        assert_eq!(Compression::from_magic_bytes(&[0x1f, 0x8b, 0x08]), Some(Compression::Gzip));
+        assert_eq!(Compression::from_magic_bytes(&[0x28, 0xb5, 0x2f, 0xfd]), Some(Compression::Zstd));
+        assert_eq!(Compression::from_magic_bytes(&[0x5e, 0x2a, 0x4d, 0x18]), Some(Compression::Zstd));
+        assert_eq!(Compression::from_magic_bytes(&[0x28, 0xb5, 0x2f]), None);
+        assert_eq!(Compression::from_magic_bytes(b"time,lfp"), None);
+
+        let path = temp_path("magic.csv.gz");
+        fs::write(&path, [0x28, 0xb5, 0x2f, 0xfd, 0x00]).unwrap();
+        assert_eq!(Compression::from_path(&path), Compression::Zstd);
+        fs::write(&path, b"time,lfp\n").unwrap();
+        assert_eq!(Compression::from_path(&path), Compression::Gzip);
+        fs::write(&path, b"").unwrap();
+        assert_eq!(Compression::from_path(&path), Compression::Gzip);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(Compression::from_path(&path), Compression::Gzip);
+    }
+
+    #[test]
+    #[cfg(all(feature = "gzip", feature = "zstd"))]
+    fn renamed_compressed_files_are_read_and_appended_by_their_magic_bytes() {
+        for name in ["trials.csv.gz", "lfp.csv.zst"] {
+            let path = temp_path(name);
+            let renamed = temp_path(&format!("renamed-{}.csv", name.len()));
+            let mut writer = CsvWriter::create(&path).unwrap();
+            writer.write_records(&[row(&["time", "lfp"]), row(&["0", "0.5"])]).unwrap();
+            writer.finish().unwrap();
+            fs::rename(&path, &renamed).unwrap();
+            let mut writer = CsvWriter::append(&renamed).unwrap();
+            writer.write_record(&row(&["0.001", "-1.25"])).unwrap();
+            writer.finish().unwrap();
+            assert_eq!(Compression::from_path(&renamed), Compression::from_extension(name));
+            let records = CsvReader::open(&renamed).unwrap().read_records().unwrap();
+            assert_eq!(records, vec![row(&["0", "0.5"]), row(&["0.001", "-1.25"])], "{}", name);
+            fs::remove_file(&renamed).unwrap();
+        }
+    }
+
+    #[test]
+    #[cfg(all(feature = "gzip", feature = "zstd"))]
+    fn levels_are_honored_and_checked() {
+        use crate::data_io::dialect::CsvDialect;
+        let records: Vec<StringRecord> = (0..5000).map(|i| row(&[&i.to_string(), &((i * 7919) % 1000).to_string(), "stimulus_on"])).collect();
+        let size = |name: &str, level: i32| {
+            let path = temp_path(name);
+            let dialect = CsvDialect { compression_level: Some(level), ..CsvDialect::new() };
+            let mut writer = CsvWriter::create_with_dialect(&path, &dialect).unwrap();
+            writer.write_records(&records).unwrap();
+            writer.finish().unwrap();
+            assert_eq!(CsvReader::open_with_dialect(&path, &CsvDialect { has_headers: false, ..dialect }).unwrap().read_records().unwrap(), records);
+            let size = fs::metadata(&path).unwrap().len();
+            fs::remove_file(&path).unwrap();
+            size
+        };
+        assert!(size("level.csv.gz", 0) > 2 * size("level.csv.gz", 9));
+        assert!(size("level.csv.zst", 1) > size("level.csv.zst", 19));
+        // Level 0 of gzip stores the data, and of Zstandard picks its default
+        assert!(size("level.csv.gz", 0) > size("level.csv.zst", 0));
+
+        for (name, level) in [("bad.csv.gz", 10), ("bad.csv.gz", -1), ("bad.csv.zst", 23)] {
+            let dialect = CsvDialect { compression_level: Some(level), ..CsvDialect::new() };
+            let error = CsvWriter::create_with_dialect(temp_path(name), &dialect).map(|_| ()).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{} at level {}", name, level);
+            assert!(error.to_string().contains(&format!("got {}", level)), "{}", error);
+        }
+        let plain = temp_path("level.csv");
+        CsvWriter::create_with_dialect(&plain, &CsvDialect { compression_level: Some(99), ..CsvDialect::new() }).unwrap().finish().unwrap();
+        fs::remove_file(&plain).unwrap();
+    }
+
+    /// Bytes that compress a little, so frames have different compressed sizes
+    #[cfg(feature = "zstd")]
+    fn samples(n: usize) -> Vec<u8> {
+        let mut rng = crate::processing::random::SeededRng::new(146);
+        (0..n).map(|i| (i / 64) as u8 ^ (rng.next_index(4) as u8)).collect()
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn seekable_files_are_read_at_any_offset() {
+        let path = temp_path("seekable.bin.zst");
+        let data = samples(100_003);
+        let mut writer = SeekableZstdWriter::create(&path, 4096, 3).unwrap();
+        for chunk in data.chunks(1000) {
+            writer.write_all(chunk).unwrap();
+        }
+        writer.finish().unwrap();
+        writer.finish().unwrap();
+        assert!(writer.write(b"late").is_err());
+        drop(writer);
+
+        let mut file = SeekableZstd::open(&path).unwrap();
+        assert!(file.is_seekable());
+        assert_eq!(file.len(), Some(data.len() as u64));
+        let mut rng = crate::processing::random::SeededRng::new(1);
+        for _ in 0..200 {
+            let offset = rng.next_index(data.len());
+            let mut buffer = vec![0u8; rng.next_index(10_000)];
+            let n_read = file.read_at(offset as u64, &mut buffer).unwrap();
+            assert_eq!(n_read, buffer.len().min(data.len() - offset));
+            assert_eq!(&buffer[..n_read], &data[offset..offset + n_read], "at offset {}", offset);
+        }
+        let mut record = [0u8; 8];
+        file.read_exact_at(4092, &mut record).unwrap();
+        assert_eq!(record, data[4092..4100]);
+        assert_eq!(file.read_at(data.len() as u64, &mut record).unwrap(), 0);
+        assert_eq!(file.read_exact_at(data.len() as u64 - 3, &mut record).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(file.read_to_end().unwrap(), data);
+
+        // Ordinary decoders skip the seek table
+        assert_eq!(zstd::stream::decode_all(File::open(&path).unwrap()).unwrap(), data);
+        let truncated = fs::read(&path).unwrap();
+        fs::write(&path, &truncated[1..]).unwrap();
+        assert_eq!(SeekableZstd::open(&path).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        fs::remove_file(&path).unwrap();
+
+        let empty = temp_path("empty.bin.zst");
+        SeekableZstdWriter::create(&empty, 16, 3).unwrap().finish().unwrap();
+        let mut file = SeekableZstd::open(&empty).unwrap();
+        assert_eq!(file.len(), Some(0));
+        assert_eq!(file.read_to_end().unwrap(), Vec::<u8>::new());
+        fs::remove_file(&empty).unwrap();
+        assert_eq!(SeekableZstdWriter::create(&empty, 0, 3).map(|_| ()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn files_without_a_seek_table_are_read_sequentially_only() {
+        let path = temp_path("stream.bin.zst");
+        let data = samples(20_000);
+        fs::write(&path, zstd::stream::encode_all(&data[..], 3).unwrap()).unwrap();
+        let mut file = SeekableZstd::open(&path).unwrap();
+        assert!(!file.is_seekable());
+        assert_eq!(file.len(), None);
+        let error = file.read_at(0, &mut [0u8; 4]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::Unsupported);
+        assert!(error.to_string().contains("not in the Zstandard seekable format"), "{}", error);
+        assert_eq!(file.read_to_end().unwrap(), data);
+        fs::remove_file(&path).unwrap();
+    }
+
+    /// The `zstd` command line tool, or the one named by `NEURORUST_ZSTD`, if it can be run
+    #[cfg(feature = "zstd")]
+    fn zstd_cli() -> Option<std::process::Command> {
+        let program = std::env::var_os("NEURORUST_ZSTD").unwrap_or_else(|| "zstd".into());
+        match std::process::Command::new(&program).arg("--version").output() {
+            Ok(output) if output.status.success() => Some(std::process::Command::new(program)),
+            _ => {
+                eprintln!("skipping: no zstd command line tool");
+                None
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn files_of_the_zstd_command_line_tool_are_read_and_ours_are_decoded_by_it() {
+        let Some(_) = zstd_cli() else { return };
+        let text = temp_path("cli.csv");
+        let rows: Vec<StringRecord> = (0..2000).map(|i| row(&[&format!("{:.3}", i as f64 / 1000.0), &((i * 37) % 101).to_string()])).collect();
+        let csv_text: String = std::iter::once("time,lfp\n".to_string()).chain(rows.iter().map(|record| format!("{},{}\n", &record[0], &record[1]))).collect();
+        fs::write(&text, &csv_text).unwrap();
+
+        // Read what the tool compressed, also under a name without the extension
+        let compressed = temp_path("cli.csv.zst");
+        assert!(zstd_cli().unwrap().args(["-q", "-f", "-19"]).arg(&text).arg("-o").arg(&compressed).status().unwrap().success());
+        assert_eq!(CsvReader::open(&compressed).unwrap().read_records().unwrap(), rows);
+        let renamed = temp_path("cli-export");
+        fs::copy(&compressed, &renamed).unwrap();
+        assert_eq!(CsvReader::open(&renamed).unwrap().read_records().unwrap(), rows);
+        let mut file = SeekableZstd::open(&compressed).unwrap();
+        assert!(!file.is_seekable());
+        assert_eq!(file.read_to_end().unwrap(), csv_text.as_bytes());
+
+        // Have the tool decode what was written here, streamed and seekable
+        let decode = |path: &Path| zstd_cli().unwrap().args(["-q", "-d", "-c"]).arg(path).output().unwrap();
+        let mut writer = CsvWriter::create_with_dialect(&compressed, &crate::data_io::dialect::CsvDialect { compression_level: Some(19), ..Default::default() }).unwrap();
+        writer.write_record(&row(&["time", "lfp"])).unwrap();
+        writer.write_records(&rows).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(decode(&compressed).stdout, csv_text.as_bytes());
+        let mut writer = SeekableZstdWriter::create(&compressed, 1000, 3).unwrap();
+        writer.write_all(csv_text.as_bytes()).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+        let output = decode(&compressed);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        assert_eq!(output.stdout, csv_text.as_bytes());
+        assert_eq!(CsvReader::open(&compressed).unwrap().read_records().unwrap(), rows);
+        assert_eq!(SeekableZstd::open(&compressed).unwrap().len(), Some(csv_text.len() as u64));
+        [text, compressed, renamed].iter().for_each(|path| fs::remove_file(path).unwrap());
+    }
+}
@@ -0,0 +1,613 @@
+// A module to convert batches of data files between formats with channel selection, cropping and decimation
+
+// Written by Amin Alam in 2024
+
+use std::convert::Infallible;
+use std::fs;
+use std::io;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use csv::StringRecord;
+use crate::data_io::compression::Compression;
+use crate::data_io::csv::{column_index, parse_number, CsvReader, CsvWriter};
+use crate::data_io::dialect::CsvDialect;
+use crate::data_io::fixed_width::{FixedWidthIO, FixedWidthSpec};
+use crate::processing::parallel::{try_map_tasks, worker_count};
+
+/// The number of lines the layout of a fixed-width input is inferred from when no layout is given
+const SNIFF_LINES: usize = 100;
+
+/// The format of the input of a ConversionJob
+///
+/// # Arguments
+///
+/// * `Csv` - A csv file with a header row
+/// * `FixedWidth` - A fixed-width text file with the given layout, or None to infer it from the first lines
+///
+/// # Examples
+///
+/// ```
+/// let format = InputFormat::FixedWidth(None);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum InputFormat {
+    Csv,
+    FixedWidth(Option<FixedWidthSpec>),
+}
+
+/// The format of the output of a ConversionJob
+///
+/// # Arguments
+///
+/// * `Csv` - One csv file with the time column and the selected channels
+/// * `PerChannelCsv` - One csv file per selected channel, named `<stem>_<channel>.csv` after the output path, with `.gz` or `.zst` added if compressed, each with the time column and the channel
+///
+/// # Examples
+///
+/// ```
+/// let format = OutputFormat::PerChannelCsv;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    Csv,
+    PerChannelCsv,
+}
+
+/// What a ConversionJob does when an output file exists already
+///
+/// # Arguments
+///
+/// * `Overwrite` - Replaces the files
+/// * `Skip` - Leaves the job out if all of its output files exist, and converts it otherwise
+/// * `Error` - Fails the job if any of its output files exists
+///
+/// # Examples
+///
+/// ```
+/// let job = ConversionJob { overwrite: OverwritePolicy::Skip, ..ConversionJob::new("raw/s01.csv", "export/s01.csv") };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverwritePolicy {
+    Overwrite,
+    Skip,
+    Error,
+}
+
+/// A file to convert
+///
+/// # Arguments
+///
+/// * `input` - The path to the input file
+/// * `input_format` - The format of the input
+/// * `output` - The path to the output file, or the path the per-channel files are named after
+/// * `output_format` - The format of the output
+/// * `channels` - The channels to keep, in output order, or None to keep every column but the time column
+/// * `time_column` - The name of the column holding the time of each row in seconds, copied to every output file if present
+/// * `time_range` - The start and end times in seconds of the rows to keep, or None to keep every row
+/// * `decimation` - Keeps every n-th of the rows in the time range, or None to keep them all
+/// * `overwrite` - What to do when an output file exists already
+/// * `compression` - How the output files are compressed, or None to choose from the extension of `output`
+///
+/// # Examples
+///
+/// ```
+/// let job = ConversionJob {
+///     input_format: InputFormat::FixedWidth(None),
+///     output_format: OutputFormat::PerChannelCsv,
+///     channels: Some(vec!["ch1".to_string(), "ch4".to_string()]),
+///     time_range: Some((10.0, 70.0)),
+///     decimation: Some(4),
+///     compression: Some(Compression::Gzip),
+///     ..ConversionJob::new("raw/s01.txt", "export/s01.csv")
+/// };
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversionJob {
+    pub input: PathBuf,
+    pub input_format: InputFormat,
+    pub output: PathBuf,
+    pub output_format: OutputFormat,
+    pub channels: Option<Vec<String>>,
+    pub time_column: String,
+    pub time_range: Option<(f64, f64)>,
+    pub decimation: Option<usize>,
+    pub overwrite: OverwritePolicy,
+    pub compression: Option<Compression>,
+}
+
+/// Implementation of the ConversionJob struct
+///
+/// # Methods
+///
+/// * `new` - Creates a job copying every row and column of a csv file to another csv file
+impl ConversionJob {
+    /// Creates a job copying every row and column of a csv file to another csv file
+    ///
+    /// # Arguments
+    ///
+    /// * `input` - The path to the input file
+    /// * `output` - The path to the output file
+    ///
+    /// # Returns
+    ///
+    /// The ConversionJob, with a `time` time column, the `Error` overwrite policy and the
+    /// compression implied by the extension of the output
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let job = ConversionJob::new("raw/s01.csv", "export/s01.csv");
+    /// ```
+    ///
+    pub fn new<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q) -> Self {
+        Self {
+            input: input.as_ref().to_path_buf(),
+            input_format: InputFormat::Csv,
+            output: output.as_ref().to_path_buf(),
+            output_format: OutputFormat::Csv,
+            channels: None,
+            time_column: "time".to_string(),
+            time_range: None,
+            decimation: None,
+            overwrite: OverwritePolicy::Error,
+            compression: None,
+        }
+    }
+}
+
+/// How a ConversionJob ended
+///
+/// # Arguments
+///
+/// * `Converted` - The output files were written
+/// * `Skipped` - The output files existed already and the policy was `Skip`
+/// * `Failed` - The job returned an error or panicked, and its partial output files were removed
+///
+/// # Examples
+///
+/// ```
+/// let failed = results.iter().filter(|result| result.status == JobStatus::Failed).count();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum JobStatus {
+    Converted,
+    Skipped,
+    Failed,
+}
+
+/// Implementation of the JobStatus enum
+///
+/// # Methods
+///
+/// * `name` - Returns the name of the status
+impl JobStatus {
+    /// Returns the name of the status
+    ///
+    /// # Returns
+    ///
+    /// The name in lower case, as written to the results csv file
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(JobStatus::Skipped.name(), "skipped");
+    /// ```
+    ///
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobStatus::Converted => "converted",
+            JobStatus::Skipped => "skipped",
+            JobStatus::Failed => "failed",
+        }
+    }
+}
+
+/// The outcome of a ConversionJob
+///
+/// # Arguments
+///
+/// * `input` - The path to the input file
+/// * `outputs` - The paths to the output files written, or that existed for a skipped job
+/// * `status` - How the job ended
+/// * `seconds` - The time spent on the job in seconds
+/// * `rows_read` - The number of records read from the input
+/// * `rows_written` - The number of rows written to each output file, not counting the header
+/// * `samples_written` - The number of channel values written over all output files, not counting the time column
+/// * `error` - The error or panic message, if the job failed
+///
+/// # Examples
+///
+/// ```
+/// for result in &results {
+///     println!("{}: {} rows in {:.1} s", result.input.display(), result.rows_written, result.seconds);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct JobResult {
+    pub input: PathBuf,
+    pub outputs: Vec<PathBuf>,
+    pub status: JobStatus,
+    pub seconds: f64,
+    pub rows_read: usize,
+    pub rows_written: usize,
+    pub samples_written: usize,
+    pub error: Option<String>,
+}
+
+/// Converts a batch of files on a bounded number of threads
+///
+/// # Arguments
+///
+/// * `jobs` - The files to convert
+/// * `parallelism` - The largest number of jobs run at once, or 0 for one per available core
+///
+/// # Returns
+///
+/// The JobResult of every job, in the order of the jobs
+///
+/// # Examples
+///
+/// ```
+/// let results = convert::batch(&jobs, 4);
+/// let mut manifest = CsvWriter::create("export/conversions.csv")?;
+/// convert::results_to_csv(&results, &mut manifest)?;
+/// ```
+///
+/// # Note
+///
+/// Every job streams its input record by record, so files larger than memory can be
+/// converted. The error of a job, or a panic, is stored in its result and the other jobs
+/// carry on. A job whose output path is also the output path of an earlier job fails
+/// without being run. The values are copied as they are in the input, and decimation picks
+/// rows without low-pass filtering, so filter first if the result is used for spectral work.
+///
+pub fn batch(jobs: &[ConversionJob], parallelism: usize) -> Vec<JobResult> {
+    let n_workers = match parallelism {
+        0 => worker_count(jobs.len()),
+        n => n.min(jobs.len()).max(1),
+    };
+    let results = try_map_tasks(vec![(); n_workers], jobs.len(), |_, index| {
+        let job = &jobs[index];
+        let start = Instant::now();
+        let mut result = JobResult {
+            input: job.input.clone(),
+            outputs: Vec::new(),
+            status: JobStatus::Converted,
+            seconds: 0.0,
+            rows_read: 0,
+            rows_written: 0,
+            samples_written: 0,
+            error: None,
+        };
+        let outcome = match jobs[..index].iter().any(|earlier| earlier.output == job.output) {
+            true => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is the output of an earlier job", job.output.display()))),
+            false => match catch_unwind(AssertUnwindSafe(|| convert(job, &mut result))) {
+                Ok(outcome) => outcome,
+                Err(panic) => Err(io::Error::other(
+                    panic
+                        .downcast_ref::<&str>()
+                        .map(|message| message.to_string())
+                        .or_else(|| panic.downcast_ref::<String>().cloned())
+                        .unwrap_or_else(|| "Unknown panic".to_string()),
+                )),
+            },
+        };
+        if let Err(error) = outcome {
+            for output in result.outputs.drain(..) {
+                let _ = fs::remove_file(output);
+            }
+            result.status = JobStatus::Failed;
+            result.error = Some(error.to_string());
+        }
+        result.seconds = start.elapsed().as_secs_f64();
+        Ok::<_, Infallible>(result)
+    });
+    results.unwrap_or_else(|(_, never)| match never {})
+}
+
+/// Writes one `input,outputs,status,seconds,rows_read,rows_written,samples_written,error` row per job
+///
+/// # Arguments
+///
+/// * `results` - The results returned by `batch`
+/// * `csv_writer` - The CsvWriter to write to
+///
+/// # Returns
+///
+/// Ok, or an error if the rows cannot be written
+///
+/// # Examples
+///
+/// ```
+/// let mut manifest = CsvWriter::create("export/conversions.csv")?;
+/// convert::results_to_csv(&results, &mut manifest)?;
+/// ```
+///
+/// # Note
+///
+/// A header row is written first and the output paths of a job are separated by `;`. The
+/// writer is flushed at the end.
+///
+pub fn results_to_csv(results: &[JobResult], csv_writer: &mut CsvWriter) -> io::Result<()> {
+    let float_format = csv_writer.float_format();
+    csv_writer.write_record(&StringRecord::from(vec!["input", "outputs", "status", "seconds", "rows_read", "rows_written", "samples_written", "error"]))?;
+    for result in results {
+        csv_writer.write_record(&StringRecord::from(vec![
+            result.input.display().to_string(),
+            result.outputs.iter().map(|output| output.display().to_string()).collect::<Vec<_>>().join(";"),
+            result.status.name().to_string(),
+            float_format.format(result.seconds),
+            result.rows_read.to_string(),
+            result.rows_written.to_string(),
+            result.samples_written.to_string(),
+            result.error.clone().unwrap_or_default(),
+        ]))?;
+    }
+    csv_writer.flush()
+}
+
+/// The reader of the input of a job
+enum Source {
+    Csv(Box<CsvReader>),
+    FixedWidth(FixedWidthIO),
+}
+
+impl Source {
+    fn open(job: &ConversionJob) -> io::Result<Self> {
+        Ok(match &job.input_format {
+            InputFormat::Csv => Source::Csv(Box::new(CsvReader::open(&job.input)?)),
+            InputFormat::FixedWidth(Some(spec)) => Source::FixedWidth(FixedWidthIO::open(&job.input, spec.clone())?),
+            InputFormat::FixedWidth(None) => Source::FixedWidth(FixedWidthIO::open_sniffed(&job.input, SNIFF_LINES)?),
+        })
+    }
+
+    fn headers(&self) -> StringRecord {
+        match self {
+            Source::Csv(reader) => reader.headers().clone(),
+            Source::FixedWidth(reader) => reader.headers(),
+        }
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<StringRecord>> {
+        match self {
+            Source::Csv(reader) => reader.read_record(),
+            Source::FixedWidth(reader) => reader.read_record(),
+        }
+    }
+}
+
+/// Runs one job, recording its outputs and counts in `result` as it goes
+fn convert(job: &ConversionJob, result: &mut JobResult) -> io::Result<()> {
+    let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidInput, message);
+    if job.decimation == Some(0) {
+        return Err(invalid("The decimation factor must be positive".to_string()));
+    }
+    if let Some((start, end)) = job.time_range {
+        if start.is_nan() || end.is_nan() || start >= end {
+            return Err(invalid(format!("The time range {}..{} is empty", start, end)));
+        }
+    }
+    let mut source = Source::open(job)?;
+    let headers = source.headers();
+    let time = headers.iter().position(|name| name == job.time_column);
+    if job.time_range.is_some() && time.is_none() {
+        return Err(invalid(format!("A time range needs the time column '{}', which is not in the input", job.time_column)));
+    }
+    let channels: Vec<usize> = match &job.channels {
+        Some(names) => names.iter().map(|name| column_index(&headers, name)).collect::<io::Result<_>>()?,
+        None => (0..headers.len()).filter(|&column| Some(column) != time).collect(),
+    };
+    if channels.is_empty() {
+        return Err(invalid(format!("{} has no channel to convert", job.input.display())));
+    }
+
+    // Each output file gets the time column, if any, and its own channels
+    let compression = job.compression.unwrap_or_else(|| Compression::from_extension(&job.output));
+    let layouts: Vec<(PathBuf, Vec<usize>)> = match job.output_format {
+        OutputFormat::Csv => vec![(job.output.clone(), time.into_iter().chain(channels.iter().copied()).collect())],
+        OutputFormat::PerChannelCsv => {
+            let stem = channel_file_stem(&job.output);
+            let extension = match compression {
+                Compression::None => "csv",
+                Compression::Gzip => "csv.gz",
+                Compression::Zstd => "csv.zst",
+            };
+            channels
+                .iter()
+                .map(|&channel| (job.output.with_file_name(format!("{}_{}.{}", stem, &headers[channel], extension)), time.into_iter().chain([channel]).collect()))
+                .collect()
+        }
+    };
+    let existing = layouts.iter().filter(|(path, _)| path.exists()).count();
+    match job.overwrite {
+        OverwritePolicy::Skip if existing == layouts.len() => {
+            result.status = JobStatus::Skipped;
+            result.outputs = layouts.into_iter().map(|(path, _)| path).collect();
+            return Ok(());
+        }
+        OverwritePolicy::Error if existing > 0 => {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} output files of {} exist already", existing, job.input.display())));
+        }
+        _ => {}
+    }
+
+    let mut writers = Vec::with_capacity(layouts.len());
+    for (path, columns) in &layouts {
+        let mut writer = CsvWriter::create_with_dialect(path, &CsvDialect { compression: Some(compression), ..CsvDialect::new() })?;
+        result.outputs.push(path.clone());
+        writer.write_record(&columns.iter().map(|&column| &headers[column]).collect())?;
+        writers.push(writer);
+    }
+    let step = job.decimation.unwrap_or(1);
+    let mut in_range = 0;
+    let mut row = StringRecord::new();
+    while let Some(record) = source.read_record()? {
+        result.rows_read += 1;
+        if let (Some((start, end)), Some(time)) = (job.time_range, time) {
+            let t = parse_number(record.get(time).unwrap_or("").trim())
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Record {}: the time is not a number", result.rows_read)))?;
+            if t < start || t >= end {
+                continue;
+            }
+        }
+        in_range += 1;
+        if (in_range - 1) % step != 0 {
+            continue;
+        }
+        for ((_, columns), writer) in layouts.iter().zip(&mut writers) {
+            row.clear();
+            columns.iter().for_each(|&column| row.push_field(record.get(column).unwrap_or("")));
+            writer.write_record(&row)?;
+        }
+        result.rows_written += 1;
+    }
+    for writer in writers {
+        writer.finish()?;
+    }
+    result.samples_written = result.rows_written * channels.len();
+    Ok(())
+}
+
+/// Returns the name of the output path without its `.csv` extension and compression extension
+fn channel_file_stem(output: &Path) -> String {
+    let name = output.file_name().map_or_else(String::new, |name| name.to_string_lossy().into_owned());
+    let lower = name.to_ascii_lowercase();
+    let without_compression = [".gz", ".zst"].iter().find(|suffix| lower.ends_with(*suffix)).map_or(name.len(), |suffix| name.len() - suffix.len());
+    let end = match lower[..without_compression].ends_with(".csv") {
+        true => without_compression - 4,
+        false => lower[..without_compression].rfind('.').filter(|&dot| dot > 0).unwrap_or(without_compression),
+    };
+    name[..end].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("neurorust-convert-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Reads a csv file, compressed or not, as lines
+    fn lines(path: &Path) -> Vec<String> {
+        let mut reader = CsvReader::open(path).unwrap();
+        let headers = reader.headers().iter().collect::<Vec<_>>().join(",");
+        std::iter::once(headers).chain(reader.read_records().unwrap().iter().map(|record| record.iter().collect::<Vec<_>>().join(","))).collect()
+    }
+
+    /// Writes a csv and a fixed-width fixture with the time and the channels ch1 to ch3
+    fn fixtures(dir: &Path) -> (PathBuf, PathBuf) {
+        let csv_path = dir.join("s01.csv");
+        let text_path = dir.join("s02.txt");
+        let mut csv_text = "time,ch1,ch2,ch3\n".to_string();
+        let mut fixed_text = " time  ch1  ch2  ch3\n".to_string();
+        for i in 0..10 {
+            csv_text.push_str(&format!("{},{},{},{}\n", i as f64 * 0.5, i, 10 * i, 100 * i));
+            fixed_text.push_str(&format!("{:>5.1}{:>5}{:>5}{:>5}\n", i as f64 * 0.5, i, 10 * i, 100 * i));
+        }
+        fs::write(&csv_path, csv_text).unwrap();
+        fs::write(&text_path, fixed_text).unwrap();
+        (csv_path, text_path)
+    }
+
+    #[test]
+    fn channels_time_ranges_and_decimation_are_applied_to_each_format() {
+        let dir = temp_dir("select");
+        let (csv_path, text_path) = fixtures(&dir);
+        let jobs = vec![
+            ConversionJob { channels: Some(vec!["ch3".to_string(), "ch1".to_string()]), time_range: Some((1.0, 4.0)), ..ConversionJob::new(&csv_path, dir.join("s01_out.csv")) },
+            ConversionJob {
+                input_format: InputFormat::FixedWidth(None),
+                output_format: OutputFormat::PerChannelCsv,
+                channels: Some(vec!["ch2".to_string()]),
+                decimation: Some(4),
+                ..ConversionJob::new(&text_path, dir.join("s02.csv"))
+            },
+        ];
+        let results = batch(&jobs, 2);
+        assert!(results.iter().all(|result| result.status == JobStatus::Converted), "{:?}", results);
+        assert_eq!(lines(&dir.join("s01_out.csv")), ["time,ch3,ch1", "1,200,2", "1.5,300,3", "2,400,4", "2.5,500,5", "3,600,6", "3.5,700,7"]);
+        assert_eq!((results[0].rows_read, results[0].rows_written, results[0].samples_written), (10, 6, 12));
+        let per_channel = dir.join("s02_ch2.csv");
+        assert_eq!(results[1].outputs, vec![per_channel.clone()]);
+        let decimated = lines(&per_channel);
+        assert_eq!(decimated.len(), 4);
+        assert_eq!(decimated[0], "time,ch2");
+        let values: Vec<(f64, f64)> = decimated[1..].iter().map(|line| line.split_once(',').map(|(t, v)| (t.parse().unwrap(), v.parse().unwrap())).unwrap()).collect();
+        assert_eq!(values, [(0.0, 0.0), (2.0, 40.0), (4.0, 80.0)]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failing_jobs_do_not_stop_the_others_and_leave_no_output() {
+        let dir = temp_dir("errors");
+        let (csv_path, _) = fixtures(&dir);
+        let existing = dir.join("existing.csv");
+        fs::write(&existing, "kept\n").unwrap();
+        let jobs = vec![
+            ConversionJob::new(dir.join("missing.csv"), dir.join("missing_out.csv")),
+            ConversionJob { channels: Some(vec!["ch9".to_string()]), ..ConversionJob::new(&csv_path, dir.join("bad_channel.csv")) },
+            ConversionJob { output_format: OutputFormat::PerChannelCsv, decimation: Some(0), ..ConversionJob::new(&csv_path, dir.join("zero.csv")) },
+            ConversionJob::new(&csv_path, &existing),
+            ConversionJob { overwrite: OverwritePolicy::Skip, ..ConversionJob::new(&csv_path, &existing) },
+            ConversionJob { overwrite: OverwritePolicy::Overwrite, ..ConversionJob::new(&csv_path, dir.join("copy.csv")) },
+        ];
+        let results = batch(&jobs, 0);
+        let statuses: Vec<JobStatus> = results.iter().map(|result| result.status).collect();
+        use JobStatus::*;
+        assert_eq!(statuses, [Failed, Failed, Failed, Failed, Failed, Converted]);
+        assert!(results[1].error.as_deref().unwrap().contains("Column 'ch9' not found"), "{:?}", results[1].error);
+        assert!(results[2].error.as_deref().unwrap().contains("decimation factor"), "{:?}", results[2].error);
+        assert!(results[3].error.as_deref().unwrap().contains("exist already"), "{:?}", results[3].error);
+        assert!(results[4].error.as_deref().unwrap().contains("output of an earlier job"), "{:?}", results[4].error);
+        assert!(!dir.join("bad_channel.csv").exists());
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "kept\n");
+        assert_eq!(lines(&dir.join("copy.csv")).len(), 11);
+
+        let skipped = batch(&[ConversionJob { overwrite: OverwritePolicy::Skip, ..ConversionJob::new(&csv_path, &existing) }], 1);
+        assert_eq!(skipped[0].status, JobStatus::Skipped);
+        assert_eq!(fs::read_to_string(&existing).unwrap(), "kept\n");
+
+        let manifest = dir.join("conversions.csv");
+        let mut writer = CsvWriter::create(&manifest).unwrap();
+        results_to_csv(&results, &mut writer).unwrap();
+        let written = lines(&manifest);
+        assert_eq!(written[0], "input,outputs,status,seconds,rows_read,rows_written,samples_written,error");
+        assert_eq!(written.len(), 7);
+        assert!(written[6].contains(",converted,") && written[6].ends_with(",10,10,30,"), "{}", written[6]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn per_channel_names_keep_the_compression_extension() {
+        assert_eq!(channel_file_stem(Path::new("export/s01.csv.gz")), "s01");
+        assert_eq!(channel_file_stem(Path::new("export/S01.CSV.ZST")), "S01");
+        assert_eq!(channel_file_stem(Path::new("export/s01.tsv")), "s01");
+        assert_eq!(channel_file_stem(Path::new("export/s01")), "s01");
+        assert_eq!(channel_file_stem(Path::new("export/run.1.csv")), "run.1");
+    }
+
+    #[test]
+    #[cfg(all(feature = "gzip", feature = "zstd"))]
+    fn outputs_are_compressed_by_extension_or_by_the_job() {
+        let dir = temp_dir("compressed");
+        let (csv_path, _) = fixtures(&dir);
+        let jobs = vec![
+            ConversionJob { output_format: OutputFormat::PerChannelCsv, channels: Some(vec!["ch1".to_string(), "ch3".to_string()]), ..ConversionJob::new(&csv_path, dir.join("s01.csv.gz")) },
+            ConversionJob { compression: Some(Compression::Zstd), ..ConversionJob::new(&csv_path, dir.join("s01_all.csv")) },
+        ];
+        let results = batch(&jobs, 1);
+        assert!(results.iter().all(|result| result.status == JobStatus::Converted), "{:?}", results);
+        assert_eq!(results[0].outputs, vec![dir.join("s01_ch1.csv.gz"), dir.join("s01_ch3.csv.gz")]);
+        for (path, magic) in [(dir.join("s01_ch1.csv.gz"), &[0x1f, 0x8b][..]), (dir.join("s01_ch3.csv.gz"), &[0x1f, 0x8b]), (dir.join("s01_all.csv"), &[0x28, 0xb5, 0x2f, 0xfd])] {
+            assert!(fs::read(&path).unwrap().starts_with(magic), "{}", path.display());
+        }
+        assert_eq!(lines(&dir.join("s01_ch3.csv.gz"))[..3], ["time,ch3", "0,0", "0.5,100"]);
+        assert_eq!(lines(&dir.join("s01_all.csv")), lines(&csv_path));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
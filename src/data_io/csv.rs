@@ -2,79 +2,433 @@
 
 // Written by Amin Alam in 2024
 
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
-use csv::{Reader, Writer, StringRecord};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use csv::{ByteRecord, Position, Reader, StringRecordsIter, Writer, StringRecord};
+use crate::core::memory::{record_bytes, MemoryBudget};
+use crate::core::session::SessionInfo;
+use crate::data_io::aliases::{ColumnAliases, ColumnMapping};
+use crate::data_io::calibration::{self, Calibration, CalibrationReport};
+use crate::data_io::categorical::{self, CategoricalMapping, CodeOrder, ValueCounts};
+use crate::data_io::compression::{decompress, Compression, Sink, Spill};
+use crate::data_io::dialect::{CsvDialect, CsvIOBuilder};
+use crate::data_io::error::DataIoError;
+use crate::data_io::float_format::FloatFormat;
+use crate::data_io::numeric::{parse_field, PlainLines, Stop};
+use crate::data_io::pseudonym::{self, PseudonymKey, PseudonymLookup};
+use crate::data_io::query::CsvQuery;
+use crate::data_io::records::{CsvRecordChunks, CsvRecords};
+use crate::data_io::rolling::{RollingColumn, RollingState, RollingWindow};
+use crate::data_io::sort::{self, SortSummary};
+use crate::data_io::winsorize::{self, WinsorizeOptions, WinsorizeReport};
+use crate::processing::error::ProcessingError;
+use crate::processing::robust::{robust_stats, RobustStatsTable};
+use crate::processing::streaming::{GroupStats, StatsTable, StreamingStats};
+use crate::processing::timing::{validate_timing, TimingReport};
+#[cfg(feature = "polars")]
+use polars::prelude::{DataFrame, PolarsError, PolarsResult};
+#[cfg(feature = "polars")]
+use crate::data_io::dataframe::{check_unique_names, column_from_fields, column_to_fields};
+#[cfg(feature = "http")]
+use crate::data_io::http::{download, HttpOptions};
 
 /// A class to read, write and manipulate csv files
 /// 
 /// # Arguments
 /// 
 /// * `file_path` - A string slice that holds the path to the csv file
-/// * `reader` - A csv::Reader object that reads the csv file
-/// * `writer` - A csv::Writer object that writes to the csv file
-/// * `headers` - A csv::StringRecord object that holds the headers of the csv file
+/// * `mode` - The OpenMode the file was opened with
+/// * `reader` - A CsvReader object that reads the csv file, None if it was opened for writing
+/// * `writer` - A CsvWriter object that writes to the csv file, None if it was opened for reading
 /// * `is_open` - A boolean that indicates if the file is open
 /// 
 /// # Examples
 /// 
 /// ```
-/// let csv_io = CsvIO::new("data.csv");
+/// let csv_io = CsvIO::open_read("data.csv")?;
 /// ```
 /// 
 /// # Note
 /// 
-/// This class is not thread safe and should not be shared between threads
+/// The methods that read records return a `NotReadable` error on a file opened for writing,
+/// and the methods that write records a `NotWritable` error on a file opened for reading.
+/// It can be moved to another thread but not shared between threads; use `split` and
+/// `CsvReader::try_clone` to read the same file from several threads at once.
 pub struct CsvIO {
     file_path: String,
-    reader: Reader<BufReader<File>>,
-    writer: Writer<BufWriter<File>>,
-    headers: StringRecord,
+    mode: OpenMode,
+    reader: Option<CsvReader>,
+    writer: Option<CsvWriter>,
     is_open: bool,
 }
 
+/// How `CsvIO::open` opens a csv file
+/// 
+/// # Arguments
+/// 
+/// * `Read` - Reads the records of an existing file, which is left unchanged
+/// * `Write` - Creates the file, or truncates it if it exists, to write new records
+/// * `Append` - Reads the records of the file and writes new records after its end, creating it if it does not exist
+/// 
+/// # Examples
+/// 
+/// ```
+/// let mut trials = CsvIO::open("trials.csv", OpenMode::Append)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OpenMode {
+    Read,
+    Write,
+    Append,
+}
 
 /// Implementation of the CsvIO class
 /// 
 /// # Methods
 /// 
-/// * `new` - Creates a new CsvIO object
+/// * `new` - Opens a csv file for reading
+/// * `open` - Opens a csv file
+/// * `open_read` - Opens an existing csv file for reading, leaving it unchanged
+/// * `open_write` - Creates a csv file, or truncates it if it exists, for writing
+/// * `open_append` - Opens a csv file to read its records and write new ones after its end
+/// * `open_stdin` - Reads csv records from the standard input
+/// * `builder` - Creates a builder opening csv files with another dialect
+/// * `mode` - Returns the mode the file was opened with
+/// * `split` - Splits the CsvIO object into its reader and writer
+/// * `concat` - Concatenates csv files with the same header row into one
+/// * `records` - Iterates over the remaining records one at a time
+/// * `records_chunked` - Iterates over the remaining records in blocks of a fixed size
+/// * `read_columns` - Reads the remaining records into one typed vector per column
+/// * `select` - Iterates over some columns of the remaining records
+/// * `filter` - Iterates over the remaining records a predicate accepts
+/// * `set_float_format` - Sets the format of the numbers written to the file
+/// * `float_format` - Returns the format of the numbers written to the file
+/// * `finish` - Saves all the changes and finishes the compressed stream of the file
+/// * `validate_time_column` - Checks the regularity of a time column
+/// * `column_stats` - Computes the statistics of every column in one pass
+/// * `grouped_stats` - Computes the running statistics of a column per combination of key columns
+/// * `robust_column_stats` - Computes the median, MAD, quartiles and trimmed mean of every column
+/// * `melt` - Reshapes the remaining records from wide to long format
+/// * `pivot` - Reshapes the remaining records from long to wide format
+/// * `with_memory_budget` - Sets the memory budget of the operations of the CsvIO object
+/// * `sort` - Writes the remaining records sorted by some columns
+/// * `encode_categorical` - Copies the remaining records with the values of some columns replaced by integer codes
+/// * `decode_categorical` - Copies the remaining records with the codes of some columns replaced by their values
+/// * `value_counts` - Counts the values of a column over the remaining records
+/// * `set_column_aliases` - Reads the columns under their canonical names
+/// * `column_mapping` - Returns the renames applied to the headers
+/// * `winsorize_columns` - Copies the remaining records with the extreme values of some columns capped at their quantiles
+/// * `with_rolling_column` - Adds a rolling aggregate of a column to `copy_transformed`
+/// * `with_rolling` - Adds a rolling column to `copy_transformed`
+/// * `copy_transformed` - Copies the remaining records with the rolling columns appended
+/// * `pseudonymize` - Copies the remaining records with the values of some columns replaced by keyed tokens
+/// * `shift_dates` - Copies the remaining records with the dates of some columns shifted by a keyed offset per subject
+/// * `apply_calibration_copy` - Copies the remaining records with the gains and offsets of a calibration applied
 /// 
 /// # Examples
 /// 
 /// ```
-/// let csv_io = CsvIO::new("data.csv");
+/// let csv_io = CsvIO::new("data.csv")?;
 /// ```
 impl CsvIO {
-    /// Creates a new CsvIO object
+    /// Opens a csv file for reading
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file_path` - A string slice that holds the path to the csv file
+    /// 
+    /// # Returns
+    /// 
+    /// The CsvIO object, or a `FileNotFound` error if the file does not exist, or an error if
+    /// it cannot be opened
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let csv_io = CsvIO::new("data.csv")?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// This is the same as `open_read`. Use `open_write` or `open_append` to write to the file.
+    /// 
+    pub fn new(file_path: &str) -> Result<Self, DataIoError> {
+        Self::open(file_path, OpenMode::Read)
+    }
+
+    /// Opens a csv file
     /// 
     /// # Arguments
     /// 
     /// * `file_path` - A string slice that holds the path to the csv file
+    /// * `mode` - Whether the file is read, written from scratch or appended to
+    /// 
+    /// # Returns
+    /// 
+    /// The CsvIO object, or a `FileNotFound` error if the file does not exist in `Read`
+    /// mode, or an error if it cannot be opened or created
     /// 
     /// # Examples
     /// 
     /// ```
-    /// let csv_io = CsvIO::new("data.csv");
+    /// let mut csv_io = CsvIO::open("psth.csv", OpenMode::Write)?;
     /// ```
     /// 
-    fn new(file_path: &str) -> Self {
-        let reader_file = File::open(file_path).expect("File not found");
-        let mut reader = Reader::from_reader(BufReader::new(reader_file));
-        let writer_file = File::create(file_path).expect("Could not create file");
-        let writer = Writer::from_writer(BufWriter::new(writer_file));
+    /// # Note
+    /// 
+    /// In `Append` mode the header row of a new or empty file still has to be written, and
+    /// the records written are expected to have the columns of the existing header row.
+    /// Files ending in `.gz` or `.zst` are read and written as gzip or Zstandard, as are
+    /// existing files starting with their magic bytes, see `CsvDialect` and
+    /// `CsvIOBuilder::compression`.
+    /// 
+    pub fn open(file_path: &str, mode: OpenMode) -> Result<Self, DataIoError> {
+        Self::open_with_dialect(file_path, mode, &CsvDialect::default())
+    }
 
-        let headers: StringRecord = reader.headers().expect("No headers found").clone();
+    /// Opens a csv file with a dialect
+    pub(crate) fn open_with_dialect(file_path: &str, mode: OpenMode, dialect: &CsvDialect) -> Result<Self, DataIoError> {
+        let (reader, writer) = match mode {
+            OpenMode::Read => (Some(CsvReader::open_with_dialect(file_path, dialect).map_err(|error| match error.kind() {
+                io::ErrorKind::NotFound => DataIoError::FileNotFound(PathBuf::from(file_path)),
+                _ => DataIoError::Io(error),
+            })?), None),
+            OpenMode::Write => (None, Some(CsvWriter::create_with_dialect(file_path, dialect)?)),
+            OpenMode::Append => {
+                OpenOptions::new().create(true).append(true).open(file_path)?;
+                (Some(CsvReader::open_with_dialect(file_path, dialect)?), Some(CsvWriter::append_with_dialect(file_path, dialect)?))
+            }
+        };
 
-        Self {
-            reader: reader,
-            writer: writer,
+        Ok(Self {
             file_path: file_path.to_string(),
-            headers: headers,
+            mode,
+            reader,
+            writer,
             is_open: true,
+        })
+    }
+
+    /// Opens an existing csv file for reading, leaving it unchanged
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file_path` - A string slice that holds the path to the csv file
+    /// 
+    /// # Returns
+    /// 
+    /// The CsvIO object, or a `FileNotFound` error if the file does not exist, or an error if
+    /// it cannot be opened
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut csv_io = CsvIO::open_read("recording.csv")?;
+    /// let records = csv_io.read_records()?;
+    /// ```
+    /// 
+    pub fn open_read(file_path: &str) -> Result<Self, DataIoError> {
+        Self::open(file_path, OpenMode::Read)
+    }
+
+    /// Reads csv records from the standard input
+    /// 
+    /// # Returns
+    /// 
+    /// The CsvIO object in `Read` mode, with `-` as its path, or an error if the header row
+    /// cannot be read
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// // cat recording.csv | my_filter > filtered.csv
+    /// let mut csv_io = CsvIO::open_stdin()?;
+    /// let table = csv_io.column_stats(&StreamingStats::new())?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// A pipe cannot be sought or read twice, so the operations that stream the remaining
+    /// records work as on a file and the ones that need a RowIndex or a second reader, such
+    /// as `split` followed by `CsvReader::try_clone`, return an `Unsupported` error
+    /// 
+    pub fn open_stdin() -> Result<Self, DataIoError> {
+        Ok(Self { file_path: "-".to_string(), mode: OpenMode::Read, reader: Some(CsvReader::from_stdin()?), writer: None, is_open: true })
+    }
+
+    /// Creates a csv file, or truncates it if it exists, for writing
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file_path` - A string slice that holds the path to the csv file
+    /// 
+    /// # Returns
+    /// 
+    /// The CsvIO object, or an error if the file cannot be created
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut csv_io = CsvIO::open_write("psth.csv")?;
+    /// psth.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    /// 
+    pub fn open_write(file_path: &str) -> Result<Self, DataIoError> {
+        Self::open(file_path, OpenMode::Write)
+    }
+
+    /// Opens a csv file to read its records and write new ones after its end
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file_path` - A string slice that holds the path to the csv file, created if it does not exist
+    /// 
+    /// # Returns
+    /// 
+    /// The CsvIO object, or an error if the file cannot be opened or created
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut trials = CsvIO::open_append("trials.csv")?;
+    /// trials.write_record(StringRecord::from(vec!["42", "left", "0.512"]))?;
+    /// trials.save()?;
+    /// ```
+    /// 
+    pub fn open_append(file_path: &str) -> Result<Self, DataIoError> {
+        Self::open(file_path, OpenMode::Append)
+    }
+
+    /// Creates a builder opening csv files with another delimiter, quote or comment byte, or without a header row
+    /// 
+    /// # Returns
+    /// 
+    /// A CsvIOBuilder of comma separated files with a header row
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut csv_io = CsvIO::builder().delimiter(b'\t').has_headers(false).open_read("lfp.tsv")?;
+    /// ```
+    /// 
+    pub fn builder() -> CsvIOBuilder {
+        CsvIOBuilder::new()
+    }
+
+    /// Returns the mode the file was opened with
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A reference to the CsvIO object
+    /// 
+    /// # Returns
+    /// 
+    /// The OpenMode of the file
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// assert_eq!(CsvIO::new("data.csv")?.mode(), OpenMode::Read);
+    /// ```
+    /// 
+    pub fn mode(&self) -> OpenMode {
+        self.mode
+    }
+
+    /// Splits the CsvIO object into its reader and writer
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - The CsvIO object, consumed
+    /// 
+    /// # Returns
+    /// 
+    /// The CsvReader of the file, None if it was opened for writing, and the CsvWriter of the
+    /// file, None if it was opened for reading
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let (reader, writer) = CsvIO::open_append("trials.csv")?.split();
+    /// ```
+    /// 
+    pub fn split(self) -> (Option<CsvReader>, Option<CsvWriter>) {
+        (self.reader, self.writer)
+    }
+
+    /// Concatenates csv files with the same header row into one
+    /// 
+    /// # Arguments
+    /// 
+    /// * `inputs` - The paths to the csv files, in output order
+    /// * `output` - The writer of the concatenated csv file
+    /// 
+    /// # Returns
+    /// 
+    /// The number of rows written after the header row, or an error if there is no input,
+    /// the header rows differ, or a file cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let shards: Vec<PathBuf> = read_manifest("shards/s01")?.iter().map(|shard| Path::new("shards/s01").join(&shard.file)).collect();
+    /// let mut output = CsvWriter::create("s01_joined.csv")?;
+    /// CsvIO::concat(&shards, &mut output)?;
+    /// output.flush()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The header row is written once and the records are streamed, one at a time, with
+    /// their fields copied as they are
+    /// 
+    pub fn concat<P: AsRef<Path>>(inputs: &[P], output: &mut CsvWriter) -> Result<usize, DataIoError> {
+        let mut n_rows = 0;
+        let mut headers: Option<StringRecord> = None;
+        for input in inputs {
+            let mut reader = CsvReader::open(input)?;
+            match &headers {
+                None => {
+                    output.write_record(reader.headers())?;
+                    headers = Some(reader.headers().clone());
+                }
+                Some(headers) if headers != reader.headers() => {
+                    return Err(DataIoError::Io(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("The header row of {} differs from the header row of the first file", input.as_ref().display()),
+                    )))
+                }
+                Some(_) => {}
+            }
+            for record in reader.records() {
+                output.write_record(&record?)?;
+                n_rows += 1;
+            }
+        }
+        match headers {
+            Some(_) => Ok(n_rows),
+            None => Err(DataIoError::Io(io::Error::new(io::ErrorKind::InvalidInput, "At least one file is needed to concatenate"))),
         }
     }
 
+    /// Returns the reader of the csv file, or an error if it was opened for writing
+    pub(crate) fn reader_mut(&mut self) -> Result<&mut CsvReader, DataIoError> {
+        let file_path = &self.file_path;
+        self.reader.as_mut().ok_or_else(|| DataIoError::NotReadable(PathBuf::from(file_path)))
+    }
+
+    /// Returns the writer of the csv file, or an error if it was opened for reading
+    fn writer_mut(&mut self) -> Result<&mut CsvWriter, DataIoError> {
+        let file_path = &self.file_path;
+        self.writer.as_mut().ok_or_else(|| DataIoError::NotWritable(PathBuf::from(file_path)))
+    }
+
     /// Reads the next record from the csv file
     /// 
     /// # Arguments
@@ -83,16 +437,19 @@ impl CsvIO {
     /// 
     /// # Returns
     /// 
-    /// A csv::StringRecord object that holds the record
+    /// A csv::StringRecord object that holds the record, None at the end of the file, or an
+    /// error if the record cannot be read
     /// 
     /// # Examples
     /// 
     /// ```
-    /// let record = csv_io.read_record();
+    /// while let Some(record) = csv_io.read_record()? {
+    ///     println!("{:?}", record);
+    /// }
     /// ```
     /// 
-    fn read_record(&mut self) -> StringRecord {
-        self.reader.records().next().expect("No records found").expect("Error reading record")
+    pub fn read_record(&mut self) -> Result<Option<StringRecord>, DataIoError> {
+        Ok(self.reader_mut()?.read_record()?)
     }
 
     /// Reads all records from the csv file
@@ -103,114 +460,3300 @@ impl CsvIO {
     /// 
     /// # Returns
     /// 
-    /// A vector of csv::StringRecord objects that holds the records
+    /// A vector of csv::StringRecord objects that holds the records, or an error if a record
+    /// cannot be read
     /// 
     /// # Examples
     /// 
     /// ```
-    /// let records = csv_io.read_records();
+    /// let records = csv_io.read_records()?;
     /// ```
     /// 
+    /// # Note
     /// 
-    fn read_records(&mut self) -> Vec<StringRecord> {
-        let mut records: Vec<StringRecord> = Vec::new();
-        for record in self.reader.records() {
-            records.push(record.expect("Error reading record"));
-        }
-        records
+    /// Every record is held in memory. Use `records` or `records_chunked` to read a large
+    /// file in constant memory.
+    /// 
+    pub fn read_records(&mut self) -> Result<Vec<StringRecord>, DataIoError> {
+        Ok(self.reader_mut()?.read_records()?)
     }
 
-    /// Writes a record to the csv file
+    /// Iterates over the remaining records one at a time
     /// 
     /// # Arguments
     /// 
     /// * `self` - A mutable reference to the CsvIO object
-    /// * `record` - A csv::StringRecord object that holds the record
+    /// 
+    /// # Returns
+    /// 
+    /// A CsvRecords iterator of the records, ending after the last record or the first error
     /// 
     /// # Examples
     /// 
     /// ```
-    /// csv_io.write_record(record);
+    /// let mut n_spikes = 0;
+    /// for record in csv_io.records() {
+    ///     n_spikes += (&record?[2] == "1") as usize;
+    /// }
     /// ```
     /// 
     /// # Note
     /// 
-    /// This method does not write the record to the file immediately.
-    /// Use the `flush` method to write the record to the file.
+    /// Only one record is held in memory at a time. `for record in &mut csv_io` and
+    /// `csv_io.into_iter()` iterate the same way.
     /// 
-    /// # See
+    pub fn records(&mut self) -> CsvRecords<'_> {
+        CsvRecords::new(self)
+    }
+
+    /// Iterates over the remaining records in blocks of a fixed size
     /// 
-    /// * `flush` - Writes the record to the file
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `n` - The number of records of every block but the last, at least 1
+    /// 
+    /// # Returns
+    /// 
+    /// A CsvRecordChunks iterator of the blocks, ending after the last record or the first error
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// for block in csv_io.records_chunked(65536) {
+    ///     process(&block?);
+    /// }
+    /// ```
     /// 
-    fn write_record(&mut self, record: StringRecord) {
-        self.writer.write_record(&record).expect("Error writing record");
+    /// # Note
+    /// 
+    /// Only one block is held in memory at a time, and an `n` of 0 is read as 1
+    /// 
+    pub fn records_chunked(&mut self, n: usize) -> CsvRecordChunks<'_> {
+        CsvRecordChunks::new(self, n)
     }
 
-    /// Writes a group of records to the csv file
+    /// Reads the remaining records into one typed vector per column
     /// 
     /// # Arguments
     /// 
     /// * `self` - A mutable reference to the CsvIO object
-    /// * `records` - A vector of csv::StringRecord objects that holds the records
+    /// 
+    /// # Returns
+    /// 
+    /// The values of every column, in the order of the headers, or a `Parse` error naming the
+    /// line and column of the first field that cannot be parsed, or an error if a record
+    /// cannot be read or the values would exceed the memory budget of the reader
     /// 
     /// # Examples
     /// 
     /// ```
-    /// csv_io.write_records(records);
+    /// let channels = csv_io.read_columns::<f64>()?;
+    /// let psd = compute_psd(&channels[1..], 30000.0, 1.0, 0.1)?;
     /// ```
     /// 
     /// # Note
     /// 
-    /// This method does not write the records to the file immediately.
-    /// Use the `flush` method to write the records to the file.
-    /// 
-    /// # See
+    /// The columns are laid out as the channels × samples matrices of the crate. Fields are
+    /// trimmed before they are parsed, so an empty field is an error unless `T` parses the
+    /// empty string. This method consumes the remaining records of the reader. Plain ASCII
+    /// lines without quotes are split without the csv parser and f64 and f32 fields are
+    /// parsed with fast-float2, which gives the same values as `str::parse`; from the first
+    /// line with a quote or a byte that is not ASCII on, the csv parser reads the file.
     /// 
-    /// * `flush` - Writes the records to the file
+    pub fn read_columns<T: FromStr + 'static>(&mut self) -> Result<Vec<Vec<T>>, DataIoError> {
+        self.read_columns_split::<T>(None).map(|(columns, _)| columns)
+    }
+
+    /// Reads the remaining records into one typed vector per column, and one column as f64
     ///
-    fn write_records(&mut self, records: Vec<StringRecord>) {
-        for record in records {
-            self.write_record(record);
-        }
+    /// The column at `f64_column`, e.g. a time column that must keep f64 precision next to
+    /// f32 samples, is left out of the typed columns and returned on its own.
+    pub(crate) fn read_columns_split<T: FromStr + 'static>(&mut self, f64_column: Option<usize>) -> Result<(Vec<Vec<T>>, Vec<f64>), DataIoError> {
+        let reader = self.reader_mut()?;
+        let headers = reader.headers().clone();
+        let budget = reader.memory_budget();
+        let n_typed = headers.len() - usize::from(f64_column.is_some_and(|index| index < headers.len()));
+        let row_bytes = n_typed * size_of::<T>() + if n_typed < headers.len() { size_of::<f64>() } else { 0 };
+        let mut columns: Vec<Vec<T>> = (0..n_typed).map(|_| Vec::new()).collect();
+        let mut split = Vec::new();
+        let mut needed = 0usize;
+        reader.for_each_row(|line, fields| -> Result<(), DataIoError> {
+            needed += row_bytes;
+            budget.check(needed).map_err(|error| ProcessingError::MemoryBudgetExceeded { needed: error.needed, budget: error.budget })?;
+            let parse_error = |field: &[u8], header: &str| DataIoError::Parse {
+                line,
+                column: header.to_string(),
+                value: String::from_utf8_lossy(field).into_owned(),
+            };
+            let mut typed = columns.iter_mut();
+            for (index, (field, header)) in fields.iter().zip(headers.iter()).enumerate() {
+                if Some(index) == f64_column {
+                    split.push(parse_field::<f64>(field).ok_or_else(|| parse_error(field, header))?);
+                } else if let Some(values) = typed.next() {
+                    values.push(parse_field(field).ok_or_else(|| parse_error(field, header))?);
+                }
+            }
+            Ok(())
+        })?;
+        Ok((columns, split))
     }
 
-    /// saves all the changes to the file
+    /// Iterates over some columns of the remaining records
     /// 
     /// # Arguments
     /// 
     /// * `self` - A mutable reference to the CsvIO object
+    /// * `columns` - The names of the columns, in the order they are returned
+    /// 
+    /// # Returns
+    /// 
+    /// A CsvQuery of the columns, to which more selections and filters can be added, or a
+    /// `ColumnNotFound` error if a column is not found
     /// 
     /// # Examples
     /// 
     /// ```
-    /// csv_io.save();
+    /// for record in csv_io.select(&["time", "ch3", "ch7"])?.filter_range("time", 10.0..20.0)? {
+    ///     println!("{:?}", record?);
+    /// }
     /// ```
     /// 
     /// # Note
     /// 
-    /// This method writes all the changes to the actual file on disk
+    /// Only one record is held in memory at a time
     /// 
-    fn save(&mut self) {
-        self.writer.flush().expect("Error saving file");
+    pub fn select(&mut self, columns: &[&str]) -> Result<CsvQuery<'_>, DataIoError> {
+        self.reader_mut()?;
+        CsvQuery::new(self).select(columns)
     }
 
-    /// Closes the file
+    /// Iterates over the remaining records a predicate accepts
     /// 
     /// # Arguments
     /// 
     /// * `self` - A mutable reference to the CsvIO object
+    /// * `predicate` - Returns whether to keep a record, given all its fields
+    /// 
+    /// # Returns
+    /// 
+    /// A CsvQuery of the records, to which more selections and filters can be added
     /// 
     /// # Examples
     /// 
     /// ```
-    /// csv_io.close();
+    /// let go_trials = csv_io.filter(|record| &record[1] == "go").count();
     /// ```
     /// 
     /// # Note
     /// 
-    /// This method closes the file and frees up resources
+    /// Only one record is held in memory at a time
     /// 
-    fn close(&mut self) {
-        self.is_open = false;
+    pub fn filter<'a, F: FnMut(&StringRecord) -> bool + 'a>(&'a mut self, predicate: F) -> CsvQuery<'a> {
+        CsvQuery::new(self).filter(predicate)
+    }
+
+    /// Writes a record to the csv file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `record` - A csv::StringRecord object that holds the record
+    /// 
+    /// # Returns
+    /// 
+    /// Nothing, or an error if the record cannot be written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// csv_io.write_record(record)?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// This method does not write the record to the file immediately.
+    /// Use the `flush` method to write the record to the file.
+    /// 
+    /// # See
+    /// 
+    /// * `flush` - Writes the record to the file
+    /// 
+    pub fn write_record(&mut self, record: StringRecord) -> Result<(), DataIoError> {
+        Ok(self.writer_mut()?.write_record(&record)?)
     }
-} 
\ No newline at end of file
+
+    /// Writes a group of records to the csv file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `records` - A vector of csv::StringRecord objects that holds the records
+    /// 
+    /// # Returns
+    /// 
+    /// Nothing, or an error if a record cannot be written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// csv_io.write_records(records)?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// This method does not write the records to the file immediately.
+    /// Use the `flush` method to write the records to the file.
+    /// 
+    /// # See
+    /// 
+    /// * `flush` - Writes the records to the file
+    ///
+    pub fn write_records(&mut self, records: Vec<StringRecord>) -> Result<(), DataIoError> {
+        for record in records {
+            self.write_record(record)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the format of the numbers written to the file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `float_format` - The format of the numbers
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// csv_io.set_float_format(FloatFormat { normalize_negative_zero: true, ..FloatFormat::fixed(4) });
+    /// psth.to_csv(&mut csv_io)?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The format is used by every writer of the crate that takes a CsvIO object, such as the
+    /// `to_csv` methods of result structs. Records written with `write_record` are written as
+    /// they are. It has no effect on a file opened for reading.
+    /// 
+    pub fn set_float_format(&mut self, float_format: FloatFormat) {
+        if let Some(writer) = &mut self.writer {
+            writer.set_float_format(float_format);
+        }
+    }
+
+    /// Returns the format of the numbers written to the file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A reference to the CsvIO object
+    /// 
+    /// # Returns
+    /// 
+    /// The FloatFormat, `FloatFormat::default()` unless `set_float_format` was called on a
+    /// file opened for writing
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let format = csv_io.float_format();
+    /// ```
+    /// 
+    pub fn float_format(&self) -> FloatFormat {
+        self.writer.as_ref().map_or_else(FloatFormat::default, CsvWriter::float_format)
+    }
+
+    /// saves all the changes to the file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// 
+    /// # Returns
+    /// 
+    /// Nothing, or an error if the changes cannot be written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// csv_io.save()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// This method writes all the changes to the actual file on disk. A compressed file is
+    /// only complete once its stream is finished by `finish` or by dropping the CsvIO object.
+    /// 
+    pub fn save(&mut self) -> Result<(), DataIoError> {
+        Ok(self.writer_mut()?.flush()?)
+    }
+
+    /// Saves all the changes and finishes the compressed stream of the file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - The CsvIO object, consumed
+    /// 
+    /// # Returns
+    /// 
+    /// Nothing, or an error if the changes cannot be written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut csv_io = CsvIO::open_write("psth.csv.zst")?;
+    /// psth.to_csv(&mut csv_io)?;
+    /// csv_io.finish()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// Dropping the CsvIO object also finishes the file but ignores errors. A file opened for
+    /// reading is left as it is.
+    /// 
+    pub fn finish(self) -> Result<(), DataIoError> {
+        match self.writer {
+            Some(writer) => Ok(writer.finish()?),
+            None => Ok(()),
+        }
+    }
+
+    /// Closes the file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// csv_io.close();
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// This method closes the file and frees up resources
+    /// 
+    pub fn close(&mut self) {
+        self.is_open = false;
+    }
+
+    /// Checks the regularity of the timestamps in a time column
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `column` - The header of the column that holds the timestamps in seconds
+    /// * `tolerance_fraction` - The tolerated deviation from the nominal sampling interval, as a fraction of it
+    /// 
+    /// # Returns
+    /// 
+    /// A TimingReport object that describes the nominal rate, gaps and overlaps, or an error
+    /// if the column is not found, a time is not a number, or the records cannot be read
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let report = csv_io.validate_time_column("time", 0.1)?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// This method consumes the remaining records of the reader
+    /// 
+    /// # See
+    /// 
+    /// * `processing::timing::validate_timing` - Validates timestamps that are already in memory
+    /// 
+    pub fn validate_time_column(&mut self, column: &str, tolerance_fraction: f64) -> Result<TimingReport, DataIoError> {
+        let index = self.reader_mut()?.headers().iter().position(|header| header == column).ok_or_else(|| DataIoError::ColumnNotFound(column.to_string()))?;
+        let mut times: Vec<f64> = Vec::new();
+        for record in self.reader_mut()?.records() {
+            let record = record?;
+            let field = record.get(index).unwrap_or("");
+            let time = field.trim().parse().map_err(|_| DataIoError::Parse {
+                line: record.position().map_or(0, |position| position.line()),
+                column: column.to_string(),
+                value: field.to_string(),
+            })?;
+            times.push(time);
+        }
+        Ok(validate_timing(&times, tolerance_fraction))
+    }
+
+    /// Computes the statistics of every column in one pass
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `template` - The empty StreamingStats copied for every column, with its compression and histogram bins
+    /// 
+    /// # Returns
+    /// 
+    /// A StatsTable with one row per header, or an error if the template already holds values
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let table = csv_io.column_stats(&StreamingStats::new())?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// This method consumes the remaining records of the reader, holding only a block of them
+    /// in memory at a time, and reads them as `read_columns` does. Fields that are not numbers
+    /// are counted as missing.
+    /// 
+    pub fn column_stats(&mut self, template: &StreamingStats) -> Result<StatsTable, DataIoError> {
+        const BLOCK_SIZE: usize = 65536;
+        let names: Vec<String> = self.reader_mut()?.headers().iter().map(|header| header.to_string()).collect();
+        let mut table = StatsTable::new(&names, template)?;
+        let mut block: Vec<Vec<f64>> = vec![Vec::with_capacity(BLOCK_SIZE); names.len()];
+        self.reader_mut()?.for_each_row(|_, fields| -> Result<(), DataIoError> {
+            for (column, value) in block.iter_mut().enumerate() {
+                value.push(fields.get(column).and_then(|field| parse_field(field)).unwrap_or(f64::NAN));
+            }
+            if block[0].len() == BLOCK_SIZE {
+                table.update(&block)?;
+                block.iter_mut().for_each(|column| column.clear());
+            }
+            Ok(())
+        })?;
+        table.update(&block)?;
+        Ok(table)
+    }
+
+    /// Computes the running statistics of a column per combination of key columns
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `keys` - The columns whose values define the groups, e.g. `["subject", "condition", "channel"]`
+    /// * `value_column` - The column the statistics are computed from
+    /// * `template` - The empty StreamingStats copied for every group, with its compression and histogram bins
+    /// * `max_groups` - The largest number of groups held in memory
+    /// 
+    /// # Returns
+    /// 
+    /// The GroupStats of every group in order of first appearance, or an error if a column is
+    /// not found, the template already holds values, or there are more than `max_groups` groups
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let groups = csv_io.grouped_stats(&["subject", "condition", "channel"], "amplitude", &StreamingStats::new(), 100_000)?;
+    /// groups_to_csv(&groups, &["subject", "condition", "channel"], &mut output)?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// This method consumes the remaining records of the reader in one pass, holding one
+    /// StreamingStats per group, so the memory used grows with the number of groups and not
+    /// with the number of records. Values that are not numbers are counted as missing in
+    /// their group. The statistics of several files can be combined with `StreamingStats::merge`.
+    /// 
+    /// # See
+    /// 
+    /// * `CsvReader::grouped_stats` - Computes the statistics of the records of a reader
+    /// 
+    pub fn grouped_stats(&mut self, keys: &[&str], value_column: &str, template: &StreamingStats, max_groups: usize) -> Result<Vec<GroupStats>, DataIoError> {
+        Ok(self.reader_mut()?.grouped_stats(keys, value_column, template, max_groups)?)
+    }
+
+    /// Computes the median, MAD, quartiles and trimmed mean of every column
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `trim_fraction` - The fraction trimmed from each end for the trimmed means, in `[0, 0.5)`
+    /// 
+    /// # Returns
+    /// 
+    /// A RobustStatsTable with one row per header, or an error if the fraction is outside `[0, 0.5)`
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let table = csv_io.robust_column_stats(0.1)?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// This is the robust mode of `column_stats`: the statistics are exact rather than
+    /// sketched, so every value of every column is held in memory until the last record is
+    /// read. Fields that are not numbers are counted as missing. A MemoryBudgetExceeded
+    /// error is returned when the values would exceed the memory budget of the reader.
+    /// 
+    pub fn robust_column_stats(&mut self, trim_fraction: f64) -> Result<RobustStatsTable, DataIoError> {
+        let names: Vec<String> = self.reader_mut()?.headers().iter().map(|header| header.to_string()).collect();
+        let mut columns: Vec<Vec<f64>> = vec![Vec::new(); names.len()];
+        let budget = self.reader_mut()?.memory_budget();
+        let row_bytes = names.len() * size_of::<f64>();
+        let mut needed = 0usize;
+        for record in self.reader_mut()?.records() {
+            let record = record?;
+            needed += row_bytes;
+            budget.check(needed).map_err(|error| ProcessingError::MemoryBudgetExceeded { needed: error.needed, budget: error.budget })?;
+            for (column, values) in columns.iter_mut().enumerate() {
+                values.push(record.get(column).and_then(|field| field.trim().parse().ok()).unwrap_or(f64::NAN));
+            }
+        }
+        Ok(robust_stats(&columns, &names, trim_fraction)?)
+    }
+
+    /// Reshapes the remaining records from wide to long format
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `id_columns` - The columns repeated on every output row, e.g. `time`
+    /// * `value_columns` - The columns turned into rows, or all columns that are not id columns if empty
+    /// * `var_name` - The name of the output column holding the name of the value column
+    /// * `value_name` - The name of the output column holding the value
+    /// * `output` - The writer of the long csv file
+    /// 
+    /// # Returns
+    /// 
+    /// The number of rows written after the header row, or an error if a column is not found
+    /// or the records cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// csv_io.melt(&["time"], &[], "channel", "value", &mut output)?;
+    /// ```
+    /// 
+    /// # See
+    /// 
+    /// * `CsvReader::melt` - Reshapes the records of a reader
+    /// 
+    pub fn melt(&mut self, id_columns: &[&str], value_columns: &[&str], var_name: &str, value_name: &str, output: &mut CsvWriter) -> Result<usize, DataIoError> {
+        Ok(self.reader_mut()?.melt(id_columns, value_columns, var_name, value_name, output)?)
+    }
+
+    /// Reshapes the remaining records from long to wide format
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `index` - The column whose distinct values become the output rows
+    /// * `columns` - The column whose distinct values become the output columns
+    /// * `values` - The column holding the values of the cells
+    /// * `output` - The writer of the wide csv file
+    /// * `agg` - How the values of repeated index and column pairs are combined, or None if pairs must be unique
+    /// 
+    /// # Returns
+    /// 
+    /// The number of rows written after the header row, or an error if a column is not found,
+    /// a pair repeats without an aggregation, a value to aggregate is not a number or the
+    /// records cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// csv_io.pivot("time", "channel", "value", &mut output, None)?;
+    /// ```
+    /// 
+    /// # See
+    /// 
+    /// * `CsvReader::pivot` - Reshapes the records of a reader
+    /// 
+    pub fn pivot(&mut self, index: &str, columns: &str, values: &str, output: &mut CsvWriter, agg: Option<Agg>) -> Result<usize, DataIoError> {
+        Ok(self.reader_mut()?.pivot(index, columns, values, output, agg)?)
+    }
+
+    /// Sets the memory budget of the operations of the CsvIO object
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - The CsvIO object, consumed
+    /// * `budget` - The budget used instead of `MemoryBudget::global`
+    /// 
+    /// # Returns
+    /// 
+    /// The CsvIO object with the budget
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut csv_io = CsvIO::new("amplitudes.csv")?.with_memory_budget(MemoryBudget::new(64 << 20));
+    /// ```
+    /// 
+    /// # See
+    /// 
+    /// * `CsvReader::with_memory_budget` - Sets the memory budget of a reader
+    /// 
+    pub fn with_memory_budget(mut self, budget: MemoryBudget) -> Self {
+        self.reader = self.reader.map(|reader| reader.with_memory_budget(budget));
+        self
+    }
+
+    /// Writes the remaining records sorted by some columns
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `columns` - The columns compared, the first one first
+    /// * `numeric` - Whether fields are compared as numbers
+    /// * `output` - The writer of the sorted csv file
+    /// 
+    /// # Returns
+    /// 
+    /// The SortSummary, or an error if a column is not found or the records or the temporary
+    /// files cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// csv_io.sort(&["time"], true, &mut output)?;
+    /// ```
+    /// 
+    /// # See
+    /// 
+    /// * `CsvReader::sort` - Sorts the records of a reader, spilling to disk beyond its memory budget
+    /// 
+    pub fn sort(&mut self, columns: &[&str], numeric: bool, output: &mut CsvWriter) -> Result<SortSummary, DataIoError> {
+        Ok(self.reader_mut()?.sort(columns, numeric, output)?)
+    }
+
+    /// Copies the remaining records with the values of some columns replaced by integer codes
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `columns` - The columns encoded
+    /// * `order` - Whether the codes follow the first appearance or the byte order of the values
+    /// * `output` - The writer of the encoded csv file
+    /// * `mapping_output` - The writer of the `column,code,value` dictionaries
+    /// 
+    /// # Returns
+    /// 
+    /// The CategoricalMapping of the codes, or an error if a column is not found or the
+    /// records cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// csv_io.encode_categorical(&["condition"], CodeOrder::FirstAppearance, &mut output, &mut mapping_output)?;
+    /// ```
+    /// 
+    /// # See
+    /// 
+    /// * `CsvReader::encode_categorical` - Encodes the records of a reader
+    /// 
+    pub fn encode_categorical(&mut self, columns: &[&str], order: CodeOrder, output: &mut CsvWriter, mapping_output: &mut CsvWriter) -> Result<CategoricalMapping, DataIoError> {
+        Ok(self.reader_mut()?.encode_categorical(columns, order, output, mapping_output)?)
+    }
+
+    /// Copies the remaining records with the codes of some columns replaced by their values
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `mapping` - The dictionaries of the encoded columns
+    /// * `output` - The writer of the decoded csv file
+    /// 
+    /// # Returns
+    /// 
+    /// The number of rows written after the header row, or an error if a code is not in the
+    /// mapping or the records cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// csv_io.decode_categorical(&mapping, &mut output)?;
+    /// ```
+    /// 
+    /// # See
+    /// 
+    /// * `CsvReader::decode_categorical` - Decodes the records of a reader
+    /// 
+    pub fn decode_categorical(&mut self, mapping: &CategoricalMapping, output: &mut CsvWriter) -> Result<usize, DataIoError> {
+        Ok(self.reader_mut()?.decode_categorical(mapping, output)?)
+    }
+
+    /// Counts the values of a column over the remaining records
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `column` - The column counted
+    /// * `top_k` - The number of most frequent values listed, or None for all
+    /// 
+    /// # Returns
+    /// 
+    /// The ValueCounts, or an error if the column is not found or the records cannot be read
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let counts = csv_io.value_counts("condition", None)?;
+    /// ```
+    /// 
+    /// # See
+    /// 
+    /// * `CsvReader::value_counts` - Counts the values of the records of a reader
+    /// 
+    pub fn value_counts(&mut self, column: &str, top_k: Option<usize>) -> Result<ValueCounts, DataIoError> {
+        Ok(self.reader_mut()?.value_counts(column, top_k)?)
+    }
+
+    /// Reads the columns under their canonical names
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `aliases` - The table of the names the canonical columns may appear under
+    /// 
+    /// # Returns
+    /// 
+    /// Nothing, or an error naming the headers if the aliases of the file are ambiguous
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// csv_io.set_column_aliases(&ColumnAliases::new(&[("time", &["Time", "time_s", "timestamp", "t"])]))?;
+    /// ```
+    /// 
+    /// # See
+    /// 
+    /// * `CsvReader::with_column_aliases` - Renames the headers of a reader to their canonical names
+    /// 
+    pub fn set_column_aliases(&mut self, aliases: &ColumnAliases) -> Result<(), DataIoError> {
+        Ok(self.reader_mut()?.apply_column_aliases(aliases)?)
+    }
+
+    /// Returns the renames applied to the headers
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A reference to the CsvIO object
+    /// 
+    /// # Returns
+    /// 
+    /// The ColumnMapping, or None if no aliases were set
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// println!("{:?}", csv_io.column_mapping());
+    /// ```
+    /// 
+    /// # See
+    /// 
+    /// * `CsvReader::column_mapping` - Returns the renames applied to the headers of a reader
+    /// 
+    pub fn column_mapping(&self) -> Option<&ColumnMapping> {
+        self.reader.as_ref().and_then(CsvReader::column_mapping)
+    }
+
+    /// Copies the remaining records with the extreme values of some columns capped at their quantiles
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `columns` - The columns winsorized
+    /// * `lower_q` - The quantile the smaller values are raised to, e.g. 0.01
+    /// * `upper_q` - The quantile the larger values are lowered to, e.g. 0.99
+    /// * `output` - The writer of the winsorized csv file
+    /// * `options` - Whether the quantiles are exact and whether flag columns are written
+    /// 
+    /// # Returns
+    /// 
+    /// The WinsorizeReport, or an error if a column is not found or listed twice, the quantiles are invalid,
+    /// or the records cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let report = csv_io.winsorize_columns(&["reaction_time", "velocity"], 0.01, 0.99, &mut output, &WinsorizeOptions::new())?;
+    /// ```
+    /// 
+    /// # See
+    /// 
+    /// * `CsvReader::winsorize_columns` - Winsorizes the records of a reader
+    /// 
+    pub fn winsorize_columns(&mut self, columns: &[&str], lower_q: f64, upper_q: f64, output: &mut CsvWriter, options: &WinsorizeOptions) -> Result<WinsorizeReport, DataIoError> {
+        Ok(self.reader_mut()?.winsorize_columns(columns, lower_q, upper_q, output, options)?)
+    }
+
+    /// Adds a rolling aggregate of a column to `copy_transformed`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - The CsvIO object, consumed
+    /// * `source_column` - The column aggregated
+    /// * `window` - The rows the aggregate covers
+    /// * `agg` - The aggregate
+    /// * `new_column_name` - The name of the new column
+    /// 
+    /// # Returns
+    /// 
+    /// The CsvIO object with the rolling column
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut csv_io = CsvIO::new("trials.csv")?
+    ///     .with_rolling_column("rt", RollingWindow::Rows(10), Agg::Mean, "rt_mean10")
+    ///     .with_rolling_column("correct", RollingWindow::Cumulative, Agg::Sum, "n_correct");
+    /// csv_io.copy_transformed(&mut output)?;
+    /// ```
+    /// 
+    /// # See
+    /// 
+    /// * `CsvReader::with_rolling_column` - Adds a rolling column to a reader
+    /// 
+    pub fn with_rolling_column(mut self, source_column: &str, window: RollingWindow, agg: Agg, new_column_name: &str) -> Self {
+        self.reader = self.reader.map(|reader| reader.with_rolling_column(source_column, window, agg, new_column_name));
+        self
+    }
+
+    /// Adds a rolling column to `copy_transformed`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - The CsvIO object, consumed
+    /// * `column` - The rolling column, with its start setting
+    /// 
+    /// # Returns
+    /// 
+    /// The CsvIO object with the rolling column
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let column = RollingColumn::new("rt", RollingWindow::Rows(10), Agg::Mean, "rt_mean10").start(RollingStart::Nan);
+    /// let mut csv_io = CsvIO::new("trials.csv")?.with_rolling(column);
+    /// ```
+    /// 
+    pub fn with_rolling(mut self, column: RollingColumn) -> Self {
+        self.reader = self.reader.map(|reader| reader.with_rolling(column));
+        self
+    }
+
+    /// Copies the remaining records with the rolling columns appended
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `output` - The writer of the transformed csv file
+    /// 
+    /// # Returns
+    /// 
+    /// The number of rows written after the header row, or an error if a column is not found,
+    /// a new column name is taken, a value is not a number or the records cannot be read or
+    /// written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// csv_io.copy_transformed(&mut output)?;
+    /// ```
+    /// 
+    /// # See
+    /// 
+    /// * `CsvReader::copy_transformed` - Copies the records of a reader
+    /// 
+    pub fn copy_transformed(&mut self, output: &mut CsvWriter) -> Result<usize, DataIoError> {
+        Ok(self.reader_mut()?.copy_transformed(output)?)
+    }
+
+    /// Copies the remaining records with the values of some columns replaced by keyed tokens
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `columns` - The columns pseudonymized, e.g. `subject`
+    /// * `key` - The secret key of the tokens
+    /// * `output` - The writer of the pseudonymized csv file
+    /// 
+    /// # Returns
+    /// 
+    /// The PseudonymLookup of the values replaced, or an error if a column is not found or
+    /// the records cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let lookup = csv_io.pseudonymize(&["subject"], &key, &mut output)?;
+    /// ```
+    /// 
+    /// # See
+    /// 
+    /// * `CsvReader::pseudonymize` - Pseudonymizes the records of a reader
+    /// 
+    pub fn pseudonymize(&mut self, columns: &[&str], key: &PseudonymKey, output: &mut CsvWriter) -> Result<PseudonymLookup, DataIoError> {
+        Ok(self.reader_mut()?.pseudonymize(columns, key, output)?)
+    }
+
+    /// Copies the remaining records with the dates of some columns shifted by a keyed offset per subject
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `columns` - The date columns shifted
+    /// * `subject_column` - The column whose value picks the offset of a row
+    /// * `key` - The secret key of the offsets
+    /// * `output` - The writer of the shifted csv file
+    /// 
+    /// # Returns
+    /// 
+    /// The number of rows written after the header row, or an error if a column is not
+    /// found, a row has no subject, a date is not valid or the records cannot be read or
+    /// written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// csv_io.shift_dates(&["visit_date"], "subject", &key, &mut output)?;
+    /// ```
+    /// 
+    /// # See
+    /// 
+    /// * `CsvReader::shift_dates` - Shifts the dates of the records of a reader
+    /// 
+    pub fn shift_dates(&mut self, columns: &[&str], subject_column: &str, key: &PseudonymKey, output: &mut CsvWriter) -> Result<usize, DataIoError> {
+        Ok(self.reader_mut()?.shift_dates(columns, subject_column, key, output)?)
+    }
+
+    /// Copies the remaining records with the gains and offsets of a calibration applied
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `calibration` - The corrections of the channels
+    /// * `output` - The writer of the corrected csv file
+    /// * `session` - The metadata of the recording, where the calibration and the units are recorded
+    /// * `force` - Applies the calibration even if the session records it as applied already
+    /// 
+    /// # Returns
+    /// 
+    /// The CalibrationReport, or an error if the calibration was applied before and `force`
+    /// is false, a value to correct is not a number or the records cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let report = csv_io.apply_calibration_copy(&calibration, &mut output, &mut session, false)?;
+    /// ```
+    /// 
+    /// # See
+    /// 
+    /// * `CsvReader::apply_calibration_copy` - Calibrates the records of a reader
+    /// 
+    pub fn apply_calibration_copy(&mut self, calibration: &Calibration, output: &mut CsvWriter, session: &mut SessionInfo, force: bool) -> Result<CalibrationReport, DataIoError> {
+        Ok(self.reader_mut()?.apply_calibration_copy(calibration, output, session, force)?)
+    }
+}
+
+/// How `CsvIO::pivot` combines the values of repeated index and column pairs
+/// 
+/// # Arguments
+/// 
+/// * `First` - Keeps the first value
+/// * `Last` - Keeps the last value
+/// * `Count` - Counts the values
+/// * `Sum` - Adds the values up
+/// * `Mean` - Averages the values
+/// * `Std` - Takes the sample standard deviation of the values, NaN for a single value
+/// * `Min` - Keeps the smallest value
+/// * `Max` - Keeps the largest value
+/// 
+/// # Examples
+/// 
+/// ```
+/// csv_io.pivot("trial", "channel", "rms", &mut output, Some(Agg::Mean))?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Agg {
+    First,
+    Last,
+    Count,
+    Sum,
+    Mean,
+    Std,
+    Min,
+    Max,
+}
+
+/// The values of a cell of a pivot table combined so far
+enum AggCell {
+    Text(String),
+    Numbers { count: usize, sum: f64, min: f64, max: f64, m2: f64 },
+}
+
+impl AggCell {
+    /// Starts a cell with its first value
+    fn new(agg: Option<Agg>, value: &str) -> io::Result<Self> {
+        match agg {
+            None | Some(Agg::First) | Some(Agg::Last) => Ok(AggCell::Text(value.to_string())),
+            Some(Agg::Count) => Ok(AggCell::Numbers { count: 1, sum: 0.0, min: 0.0, max: 0.0, m2: 0.0 }),
+            Some(_) => {
+                let number = parse_number(value)?;
+                Ok(AggCell::Numbers { count: 1, sum: number, min: number, max: number, m2: 0.0 })
+            }
+        }
+    }
+
+    /// Combines the next value of the cell
+    fn add(&mut self, agg: Agg, value: &str) -> io::Result<()> {
+        match self {
+            AggCell::Text(text) if agg == Agg::Last => *text = value.to_string(),
+            AggCell::Text(_) => {}
+            AggCell::Numbers { count, .. } if agg == Agg::Count => *count += 1,
+            AggCell::Numbers { count, sum, min, max, m2 } => {
+                let number = parse_number(value)?;
+                // Welford's update of the squared deviations from the mean
+                let delta = number - *sum / *count as f64;
+                *count += 1;
+                *sum += number;
+                *m2 += delta * (number - *sum / *count as f64);
+                *min = min.min(number);
+                *max = max.max(number);
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the combined value of the cell
+    fn value(&self, agg: Option<Agg>, float_format: &FloatFormat) -> String {
+        match (self, agg) {
+            (AggCell::Text(text), _) => text.clone(),
+            (AggCell::Numbers { count, .. }, Some(Agg::Count)) => count.to_string(),
+            (AggCell::Numbers { sum, .. }, Some(Agg::Sum)) => float_format.format(*sum),
+            (AggCell::Numbers { count, sum, .. }, Some(Agg::Mean)) => float_format.format(sum / *count as f64),
+            (AggCell::Numbers { count: 1, .. }, Some(Agg::Std)) => float_format.format(f64::NAN),
+            (AggCell::Numbers { count, m2, .. }, Some(Agg::Std)) => float_format.format((m2 / (*count - 1) as f64).sqrt()),
+            (AggCell::Numbers { min, .. }, Some(Agg::Min)) => float_format.format(*min),
+            (AggCell::Numbers { max, .. }, _) => float_format.format(*max),
+        }
+    }
+}
+
+/// Parses a value to aggregate
+pub(crate) fn parse_number(value: &str) -> io::Result<f64> {
+    value
+        .trim()
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Cannot aggregate '{}', which is not a number", value)))
+}
+
+/// Returns the position of a column in the headers
+pub(crate) fn column_index(headers: &StringRecord, name: &str) -> io::Result<usize> {
+    headers
+        .iter()
+        .position(|header| header == name)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Column '{}' not found", name)))
+}
+
+/// Implementation of the serde deserialization of the CsvIO class
+/// 
+/// # Methods
+/// 
+/// * `read_into` - Reads the remaining records into a type that can be deserialized
+#[cfg(feature = "serde")]
+impl CsvIO {
+    /// Reads the remaining records into a type that can be deserialized
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// 
+    /// # Returns
+    /// 
+    /// One value per record, with the fields matched to the headers by name, or a `Parse`
+    /// error naming the line and column of a field that cannot be deserialized, or an error
+    /// if a record cannot be read or deserialized
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// #[derive(serde::Deserialize)]
+    /// struct Trial { trial: u32, condition: String, reaction_time: Option<f64> }
+    /// 
+    /// let trials: Vec<Trial> = csv_io.read_into()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The headers are the ones the reader reads under, so columns renamed by
+    /// `set_column_aliases` are matched by their canonical names. This method consumes the
+    /// remaining records of the reader.
+    /// 
+    pub fn read_into<T: serde::de::DeserializeOwned>(&mut self) -> Result<Vec<T>, DataIoError> {
+        let reader = self.reader_mut()?;
+        let headers = reader.headers().clone();
+        let mut rows = Vec::new();
+        for record in reader.records() {
+            let record = record?;
+            rows.push(record.deserialize(Some(&headers)).map_err(|error| deserialize_error(error, &headers, &record))?);
+        }
+        Ok(rows)
+    }
+}
+
+/// Turns an error deserializing a field of a record into a Parse error naming its line and column
+#[cfg(feature = "serde")]
+fn deserialize_error(error: csv::Error, headers: &StringRecord, record: &StringRecord) -> DataIoError {
+    if let csv::ErrorKind::Deserialize { pos: Some(position), err } = error.kind() {
+        if let Some(field) = err.field() {
+            return DataIoError::Parse {
+                line: position.line(),
+                column: headers.get(field as usize).unwrap_or("").to_string(),
+                value: record.get(field as usize).unwrap_or("").to_string(),
+            };
+        }
+    }
+    DataIoError::Csv(error)
+}
+
+/// Implementation of the Polars conversions of the CsvIO class
+/// 
+/// # Methods
+/// 
+/// * `to_dataframe` - Reads the remaining records into a DataFrame
+/// * `write_dataframe` - Writes a DataFrame as a header row and records
+#[cfg(feature = "polars")]
+impl CsvIO {
+    /// Reads the remaining records into a DataFrame
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// 
+    /// # Returns
+    /// 
+    /// A DataFrame with one column per header, or an error if two headers are equal or a
+    /// record cannot be read
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let df = csv_io.to_dataframe()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The dtype of each column is inferred from its values: Int64 if every value parses as
+    /// an integer, otherwise Float64 if every value parses as a number, otherwise Boolean if
+    /// every value is `true` or `false`, otherwise String. Empty fields become nulls and are
+    /// ignored by the inference. This method consumes the remaining records of the reader.
+    /// 
+    pub fn to_dataframe(&mut self) -> PolarsResult<DataFrame> {
+        let reader = self.reader_mut().map_err(|error| PolarsError::ComputeError(error.to_string().into()))?;
+        let headers = reader.headers().clone();
+        let names: Vec<&str> = headers.iter().collect();
+        check_unique_names(&names)?;
+        let mut fields: Vec<Vec<String>> = vec![Vec::new(); names.len()];
+        for record in reader.records() {
+            let record = record.map_err(|error| PolarsError::ComputeError(format!("Error reading record: {}", error).into()))?;
+            for (column, field) in fields.iter_mut().zip(record.iter()) {
+                column.push(field.to_string());
+            }
+        }
+        let columns = names.iter().zip(&fields).map(|(name, values)| column_from_fields(name, values)).collect();
+        DataFrame::new(columns)
+    }
+
+    /// Writes a DataFrame as a header row and records
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - A mutable reference to the CsvIO object
+    /// * `df` - The DataFrame to write
+    /// 
+    /// # Returns
+    /// 
+    /// Nothing, or an error if a column has a dtype other than a boolean, integer, float or string
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// csv_io.write_dataframe(&df)?;
+    /// csv_io.save()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// Nulls are written as empty fields, so `to_dataframe` reads them back as nulls. The
+    /// records are not written to the file until `save` is called.
+    /// 
+    pub fn write_dataframe(&mut self, df: &DataFrame) -> PolarsResult<()> {
+        let float_format = self.float_format();
+        let columns: Vec<Vec<String>> = df.get_columns().iter().map(|column| column_to_fields(column, &float_format)).collect::<PolarsResult<_>>()?;
+        let header: Vec<&str> = df.get_columns().iter().map(|column| column.name().as_str()).collect();
+        let write_error = |error: DataIoError| PolarsError::ComputeError(format!("Error writing record: {}", error).into());
+        self.write_record(StringRecord::from(header)).map_err(write_error)?;
+        for row in 0..df.height() {
+            self.write_record(columns.iter().map(|column| column[row].as_str()).collect()).map_err(write_error)?;
+        }
+        Ok(())
+    }
+}
+
+
+/// Implementation of the HTTP opener of the CsvIO class
+/// 
+/// # Methods
+/// 
+/// * `open_url` - Downloads a csv file and opens it
+#[cfg(feature = "http")]
+impl CsvIO {
+    /// Downloads a csv file and opens it
+    /// 
+    /// # Arguments
+    /// 
+    /// * `url` - The HTTP or HTTPS URL of the csv file
+    /// * `options` - The authentication, spilling, retries and progress callback of the download
+    /// 
+    /// # Returns
+    /// 
+    /// A CsvIO object reading the downloaded file, or an error if spilling is not allowed,
+    /// the download fails or the file has no header row
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut options = HttpOptions::new().bearer_token(&token).allow_spill(true);
+    /// let mut csv_io = CsvIO::open_url("https://files.osf.io/v1/resources/abc12/providers/osfstorage/lfp.csv", &mut options)?;
+    /// let records = csv_io.read_records()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// A CsvIO object reads from a local file, so the response is spilled to a file and
+    /// `allow_spill(true)` is required; without it an `Unsupported` error is returned, and
+    /// `HttpReader` streams the response without touching the disk. By default the file is
+    /// a temporary one, opened in `Read` mode and removed once the CsvIO object and the
+    /// clones of its reader are dropped, or if the download fails. A file set with
+    /// `spill_path` is kept and opened in `Append` mode: written records are appended to it
+    /// and never sent back to the server.
+    /// 
+    pub fn open_url(url: &str, options: &mut HttpOptions) -> Result<Self, DataIoError> {
+        if !options.spill_allowed() {
+            return Err(DataIoError::Io(std::io::Error::new(
+                std::io::ErrorKind::Unsupported,
+                "CsvIO reads from a local file; use allow_spill(true) to download the response to a file first, or HttpReader to stream it",
+            )));
+        }
+        let (path, temporary) = options.spill_file();
+        if !temporary {
+            download(url, options, &path)?;
+            return Ok(Self {
+                file_path: path.to_string_lossy().into_owned(),
+                mode: OpenMode::Append,
+                reader: Some(CsvReader::open(&path)?),
+                writer: Some(CsvWriter::append(&path)?),
+                is_open: true,
+            });
+        }
+        let spill = Spill::new(path);
+        download(url, options, spill.path())?;
+        let mut reader = CsvReader::open(spill.path())?;
+        reader.spill = Some(Arc::new(spill));
+        Ok(Self { file_path: reader.file_path.to_string_lossy().into_owned(), mode: OpenMode::Read, reader: Some(reader), writer: None, is_open: true })
+    }
+}
+
+/// A reader of csv files that can be cloned into worker threads
+/// 
+/// # Arguments
+/// 
+/// * `file_path` - The path to the csv file
+/// * `dialect` - The CsvDialect the file is read with, also by its clones
+/// * `spill` - The decompressed copy of a compressed file, shared by all clones and removed after the last one
+/// * `reader` - A csv::Reader object with its own handle to the file
+/// * `headers` - The headers of the csv file, shared by all clones
+/// * `index` - The positions of the records, shared by all clones once built
+/// 
+/// # Examples
+/// 
+/// ```
+/// let mut reader = CsvReader::open("data.csv")?;
+/// let index = reader.build_index()?;
+/// let handles: Vec<_> = (0..8)
+///     .map(|i| {
+///         let mut reader = reader.try_clone()?;
+///         let rows = i * index.len() / 8..(i + 1) * index.len() / 8;
+///         Ok(std::thread::spawn(move || reader.read_rows(rows)))
+///     })
+///     .collect::<std::io::Result<_>>()?;
+/// ```
+/// 
+/// # Note
+/// 
+/// A CsvReader is `Send`, so it can be moved into a thread, but each thread needs its own
+/// clone because reading moves its position. Clones reopen the file, so they never share a
+/// file position, and share the parsed headers and the RowIndex through an `Arc`. There is
+/// no `Clone` implementation, because reopening fails if the file was moved or deleted, so
+/// clones are made with `try_clone`.
+pub struct CsvReader {
+    file_path: PathBuf,
+    dialect: CsvDialect,
+    spill: Option<Arc<Spill>>,
+    reader: Reader<BufReader<Input>>,
+    headers: Arc<StringRecord>,
+    index: Option<Arc<RowIndex>>,
+    rolling: Vec<RollingColumn>,
+    memory_budget: Option<MemoryBudget>,
+    column_mapping: Option<ColumnMapping>,
+}
+
+/// Where a CsvReader reads its records from
+/// 
+/// # Arguments
+/// 
+/// * `File` - A file, which can be sought and opened again by clones
+/// * `Stdin` - The standard input, which can only be read once from start to end
+pub(crate) enum Input {
+    File(File),
+    Stdin(io::Stdin),
+}
+
+impl Read for Input {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Input::File(file) => file.read(buffer),
+            Input::Stdin(stdin) => stdin.read(buffer),
+        }
+    }
+}
+
+impl Seek for Input {
+    fn seek(&mut self, position: SeekFrom) -> io::Result<u64> {
+        match self {
+            Input::File(file) => file.seek(position),
+            Input::Stdin(_) => Err(not_seekable()),
+        }
+    }
+}
+
+/// The error of the operations that need to seek or reopen a reader of the standard input
+fn not_seekable() -> io::Error {
+    io::Error::new(io::ErrorKind::Unsupported, "The standard input can only be read once from start to end, it cannot be sought or reopened")
+}
+
+/// The byte positions of the records of a csv file, for random access to its rows
+/// 
+/// # Arguments
+/// 
+/// * `positions` - The position of every record, followed by the position of the end of the file
+/// 
+/// # Examples
+/// 
+/// ```
+/// let index = reader.build_index()?;
+/// println!("{} rows", index.len());
+/// ```
+/// 
+/// # Note
+/// 
+/// A RowIndex is never modified once built, so it is `Sync` and one copy is shared by any
+/// number of readers in any number of threads
+#[derive(Debug, Clone, PartialEq)]
+pub struct RowIndex {
+    positions: Vec<Position>,
+}
+
+/// Implementation of the RowIndex class
+/// 
+/// # Methods
+/// 
+/// * `len` - Returns the number of rows
+/// * `is_empty` - Checks whether there are no rows
+impl RowIndex {
+    /// Returns the number of rows
+    /// 
+    /// # Returns
+    /// 
+    /// The number of records after the header row
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let n_rows = index.len();
+    /// ```
+    /// 
+    pub fn len(&self) -> usize {
+        self.positions.len() - 1
+    }
+
+    /// Checks whether there are no rows
+    /// 
+    /// # Returns
+    /// 
+    /// `true` if the file holds only a header row
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// if index.is_empty() { return Ok(()); }
+    /// ```
+    /// 
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Implementation of the CsvReader class
+/// 
+/// # Methods
+/// 
+/// * `open` - Opens a csv file and reads its headers
+/// * `open_with_dialect` - Opens a delimited text file of a dialect and reads its headers
+/// * `from_stdin` - Reads csv records from the standard input
+/// * `try_clone` - Opens another reader of the same file at its first record
+/// * `headers` - Returns the headers
+/// * `path` - Returns the path to the csv file
+/// * `read_record` - Reads the next record
+/// * `read_records` - Reads all remaining records
+/// * `build_index` - Builds the RowIndex of the file, or returns the one already built
+/// * `with_index` - Uses a RowIndex built by another reader of the same file
+/// * `index` - Returns the RowIndex, if built
+/// * `seek_row` - Moves to a row
+/// * `read_rows` - Reads a range of rows
+/// * `melt` - Reshapes the remaining records from wide to long format
+/// * `pivot` - Reshapes the remaining records from long to wide format
+/// * `with_rolling_column` - Adds a rolling aggregate of a column to `copy_transformed`
+/// * `with_rolling` - Adds a rolling column to `copy_transformed`
+/// * `copy_transformed` - Copies the remaining records with the rolling columns appended
+/// * `pseudonymize` - Copies the remaining records with the values of some columns replaced by keyed tokens
+/// * `shift_dates` - Copies the remaining records with the dates of some columns shifted by a keyed offset per subject
+/// * `apply_calibration_copy` - Copies the remaining records with the gains and offsets of a calibration applied
+/// * `grouped_stats` - Computes the running statistics of a column per combination of key columns
+/// * `with_memory_budget` - Sets the memory budget of the operations of the reader
+/// * `memory_budget` - Returns the memory budget of the operations of the reader
+/// * `sort` - Writes the remaining records sorted by some columns
+/// * `encode_categorical` - Copies the remaining records with the values of some columns replaced by integer codes
+/// * `decode_categorical` - Copies the remaining records with the codes of some columns replaced by their values
+/// * `value_counts` - Counts the values of a column over the remaining records
+/// * `with_column_aliases` - Reads the columns under their canonical names
+/// * `column_mapping` - Returns the renames applied to the headers
+/// * `winsorize_columns` - Copies the remaining records with the extreme values of some columns capped at their quantiles
+impl CsvReader {
+    /// Opens a csv file and reads its headers
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file_path` - The path to the csv file
+    /// 
+    /// # Returns
+    /// 
+    /// A CsvReader at the first record, or an error if the file cannot be opened or its
+    /// header row cannot be read
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let reader = CsvReader::open("data.csv")?;
+    /// ```
+    /// 
+    pub fn open<P: AsRef<Path>>(file_path: P) -> io::Result<Self> {
+        Self::open_with_dialect(file_path, &CsvDialect::default())
+    }
+
+    /// Opens a delimited text file of a dialect and reads its headers
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file_path` - The path to the file
+    /// * `dialect` - The delimiter, quote and comment bytes of the file and whether it has a header row
+    /// 
+    /// # Returns
+    /// 
+    /// A CsvReader at the first record, or an error if the file cannot be opened or its
+    /// first record cannot be read
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let reader = CsvReader::open_with_dialect("lfp.tsv", &CsvDialect { has_headers: false, ..CsvDialect::tsv() })?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// Without a header row the columns are named `column_0`, `column_1` and so on, and the
+    /// first record is read as values
+    /// 
+    pub fn open_with_dialect<P: AsRef<Path>>(file_path: P, dialect: &CsvDialect) -> io::Result<Self> {
+        let file_path = file_path.as_ref().to_path_buf();
+        let spill = match dialect.compression_of(&file_path) {
+            Compression::None => None,
+            compression => Some(Arc::new(decompress(&file_path, compression)?)),
+        };
+        let data_path = spill.as_ref().map_or(file_path.as_path(), |spill| spill.path());
+        let mut reader = dialect.reader_builder().from_reader(BufReader::new(Input::File(File::open(data_path)?)));
+        let headers = Arc::new(dialect.headers(reader.headers().map_err(io::Error::from)?));
+        Ok(Self { file_path, dialect: *dialect, spill, reader, headers, index: None, rolling: Vec::new(), memory_budget: None, column_mapping: None })
+    }
+
+    /// Reads csv records from the standard input
+    /// 
+    /// # Returns
+    /// 
+    /// A CsvReader at the first record, with `-` as its path, or an error if the header row
+    /// cannot be read
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// // cat recording.csv | my_filter
+    /// let mut reader = CsvReader::from_stdin()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The operations that stream the remaining records work as on a file. The ones that
+    /// seek or open the file again, `try_clone`, `build_index`, `seek_row` and `read_rows`,
+    /// and those built on them, return an `Unsupported` error.
+    /// 
+    pub fn from_stdin() -> io::Result<Self> {
+        let dialect = CsvDialect::default();
+        let mut reader = dialect.reader_builder().from_reader(BufReader::new(Input::Stdin(io::stdin())));
+        let headers = Arc::new(dialect.headers(reader.headers().map_err(io::Error::from)?));
+        Ok(Self {
+            file_path: PathBuf::from("-"),
+            dialect,
+            spill: None,
+            reader,
+            headers,
+            index: None,
+            rolling: Vec::new(),
+            memory_budget: None,
+            column_mapping: None,
+        })
+    }
+
+    /// Opens another reader of the same file at its first record
+    /// 
+    /// # Returns
+    /// 
+    /// A CsvReader with its own file handle, sharing the headers and the RowIndex, or an
+    /// error if the file cannot be opened again
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut worker_reader = reader.try_clone()?;
+    /// ```
+    /// 
+    pub fn try_clone(&self) -> io::Result<Self> {
+        let mut reader = self.dialect.reader_builder().from_reader(BufReader::new(Input::File(self.reopen()?)));
+        // The header row is only read to move past it, the parsed copy is shared. Without a
+        // header row the first record is only peeked at and is still read as values
+        reader.byte_headers().map_err(io::Error::from)?;
+        Ok(Self {
+            file_path: self.file_path.clone(),
+            dialect: self.dialect,
+            spill: self.spill.clone(),
+            reader,
+            headers: Arc::clone(&self.headers),
+            index: self.index.clone(),
+            rolling: self.rolling.clone(),
+            memory_budget: self.memory_budget.clone(),
+            column_mapping: self.column_mapping.clone(),
+        })
+    }
+
+    /// Returns the headers
+    /// 
+    /// # Returns
+    /// 
+    /// The header row of the file
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let names: Vec<&str> = reader.headers().iter().collect();
+    /// ```
+    /// 
+    pub fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    /// Returns the path to the csv file
+    /// 
+    /// # Returns
+    /// 
+    /// The path the reader was opened with
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let directory = reader.path().parent();
+    /// ```
+    /// 
+    pub fn path(&self) -> &Path {
+        &self.file_path
+    }
+
+    /// Reads the next record
+    /// 
+    /// # Returns
+    /// 
+    /// The record, None at the end of the file, or an error if the record cannot be read
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// while let Some(record) = reader.read_record()? {
+    ///     println!("{:?}", record);
+    /// }
+    /// ```
+    /// 
+    pub fn read_record(&mut self) -> io::Result<Option<StringRecord>> {
+        let mut record = StringRecord::new();
+        Ok(self.reader.read_record(&mut record).map_err(io::Error::from)?.then_some(record))
+    }
+
+    /// Reads all remaining records
+    /// 
+    /// # Returns
+    /// 
+    /// The records, or an error if a record cannot be read
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let records = reader.read_records()?;
+    /// ```
+    /// 
+    pub fn read_records(&mut self) -> io::Result<Vec<StringRecord>> {
+        self.reader.records().map(|record| record.map_err(io::Error::from)).collect()
+    }
+
+    /// Builds the RowIndex of the file, or returns the one already built
+    /// 
+    /// # Returns
+    /// 
+    /// The RowIndex, or an error if a record cannot be read
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let index = reader.build_index()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The file is scanned once with a separate handle, so the position of this reader does
+    /// not change. Clones made afterwards share the index.
+    /// 
+    pub fn build_index(&mut self) -> io::Result<Arc<RowIndex>> {
+        if let Some(index) = &self.index {
+            return Ok(Arc::clone(index));
+        }
+        let mut reader = self.dialect.reader_builder().from_reader(BufReader::new(self.reopen()?));
+        reader.byte_headers().map_err(io::Error::from)?;
+        let mut positions = Vec::new();
+        let mut record = csv::ByteRecord::new();
+        loop {
+            positions.push(reader.position().clone());
+            if !reader.read_byte_record(&mut record).map_err(io::Error::from)? {
+                break;
+            }
+        }
+        let index = Arc::new(RowIndex { positions });
+        self.index = Some(Arc::clone(&index));
+        Ok(index)
+    }
+
+    /// Uses a RowIndex built by another reader of the same file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `index` - The shared RowIndex
+    /// 
+    /// # Returns
+    /// 
+    /// The CsvReader with the index
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let reader = CsvReader::open("data.csv")?.with_index(Arc::clone(&index));
+    /// ```
+    /// 
+    pub fn with_index(mut self, index: Arc<RowIndex>) -> Self {
+        self.index = Some(index);
+        self
+    }
+
+    /// Returns the RowIndex, if built
+    /// 
+    /// # Returns
+    /// 
+    /// The shared RowIndex, or None if neither `build_index` nor `with_index` was called
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let n_rows = reader.index().map(|index| index.len());
+    /// ```
+    /// 
+    pub fn index(&self) -> Option<&Arc<RowIndex>> {
+        self.index.as_ref()
+    }
+
+    /// Moves to a row
+    /// 
+    /// # Arguments
+    /// 
+    /// * `row` - The index of the record read next, counted from 0 after the header row
+    /// 
+    /// # Returns
+    /// 
+    /// Nothing, or an error if there is no RowIndex, the row is past the end of the file or
+    /// the file cannot be sought
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// reader.seek_row(1000)?;
+    /// let record = reader.read_record()?;
+    /// ```
+    /// 
+    pub fn seek_row(&mut self, row: usize) -> io::Result<()> {
+        let index = self.index.as_ref().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "No row index, call build_index first"))?;
+        let position = index.positions.get(row).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, format!("Row {} is past the {} rows of the file", row, index.len()))
+        })?;
+        self.reader.seek(position.clone()).map_err(io::Error::from)
+    }
+
+    /// Reads a range of rows
+    /// 
+    /// # Arguments
+    /// 
+    /// * `rows` - The indices of the records, counted from 0 after the header row
+    /// 
+    /// # Returns
+    /// 
+    /// The records, or an error as in `seek_row` or if a record cannot be read
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let window = reader.read_rows(30000..60000)?;
+    /// ```
+    /// 
+    pub fn read_rows(&mut self, rows: Range<usize>) -> io::Result<Vec<StringRecord>> {
+        if rows.is_empty() {
+            return Ok(Vec::new());
+        }
+        let n_rows = self.index.as_ref().map_or(0, |index| index.len());
+        if rows.end > n_rows {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Rows up to {} are past the {} rows of the file", rows.end, n_rows)));
+        }
+        self.seek_row(rows.start)?;
+        self.reader.records().take(rows.len()).map(|record| record.map_err(io::Error::from)).collect()
+    }
+
+    /// Reshapes the remaining records from wide to long format
+    /// 
+    /// # Arguments
+    /// 
+    /// * `id_columns` - The columns repeated on every output row, e.g. `time`
+    /// * `value_columns` - The columns turned into rows, or all columns that are not id columns if empty
+    /// * `var_name` - The name of the output column holding the name of the value column
+    /// * `value_name` - The name of the output column holding the value
+    /// * `output` - The writer of the long csv file
+    /// 
+    /// # Returns
+    /// 
+    /// The number of rows written after the header row, or an error if a column is not found
+    /// or the records cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut output = CsvWriter::create("long.csv")?;
+    /// reader.melt(&["trial", "time"], &[], "channel", "value", &mut output)?;
+    /// output.flush()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// Every record is written out as soon as it is read, so files of any size can be melted.
+    /// Each record gives one row per value column, in the order of `value_columns`.
+    /// 
+    pub fn melt(&mut self, id_columns: &[&str], value_columns: &[&str], var_name: &str, value_name: &str, output: &mut CsvWriter) -> io::Result<usize> {
+        let headers = Arc::clone(&self.headers);
+        let ids = id_columns.iter().map(|name| column_index(&headers, name)).collect::<io::Result<Vec<usize>>>()?;
+        let values = if value_columns.is_empty() {
+            (0..headers.len()).filter(|column| !ids.contains(column)).collect()
+        } else {
+            value_columns.iter().map(|name| column_index(&headers, name)).collect::<io::Result<Vec<usize>>>()?
+        };
+
+        let mut row = StringRecord::from(id_columns.to_vec());
+        row.push_field(var_name);
+        row.push_field(value_name);
+        output.write_record(&row)?;
+        let mut count = 0;
+        for record in self.reader.records() {
+            let record = record?;
+            for &column in &values {
+                row.clear();
+                ids.iter().for_each(|&id| row.push_field(record.get(id).unwrap_or("")));
+                row.push_field(&headers[column]);
+                row.push_field(record.get(column).unwrap_or(""));
+                output.write_record(&row)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Reshapes the remaining records from long to wide format
+    /// 
+    /// # Arguments
+    /// 
+    /// * `index` - The column whose distinct values become the output rows, e.g. `time`
+    /// * `columns` - The column whose distinct values become the output columns, e.g. `channel`
+    /// * `values` - The column holding the values of the cells
+    /// * `output` - The writer of the wide csv file
+    /// * `agg` - How the values of repeated index and column pairs are combined, or None if pairs must be unique
+    /// 
+    /// # Returns
+    /// 
+    /// The number of rows written after the header row, or an error if a column is not found,
+    /// a pair repeats without an aggregation, a value to aggregate is not a number or the
+    /// records cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut output = CsvWriter::create("wide.csv")?;
+    /// reader.pivot("time", "channel", "value", &mut output, None)?;
+    /// output.flush()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The rows and columns keep the order in which their values first appear, and cells
+    /// without a value are left empty. All cells are held in memory until the last record
+    /// is read, since any record can add to any row, and an error of kind `OutOfMemory`
+    /// holding a MemoryBudgetExceeded is returned when they would exceed the memory budget.
+    /// 
+    pub fn pivot(&mut self, index: &str, columns: &str, values: &str, output: &mut CsvWriter, agg: Option<Agg>) -> io::Result<usize> {
+        let headers = &self.headers;
+        let (index_column, name_column, value_column) = (column_index(headers, index)?, column_index(headers, columns)?, column_index(headers, values)?);
+        let mut rows: HashMap<String, usize> = HashMap::new();
+        let mut names: HashMap<String, usize> = HashMap::new();
+        let mut row_keys: Vec<String> = Vec::new();
+        let mut name_keys: Vec<String> = Vec::new();
+        let mut cells: HashMap<(usize, usize), AggCell> = HashMap::new();
+        let budget = self.memory_budget();
+        let mut needed = 0usize;
+        for record in self.reader.records() {
+            let record = record?;
+            // Every record can add a cell and a row or column name, which are held until the end
+            needed += record_bytes(&record) + size_of::<((usize, usize), AggCell)>();
+            budget.check(needed)?;
+            let field = |column: usize| record.get(column).unwrap_or("");
+            let row = *rows.entry(field(index_column).to_string()).or_insert_with(|| {
+                row_keys.push(field(index_column).to_string());
+                row_keys.len() - 1
+            });
+            let name = *names.entry(field(name_column).to_string()).or_insert_with(|| {
+                name_keys.push(field(name_column).to_string());
+                name_keys.len() - 1
+            });
+            let value = field(value_column);
+            match (cells.get_mut(&(row, name)), agg) {
+                (Some(_), None) => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("Duplicate entry for {} '{}' and {} '{}'; pass an Agg to combine them", index, row_keys[row], columns, name_keys[name]),
+                    ))
+                }
+                (Some(cell), Some(agg)) => cell.add(agg, value)?,
+                (None, agg) => {
+                    cells.insert((row, name), AggCell::new(agg, value)?);
+                }
+            }
+        }
+
+        let float_format = output.float_format();
+        let mut header = StringRecord::from(vec![index]);
+        name_keys.iter().for_each(|name| header.push_field(name));
+        output.write_record(&header)?;
+        let mut record = StringRecord::new();
+        for (row, key) in row_keys.iter().enumerate() {
+            record.clear();
+            record.push_field(key);
+            for name in 0..name_keys.len() {
+                record.push_field(&cells.get(&(row, name)).map_or_else(String::new, |cell| cell.value(agg, &float_format)));
+            }
+            output.write_record(&record)?;
+        }
+        Ok(row_keys.len())
+    }
+
+    /// Adds a rolling aggregate of a column to `copy_transformed`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `source_column` - The column aggregated
+    /// * `window` - The rows the aggregate covers
+    /// * `agg` - The aggregate
+    /// * `new_column_name` - The name of the new column
+    /// 
+    /// # Returns
+    /// 
+    /// The CsvReader with the rolling column
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let window = RollingWindow::Span { column: "time_s".to_string(), width: 60.0 };
+    /// let mut reader = CsvReader::open("licks.csv")?.with_rolling_column("lick", window, Agg::Sum, "licks_per_minute");
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The aggregate is over partial windows at the start of the file; use `with_rolling`
+    /// with `RollingStart::Nan` to write NaN until the window is full. The columns are
+    /// checked when `copy_transformed` is called.
+    /// 
+    pub fn with_rolling_column(self, source_column: &str, window: RollingWindow, agg: Agg, new_column_name: &str) -> Self {
+        self.with_rolling(RollingColumn::new(source_column, window, agg, new_column_name))
+    }
+
+    /// Adds a rolling column to `copy_transformed`
+    /// 
+    /// # Arguments
+    /// 
+    /// * `column` - The rolling column, with its start setting
+    /// 
+    /// # Returns
+    /// 
+    /// The CsvReader with the rolling column
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let column = RollingColumn::new("rt", RollingWindow::Rows(10), Agg::Std, "rt_std10").start(RollingStart::Nan);
+    /// let mut reader = CsvReader::open("trials.csv")?.with_rolling(column);
+    /// ```
+    /// 
+    pub fn with_rolling(mut self, column: RollingColumn) -> Self {
+        self.rolling.push(column);
+        self
+    }
+
+    /// Copies the remaining records with the rolling columns appended
+    /// 
+    /// # Arguments
+    /// 
+    /// * `output` - The writer of the transformed csv file
+    /// 
+    /// # Returns
+    /// 
+    /// The number of rows written after the header row, or an error if a column is not found,
+    /// a new column name is taken, a window is empty, the key of a span window is not a
+    /// number or decreases, a value is not a number or the records cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut reader = CsvReader::open("trials.csv")?.with_rolling_column("rt", RollingWindow::Rows(10), Agg::Mean, "rt_mean10");
+    /// let mut output = CsvWriter::create("trials_rolling.csv")?;
+    /// reader.copy_transformed(&mut output)?;
+    /// output.flush()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The file is read once and every window ends at the current row. Empty values are
+    /// skipped but still move the window, so `Count` counts the values present; a window
+    /// without values gets an empty field, and `Std` is NaN for a single value. Running
+    /// sums and monotonic deques keep the cost per row constant on average, and only the
+    /// values inside the windows are held in memory.
+    /// 
+    pub fn copy_transformed(&mut self, output: &mut CsvWriter) -> io::Result<usize> {
+        let mut header = self.headers.as_ref().clone();
+        for column in &self.rolling {
+            if header.iter().any(|name| name == column.name) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Column '{}' already exists", column.name)));
+            }
+            header.push_field(&column.name);
+        }
+        let mut states = self.rolling.iter().map(|column| RollingState::new(column, &self.headers)).collect::<io::Result<Vec<_>>>()?;
+        let float_format = output.float_format();
+        output.write_record(&header)?;
+        let mut n_rows = 0;
+        for record in self.reader.records() {
+            let mut record = record?;
+            let fields = states.iter_mut().map(|state| state.update(&record, &float_format)).collect::<io::Result<Vec<_>>>()?;
+            fields.iter().for_each(|field| record.push_field(field));
+            output.write_record(&record)?;
+            n_rows += 1;
+        }
+        Ok(n_rows)
+    }
+
+    /// Copies the remaining records with the values of some columns replaced by keyed tokens
+    /// 
+    /// # Arguments
+    /// 
+    /// * `columns` - The columns pseudonymized, e.g. `subject`
+    /// * `key` - The secret key of the tokens
+    /// * `output` - The writer of the pseudonymized csv file
+    /// 
+    /// # Returns
+    /// 
+    /// The PseudonymLookup of the values replaced, or an error if a column is not found or
+    /// the records cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let key = PseudonymKey::load("study.key")?;
+    /// let mut output = CsvWriter::create("sessions_shared.csv")?;
+    /// CsvReader::open("sessions.csv")?.pseudonymize(&["subject"], &key, &mut output)?;
+    /// output.flush()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// Every value is replaced by `PseudonymKey::token`, a keyed HMAC-SHA256, so the same
+    /// value gets the same token in every file pseudonymized with the same key and joins on
+    /// the column still work, while the token cannot be traced back without the key. Empty
+    /// values stay empty. The columns are checked before anything is written, and records
+    /// are written out as they are read; only the distinct values replaced are held in the
+    /// returned lookup, which is written out only on request.
+    /// 
+    pub fn pseudonymize(&mut self, columns: &[&str], key: &PseudonymKey, output: &mut CsvWriter) -> io::Result<PseudonymLookup> {
+        pseudonym::pseudonymize(self, columns, key, output)
+    }
+
+    /// Copies the remaining records with the dates of some columns shifted by a keyed offset per subject
+    /// 
+    /// # Arguments
+    /// 
+    /// * `columns` - The date columns shifted
+    /// * `subject_column` - The column whose value picks the offset of a row
+    /// * `key` - The secret key of the offsets
+    /// * `output` - The writer of the shifted csv file
+    /// 
+    /// # Returns
+    /// 
+    /// The number of rows written after the header row, or an error if a column is not
+    /// found, a row has no subject, a date is not valid or the records cannot be read or
+    /// written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut output = CsvWriter::create("visits_shifted.csv")?;
+    /// CsvReader::open("visits.csv")?.shift_dates(&["visit_date", "scan_time"], "subject", &key, &mut output)?;
+    /// output.flush()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// Dates are `YYYY-MM-DD`, optionally followed by a time after `T` or a space, which is
+    /// kept as it is. All dates of a subject move by the same `PseudonymKey::date_offset`,
+    /// so intervals within a subject are preserved, and the offset only depends on the key
+    /// and the subject value, so it is the same across files. Shift all files before or
+    /// all files after pseudonymizing the subject column, since a token gives another
+    /// offset than the value it replaces. Empty values stay empty, a row whose subject is
+    /// missing or blank is an error naming its line, and the columns are checked before
+    /// anything is written.
+    /// 
+    pub fn shift_dates(&mut self, columns: &[&str], subject_column: &str, key: &PseudonymKey, output: &mut CsvWriter) -> io::Result<usize> {
+        pseudonym::shift_dates(self, columns, subject_column, key, output)
+    }
+
+    /// Copies the remaining records with the gains and offsets of a calibration applied
+    /// 
+    /// # Arguments
+    /// 
+    /// * `calibration` - The corrections of the channels, matched to the columns by name
+    /// * `output` - The writer of the corrected csv file
+    /// * `session` - The metadata of the recording, where the calibration and the units are recorded
+    /// * `force` - Applies the calibration even if the session records it as applied already
+    /// 
+    /// # Returns
+    /// 
+    /// The CalibrationReport, or an `AlreadyExists` error if the calibration was applied
+    /// before and `force` is false, or an error if a value to correct is not a number or
+    /// the records cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let calibration = Calibration::load("calibration.csv")?;
+    /// let mut session = SessionInfo::load("session.json")?;
+    /// let mut output = CsvWriter::create("recording_calibrated.csv")?;
+    /// let report = CsvReader::open("recording.csv")?.apply_calibration_copy(&calibration, &mut output, &mut session, false)?;
+    /// output.flush()?;
+    /// session.save("session.json")?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// Every value of a calibrated column becomes `value * gain_correction + offset`,
+    /// written with the float format of the output, and the other columns are copied as
+    /// they are. Empty values stay empty. The session is checked before anything is written
+    /// and only updated once every record is copied, as in `Calibration::apply`; the report
+    /// lists the calibrated channels missing from the file and the columns left uncalibrated.
+    /// 
+    pub fn apply_calibration_copy(&mut self, calibration: &Calibration, output: &mut CsvWriter, session: &mut SessionInfo, force: bool) -> io::Result<CalibrationReport> {
+        calibration::apply_calibration_copy(self, calibration, output, session, force)
+    }
+
+    /// Computes the running statistics of a column per combination of key columns
+    /// 
+    /// # Arguments
+    /// 
+    /// * `keys` - The columns whose values define the groups, e.g. `["subject", "condition", "channel"]`
+    /// * `value_column` - The column the statistics are computed from
+    /// * `template` - The empty StreamingStats copied for every group, with its compression and histogram bins
+    /// * `max_groups` - The largest number of groups held in memory
+    /// 
+    /// # Returns
+    /// 
+    /// The GroupStats of every group in order of first appearance, or an error if a column is
+    /// not found, the template already holds values, or there are more than `max_groups` groups
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let groups = CsvReader::open("amplitudes_long.csv")?.grouped_stats(&["subject", "channel"], "amplitude", &StreamingStats::new(), 100_000)?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The records are read in one pass, holding one StreamingStats per group, so the memory
+    /// used grows with the number of groups and not with the number of records. Values that
+    /// are not numbers are counted as missing in their group.
+    /// 
+    pub fn grouped_stats(&mut self, keys: &[&str], value_column: &str, template: &StreamingStats, max_groups: usize) -> io::Result<Vec<GroupStats>> {
+        if template.count() + template.missing() > 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "The template statistics must be empty"));
+        }
+        let key_columns = keys.iter().map(|key| column_index(self.headers(), key)).collect::<io::Result<Vec<usize>>>()?;
+        let value_column = column_index(self.headers(), value_column)?;
+        let mut index: HashMap<Vec<String>, usize> = HashMap::new();
+        let mut groups: Vec<GroupStats> = Vec::new();
+        let mut group_keys: Vec<String> = Vec::with_capacity(key_columns.len());
+        for record in self.reader.records() {
+            let record = record?;
+            group_keys.clear();
+            group_keys.extend(key_columns.iter().map(|&column| record.get(column).unwrap_or("").to_string()));
+            let group = match index.get(&group_keys) {
+                Some(&group) => group,
+                None => {
+                    if groups.len() == max_groups {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!(
+                                "Found more than {} groups at line {}, where group {:?} starts",
+                                max_groups,
+                                record.position().map_or(0, |position| position.line()),
+                                group_keys
+                            ),
+                        ));
+                    }
+                    index.insert(group_keys.clone(), groups.len());
+                    groups.push(GroupStats { keys: group_keys.clone(), stats: template.clone() });
+                    groups.len() - 1
+                }
+            };
+            let value = record.get(value_column).and_then(|field| field.trim().parse().ok()).unwrap_or(f64::NAN);
+            groups[group].stats.update(&[value]);
+        }
+        Ok(groups)
+    }
+
+    /// Sets the memory budget of the operations of the reader
+    /// 
+    /// # Arguments
+    /// 
+    /// * `budget` - The budget used instead of `MemoryBudget::global`
+    /// 
+    /// # Returns
+    /// 
+    /// The CsvReader with the budget
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut reader = CsvReader::open("spikes.csv")?.with_memory_budget(MemoryBudget::new(256 << 20).with_spill_dir("/scratch/tmp"));
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The budget is consulted by `sort`, which spills sorted runs to disk beyond it, and by
+    /// `pivot` and `CsvIO::robust_column_stats`, which return a MemoryBudgetExceeded error.
+    /// Clones of the reader keep the budget.
+    /// 
+    pub fn with_memory_budget(mut self, budget: MemoryBudget) -> Self {
+        self.memory_budget = Some(budget);
+        self
+    }
+
+    /// Returns the memory budget of the operations of the reader
+    /// 
+    /// # Returns
+    /// 
+    /// The budget set with `with_memory_budget`, or `MemoryBudget::global`
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// println!("{:?} bytes", reader.memory_budget().limit);
+    /// ```
+    /// 
+    pub fn memory_budget(&self) -> MemoryBudget {
+        self.memory_budget.clone().unwrap_or_else(MemoryBudget::global)
+    }
+
+    /// Writes the remaining records sorted by some columns
+    /// 
+    /// # Arguments
+    /// 
+    /// * `columns` - The columns compared, the first one first
+    /// * `numeric` - Whether fields are compared as numbers, with fields that are not numbers after all numbers
+    /// * `output` - The writer of the sorted csv file, given the header row first
+    /// 
+    /// # Returns
+    /// 
+    /// The SortSummary, or an error if a column is not found, no column is given, or the
+    /// records or the temporary files cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut output = CsvWriter::create("sorted.csv")?;
+    /// let summary = CsvReader::open("spikes.csv")?.sort(&["unit", "time"], true, &mut output)?;
+    /// output.flush()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The sort is stable. Records are sorted in memory while they fit in the memory budget;
+    /// beyond it, sorted runs are written to temporary files in the spill directory and
+    /// merged, which gives the same output. The temporary files are removed when the sort
+    /// ends, also when it fails.
+    /// 
+    pub fn sort(&mut self, columns: &[&str], numeric: bool, output: &mut CsvWriter) -> io::Result<SortSummary> {
+        let budget = self.memory_budget();
+        sort::sort_records(self, columns, numeric, output, &budget)
+    }
+
+    /// Copies the remaining records with the values of some columns replaced by integer codes
+    /// 
+    /// # Arguments
+    /// 
+    /// * `columns` - The columns encoded, e.g. `condition`
+    /// * `order` - Whether the codes follow the first appearance or the byte order of the values
+    /// * `output` - The writer of the encoded csv file
+    /// * `mapping_output` - The writer of the `column,code,value` dictionaries
+    /// 
+    /// # Returns
+    /// 
+    /// The CategoricalMapping of the codes, or an error if a column is not found or the
+    /// records cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let (mut output, mut mapping_output) = (CsvWriter::create("trials_coded.csv")?, CsvWriter::create("trials_codes.csv")?);
+    /// CsvReader::open("trials.csv")?.encode_categorical(&["condition", "electrode"], CodeOrder::Sorted, &mut output, &mut mapping_output)?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// Each column has its own codes, from 0. Empty values stay empty and get no code, and
+    /// every other field is copied unchanged, so `decode_categorical` gives back the
+    /// original file. `CodeOrder::Sorted` reads the remaining records twice.
+    /// 
+    pub fn encode_categorical(&mut self, columns: &[&str], order: CodeOrder, output: &mut CsvWriter, mapping_output: &mut CsvWriter) -> io::Result<CategoricalMapping> {
+        let mapping = categorical::encode(self, columns, order, output)?;
+        mapping.to_csv(mapping_output)?;
+        Ok(mapping)
+    }
+
+    /// Copies the remaining records with the codes of some columns replaced by their values
+    /// 
+    /// # Arguments
+    /// 
+    /// * `mapping` - The dictionaries of the encoded columns, e.g. from `CategoricalMapping::from_csv`
+    /// * `output` - The writer of the decoded csv file
+    /// 
+    /// # Returns
+    /// 
+    /// The number of rows written after the header row, or an error if a column of the
+    /// mapping is not found, a code is not in the mapping, or the records cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mapping = CategoricalMapping::from_csv(&mut CsvReader::open("trials_codes.csv")?)?;
+    /// CsvReader::open("trials_coded.csv")?.decode_categorical(&mapping, &mut output)?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The error of an unknown code names the code, the column and the line of the record.
+    /// Empty fields stay empty.
+    /// 
+    pub fn decode_categorical(&mut self, mapping: &CategoricalMapping, output: &mut CsvWriter) -> io::Result<usize> {
+        categorical::decode(self, mapping, output)
+    }
+
+    /// Counts the values of a column over the remaining records
+    /// 
+    /// # Arguments
+    /// 
+    /// * `column` - The column counted
+    /// * `top_k` - The number of most frequent values listed, the others counted together, or None for all
+    /// 
+    /// # Returns
+    /// 
+    /// The ValueCounts, or an error if the column is not found or the records cannot be read
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let counts = CsvReader::open("spikes.csv")?.value_counts("unit", Some(20))?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// Every distinct value is counted exactly, so the memory used grows with the number of
+    /// distinct values; `top_k` only shortens the list returned
+    /// 
+    pub fn value_counts(&mut self, column: &str, top_k: Option<usize>) -> io::Result<ValueCounts> {
+        categorical::value_counts(self, column, top_k)
+    }
+
+    /// Reads the columns under their canonical names
+    /// 
+    /// # Arguments
+    /// 
+    /// * `aliases` - The table of the names the canonical columns may appear under
+    /// 
+    /// # Returns
+    /// 
+    /// The CsvReader with its headers renamed, or an error naming the headers if the aliases
+    /// of the file are ambiguous
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut reader = CsvReader::open("rig_b.csv")?.with_column_aliases(&ColumnAliases::neuroscience())?;
+    /// let hypnogram = Hypnogram::from_csv(&mut reader, 30.0)?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The headers themselves are renamed, so every operation that looks up a column by
+    /// name, and every file written with the headers, uses the canonical names. See
+    /// `ColumnAliases::resolve` for the priority of the matches. Setting another table
+    /// resolves it against the already renamed headers and adds its renames to the
+    /// mapping. Clones of the reader keep the names.
+    /// 
+    pub fn with_column_aliases(mut self, aliases: &ColumnAliases) -> io::Result<Self> {
+        self.apply_column_aliases(aliases)?;
+        Ok(self)
+    }
+
+    /// Returns the renames applied to the headers
+    /// 
+    /// # Returns
+    /// 
+    /// The ColumnMapping, or None if no aliases were set
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// if let Some(mapping) = reader.column_mapping() {
+    ///     for alias in &mapping.applied {
+    ///         println!("{} -> {}", alias.header, alias.canonical);
+    ///     }
+    /// }
+    /// ```
+    /// 
+    pub fn column_mapping(&self) -> Option<&ColumnMapping> {
+        self.column_mapping.as_ref()
+    }
+
+    /// Copies the remaining records with the extreme values of some columns capped at their quantiles
+    /// 
+    /// # Arguments
+    /// 
+    /// * `columns` - The columns winsorized
+    /// * `lower_q` - The quantile the smaller values are raised to, e.g. 0.01
+    /// * `upper_q` - The quantile the larger values are lowered to, e.g. 0.99
+    /// * `output` - The writer of the winsorized csv file, given the header row first
+    /// * `options` - Whether the quantiles are exact and whether flag columns are written
+    /// 
+    /// # Returns
+    /// 
+    /// The WinsorizeReport with the quantiles and the number of values capped at each tail
+    /// of every column, or an error if a column is not found or listed twice, the quantiles are not
+    /// `0 <= lower_q < upper_q <= 1`, a flag column already exists, the exact values exceed
+    /// the memory budget, or the records cannot be read or written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut output = CsvWriter::create("behavior_winsorized.csv")?;
+    /// let options = WinsorizeOptions { flag_columns: true, ..WinsorizeOptions::new() };
+    /// let report = CsvReader::open("behavior.csv")?.winsorize_columns(&["reaction_time"], 0.01, 0.99, &mut output, &options)?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The remaining records are read twice, first to compute the quantiles and then to copy
+    /// them, so only the quantile sketch is held in memory unless `options.exact` is set,
+    /// in which case the values of the columns must fit in the memory budget of the reader.
+    /// The sketched quantiles are estimated as in `StreamingStats::quantile`.
+    /// Values strictly between the quantiles and fields that are not finite numbers are
+    /// copied unchanged; the latter are left out of the quantiles and counted as missing.
+    /// A capped value is written with the float format of the writer. The flag columns are
+    /// appended after the other columns, in the order of `columns`.
+    /// 
+    pub fn winsorize_columns(&mut self, columns: &[&str], lower_q: f64, upper_q: f64, output: &mut CsvWriter, options: &WinsorizeOptions) -> io::Result<WinsorizeReport> {
+        winsorize::winsorize(self, columns, lower_q, upper_q, output, options)
+    }
+
+    /// Renames the headers to their canonical names and records the renames
+    pub(crate) fn apply_column_aliases(&mut self, aliases: &ColumnAliases) -> io::Result<()> {
+        let mapping = aliases.resolve(&self.headers)?;
+        self.headers = Arc::new(mapping.canonical_headers(&self.headers));
+        self.column_mapping.get_or_insert_with(ColumnMapping::default).applied.extend(mapping.applied);
+        Ok(())
+    }
+
+    /// Returns the path the records are read from, the decompressed copy of a compressed file
+    fn data_path(&self) -> &Path {
+        self.spill.as_ref().map_or(self.file_path.as_path(), |spill| spill.path())
+    }
+
+    /// Opens the file the records are read from again, which the standard input cannot be
+    fn reopen(&self) -> io::Result<File> {
+        match self.reader.get_ref().get_ref() {
+            Input::File(_) => File::open(self.data_path()),
+            Input::Stdin(_) => Err(not_seekable()),
+        }
+    }
+
+    /// Opens another reader of the same file at the next record of this one
+    pub(crate) fn try_clone_at_position(&self) -> io::Result<Self> {
+        let mut clone = self.try_clone()?;
+        clone.reader.seek(self.reader.position().clone()).map_err(io::Error::from)?;
+        Ok(clone)
+    }
+
+    /// Returns an iterator over the remaining records
+    pub(crate) fn records(&mut self) -> StringRecordsIter<'_, BufReader<Input>> {
+        self.reader.records()
+    }
+
+    /// Visits the line number and fields of every remaining record, bypassing the csv parser where it can
+    ///
+    /// The first record is read by the csv parser, which also holds back the first record of a
+    /// file without a header row. The following lines of a file are split by PlainLines until
+    /// one of them has a quote, a byte that is not ASCII or another feature only the csv parser
+    /// handles; that line and all the later ones are read by the csv parser. The standard input
+    /// cannot be opened again and is always read by the csv parser.
+    pub(crate) fn for_each_row<E, F>(&mut self, mut visit: F) -> Result<(), E>
+    where
+        E: From<io::Error> + From<csv::Error>,
+        F: FnMut(u64, &[&[u8]]) -> Result<(), E>,
+    {
+        let mut record = ByteRecord::new();
+        let mut fast = true;
+        while self.reader.read_byte_record(&mut record)? {
+            let fields: Vec<&[u8]> = record.iter().collect();
+            visit(record.position().map_or(0, |position| position.line()), &fields)?;
+            if !std::mem::take(&mut fast) || !self.dialect.delimiter.is_ascii() {
+                continue;
+            }
+            let Ok(mut file) = self.reopen() else { continue };
+            let position = self.reader.position().clone();
+            file.seek(SeekFrom::Start(position.byte()))?;
+            let n_fields = (!self.dialect.flexible).then_some(record.len());
+            let stop = PlainLines::new(file, self.dialect.delimiter, self.dialect.quote, self.dialect.comment, n_fields, position).scan(&mut visit)?;
+            match stop {
+                Stop::End(position) => {
+                    self.reader.seek(position)?;
+                    return Ok(());
+                }
+                Stop::Fallback(position) => self.reader.seek(position)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A writer of csv files
+/// 
+/// # Arguments
+/// 
+/// * `writer` - A csv::Writer object that writes to the csv file
+/// * `float_format` - The format of the numbers written by the result structs of the crate
+/// 
+/// # Examples
+/// 
+/// ```
+/// let mut writer = CsvWriter::create("out.csv")?;
+/// writer.write_record(&StringRecord::from(vec!["time", "lfp"]))?;
+/// writer.flush()?;
+/// ```
+pub struct CsvWriter {
+    writer: Writer<Sink>,
+    float_format: FloatFormat,
+}
+
+/// Implementation of the CsvWriter class
+/// 
+/// # Methods
+/// 
+/// * `create` - Creates or truncates a csv file
+/// * `append` - Opens a csv file to add records after its end
+/// * `write_record` - Writes a record
+/// * `write_records` - Writes a group of records
+/// * `set_float_format` - Sets the format of the numbers written to the file
+/// * `float_format` - Returns the format of the numbers written to the file
+/// * `format_float` - Formats a number with the format of the file
+/// * `flush` - Writes the buffered records to the file
+/// * `finish` - Writes the buffered records and finishes the compressed stream of the file
+impl CsvWriter {
+    /// Creates or truncates a csv file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file_path` - The path to the csv file
+    /// 
+    /// # Returns
+    /// 
+    /// A CsvWriter, or an error if the file cannot be created
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let writer = CsvWriter::create("out.csv")?;
+    /// ```
+    /// 
+    pub fn create<P: AsRef<Path>>(file_path: P) -> io::Result<Self> {
+        Self::create_with_dialect(file_path, &CsvDialect::default())
+    }
+
+    /// Creates or truncates a csv file written with the delimiter and quote of a dialect
+    pub(crate) fn create_with_dialect<P: AsRef<Path>>(file_path: P, dialect: &CsvDialect) -> io::Result<Self> {
+        // The old bytes of the file are replaced, so only its extension tells the compression
+        let compression = dialect.compression.unwrap_or_else(|| Compression::from_extension(&file_path));
+        let sink = Sink::open(file_path.as_ref(), false, compression, dialect.compression_level)?;
+        Ok(Self { writer: dialect.writer_builder().from_writer(sink), float_format: FloatFormat::default() })
+    }
+
+    /// Opens a csv file to add records after its end
+    /// 
+    /// # Arguments
+    /// 
+    /// * `file_path` - The path to the existing csv file
+    /// 
+    /// # Returns
+    /// 
+    /// A CsvWriter, or an error if the file cannot be opened
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let writer = CsvWriter::append("log.csv")?;
+    /// ```
+    /// 
+    pub fn append<P: AsRef<Path>>(file_path: P) -> io::Result<Self> {
+        Self::append_with_dialect(file_path, &CsvDialect::default())
+    }
+
+    /// Opens a csv file written with the delimiter and quote of a dialect to add records after its end
+    pub(crate) fn append_with_dialect<P: AsRef<Path>>(file_path: P, dialect: &CsvDialect) -> io::Result<Self> {
+        let sink = Sink::open(file_path.as_ref(), true, dialect.compression_of(&file_path), dialect.compression_level)?;
+        Ok(Self { writer: dialect.writer_builder().from_writer(sink), float_format: FloatFormat::default() })
+    }
+
+    /// Writes a record
+    /// 
+    /// # Arguments
+    /// 
+    /// * `record` - The record
+    /// 
+    /// # Returns
+    /// 
+    /// Nothing, or an error if the record cannot be written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// writer.write_record(&record)?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The record is buffered until `flush` is called or the buffer fills up
+    /// 
+    pub fn write_record(&mut self, record: &StringRecord) -> io::Result<()> {
+        self.writer.write_record(record).map_err(io::Error::from)
+    }
+
+    /// Writes a group of records
+    /// 
+    /// # Arguments
+    /// 
+    /// * `records` - The records
+    /// 
+    /// # Returns
+    /// 
+    /// Nothing, or the error of the first record that cannot be written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// writer.write_records(&records)?;
+    /// ```
+    /// 
+    pub fn write_records(&mut self, records: &[StringRecord]) -> io::Result<()> {
+        records.iter().try_for_each(|record| self.write_record(record))
+    }
+
+    /// Sets the format of the numbers written to the file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `float_format` - The format of the numbers
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// writer.set_float_format(FloatFormat::significant(6));
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// The format is used by the writers of the crate that produce numbers, such as the
+    /// `to_csv` methods of result structs and `pivot`. Records written with `write_record`
+    /// are written as they are.
+    /// 
+    pub fn set_float_format(&mut self, float_format: FloatFormat) {
+        self.float_format = float_format;
+    }
+
+    /// Returns the format of the numbers written to the file
+    /// 
+    /// # Returns
+    /// 
+    /// The FloatFormat, `FloatFormat::default()` unless `set_float_format` was called on a
+    /// file opened for writing
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let format = writer.float_format();
+    /// ```
+    /// 
+    pub fn float_format(&self) -> FloatFormat {
+        self.float_format
+    }
+
+    /// Formats a number with the format of the file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `value` - The number
+    /// 
+    /// # Returns
+    /// 
+    /// The text of the number
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// writer.write_record(&StringRecord::from(vec![writer.format_float(time), writer.format_float(value)]))?;
+    /// ```
+    /// 
+    pub fn format_float(&self, value: f64) -> String {
+        self.float_format.format(value)
+    }
+
+    /// Writes the buffered records to the file
+    /// 
+    /// # Returns
+    /// 
+    /// Nothing, or an error if the file cannot be written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// writer.flush()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// A compressed file is only complete once the stream is finished by `finish` or by
+    /// dropping the writer
+    /// 
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Writes the buffered records and finishes the compressed stream of the file
+    /// 
+    /// # Arguments
+    /// 
+    /// * `self` - The CsvWriter, consumed
+    /// 
+    /// # Returns
+    /// 
+    /// Nothing, or an error if the file cannot be written
+    /// 
+    /// # Examples
+    /// 
+    /// ```
+    /// let mut writer = CsvWriter::create("behavior.csv.gz")?;
+    /// writer.write_records(&records)?;
+    /// writer.finish()?;
+    /// ```
+    /// 
+    /// # Note
+    /// 
+    /// Dropping a writer also finishes its file but ignores errors, as does dropping a
+    /// `BufWriter`. For a file that is not compressed this is the same as `flush`.
+    /// 
+    pub fn finish(self) -> io::Result<()> {
+        let mut sink = self.writer.into_inner().map_err(|error| io::Error::new(error.error().kind(), error.error().to_string()))?;
+        sink.finish()
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::ReaderBuilder;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("neurorust-csv-{}-{}", std::process::id(), name))
+    }
+
+    #[test]
+    fn try_clone_fails_instead_of_panicking_once_the_file_is_gone() {
+        let path = temp_path("gone.csv");
+        std::fs::write(&path, "a,b\n1,2\n3,4\n").unwrap();
+        let mut reader = CsvReader::open(&path).unwrap();
+        let mut clone = reader.try_clone().unwrap();
+        assert_eq!(reader.read_record().unwrap().unwrap(), StringRecord::from(vec!["1", "2"]));
+        // A clone starts at the first record, whatever the position of the original
+        assert_eq!(reader.try_clone().unwrap().read_record().unwrap().unwrap(), StringRecord::from(vec!["1", "2"]));
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(reader.try_clone().err().map(|error| error.kind()), Some(io::ErrorKind::NotFound));
+        assert_eq!(clone.read_records().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn threads_reading_disjoint_row_ranges_rebuild_every_record() {
+        let path = temp_path("threads.csv");
+        let mut text = String::from("time,a,\"b,c\"\n");
+        for i in 0..10007 {
+            // Every third field is quoted and spans two lines, so rows and lines differ
+            text += &format!("{},{},\"q\"\"{}\nz\"\n", i, i * 3, i);
+        }
+        std::fs::write(&path, text).unwrap();
+        let mut reader = CsvReader::open(&path).unwrap();
+        let index = reader.build_index().unwrap();
+        assert_eq!(index.len(), 10007);
+        let n = index.len();
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let mut reader = reader.try_clone().unwrap();
+                std::thread::spawn(move || reader.read_rows(i * n / 8..(i + 1) * n / 8).unwrap())
+            })
+            .collect();
+        let rows: Vec<StringRecord> = handles.into_iter().flat_map(|handle| handle.join().unwrap()).collect();
+        let direct = reader.read_records().unwrap();
+        assert_eq!(rows, direct);
+        assert_eq!(&rows[5000][2], "q\"5000\nz");
+
+        let mut at_end = reader.try_clone().unwrap();
+        at_end.seek_row(n).unwrap();
+        assert!(at_end.read_record().unwrap().is_none());
+        assert!(at_end.seek_row(n + 1).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    /// Melts a file to long format and pivots it back, returning the rows of each step
+    fn melt_and_pivot(name: &str, wide: &str) -> (String, String) {
+        let (wide_path, long_path, back_path) = (temp_path(&format!("{}-wide.csv", name)), temp_path(&format!("{}-long.csv", name)), temp_path(&format!("{}-back.csv", name)));
+        std::fs::write(&wide_path, wide).unwrap();
+        let mut output = CsvWriter::create(&long_path).unwrap();
+        CsvReader::open(&wide_path).unwrap().melt(&["time"], &[], "channel", "value", &mut output).unwrap();
+        output.flush().unwrap();
+        let mut output = CsvWriter::create(&back_path).unwrap();
+        CsvReader::open(&long_path).unwrap().pivot("time", "channel", "value", &mut output, None).unwrap();
+        output.flush().unwrap();
+        let texts = (std::fs::read_to_string(&long_path).unwrap(), std::fs::read_to_string(&back_path).unwrap());
+        [wide_path, long_path, back_path].iter().for_each(|path| std::fs::remove_file(path).unwrap());
+        texts
+    }
+
+    #[test]
+    fn melt_then_pivot_recovers_the_wide_file() {
+        let wide = "time,ch1,ch2,ch3\n0,1.5,2,3\n0.001,-1,x,\n0.002,4,5,6\n";
+        let (long, back) = melt_and_pivot("round-trip", wide);
+        assert_eq!(long.lines().count(), 10);
+        assert_eq!(long.lines().nth(5), Some("0.001,ch2,x"));
+        // Empty cells are kept as rows, so the pivot puts them back
+        assert_eq!(long.lines().nth(6), Some("0.001,ch3,"));
+        assert_eq!(back, wide);
+    }
+
+    #[test]
+    fn pivot_keeps_the_order_in_which_columns_first_appear() {
+        let path = temp_path("pivot-order.csv");
+        std::fs::write(&path, "time,channel,value\n0,b,1\n0,a,2\n1,a,3\n1,c,4\n").unwrap();
+        let out = temp_path("pivot-order-out.csv");
+        let mut output = CsvWriter::create(&out).unwrap();
+        assert_eq!(CsvReader::open(&path).unwrap().pivot("time", "channel", "value", &mut output, None).unwrap(), 2);
+        output.flush().unwrap();
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "time,b,a,c\n0,1,2,\n1,,3,4\n");
+        [path, out].iter().for_each(|path| std::fs::remove_file(path).unwrap());
+    }
+
+    #[test]
+    fn pivot_names_the_first_repeated_pair_unless_aggregating() {
+        let path = temp_path("pivot-duplicates.csv");
+        std::fs::write(&path, "t,c,v\n2,b,7\n1,a,2\n1,b,3\n2,a,4\n1,a,6\n1,b,1\n").unwrap();
+        let out = temp_path("pivot-duplicates-out.csv");
+        let error = CsvReader::open(&path).unwrap().pivot("t", "c", "v", &mut CsvWriter::create(&out).unwrap(), None).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("t '1' and c 'a'"), "{}", error);
+        for (agg, expected) in [
+            (Agg::Mean, "t,b,a\n2,7,4\n1,2,4\n"),
+            (Agg::Count, "t,b,a\n2,1,1\n1,2,2\n"),
+            (Agg::Last, "t,b,a\n2,7,4\n1,1,6\n"),
+            (Agg::Max, "t,b,a\n2,7,4\n1,3,6\n"),
+        ] {
+            let mut output = CsvWriter::create(&out).unwrap();
+            CsvReader::open(&path).unwrap().pivot("t", "c", "v", &mut output, Some(agg)).unwrap();
+            output.flush().unwrap();
+            assert_eq!(std::fs::read_to_string(&out).unwrap(), expected, "{:?}", agg);
+        }
+        [path, out].iter().for_each(|path| std::fs::remove_file(path).unwrap());
+    }
+
+    /// Returns the peak resident memory of this process in kB
+    #[cfg(target_os = "linux")]
+    fn peak_memory_kb() -> usize {
+        let status = std::fs::read_to_string("/proc/self/status").unwrap();
+        let line = status.lines().find(|line| line.starts_with("VmHWM")).unwrap();
+        line.split_whitespace().nth(1).unwrap().parse().unwrap()
+    }
+
+    /// Melts a 20 MB file into 3.2 million rows; run alone by `melt_streams_large_files`
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore]
+    fn melt_large_file_alone() {
+        use std::io::Write;
+        let path = temp_path("melt-large.csv");
+        {
+            let mut file = io::BufWriter::new(File::create(&path).unwrap());
+            writeln!(file, "time,{}", (0..32).map(|c| format!("ch{}", c)).collect::<Vec<_>>().join(",")).unwrap();
+            for i in 0..100_000 {
+                writeln!(file, "{},{}", i, (0..32).map(|c| (c * i).to_string()).collect::<Vec<_>>().join(",")).unwrap();
+            }
+        }
+        let size_kb = std::fs::metadata(&path).unwrap().len() as usize / 1024;
+        let before = peak_memory_kb();
+        let mut output = CsvWriter::create("/dev/null").unwrap();
+        assert_eq!(CsvReader::open(&path).unwrap().melt(&["time"], &[], "channel", "value", &mut output).unwrap(), 3_200_000);
+        let growth = peak_memory_kb() - before;
+        std::fs::remove_file(&path).unwrap();
+        assert!(growth < size_kb / 4, "Peak memory grew by {} kB while melting {} kB", growth, size_kb);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn melt_streams_large_files() {
+        // The peak memory of the process is only meaningful without other tests running in it
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "data_io::csv::tests::melt_large_file_alone", "--ignored", "--test-threads=1"])
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    }
+
+    #[test]
+    #[ignore = "reads the standard input, run by stdin_streams_but_cannot_seek"]
+    fn stdin_alone() {
+        let mut csv_io = CsvIO::open_stdin().unwrap();
+        assert_eq!(csv_io.mode(), OpenMode::Read);
+        let reader = csv_io.reader_mut().unwrap();
+        assert_eq!(reader.path(), Path::new("-"));
+        assert_eq!(reader.headers(), &StringRecord::from(vec!["time", "x"]));
+        assert_eq!(reader.build_index().unwrap_err().kind(), io::ErrorKind::Unsupported);
+        assert!(reader.try_clone().is_err_and(|error| error.kind() == io::ErrorKind::Unsupported));
+        assert_eq!(reader.read_record().unwrap(), Some(StringRecord::from(vec!["0", "0"])));
+        let table = csv_io.column_stats(&StreamingStats::new()).unwrap();
+        assert_eq!(table.channels[1].count(), 99_999);
+        assert_eq!(table.channels[1].mean(), 50_000.0);
+    }
+
+    #[test]
+    fn stdin_streams_but_cannot_seek() {
+        let mut child = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "data_io::csv::tests::stdin_alone", "--ignored", "--test-threads=1"])
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        // More than a pipe buffer, so that the child reads while the parent writes
+        let mut input = String::from("time,x\n");
+        for i in 0..100_000 {
+            input.push_str(&format!("{},{}\n", i as f64 / 1000.0, i));
+        }
+        std::io::Write::write_all(&mut child.stdin.take().unwrap(), input.as_bytes()).unwrap();
+        let output = child.wait_with_output().unwrap();
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        assert!(output.status.success() && stdout.contains("1 passed"), "{}", stdout);
+    }
+
+    #[test]
+    fn readers_and_indices_can_cross_threads() {
+        fn send<T: Send>() {}
+        fn share<T: Send + Sync>() {}
+        send::<CsvReader>();
+        share::<RowIndex>();
+    }
+
+    #[test]
+    fn grouped_stats_match_each_group_and_stop_at_the_group_cap() {
+        let path = temp_path("grouped.csv");
+        std::fs::write(&path, "subject,channel,amplitude\ns1,Cz,1\ns1,Fz,10\ns2,Cz,4\ns1,Cz,3\ns1,Cz,x\ns2,Cz,7\n").unwrap();
+        let groups = CsvReader::open(&path).unwrap().grouped_stats(&["subject", "channel"], "amplitude", &StreamingStats::new(), 3).unwrap();
+        let keys: Vec<&[String]> = groups.iter().map(|group| &group.keys[..]).collect();
+        assert_eq!(keys, [["s1", "Cz"], ["s1", "Fz"], ["s2", "Cz"]]);
+        let stats = &groups[0].stats;
+        assert_eq!((stats.count(), stats.missing(), stats.mean(), stats.min(), stats.max()), (2, 1, 2.0, 1.0, 3.0));
+        assert_eq!(stats.std(), 2f64.sqrt());
+        assert_eq!((groups[2].stats.mean(), groups[2].stats.std()), (5.5, 4.5f64.sqrt()));
+        assert!(groups[1].stats.std().is_nan());
+
+        let error = CsvReader::open(&path).unwrap().grouped_stats(&["subject", "channel"], "amplitude", &StreamingStats::new(), 2).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("more than 2 groups at line 4"), "{}", error);
+        let error = CsvReader::open(&path).unwrap().grouped_stats(&["session"], "amplitude", &StreamingStats::new(), 2).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Reads every column as f64 the way read_columns did before the fast path, with the csv parser and `str::parse`
+    fn csv_parser_columns(path: &Path, has_headers: bool) -> Vec<Vec<f64>> {
+        let mut reader = ReaderBuilder::new().has_headers(has_headers).from_path(path).unwrap();
+        let mut columns: Vec<Vec<f64>> = Vec::new();
+        for record in reader.records() {
+            let record = record.unwrap();
+            columns.resize_with(record.len(), Vec::new);
+            for (values, field) in columns.iter_mut().zip(record.iter()) {
+                values.push(field.trim().parse().unwrap());
+            }
+        }
+        columns
+    }
+
+    fn numeric_text(n_rows: usize, seed: u64, line: impl Fn(usize, &[String]) -> String) -> String {
+        let mut rng = crate::processing::random::SeededRng::new(seed);
+        let mut text = String::from("time,a,b\n");
+        for row in 0..n_rows {
+            let value = f64::from_bits(rng.next_u64() >> 2);
+            let fields = [format!("{}", row as f64 / 30000.0), format!("{}", value), format!("{:e}", rng.next_gaussian())];
+            text += &line(row, &fields);
+        }
+        text
+    }
+
+    fn bits(columns: &[Vec<f64>]) -> Vec<Vec<u64>> {
+        columns.iter().map(|column| column.iter().map(|value| value.to_bits()).collect()).collect()
+    }
+
+    #[test]
+    fn numeric_reads_fall_back_to_the_csv_parser_when_quotes_start_halfway() {
+        let path = temp_path("quoted_halfway.csv");
+        let text = numeric_text(4000, 143, |row, fields| match row {
+            // From the middle of the file on fields are quoted, with a delimiter inside one of them
+            0..2000 if row % 5 == 0 => format!("{}\n\n", fields.join(",")),
+            0..2000 => format!("{}\n", fields.join(",")),
+            2000 => format!("\"{}\",\"{}\",\" {}\"\r\n", fields[0], fields[1], fields[2]),
+            _ if row % 3 == 0 => format!("{},\"{}\",{}\n", fields[0], fields[1], fields[2]),
+            _ => format!("{}\n\n", fields.join(",")),
+        });
+        std::fs::write(&path, text).unwrap();
+        let expected = csv_parser_columns(&path, true);
+        assert_eq!(expected[0].len(), 4000);
+        let columns = CsvIO::open_read(path.to_str().unwrap()).unwrap().read_columns::<f64>().unwrap();
+        assert_eq!(bits(&columns), bits(&expected));
+        let single = CsvIO::open_read(path.to_str().unwrap()).unwrap().read_columns::<f32>().unwrap();
+        assert_eq!(single[2], expected[2].iter().map(|value| format!("{:e}", value).parse::<f32>().unwrap()).collect::<Vec<_>>());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn numeric_reads_match_the_csv_parser_without_headers_and_with_crlf() {
+        let path = temp_path("crlf.csv");
+        let text = numeric_text(3000, 7, |_, fields| format!(" {} ,{},{}\r\n", fields[0], fields[1], fields[2]));
+        std::fs::write(&path, text.split_once('\n').unwrap().1).unwrap();
+        let expected = csv_parser_columns(&path, false);
+        assert_eq!(expected[0].len(), 3000);
+        let mut csv_io = CsvIOBuilder::new().has_headers(false).open_read(path.to_str().unwrap()).unwrap();
+        assert_eq!(bits(&csv_io.read_columns::<f64>().unwrap()), bits(&expected));
+        assert_eq!(csv_io.read_record().unwrap(), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn numeric_reads_report_the_line_of_a_bad_field_on_either_path() {
+        let path = temp_path("bad_field.csv");
+        for (bad_row, quoted_from) in [(1500, 4000), (3000, 2000), (1, 0)] {
+            let text = numeric_text(4000, 1, |row, fields| {
+                let fields = if row == bad_row { vec![fields[0].clone(), "x".to_string(), fields[2].clone()] } else { fields.to_vec() };
+                match row >= quoted_from {
+                    true => format!("\"{}\",{},{}\n", fields[0], fields[1], fields[2]),
+                    false => format!("{}\n", fields.join(",")),
+                }
+            });
+            std::fs::write(&path, text).unwrap();
+            match CsvIO::open_read(path.to_str().unwrap()).unwrap().read_columns::<f64>() {
+                Err(DataIoError::Parse { line, column, value }) => assert_eq!((line, column.as_str(), value.as_str()), (bad_row as u64 + 2, "a", "x")),
+                other => panic!("{:?}", other.map(|columns| columns.len())),
+            }
+        }
+        std::fs::write(&path, "a,b\n1,2\n3,4\n5\n").unwrap();
+        assert!(matches!(CsvIO::open_read(path.to_str().unwrap()).unwrap().read_columns::<f64>(), Err(DataIoError::Csv(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn column_stats_are_the_same_on_the_fast_path_and_the_csv_parser() {
+        let plain = temp_path("stats_plain.csv");
+        let quoted = temp_path("stats_quoted.csv");
+        let line = |row: usize, fields: &[String]| match row % 7 {
+            0 => format!("{},NA,{}\n", fields[0], fields[2]),
+            _ => format!("{}\n", fields.join(",")),
+        };
+        std::fs::write(&plain, numeric_text(70_000, 3, line)).unwrap();
+        // Quoting a value of the second record sends every later line of the second file to the csv parser
+        std::fs::write(&quoted, numeric_text(70_000, 3, |row, fields| match row {
+            1 => format!("\"{}\",{},{}\n", fields[0], fields[1], fields[2]),
+            _ => line(row, fields),
+        })).unwrap();
+        let table = CsvIO::open_read(plain.to_str().unwrap()).unwrap().column_stats(&StreamingStats::new()).unwrap();
+        let reference = CsvIO::open_read(quoted.to_str().unwrap()).unwrap().column_stats(&StreamingStats::new()).unwrap();
+        assert_eq!(table, reference);
+        assert_eq!(table.channels[1].count() + table.channels[1].missing(), 70_000);
+        std::fs::remove_file(&plain).unwrap();
+        std::fs::remove_file(&quoted).unwrap();
+    }
+
+    #[test]
+    #[ignore = "benchmark, run with --release -- --ignored --nocapture"]
+    fn numeric_reads_benchmark() {
+        use std::time::Instant;
+        let path = temp_path("benchmark.csv");
+        let mut text = String::from("time,ch0,ch1,ch2,ch3,ch4,ch5,ch6\n");
+        let mut rng = crate::processing::random::SeededRng::new(1);
+        for row in 0..2_000_000 {
+            text += &format!("{}", row as f64 / 30000.0);
+            for _ in 0..7 {
+                text += &format!(",{}", rng.next_f64() * 200.0 - 100.0);
+            }
+            text.push('\n');
+        }
+        std::fs::write(&path, &text).unwrap();
+        let best = |read: &dyn Fn() -> Vec<Vec<f64>>| (0..5).map(|_| {
+            let start = Instant::now();
+            assert_eq!(read()[7].len(), 2_000_000);
+            start.elapsed().as_secs_f64()
+        }).fold(f64::INFINITY, f64::min);
+        let csv_parser = best(&|| csv_parser_columns(&path, true));
+        let fast = best(&|| CsvIO::open_read(path.to_str().unwrap()).unwrap().read_columns().unwrap());
+        println!("{} MB: csv parser and str::parse {:.3} s, fast path {:.3} s, {:.2}x", text.len() / 1_000_000, csv_parser, fast, csv_parser / fast);
+        assert_eq!(bits(&csv_parser_columns(&path, true)), bits(&CsvIO::open_read(path.to_str().unwrap()).unwrap().read_columns().unwrap()));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn operations_that_cannot_spill_report_the_exceeded_budget() {
+        let path = temp_path("budget.csv");
+        let mut text = String::from("subject,condition,value\n");
+        for row in 0..200 {
+            text.push_str(&format!("s{},c{},{}\n", row % 20, row % 3, row));
+        }
+        std::fs::write(&path, &text).unwrap();
+        let output = temp_path("budget_pivot.csv");
+        let mut writer = CsvWriter::create(&output).unwrap();
+        let error = CsvReader::open(&path).unwrap().with_memory_budget(MemoryBudget::new(1024)).pivot("subject", "condition", "value", &mut writer, Some(Agg::Mean)).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::OutOfMemory);
+        let exceeded = error.get_ref().and_then(|inner| inner.downcast_ref::<crate::core::memory::MemoryBudgetExceeded>()).copied().unwrap();
+        assert_eq!(exceeded.budget, 1024);
+        assert!(exceeded.needed > 1024);
+        assert_eq!(CsvReader::open(&path).unwrap().with_memory_budget(MemoryBudget::new(1 << 20)).pivot("subject", "condition", "value", &mut writer, Some(Agg::Mean)).unwrap(), 20);
+
+        let mut csv_io = CsvIO::open_read(path.to_str().unwrap()).unwrap().with_memory_budget(MemoryBudget::new(1000));
+        match csv_io.robust_column_stats(0.1) {
+            // 3 columns of 8 bytes, so the 42nd row is the first over the budget
+            Err(DataIoError::Processing(ProcessingError::MemoryBudgetExceeded { needed, budget })) => assert_eq!((needed, budget), (1008, 1000)),
+            other => panic!("Expected an exceeded budget, got {:?}", other),
+        }
+        let table = CsvIO::open_read(path.to_str().unwrap()).unwrap().with_memory_budget(MemoryBudget::new(4800)).robust_column_stats(0.1).unwrap();
+        assert_eq!(table.channels[2].median, 99.5);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn reading_leaves_the_file_unchanged() {
+        let path = temp_path("modes_read.csv");
+        let text = "trial,rt\n1,0.512\n2,0.498\n";
+        std::fs::write(&path, text).unwrap();
+        let mut csv_io = CsvIO::open(path.to_str().unwrap(), OpenMode::Read).unwrap();
+        assert_eq!(csv_io.mode(), OpenMode::Read);
+        assert_eq!(csv_io.read_records().unwrap().len(), 2);
+        csv_io.close();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+        // Both readers see the records the file held before they were opened
+        let mut first = CsvIO::new(path.to_str().unwrap()).unwrap();
+        let mut second = CsvIO::open_read(path.to_str().unwrap()).unwrap();
+        assert_eq!(first.read_records().unwrap(), second.read_records().unwrap());
+        assert_eq!(first.mode(), OpenMode::Read);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), text);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn writing_truncates_and_appending_adds_records() {
+        let path = temp_path("modes_write.csv");
+        std::fs::write(&path, "old,header\nstale,row\n").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        assert_eq!(csv_io.mode(), OpenMode::Write);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "");
+        csv_io.write_records(vec![StringRecord::from(vec!["trial", "rt"]), StringRecord::from(vec!["1", "0.512"])]).unwrap();
+        csv_io.finish().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "trial,rt\n1,0.512\n");
+
+        // An appending CsvIO reads the existing records and writes after them
+        let mut csv_io = CsvIO::open_append(path.to_str().unwrap()).unwrap();
+        assert_eq!(csv_io.mode(), OpenMode::Append);
+        assert_eq!(csv_io.read_records().unwrap(), [StringRecord::from(vec!["1", "0.512"])]);
+        csv_io.write_record(StringRecord::from(vec!["2", "0.498"])).unwrap();
+        csv_io.save().unwrap();
+        csv_io.write_record(StringRecord::from(vec!["3", "0.601"])).unwrap();
+        csv_io.finish().unwrap();
+        let mut csv_io = CsvIO::open(path.to_str().unwrap(), OpenMode::Append).unwrap();
+        csv_io.write_record(StringRecord::from(vec!["4", "0.455"])).unwrap();
+        csv_io.save().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "trial,rt\n1,0.512\n2,0.498\n3,0.601\n4,0.455\n");
+
+        let (reader, writer) = CsvIO::open_append(path.to_str().unwrap()).unwrap().split();
+        assert_eq!(reader.unwrap().headers(), &StringRecord::from(vec!["trial", "rt"]));
+        assert!(writer.is_some());
+        let (reader, writer) = CsvIO::open_read(path.to_str().unwrap()).unwrap().split();
+        assert!(reader.is_some() && writer.is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn appending_creates_a_missing_file() {
+        let path = temp_path("modes_new.csv");
+        let _ = std::fs::remove_file(&path);
+        let mut csv_io = CsvIO::open_append(path.to_str().unwrap()).unwrap();
+        assert!(path.exists());
+        assert!(csv_io.read_records().unwrap().is_empty());
+        // The header row of a new file is written like any record
+        csv_io.write_records(vec![StringRecord::from(vec!["trial", "rt"]), StringRecord::from(vec!["1", "0.512"])]).unwrap();
+        csv_io.finish().unwrap();
+        let mut csv_io = CsvIO::open_read(path.to_str().unwrap()).unwrap();
+        assert_eq!(csv_io.read_records().unwrap(), [StringRecord::from(vec!["1", "0.512"])]);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(CsvIO::open_read(path.to_str().unwrap()), Err(DataIoError::FileNotFound(_))));
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn read_columns_parses_each_column_into_its_type() {
+        let path = temp_path("typed.csv");
+        std::fs::write(&path, "time,ch1,ch2\n0.000, 1.5,-2\n0.001,2.5 ,-3e0\n0.002,3.5,4\n").unwrap();
+        let mut csv_io = CsvIO::open_read(path.to_str().unwrap()).unwrap();
+        assert_eq!(csv_io.read_columns::<f64>().unwrap(), [vec![0.0, 0.001, 0.002], vec![1.5, 2.5, 3.5], vec![-2.0, -3.0, 4.0]]);
+        let columns = CsvIO::open_read(path.to_str().unwrap()).unwrap().read_columns::<f32>().unwrap();
+        assert_eq!(columns[1], [1.5f32, 2.5, 3.5]);
+        // Only the remaining records are read
+        let mut csv_io = CsvIO::open_read(path.to_str().unwrap()).unwrap();
+        csv_io.read_record().unwrap();
+        assert_eq!(csv_io.read_columns::<String>().unwrap()[2], ["-3e0", "4"]);
+        assert!(csv_io.read_columns::<f64>().unwrap().iter().all(Vec::is_empty));
+
+        std::fs::write(&path, "trial,count\n1,250\n2,300\n").unwrap();
+        assert_eq!(CsvIO::open_read(path.to_str().unwrap()).unwrap().read_columns::<u32>().unwrap(), [vec![1, 2], vec![250, 300]]);
+        match CsvIO::open_read(path.to_str().unwrap()).unwrap().read_columns::<u8>() {
+            Err(DataIoError::Parse { line, column, value }) => assert_eq!((line, column.as_str(), value.as_str()), (3, "count", "300")),
+            other => panic!("Expected a parse error, got {:?}", other),
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_columns_names_the_offending_line_on_every_path() {
+        let path = temp_path("typed_errors.csv");
+        let mut text = String::from("time,lfp\n");
+        (0..50).for_each(|row| text.push_str(&format!("{},{}\n", row, row)));
+        // A quoted field hands the rest of the file to the csv parser
+        text.push_str("50,\"51\"\n51,\n");
+        std::fs::write(&path, &text).unwrap();
+        let error = CsvIO::open_read(path.to_str().unwrap()).unwrap().read_columns::<f64>().unwrap_err();
+        assert_eq!(error.to_string(), "Value '' of column 'lfp' at line 53 cannot be parsed");
+
+        for (bad_row, expected_line) in [(0, 2), (1, 3), (30, 32)] {
+            let mut text = String::from("time,lfp\n");
+            (0..40).for_each(|row| text.push_str(&format!("{},{}\n", row, if row == bad_row { "n/a" } else { "0.5" })));
+            std::fs::write(&path, &text).unwrap();
+            match CsvIO::open_read(path.to_str().unwrap()).unwrap().read_columns::<f64>() {
+                Err(DataIoError::Parse { line, column, value }) => assert_eq!((line, column.as_str(), value.as_str()), (expected_line, "lfp", "n/a")),
+                other => panic!("Expected a parse error, got {:?}", other),
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn read_into_deserializes_records_by_header_name() {
+        #[derive(serde::Deserialize, Debug, PartialEq)]
+        struct Trial {
+            trial: u32,
+            condition: String,
+            reaction_time: Option<f64>,
+        }
+        let path = temp_path("typed_serde.csv");
+        std::fs::write(&path, "condition,trial,reaction_time\ngo,1,0.512\nnogo,2,\n").unwrap();
+        let trials: Vec<Trial> = CsvIO::open_read(path.to_str().unwrap()).unwrap().read_into().unwrap();
+        assert_eq!(trials, [
+            Trial { trial: 1, condition: "go".to_string(), reaction_time: Some(0.512) },
+            Trial { trial: 2, condition: "nogo".to_string(), reaction_time: None },
+        ]);
+
+        std::fs::write(&path, "condition,trial,reaction_time\ngo,1,0.512\ngo,two,0.4\n").unwrap();
+        let error = CsvIO::open_read(path.to_str().unwrap()).unwrap().read_into::<Trial>().unwrap_err();
+        assert_eq!(error.to_string(), "Value 'two' of column 'trial' at line 3 cannot be parsed");
+        std::fs::write(&path, "condition,reaction_time\ngo,0.512\n").unwrap();
+        let error = CsvIO::open_read(path.to_str().unwrap()).unwrap().read_into::<Trial>().unwrap_err();
+        assert!(matches!(error, DataIoError::Csv(_)) && error.to_string().contains("trial"), "{}", error);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,322 @@
+// A module to convert recordings, spike trains and events to and from Polars DataFrames
+
+// Written by Amin Alam in 2024
+
+use std::collections::BTreeMap;
+use polars::prelude::{Column, DataFrame, DataType, NamedFrom, PolarsError, PolarsResult, Series};
+use crate::data_io::float_format::FloatFormat;
+
+/// Builds a DataFrame with a time column and one column per channel
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel
+/// * `names` - The name of each channel
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample in seconds
+///
+/// # Returns
+///
+/// The DataFrame with a Float64 `time` column followed by one Float64 column per channel, or
+/// an error if the channels and names differ in number or length, or a name is repeated or
+/// is `time`
+///
+/// # Examples
+///
+/// ```
+/// let df = recording_to_dataframe(&channels, &names, 1000.0, 0.0)?;
+/// ```
+///
+/// # Note
+///
+/// NaN samples are kept as NaN rather than converted to nulls
+///
+pub fn recording_to_dataframe(channels: &[Vec<f64>], names: &[String], sampling_rate: f64, start_time: f64) -> PolarsResult<DataFrame> {
+    let length = channels.first().map_or(0, |channel| channel.len());
+    if channels.len() != names.len() || channels.iter().any(|channel| channel.len() != length) {
+        return Err(PolarsError::ShapeMismatch(format!("Expected {} channels of equal length", names.len()).into()));
+    }
+    if !(sampling_rate > 0.0 && sampling_rate.is_finite()) {
+        return Err(PolarsError::ComputeError(format!("Sampling rate must be positive, got {}", sampling_rate).into()));
+    }
+    let mut all_names = vec!["time"];
+    all_names.extend(names.iter().map(String::as_str));
+    check_unique_names(&all_names)?;
+    let times: Vec<f64> = (0..length).map(|i| start_time + i as f64 / sampling_rate).collect();
+    let mut columns: Vec<Column> = vec![Series::new("time".into(), times).into()];
+    columns.extend(names.iter().zip(channels).map(|(name, channel)| Column::from(Series::new(name.as_str().into(), channel.as_slice()))));
+    DataFrame::new(columns)
+}
+
+/// Builds a long-format DataFrame of spike trains with one row per spike
+///
+/// # Arguments
+///
+/// * `trains` - The spike times of each unit in seconds
+/// * `names` - The name of each unit
+///
+/// # Returns
+///
+/// The DataFrame with a String `unit` column and a Float64 `time` column, ordered by unit and
+/// then as given, or an error if the trains and names differ in number or a name is repeated
+///
+/// # Examples
+///
+/// ```
+/// let df = spike_trains_to_dataframe(&trains, &names)?;
+/// ```
+///
+pub fn spike_trains_to_dataframe(trains: &[Vec<f64>], names: &[String]) -> PolarsResult<DataFrame> {
+    if trains.len() != names.len() {
+        return Err(PolarsError::ShapeMismatch(format!("Got {} trains but {} names", trains.len(), names.len()).into()));
+    }
+    check_unique_names(&names.iter().map(String::as_str).collect::<Vec<_>>())?;
+    let units: Vec<&str> = trains.iter().zip(names).flat_map(|(train, name)| std::iter::repeat_n(name.as_str(), train.len())).collect();
+    let times: Vec<f64> = trains.iter().flatten().copied().collect();
+    DataFrame::new(vec![Series::new("unit".into(), units).into(), Series::new("time".into(), times).into()])
+}
+
+/// Reads spike trains back from a long-format DataFrame
+///
+/// # Arguments
+///
+/// * `df` - The DataFrame with a `unit` column of any dtype that casts to String and a numeric `time` column
+///
+/// # Returns
+///
+/// The name of each unit in sorted order and its spike times in row order, or an error if a
+/// column is missing, cannot be cast or holds nulls
+///
+/// # Examples
+///
+/// ```
+/// let (names, trains) = spike_trains_from_dataframe(&df)?;
+/// ```
+///
+pub fn spike_trains_from_dataframe(df: &DataFrame) -> PolarsResult<(Vec<String>, Vec<Vec<f64>>)> {
+    let grouped = group_times(df, "unit")?;
+    Ok(grouped.into_iter().unzip())
+}
+
+/// Builds a DataFrame of events with one row per event
+///
+/// # Arguments
+///
+/// * `times` - The time of each event in seconds
+/// * `labels` - The label of each event, e.g. the stimulus condition
+///
+/// # Returns
+///
+/// The DataFrame with a Float64 `time` column and a String `label` column, or an error if
+/// the times and labels differ in length
+///
+/// # Examples
+///
+/// ```
+/// let df = events_to_dataframe(&onsets, &conditions)?;
+/// ```
+///
+pub fn events_to_dataframe(times: &[f64], labels: &[String]) -> PolarsResult<DataFrame> {
+    if times.len() != labels.len() {
+        return Err(PolarsError::ShapeMismatch(format!("Got {} times but {} labels", times.len(), labels.len()).into()));
+    }
+    let labels: Vec<&str> = labels.iter().map(String::as_str).collect();
+    DataFrame::new(vec![Series::new("time".into(), times).into(), Series::new("label".into(), labels).into()])
+}
+
+/// Reads events back from a DataFrame
+///
+/// # Arguments
+///
+/// * `df` - The DataFrame with a numeric `time` column and a `label` column of any dtype that casts to String
+///
+/// # Returns
+///
+/// The time and label of each event in row order, or an error if a column is missing, cannot
+/// be cast or holds nulls
+///
+/// # Examples
+///
+/// ```
+/// let (onsets, conditions) = events_from_dataframe(&df)?;
+/// ```
+///
+pub fn events_from_dataframe(df: &DataFrame) -> PolarsResult<(Vec<f64>, Vec<String>)> {
+    let times = float_values(df, "time")?;
+    let labels = string_values(df, "label")?;
+    Ok((times, labels))
+}
+
+/// Returns an error naming the first repeated column name
+pub(crate) fn check_unique_names(names: &[&str]) -> PolarsResult<()> {
+    let mut seen = BTreeMap::new();
+    for (index, name) in names.iter().enumerate() {
+        if let Some(first) = seen.insert(*name, index) {
+            return Err(PolarsError::Duplicate(
+                format!("Column name `{}` is used by columns {} and {}", name, first, index).into(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Builds a column from CSV fields, inferring its dtype and reading empty fields as nulls
+pub(crate) fn column_from_fields(name: &str, fields: &[String]) -> Column {
+    let present = || fields.iter().map(|field| field.trim()).filter(|field| !field.is_empty());
+    let parse_all = |parse: &dyn Fn(&str) -> bool| present().all(parse);
+    fn optional(field: &str) -> Option<&str> {
+        Some(field.trim()).filter(|field| !field.is_empty())
+    }
+    let series = if parse_all(&|field| field.parse::<i64>().is_ok()) {
+        let values: Vec<Option<i64>> = fields.iter().map(|field| optional(field).and_then(|field| field.parse().ok())).collect();
+        Series::new(name.into(), values)
+    } else if parse_all(&|field| field.parse::<f64>().is_ok()) {
+        let values: Vec<Option<f64>> = fields.iter().map(|field| optional(field).and_then(|field| field.parse().ok())).collect();
+        Series::new(name.into(), values)
+    } else if parse_all(&|field| field == "true" || field == "false") {
+        let values: Vec<Option<bool>> = fields.iter().map(|field| optional(field).map(|field| field == "true")).collect();
+        Series::new(name.into(), values)
+    } else {
+        // Strings keep their surrounding whitespace, and only fully empty fields are nulls
+        let values: Vec<Option<&str>> = fields.iter().map(|field| if field.is_empty() { None } else { Some(field.as_str()) }).collect();
+        Series::new(name.into(), values)
+    };
+    series.into()
+}
+
+/// Formats the values of a column as CSV fields, with nulls as empty fields and floats in the given format
+pub(crate) fn column_to_fields(column: &Column, float_format: &FloatFormat) -> PolarsResult<Vec<String>> {
+    match column.dtype() {
+        DataType::Boolean
+        | DataType::Int8
+        | DataType::Int16
+        | DataType::Int32
+        | DataType::Int64
+        | DataType::UInt8
+        | DataType::UInt16
+        | DataType::UInt32
+        | DataType::UInt64
+        | DataType::Float32
+        | DataType::Float64
+        | DataType::String => {}
+        other => {
+            return Err(PolarsError::InvalidOperation(
+                format!("Column `{}` has dtype {}, which cannot be written to CSV", column.name(), other).into(),
+            ))
+        }
+    }
+    if matches!(column.dtype(), DataType::Float32 | DataType::Float64) {
+        let values = column.as_materialized_series().cast(&DataType::Float64)?;
+        return Ok(values.f64()?.into_iter().map(|value| value.map_or(String::new(), |value| float_format.format(value))).collect());
+    }
+    let strings = column.as_materialized_series().cast(&DataType::String)?;
+    Ok(strings.str()?.into_iter().map(|value| value.unwrap_or("").to_string()).collect())
+}
+
+/// Groups the times of a DataFrame by the values of a key column, in sorted key order
+fn group_times(df: &DataFrame, key: &str) -> PolarsResult<BTreeMap<String, Vec<f64>>> {
+    let keys = string_values(df, key)?;
+    let times = float_values(df, "time")?;
+    let mut grouped: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for (key, time) in keys.into_iter().zip(times) {
+        grouped.entry(key).or_default().push(time);
+    }
+    Ok(grouped)
+}
+
+/// Returns the values of a column cast to Float64, or an error if it holds nulls
+fn float_values(df: &DataFrame, name: &str) -> PolarsResult<Vec<f64>> {
+    let values = df.column(name)?.as_materialized_series().cast(&DataType::Float64)?;
+    values
+        .f64()?
+        .into_iter()
+        .map(|value| value.ok_or_else(|| PolarsError::ComputeError(format!("Column `{}` holds nulls", name).into())))
+        .collect()
+}
+
+/// Returns the values of a column cast to String, or an error if it holds nulls
+fn string_values(df: &DataFrame, name: &str) -> PolarsResult<Vec<String>> {
+    let values = df.column(name)?.as_materialized_series().cast(&DataType::String)?;
+    values
+        .str()?
+        .into_iter()
+        .map(|value| {
+            value
+                .map(str::to_string)
+                .ok_or_else(|| PolarsError::ComputeError(format!("Column `{}` holds nulls", name).into()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_io::csv::CsvIO;
+
+    /// Writes a DataFrame to a csv file and reads it back
+    fn through_csv(name: &str, df: &DataFrame) -> DataFrame {
+        let path = std::env::temp_dir().join(format!("neurorust-dataframe-{}-{}.csv", std::process::id(), name)).to_string_lossy().into_owned();
+        std::fs::write(&path, "").unwrap();
+        let mut output = CsvIO::open_write(&path).unwrap();
+        output.write_dataframe(df).unwrap();
+        output.save().unwrap();
+        let read = CsvIO::open_read(&path).unwrap().to_dataframe().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        read
+    }
+
+    #[test]
+    fn recordings_round_trip_through_csv() {
+        let channels = vec![vec![0.5, -1.25, f64::NAN, 3.0], vec![1.0, 2.0, 3.0, 4.5]];
+        let names = vec!["ch1".to_string(), "ch2".to_string()];
+        let df = recording_to_dataframe(&channels, &names, 1000.0, 2.0).unwrap();
+        assert_eq!(df.shape(), (4, 3));
+        assert_eq!(float_values(&df, "time").unwrap(), vec![2.0, 2.001, 2.002, 2.003]);
+        let read = through_csv("recording", &df);
+        assert_eq!(read.get_column_names(), ["time", "ch1", "ch2"]);
+        assert!(read.get_columns().iter().all(|column| column.dtype() == &DataType::Float64));
+        assert_eq!(float_values(&read, "time").unwrap(), float_values(&df, "time").unwrap());
+        let ch1 = float_values(&read, "ch1").unwrap();
+        assert_eq!(ch1[..2], [0.5, -1.25]);
+        assert!(ch1[2].is_nan());
+        assert_eq!(float_values(&read, "ch2").unwrap(), channels[1]);
+    }
+
+    #[test]
+    fn spike_trains_and_events_round_trip_through_csv() {
+        let trains = vec![vec![0.1, 0.05], vec![], vec![1.5]];
+        let names = vec!["u2".to_string(), "u0".to_string(), "u1".to_string()];
+        let read = through_csv("spikes", &spike_trains_to_dataframe(&trains, &names).unwrap());
+        let (units, times) = spike_trains_from_dataframe(&read).unwrap();
+        // Units without spikes have no rows, and the units come back in sorted order
+        assert_eq!(units, ["u1", "u2"]);
+        assert_eq!(times, vec![vec![1.5], vec![0.1, 0.05]]);
+
+        let labels = vec!["go".to_string(), "7".to_string(), "go".to_string()];
+        let read = through_csv("events", &events_to_dataframe(&[0.5, 1.0, 2.25], &labels).unwrap());
+        // A label column of numbers is read back as integers, which cast to the same strings
+        assert_eq!(events_from_dataframe(&read).unwrap(), (vec![0.5, 1.0, 2.25], labels));
+    }
+
+    #[test]
+    fn csv_fields_infer_dtypes_with_empty_fields_as_nulls() {
+        let fields = |values: &[&str]| values.iter().map(|value| value.to_string()).collect::<Vec<String>>();
+        let integers = column_from_fields("a", &fields(&["1", " ", "-3"]));
+        assert_eq!(integers.dtype(), &DataType::Int64);
+        assert_eq!(integers.null_count(), 1);
+        assert_eq!(column_from_fields("b", &fields(&["1", "2.5", ""])).dtype(), &DataType::Float64);
+        assert_eq!(column_from_fields("c", &fields(&["true", "", "false"])).dtype(), &DataType::Boolean);
+        let strings = column_from_fields("d", &fields(&[" x", "", "1"]));
+        assert_eq!(strings.dtype(), &DataType::String);
+        assert_eq!(column_to_fields(&strings, &FloatFormat::default()).unwrap(), fields(&[" x", "", "1"]));
+    }
+
+    #[test]
+    fn repeated_or_reserved_names_are_errors() {
+        let channels = vec![vec![1.0], vec![2.0]];
+        assert!(recording_to_dataframe(&channels, &["a".to_string(), "a".to_string()], 1000.0, 0.0).is_err());
+        assert!(recording_to_dataframe(&channels, &["time".to_string(), "b".to_string()], 1000.0, 0.0).is_err());
+        assert!(recording_to_dataframe(&channels, &["a".to_string()], 1000.0, 0.0).is_err());
+        assert!(spike_trains_to_dataframe(&[vec![1.0]], &["u".to_string(), "v".to_string()]).is_err());
+    }
+}
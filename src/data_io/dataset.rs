@@ -0,0 +1,696 @@
+// A module to walk the data files of a study listed in a manifest csv file
+
+// Written by Amin Alam in 2024
+
+use std::convert::Infallible;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use csv::StringRecord;
+use crate::data_io::csv::{CsvIO, CsvReader};
+use crate::data_io::error::DataIoError;
+use crate::processing::parallel::{try_map_tasks, worker_count};
+
+/// The columns every manifest must have
+pub const MANIFEST_COLUMNS: [&str; 5] = ["subject", "session", "task", "path", "format"];
+
+/// The errors returned while loading a manifest
+///
+/// # Arguments
+///
+/// * `Io` - The manifest could not be read
+/// * `MissingColumns` - The manifest lacks some of the required columns, all listed
+/// * `MissingFiles` - Some of the referenced files do not exist, all listed
+/// * `InvalidRow` - A row of the manifest is malformed, with its line number
+///
+/// # Examples
+///
+/// ```
+/// if let Err(ManifestError::MissingFiles(paths)) = manifest.require_files() {
+///     eprintln!("{} files are missing", paths.len());
+/// }
+/// ```
+#[derive(Debug)]
+pub enum ManifestError {
+    Io(io::Error),
+    MissingColumns(Vec<String>),
+    MissingFiles(Vec<PathBuf>),
+    InvalidRow { line: u64, message: String },
+}
+
+impl fmt::Display for ManifestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ManifestError::Io(error) => write!(f, "I/O error: {}", error),
+            ManifestError::MissingColumns(columns) => write!(f, "Manifest is missing the columns {}", columns.join(", ")),
+            ManifestError::MissingFiles(paths) => {
+                let paths: Vec<String> = paths.iter().map(|path| path.display().to_string()).collect();
+                write!(f, "{} files of the manifest do not exist: {}", paths.len(), paths.join(", "))
+            }
+            ManifestError::InvalidRow { line, message } => write!(f, "Line {} of the manifest: {}", line, message),
+        }
+    }
+}
+
+impl Error for ManifestError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ManifestError::Io(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ManifestError {
+    fn from(error: io::Error) -> Self {
+        ManifestError::Io(error)
+    }
+}
+
+/// A data file listed in a manifest
+///
+/// # Arguments
+///
+/// * `subject` - The subject the file belongs to
+/// * `session` - The session the file belongs to
+/// * `task` - The task recorded in the file
+/// * `path` - The path to the file, resolved against the directory of the manifest if relative
+/// * `format` - The format of the file as written in the manifest, e.g. `csv`
+/// * `line` - The line of the manifest the entry was read from
+/// * `exists` - Whether the file existed when the manifest was loaded
+///
+/// # Examples
+///
+/// ```
+/// for entry in manifest.iter() {
+///     println!("{}/{}/{}: {}", entry.subject, entry.session, entry.task, entry.path.display());
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestEntry {
+    pub subject: String,
+    pub session: String,
+    pub task: String,
+    pub path: PathBuf,
+    pub format: String,
+    pub line: u64,
+    pub exists: bool,
+}
+
+/// The data files of a study
+///
+/// # Arguments
+///
+/// * `directory` - The directory of the manifest, against which relative paths are resolved
+/// * `entries` - The entries of the manifest, in file order
+///
+/// # Examples
+///
+/// ```
+/// let manifest = Manifest::from_reader(&mut CsvReader::open("study/manifest.csv")?)?;
+/// let results = manifest.filter(None, None, Some("rest")).map(|entry| analyse(&entry.path));
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub directory: PathBuf,
+    pub entries: Vec<ManifestEntry>,
+}
+
+/// Implementation of the Manifest struct
+///
+/// # Methods
+///
+/// * `load` - Loads a manifest from the remaining records of a CsvIO object
+/// * `from_reader` - Loads a manifest from the remaining records of a CsvReader
+/// * `iter` - Iterates over the entries
+/// * `missing` - Returns the entries whose file does not exist
+/// * `require_files` - Checks that every referenced file exists
+/// * `filter` - Selects the entries of a subject, session or task
+/// * `without_completed` - Drops the entries that succeeded in a previous run
+/// * `map` - Processes the entries in parallel, capturing the error of each
+impl Manifest {
+    /// Loads a manifest from the remaining records of a CsvIO object
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object of the manifest
+    ///
+    /// # Returns
+    ///
+    /// The Manifest, or an error if a required column is missing or a record cannot be read
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let manifest = Manifest::load(&mut csv_io)?;
+    /// ```
+    ///
+    /// # See
+    ///
+    /// * `from_reader` - Loads a manifest from a CsvReader
+    ///
+    pub fn load(csv_io: &mut CsvIO) -> Result<Self, ManifestError> {
+        Self::from_reader(csv_io.reader_mut().map_err(io::Error::from)?)
+    }
+
+    /// Loads a manifest from the remaining records of a CsvReader
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The reader of the manifest
+    ///
+    /// # Returns
+    ///
+    /// The Manifest, or an error listing all missing required columns, or an error if a
+    /// record cannot be read or has an empty path
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let manifest = Manifest::from_reader(&mut CsvReader::open("study/manifest.csv")?)?;
+    /// println!("{} of {} files are missing", manifest.missing().len(), manifest.entries.len());
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Columns beyond `MANIFEST_COLUMNS` are ignored. Whether each file exists is recorded
+    /// rather than checked, so that `require_files` can list all missing files at once and
+    /// `map` can report them per entry.
+    ///
+    pub fn from_reader(reader: &mut CsvReader) -> Result<Self, ManifestError> {
+        let headers = reader.headers();
+        let missing: Vec<String> = MANIFEST_COLUMNS.iter().filter(|column| !headers.iter().any(|header| header == **column)).map(|column| column.to_string()).collect();
+        if !missing.is_empty() {
+            return Err(ManifestError::MissingColumns(missing));
+        }
+        let columns: Vec<usize> = MANIFEST_COLUMNS.iter().map(|column| headers.iter().position(|header| header == *column).unwrap_or_default()).collect();
+        let directory = reader.path().parent().map(Path::to_path_buf).unwrap_or_default();
+
+        let mut entries = Vec::new();
+        while let Some(record) = reader.read_record()? {
+            let line = record.position().map_or(0, |position| position.line());
+            let field = |column: usize| record.get(columns[column]).unwrap_or("").trim().to_string();
+            let path = field(3);
+            if path.is_empty() {
+                return Err(ManifestError::InvalidRow { line, message: "The path is empty".to_string() });
+            }
+            let path = directory.join(path);
+            entries.push(ManifestEntry { subject: field(0), session: field(1), task: field(2), exists: path.is_file(), path, format: field(4), line });
+        }
+        Ok(Self { directory, entries })
+    }
+
+    /// Iterates over the entries
+    ///
+    /// # Returns
+    ///
+    /// An iterator over the entries in file order
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let subjects: BTreeSet<&str> = manifest.iter().map(|entry| entry.subject.as_str()).collect();
+    /// ```
+    ///
+    pub fn iter(&self) -> std::slice::Iter<'_, ManifestEntry> {
+        self.entries.iter()
+    }
+
+    /// Returns the entries whose file does not exist
+    ///
+    /// # Returns
+    ///
+    /// The entries whose file did not exist when the manifest was loaded
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// for entry in manifest.missing() {
+    ///     eprintln!("Missing {}", entry.path.display());
+    /// }
+    /// ```
+    ///
+    pub fn missing(&self) -> Vec<&ManifestEntry> {
+        self.entries.iter().filter(|entry| !entry.exists).collect()
+    }
+
+    /// Checks that every referenced file exists
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error listing every missing file
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// manifest.require_files()?;
+    /// ```
+    ///
+    pub fn require_files(&self) -> Result<(), ManifestError> {
+        let missing: Vec<PathBuf> = self.missing().into_iter().map(|entry| entry.path.clone()).collect();
+        if missing.is_empty() { Ok(()) } else { Err(ManifestError::MissingFiles(missing)) }
+    }
+
+    /// Selects the entries of a subject, session or task
+    ///
+    /// # Arguments
+    ///
+    /// * `subject` - The subject to keep, or None for all
+    /// * `session` - The session to keep, or None for all
+    /// * `task` - The task to keep, or None for all
+    ///
+    /// # Returns
+    ///
+    /// A Manifest with the matching entries
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let rest = manifest.filter(Some("sub-01"), None, Some("rest"));
+    /// ```
+    ///
+    pub fn filter(&self, subject: Option<&str>, session: Option<&str>, task: Option<&str>) -> Manifest {
+        let matches = |wanted: Option<&str>, value: &str| wanted.is_none_or(|wanted| wanted == value);
+        Manifest {
+            directory: self.directory.clone(),
+            entries: self
+                .entries
+                .iter()
+                .filter(|entry| matches(subject, &entry.subject) && matches(session, &entry.session) && matches(task, &entry.task))
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Drops the entries that succeeded in a previous run
+    ///
+    /// # Arguments
+    ///
+    /// * `results` - The reader of the results csv file written by `ManifestResults::to_csv`
+    ///
+    /// # Returns
+    ///
+    /// A Manifest with the entries whose path has no `ok` row in the results, or an error if
+    /// the results cannot be read or lack the `path` or `status` column
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let remaining = manifest.without_completed(&mut CsvReader::open("results.csv")?)?;
+    /// let results = remaining.map(process);
+    /// ```
+    ///
+    pub fn without_completed(&self, results: &mut CsvReader) -> Result<Manifest, ManifestError> {
+        let headers = results.headers();
+        let column = |name: &str| headers.iter().position(|header| header == name);
+        let (path, status) = match (column("path"), column("status")) {
+            (Some(path), Some(status)) => (path, status),
+            (path, _) => {
+                let missing = if path.is_none() { "path" } else { "status" };
+                return Err(ManifestError::MissingColumns(vec![missing.to_string()]));
+            }
+        };
+        let mut completed = Vec::new();
+        while let Some(record) = results.read_record()? {
+            if record.get(status) == Some(EntryStatus::Ok.name()) {
+                completed.push(PathBuf::from(record.get(path).unwrap_or("")));
+            }
+        }
+        Ok(Manifest {
+            directory: self.directory.clone(),
+            entries: self.entries.iter().filter(|entry| !completed.contains(&entry.path)).cloned().collect(),
+        })
+    }
+
+    /// Processes the entries in parallel, capturing the error of each
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - The function called on every entry whose file exists
+    ///
+    /// # Returns
+    ///
+    /// The ManifestResults, with one outcome per entry in manifest order
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let results = manifest.map(|entry| -> Result<f64, ProcessingError> {
+    ///     let channels = load_channels(&entry.path)?;
+    ///     Ok(band_power(&channels[0], 1000.0, &[ALPHA], BandPowerMethod::Welch, true)?[0])
+    /// });
+    /// results.to_csv(&mut csv_io)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The error of an entry, or the message of a panic in `f`, is stored in its outcome and
+    /// the other entries carry on, so one corrupt file cannot stop a run. Entries whose file
+    /// is missing get the `missing` status without calling `f`.
+    ///
+    pub fn map<T, E, F>(&self, f: F) -> ManifestResults<T>
+    where
+        T: Send,
+        E: fmt::Display,
+        F: Fn(&ManifestEntry) -> Result<T, E> + Sync,
+    {
+        let n_entries = self.entries.len();
+        let outcomes = try_map_tasks(vec![(); worker_count(n_entries)], n_entries, |_, index| {
+            let entry = &self.entries[index];
+            let start = Instant::now();
+            let (status, value, error) = if !entry.exists {
+                (EntryStatus::Missing, None, Some(format!("{} does not exist", entry.path.display())))
+            } else {
+                match catch_unwind(AssertUnwindSafe(|| f(entry))) {
+                    Ok(Ok(value)) => (EntryStatus::Ok, Some(value), None),
+                    Ok(Err(error)) => (EntryStatus::Failed, None, Some(error.to_string())),
+                    Err(panic) => {
+                        let message = panic
+                            .downcast_ref::<&str>()
+                            .map(|message| message.to_string())
+                            .or_else(|| panic.downcast_ref::<String>().cloned())
+                            .unwrap_or_else(|| "Unknown panic".to_string());
+                        (EntryStatus::Panicked, None, Some(message))
+                    }
+                }
+            };
+            Ok::<_, Infallible>(EntryOutcome { entry: entry.clone(), status, seconds: start.elapsed().as_secs_f64(), value, error })
+        });
+        ManifestResults { outcomes: outcomes.unwrap_or_else(|(_, never)| match never {}) }
+    }
+}
+
+/// The status of an entry processed by `Manifest::map`
+///
+/// # Arguments
+///
+/// * `Ok` - The function returned a value
+/// * `Failed` - The function returned an error
+/// * `Panicked` - The function panicked
+/// * `Missing` - The file does not exist, so the function was not called
+///
+/// # Examples
+///
+/// ```
+/// let failed = results.outcomes.iter().filter(|outcome| outcome.status != EntryStatus::Ok).count();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum EntryStatus {
+    Ok,
+    Failed,
+    Panicked,
+    Missing,
+}
+
+/// Implementation of the EntryStatus enum
+///
+/// # Methods
+///
+/// * `name` - Returns the name of the status
+impl EntryStatus {
+    /// Returns the name of the status
+    ///
+    /// # Returns
+    ///
+    /// The name in lower case, as written to the results csv file
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(EntryStatus::Panicked.name(), "panicked");
+    /// ```
+    ///
+    pub fn name(&self) -> &'static str {
+        match self {
+            EntryStatus::Ok => "ok",
+            EntryStatus::Failed => "failed",
+            EntryStatus::Panicked => "panicked",
+            EntryStatus::Missing => "missing",
+        }
+    }
+}
+
+/// The outcome of an entry processed by `Manifest::map`
+///
+/// # Arguments
+///
+/// * `entry` - The entry
+/// * `status` - How processing the entry ended
+/// * `seconds` - The time spent on the entry in seconds
+/// * `value` - The value returned by the function, if it succeeded
+/// * `error` - The error or panic message, if it did not
+///
+/// # Examples
+///
+/// ```
+/// for outcome in &results.outcomes {
+///     println!("{}: {} in {:.1} s", outcome.entry.path.display(), outcome.status.name(), outcome.seconds);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct EntryOutcome<T> {
+    pub entry: ManifestEntry,
+    pub status: EntryStatus,
+    pub seconds: f64,
+    pub value: Option<T>,
+    pub error: Option<String>,
+}
+
+/// The outcomes of `Manifest::map`
+///
+/// # Arguments
+///
+/// * `outcomes` - The outcome of every entry, in manifest order
+///
+/// # Examples
+///
+/// ```
+/// let results = manifest.map(process);
+/// let values: Vec<&f64> = results.values().collect();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ManifestResults<T> {
+    pub outcomes: Vec<EntryOutcome<T>>,
+}
+
+/// Implementation of the ManifestResults struct
+///
+/// # Methods
+///
+/// * `values` - Iterates over the values of the entries that succeeded
+/// * `failures` - Returns the outcomes of the entries that did not succeed
+/// * `to_csv` - Writes one `subject,session,task,path,format,status,seconds,error` row per entry
+impl<T> ManifestResults<T> {
+    /// Iterates over the values of the entries that succeeded
+    ///
+    /// # Returns
+    ///
+    /// An iterator over the values in manifest order
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let total: f64 = results.values().sum();
+    /// ```
+    ///
+    pub fn values(&self) -> impl Iterator<Item = &T> {
+        self.outcomes.iter().filter_map(|outcome| outcome.value.as_ref())
+    }
+
+    /// Returns the outcomes of the entries that did not succeed
+    ///
+    /// # Returns
+    ///
+    /// The outcomes whose status is not `Ok`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// for outcome in results.failures() {
+    ///     eprintln!("{}: {}", outcome.entry.path.display(), outcome.error.as_deref().unwrap_or(""));
+    /// }
+    /// ```
+    ///
+    pub fn failures(&self) -> Vec<&EntryOutcome<T>> {
+        self.outcomes.iter().filter(|outcome| outcome.status != EntryStatus::Ok).collect()
+    }
+
+    /// Writes one `subject,session,task,path,format,status,seconds,error` row per entry
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// results.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. The rows are not flushed to disk until `save` is called.
+    /// The file can be passed to `Manifest::without_completed` to resume an interrupted run.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["subject", "session", "task", "path", "format", "status", "seconds", "error"]))?;
+        for outcome in &self.outcomes {
+            let entry = &outcome.entry;
+            csv_io.write_record(StringRecord::from(vec![
+                entry.subject.clone(),
+                entry.session.clone(),
+                entry.task.clone(),
+                entry.path.display().to_string(),
+                entry.format.clone(),
+                outcome.status.name().to_string(),
+                float_format.format(outcome.seconds),
+                outcome.error.clone().unwrap_or_default(),
+            ]))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A study directory with a manifest listing four files, one of them missing and one corrupt
+    ///
+    /// ```text
+    /// manifest.csv
+    /// sub-01/rest.csv    ok
+    /// sub-01/task.csv    corrupt, a value is not a number
+    /// sub-02/rest.csv    ok, listed by its absolute path
+    /// sub-02/task.csv    missing
+    /// ```
+    fn fixture_tree(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("neurorust-dataset-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("sub-01")).unwrap();
+        fs::create_dir_all(root.join("sub-02")).unwrap();
+        fs::write(root.join("sub-01/rest.csv"), "lfp\n1.5\n2.5\n").unwrap();
+        fs::write(root.join("sub-01/task.csv"), "lfp\n1.0\nclipped\n").unwrap();
+        fs::write(root.join("sub-02/rest.csv"), "lfp\n4\n").unwrap();
+        let absolute = root.join("sub-02/rest.csv");
+        let manifest = format!(
+            "subject,session,task,path,format,notes\nsub-01,ses-1,rest,sub-01/rest.csv,csv,\nsub-01,ses-1,task,sub-01/task.csv,csv,\nsub-02,ses-1,rest,{},csv,retest\nsub-02,ses-2,task, sub-02/task.csv ,csv,\n",
+            absolute.display()
+        );
+        fs::write(root.join("manifest.csv"), manifest).unwrap();
+        root
+    }
+
+    fn load(root: &Path) -> Manifest {
+        Manifest::from_reader(&mut CsvReader::open(root.join("manifest.csv")).unwrap()).unwrap()
+    }
+
+    /// Sums the `lfp` column of a data file
+    fn sum_file(entry: &ManifestEntry) -> Result<f64, String> {
+        let mut reader = CsvReader::open(&entry.path).map_err(|error| error.to_string())?;
+        let mut total = 0.0;
+        while let Some(record) = reader.read_record().map_err(|error| error.to_string())? {
+            total += record[0].parse::<f64>().map_err(|_| format!("'{}' is not a number", &record[0]))?;
+        }
+        Ok(total)
+    }
+
+    #[test]
+    fn the_fixture_tree_produces_the_expected_statuses() {
+        let root = fixture_tree("statuses");
+        let manifest = load(&root);
+        assert_eq!(manifest.directory, root);
+        assert_eq!(manifest.iter().map(|entry| entry.path.clone()).collect::<Vec<_>>(), ["sub-01/rest.csv", "sub-01/task.csv", "sub-02/rest.csv", "sub-02/task.csv"].map(|path| root.join(path)));
+        assert_eq!(manifest.iter().map(|entry| entry.line).collect::<Vec<_>>(), vec![2, 3, 4, 5]);
+        assert_eq!(manifest.missing().len(), 1);
+        match manifest.require_files() {
+            Err(ManifestError::MissingFiles(paths)) => assert_eq!(paths, vec![root.join("sub-02/task.csv")]),
+            other => panic!("{:?}", other),
+        }
+
+        let results = manifest.map(sum_file);
+        let statuses: Vec<EntryStatus> = results.outcomes.iter().map(|outcome| outcome.status).collect();
+        assert_eq!(statuses, vec![EntryStatus::Ok, EntryStatus::Failed, EntryStatus::Ok, EntryStatus::Missing]);
+        assert_eq!(results.values().copied().collect::<Vec<_>>(), vec![4.0, 4.0]);
+        assert_eq!(results.outcomes[1].error.as_deref(), Some("'clipped' is not a number"));
+        assert!(results.outcomes[3].error.as_deref().unwrap().ends_with("does not exist"));
+        assert_eq!(results.failures().len(), 2);
+        assert!(results.outcomes.iter().all(|outcome| outcome.seconds >= 0.0));
+
+        // A panic is captured as the status of its entry only
+        let results = manifest.map(|entry| -> Result<f64, String> {
+            if entry.task == "task" {
+                panic!("corrupt header in {}", entry.subject);
+            }
+            sum_file(entry)
+        });
+        let statuses: Vec<EntryStatus> = results.outcomes.iter().map(|outcome| outcome.status).collect();
+        assert_eq!(statuses, vec![EntryStatus::Ok, EntryStatus::Panicked, EntryStatus::Ok, EntryStatus::Missing]);
+        assert_eq!(results.outcomes[1].error.as_deref(), Some("corrupt header in sub-01"));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn entries_are_filtered_and_a_run_resumes_from_its_results() {
+        let root = fixture_tree("resume");
+        let manifest = load(&root);
+        let rest = manifest.filter(None, None, Some("rest"));
+        assert_eq!(rest.iter().map(|entry| entry.subject.as_str()).collect::<Vec<_>>(), vec!["sub-01", "sub-02"]);
+        assert_eq!(manifest.filter(Some("sub-02"), Some("ses-2"), None).entries.len(), 1);
+        assert!(manifest.filter(Some("sub-03"), None, None).entries.is_empty());
+
+        let results_path = root.join("results.csv");
+        fs::write(&results_path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(results_path.to_str().unwrap()).unwrap();
+        manifest.map(sum_file).to_csv(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let written = fs::read_to_string(&results_path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines[0], "subject,session,task,path,format,status,seconds,error");
+        assert!(lines[2].starts_with(&format!("sub-01,ses-1,task,{},csv,failed,", root.join("sub-01/task.csv").display())));
+        assert!(lines[2].ends_with(",'clipped' is not a number"));
+
+        // Only the failed and the missing entries are left to run again
+        let remaining = manifest.without_completed(&mut CsvReader::open(&results_path).unwrap()).unwrap();
+        assert_eq!(remaining.iter().map(|entry| (entry.subject.as_str(), entry.task.as_str())).collect::<Vec<_>>(), vec![("sub-01", "task"), ("sub-02", "task")]);
+        fs::write(&results_path, "path,outcome\n").unwrap();
+        assert!(matches!(manifest.without_completed(&mut CsvReader::open(&results_path).unwrap()), Err(ManifestError::MissingColumns(columns)) if columns == vec!["status"]));
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn malformed_manifests_are_rejected() {
+        let root = fixture_tree("malformed");
+        let path = root.join("manifest.csv");
+        fs::write(&path, "subject,task,file\nsub-01,rest,a.csv\n").unwrap();
+        match Manifest::from_reader(&mut CsvReader::open(&path).unwrap()) {
+            Err(error @ ManifestError::MissingColumns(_)) => assert_eq!(error.to_string(), "Manifest is missing the columns session, path, format"),
+            other => panic!("{:?}", other),
+        }
+        fs::write(&path, "subject,session,task,path,format\nsub-01,ses-1,rest,sub-01/rest.csv,csv\nsub-01,ses-1,task,  ,csv\n").unwrap();
+        match Manifest::from_reader(&mut CsvReader::open(&path).unwrap()) {
+            Err(ManifestError::InvalidRow { line, .. }) => assert_eq!(line, 3),
+            other => panic!("{:?}", other),
+        }
+
+        // Loading through a CsvIO object reads the same manifest
+        fs::write(&path, "subject,session,task,path,format\nsub-01,ses-1,rest,sub-01/rest.csv,csv\n").unwrap();
+        let mut csv_io = CsvIO::open_read(path.to_str().unwrap()).unwrap();
+        let manifest = Manifest::load(&mut csv_io).unwrap();
+        assert_eq!(manifest.entries.len(), 1);
+        assert!(manifest.entries[0].exists);
+        assert!(manifest.require_files().is_ok());
+        assert_eq!(EntryStatus::Missing.name(), "missing");
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,713 @@
+// A module to detect the format of a data file from its content and open it behind one interface
+
+// Written by Amin Alam in 2024
+
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use csv::{ReaderBuilder, StringRecord};
+use crate::data_io::fixed_width::{sniff_spec, FixedWidthIO, FixedWidthSpec};
+use crate::data_io::numeric::parse_field;
+
+/// The number of bytes read from the start of a file to detect its format
+pub const SNIFF_BYTES: usize = 65536;
+
+/// The number of lines of a text file its layout is inferred from
+const SNIFF_LINES: usize = 100;
+
+/// The delimiters a delimited text file is tried with, in order of preference
+const DELIMITERS: [u8; 4] = [b',', b'\t', b';', b'|'];
+
+/// The signatures of the binary formats that are recognized but have no reader, as `(name, usual extensions, magic bytes)`
+const SIGNATURES: [(&str, &[&str], &[u8]); 7] = [
+    ("gzip", &["gz"], &[0x1f, 0x8b]),
+    ("zstd", &["zst"], &[0x28, 0xb5, 0x2f, 0xfd]),
+    ("NPY", &["npy"], b"\x93NUMPY"),
+    ("HDF5", &["h5", "hdf5", "nwb", "mat"], b"\x89HDF\r\n\x1a\n"),
+    ("Parquet", &["parquet"], b"PAR1"),
+    ("zip", &["zip", "npz"], b"PK\x03\x04"),
+    ("BDF", &["bdf"], b"\xffBIOSEMI"),
+];
+
+/// The format of a file found by `detect_format`
+///
+/// # Arguments
+///
+/// * `Delimited` - Text with one record per line and fields separated by `delimiter`, e.g. CSV or TSV
+/// * `FixedWidth` - Text with fields at fixed byte columns, with the inferred layout
+///
+/// # Examples
+///
+/// ```
+/// match detect_format("recording.txt")? {
+///     DetectedFormat::Delimited { delimiter } => println!("delimited by {:?}", delimiter as char),
+///     DetectedFormat::FixedWidth(spec) => println!("{} fixed-width fields", spec.fields.len()),
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum DetectedFormat {
+    Delimited { delimiter: u8 },
+    FixedWidth(FixedWidthSpec),
+}
+
+/// A data file opened by `open_any`, whatever its format
+///
+/// # Examples
+///
+/// ```
+/// let source = open_any("session_04.tsv")?;
+/// println!("{:?} at {:?} Hz", source.channels(), source.sampling_rate());
+/// let first_second = source.read_window(0, 1000, &["CA1"])?;
+/// ```
+#[derive(Debug, Clone)]
+pub struct DataSource {
+    path: PathBuf,
+    format: DetectedFormat,
+    evidence: String,
+    headers: StringRecord,
+    has_header: bool,
+    time_column: Option<usize>,
+    channels: Vec<String>,
+    channel_columns: Vec<usize>,
+    sampling_rate: Option<f64>,
+}
+
+/// Implementation of the DetectedFormat enum
+///
+/// # Methods
+///
+/// * `name` - Returns the name of the format
+impl DetectedFormat {
+    /// Returns the name of the format
+    ///
+    /// # Returns
+    ///
+    /// `csv`, `tsv`, `delimited` for the other delimiters, or `fixed-width`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// println!("format: {}", source.format().name());
+    /// ```
+    ///
+    pub fn name(&self) -> &'static str {
+        match self {
+            DetectedFormat::Delimited { delimiter: b',' } => "csv",
+            DetectedFormat::Delimited { delimiter: b'\t' } => "tsv",
+            DetectedFormat::Delimited { .. } => "delimited",
+            DetectedFormat::FixedWidth(_) => "fixed-width",
+        }
+    }
+}
+
+/// Implementation of the DataSource struct
+///
+/// # Methods
+///
+/// * `path` - Returns the path to the file
+/// * `format` - Returns the detected format
+/// * `channels` - Returns the names of the numeric columns other than the time column
+/// * `sampling_rate` - Returns the sampling rate estimated from the time column
+/// * `read_window` - Reads a range of samples of some channels
+/// * `events` - Returns the events stored in the file
+/// * `metadata` - Returns what is known about the file as key-value pairs
+impl DataSource {
+    /// Returns the path to the file
+    ///
+    /// # Returns
+    ///
+    /// The path the DataSource was opened from
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// println!("{}", source.path().display());
+    /// ```
+    ///
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the detected format
+    ///
+    /// # Returns
+    ///
+    /// The DetectedFormat the file is read with
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let format = source.format();
+    /// ```
+    ///
+    pub fn format(&self) -> &DetectedFormat {
+        &self.format
+    }
+
+    /// Returns the names of the numeric columns other than the time column
+    ///
+    /// # Returns
+    ///
+    /// The channel names, in the order of the columns
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let n_channels = source.channels().len();
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A column is a channel if all its values in the first lines of the file are numbers
+    ///
+    pub fn channels(&self) -> &[String] {
+        &self.channels
+    }
+
+    /// Returns the sampling rate estimated from the time column
+    ///
+    /// # Returns
+    ///
+    /// The sampling rate in Hz, or None if there is no column named `time` or it does not
+    /// increase over the first lines of the file
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let sampling_rate = source.sampling_rate().unwrap_or(1000.0);
+    /// ```
+    ///
+    pub fn sampling_rate(&self) -> Option<f64> {
+        self.sampling_rate
+    }
+
+    /// Reads a range of samples of some channels
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - The index of the first sample
+    /// * `end` - The index after the last sample, beyond the end of the file for all remaining samples
+    /// * `channels` - The names of the channels, in the order of the output, or empty for every channel
+    ///
+    /// # Returns
+    ///
+    /// The samples of each channel, or an error if a channel does not exist, a value is not a
+    /// number or the file cannot be read
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let window = source.read_window(30000, 60000, &[])?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The file is read from its start on every call, as text formats hold no index of their rows
+    ///
+    pub fn read_window(&self, start: usize, end: usize, channels: &[&str]) -> io::Result<Vec<Vec<f64>>> {
+        let selected: Vec<(usize, &str)> = if channels.is_empty() {
+            self.channel_columns.iter().zip(&self.channels).map(|(&column, name)| (column, name.as_str())).collect()
+        } else {
+            channels
+                .iter()
+                .map(|&name| match self.channels.iter().position(|channel| channel == name) {
+                    Some(i) => Ok((self.channel_columns[i], name)),
+                    None => Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Channel '{}' not found in {}", name, self.path.display()))),
+                })
+                .collect::<io::Result<_>>()?
+        };
+        let mut output = vec![Vec::with_capacity(end.saturating_sub(start).min(SNIFF_BYTES)); selected.len()];
+        let mut push = |record: &StringRecord, line: u64| -> io::Result<()> {
+            for (channel, &(column, name)) in output.iter_mut().zip(&selected) {
+                let field = record.get(column).unwrap_or("");
+                let value = field.trim().parse::<f64>().map_err(|_| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("Line {}: {:?} of channel '{}' is not a number", line, field, name))
+                })?;
+                channel.push(value);
+            }
+            Ok(())
+        };
+        match &self.format {
+            DetectedFormat::Delimited { delimiter } => {
+                let mut reader = ReaderBuilder::new()
+                    .delimiter(*delimiter)
+                    .has_headers(self.has_header)
+                    .flexible(true)
+                    .from_reader(BufReader::new(File::open(&self.path)?));
+                let mut record = StringRecord::new();
+                let mut row = 0;
+                while row < end && reader.read_record(&mut record).map_err(io::Error::from)? {
+                    if row >= start {
+                        push(&record, record.position().map_or(0, |position| position.line()))?;
+                    }
+                    row += 1;
+                }
+            }
+            DetectedFormat::FixedWidth(spec) => {
+                let mut reader = FixedWidthIO::open(&self.path, spec.clone())?;
+                let mut row = 0;
+                while row < end {
+                    let Some(record) = reader.read_record()? else {
+                        break;
+                    };
+                    if row >= start {
+                        push(&record, row as u64 + 1 + spec.header as u64)?;
+                    }
+                    row += 1;
+                }
+            }
+        }
+        Ok(output)
+    }
+
+    /// Returns the events stored in the file
+    ///
+    /// # Returns
+    ///
+    /// The onset in seconds and label of each event, or None if the format holds no events
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// if let Some(events) = source.events() {
+    ///     println!("{} events", events.len());
+    /// }
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// None of the text formats that can be opened hold events, so this is None for now;
+    /// events of text recordings are read with `events_from_bids` instead
+    ///
+    pub fn events(&self) -> Option<Vec<(f64, String)>> {
+        None
+    }
+
+    /// Returns what is known about the file as key-value pairs
+    ///
+    /// # Returns
+    ///
+    /// The `path`, `format`, `detected_by`, `columns`, `header` and `channels` entries,
+    /// followed by `delimiter`, `time_column`, `sampling_rate` and `other_columns` when
+    /// they apply
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// for (key, value) in source.metadata() {
+    ///     println!("{}: {}", key, value);
+    /// }
+    /// ```
+    ///
+    pub fn metadata(&self) -> Vec<(String, String)> {
+        let mut metadata = vec![
+            ("path".to_string(), self.path.display().to_string()),
+            ("format".to_string(), self.format.name().to_string()),
+            ("detected_by".to_string(), self.evidence.clone()),
+            ("columns".to_string(), self.headers.len().to_string()),
+            ("header".to_string(), self.has_header.to_string()),
+            ("channels".to_string(), self.channels.len().to_string()),
+        ];
+        if let DetectedFormat::Delimited { delimiter } = self.format {
+            metadata.push(("delimiter".to_string(), format!("{:?}", delimiter as char)));
+        }
+        if let Some(column) = self.time_column {
+            metadata.push(("time_column".to_string(), self.headers[column].to_string()));
+        }
+        if let Some(sampling_rate) = self.sampling_rate {
+            metadata.push(("sampling_rate".to_string(), sampling_rate.to_string()));
+        }
+        let others: Vec<&str> = (0..self.headers.len())
+            .filter(|column| Some(*column) != self.time_column && !self.channel_columns.contains(column))
+            .map(|column| &self.headers[column])
+            .collect();
+        if !others.is_empty() {
+            metadata.push(("other_columns".to_string(), others.join(";")));
+        }
+        metadata
+    }
+}
+
+/// Detects the format of a file from its first bytes
+///
+/// # Arguments
+///
+/// * `file_path` - The path to the file
+///
+/// # Returns
+///
+/// The DetectedFormat, or an error naming what was detected and why it was rejected if the
+/// file is empty, binary, in a recognized format that has no reader, or ambiguous
+///
+/// # Examples
+///
+/// ```
+/// let format = detect_format("legacy.txt")?;
+/// ```
+///
+/// # Note
+///
+/// See `open_any` for how the format is detected
+///
+pub fn detect_format<P: AsRef<Path>>(file_path: P) -> io::Result<DetectedFormat> {
+    detect(file_path.as_ref()).map(|(format, _)| format)
+}
+
+/// Opens a data file whatever its format
+///
+/// # Arguments
+///
+/// * `file_path` - The path to the file
+///
+/// # Returns
+///
+/// The DataSource, or an error naming what was detected and why it was rejected if the
+/// file is empty, binary, in a recognized format that has no reader, or ambiguous
+///
+/// # Examples
+///
+/// ```
+/// let source = open_any("recordings/s01.dat")?;
+/// for (key, value) in source.metadata() {
+///     println!("{}: {}", key, value);
+/// }
+/// ```
+///
+/// # Note
+///
+/// The content decides the format and the extension only breaks ties, so a renamed file is
+/// still detected. The first `SNIFF_BYTES` bytes are checked for the signatures of EDF
+/// (the `0       ` version field of its 256-byte header), BDF, gzip, zstd, NPY, HDF5,
+/// Parquet and zip, which are rejected by name as they have no reader in this crate. Other
+/// data with NUL bytes or invalid UTF-8 is rejected as unknown binary. Text is delimited if
+/// its first lines split into the same number of fields, at least two, with exactly one of
+/// `,`, tab, `;` and `|`, or with the one the extension implies (`.csv`, `.tsv`, `.tab`)
+/// when several do; otherwise it is fixed-width if blank columns split it into several
+/// fields, and delimited with a single column if every line is one word. The first row is
+/// a header unless all its values are numbers, in which case the columns are named
+/// `field_1`, `field_2` and so on.
+///
+pub fn open_any<P: AsRef<Path>>(file_path: P) -> io::Result<DataSource> {
+    let path = file_path.as_ref();
+    let (format, evidence) = detect(path)?;
+    let lines = sample_lines(path)?;
+    let (headers, has_header, rows) = match &format {
+        DetectedFormat::Delimited { delimiter } => {
+            let records = split_lines(&lines, *delimiter);
+            let has_header = records.first().is_some_and(|first| !first.iter().all(is_number));
+            let headers = match (has_header, records.first()) {
+                (true, Some(first)) => first.iter().map(|field| field.trim()).collect(),
+                _ => (1..=records.first().map_or(0, |first| first.len())).map(|i| format!("field_{}", i)).collect(),
+            };
+            (headers, has_header, records.into_iter().skip(has_header as usize).collect::<Vec<_>>())
+        }
+        DetectedFormat::FixedWidth(spec) => {
+            let data = lines.iter().filter(|line| !line.trim().is_empty()).skip(spec.header as usize);
+            let rows = data.map(|line| spec.parse_line(line)).collect::<io::Result<Vec<_>>>()?;
+            (spec.headers(), spec.header, rows)
+        }
+    };
+
+    let time_column = headers.iter().position(|header| header.eq_ignore_ascii_case("time"));
+    let channel_columns: Vec<usize> = (0..headers.len())
+        .filter(|&column| Some(column) != time_column)
+        .filter(|&column| !rows.is_empty() && rows.iter().all(|row| row.get(column).is_some_and(is_number)))
+        .collect();
+    let channels = channel_columns.iter().map(|&column| headers[column].to_string()).collect();
+    let sampling_rate = time_column.and_then(|column| {
+        let times: Vec<f64> = rows.iter().filter_map(|row| row.get(column)?.trim().parse().ok()).collect();
+        let span = times.last()? - times.first()?;
+        (times.len() > 1 && span > 0.0).then(|| (times.len() - 1) as f64 / span)
+    });
+    Ok(DataSource { path: path.to_path_buf(), format, evidence, headers, has_header, time_column, channels, channel_columns, sampling_rate })
+}
+
+/// Detects the format of a file and describes the evidence for it
+fn detect(path: &Path) -> io::Result<(DetectedFormat, String)> {
+    let mut sample = Vec::with_capacity(SNIFF_BYTES);
+    File::open(path)?.take(SNIFF_BYTES as u64).read_to_end(&mut sample)?;
+    let extension = path.extension().and_then(|extension| extension.to_str()).map(str::to_ascii_lowercase);
+    let rejected = |format: &str, extensions: &[&str], evidence: &str| {
+        let named = match &extension {
+            Some(extension) if !extensions.contains(&extension.as_str()) => format!(" despite its .{} extension", extension),
+            _ => String::new(),
+        };
+        io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("{} was detected as {} from {}{}, which has no reader in this crate", path.display(), format, evidence, named),
+        )
+    };
+
+    if sample.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("{} is empty, so its format cannot be detected", path.display())));
+    }
+    if is_edf_header(&sample) {
+        return Err(rejected("EDF", &["edf", "rec"], "the '0       ' version field of its header"));
+    }
+    if let Some((name, extensions, _)) = SIGNATURES.iter().find(|(_, _, magic)| sample.starts_with(magic)) {
+        return Err(rejected(name, extensions, "its magic bytes"));
+    }
+    let text = match std::str::from_utf8(&sample) {
+        Ok(text) => text,
+        // A character cut by the end of the sample is not an error
+        Err(error) if error.error_len().is_none() => std::str::from_utf8(&sample[..error.valid_up_to()]).unwrap_or_default(),
+        Err(error) => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{} holds binary data with no known signature (invalid UTF-8 at byte {})", path.display(), error.valid_up_to()),
+            ));
+        }
+    };
+    if let Some(position) = text.find('\0') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} holds binary data with no known signature (NUL byte at {}); raw binary needs its channel count and sample type", path.display(), position),
+        ));
+    }
+
+    let lines = text_lines(text, sample.len() == SNIFF_BYTES);
+    let consistent: Vec<u8> = DELIMITERS
+        .iter()
+        .copied()
+        .filter(|&delimiter| {
+            let records = split_lines(&lines, delimiter);
+            let width = records.first().map_or(0, |first| first.len());
+            width > 1 && records.iter().all(|record| record.len() == width)
+        })
+        .collect();
+    let preferred = match extension.as_deref() {
+        Some("csv") => Some(b','),
+        Some("tsv") | Some("tab") => Some(b'\t'),
+        _ => None,
+    };
+    match consistent.as_slice() {
+        [delimiter] => return Ok((DetectedFormat::Delimited { delimiter: *delimiter }, format!("consistent {:?} fields", *delimiter as char))),
+        [] => {}
+        several => match preferred.filter(|delimiter| several.contains(delimiter)) {
+            Some(delimiter) => {
+                return Ok((DetectedFormat::Delimited { delimiter }, format!("consistent {:?} fields and the extension", delimiter as char)));
+            }
+            None => {
+                let names: Vec<String> = several.iter().map(|&delimiter| format!("{:?}", delimiter as char)).collect();
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} is ambiguous: its lines split consistently with each of {}", path.display(), names.join(", ")),
+                ));
+            }
+        },
+    }
+
+    let words = |line: &&str| line.split_whitespace().count();
+    let non_blank: Vec<&str> = lines.iter().copied().filter(|line| !line.trim().is_empty()).collect();
+    if non_blank.iter().all(|line| words(line) == 1) {
+        return Ok((DetectedFormat::Delimited { delimiter: b',' }, "one value per line".to_string()));
+    }
+    match sniff_spec(&non_blank) {
+        Ok(spec) if spec.fields.len() > 1 => Ok((DetectedFormat::FixedWidth(spec), "fields separated by blank columns".to_string())),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} is text but neither a consistent delimiter nor blank columns split its lines into fields", path.display()),
+        )),
+    }
+}
+
+/// Checks whether a sample starts with the ASCII header of an EDF file
+fn is_edf_header(sample: &[u8]) -> bool {
+    sample.len() >= 256 && sample.starts_with(b"0       ") && sample[..256].iter().all(|byte| (0x20..0x7f).contains(byte))
+}
+
+/// Reads the first lines of a text file, as detection saw them
+fn sample_lines(path: &Path) -> io::Result<Vec<String>> {
+    let mut sample = Vec::with_capacity(SNIFF_BYTES);
+    File::open(path)?.take(SNIFF_BYTES as u64).read_to_end(&mut sample)?;
+    let text = String::from_utf8_lossy(&sample);
+    Ok(text_lines(&text, sample.len() == SNIFF_BYTES).into_iter().map(str::to_string).collect())
+}
+
+/// Splits a text sample into its first lines, leaving out a last line cut by the end of the sample
+fn text_lines(text: &str, truncated: bool) -> Vec<&str> {
+    let complete = match (truncated, text.rfind('\n')) {
+        (true, Some(end)) => &text[..end],
+        _ => text,
+    };
+    complete.lines().take(SNIFF_LINES).collect()
+}
+
+/// Splits lines into records with a delimiter, leaving out blank lines
+fn split_lines<S: AsRef<str>>(lines: &[S], delimiter: u8) -> Vec<StringRecord> {
+    let text: Vec<&str> = lines.iter().map(|line| line.as_ref()).filter(|line| !line.trim().is_empty()).collect();
+    let joined = text.join("\n");
+    let mut reader = ReaderBuilder::new().delimiter(delimiter).has_headers(false).flexible(true).from_reader(joined.as_bytes());
+    reader.records().map_while(Result::ok).collect()
+}
+
+fn is_number(field: &str) -> bool {
+    parse_field::<f64>(field.as_bytes()).is_some()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str, contents: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("neurorust-detect-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn rejection(name: &str, contents: &[u8]) -> (io::ErrorKind, String) {
+        let path = fixture(name, contents);
+        let error = open_any(&path).unwrap_err();
+        let message = error.to_string().replace(&path.display().to_string(), "<file>");
+        std::fs::remove_file(&path).unwrap();
+        (error.kind(), message)
+    }
+
+    fn metadata(source: &DataSource) -> Vec<(String, String)> {
+        source.metadata().into_iter().filter(|(key, _)| key != "path").collect()
+    }
+
+    fn entries(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    fn edf_header() -> Vec<u8> {
+        let mut header = format!("{:<8}{:<80}{:<80}{:<8}{:<8}{:<8}{:<44}{:<8}{:<8}{:<4}", "0", "X F 01-JAN-2024 X", "Startdate 01-JAN-2024", "01.01.24", "10.00.00", "512", "EDF+C", "1", "1", "1").into_bytes();
+        assert_eq!(header.len(), 256);
+        header.extend([0x00, 0x80, 0xff, 0x7f]);
+        header
+    }
+
+    #[test]
+    fn delimited_text_is_detected_by_its_consistent_delimiter() {
+        let text = "time,CA1,label,CA3\n0.000,1.5,a,-2\n0.001,2.5,b,-3\n0.002,3.5,c,-4\n";
+        let path = fixture("recording.dat", text.as_bytes());
+        let source = open_any(&path).unwrap();
+        assert_eq!(*source.format(), DetectedFormat::Delimited { delimiter: b',' });
+        assert_eq!(detect_format(&path).unwrap(), *source.format());
+        assert_eq!(source.channels(), ["CA1", "CA3"]);
+        assert!((source.sampling_rate().unwrap() - 1000.0).abs() < 1e-9);
+        assert_eq!(metadata(&source)[..7], entries(&[("format", "csv"), ("detected_by", "consistent ',' fields"), ("columns", "4"), ("header", "true"), ("channels", "2"), ("delimiter", "','"), ("time_column", "time")]));
+        assert_eq!(source.metadata().last().unwrap(), &("other_columns".to_string(), "label".to_string()));
+        assert_eq!(source.read_window(1, 10, &["CA3", "CA1"]).unwrap(), [vec![-3.0, -4.0], vec![2.5, 3.5]]);
+        assert_eq!(source.read_window(0, 1, &[]).unwrap(), [vec![1.5], vec![-2.0]]);
+        let missing = source.read_window(0, 1, &["CA2"]).unwrap_err();
+        assert_eq!(missing.kind(), io::ErrorKind::InvalidInput);
+        assert!(source.events().is_none());
+        std::fs::remove_file(&path).unwrap();
+
+        for (name, text, format) in [("tabs.txt", "time\tx\n0\t1\n0.5\t2\n", "tsv"), ("pipes.txt", "x|y\n1|2\n3|4\n", "delimited"), ("semicolons.txt", "x;y\n1,5;2\n3,5;4\n", "delimited")] {
+            let path = fixture(name, text.as_bytes());
+            let source = open_any(&path).unwrap();
+            assert_eq!(source.format().name(), format, "{}", name);
+            std::fs::remove_file(&path).unwrap();
+        }
+    }
+
+    #[test]
+    fn headerless_and_single_column_text_get_named_columns() {
+        let path = fixture("numbers.txt", b"1,2\n3,4\n5,6\n");
+        let source = open_any(&path).unwrap();
+        assert_eq!(source.channels(), ["field_1", "field_2"]);
+        assert_eq!(source.metadata()[4].1, "false");
+        assert_eq!(source.sampling_rate(), None);
+        assert_eq!(source.read_window(0, usize::MAX, &["field_2"]).unwrap(), [vec![2.0, 4.0, 6.0]]);
+        std::fs::remove_file(&path).unwrap();
+
+        let path = fixture("single.txt", b"voltage\n0.5\n\n-1\n");
+        let source = open_any(&path).unwrap();
+        assert_eq!((source.format().name(), source.channels()), ("csv", ["voltage".to_string()].as_slice()));
+        assert_eq!(source.metadata()[2].1, "one value per line");
+        assert_eq!(source.read_window(0, 10, &[]).unwrap(), [vec![0.5, -1.0]]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn fixed_width_text_is_detected_by_its_blank_columns() {
+        let path = fixture("legacy.txt", b" time   ch1   ch2\n0.000   1.5  -2.0\n0.002   2.5  -3.0\n0.004   3.5  -4.0\n");
+        let source = open_any(&path).unwrap();
+        let DetectedFormat::FixedWidth(spec) = source.format() else {
+            panic!("Expected fixed-width, got {:?}", source.format());
+        };
+        assert!(spec.header && spec.fields.len() == 3);
+        assert_eq!(source.channels(), ["ch1", "ch2"]);
+        assert!((source.sampling_rate().unwrap() - 500.0).abs() < 1e-9);
+        assert_eq!(metadata(&source)[..2], entries(&[("format", "fixed-width"), ("detected_by", "fields separated by blank columns")]));
+        assert_eq!(source.read_window(1, 3, &["ch2"]).unwrap(), [vec![-3.0, -4.0]]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn the_extension_only_breaks_ties() {
+        let text = b"a,b;c\n1,2;3\n4,5;6\n";
+        let path = fixture("tied.csv", text);
+        let source = open_any(&path).unwrap();
+        assert_eq!(*source.format(), DetectedFormat::Delimited { delimiter: b',' });
+        assert_eq!(source.metadata()[2].1, "consistent ',' fields and the extension");
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(rejection("tied.txt", text), (io::ErrorKind::InvalidData, "<file> is ambiguous: its lines split consistently with each of ',', ';'".to_string()));
+
+        // Tabs win in a .csv file when commas do not split it
+        let path = fixture("misnamed.csv", b"x\ty\n1\t2\n");
+        assert_eq!(detect_format(&path).unwrap(), DetectedFormat::Delimited { delimiter: b'\t' });
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn binary_formats_are_rejected_by_name() {
+        assert_eq!(
+            rejection("renamed.csv", &edf_header()),
+            (io::ErrorKind::Unsupported, "<file> was detected as EDF from the '0       ' version field of its header despite its .csv extension, which has no reader in this crate".to_string())
+        );
+        assert_eq!(rejection("eeg.edf", &edf_header()).1, "<file> was detected as EDF from the '0       ' version field of its header, which has no reader in this crate");
+        for (name, contents, format, despite) in [
+            ("data.gz", &b"\x1f\x8b\x08\x00rest"[..], "gzip", ""),
+            ("data.dat", b"\x28\xb5\x2f\xfdrest", "zstd", " despite its .dat extension"),
+            ("array.npy", b"\x93NUMPY\x01\x00", "NPY", ""),
+            ("session.nwb", b"\x89HDF\r\n\x1a\nrest", "HDF5", ""),
+            ("table.parquet", b"PAR1rest", "Parquet", ""),
+            ("arrays.npz", b"PK\x03\x04rest", "zip", ""),
+            ("eeg.bdf", b"\xffBIOSEMIrest", "BDF", ""),
+        ] {
+            let (kind, message) = rejection(name, contents);
+            assert_eq!(kind, io::ErrorKind::Unsupported);
+            assert_eq!(message, format!("<file> was detected as {} from its magic bytes{}, which has no reader in this crate", format, despite));
+        }
+    }
+
+    #[test]
+    fn unreadable_files_say_why() {
+        assert_eq!(rejection("empty.csv", b""), (io::ErrorKind::InvalidData, "<file> is empty, so its format cannot be detected".to_string()));
+        assert_eq!(rejection("raw.bin", b"abc\0def").1, "<file> holds binary data with no known signature (NUL byte at 3); raw binary needs its channel count and sample type");
+        assert_eq!(rejection("latin.txt", b"abc\xff\xfedef").1, "<file> holds binary data with no known signature (invalid UTF-8 at byte 3)");
+        assert_eq!(rejection("notes.txt", b"ab cd\nabcde\n").1, "<file> is text but neither a consistent delimiter nor blank columns split its lines into fields");
+        // The short EDF-like header is text, not EDF
+        assert_eq!(rejection("short.txt", b"0       x y\nabcdefghijk\n").1, "<file> is text but neither a consistent delimiter nor blank columns split its lines into fields");
+    }
+
+    #[test]
+    fn large_files_are_sniffed_from_their_start() {
+        // Rows of 15 bytes after a 9-byte header put the first byte of row 4368's 'é' at the last sniffed byte
+        let mut text = String::from("t,lbl,ab\n");
+        for row in 0..6000 {
+            text.push_str(&format!("{:05},{},{:05}\n", row, if row == 4368 { "é" } else { "e." }, row));
+        }
+        assert_eq!(text.as_bytes()[SNIFF_BYTES - 1], 0xc3);
+        let path = fixture("large.txt", text.as_bytes());
+        let source = open_any(&path).unwrap();
+        assert_eq!(source.channels(), ["t", "ab"]);
+        let window = source.read_window(5990, 7000, &["ab"]).unwrap();
+        assert_eq!(window, [(5990..6000).map(f64::from).collect::<Vec<_>>()]);
+        std::fs::remove_file(&path).unwrap();
+
+        // A value past the sniffed lines that is not a number fails the read with its line
+        let mut text = String::from("time,a\n");
+        (0..300).for_each(|row| text.push_str(&format!("{},{}\n", row, if row == 250 { "bad" } else { "1" })));
+        let path = fixture("late.csv", text.as_bytes());
+        let source = open_any(&path).unwrap();
+        assert_eq!(source.read_window(0, 250, &[]).unwrap()[0].len(), 250);
+        let error = source.read_window(0, 300, &["a"]).unwrap_err();
+        assert_eq!((error.kind(), error.to_string()), (io::ErrorKind::InvalidData, "Line 252: \"bad\" of channel 'a' is not a number".to_string()));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
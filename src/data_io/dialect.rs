@@ -0,0 +1,534 @@
+// A module to describe the dialect of delimited text files and open them with it
+
+// Written by Amin Alam in 2024
+
+use std::path::Path;
+use csv::{ReaderBuilder, StringRecord, WriterBuilder};
+use crate::data_io::compression::Compression;
+use crate::data_io::csv::{CsvIO, OpenMode};
+use crate::data_io::error::DataIoError;
+
+/// The prefix of the headers given to the columns of a file without a header row
+pub const UNNAMED_COLUMN_PREFIX: &str = "column_";
+
+/// How the records of a delimited text file are separated, quoted and commented
+///
+/// # Arguments
+///
+/// * `delimiter` - The byte between the fields of a record, e.g. `b','`, `b'\t'` or `b';'`
+/// * `quote` - The byte quoting fields that contain the delimiter, a quote or a line break
+/// * `comment` - The byte starting lines that are skipped when reading, or None
+/// * `flexible` - Whether records may have a different number of fields than the first one
+/// * `has_headers` - Whether the first record is a header row, rather than the first record of values
+/// * `compression` - How the file is compressed, or None to choose from its first bytes or extension with `Compression::from_path`
+/// * `compression_level` - The level files are compressed at when written, from 0 to 9 for gzip and up to 22 for Zstandard, or None for the default of the compression
+///
+/// # Examples
+///
+/// ```
+/// let dialect = CsvDialect { delimiter: b';', comment: Some(b'%'), ..CsvDialect::new() };
+/// let reader = CsvReader::open_with_dialect("export.csv", &dialect)?;
+/// ```
+///
+/// # Note
+///
+/// A file without a header row is read with the headers `column_0`, `column_1` and so on,
+/// and its first record is read as values. The comment byte and `has_headers` only apply
+/// when reading; the writers of the crate write their own header rows. A compressed file is
+/// decompressed into a temporary file when it is opened for reading, so its rows can be
+/// sought and read by several readers, and the temporary file is removed once the last of
+/// them is dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CsvDialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    pub comment: Option<u8>,
+    pub flexible: bool,
+    pub has_headers: bool,
+    pub compression: Option<Compression>,
+    pub compression_level: Option<i32>,
+}
+
+/// A builder of CsvIO objects reading and writing a CsvDialect
+///
+/// # Arguments
+///
+/// * `dialect` - The dialect of the files opened by the builder
+///
+/// # Examples
+///
+/// ```
+/// let mut csv_io = CsvIOBuilder::new().delimiter(b'\t').has_headers(false).open_read("lfp.tsv")?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CsvIOBuilder {
+    dialect: CsvDialect,
+}
+
+/// Implementation of the CsvDialect struct
+///
+/// # Methods
+///
+/// * `new` - Creates the dialect of comma separated files with a header row
+/// * `tsv` - Creates the dialect of tab separated files with a header row
+/// * `compression_of` - Returns how a file of the dialect is compressed
+impl CsvDialect {
+    /// Creates the dialect of comma separated files with a header row
+    ///
+    /// # Returns
+    ///
+    /// The CsvDialect, with `"` quotes, no comments and records of equal length
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let dialect = CsvDialect::new();
+    /// ```
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates the dialect of tab separated files with a header row
+    ///
+    /// # Returns
+    ///
+    /// The CsvDialect, with `"` quotes, no comments and records of equal length
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let dialect = CsvDialect { has_headers: false, ..CsvDialect::tsv() };
+    /// ```
+    ///
+    pub fn tsv() -> Self {
+        Self { delimiter: b'\t', ..Self::default() }
+    }
+
+    /// Returns how a file of the dialect is compressed
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path to the file
+    ///
+    /// # Returns
+    ///
+    /// The `compression` of the dialect, or if it is None the one the file starts with, or
+    /// implied by its extension for a file that does not exist yet
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(CsvDialect::new().compression_of("new_export.csv.gz"), Compression::Gzip);
+    /// ```
+    ///
+    pub fn compression_of<P: AsRef<Path>>(&self, file_path: P) -> Compression {
+        self.compression.unwrap_or_else(|| Compression::from_path(file_path))
+    }
+
+    /// Returns a csv::ReaderBuilder reading the dialect
+    pub(crate) fn reader_builder(&self) -> ReaderBuilder {
+        let mut builder = ReaderBuilder::new();
+        builder.delimiter(self.delimiter).quote(self.quote).comment(self.comment).flexible(self.flexible).has_headers(self.has_headers);
+        builder
+    }
+
+    /// Returns a csv::WriterBuilder writing the dialect
+    pub(crate) fn writer_builder(&self) -> WriterBuilder {
+        let mut builder = WriterBuilder::new();
+        builder.delimiter(self.delimiter).quote(self.quote).flexible(self.flexible);
+        builder
+    }
+
+    /// Returns the headers of a file from its first record, named by position if it has no header row
+    pub(crate) fn headers(&self, first_record: &StringRecord) -> StringRecord {
+        match self.has_headers {
+            true => first_record.clone(),
+            false => (0..first_record.len()).map(|column| format!("{}{}", UNNAMED_COLUMN_PREFIX, column)).collect(),
+        }
+    }
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        Self { delimiter: b',', quote: b'"', comment: None, flexible: false, has_headers: true, compression: None, compression_level: None }
+    }
+}
+
+/// Implementation of the CsvIOBuilder struct
+///
+/// # Methods
+///
+/// * `new` - Creates a builder of comma separated files with a header row
+/// * `dialect` - Replaces the whole dialect
+/// * `delimiter` - Sets the byte between the fields of a record
+/// * `quote` - Sets the byte quoting fields
+/// * `comment` - Sets the byte starting lines that are skipped when reading
+/// * `flexible` - Sets whether records may have a different number of fields
+/// * `has_headers` - Sets whether the first record is a header row
+/// * `compression` - Sets how the files are compressed, instead of choosing from their first bytes or extension
+/// * `compression_level` - Sets the level the files are compressed at when written
+/// * `open` - Opens a csv file with the dialect
+/// * `open_read` - Opens an existing csv file with the dialect for reading
+/// * `open_write` - Creates a csv file with the dialect for writing
+/// * `open_append` - Opens a csv file with the dialect to read its records and write new ones
+impl CsvIOBuilder {
+    /// Creates a builder of comma separated files with a header row
+    ///
+    /// # Returns
+    ///
+    /// The CsvIOBuilder, with the dialect of `CsvDialect::new`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let builder = CsvIOBuilder::new();
+    /// ```
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Replaces the whole dialect
+    ///
+    /// # Arguments
+    ///
+    /// * `dialect` - The dialect of the files opened by the builder
+    ///
+    /// # Returns
+    ///
+    /// The CsvIOBuilder with the dialect
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let builder = CsvIOBuilder::new().dialect(CsvDialect::tsv());
+    /// ```
+    ///
+    pub fn dialect(mut self, dialect: CsvDialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Sets the byte between the fields of a record
+    ///
+    /// # Arguments
+    ///
+    /// * `delimiter` - The delimiter, `b','` by default
+    ///
+    /// # Returns
+    ///
+    /// The CsvIOBuilder with the delimiter
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let builder = CsvIOBuilder::new().delimiter(b';');
+    /// ```
+    ///
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.dialect.delimiter = delimiter;
+        self
+    }
+
+    /// Sets the byte quoting fields
+    ///
+    /// # Arguments
+    ///
+    /// * `quote` - The quote, `b'"'` by default
+    ///
+    /// # Returns
+    ///
+    /// The CsvIOBuilder with the quote
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let builder = CsvIOBuilder::new().quote(b'\'');
+    /// ```
+    ///
+    pub fn quote(mut self, quote: u8) -> Self {
+        self.dialect.quote = quote;
+        self
+    }
+
+    /// Sets the byte starting lines that are skipped when reading
+    ///
+    /// # Arguments
+    ///
+    /// * `comment` - The comment byte, or None to read every line, None by default
+    ///
+    /// # Returns
+    ///
+    /// The CsvIOBuilder with the comment byte
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let builder = CsvIOBuilder::new().comment(Some(b'#'));
+    /// ```
+    ///
+    pub fn comment(mut self, comment: Option<u8>) -> Self {
+        self.dialect.comment = comment;
+        self
+    }
+
+    /// Sets whether records may have a different number of fields
+    ///
+    /// # Arguments
+    ///
+    /// * `flexible` - Whether records may be shorter or longer than the first one, false by default
+    ///
+    /// # Returns
+    ///
+    /// The CsvIOBuilder with the setting
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let builder = CsvIOBuilder::new().flexible(true);
+    /// ```
+    ///
+    pub fn flexible(mut self, flexible: bool) -> Self {
+        self.dialect.flexible = flexible;
+        self
+    }
+
+    /// Sets whether the first record is a header row
+    ///
+    /// # Arguments
+    ///
+    /// * `has_headers` - Whether the file has a header row, true by default
+    ///
+    /// # Returns
+    ///
+    /// The CsvIOBuilder with the setting
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let builder = CsvIOBuilder::new().has_headers(false);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Without a header row the columns are named `column_0`, `column_1` and so on
+    ///
+    pub fn has_headers(mut self, has_headers: bool) -> Self {
+        self.dialect.has_headers = has_headers;
+        self
+    }
+
+    /// Sets how the files are compressed, instead of choosing from their extension
+    ///
+    /// # Arguments
+    ///
+    /// * `compression` - The compression of the files
+    ///
+    /// # Returns
+    ///
+    /// The CsvIOBuilder with the compression
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut csv_io = CsvIOBuilder::new().compression(Compression::Gzip).open_read("behavior.log")?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Without it `.gz` files are read and written as gzip and `.zst` files as Zstandard,
+    /// which need the `gzip` and `zstd` features. Existing files that start with the magic
+    /// bytes of either are read and appended to as such whatever their extension.
+    ///
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.dialect.compression = Some(compression);
+        self
+    }
+
+    /// Sets the level the files are compressed at when written
+    ///
+    /// # Arguments
+    ///
+    /// * `level` - From 0 to 9 for gzip, and up to 22 for Zstandard, where 0 is its default and negative levels trade size for speed
+    ///
+    /// # Returns
+    ///
+    /// The CsvIOBuilder with the level
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut csv_io = CsvIOBuilder::new().compression_level(19).open_write("archive/lfp.csv.zst")?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The level is ignored for files that are not compressed, and a level out of the range
+    /// of the compression is an `InvalidInput` error when the file is opened for writing
+    ///
+    pub fn compression_level(mut self, level: i32) -> Self {
+        self.dialect.compression_level = Some(level);
+        self
+    }
+
+    /// Opens a csv file with the dialect
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - A string slice that holds the path to the csv file
+    /// * `mode` - Whether the file is read, written from scratch or appended to
+    ///
+    /// # Returns
+    ///
+    /// The CsvIO object, or an error as returned by `CsvIO::open`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut csv_io = CsvIOBuilder::new().delimiter(b';').open("trials.csv", OpenMode::Append)?;
+    /// ```
+    ///
+    pub fn open(&self, file_path: &str, mode: OpenMode) -> Result<CsvIO, DataIoError> {
+        CsvIO::open_with_dialect(file_path, mode, &self.dialect)
+    }
+
+    /// Opens an existing csv file with the dialect for reading, leaving it unchanged
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - A string slice that holds the path to the csv file
+    ///
+    /// # Returns
+    ///
+    /// The CsvIO object, or a `FileNotFound` error if the file does not exist, or an error if
+    /// it cannot be opened
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut csv_io = CsvIOBuilder::new().delimiter(b'\t').has_headers(false).open_read("lfp.tsv")?;
+    /// ```
+    ///
+    pub fn open_read(&self, file_path: &str) -> Result<CsvIO, DataIoError> {
+        self.open(file_path, OpenMode::Read)
+    }
+
+    /// Creates a csv file with the dialect, or truncates it if it exists, for writing
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - A string slice that holds the path to the csv file
+    ///
+    /// # Returns
+    ///
+    /// The CsvIO object, or an error if the file cannot be created
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut csv_io = CsvIOBuilder::new().delimiter(b'\t').open_write("psth.tsv")?;
+    /// ```
+    ///
+    pub fn open_write(&self, file_path: &str) -> Result<CsvIO, DataIoError> {
+        self.open(file_path, OpenMode::Write)
+    }
+
+    /// Opens a csv file with the dialect to read its records and write new ones after its end
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - A string slice that holds the path to the csv file, created if it does not exist
+    ///
+    /// # Returns
+    ///
+    /// The CsvIO object, or an error if the file cannot be opened or created
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut trials = CsvIOBuilder::new().delimiter(b';').open_append("trials.csv")?;
+    /// ```
+    ///
+    pub fn open_append(&self, file_path: &str) -> Result<CsvIO, DataIoError> {
+        self.open(file_path, OpenMode::Append)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str, contents: &str) -> String {
+        let path = std::env::temp_dir().join(format!("neurorust-dialect-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn records(csv_io: &mut CsvIO) -> Vec<Vec<String>> {
+        csv_io.read_records().unwrap().iter().map(|record| record.iter().map(str::to_string).collect()).collect()
+    }
+
+    #[test]
+    fn headerless_tsv_files_read_every_row_under_numbered_columns() {
+        let path = fixture("lfp.tsv", "0.0\t1.5\t-2\n0.001\t2.5\t-3\n");
+        let csv_io = CsvIO::builder().dialect(CsvDialect::tsv()).has_headers(false).open_read(&path).unwrap();
+        assert_eq!(csv_io.split().0.unwrap().headers(), &StringRecord::from(vec!["column_0", "column_1", "column_2"]));
+        let mut csv_io = CsvIO::builder().delimiter(b'\t').has_headers(false).open_read(&path).unwrap();
+        assert_eq!(csv_io.read_columns::<f64>().unwrap(), [vec![0.0, 0.001], vec![1.5, 2.5], vec![-2.0, -3.0]]);
+        // With the default dialect the whole line is one field and the first row a header
+        let mut default = CsvIO::open_read(&path).unwrap();
+        assert_eq!(records(&mut default), [["0.001\t2.5\t-3"]]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn quotes_comments_and_ragged_rows_follow_the_dialect() {
+        let path = fixture("events.csv", "# exported by rig 3\nonset;label\n1.5;'left; fast'\n# paused\n2.5;'it''s'\n");
+        let builder = CsvIO::builder().delimiter(b';').quote(b'\'').comment(Some(b'#'));
+        let mut csv_io = builder.open_read(&path).unwrap();
+        assert_eq!(records(&mut csv_io), [["1.5", "left; fast"], ["2.5", "it's"]]);
+        // Without the comment byte the first comment is the header row
+        let mut uncommented = CsvIO::builder().delimiter(b';').quote(b'\'').flexible(true).open_read(&path).unwrap();
+        assert_eq!(uncommented.read_record().unwrap().unwrap().len(), 2);
+
+        std::fs::write(&path, "a,b\n1,2\n3\n4,5,6\n").unwrap();
+        assert!(CsvIO::open_read(&path).unwrap().read_records().is_err());
+        let mut flexible = CsvIO::builder().flexible(true).open_read(&path).unwrap();
+        assert_eq!(records(&mut flexible), [vec!["1", "2"], vec!["3"], vec!["4", "5", "6"]]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn writers_use_the_delimiter_and_quote() {
+        let path = fixture("written.csv", "");
+        let builder = CsvIO::builder().delimiter(b';').quote(b'\'');
+        let mut csv_io = builder.open_write(&path).unwrap();
+        csv_io.write_record(StringRecord::from(vec!["onset", "label"])).unwrap();
+        csv_io.write_record(StringRecord::from(vec!["1.5", "left; fast"])).unwrap();
+        csv_io.write_record(StringRecord::from(vec!["2.5", "it's"])).unwrap();
+        csv_io.finish().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "onset;label\n1.5;'left; fast'\n2.5;'it''s'\n");
+        assert_eq!(records(&mut builder.open_read(&path).unwrap()), [["1.5", "left; fast"], ["2.5", "it's"]]);
+
+        let mut appending = builder.open(&path, OpenMode::Append).unwrap();
+        appending.write_record(StringRecord::from(vec!["3.5", "a;b"])).unwrap();
+        appending.finish().unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().ends_with("2.5;'it''s'\n3.5;'a;b'\n"));
+        assert_eq!(builder.open_append(&path).unwrap().read_records().unwrap().len(), 3);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn builders_start_from_the_default_dialect() {
+        let default = CsvDialect::default();
+        assert_eq!((default.delimiter, default.quote, default.comment, default.flexible, default.has_headers), (b',', b'"', None, false, true));
+        assert_eq!(CsvDialect::new(), default);
+        assert_eq!(CsvIOBuilder::new(), CsvIO::builder());
+        assert_eq!(CsvIOBuilder::new().dialect(CsvDialect::tsv()), CsvIOBuilder::new().delimiter(b'\t'));
+        let headers = CsvDialect { has_headers: false, ..default }.headers(&StringRecord::from(vec!["1", "2"]));
+        assert_eq!(headers, StringRecord::from(vec!["column_0", "column_1"]));
+        assert_eq!(default.headers(&StringRecord::from(vec!["t", "x"])), StringRecord::from(vec!["t", "x"]));
+        assert!(matches!(CsvIOBuilder::new().open_read("/nonexistent/neurorust.tsv"), Err(DataIoError::FileNotFound(_))));
+    }
+}
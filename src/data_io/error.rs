@@ -0,0 +1,204 @@
+// A module to describe the errors of the data input and output functions
+
+// Written by Amin Alam in 2024
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use crate::processing::error::ProcessingError;
+
+/// The errors returned by CsvIO and the functions that read or write files through it
+///
+/// # Arguments
+///
+/// * `FileNotFound` - The file does not exist
+/// * `Io` - The file could not be read or written
+/// * `Csv` - A record is not valid csv, e.g. it has a different number of fields than the header row
+/// * `ColumnNotFound` - No column has the header
+/// * `Parse` - A field is not a value of the expected type, at a line of the file counted from 1
+/// * `Processing` - The values were read but could not be processed
+/// * `NotReadable` - Records were read from a file opened for writing
+/// * `NotWritable` - Records were written to a file opened for reading
+///
+/// # Examples
+///
+/// ```
+/// match CsvIO::new("trials.csv") {
+///     Err(DataIoError::FileNotFound(path)) => eprintln!("{} does not exist", path.display()),
+///     Err(error) => eprintln!("Could not open the trials: {}", error),
+///     Ok(csv_io) => run(csv_io),
+/// }
+/// ```
+///
+/// # Note
+///
+/// A DataIoError converts into an io::Error, so functions returning io::Result can use
+/// `?` on it, and an io::Error or csv::Error converts into a DataIoError
+#[derive(Debug)]
+pub enum DataIoError {
+    FileNotFound(PathBuf),
+    Io(io::Error),
+    Csv(csv::Error),
+    ColumnNotFound(String),
+    Parse { line: u64, column: String, value: String },
+    Processing(ProcessingError),
+    NotReadable(PathBuf),
+    NotWritable(PathBuf),
+}
+
+impl fmt::Display for DataIoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataIoError::FileNotFound(path) => write!(f, "File {} not found", path.display()),
+            DataIoError::Io(error) => write!(f, "I/O error: {}", error),
+            DataIoError::Csv(error) => write!(f, "CSV error: {}", error),
+            DataIoError::ColumnNotFound(column) => write!(f, "Column '{}' not found", column),
+            DataIoError::Parse { line, column, value } => write!(f, "Value '{}' of column '{}' at line {} cannot be parsed", value, column, line),
+            DataIoError::Processing(error) => write!(f, "{}", error),
+            DataIoError::NotReadable(path) => write!(f, "File {} was opened for writing and cannot be read", path.display()),
+            DataIoError::NotWritable(path) => write!(f, "File {} was opened for reading and cannot be written", path.display()),
+        }
+    }
+}
+
+impl Error for DataIoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DataIoError::Io(error) => Some(error),
+            DataIoError::Csv(error) => Some(error),
+            DataIoError::Processing(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DataIoError {
+    fn from(error: io::Error) -> Self {
+        DataIoError::Io(error)
+    }
+}
+
+impl From<csv::Error> for DataIoError {
+    fn from(error: csv::Error) -> Self {
+        DataIoError::Csv(error)
+    }
+}
+
+impl From<ProcessingError> for DataIoError {
+    fn from(error: ProcessingError) -> Self {
+        DataIoError::Processing(error)
+    }
+}
+
+impl From<DataIoError> for io::Error {
+    fn from(error: DataIoError) -> Self {
+        match error {
+            DataIoError::Io(error) => error,
+            DataIoError::FileNotFound(_) => io::Error::new(io::ErrorKind::NotFound, error.to_string()),
+            DataIoError::ColumnNotFound(_) | DataIoError::Processing(_) | DataIoError::NotReadable(_) | DataIoError::NotWritable(_) => {
+                io::Error::new(io::ErrorKind::InvalidInput, error.to_string())
+            }
+            DataIoError::Csv(_) | DataIoError::Parse { .. } => io::Error::new(io::ErrorKind::InvalidData, error.to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use csv::StringRecord;
+    use crate::data_io::csv::CsvIO;
+
+    fn fixture(name: &str, contents: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("neurorust-error-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn missing_files_are_errors_not_panics() {
+        let path = std::env::temp_dir().join(format!("neurorust-error-{}-missing.csv", std::process::id()));
+        for result in [CsvIO::new(path.to_str().unwrap()), CsvIO::open_read(path.to_str().unwrap())] {
+            match result {
+                Err(DataIoError::FileNotFound(missing)) => assert_eq!(missing, path),
+                other => panic!("Expected FileNotFound, got {:?}", other.err()),
+            }
+        }
+        let error = CsvIO::new(path.to_str().unwrap()).err().unwrap();
+        assert_eq!(error.to_string(), format!("File {} not found", path.display()));
+        assert_eq!(io::Error::from(error).kind(), io::ErrorKind::NotFound);
+
+        // A file that cannot be created is an I/O error
+        let nowhere = path.join("nested.csv");
+        match CsvIO::open_write(nowhere.to_str().unwrap()) {
+            Err(DataIoError::Io(error)) => assert_eq!(error.kind(), io::ErrorKind::NotFound),
+            other => panic!("Expected an I/O error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn malformed_rows_and_values_are_errors() {
+        let path = fixture("ragged.csv", "a,b\n1,2\n3,4,5\n");
+        let mut csv_io = CsvIO::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(csv_io.read_record().unwrap(), Some(StringRecord::from(vec!["1", "2"])));
+        let error = csv_io.read_record().unwrap_err();
+        assert!(matches!(error, DataIoError::Io(_)) && error.to_string().contains("found record with 3 fields"), "{}", error);
+        assert!(CsvIO::new(path.to_str().unwrap()).unwrap().read_records().is_err());
+        let error = CsvIO::new(path.to_str().unwrap()).unwrap().read_columns::<f64>().unwrap_err();
+        assert!(matches!(error, DataIoError::Csv(_)) && error.source().is_some());
+        assert_eq!(io::Error::from(error).kind(), io::ErrorKind::InvalidData);
+
+        std::fs::write(&path, "a,b\n1,2\n3,x\n").unwrap();
+        let error = CsvIO::new(path.to_str().unwrap()).unwrap().read_columns::<f64>().unwrap_err();
+        assert_eq!(error.to_string(), "Value 'x' of column 'b' at line 3 cannot be parsed");
+        assert!(matches!(error, DataIoError::Parse { line: 3, .. }) && error.source().is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn the_wrong_mode_is_an_error() {
+        let path = fixture("modes.csv", "a\n1\n");
+        let mut reading = CsvIO::new(path.to_str().unwrap()).unwrap();
+        assert!(matches!(reading.write_record(StringRecord::from(vec!["2"])), Err(DataIoError::NotWritable(_))));
+        assert!(matches!(reading.write_records(vec![StringRecord::from(vec!["2"])]), Err(DataIoError::NotWritable(_))));
+        let error = reading.save().unwrap_err();
+        assert_eq!(error.to_string(), format!("File {} was opened for reading and cannot be written", path.display()));
+        // Reading is unaffected and finishing a reader is not an error
+        assert_eq!(reading.read_records().unwrap(), [StringRecord::from(vec!["1"])]);
+        assert!(reading.finish().is_ok());
+
+        let mut writing = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        let error = writing.read_record().unwrap_err();
+        assert_eq!(error.to_string(), format!("File {} was opened for writing and cannot be read", path.display()));
+        assert!(matches!(writing.read_records(), Err(DataIoError::NotReadable(_))));
+        assert_eq!(io::Error::from(error).kind(), io::ErrorKind::InvalidInput);
+        writing.write_record(StringRecord::from(vec!["b"])).unwrap();
+        writing.save().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "b\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn errors_convert_both_ways() {
+        let io_error = io::Error::new(io::ErrorKind::PermissionDenied, "denied");
+        let error = DataIoError::from(io_error);
+        assert_eq!(error.to_string(), "I/O error: denied");
+        assert!(error.source().is_some());
+        // An I/O error comes back out unchanged
+        assert_eq!(io::Error::from(error).kind(), io::ErrorKind::PermissionDenied);
+
+        let error = DataIoError::from(ProcessingError::InvalidParameter("Window must be positive".to_string()));
+        assert_eq!(error.to_string(), "Invalid parameter: Window must be positive");
+        assert_eq!(io::Error::from(error).kind(), io::ErrorKind::InvalidInput);
+        let error = DataIoError::ColumnNotFound("time".to_string());
+        assert_eq!(error.to_string(), "Column 'time' not found");
+        assert_eq!(io::Error::from(error).to_string(), "Column 'time' not found");
+
+        // Functions returning io::Result use ? on DataIoError
+        fn open(path: &str) -> io::Result<CsvIO> {
+            Ok(CsvIO::new(path)?)
+        }
+        assert_eq!(open("/nonexistent/neurorust.csv").err().unwrap().kind(), io::ErrorKind::NotFound);
+    }
+}
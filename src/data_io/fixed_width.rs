@@ -0,0 +1,905 @@
+// A module to read and write fixed-width text files
+
+// Written by Amin Alam in 2024
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use csv::StringRecord;
+use crate::data_io::csv::CsvWriter;
+use crate::data_io::numeric::parse_f64;
+
+/// The type of the values of a fixed-width field
+///
+/// # Arguments
+///
+/// * `Text` - Any text, left-aligned when written
+/// * `Integer` - Whole numbers, right-aligned when written
+/// * `Float` - Real numbers, right-aligned when written with `decimals` digits after the point, or as few as represent the value exactly if `None`
+///
+/// # Examples
+///
+/// ```
+/// let voltage = FieldSpec::new("voltage", 12..24, FieldType::Float { decimals: Some(4) });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldType {
+    Text,
+    Integer,
+    Float { decimals: Option<usize> },
+}
+
+/// A named field of a fixed-width file
+///
+/// # Arguments
+///
+/// * `name` - The name of the field
+/// * `columns` - The byte range of the field within each line
+/// * `kind` - The type of the values of the field
+///
+/// # Examples
+///
+/// ```
+/// let time = FieldSpec::new("time", 0..12, FieldType::Float { decimals: None });
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldSpec {
+    pub name: String,
+    pub columns: Range<usize>,
+    pub kind: FieldType,
+}
+
+/// Implementation of the FieldSpec struct
+///
+/// # Methods
+///
+/// * `new` - Creates a FieldSpec
+/// * `width` - Returns the number of bytes of the field
+impl FieldSpec {
+    /// Creates a FieldSpec
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the field
+    /// * `columns` - The byte range of the field within each line
+    /// * `kind` - The type of the values of the field
+    ///
+    /// # Returns
+    ///
+    /// The FieldSpec
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let unit = FieldSpec::new("unit", 24..30, FieldType::Integer);
+    /// ```
+    ///
+    pub fn new(name: &str, columns: Range<usize>, kind: FieldType) -> Self {
+        Self { name: name.to_string(), columns, kind }
+    }
+
+    /// Returns the number of bytes of the field
+    ///
+    /// # Returns
+    ///
+    /// The width of the field
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(FieldSpec::new("ch1", 12..24, FieldType::Text).width(), 12);
+    /// ```
+    ///
+    pub fn width(&self) -> usize {
+        self.columns.len()
+    }
+}
+
+/// The layout of a fixed-width file
+///
+/// # Arguments
+///
+/// * `fields` - The fields of each line, in the order of their columns
+/// * `header` - Whether the first line of the file holds the names of the fields
+///
+/// # Examples
+///
+/// ```
+/// let spec = FixedWidthSpec::new(
+///     vec![
+///         FieldSpec::new("time", 0..12, FieldType::Float { decimals: Some(4) }),
+///         FieldSpec::new("ch1", 12..24, FieldType::Float { decimals: Some(6) }),
+///         FieldSpec::new("ch2", 24..36, FieldType::Float { decimals: Some(6) }),
+///     ],
+///     false,
+/// )?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct FixedWidthSpec {
+    pub fields: Vec<FieldSpec>,
+    pub header: bool,
+}
+
+/// Implementation of the FixedWidthSpec struct
+///
+/// # Methods
+///
+/// * `new` - Creates a FixedWidthSpec after checking the fields
+/// * `headers` - Returns the names of the fields
+/// * `parse_line` - Splits a line into the trimmed values of its fields
+impl FixedWidthSpec {
+    /// Creates a FixedWidthSpec after checking the fields
+    ///
+    /// # Arguments
+    ///
+    /// * `fields` - The fields of each line, in the order of their columns
+    /// * `header` - Whether the first line of the file holds the names of the fields
+    ///
+    /// # Returns
+    ///
+    /// The FixedWidthSpec, or an error if there is no field, a field is empty, two fields
+    /// overlap or are out of order, or two fields share a name
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let spec = FixedWidthSpec::new(vec![FieldSpec::new("lfp", 0..12, FieldType::Float { decimals: None })], false)?;
+    /// ```
+    ///
+    pub fn new(fields: Vec<FieldSpec>, header: bool) -> io::Result<Self> {
+        if fields.is_empty() {
+            return Err(invalid_input("A fixed-width spec needs at least one field".to_string()));
+        }
+        let mut names = HashSet::new();
+        let mut end = 0;
+        for field in &fields {
+            if field.columns.is_empty() || field.columns.start < end {
+                return Err(invalid_input(format!(
+                    "Field '{}' spans {:?}, which is empty or overlaps the previous field",
+                    field.name, field.columns
+                )));
+            }
+            if !names.insert(field.name.as_str()) {
+                return Err(invalid_input(format!("Field '{}' is declared twice", field.name)));
+            }
+            end = field.columns.end;
+        }
+        Ok(Self { fields, header })
+    }
+
+    /// Returns the names of the fields
+    ///
+    /// # Returns
+    ///
+    /// A record with the name of every field
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// csv_writer.write_record(&spec.headers())?;
+    /// ```
+    ///
+    pub fn headers(&self) -> StringRecord {
+        self.fields.iter().map(|field| field.name.as_str()).collect()
+    }
+
+    /// Splits a line into the trimmed values of its fields
+    ///
+    /// # Arguments
+    ///
+    /// * `line` - The line, without its line ending
+    ///
+    /// # Returns
+    ///
+    /// A record with one value per field, empty for the fields past the end of the line, or
+    /// an error if a field boundary splits a character
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let record = spec.parse_line("      0.0010   -12.500000")?;
+    /// ```
+    ///
+    pub fn parse_line(&self, line: &str) -> io::Result<StringRecord> {
+        let mut record = StringRecord::with_capacity(line.len(), self.fields.len());
+        for field in &self.fields {
+            let start = field.columns.start.min(line.len());
+            let end = field.columns.end.min(line.len());
+            let value = line.get(start..end).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("Field '{}' splits a character of the line '{}'", field.name, line))
+            })?;
+            record.push_field(value.trim());
+        }
+        Ok(record)
+    }
+}
+
+/// Infers the layout of a fixed-width file from some of its lines
+///
+/// # Arguments
+///
+/// * `sample_lines` - The first lines of the file
+///
+/// # Returns
+///
+/// The FixedWidthSpec, or an error if the lines hold no data
+///
+/// # Examples
+///
+/// ```
+/// let sample: Vec<String> = BufReader::new(File::open("legacy.txt")?).lines().take(100).collect::<io::Result<_>>()?;
+/// let spec = sniff_spec(&sample.iter().map(String::as_str).collect::<Vec<_>>())?;
+/// ```
+///
+/// # Note
+///
+/// A field is a run of byte columns that hold a non-blank character in at least one data
+/// line, so two fields only come apart if a blank column separates them in every line of
+/// the sample. Each field is widened to the left up to the end of the previous field, which
+/// matches right-aligned numbers. The first line is taken as a header if none of its words
+/// is a number while the other lines hold numbers; its words then name the fields, and
+/// fields without a name are called `field_1`, `field_2` and so on. A field is typed as
+/// Integer or Float if all its values in the sample are, and as Text otherwise.
+///
+pub fn sniff_spec(sample_lines: &[&str]) -> io::Result<FixedWidthSpec> {
+    let lines: Vec<&str> = sample_lines.iter().map(|line| line.trim_end_matches(['\r', '\n'])).filter(|line| !line.trim().is_empty()).collect();
+    let is_number = |word: &str| word.parse::<f64>().is_ok();
+    let header = lines.len() > 1
+        && !lines[0].split_whitespace().any(is_number)
+        && lines[1..].iter().any(|line| line.split_whitespace().any(is_number));
+    let data = &lines[header as usize..];
+    let width = data.iter().map(|line| line.len()).max().unwrap_or(0);
+    if width == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "No data lines to infer fixed-width fields from"));
+    }
+
+    let mut filled = vec![false; width];
+    for line in data {
+        for (column, byte) in line.bytes().enumerate() {
+            filled[column] |= !byte.is_ascii_whitespace();
+        }
+    }
+    let mut ends: Vec<usize> = (1..=width).filter(|&column| filled[column - 1] && (column == width || !filled[column])).collect();
+    if let Some(last) = ends.last_mut() {
+        *last = width.max(if header { lines[0].len() } else { 0 });
+    }
+
+    let mut used = HashSet::new();
+    let mut fields = Vec::with_capacity(ends.len());
+    let mut start = 0;
+    for (i, &end) in ends.iter().enumerate() {
+        let columns = start..end;
+        let values: Vec<&str> = data.iter().filter_map(|line| line.get(columns.start.min(line.len())..end.min(line.len()))).map(str::trim).filter(|value| !value.is_empty()).collect();
+        let kind = if !values.is_empty() && values.iter().all(|value| value.parse::<i64>().is_ok()) {
+            FieldType::Integer
+        } else if !values.is_empty() && values.iter().all(|value| is_number(value)) {
+            FieldType::Float { decimals: None }
+        } else {
+            FieldType::Text
+        };
+        let name = header
+            .then(|| lines[0].get(columns.start.min(lines[0].len())..end.min(lines[0].len())).map(str::trim))
+            .flatten()
+            .filter(|name| !name.is_empty() && !name.contains(char::is_whitespace) && !used.contains(*name))
+            .map_or_else(|| format!("field_{}", i + 1), str::to_string);
+        used.insert(name.clone());
+        fields.push(FieldSpec { name, columns, kind });
+        start = end;
+    }
+    FixedWidthSpec::new(fields, header)
+}
+
+/// A class to read fixed-width text files
+///
+/// # Arguments
+///
+/// * `file_path` - The path to the file
+/// * `spec` - The layout of the file
+/// * `reader` - A buffered reader of the file
+/// * `line` - The number of lines read so far
+///
+/// # Examples
+///
+/// ```
+/// let mut legacy = FixedWidthIO::open_sniffed("session_04.txt", 100)?;
+/// let ch1 = legacy.read_column_f64("ch1")?;
+/// ```
+///
+/// # Note
+///
+/// The values are trimmed of the padding of their fields, and lines that are entirely blank
+/// are skipped
+pub struct FixedWidthIO {
+    file_path: PathBuf,
+    spec: FixedWidthSpec,
+    reader: BufReader<File>,
+    line: usize,
+}
+
+/// Implementation of the FixedWidthIO class
+///
+/// # Methods
+///
+/// * `open` - Opens a fixed-width file with a known layout
+/// * `open_sniffed` - Opens a fixed-width file and infers its layout from its first lines
+/// * `spec` - Returns the layout of the file
+/// * `headers` - Returns the names of the fields
+/// * `read_record` - Reads the next record
+/// * `read_records` - Reads all remaining records
+/// * `read_column_f64` - Reads a field of all remaining records as numbers
+/// * `to_csv` - Converts the remaining records to csv
+impl FixedWidthIO {
+    /// Opens a fixed-width file with a known layout
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path to the file
+    /// * `spec` - The layout of the file
+    ///
+    /// # Returns
+    ///
+    /// A FixedWidthIO positioned at the first record, or an error if the file cannot be opened
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut legacy = FixedWidthIO::open("session_04.txt", spec)?;
+    /// ```
+    ///
+    pub fn open<P: AsRef<Path>>(file_path: P, spec: FixedWidthSpec) -> io::Result<Self> {
+        let mut io = Self { file_path: file_path.as_ref().to_path_buf(), reader: BufReader::new(File::open(&file_path)?), spec, line: 0 };
+        if io.spec.header {
+            io.next_line()?;
+        }
+        Ok(io)
+    }
+
+    /// Opens a fixed-width file and infers its layout from its first lines
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path to the file
+    /// * `sample_size` - The number of lines the layout is inferred from
+    ///
+    /// # Returns
+    ///
+    /// A FixedWidthIO positioned at the first record, or an error if the file cannot be
+    /// opened or its first lines hold no data
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let legacy = FixedWidthIO::open_sniffed("session_04.txt", 100)?;
+    /// println!("{:?}", legacy.spec());
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// See `sniff_spec` for how the layout is inferred. A field that is only filled in
+    /// beyond the sampled lines is not detected.
+    ///
+    pub fn open_sniffed<P: AsRef<Path>>(file_path: P, sample_size: usize) -> io::Result<Self> {
+        let sample = BufReader::new(File::open(&file_path)?).lines().take(sample_size).collect::<io::Result<Vec<String>>>()?;
+        let spec = sniff_spec(&sample.iter().map(String::as_str).collect::<Vec<_>>())?;
+        Self::open(file_path, spec)
+    }
+
+    /// Returns the layout of the file
+    ///
+    /// # Returns
+    ///
+    /// The FixedWidthSpec the file is read with
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let widths: Vec<usize> = legacy.spec().fields.iter().map(FieldSpec::width).collect();
+    /// ```
+    ///
+    pub fn spec(&self) -> &FixedWidthSpec {
+        &self.spec
+    }
+
+    /// Returns the names of the fields
+    ///
+    /// # Returns
+    ///
+    /// A record with the name of every field
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let headers = legacy.headers();
+    /// ```
+    ///
+    pub fn headers(&self) -> StringRecord {
+        self.spec.headers()
+    }
+
+    /// Reads the next record
+    ///
+    /// # Returns
+    ///
+    /// The trimmed values of the next non-blank line, None at the end of the file, or an error
+    /// if the file cannot be read
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// while let Some(record) = legacy.read_record()? {
+    ///     println!("{:?}", record);
+    /// }
+    /// ```
+    ///
+    pub fn read_record(&mut self) -> io::Result<Option<StringRecord>> {
+        while let Some(line) = self.next_line()? {
+            if !line.trim().is_empty() {
+                return self.spec.parse_line(&line).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    /// Reads all remaining records
+    ///
+    /// # Returns
+    ///
+    /// The records, or the error of the first line that cannot be read
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let records = legacy.read_records()?;
+    /// ```
+    ///
+    pub fn read_records(&mut self) -> io::Result<Vec<StringRecord>> {
+        let mut records = Vec::new();
+        while let Some(record) = self.read_record()? {
+            records.push(record);
+        }
+        Ok(records)
+    }
+
+    /// Reads a field of all remaining records as numbers
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the field
+    ///
+    /// # Returns
+    ///
+    /// The value of the field in every record, NaN where it is blank, or an error if there
+    /// is no such field or a value is not a number
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let ch1 = legacy.read_column_f64("ch1")?;
+    /// ```
+    ///
+    pub fn read_column_f64(&mut self, name: &str) -> io::Result<Vec<f64>> {
+        let column = self
+            .spec
+            .fields
+            .iter()
+            .position(|field| field.name == name)
+            .ok_or_else(|| invalid_input(format!("No field named '{}' in {}", name, self.file_path.display())))?;
+        let mut values = Vec::new();
+        while let Some(record) = self.read_record()? {
+            let value = &record[column];
+            values.push(if value.is_empty() {
+                f64::NAN
+            } else {
+                parse_f64(value.as_bytes()).ok_or_else(|| {
+                    io::Error::new(io::ErrorKind::InvalidData, format!("Field '{}' on line {} is not a number: '{}'", name, self.line, value))
+                })?
+            });
+        }
+        Ok(values)
+    }
+
+    /// Converts the remaining records to csv
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_writer` - The writer of the csv file
+    ///
+    /// # Returns
+    ///
+    /// The number of records written after the header row, or the first error of reading or writing
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut csv_writer = CsvWriter::create("session_04.csv")?;
+    /// FixedWidthIO::open_sniffed("session_04.txt", 100)?.to_csv(&mut csv_writer)?;
+    /// csv_writer.flush()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row with the names of the fields is written first
+    ///
+    pub fn to_csv(&mut self, csv_writer: &mut CsvWriter) -> io::Result<usize> {
+        csv_writer.write_record(&self.headers())?;
+        let mut count = 0;
+        while let Some(record) = self.read_record()? {
+            csv_writer.write_record(&record)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Reads the next line without its line ending
+    fn next_line(&mut self) -> io::Result<Option<String>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        self.line += 1;
+        line.truncate(line.trim_end_matches(['\r', '\n']).len());
+        Ok(Some(line))
+    }
+}
+
+#[cfg(feature = "serde")]
+impl FixedWidthIO {
+    /// Reads all remaining records into a type that can be deserialized
+    ///
+    /// # Returns
+    ///
+    /// One value per record, with the fields matched by name, or an error if a record cannot
+    /// be read or deserialized
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// #[derive(serde::Deserialize)]
+    /// struct Row { time: f64, ch1: f64, unit: Option<u32> }
+    ///
+    /// let rows: Vec<Row> = legacy.deserialize()?;
+    /// ```
+    ///
+    pub fn deserialize<T: serde::de::DeserializeOwned>(&mut self) -> io::Result<Vec<T>> {
+        let headers = self.headers();
+        let mut rows = Vec::new();
+        while let Some(record) = self.read_record()? {
+            rows.push(record.deserialize(Some(&headers)).map_err(|error| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("Line {} of {}: {}", self.line, self.file_path.display(), error))
+            })?);
+        }
+        Ok(rows)
+    }
+}
+
+/// A class to write fixed-width text files
+///
+/// # Arguments
+///
+/// * `writer` - A buffered writer of the file
+/// * `spec` - The layout of the file
+///
+/// # Examples
+///
+/// ```
+/// let mut writer = FixedWidthWriter::create("export.txt", spec)?;
+/// writer.write_values(&[0.001, -12.5, 3.25])?;
+/// writer.flush()?;
+/// ```
+///
+/// # Note
+///
+/// Numbers are right-aligned and text left-aligned within their fields, and the columns
+/// between fields are filled with spaces. A value wider than its field is an error; it is
+/// never cut to fit.
+pub struct FixedWidthWriter {
+    writer: BufWriter<File>,
+    spec: FixedWidthSpec,
+}
+
+/// Implementation of the FixedWidthWriter class
+///
+/// # Methods
+///
+/// * `create` - Creates or truncates a fixed-width file
+/// * `write_record` - Writes a record of text values
+/// * `write_values` - Writes a record of numbers
+/// * `flush` - Writes the buffered lines to the file
+impl FixedWidthWriter {
+    /// Creates or truncates a fixed-width file
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path to the file
+    /// * `spec` - The layout of the file
+    ///
+    /// # Returns
+    ///
+    /// A FixedWidthWriter, or an error if the file cannot be created or a field name is wider
+    /// than its field while the spec has a header
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let writer = FixedWidthWriter::create("export.txt", spec)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// If the spec has a header, the names of the fields are written as the first line
+    ///
+    pub fn create<P: AsRef<Path>>(file_path: P, spec: FixedWidthSpec) -> io::Result<Self> {
+        let mut writer = Self { writer: BufWriter::new(File::create(file_path)?), spec };
+        if writer.spec.header {
+            let names = writer.spec.headers();
+            writer.write_line(&names, false)?;
+        }
+        Ok(writer)
+    }
+
+    /// Writes a record of text values
+    ///
+    /// # Arguments
+    ///
+    /// * `record` - One value per field
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if the record does not have one value per field, a value does
+    /// not match the type of its field or is wider than its field, or the file cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// writer.write_record(&StringRecord::from(vec!["0.0010", "-12.500000", "3"]))?;
+    /// ```
+    ///
+    pub fn write_record(&mut self, record: &StringRecord) -> io::Result<()> {
+        self.write_line(record, true)
+    }
+
+    /// Writes a record of numbers
+    ///
+    /// # Arguments
+    ///
+    /// * `values` - One value per field
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if there is not one value per field, a value of an Integer field
+    /// is not whole, a value is wider than its field, or the file cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// writer.write_values(&[time, ch1, ch2])?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Float fields are written with their declared number of decimals, and NaN as a blank field
+    ///
+    pub fn write_values(&mut self, values: &[f64]) -> io::Result<()> {
+        if values.len() != self.spec.fields.len() {
+            return Err(invalid_input(format!("Expected {} values, got {}", self.spec.fields.len(), values.len())));
+        }
+        let record: StringRecord = values
+            .iter()
+            .zip(&self.spec.fields)
+            .map(|(&value, field)| match field.kind {
+                _ if value.is_nan() => String::new(),
+                FieldType::Float { decimals: Some(decimals) } => format!("{:.*}", decimals, value),
+                FieldType::Integer if value.fract() == 0.0 => format!("{:.0}", value),
+                _ => value.to_string(),
+            })
+            .collect();
+        self.write_line(&record, true)
+    }
+
+    /// Writes the buffered lines to the file
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if the file cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// writer.flush()?;
+    /// ```
+    ///
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    /// Aligns the values into their fields and writes them as one line
+    fn write_line(&mut self, record: &StringRecord, check_types: bool) -> io::Result<()> {
+        if record.len() != self.spec.fields.len() {
+            return Err(invalid_input(format!("Expected {} values, got {}", self.spec.fields.len(), record.len())));
+        }
+        let mut line = String::with_capacity(self.spec.fields.last().map_or(0, |field| field.columns.end));
+        for (value, field) in record.iter().zip(&self.spec.fields) {
+            let value = value.trim();
+            let width = field.width();
+            if value.len() > width {
+                return Err(invalid_input(format!("'{}' does not fit in the {} characters of field '{}'", value, width, field.name)));
+            }
+            let numeric = match field.kind {
+                FieldType::Text => false,
+                FieldType::Integer if check_types && !value.is_empty() && value.parse::<i64>().is_err() => {
+                    return Err(invalid_input(format!("'{}' is not a whole number for field '{}'", value, field.name)));
+                }
+                FieldType::Float { .. } if check_types && !value.is_empty() && value.parse::<f64>().is_err() => {
+                    return Err(invalid_input(format!("'{}' is not a number for field '{}'", value, field.name)));
+                }
+                _ => true,
+            };
+            let padding = width - value.len();
+            line.extend(std::iter::repeat_n(' ', field.columns.start - line.len() + if numeric { padding } else { 0 }));
+            line.push_str(value);
+            line.extend(std::iter::repeat_n(' ', if numeric { 0 } else { padding }));
+        }
+        line.truncate(line.trim_end().len());
+        line.push('\n');
+        self.writer.write_all(line.as_bytes())
+    }
+}
+
+/// Creates an error for an invalid spec or value
+fn invalid_input(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Output of the legacy program: a header, 12-character right-aligned fields, a 6-character
+    /// unit field, CRLF line endings, a blank line and a blank unit where no spike was sorted
+    const LEGACY: &str = "        time         ch1         ch2  unit\r\n      0.0000  -12.500000    3.250000     1\r\n      0.0010   -8.125000    2.000000     2\r\n\r\n      0.0020    0.000000   -1.875000\r\n      0.0030   14.062500 -100.000000    12\r\n";
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("neurorust-fixed-width-{}-{}", std::process::id(), name))
+    }
+
+    fn float(name: &str, columns: Range<usize>, decimals: Option<usize>) -> FieldSpec {
+        FieldSpec::new(name, columns, FieldType::Float { decimals })
+    }
+
+    #[test]
+    fn sniffing_the_legacy_layout_finds_the_fields_names_and_types() {
+        let lines: Vec<&str> = LEGACY.split('\n').collect();
+        let spec = sniff_spec(&lines).unwrap();
+        assert!(spec.header);
+        let expected = vec![float("time", 0..12, None), float("ch1", 12..24, None), float("ch2", 24..36, None), FieldSpec::new("unit", 36..42, FieldType::Integer)];
+        assert_eq!(spec.fields, expected);
+
+        let path = temp_path("legacy.txt");
+        std::fs::write(&path, LEGACY).unwrap();
+        let mut legacy = FixedWidthIO::open_sniffed(&path, 3).unwrap();
+        assert_eq!(legacy.spec(), &spec);
+        assert_eq!(legacy.headers(), StringRecord::from(vec!["time", "ch1", "ch2", "unit"]));
+        let records = legacy.read_records().unwrap();
+        assert_eq!(records.len(), 4);
+        assert_eq!(records[2], StringRecord::from(vec!["0.0020", "0.000000", "-1.875000", ""]));
+        let mut legacy = FixedWidthIO::open(&path, spec.clone()).unwrap();
+        assert_eq!(legacy.read_column_f64("ch2").unwrap(), vec![3.25, 2.0, -1.875, -100.0]);
+        let mut legacy = FixedWidthIO::open(&path, spec).unwrap();
+        let units = legacy.read_column_f64("unit").unwrap();
+        assert_eq!(&units[..2], &[1.0, 2.0]);
+        assert!(units[2].is_nan() && units[3] == 12.0);
+        let error = legacy.read_column_f64("ch3").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        std::fs::remove_file(&path).unwrap();
+
+        // Without a header the fields are numbered, and text makes a field Text
+        let spec = sniff_spec(&["  1 north", " 20 south"]).unwrap();
+        assert!(!spec.header);
+        assert_eq!(spec.fields, vec![FieldSpec::new("field_1", 0..3, FieldType::Integer), FieldSpec::new("field_2", 3..9, FieldType::Text)]);
+        assert!(sniff_spec(&["", "   "]).is_err());
+    }
+
+    #[test]
+    fn a_declared_spec_reads_fields_that_fill_their_width() {
+        // With no blank column between full fields sniffing cannot split them, a declared spec can
+        let text = "   0.000000   -1.000000\n-1234.5678901234.567890\n";
+        assert_eq!(sniff_spec(&text.lines().collect::<Vec<_>>()).unwrap().fields.len(), 1);
+        let path = temp_path("full.txt");
+        std::fs::write(&path, text).unwrap();
+        let spec = FixedWidthSpec::new(vec![float("ch1", 0..12, None), float("ch2", 12..24, None)], false).unwrap();
+        let mut legacy = FixedWidthIO::open(&path, spec.clone()).unwrap();
+        assert_eq!(legacy.read_column_f64("ch2").unwrap(), vec![-1.0, 1234.56789]);
+        std::fs::write(&path, "    1.5    abc\n").unwrap();
+        let error = FixedWidthIO::open(&path, spec.clone()).unwrap().read_column_f64("ch2").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("line 1"), "{}", error);
+        std::fs::remove_file(&path).unwrap();
+
+        // A short line leaves the missing fields empty, and a field boundary may not split a character
+        assert_eq!(spec.parse_line("         2.5").unwrap(), StringRecord::from(vec!["2.5", ""]));
+        assert!(spec.parse_line("           µV   3").is_err());
+
+        for fields in [
+            vec![],
+            vec![float("a", 0..0, None)],
+            vec![float("a", 0..12, None), float("b", 10..20, None)],
+            vec![float("a", 0..12, None), float("a", 12..24, None)],
+        ] {
+            assert_eq!(FixedWidthSpec::new(fields, false).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+
+    #[test]
+    fn the_writer_aligns_values_and_refuses_to_truncate() {
+        let spec = FixedWidthSpec::new(
+            vec![float("time", 0..12, Some(4)), float("ch1", 12..24, Some(6)), FieldSpec::new("unit", 26..32, FieldType::Integer), FieldSpec::new("label", 33..41, FieldType::Text)],
+            true,
+        )
+        .unwrap();
+        let path = temp_path("written.txt");
+        let mut writer = FixedWidthWriter::create(&path, spec.clone()).unwrap();
+        writer.write_values(&[0.001, -12.5, 3.0, f64::NAN]).unwrap();
+        writer.write_record(&StringRecord::from(vec!["0.0020", "", "12", "noise"])).unwrap();
+        // Too wide for its field, not a whole number, not a number, and the wrong count
+        let errors = [
+            writer.write_values(&[0.001, -123456.5, 3.0, 0.0]).unwrap_err(),
+            writer.write_values(&[0.001, 1.0, 2.5, 0.0]).unwrap_err(),
+            writer.write_record(&StringRecord::from(vec!["0.0030", "high", "1", "x"])).unwrap_err(),
+            writer.write_record(&StringRecord::from(vec!["0.0030", "1.0", "1", "a long label"])).unwrap_err(),
+            writer.write_values(&[0.001]).unwrap_err(),
+        ];
+        assert!(errors.iter().all(|error| error.kind() == io::ErrorKind::InvalidInput));
+        assert!(errors[0].to_string().contains("-123456.500000"), "{}", errors[0]);
+        writer.flush().unwrap();
+        drop(writer);
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(written, "        time         ch1    unit label\n      0.0010  -12.500000       3\n      0.0020                  12 noise\n");
+        let mut legacy = FixedWidthIO::open(&path, spec.clone()).unwrap();
+        let records = legacy.read_records().unwrap();
+        assert_eq!(records, vec![StringRecord::from(vec!["0.0010", "-12.500000", "3", ""]), StringRecord::from(vec!["0.0020", "", "12", "noise"])]);
+        std::fs::remove_file(&path).unwrap();
+
+        // A header name wider than its field cannot be written either
+        let narrow = FixedWidthSpec::new(vec![float("voltage", 0..4, None)], true).unwrap();
+        assert!(FixedWidthWriter::create(&path, narrow).is_err());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn legacy_files_convert_to_csv() {
+        let path = temp_path("convert.txt");
+        std::fs::write(&path, LEGACY).unwrap();
+        let csv_path = temp_path("convert.csv");
+        let mut csv_writer = CsvWriter::create(&csv_path).unwrap();
+        let count = FixedWidthIO::open_sniffed(&path, 100).unwrap().to_csv(&mut csv_writer).unwrap();
+        csv_writer.flush().unwrap();
+        drop(csv_writer);
+        let converted = std::fs::read_to_string(&csv_path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&csv_path).unwrap();
+        assert_eq!(count, 4);
+        assert_eq!(
+            converted.lines().collect::<Vec<_>>(),
+            vec!["time,ch1,ch2,unit", "0.0000,-12.500000,3.250000,1", "0.0010,-8.125000,2.000000,2", "0.0020,0.000000,-1.875000,", "0.0030,14.062500,-100.000000,12"]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn records_deserialize_by_field_name() {
+        #[derive(serde::Deserialize)]
+        struct Row {
+            ch2: f64,
+            time: f64,
+            unit: Option<u32>,
+        }
+        let path = temp_path("serde.txt");
+        std::fs::write(&path, LEGACY).unwrap();
+        let rows: Vec<Row> = FixedWidthIO::open_sniffed(&path, 100).unwrap().deserialize().unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(rows.iter().map(|row| (row.time, row.ch2, row.unit)).collect::<Vec<_>>(), vec![(0.0, 3.25, Some(1)), (0.001, 2.0, Some(2)), (0.002, -1.875, None), (0.003, -100.0, Some(12))]);
+    }
+}
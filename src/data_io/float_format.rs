@@ -0,0 +1,277 @@
+// A module to control how floating-point numbers are written to text files
+
+// Written by Amin Alam in 2024
+
+/// The notation of a FloatFormat
+///
+/// # Arguments
+///
+/// * `Shortest` - The fewest digits that parse back to the same number, in plain notation from 1e-5 to 1e16 and in scientific notation outside
+/// * `Fixed` - A fixed number of `decimals` after the point
+/// * `Significant` - A fixed number of significant `digits`, without trailing zeros, in scientific notation for very small or large numbers
+/// * `Scientific` - Scientific notation with a fixed number of significant `digits`
+///
+/// # Examples
+///
+/// ```
+/// let format = FloatFormat { notation: FloatNotation::Significant { digits: 6 }, ..FloatFormat::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FloatNotation {
+    Shortest,
+    Fixed { decimals: usize },
+    Significant { digits: usize },
+    Scientific { digits: usize },
+}
+
+/// How the writers of the crate format floating-point numbers
+///
+/// # Arguments
+///
+/// * `notation` - The notation of the numbers, `Shortest` by default
+/// * `normalize_negative_zero` - Writes negative zero, including negative numbers that round to zero, without its sign, false by default
+/// * `zero_below` - Writes numbers whose magnitude is below this threshold as 0, None by default
+///
+/// # Examples
+///
+/// ```
+/// let mut csv_io = CsvIO::open_write("spectrum.csv")?;
+/// csv_io.set_float_format(FloatFormat { zero_below: Some(1e-12), ..FloatFormat::significant(6) });
+/// spectrum.to_csv(&mut csv_io)?;
+/// csv_io.save()?;
+/// ```
+///
+/// # Note
+///
+/// Only `Shortest` is lossless: every number parses back to the identical f64. The other
+/// notations round, `Fixed` to an absolute and `Significant` and `Scientific` to a relative
+/// precision. Six significant digits keep a relative error below 5e-7, which is far below the
+/// noise of recorded signals but not enough to reproduce a computation bit for bit, and 17
+/// digits are always enough to make them lossless too. NaN and infinities are written as
+/// `NaN`, `inf` and `-inf` in every notation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FloatFormat {
+    pub notation: FloatNotation,
+    pub normalize_negative_zero: bool,
+    pub zero_below: Option<f64>,
+}
+
+impl Default for FloatFormat {
+    fn default() -> Self {
+        Self { notation: FloatNotation::Shortest, normalize_negative_zero: false, zero_below: None }
+    }
+}
+
+/// Implementation of the FloatFormat struct
+///
+/// # Methods
+///
+/// * `fixed` - Returns a format with a fixed number of decimals
+/// * `significant` - Returns a format with a fixed number of significant digits
+/// * `scientific` - Returns a format in scientific notation
+/// * `format` - Formats a number
+impl FloatFormat {
+    /// Returns a format with a fixed number of decimals
+    ///
+    /// # Arguments
+    ///
+    /// * `decimals` - The number of digits after the point
+    ///
+    /// # Returns
+    ///
+    /// The FloatFormat, with the other options at their defaults
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(FloatFormat::fixed(3).format(0.25), "0.250");
+    /// ```
+    ///
+    pub fn fixed(decimals: usize) -> Self {
+        Self { notation: FloatNotation::Fixed { decimals }, ..Self::default() }
+    }
+
+    /// Returns a format with a fixed number of significant digits
+    ///
+    /// # Arguments
+    ///
+    /// * `digits` - The number of significant digits, at least 1
+    ///
+    /// # Returns
+    ///
+    /// The FloatFormat, with the other options at their defaults
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(FloatFormat::significant(4).format(std::f64::consts::PI), "3.142");
+    /// ```
+    ///
+    pub fn significant(digits: usize) -> Self {
+        Self { notation: FloatNotation::Significant { digits }, ..Self::default() }
+    }
+
+    /// Returns a format in scientific notation
+    ///
+    /// # Arguments
+    ///
+    /// * `digits` - The number of significant digits, at least 1
+    ///
+    /// # Returns
+    ///
+    /// The FloatFormat, with the other options at their defaults
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(FloatFormat::scientific(3).format(1234.5), "1.23e3");
+    /// ```
+    ///
+    pub fn scientific(digits: usize) -> Self {
+        Self { notation: FloatNotation::Scientific { digits }, ..Self::default() }
+    }
+
+    /// Formats a number
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The number
+    ///
+    /// # Returns
+    ///
+    /// The text of the number
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let format = FloatFormat { normalize_negative_zero: true, ..FloatFormat::fixed(2) };
+    /// assert_eq!(format.format(-0.001), "0.00");
+    /// assert_eq!(FloatFormat::default().format(1e-17), "1e-17");
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A digit count of 0 is treated as 1
+    ///
+    pub fn format(&self, value: f64) -> String {
+        let value = match self.zero_below {
+            Some(threshold) if value.abs() < threshold => 0.0,
+            _ => value,
+        };
+        let text = if !value.is_finite() {
+            value.to_string()
+        } else {
+            match self.notation {
+                FloatNotation::Shortest if value == 0.0 || (1e-5..1e16).contains(&value.abs()) => value.to_string(),
+                FloatNotation::Shortest => format!("{:e}", value),
+                FloatNotation::Fixed { decimals } => format!("{:.*}", decimals, value),
+                FloatNotation::Significant { digits } => significant(value, digits.max(1)),
+                FloatNotation::Scientific { digits } => format!("{:.*e}", digits.max(1) - 1, value),
+            }
+        };
+        match text.strip_prefix('-') {
+            Some(magnitude) if self.normalize_negative_zero && is_zero(magnitude) => magnitude.to_string(),
+            _ => text,
+        }
+    }
+}
+
+/// Formats a finite number with a number of significant digits, like `%g` in C
+fn significant(value: f64, digits: usize) -> String {
+    let scientific = format!("{:.*e}", digits - 1, value);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap_or((&scientific, "0"));
+    let exponent: i64 = exponent.parse().unwrap_or_default();
+    if exponent < -5 || exponent >= digits as i64 {
+        format!("{}e{}", trim_zeros(mantissa), exponent)
+    } else {
+        trim_zeros(&format!("{:.*}", (digits as i64 - 1 - exponent) as usize, value)).to_string()
+    }
+}
+
+/// Removes the trailing zeros after the point, and the point if nothing follows it
+fn trim_zeros(text: &str) -> &str {
+    if text.contains('.') { text.trim_end_matches('0').trim_end_matches('.') } else { text }
+}
+
+/// Checks whether the text of a number without its sign is zero
+fn is_zero(text: &str) -> bool {
+    text.split('e').next().is_some_and(|mantissa| mantissa.chars().all(|c| c == '0' || c == '.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    /// Random finite numbers over the whole exponent range, including subnormals
+    fn random_finite(n: usize) -> Vec<f64> {
+        let mut rng = SeededRng::new(160);
+        let mut values = vec![0.0, -0.0, 1e-5, 1e16, f64::MIN_POSITIVE, 5e-324, f64::MAX, -f64::MAX, 0.1, 1.0 / 3.0];
+        while values.len() < n {
+            let value = f64::from_bits(rng.next_u64());
+            if value.is_finite() {
+                values.push(value);
+            }
+        }
+        values
+    }
+
+    #[test]
+    fn shortest_parses_back_to_the_same_number_with_the_fewest_digits() {
+        for value in random_finite(20000) {
+            let text = FloatFormat::default().format(value);
+            let parsed: f64 = text.parse().unwrap();
+            assert_eq!(parsed.to_bits(), value.to_bits(), "{} was written as {}", value, text);
+            let mantissa = text.trim_start_matches('-').split('e').next().unwrap();
+            let digits = mantissa.trim_matches(|c| c == '0' || c == '.').replace('.', "").len();
+            if digits > 1 {
+                let shorter: f64 = format!("{:.*e}", digits - 2, value).parse().unwrap();
+                assert_ne!(shorter, value, "{} digits of {} are enough", digits - 1, value);
+            }
+            let scientific = text.contains('e');
+            assert_eq!(scientific, value != 0.0 && !(1e-5..1e16).contains(&value.abs()), "{}", text);
+        }
+    }
+
+    #[test]
+    fn seventeen_significant_digits_are_lossless_and_six_keep_the_relative_error() {
+        for value in random_finite(5000) {
+            for format in [FloatFormat::significant(17), FloatFormat::scientific(17)] {
+                assert_eq!(format.format(value).parse::<f64>().unwrap().to_bits(), value.to_bits());
+            }
+            if value.abs() >= f64::MIN_POSITIVE && value.abs() < 1e300 {
+                let parsed: f64 = FloatFormat::significant(6).format(value).parse().unwrap();
+                assert!(((parsed - value) / value).abs() <= 5e-6, "{} against {}", parsed, value);
+            }
+        }
+    }
+
+    #[test]
+    fn notations_and_options_write_the_documented_text() {
+        assert_eq!(FloatFormat::fixed(3).format(0.25), "0.250");
+        assert_eq!(FloatFormat::fixed(0).format(2.5), "2");
+        assert_eq!(FloatFormat::significant(4).format(std::f64::consts::PI), "3.142");
+        assert_eq!(FloatFormat::significant(3).format(1200.0), "1.2e3");
+        assert_eq!(FloatFormat::significant(3).format(120.0), "120");
+        assert_eq!(FloatFormat::significant(6).format(1.5e-6), "1.5e-6");
+        assert_eq!(FloatFormat::significant(0).format(0.0152), "0.02");
+        assert_eq!(FloatFormat::scientific(3).format(1234.5), "1.23e3");
+        assert_eq!(FloatFormat::default().format(1e-17), "1e-17");
+        assert_eq!(FloatFormat::default().format(-0.0), "-0");
+        for format in [FloatFormat::default(), FloatFormat::fixed(2), FloatFormat::significant(3)] {
+            assert_eq!(format.format(f64::NAN), "NaN");
+            assert_eq!(format.format(f64::NEG_INFINITY), "-inf");
+        }
+
+        let normalized = FloatFormat { normalize_negative_zero: true, ..FloatFormat::fixed(2) };
+        assert_eq!(normalized.format(-0.001), "0.00");
+        assert_eq!(normalized.format(-0.01), "-0.01");
+        assert_eq!(FloatFormat { normalize_negative_zero: true, ..FloatFormat::default() }.format(-0.0), "0");
+        let thresholded = FloatFormat { zero_below: Some(1e-12), ..FloatFormat::default() };
+        assert_eq!(thresholded.format(3e-13), "0");
+        assert_eq!(thresholded.format(-3e-13), "0");
+        assert_eq!(thresholded.format(2e-12), "2e-12");
+    }
+}
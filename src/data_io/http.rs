@@ -0,0 +1,700 @@
+// A module to download csv data over HTTP(S)
+
+// Written by Amin Alam in 2024
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// The number of times an interrupted download is resumed by default
+pub const DEFAULT_MAX_RETRIES: usize = 3;
+
+/// The number of bytes read from the response at a time
+const BUFFER_SIZE: usize = 64 * 1024;
+
+/// Numbers the temporary files of the downloads of this process
+static SPILL_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+type ProgressCallback<'a> = Box<dyn FnMut(u64, Option<u64>) + 'a>;
+
+enum Auth {
+    Bearer(String),
+    Basic(String, String),
+}
+
+/// The options of a download
+///
+/// # Examples
+///
+/// ```
+/// let mut options = HttpOptions::new()
+///     .bearer_token(&token)
+///     .allow_spill(true)
+///     .on_progress(|bytes, total| eprintln!("{} of {:?} bytes", bytes, total));
+/// let csv_io = CsvIO::open_url("https://files.osf.io/v1/resources/abc12/providers/osfstorage/lfp.csv", &mut options)?;
+/// ```
+pub struct HttpOptions<'a> {
+    auth: Option<Auth>,
+    allow_spill: bool,
+    spill_path: Option<PathBuf>,
+    max_retries: usize,
+    timeout: Option<Duration>,
+    progress: Option<ProgressCallback<'a>>,
+}
+
+impl Default for HttpOptions<'_> {
+    fn default() -> Self {
+        Self { auth: None, allow_spill: false, spill_path: None, max_retries: DEFAULT_MAX_RETRIES, timeout: None, progress: None }
+    }
+}
+
+/// Implementation of the HttpOptions struct
+///
+/// # Methods
+///
+/// * `new` - Creates the default HttpOptions
+/// * `bearer_token` - Authenticates with a bearer token
+/// * `basic_auth` - Authenticates with a user name and password
+/// * `allow_spill` - Allows the response to be downloaded to a temporary file
+/// * `spill_path` - Sets the file the response is downloaded to
+/// * `max_retries` - Sets how often an interrupted download is resumed
+/// * `timeout` - Sets the timeout of each request
+/// * `on_progress` - Sets a callback called as the response is downloaded
+impl<'a> HttpOptions<'a> {
+    /// Creates the default HttpOptions
+    ///
+    /// # Returns
+    ///
+    /// HttpOptions with no authentication, no spilling, `DEFAULT_MAX_RETRIES` retries and no timeout
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let options = HttpOptions::new();
+    /// ```
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Authenticates with a bearer token
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The token sent in the `Authorization: Bearer` header
+    ///
+    /// # Returns
+    ///
+    /// The HttpOptions with the token, replacing any earlier authentication
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let options = HttpOptions::new().bearer_token(&std::env::var("OSF_TOKEN")?);
+    /// ```
+    ///
+    pub fn bearer_token(mut self, token: &str) -> Self {
+        self.auth = Some(Auth::Bearer(token.to_string()));
+        self
+    }
+
+    /// Authenticates with a user name and password
+    ///
+    /// # Arguments
+    ///
+    /// * `user` - The user name
+    /// * `password` - The password
+    ///
+    /// # Returns
+    ///
+    /// The HttpOptions with the credentials, replacing any earlier authentication
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let options = HttpOptions::new().basic_auth("lab", &password);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The credentials are only base64-encoded, so use an `https` URL
+    ///
+    pub fn basic_auth(mut self, user: &str, password: &str) -> Self {
+        self.auth = Some(Auth::Basic(user.to_string(), password.to_string()));
+        self
+    }
+
+    /// Allows the response to be downloaded to a temporary file
+    ///
+    /// # Arguments
+    ///
+    /// * `allow` - Downloads the response to a file before it is read if true
+    ///
+    /// # Returns
+    ///
+    /// The HttpOptions with the new setting
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let options = HttpOptions::new().allow_spill(true);
+    /// ```
+    ///
+    pub fn allow_spill(mut self, allow: bool) -> Self {
+        self.allow_spill = allow;
+        self
+    }
+
+    /// Sets the file the response is downloaded to
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file, created or truncated
+    ///
+    /// # Returns
+    ///
+    /// The HttpOptions with the path, used instead of a new file in the temporary directory
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let options = HttpOptions::new().allow_spill(true).spill_path("/scratch/lfp.csv");
+    /// ```
+    ///
+    pub fn spill_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.spill_path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Sets how often an interrupted download is resumed
+    ///
+    /// # Arguments
+    ///
+    /// * `retries` - The number of new requests made after a connection fails
+    ///
+    /// # Returns
+    ///
+    /// The HttpOptions with the new limit
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let options = HttpOptions::new().max_retries(10);
+    /// ```
+    ///
+    pub fn max_retries(mut self, retries: usize) -> Self {
+        self.max_retries = retries;
+        self
+    }
+
+    /// Sets the timeout of each request
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - The longest time a request may take, including reading its body
+    ///
+    /// # Returns
+    ///
+    /// The HttpOptions with the timeout
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let options = HttpOptions::new().timeout(Duration::from_secs(600));
+    /// ```
+    ///
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Sets a callback called as the response is downloaded
+    ///
+    /// # Arguments
+    ///
+    /// * `callback` - The function called with the bytes downloaded so far and the size of the file, if the server sent it
+    ///
+    /// # Returns
+    ///
+    /// The HttpOptions with the callback
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let options = HttpOptions::new().on_progress(|bytes, _| eprint!("\r{} MB", bytes >> 20));
+    /// ```
+    ///
+    pub fn on_progress<F: FnMut(u64, Option<u64>) + 'a>(mut self, callback: F) -> Self {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Returns the file a download goes to and whether it is a new one in the temporary
+    /// directory, which is removed once read
+    pub(crate) fn spill_file(&self) -> (PathBuf, bool) {
+        match &self.spill_path {
+            Some(path) => (path.clone(), false),
+            None => {
+                let n = SPILL_COUNTER.fetch_add(1, Ordering::Relaxed);
+                (std::env::temp_dir().join(format!("neurorust-download-{}-{}.csv", std::process::id(), n)), true)
+            }
+        }
+    }
+
+    /// Returns whether the response may be downloaded to a file
+    pub(crate) fn spill_allowed(&self) -> bool {
+        self.allow_spill
+    }
+}
+
+/// A GET request that is sent again, with a `Range` header for the missing bytes, after its
+/// connection fails
+struct Transfer<'o, 'a> {
+    url: String,
+    agent: ureq::Agent,
+    authorization: Option<String>,
+    options: &'o mut HttpOptions<'a>,
+    position: u64,
+    total: Option<u64>,
+    retries: usize,
+}
+
+impl<'o, 'a> Transfer<'o, 'a> {
+    /// Prepares the request of a URL, sending nothing yet
+    fn new(url: &str, options: &'o mut HttpOptions<'a>) -> Self {
+        let mut builder = ureq::AgentBuilder::new();
+        if let Some(timeout) = options.timeout {
+            builder = builder.timeout(timeout);
+        }
+        let authorization = options.auth.as_ref().map(|auth| match auth {
+            Auth::Bearer(token) => format!("Bearer {}", token),
+            Auth::Basic(user, password) => format!("Basic {}", base64(format!("{}:{}", user, password).as_bytes())),
+        });
+        Self { url: url.to_string(), agent: builder.build(), authorization, options, position: 0, total: None, retries: 0 }
+    }
+
+    /// Requests the bytes from `position` on, returning the body and the offset of its first byte
+    ///
+    /// The offset is `position` if the server honoured the range and 0 if it resent the whole
+    /// file. A partial response that starts anywhere else is an `InvalidData` error.
+    fn request(&mut self) -> io::Result<(Box<dyn Read + Send + Sync>, u64)> {
+        loop {
+            let mut request = self.agent.get(&self.url);
+            if let Some(authorization) = &self.authorization {
+                request = request.set("Authorization", authorization);
+            }
+            if self.position > 0 {
+                request = request.set("Range", &format!("bytes={}-", self.position));
+            }
+            let response = match request.call() {
+                Ok(response) => response,
+                Err(ureq::Error::Status(status, _)) => {
+                    return Err(io::Error::other(format!("{} answered with HTTP status {}", self.url, status)));
+                }
+                Err(error) => {
+                    self.retry(io::Error::other(error))?;
+                    continue;
+                }
+            };
+            if response.status() != 206 {
+                self.total = response.header("Content-Length").and_then(|length| length.parse().ok());
+                return Ok((response.into_reader(), 0));
+            }
+            let range = response.header("Content-Range").unwrap_or_default();
+            if !range.starts_with(&format!("bytes {}-", self.position)) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} answered a request for the bytes from {} with `Content-Range: {}`", self.url, self.position, range),
+                ));
+            }
+            self.total = self.total.or_else(|| range.rsplit('/').next()?.parse().ok());
+            return Ok((response.into_reader(), self.position));
+        }
+    }
+
+    /// Counts bytes received and reports the progress
+    fn advance(&mut self, bytes: usize) {
+        self.position += bytes as u64;
+        if let Some(callback) = self.options.progress.as_mut() {
+            callback(self.position, self.total);
+        }
+    }
+
+    /// Returns true once a body that ended, with `error` or without, holds the whole file
+    ///
+    /// Otherwise a retry is counted and false returned, or the error returned if no retry is left
+    fn finished(&mut self, error: Option<io::Error>) -> io::Result<bool> {
+        let error = match error {
+            None if self.total.is_none_or(|total| self.position >= total) => return Ok(true),
+            None => io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} ended after {} of {:?} bytes", self.url, self.position, self.total)),
+            Some(error) => error,
+        };
+        self.retry(error)?;
+        Ok(false)
+    }
+
+    /// Counts a retry, or returns the error that caused it if none is left
+    fn retry(&mut self, error: io::Error) -> io::Result<()> {
+        if self.retries >= self.options.max_retries {
+            return Err(error);
+        }
+        self.retries += 1;
+        Ok(())
+    }
+}
+
+/// Downloads a URL to a file
+///
+/// # Arguments
+///
+/// * `url` - The HTTP or HTTPS URL
+/// * `options` - The authentication, retries and progress callback of the download
+/// * `path` - The path of the file, created or truncated
+///
+/// # Returns
+///
+/// The number of bytes downloaded, or an error if the server answers with an error status or
+/// with a range other than the one requested, the connection fails more than `max_retries`
+/// times or the file cannot be written
+///
+/// # Examples
+///
+/// ```
+/// let bytes = download("https://data.lab.internal/sessions/m12.csv", &mut HttpOptions::new(), Path::new("m12.csv"))?;
+/// ```
+///
+/// # Note
+///
+/// After a connection drops, the download resumes with a `Range` request for the missing
+/// bytes. A server that ignores the range resends the whole file, which then replaces the
+/// partial one. The body is stored as sent, so a file served with `Content-Encoding: gzip`
+/// is decompressed but a `.csv.gz` file is not.
+///
+pub fn download(url: &str, options: &mut HttpOptions, path: &Path) -> io::Result<u64> {
+    let mut transfer = Transfer::new(url, options);
+    let mut file = File::create(path)?;
+    let mut buffer = vec![0u8; BUFFER_SIZE];
+    loop {
+        let (mut body, start) = transfer.request()?;
+        if start < transfer.position {
+            // The server sent the whole file, so the partial download is discarded
+            file.set_len(0)?;
+            file.seek(SeekFrom::Start(0))?;
+            transfer.position = 0;
+        }
+        let interrupted = loop {
+            match body.read(&mut buffer) {
+                Ok(0) => break None,
+                Ok(n) => {
+                    file.write_all(&buffer[..n])?;
+                    transfer.advance(n);
+                }
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => break Some(error),
+            }
+        };
+        if transfer.finished(interrupted)? {
+            break;
+        }
+    }
+    file.flush()?;
+    Ok(transfer.position)
+}
+
+/// Streams the body of a URL, resuming it after its connection fails
+///
+/// # Examples
+///
+/// ```
+/// let mut options = HttpOptions::new().bearer_token(&token);
+/// let reader = HttpReader::open("https://data.lab.internal/sessions/m12.csv", &mut options)?;
+/// let mut source = SignalSource::from_reader(reader, 30000.0, Some("time"))?;
+/// ```
+///
+/// # Note
+///
+/// Nothing is written to disk. After a connection drops, the bytes from the last one read
+/// are requested with a `Range` header, and if the server resends the whole file instead,
+/// the bytes already read are skipped, so the reader sees every byte once. The spilling
+/// settings of the options are ignored.
+pub struct HttpReader<'o, 'a> {
+    transfer: Transfer<'o, 'a>,
+    body: Option<Box<dyn Read + Send + Sync>>,
+}
+
+/// Implementation of the HttpReader struct
+///
+/// # Methods
+///
+/// * `open` - Sends the request of a URL and returns a reader of its body
+impl<'o, 'a> HttpReader<'o, 'a> {
+    /// Sends the request of a URL and returns a reader of its body
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - The HTTP or HTTPS URL
+    /// * `options` - The authentication, retries and progress callback of the download
+    ///
+    /// # Returns
+    ///
+    /// The HttpReader, or an error if the server answers with an error status or cannot be
+    /// reached in `max_retries` retries. Later failures are returned by `read`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut lines = BufReader::new(HttpReader::open(url, &mut HttpOptions::new())?).lines();
+    /// ```
+    ///
+    pub fn open(url: &str, options: &'o mut HttpOptions<'a>) -> io::Result<Self> {
+        let mut transfer = Transfer::new(url, options);
+        let (body, _) = transfer.request()?;
+        Ok(Self { transfer, body: Some(body) })
+    }
+}
+
+impl Read for HttpReader<'_, '_> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let body = match self.body.as_mut() {
+                Some(body) => body,
+                None => return Ok(0),
+            };
+            let interrupted = match body.read(buffer) {
+                Ok(0) => None,
+                Ok(n) => {
+                    self.transfer.advance(n);
+                    return Ok(n);
+                }
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => Some(error),
+            };
+            self.body = None;
+            if self.transfer.finished(interrupted)? {
+                return Ok(0);
+            }
+            let (mut body, start) = self.transfer.request()?;
+            // A server that ignores the range resends the bytes already read, which are skipped
+            let skip = self.transfer.position - start;
+            let skipped = io::copy(&mut (&mut body).take(skip), &mut io::sink());
+            match skipped {
+                Ok(skipped) if skipped == skip => self.body = Some(body),
+                Ok(_) => self.transfer.retry(io::Error::new(io::ErrorKind::UnexpectedEof, format!("{} ended before the bytes already read", self.transfer.url)))?,
+                Err(error) => self.transfer.retry(error)?,
+            }
+        }
+    }
+}
+
+/// Encodes bytes as standard base64 with padding
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for group in bytes.chunks(3) {
+        let value = group.iter().enumerate().fold(0u32, |value, (i, &byte)| value | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= group.len() {
+                encoded.push(ALPHABET[(value >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::net::TcpListener;
+    use std::thread::{self, JoinHandle};
+    use crate::data_io::csv::CsvIO;
+    use crate::processing::pipeline::{Pipeline, SignalSource};
+
+    /// One scripted answer: the status line and headers, followed by some bytes of the body
+    struct Answer {
+        head: String,
+        body: Vec<u8>,
+    }
+
+    /// Answers with the whole file, but closes the connection after `sent` bytes
+    fn full(file: &[u8], sent: usize) -> Answer {
+        Answer {
+            head: format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n", file.len()),
+            body: file[..sent].to_vec(),
+        }
+    }
+
+    /// Answers with the file from `start` on, but closes the connection after `sent` bytes
+    fn partial(file: &[u8], start: usize, sent: usize) -> Answer {
+        Answer {
+            head: format!(
+                "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes {}-{}/{}\r\nContent-Length: {}\r\nConnection: close\r\n",
+                start,
+                file.len() - 1,
+                file.len(),
+                file.len() - start
+            ),
+            body: file[start..start + sent].to_vec(),
+        }
+    }
+
+    /// Serves the answers in order, one per connection, and returns the URL and the request
+    /// headers received
+    fn serve(answers: Vec<Answer>) -> (String, JoinHandle<Vec<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/lfp.csv", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for answer in answers {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut headers = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line.trim().is_empty() {
+                        break;
+                    }
+                    headers.push(line.trim().to_string());
+                }
+                requests.push(headers);
+                // The body is shorter than its Content-Length when the connection is meant to drop
+                stream.write_all(format!("{}\r\n", answer.head).as_bytes()).unwrap();
+                let _ = stream.write_all(&answer.body);
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    /// Returns the `Range` header of a request, if it has one
+    fn range(request: &[String]) -> Option<&str> {
+        request.iter().find_map(|header| header.strip_prefix("Range: "))
+    }
+
+    /// A csv file of 5000 rows, large enough to be cut mid-record
+    fn recording() -> Vec<u8> {
+        let mut file = String::from("time,Fz\n");
+        for i in 0..5000 {
+            file.push_str(&format!("{},{}\n", i as f64 / 1000.0, (i * 7 % 13) as f64 - 6.0));
+        }
+        file.into_bytes()
+    }
+
+    #[test]
+    fn downloads_resume_from_the_last_byte_written() {
+        let file = recording();
+        let (url, server) = serve(vec![full(&file, 10_000), partial(&file, 10_000, 20_000), partial(&file, 30_000, file.len() - 30_000)]);
+        let path = std::env::temp_dir().join(format!("neurorust-http-{}-resume.csv", std::process::id()));
+        let mut progress = Vec::new();
+        let mut options = HttpOptions::new().bearer_token("t0k3n").on_progress(|bytes, total| progress.push((bytes, total)));
+        assert_eq!(download(&url, &mut options, &path).unwrap(), file.len() as u64);
+        drop(options);
+        assert_eq!(std::fs::read(&path).unwrap(), file);
+        let requests = server.join().unwrap();
+        assert_eq!(requests.iter().map(|request| range(request)).collect::<Vec<_>>(), [None, Some("bytes=10000-"), Some("bytes=30000-")]);
+        assert!(requests.iter().all(|request| request.contains(&"Authorization: Bearer t0k3n".to_string())));
+        assert_eq!(progress.last(), Some(&(file.len() as u64, Some(file.len() as u64))));
+        assert!(progress.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn a_server_that_ignores_the_range_replaces_the_partial_file() {
+        let file = recording();
+        let (url, server) = serve(vec![full(&file, 10_000), full(&file, file.len())]);
+        let path = std::env::temp_dir().join(format!("neurorust-http-{}-restart.csv", std::process::id()));
+        let mut options = HttpOptions::new().basic_auth("user", "pass");
+        assert_eq!(download(&url, &mut options, &path).unwrap(), file.len() as u64);
+        assert_eq!(std::fs::read(&path).unwrap(), file);
+        let requests = server.join().unwrap();
+        assert!(requests[1].contains(&"Authorization: Basic dXNlcjpwYXNz".to_string()));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn a_range_that_starts_elsewhere_is_refused() {
+        let file = recording();
+        let (url, server) = serve(vec![full(&file, 10_000), partial(&file, 9_000, file.len() - 9_000)]);
+        let path = std::env::temp_dir().join(format!("neurorust-http-{}-misplaced.csv", std::process::id()));
+        let error = download(&url, &mut HttpOptions::new(), &path).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("bytes 9000-"), "{}", error);
+        server.join().unwrap();
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn downloads_give_up_after_the_last_retry() {
+        let file = recording();
+        let (url, server) = serve(vec![full(&file, 1000), partial(&file, 1000, 1000), partial(&file, 2000, 1000)]);
+        let path = std::env::temp_dir().join(format!("neurorust-http-{}-retries.csv", std::process::id()));
+        assert!(download(&url, &mut HttpOptions::new().max_retries(2), &path).is_err());
+        assert_eq!(server.join().unwrap().len(), 3);
+        std::fs::remove_file(&path).unwrap();
+
+        let (url, server) = serve(vec![Answer { head: "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n".to_string(), body: vec![] }]);
+        let error = download(&url, &mut HttpOptions::new(), &path).unwrap_err();
+        assert!(error.to_string().contains("404"), "{}", error);
+        server.join().unwrap();
+        let _ = std::fs::remove_file(path);
+    }
+
+    #[test]
+    fn streams_see_every_byte_once_across_dropped_connections() {
+        let file = recording();
+        // A resumed range, then a server that resends the whole file from its start
+        let (url, server) = serve(vec![full(&file, 10_001), partial(&file, 10_001, 7_000), full(&file, file.len())]);
+        let mut options = HttpOptions::new();
+        let mut body = Vec::new();
+        HttpReader::open(&url, &mut options).unwrap().read_to_end(&mut body).unwrap();
+        assert_eq!(body, file);
+        assert_eq!(server.join().unwrap().len(), 3);
+
+        let (url, server) = serve(vec![full(&file, 4321), partial(&file, 4321, file.len() - 4321)]);
+        let mut options = HttpOptions::new();
+        let source = SignalSource::from_reader(HttpReader::open(&url, &mut options).unwrap(), 1000.0, Some("time")).unwrap();
+        let output = Pipeline::source(source).collect().unwrap();
+        assert_eq!(output.channels[0], (0..5000).map(|i| (i * 7 % 13) as f64 - 6.0).collect::<Vec<_>>());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn temporary_downloads_are_removed_with_their_reader() {
+        let file = recording();
+        let (url, server) = serve(vec![full(&file, file.len())]);
+        let mut options = HttpOptions::new().allow_spill(true);
+        let mut csv_io = CsvIO::open_url(&url, &mut options).unwrap();
+        let spill = csv_io.reader_mut().unwrap().path().to_path_buf();
+        assert!(spill.exists());
+        assert_eq!(csv_io.read_records().unwrap().len(), 5000);
+        drop(csv_io);
+        assert!(!spill.exists());
+        server.join().unwrap();
+
+        let kept = std::env::temp_dir().join(format!("neurorust-http-{}-kept.csv", std::process::id()));
+        let (url, server) = serve(vec![full(&file, file.len())]);
+        let mut options = HttpOptions::new().allow_spill(true).spill_path(&kept);
+        drop(CsvIO::open_url(&url, &mut options).unwrap());
+        assert_eq!(std::fs::read(&kept).unwrap(), file);
+        server.join().unwrap();
+        std::fs::remove_file(kept).unwrap();
+
+        match CsvIO::open_url(&url, &mut HttpOptions::new()) {
+            Err(error) => assert!(error.to_string().contains("HttpReader"), "{}", error),
+            Ok(_) => panic!("opened a URL without allow_spill"),
+        }
+    }
+
+    #[test]
+    fn base64_matches_rfc_4648() {
+        let vectors = [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"), ("foob", "Zm9vYg=="), ("fooba", "Zm9vYmE="), ("foobar", "Zm9vYmFy")];
+        for (text, encoded) in vectors {
+            assert_eq!(base64(text.as_bytes()), encoded);
+        }
+    }
+}
@@ -0,0 +1,686 @@
+// A module to log experiment events to csv files from several threads with schema checks and rotation
+
+// Written by Amin Alam in 2024
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use csv::{StringRecord, Writer};
+
+/// The type of the values of a logged column
+///
+/// # Arguments
+///
+/// * `Text` - Any text
+/// * `Integer` - Whole numbers that fit in an `i64`
+/// * `Float` - Real numbers, including `NaN` and `inf`
+/// * `Boolean` - `true`, `false`, `1` or `0`
+///
+/// # Examples
+///
+/// ```
+/// let schema = CsvSchema::new().column("trial", ColumnType::Integer).column("correct", ColumnType::Boolean);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ColumnType {
+    Text,
+    Integer,
+    Float,
+    Boolean,
+}
+
+/// A column of a CsvSchema
+///
+/// # Arguments
+///
+/// * `name` - The name of the column
+/// * `kind` - The type of its values
+/// * `nullable` - Allows empty values
+///
+/// # Examples
+///
+/// ```
+/// let column = SchemaColumn { name: "note".to_string(), kind: ColumnType::Text, nullable: true };
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SchemaColumn {
+    pub name: String,
+    pub kind: ColumnType,
+    pub nullable: bool,
+}
+
+/// The columns every record of a CsvLogger must have
+///
+/// # Arguments
+///
+/// * `columns` - The columns, in order
+///
+/// # Examples
+///
+/// ```
+/// let schema = CsvSchema::new()
+///     .column("event", ColumnType::Text)
+///     .column("trial", ColumnType::Integer)
+///     .nullable_column("reaction_time", ColumnType::Float);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CsvSchema {
+    pub columns: Vec<SchemaColumn>,
+}
+
+/// Implementation of the CsvSchema struct
+///
+/// # Methods
+///
+/// * `new` - Creates a schema without columns
+/// * `column` - Adds a column that must have a value
+/// * `nullable_column` - Adds a column whose values may be empty
+/// * `validate` - Checks a record against the schema
+impl CsvSchema {
+    /// Creates a schema without columns
+    ///
+    /// # Returns
+    ///
+    /// The empty CsvSchema
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let schema = CsvSchema::new();
+    /// ```
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a column that must have a value
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the column
+    /// * `kind` - The type of its values
+    ///
+    /// # Returns
+    ///
+    /// The CsvSchema with the column appended
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let schema = CsvSchema::new().column("trial", ColumnType::Integer);
+    /// ```
+    ///
+    pub fn column(mut self, name: &str, kind: ColumnType) -> Self {
+        self.columns.push(SchemaColumn { name: name.to_string(), kind, nullable: false });
+        self
+    }
+
+    /// Adds a column whose values may be empty
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the column
+    /// * `kind` - The type of its values when present
+    ///
+    /// # Returns
+    ///
+    /// The CsvSchema with the column appended
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let schema = CsvSchema::new().nullable_column("reaction_time", ColumnType::Float);
+    /// ```
+    ///
+    pub fn nullable_column(mut self, name: &str, kind: ColumnType) -> Self {
+        self.columns.push(SchemaColumn { name: name.to_string(), kind, nullable: true });
+        self
+    }
+
+    /// Checks a record against the schema
+    ///
+    /// # Arguments
+    ///
+    /// * `fields` - The values of the record, one per column
+    ///
+    /// # Returns
+    ///
+    /// Ok, or an `InvalidInput` error naming the first column whose value does not fit, or
+    /// the counts if the record does not have one value per column
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// schema.validate(&["cue", "12", ""])?;
+    /// ```
+    ///
+    pub fn validate<S: AsRef<str>>(&self, fields: &[S]) -> io::Result<()> {
+        if fields.len() != self.columns.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Got {} values but the schema has {} columns", fields.len(), self.columns.len()),
+            ));
+        }
+        for (field, column) in fields.iter().zip(&self.columns) {
+            let field = field.as_ref();
+            let valid = match column.kind {
+                _ if field.is_empty() => column.nullable,
+                ColumnType::Text => true,
+                ColumnType::Integer => field.parse::<i64>().is_ok(),
+                ColumnType::Float => field.parse::<f64>().is_ok(),
+                ColumnType::Boolean => matches!(field, "true" | "false" | "1" | "0"),
+            };
+            if !valid {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("'{}' is not a valid {:?} value for column '{}'", field, column.kind, column.name),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// When a CsvLogger starts a new file
+///
+/// # Arguments
+///
+/// * `Never` - Everything goes to one file
+/// * `Bytes` - Before a row would take the file beyond this many bytes
+/// * `Duration` - Before the first row once the file has been open this long
+///
+/// # Examples
+///
+/// ```
+/// let hourly = Rotation::Duration(Duration::from_secs(3600));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rotation {
+    Never,
+    Bytes(u64),
+    Duration(Duration),
+}
+
+/// Options of `CsvLogger::create`
+///
+/// # Arguments
+///
+/// * `flush_rows` - The number of rows after which the file is flushed, 100 by default
+/// * `flush_interval` - The longest time a logged row waits before the file is flushed, 1 second by default, or None to flush by row count only
+/// * `rotation` - When to start a new file, `Never` by default
+/// * `timestamp_column` - The name of a column prepended with the seconds since the logger was created, or None
+/// * `queue_capacity` - The number of rows waiting for the writer beyond which `log` blocks, 1024 by default
+///
+/// # Examples
+///
+/// ```
+/// let options = LoggerOptions { rotation: Rotation::Bytes(64 << 20), timestamp_column: Some("t".to_string()), ..LoggerOptions::default() };
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggerOptions {
+    pub flush_rows: usize,
+    pub flush_interval: Option<Duration>,
+    pub rotation: Rotation,
+    pub timestamp_column: Option<String>,
+    pub queue_capacity: usize,
+}
+
+impl Default for LoggerOptions {
+    fn default() -> Self {
+        Self { flush_rows: 100, flush_interval: Some(Duration::from_secs(1)), rotation: Rotation::Never, timestamp_column: None, queue_capacity: 1024 }
+    }
+}
+
+/// What a CsvLogger wrote
+///
+/// # Arguments
+///
+/// * `files` - The files written, in order
+/// * `n_rows` - The number of rows logged, not counting the header rows
+///
+/// # Examples
+///
+/// ```
+/// let summary = logger.close()?;
+/// println!("{} rows in {} files", summary.n_rows, summary.files.len());
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogSummary {
+    pub files: Vec<PathBuf>,
+    pub n_rows: usize,
+}
+
+enum Message {
+    Row(StringRecord),
+    Flush(SyncSender<io::Result<()>>),
+}
+
+/// An append-only csv logger that checks every record against a schema and writes from its own thread
+///
+/// # Examples
+///
+/// ```
+/// let schema = CsvSchema::new().column("event", ColumnType::Text).column("trial", ColumnType::Integer);
+/// let logger = CsvLogger::create("events.csv", schema, LoggerOptions::default())?;
+/// logger.log(&["cue", "1"])?;
+/// let summary = logger.close()?;
+/// ```
+///
+/// # Note
+///
+/// `log` checks the record in the calling thread and sends it through a bounded channel
+/// to a single writer thread, so rows of several threads are never interleaved and a
+/// producer that outpaces the disk is slowed down instead of filling memory. The logger
+/// can be shared between threads by reference, e.g. in an `Arc` or a scoped thread.
+/// Dropping it flushes and closes the file, but only `close` reports a write error.
+pub struct CsvLogger {
+    sender: Option<SyncSender<Message>>,
+    writer: Option<JoinHandle<io::Result<LogSummary>>>,
+    schema: CsvSchema,
+}
+
+/// Implementation of the CsvLogger struct
+///
+/// # Methods
+///
+/// * `create` - Creates the log file, writes its header and starts the writer thread
+/// * `schema` - Returns the schema of the logged records
+/// * `log` - Checks a record and queues it for writing
+/// * `flush` - Waits until every queued row is written and flushed
+/// * `close` - Writes the queued rows, closes the file and reports what was written
+impl CsvLogger {
+    /// Creates the log file, writes its header and starts the writer thread
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the first log file, e.g. `events.csv`; rotated files are numbered `events_1.csv`, `events_2.csv` and so on
+    /// * `schema` - The columns of the records
+    /// * `options` - The flushing, rotation, timestamp and queue options
+    ///
+    /// # Returns
+    ///
+    /// The CsvLogger, or an error if the schema has no columns, a column name repeats,
+    /// `flush_rows` or `queue_capacity` is 0, or the file cannot be created
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let logger = CsvLogger::create("events.csv", schema, LoggerOptions::default())?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Existing files are truncated. The timestamp is taken by the writer thread when it
+    /// receives the row, from a monotonic clock, so it never decreases down the file.
+    ///
+    pub fn create<P: AsRef<Path>>(path: P, schema: CsvSchema, options: LoggerOptions) -> io::Result<Self> {
+        let mut header: Vec<&str> = options.timestamp_column.iter().map(String::as_str).collect();
+        header.extend(schema.columns.iter().map(|column| column.name.as_str()));
+        if schema.columns.is_empty() || (1..header.len()).any(|i| header[..i].contains(&header[i])) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("The columns must be distinct and not empty, got {:?}", header)));
+        }
+        if options.flush_rows == 0 || options.queue_capacity == 0 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "flush_rows and queue_capacity must be positive"));
+        }
+        let header = StringRecord::from(header);
+        let mut state = WriterState::open(path.as_ref().to_path_buf(), header, options.clone())?;
+        let (sender, receiver) = mpsc::sync_channel(options.queue_capacity);
+        let writer = thread::Builder::new().name("csv-logger".to_string()).spawn(move || state.run(receiver))?;
+        Ok(Self { sender: Some(sender), writer: Some(writer), schema })
+    }
+
+    /// Returns the schema of the logged records
+    ///
+    /// # Returns
+    ///
+    /// The CsvSchema given to `create`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let n_columns = logger.schema().columns.len();
+    /// ```
+    ///
+    pub fn schema(&self) -> &CsvSchema {
+        &self.schema
+    }
+
+    /// Checks a record and queues it for writing
+    ///
+    /// # Arguments
+    ///
+    /// * `fields` - The values of the record, one per schema column, without the timestamp
+    ///
+    /// # Returns
+    ///
+    /// Ok once the record is queued, an `InvalidInput` error if it does not fit the schema,
+    /// in which case nothing is written, or a `BrokenPipe` error if the writer thread has
+    /// stopped on an error, which `close` returns
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// logger.log(&["reward", &trial.to_string()])?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The call blocks while `queue_capacity` rows are waiting for the writer.
+    ///
+    pub fn log<S: AsRef<str>>(&self, fields: &[S]) -> io::Result<()> {
+        self.schema.validate(fields)?;
+        let record = StringRecord::from(fields.iter().map(AsRef::as_ref).collect::<Vec<&str>>());
+        self.send(Message::Row(record))
+    }
+
+    /// Waits until every queued row is written and flushed
+    ///
+    /// # Returns
+    ///
+    /// Ok, or an error if a row could not be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// logger.flush()?;
+    /// ```
+    ///
+    pub fn flush(&self) -> io::Result<()> {
+        let (reply, done) = mpsc::sync_channel(1);
+        self.send(Message::Flush(reply))?;
+        done.recv().map_err(|_| stopped())?
+    }
+
+    /// Writes the queued rows, closes the file and reports what was written
+    ///
+    /// # Returns
+    ///
+    /// The LogSummary, or the error that stopped the writer thread
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let summary = logger.close()?;
+    /// ```
+    ///
+    pub fn close(mut self) -> io::Result<LogSummary> {
+        self.finish()
+    }
+
+    fn send(&self, message: Message) -> io::Result<()> {
+        self.sender.as_ref().ok_or_else(stopped)?.send(message).map_err(|_| stopped())
+    }
+
+    fn finish(&mut self) -> io::Result<LogSummary> {
+        // Dropping the sender ends the writer loop once the queue is empty
+        self.sender = None;
+        match self.writer.take() {
+            Some(writer) => writer.join().map_err(|_| io::Error::other("The logger thread panicked"))?,
+            None => Ok(LogSummary::default()),
+        }
+    }
+}
+
+impl Drop for CsvLogger {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+fn stopped() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "The logger has stopped; close it to get the error")
+}
+
+/// The file side of a CsvLogger, owned by the writer thread
+struct WriterState {
+    path: PathBuf,
+    header: StringRecord,
+    options: LoggerOptions,
+    start: Instant,
+    file: BufWriter<File>,
+    /// Formats one row at a time so that its length is known before it is written
+    formatter: Writer<Vec<u8>>,
+    file_bytes: u64,
+    file_rows: usize,
+    opened: Instant,
+    unflushed: usize,
+    oldest_unflushed: Option<Instant>,
+    summary: LogSummary,
+}
+
+impl WriterState {
+    fn open(path: PathBuf, header: StringRecord, options: LoggerOptions) -> io::Result<Self> {
+        let file = BufWriter::new(File::create(&path)?);
+        let mut state = Self {
+            path: path.clone(),
+            header,
+            options,
+            start: Instant::now(),
+            file,
+            formatter: Writer::from_writer(Vec::new()),
+            file_bytes: 0,
+            file_rows: 0,
+            opened: Instant::now(),
+            unflushed: 0,
+            oldest_unflushed: None,
+            summary: LogSummary { files: vec![path], n_rows: 0 },
+        };
+        let header = state.format(&state.header.clone())?;
+        state.write_bytes(&header)?;
+        state.file.flush()?;
+        Ok(state)
+    }
+
+    fn run(&mut self, receiver: Receiver<Message>) -> io::Result<LogSummary> {
+        loop {
+            let message = match self.oldest_unflushed.zip(self.options.flush_interval) {
+                Some((oldest, interval)) => match receiver.recv_timeout(interval.saturating_sub(oldest.elapsed())) {
+                    Ok(message) => message,
+                    Err(RecvTimeoutError::Timeout) => {
+                        self.flush()?;
+                        continue;
+                    }
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+                None => match receiver.recv() {
+                    Ok(message) => message,
+                    Err(_) => break,
+                },
+            };
+            match message {
+                Message::Row(record) => self.write_row(record)?,
+                Message::Flush(reply) => {
+                    let _ = reply.send(self.flush());
+                }
+            }
+        }
+        self.flush()?;
+        Ok(self.summary.clone())
+    }
+
+    fn write_row(&mut self, mut record: StringRecord) -> io::Result<()> {
+        if self.options.timestamp_column.is_some() {
+            let mut stamped = StringRecord::from(vec![format!("{:.6}", self.start.elapsed().as_secs_f64())]);
+            stamped.extend(record.iter());
+            record = stamped;
+        }
+        let row = self.format(&record)?;
+        let rotate = self.file_rows > 0
+            && match self.options.rotation {
+                Rotation::Never => false,
+                Rotation::Bytes(limit) => self.file_bytes + row.len() as u64 > limit,
+                Rotation::Duration(duration) => self.opened.elapsed() >= duration,
+            };
+        if rotate {
+            self.rotate()?;
+        }
+        self.write_bytes(&row)?;
+        self.file_rows += 1;
+        self.summary.n_rows += 1;
+        self.unflushed += 1;
+        self.oldest_unflushed.get_or_insert_with(Instant::now);
+        if self.unflushed >= self.options.flush_rows {
+            self.flush()?;
+        }
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.flush()?;
+        let stem = self.path.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        let name = match self.path.extension() {
+            Some(extension) => format!("{}_{}.{}", stem, self.summary.files.len(), extension.to_string_lossy()),
+            None => format!("{}_{}", stem, self.summary.files.len()),
+        };
+        let path = self.path.with_file_name(name);
+        self.file = BufWriter::new(File::create(&path)?);
+        self.summary.files.push(path);
+        self.file_bytes = 0;
+        self.file_rows = 0;
+        self.opened = Instant::now();
+        let header = self.format(&self.header.clone())?;
+        self.write_bytes(&header)
+    }
+
+    fn format(&mut self, record: &StringRecord) -> io::Result<Vec<u8>> {
+        self.formatter.write_record(record)?;
+        let formatter = std::mem::replace(&mut self.formatter, Writer::from_writer(Vec::new()));
+        formatter.into_inner().map_err(|error| error.into_error())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.file.write_all(bytes)?;
+        self.file_bytes += bytes.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.unflushed = 0;
+        self.oldest_unflushed = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("neurorust-logger-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Returns the number of rows after the header of a file
+    fn rows_on_disk(path: &Path) -> usize {
+        std::fs::read_to_string(path).unwrap().lines().count().saturating_sub(1)
+    }
+
+    #[test]
+    fn concurrent_producers_write_every_row_once_across_rotated_files() {
+        let dir = temp_dir("concurrent");
+        let schema = CsvSchema::new()
+            .column("thread", ColumnType::Integer)
+            .column("i", ColumnType::Integer)
+            .nullable_column("note", ColumnType::Text)
+            .column("ok", ColumnType::Boolean);
+        let options = LoggerOptions { rotation: Rotation::Bytes(200_000), timestamp_column: Some("t".to_string()), queue_capacity: 16, ..LoggerOptions::default() };
+        let logger = CsvLogger::create(dir.join("log.csv"), schema, options).unwrap();
+        thread::scope(|scope| {
+            for producer in 0..8 {
+                let logger = &logger;
+                scope.spawn(move || {
+                    for i in 0..12_500 {
+                        // Quoted fields with commas would show any interleaving of two rows
+                        let note = if i % 3 == 0 { String::new() } else { "a,\"b\"\nc".to_string() };
+                        logger.log(&[producer.to_string(), i.to_string(), note, "1".to_string()]).unwrap();
+                    }
+                });
+            }
+        });
+        let summary = logger.close().unwrap();
+        assert_eq!(summary.n_rows, 100_000);
+        assert!(summary.files.len() > 1);
+        assert_eq!(summary.files[1], dir.join("log_1.csv"));
+
+        let mut seen = HashSet::new();
+        let mut last_time = 0.0;
+        for file in &summary.files {
+            assert!(std::fs::metadata(file).unwrap().len() <= 200_000);
+            let mut reader = csv::Reader::from_path(file).unwrap();
+            assert_eq!(reader.headers().unwrap(), vec!["t", "thread", "i", "note", "ok"]);
+            for record in reader.records() {
+                let record = record.unwrap();
+                assert_eq!(record.len(), 5);
+                assert!(record[3].is_empty() || &record[3] == "a,\"b\"\nc");
+                let time: f64 = record[0].parse().unwrap();
+                assert!(time >= last_time);
+                last_time = time;
+                assert!(seen.insert((record[1].to_string(), record[2].to_string())), "Row {:?} written twice", record);
+            }
+        }
+        assert_eq!(seen.len(), 100_000);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn records_that_break_the_schema_are_not_written() {
+        let dir = temp_dir("schema");
+        let schema = CsvSchema::new().column("trial", ColumnType::Integer).column("rt", ColumnType::Float).nullable_column("note", ColumnType::Text);
+        let logger = CsvLogger::create(dir.join("log.csv"), schema, LoggerOptions::default()).unwrap();
+        for fields in [vec!["x", "0.5", ""], vec!["1", "fast", ""], vec!["1", "", ""], vec!["1", "0.5"], vec!["1", "0.5", "", ""]] {
+            assert_eq!(logger.log(&fields).unwrap_err().kind(), io::ErrorKind::InvalidInput, "{:?}", fields);
+        }
+        logger.log(&["1", "0.5", ""]).unwrap();
+        assert_eq!(logger.close().unwrap().n_rows, 1);
+        assert_eq!(std::fs::read_to_string(dir.join("log.csv")).unwrap(), "trial,rt,note\n1,0.5,\n");
+        assert!(CsvLogger::create(dir.join("empty.csv"), CsvSchema::new(), LoggerOptions::default()).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rows_reach_the_disk_after_flush_rows_without_waiting_for_close() {
+        let dir = temp_dir("flush-rows");
+        let path = dir.join("log.csv");
+        let options = LoggerOptions { flush_rows: 10, flush_interval: None, ..LoggerOptions::default() };
+        let logger = CsvLogger::create(&path, CsvSchema::new().column("i", ColumnType::Integer), options).unwrap();
+        for i in 0..25 {
+            logger.log(&[i.to_string()]).unwrap();
+        }
+        // A crash now would lose at most the 5 rows logged since the last flush
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while rows_on_disk(&path) < 20 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(rows_on_disk(&path), 20);
+        logger.flush().unwrap();
+        assert_eq!(rows_on_disk(&path), 25);
+        drop(logger);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_single_row_is_flushed_after_the_interval() {
+        let dir = temp_dir("flush-interval");
+        let path = dir.join("log.csv");
+        let options = LoggerOptions { flush_interval: Some(Duration::from_millis(20)), ..LoggerOptions::default() };
+        let logger = CsvLogger::create(&path, CsvSchema::new().column("a", ColumnType::Float), options).unwrap();
+        logger.log(&["1.5"]).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while rows_on_disk(&path) < 1 && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a\n1.5\n");
+        drop(logger);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
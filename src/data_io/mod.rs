@@ -1 +1,32 @@
-pub mod csv;
\ No newline at end of file
+pub mod aliases;
+pub mod bids;
+pub mod calibration;
+pub mod categorical;
+pub mod compression;
+pub mod convert;
+pub mod csv;
+pub mod dataset;
+pub mod detect;
+pub mod dialect;
+pub mod error;
+pub mod float_format;
+pub mod fixed_width;
+pub mod logger;
+pub mod numeric;
+#[cfg(feature = "serde")]
+pub mod cache;
+#[cfg(feature = "polars")]
+pub mod dataframe;
+#[cfg(feature = "http")]
+pub mod http;
+pub mod plot;
+pub mod preview;
+pub mod pseudonym;
+pub mod query;
+pub mod records;
+pub mod rolling;
+pub mod shards;
+pub mod sort;
+pub mod winsorize;
+
+pub use detect::{detect_format, open_any};
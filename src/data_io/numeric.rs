@@ -0,0 +1,335 @@
+// A module to parse the numbers of delimited text files quickly
+
+// Written by Amin Alam in 2024
+
+use std::any::Any;
+use std::fs::File;
+use std::io::{self, Read};
+use std::str::{self, FromStr};
+use csv::Position;
+use fast_float2::FastFloat;
+use memchr::{memchr, memchr_iter};
+
+/// The number of bytes read from the file at a time by PlainLines
+pub(crate) const BLOCK_BYTES: usize = 1 << 20;
+
+/// Parses a number as `str::parse` does, with the same result for every input
+///
+/// # Arguments
+///
+/// * `text` - The bytes of the number, without surrounding whitespace
+///
+/// # Returns
+///
+/// The number, or None if the text is not a number
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(parse_f64(b"-1.5e3"), Some(-1500.0));
+/// ```
+///
+/// # Note
+///
+/// Both parsers round correctly and accept the same syntax, so they agree bit for bit.
+/// Text that fast-float2 rejects is parsed again by `str::parse`, so the two can only
+/// differ in speed.
+///
+pub(crate) fn parse_f64(text: &[u8]) -> Option<f64> {
+    parse_float(text)
+}
+
+/// Parses a trimmed field of a record as a value, with the fast parser for f64 and f32
+///
+/// # Arguments
+///
+/// * `field` - The bytes of the field, which may be surrounded by whitespace
+///
+/// # Returns
+///
+/// The value, as `str::parse` returns it after `str::trim`, or None if the field is not
+/// UTF-8 or cannot be parsed
+///
+/// # Examples
+///
+/// ```
+/// assert_eq!(parse_field::<f32>(b" 2.5 "), Some(2.5));
+/// ```
+///
+pub(crate) fn parse_field<T: FromStr + 'static>(field: &[u8]) -> Option<T> {
+    let mut value: Option<T> = None;
+    let slot = &mut value as &mut dyn Any;
+    if let Some(slot) = slot.downcast_mut::<Option<f64>>() {
+        *slot = parse_trimmed_float(field);
+    } else if let Some(slot) = slot.downcast_mut::<Option<f32>>() {
+        *slot = parse_trimmed_float(field);
+    } else {
+        value = str::from_utf8(field).ok().and_then(|text| text.trim().parse().ok());
+    }
+    value
+}
+
+fn parse_float<F: FastFloat + FromStr>(text: &[u8]) -> Option<F> {
+    fast_float2::parse(text).ok().or_else(|| str::from_utf8(text).ok()?.parse().ok())
+}
+
+fn parse_trimmed_float<F: FastFloat + FromStr>(field: &[u8]) -> Option<F> {
+    fast_float2::parse(trim_ascii(field)).ok().or_else(|| str::from_utf8(field).ok()?.trim().parse().ok())
+}
+
+/// Trims the ASCII bytes that `str::trim` trims, which unlike `u8::is_ascii_whitespace` include the vertical tab
+fn trim_ascii(field: &[u8]) -> &[u8] {
+    let is_space = |byte: &u8| *byte == b' ' || (b'\t'..=b'\r').contains(byte);
+    let start = field.iter().position(|byte| !is_space(byte)).unwrap_or(field.len());
+    let end = field.iter().rposition(|byte| !is_space(byte)).map_or(start, |end| end + 1);
+    &field[start..end]
+}
+
+/// Where PlainLines stopped
+///
+/// # Arguments
+///
+/// * `End` - Every line was plain and the file ended, at the position of its end
+/// * `Fallback` - The line at the position needs the csv parser: it has a quote, a byte
+///   that is not ASCII, a lone carriage return, a comment or a number of fields other
+///   than the header row
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Stop {
+    End(Position),
+    Fallback(Position),
+}
+
+/// A scanner of the lines of a delimited file that no csv quoting rule applies to
+///
+/// # Arguments
+///
+/// * `file` - The file, read from the position the scanner was created at
+/// * `delimiter` - The byte between fields
+/// * `quote` - The quote byte, whose presence sends a line to the csv parser
+/// * `comment` - The byte that starts comment lines, which are sent to the csv parser
+/// * `n_fields` - The number of fields every line has, or None if the records are flexible
+/// * `position` - The byte, line and record of the next line
+///
+/// # Note
+///
+/// Lines are split with memchr, which scans the block of bytes with SIMD instructions
+/// where the target supports them. Blank lines are skipped and a trailing carriage return
+/// is removed, as the csv parser does.
+pub(crate) struct PlainLines {
+    file: File,
+    delimiter: u8,
+    quote: u8,
+    comment: Option<u8>,
+    n_fields: Option<usize>,
+    position: Position,
+}
+
+/// Implementation of the PlainLines struct
+///
+/// # Methods
+///
+/// * `new` - Creates a scanner of a file opened at a position
+/// * `scan` - Visits the fields of the plain lines until the end of the file or the first other line
+impl PlainLines {
+    /// Creates a scanner of a file opened at a position
+    ///
+    /// # Arguments
+    ///
+    /// * `file` - The file, whose cursor is at the byte of `position`
+    /// * `delimiter` - The byte between fields, which must be ASCII
+    /// * `quote` - The quote byte of the dialect
+    /// * `comment` - The comment byte of the dialect
+    /// * `n_fields` - The number of fields of every line, or None if the records are flexible
+    /// * `position` - The position of the next line
+    ///
+    /// # Returns
+    ///
+    /// The PlainLines object
+    ///
+    pub(crate) fn new(file: File, delimiter: u8, quote: u8, comment: Option<u8>, n_fields: Option<usize>, position: Position) -> Self {
+        Self { file, delimiter, quote, comment, n_fields, position }
+    }
+
+    /// Visits the fields of the plain lines until the end of the file or the first other line
+    ///
+    /// # Arguments
+    ///
+    /// * `visit` - Called with the line number and fields of every plain line
+    ///
+    /// # Returns
+    ///
+    /// Where the scan stopped, or the first error of reading the file or of `visit`
+    ///
+    pub(crate) fn scan<E, F>(mut self, mut visit: F) -> Result<Stop, E>
+    where
+        E: From<io::Error>,
+        F: FnMut(u64, &[&[u8]]) -> Result<(), E>,
+    {
+        let mut buffer = vec![0u8; BLOCK_BYTES];
+        let mut filled = 0;
+        loop {
+            let read = read_some(&mut self.file, &mut buffer[filled..])?;
+            let end = filled + read;
+            let at_end = read == 0;
+            let block = &buffer[..end];
+            let has_cr = memchr(b'\r', block).is_some();
+            let clean = block.is_ascii() && memchr(self.quote, block).is_none();
+            let mut fields: Vec<&[u8]> = Vec::new();
+            let mut start = 0;
+            while start < end {
+                let (line_end, next) = match memchr(b'\n', &block[start..]) {
+                    Some(offset) => (start + offset, start + offset + 1),
+                    None if at_end => (end, end),
+                    None => break,
+                };
+                let mut line = &block[start..line_end];
+                if let Some(stripped) = line.strip_suffix(b"\r") {
+                    line = stripped;
+                }
+                if !line.is_empty() {
+                    let plain = (clean || (line.is_ascii() && memchr(self.quote, line).is_none()))
+                        && !(has_cr && memchr(b'\r', line).is_some())
+                        && line.first() != self.comment.as_ref();
+                    fields.clear();
+                    if plain {
+                        let mut field_start = 0;
+                        for delimiter in memchr_iter(self.delimiter, line) {
+                            fields.push(&line[field_start..delimiter]);
+                            field_start = delimiter + 1;
+                        }
+                        fields.push(&line[field_start..]);
+                    }
+                    if !plain || self.n_fields.is_some_and(|n_fields| n_fields != fields.len()) {
+                        return Ok(Stop::Fallback(self.position));
+                    }
+                    visit(self.position.line(), &fields)?;
+                    self.position.set_record(self.position.record() + 1);
+                }
+                let newlines = u64::from(next > line_end);
+                self.position.set_byte(self.position.byte() + (next - start) as u64);
+                self.position.set_line(self.position.line() + newlines);
+                start = next;
+            }
+            if at_end {
+                return Ok(Stop::End(self.position));
+            }
+            buffer.copy_within(start..end, 0);
+            filled = end - start;
+            if filled == buffer.len() {
+                buffer.resize(buffer.len() * 2, 0);
+            }
+        }
+    }
+}
+
+/// Reads into a buffer, retrying when interrupted
+fn read_some(file: &mut File, buffer: &mut [u8]) -> io::Result<usize> {
+    loop {
+        match file.read(buffer) {
+            Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+            result => return result,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    fn same<F: Copy + PartialEq + std::fmt::Debug>(a: Option<F>, b: Option<F>, bits: impl Fn(F) -> u64) -> bool {
+        match (a, b) {
+            (Some(a), Some(b)) => bits(a) == bits(b),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn parse_is_bit_identical_to_std_on_round_trippable_values() {
+        let mut rng = SeededRng::new(143);
+        for _ in 0..50_000 {
+            let value = f64::from_bits(rng.next_u64());
+            for text in [format!("{}", value), format!("{:e}", value), format!("{:.3}", value)] {
+                assert!(same(parse_f64(text.as_bytes()), text.parse().ok(), f64::to_bits), "{}", text);
+            }
+            let single = f32::from_bits(rng.next_u64() as u32);
+            let text = format!("{}", single);
+            assert!(same(parse_field::<f32>(text.as_bytes()), text.parse().ok(), |x: f32| x.to_bits() as u64), "{}", text);
+        }
+    }
+
+    #[test]
+    fn parse_accepts_what_std_accepts() {
+        let alphabet = b"0123456789+-.eE_ xinfatyINFATY";
+        let mut rng = SeededRng::new(7);
+        for _ in 0..100_000 {
+            let length = 1 + rng.next_index(9);
+            let text: String = (0..length).map(|_| alphabet[rng.next_index(alphabet.len())] as char).collect();
+            let expected: Option<f64> = text.trim().parse().ok();
+            let parsed = parse_field::<f64>(text.as_bytes());
+            assert!(same(parsed, expected, |x| if x.is_nan() { 0 } else { x.to_bits() }), "{:?}", text);
+        }
+        for text in ["\u{a0}1.5", "1.5\u{b}", "\t-0\r", "", " ", "nan(1)", "1e99999", "0x10"] {
+            let expected: Option<f64> = text.trim().parse().ok();
+            assert!(same(parse_field::<f64>(text.as_bytes()), expected, |x| if x.is_nan() { 0 } else { x.to_bits() }), "{:?}", text);
+        }
+        assert_eq!(parse_field::<i64>(b" 42 "), Some(42));
+        assert_eq!(parse_field::<String>(&[0xff]), None);
+    }
+
+    fn scan_text(text: &str, n_fields: Option<usize>) -> (Vec<(u64, Vec<String>)>, Stop) {
+        let path = std::env::temp_dir().join(format!("neurorust-numeric-{}-{}", std::process::id(), text.len()));
+        std::fs::write(&path, text).unwrap();
+        let mut lines = Vec::new();
+        let stop = PlainLines::new(File::open(&path).unwrap(), b',', b'"', Some(b'#'), n_fields, Position::new())
+            .scan(|line, fields: &[&[u8]]| -> io::Result<()> {
+                lines.push((line, fields.iter().map(|field| String::from_utf8(field.to_vec()).unwrap()).collect()));
+                Ok(())
+            })
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        (lines, stop)
+    }
+
+    #[test]
+    fn scan_skips_blank_lines_and_strips_carriage_returns() {
+        let (lines, stop) = scan_text("1,2\r\n\n3,4\r\n5,6", Some(2));
+        assert_eq!(lines, vec![(1, vec!["1".to_string(), "2".to_string()]), (3, vec!["3".to_string(), "4".to_string()]), (4, vec!["5".to_string(), "6".to_string()])]);
+        match stop {
+            Stop::End(position) => assert_eq!((position.byte(), position.line(), position.record()), (14, 4, 3)),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn scan_stops_at_the_first_line_for_the_csv_parser() {
+        for (text, byte, line) in [
+            ("1,2\n3,4\n\"5\",6\n7,8\n", 8, 3),
+            ("1,2\n3\u{e9},4\n", 4, 2),
+            ("1,2\n#c\n", 4, 2),
+            ("1,2\n3,4,5\n", 4, 2),
+            ("1,2\n3\r4,5\n", 4, 2),
+        ] {
+            let (lines, stop) = scan_text(text, Some(2));
+            assert_eq!(lines.len() as u64, line - 1, "{:?}", text);
+            match stop {
+                Stop::Fallback(position) => assert_eq!((position.byte(), position.line(), position.record()), (byte, line, line - 1), "{:?}", text),
+                other => panic!("{:?} {:?}", text, other),
+            }
+        }
+        let (lines, _) = scan_text("1,2\n3,4,5\n", None);
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn scan_carries_lines_across_blocks() {
+        let row = "0.123456789,1.5,-2.25\n";
+        let n_rows = 2 * BLOCK_BYTES / row.len() + 7;
+        let (lines, stop) = scan_text(&row.repeat(n_rows), Some(3));
+        assert_eq!(lines.len(), n_rows);
+        assert!(lines.iter().all(|(_, fields)| fields == &["0.123456789", "1.5", "-2.25"]));
+        assert_eq!(lines.last().unwrap().0, n_rows as u64);
+        assert!(matches!(stop, Stop::End(position) if position.byte() == (row.len() * n_rows) as u64));
+    }
+}
@@ -0,0 +1,556 @@
+// A module to prepare signals for plotting and render quick-look figures
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+#[cfg(feature = "plot")]
+use std::io;
+#[cfg(feature = "plot")]
+use std::path::Path;
+#[cfg(feature = "plot")]
+use plotters::prelude::*;
+#[cfg(feature = "plot")]
+use crate::processing::spectral::{Spectrogram, Spectrum};
+
+/// The colors used to map values onto an image
+///
+/// # Arguments
+///
+/// * `Viridis` - The perceptually uniform dark blue to yellow map of matplotlib
+/// * `Gray` - Black to white
+///
+/// # Examples
+///
+/// ```
+/// let (r, g, b) = Colormap::Viridis.rgb(0.5);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Colormap {
+    #[default]
+    Viridis,
+    Gray,
+}
+
+/// Samples of the viridis colormap at nine evenly spaced positions
+const VIRIDIS: [(u8, u8, u8); 9] = [
+    (68, 1, 84),
+    (71, 44, 122),
+    (59, 81, 139),
+    (44, 113, 142),
+    (33, 144, 141),
+    (39, 173, 129),
+    (92, 200, 99),
+    (170, 220, 50),
+    (253, 231, 37),
+];
+
+/// Implementation of the Colormap enum
+///
+/// # Methods
+///
+/// * `rgb` - Returns the color of a value
+impl Colormap {
+    /// Returns the color of a value
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The position in the colormap, from 0 to 1, clamped to that range
+    ///
+    /// # Returns
+    ///
+    /// The red, green and blue components, or black for NaN
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (r, g, b) = Colormap::Gray.rgb(1.0);
+    /// assert_eq!((r, g, b), (255, 255, 255));
+    /// ```
+    ///
+    pub fn rgb(&self, value: f64) -> (u8, u8, u8) {
+        if value.is_nan() {
+            return (0, 0, 0);
+        }
+        let value = value.clamp(0.0, 1.0);
+        match self {
+            Colormap::Gray => {
+                let level = (255.0 * value).round() as u8;
+                (level, level, level)
+            }
+            Colormap::Viridis => {
+                let position = value * (VIRIDIS.len() - 1) as f64;
+                let below = (position.floor() as usize).min(VIRIDIS.len() - 2);
+                let fraction = position - below as f64;
+                let (low, high) = (VIRIDIS[below], VIRIDIS[below + 1]);
+                let mix = |a: u8, b: u8| (a as f64 + fraction * (b as f64 - a as f64)).round() as u8;
+                (mix(low.0, high.0), mix(low.1, high.1), mix(low.2, high.2))
+            }
+        }
+    }
+}
+
+/// Options of the quick-look figures
+///
+/// # Arguments
+///
+/// * `width` - The width of the figure in pixels
+/// * `height` - The height of the figure in pixels
+/// * `title` - The caption above the plot, if any
+/// * `time_range` - The start and end of the plotted part of a trace or spectrogram in seconds, the whole signal if None
+/// * `y_limits` - The range of the vertical axis, fitted to the data if None
+/// * `max_points` - The largest number of points drawn for a trace, see `display_decimate`
+/// * `log_x` - Plots the frequencies of a spectrum on a logarithmic axis if true
+/// * `log_y` - Plots the power of a spectrum on a logarithmic axis if true
+/// * `colormap` - The colors of a spectrogram
+/// * `color_limits` - The power in dB mapped to the two ends of the colormap, fitted to the data if None
+///
+/// # Examples
+///
+/// ```
+/// let options = PlotOptions { time_range: Some((10.0, 12.0)), ..PlotOptions::default() };
+/// plot_trace_svg(&filtered, 30000.0, "trace.svg", &options)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlotOptions {
+    pub width: u32,
+    pub height: u32,
+    pub title: Option<String>,
+    pub time_range: Option<(f64, f64)>,
+    pub y_limits: Option<(f64, f64)>,
+    pub max_points: usize,
+    pub log_x: bool,
+    pub log_y: bool,
+    pub colormap: Colormap,
+    pub color_limits: Option<(f64, f64)>,
+}
+
+impl Default for PlotOptions {
+    fn default() -> Self {
+        Self {
+            width: 1200,
+            height: 400,
+            title: None,
+            time_range: None,
+            y_limits: None,
+            max_points: 4000,
+            log_x: false,
+            log_y: false,
+            colormap: Colormap::Viridis,
+            color_limits: None,
+        }
+    }
+}
+
+/// Reduces a trace to the points needed to draw it at screen resolution
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `max_points` - The largest number of points returned, at least 2
+///
+/// # Returns
+///
+/// The times in seconds and the values of the points, every sample if there are at most
+/// `max_points` of them
+///
+/// # Examples
+///
+/// ```
+/// // Ten million samples reduced to 4000 points that draw the same outline
+/// let (times, values) = display_decimate(&wideband, 30000.0, 4000);
+/// ```
+///
+/// # Note
+///
+/// The samples are split into `max_points / 2` bins and the minimum and maximum of every
+/// bin are kept in the order they occur, each at its own time, so spikes and artifacts
+/// remain visible where plain downsampling would skip them. NaN values are left out.
+///
+pub fn display_decimate(samples: &[f64], sampling_rate: f64, max_points: usize) -> (Vec<f64>, Vec<f64>) {
+    let time = |index: usize| index as f64 / sampling_rate;
+    if samples.len() <= max_points.max(2) {
+        return samples
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| !value.is_nan())
+            .map(|(i, &value)| (time(i), value))
+            .unzip();
+    }
+    let bin_size = samples.len().div_ceil(max_points.max(2) / 2);
+    let mut times = Vec::with_capacity(max_points);
+    let mut values = Vec::with_capacity(max_points);
+    for (bin, chunk) in samples.chunks(bin_size).enumerate() {
+        let extremes = chunk.iter().enumerate().filter(|(_, value)| !value.is_nan()).fold(None, |extremes, (i, &value)| match extremes {
+            None => Some(((i, value), (i, value))),
+            Some((low, high)) => Some((if value < low.1 { (i, value) } else { low }, if value > high.1 { (i, value) } else { high })),
+        });
+        if let Some((low, high)) = extremes {
+            let (first, second) = if low.0 <= high.0 { (low, high) } else { (high, low) };
+            times.push(time(bin * bin_size + first.0));
+            values.push(first.1);
+            if second.0 != first.0 {
+                times.push(time(bin * bin_size + second.0));
+                values.push(second.1);
+            }
+        }
+    }
+    (times, values)
+}
+
+/// Writes a trace reduced for display as `time,value` rows
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `max_points` - The largest number of rows written, see `display_decimate`
+/// * `csv_io` - The CsvIO object to write to
+///
+/// # Returns
+///
+/// Nothing, or an error if a row cannot be written
+///
+/// # Examples
+///
+/// ```
+/// let mut csv_io = CsvIO::open_write("trace_plot.csv")?;
+/// trace_to_plot_csv(&filtered, 30000.0, 4000, &mut csv_io)?;
+/// csv_io.save()?;
+/// ```
+///
+/// # Note
+///
+/// A header row is written first. The rows are not flushed to disk until `save` is called.
+///
+pub fn trace_to_plot_csv(samples: &[f64], sampling_rate: f64, max_points: usize, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+    let float_format = csv_io.float_format();
+    let (times, values) = display_decimate(samples, sampling_rate, max_points);
+    csv_io.write_record(StringRecord::from(vec!["time", "value"]))?;
+    for (time, value) in times.iter().zip(&values) {
+        csv_io.write_record(StringRecord::from(vec![float_format.format(*time), float_format.format(*value)]))?;
+    }
+    Ok(())
+}
+
+/// Renders a trace as an SVG file
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `path` - The path of the SVG file, created or truncated
+/// * `options` - The size, title, time range, y-limits and number of points of the figure
+///
+/// # Returns
+///
+/// Nothing, or an error if the time range holds no sample or the file cannot be written
+///
+/// # Examples
+///
+/// ```
+/// plot_trace_svg(&filtered, 30000.0, "trace.svg", &PlotOptions::default())?;
+/// ```
+///
+/// # Note
+///
+/// The trace is reduced with `display_decimate` to `options.max_points` points, so a
+/// signal of millions of samples renders in about the same time as a short one
+///
+#[cfg(feature = "plot")]
+pub fn plot_trace_svg<P: AsRef<Path>>(samples: &[f64], sampling_rate: f64, path: P, options: &PlotOptions) -> io::Result<()> {
+    let (start, end) = match options.time_range {
+        Some((start, end)) => (
+            ((start * sampling_rate).ceil().max(0.0) as usize).min(samples.len()),
+            ((end * sampling_rate).floor() as usize + 1).min(samples.len()),
+        ),
+        None => (0, samples.len()),
+    };
+    if start >= end {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "The time range holds no sample"));
+    }
+    let (mut times, values) = display_decimate(&samples[start..end], sampling_rate, options.max_points);
+    let offset = start as f64 / sampling_rate;
+    times.iter_mut().for_each(|time| *time += offset);
+    let points: Vec<(f64, f64)> = times.into_iter().zip(values).collect();
+    let x_range = options.time_range.unwrap_or((offset, (end - 1) as f64 / sampling_rate));
+    let y_range = options.y_limits.unwrap_or_else(|| data_range(points.iter().map(|point| point.1)));
+    let root = SVGBackend::new(path.as_ref(), (options.width, options.height)).into_drawing_area();
+    draw_lines(&root, options, &points, x_range, y_range, ("Time (s)", "Amplitude"), (false, false))
+}
+
+/// Renders a spectrum as an SVG file, used by `Spectrum::plot_svg`
+#[cfg(feature = "plot")]
+pub(crate) fn plot_spectrum_svg(spectrum: &Spectrum, path: &Path, options: &PlotOptions) -> io::Result<()> {
+    // Logarithmic axes are drawn as linear axes of log10 values with relabelled ticks
+    let axis = |value: f64, log: bool| if log { value.log10() } else { value };
+    let points: Vec<(f64, f64)> = spectrum
+        .frequencies
+        .iter()
+        .zip(&spectrum.power)
+        .map(|(&frequency, &power)| (axis(frequency, options.log_x), axis(power, options.log_y)))
+        .filter(|(frequency, power)| frequency.is_finite() && power.is_finite())
+        .collect();
+    if points.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "The spectrum has no finite bin to plot"));
+    }
+    let x_range = data_range(points.iter().map(|point| point.0));
+    let y_range = match options.y_limits {
+        Some((low, high)) => (axis(low, options.log_y), axis(high, options.log_y)),
+        None => data_range(points.iter().map(|point| point.1)),
+    };
+    let root = SVGBackend::new(path, (options.width, options.height)).into_drawing_area();
+    draw_lines(&root, options, &points, x_range, y_range, ("Frequency (Hz)", "Power (units²/Hz)"), (options.log_x, options.log_y))
+}
+
+/// Renders a spectrogram as a PNG file, used by `Spectrogram::plot_png`
+#[cfg(feature = "plot")]
+pub(crate) fn plot_spectrogram_png(spectrogram: &Spectrogram, path: &Path, options: &PlotOptions) -> io::Result<()> {
+    let (times, frequencies) = (&spectrogram.times, &spectrogram.frequencies);
+    if times.is_empty() || frequencies.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "The spectrogram is empty"));
+    }
+    let db = spectrogram.to_db(1.0);
+    let columns: Vec<usize> = (0..times.len())
+        .filter(|&t| options.time_range.is_none_or(|(start, end)| times[t] >= start && times[t] <= end))
+        .collect();
+    if columns.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "The time range holds no window"));
+    }
+    let (low, high) = widened(
+        options
+            .color_limits
+            .unwrap_or_else(|| data_range(columns.iter().flat_map(|&t| db.power.iter().map(move |row| row[t])))),
+    );
+    let t_edges = cell_edges(times);
+    let f_edges = cell_edges(frequencies);
+    let x_range = (t_edges[columns[0]], t_edges[columns[columns.len() - 1] + 1]);
+    let y_range = options.y_limits.unwrap_or((f_edges[0], f_edges[frequencies.len()]));
+
+    let root = BitMapBackend::new(path, (options.width, options.height)).into_drawing_area();
+    root.fill(&WHITE).map_err(plot_error)?;
+    let mut builder = ChartBuilder::on(&root);
+    builder.margin(10).x_label_area_size(40).y_label_area_size(70);
+    if let Some(title) = &options.title {
+        builder.caption(title, ("sans-serif", 20));
+    }
+    let mut chart = builder.build_cartesian_2d(x_range.0..x_range.1, y_range.0..y_range.1).map_err(plot_error)?;
+    chart
+        .configure_mesh()
+        .disable_mesh()
+        .x_desc("Time (s)")
+        .y_desc("Frequency (Hz)")
+        .draw()
+        .map_err(plot_error)?;
+    let (db, t_edges, f_edges, colormap) = (&db, &t_edges, &f_edges, options.colormap);
+    let cells = columns.iter().flat_map(|&t| {
+        (0..frequencies.len()).map(move |f| {
+            let (r, g, b) = colormap.rgb((db.power[f][t] - low) / (high - low));
+            Rectangle::new([(t_edges[t], f_edges[f]), (t_edges[t + 1], f_edges[f + 1])], RGBColor(r, g, b).filled())
+        })
+    });
+    chart.draw_series(cells).map_err(plot_error)?;
+    root.present().map_err(plot_error)
+}
+
+/// Draws connected points on a drawing area with labelled axes
+#[cfg(feature = "plot")]
+fn draw_lines<DB: DrawingBackend>(
+    root: &DrawingArea<DB, plotters::coord::Shift>,
+    options: &PlotOptions,
+    points: &[(f64, f64)],
+    x_range: (f64, f64),
+    y_range: (f64, f64),
+    labels: (&str, &str),
+    log: (bool, bool),
+) -> io::Result<()> {
+    root.fill(&WHITE).map_err(plot_error)?;
+    let mut builder = ChartBuilder::on(root);
+    builder.margin(10).x_label_area_size(40).y_label_area_size(70);
+    if let Some(title) = &options.title {
+        builder.caption(title, ("sans-serif", 20));
+    }
+    let (x_range, y_range) = (widened(x_range), widened(y_range));
+    let mut chart = builder.build_cartesian_2d(x_range.0..x_range.1, y_range.0..y_range.1).map_err(plot_error)?;
+    let x_format = |value: &f64| format_tick(*value, log.0);
+    let y_format = |value: &f64| format_tick(*value, log.1);
+    chart
+        .configure_mesh()
+        .x_desc(labels.0)
+        .y_desc(labels.1)
+        .x_label_formatter(&x_format)
+        .y_label_formatter(&y_format)
+        .draw()
+        .map_err(plot_error)?;
+    chart.draw_series(LineSeries::new(points.iter().copied(), &BLUE)).map_err(plot_error)?;
+    root.present().map_err(plot_error)
+}
+
+/// Labels a tick, undoing the log10 of a logarithmic axis
+#[cfg(feature = "plot")]
+fn format_tick(value: f64, log: bool) -> String {
+    let value = if log { 10f64.powf(value) } else { value };
+    if value != 0.0 && (value.abs() >= 1e4 || value.abs() < 1e-3) { format!("{:.1e}", value) } else { format!("{}", (value * 1e3).round() / 1e3) }
+}
+
+/// Returns the smallest and largest finite value, or (0, 1) if there is none
+#[cfg(feature = "plot")]
+fn data_range(values: impl Iterator<Item = f64>) -> (f64, f64) {
+    values
+        .filter(|value| value.is_finite())
+        .fold(None, |range: Option<(f64, f64)>, value| Some(range.map_or((value, value), |(low, high)| (low.min(value), high.max(value)))))
+        .unwrap_or((0.0, 1.0))
+}
+
+/// Widens an empty range so that the axis can be drawn
+#[cfg(feature = "plot")]
+fn widened((low, high): (f64, f64)) -> (f64, f64) {
+    if high > low { (low, high) } else { (low - 0.5, low + 0.5) }
+}
+
+/// Returns the edges of cells centred on increasing positions
+#[cfg(feature = "plot")]
+fn cell_edges(centers: &[f64]) -> Vec<f64> {
+    if centers.len() == 1 {
+        return vec![centers[0] - 0.5, centers[0] + 0.5];
+    }
+    let mut edges = Vec::with_capacity(centers.len() + 1);
+    edges.push(centers[0] - (centers[1] - centers[0]) / 2.0);
+    edges.extend(centers.windows(2).map(|pair| (pair[0] + pair[1]) / 2.0));
+    edges.push(centers[centers.len() - 1] + (centers[centers.len() - 1] - centers[centers.len() - 2]) / 2.0);
+    edges
+}
+
+#[cfg(feature = "plot")]
+fn plot_error<E: std::fmt::Display>(error: E) -> io::Error {
+    io::Error::other(error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decimation_keeps_the_extremes_of_every_bin_in_order() {
+        let mut samples: Vec<f64> = (0..100_000).map(|i| (i as f64 * 0.01).sin() * 0.1).collect();
+        samples[31_234] = 5.0;
+        samples[77_777] = -4.0;
+        samples[500] = f64::NAN;
+        let (times, values) = display_decimate(&samples, 1000.0, 400);
+        assert!(times.len() <= 400 && times.len() >= 300, "{}", times.len());
+        assert!(times.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(values.iter().all(|value| value.is_finite()));
+        let spike = values.iter().position(|&value| value == 5.0).unwrap();
+        assert_eq!(times[spike], 31.234);
+        let dip = values.iter().position(|&value| value == -4.0).unwrap();
+        assert_eq!(times[dip], 77.777);
+
+        let (times, values) = display_decimate(&[1.0, f64::NAN, 3.0], 2.0, 400);
+        assert_eq!((times, values), (vec![0.0, 1.0], vec![1.0, 3.0]));
+    }
+
+    #[test]
+    fn colormaps_run_from_their_first_to_their_last_color() {
+        assert_eq!(Colormap::Viridis.rgb(0.0), VIRIDIS[0]);
+        assert_eq!(Colormap::Viridis.rgb(7.0), VIRIDIS[8]);
+        assert_eq!(Colormap::Viridis.rgb(0.5), VIRIDIS[4]);
+        assert_eq!(Colormap::Gray.rgb(-1.0), (0, 0, 0));
+        assert_eq!(Colormap::Gray.rgb(f64::NAN), (0, 0, 0));
+    }
+
+    #[test]
+    fn plot_csv_holds_the_decimated_trace() {
+        let path = std::env::temp_dir().join(format!("neurorust-plot-{}-trace.csv", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let samples: Vec<f64> = (0..10_000).map(|i| (i % 100) as f64).collect();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        trace_to_plot_csv(&samples, 100.0, 200, &mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("time,value"));
+        assert_eq!(lines.count(), 200);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Returns the points of the blue line of an SVG file, the axes and ticks being black
+    #[cfg(feature = "plot")]
+    fn polyline(svg: &str) -> Vec<(f64, f64)> {
+        let start = svg.find("stroke=\"#0000FF\"").expect("no blue line in the SVG");
+        let points = &svg[start..];
+        let points = &points[points.find("points=\"").unwrap() + 8..];
+        points[..points.find('"').unwrap()]
+            .split_whitespace()
+            .map(|point| {
+                let (x, y) = point.split_once(',').unwrap();
+                (x.parse().unwrap(), y.parse().unwrap())
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "plot")]
+    #[test]
+    fn traces_render_as_svg_with_their_spikes() {
+        let path = std::env::temp_dir().join(format!("neurorust-plot-{}-trace.svg", std::process::id()));
+        let mut samples = vec![0.0; 1_000_000];
+        samples[600_000] = 1.0;
+        let options = PlotOptions { width: 800, height: 300, title: Some("Channel 3".to_string()), max_points: 500, ..PlotOptions::default() };
+        plot_trace_svg(&samples, 30000.0, &path, &options).unwrap();
+        let svg = std::fs::read_to_string(&path).unwrap();
+        assert!(svg.starts_with("<svg") && svg.contains("width=\"800\"") && svg.contains("height=\"300\""));
+        assert!(svg.contains("Channel 3") && svg.contains("Time (s)"));
+        let points = polyline(&svg);
+        assert!(points.len() > 2 && points.len() <= 500, "{}", points.len());
+        // The spike is the only point well above the flat line, three fifths of the way along
+        let top = points.iter().copied().fold((0.0, f64::INFINITY), |top, point| if point.1 < top.1 { point } else { top });
+        let (left, right) = (points[0].0, points[points.len() - 1].0);
+        assert!(((top.0 - left) / (right - left) - 0.6).abs() < 0.01, "{:?}", top);
+        assert_eq!(points.iter().filter(|point| point.1 < top.1 + 10.0).count(), 1);
+
+        let options = PlotOptions { time_range: Some((50.0, 60.0)), ..PlotOptions::default() };
+        let error = plot_trace_svg(&samples, 30000.0, &path, &options).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "plot")]
+    #[test]
+    fn spectra_and_spectrograms_render_to_files() {
+        let directory = std::env::temp_dir();
+        let svg = directory.join(format!("neurorust-plot-{}-spectrum.svg", std::process::id()));
+        let frequencies: Vec<f64> = (0..=500).map(f64::from).collect();
+        let power = frequencies.iter().map(|frequency| 1.0 / (1.0 + frequency)).collect();
+        let spectrum = Spectrum { frequencies, power, amplitude: None, phase: None };
+        let options = PlotOptions { log_x: true, log_y: true, ..PlotOptions::default() };
+        spectrum.plot_svg(&svg, &options).unwrap();
+        let text = std::fs::read_to_string(&svg).unwrap();
+        // The 0 Hz bin has no logarithm and is left out, and 1/(1+f) falls steadily, to the
+        // pixel, since the SVG rounds every point
+        let points = polyline(&text);
+        assert_eq!(points.len(), 500);
+        assert!(points.windows(2).all(|pair| pair[1].0 >= pair[0].0 && pair[1].1 >= pair[0].1));
+        // On a logarithmic axis 10 Hz sits log(10) / log(500) of the way from 1 Hz to 500 Hz
+        let (left, right) = (points[0].0, points[499].0);
+        assert!((points[9].0 - left - (right - left) / 500f64.log10()).abs() <= 1.0, "{:?}", (left, points[9], right));
+        assert!(text.contains("Frequency (Hz)"));
+        std::fs::remove_file(svg).unwrap();
+
+        let png = directory.join(format!("neurorust-plot-{}-spectrogram.png", std::process::id()));
+        let spectrogram = Spectrogram {
+            times: (0..20).map(|t| t as f64 * 0.1).collect(),
+            frequencies: (0..30).map(|f| f as f64 * 2.0).collect(),
+            power: (0..30).map(|f| (0..20).map(|t| 1.0 + (f * t) as f64).collect()).collect(),
+        };
+        let options = PlotOptions { width: 320, height: 240, ..PlotOptions::default() };
+        spectrogram.plot_png(&png, &options).unwrap();
+        let bytes = std::fs::read(&png).unwrap();
+        assert_eq!(&bytes[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(u32::from_be_bytes(bytes[16..20].try_into().unwrap()), 320);
+        assert_eq!(u32::from_be_bytes(bytes[20..24].try_into().unwrap()), 240);
+        let empty = Spectrogram { times: vec![], frequencies: vec![], power: vec![] };
+        assert_eq!(empty.plot_png(&png, &options).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        std::fs::remove_file(png).unwrap();
+    }
+}
@@ -0,0 +1,517 @@
+// A module to write small min/max preview files of large csv recordings
+
+// Written by Amin Alam in 2024
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+use csv::StringRecord;
+use crate::data_io::csv::{column_index, CsvIO, CsvReader, CsvWriter};
+use crate::data_io::error::DataIoError;
+use crate::processing::checkpoint::quote;
+
+/// Options of `generate`
+///
+/// # Arguments
+///
+/// * `event_columns` - Columns always treated as events, in addition to those found to be sparse
+/// * `sparse_fraction` - Numeric columns with values in fewer than this fraction of rows are treated as events, 0.5 by default
+/// * `max_events` - The largest number of event rows kept, 10000 by default
+///
+/// # Examples
+///
+/// ```
+/// let options = PreviewOptions { event_columns: vec!["trigger".to_string()], ..PreviewOptions::default() };
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PreviewOptions {
+    pub event_columns: Vec<String>,
+    pub sparse_fraction: f64,
+    pub max_events: usize,
+}
+
+impl Default for PreviewOptions {
+    fn default() -> Self {
+        Self { event_columns: Vec::new(), sparse_fraction: 0.5, max_events: 10000 }
+    }
+}
+
+/// What `generate` wrote
+///
+/// # Arguments
+///
+/// * `output` - The path to the binned preview
+/// * `events_output` - The path to the event rows, or None if there are no event columns
+/// * `sidecar` - The path to the JSON sidecar describing the preview
+/// * `n_source_rows` - The number of records in the source file
+/// * `decimation_factor` - The number of source rows per preview row
+/// * `n_bins` - The number of rows of the binned preview
+/// * `signal_columns` - The columns binned into `<column>_min` and `<column>_max`
+/// * `event_columns` - The columns whose non-empty rows were kept
+/// * `n_events` - The number of event rows written
+/// * `events_truncated` - True if more event rows than `max_events` were found
+///
+/// # Examples
+///
+/// ```
+/// let report = generate("session.csv", "session_preview.csv", 5000, &PreviewOptions::default())?;
+/// println!("{} rows per bin", report.decimation_factor);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PreviewReport {
+    pub output: PathBuf,
+    pub events_output: Option<PathBuf>,
+    pub sidecar: PathBuf,
+    pub n_source_rows: usize,
+    pub decimation_factor: usize,
+    pub n_bins: usize,
+    pub signal_columns: Vec<String>,
+    pub event_columns: Vec<String>,
+    pub n_events: usize,
+    pub events_truncated: bool,
+}
+
+/// The in-memory min/max preview of a set of channels
+///
+/// # Arguments
+///
+/// * `decimation_factor` - The number of samples per bin
+/// * `minima` - The minimum of each bin of each channel, indexed as `minima[channel][bin]`, NaN for a bin without values
+/// * `maxima` - The maximum of each bin of each channel
+///
+/// # Examples
+///
+/// ```
+/// let preview = preview(&channels, 2000)?;
+/// preview.to_csv(&names, &mut csv_io)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Preview {
+    pub decimation_factor: usize,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub minima: Vec<Vec<f64>>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub maxima: Vec<Vec<f64>>,
+}
+
+/// Implementation of the Preview struct
+///
+/// # Methods
+///
+/// * `n_bins` - Returns the number of bins
+/// * `to_csv` - Writes the preview in the layout of `generate`
+impl Preview {
+    /// Returns the number of bins
+    ///
+    /// # Returns
+    ///
+    /// The number of bins of every channel
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// println!("{} bins", preview.n_bins());
+    /// ```
+    ///
+    pub fn n_bins(&self) -> usize {
+        self.minima.first().map_or(0, Vec::len)
+    }
+
+    /// Writes the preview in the layout of `generate`
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - The name of each channel
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// preview.to_csv(&names, &mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The header is `row` followed by `<name>_min` and `<name>_max` for every channel,
+    /// where `row` is the index of the first sample of the bin. The rows are not flushed to
+    /// disk until `save` is called.
+    ///
+    pub fn to_csv(&self, names: &[String], csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        let mut header = vec!["row".to_string()];
+        for name in names {
+            header.push(format!("{}_min", name));
+            header.push(format!("{}_max", name));
+        }
+        csv_io.write_record(StringRecord::from(header))?;
+        for bin in 0..self.n_bins() {
+            let mut record = vec![(bin * self.decimation_factor).to_string()];
+            for (minima, maxima) in self.minima.iter().zip(&self.maxima) {
+                record.push(float_format.format(minima[bin]));
+                record.push(float_format.format(maxima[bin]));
+            }
+            csv_io.write_record(StringRecord::from(record))?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes the min/max preview of a set of channels in memory
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel, all of the same length
+/// * `target_samples` - The largest number of bins, at least 1
+///
+/// # Returns
+///
+/// The Preview, or an error if `target_samples` is 0 or the channels differ in length
+///
+/// # Examples
+///
+/// ```
+/// let preview = preview(&channels, 2000)?;
+/// ```
+///
+/// # Note
+///
+/// The bins are the same as those of `generate` for a file of the same number of rows.
+/// NaN samples are left out.
+///
+pub fn preview(channels: &[Vec<f64>], target_samples: usize) -> io::Result<Preview> {
+    let n_samples = channels.first().map_or(0, Vec::len);
+    if target_samples == 0 || channels.iter().any(|channel| channel.len() != n_samples) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Need a positive number of bins and channels of equal length, got {} bins", target_samples),
+        ));
+    }
+    let decimation_factor = decimation_factor(n_samples, target_samples);
+    let extremes: Vec<Vec<(f64, f64)>> = channels
+        .iter()
+        .map(|channel| {
+            channel
+                .chunks(decimation_factor)
+                .map(|chunk| chunk.iter().filter(|value| !value.is_nan()).fold((f64::NAN, f64::NAN), |(min, max), &value| (value.min(min), value.max(max))))
+                .collect()
+        })
+        .collect();
+    Ok(Preview {
+        decimation_factor,
+        minima: extremes.iter().map(|channel| channel.iter().map(|extreme| extreme.0).collect()).collect(),
+        maxima: extremes.iter().map(|channel| channel.iter().map(|extreme| extreme.1).collect()).collect(),
+    })
+}
+
+/// Writes a min/max preview of a csv file, its event rows and a JSON sidecar
+///
+/// # Arguments
+///
+/// * `input` - The path to the source csv file
+/// * `output` - The path to the binned preview, e.g. `session_preview.csv`
+/// * `target_rows` - The largest number of rows of the binned preview, at least 1
+/// * `options` - The event columns, the sparseness threshold and the cap on event rows
+///
+/// # Returns
+///
+/// The PreviewReport, or an error if `target_rows` is 0, an event column is not found,
+/// or a file cannot be read or written
+///
+/// # Examples
+///
+/// ```
+/// let report = generate("session.csv", "session_preview.csv", 5000, &PreviewOptions::default())?;
+/// ```
+///
+/// # Note
+///
+/// The source is read twice as a stream, first to count its rows and classify its
+/// columns and then to bin them, so files of any size take little memory. Every
+/// `decimation_factor = ceil(n_rows / target_rows)` rows form a bin, and the preview
+/// has a `row` column with the index of the first row of each bin followed by the
+/// minimum and maximum of every signal column, so single-sample transients are kept.
+/// A time column is binned like any other, giving the start and end time of each bin.
+/// Signal columns are the numeric columns with values in at least `sparse_fraction` of
+/// the rows; the others, e.g. trigger or label columns, are event columns, and their
+/// non-empty rows are copied with their `row` index to `<output stem>_events.csv` up to
+/// `max_events`. The sidecar, `output` with a `.json` extension, records the source
+/// path, size and modification time, the number of rows and the decimation factor. The
+/// output does not depend on anything but the source and the arguments.
+///
+pub fn generate<P: AsRef<Path>, Q: AsRef<Path>>(input: P, output: Q, target_rows: usize, options: &PreviewOptions) -> io::Result<PreviewReport> {
+    let (input, output) = (input.as_ref(), output.as_ref());
+    if target_rows == 0 {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "A preview needs at least one row"));
+    }
+
+    // First pass: count the rows and the values of each column, and check which are numeric
+    let mut reader = CsvReader::open(input)?;
+    let headers = reader.headers().clone();
+    for name in &options.event_columns {
+        column_index(&headers, name)?;
+    }
+    let mut n_values = vec![0usize; headers.len()];
+    let mut numeric = vec![true; headers.len()];
+    let mut n_rows = 0;
+    for record in reader.records() {
+        let record = record?;
+        for (column, field) in record.iter().enumerate().take(headers.len()) {
+            let field = field.trim();
+            if !field.is_empty() {
+                n_values[column] += 1;
+                numeric[column] &= field.parse::<f64>().is_ok();
+            }
+        }
+        n_rows += 1;
+    }
+    let is_signal: Vec<bool> = (0..headers.len())
+        .map(|column| {
+            numeric[column] && !options.event_columns.iter().any(|name| name == &headers[column]) && n_values[column] as f64 >= options.sparse_fraction * n_rows as f64 && n_values[column] > 0
+        })
+        .collect();
+    let signals: Vec<usize> = (0..headers.len()).filter(|&column| is_signal[column]).collect();
+    let events: Vec<usize> = (0..headers.len()).filter(|&column| !is_signal[column]).collect();
+    let decimation_factor = decimation_factor(n_rows, target_rows);
+
+    // Second pass: bin the signal columns and copy the event rows
+    let mut writer = CsvWriter::create(output)?;
+    let float_format = writer.float_format();
+    let mut header = StringRecord::from(vec!["row"]);
+    for &column in &signals {
+        header.push_field(&format!("{}_min", &headers[column]));
+        header.push_field(&format!("{}_max", &headers[column]));
+    }
+    writer.write_record(&header)?;
+    let events_output = (!events.is_empty()).then(|| {
+        let stem = output.file_stem().map_or_else(String::new, |stem| stem.to_string_lossy().into_owned());
+        output.with_file_name(format!("{}_events.csv", stem))
+    });
+    let mut event_writer = match &events_output {
+        Some(path) => {
+            let mut event_writer = CsvWriter::create(path)?;
+            let mut header = StringRecord::from(vec!["row"]);
+            events.iter().for_each(|&column| header.push_field(&headers[column]));
+            event_writer.write_record(&header)?;
+            Some(event_writer)
+        }
+        None => None,
+    };
+
+    let mut reader = CsvReader::open(input)?;
+    let mut extremes = vec![(f64::NAN, f64::NAN); signals.len()];
+    let (mut n_bins, mut n_events, mut events_truncated) = (0, 0, false);
+    let mut row = StringRecord::new();
+    let mut write_bin = |writer: &mut CsvWriter, extremes: &mut Vec<(f64, f64)>, n_bins: &mut usize| -> io::Result<()> {
+        row.clear();
+        row.push_field(&(*n_bins * decimation_factor).to_string());
+        for extreme in extremes.iter_mut() {
+            let (min, max) = std::mem::replace(extreme, (f64::NAN, f64::NAN));
+            row.push_field(&if min.is_nan() { String::new() } else { float_format.format(min) });
+            row.push_field(&if max.is_nan() { String::new() } else { float_format.format(max) });
+        }
+        *n_bins += 1;
+        writer.write_record(&row)
+    };
+    for (index, record) in reader.records().enumerate() {
+        let record = record?;
+        for (extreme, &column) in extremes.iter_mut().zip(&signals) {
+            let field = record.get(column).unwrap_or("").trim();
+            if !field.is_empty() {
+                let value: f64 = field
+                    .parse()
+                    .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Row {}: '{}' is not a number; the file changed while it was read", index, field)))?;
+                if !value.is_nan() {
+                    *extreme = (value.min(extreme.0), value.max(extreme.1));
+                }
+            }
+        }
+        if let Some(event_writer) = event_writer.as_mut() {
+            if events.iter().any(|&column| !record.get(column).unwrap_or("").trim().is_empty()) {
+                if n_events < options.max_events {
+                    let mut event = StringRecord::from(vec![index.to_string()]);
+                    events.iter().for_each(|&column| event.push_field(record.get(column).unwrap_or("")));
+                    event_writer.write_record(&event)?;
+                    n_events += 1;
+                } else {
+                    events_truncated = true;
+                }
+            }
+        }
+        if (index + 1) % decimation_factor == 0 || index + 1 == n_rows {
+            write_bin(&mut writer, &mut extremes, &mut n_bins)?;
+        }
+    }
+    writer.flush()?;
+    if let Some(event_writer) = event_writer.as_mut() {
+        event_writer.flush()?;
+    }
+
+    let report = PreviewReport {
+        output: output.to_path_buf(),
+        events_output,
+        sidecar: output.with_extension("json"),
+        n_source_rows: n_rows,
+        decimation_factor,
+        n_bins,
+        signal_columns: signals.iter().map(|&column| headers[column].to_string()).collect(),
+        event_columns: events.iter().map(|&column| headers[column].to_string()).collect(),
+        n_events,
+        events_truncated,
+    };
+    write_sidecar(input, &report)?;
+    Ok(report)
+}
+
+/// Returns the number of rows per bin that gives at most `target` bins
+fn decimation_factor(n_rows: usize, target: usize) -> usize {
+    n_rows.div_ceil(target).max(1)
+}
+
+fn write_sidecar(input: &Path, report: &PreviewReport) -> io::Result<()> {
+    let metadata = fs::metadata(input)?;
+    let modified = metadata.modified().ok().and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map_or(0, |duration| duration.as_secs());
+    let source = fs::canonicalize(input).unwrap_or_else(|_| input.to_path_buf());
+    let names = |columns: &[String]| columns.iter().map(|name| quote(name)).collect::<Vec<String>>().join(", ");
+    let fields = [
+        format!("\"Source\": {}", quote(&source.to_string_lossy())),
+        format!("\"SourceBytes\": {}", metadata.len()),
+        format!("\"SourceModified\": {}", modified),
+        format!("\"SourceRows\": {}", report.n_source_rows),
+        format!("\"DecimationFactor\": {}", report.decimation_factor),
+        format!("\"Bins\": {}", report.n_bins),
+        format!("\"SignalColumns\": [{}]", names(&report.signal_columns)),
+        format!("\"EventColumns\": [{}]", names(&report.event_columns)),
+        format!("\"EventRows\": {}", report.n_events),
+        format!("\"EventsTruncated\": {}", report.events_truncated),
+    ];
+    let mut writer = BufWriter::new(File::create(&report.sidecar)?);
+    writeln!(writer, "{{\n  {}\n}}", fields.join(",\n  "))?;
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    const N_ROWS: usize = 600_000;
+    const SPIKE_ROW: usize = 345_678;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("neurorust-preview-{}-{}", std::process::id(), name))
+    }
+
+    /// Writes a 1 kHz recording of two noisy channels with a single-sample spike on `ch2` and sparse triggers
+    fn write_recording(path: &Path) {
+        let mut rng = SeededRng::new(172);
+        let mut writer = BufWriter::new(File::create(path).unwrap());
+        writeln!(writer, "time,ch1,ch2,trigger").unwrap();
+        for row in 0..N_ROWS {
+            let ch2 = if row == SPIKE_ROW { 50.0 } else { rng.next_gaussian() };
+            let trigger = if row % 100_000 == 12_345 { "stim" } else { "" };
+            writeln!(writer, "{},{},{},{}", row as f64 / 1000.0, (row as f64 / 50.0).sin() + 0.1 * rng.next_gaussian(), ch2, trigger).unwrap();
+        }
+        writer.flush().unwrap();
+    }
+
+    #[test]
+    fn a_planted_spike_survives_in_its_bin() {
+        let (input, output) = (temp_path("spike.csv"), temp_path("spike_preview.csv"));
+        write_recording(&input);
+        let report = generate(&input, &output, 1000, &PreviewOptions::default()).unwrap();
+        assert_eq!(report.n_source_rows, N_ROWS);
+        assert_eq!(report.decimation_factor, 600);
+        assert_eq!(report.n_bins, 1000);
+        assert_eq!(report.signal_columns, ["time", "ch1", "ch2"]);
+        assert_eq!(report.event_columns, ["trigger"]);
+        assert_eq!((report.n_events, report.events_truncated), (6, false));
+
+        let mut preview = CsvReader::open(&output).unwrap();
+        assert_eq!(preview.headers().iter().collect::<Vec<_>>(), ["row", "time_min", "time_max", "ch1_min", "ch1_max", "ch2_min", "ch2_max"]);
+        let rows: Vec<Vec<f64>> = preview.records().map(|record| record.unwrap().iter().map(|field| field.parse().unwrap()).collect()).collect();
+        assert_eq!(rows.len(), 1000);
+        let spike_bin = SPIKE_ROW / 600;
+        let (loudest, _) = rows.iter().enumerate().max_by(|a, b| a.1[6].total_cmp(&b.1[6])).unwrap();
+        assert_eq!(loudest, spike_bin);
+        let bin = &rows[spike_bin];
+        assert_eq!(bin[..3], [(spike_bin * 600) as f64, (spike_bin * 600) as f64 / 1000.0, (spike_bin * 600 + 599) as f64 / 1000.0]);
+        assert_eq!(bin[6], 50.0);
+        assert!(bin[3] > -1.6 && bin[4] < 1.6, "{:?}", bin);
+        assert!(rows.iter().enumerate().all(|(index, row)| index == spike_bin || row[6] < 7.0));
+
+        let events = fs::read_to_string(report.events_output.as_ref().unwrap()).unwrap();
+        assert!(events.starts_with("row,trigger\n12345,stim\n112345,stim\n"), "{}", events);
+        let sidecar = fs::read_to_string(&report.sidecar).unwrap();
+        for field in ["\"DecimationFactor\": 600", "\"SourceRows\": 600000", "\"Bins\": 1000", "\"EventColumns\": [\"trigger\"]", "\"EventsTruncated\": false"] {
+            assert!(sidecar.contains(field), "{} not in {}", field, sidecar);
+        }
+        assert!(sidecar.contains(&format!("\"SourceBytes\": {}", fs::metadata(&input).unwrap().len())));
+        assert!(sidecar.contains(&quote(&fs::canonicalize(&input).unwrap().to_string_lossy())));
+
+        let first = fs::read(&output).unwrap();
+        let again = temp_path("spike_again.csv");
+        generate(&input, &again, 1000, &PreviewOptions::default()).unwrap();
+        assert_eq!(fs::read(&again).unwrap(), first);
+        for path in [input, output.clone(), output.with_extension("json"), temp_path("spike_preview_events.csv"), again.clone(), again.with_extension("json"), temp_path("spike_again_events.csv")] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn events_are_capped_and_partial_bins_kept() {
+        let (input, output) = (temp_path("events.csv"), temp_path("events_preview.csv"));
+        fs::write(&input, "x,label,code\n1,,\n-2,a,\n3,,7\nNaN,b,\n5,,\n,,\n4,c,\n").unwrap();
+        let options = PreviewOptions { event_columns: vec!["code".to_string()], max_events: 2, ..PreviewOptions::default() };
+        let report = generate(&input, &output, 3, &options).unwrap();
+        assert_eq!((report.decimation_factor, report.n_bins), (3, 3));
+        assert_eq!(report.event_columns, ["label", "code"]);
+        assert_eq!((report.n_events, report.events_truncated), (2, true));
+        assert_eq!(fs::read_to_string(&output).unwrap(), "row,x_min,x_max\n0,-2,3\n3,5,5\n6,4,4\n");
+        assert_eq!(fs::read_to_string(temp_path("events_preview_events.csv")).unwrap(), "row,label,code\n1,a,\n2,,7\n");
+
+        let missing = PreviewOptions { event_columns: vec!["nope".to_string()], ..PreviewOptions::default() };
+        assert!(generate(&input, &output, 3, &missing).is_err());
+        assert_eq!(generate(&input, &output, 0, &options).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        for path in [input, output.clone(), output.with_extension("json"), temp_path("events_preview_events.csv")] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn in_memory_preview_matches_the_file_bins() {
+        let channels = vec![vec![1.0, 5.0, -3.0, 2.0, f64::NAN, f64::NAN, 8.0], vec![0.0, 0.0, 0.0, 1.0, 1.0, 1.0, -1.0]];
+        let preview = preview(&channels, 3).unwrap();
+        assert_eq!(preview.decimation_factor, 3);
+        assert_eq!(preview.n_bins(), 3);
+        assert_eq!(preview.minima[0][..2], [-3.0, 2.0]);
+        assert_eq!(preview.maxima[0][..2], [5.0, 2.0]);
+        assert_eq!((preview.minima[0][2], preview.maxima[0][2]), (8.0, 8.0));
+        assert_eq!((preview.minima[1].clone(), preview.maxima[1].clone()), (vec![0.0, 1.0, -1.0], vec![0.0, 1.0, -1.0]));
+
+        let (input, output) = (temp_path("memory.csv"), temp_path("memory_preview.csv"));
+        fs::write(&input, "a,b\n1,0\n5,0\n-3,0\n2,1\nNaN,1\nNaN,1\n8,-1\n").unwrap();
+        generate(&input, &output, 3, &PreviewOptions::default()).unwrap();
+        let path = temp_path("memory_from_channels.csv");
+        fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        preview.to_csv(&["a".to_string(), "b".to_string()], &mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), fs::read_to_string(&output).unwrap());
+
+        assert!(super::preview(&channels, 0).is_err());
+        assert!(super::preview(&[vec![1.0], vec![1.0, 2.0]], 2).is_err());
+        assert_eq!(super::preview(&channels, 100).unwrap().decimation_factor, 1);
+        for path in [input, output.clone(), output.with_extension("json"), path] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,612 @@
+// A module to pseudonymize subject identifiers and shift dates in csv files with a secret key
+
+// Written by Amin Alam in 2024
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::Path;
+use csv::StringRecord;
+use crate::data_io::csv::{column_index, CsvReader, CsvWriter};
+
+/// The largest shift of `shift_dates` in days, in either direction
+pub const MAX_DATE_SHIFT_DAYS: i64 = 3650;
+
+/// The secret key behind the tokens and date shifts of `pseudonymize` and `shift_dates`
+///
+/// # Examples
+///
+/// ```
+/// let key = PseudonymKey::generate()?;
+/// key.save("study.key")?;
+/// let key = PseudonymKey::load("study.key")?;
+/// ```
+///
+/// # Note
+///
+/// Anyone holding the key can recompute the token of a known identifier and undo the date
+/// shifts, so it must be kept apart from the shared files. The key is never printed by
+/// `Debug`.
+#[derive(Clone, PartialEq, Eq)]
+pub struct PseudonymKey {
+    bytes: [u8; 32],
+}
+
+/// Implementation of the PseudonymKey struct
+///
+/// # Methods
+///
+/// * `generate` - Creates a key from the random bytes of the operating system
+/// * `from_bytes` - Creates a key from known bytes
+/// * `load` - Reads a key written by `save`
+/// * `save` - Writes the key as a line of hexadecimal digits
+/// * `token` - Returns the token that replaces a value
+/// * `date_offset` - Returns the shift in days of the dates of a subject
+impl PseudonymKey {
+    /// Creates a key from the random bytes of the operating system
+    ///
+    /// # Returns
+    ///
+    /// The PseudonymKey, or an error if the operating system cannot provide random bytes
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let key = PseudonymKey::generate()?;
+    /// ```
+    ///
+    pub fn generate() -> io::Result<Self> {
+        let mut bytes = [0u8; 32];
+        getrandom::getrandom(&mut bytes).map_err(|error| io::Error::other(error.to_string()))?;
+        Ok(Self { bytes })
+    }
+
+    /// Creates a key from known bytes
+    ///
+    /// # Arguments
+    ///
+    /// * `bytes` - The 32 bytes of the key
+    ///
+    /// # Returns
+    ///
+    /// The PseudonymKey
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let key = PseudonymKey::from_bytes([7; 32]);
+    /// ```
+    ///
+    pub fn from_bytes(bytes: [u8; 32]) -> Self {
+        Self { bytes }
+    }
+
+    /// Reads a key written by `save`
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the key file
+    ///
+    /// # Returns
+    ///
+    /// The PseudonymKey, or an error if the file cannot be read or does not hold 64 hexadecimal digits
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let key = PseudonymKey::load("study.key")?;
+    /// ```
+    ///
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let text = std::fs::read_to_string(path)?;
+        let text = text.trim();
+        let invalid = || io::Error::new(io::ErrorKind::InvalidData, "A key file must hold 64 hexadecimal digits");
+        if text.len() != 64 || !text.is_ascii() {
+            return Err(invalid());
+        }
+        let mut bytes = [0u8; 32];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = u8::from_str_radix(&text[2 * i..2 * i + 2], 16).map_err(|_| invalid())?;
+        }
+        Ok(Self { bytes })
+    }
+
+    /// Writes the key as a line of hexadecimal digits
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path to the key file, which must not exist yet
+    ///
+    /// # Returns
+    ///
+    /// Ok, or an error if the file exists or cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// PseudonymKey::generate()?.save("study.key")?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// An existing file is never overwritten, since losing a key breaks the links to every
+    /// file pseudonymized with it. On Unix the file is readable by its owner only.
+    ///
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = options.open(path)?;
+        writeln!(file, "{}", hex(&self.bytes))?;
+        file.sync_all()
+    }
+
+    /// Returns the token that replaces a value
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The original value, e.g. a subject identifier
+    ///
+    /// # Returns
+    ///
+    /// 32 lowercase hexadecimal digits, the first half of the HMAC-SHA256 of the value
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let token = key.token("sub-017");
+    /// ```
+    ///
+    pub fn token(&self, value: &str) -> String {
+        hex(&hmac_sha256(&self.bytes, &[b"token:", value.as_bytes()].concat())[..16])
+    }
+
+    /// Returns the shift in days of the dates of a subject
+    ///
+    /// # Arguments
+    ///
+    /// * `subject` - The subject identifier
+    ///
+    /// # Returns
+    ///
+    /// A number of days between `-MAX_DATE_SHIFT_DAYS` and `MAX_DATE_SHIFT_DAYS`, the same for every call with this key and subject
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let days = key.date_offset("sub-017");
+    /// ```
+    ///
+    pub fn date_offset(&self, subject: &str) -> i64 {
+        let digest = hmac_sha256(&self.bytes, &[b"date:", subject.as_bytes()].concat());
+        let value = u64::from_be_bytes(digest[..8].try_into().unwrap());
+        (value % (2 * MAX_DATE_SHIFT_DAYS as u64 + 1)) as i64 - MAX_DATE_SHIFT_DAYS
+    }
+}
+
+impl fmt::Debug for PseudonymKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("PseudonymKey(..)")
+    }
+}
+
+/// The tokens given to the values of a pseudonymized file
+///
+/// # Examples
+///
+/// ```
+/// let lookup = reader.pseudonymize(&["subject"], &key, &mut output)?;
+/// println!("{} subjects", lookup.len());
+/// ```
+///
+/// # Note
+///
+/// The lookup links every original value to its token, so it is only written out by
+/// `to_csv` when asked for explicitly.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PseudonymLookup {
+    tokens: BTreeMap<String, String>,
+}
+
+/// Implementation of the PseudonymLookup struct
+///
+/// # Methods
+///
+/// * `len` - Returns the number of distinct values replaced
+/// * `is_empty` - Returns true if no value was replaced
+/// * `token` - Returns the token of a value, if it was seen
+/// * `to_csv` - Writes the lookup as `value,token` rows, if explicitly allowed
+impl PseudonymLookup {
+    /// Returns the number of distinct values replaced
+    ///
+    /// # Returns
+    ///
+    /// The number of values
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// println!("{} subjects", lookup.len());
+    /// ```
+    ///
+    pub fn len(&self) -> usize {
+        self.tokens.len()
+    }
+
+    /// Returns true if no value was replaced
+    ///
+    /// # Returns
+    ///
+    /// A boolean that indicates if the lookup is empty
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert!(!lookup.is_empty());
+    /// ```
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.tokens.is_empty()
+    }
+
+    /// Returns the token of a value, if it was seen
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The original value
+    ///
+    /// # Returns
+    ///
+    /// The token, or None if the value was not in the pseudonymized columns
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let token = lookup.token("sub-017");
+    /// ```
+    ///
+    pub fn token(&self, value: &str) -> Option<&str> {
+        self.tokens.get(value).map(String::as_str)
+    }
+
+    /// Writes the lookup as `value,token` rows, if explicitly allowed
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - The writer of the lookup file
+    /// * `allow_lookup_export` - Must be true, to acknowledge that the file undoes the pseudonymization
+    ///
+    /// # Returns
+    ///
+    /// The number of rows written after the header row, or a `PermissionDenied` error if
+    /// the export is not allowed or an error if the rows cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut output = CsvWriter::create("lookup.csv")?;
+    /// lookup.to_csv(&mut output, true)?;
+    /// output.flush()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The rows are sorted by value. Nothing is written if the export is not allowed.
+    ///
+    pub fn to_csv(&self, output: &mut CsvWriter, allow_lookup_export: bool) -> io::Result<usize> {
+        if !allow_lookup_export {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied, "Writing a lookup table needs allow_lookup_export"));
+        }
+        output.write_record(&StringRecord::from(vec!["value", "token"]))?;
+        for (value, token) in &self.tokens {
+            output.write_record(&StringRecord::from(vec![value.as_str(), token.as_str()]))?;
+        }
+        Ok(self.tokens.len())
+    }
+}
+
+/// Copies the remaining records with the values of the columns replaced by their tokens
+pub(crate) fn pseudonymize(reader: &mut CsvReader, columns: &[&str], key: &PseudonymKey, output: &mut CsvWriter) -> io::Result<PseudonymLookup> {
+    let indices = columns.iter().map(|name| column_index(reader.headers(), name)).collect::<io::Result<Vec<usize>>>()?;
+    output.write_record(reader.headers())?;
+    let mut lookup = PseudonymLookup::default();
+    let mut row = StringRecord::new();
+    for record in reader.records() {
+        let record = record?;
+        row.clear();
+        for (column, field) in record.iter().enumerate() {
+            if indices.contains(&column) && !field.is_empty() {
+                row.push_field(lookup.tokens.entry(field.to_string()).or_insert_with(|| key.token(field)));
+            } else {
+                row.push_field(field);
+            }
+        }
+        output.write_record(&row)?;
+    }
+    Ok(lookup)
+}
+
+/// Copies the remaining records with the dates of the columns shifted by the offset of the subject of each row
+pub(crate) fn shift_dates(reader: &mut CsvReader, columns: &[&str], subject_column: &str, key: &PseudonymKey, output: &mut CsvWriter) -> io::Result<usize> {
+    let subject = column_index(reader.headers(), subject_column)?;
+    let indices = columns.iter().map(|name| column_index(reader.headers(), name)).collect::<io::Result<Vec<usize>>>()?;
+    output.write_record(reader.headers())?;
+    let mut offsets: BTreeMap<String, i64> = BTreeMap::new();
+    let mut row = StringRecord::new();
+    let mut n_rows = 0;
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, |position| position.line());
+        let id = record
+            .get(subject)
+            .filter(|id| !id.trim().is_empty())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Line {}: no '{}' value to pick the date offset", line, subject_column)))?;
+        let offset = *offsets.entry(id.to_string()).or_insert_with(|| key.date_offset(id));
+        row.clear();
+        for (column, field) in record.iter().enumerate() {
+            if indices.contains(&column) && !field.trim().is_empty() {
+                let shifted = shift_date(field.trim(), offset)
+                    .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("Line {}: '{}' is not a YYYY-MM-DD date", line, field)))?;
+                row.push_field(&shifted);
+            } else {
+                row.push_field(field);
+            }
+        }
+        output.write_record(&row)?;
+        n_rows += 1;
+    }
+    Ok(n_rows)
+}
+
+/// Shifts the `YYYY-MM-DD` date at the start of a field, keeping any time after it
+fn shift_date(field: &str, days: i64) -> Option<String> {
+    let (date, rest) = field.split_at_checked(10)?;
+    if !(rest.is_empty() || rest.starts_with('T') || rest.starts_with(' ')) {
+        return None;
+    }
+    let mut parts = date.split('-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 || !date.bytes().all(|b| b.is_ascii_digit() || b == b'-') {
+        return None;
+    }
+    let (year, month, day): (i64, u32, u32) = (year.parse().ok()?, month.parse().ok()?, day.parse().ok()?);
+    if !(1..=12).contains(&month) || day == 0 || day > days_in_month(year, month) {
+        return None;
+    }
+    let (year, month, day) = civil_from_days(days_from_civil(year, month, day) + days);
+    Some(format!("{:04}-{:02}-{:02}{}", year, month, day, rest))
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Returns the days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// Returns the proleptic Gregorian date of a number of days since 1970-01-01
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Computes the HMAC-SHA256 of a message, as defined in RFC 2104
+fn hmac_sha256(key: &[u8], message: &[u8]) -> [u8; 32] {
+    // Keys longer than a block are replaced by their digest
+    let hashed;
+    let key = if key.len() > 64 {
+        hashed = sha256(key);
+        &hashed[..]
+    } else {
+        key
+    };
+    let mut inner = [0x36u8; 64];
+    let mut outer = [0x5cu8; 64];
+    for (i, byte) in key.iter().enumerate() {
+        inner[i] ^= byte;
+        outer[i] ^= byte;
+    }
+    let inner_digest = sha256(&[&inner[..], message].concat());
+    sha256(&[&outer[..], &inner_digest[..]].concat())
+}
+
+const SHA256_K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Computes the SHA-256 digest of a message
+pub(crate) fn sha256(message: &[u8]) -> [u8; 32] {
+    let mut state: [u32; 8] = [0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19];
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((message.len() as u64) * 8).to_be_bytes());
+    for block in padded.chunks_exact(64) {
+        let mut w = [0u32; 64];
+        for (t, word) in block.chunks_exact(4).enumerate() {
+            w[t] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for t in 16..64 {
+            let s0 = w[t - 15].rotate_right(7) ^ w[t - 15].rotate_right(18) ^ (w[t - 15] >> 3);
+            let s1 = w[t - 2].rotate_right(17) ^ w[t - 2].rotate_right(19) ^ (w[t - 2] >> 10);
+            w[t] = w[t - 16].wrapping_add(s0).wrapping_add(w[t - 7]).wrapping_add(s1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = state;
+        for t in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let choice = (e & f) ^ (!e & g);
+            let temp1 = h.wrapping_add(s1).wrapping_add(choice).wrapping_add(SHA256_K[t]).wrapping_add(w[t]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let majority = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(majority);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+        for (word, value) in state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *word = word.wrapping_add(value);
+        }
+    }
+    let mut digest = [0u8; 32];
+    for (chunk, word) in digest.chunks_exact_mut(4).zip(state) {
+        chunk.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn unhex(text: &str) -> Vec<u8> {
+        (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn sha256_matches_the_fips_180_examples() {
+        let long = "a".repeat(1_000_000);
+        for (message, digest) in [
+            ("", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            ("abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            ("abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq", "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"),
+            (long.as_str(), "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"),
+        ] {
+            assert_eq!(hex(&sha256(message.as_bytes())), digest, "{} bytes", message.len());
+        }
+    }
+
+    #[test]
+    fn hmac_sha256_matches_the_rfc_4231_test_cases() {
+        let cases: [(Vec<u8>, Vec<u8>, &str); 7] = [
+            (vec![0x0b; 20], b"Hi There".to_vec(), "b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"),
+            (b"Jefe".to_vec(), b"what do ya want for nothing?".to_vec(), "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"),
+            (vec![0xaa; 20], vec![0xdd; 50], "773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe"),
+            ((1..=25).collect(), vec![0xcd; 50], "82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b"),
+            // Test case 5 only gives the first 128 bits
+            (vec![0x0c; 20], b"Test With Truncation".to_vec(), "a3b6167473100ee06e0c796c2955552b"),
+            (
+                vec![0xaa; 131],
+                b"Test Using Larger Than Block-Size Key - Hash Key First".to_vec(),
+                "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54",
+            ),
+            (
+                vec![0xaa; 131],
+                b"This is a test using a larger than block-size key and a larger than block-size data. The key needs to be hashed before being used by the HMAC algorithm.".to_vec(),
+                "9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2",
+            ),
+        ];
+        for (number, (key, data, mac)) in cases.iter().enumerate() {
+            let expected = unhex(mac);
+            assert_eq!(hmac_sha256(key, data)[..expected.len()], expected[..], "test case {}", number + 1);
+        }
+    }
+
+    #[test]
+    fn generated_keys_differ_and_tokens_are_stable() {
+        assert_ne!(PseudonymKey::generate().unwrap(), PseudonymKey::generate().unwrap());
+        let key = PseudonymKey::from_bytes([7; 32]);
+        let token = key.token("sub-017");
+        assert_eq!(token, hex(&hmac_sha256(&[7; 32], b"token:sub-017")[..16]));
+        assert_eq!(token.len(), 32);
+        assert_ne!(token, key.token("sub-018"));
+        assert!(key.date_offset("sub-017").abs() <= MAX_DATE_SHIFT_DAYS);
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("neurorust-pseudonym-{}-{}", std::process::id(), name))
+    }
+
+    /// Writes `input` to a file, runs `f` from a reader of it into a writer, and returns the output
+    fn transform<F>(name: &str, input: &str, f: F) -> io::Result<String>
+    where
+        F: FnOnce(&mut CsvReader, &mut CsvWriter) -> io::Result<()>,
+    {
+        let (input_path, output_path) = (temp_path(&format!("{}-in.csv", name)), temp_path(&format!("{}-out.csv", name)));
+        std::fs::write(&input_path, input)?;
+        let mut output = CsvWriter::create(&output_path)?;
+        let result = f(&mut CsvReader::open(&input_path)?, &mut output).and_then(|_| output.flush());
+        let written = std::fs::read_to_string(&output_path);
+        std::fs::remove_file(input_path)?;
+        std::fs::remove_file(output_path)?;
+        result.and(written)
+    }
+
+    #[test]
+    fn the_same_subject_gets_the_same_token_in_two_files_and_keys_differ() {
+        let key = PseudonymKey::from_bytes([3; 32]);
+        let pseudonymize = |reader: &mut CsvReader, output: &mut CsvWriter| reader.pseudonymize(&["subject"], &key, output).map(|_| ());
+        let visits = transform("visits", "subject,visit\nsub-01,1\nsub-02,1\nsub-01,2\n", pseudonymize).unwrap();
+        let scores = transform("scores", "score,subject\n12,sub-02\n15,sub-01\n,\n", pseudonymize).unwrap();
+        let (one, two) = (key.token("sub-01"), key.token("sub-02"));
+        assert_eq!(visits, format!("subject,visit\n{one},1\n{two},1\n{one},2\n"));
+        assert_eq!(scores, format!("score,subject\n12,{two}\n15,{one}\n,\n"));
+        assert_ne!(PseudonymKey::from_bytes([4; 32]).token("sub-01"), one);
+    }
+
+    #[test]
+    fn shifted_dates_keep_their_intervals_within_a_subject() {
+        let key = PseudonymKey::from_bytes([9; 32]);
+        let input = "subject,visit_date\nsub-01,2023-12-30\nsub-02,2024-02-28\nsub-01,2024-03-01T08:30\nsub-02,2024-03-01\nsub-01,\n";
+        let shifted = transform("dates", input, |reader, output| reader.shift_dates(&["visit_date"], "subject", &key, output).map(|_| ())).unwrap();
+        let rows: Vec<Vec<&str>> = shifted.lines().skip(1).map(|line| line.split(',').collect()).collect();
+        let day = |date: &str| days_from_civil(date[..4].parse().unwrap(), date[5..7].parse().unwrap(), date[8..10].parse().unwrap());
+        // 2023-12-30 to 2024-03-01 is 62 days across a leap day, 2024-02-28 to 2024-03-01 is 2
+        assert_eq!(day(rows[2][1]) - day(rows[0][1]), 62);
+        assert_eq!(day(rows[3][1]) - day(rows[1][1]), 2);
+        assert_eq!(day(rows[0][1]) - day("2023-12-30"), key.date_offset("sub-01"));
+        assert_eq!(day(rows[1][1]) - day("2024-02-28"), key.date_offset("sub-02"));
+        assert!(rows[2][1].ends_with("T08:30"));
+        assert_eq!(rows[4], vec!["sub-01", ""]);
+    }
+
+    #[test]
+    fn rows_without_a_subject_are_an_error() {
+        let key = PseudonymKey::from_bytes([9; 32]);
+        let shift = |reader: &mut CsvReader, output: &mut CsvWriter| reader.shift_dates(&["visit_date"], "subject", &key, output).map(|_| ());
+        let error = transform("no-subject", "subject,visit_date\nsub-01,2024-01-02\n,2024-01-03\n", shift).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(error.to_string().contains("Line 3"), "{}", error);
+    }
+}
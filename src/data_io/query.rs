@@ -0,0 +1,300 @@
+// A module to select the columns and filter the rows of a csv file while streaming its records
+
+// Written by Amin Alam in 2024
+
+use std::ops::Range;
+use csv::StringRecord;
+use crate::data_io::csv::{CsvIO, CsvWriter};
+use crate::data_io::error::DataIoError;
+use crate::data_io::records::CsvRecords;
+
+type Predicate<'a> = Box<dyn FnMut(&StringRecord) -> bool + 'a>;
+
+/// A step of a CsvQuery, applied to every record in the order the steps were added
+enum Step<'a> {
+    Select(Vec<usize>),
+    Filter(Predicate<'a>),
+}
+
+/// An iterator over the remaining records of a CsvIO object with some columns selected and some rows filtered out
+///
+/// # Arguments
+///
+/// * `records` - The records of the CsvIO object
+/// * `headers` - The headers of the records returned, after the selections so far
+/// * `steps` - The selections and filters, in the order they were added
+///
+/// # Examples
+///
+/// ```
+/// let mut query = csv_io.select(&["time", "ch3", "ch7"])?.filter_range("time", 10.0..20.0)?;
+/// let mut output = CsvWriter::create("ch3_ch7_10s.csv")?;
+/// query.copy_to(&mut output)?;
+/// ```
+///
+/// # Note
+///
+/// One record is held in memory at a time. A filter sees the columns selected before it was
+/// added, so a row can be filtered on a column that a later selection drops. The iteration
+/// ends after the first error.
+pub struct CsvQuery<'a> {
+    records: CsvRecords<'a>,
+    headers: StringRecord,
+    steps: Vec<Step<'a>>,
+}
+
+/// Implementation of the CsvQuery struct
+///
+/// # Methods
+///
+/// * `headers` - Returns the headers of the records returned
+/// * `select` - Keeps some columns, in the order given
+/// * `filter` - Keeps the rows a predicate accepts
+/// * `filter_range` - Keeps the rows whose value of a column is within a range
+/// * `copy_to` - Writes the headers and the records returned
+impl<'a> CsvQuery<'a> {
+    /// Creates a query returning every remaining record of a CsvIO object
+    pub(crate) fn new(csv_io: &'a mut CsvIO) -> Self {
+        // A file opened for writing has no headers, and its records yield a NotReadable error
+        let headers = csv_io.reader_mut().map(|reader| reader.headers().clone()).unwrap_or_default();
+        Self { records: csv_io.records(), headers, steps: Vec::new() }
+    }
+
+    /// Returns the headers of the records returned
+    ///
+    /// # Returns
+    ///
+    /// The names of the columns selected so far, or all headers if none was selected
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let query = csv_io.select(&["time", "ch3"])?;
+    /// assert_eq!(query.headers(), &StringRecord::from(vec!["time", "ch3"]));
+    /// ```
+    ///
+    pub fn headers(&self) -> &StringRecord {
+        &self.headers
+    }
+
+    /// Keeps some columns, in the order given
+    ///
+    /// # Arguments
+    ///
+    /// * `columns` - The names of the columns, among the headers selected so far
+    ///
+    /// # Returns
+    ///
+    /// The CsvQuery with the selection, or a `ColumnNotFound` error if a column is not among
+    /// its headers
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let query = csv_io.filter(|record| &record[0] != "NaN").select(&["time", "ch3"])?;
+    /// ```
+    ///
+    pub fn select(mut self, columns: &[&str]) -> Result<Self, DataIoError> {
+        let indices = columns.iter().map(|column| self.column(column)).collect::<Result<Vec<usize>, DataIoError>>()?;
+        self.headers = indices.iter().map(|&index| &self.headers[index]).collect();
+        self.steps.push(Step::Select(indices));
+        Ok(self)
+    }
+
+    /// Keeps the rows a predicate accepts
+    ///
+    /// # Arguments
+    ///
+    /// * `predicate` - Returns whether to keep a record, given its fields in the order of `headers`
+    ///
+    /// # Returns
+    ///
+    /// The CsvQuery with the filter
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let query = csv_io.select(&["trial", "condition", "ch1"])?.filter(|record| &record[1] == "go");
+    /// ```
+    ///
+    pub fn filter<F: FnMut(&StringRecord) -> bool + 'a>(mut self, predicate: F) -> Self {
+        self.steps.push(Step::Filter(Box::new(predicate)));
+        self
+    }
+
+    /// Keeps the rows whose value of a column is within a range
+    ///
+    /// # Arguments
+    ///
+    /// * `column` - The name of the column, among the headers selected so far
+    /// * `range` - The values kept, from `range.start` included to `range.end` excluded
+    ///
+    /// # Returns
+    ///
+    /// The CsvQuery with the filter, or a `ColumnNotFound` error if the column is not among
+    /// its headers
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let query = csv_io.select(&["time", "ch3", "ch7"])?.filter_range("time", 10.0..20.0)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Rows whose field of the column is not a number are left out
+    ///
+    pub fn filter_range(self, column: &str, range: Range<f64>) -> Result<Self, DataIoError> {
+        let index = self.column(column)?;
+        Ok(self.filter(move |record| record.get(index).and_then(|field| field.trim().parse().ok()).is_some_and(|value| range.contains(&value))))
+    }
+
+    /// Writes the headers and the records returned
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - The writer of the csv file
+    ///
+    /// # Returns
+    ///
+    /// The number of rows written after the header row, or an error if a record cannot be read
+    /// or written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut output = CsvWriter::create("go_trials.csv")?;
+    /// csv_io.filter(|record| &record[1] == "go").copy_to(&mut output)?;
+    /// output.flush()?;
+    /// ```
+    ///
+    pub fn copy_to(&mut self, output: &mut CsvWriter) -> Result<usize, DataIoError> {
+        output.write_record(&self.headers)?;
+        let mut n_rows = 0;
+        for record in self {
+            output.write_record(&record?)?;
+            n_rows += 1;
+        }
+        Ok(n_rows)
+    }
+
+    /// Returns the position of a column among the headers selected so far
+    fn column(&self, name: &str) -> Result<usize, DataIoError> {
+        self.headers.iter().position(|header| header == name).ok_or_else(|| DataIoError::ColumnNotFound(name.to_string()))
+    }
+}
+
+impl Iterator for CsvQuery<'_> {
+    type Item = Result<StringRecord, DataIoError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        'records: loop {
+            let mut record = match self.records.next()? {
+                Ok(record) => record,
+                Err(error) => return Some(Err(error)),
+            };
+            for step in &mut self.steps {
+                match step {
+                    Step::Select(indices) => {
+                        let mut selected: StringRecord = indices.iter().map(|&index| record.get(index).unwrap_or("")).collect();
+                        selected.set_position(record.position().cloned());
+                        record = selected;
+                    }
+                    Step::Filter(predicate) => {
+                        if !predicate(&record) {
+                            continue 'records;
+                        }
+                    }
+                }
+            }
+            return Some(Ok(record));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDE: &str = "time,ch1,ch2,ch3,label\n0.0,1,10,100,a\n0.1,2,20,200,b\n0.2,3,30,300,a\n0.3,4,40,400,b\n0.4,5,50,n/a,a\n";
+
+    fn fixture(name: &str) -> String {
+        let path = std::env::temp_dir().join(format!("neurorust-query-{}-{}", std::process::id(), name));
+        std::fs::write(&path, WIDE).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn rows(query: CsvQuery) -> Vec<Vec<String>> {
+        query.map(|record| record.unwrap().iter().map(str::to_string).collect()).collect()
+    }
+
+    #[test]
+    fn select_keeps_the_requested_columns_in_order() {
+        let path = fixture("select.csv");
+        let mut csv_io = CsvIO::open_read(&path).unwrap();
+        let query = csv_io.select(&["ch3", "time", "ch3"]).unwrap();
+        assert_eq!(query.headers(), &StringRecord::from(vec!["ch3", "time", "ch3"]));
+        assert_eq!(rows(query)[..2], [["100", "0.0", "100"], ["200", "0.1", "200"]]);
+
+        let mut csv_io = CsvIO::open_read(&path).unwrap();
+        assert!(matches!(csv_io.select(&["time", "ch9"]), Err(DataIoError::ColumnNotFound(column)) if column == "ch9"));
+        // A second select picks from the first
+        let query = csv_io.select(&["ch1", "ch2"]).unwrap().select(&["ch2"]).unwrap();
+        assert_eq!(rows(query), [["10"], ["20"], ["30"], ["40"], ["50"]]);
+        assert!(CsvIO::open_read(&path).unwrap().select(&["time"]).unwrap().select(&["ch1"]).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn filters_see_the_record_as_the_earlier_steps_left_it() {
+        let path = fixture("filter.csv");
+        let mut csv_io = CsvIO::open_read(&path).unwrap();
+        let query = csv_io.filter(|record| &record[4] == "a").select(&["time", "ch2"]).unwrap();
+        assert_eq!(rows(query), [["0.0", "10"], ["0.2", "30"], ["0.4", "50"]]);
+
+        let mut csv_io = CsvIO::open_read(&path).unwrap();
+        let query = csv_io.select(&["label", "ch1"]).unwrap().filter(|record| record[1].parse::<i32>().unwrap() % 2 == 0);
+        assert_eq!(rows(query), [["b", "2"], ["b", "4"]]);
+
+        // Filtered records keep their line in the file
+        let mut csv_io = CsvIO::open_read(&path).unwrap();
+        let lines: Vec<u64> = csv_io.filter(|record| &record[4] == "b").map(|record| record.unwrap().position().unwrap().line()).collect();
+        assert_eq!(lines, [3, 5]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn ranges_are_half_open_and_skip_values_that_are_not_numbers() {
+        let path = fixture("range.csv");
+        let mut csv_io = CsvIO::open_read(&path).unwrap();
+        let query = csv_io.select(&["time", "ch3"]).unwrap().filter_range("time", 0.1..0.3).unwrap();
+        assert_eq!(rows(query), [["0.1", "200"], ["0.2", "300"]]);
+        let mut csv_io = CsvIO::open_read(&path).unwrap();
+        let query = csv_io.filter(|_| true).filter_range("ch3", 0.0..1e9).unwrap();
+        assert_eq!(query.count(), 4);
+        let mut csv_io = CsvIO::open_read(&path).unwrap();
+        // The column must survive the selection
+        assert!(matches!(csv_io.select(&["ch1"]).unwrap().filter_range("time", 0.0..1.0), Err(DataIoError::ColumnNotFound(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn queries_copy_their_columns_and_rows() {
+        let path = fixture("copy.csv");
+        let output = path.replace("copy.csv", "copy_out.csv");
+        let mut csv_io = CsvIO::open_read(&path).unwrap();
+        let mut query = csv_io.select(&["time", "ch1", "label"]).unwrap().filter_range("time", 0.15..1.0).unwrap().filter(|record| &record[2] == "a");
+        assert_eq!(query.copy_to(&mut CsvWriter::create(&output).unwrap()).unwrap(), 2);
+        drop(query);
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "time,ch1,label\n0.2,3,a\n0.4,5,a\n");
+        // The query consumed the records of the CsvIO
+        assert!(csv_io.read_record().unwrap().is_none());
+
+        let mut writing = CsvIO::open_write(&output).unwrap();
+        assert!(matches!(writing.select(&["time"]), Err(DataIoError::NotReadable(_))));
+        let results: Vec<_> = writing.filter(|_| true).collect();
+        assert!(results.len() == 1 && matches!(results[0], Err(DataIoError::NotReadable(_))));
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&output).unwrap();
+    }
+}
@@ -0,0 +1,245 @@
+// A module to iterate over the records of a csv file without holding them in memory
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+
+/// An iterator over the remaining records of a borrowed CsvIO object
+///
+/// # Arguments
+///
+/// * `csv_io` - The CsvIO object the records are read from
+/// * `done` - Whether the end of the file or an error was reached
+///
+/// # Examples
+///
+/// ```
+/// for record in csv_io.records() {
+///     let record = record?;
+///     println!("{:?}", record);
+/// }
+/// ```
+///
+/// # Note
+///
+/// One record is held in memory at a time. The iteration ends after the first error.
+pub struct CsvRecords<'a> {
+    csv_io: &'a mut CsvIO,
+    done: bool,
+}
+
+/// An iterator over the remaining records of a CsvIO object it owns
+///
+/// # Arguments
+///
+/// * `csv_io` - The CsvIO object the records are read from
+/// * `done` - Whether the end of the file or an error was reached
+///
+/// # Examples
+///
+/// ```
+/// let n_records = CsvIO::open_read("lfp.csv")?.into_iter().count();
+/// ```
+///
+/// # Note
+///
+/// One record is held in memory at a time. The iteration ends after the first error.
+pub struct IntoCsvRecords {
+    csv_io: CsvIO,
+    done: bool,
+}
+
+/// An iterator over the remaining records of a borrowed CsvIO object in blocks of a fixed size
+///
+/// # Arguments
+///
+/// * `csv_io` - The CsvIO object the records are read from
+/// * `size` - The number of records of every block but the last
+/// * `done` - Whether the end of the file or an error was reached
+///
+/// # Examples
+///
+/// ```
+/// for block in csv_io.records_chunked(65536) {
+///     let block = block?;
+///     println!("{} records", block.len());
+/// }
+/// ```
+///
+/// # Note
+///
+/// One block is held in memory at a time. The iteration ends after the first error, which
+/// is returned in place of the block it occurred in.
+pub struct CsvRecordChunks<'a> {
+    csv_io: &'a mut CsvIO,
+    size: usize,
+    done: bool,
+}
+
+/// Reads the next record of an iterator, marking it done at the end of the file or after an error
+fn next_record(csv_io: &mut CsvIO, done: &mut bool) -> Option<Result<StringRecord, DataIoError>> {
+    if *done {
+        return None;
+    }
+    let record = csv_io.read_record().transpose();
+    *done = !matches!(record, Some(Ok(_)));
+    record
+}
+
+impl<'a> CsvRecords<'a> {
+    /// Creates an iterator over the remaining records of a CsvIO object
+    pub(crate) fn new(csv_io: &'a mut CsvIO) -> Self {
+        Self { csv_io, done: false }
+    }
+}
+
+impl Iterator for CsvRecords<'_> {
+    type Item = Result<StringRecord, DataIoError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        next_record(self.csv_io, &mut self.done)
+    }
+}
+
+impl Iterator for IntoCsvRecords {
+    type Item = Result<StringRecord, DataIoError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        next_record(&mut self.csv_io, &mut self.done)
+    }
+}
+
+impl<'a> CsvRecordChunks<'a> {
+    /// Creates an iterator over the remaining records of a CsvIO object in blocks of at least one record
+    pub(crate) fn new(csv_io: &'a mut CsvIO, size: usize) -> Self {
+        Self { csv_io, size: size.max(1), done: false }
+    }
+}
+
+impl Iterator for CsvRecordChunks<'_> {
+    type Item = Result<Vec<StringRecord>, DataIoError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let mut block = Vec::with_capacity(self.size);
+        while block.len() < self.size {
+            match next_record(self.csv_io, &mut self.done) {
+                Some(Ok(record)) => block.push(record),
+                Some(Err(error)) => return Some(Err(error)),
+                None => break,
+            }
+        }
+        (!block.is_empty()).then_some(Ok(block))
+    }
+}
+
+impl IntoIterator for CsvIO {
+    type Item = Result<StringRecord, DataIoError>;
+    type IntoIter = IntoCsvRecords;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoCsvRecords { csv_io: self, done: false }
+    }
+}
+
+impl<'a> IntoIterator for &'a mut CsvIO {
+    type Item = Result<StringRecord, DataIoError>;
+    type IntoIter = CsvRecords<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        CsvRecords::new(self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str, n_rows: usize) -> String {
+        let path = std::env::temp_dir().join(format!("neurorust-records-{}-{}", std::process::id(), name));
+        let text: String = std::iter::once("trial,rt\n".to_string()).chain((0..n_rows).map(|row| format!("{},{}\n", row, row * 2))).collect();
+        std::fs::write(&path, text).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn trial(record: &StringRecord) -> usize {
+        record[0].parse().unwrap()
+    }
+
+    #[test]
+    fn records_are_read_one_at_a_time() {
+        let path = fixture("lazy.csv", 10);
+        let mut csv_io = CsvIO::open_read(&path).unwrap();
+        assert_eq!(trial(&csv_io.records().next().unwrap().unwrap()), 0);
+        // Taking one record leaves the next ones to the reader
+        assert_eq!(trial(&csv_io.read_record().unwrap().unwrap()), 1);
+        let rest: Vec<usize> = csv_io.records().map(|record| trial(&record.unwrap())).collect();
+        assert_eq!(rest, (2..10).collect::<Vec<_>>());
+        assert!(csv_io.records().next().is_none());
+
+        let mut csv_io = CsvIO::open_read(&path).unwrap();
+        let mut seen = 0;
+        for record in &mut csv_io {
+            assert_eq!(trial(&record.unwrap()), seen);
+            seen += 1;
+        }
+        assert_eq!(seen, 10);
+        let owned: Vec<StringRecord> = CsvIO::open_read(&path).unwrap().into_iter().collect::<Result<_, _>>().unwrap();
+        assert_eq!(owned, CsvIO::open_read(&path).unwrap().read_records().unwrap());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn chunks_hold_at_most_n_records() {
+        let path = fixture("chunks.csv", 10);
+        let sizes: Vec<usize> = CsvIO::open_read(&path).unwrap().records_chunked(3).map(|chunk| chunk.unwrap().len()).collect();
+        assert_eq!(sizes, [3, 3, 3, 1]);
+        let first: Vec<usize> = CsvIO::open_read(&path).unwrap().records_chunked(4).flat_map(|chunk| chunk.unwrap()).map(|record| trial(&record)).collect();
+        assert_eq!(first, (0..10).collect::<Vec<_>>());
+        assert_eq!(CsvIO::open_read(&path).unwrap().records_chunked(10).count(), 1);
+        assert_eq!(CsvIO::open_read(&path).unwrap().records_chunked(100).next().unwrap().unwrap().len(), 10);
+        // A chunk size of zero reads one record at a time
+        assert_eq!(CsvIO::open_read(&path).unwrap().records_chunked(0).count(), 10);
+        let empty = fixture("empty.csv", 0);
+        assert!(CsvIO::open_read(&empty).unwrap().records_chunked(3).next().is_none());
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&empty).unwrap();
+    }
+
+    #[test]
+    fn an_error_ends_the_iteration() {
+        let path = fixture("broken.csv", 0);
+        std::fs::write(&path, "trial,rt\n0,0\n1,2,extra\n2,4\n").unwrap();
+        let mut csv_io = CsvIO::open_read(&path).unwrap();
+        let results: Vec<Result<StringRecord, DataIoError>> = csv_io.records().collect();
+        assert_eq!(results.len(), 2);
+        assert!(results[0].is_ok() && results[1].is_err());
+
+        let chunks: Vec<_> = CsvIO::open_read(&path).unwrap().records_chunked(5).collect();
+        assert_eq!(chunks.len(), 1);
+        assert!(chunks[0].is_err());
+
+        let mut writing = CsvIO::open_write(&path).unwrap();
+        let results: Vec<_> = writing.records().collect();
+        assert!(results.len() == 1 && matches!(results[0], Err(DataIoError::NotReadable(_))));
+        assert_eq!(writing.records_chunked(2).count(), 1);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn large_files_stream_in_chunks() {
+        let path = fixture("large.csv", 200_000);
+        let mut csv_io = CsvIO::open_read(&path).unwrap();
+        let (mut n, mut total, mut largest) = (0, 0u64, 0);
+        for chunk in csv_io.records_chunked(4096) {
+            let chunk = chunk.unwrap();
+            largest = largest.max(chunk.len());
+            n += chunk.len();
+            total += chunk.iter().map(|record| record[1].parse::<u64>().unwrap()).sum::<u64>();
+        }
+        assert_eq!((n, largest), (200_000, 4096));
+        assert_eq!(total, 199_999 * 200_000);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
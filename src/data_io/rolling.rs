@@ -0,0 +1,371 @@
+// A module to compute rolling aggregates of csv columns in one streaming pass
+
+// Written by Amin Alam in 2024
+
+use std::collections::VecDeque;
+use std::io;
+use csv::StringRecord;
+use crate::data_io::csv::{column_index, parse_number, Agg};
+use crate::data_io::float_format::FloatFormat;
+
+/// The rows a rolling aggregate covers, always ending at the current row
+///
+/// # Arguments
+///
+/// * `Rows` - The current row and the rows before it, this many in total
+/// * `Span` - The rows whose value in the monotonically increasing `column` is within `width` of the current row's, i.e. in `(current - width, current]`
+/// * `Cumulative` - Every row from the first to the current one
+///
+/// # Examples
+///
+/// ```
+/// let last_ten = RollingWindow::Rows(10);
+/// let last_thirty_seconds = RollingWindow::Span { column: "time_s".to_string(), width: 30.0 };
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RollingWindow {
+    Rows(usize),
+    Span { column: String, width: f64 },
+    Cumulative,
+}
+
+/// What a rolling aggregate is at the start of the file, before its window is full
+///
+/// # Arguments
+///
+/// * `Partial` - The aggregate of the rows seen so far
+/// * `Nan` - NaN until the window is full, i.e. until the `Rows` window holds that many rows or the `Span` column has advanced by `width` from its first value
+///
+/// # Examples
+///
+/// ```
+/// let column = RollingColumn::new("rt", RollingWindow::Rows(10), Agg::Mean, "rt_mean10").start(RollingStart::Nan);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum RollingStart {
+    Partial,
+    Nan,
+}
+
+/// A column added by `CsvReader::copy_transformed`
+///
+/// # Arguments
+///
+/// * `source` - The column aggregated
+/// * `window` - The rows the aggregate covers
+/// * `agg` - The aggregate
+/// * `name` - The name of the new column
+/// * `start` - What the aggregate is before the window is full, `Partial` by default
+///
+/// # Examples
+///
+/// ```
+/// let errors = RollingColumn::new("error", RollingWindow::Cumulative, Agg::Sum, "n_errors");
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RollingColumn {
+    pub source: String,
+    pub window: RollingWindow,
+    pub agg: Agg,
+    pub name: String,
+    pub start: RollingStart,
+}
+
+/// Implementation of the RollingColumn struct
+///
+/// # Methods
+///
+/// * `new` - Creates a rolling column that aggregates partial windows
+/// * `start` - Sets what the aggregate is before the window is full
+impl RollingColumn {
+    /// Creates a rolling column that aggregates partial windows
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The column aggregated
+    /// * `window` - The rows the aggregate covers
+    /// * `agg` - The aggregate
+    /// * `name` - The name of the new column
+    ///
+    /// # Returns
+    ///
+    /// The RollingColumn
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let column = RollingColumn::new("rt", RollingWindow::Rows(10), Agg::Mean, "rt_mean10");
+    /// ```
+    ///
+    pub fn new(source: &str, window: RollingWindow, agg: Agg, name: &str) -> Self {
+        Self { source: source.to_string(), window, agg, name: name.to_string(), start: RollingStart::Partial }
+    }
+
+    /// Sets what the aggregate is before the window is full
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - `Partial` or `Nan`
+    ///
+    /// # Returns
+    ///
+    /// The RollingColumn with the new setting
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let column = RollingColumn::new("rt", RollingWindow::Rows(10), Agg::Mean, "rt_mean10").start(RollingStart::Nan);
+    /// ```
+    ///
+    pub fn start(mut self, start: RollingStart) -> Self {
+        self.start = start;
+        self
+    }
+}
+
+/// The running state of a rolling column
+pub(crate) struct RollingState {
+    column: RollingColumn,
+    source: usize,
+    key: Option<usize>,
+    /// The (position, value) of the present values in the window, where the position is the row index or the key
+    values: VecDeque<(f64, f64)>,
+    /// Monotonic deques of the values in `values` for the minimum and the maximum
+    minima: VecDeque<(f64, f64)>,
+    maxima: VecDeque<(f64, f64)>,
+    /// The sums of the values and their squares, shifted by the first value to limit cancellation
+    shift: Option<f64>,
+    sum: f64,
+    sum_squares: f64,
+    first_key: Option<f64>,
+    last_key: f64,
+    row: usize,
+}
+
+impl RollingState {
+    /// Resolves the columns of a rolling column in the headers
+    pub(crate) fn new(column: &RollingColumn, headers: &StringRecord) -> io::Result<Self> {
+        let key = match &column.window {
+            RollingWindow::Rows(0) => return Err(io::Error::new(io::ErrorKind::InvalidInput, "A rolling window must hold at least one row")),
+            RollingWindow::Span { width, .. } if !(*width > 0.0 && width.is_finite()) => {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("A rolling span must be positive, got {}", width)))
+            }
+            RollingWindow::Span { column, .. } => Some(column_index(headers, column)?),
+            _ => None,
+        };
+        Ok(Self {
+            column: column.clone(),
+            source: column_index(headers, &column.source)?,
+            key,
+            values: VecDeque::new(),
+            minima: VecDeque::new(),
+            maxima: VecDeque::new(),
+            shift: None,
+            sum: 0.0,
+            sum_squares: 0.0,
+            first_key: None,
+            last_key: f64::NEG_INFINITY,
+            row: 0,
+        })
+    }
+
+    /// Adds a record to the window and returns the aggregate as a field
+    pub(crate) fn update(&mut self, record: &StringRecord, float_format: &FloatFormat) -> io::Result<String> {
+        let line = record.position().map_or(0, |position| position.line());
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("Line {}: {}", line, message));
+        let position = match self.key {
+            Some(key) => {
+                let text = record.get(key).unwrap_or("").trim();
+                let key = text.parse::<f64>().ok().filter(|key| !key.is_nan()).ok_or_else(|| invalid(format!("'{}' is not a number", text)))?;
+                if key < self.last_key {
+                    return Err(invalid(format!("The window column decreases from {} to {}", self.last_key, key)));
+                }
+                self.last_key = key;
+                self.first_key.get_or_insert(key);
+                key
+            }
+            None => self.row as f64,
+        };
+        self.row += 1;
+
+        let text = record.get(self.source).unwrap_or("").trim();
+        if !text.is_empty() {
+            let value = parse_number(text).map_err(|error| invalid(error.to_string()))?;
+            let shifted = value - *self.shift.get_or_insert(value);
+            self.sum += shifted;
+            self.sum_squares += shifted * shifted;
+            self.values.push_back((position, value));
+            while self.minima.back().is_some_and(|&(_, minimum)| minimum > value) {
+                self.minima.pop_back();
+            }
+            self.minima.push_back((position, value));
+            while self.maxima.back().is_some_and(|&(_, maximum)| maximum < value) {
+                self.maxima.pop_back();
+            }
+            self.maxima.push_back((position, value));
+        }
+
+        let (oldest, full) = match &self.column.window {
+            RollingWindow::Rows(rows) => (position - *rows as f64, self.row >= *rows),
+            RollingWindow::Span { width, .. } => (position - width, position - self.first_key.unwrap_or(position) >= *width),
+            RollingWindow::Cumulative => (f64::NEG_INFINITY, true),
+        };
+        while let Some(&(_, value)) = self.values.front().filter(|&&(front, _)| front <= oldest) {
+            let shifted = value - self.shift.unwrap_or(0.0);
+            self.sum -= shifted;
+            self.sum_squares -= shifted * shifted;
+            self.values.pop_front();
+        }
+        while self.minima.front().is_some_and(|&(front, _)| front <= oldest) {
+            self.minima.pop_front();
+        }
+        while self.maxima.front().is_some_and(|&(front, _)| front <= oldest) {
+            self.maxima.pop_front();
+        }
+        if self.values.is_empty() {
+            // Start afresh so that the rounding errors of the removed values do not linger
+            self.shift = None;
+            self.sum = 0.0;
+            self.sum_squares = 0.0;
+        }
+
+        if !full && self.column.start == RollingStart::Nan {
+            return Ok(float_format.format(f64::NAN));
+        }
+        let count = self.values.len();
+        let mean = self.shift.unwrap_or(0.0) + self.sum / count as f64;
+        Ok(match self.column.agg {
+            Agg::Count => count.to_string(),
+            _ if count == 0 => String::new(),
+            Agg::First => float_format.format(self.values[0].1),
+            Agg::Last => float_format.format(self.values[count - 1].1),
+            Agg::Sum => float_format.format(self.shift.unwrap_or(0.0) * count as f64 + self.sum),
+            Agg::Mean => float_format.format(mean),
+            Agg::Std if count < 2 => float_format.format(f64::NAN),
+            Agg::Std => {
+                let variance = (self.sum_squares - self.sum * self.sum / count as f64) / (count - 1) as f64;
+                float_format.format(variance.max(0.0).sqrt())
+            }
+            Agg::Min => float_format.format(self.minima.front().map_or(f64::NAN, |&(_, value)| value)),
+            Agg::Max => float_format.format(self.maxima.front().map_or(f64::NAN, |&(_, value)| value)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_io::csv::{CsvReader, CsvWriter};
+    use crate::processing::random::SeededRng;
+
+    const AGGS: [Agg; 8] = [Agg::First, Agg::Last, Agg::Count, Agg::Sum, Agg::Mean, Agg::Std, Agg::Min, Agg::Max];
+
+    /// The aggregate of the values in a window, computed directly
+    fn naive(values: &[f64], agg: Agg) -> Option<f64> {
+        let n = values.len() as f64;
+        let mean = values.iter().sum::<f64>() / n;
+        match agg {
+            Agg::Count => Some(n),
+            _ if values.is_empty() => None,
+            Agg::First => Some(values[0]),
+            Agg::Last => Some(values[values.len() - 1]),
+            Agg::Sum => Some(values.iter().sum()),
+            Agg::Mean => Some(mean),
+            Agg::Std if values.len() < 2 => Some(f64::NAN),
+            Agg::Std => Some((values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / (n - 1.0)).sqrt()),
+            Agg::Min => Some(values.iter().copied().fold(f64::INFINITY, f64::min)),
+            Agg::Max => Some(values.iter().copied().fold(f64::NEG_INFINITY, f64::max)),
+        }
+    }
+
+    /// Checks every aggregate of a window over `rows` of (key, value) against the naive one
+    fn check_against_naive(rows: &[(f64, Option<f64>)], window: RollingWindow, in_window: impl Fn(usize, usize) -> bool) {
+        let headers = StringRecord::from(vec!["t", "x"]);
+        let records: Vec<StringRecord> =
+            rows.iter().map(|(key, value)| StringRecord::from(vec![key.to_string(), value.map_or(String::new(), |value| value.to_string())])).collect();
+        for agg in AGGS {
+            let mut state = RollingState::new(&RollingColumn::new("x", window.clone(), agg, "y"), &headers).unwrap();
+            for (i, record) in records.iter().enumerate() {
+                let field = state.update(record, &FloatFormat::default()).unwrap();
+                let values: Vec<f64> = (0..=i).filter(|&j| in_window(i, j)).filter_map(|j| rows[j].1).collect();
+                match naive(&values, agg) {
+                    None => assert_eq!(field, "", "{:?} at row {}", agg, i),
+                    Some(expected) if expected.is_nan() => assert_eq!(field, "NaN", "{:?} at row {}", agg, i),
+                    Some(expected) => {
+                        let value: f64 = field.parse().unwrap();
+                        assert!((value - expected).abs() <= 1e-9 * (1.0 + expected.abs()), "{:?} at row {}: {} != {}", agg, i, value, expected);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Random values around a large offset, with some missing, at times with uneven steps
+    fn fixture(n: usize) -> Vec<(f64, Option<f64>)> {
+        let mut rng = SeededRng::new(164);
+        let mut time = 0.0;
+        (0..n)
+            .map(|_| {
+                time += (rng.next_index(4) as f64) * 0.25;
+                let value = (rng.next_index(10) != 0).then(|| 1000.0 + (rng.next_gaussian() * 8.0).round() / 4.0);
+                (time, value)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn row_windows_match_a_naive_window() {
+        let rows = fixture(400);
+        for n in [1, 2, 7, 50] {
+            check_against_naive(&rows, RollingWindow::Rows(n), |i, j| j + n > i);
+        }
+        check_against_naive(&rows, RollingWindow::Cumulative, |_, _| true);
+    }
+
+    #[test]
+    fn span_windows_match_a_naive_window() {
+        let rows = fixture(400);
+        for width in [0.25, 1.0, 3.3, 20.0] {
+            check_against_naive(&rows, RollingWindow::Span { column: "t".to_string(), width }, |i, j| rows[j].0 > rows[i].0 - width);
+        }
+    }
+
+    #[test]
+    fn copy_transformed_writes_hand_computed_columns() {
+        let path = std::env::temp_dir().join(format!("neurorust-rolling-{}-trials.csv", std::process::id()));
+        let out = std::env::temp_dir().join(format!("neurorust-rolling-{}-trials-out.csv", std::process::id()));
+        std::fs::write(&path, "time_s,rt,error\n0,0.5,0\n10,0.7,1\n25,,1\n31,0.4,0\n70,0.6,1\n").unwrap();
+        let mut output = CsvWriter::create(&out).unwrap();
+        let n_rows = CsvReader::open(&path)
+            .unwrap()
+            .with_rolling_column("rt", RollingWindow::Rows(2), Agg::Mean, "rt_mean2")
+            .with_rolling(RollingColumn::new("rt", RollingWindow::Rows(2), Agg::Max, "rt_max2").start(RollingStart::Nan))
+            .with_rolling_column("rt", RollingWindow::Span { column: "time_s".to_string(), width: 30.0 }, Agg::Min, "rt_min30")
+            .with_rolling(RollingColumn::new("rt", RollingWindow::Span { column: "time_s".to_string(), width: 30.0 }, Agg::Count, "n30").start(RollingStart::Nan))
+            .with_rolling_column("error", RollingWindow::Cumulative, Agg::Sum, "n_errors")
+            .copy_transformed(&mut output)
+            .unwrap();
+        output.flush().unwrap();
+        assert_eq!(n_rows, 5);
+        assert_eq!(
+            std::fs::read_to_string(&out).unwrap(),
+            "time_s,rt,error,rt_mean2,rt_max2,rt_min30,n30,n_errors\n\
+             0,0.5,0,0.5,NaN,0.5,NaN,0\n\
+             10,0.7,1,0.6,0.7,0.5,NaN,1\n\
+             25,,1,0.7,0.7,0.5,NaN,2\n\
+             31,0.4,0,0.4,0.4,0.4,2,2\n\
+             70,0.6,1,0.5,0.6,0.6,1,3\n"
+        );
+        std::fs::remove_file(&out).unwrap();
+
+        let mut output = CsvWriter::create(&out).unwrap();
+        std::fs::write(&path, "time_s,rt\n0,1\n5,2\n4,3\n").unwrap();
+        let span = RollingWindow::Span { column: "time_s".to_string(), width: 30.0 };
+        let error = CsvReader::open(&path).unwrap().with_rolling_column("rt", span, Agg::Mean, "m").copy_transformed(&mut output).unwrap_err();
+        assert!(error.to_string().contains("Line 4: The window column decreases from 5 to 4"), "{}", error);
+        [path, out].iter().for_each(|path| std::fs::remove_file(path).unwrap());
+    }
+}
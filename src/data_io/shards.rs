@@ -0,0 +1,488 @@
+// A module to cut long recordings into aligned time shards for distributed processing
+
+// Written by Amin Alam in 2024
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use csv::StringRecord;
+use crate::data_io::compression::Compression;
+use crate::data_io::csv::{column_index, CsvReader, CsvWriter};
+use crate::data_io::dialect::CsvDialect;
+
+/// The name of the manifest written next to the shards
+pub const MANIFEST_FILE: &str = "manifest.csv";
+
+/// The options of `export_shards`
+///
+/// # Arguments
+///
+/// * `shard_duration` - The duration of each shard in seconds
+/// * `channels` - The channels to keep, in output order, or None to keep every column but the time column
+/// * `time_column` - The name of the column holding the time of each row in seconds
+/// * `compression` - How each shard is compressed, `None` by default; compressed shards are named `shard_000.csv.gz` or `shard_000.csv.zst`
+///
+/// # Examples
+///
+/// ```
+/// let options = ShardOptions { channels: Some(vec!["CA1".to_string(), "CA3".to_string()]), compression: Compression::Gzip, ..ShardOptions::new(600.0) };
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShardOptions {
+    pub shard_duration: f64,
+    pub channels: Option<Vec<String>>,
+    pub time_column: String,
+    pub compression: Compression,
+}
+
+/// One shard of the manifest
+///
+/// # Arguments
+///
+/// * `index` - The number of the shard, counted from the shard of the first row
+/// * `file` - The name of the shard file within the directory, e.g. `shard_000.csv`
+/// * `start` - The start of the time range of the shard in seconds, included
+/// * `end` - The end of the time range of the shard in seconds, excluded
+/// * `n_rows` - The number of rows of the shard after its header row
+///
+/// # Examples
+///
+/// ```
+/// for shard in read_manifest("shards/s01")? { println!("{} covers [{}, {})", shard.file, shard.start, shard.end); }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShardEntry {
+    pub index: usize,
+    pub file: String,
+    pub start: f64,
+    pub end: f64,
+    pub n_rows: usize,
+}
+
+/// What `export_shards` did
+///
+/// # Arguments
+///
+/// * `shards` - The manifest entries of all shards, in time order
+/// * `n_written` - The number of shard files written
+/// * `n_skipped` - The number of shard files kept from an earlier run
+///
+/// # Examples
+///
+/// ```
+/// let export = export_shards("s01.csv", "shards/s01", &ShardOptions::new(600.0))?;
+/// println!("{} shards, {} resumed", export.shards.len(), export.n_skipped);
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct ShardExport {
+    pub shards: Vec<ShardEntry>,
+    pub n_written: usize,
+    pub n_skipped: usize,
+}
+
+/// Implementation of the ShardOptions struct
+///
+/// # Methods
+///
+/// * `new` - Creates options keeping every uncompressed channel with a `time` time column
+impl ShardOptions {
+    /// Creates options keeping every uncompressed channel with a `time` time column
+    ///
+    /// # Arguments
+    ///
+    /// * `shard_duration` - The duration of each shard in seconds
+    ///
+    /// # Returns
+    ///
+    /// The ShardOptions
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let options = ShardOptions::new(300.0);
+    /// ```
+    ///
+    pub fn new(shard_duration: f64) -> Self {
+        Self { shard_duration, channels: None, time_column: "time".to_string(), compression: Compression::None }
+    }
+}
+
+/// Cuts a recording into shards of equal duration
+///
+/// # Arguments
+///
+/// * `input` - The path to the csv file of the recording, with one row per sample in time order
+/// * `directory` - The directory of the shards and the manifest, created if needed
+/// * `options` - The shard duration, the channels, the time column and the compression
+///
+/// # Returns
+///
+/// The ShardExport, or an error if the duration is not positive, a column is not found, a
+/// time is not a number or goes backwards, the manifest of an earlier run is invalid, or a
+/// file cannot be read or written
+///
+/// # Examples
+///
+/// ```
+/// let export = export_shards("recordings/s01.csv", "shards/s01", &ShardOptions::new(600.0))?;
+/// ```
+///
+/// # Note
+///
+/// Shard `k` covers the times from `(k0 + k) * shard_duration`, included, to
+/// `(k0 + k + 1) * shard_duration`, excluded, where `k0` numbers the multiple of the
+/// duration holding the first row, so the boundaries depend only on the duration and the
+/// first time and a second run writes identical files. A time within a nanosecond of a
+/// boundary belongs to the later shard. Shards are gapless: a stretch without rows gives
+/// a shard with a header row only. Every shard has the time column followed by the
+/// channels, with the fields copied as they are. The manifest is never compressed, and
+/// compressed shards are read back by `CsvIO::concat` and the readers of the crate.
+///
+/// The rows are streamed, one at a time, so the memory used does not depend on the length
+/// of the recording. Each shard is written to `<file>.partial` and renamed when complete,
+/// and `manifest.csv` is rewritten after every shard, so an interrupted export can be
+/// resumed by running it again: shards listed in the manifest whose files exist with the
+/// listed number of rows are kept and not written again.
+///
+pub fn export_shards<P: AsRef<Path>, Q: AsRef<Path>>(input: P, directory: Q, options: &ShardOptions) -> io::Result<ShardExport> {
+    let duration = options.shard_duration;
+    if !(duration.is_finite() && duration > 0.0) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("The shard duration must be positive, got {}", duration)));
+    }
+    let directory = directory.as_ref();
+    fs::create_dir_all(directory)?;
+    let manifest_path = directory.join(MANIFEST_FILE);
+    let previous = match manifest_path.exists() {
+        true => read_manifest(directory)?,
+        false => Vec::new(),
+    };
+
+    let mut reader = CsvReader::open(input)?;
+    let headers = reader.headers().clone();
+    let time = column_index(&headers, &options.time_column)?;
+    let columns: Vec<usize> = match &options.channels {
+        Some(channels) => channels.iter().map(|channel| column_index(&headers, channel)).collect::<io::Result<_>>()?,
+        None => (0..headers.len()).filter(|&column| column != time).collect(),
+    };
+    let header: StringRecord = std::iter::once(time).chain(columns.iter().copied()).map(|column| &headers[column]).collect();
+
+    let mut export = ShardExport::default();
+    let mut current: Option<Shard> = None;
+    let mut first_multiple: Option<i64> = None;
+    let mut last_time = f64::NEG_INFINITY;
+    let mut row = StringRecord::new();
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, |position| position.line());
+        let value = parse_field(record.get(time).unwrap_or(""), line)?;
+        if !value.is_finite() || value < last_time {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("The time {} at line {} is not finite or is before the time of the previous row", value, line),
+            ));
+        }
+        last_time = value;
+        let multiple = (value / duration + 1e-9 / duration).floor() as i64;
+        let k0 = *first_multiple.get_or_insert(multiple);
+        let index = (multiple - k0) as usize;
+        // Close the shards before this row, including any without rows
+        while current.as_ref().is_none_or(|shard| shard.entry.index != index) {
+            let next = current.as_ref().map_or(0, |shard| shard.entry.index + 1);
+            if let Some(shard) = current.take() {
+                shard.finish(&manifest_path, &mut export)?;
+            }
+            current = Some(Shard::start(directory, next, k0, options, &header, &previous)?);
+        }
+        if let Some(shard) = current.as_mut() {
+            row.clear();
+            row.push_field(record.get(time).unwrap_or(""));
+            columns.iter().for_each(|&column| row.push_field(record.get(column).unwrap_or("")));
+            shard.write(&row)?;
+        }
+    }
+    if let Some(shard) = current.take() {
+        shard.finish(&manifest_path, &mut export)?;
+    }
+    write_manifest(&manifest_path, &export.shards)?;
+    Ok(export)
+}
+
+/// Reads the manifest of a directory of shards
+///
+/// # Arguments
+///
+/// * `directory` - The directory holding `manifest.csv`
+///
+/// # Returns
+///
+/// The entries of the shards, in time order, or an error if the manifest cannot be read
+/// or a field is not valid
+///
+/// # Examples
+///
+/// ```
+/// let files: Vec<PathBuf> = read_manifest("shards/s01")?.iter().map(|shard| Path::new("shards/s01").join(&shard.file)).collect();
+/// ```
+///
+pub fn read_manifest<P: AsRef<Path>>(directory: P) -> io::Result<Vec<ShardEntry>> {
+    let mut reader = CsvReader::open(directory.as_ref().join(MANIFEST_FILE))?;
+    let headers = reader.headers().clone();
+    let columns = ["shard", "file", "start", "end", "n_rows"].map(|name| column_index(&headers, name));
+    let [index, file, start, end, n_rows] = columns;
+    let (index, file, start, end, n_rows) = (index?, file?, start?, end?, n_rows?);
+    let mut shards = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        let line = record.position().map_or(0, |position| position.line());
+        let count = |column: usize| {
+            record.get(column).unwrap_or("").trim().parse::<usize>().map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("Invalid count '{}' at line {} of the manifest", record.get(column).unwrap_or(""), line))
+            })
+        };
+        shards.push(ShardEntry {
+            index: count(index)?,
+            file: record.get(file).unwrap_or("").to_string(),
+            start: parse_field(record.get(start).unwrap_or(""), line)?,
+            end: parse_field(record.get(end).unwrap_or(""), line)?,
+            n_rows: count(n_rows)?,
+        });
+    }
+    Ok(shards)
+}
+
+/// A shard being written, or kept from an earlier run
+struct Shard {
+    entry: ShardEntry,
+    path: PathBuf,
+    /// The writer of the partial file, or None if the shard is kept
+    writer: Option<CsvWriter>,
+    /// The number of rows of the kept file
+    kept: Option<usize>,
+}
+
+impl Shard {
+    fn start(directory: &Path, index: usize, k0: i64, options: &ShardOptions, header: &StringRecord, previous: &[ShardEntry]) -> io::Result<Self> {
+        let duration = options.shard_duration;
+        let extension = match options.compression {
+            Compression::None => "csv",
+            Compression::Gzip => "csv.gz",
+            Compression::Zstd => "csv.zst",
+        };
+        let file = format!("shard_{:03}.{}", index, extension);
+        let path = directory.join(&file);
+        let entry = ShardEntry {
+            index,
+            file,
+            start: (k0 + index as i64) as f64 * duration,
+            end: (k0 + index as i64 + 1) as f64 * duration,
+            n_rows: 0,
+        };
+        let tolerance = 1e-9 * duration;
+        let kept = previous
+            .iter()
+            .find(|shard| shard.index == index && shard.file == entry.file)
+            .filter(|shard| (shard.start - entry.start).abs() <= tolerance && (shard.end - entry.end).abs() <= tolerance)
+            .filter(|shard| count_rows(&path).ok() == Some(shard.n_rows))
+            .map(|shard| shard.n_rows);
+        let writer = match kept {
+            Some(_) => None,
+            None => {
+                let dialect = CsvDialect { compression: Some(options.compression), ..CsvDialect::new() };
+                let mut writer = CsvWriter::create_with_dialect(partial_path(&path), &dialect)?;
+                writer.write_record(header)?;
+                Some(writer)
+            }
+        };
+        Ok(Self { entry, path, writer, kept })
+    }
+
+    fn write(&mut self, row: &StringRecord) -> io::Result<()> {
+        self.entry.n_rows += 1;
+        match self.writer.as_mut() {
+            Some(writer) => writer.write_record(row),
+            None => Ok(()),
+        }
+    }
+
+    /// Renames the complete shard into place and records it in the manifest
+    fn finish(self, manifest_path: &Path, export: &mut ShardExport) -> io::Result<()> {
+        match (self.writer, self.kept) {
+            (Some(writer), _) => {
+                writer.finish()?;
+                fs::rename(partial_path(&self.path), &self.path)?;
+                export.n_written += 1;
+            }
+            (None, Some(n_rows)) if n_rows != self.entry.n_rows => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "{} from an earlier run has {} rows but the input has {} in its range; remove it to write it again",
+                        self.path.display(),
+                        n_rows,
+                        self.entry.n_rows
+                    ),
+                ))
+            }
+            (None, _) => export.n_skipped += 1,
+        }
+        export.shards.push(self.entry);
+        write_manifest(manifest_path, &export.shards)
+    }
+}
+
+fn parse_field(field: &str, line: u64) -> io::Result<f64> {
+    field
+        .trim()
+        .parse()
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("'{}' at line {} is not a number", field, line)))
+}
+
+fn partial_path(path: &Path) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(".partial");
+    PathBuf::from(name)
+}
+
+/// Counts the rows of a csv file after its header row
+fn count_rows(path: &Path) -> io::Result<usize> {
+    let mut reader = CsvReader::open(path)?;
+    let mut n_rows = 0;
+    for record in reader.records() {
+        record?;
+        n_rows += 1;
+    }
+    Ok(n_rows)
+}
+
+/// Writes the manifest to a temporary file and renames it, so it is never left half written
+fn write_manifest(path: &Path, shards: &[ShardEntry]) -> io::Result<()> {
+    let partial = partial_path(path);
+    let mut writer = CsvWriter::create(&partial)?;
+    let float_format = writer.float_format();
+    writer.write_record(&StringRecord::from(vec!["shard", "file", "start", "end", "n_rows"]))?;
+    for shard in shards {
+        writer.write_record(&StringRecord::from(vec![
+            shard.index.to_string(),
+            shard.file.clone(),
+            float_format.format(shard.start),
+            float_format.format(shard.end),
+            shard.n_rows.to_string(),
+        ]))?;
+    }
+    writer.flush()?;
+    drop(writer);
+    fs::rename(partial, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use crate::data_io::csv::CsvIO;
+
+    /// Writes a recording from 0.123 s to 10 s at 1 kHz with no rows from 3 s to 5.2 s,
+    /// whose time column is not the first
+    fn recording(directory: &Path) -> PathBuf {
+        let _ = fs::remove_dir_all(directory);
+        fs::create_dir_all(directory).unwrap();
+        let path = directory.join("recording.csv");
+        let mut file = io::BufWriter::new(fs::File::create(&path).unwrap());
+        writeln!(file, "a,time,b,c").unwrap();
+        for i in (123..10_000).filter(|i| !(3000..5200).contains(i)) {
+            writeln!(file, "{},{},{},{}", i % 7, i as f64 / 1000.0, -(i as f64) * 0.25, i % 3).unwrap();
+        }
+        path
+    }
+
+    #[test]
+    fn concatenated_shards_equal_a_direct_export() {
+        let directory = std::env::temp_dir().join(format!("neurorust-shards-{}-direct", std::process::id()));
+        let input = recording(&directory);
+        let shards = directory.join("shards");
+        let options = ShardOptions { channels: Some(vec!["c".to_string(), "a".to_string()]), ..ShardOptions::new(1.0) };
+        let export = export_shards(&input, &shards, &options).unwrap();
+        assert_eq!(export.shards.len(), 10);
+        assert_eq!(export.n_written, 10);
+        assert_eq!(export.shards.iter().map(|shard| shard.n_rows).collect::<Vec<_>>(), vec![877, 1000, 1000, 0, 0, 800, 1000, 1000, 1000, 1000]);
+        for (k, shard) in export.shards.iter().enumerate() {
+            assert_eq!((shard.start, shard.end), (k as f64, k as f64 + 1.0));
+            assert_eq!(shard.file, format!("shard_{:03}.csv", k));
+        }
+        assert_eq!(read_manifest(&shards).unwrap(), export.shards);
+
+        let files: Vec<PathBuf> = export.shards.iter().map(|shard| shards.join(&shard.file)).collect();
+        let mut joined = CsvWriter::create(directory.join("joined.csv")).unwrap();
+        CsvIO::concat(&files, &mut joined).unwrap();
+        joined.flush().unwrap();
+        let mut direct = CsvWriter::create(directory.join("direct.csv")).unwrap();
+        direct.write_record(&StringRecord::from(vec!["time", "c", "a"])).unwrap();
+        let mut csv_io = CsvIO::open_read(input.to_str().unwrap()).unwrap();
+        for record in csv_io.select(&["time", "c", "a"]).unwrap() {
+            direct.write_record(&record.unwrap()).unwrap();
+        }
+        direct.flush().unwrap();
+        assert_eq!(fs::read_to_string(directory.join("joined.csv")).unwrap(), fs::read_to_string(directory.join("direct.csv")).unwrap());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn a_second_run_resumes_and_writes_identical_files() {
+        let directory = std::env::temp_dir().join(format!("neurorust-shards-{}-resume", std::process::id()));
+        let input = recording(&directory);
+        let shards = directory.join("shards");
+        let options = ShardOptions::new(2.5);
+        export_shards(&input, &shards, &options).unwrap();
+        let before = fs::read(shards.join("shard_001.csv")).unwrap();
+        let export = export_shards(&input, &shards, &options).unwrap();
+        assert_eq!((export.n_written, export.n_skipped), (0, export.shards.len()));
+
+        fs::remove_file(shards.join("shard_001.csv")).unwrap();
+        let export = export_shards(&input, &shards, &options).unwrap();
+        assert_eq!((export.n_written, export.n_skipped), (1, export.shards.len() - 1));
+        assert_eq!(fs::read(shards.join("shard_001.csv")).unwrap(), before);
+        assert!(fs::read_dir(&shards).unwrap().all(|entry| !entry.unwrap().file_name().to_string_lossy().ends_with(".partial")));
+
+        // A kept shard that no longer matches the input is an error, not a silent mix
+        let text = fs::read_to_string(&input).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        fs::write(&input, lines[..lines.len() - 5].join("\n") + "\n").unwrap();
+        assert!(export_shards(&input, &shards, &options).is_err());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn gzip_shards_hold_the_rows_of_plain_shards_and_resume() {
+        let directory = std::env::temp_dir().join(format!("neurorust-shards-{}-gzip", std::process::id()));
+        let input = recording(&directory);
+        let plain = export_shards(&input, directory.join("plain"), &ShardOptions::new(2.0)).unwrap();
+        let options = ShardOptions { compression: Compression::Gzip, ..ShardOptions::new(2.0) };
+        let gzip = export_shards(&input, directory.join("gzip"), &options).unwrap();
+        assert_eq!(gzip.shards.len(), plain.shards.len());
+        for (gzip_shard, plain_shard) in gzip.shards.iter().zip(&plain.shards) {
+            assert_eq!(gzip_shard.file, format!("{}.gz", plain_shard.file));
+            assert_eq!((gzip_shard.start, gzip_shard.end, gzip_shard.n_rows), (plain_shard.start, plain_shard.end, plain_shard.n_rows));
+            let bytes = fs::read(directory.join("gzip").join(&gzip_shard.file)).unwrap();
+            assert!(bytes.starts_with(&[0x1f, 0x8b]), "{}", gzip_shard.file);
+            let rows = |path: PathBuf| CsvReader::open(path).unwrap().read_records().unwrap();
+            assert_eq!(rows(directory.join("gzip").join(&gzip_shard.file)), rows(directory.join("plain").join(&plain_shard.file)));
+        }
+        assert!(fs::read_to_string(directory.join("gzip").join(MANIFEST_FILE)).unwrap().starts_with("shard,file,"));
+
+        let files: Vec<PathBuf> = gzip.shards.iter().map(|shard| directory.join("gzip").join(&shard.file)).collect();
+        let mut joined = CsvWriter::create(directory.join("joined.csv")).unwrap();
+        CsvIO::concat(&files, &mut joined).unwrap();
+        joined.flush().unwrap();
+        let n_rows: usize = plain.shards.iter().map(|shard| shard.n_rows).sum();
+        assert_eq!(count_rows(&directory.join("joined.csv")).unwrap(), n_rows);
+
+        let export = export_shards(&input, directory.join("gzip"), &options).unwrap();
+        assert_eq!((export.n_written, export.n_skipped), (0, gzip.shards.len()));
+        let shard = directory.join("gzip").join("shard_001.csv.gz");
+        let before = fs::read(&shard).unwrap();
+        fs::remove_file(&shard).unwrap();
+        assert_eq!(export_shards(&input, directory.join("gzip"), &options).unwrap().n_written, 1);
+        assert_eq!(fs::read(&shard).unwrap(), before);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,346 @@
+// A module to sort the records of csv files larger than memory
+
+// Written by Amin Alam in 2024
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
+use csv::{Reader, ReaderBuilder, StringRecord, Writer, WriterBuilder};
+use crate::core::memory::{record_bytes, MemoryBudget};
+use crate::data_io::csv::{column_index, CsvReader, CsvWriter};
+
+/// The largest number of sorted runs merged at once, to bound the number of open files
+const MAX_MERGE_RUNS: usize = 64;
+
+/// Numbers the sorts of the process, so that concurrent sorts never share a temporary file
+static SORT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// What `CsvReader::sort` did
+///
+/// # Arguments
+///
+/// * `n_rows` - The number of rows written after the header row
+/// * `spilled_runs` - The number of sorted runs written to temporary files, 0 if the records fit in the budget
+///
+/// # Examples
+///
+/// ```
+/// let summary = reader.sort(&["subject", "time"], false, &mut output)?;
+/// if summary.spilled_runs > 0 { println!("Spilled {} runs", summary.spilled_runs); }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SortSummary {
+    pub n_rows: usize,
+    pub spilled_runs: usize,
+}
+
+/// A record with its sort key, ordered so that a `BinaryHeap` pops the smallest first
+struct Keyed {
+    key: Vec<Key>,
+    record: StringRecord,
+    /// The run the record was read from, which keeps records with equal keys in file order
+    run: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Key {
+    Text(String),
+    Number(f64),
+}
+
+impl Key {
+    fn cmp(&self, other: &Key) -> Ordering {
+        match (self, other) {
+            (Key::Number(a), Key::Number(b)) => a.total_cmp(b),
+            (Key::Text(a), Key::Text(b)) => a.cmp(b),
+            (Key::Number(_), Key::Text(_)) => Ordering::Less,
+            (Key::Text(_), Key::Number(_)) => Ordering::Greater,
+        }
+    }
+}
+
+fn compare(a: &[Key], b: &[Key]) -> Ordering {
+    a.iter().zip(b).map(|(a, b)| a.cmp(b)).find(|ordering| ordering.is_ne()).unwrap_or(Ordering::Equal)
+}
+
+impl PartialEq for Keyed {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Keyed {}
+
+impl PartialOrd for Keyed {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Keyed {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(&other.key, &self.key).then(other.run.cmp(&self.run))
+    }
+}
+
+/// The temporary files of a sort, removed when it ends, also on error
+struct Runs {
+    directory: PathBuf,
+    sort: usize,
+    paths: Vec<PathBuf>,
+    created: usize,
+}
+
+impl Runs {
+    fn create(&mut self) -> io::Result<(PathBuf, Writer<BufWriter<File>>)> {
+        let path = self.directory.join(format!("neurorust-sort-{}-{}-{}.csv", std::process::id(), self.sort, self.created));
+        self.created += 1;
+        let file = fs::OpenOptions::new().write(true).create_new(true).open(&path)?;
+        self.paths.push(path.clone());
+        Ok((path, WriterBuilder::new().has_headers(false).from_writer(BufWriter::new(file))))
+    }
+}
+
+impl Drop for Runs {
+    fn drop(&mut self) {
+        for path in &self.paths {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Sorts the remaining records by some columns, spilling sorted runs to disk beyond the budget
+pub(crate) fn sort_records(reader: &mut CsvReader, columns: &[&str], numeric: bool, output: &mut CsvWriter, budget: &MemoryBudget) -> io::Result<SortSummary> {
+    let headers = reader.headers().clone();
+    if columns.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Sorting needs at least one column"));
+    }
+    let key_columns = columns.iter().map(|column| column_index(&headers, column)).collect::<io::Result<Vec<usize>>>()?;
+    let key_of = |record: &StringRecord| -> Vec<Key> {
+        key_columns
+            .iter()
+            .map(|&column| {
+                let field = record.get(column).unwrap_or("");
+                match numeric {
+                    true => field.trim().parse().map_or_else(|_| Key::Text(field.to_string()), Key::Number),
+                    false => Key::Text(field.to_string()),
+                }
+            })
+            .collect()
+    };
+    let limit = budget.limit.unwrap_or(usize::MAX);
+    let mut runs = Runs { directory: budget.spill_dir(), sort: SORT_COUNTER.fetch_add(1, AtomicOrdering::Relaxed), paths: Vec::new(), created: 0 };
+    let mut spilled: Vec<PathBuf> = Vec::new();
+    let mut buffer: Vec<(Vec<Key>, StringRecord)> = Vec::new();
+    let mut buffered = 0usize;
+    let mut n_rows = 0;
+    for record in reader.records() {
+        let record = record?;
+        let key = key_of(&record);
+        let bytes = 2 * record_bytes(&record);
+        if buffered + bytes > limit && !buffer.is_empty() {
+            spilled.push(spill(&mut runs, &mut buffer)?);
+            buffered = 0;
+        }
+        buffered += bytes;
+        buffer.push((key, record));
+        n_rows += 1;
+    }
+
+    output.write_record(&headers)?;
+    if spilled.is_empty() {
+        buffer.sort_by(|a, b| compare(&a.0, &b.0));
+        for (_, record) in &buffer {
+            output.write_record(record)?;
+        }
+        return Ok(SortSummary { n_rows, spilled_runs: 0 });
+    }
+    if !buffer.is_empty() {
+        spilled.push(spill(&mut runs, &mut buffer)?);
+    }
+    let spilled_runs = spilled.len();
+    // Merge the oldest runs first, so that earlier runs always come first among equal keys
+    while spilled.len() > MAX_MERGE_RUNS {
+        let (path, mut writer) = runs.create()?;
+        merge(&spilled[..MAX_MERGE_RUNS], &key_of, |record| writer.write_record(record).map_err(io::Error::from))?;
+        writer.flush()?;
+        spilled.splice(..MAX_MERGE_RUNS, [path]);
+    }
+    merge(&spilled, &key_of, |record| output.write_record(record))?;
+    Ok(SortSummary { n_rows, spilled_runs })
+}
+
+/// Sorts the buffered records and writes them to a new run
+fn spill(runs: &mut Runs, buffer: &mut Vec<(Vec<Key>, StringRecord)>) -> io::Result<PathBuf> {
+    buffer.sort_by(|a, b| compare(&a.0, &b.0));
+    let (path, mut writer) = runs.create()?;
+    for (_, record) in buffer.drain(..) {
+        writer.write_record(&record)?;
+    }
+    writer.flush()?;
+    Ok(path)
+}
+
+/// Merges sorted runs, in order, into one sorted stream of records
+fn merge<K, W>(paths: &[PathBuf], key_of: &K, mut write: W) -> io::Result<()>
+where
+    K: Fn(&StringRecord) -> Vec<Key>,
+    W: FnMut(&StringRecord) -> io::Result<()>,
+{
+    let mut readers: Vec<Reader<BufReader<File>>> = paths
+        .iter()
+        .map(|path| Ok(ReaderBuilder::new().has_headers(false).flexible(true).from_reader(BufReader::new(File::open(path)?))))
+        .collect::<io::Result<_>>()?;
+    let mut heap = BinaryHeap::with_capacity(readers.len());
+    for (run, reader) in readers.iter_mut().enumerate() {
+        let mut record = StringRecord::new();
+        if reader.read_record(&mut record)? {
+            heap.push(Keyed { key: key_of(&record), record, run });
+        }
+    }
+    while let Some(Keyed { mut record, run, .. }) = heap.pop() {
+        write(&record)?;
+        if readers[run].read_record(&mut record)? {
+            heap.push(Keyed { key: key_of(&record), record, run });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::path::Path;
+    use crate::processing::random::SeededRng;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("neurorust-sort-test-{}-{}", std::process::id(), name))
+    }
+
+    /// Writes rows with repeated subjects and times, quoted notes and a row number to check stability
+    fn write_unsorted(path: &Path, n_rows: usize, seed: u64) {
+        let mut rng = SeededRng::new(seed);
+        let mut file = io::BufWriter::new(File::create(path).unwrap());
+        writeln!(file, "row,subject,time,note").unwrap();
+        for row in 0..n_rows {
+            let subject = ["s10", "s2", "s1", "control"][rng.next_index(4)];
+            let time = match rng.next_index(10) {
+                0 => "n/a".to_string(),
+                1 => String::new(),
+                _ => format!("{}", rng.next_index(200) as f64 * 0.5 - 20.0),
+            };
+            let note = if row % 7 == 0 { "\"said \"\"hi\"\", then left\"".to_string() } else { format!("note {}", row) };
+            writeln!(file, "{},{},{},{}", row, subject, time, note).unwrap();
+        }
+    }
+
+    fn sorted_with(input: &Path, name: &str, columns: &[&str], numeric: bool, budget: MemoryBudget) -> (String, SortSummary) {
+        let output = temp_path(name);
+        let mut writer = CsvWriter::create(&output).unwrap();
+        let summary = CsvReader::open(input).unwrap().with_memory_budget(budget).sort(columns, numeric, &mut writer).unwrap();
+        writer.flush().unwrap();
+        let text = fs::read_to_string(&output).unwrap();
+        fs::remove_file(&output).unwrap();
+        (text, summary)
+    }
+
+    /// A stable sort of the rows in memory, the reference for the spilled sorts
+    fn reference(input: &Path, columns: &[usize], numeric: bool) -> Vec<StringRecord> {
+        let mut reader = ReaderBuilder::new().from_path(input).unwrap();
+        let mut records: Vec<StringRecord> = reader.records().map(Result::unwrap).collect();
+        let key = |record: &StringRecord| -> Vec<Key> {
+            columns
+                .iter()
+                .map(|&column| match (numeric, record[column].trim().parse::<f64>()) {
+                    (true, Ok(number)) => Key::Number(number),
+                    _ => Key::Text(record[column].to_string()),
+                })
+                .collect()
+        };
+        records.sort_by(|a, b| compare(&key(a), &key(b)));
+        records
+    }
+
+    fn parse(text: &str) -> Vec<StringRecord> {
+        ReaderBuilder::new().from_reader(text.as_bytes()).records().map(Result::unwrap).collect()
+    }
+
+    #[test]
+    fn spilled_sorts_match_the_in_memory_sort() {
+        let input = temp_path("unsorted.csv");
+        write_unsorted(&input, 5000, 179);
+        for (columns, key_columns, numeric) in [(vec!["subject", "time"], vec![1, 2], true), (vec!["time"], vec![2], false), (vec!["note"], vec![3], true)] {
+            let (in_memory, summary) = sorted_with(&input, "memory.csv", &columns, numeric, MemoryBudget::unlimited());
+            assert_eq!(summary, SortSummary { n_rows: 5000, spilled_runs: 0 });
+            assert!(in_memory.starts_with("row,subject,time,note\n"));
+            assert_eq!(parse(&in_memory), reference(&input, &key_columns, numeric), "{:?}", columns);
+            for limit in [64 << 10, 4 << 10] {
+                let (spilled, summary) = sorted_with(&input, "spilled.csv", &columns, numeric, MemoryBudget::new(limit));
+                assert!(summary.spilled_runs > 1, "{} bytes gave {} runs", limit, summary.spilled_runs);
+                assert_eq!(spilled, in_memory, "{:?} with {} bytes", columns, limit);
+            }
+        }
+        fs::remove_file(&input).unwrap();
+    }
+
+    #[test]
+    fn more_runs_than_can_be_merged_at_once_are_merged_in_stages() {
+        let input = temp_path("many_runs.csv");
+        write_unsorted(&input, 300, 1790);
+        let (in_memory, _) = sorted_with(&input, "many_memory.csv", &["subject"], false, MemoryBudget::unlimited());
+        // A budget of one byte puts every record in a run of its own
+        let (spilled, summary) = sorted_with(&input, "many_spilled.csv", &["subject"], false, MemoryBudget::new(1));
+        assert_eq!(summary, SortSummary { n_rows: 300, spilled_runs: 300 });
+        assert!(summary.spilled_runs > MAX_MERGE_RUNS);
+        assert_eq!(spilled, in_memory);
+        // Equal subjects keep the order of the input
+        let records = parse(&spilled);
+        for pair in records.windows(2).filter(|pair| pair[0][1] == pair[1][1]) {
+            assert!(pair[0][0].parse::<usize>().unwrap() < pair[1][0].parse::<usize>().unwrap());
+        }
+        fs::remove_file(&input).unwrap();
+    }
+
+    #[test]
+    fn runs_go_to_the_spill_directory_and_are_removed() {
+        let input = temp_path("spill_dir.csv");
+        write_unsorted(&input, 500, 17);
+        let directory = temp_path("spill_dir");
+        fs::create_dir_all(&directory).unwrap();
+        let budget = MemoryBudget::new(2 << 10).with_spill_dir(&directory);
+        let output = temp_path("spill_dir_sorted.csv");
+        let mut writer = CsvWriter::create(&output).unwrap();
+        let mut reader = CsvReader::open(&input).unwrap().with_memory_budget(budget);
+        let summary = reader.sort(&["time"], true, &mut writer).unwrap();
+        assert!(summary.spilled_runs > 1);
+        assert_eq!(fs::read_dir(&directory).unwrap().count(), 0);
+
+        let missing = MemoryBudget::new(1).with_spill_dir(directory.join("absent"));
+        let mut writer = CsvWriter::create(&output).unwrap();
+        let error = CsvReader::open(&input).unwrap().with_memory_budget(missing).sort(&["time"], true, &mut writer).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::NotFound);
+        fs::remove_dir(&directory).unwrap();
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(&output).unwrap();
+    }
+
+    #[test]
+    fn numbers_sort_before_text_and_bad_columns_are_rejected() {
+        let input = temp_path("mixed.csv");
+        fs::write(&input, "key\n10\nabc\n-2\n\"\"\n9.5\n2\n").unwrap();
+        let (numeric, _) = sorted_with(&input, "mixed_numeric.csv", &["key"], true, MemoryBudget::unlimited());
+        assert_eq!(numeric, "key\n-2\n2\n9.5\n10\n\"\"\nabc\n");
+        let (text, _) = sorted_with(&input, "mixed_text.csv", &["key"], false, MemoryBudget::new(1));
+        assert_eq!(text, "key\n\"\"\n-2\n10\n2\n9.5\nabc\n");
+
+        let mut writer = CsvWriter::create(temp_path("mixed_out.csv")).unwrap();
+        assert_eq!(CsvReader::open(&input).unwrap().sort(&[], true, &mut writer).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        assert!(CsvReader::open(&input).unwrap().sort(&["nope"], true, &mut writer).is_err());
+        fs::remove_file(&input).unwrap();
+        fs::remove_file(temp_path("mixed_out.csv")).unwrap();
+    }
+}
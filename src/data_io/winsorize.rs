@@ -0,0 +1,352 @@
+// A module to cap the extreme values of csv columns at their quantiles
+
+// Written by Amin Alam in 2024
+
+use std::io;
+use csv::StringRecord;
+use crate::data_io::csv::{column_index, CsvReader, CsvWriter};
+use crate::processing::robust::select_quantile;
+use crate::processing::streaming::{StreamingStats, DEFAULT_COMPRESSION};
+
+/// The suffix of the flag columns written by `winsorize_columns`
+pub const WINSORIZED_SUFFIX: &str = "_winsorized";
+
+/// The options of `winsorize_columns`
+///
+/// # Arguments
+///
+/// * `exact` - Whether the quantiles are computed exactly from all values held in memory, rather than sketched
+/// * `flag_columns` - Whether a `<column>_winsorized` column of `true` or `false` is appended for every column
+/// * `compression` - The compression of the quantile sketch, unused when `exact` is set
+///
+/// # Examples
+///
+/// ```
+/// let options = WinsorizeOptions { flag_columns: true, ..WinsorizeOptions::new() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WinsorizeOptions {
+    pub exact: bool,
+    pub flag_columns: bool,
+    pub compression: f64,
+}
+
+/// What `winsorize_columns` did to one column
+///
+/// # Arguments
+///
+/// * `column` - The name of the column
+/// * `lower` - The lower quantile the values were capped at, NaN if the column holds no number
+/// * `upper` - The upper quantile the values were capped at, NaN if the column holds no number
+/// * `n_lower` - The number of values below `lower` that were raised to it
+/// * `n_upper` - The number of values above `upper` that were lowered to it
+/// * `n_missing` - The number of fields that are not finite numbers, copied unchanged
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColumnWinsorization {
+    pub column: String,
+    pub lower: f64,
+    pub upper: f64,
+    pub n_lower: usize,
+    pub n_upper: usize,
+    pub n_missing: usize,
+}
+
+/// What `winsorize_columns` did
+///
+/// # Arguments
+///
+/// * `columns` - The ColumnWinsorization of every winsorized column, in the order given
+/// * `n_rows` - The number of rows written after the header row
+///
+/// # Examples
+///
+/// ```
+/// let report = csv_io.winsorize_columns(&["reaction_time"], 0.01, 0.99, &mut output, &WinsorizeOptions::new())?;
+/// report.to_csv(&mut CsvWriter::create("winsorized_counts.csv")?)?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct WinsorizeReport {
+    pub columns: Vec<ColumnWinsorization>,
+    pub n_rows: usize,
+}
+
+/// Implementation of the WinsorizeOptions struct
+///
+/// # Methods
+///
+/// * `new` - Creates options sketching the quantiles without flag columns
+impl WinsorizeOptions {
+    /// Creates options sketching the quantiles without flag columns
+    ///
+    /// # Returns
+    ///
+    /// The WinsorizeOptions, with a sketch of `DEFAULT_COMPRESSION`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let options = WinsorizeOptions { exact: true, ..WinsorizeOptions::new() };
+    /// ```
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl Default for WinsorizeOptions {
+    fn default() -> Self {
+        Self { exact: false, flag_columns: false, compression: DEFAULT_COMPRESSION }
+    }
+}
+
+/// Implementation of the WinsorizeReport struct
+///
+/// # Methods
+///
+/// * `column` - Returns what was done to a column
+/// * `to_csv` - Writes one `column,lower,upper,n_lower,n_upper,n_missing` row per column
+impl WinsorizeReport {
+    /// Returns what was done to a column
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the column
+    ///
+    /// # Returns
+    ///
+    /// The ColumnWinsorization of the column, or None if it was not winsorized
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let capped = report.column("reaction_time").map_or(0, |column| column.n_lower + column.n_upper);
+    /// ```
+    ///
+    pub fn column(&self, name: &str) -> Option<&ColumnWinsorization> {
+        self.columns.iter().find(|column| column.column == name)
+    }
+
+    /// Writes one `column,lower,upper,n_lower,n_upper,n_missing` row per column
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - The writer of the csv file
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if the rows cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// report.to_csv(&mut CsvWriter::create("winsorized_counts.csv")?)?;
+    /// ```
+    ///
+    pub fn to_csv(&self, output: &mut CsvWriter) -> io::Result<()> {
+        output.write_record(&StringRecord::from(vec!["column", "lower", "upper", "n_lower", "n_upper", "n_missing"]))?;
+        for column in &self.columns {
+            output.write_record(&StringRecord::from(vec![
+                column.column.clone(),
+                output.format_float(column.lower),
+                output.format_float(column.upper),
+                column.n_lower.to_string(),
+                column.n_upper.to_string(),
+                column.n_missing.to_string(),
+            ]))?;
+        }
+        Ok(())
+    }
+}
+
+/// Copies the remaining records with the values of some columns capped at their quantiles
+pub(crate) fn winsorize(reader: &mut CsvReader, columns: &[&str], lower_q: f64, upper_q: f64, output: &mut CsvWriter, options: &WinsorizeOptions) -> io::Result<WinsorizeReport> {
+    if lower_q.is_nan() || upper_q.is_nan() || lower_q < 0.0 || upper_q > 1.0 || lower_q >= upper_q {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("The quantiles must satisfy 0 <= lower < upper <= 1, got {} and {}", lower_q, upper_q)));
+    }
+    if let Some((i, name)) = columns.iter().enumerate().find(|(i, name)| columns[..*i].contains(name)) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Column '{}' is listed twice, at positions {} and {}", name, columns.iter().position(|column| column == name).unwrap_or(0), i)));
+    }
+    let indices = columns.iter().map(|name| column_index(reader.headers(), name)).collect::<io::Result<Vec<usize>>>()?;
+    let mut headers = reader.headers().clone();
+    if options.flag_columns {
+        for name in columns {
+            let flag = format!("{}{}", name, WINSORIZED_SUFFIX);
+            if headers.iter().any(|header| header == flag) {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("Column '{}' already exists", flag)));
+            }
+            headers.push_field(&flag);
+        }
+    }
+    let bounds = if options.exact { exact_bounds(reader, &indices, lower_q, upper_q)? } else { sketched_bounds(reader, &indices, lower_q, upper_q, options.compression)? };
+
+    let mut report = WinsorizeReport {
+        columns: columns
+            .iter()
+            .zip(&bounds)
+            .map(|(name, &(lower, upper))| ColumnWinsorization { column: name.to_string(), lower, upper, n_lower: 0, n_upper: 0, n_missing: 0 })
+            .collect(),
+        n_rows: 0,
+    };
+    output.write_record(&headers)?;
+    let mut row = StringRecord::new();
+    let mut flags = vec![false; indices.len()];
+    for record in reader.records() {
+        let record = record?;
+        row.clear();
+        flags.iter_mut().for_each(|flag| *flag = false);
+        for (column, field) in record.iter().enumerate() {
+            let Some(i) = indices.iter().position(|&index| index == column) else {
+                row.push_field(field);
+                continue;
+            };
+            let summary = &mut report.columns[i];
+            match parse_finite(field) {
+                None => {
+                    summary.n_missing += 1;
+                    row.push_field(field);
+                }
+                Some(value) if value < summary.lower => {
+                    summary.n_lower += 1;
+                    flags[i] = true;
+                    row.push_field(&output.format_float(summary.lower));
+                }
+                Some(value) if value > summary.upper => {
+                    summary.n_upper += 1;
+                    flags[i] = true;
+                    row.push_field(&output.format_float(summary.upper));
+                }
+                Some(_) => row.push_field(field),
+            }
+        }
+        if options.flag_columns {
+            flags.iter().for_each(|&flag| row.push_field(if flag { "true" } else { "false" }));
+        }
+        output.write_record(&row)?;
+        report.n_rows += 1;
+    }
+    Ok(report)
+}
+
+/// Parses a field as a finite number
+fn parse_finite(field: &str) -> Option<f64> {
+    field.trim().parse::<f64>().ok().filter(|value| value.is_finite())
+}
+
+/// Sketches the quantiles of the columns over a first pass of another reader
+fn sketched_bounds(reader: &CsvReader, indices: &[usize], lower_q: f64, upper_q: f64, compression: f64) -> io::Result<Vec<(f64, f64)>> {
+    let template = StreamingStats::new().with_compression(compression).map_err(|error| io::Error::new(io::ErrorKind::InvalidInput, error.to_string()))?;
+    let mut stats = vec![template; indices.len()];
+    for record in reader.try_clone_at_position()?.records() {
+        let record = record?;
+        for (stats, &index) in stats.iter_mut().zip(indices) {
+            if let Some(value) = record.get(index).and_then(parse_finite) {
+                stats.update(&[value]);
+            }
+        }
+    }
+    Ok(stats.iter().map(|stats| (stats.quantile(lower_q), stats.quantile(upper_q))).collect())
+}
+
+/// Computes the exact quantiles of the columns over a first pass of another reader, within the memory budget of the reader
+fn exact_bounds(reader: &CsvReader, indices: &[usize], lower_q: f64, upper_q: f64) -> io::Result<Vec<(f64, f64)>> {
+    let budget = reader.memory_budget();
+    let mut values: Vec<Vec<f64>> = vec![Vec::new(); indices.len()];
+    let mut needed = 0usize;
+    for record in reader.try_clone_at_position()?.records() {
+        let record = record?;
+        for (values, &index) in values.iter_mut().zip(indices) {
+            if let Some(value) = record.get(index).and_then(parse_finite) {
+                needed += size_of::<f64>();
+                budget.check(needed)?;
+                values.push(value);
+            }
+        }
+    }
+    Ok(values.iter_mut().map(|values| (select_quantile(values, lower_q), select_quantile(values, upper_q))).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use crate::data_io::float_format::FloatFormat;
+    use crate::processing::random::SeededRng;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("neurorust-winsorize-{}-{}", std::process::id(), name))
+    }
+
+    /// Winsorizes a csv text and returns the report and the written text
+    fn run(name: &str, input: &str, columns: &[&str], lower_q: f64, upper_q: f64, options: &WinsorizeOptions) -> io::Result<(WinsorizeReport, String)> {
+        let (input_path, output_path) = (temp_path(&format!("{}-in.csv", name)), temp_path(&format!("{}-out.csv", name)));
+        std::fs::write(&input_path, input)?;
+        let mut output = CsvWriter::create(&output_path)?;
+        let report = CsvReader::open(&input_path)?.winsorize_columns(columns, lower_q, upper_q, &mut output, options);
+        output.flush()?;
+        let written = std::fs::read_to_string(&output_path)?;
+        std::fs::remove_file(input_path)?;
+        std::fs::remove_file(output_path)?;
+        Ok((report?, written))
+    }
+
+    #[test]
+    fn counts_the_capped_values_and_leaves_the_others_untouched() {
+        let mut input = String::from("trial,rt,label\n");
+        for k in 1..=100 {
+            // The values are written with a trailing zero so that an untouched field keeps it
+            input.push_str(&format!("{},{}.0,a\n", k, (k * 37) % 100 + 1));
+        }
+        input.push_str("101,NaN,b\n102,,b\n");
+        let options = WinsorizeOptions { exact: true, flag_columns: true, ..WinsorizeOptions::new() };
+        let (report, written) = run("counts", &input, &["rt"], 0.05, 0.95, &options).unwrap();
+        let rt = report.column("rt").unwrap();
+        // NumPy's linear quantiles of 1..=100 at 0.05 and 0.95
+        assert!((rt.lower - 5.95).abs() < 1e-12 && (rt.upper - 95.05).abs() < 1e-12, "{:?}", rt);
+        assert_eq!((rt.n_lower, rt.n_upper, rt.n_missing, report.n_rows), (5, 5, 2, 102));
+
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines[0], "trial,rt,label,rt_winsorized");
+        for (line, k) in lines[1..=100].iter().zip(1..) {
+            let value = (k * 37) % 100 + 1;
+            let expected = if value < 6 {
+                format!("{},{},a,true", k, FloatFormat::default().format(rt.lower))
+            } else if value > 95 {
+                format!("{},{},a,true", k, FloatFormat::default().format(rt.upper))
+            } else {
+                format!("{},{}.0,a,false", k, value)
+            };
+            assert_eq!(*line, expected);
+        }
+        assert_eq!(&lines[101..], ["101,NaN,b,false", "102,,b,false"]);
+    }
+
+    #[test]
+    fn sketched_quantiles_agree_with_exact_ones() {
+        let mut rng = SeededRng::new(189);
+        let mut input = String::from("x,y\n");
+        for _ in 0..20_000 {
+            input.push_str(&format!("{},{}\n", rng.next_gaussian(), rng.next_f64() * 10.0));
+        }
+        let (exact, _) = run("exact", &input, &["x", "y"], 0.01, 0.99, &WinsorizeOptions { exact: true, ..WinsorizeOptions::new() }).unwrap();
+        let (sketched, _) = run("sketched", &input, &["x", "y"], 0.01, 0.99, &WinsorizeOptions::new()).unwrap();
+        for (exact, sketched) in exact.columns.iter().zip(&sketched.columns) {
+            let spread = exact.upper - exact.lower;
+            assert!((exact.lower - sketched.lower).abs() < 5e-3 * spread, "{:?} {:?}", exact, sketched);
+            assert!((exact.upper - sketched.upper).abs() < 5e-3 * spread, "{:?} {:?}", exact, sketched);
+            // 1% of 20000 values lie beyond each exact quantile, and a rank error of 0.05% in the sketch
+            assert_eq!((exact.n_lower, exact.n_upper), (200, 200));
+            assert!(sketched.n_lower.abs_diff(200) <= 10 && sketched.n_upper.abs_diff(200) <= 10, "{:?}", sketched);
+        }
+    }
+
+    #[test]
+    fn repeated_columns_and_bad_quantiles_are_rejected() {
+        let input = "rt,acc\n1,0\n2,1\n";
+        let error = run("repeated", input, &["rt", "acc", "rt"], 0.1, 0.9, &WinsorizeOptions { flag_columns: true, ..WinsorizeOptions::new() }).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("'rt' is listed twice"), "{}", error);
+        for (lower_q, upper_q) in [(0.5, 0.5), (-0.1, 0.9), (0.1, f64::NAN)] {
+            assert_eq!(run("quantiles", input, &["rt"], lower_q, upper_q, &WinsorizeOptions::new()).unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        }
+    }
+}
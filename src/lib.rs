@@ -1,6 +1,106 @@
 // Module declarations for the library
+pub mod core;
 pub mod data_io;
+pub mod processing;
+#[cfg(feature = "cli")]
+pub mod cli;
 
 
 // Re-exporting items from submodules to create a unified public API
-pub use data_io::csv::CsvIO;
\ No newline at end of file
+pub use crate::core::epochs::{EdgeTrials, Epochs};
+pub use crate::core::events::Events;
+pub use crate::core::memory::{matrix_bytes, MemoryBudget, MemoryBudgetExceeded};
+pub use crate::core::recording::{Recording, RecordingChannel, PROBE_KEY, SEAMS_KEY};
+pub use crate::core::sample::Sample;
+pub use crate::core::session::SessionInfo;
+pub use crate::core::spike_train::SpikeTrain;
+pub use crate::core::timeseries::TimeSeries;
+pub use data_io::aliases::{AppliedAlias, ColumnAliases, ColumnMapping, DEFAULT_ALIASES};
+pub use data_io::bids::{epochs_to_bids, events_from_bids, events_to_bids, evoked_to_bids, BidsEntities, BidsEvent, BidsSidecar, SoftwareFilter};
+pub use data_io::calibration::{Calibration, CalibrationReport, ChannelCalibration, CALIBRATION_KEY, UNIT_KEY_PREFIX};
+pub use data_io::categorical::{CategoricalMapping, CodeOrder, ValueCounts, OTHER_LABEL};
+pub use data_io::compression::Compression;
+#[cfg(feature = "zstd")]
+pub use data_io::compression::{SeekableZstd, SeekableZstdWriter, MAX_SEEKABLE_FRAME};
+pub use data_io::convert::{ConversionJob, InputFormat, JobResult, JobStatus, OutputFormat, OverwritePolicy};
+pub use data_io::csv::{Agg, CsvIO, CsvReader, CsvWriter, OpenMode, RowIndex};
+pub use data_io::dataset::{EntryOutcome, EntryStatus, Manifest, ManifestEntry, ManifestError, ManifestResults};
+pub use data_io::detect::{detect_format, open_any, DataSource, DetectedFormat};
+pub use data_io::dialect::{CsvDialect, CsvIOBuilder, UNNAMED_COLUMN_PREFIX};
+pub use data_io::error::DataIoError;
+pub use data_io::float_format::{FloatFormat, FloatNotation};
+pub use data_io::logger::{ColumnType, CsvLogger, CsvSchema, LogSummary, LoggerOptions, Rotation, SchemaColumn};
+pub use data_io::preview::{Preview, PreviewOptions, PreviewReport};
+pub use data_io::pseudonym::{PseudonymKey, PseudonymLookup, MAX_DATE_SHIFT_DAYS};
+pub use data_io::query::CsvQuery;
+pub use data_io::records::{CsvRecordChunks, CsvRecords, IntoCsvRecords};
+pub use data_io::rolling::{RollingColumn, RollingStart, RollingWindow};
+pub use data_io::shards::{export_shards, read_manifest, ShardEntry, ShardExport, ShardOptions};
+pub use data_io::sort::SortSummary;
+pub use data_io::winsorize::{ColumnWinsorization, WinsorizeOptions, WinsorizeReport, WINSORIZED_SUFFIX};
+pub use data_io::fixed_width::{sniff_spec, FieldSpec, FieldType, FixedWidthIO, FixedWidthSpec, FixedWidthWriter};
+#[cfg(feature = "serde")]
+pub use data_io::cache::{Persist, SCHEMA_VERSION};
+#[cfg(feature = "http")]
+pub use data_io::http::{download, HttpOptions, HttpReader};
+pub use data_io::plot::{display_decimate, trace_to_plot_csv, Colormap, PlotOptions};
+#[cfg(feature = "plot")]
+pub use data_io::plot::plot_trace_svg;
+#[cfg(feature = "polars")]
+pub use data_io::dataframe::{events_from_dataframe, events_to_dataframe, recording_to_dataframe, spike_trains_from_dataframe, spike_trains_to_dataframe};
+pub use processing::artifacts::{merge_spans, reject_epochs, ArtifactCriterion, ArtifactKind, ArtifactSpan};
+pub use processing::autoreject::{auto_reject, AutoRejectOptions, AutoRejectResult, TrialDecision};
+pub use processing::bad_channels::{detect_bad_channels, BadChannel, BadChannelCriterion, BadChannelFlag, BadChannelOptions, BadChannelReport};
+pub use processing::bursts::{burst_rate, fraction_spikes_in_bursts, mean_burst_duration, Burst, BurstMethod, LogIsiOptions, MaxIntervalOptions};
+pub use processing::channel_interpolation::{interpolate_channels, ChannelInterpolation, ChannelInterpolationOptions, InterpolatedChannels};
+pub use processing::channel_math::{derive_channel, derive_channel_with, elementwise, scalar, BinaryOp, ChannelContext, ChannelExpr, ChannelFunction, DerivedChannel};
+pub use processing::cleanline::{remove_line_noise_clean, CleanLineOptions};
+pub use processing::cluster::{kmeans, KMeansOptions, KMeansResult};
+pub use processing::concat::{concatenate, ConcatenatedRecording, ConcatenatedView, GapPolicy, PartPlacement, RecordingPart, SessionLayout};
+pub use processing::connectivity::{band_phase_locking, plv, plv_epochs, plv_matrix, ppc, ppc_epochs, PhaseLockingMatrix, PhaseLockingOptions, PhaseMeasure};
+pub use processing::correlogram::{Correlogram, CorrelogramNormalization};
+pub use processing::decomposition::{fast_ica, pca, IcaOptions, IcaResult, PcaResult};
+pub use processing::detrend::{baseline_correct, detrend, DetrendMethod};
+pub use processing::evoked::{average, average_by_label, EvokedResponse};
+pub use processing::erp_measures::{measure_trials, ComponentMeasure, ComponentRow, ComponentStatus, ComponentTable, PeakMeasure};
+pub use processing::export::{trial_table, Behavior, TrialJoin, TrialRecording, TrialSpikes, TrialTableOptions, TrialTableSummary};
+pub use processing::features::{sliding, Feature, FeatureTable, PartialWindow, SlidingFeatures};
+pub use processing::hilbert::{analytic, envelope, instantaneous_frequency, instantaneous_phase};
+pub use processing::histogram::Histogram;
+pub use processing::interpolate::{interpolate_gaps, GapReport, InterpMethod};
+pub use processing::normalize::{minmax_scale, robust_scale, zscore, NormalizationMethod, Normalizer, ZeroVariance};
+pub use processing::pac::{modulation_index, pac, surrogate_test, PacOptions, PacResult, SurrogateTest};
+pub use processing::parallel::{chunk_map, chunk_map_records, ChunkError, ChunkView, RecordChunk};
+pub use processing::peaks::{find_peaks, Peak, PeakOptions};
+pub use processing::checkpoint::{Checkpoint, InputIdentity};
+pub use processing::pipeline::{Pipeline, PipelineError, PipelineOutput, PipelineSummary, Progress, SignalSource, Sink, Stage, ZeroPhaseFilter};
+pub use processing::psth::Psth;
+pub use processing::qc::{generate_report, ChannelQc, FileQc, QcFormat, QcOptions, QcReport, QcStatus, QcThreshold, QcTiming};
+pub use processing::rate::{binned_rate, kernel_rate, FiringRate, Kernel};
+pub use processing::reference::{drop_bad_channels, rereference, Reference};
+pub use processing::resample::{decimate, resample, ResampleMethod};
+pub use processing::robust::{mad, median, nan_mad, nan_median, nan_quantile, nan_trimmed_mean, nan_winsorize, quantile, robust_stats, trimmed_mean, winsorize, RobustStatsTable, RobustSummary, MAD_CONSISTENCY};
+pub use processing::sleep::{epoch_features, EpochFeatures, Hypnogram, StageTransitions, STANDARD_RATIOS};
+pub use processing::smooth::{savgol_coefficients, smooth, smooth_channels, EdgeMode, SmoothMethod};
+pub use processing::spectral::{
+    band_power, band_power_table, band_power_trials, coherence, coherence_matrix, fft_spectrum, parameterize, spectrogram, welch, AperiodicMode,
+    BandPowerMethod, Coherence, CoherenceMatrix, FrequencyBand, ParameterizeError, ParameterizeOptions, SpectralModel, SpectralPeak, Spectrogram,
+    Spectrum, SpectrumOptions,
+};
+pub use processing::spike_stats::{cv_isi, fano_factor, isi, isi_histogram, refractory_violations};
+pub use processing::spikes::{noise_estimate, split_by_labels, Polarity, SpikeDetectionOptions, SpikeDetectionResult, Threshold};
+pub use processing::sta::{ChanceControl, TriggeredAverage, TriggeredOptions};
+pub use processing::stability::{assess, rate_stability, units_to_csv, ChannelStability, RateStabilityOptions, StabilityOptions, StabilityReport, UnitStability};
+pub use processing::stim_artifact::{blank, template_subtract, BlankFill, StimRepair, TemplateOptions};
+pub use processing::stimulus::{event_signal, EventSignalKind, EventSignalOptions, OutOfRange, SampleRounding};
+pub use processing::streaming::{groups_to_csv, GroupStats, StatsTable, StreamingStats};
+pub use processing::sync::{ClockMapping, ClockModel, SyncOptions};
+pub use processing::synth::{Component, SyntheticRecording};
+pub use processing::timing::{validate_timing, TimingPolicy, TimingReport};
+pub use processing::triggers::{decode, read_trigger_labels, words_from_samples, TriggerEvent, TriggerMode, TriggerOptions, TriggerPolarity};
+pub use processing::wavelet::{cwt, Cycles, CwtOptions, TimeFrequency};
+pub use processing::xcorr::{align, cross_correlate, CorrelationResult};
+pub use processing::convolution::{convolve, ConvMode};
+pub use processing::error::ProcessingError;
+pub use processing::filter::{butterworth, fir_design, notch, remove_line_noise, FilterKind, FirFilter, IirFilter};
+pub use processing::window::Window;
\ No newline at end of file
@@ -1,3 +1,14 @@
+// The entry point of the neurorust command-line tool
+
+// Written by Amin Alam in 2024
+
+#[cfg(feature = "cli")]
+fn main() -> std::process::ExitCode {
+    neurorust::cli::run()
+}
+
+#[cfg(not(feature = "cli"))]
 fn main() {
-    println!("Hello, world!");
+    eprintln!("neurorust was built without the `cli` feature; rebuild it with `--features cli`");
+    std::process::exit(2);
 }
@@ -0,0 +1,577 @@
+// A module to detect amplitude, gradient and flatline artifacts in recordings
+
+// Written by Amin Alam in 2024
+
+use std::collections::{BTreeMap, VecDeque};
+use std::convert::Infallible;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+use crate::processing::parallel::{chunk_map, ChunkError};
+use crate::processing::robust::{nan_mad, nan_median};
+
+/// The number of samples per channel in the core of each chunk processed by `detect`
+const DETECTION_CHUNK: usize = 1 << 16;
+
+/// A rule that marks samples as artifactual
+///
+/// # Arguments
+///
+/// * `Amplitude` - Marks samples whose absolute value exceeds the threshold
+/// * `PeakToPeak` - Marks sliding windows of `window` seconds whose maximum minus minimum exceeds `threshold`
+/// * `Gradient` - Marks pairs of consecutive samples that differ by more than the threshold
+/// * `Flatline` - Marks runs of at least `min_duration` seconds whose consecutive samples differ by at most `tolerance`
+///
+/// # Examples
+///
+/// ```
+/// let criteria = [
+///     ArtifactCriterion::PeakToPeak { threshold: 150e-6, window: 0.2 },
+///     ArtifactCriterion::Gradient(50e-6),
+///     ArtifactCriterion::Flatline { tolerance: 1e-9, min_duration: 0.5 },
+/// ];
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ArtifactCriterion {
+    Amplitude(f64),
+    PeakToPeak { threshold: f64, window: f64 },
+    Gradient(f64),
+    Flatline { tolerance: f64, min_duration: f64 },
+}
+
+/// Implementation of the ArtifactCriterion enum
+///
+/// # Methods
+///
+/// * `robust_amplitude` - Creates an amplitude criterion from the spread of clean-looking data
+/// * `robust_gradient` - Creates a gradient criterion from the spread of the sample-to-sample steps
+impl ArtifactCriterion {
+    /// Creates an amplitude criterion from the spread of clean-looking data
+    ///
+    /// # Arguments
+    ///
+    /// * `samples` - Samples of the signal, e.g. a baseline recording, NaN for missing ones
+    /// * `n_mads` - The number of scaled median absolute deviations beyond the median allowed
+    ///
+    /// # Returns
+    ///
+    /// `Amplitude(|median| + n_mads * mad)`, or an error if `n_mads` is not positive or no sample is present
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let criterion = ArtifactCriterion::robust_amplitude(&baseline, 6.0)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The median absolute deviation is scaled to match the standard deviation of normal
+    /// data, so `n_mads` reads like a number of standard deviations, but the threshold is
+    /// not inflated by the artifacts it is meant to find.
+    ///
+    pub fn robust_amplitude(samples: &[f64], n_mads: f64) -> Result<Self, ProcessingError> {
+        Ok(ArtifactCriterion::Amplitude(robust_threshold(samples, n_mads)?))
+    }
+
+    /// Creates a gradient criterion from the spread of the sample-to-sample steps
+    ///
+    /// # Arguments
+    ///
+    /// * `samples` - Samples of the signal, NaN for missing ones
+    /// * `n_mads` - The number of scaled median absolute deviations of the steps allowed
+    ///
+    /// # Returns
+    ///
+    /// `Gradient(|median step| + n_mads * mad of the steps)`, or an error if `n_mads` is not
+    /// positive or fewer than two consecutive samples are present
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let criterion = ArtifactCriterion::robust_gradient(&baseline, 8.0)?;
+    /// ```
+    ///
+    pub fn robust_gradient(samples: &[f64], n_mads: f64) -> Result<Self, ProcessingError> {
+        let steps: Vec<f64> = samples.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        Ok(ArtifactCriterion::Gradient(robust_threshold(&steps, n_mads)?))
+    }
+}
+
+/// The label of an artifact, naming the criterion that found it
+///
+/// # Arguments
+///
+/// * `Amplitude` - Found by `ArtifactCriterion::Amplitude`
+/// * `PeakToPeak` - Found by `ArtifactCriterion::PeakToPeak`
+/// * `Gradient` - Found by `ArtifactCriterion::Gradient`
+/// * `Flatline` - Found by `ArtifactCriterion::Flatline`
+/// * `Stimulation` - Repaired around a stimulation event by `stim_artifact::blank` or `stim_artifact::template_subtract`
+/// * `Seam` - A junction of two parts of a session joined by `concat::concatenate`, or the samples filled between them
+///
+/// # Examples
+///
+/// ```
+/// let jumps = spans.iter().filter(|span| span.kind == ArtifactKind::Gradient).count();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ArtifactKind {
+    Amplitude,
+    PeakToPeak,
+    Gradient,
+    Flatline,
+    Stimulation,
+    Seam,
+}
+
+/// A stretch of one channel marked by one criterion
+///
+/// # Arguments
+///
+/// * `start` - The time of the first marked sample in seconds
+/// * `end` - The time just after the last marked sample in seconds, i.e. one sample period later
+/// * `kind` - The criterion that marked the span
+/// * `channel` - The index of the channel that triggered the criterion
+///
+/// # Examples
+///
+/// ```
+/// for span in detect(&channels, 1000.0, 0.0, &criteria)? {
+///     println!("{:?} on channel {} from {} to {} s", span.kind, span.channel, span.start, span.end);
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArtifactSpan {
+    pub start: f64,
+    pub end: f64,
+    pub kind: ArtifactKind,
+    pub channel: usize,
+}
+
+/// The outcome of rejecting epochs that overlap artifacts
+///
+/// # Arguments
+///
+/// * `kept` - The indices of the epochs free of artifacts
+/// * `rejected` - The indices of the epochs that overlap at least one artifact
+/// * `removed_per_kind` - The number of rejected epochs overlapping each kind of artifact
+///
+/// # Examples
+///
+/// ```
+/// let rejection = reject_epochs(&spans, &epoch_windows);
+/// println!("{} of {} trials kept", rejection.kept.len(), epoch_windows.len());
+/// ```
+///
+/// # Note
+///
+/// An epoch overlapping several kinds of artifacts is counted once for each kind, so the
+/// counts can add up to more than the number of rejected epochs
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArtifactRejection {
+    pub kept: Vec<usize>,
+    pub rejected: Vec<usize>,
+    pub removed_per_kind: BTreeMap<ArtifactKind, usize>,
+}
+
+/// Detects artifacts in a set of channels
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel; pass a single channel for one signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample in seconds
+/// * `criteria` - The rules to apply to every channel
+///
+/// # Returns
+///
+/// The spans marked by each criterion on each channel, sorted by start time, or an error if
+/// the sampling rate or a criterion parameter is invalid or the channels differ in length
+///
+/// # Examples
+///
+/// ```
+/// let spans = detect(&channels, 500.0, 0.0, &[ArtifactCriterion::Amplitude(100e-6)])?;
+/// let bad_stretches = merge_spans(&spans, 0.1);
+/// ```
+///
+/// # Note
+///
+/// NaN samples never trigger a criterion. Overlapping windows of `PeakToPeak` are joined
+/// into one span. The recording is split in time into chunks processed in parallel, each
+/// with the context of the longest window on both sides, so the spans are the same as if
+/// the whole recording were processed at once.
+///
+pub fn detect(
+    channels: &[Vec<f64>],
+    sampling_rate: f64,
+    start_time: f64,
+    criteria: &[ArtifactCriterion],
+) -> Result<Vec<ArtifactSpan>, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    let windows = criteria
+        .iter()
+        .map(|criterion| validate_criterion(criterion, sampling_rate))
+        .collect::<Result<Vec<usize>, ProcessingError>>()?;
+
+    // Each chunk marks its core exactly as the whole signal would, since no criterion looks
+    // further than its window from a sample
+    let overlap = windows.iter().copied().max().unwrap_or(1);
+    let chunk = DETECTION_CHUNK.max(4 * overlap);
+    let per_chunk = chunk_map(channels, sampling_rate, chunk as f64 / sampling_rate, overlap as f64 / sampling_rate, |view| {
+        let start = view.offset + view.core.start;
+        Ok::<_, Infallible>(
+            view.channels
+                .iter()
+                .map(|samples| {
+                    criteria
+                        .iter()
+                        .zip(&windows)
+                        .map(|(criterion, &window)| {
+                            let marked = mark(samples, criterion, window);
+                            runs(view.trim(&marked)).into_iter().map(|(first, last)| (start + first, start + last)).collect()
+                        })
+                        .collect::<Vec<Vec<(usize, usize)>>>()
+                })
+                .collect::<Vec<_>>(),
+        )
+    })
+    .map_err(|error| match error {
+        ChunkError::Setup(error) => error,
+        error => ProcessingError::InvalidParameter(error.to_string()),
+    })?;
+
+    // Join the runs that continue across the boundary of two chunks
+    let mut per_channel: Vec<Vec<Vec<(usize, usize)>>> = vec![vec![Vec::new(); criteria.len()]; channels.len()];
+    for chunk in per_chunk {
+        for (joined, chunk_runs) in per_channel.iter_mut().flatten().zip(chunk.into_iter().flatten()) {
+            for (first, last) in chunk_runs {
+                match joined.last_mut() {
+                    Some(previous) if previous.1 == first => previous.1 = last,
+                    _ => joined.push((first, last)),
+                }
+            }
+        }
+    }
+
+    let mut spans: Vec<ArtifactSpan> = per_channel
+        .into_iter()
+        .enumerate()
+        .flat_map(|(channel, per_criterion)| {
+            criteria.iter().zip(per_criterion).flat_map(move |(criterion, runs)| {
+                runs.into_iter().map(move |(first, last)| ArtifactSpan {
+                    start: start_time + first as f64 / sampling_rate,
+                    end: start_time + last as f64 / sampling_rate,
+                    kind: criterion_kind(criterion),
+                    channel,
+                })
+            })
+        })
+        .collect();
+    spans.sort_by(|a, b| a.start.total_cmp(&b.start).then(a.channel.cmp(&b.channel)));
+    Ok(spans)
+}
+
+/// Merges artifact spans from all channels and criteria into disjoint intervals
+///
+/// # Arguments
+///
+/// * `spans` - The spans to merge, in any order
+/// * `min_gap` - The spans separated by at most this many seconds are joined
+///
+/// # Returns
+///
+/// The sorted start and end times of the merged intervals
+///
+/// # Examples
+///
+/// ```
+/// // Treat artifacts closer than 100 ms as one contaminated stretch
+/// let bad_stretches = merge_spans(&spans, 0.1);
+/// ```
+///
+pub fn merge_spans(spans: &[ArtifactSpan], min_gap: f64) -> Vec<(f64, f64)> {
+    let mut intervals: Vec<(f64, f64)> = spans.iter().map(|span| (span.start, span.end)).collect();
+    intervals.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut merged: Vec<(f64, f64)> = Vec::with_capacity(intervals.len());
+    for (start, end) in intervals {
+        match merged.last_mut() {
+            Some(last) if start <= last.1 + min_gap.max(0.0) => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+/// Rejects the epochs that overlap any artifact span
+///
+/// # Arguments
+///
+/// * `spans` - The detected artifact spans
+/// * `epochs` - The start and end time of each epoch in seconds
+///
+/// # Returns
+///
+/// The kept and rejected epoch indices and the number of rejections per kind of artifact
+///
+/// # Examples
+///
+/// ```
+/// let windows: Vec<(f64, f64)> = onsets.iter().map(|onset| (onset - 0.2, onset + 0.8)).collect();
+/// let rejection = reject_epochs(&spans, &windows);
+/// ```
+///
+/// # Note
+///
+/// An epoch and a span overlap if they share any time, touching ends do not count
+///
+pub fn reject_epochs(spans: &[ArtifactSpan], epochs: &[(f64, f64)]) -> ArtifactRejection {
+    let mut rejection = ArtifactRejection::default();
+    for (index, &(epoch_start, epoch_end)) in epochs.iter().enumerate() {
+        let mut kinds: Vec<ArtifactKind> = spans
+            .iter()
+            .filter(|span| span.start < epoch_end && span.end > epoch_start)
+            .map(|span| span.kind)
+            .collect();
+        if kinds.is_empty() {
+            rejection.kept.push(index);
+            continue;
+        }
+        kinds.sort_unstable();
+        kinds.dedup();
+        for kind in kinds {
+            *rejection.removed_per_kind.entry(kind).or_insert(0) += 1;
+        }
+        rejection.rejected.push(index);
+    }
+    rejection
+}
+
+/// Returns the label of the spans found by a criterion
+fn criterion_kind(criterion: &ArtifactCriterion) -> ArtifactKind {
+    match criterion {
+        ArtifactCriterion::Amplitude(_) => ArtifactKind::Amplitude,
+        ArtifactCriterion::PeakToPeak { .. } => ArtifactKind::PeakToPeak,
+        ArtifactCriterion::Gradient(_) => ArtifactKind::Gradient,
+        ArtifactCriterion::Flatline { .. } => ArtifactKind::Flatline,
+    }
+}
+
+/// Checks the parameters of a criterion and returns its window length in samples
+fn validate_criterion(criterion: &ArtifactCriterion, sampling_rate: f64) -> Result<usize, ProcessingError> {
+    let (threshold, duration) = match *criterion {
+        ArtifactCriterion::Amplitude(threshold) | ArtifactCriterion::Gradient(threshold) => (threshold, None),
+        ArtifactCriterion::PeakToPeak { threshold, window } => (threshold, Some(window)),
+        ArtifactCriterion::Flatline { tolerance, min_duration } => (tolerance, Some(min_duration)),
+    };
+    if !(threshold >= 0.0 && threshold.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Threshold of {:?} must be a non-negative number",
+            criterion
+        )));
+    }
+    match duration {
+        None => Ok(1),
+        Some(duration) => {
+            let samples = (duration * sampling_rate).round();
+            if samples >= 2.0 && duration.is_finite() {
+                Ok(samples as usize)
+            } else {
+                Err(ProcessingError::InvalidParameter(format!(
+                    "Duration of {:?} must span at least two samples",
+                    criterion
+                )))
+            }
+        }
+    }
+}
+
+/// Marks the samples of one channel that violate a criterion
+fn mark(samples: &[f64], criterion: &ArtifactCriterion, window: usize) -> Vec<bool> {
+    let n = samples.len();
+    let mut marked = vec![false; n];
+    match *criterion {
+        ArtifactCriterion::Amplitude(threshold) => {
+            for (flag, sample) in marked.iter_mut().zip(samples) {
+                *flag = sample.abs() > threshold;
+            }
+        }
+        ArtifactCriterion::Gradient(threshold) => {
+            for i in 1..n {
+                if (samples[i] - samples[i - 1]).abs() > threshold {
+                    marked[i - 1] = true;
+                    marked[i] = true;
+                }
+            }
+        }
+        ArtifactCriterion::PeakToPeak { threshold, .. } => {
+            // Sliding maximum and minimum over monotonic deques of indices
+            let mut maxima: VecDeque<usize> = VecDeque::new();
+            let mut minima: VecDeque<usize> = VecDeque::new();
+            let mut marked_until = 0;
+            for i in 0..n {
+                if samples[i].is_nan() {
+                    continue;
+                }
+                while maxima.back().is_some_and(|&j| samples[j] <= samples[i]) {
+                    maxima.pop_back();
+                }
+                while minima.back().is_some_and(|&j| samples[j] >= samples[i]) {
+                    minima.pop_back();
+                }
+                maxima.push_back(i);
+                minima.push_back(i);
+                if i + 1 < window {
+                    continue;
+                }
+                let first = i + 1 - window;
+                while maxima.front().is_some_and(|&j| j < first) {
+                    maxima.pop_front();
+                }
+                while minima.front().is_some_and(|&j| j < first) {
+                    minima.pop_front();
+                }
+                if let (Some(&max), Some(&min)) = (maxima.front(), minima.front()) {
+                    if samples[max] - samples[min] > threshold {
+                        for flag in &mut marked[first.max(marked_until)..=i] {
+                            *flag = true;
+                        }
+                        marked_until = i + 1;
+                    }
+                }
+            }
+        }
+        ArtifactCriterion::Flatline { tolerance, .. } => {
+            let mut run_start = 0;
+            for i in 1..=n {
+                let continues = i < n && (samples[i] - samples[i - 1]).abs() <= tolerance;
+                if !continues {
+                    if i - run_start >= window {
+                        for flag in &mut marked[run_start..i] {
+                            *flag = true;
+                        }
+                    }
+                    run_start = i;
+                }
+            }
+        }
+    }
+    marked
+}
+
+/// Returns the half-open index ranges of consecutive marked samples
+fn runs(marked: &[bool]) -> Vec<(usize, usize)> {
+    let mut runs = Vec::new();
+    let mut start = None;
+    for (i, &flag) in marked.iter().enumerate() {
+        match (flag, start) {
+            (true, None) => start = Some(i),
+            (false, Some(first)) => {
+                runs.push((first, i));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(first) = start {
+        runs.push((first, marked.len()));
+    }
+    runs
+}
+
+/// Returns the absolute median plus `n_mads` scaled median absolute deviations of the values present
+fn robust_threshold(values: &[f64], n_mads: f64) -> Result<f64, ProcessingError> {
+    if !(n_mads > 0.0 && n_mads.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!("The number of deviations must be positive, got {}", n_mads)));
+    }
+    let centre = nan_median(values);
+    if centre.is_nan() {
+        return Err(ProcessingError::InvalidParameter("No sample to derive a threshold from".to_string()));
+    }
+    Ok(centre.abs() + n_mads * nan_mad(values, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    /// The spans found by marking every channel whole, one after the other
+    fn sequential(channels: &[Vec<f64>], sampling_rate: f64, criteria: &[ArtifactCriterion]) -> Vec<ArtifactSpan> {
+        let mut spans = Vec::new();
+        for (channel, samples) in channels.iter().enumerate() {
+            for criterion in criteria {
+                let window = validate_criterion(criterion, sampling_rate).unwrap();
+                for (first, last) in runs(&mark(samples, criterion, window)) {
+                    spans.push(ArtifactSpan {
+                        start: first as f64 / sampling_rate,
+                        end: last as f64 / sampling_rate,
+                        kind: criterion_kind(criterion),
+                        channel,
+                    });
+                }
+            }
+        }
+        spans.sort_by(|a, b| a.start.total_cmp(&b.start).then(a.channel.cmp(&b.channel)));
+        spans
+    }
+
+    #[test]
+    fn chunked_detection_matches_the_whole_signal() {
+        let mut rng = SeededRng::new(7);
+        let n = 3 * DETECTION_CHUNK + 1234;
+        let mut channels: Vec<Vec<f64>> = (0..3).map(|_| (0..n).map(|_| rng.next_gaussian()).collect()).collect();
+        for channel in &mut channels {
+            for _ in 0..40 {
+                let at = rng.next_index(n);
+                channel[at] += 12.0 * (rng.next_f64() - 0.5);
+            }
+            channel[n / 2] = f64::NAN;
+        }
+        // Artifacts that straddle the first and second chunk boundaries
+        let boundary = DETECTION_CHUNK;
+        channels[0][boundary - 1] = 9.0;
+        channels[0][boundary] = -9.0;
+        for sample in &mut channels[1][boundary - 300..boundary + 400] {
+            *sample = 0.25;
+        }
+        for sample in &mut channels[2][2 * boundary - 2000..2 * boundary + 3000] {
+            *sample = 1.0;
+        }
+        channels[2][2 * boundary + 10] = 1.0 + 1e-12;
+
+        let sampling_rate = 1000.0;
+        let criteria = [
+            ArtifactCriterion::Amplitude(4.0),
+            ArtifactCriterion::PeakToPeak { threshold: 9.0, window: 0.2 },
+            ArtifactCriterion::Gradient(6.0),
+            ArtifactCriterion::Flatline { tolerance: 1e-9, min_duration: 0.5 },
+        ];
+        let spans = detect(&channels, sampling_rate, 0.0, &criteria).unwrap();
+        assert_eq!(spans, sequential(&channels, sampling_rate, &criteria));
+        assert!(spans.iter().any(|span| span.kind == ArtifactKind::Flatline && span.channel == 1));
+        assert!(spans.iter().any(|span| span.kind == ArtifactKind::Flatline && span.channel == 2 && span.end - span.start == 5.0));
+        assert!(spans.iter().any(|span| span.kind == ArtifactKind::Gradient && span.start < 65.536 && span.end > 65.536));
+    }
+
+    #[test]
+    fn channels_of_different_lengths_are_rejected() {
+        let channels = vec![vec![0.0; 10], vec![0.0; 9]];
+        assert!(detect(&channels, 100.0, 0.0, &[ArtifactCriterion::Amplitude(1.0)]).is_err());
+    }
+
+    #[test]
+    fn robust_thresholds_ignore_the_artifacts_they_are_set_against() {
+        let mut rng = SeededRng::new(173);
+        let mut samples: Vec<f64> = (0..2000).map(|_| rng.next_gaussian()).collect();
+        samples.iter_mut().skip(100).step_by(10).for_each(|sample| *sample = 1e4);
+        let ArtifactCriterion::Amplitude(threshold) = ArtifactCriterion::robust_amplitude(&samples, 5.0).unwrap() else { unreachable!() };
+        assert!(threshold > 4.0 && threshold < 7.0, "{}", threshold);
+        let ArtifactCriterion::Gradient(threshold) = ArtifactCriterion::robust_gradient(&[0.0, 1.0, 3.0, 4.0, f64::NAN, 5.0], 2.0).unwrap() else { unreachable!() };
+        assert_eq!(threshold, 1.0);
+        assert!(ArtifactCriterion::robust_amplitude(&samples, 0.0).is_err());
+        assert!(ArtifactCriterion::robust_amplitude(&[f64::NAN; 4], 3.0).is_err());
+    }
+}
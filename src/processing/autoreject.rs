@@ -0,0 +1,486 @@
+// A module to learn per-channel peak-to-peak rejection thresholds of epochs from the data
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::random::SeededRng;
+use crate::processing::timing::median;
+
+/// The options of `auto_reject`
+///
+/// # Arguments
+///
+/// * `n_thresholds` - The number of candidate thresholds tried per channel, 30 by default
+/// * `n_folds` - The number of cross-validation folds, 5 by default
+/// * `max_bad_channels` - The number of channels above their threshold beyond which a trial is rejected, 2 by default
+/// * `seed` - The seed of the shuffling of the trials into folds
+///
+/// # Examples
+///
+/// ```
+/// let options = AutoRejectOptions { max_bad_channels: 4, seed: 7, ..AutoRejectOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AutoRejectOptions {
+    pub n_thresholds: usize,
+    pub n_folds: usize,
+    pub max_bad_channels: usize,
+    pub seed: u64,
+}
+
+impl Default for AutoRejectOptions {
+    fn default() -> Self {
+        Self { n_thresholds: 30, n_folds: 5, max_bad_channels: 2, seed: 0 }
+    }
+}
+
+/// What `auto_reject` decided for a trial
+///
+/// # Arguments
+///
+/// * `Keep` - Every channel is within its threshold
+/// * `Interpolate` - Some channels exceed their threshold, but no more than `max_bad_channels`, so they can be interpolated within the trial
+/// * `Reject` - More than `max_bad_channels` channels exceed their threshold
+///
+/// # Examples
+///
+/// ```
+/// let n_repaired = result.decisions.iter().filter(|&&decision| decision == TrialDecision::Interpolate).count();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TrialDecision {
+    Keep,
+    Interpolate,
+    Reject,
+}
+
+/// Implementation of the TrialDecision enum
+///
+/// # Methods
+///
+/// * `name` - Returns the lowercase name written to csv files
+impl TrialDecision {
+    /// Returns the lowercase name written to csv files
+    ///
+    /// # Returns
+    ///
+    /// `keep`, `interpolate` or `reject`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// println!("{}", TrialDecision::Reject.name());
+    /// ```
+    ///
+    pub fn name(&self) -> &'static str {
+        match self {
+            TrialDecision::Keep => "keep",
+            TrialDecision::Interpolate => "interpolate",
+            TrialDecision::Reject => "reject",
+        }
+    }
+}
+
+/// The thresholds learned by `auto_reject` and the decision for every trial
+///
+/// # Arguments
+///
+/// * `names` - The name of each channel
+/// * `thresholds` - The peak-to-peak threshold chosen for each channel
+/// * `cv_errors` - The cross-validated error of each channel at its chosen threshold
+/// * `peak_to_peak` - The peak-to-peak amplitude of each trial and channel, indexed as `peak_to_peak[trial][channel]`
+/// * `decisions` - The decision for each trial
+/// * `bad_channels` - The indices of the channels above their threshold in each trial
+///
+/// # Examples
+///
+/// ```
+/// let result = auto_reject(&trials, &names, &AutoRejectOptions::default())?;
+/// let clean: Vec<&Vec<Vec<f64>>> = result.kept().iter().map(|&trial| &trials[trial]).collect();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AutoRejectResult {
+    pub names: Vec<String>,
+    pub thresholds: Vec<f64>,
+    pub cv_errors: Vec<f64>,
+    pub peak_to_peak: Vec<Vec<f64>>,
+    pub decisions: Vec<TrialDecision>,
+    pub bad_channels: Vec<Vec<usize>>,
+}
+
+/// Implementation of the AutoRejectResult struct
+///
+/// # Methods
+///
+/// * `count` - Counts the trials with a decision
+/// * `kept` - Returns the indices of the trials that are not rejected
+/// * `rejected` - Returns the indices of the rejected trials
+/// * `to_csv` - Writes the decision for each trial as `trial,decision,n_bad_channels,bad_channels` rows
+/// * `thresholds_to_csv` - Writes the threshold of each channel as `channel,threshold,cv_error,n_exceeding` rows
+impl AutoRejectResult {
+    /// Counts the trials with a decision
+    ///
+    /// # Arguments
+    ///
+    /// * `decision` - The decision counted
+    ///
+    /// # Returns
+    ///
+    /// The number of trials with that decision
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// println!("{} trials rejected", result.count(TrialDecision::Reject));
+    /// ```
+    ///
+    pub fn count(&self, decision: TrialDecision) -> usize {
+        self.decisions.iter().filter(|&&other| other == decision).count()
+    }
+
+    /// Returns the indices of the trials that are not rejected
+    ///
+    /// # Returns
+    ///
+    /// The trials kept as they are or with interpolated channels, in order
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let kept = result.kept();
+    /// ```
+    ///
+    pub fn kept(&self) -> Vec<usize> {
+        (0..self.decisions.len()).filter(|&trial| self.decisions[trial] != TrialDecision::Reject).collect()
+    }
+
+    /// Returns the indices of the rejected trials
+    ///
+    /// # Returns
+    ///
+    /// The rejected trials, in order
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let rejected = result.rejected();
+    /// ```
+    ///
+    pub fn rejected(&self) -> Vec<usize> {
+        (0..self.decisions.len()).filter(|&trial| self.decisions[trial] == TrialDecision::Reject).collect()
+    }
+
+    /// Writes the decision for each trial as `trial,decision,n_bad_channels,bad_channels` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// result.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. The names of the bad channels are joined by `;`. The
+    /// rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        csv_io.write_record(StringRecord::from(vec!["trial", "decision", "n_bad_channels", "bad_channels"]))?;
+        for (trial, (decision, bad)) in self.decisions.iter().zip(&self.bad_channels).enumerate() {
+            let names: Vec<&str> = bad.iter().map(|&channel| self.names[channel].as_str()).collect();
+            csv_io.write_record(StringRecord::from(vec![trial.to_string(), decision.name().to_string(), bad.len().to_string(), names.join(";")]))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the threshold of each channel as `channel,threshold,cv_error,n_exceeding` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// result.thresholds_to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. `n_exceeding` counts the trials in which the channel
+    /// is above its threshold. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn thresholds_to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["channel", "threshold", "cv_error", "n_exceeding"]))?;
+        for (channel, name) in self.names.iter().enumerate() {
+            let n_exceeding = self.bad_channels.iter().filter(|bad| bad.contains(&channel)).count();
+            csv_io.write_record(StringRecord::from(vec![
+                name.clone(),
+                float_format.format(self.thresholds[channel]),
+                float_format.format(self.cv_errors[channel]),
+                n_exceeding.to_string(),
+            ]))?;
+        }
+        Ok(())
+    }
+}
+
+/// Learns a peak-to-peak rejection threshold per channel and decides which trials to keep, repair or reject
+///
+/// # Arguments
+///
+/// * `trials` - The samples of each trial, indexed as `trials[trial][channel][time]`
+/// * `names` - The name of each channel
+/// * `options` - The number of candidate thresholds and folds, the consensus limit and the seed
+///
+/// # Returns
+///
+/// The AutoRejectResult, or an error if there are fewer trials than folds, fewer than two
+/// folds or no candidate threshold, the trials differ in their number of channels or
+/// samples, or a sample is not finite
+///
+/// # Examples
+///
+/// ```
+/// let result = auto_reject(&trials, &names, &AutoRejectOptions::default())?;
+/// println!("{} kept, {} repaired, {} rejected", result.count(TrialDecision::Keep), result.count(TrialDecision::Interpolate), result.count(TrialDecision::Reject));
+/// ```
+///
+/// # Note
+///
+/// The candidate thresholds of a channel are its peak-to-peak amplitudes at evenly spaced
+/// ranks, from the median up to the largest. For every candidate, the trials are split into
+/// `n_folds` folds after a seeded shuffle, and each fold is predicted by the mean of the
+/// other trials whose peak-to-peak amplitude is within the candidate: the error is the root
+/// mean square difference to the median of the held-out fold, which artifacts barely move,
+/// averaged over the folds. Each channel gets the candidate with the lowest error, so that
+/// a threshold too low averages too few trials and one too high lets artifacts into the
+/// mean. A trial is then rejected if more than `max_bad_channels` channels exceed their
+/// threshold, and otherwise those channels are marked for interpolation, e.g. with
+/// `interpolate_channels`. The result is the same for the same seed.
+///
+pub fn auto_reject(trials: &[Vec<Vec<f64>>], names: &[String], options: &AutoRejectOptions) -> Result<AutoRejectResult, ProcessingError> {
+    if options.n_folds < 2 || options.n_thresholds == 0 || trials.len() < options.n_folds {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Need at least two folds, one threshold and as many trials as folds, got {} folds, {} thresholds and {} trials",
+            options.n_folds,
+            options.n_thresholds,
+            trials.len()
+        )));
+    }
+    let n_samples = trials[0].first().map_or(0, Vec::len);
+    if n_samples == 0 || trials.iter().any(|trial| trial.len() != names.len() || trial.iter().any(|channel| channel.len() != n_samples)) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Every trial must have {} channels of the same, nonzero number of samples",
+            names.len()
+        )));
+    }
+    if trials.iter().flatten().flatten().any(|sample| !sample.is_finite()) {
+        return Err(ProcessingError::InvalidParameter("The trials must not hold NaN or infinite samples".to_string()));
+    }
+
+    let peak_to_peak: Vec<Vec<f64>> = trials
+        .iter()
+        .map(|trial| {
+            trial
+                .iter()
+                .map(|channel| {
+                    let (min, max) = channel.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &sample| (min.min(sample), max.max(sample)));
+                    max - min
+                })
+                .collect()
+        })
+        .collect();
+
+    // The same folds serve every channel
+    let mut order: Vec<usize> = (0..trials.len()).collect();
+    let mut rng = SeededRng::new(options.seed);
+    for i in (1..order.len()).rev() {
+        order.swap(i, rng.next_index(i + 1));
+    }
+    let folds: Vec<Vec<usize>> = (0..options.n_folds).map(|fold| order.iter().copied().skip(fold).step_by(options.n_folds).collect()).collect();
+
+    let mut thresholds = Vec::with_capacity(names.len());
+    let mut cv_errors = Vec::with_capacity(names.len());
+    for channel in 0..names.len() {
+        let amplitudes: Vec<f64> = peak_to_peak.iter().map(|trial| trial[channel]).collect();
+        let candidates = candidate_thresholds(&amplitudes, options.n_thresholds);
+        let mut errors = vec![0.0; candidates.len()];
+        for fold in &folds {
+            let held_out = median_trace(fold.iter().map(|&trial| trials[trial][channel].as_slice()), n_samples);
+            let mut training: Vec<usize> = (0..trials.len()).filter(|trial| !fold.contains(trial)).collect();
+            training.sort_by(|&a, &b| amplitudes[a].total_cmp(&amplitudes[b]));
+            // The candidates only grow, so each adds the training trials up to it to a running sum
+            let mut sum = vec![0.0; n_samples];
+            let mut n_accepted = 0;
+            for (error, &threshold) in errors.iter_mut().zip(&candidates) {
+                while n_accepted < training.len() && amplitudes[training[n_accepted]] <= threshold {
+                    for (total, sample) in sum.iter_mut().zip(&trials[training[n_accepted]][channel]) {
+                        *total += sample;
+                    }
+                    n_accepted += 1;
+                }
+                *error += if n_accepted == 0 {
+                    f64::INFINITY
+                } else {
+                    let squares: f64 = sum.iter().zip(&held_out).map(|(total, median)| (total / n_accepted as f64 - median).powi(2)).sum();
+                    (squares / n_samples as f64).sqrt()
+                };
+            }
+        }
+        // The lowest error, preferring the highest threshold among equal errors
+        let best = (0..candidates.len()).rev().min_by(|&a, &b| errors[a].total_cmp(&errors[b])).unwrap_or(0);
+        thresholds.push(candidates[best]);
+        cv_errors.push(errors[best] / folds.len() as f64);
+    }
+
+    let bad_channels: Vec<Vec<usize>> = peak_to_peak
+        .iter()
+        .map(|trial| (0..names.len()).filter(|&channel| trial[channel] > thresholds[channel]).collect())
+        .collect();
+    let decisions = bad_channels
+        .iter()
+        .map(|bad: &Vec<usize>| match bad.len() {
+            0 => TrialDecision::Keep,
+            n if n > options.max_bad_channels => TrialDecision::Reject,
+            _ => TrialDecision::Interpolate,
+        })
+        .collect();
+    Ok(AutoRejectResult { names: names.to_vec(), thresholds, cv_errors, peak_to_peak, decisions, bad_channels })
+}
+
+/// Returns the amplitudes at evenly spaced ranks from the median to the largest, without repeats
+fn candidate_thresholds(amplitudes: &[f64], n_thresholds: usize) -> Vec<f64> {
+    let mut sorted = amplitudes.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let (first, last) = ((sorted.len() - 1) / 2, sorted.len() - 1);
+    let mut candidates: Vec<f64> = (0..n_thresholds)
+        .map(|k| sorted[first + (k * (last - first)).checked_div(n_thresholds - 1).unwrap_or(last - first)])
+        .collect();
+    candidates.dedup();
+    candidates
+}
+
+/// Returns the median over trials of every sample
+fn median_trace<'a>(trials: impl Iterator<Item = &'a [f64]>, n_samples: usize) -> Vec<f64> {
+    let trials: Vec<&[f64]> = trials.collect();
+    (0..n_samples).map(|t| median(&trials.iter().map(|trial| trial[t]).collect::<Vec<f64>>())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 120 trials of 8 channels, where every tenth trial has a large artifact on half of the
+    /// channels and every seventeenth a single spike on channel 6
+    struct Planted {
+        trials: Vec<Vec<Vec<f64>>>,
+        names: Vec<String>,
+        artifacts: Vec<usize>,
+        spikes: Vec<usize>,
+    }
+
+    fn planted() -> Planted {
+        let mut rng = SeededRng::new(12345);
+        let (mut trials, mut artifacts, mut spikes) = (Vec::new(), Vec::new(), Vec::new());
+        for trial in 0..120 {
+            let channels: Vec<Vec<f64>> = (0..8)
+                .map(|channel| {
+                    let mut samples: Vec<f64> = (0..200).map(|i| 2.0 * (i as f64 / 20.0).sin() + 2.0 * (rng.next_f64() - 0.5)).collect();
+                    if trial % 10 == 3 && channel < 4 {
+                        samples.iter_mut().enumerate().for_each(|(i, sample)| *sample += 80.0 * (i as f64 / 60.0).sin());
+                    }
+                    if trial % 17 == 5 && channel == 6 {
+                        samples[50] += 60.0;
+                    }
+                    samples
+                })
+                .collect();
+            if trial % 10 == 3 {
+                artifacts.push(trial);
+            } else if trial % 17 == 5 {
+                spikes.push(trial);
+            }
+            trials.push(channels);
+        }
+        Planted { trials, names: (0..8).map(|channel| format!("ch{}", channel)).collect(), artifacts, spikes }
+    }
+
+    #[test]
+    fn rejects_planted_artifacts_and_keeps_clean_trials() {
+        let Planted { trials, names, artifacts, spikes } = planted();
+        let result = auto_reject(&trials, &names, &AutoRejectOptions::default()).unwrap();
+        // Clean channels span about 6 from peak to peak, the artifacts about 160
+        assert!(result.thresholds.iter().all(|&threshold| threshold > 4.0 && threshold < 20.0), "{:?}", result.thresholds);
+        assert!(artifacts.iter().all(|&trial| result.decisions[trial] == TrialDecision::Reject));
+        let n_clean = trials.len() - artifacts.len();
+        let clean_kept = (0..trials.len()).filter(|trial| !artifacts.contains(trial) && result.decisions[*trial] != TrialDecision::Reject).count();
+        assert!(clean_kept as f64 >= 0.95 * n_clean as f64, "{} of {} clean trials kept", clean_kept, n_clean);
+        // A single bad channel is repaired rather than costing the trial
+        for &trial in &spikes {
+            assert_eq!(result.decisions[trial], TrialDecision::Interpolate);
+            assert!(result.bad_channels[trial].contains(&6));
+        }
+        assert_eq!(result.count(TrialDecision::Reject), result.rejected().len());
+        assert_eq!(result.kept().len() + result.rejected().len(), trials.len());
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_result() {
+        let Planted { trials, names, .. } = planted();
+        let options = AutoRejectOptions { seed: 9, ..AutoRejectOptions::default() };
+        assert_eq!(auto_reject(&trials, &names, &options).unwrap(), auto_reject(&trials, &names, &options).unwrap());
+    }
+
+    #[test]
+    fn decisions_and_thresholds_export_to_csv() {
+        let Planted { trials, names, artifacts, .. } = planted();
+        let result = auto_reject(&trials, &names, &AutoRejectOptions::default()).unwrap();
+        let path = std::env::temp_dir().join(format!("neurorust-autoreject-{}.csv", std::process::id())).to_string_lossy().into_owned();
+        for thresholds in [false, true] {
+            std::fs::write(&path, "").unwrap();
+            let mut csv_io = CsvIO::open_write(&path).unwrap();
+            if thresholds { result.thresholds_to_csv(&mut csv_io) } else { result.to_csv(&mut csv_io) }.unwrap();
+            csv_io.save().unwrap();
+            let text = std::fs::read_to_string(&path).unwrap();
+            let lines: Vec<&str> = text.lines().collect();
+            if thresholds {
+                assert_eq!(lines[0], "channel,threshold,cv_error,n_exceeding");
+                assert_eq!(lines.len(), 1 + names.len());
+                assert!(lines[1].starts_with("ch0,"));
+            } else {
+                assert_eq!(lines[0], "trial,decision,n_bad_channels,bad_channels");
+                assert_eq!(lines.len(), 1 + trials.len());
+                assert!(lines[1 + artifacts[0]].starts_with(&format!("{},reject,", artifacts[0])), "{}", lines[1 + artifacts[0]]);
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn needs_as_many_trials_as_folds() {
+        let Planted { trials, names, .. } = planted();
+        assert!(auto_reject(&trials[..3], &names, &AutoRejectOptions::default()).is_err());
+        assert!(auto_reject(&trials, &names, &AutoRejectOptions { n_thresholds: 0, ..AutoRejectOptions::default() }).is_err());
+    }
+}
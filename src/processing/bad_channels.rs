@@ -0,0 +1,546 @@
+// A module to find dead and noisy channels of multi-channel recordings
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::{map_channels, validate_sampling_rate};
+use crate::processing::spectral::{welch, Spectrum, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW};
+use crate::processing::robust::MAD_CONSISTENCY;
+use crate::processing::timing::median;
+
+/// A rule that marks a channel as bad
+///
+/// # Arguments
+///
+/// * `Dead` - The variance is a tiny fraction of the median variance of the channels
+/// * `Deviation` - The robust z-score of the log variance is too far from 0
+/// * `HighFrequencyNoise` - The robust z-score of the fraction of power above the high-frequency cutoff is too high
+/// * `LowCorrelation` - The median correlation with the neighbouring channels, or with the average of the other channels, is too low
+/// * `LineNoise` - The robust z-score of the fraction of power at the line frequency and its harmonics is too high
+///
+/// # Examples
+///
+/// ```
+/// let dead: Vec<&BadChannel> = report.channels.iter().filter(|channel| channel.has(BadChannelCriterion::Dead)).collect();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BadChannelCriterion {
+    Dead,
+    Deviation,
+    HighFrequencyNoise,
+    LowCorrelation,
+    LineNoise,
+}
+
+/// Implementation of the BadChannelCriterion enum
+///
+/// # Methods
+///
+/// * `name` - Returns the name of the criterion
+impl BadChannelCriterion {
+    /// Returns the name of the criterion
+    ///
+    /// # Returns
+    ///
+    /// The name in snake case, e.g. `high_frequency_noise`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(BadChannelCriterion::LineNoise.name(), "line_noise");
+    /// ```
+    ///
+    pub fn name(&self) -> &'static str {
+        match self {
+            BadChannelCriterion::Dead => "dead",
+            BadChannelCriterion::Deviation => "deviation",
+            BadChannelCriterion::HighFrequencyNoise => "high_frequency_noise",
+            BadChannelCriterion::LowCorrelation => "low_correlation",
+            BadChannelCriterion::LineNoise => "line_noise",
+        }
+    }
+}
+
+/// The options of `detect_bad_channels`
+///
+/// # Arguments
+///
+/// * `dead_ratio` - The fraction of the median variance below which a channel is dead
+/// * `deviation_z` - The largest absolute robust z-score of the log variance
+/// * `high_frequency_cutoff` - The frequency in Hz above which power counts as high-frequency noise
+/// * `high_frequency_z` - The largest robust z-score of the high-frequency power fraction
+/// * `min_correlation` - The smallest median correlation with the neighbouring channels
+/// * `positions` - The position of each channel, used to find its neighbours, or None to correlate every channel with the average of the others
+/// * `n_neighbours` - The number of nearest channels a channel is correlated with when positions are given
+/// * `line_frequency` - The frequency of the power line in Hz
+/// * `line_bandwidth` - The half-width in Hz of the band around the line frequency and each harmonic
+/// * `line_noise_z` - The largest robust z-score of the line-noise power fraction
+///
+/// # Examples
+///
+/// ```
+/// let options = BadChannelOptions { line_frequency: 60.0, positions: Some(montage.positions()), ..BadChannelOptions::default() };
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BadChannelOptions {
+    pub dead_ratio: f64,
+    pub deviation_z: f64,
+    pub high_frequency_cutoff: f64,
+    pub high_frequency_z: f64,
+    pub min_correlation: f64,
+    pub positions: Option<Vec<[f64; 3]>>,
+    pub n_neighbours: usize,
+    pub line_frequency: f64,
+    pub line_bandwidth: f64,
+    pub line_noise_z: f64,
+}
+
+impl Default for BadChannelOptions {
+    fn default() -> Self {
+        Self {
+            dead_ratio: 1e-6,
+            deviation_z: 5.0,
+            high_frequency_cutoff: 40.0,
+            high_frequency_z: 5.0,
+            min_correlation: 0.4,
+            positions: None,
+            n_neighbours: 4,
+            line_frequency: 50.0,
+            line_bandwidth: 1.0,
+            line_noise_z: 5.0,
+        }
+    }
+}
+
+/// A criterion that marked a channel, with the measured value and the threshold it crossed
+///
+/// # Arguments
+///
+/// * `criterion` - The criterion
+/// * `value` - The measured value, e.g. the variance ratio, robust z-score or correlation
+/// * `threshold` - The threshold of the criterion
+///
+/// # Examples
+///
+/// ```
+/// for flag in &channel.flags {
+///     println!("{}: {} (threshold {})", flag.criterion.name(), flag.value, flag.threshold);
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BadChannelFlag {
+    pub criterion: BadChannelCriterion,
+    pub value: f64,
+    pub threshold: f64,
+}
+
+/// A channel marked as bad
+///
+/// # Arguments
+///
+/// * `index` - The index of the channel
+/// * `name` - The name of the channel
+/// * `flags` - The criteria that marked the channel
+///
+/// # Examples
+///
+/// ```
+/// println!("{} is bad: {:?}", channel.name, channel.flags);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BadChannel {
+    pub index: usize,
+    pub name: String,
+    pub flags: Vec<BadChannelFlag>,
+}
+
+/// Implementation of the BadChannel struct
+///
+/// # Methods
+///
+/// * `has` - Checks whether a criterion marked the channel
+impl BadChannel {
+    /// Checks whether a criterion marked the channel
+    ///
+    /// # Arguments
+    ///
+    /// * `criterion` - The criterion
+    ///
+    /// # Returns
+    ///
+    /// True if the criterion is among the flags of the channel
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// if channel.has(BadChannelCriterion::Dead) {
+    ///     println!("Check the connector of {}", channel.name);
+    /// }
+    /// ```
+    ///
+    pub fn has(&self, criterion: BadChannelCriterion) -> bool {
+        self.flags.iter().any(|flag| flag.criterion == criterion)
+    }
+}
+
+/// The bad channels found by `detect_bad_channels`
+///
+/// # Arguments
+///
+/// * `channels` - The channels marked by at least one criterion, in channel order
+///
+/// # Examples
+///
+/// ```
+/// let report = detect_bad_channels(&channels, &names, 1000.0, &BadChannelOptions::default())?;
+/// let (referenced, names) = rereference(&channels, &names, &report.names(), &Reference::CommonAverage, false)?;
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BadChannelReport {
+    pub channels: Vec<BadChannel>,
+}
+
+/// Implementation of the BadChannelReport struct
+///
+/// # Methods
+///
+/// * `names` - Returns the names of the bad channels
+/// * `to_csv` - Writes one `channel,criterion,value,threshold` row per flag
+impl BadChannelReport {
+    /// Returns the names of the bad channels
+    ///
+    /// # Returns
+    ///
+    /// The names, as taken by the `bad_channels` of `rereference` and `drop_bad_channels`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (good, good_names) = drop_bad_channels(&channels, &names, &report.names())?;
+    /// ```
+    ///
+    pub fn names(&self) -> Vec<String> {
+        self.channels.iter().map(|channel| channel.name.clone()).collect()
+    }
+
+    /// Writes one `channel,criterion,value,threshold` row per flag
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// report.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["channel", "criterion", "value", "threshold"]))?;
+        for channel in &self.channels {
+            for flag in &channel.flags {
+                csv_io.write_record(StringRecord::from(vec![
+                    channel.name.clone(),
+                    flag.criterion.name().to_string(),
+                    float_format.format(flag.value),
+                    float_format.format(flag.threshold),
+                ]))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Finds dead and noisy channels of a multi-channel recording
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel, all of the same length
+/// * `names` - The name of each channel
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `options` - The thresholds of the criteria and the positions of the channels
+///
+/// # Returns
+///
+/// The BadChannelReport, or an error if there are fewer than three channels, the channels
+/// and names or positions do not match, the channels differ in length or are shorter than
+/// one second, or a threshold is invalid
+///
+/// # Examples
+///
+/// ```
+/// let report = detect_bad_channels(&channels, &names, 1000.0, &BadChannelOptions::default())?;
+/// for channel in &report.channels {
+///     println!("{}: {:?}", channel.name, channel.flags);
+/// }
+/// ```
+///
+/// # Note
+///
+/// The robust z-scores compare each channel with the median and median absolute deviation
+/// of the channels that are not dead, so a few bad channels cannot hide each other. The
+/// power fractions come from a Welch estimate with one-second segments, and the
+/// high-frequency fraction leaves out the line-noise bands. Dead channels are only marked
+/// as dead, since the other measures are meaningless for them. Without positions, each
+/// channel is correlated with the average of the other live channels.
+///
+pub fn detect_bad_channels(
+    channels: &[Vec<f64>],
+    names: &[String],
+    sampling_rate: f64,
+    options: &BadChannelOptions,
+) -> Result<BadChannelReport, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    validate_options(channels, names, sampling_rate, options)?;
+    let length = channels[0].len();
+    let segment_len = (sampling_rate.round() as usize).max(1);
+    if channels.iter().any(|channel| channel.len() != length) || length < segment_len {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Channels must have the same length of at least one second, i.e. {} samples",
+            segment_len
+        )));
+    }
+
+    let centred: Vec<Vec<f64>> = map_channels(channels, |channel| {
+        let mean = channel.iter().sum::<f64>() / length as f64;
+        channel.iter().map(|sample| sample - mean).collect()
+    });
+    let variances: Vec<f64> = centred.iter().map(|channel| channel.iter().map(|x| x * x).sum::<f64>() / length as f64).collect();
+    let median_variance = median(&variances);
+    let dead: Vec<bool> = variances.iter().map(|&variance| variance.is_nan() || variance <= options.dead_ratio * median_variance).collect();
+
+    let spectra = map_channels(&centred, |channel| welch(channel, sampling_rate, segment_len, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW))
+        .into_iter()
+        .collect::<Result<Vec<Spectrum>, ProcessingError>>()?;
+    let (high_frequency, line_noise): (Vec<f64>, Vec<f64>) = spectra.iter().map(|spectrum| power_fractions(spectrum, options)).unzip();
+    let log_variances: Vec<f64> = variances.iter().map(|variance| variance.log10()).collect();
+    let deviation = robust_z(&log_variances, &dead);
+    let high_frequency = robust_z(&high_frequency, &dead);
+    let line_noise = robust_z(&line_noise, &dead);
+    let correlations = neighbour_correlations(&centred, &dead, options);
+
+    let mut report = BadChannelReport::default();
+    for (index, name) in names.iter().enumerate() {
+        let mut flags = Vec::new();
+        let mut flag = |criterion, value, threshold| flags.push(BadChannelFlag { criterion, value, threshold });
+        if dead[index] {
+            flag(BadChannelCriterion::Dead, variances[index] / median_variance, options.dead_ratio);
+        } else {
+            if deviation[index].abs() > options.deviation_z {
+                flag(BadChannelCriterion::Deviation, deviation[index], options.deviation_z);
+            }
+            if high_frequency[index] > options.high_frequency_z {
+                flag(BadChannelCriterion::HighFrequencyNoise, high_frequency[index], options.high_frequency_z);
+            }
+            if correlations[index] < options.min_correlation {
+                flag(BadChannelCriterion::LowCorrelation, correlations[index], options.min_correlation);
+            }
+            if line_noise[index] > options.line_noise_z {
+                flag(BadChannelCriterion::LineNoise, line_noise[index], options.line_noise_z);
+            }
+        }
+        if !flags.is_empty() {
+            report.channels.push(BadChannel { index, name: name.clone(), flags });
+        }
+    }
+    Ok(report)
+}
+
+/// Checks the channels, names and options of `detect_bad_channels`
+fn validate_options(channels: &[Vec<f64>], names: &[String], sampling_rate: f64, options: &BadChannelOptions) -> Result<(), ProcessingError> {
+    if channels.len() < 3 || channels.len() != names.len() {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Need at least three channels with one name each, got {} channels and {} names",
+            channels.len(),
+            names.len()
+        )));
+    }
+    if let Some(positions) = options.positions.as_ref().filter(|positions| positions.len() != channels.len()) {
+        return Err(ProcessingError::InvalidParameter(format!("Got {} channels but {} positions", channels.len(), positions.len())));
+    }
+    let nyquist = sampling_rate / 2.0;
+    if !(options.dead_ratio >= 0.0
+        && options.n_neighbours > 0
+        && options.line_bandwidth > 0.0
+        && options.high_frequency_cutoff > 0.0
+        && options.high_frequency_cutoff < nyquist
+        && options.line_frequency > 0.0
+        && options.line_frequency < nyquist)
+    {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Dead ratio must not be negative, the neighbour count and line bandwidth must be positive and the cutoff and line frequency within (0, {}) Hz",
+            nyquist
+        )));
+    }
+    Ok(())
+}
+
+/// Returns the fractions of the power above the high-frequency cutoff and in the line-noise bands, leaving out the DC bin
+fn power_fractions(spectrum: &Spectrum, options: &BadChannelOptions) -> (f64, f64) {
+    let in_line_band = |frequency: f64| {
+        let harmonic = (frequency / options.line_frequency).round().max(1.0);
+        (frequency - harmonic * options.line_frequency).abs() <= options.line_bandwidth
+    };
+    let (mut total, mut line, mut high) = (0.0, 0.0, 0.0);
+    for (&frequency, &power) in spectrum.frequencies.iter().zip(&spectrum.power).skip(1) {
+        total += power;
+        if in_line_band(frequency) {
+            line += power;
+        } else if frequency > options.high_frequency_cutoff {
+            high += power;
+        }
+    }
+    let rest = total - line;
+    (if rest > 0.0 { high / rest } else { 0.0 }, if total > 0.0 { line / total } else { 0.0 })
+}
+
+/// Returns the robust z-score of each value against the values of the live channels, NaN for dead channels
+fn robust_z(values: &[f64], dead: &[bool]) -> Vec<f64> {
+    let live: Vec<f64> = values.iter().zip(dead).filter(|(_, &dead)| !dead).map(|(&value, _)| value).collect();
+    let centre = median(&live);
+    let deviations: Vec<f64> = live.iter().map(|value| (value - centre).abs()).collect();
+    let spread = MAD_CONSISTENCY * median(&deviations);
+    values
+        .iter()
+        .zip(dead)
+        .map(|(&value, &dead)| match dead {
+            true => f64::NAN,
+            false if spread > 0.0 => (value - centre) / spread,
+            false if value == centre => 0.0,
+            false => (value - centre).signum() * f64::INFINITY,
+        })
+        .collect()
+}
+
+/// Returns the median correlation of each live channel with its neighbours, NaN for dead channels
+fn neighbour_correlations(centred: &[Vec<f64>], dead: &[bool], options: &BadChannelOptions) -> Vec<f64> {
+    let live: Vec<usize> = (0..centred.len()).filter(|&channel| !dead[channel]).collect();
+    let correlation = |a: &[f64], b: &[f64]| {
+        let (mut ab, mut aa, mut bb) = (0.0, 0.0, 0.0);
+        for (x, y) in a.iter().zip(b) {
+            ab += x * y;
+            aa += x * x;
+            bb += y * y;
+        }
+        if aa > 0.0 && bb > 0.0 { ab / (aa * bb).sqrt() } else { 0.0 }
+    };
+    let mut total = vec![0.0; centred[0].len()];
+    for &channel in &live {
+        total.iter_mut().zip(&centred[channel]).for_each(|(sum, sample)| *sum += sample);
+    }
+    (0..centred.len())
+        .map(|channel| {
+            if dead[channel] {
+                return f64::NAN;
+            }
+            match &options.positions {
+                Some(positions) => {
+                    let distance = |other: usize| positions[channel].iter().zip(&positions[other]).map(|(a, b)| (a - b).powi(2)).sum::<f64>();
+                    let mut neighbours: Vec<usize> = live.iter().copied().filter(|&other| other != channel).collect();
+                    neighbours.sort_by(|&a, &b| distance(a).total_cmp(&distance(b)));
+                    neighbours.truncate(options.n_neighbours);
+                    let correlations: Vec<f64> = neighbours.iter().map(|&other| correlation(&centred[channel], &centred[other])).collect();
+                    median(&correlations)
+                }
+                None => {
+                    let others: Vec<f64> = total.iter().zip(&centred[channel]).map(|(sum, sample)| sum - sample).collect();
+                    correlation(&centred[channel], &others)
+                }
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+    use crate::processing::reference::{drop_bad_channels, rereference, Reference};
+    use std::f64::consts::PI;
+
+    const SAMPLING_RATE: f64 = 500.0;
+
+    /// Twelve channels sharing a brain source, of which E3 is flat, E7 is broadband noise and E10 is pure 50 Hz
+    fn fixture() -> (Vec<Vec<f64>>, Vec<String>) {
+        let n = 5000;
+        let mut rng = SeededRng::new(7);
+        let mut state = 0.0;
+        let common: Vec<f64> = (0..n)
+            .map(|i| {
+                state = 0.95 * state + rng.next_gaussian();
+                state + 3.0 * (2.0 * PI * 10.0 * i as f64 / SAMPLING_RATE).sin()
+            })
+            .collect();
+        let line = |i: usize| (2.0 * PI * 50.0 * i as f64 / SAMPLING_RATE).sin();
+        let mut channels: Vec<Vec<f64>> = (0..12)
+            .map(|c| {
+                let mut local = 0.0;
+                (0..n)
+                    .map(|i| {
+                        local = 0.8 * local + 0.3 * rng.next_gaussian();
+                        (1.0 + 0.05 * c as f64) * common[i] + local + 0.05 * line(i)
+                    })
+                    .collect()
+            })
+            .collect();
+        channels[3] = vec![1.5; n];
+        channels[7] = (0..n).map(|_| 6.0 * rng.next_gaussian()).collect();
+        channels[10] = (0..n).map(|i| 5.0 * line(i)).collect();
+        (channels, (0..12).map(|c| format!("E{}", c)).collect())
+    }
+
+    #[test]
+    fn flags_exactly_the_flat_noisy_and_disconnected_channels() {
+        let (channels, names) = fixture();
+        let report = detect_bad_channels(&channels, &names, SAMPLING_RATE, &BadChannelOptions::default()).unwrap();
+        assert_eq!(report.names(), vec!["E3", "E7", "E10"]);
+        // A dead channel is only marked as dead
+        assert_eq!(report.channels[0].flags.len(), 1);
+        assert!(report.channels[0].has(BadChannelCriterion::Dead));
+        assert!(!report.channels[1].has(BadChannelCriterion::Dead));
+        assert!(report.channels[2].has(BadChannelCriterion::LineNoise));
+        for flag in report.channels.iter().flat_map(|channel| &channel.flags) {
+            assert!(flag.value.is_finite() && flag.threshold.is_finite());
+        }
+    }
+
+    #[test]
+    fn neighbours_from_positions_flag_the_same_channels() {
+        let (channels, names) = fixture();
+        let positions: Vec<[f64; 3]> = (0..12).map(|i| [(i % 4) as f64, (i / 4) as f64, 0.0]).collect();
+        let options = BadChannelOptions { positions: Some(positions), ..BadChannelOptions::default() };
+        let report = detect_bad_channels(&channels, &names, SAMPLING_RATE, &options).unwrap();
+        assert_eq!(report.names(), vec!["E3", "E7", "E10"]);
+    }
+
+    #[test]
+    fn flagged_channels_are_left_out_of_the_common_average() {
+        let (channels, names) = fixture();
+        let bad = detect_bad_channels(&channels, &names, SAMPLING_RATE, &BadChannelOptions::default()).unwrap().names();
+        let (good, good_names) = drop_bad_channels(&channels, &names, &bad).unwrap();
+        assert_eq!(good_names.len(), 9);
+        assert!(!good_names.iter().any(|name| bad.contains(name)));
+
+        let (referenced, _) = rereference(&channels, &names, &bad, &Reference::CommonAverage, false).unwrap();
+        let average: f64 = good.iter().map(|channel| channel[100]).sum::<f64>() / good.len() as f64;
+        assert!((referenced[0][100] - (channels[0][100] - average)).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,566 @@
+// A module to detect bursts of spikes in spike trains
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::peaks::{find_peaks, PeakOptions};
+
+/// Options of the Max-Interval method
+///
+/// # Arguments
+///
+/// * `max_begin_isi` - The longest inter-spike interval in seconds that starts a burst, 0.17 s by default
+/// * `max_end_isi` - The longest inter-spike interval in seconds that continues a burst, 0.3 s by default
+/// * `min_interburst_interval` - Bursts closer than this many seconds are merged, 0.2 s by default
+/// * `min_duration` - The shortest burst kept in seconds, 0.01 s by default
+/// * `min_spikes` - The fewest spikes of a kept burst, 3 by default
+///
+/// # Examples
+///
+/// ```
+/// let options = MaxIntervalOptions { max_begin_isi: 0.05, max_end_isi: 0.1, ..MaxIntervalOptions::default() };
+/// ```
+///
+/// # Note
+///
+/// The defaults are those compared on MEA recordings by Cotterill et al. (2016)
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct MaxIntervalOptions {
+    pub max_begin_isi: f64,
+    pub max_end_isi: f64,
+    pub min_interburst_interval: f64,
+    pub min_duration: f64,
+    pub min_spikes: usize,
+}
+
+impl Default for MaxIntervalOptions {
+    fn default() -> Self {
+        Self { max_begin_isi: 0.17, max_end_isi: 0.3, min_interburst_interval: 0.2, min_duration: 0.01, min_spikes: 3 }
+    }
+}
+
+/// Options of the logISI method
+///
+/// # Arguments
+///
+/// * `isi_cutoff` - The longest inter-spike interval in seconds of the intra-burst peak of the histogram, and of the burst cores when the threshold is longer, 0.1 s by default
+/// * `void_threshold` - The smallest void parameter of the minimum that separates intra- and inter-burst intervals, 0.7 by default
+/// * `bins_per_decade` - The number of bins per decade of the log-ISI histogram, 10 by default
+/// * `min_spikes` - The fewest spikes of a kept burst, 3 by default
+///
+/// # Examples
+///
+/// ```
+/// let options = LogIsiOptions { isi_cutoff: 0.05, ..LogIsiOptions::default() };
+/// ```
+///
+/// # Note
+///
+/// The defaults are those of Pasquale et al. (2010)
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LogIsiOptions {
+    pub isi_cutoff: f64,
+    pub void_threshold: f64,
+    pub bins_per_decade: usize,
+    pub min_spikes: usize,
+}
+
+impl Default for LogIsiOptions {
+    fn default() -> Self {
+        Self { isi_cutoff: 0.1, void_threshold: 0.7, bins_per_decade: 10, min_spikes: 3 }
+    }
+}
+
+/// The burst detection method of `detect`
+///
+/// # Arguments
+///
+/// * `MaxInterval` - Bursts start and continue with inter-spike intervals below fixed thresholds, then close bursts are merged and short ones dropped
+/// * `LogIsi` - The interval threshold is the trough between the intra- and inter-burst peaks of the log-ISI histogram of the train itself
+///
+/// # Examples
+///
+/// ```
+/// let bursts = detect(&unit, 0.0, 600.0, &BurstMethod::LogIsi(LogIsiOptions::default()))?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BurstMethod {
+    MaxInterval(MaxIntervalOptions),
+    LogIsi(LogIsiOptions),
+}
+
+/// A burst of spikes
+///
+/// # Arguments
+///
+/// * `start` - The time of the first spike of the burst in seconds
+/// * `end` - The time of the last spike of the burst in seconds
+/// * `n_spikes` - The number of spikes in the burst
+/// * `rate` - The mean intra-burst rate in spikes per second, `(n_spikes - 1) / (end - start)`
+/// * `truncated` - Whether the burst is so close to the start or end of the recording that it may extend beyond it
+///
+/// # Examples
+///
+/// ```
+/// let bursts = detect(&unit, 0.0, 600.0, &BurstMethod::MaxInterval(MaxIntervalOptions::default()))?;
+/// let complete: Vec<&Burst> = bursts.iter().filter(|burst| !burst.truncated).collect();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Burst {
+    pub start: f64,
+    pub end: f64,
+    pub n_spikes: usize,
+    pub rate: f64,
+    pub truncated: bool,
+}
+
+/// Detects the bursts of a spike train
+///
+/// # Arguments
+///
+/// * `spike_times` - The spike times in seconds, in any order
+/// * `t_start` - The start of the recording in seconds
+/// * `t_stop` - The end of the recording in seconds
+/// * `method` - The detection method and its options
+///
+/// # Returns
+///
+/// The bursts in time order, or an error if the recording ends before it starts or an
+/// option is outside of its valid range
+///
+/// # Examples
+///
+/// ```
+/// let bursts = detect(&unit, 0.0, 600.0, &BurstMethod::MaxInterval(MaxIntervalOptions::default()))?;
+/// println!("{} bursts per minute", 60.0 * burst_rate(&bursts, 0.0, 600.0));
+/// ```
+///
+/// # Note
+///
+/// Spikes outside `[t_start, t_stop]` are ignored. A burst is truncated when the gap between
+/// the start of the recording and its first spike, or between its last spike and the end of
+/// the recording, is no longer than the interval that continues a burst (`max_end_isi`, or
+/// the logISI threshold), since unseen spikes there could belong to it. Truncated bursts
+/// are kept and flagged so that the caller decides whether to count them. The logISI method
+/// finds no bursts if the histogram has no peak at or below `isi_cutoff`, and falls back to
+/// `isi_cutoff` as the threshold if no trough reaches the void threshold.
+///
+pub fn detect(spike_times: &[f64], t_start: f64, t_stop: f64, method: &BurstMethod) -> Result<Vec<Burst>, ProcessingError> {
+    if !(t_stop >= t_start && t_start.is_finite() && t_stop.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Recording must end after it starts, got {} to {}",
+            t_start, t_stop
+        )));
+    }
+    let mut spikes: Vec<f64> = spike_times.iter().copied().filter(|&time| time >= t_start && time <= t_stop).collect();
+    spikes.sort_by(|a, b| a.total_cmp(b));
+    let intervals: Vec<f64> = spikes.windows(2).map(|pair| pair[1] - pair[0]).collect();
+
+    let (runs, continue_isi) = match method {
+        BurstMethod::MaxInterval(options) => {
+            validate_max_interval(options)?;
+            (max_interval_runs(&spikes, &intervals, options), options.max_end_isi)
+        }
+        BurstMethod::LogIsi(options) => {
+            validate_log_isi(options)?;
+            let threshold = match log_isi_threshold(&intervals, options) {
+                Some(threshold) => threshold,
+                None => return Ok(Vec::new()),
+            };
+            (log_isi_runs(&intervals, threshold, options), threshold)
+        }
+    };
+
+    Ok(runs
+        .into_iter()
+        .map(|(first, last)| {
+            let (start, end) = (spikes[first], spikes[last]);
+            let n_spikes = last - first + 1;
+            Burst {
+                start,
+                end,
+                n_spikes,
+                rate: (n_spikes - 1) as f64 / (end - start),
+                truncated: start - t_start <= continue_isi || t_stop - end <= continue_isi,
+            }
+        })
+        .collect())
+}
+
+/// Computes the number of bursts per second of recording
+///
+/// # Arguments
+///
+/// * `bursts` - The bursts, e.g. from `detect`
+/// * `t_start` - The start of the recording in seconds
+/// * `t_stop` - The end of the recording in seconds
+///
+/// # Returns
+///
+/// The burst rate in bursts per second, or NaN if the recording has no duration
+///
+/// # Examples
+///
+/// ```
+/// let per_minute = 60.0 * burst_rate(&bursts, 0.0, 600.0);
+/// ```
+///
+pub fn burst_rate(bursts: &[Burst], t_start: f64, t_stop: f64) -> f64 {
+    let duration = t_stop - t_start;
+    if duration > 0.0 { bursts.len() as f64 / duration } else { f64::NAN }
+}
+
+/// Computes the fraction of the spikes of a train that fall within bursts
+///
+/// # Arguments
+///
+/// * `bursts` - The bursts, e.g. from `detect`
+/// * `n_spikes` - The number of spikes of the train within the recording
+///
+/// # Returns
+///
+/// The fraction of spikes in bursts, or NaN if the train has no spikes
+///
+/// # Examples
+///
+/// ```
+/// let fraction = fraction_spikes_in_bursts(&bursts, unit.len());
+/// ```
+///
+pub fn fraction_spikes_in_bursts(bursts: &[Burst], n_spikes: usize) -> f64 {
+    if n_spikes == 0 {
+        return f64::NAN;
+    }
+    bursts.iter().map(|burst| burst.n_spikes).sum::<usize>() as f64 / n_spikes as f64
+}
+
+/// Computes the mean duration of bursts
+///
+/// # Arguments
+///
+/// * `bursts` - The bursts, e.g. from `detect`
+///
+/// # Returns
+///
+/// The mean time from the first to the last spike of a burst in seconds, or NaN if there are no bursts
+///
+/// # Examples
+///
+/// ```
+/// let duration = mean_burst_duration(&bursts);
+/// ```
+///
+pub fn mean_burst_duration(bursts: &[Burst]) -> f64 {
+    bursts.iter().map(|burst| burst.end - burst.start).sum::<f64>() / bursts.len() as f64
+}
+
+/// Writes bursts as `start,end,n_spikes,rate,truncated` rows
+///
+/// # Arguments
+///
+/// * `bursts` - The bursts to write
+/// * `csv_io` - The CsvIO object to write to
+///
+/// # Returns
+///
+/// Nothing, or an error if a row cannot be written
+///
+/// # Examples
+///
+/// ```
+/// to_csv(&bursts, &mut csv_io)?;
+/// csv_io.save()?;
+/// ```
+///
+/// # Note
+///
+/// A header row is written first. The rows are not flushed to disk until `save` is called.
+///
+pub fn to_csv(bursts: &[Burst], csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+    let float_format = csv_io.float_format();
+    csv_io.write_record(StringRecord::from(vec!["start", "end", "n_spikes", "rate", "truncated"]))?;
+    for burst in bursts {
+        csv_io.write_record(StringRecord::from(vec![
+            float_format.format(burst.start),
+            float_format.format(burst.end),
+            burst.n_spikes.to_string(),
+            float_format.format(burst.rate),
+            burst.truncated.to_string(),
+        ]))?;
+    }
+    Ok(())
+}
+
+fn validate_max_interval(options: &MaxIntervalOptions) -> Result<(), ProcessingError> {
+    if !(options.max_begin_isi > 0.0 && options.max_end_isi >= options.max_begin_isi) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Maximum intervals must be positive with the end one at least the begin one, got {} and {}",
+            options.max_begin_isi, options.max_end_isi
+        )));
+    }
+    if options.min_interburst_interval.is_nan() || options.min_interburst_interval < 0.0 || options.min_duration.is_nan() || options.min_duration < 0.0 {
+        return Err(ProcessingError::InvalidParameter("Minimum interburst interval and duration must be non-negative".to_string()));
+    }
+    if options.min_spikes < 2 {
+        return Err(ProcessingError::InvalidParameter(format!("A burst needs at least 2 spikes, got {}", options.min_spikes)));
+    }
+    Ok(())
+}
+
+fn validate_log_isi(options: &LogIsiOptions) -> Result<(), ProcessingError> {
+    if !(options.isi_cutoff > 0.0 && options.isi_cutoff.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!("ISI cutoff must be positive, got {}", options.isi_cutoff)));
+    }
+    if !(0.0..=1.0).contains(&options.void_threshold) || options.bins_per_decade == 0 {
+        return Err(ProcessingError::InvalidParameter(
+            "Void threshold must be within [0, 1] and the histogram needs at least one bin per decade".to_string(),
+        ));
+    }
+    if options.min_spikes < 2 {
+        return Err(ProcessingError::InvalidParameter(format!("A burst needs at least 2 spikes, got {}", options.min_spikes)));
+    }
+    Ok(())
+}
+
+/// Returns the first and last spike index of each Max-Interval burst
+fn max_interval_runs(spikes: &[f64], intervals: &[f64], options: &MaxIntervalOptions) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+    let mut i = 0;
+    while i < intervals.len() {
+        if intervals[i] > options.max_begin_isi {
+            i += 1;
+            continue;
+        }
+        let mut last = i + 1;
+        while last < intervals.len() && intervals[last] <= options.max_end_isi {
+            last += 1;
+        }
+        match runs.last_mut() {
+            Some(previous) if spikes[i] - spikes[previous.1] < options.min_interburst_interval => previous.1 = last,
+            _ => runs.push((i, last)),
+        }
+        i = last;
+    }
+    runs.retain(|&(first, last)| last - first + 1 >= options.min_spikes && spikes[last] - spikes[first] >= options.min_duration);
+    runs
+}
+
+/// Finds the interval threshold of the logISI method, or None if the histogram has no intra-burst peak
+fn log_isi_threshold(intervals: &[f64], options: &LogIsiOptions) -> Option<f64> {
+    let logs: Vec<f64> = intervals.iter().filter(|&&interval| interval > 0.0).map(|interval| interval.log10()).collect();
+    if logs.len() < 2 {
+        return None;
+    }
+    let bin_size = 1.0 / options.bins_per_decade as f64;
+    let low = (logs.iter().fold(f64::INFINITY, |low, &x| low.min(x)) / bin_size).floor() * bin_size;
+    let high = logs.iter().fold(f64::NEG_INFINITY, |high, &x| high.max(x));
+    let n_bins = (((high - low) / bin_size).floor() as usize) + 1;
+    // One empty bin on each side so that peaks in the outermost bins are found
+    let mut counts = vec![0.0; n_bins + 2];
+    for x in &logs {
+        counts[1 + (((x - low) / bin_size) as usize).min(n_bins - 1)] += 1.0;
+    }
+    let smoothed: Vec<f64> = (0..counts.len())
+        .map(|i| counts[i.saturating_sub(1)..(i + 2).min(counts.len())].iter().sum::<f64>() / 3.0)
+        .collect();
+    let center = |bin: usize| 10f64.powf(low + (bin as f64 - 0.5) * bin_size);
+
+    let peaks = find_peaks(&smoothed, 1.0, 0.0, &PeakOptions::default()).ok()?;
+    let intra = peaks
+        .iter()
+        .enumerate()
+        .filter(|(_, peak)| center(peak.index) <= options.isi_cutoff * (1.0 + 1e-9))
+        .fold(None, |best: Option<(usize, f64)>, (k, peak)| match best {
+            Some((_, height)) if height >= peak.height => best,
+            _ => Some((k, peak.height)),
+        })?;
+    let first = &peaks[intra.0];
+    for peak in &peaks[intra.0 + 1..] {
+        let (trough, depth) = (first.index..=peak.index).fold((first.index, f64::INFINITY), |best, bin| {
+            if smoothed[bin] < best.1 { (bin, smoothed[bin]) } else { best }
+        });
+        if 1.0 - depth / (first.height * peak.height).sqrt() >= options.void_threshold {
+            return Some(center(trough));
+        }
+    }
+    Some(options.isi_cutoff)
+}
+
+/// Returns the first and last spike index of each logISI burst
+fn log_isi_runs(intervals: &[f64], threshold: f64, options: &LogIsiOptions) -> Vec<(usize, usize)> {
+    let core_isi = threshold.min(options.isi_cutoff);
+    let mut runs = Vec::new();
+    let mut i = 0;
+    while i < intervals.len() {
+        if intervals[i] > threshold {
+            i += 1;
+            continue;
+        }
+        let mut last = i;
+        let (mut core, mut longest_core) = (0, 0);
+        while last < intervals.len() && intervals[last] <= threshold {
+            core = if intervals[last] <= core_isi { core + 1 } else { 0 };
+            longest_core = longest_core.max(core);
+            last += 1;
+        }
+        // A burst is a run of intervals below the threshold that holds a core of at least
+        // `min_spikes` spikes with intervals below the cutoff, extended to the whole run
+        if longest_core + 1 >= options.min_spikes {
+            runs.push((i, last));
+        }
+        i = last;
+    }
+    runs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    const DURATION: f64 = 300.0;
+
+    /// A 1 Hz Poisson background with bursts of 5 to 12 spikes at 5 to 15 ms intervals
+    /// planted every 8 to 12 s, and no background spike within 0.5 s of a burst
+    fn planted_train(seed: u64) -> (Vec<f64>, Vec<Burst>) {
+        let mut rng = SeededRng::new(seed);
+        let mut planted = Vec::new();
+        let mut spikes = Vec::new();
+        let mut time = 5.0;
+        while time < DURATION - 5.0 {
+            let n_spikes = 5 + rng.next_index(8);
+            let start = time;
+            for _ in 0..n_spikes {
+                spikes.push(time);
+                time += 0.005 + 0.01 * rng.next_f64();
+            }
+            let end = *spikes.last().unwrap();
+            planted.push(Burst { start, end, n_spikes, rate: (n_spikes - 1) as f64 / (end - start), truncated: false });
+            time = end + 8.0 + 4.0 * rng.next_f64();
+        }
+        let mut time = 0.0;
+        loop {
+            time -= (1.0 - rng.next_f64()).ln();
+            if time >= DURATION {
+                break;
+            }
+            if planted.iter().all(|burst| time < burst.start - 0.5 || time > burst.end + 0.5) {
+                spikes.push(time);
+            }
+        }
+        // Unsorted on purpose
+        spikes.reverse();
+        (spikes, planted)
+    }
+
+    fn assert_recovered(detected: &[Burst], planted: &[Burst]) {
+        assert_eq!(detected.len(), planted.len());
+        for (found, truth) in detected.iter().zip(planted) {
+            assert_eq!((found.start, found.end, found.n_spikes, found.truncated), (truth.start, truth.end, truth.n_spikes, false));
+            assert!((found.rate - truth.rate).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn max_interval_recovers_planted_burst_boundaries() {
+        let (spikes, planted) = planted_train(1);
+        // Five spikes, the smallest planted burst, excludes background triplets that fall within 20 and 30 ms by chance
+        let options = MaxIntervalOptions { max_begin_isi: 0.02, max_end_isi: 0.03, min_interburst_interval: 0.1, min_duration: 0.0, min_spikes: 5 };
+        let bursts = detect(&spikes, 0.0, DURATION, &BurstMethod::MaxInterval(options)).unwrap();
+        assert_recovered(&bursts, &planted);
+
+        let in_bursts: usize = planted.iter().map(|burst| burst.n_spikes).sum();
+        assert_eq!(fraction_spikes_in_bursts(&bursts, spikes.len()), in_bursts as f64 / spikes.len() as f64);
+        assert_eq!(burst_rate(&bursts, 0.0, DURATION), planted.len() as f64 / DURATION);
+        let duration = planted.iter().map(|burst| burst.end - burst.start).sum::<f64>() / planted.len() as f64;
+        assert!((mean_burst_duration(&bursts) - duration).abs() < 1e-12);
+    }
+
+    #[test]
+    fn log_isi_finds_the_trough_and_recovers_planted_bursts() {
+        let (spikes, planted) = planted_train(2);
+        let mut sorted = spikes.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let intervals: Vec<f64> = sorted.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        let threshold = log_isi_threshold(&intervals, &LogIsiOptions::default()).unwrap();
+        assert!(threshold > 0.015 && threshold < 0.5, "{}", threshold);
+        let bursts = detect(&spikes, 0.0, DURATION, &BurstMethod::LogIsi(LogIsiOptions::default())).unwrap();
+        assert_recovered(&bursts, &planted);
+
+        // A regular 1 Hz train has no intra-burst peak, and a single peak falls back to the cutoff
+        let regular: Vec<f64> = (0..100).map(|k| k as f64).collect();
+        assert!(detect(&regular, 0.0, 100.0, &BurstMethod::LogIsi(LogIsiOptions::default())).unwrap().is_empty());
+        let dense: Vec<f64> = (0..20).map(|k| 1.0 + 0.01 * k as f64).collect();
+        let intervals: Vec<f64> = dense.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        assert_eq!(log_isi_threshold(&intervals, &LogIsiOptions::default()), Some(0.1));
+        let bursts = detect(&dense, 0.0, 2.0, &BurstMethod::LogIsi(LogIsiOptions::default())).unwrap();
+        assert_eq!(bursts.len(), 1);
+        assert_eq!((bursts[0].start, bursts[0].end, bursts[0].n_spikes), (1.0, dense[19], 20));
+    }
+
+    #[test]
+    fn max_interval_merges_close_bursts_and_drops_small_ones() {
+        let options = MaxIntervalOptions { max_begin_isi: 0.015, max_end_isi: 0.025, min_interburst_interval: 0.1, min_duration: 0.025, min_spikes: 3 };
+        let method = BurstMethod::MaxInterval(options);
+        // Two bursts 50 ms apart merge, a 2-spike pair and a 3-spike burst of 20 ms are dropped
+        let spikes = [1.0, 1.01, 1.02, 1.07, 1.08, 1.09, 3.0, 3.005, 5.0, 5.01, 5.02, 7.0, 7.005, 7.015, 7.03, 7.04];
+        let bursts = detect(&spikes, 0.0, 10.0, &method).unwrap();
+        assert_eq!(bursts.iter().map(|burst| (burst.start, burst.end, burst.n_spikes)).collect::<Vec<_>>(), vec![(1.0, 1.09, 6), (7.0, 7.04, 5)]);
+        // An interval above max_begin_isi does not start a burst, but one up to max_end_isi continues it
+        let spikes = [1.0, 1.02, 1.04, 2.0, 2.01, 2.03, 2.05];
+        let bursts = detect(&spikes, 0.0, 10.0, &method).unwrap();
+        assert_eq!(bursts.iter().map(|burst| (burst.start, burst.n_spikes)).collect::<Vec<_>>(), vec![(2.0, 4)]);
+    }
+
+    #[test]
+    fn bursts_at_the_recording_edges_are_flagged_as_truncated() {
+        let options = MaxIntervalOptions { max_begin_isi: 0.015, max_end_isi: 0.02, min_interburst_interval: 0.1, min_duration: 0.0, min_spikes: 3 };
+        let method = BurstMethod::MaxInterval(options);
+        // Spikes outside the recording are ignored, so the edge bursts are cut there
+        let spikes = [0.99, 1.015, 1.02, 1.03, 5.0, 5.01, 5.02, 8.96, 8.97, 8.98, 9.005];
+        let bursts = detect(&spikes, 1.0, 9.0, &method).unwrap();
+        let summary: Vec<(f64, usize, bool)> = bursts.iter().map(|burst| (burst.start, burst.n_spikes, burst.truncated)).collect();
+        assert_eq!(summary, vec![(1.015, 3, true), (5.0, 3, false), (8.96, 3, true)]);
+        // Moving the edges more than max_end_isi away clears the flags
+        assert!(detect(&spikes, 0.9, 9.1, &method).unwrap().iter().all(|burst| !burst.truncated));
+
+        assert!(detect(&[], 0.0, 1.0, &method).unwrap().is_empty());
+        assert!(burst_rate(&[], 1.0, 1.0).is_nan());
+        assert!(fraction_spikes_in_bursts(&[], 0).is_nan());
+        assert!(mean_burst_duration(&[]).is_nan());
+    }
+
+    #[test]
+    fn invalid_options_are_rejected_and_bursts_export_to_csv() {
+        let default = MaxIntervalOptions::default();
+        for options in [
+            MaxIntervalOptions { max_begin_isi: 0.0, ..default },
+            MaxIntervalOptions { max_end_isi: 0.1, ..default },
+            MaxIntervalOptions { min_duration: f64::NAN, ..default },
+            MaxIntervalOptions { min_spikes: 1, ..default },
+        ] {
+            assert!(detect(&[1.0], 0.0, 2.0, &BurstMethod::MaxInterval(options)).is_err());
+        }
+        for options in [LogIsiOptions { isi_cutoff: -1.0, ..LogIsiOptions::default() }, LogIsiOptions { void_threshold: 1.5, ..LogIsiOptions::default() }] {
+            assert!(detect(&[1.0], 0.0, 2.0, &BurstMethod::LogIsi(options)).is_err());
+        }
+        assert!(detect(&[1.0], 2.0, 1.0, &BurstMethod::MaxInterval(default)).is_err());
+
+        let bursts = [Burst { start: 1.0, end: 1.5, n_spikes: 6, rate: 10.0, truncated: true }];
+        let path = std::env::temp_dir().join(format!("neurorust-bursts-{}-bursts.csv", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        to_csv(&bursts, &mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written.lines().collect::<Vec<_>>(), vec!["start,end,n_spikes,rate,truncated", "1,1.5,6,10,true"]);
+    }
+}
@@ -0,0 +1,431 @@
+// A module to reconstruct bad channels from the good channels around them
+
+// Written by Amin Alam in 2024
+
+use std::f64::consts::PI;
+use crate::processing::error::ProcessingError;
+use crate::processing::linalg::{least_squares, legendre_basis};
+
+/// The method used by `interpolate_channels` to reconstruct a channel
+///
+/// # Arguments
+///
+/// * `InverseDistance` - Averages the `n_neighbours` nearest good channels, weighted by the inverse of their distance raised to `power`
+/// * `SphericalSpline` - Fits a spherical spline through the good channels (Perrin et al., 1989), with the spline `order`, the number of Legendre terms `n_terms` and the `regularization` added to the diagonal
+///
+/// # Examples
+///
+/// ```
+/// let method = ChannelInterpolation::InverseDistance { n_neighbours: 4, power: 2.0 };
+/// let method = ChannelInterpolation::spherical_spline();
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChannelInterpolation {
+    InverseDistance { n_neighbours: usize, power: f64 },
+    SphericalSpline { order: usize, n_terms: usize, regularization: f64 },
+}
+
+/// Implementation of the ChannelInterpolation enum
+///
+/// # Methods
+///
+/// * `spherical_spline` - Returns the spherical spline with the usual parameters
+impl ChannelInterpolation {
+    /// Returns the spherical spline with the usual parameters
+    ///
+    /// # Returns
+    ///
+    /// A spherical spline of order 4 with 50 Legendre terms and a regularization of 1e-5
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let options = ChannelInterpolationOptions { method: ChannelInterpolation::spherical_spline(), ..ChannelInterpolationOptions::default() };
+    /// ```
+    ///
+    pub fn spherical_spline() -> Self {
+        ChannelInterpolation::SphericalSpline { order: 4, n_terms: 50, regularization: 1e-5 }
+    }
+}
+
+/// The options of `interpolate_channels`
+///
+/// # Arguments
+///
+/// * `method` - The interpolation method, inverse-distance weighting of 4 neighbours by default
+/// * `max_bad_fraction` - The largest fraction of the channels that may be interpolated, 0.25 by default
+///
+/// # Examples
+///
+/// ```
+/// let options = ChannelInterpolationOptions { max_bad_fraction: 0.1, ..ChannelInterpolationOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChannelInterpolationOptions {
+    pub method: ChannelInterpolation,
+    pub max_bad_fraction: f64,
+}
+
+impl Default for ChannelInterpolationOptions {
+    fn default() -> Self {
+        Self { method: ChannelInterpolation::InverseDistance { n_neighbours: 4, power: 2.0 }, max_bad_fraction: 0.25 }
+    }
+}
+
+/// The channels returned by `interpolate_channels`
+///
+/// # Arguments
+///
+/// * `channels` - The samples of every channel, with the bad channels replaced by their reconstruction
+/// * `interpolated` - The names of the reconstructed channels
+///
+/// # Examples
+///
+/// ```
+/// let repaired = interpolate_channels(&channels, &names, &positions, &report.names(), &ChannelInterpolationOptions::default())?;
+/// let (measured, measured_names) = drop_bad_channels(&repaired.channels, &names, &repaired.interpolated)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InterpolatedChannels {
+    pub channels: Vec<Vec<f64>>,
+    pub interpolated: Vec<String>,
+}
+
+/// Implementation of the InterpolatedChannels struct
+///
+/// # Methods
+///
+/// * `is_interpolated` - Checks whether a channel was reconstructed
+impl InterpolatedChannels {
+    /// Checks whether a channel was reconstructed
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the channel
+    ///
+    /// # Returns
+    ///
+    /// True if the channel holds interpolated rather than recorded samples
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let measured: Vec<&String> = names.iter().filter(|name| !repaired.is_interpolated(name)).collect();
+    /// ```
+    ///
+    pub fn is_interpolated(&self, name: &str) -> bool {
+        self.interpolated.iter().any(|interpolated| interpolated == name)
+    }
+}
+
+/// Reconstructs bad channels from the good channels around them
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel, all of the same length
+/// * `names` - The name of each channel
+/// * `positions` - The position of each channel, with NaN coordinates for channels without a position
+/// * `bad_channels` - The names of the channels to reconstruct
+/// * `options` - The interpolation method and the largest fraction of bad channels
+///
+/// # Returns
+///
+/// The channels with the bad ones replaced, or an error if the channels, names and positions
+/// do not match, a bad channel is unknown, a channel involved has no position, too many
+/// channels are bad, or the method's parameters are invalid
+///
+/// # Examples
+///
+/// ```
+/// let options = ChannelInterpolationOptions { method: ChannelInterpolation::spherical_spline(), ..ChannelInterpolationOptions::default() };
+/// let repaired = interpolate_channels(&channels, &names, &positions, &["T7".to_string()], &options)?;
+/// ```
+///
+/// # Note
+///
+/// The spherical spline projects the positions onto the unit sphere around the origin, so
+/// they must be centred on the head, as in standard EEG montages. Bad channels only need a
+/// position themselves; good channels without one are left out of the reconstruction.
+///
+pub fn interpolate_channels(
+    channels: &[Vec<f64>],
+    names: &[String],
+    positions: &[[f64; 3]],
+    bad_channels: &[String],
+    options: &ChannelInterpolationOptions,
+) -> Result<InterpolatedChannels, ProcessingError> {
+    if channels.len() != names.len() || positions.len() != channels.len() {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Channel interpolation needs one name and one position per channel, got {} channels, {} names and {} positions",
+            channels.len(),
+            names.len(),
+            positions.len()
+        )));
+    }
+    let length = channels.first().map_or(0, |channel| channel.len());
+    if channels.iter().any(|channel| channel.len() != length) {
+        return Err(ProcessingError::InvalidParameter("Channels must have the same length".to_string()));
+    }
+    let bad = bad_channels
+        .iter()
+        .map(|name| names.iter().position(|candidate| candidate == name).ok_or_else(|| ProcessingError::InvalidParameter(format!("Unknown channel {}", name))))
+        .collect::<Result<Vec<usize>, ProcessingError>>()?;
+    if bad.len() as f64 > options.max_bad_fraction * channels.len() as f64 {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "{} of {} channels are bad, more than the limit of {}%; raise max_bad_fraction to interpolate them anyway",
+            bad.len(),
+            channels.len(),
+            options.max_bad_fraction * 100.0
+        )));
+    }
+    let has_position = |channel: usize| positions[channel].iter().all(|coordinate| coordinate.is_finite());
+    if let Some(&channel) = bad.iter().find(|&&channel| !has_position(channel)) {
+        return Err(ProcessingError::InvalidParameter(format!("Bad channel {} has no position to interpolate at", names[channel])));
+    }
+    let good: Vec<usize> = (0..channels.len()).filter(|channel| !bad.contains(channel) && has_position(*channel)).collect();
+    if good.is_empty() {
+        return Err(ProcessingError::InvalidParameter("No good channel with a position is left to interpolate from".to_string()));
+    }
+
+    let weights = match options.method {
+        ChannelInterpolation::InverseDistance { n_neighbours, power } => inverse_distance_weights(positions, &good, &bad, n_neighbours, power)?,
+        ChannelInterpolation::SphericalSpline { order, n_terms, regularization } => spline_weights(positions, &good, &bad, order, n_terms, regularization)?,
+    };
+    let mut repaired = channels.to_vec();
+    for (&channel, weights) in bad.iter().zip(&weights) {
+        let mut samples = vec![0.0; length];
+        for (&source, &weight) in good.iter().zip(weights) {
+            if weight != 0.0 {
+                samples.iter_mut().zip(&channels[source]).for_each(|(sum, sample)| *sum += weight * sample);
+            }
+        }
+        repaired[channel] = samples;
+    }
+    Ok(InterpolatedChannels { channels: repaired, interpolated: bad.iter().map(|&channel| names[channel].clone()).collect() })
+}
+
+/// Returns the weights of the good channels for each bad channel under inverse-distance weighting
+fn inverse_distance_weights(positions: &[[f64; 3]], good: &[usize], bad: &[usize], n_neighbours: usize, power: f64) -> Result<Vec<Vec<f64>>, ProcessingError> {
+    if n_neighbours == 0 || !(power >= 0.0 && power.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Inverse-distance weighting needs at least one neighbour and a non-negative power, got {} and {}",
+            n_neighbours, power
+        )));
+    }
+    let distance = |a: usize, b: usize| positions[a].iter().zip(&positions[b]).map(|(x, y)| (x - y).powi(2)).sum::<f64>().sqrt();
+    Ok(bad
+        .iter()
+        .map(|&channel| {
+            let mut order: Vec<usize> = (0..good.len()).collect();
+            order.sort_by(|&a, &b| distance(channel, good[a]).total_cmp(&distance(channel, good[b])));
+            order.truncate(n_neighbours);
+            let mut weights = vec![0.0; good.len()];
+            if let Some(&same) = order.iter().find(|&&neighbour| distance(channel, good[neighbour]) == 0.0) {
+                weights[same] = 1.0;
+                return weights;
+            }
+            for &neighbour in &order {
+                weights[neighbour] = distance(channel, good[neighbour]).powf(-power);
+            }
+            let total: f64 = weights.iter().sum();
+            weights.iter_mut().for_each(|weight| *weight /= total);
+            weights
+        })
+        .collect())
+}
+
+/// Returns the weights of the good channels for each bad channel under spherical-spline interpolation
+fn spline_weights(positions: &[[f64; 3]], good: &[usize], bad: &[usize], order: usize, n_terms: usize, regularization: f64) -> Result<Vec<Vec<f64>>, ProcessingError> {
+    if order == 0 || n_terms == 0 || !(regularization >= 0.0 && regularization.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Spherical splines need a positive order and number of terms and a non-negative regularization, got {}, {} and {}",
+            order, n_terms, regularization
+        )));
+    }
+    let unit = |channel: usize| {
+        let norm = positions[channel].iter().map(|x| x * x).sum::<f64>().sqrt();
+        positions[channel].map(|x| x / norm)
+    };
+    if let Some(&channel) = bad.iter().chain(good).find(|&&channel| !unit(channel).iter().all(|x| x.is_finite())) {
+        return Err(ProcessingError::InvalidParameter(format!("Channel {} is at the centre of the sphere", channel + 1)));
+    }
+    let factors: Vec<f64> = (1..=n_terms).map(|n| (2 * n + 1) as f64 / ((n * (n + 1)) as f64).powi(order as i32) / (4.0 * PI)).collect();
+    let g = |a: [f64; 3], b: [f64; 3]| {
+        let cosine = a.iter().zip(&b).map(|(x, y)| x * y).sum::<f64>().clamp(-1.0, 1.0);
+        legendre_basis(cosine, n_terms)[1..].iter().zip(&factors).map(|(p, factor)| p * factor).sum::<f64>()
+    };
+
+    let sphere: Vec<[f64; 3]> = good.iter().map(|&channel| unit(channel)).collect();
+    let k = good.len();
+    let columns: Vec<Vec<f64>> = (0..=k)
+        .map(|j| {
+            (0..=k)
+                .map(|i| match (i < k, j < k) {
+                    (true, true) => g(sphere[i], sphere[j]) + if i == j { regularization } else { 0.0 },
+                    (false, false) => 0.0,
+                    _ => 1.0,
+                })
+                .collect()
+        })
+        .collect();
+    bad.iter()
+        .map(|&channel| {
+            let target = unit(channel);
+            let mut rhs: Vec<f64> = sphere.iter().map(|&position| g(target, position)).collect();
+            rhs.push(1.0);
+            let mut weights = least_squares(&columns, &rhs)
+                .ok_or_else(|| ProcessingError::InvalidParameter("The spherical spline system is singular; check for duplicate positions".to_string()))?;
+            weights.truncate(k);
+            Ok(weights)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    const N_CHANNELS: usize = 32;
+    const N_SAMPLES: usize = 2000;
+
+    /// Positions spread evenly over the upper part of a 9 cm sphere, like an EEG cap
+    fn montage() -> Vec<[f64; 3]> {
+        let golden = PI * (3.0 - 5f64.sqrt());
+        (0..N_CHANNELS)
+            .map(|k| {
+                let z = 1.0 - 0.9 * (k as f64 + 0.5) / N_CHANNELS as f64;
+                let radius = (1.0 - z * z).sqrt();
+                let azimuth = golden * k as f64;
+                [0.09 * radius * azimuth.cos(), 0.09 * radius * azimuth.sin(), 0.09 * z]
+            })
+            .collect()
+    }
+
+    /// Three sources with broad, smooth scalp maps, plus 5% sensor noise
+    fn fixture(positions: &[[f64; 3]]) -> Vec<Vec<f64>> {
+        let mut rng = SeededRng::new(1);
+        let directions = [[0.0, 0.0, 1.0], [0.6, 0.0, 0.8], [-0.3, 0.7, 0.65]];
+        let sources: Vec<Vec<f64>> = [3.0, 7.0, 11.0]
+            .iter()
+            .map(|frequency| (0..N_SAMPLES).map(|k| (2.0 * PI * frequency * k as f64 / 250.0).sin() + 0.3 * rng.next_gaussian()).collect())
+            .collect();
+        positions
+            .iter()
+            .map(|position| {
+                let norm = position.iter().map(|x| x * x).sum::<f64>().sqrt();
+                let gains: Vec<f64> = directions
+                    .iter()
+                    .map(|direction| (2.0 * (position.iter().zip(direction).map(|(x, d)| x * d).sum::<f64>() / norm - 1.0)).exp())
+                    .collect();
+                (0..N_SAMPLES).map(|k| gains.iter().zip(&sources).map(|(gain, source)| gain * source[k]).sum::<f64>() + 0.05 * rng.next_gaussian()).collect()
+            })
+            .collect()
+    }
+
+    fn names() -> Vec<String> {
+        (0..N_CHANNELS).map(|k| format!("E{}", k + 1)).collect()
+    }
+
+    fn correlation(a: &[f64], b: &[f64]) -> f64 {
+        let (mean_a, mean_b) = (a.iter().sum::<f64>() / a.len() as f64, b.iter().sum::<f64>() / b.len() as f64);
+        let covariance: f64 = a.iter().zip(b).map(|(x, y)| (x - mean_a) * (y - mean_b)).sum();
+        let (var_a, var_b): (f64, f64) = (a.iter().map(|x| (x - mean_a).powi(2)).sum(), b.iter().map(|y| (y - mean_b).powi(2)).sum());
+        covariance / (var_a * var_b).sqrt()
+    }
+
+    #[test]
+    fn a_removed_good_channel_is_recovered_by_both_methods() {
+        let positions = montage();
+        let channels = fixture(&positions);
+        let names = names();
+        let methods = [ChannelInterpolationOptions::default().method, ChannelInterpolation::spherical_spline()];
+        for method in methods {
+            let options = ChannelInterpolationOptions { method, ..ChannelInterpolationOptions::default() };
+            for bad in [5, 12, 20] {
+                // The recorded samples of the bad channel are never read
+                let mut corrupted = channels.clone();
+                corrupted[bad].iter_mut().for_each(|sample| *sample = 1e6);
+                let repaired = interpolate_channels(&corrupted, &names, &positions, std::slice::from_ref(&names[bad]), &options).unwrap();
+                let r = correlation(&repaired.channels[bad], &channels[bad]);
+                assert!(r > 0.97, "{:?} channel {}: {}", method, bad, r);
+                assert_eq!(repaired.interpolated, vec![names[bad].clone()]);
+                assert!(repaired.is_interpolated(&names[bad]) && !repaired.is_interpolated(&names[0]));
+                assert!((0..N_CHANNELS).filter(|&k| k != bad).all(|k| repaired.channels[k] == corrupted[k]));
+            }
+        }
+    }
+
+    #[test]
+    fn both_methods_keep_a_uniform_field_and_a_coincident_channel() {
+        let positions = montage();
+        let names = names();
+        let uniform = vec![vec![2.5; 4]; N_CHANNELS];
+        for method in [ChannelInterpolationOptions::default().method, ChannelInterpolation::spherical_spline()] {
+            let options = ChannelInterpolationOptions { method, ..ChannelInterpolationOptions::default() };
+            let repaired = interpolate_channels(&uniform, &names, &positions, &[names[7].clone(), names[8].clone()], &options).unwrap();
+            for k in [7, 8] {
+                assert!(repaired.channels[k].iter().all(|sample| (sample - 2.5).abs() < 1e-6), "{:?}: {:?}", method, repaired.channels[k]);
+            }
+        }
+
+        // A good channel at the position of the bad one is copied by inverse-distance weighting
+        let mut positions = positions;
+        positions[3] = positions[10];
+        let channels: Vec<Vec<f64>> = (0..N_CHANNELS).map(|k| vec![k as f64, -(k as f64)]).collect();
+        let repaired = interpolate_channels(&channels, &names, &positions, &[names[3].clone()], &ChannelInterpolationOptions::default()).unwrap();
+        assert_eq!(repaired.channels[3], vec![10.0, -10.0]);
+        // With one neighbour and no coincident channel, the nearest one is copied
+        let nearest = ChannelInterpolationOptions { method: ChannelInterpolation::InverseDistance { n_neighbours: 1, power: 2.0 }, ..ChannelInterpolationOptions::default() };
+        let repaired = interpolate_channels(&channels, &names, &montage(), &[names[0].clone()], &nearest).unwrap();
+        let nearest_channel = (1..N_CHANNELS)
+            .min_by(|&a, &b| {
+                let distance = |k: usize| montage()[k].iter().zip(&montage()[0]).map(|(x, y)| (x - y).powi(2)).sum::<f64>();
+                distance(a).total_cmp(&distance(b))
+            })
+            .unwrap();
+        assert_eq!(repaired.channels[0], channels[nearest_channel]);
+    }
+
+    #[test]
+    fn missing_positions_and_too_many_bad_channels_are_reported() {
+        let mut positions = montage();
+        let names = names();
+        let channels = vec![vec![0.0; 4]; N_CHANNELS];
+        let options = ChannelInterpolationOptions::default();
+        let nine: Vec<String> = names[..9].to_vec();
+        let error = interpolate_channels(&channels, &names, &positions, &nine, &options).unwrap_err();
+        assert!(error.to_string().contains("raise max_bad_fraction"), "{}", error);
+        let lenient = ChannelInterpolationOptions { max_bad_fraction: 0.3, ..options };
+        assert_eq!(interpolate_channels(&channels, &names, &positions, &nine, &lenient).unwrap().interpolated, nine);
+
+        positions[2] = [f64::NAN; 3];
+        let error = interpolate_channels(&channels, &names, &positions, &[names[2].clone()], &options).unwrap_err();
+        assert!(error.to_string().contains("E3 has no position"), "{}", error);
+        // A good channel without a position is left out instead
+        let inputs: Vec<Vec<f64>> = (0..N_CHANNELS).map(|k| vec![if k == 2 { 1e6 } else { 1.0 }]).collect();
+        assert_eq!(interpolate_channels(&inputs, &names, &positions, &[names[1].clone()], &options).unwrap().channels[1], vec![1.0]);
+
+        assert!(interpolate_channels(&channels, &names, &positions, &["Cz".to_string()], &options).is_err());
+        assert!(interpolate_channels(&channels, &names[1..], &positions, &[], &options).is_err());
+        assert!(interpolate_channels(&channels, &names, &positions[1..], &[], &options).is_err());
+        let mut ragged = channels.clone();
+        ragged[4].push(0.0);
+        assert!(interpolate_channels(&ragged, &names, &positions, &[], &options).is_err());
+        for method in [
+            ChannelInterpolation::InverseDistance { n_neighbours: 0, power: 2.0 },
+            ChannelInterpolation::InverseDistance { n_neighbours: 4, power: -1.0 },
+            ChannelInterpolation::SphericalSpline { order: 0, n_terms: 50, regularization: 1e-5 },
+            ChannelInterpolation::SphericalSpline { order: 4, n_terms: 50, regularization: f64::NAN },
+        ] {
+            assert!(interpolate_channels(&channels, &names, &montage(), &[names[0].clone()], &ChannelInterpolationOptions { method, ..options }).is_err());
+        }
+        let mut centred = montage();
+        centred[5] = [0.0; 3];
+        let spline = ChannelInterpolationOptions { method: ChannelInterpolation::spherical_spline(), ..options };
+        assert!(interpolate_channels(&channels, &names, &centred, &[names[0].clone()], &spline).is_err());
+    }
+}
@@ -0,0 +1,1050 @@
+// A module to derive channels from arithmetic expressions over named channels
+
+// Written by Amin Alam in 2024
+
+use std::fmt;
+use crate::processing::error::ProcessingError;
+
+/// An arithmetic operation between two operands
+///
+/// # Arguments
+///
+/// * `Add` - `a + b`
+/// * `Sub` - `a - b`
+/// * `Mul` - `a * b`
+/// * `Div` - `a / b`
+///
+/// # Examples
+///
+/// ```
+/// let difference = elementwise(&c3, &c4, BinaryOp::Sub)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+}
+
+/// A function applied to every sample of its argument
+///
+/// # Arguments
+///
+/// * `Abs` - The absolute value, written `abs(x)`
+/// * `Sqrt` - The square root, written `sqrt(x)`
+/// * `Powi` - An integer power, written `powi(x, n)`
+///
+/// # Examples
+///
+/// ```
+/// let rectified = ChannelExpr::parse("abs(EMG1 - EMG2)")?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelFunction {
+    Abs,
+    Sqrt,
+    Powi(i32),
+}
+
+/// A parsed channel expression
+///
+/// # Arguments
+///
+/// * `Channel` - The samples of the named channel
+/// * `Constant` - A number
+/// * `Negate` - The negated value of an expression
+/// * `Binary` - An operation between two expressions
+/// * `Function` - A function of an expression
+///
+/// # Examples
+///
+/// ```
+/// let expr = ChannelExpr::parse("(C3 - C4) * 0.5")?;
+/// assert_eq!(expr.channels(), vec!["C3", "C4"]);
+/// ```
+///
+/// # Note
+///
+/// Channel names are letters, digits, `_` and `.`, starting with a letter or `_`. Names
+/// with other characters, such as the bipolar `C3-C4`, are written in double quotes. The
+/// usual precedence applies: `*` and `/` bind tighter than `+` and `-`, which are all left
+/// associative, and a leading `-` negates the operand that follows it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelExpr {
+    Channel(String),
+    Constant(f64),
+    Negate(Box<ChannelExpr>),
+    Binary(BinaryOp, Box<ChannelExpr>, Box<ChannelExpr>),
+    Function(ChannelFunction, Box<ChannelExpr>),
+}
+
+/// The channels an expression or closure can refer to
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel, all of the same length
+/// * `names` - The name of each channel
+/// * `units` - The unit of each channel, None where it is not known
+///
+/// # Examples
+///
+/// ```
+/// let context = ChannelContext::new(&channels, &names)?.with_units(units)?;
+/// let c3 = context.get("C3")?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelContext<'a> {
+    channels: &'a [Vec<f64>],
+    names: &'a [String],
+    units: Vec<Option<String>>,
+}
+
+/// A channel computed from other channels
+///
+/// # Arguments
+///
+/// * `name` - The name of the channel
+/// * `samples` - The samples of the channel
+/// * `unit` - The unit of the samples, or None if a channel it was computed from has no known unit
+/// * `provenance` - A note of how the channel was computed, e.g. `derived: (C3 - C4) * 0.5`
+///
+/// # Examples
+///
+/// ```
+/// let emg = derive_channel(&context, "EMG", &ChannelExpr::parse("abs(ch5 - ch6)")?)?;
+/// println!("{} [{}]: {}", emg.name, emg.unit.as_deref().unwrap_or("?"), emg.provenance);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct DerivedChannel {
+    pub name: String,
+    pub samples: Vec<f64>,
+    pub unit: Option<String>,
+    pub provenance: String,
+}
+
+/// Implementation of the ChannelExpr enum
+///
+/// # Methods
+///
+/// * `parse` - Parses an expression such as `(C3 - C4) * 0.5`
+/// * `channels` - Returns the names of the channels the expression refers to
+/// * `evaluate` - Computes the samples and the unit of the expression
+impl ChannelExpr {
+    /// Parses an expression such as `(C3 - C4) * 0.5`
+    ///
+    /// # Arguments
+    ///
+    /// * `text` - The expression, made of numbers, channel names, `+`, `-`, `*`, `/`, parentheses and the functions `abs`, `sqrt` and `powi`
+    ///
+    /// # Returns
+    ///
+    /// The ChannelExpr, or an error naming the position of the first character that does not fit
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let expr = ChannelExpr::parse("sqrt(powi(x, 2) + powi(y, 2))")?;
+    /// ```
+    ///
+    pub fn parse(text: &str) -> Result<Self, ProcessingError> {
+        let mut parser = Parser { text, position: 0 };
+        let expr = parser.expression()?;
+        parser.skip_whitespace();
+        match parser.peek() {
+            None => Ok(expr),
+            Some(_) => Err(parser.error("an operator or the end of the expression")),
+        }
+    }
+
+    /// Returns the names of the channels the expression refers to
+    ///
+    /// # Returns
+    ///
+    /// The names in order of first appearance, each once
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let inputs = ChannelExpr::parse("C3 - (C3 + C4) / 2")?.channels();
+    /// ```
+    ///
+    pub fn channels(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        self.collect_channels(&mut names);
+        names
+    }
+
+    /// Computes the samples and the unit of the expression
+    ///
+    /// # Arguments
+    ///
+    /// * `context` - The channels the expression refers to
+    ///
+    /// # Returns
+    ///
+    /// The samples and the unit, None if a channel has no known unit, or an error if a
+    /// channel is unknown, the units of added or subtracted channels differ, or the
+    /// expression refers to no channel
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (samples, unit) = ChannelExpr::parse("(C3 - C4) * 0.5")?.evaluate(&context)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Sums and differences keep the unit of their operands, which must agree. Products and
+    /// quotients combine the units, e.g. `uV*uV` becomes `uV^2` and `uV/uV` is dimensionless,
+    /// written as an empty unit. Numbers take the unit of what they are added to and scale
+    /// without changing a unit. `sqrt` undoes a square unit. Division by zero gives infinite
+    /// or NaN samples, as for `f64`.
+    ///
+    pub fn evaluate(&self, context: &ChannelContext) -> Result<(Vec<f64>, Option<String>), ProcessingError> {
+        if let Some(name) = self.channels().into_iter().find(|name| context.index_of(name).is_err()) {
+            return Err(ProcessingError::InvalidParameter(format!("Unknown channel {} in {}; the channels are {}", name, self, context.names.join(", "))));
+        }
+        match self.value(context)? {
+            (Value::Samples(samples), Unit::Known(unit)) => Ok((samples, Some(unit))),
+            (Value::Samples(samples), _) => Ok((samples, None)),
+            (Value::Scalar(_), _) => Err(ProcessingError::InvalidParameter(format!("{} refers to no channel", self))),
+        }
+    }
+
+    fn collect_channels<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self {
+            ChannelExpr::Channel(name) => {
+                if !names.contains(&name.as_str()) {
+                    names.push(name);
+                }
+            }
+            ChannelExpr::Constant(_) => {}
+            ChannelExpr::Negate(operand) | ChannelExpr::Function(_, operand) => operand.collect_channels(names),
+            ChannelExpr::Binary(_, left, right) => {
+                left.collect_channels(names);
+                right.collect_channels(names);
+            }
+        }
+    }
+
+    fn value(&self, context: &ChannelContext) -> Result<(Value, Unit), ProcessingError> {
+        Ok(match self {
+            ChannelExpr::Channel(name) => {
+                let index = context.index_of(name)?;
+                let unit = context.units[index].clone().map_or(Unit::Unknown, Unit::Known);
+                (Value::Samples(context.channels[index].clone()), unit)
+            }
+            ChannelExpr::Constant(value) => (Value::Scalar(*value), Unit::Scalar),
+            ChannelExpr::Negate(operand) => {
+                let (value, unit) = operand.value(context)?;
+                (value.map(|x| -x), unit)
+            }
+            ChannelExpr::Function(function, operand) => {
+                let (value, unit) = operand.value(context)?;
+                match function {
+                    ChannelFunction::Abs => (value.map(f64::abs), unit),
+                    ChannelFunction::Sqrt => (value.map(f64::sqrt), unit.sqrt()),
+                    ChannelFunction::Powi(n) => (value.map(|x| x.powi(*n)), unit.powi(*n)),
+                }
+            }
+            ChannelExpr::Binary(op, left, right) => {
+                let (left_value, left_unit) = left.value(context)?;
+                let (right_value, right_unit) = right.value(context)?;
+                let unit = left_unit.combine(*op, right_unit).map_err(|(a, b)| {
+                    ProcessingError::InvalidParameter(format!("Cannot {} {} [{}] and {} [{}]", op.verb(), left, a, right, b))
+                })?;
+                (left_value.combine(*op, right_value), unit)
+            }
+        })
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            ChannelExpr::Binary(BinaryOp::Add | BinaryOp::Sub, _, _) => 1,
+            ChannelExpr::Binary(BinaryOp::Mul | BinaryOp::Div, _, _) => 2,
+            ChannelExpr::Negate(_) => 3,
+            _ => 4,
+        }
+    }
+}
+
+impl fmt::Display for ChannelExpr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let operand = |f: &mut fmt::Formatter, expr: &ChannelExpr, parenthesize: bool| match parenthesize {
+            true => write!(f, "({})", expr),
+            false => write!(f, "{}", expr),
+        };
+        match self {
+            ChannelExpr::Channel(name) if is_plain_name(name) => write!(f, "{}", name),
+            ChannelExpr::Channel(name) => write!(f, "\"{}\"", name),
+            ChannelExpr::Constant(value) => write!(f, "{}", value),
+            ChannelExpr::Negate(inner) => {
+                write!(f, "-")?;
+                operand(f, inner, inner.precedence() < 3)
+            }
+            ChannelExpr::Function(ChannelFunction::Powi(n), inner) => write!(f, "powi({}, {})", inner, n),
+            ChannelExpr::Function(function, inner) => write!(f, "{}({})", if *function == ChannelFunction::Abs { "abs" } else { "sqrt" }, inner),
+            ChannelExpr::Binary(op, left, right) => {
+                let precedence = self.precedence();
+                operand(f, left, left.precedence() < precedence)?;
+                write!(f, " {} ", op.symbol())?;
+                // The right operand of - and / needs parentheses at the same precedence too
+                operand(f, right, right.precedence() < precedence || (right.precedence() == precedence && matches!(op, BinaryOp::Sub | BinaryOp::Div)))
+            }
+        }
+    }
+}
+
+/// Implementation of the ChannelContext struct
+///
+/// # Methods
+///
+/// * `new` - Creates a context over a set of channels with unknown units
+/// * `with_units` - Sets the unit of each channel
+/// * `names` - Returns the names of the channels
+/// * `n_samples` - Returns the number of samples of each channel
+/// * `get` - Returns the samples of a channel
+/// * `unit` - Returns the unit of a channel
+impl<'a> ChannelContext<'a> {
+    /// Creates a context over a set of channels with unknown units
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - The samples of each channel
+    /// * `names` - The name of each channel
+    ///
+    /// # Returns
+    ///
+    /// The ChannelContext, or an error if the channels and names do not match or two channels
+    /// differ in length, naming both
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let context = ChannelContext::new(&channels, &names)?;
+    /// ```
+    ///
+    pub fn new(channels: &'a [Vec<f64>], names: &'a [String]) -> Result<Self, ProcessingError> {
+        if channels.len() != names.len() {
+            return Err(ProcessingError::InvalidParameter(format!("Got {} channels but {} channel names", channels.len(), names.len())));
+        }
+        let length = channels.first().map_or(0, |channel| channel.len());
+        if let Some(index) = channels.iter().position(|channel| channel.len() != length) {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Channel {} has {} samples but channel {} has {}",
+                names[index],
+                channels[index].len(),
+                names[0],
+                length
+            )));
+        }
+        Ok(Self { channels, names, units: vec![None; channels.len()] })
+    }
+
+    /// Sets the unit of each channel
+    ///
+    /// # Arguments
+    ///
+    /// * `units` - The unit of each channel, None where it is not known
+    ///
+    /// # Returns
+    ///
+    /// The ChannelContext with the units, or an error if there is not one unit per channel
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // Units recorded by a calibration under the unit_<channel> session keys
+    /// let units = names.iter().map(|name| session.get(&format!("unit_{}", name)).map(str::to_string)).collect();
+    /// let context = ChannelContext::new(&channels, &names)?.with_units(units)?;
+    /// ```
+    ///
+    pub fn with_units(mut self, units: Vec<Option<String>>) -> Result<Self, ProcessingError> {
+        if units.len() != self.channels.len() {
+            return Err(ProcessingError::InvalidParameter(format!("Got {} channels but {} units", self.channels.len(), units.len())));
+        }
+        self.units = units;
+        Ok(self)
+    }
+
+    /// Returns the names of the channels
+    ///
+    /// # Returns
+    ///
+    /// The name of each channel
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let names = context.names();
+    /// ```
+    ///
+    pub fn names(&self) -> &[String] {
+        self.names
+    }
+
+    /// Returns the number of samples of each channel
+    ///
+    /// # Returns
+    ///
+    /// The length shared by all channels, 0 without channels
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let n_samples = context.n_samples();
+    /// ```
+    ///
+    pub fn n_samples(&self) -> usize {
+        self.channels.first().map_or(0, |channel| channel.len())
+    }
+
+    /// Returns the samples of a channel
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the channel
+    ///
+    /// # Returns
+    ///
+    /// The samples, or an error if there is no such channel
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let c3 = context.get("C3")?;
+    /// ```
+    ///
+    pub fn get(&self, name: &str) -> Result<&'a [f64], ProcessingError> {
+        Ok(&self.channels[self.index_of(name)?])
+    }
+
+    /// Returns the unit of a channel
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the channel
+    ///
+    /// # Returns
+    ///
+    /// The unit, None if it is not known, or an error if there is no such channel
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let unit = context.unit("C3")?;
+    /// ```
+    ///
+    pub fn unit(&self, name: &str) -> Result<Option<&str>, ProcessingError> {
+        Ok(self.units[self.index_of(name)?].as_deref())
+    }
+
+    fn index_of(&self, name: &str) -> Result<usize, ProcessingError> {
+        self.names
+            .iter()
+            .position(|candidate| candidate == name)
+            .ok_or_else(|| ProcessingError::InvalidParameter(format!("Unknown channel {}", name)))
+    }
+}
+
+/// Derives a channel from an expression over the channels of a context
+///
+/// # Arguments
+///
+/// * `context` - The channels the expression refers to
+/// * `name` - The name of the derived channel
+/// * `expr` - The expression
+///
+/// # Returns
+///
+/// The DerivedChannel, whose provenance is `derived: <expression>`, or the error of `ChannelExpr::evaluate`
+///
+/// # Examples
+///
+/// ```
+/// let context = ChannelContext::new(&channels, &names)?;
+/// let emg = derive_channel(&context, "EMG", &ChannelExpr::parse("abs(ch5 - ch6)")?)?;
+/// channels.push(emg.samples);
+/// names.push(emg.name);
+/// ```
+///
+pub fn derive_channel(context: &ChannelContext, name: &str, expr: &ChannelExpr) -> Result<DerivedChannel, ProcessingError> {
+    let (samples, unit) = expr.evaluate(context)?;
+    Ok(DerivedChannel { name: name.to_string(), samples, unit, provenance: format!("derived: {}", expr) })
+}
+
+/// Derives a channel with a function of the channels of a context
+///
+/// # Arguments
+///
+/// * `context` - The channels the function can look up
+/// * `name` - The name of the derived channel
+/// * `description` - A description of the computation, recorded in the provenance
+/// * `unit` - The unit of the derived channel, or None if it is not known
+/// * `f` - The function computing the samples from the context
+///
+/// # Returns
+///
+/// The DerivedChannel, whose provenance is `derived: <description>`, or the error of `f`, or
+/// an error if `f` returns a different number of samples than the channels have
+///
+/// # Examples
+///
+/// ```
+/// let power = derive_channel_with(&context, "EMG_power", "(ch5 - ch6)^2", None, |context| {
+///     Ok(elementwise(context.get("ch5")?, context.get("ch6")?, BinaryOp::Sub)?.iter().map(|x| x * x).collect())
+/// })?;
+/// ```
+///
+pub fn derive_channel_with<F>(context: &ChannelContext, name: &str, description: &str, unit: Option<&str>, f: F) -> Result<DerivedChannel, ProcessingError>
+where
+    F: FnOnce(&ChannelContext) -> Result<Vec<f64>, ProcessingError>,
+{
+    let samples = f(context)?;
+    if samples.len() != context.n_samples() {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Derived channel {} has {} samples but the channels have {}",
+            name,
+            samples.len(),
+            context.n_samples()
+        )));
+    }
+    Ok(DerivedChannel { name: name.to_string(), samples, unit: unit.map(str::to_string), provenance: format!("derived: {}", description) })
+}
+
+/// Applies an operation to every pair of samples of two signals
+///
+/// # Arguments
+///
+/// * `a` - The samples of the left operand
+/// * `b` - The samples of the right operand
+/// * `op` - The operation
+///
+/// # Returns
+///
+/// The samples `a[i] op b[i]`, or an error naming both lengths if they differ
+///
+/// # Examples
+///
+/// ```
+/// let bipolar = elementwise(&c3, &c4, BinaryOp::Sub)?;
+/// ```
+///
+pub fn elementwise(a: &[f64], b: &[f64], op: BinaryOp) -> Result<Vec<f64>, ProcessingError> {
+    if a.len() != b.len() {
+        return Err(ProcessingError::InvalidParameter(format!("The left operand has {} samples but the right operand has {}", a.len(), b.len())));
+    }
+    Ok(a.iter().zip(b).map(|(&x, &y)| op.apply(x, y)).collect())
+}
+
+/// Applies an operation between every sample of a signal and a number
+///
+/// # Arguments
+///
+/// * `a` - The samples of the signal
+/// * `value` - The number
+/// * `op` - The operation, with the signal on the left
+///
+/// # Returns
+///
+/// The samples `a[i] op value`
+///
+/// # Examples
+///
+/// ```
+/// let microvolts = scalar(&volts, 1e6, BinaryOp::Mul);
+/// ```
+///
+pub fn scalar(a: &[f64], value: f64, op: BinaryOp) -> Vec<f64> {
+    a.iter().map(|&x| op.apply(x, value)).collect()
+}
+
+/// Implementation of the BinaryOp enum
+///
+/// # Methods
+///
+/// * `apply` - Applies the operation to two numbers
+/// * `symbol` - Returns the symbol of the operation
+impl BinaryOp {
+    /// Applies the operation to two numbers
+    ///
+    /// # Arguments
+    ///
+    /// * `a` - The left operand
+    /// * `b` - The right operand
+    ///
+    /// # Returns
+    ///
+    /// `a op b`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(BinaryOp::Div.apply(1.0, 4.0), 0.25);
+    /// ```
+    ///
+    pub fn apply(&self, a: f64, b: f64) -> f64 {
+        match self {
+            BinaryOp::Add => a + b,
+            BinaryOp::Sub => a - b,
+            BinaryOp::Mul => a * b,
+            BinaryOp::Div => a / b,
+        }
+    }
+
+    /// Returns the symbol of the operation
+    ///
+    /// # Returns
+    ///
+    /// `+`, `-`, `*` or `/`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(BinaryOp::Mul.symbol(), '*');
+    /// ```
+    ///
+    pub fn symbol(&self) -> char {
+        match self {
+            BinaryOp::Add => '+',
+            BinaryOp::Sub => '-',
+            BinaryOp::Mul => '*',
+            BinaryOp::Div => '/',
+        }
+    }
+
+    fn verb(&self) -> &'static str {
+        match self {
+            BinaryOp::Add => "add",
+            BinaryOp::Sub => "subtract",
+            BinaryOp::Mul => "multiply",
+            BinaryOp::Div => "divide",
+        }
+    }
+}
+
+/// An intermediate value, kept as a number until it meets a channel
+enum Value {
+    Scalar(f64),
+    Samples(Vec<f64>),
+}
+
+impl Value {
+    fn map(self, f: impl Fn(f64) -> f64) -> Self {
+        match self {
+            Value::Scalar(x) => Value::Scalar(f(x)),
+            Value::Samples(mut samples) => {
+                samples.iter_mut().for_each(|x| *x = f(*x));
+                Value::Samples(samples)
+            }
+        }
+    }
+
+    /// Combines two values; the channels of a context all have the same length
+    fn combine(self, op: BinaryOp, other: Value) -> Self {
+        match (self, other) {
+            (Value::Scalar(a), Value::Scalar(b)) => Value::Scalar(op.apply(a, b)),
+            (Value::Samples(a), Value::Scalar(b)) => Value::Samples(scalar(&a, b, op)),
+            (Value::Scalar(a), Value::Samples(mut b)) => {
+                b.iter_mut().for_each(|x| *x = op.apply(a, *x));
+                Value::Samples(b)
+            }
+            (Value::Samples(mut a), Value::Samples(b)) => {
+                a.iter_mut().zip(&b).for_each(|(x, &y)| *x = op.apply(*x, y));
+                Value::Samples(a)
+            }
+        }
+    }
+}
+
+/// The unit of an intermediate value
+#[derive(Debug, Clone, PartialEq)]
+enum Unit {
+    /// A number, which takes the unit of what it is added to
+    Scalar,
+    Unknown,
+    Known(String),
+}
+
+impl Unit {
+    /// Combines two units, or returns both as text if they cannot be added or subtracted
+    fn combine(self, op: BinaryOp, other: Unit) -> Result<Unit, (String, String)> {
+        Ok(match (op, self, other) {
+            (_, Unit::Scalar, Unit::Scalar) => Unit::Scalar,
+            (_, Unit::Unknown, _) | (_, _, Unit::Unknown) => Unit::Unknown,
+            (BinaryOp::Add | BinaryOp::Sub, Unit::Known(a), Unit::Known(b)) if a != b => return Err((a, b)),
+            (BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul, unit, Unit::Scalar) | (BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul, Unit::Scalar, unit) => unit,
+            (BinaryOp::Div, unit, Unit::Scalar) => unit,
+            (BinaryOp::Div, Unit::Scalar, Unit::Known(b)) if b.is_empty() => Unit::Known(b),
+            (BinaryOp::Div, Unit::Scalar, Unit::Known(b)) => Unit::Known(power(&b, -1)),
+            (BinaryOp::Add | BinaryOp::Sub, unit, _) => unit,
+            (BinaryOp::Mul, Unit::Known(a), Unit::Known(b)) => Unit::Known(match (a.as_str(), b.as_str()) {
+                ("", _) => b,
+                (_, "") => a,
+                _ if a == b => power(&a, 2),
+                _ => format!("{}*{}", a, b),
+            }),
+            (BinaryOp::Div, Unit::Known(a), Unit::Known(b)) => Unit::Known(match (a.as_str(), b.as_str()) {
+                _ if a == b => String::new(),
+                (_, "") => a,
+                ("", _) => power(&b, -1),
+                _ => format!("{}/{}", a, b),
+            }),
+        })
+    }
+
+    fn sqrt(self) -> Unit {
+        match self {
+            Unit::Known(unit) if !unit.is_empty() => Unit::Known(unit.strip_suffix("^2").map_or_else(|| format!("sqrt({})", unit), str::to_string)),
+            unit => unit,
+        }
+    }
+
+    fn powi(self, n: i32) -> Unit {
+        match self {
+            Unit::Known(unit) if !unit.is_empty() => Unit::Known(match n {
+                0 => String::new(),
+                1 => unit,
+                n => power(&unit, n),
+            }),
+            unit => unit,
+        }
+    }
+}
+
+/// Writes a unit raised to a power, in parentheses if it is compound
+fn power(unit: &str, n: i32) -> String {
+    match unit.contains(['*', '/', '^']) {
+        true => format!("({})^{}", unit, n),
+        false => format!("{}^{}", unit, n),
+    }
+}
+
+fn is_plain_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_') && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+/// A recursive descent parser of channel expressions
+struct Parser<'t> {
+    text: &'t str,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<char> {
+        self.text[self.position..].chars().next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    /// Consumes `c` if it is the next character after any whitespace
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_whitespace();
+        let found = self.peek() == Some(c);
+        if found {
+            self.position += c.len_utf8();
+        }
+        found
+    }
+
+    fn error(&self, expected: &str) -> ProcessingError {
+        let found = self.peek().map_or_else(|| "the end".to_string(), |c| format!("'{}'", c));
+        ProcessingError::InvalidParameter(format!("Expected {} at position {} of '{}' but found {}", expected, self.position, self.text, found))
+    }
+
+    fn expression(&mut self) -> Result<ChannelExpr, ProcessingError> {
+        let mut expr = self.term()?;
+        loop {
+            let op = match () {
+                _ if self.eat('+') => BinaryOp::Add,
+                _ if self.eat('-') => BinaryOp::Sub,
+                _ => return Ok(expr),
+            };
+            expr = ChannelExpr::Binary(op, Box::new(expr), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Result<ChannelExpr, ProcessingError> {
+        let mut expr = self.unary()?;
+        loop {
+            let op = match () {
+                _ if self.eat('*') => BinaryOp::Mul,
+                _ if self.eat('/') => BinaryOp::Div,
+                _ => return Ok(expr),
+            };
+            expr = ChannelExpr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<ChannelExpr, ProcessingError> {
+        match self.eat('-') {
+            true => Ok(ChannelExpr::Negate(Box::new(self.unary()?))),
+            false => self.primary(),
+        }
+    }
+
+    fn primary(&mut self) -> Result<ChannelExpr, ProcessingError> {
+        if self.eat('(') {
+            let expr = self.expression()?;
+            return match self.eat(')') {
+                true => Ok(expr),
+                false => Err(self.error("')'")),
+            };
+        }
+        self.skip_whitespace();
+        let start = self.position;
+        match self.peek() {
+            Some('"') => {
+                let length = self.text[start + 1..].find('"').ok_or_else(|| self.error("a closing '\"'"))?;
+                self.position = start + length + 2;
+                Ok(ChannelExpr::Channel(self.text[start + 1..start + 1 + length].to_string()))
+            }
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                while self.peek().is_some_and(|c| c.is_ascii_digit() || c == '.') {
+                    self.position += 1;
+                }
+                // An exponent, e.g. 1e-6
+                let mantissa_end = self.position;
+                if matches!(self.peek(), Some('e' | 'E')) {
+                    self.position += 1;
+                    if matches!(self.peek(), Some('+' | '-')) {
+                        self.position += 1;
+                    }
+                    match self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                        true => {
+                            while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                                self.position += 1;
+                            }
+                        }
+                        false => self.position = mantissa_end,
+                    }
+                }
+                self.text[start..self.position].parse().map(ChannelExpr::Constant).map_err(|_| {
+                    self.position = start;
+                    self.error("a number")
+                })
+            }
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {
+                while self.peek().is_some_and(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.') {
+                    self.position += 1;
+                }
+                let name = &self.text[start..self.position];
+                let after_name = self.position;
+                if !self.eat('(') {
+                    self.position = after_name;
+                    return Ok(ChannelExpr::Channel(name.to_string()));
+                }
+                let argument = self.expression()?;
+                let function = match name {
+                    "abs" => ChannelFunction::Abs,
+                    "sqrt" => ChannelFunction::Sqrt,
+                    "powi" if self.eat(',') => {
+                        self.skip_whitespace();
+                        let exponent_start = self.position;
+                        if self.peek() == Some('-') {
+                            self.position += 1;
+                        }
+                        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+                            self.position += 1;
+                        }
+                        let exponent = self.text[exponent_start..self.position].parse().map_err(|_| {
+                            self.position = exponent_start;
+                            self.error("an integer exponent")
+                        })?;
+                        ChannelFunction::Powi(exponent)
+                    }
+                    "powi" => return Err(self.error("',' and an integer exponent")),
+                    _ => {
+                        self.position = start;
+                        return Err(self.error("a channel name or one of the functions abs, sqrt and powi"));
+                    }
+                };
+                match self.eat(')') {
+                    true => Ok(ChannelExpr::Function(function, Box::new(argument))),
+                    false => Err(self.error("')'")),
+                }
+            }
+            _ => Err(self.error("a number, a channel name or '('")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    fn message(error: ProcessingError) -> String {
+        match error {
+            ProcessingError::InvalidParameter(message) => message,
+            other => panic!("Unexpected error {:?}", other),
+        }
+    }
+
+    fn evaluate(text: &str, context: &ChannelContext) -> Result<(Vec<f64>, Option<String>), ProcessingError> {
+        ChannelExpr::parse(text)?.evaluate(context)
+    }
+
+    #[test]
+    fn operators_apply_sample_by_sample() {
+        let (a, b) = ([1.0, -2.0, 6.0], [4.0, 0.5, -3.0]);
+        assert_eq!(elementwise(&a, &b, BinaryOp::Add).unwrap(), [5.0, -1.5, 3.0]);
+        assert_eq!(elementwise(&a, &b, BinaryOp::Sub).unwrap(), [-3.0, -2.5, 9.0]);
+        assert_eq!(elementwise(&a, &b, BinaryOp::Mul).unwrap(), [4.0, -1.0, -18.0]);
+        assert_eq!(elementwise(&a, &b, BinaryOp::Div).unwrap(), [0.25, -4.0, -2.0]);
+        assert_eq!(scalar(&a, 2.0, BinaryOp::Mul), [2.0, -4.0, 12.0]);
+        assert_eq!(scalar(&a, 2.0, BinaryOp::Sub), [-1.0, -4.0, 4.0]);
+        assert_eq!(scalar(&a, 0.0, BinaryOp::Div)[0], f64::INFINITY);
+        let error = message(elementwise(&a, &b[..2], BinaryOp::Add).unwrap_err());
+        assert!(error.contains("3 samples") && error.contains("has 2"), "{}", error);
+    }
+
+    #[test]
+    fn expressions_evaluate_like_the_operators() {
+        let channels = vec![vec![1.0, 4.0, -2.0], vec![3.0, 1.0, 2.0], vec![9.0, 16.0, 0.25]];
+        let names = names(&["C3", "C4", "EMG 5"]);
+        let context = ChannelContext::new(&channels, &names).unwrap();
+        let value = |text: &str| evaluate(text, &context).unwrap().0;
+        assert_eq!(value("(C3 - C4) * 0.5"), [-1.0, 1.5, -2.0]);
+        assert_eq!(value("abs(C3 - C4)"), [2.0, 3.0, 4.0]);
+        assert_eq!(value("sqrt(\"EMG 5\")"), [3.0, 4.0, 0.5]);
+        assert_eq!(value("powi(C3, 2) + powi(C4, -1)"), [1.0 + 1.0 / 3.0, 17.0, 4.5]);
+        assert_eq!(value("-C3"), [-1.0, -4.0, 2.0]);
+        assert_eq!(value("2 / C4 * 3"), [2.0, 6.0, 3.0]);
+        assert_eq!(value("C3 * 1e-1 + 2.5E1"), [25.1, 25.4, 24.8]);
+        assert_eq!(value("C3 / (C4 - C4)")[0], f64::INFINITY);
+        assert!(value("sqrt(C3)")[2].is_nan());
+    }
+
+    #[test]
+    fn parsing_respects_precedence_and_associativity() {
+        let channel = |name: &str| Box::new(ChannelExpr::Channel(name.to_string()));
+        assert_eq!(
+            ChannelExpr::parse("a - b * 2").unwrap(),
+            ChannelExpr::Binary(BinaryOp::Sub, channel("a"), Box::new(ChannelExpr::Binary(BinaryOp::Mul, channel("b"), Box::new(ChannelExpr::Constant(2.0)))))
+        );
+        assert_eq!(
+            ChannelExpr::parse("a - b - c").unwrap(),
+            ChannelExpr::Binary(BinaryOp::Sub, Box::new(ChannelExpr::Binary(BinaryOp::Sub, channel("a"), channel("b"))), channel("c"))
+        );
+        assert_eq!(
+            ChannelExpr::parse("-a * b").unwrap(),
+            ChannelExpr::Binary(BinaryOp::Mul, Box::new(ChannelExpr::Negate(channel("a"))), channel("b"))
+        );
+        for (text, shown) in [
+            ("(C3 - C4) * 0.5", "(C3 - C4) * 0.5"),
+            ("a - (b - c)", "a - (b - c)"),
+            ("(a - b) - c", "a - b - c"),
+            ("a / (b * c)", "a / (b * c)"),
+            ("-(a + b)", "-(a + b)"),
+            ("  abs( a )+ powi(b,3) ", "abs(a) + powi(b, 3)"),
+            ("\"EMG 5\" * 2", "\"EMG 5\" * 2"),
+            ("ch.1_ref", "ch.1_ref"),
+        ] {
+            let expr = ChannelExpr::parse(text).unwrap();
+            assert_eq!(expr.to_string(), shown);
+            assert_eq!(ChannelExpr::parse(shown).unwrap(), expr, "{}", shown);
+        }
+        assert_eq!(ChannelExpr::parse("a * b + a / c").unwrap().channels(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn malformed_expressions_and_unknown_channels_are_rejected() {
+        for (text, expected) in [
+            ("(a - b", "')'"),
+            ("a - ", "a number, a channel name or '('"),
+            ("a b", "an operator or the end"),
+            ("log(a)", "one of the functions abs, sqrt and powi"),
+            ("powi(a)", "',' and an integer exponent"),
+            ("powi(a, 2.5)", "')'"),
+            ("powi(a, x)", "an integer exponent"),
+            ("\"open", "a closing"),
+            ("1..2", "a number"),
+            ("", "the end"),
+        ] {
+            let error = message(ChannelExpr::parse(text).unwrap_err());
+            assert!(error.contains(expected), "{}: {}", text, error);
+        }
+
+        let channels = vec![vec![1.0], vec![2.0]];
+        let names = names(&["C3", "C4"]);
+        let context = ChannelContext::new(&channels, &names).unwrap();
+        let error = message(evaluate("C3 - Cz", &context).unwrap_err());
+        assert!(error.contains("Unknown channel Cz") && error.contains("C3, C4"), "{}", error);
+        assert!(message(evaluate("2 * 3", &context).unwrap_err()).contains("refers to no channel"));
+        assert!(context.get("Cz").is_err());
+    }
+
+    #[test]
+    fn contexts_name_both_channels_when_lengths_differ() {
+        let channels = vec![vec![1.0, 2.0, 3.0], vec![1.0, 2.0]];
+        let error = message(ChannelContext::new(&channels, &names(&["C3", "C4"])).unwrap_err());
+        assert_eq!(error, "Channel C4 has 2 samples but channel C3 has 3");
+        assert!(ChannelContext::new(&channels, &names(&["C3"])).is_err());
+        let channels = vec![vec![1.0], vec![2.0]];
+        let names = names(&["C3", "C4"]);
+        assert!(ChannelContext::new(&channels, &names).unwrap().with_units(vec![None]).is_err());
+    }
+
+    #[test]
+    fn units_propagate_through_the_operators() {
+        let channels = vec![vec![4.0], vec![1.0], vec![2.0], vec![8.0], vec![1.0]];
+        let names = names(&["C3", "C4", "ref", "time", "raw"]);
+        let units = vec![Some("uV".to_string()), Some("uV".to_string()), Some("mV".to_string()), Some("s".to_string()), None];
+        let context = ChannelContext::new(&channels, &names).unwrap().with_units(units).unwrap();
+        assert_eq!(context.unit("C3").unwrap(), Some("uV"));
+        let unit = |text: &str| evaluate(text, &context).unwrap().1;
+        for (text, expected) in [
+            ("C3 - C4", Some("uV")),
+            ("(C3 + C4) * 0.5", Some("uV")),
+            ("abs(C3 - C4) + 1", Some("uV")),
+            ("-C3 / 2", Some("uV")),
+            ("C3 * C4", Some("uV^2")),
+            ("sqrt(C3 * C4)", Some("uV")),
+            ("powi(C3, 3)", Some("uV^3")),
+            ("powi(C3, 1)", Some("uV")),
+            ("powi(C3, 0)", Some("")),
+            ("C3 / C4", Some("")),
+            ("C3 / C4 * ref", Some("mV")),
+            ("C3 / time", Some("uV/s")),
+            ("C3 * time", Some("uV*s")),
+            ("1 / time", Some("s^-1")),
+            ("powi(C3 / time, 2)", Some("(uV/s)^2")),
+            ("sqrt(ref)", Some("sqrt(mV)")),
+            ("C3 + raw", None),
+            ("C3 + ref * raw", None),
+        ] {
+            assert_eq!(unit(text).as_deref(), expected, "{}", text);
+        }
+        let error = message(evaluate("C3 - ref", &context).unwrap_err());
+        assert_eq!(error, "Cannot subtract C3 [uV] and ref [mV]");
+        assert!(message(evaluate("C3 * C4 + C3", &context).unwrap_err()).contains("[uV^2] and C3 [uV]"));
+    }
+
+    #[test]
+    fn derived_channels_carry_their_provenance() {
+        let channels = vec![vec![1.0, 4.0], vec![3.0, 1.0]];
+        let names = names(&["C5", "C6"]);
+        let context = ChannelContext::new(&channels, &names).unwrap().with_units(vec![Some("uV".to_string()); 2]).unwrap();
+        let derived = derive_channel(&context, "EMG", &ChannelExpr::parse("abs(C5-C6)").unwrap()).unwrap();
+        assert_eq!(derived, DerivedChannel { name: "EMG".to_string(), samples: vec![2.0, 3.0], unit: Some("uV".to_string()), provenance: "derived: abs(C5 - C6)".to_string() });
+
+        let mean = derive_channel_with(&context, "mean", "mean of C5 and C6", Some("uV"), |context| {
+            Ok(elementwise(context.get("C5")?, context.get("C6")?, BinaryOp::Add)?.iter().map(|x| x / 2.0).collect())
+        })
+        .unwrap();
+        assert_eq!((mean.samples, mean.provenance), (vec![2.0, 2.5], "derived: mean of C5 and C6".to_string()));
+        let error = message(derive_channel_with(&context, "short", "", None, |_| Ok(vec![0.0])).unwrap_err());
+        assert_eq!(error, "Derived channel short has 1 samples but the channels have 2");
+        assert!(derive_channel_with(&context, "missing", "", None, |context| Ok(context.get("Cz")?.to_vec())).is_err());
+    }
+}
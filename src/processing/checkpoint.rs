@@ -0,0 +1,560 @@
+// A module to checkpoint long-running pipelines so that they can resume after a failure
+
+// Written by Amin Alam in 2024
+
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+/// The version of the checkpoint format, increased whenever its fields change
+pub const CHECKPOINT_VERSION: u64 = 1;
+
+/// The number of bytes at the start of an input hashed into its identity
+pub const HEAD_BYTES: usize = 1 << 20;
+
+/// The identity of the input of a checkpointed run, used to detect a changed input on resume
+///
+/// # Arguments
+///
+/// * `path` - The canonical path of the input file, or None for channels held in memory
+/// * `size` - The size of the input in bytes
+/// * `modified` - The modification time of the input file in nanoseconds since the Unix epoch, or 0 for channels held in memory
+/// * `head_hash` - The FNV-1a hash of the first `HEAD_BYTES` bytes of the input
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputIdentity {
+    pub path: Option<String>,
+    pub size: u64,
+    pub modified: u64,
+    pub head_hash: u64,
+}
+
+/// Implementation of the InputIdentity struct
+///
+/// # Methods
+///
+/// * `of_file` - Computes the identity of a file
+impl InputIdentity {
+    /// Computes the identity of a file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file
+    ///
+    /// # Returns
+    ///
+    /// The InputIdentity, or an error if the file cannot be read
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let identity = InputIdentity::of_file(Path::new("wideband.csv"))?;
+    /// ```
+    ///
+    pub fn of_file(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_nanos() as u64);
+        let mut head = Vec::with_capacity(HEAD_BYTES);
+        File::open(path)?.take(HEAD_BYTES as u64).read_to_end(&mut head)?;
+        let mut hasher = Fnv::new();
+        hasher.write(&head);
+        Ok(Self {
+            path: Some(fs::canonicalize(path)?.to_string_lossy().into_owned()),
+            size: metadata.len(),
+            modified,
+            head_hash: hasher.finish(),
+        })
+    }
+
+    /// Computes the identity of channels held in memory from the bytes of their samples
+    pub(crate) fn of_channels(channels: &[Vec<f64>]) -> Self {
+        let mut hasher = Fnv::new();
+        let mut hashed = 0;
+        for sample in channels.iter().flatten() {
+            if hashed >= HEAD_BYTES {
+                break;
+            }
+            hasher.write(&sample.to_le_bytes());
+            hashed += 8;
+        }
+        let size = channels.iter().map(|channel| channel.len() as u64 * 8).sum();
+        Self { path: None, size, modified: 0, head_hash: hasher.finish() }
+    }
+}
+
+/// The position of a checkpointed run after the last chunk that was fully written
+///
+/// # Arguments
+///
+/// * `input` - The identity of the input
+/// * `config_hash` - The hash of the channels, sampling rates, chunk size and stages of the pipeline
+/// * `input_offset` - The byte offset of the next CSV record, or the index of the next sample for channels held in memory
+/// * `input_line` - The line number of the next CSV record, or 0 for channels held in memory
+/// * `samples_read` - The number of samples per channel read from the input
+/// * `samples_written` - The number of samples per channel written to the output
+/// * `output_offset` - The length of the output in bytes
+/// * `states` - The state of each stage, as returned by `Stage::state`
+///
+/// # Examples
+///
+/// ```
+/// let checkpoint = Checkpoint::load("lfp.csv.checkpoint")?;
+/// println!("{} samples were written before the failure", checkpoint.samples_written);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checkpoint {
+    pub input: InputIdentity,
+    pub config_hash: u64,
+    pub input_offset: u64,
+    pub input_line: u64,
+    pub samples_read: usize,
+    pub samples_written: usize,
+    pub output_offset: u64,
+    pub states: Vec<Vec<f64>>,
+}
+
+/// Implementation of the Checkpoint struct
+///
+/// # Methods
+///
+/// * `save` - Writes the Checkpoint as a JSON file, replacing any earlier one atomically
+/// * `load` - Reads a Checkpoint written by `save`
+impl Checkpoint {
+    /// Writes the Checkpoint as a JSON file, replacing any earlier one atomically
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the JSON file
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or the error of writing or renaming the file
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// checkpoint.save("lfp.csv.checkpoint")?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The file is written next to the target with a `.tmp` suffix, synced and then renamed
+    /// over the target, so a crash while saving leaves the previous checkpoint intact. The
+    /// hashes and the stage states are written as hexadecimal strings, the states as the bits
+    /// of each f64, so that they are restored exactly, NaN included.
+    ///
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let path = path.as_ref();
+        let states: Vec<String> = self
+            .states
+            .iter()
+            .map(|state| format!("[{}]", state.iter().map(|value| format!("\"{:016x}\"", value.to_bits())).collect::<Vec<_>>().join(",")))
+            .collect();
+        let input_path = self.input.path.as_deref().map_or("null".to_string(), quote);
+        let json = format!(
+            "{{\"version\":{},\"input\":{{\"path\":{},\"size\":{},\"modified\":{},\"head_hash\":\"{:016x}\"}},\"config_hash\":\"{:016x}\",\
+             \"input_offset\":{},\"input_line\":{},\"samples_read\":{},\"samples_written\":{},\"output_offset\":{},\"states\":[{}]}}\n",
+            CHECKPOINT_VERSION,
+            input_path,
+            self.input.size,
+            self.input.modified,
+            self.input.head_hash,
+            self.config_hash,
+            self.input_offset,
+            self.input_line,
+            self.samples_read,
+            self.samples_written,
+            self.output_offset,
+            states.join(","),
+        );
+
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        writer.write_all(json.as_bytes())?;
+        writer.into_inner().map_err(|error| error.into_error())?.sync_all()?;
+        fs::rename(&temporary, path)
+    }
+
+    /// Reads a Checkpoint written by `save`
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the JSON file
+    ///
+    /// # Returns
+    ///
+    /// The Checkpoint, or an error if the file cannot be read, was written with another
+    /// format version or does not hold a valid Checkpoint
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let checkpoint = Checkpoint::load("lfp.csv.checkpoint")?;
+    /// ```
+    ///
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut text = String::new();
+        BufReader::new(File::open(path)?).read_to_string(&mut text)?;
+        let mut parser = Parser { bytes: text.as_bytes(), position: 0, source: "the checkpoint" };
+        let value = parser.value()?;
+        parser.skip_whitespace();
+        if parser.position != text.len() {
+            return Err(invalid("Trailing characters after the checkpoint"));
+        }
+
+        let version = value.field("version")?.number()?;
+        if version != CHECKPOINT_VERSION {
+            return Err(invalid(&format!("Checkpoint has version {} but version {} is expected", version, CHECKPOINT_VERSION)));
+        }
+        let input = value.field("input")?;
+        let input = InputIdentity {
+            path: match input.field("path")? {
+                Json::Null => None,
+                path => Some(path.string()?.to_string()),
+            },
+            size: input.field("size")?.number()?,
+            modified: input.field("modified")?.number()?,
+            head_hash: input.field("head_hash")?.hex()?,
+        };
+        let states = value
+            .field("states")?
+            .array()?
+            .iter()
+            .map(|state| state.array()?.iter().map(|value| Ok(f64::from_bits(value.hex()?))).collect())
+            .collect::<io::Result<Vec<Vec<f64>>>>()?;
+        Ok(Self {
+            input,
+            config_hash: value.field("config_hash")?.hex()?,
+            input_offset: value.field("input_offset")?.number()?,
+            input_line: value.field("input_line")?.number()?,
+            samples_read: value.field("samples_read")?.number()? as usize,
+            samples_written: value.field("samples_written")?.number()? as usize,
+            output_offset: value.field("output_offset")?.number()?,
+            states,
+        })
+    }
+}
+
+/// The 64-bit FNV-1a hash, stable across platforms and Rust versions unlike `DefaultHasher`
+pub(crate) struct Fnv(u64);
+
+impl Fnv {
+    pub(crate) fn new() -> Self {
+        Self(0xcbf29ce484222325)
+    }
+
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x100000001b3);
+        }
+    }
+
+    pub(crate) fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Writes a string as a JSON string literal
+pub(crate) fn quote(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The subset of JSON that checkpoints are written in: no floats and no booleans
+enum Json {
+    Null,
+    Number(u64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn field(&self, name: &str) -> io::Result<&Json> {
+        match self {
+            Json::Object(fields) => fields
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value)
+                .ok_or_else(|| invalid(&format!("Checkpoint has no field {}", name))),
+            _ => Err(invalid("Expected an object in the checkpoint")),
+        }
+    }
+
+    fn number(&self) -> io::Result<u64> {
+        match self {
+            Json::Number(number) => Ok(*number),
+            _ => Err(invalid("Expected a number in the checkpoint")),
+        }
+    }
+
+    fn string(&self) -> io::Result<&str> {
+        match self {
+            Json::String(string) => Ok(string),
+            _ => Err(invalid("Expected a string in the checkpoint")),
+        }
+    }
+
+    fn hex(&self) -> io::Result<u64> {
+        u64::from_str_radix(self.string()?, 16).map_err(|_| invalid("Expected a hexadecimal string in the checkpoint"))
+    }
+
+    fn array(&self) -> io::Result<&[Json]> {
+        match self {
+            Json::Array(values) => Ok(values),
+            _ => Err(invalid("Expected an array in the checkpoint")),
+        }
+    }
+}
+
+struct Parser<'t> {
+    bytes: &'t [u8],
+    position: usize,
+    /// What is parsed, named in the errors, e.g. `the checkpoint`
+    source: &'t str,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.bytes.get(self.position).is_some_and(|byte| byte.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> io::Result<()> {
+        self.skip_whitespace();
+        if self.bytes.get(self.position) != Some(&byte) {
+            return Err(invalid(&format!("Expected {:?} at byte {} of {}", byte as char, self.position, self.source)));
+        }
+        self.position += 1;
+        Ok(())
+    }
+
+    /// Parses a comma-separated list up to `close`, calling `item` for each element
+    fn list<F: FnMut(&mut Self) -> io::Result<()>>(&mut self, close: u8, mut item: F) -> io::Result<()> {
+        self.skip_whitespace();
+        if self.bytes.get(self.position) == Some(&close) {
+            self.position += 1;
+            return Ok(());
+        }
+        loop {
+            item(self)?;
+            self.skip_whitespace();
+            match self.bytes.get(self.position) {
+                Some(b',') => self.position += 1,
+                Some(&byte) if byte == close => {
+                    self.position += 1;
+                    return Ok(());
+                }
+                _ => return Err(invalid(&format!("Expected ',' or {:?} at byte {} of {}", close as char, self.position, self.source))),
+            }
+        }
+    }
+
+    fn value(&mut self) -> io::Result<Json> {
+        self.skip_whitespace();
+        match self.bytes.get(self.position) {
+            Some(b'{') => {
+                self.position += 1;
+                let mut fields = Vec::new();
+                self.list(b'}', |parser| {
+                    parser.skip_whitespace();
+                    let key = parser.string()?;
+                    parser.expect(b':')?;
+                    fields.push((key, parser.value()?));
+                    Ok(())
+                })?;
+                Ok(Json::Object(fields))
+            }
+            Some(b'[') => {
+                self.position += 1;
+                let mut values = Vec::new();
+                self.list(b']', |parser| {
+                    values.push(parser.value()?);
+                    Ok(())
+                })?;
+                Ok(Json::Array(values))
+            }
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b'n') if self.bytes[self.position..].starts_with(b"null") => {
+                self.position += 4;
+                Ok(Json::Null)
+            }
+            Some(byte) if byte.is_ascii_digit() => {
+                let start = self.position;
+                while self.bytes.get(self.position).is_some_and(|byte| byte.is_ascii_digit()) {
+                    self.position += 1;
+                }
+                let digits = std::str::from_utf8(&self.bytes[start..self.position]).unwrap_or_default();
+                digits.parse().map(Json::Number).map_err(|_| invalid(&format!("Number {} is out of range in {}", digits, self.source)))
+            }
+            _ => Err(invalid(&format!("Unexpected character at byte {} of {}", self.position, self.source))),
+        }
+    }
+
+    fn string(&mut self) -> io::Result<String> {
+        self.expect(b'"')?;
+        let mut bytes = Vec::new();
+        loop {
+            match self.bytes.get(self.position) {
+                None => return Err(invalid(&format!("Unterminated string in {}", self.source))),
+                Some(b'"') => {
+                    self.position += 1;
+                    break;
+                }
+                Some(b'\\') => {
+                    let escape = self.bytes.get(self.position + 1).copied();
+                    self.position += 2;
+                    match escape {
+                        Some(b'"') => bytes.push(b'"'),
+                        Some(b'\\') => bytes.push(b'\\'),
+                        Some(b'/') => bytes.push(b'/'),
+                        Some(b'n') => bytes.push(b'\n'),
+                        Some(b't') => bytes.push(b'\t'),
+                        Some(b'r') => bytes.push(b'\r'),
+                        Some(b'b') => bytes.push(0x08),
+                        Some(b'f') => bytes.push(0x0c),
+                        Some(b'u') => {
+                            let code = self.unicode_escape()?;
+                            bytes.extend_from_slice(code.encode_utf8(&mut [0; 4]).as_bytes());
+                        }
+                        _ => return Err(invalid(&format!("Invalid escape in {}", self.source))),
+                    }
+                }
+                Some(&byte) => {
+                    bytes.push(byte);
+                    self.position += 1;
+                }
+            }
+        }
+        String::from_utf8(bytes).map_err(|_| invalid(&format!("Invalid UTF-8 in {}", self.source)))
+    }
+
+    /// Reads the four hex digits after `\\u`, and a second escape if they are a high surrogate
+    fn unicode_escape(&mut self) -> io::Result<char> {
+        let (start, source) = (self.position, self.source);
+        let error = || invalid(&format!("Invalid \\u escape at byte {} of {}", start, source));
+        let hex = |parser: &Self| {
+            let digits = parser.bytes.get(parser.position..parser.position + 4)?;
+            u32::from_str_radix(std::str::from_utf8(digits).ok()?, 16).ok()
+        };
+        let code = hex(self).ok_or_else(error)?;
+        self.position += 4;
+        if !(0xD800..0xDC00).contains(&code) {
+            return char::from_u32(code).ok_or_else(error);
+        }
+        // Characters beyond the Basic Multilingual Plane are written as two escapes
+        if !self.bytes[self.position..].starts_with(b"\\u") {
+            return Err(error());
+        }
+        self.position += 2;
+        let low = hex(self).filter(|low| (0xDC00..0xE000).contains(low)).ok_or_else(error)?;
+        self.position += 4;
+        char::from_u32(0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00)).ok_or_else(error)
+    }
+
+    /// Reads a string, number, boolean or null as its text, with null as None
+    #[cfg(any(feature = "cli", test))]
+    fn scalar(&mut self) -> io::Result<Option<String>> {
+        self.skip_whitespace();
+        let rest = &self.bytes[self.position..];
+        for literal in ["null", "true", "false"] {
+            if rest.starts_with(literal.as_bytes()) {
+                self.position += literal.len();
+                return Ok((literal != "null").then(|| literal.to_string()));
+            }
+        }
+        match rest.first() {
+            Some(b'"') => self.string().map(Some),
+            Some(&byte) if byte == b'-' || byte.is_ascii_digit() => {
+                let length = rest.iter().take_while(|byte| byte.is_ascii_digit() || b"+-.eE".contains(byte)).count();
+                let number = std::str::from_utf8(&rest[..length]).unwrap_or_default();
+                if number.parse::<f64>().is_err() {
+                    return Err(invalid(&format!("Invalid number {} at byte {} of {}", number, self.position, self.source)));
+                }
+                self.position += length;
+                Ok(Some(number.to_string()))
+            }
+            Some(b'{' | b'[') => Err(invalid(&format!("Nested objects and arrays are not supported, at byte {} of {}", self.position, self.source))),
+            _ => Err(invalid(&format!("Unexpected character at byte {} of {}", self.position, self.source))),
+        }
+    }
+}
+
+/// Parses a JSON object whose values are all strings, numbers, booleans or null
+///
+/// # Arguments
+///
+/// * `text` - The JSON text, e.g. one line of a JSON Lines file
+/// * `source` - What the text is, named in the errors, e.g. `line 3 of trials.jsonl`
+///
+/// # Returns
+///
+/// The keys and values in order, numbers as written and null as None, or an `InvalidData`
+/// error if the text is not such an object or a key repeats
+#[cfg(any(feature = "cli", test))]
+pub(crate) fn parse_flat_object(text: &str, source: &str) -> io::Result<Vec<(String, Option<String>)>> {
+    let mut parser = Parser { bytes: text.as_bytes(), position: 0, source };
+    parser.expect(b'{')?;
+    let mut fields: Vec<(String, Option<String>)> = Vec::new();
+    parser.list(b'}', |parser| {
+        parser.skip_whitespace();
+        let key = parser.string()?;
+        if fields.iter().any(|(other, _)| *other == key) {
+            return Err(invalid(&format!("Key {} appears twice in {}", quote(&key), parser.source)));
+        }
+        parser.expect(b':')?;
+        fields.push((key, parser.scalar()?));
+        Ok(())
+    })?;
+    parser.skip_whitespace();
+    if parser.position != text.len() {
+        return Err(invalid(&format!("Trailing characters after the object in {}", source)));
+    }
+    Ok(fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quoted_strings_read_back_unchanged() {
+        for text in ["plain", "say \"hi\"", "C:\\data\\s01", "two\nlines\r\n\tindented", "bell \u{7} and é"] {
+            let quoted = quote(text);
+            assert!(!quoted.chars().any(|c| (c as u32) < 0x20), "{:?}", quoted);
+            assert_eq!(Parser { bytes: quoted.as_bytes(), position: 0, source: "the test" }.string().unwrap(), text);
+        }
+        assert_eq!(quote("a\nb\u{1}"), "\"a\\nb\\u0001\"");
+    }
+
+    #[test]
+    fn flat_objects_keep_their_order_and_the_text_of_numbers() {
+        let fields = parse_flat_object(r#" {"t": 1.50, "label":"go\ud83d\ude00", "ok":true, "note":null, "n":-2e3} "#, "the test").unwrap();
+        let expected = [("t", Some("1.50")), ("label", Some("go\u{1F600}")), ("ok", Some("true")), ("note", None), ("n", Some("-2e3"))];
+        assert_eq!(fields, expected.map(|(key, value)| (key.to_string(), value.map(str::to_string))));
+        assert_eq!(parse_flat_object("{}", "the test").unwrap(), vec![]);
+        for text in [r#"{"a":[1]}"#, r#"{"a":1,"a":2}"#, r#"{"a":1} x"#, r#"{"a":1.2.3}"#, r#"{"a":"\ud83d"}"#, "[1]"] {
+            let error = parse_flat_object(text, "line 7").unwrap_err();
+            assert!(error.to_string().contains("line 7"), "{}", error);
+        }
+    }
+}
@@ -0,0 +1,289 @@
+// A module to remove line noise without notch filters, by sliding-window sinusoidal regression
+
+// Written by Amin Alam in 2024
+
+use std::f64::consts::PI;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::{validate_frequency, validate_sampling_rate};
+use crate::processing::linalg::least_squares;
+
+/// The options of `remove_line_noise_clean`
+///
+/// # Arguments
+///
+/// * `n_harmonics` - The number of multiples of the line frequency to remove, including the line frequency itself, 1 by default
+/// * `window` - The length of the regression windows in seconds, 2 by default
+/// * `step` - The time between the starts of consecutive windows in seconds, 0.5 by default
+///
+/// # Examples
+///
+/// ```
+/// let options = CleanLineOptions { n_harmonics: 3, ..CleanLineOptions::default() };
+/// ```
+///
+/// # Note
+///
+/// The amplitude and phase of the line noise are assumed constant within a window, so
+/// shorter windows follow faster changes, while longer windows separate the line from
+/// nearby frequencies better: a component `df` Hz away is left untouched as long as
+/// `df * window` is well above 1.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CleanLineOptions {
+    pub n_harmonics: usize,
+    pub window: f64,
+    pub step: f64,
+}
+
+impl Default for CleanLineOptions {
+    fn default() -> Self {
+        Self { n_harmonics: 1, window: 2.0, step: 0.5 }
+    }
+}
+
+/// Removes line noise and its harmonics by fitting and subtracting sinusoids
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `base_frequency` - The line frequency in Hz (usually 50 or 60)
+/// * `options` - The number of harmonics and the length and step of the regression windows
+///
+/// # Returns
+///
+/// The cleaned samples, or an error if the frequencies are invalid, there is no harmonic to
+/// remove, or the window is shorter than two periods of the line or the step is not between
+/// zero and the window
+///
+/// # Examples
+///
+/// ```
+/// let cleaned = remove_line_noise_clean(&lfp, 1000.0, 50.0, &CleanLineOptions::default())?;
+/// let coherence = coherence(&cleaned, &reference, 1000.0, 1024, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW, false)?;
+/// ```
+///
+/// # Note
+///
+/// In every window, a cosine and a sine at each harmonic are fitted to the Hann-weighted
+/// samples by least squares, and the fits of the overlapping windows are blended with the
+/// same weights into a line noise estimate with a slowly varying amplitude and phase, which
+/// is subtracted from the signal. Unlike `remove_line_noise`, the rest of the spectrum keeps
+/// its amplitude and phase, including frequencies a few Hz from the line, so it suits phase
+/// and connectivity analyses. Harmonics at or above the Nyquist frequency are skipped. A
+/// signal shorter than the window is fitted as a single window.
+///
+pub fn remove_line_noise_clean(samples: &[f64], sampling_rate: f64, base_frequency: f64, options: &CleanLineOptions) -> Result<Vec<f64>, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    validate_frequency(base_frequency, sampling_rate)?;
+    if options.n_harmonics == 0 {
+        return Err(ProcessingError::InvalidParameter("At least one harmonic must be removed".to_string()));
+    }
+    if !(options.window * base_frequency >= 2.0 && options.window.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "The window must cover at least two periods of the {} Hz line, got {} s",
+            base_frequency, options.window
+        )));
+    }
+    if options.step.is_nan() || options.step <= 0.0 || options.step > options.window {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "The step must be positive and at most the window of {} s, got {} s",
+            options.window, options.step
+        )));
+    }
+    let frequencies: Vec<f64> = (1..=options.n_harmonics)
+        .map(|harmonic| base_frequency * harmonic as f64)
+        .take_while(|&frequency| frequency < sampling_rate / 2.0)
+        .collect();
+    let n = samples.len();
+    let window_len = ((options.window * sampling_rate).round() as usize).min(n);
+    let step_len = ((options.step * sampling_rate).round() as usize).max(1);
+    if window_len < 2 * frequencies.len() + 1 {
+        return Ok(samples.to_vec());
+    }
+
+    // Windows start every step and the last one ends at the last sample
+    let mut starts: Vec<usize> = (0..=n - window_len).step_by(step_len).collect();
+    if starts.last() != Some(&(n - window_len)) {
+        starts.push(n - window_len);
+    }
+    // A Hann window sampled between its zeros, so that even the first and last sample have weight
+    let weights: Vec<f64> = (0..window_len).map(|k| 0.5 - 0.5 * (2.0 * PI * (k as f64 + 0.5) / window_len as f64).cos()).collect();
+    let roots: Vec<f64> = weights.iter().map(|weight| weight.sqrt()).collect();
+
+    let mut estimate = vec![0.0; n];
+    let mut total_weight = vec![0.0; n];
+    for &start in &starts {
+        // Phases are taken from the absolute sample index so that the fits of all windows agree
+        let basis: Vec<Vec<f64>> = frequencies
+            .iter()
+            .flat_map(|&frequency| {
+                let omega = 2.0 * PI * frequency / sampling_rate;
+                let cosine = (start..start + window_len).map(move |t| (omega * t as f64).cos()).collect();
+                let sine = (start..start + window_len).map(move |t| (omega * t as f64).sin()).collect();
+                [cosine, sine]
+            })
+            .collect();
+        let columns: Vec<Vec<f64>> = basis.iter().map(|column| column.iter().zip(&roots).map(|(value, root)| value * root).collect()).collect();
+        let target: Vec<f64> = samples[start..start + window_len].iter().zip(&roots).map(|(sample, root)| sample * root).collect();
+        let Some(coefficients) = least_squares(&columns, &target) else {
+            continue;
+        };
+        for k in 0..window_len {
+            let fit: f64 = basis.iter().zip(&coefficients).map(|(column, coefficient)| column[k] * coefficient).sum();
+            estimate[start + k] += weights[k] * fit;
+            total_weight[start + k] += weights[k];
+        }
+    }
+    Ok(samples
+        .iter()
+        .zip(estimate.iter().zip(&total_weight))
+        .map(|(sample, (fit, weight))| if *weight > 0.0 { sample - fit / weight } else { *sample })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::filter::{line_noise_filter, remove_line_noise, DEFAULT_NOTCH_Q};
+    use crate::processing::random::SeededRng;
+    use crate::processing::spectral::{coherence, welch};
+    use crate::processing::window::Window;
+
+    const SAMPLING_RATE: f64 = 1000.0;
+    const N_SAMPLES: usize = 60_000;
+
+    /// Pink noise from white noise through Kellet's filter
+    fn pink_noise(seed: u64) -> Vec<f64> {
+        let mut rng = SeededRng::new(seed);
+        let mut state = [0.0; 7];
+        (0..N_SAMPLES)
+            .map(|_| {
+                let white = rng.next_gaussian();
+                state[0] = 0.99886 * state[0] + white * 0.0555179;
+                state[1] = 0.99332 * state[1] + white * 0.0750759;
+                state[2] = 0.96900 * state[2] + white * 0.1538520;
+                state[3] = 0.86650 * state[3] + white * 0.3104856;
+                state[4] = 0.55000 * state[4] + white * 0.5329522;
+                state[5] = -0.7616 * state[5] - white * 0.0168980;
+                let pink = state[..6].iter().sum::<f64>() + state[6] + white * 0.5362;
+                state[6] = white * 0.115926;
+                0.1 * pink
+            })
+            .collect()
+    }
+
+    fn sine(frequency: f64, amplitude: f64, phase: f64) -> Vec<f64> {
+        (0..N_SAMPLES).map(|k| amplitude * (2.0 * PI * frequency * k as f64 / SAMPLING_RATE + phase).sin()).collect()
+    }
+
+    fn add(a: &[f64], b: &[f64]) -> Vec<f64> {
+        a.iter().zip(b).map(|(x, y)| x + y).collect()
+    }
+
+    /// Line noise whose amplitude swings between 0.5 and 1.5 and whose phase wanders
+    fn nonstationary_line() -> Vec<f64> {
+        (0..N_SAMPLES)
+            .map(|k| {
+                let t = k as f64 / SAMPLING_RATE;
+                let amplitude = 1.0 + 0.5 * (2.0 * PI * 0.05 * t).sin();
+                amplitude * (2.0 * PI * 50.0 * t + 0.8 * (2.0 * PI * 0.02 * t).sin()).sin()
+            })
+            .collect()
+    }
+
+    /// The Fourier coefficient of the whole signal at a frequency, as (real, imaginary)
+    fn fourier(samples: &[f64], frequency: f64) -> (f64, f64) {
+        let omega = 2.0 * PI * frequency / SAMPLING_RATE;
+        samples.iter().enumerate().fold((0.0, 0.0), |(re, im), (k, x)| (re + x * (omega * k as f64).cos(), im - x * (omega * k as f64).sin()))
+    }
+
+    /// The gain and the phase shift in degrees of `processed` against `truth` at a frequency
+    fn gain_and_phase(processed: &[f64], truth: &[f64], frequency: f64) -> (f64, f64) {
+        let (a, b) = (fourier(processed, frequency), fourier(truth, frequency));
+        let gain = (a.0.hypot(a.1)) / b.0.hypot(b.1);
+        let phase = (a.1.atan2(a.0) - b.1.atan2(b.0) + PI).rem_euclid(2.0 * PI) - PI;
+        (gain, phase.to_degrees())
+    }
+
+    fn power_at(samples: &[f64], frequency: f64) -> f64 {
+        let spectrum = welch(samples, SAMPLING_RATE, 1000, 0.5, Window::Hann).unwrap();
+        spectrum.power[frequency as usize]
+    }
+
+    fn coherence_at(a: &[f64], b: &[f64], frequency: f64) -> f64 {
+        coherence(a, b, SAMPLING_RATE, 1000, 0.5, Window::Hann, false).unwrap().coherence[frequency as usize]
+    }
+
+    #[test]
+    fn cleaning_removes_a_nonstationary_line_and_keeps_a_45_hz_component() {
+        let test_component = sine(45.0, 0.3, 0.4);
+        let truth = add(&pink_noise(1), &test_component);
+        let recorded = add(&truth, &nonstationary_line());
+        let reference = add(&pink_noise(2), &sine(45.0, 0.3, 1.2));
+        let cleaned = remove_line_noise_clean(&recorded, SAMPLING_RATE, 50.0, &CleanLineOptions::default()).unwrap();
+        let notched = remove_line_noise(&recorded, SAMPLING_RATE, 50.0, 1).unwrap();
+        let causal = line_noise_filter(50.0, 1, DEFAULT_NOTCH_Q, SAMPLING_RATE).unwrap().apply(&recorded);
+
+        let drop = |processed: &[f64]| 10.0 * (power_at(&recorded, 50.0) / power_at(processed, 50.0)).log10();
+        assert!(drop(&cleaned) > 30.0, "{}", drop(&cleaned));
+
+        // The 45 Hz component keeps its amplitude and phase, and its coherence with the reference
+        let (gain, phase) = gain_and_phase(&cleaned, &truth, 45.0);
+        assert!((gain - 1.0).abs() < 0.005 && phase.abs() < 0.5, "{} {}", gain, phase);
+        let coherence_truth = coherence_at(&truth, &reference, 45.0);
+        assert!(coherence_truth > 0.95);
+        assert!((coherence_at(&cleaned, &reference, 45.0) - coherence_truth).abs() < 1e-3);
+
+        // The zero-phase notch loses 2.4% of the amplitude, and the causal notch shifts it by about 9 degrees
+        let (notch_gain, notch_phase) = gain_and_phase(&notched, &truth, 45.0);
+        assert!(notch_gain < 0.985 && notch_phase.abs() < 0.5, "{} {}", notch_gain, notch_phase);
+        let (_, causal_phase) = gain_and_phase(&causal, &truth, 45.0);
+        assert!(causal_phase < -5.0, "{}", causal_phase);
+    }
+
+    #[test]
+    fn a_component_2_hz_from_the_line_survives_where_the_notch_removes_most_of_it() {
+        let near = sine(48.0, 0.5, 0.0);
+        let recorded = add(&near, &sine(50.0, 2.0, 1.0));
+        let cleaned = remove_line_noise_clean(&recorded, SAMPLING_RATE, 50.0, &CleanLineOptions::default()).unwrap();
+        let notched = remove_line_noise(&recorded, SAMPLING_RATE, 50.0, 1).unwrap();
+        let (gain, phase) = gain_and_phase(&cleaned, &near, 48.0);
+        assert!((gain - 1.0).abs() < 1e-6 && phase.abs() < 1e-6, "{} {}", gain, phase);
+        // Windows of whole periods of both frequencies make the Hann-weighted fits blind to 48 Hz
+        let residual = cleaned.iter().zip(&near).map(|(x, y)| (x - y).powi(2)).sum::<f64>() / N_SAMPLES as f64;
+        assert!(residual.sqrt() < 1e-6, "{}", residual.sqrt());
+        assert!(gain_and_phase(&notched, &near, 48.0).0 < 0.9);
+    }
+
+    #[test]
+    fn harmonics_are_removed_and_options_are_checked() {
+        let truth = pink_noise(3);
+        let mains: Vec<f64> = add(&sine(60.0, 1.0, 0.2), &add(&sine(180.0, 0.5, 2.0), &sine(300.0, 0.25, -1.0)));
+        let recorded = add(&truth, &mains);
+        let options = CleanLineOptions { n_harmonics: 5, ..CleanLineOptions::default() };
+        let cleaned = remove_line_noise_clean(&recorded, SAMPLING_RATE, 60.0, &options).unwrap();
+        for frequency in [60.0, 180.0, 300.0] {
+            let drop = 10.0 * (power_at(&recorded, frequency) / power_at(&cleaned, frequency)).log10();
+            assert!(drop > 20.0, "{} Hz: {} dB", frequency, drop);
+        }
+        // Only the fundamental is removed by default
+        let fundamental = remove_line_noise_clean(&recorded, SAMPLING_RATE, 60.0, &CleanLineOptions::default()).unwrap();
+        assert!((power_at(&fundamental, 180.0) / power_at(&recorded, 180.0) - 1.0).abs() < 0.01);
+
+        // A signal shorter than the window is fitted as one window
+        let short: Vec<f64> = recorded[..300].to_vec();
+        let cleaned = remove_line_noise_clean(&short, SAMPLING_RATE, 60.0, &options).unwrap();
+        assert_eq!(cleaned.len(), 300);
+        assert!(cleaned.iter().zip(&truth[..300]).all(|(x, y)| (x - y).abs() < 0.5));
+
+        let default = CleanLineOptions::default();
+        assert!(remove_line_noise_clean(&recorded, SAMPLING_RATE, 0.0, &default).is_err());
+        assert!(remove_line_noise_clean(&recorded, SAMPLING_RATE, 600.0, &default).is_err());
+        assert!(remove_line_noise_clean(&recorded, 0.0, 50.0, &default).is_err());
+        assert!(remove_line_noise_clean(&recorded, SAMPLING_RATE, 50.0, &CleanLineOptions { n_harmonics: 0, ..default }).is_err());
+        assert!(remove_line_noise_clean(&recorded, SAMPLING_RATE, 50.0, &CleanLineOptions { window: 0.03, ..default }).is_err());
+        assert!(remove_line_noise_clean(&recorded, SAMPLING_RATE, 50.0, &CleanLineOptions { step: 3.0, ..default }).is_err());
+        assert!(remove_line_noise_clean(&recorded, SAMPLING_RATE, 50.0, &CleanLineOptions { step: 0.0, ..default }).is_err());
+    }
+}
@@ -0,0 +1,267 @@
+// A module to cluster feature vectors, e.g. to sort spikes into units
+
+// Written by Amin Alam in 2024
+
+use crate::processing::decomposition::check_row_lengths;
+use crate::processing::error::ProcessingError;
+use crate::processing::random::SeededRng;
+
+/// Options of `kmeans`
+///
+/// # Arguments
+///
+/// * `n_init` - The number of restarts from different k-means++ initializations, 10 by default
+/// * `max_iterations` - The maximum number of Lloyd iterations per restart, 300 by default
+/// * `tolerance` - The convergence threshold on the squared centroid shift, relative to the mean feature variance, 1e-4 by default
+/// * `seed` - The seed of the random initializations, 0 by default
+///
+/// # Examples
+///
+/// ```
+/// let options = KMeansOptions { seed: 42, ..KMeansOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KMeansOptions {
+    pub n_init: usize,
+    pub max_iterations: usize,
+    pub tolerance: f64,
+    pub seed: u64,
+}
+
+impl Default for KMeansOptions {
+    fn default() -> Self {
+        Self { n_init: 10, max_iterations: 300, tolerance: 1e-4, seed: 0 }
+    }
+}
+
+/// The best clustering found by `kmeans`
+///
+/// # Arguments
+///
+/// * `labels` - The cluster of each observation
+/// * `centroids` - The mean of each cluster
+/// * `inertia` - The sum of squared distances of the observations to their centroids
+/// * `counts` - The number of observations in each cluster
+/// * `iterations` - The number of Lloyd iterations of the best restart
+///
+/// # Examples
+///
+/// ```
+/// let result = kmeans(&features.scores, 3, &KMeansOptions::default())?;
+/// let units = split_by_labels(&detection.times, &result.labels)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct KMeansResult {
+    pub labels: Vec<usize>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub centroids: Vec<Vec<f64>>,
+    pub inertia: f64,
+    pub counts: Vec<usize>,
+    pub iterations: usize,
+}
+
+/// Clusters observations with k-means
+///
+/// # Arguments
+///
+/// * `data` - The observations, one row per observation and one column per feature
+/// * `k` - The number of clusters
+/// * `options` - The restarts, stopping rule and seed
+///
+/// # Returns
+///
+/// The clustering with the lowest inertia over all restarts, or an error if the rows differ
+/// in length, `k` is zero or exceeds the number of observations, or `n_init` is zero
+///
+/// # Examples
+///
+/// ```
+/// let features = detection.pca_features(3)?;
+/// let result = kmeans(&features.scores, 3, &KMeansOptions { seed: 7, ..KMeansOptions::default() })?;
+/// ```
+///
+/// # Note
+///
+/// Every restart draws its initial centroids with k-means++ and runs Lloyd iterations until
+/// the squared centroid shift falls below the tolerance. A cluster that loses all of its
+/// observations is moved to the observation farthest from its centroid, which leaves its old
+/// cluster before the means are taken, and no observation seeds two clusters. Results are
+/// identical for the same seed on every platform.
+///
+pub fn kmeans(data: &[Vec<f64>], k: usize, options: &KMeansOptions) -> Result<KMeansResult, ProcessingError> {
+    let n = data.len();
+    let n_features = data.first().map_or(0, |row| row.len());
+    check_row_lengths(data, n_features)?;
+    if k == 0 || k > n {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Number of clusters must be between 1 and the number of observations {}, got {}",
+            n, k
+        )));
+    }
+    if options.n_init == 0 {
+        return Err(ProcessingError::InvalidParameter("Number of restarts must be at least 1".to_string()));
+    }
+
+    let mean_variance = (0..n_features)
+        .map(|j| {
+            let mean = data.iter().map(|row| row[j]).sum::<f64>() / n as f64;
+            data.iter().map(|row| (row[j] - mean).powi(2)).sum::<f64>() / n as f64
+        })
+        .sum::<f64>()
+        / n_features.max(1) as f64;
+    let tolerance = options.tolerance * mean_variance;
+
+    let mut rng = SeededRng::new(options.seed);
+    let mut best: Option<KMeansResult> = None;
+    for _ in 0..options.n_init {
+        let centroids = kmeans_plus_plus(data, k, &mut rng);
+        let result = lloyd(data, centroids, options.max_iterations, tolerance);
+        if best.as_ref().is_none_or(|best| result.inertia < best.inertia) {
+            best = Some(result);
+        }
+    }
+    Ok(best.expect("At least one restart was run"))
+}
+
+/// Draws initial centroids, each with probability proportional to its squared distance to the closest one drawn
+fn kmeans_plus_plus(data: &[Vec<f64>], k: usize, rng: &mut SeededRng) -> Vec<Vec<f64>> {
+    let mut centroids = vec![data[rng.next_index(data.len())].clone()];
+    let mut distances: Vec<f64> = data.iter().map(|row| squared_distance(row, &centroids[0])).collect();
+    while centroids.len() < k {
+        let total: f64 = distances.iter().sum();
+        let chosen = if total > 0.0 {
+            let target = rng.next_f64() * total;
+            let mut cumulative = 0.0;
+            distances
+                .iter()
+                .position(|distance| {
+                    cumulative += distance;
+                    cumulative > target
+                })
+                .unwrap_or(data.len() - 1)
+        } else {
+            // All remaining observations coincide with a centroid
+            rng.next_index(data.len())
+        };
+        centroids.push(data[chosen].clone());
+        for (distance, row) in distances.iter_mut().zip(data) {
+            *distance = distance.min(squared_distance(row, &data[chosen]));
+        }
+    }
+    centroids
+}
+
+/// Runs Lloyd iterations from the given centroids
+fn lloyd(data: &[Vec<f64>], mut centroids: Vec<Vec<f64>>, max_iterations: usize, tolerance: f64) -> KMeansResult {
+    let k = centroids.len();
+    let n_features = centroids[0].len();
+    let mut labels = vec![0; data.len()];
+    let mut iterations = 0;
+    while iterations < max_iterations {
+        iterations += 1;
+        assign(data, &centroids, &mut labels);
+
+        let mut sums = vec![vec![0.0; n_features]; k];
+        let mut counts = vec![0usize; k];
+        for (row, &label) in data.iter().zip(&labels) {
+            counts[label] += 1;
+            for (sum, value) in sums[label].iter_mut().zip(row) {
+                *sum += value;
+            }
+        }
+        reseed_empty(data, &centroids, &mut labels, &mut sums, &mut counts);
+        for (sum, &count) in sums.iter_mut().zip(&counts) {
+            sum.iter_mut().for_each(|sum| *sum /= count as f64);
+        }
+        let shift: f64 = centroids.iter().zip(&sums).map(|(old, new)| squared_distance(old, new)).sum();
+        centroids = sums;
+        if shift <= tolerance {
+            break;
+        }
+    }
+
+    assign(data, &centroids, &mut labels);
+    let mut counts = vec![0usize; k];
+    let mut inertia = 0.0;
+    for (row, &label) in data.iter().zip(&labels) {
+        counts[label] += 1;
+        inertia += squared_distance(row, &centroids[label]);
+    }
+    KMeansResult { labels, centroids, inertia, counts, iterations }
+}
+
+/// Moves each empty cluster onto the observation farthest from its centroid, taking it out of the sum of its old cluster
+///
+/// Observations already used as seeds and the only member of a cluster are not moved, so
+/// every cluster ends with at least one observation as long as there are at least `k`
+fn reseed_empty(data: &[Vec<f64>], centroids: &[Vec<f64>], labels: &mut [usize], sums: &mut [Vec<f64>], counts: &mut [usize]) {
+    let mut seeds = vec![false; data.len()];
+    for cluster in 0..counts.len() {
+        if counts[cluster] > 0 {
+            continue;
+        }
+        let farthest = (0..data.len())
+            .filter(|&i| !seeds[i] && counts[labels[i]] > 1)
+            .max_by(|&a, &b| {
+                squared_distance(&data[a], &centroids[labels[a]]).total_cmp(&squared_distance(&data[b], &centroids[labels[b]]))
+            });
+        let Some(farthest) = farthest else {
+            break;
+        };
+        let donor = labels[farthest];
+        for (sum, value) in sums[donor].iter_mut().zip(&data[farthest]) {
+            *sum -= value;
+        }
+        counts[donor] -= 1;
+        sums[cluster] = data[farthest].clone();
+        counts[cluster] = 1;
+        labels[farthest] = cluster;
+        seeds[farthest] = true;
+    }
+}
+
+/// Labels every observation with its closest centroid
+fn assign(data: &[Vec<f64>], centroids: &[Vec<f64>], labels: &mut [usize]) {
+    for (label, row) in labels.iter_mut().zip(data) {
+        *label = (0..centroids.len())
+            .min_by(|&a, &b| squared_distance(row, &centroids[a]).total_cmp(&squared_distance(row, &centroids[b])))
+            .unwrap_or(0);
+    }
+}
+
+fn squared_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(x, y)| (x - y).powi(2)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_clusters_take_distinct_observations_out_of_their_donor() {
+        let data = vec![vec![0.0], vec![1.0], vec![10.0], vec![11.0]];
+        let centroids = vec![vec![0.0], vec![100.0], vec![200.0]];
+        let result = lloyd(&data, centroids, 1, 0.0);
+        // The first iteration empties the two far clusters, which take 11 and then 10 from the
+        // first one, whose mean no longer includes them
+        assert_eq!(result.centroids, vec![vec![0.5], vec![11.0], vec![10.0]]);
+        assert_eq!(result.counts, vec![2, 1, 1]);
+        assert_eq!(result.labels, vec![0, 0, 2, 1]);
+    }
+
+    #[test]
+    fn centroids_are_the_means_of_their_clusters() {
+        let data: Vec<Vec<f64>> = (0..60).map(|i| vec![(i % 3) as f64 * 10.0 + (i as f64 * 0.37).sin(), (i % 3) as f64]).collect();
+        let result = kmeans(&data, 3, &KMeansOptions { seed: 3, ..KMeansOptions::default() }).unwrap();
+        assert_eq!(result.counts, vec![20, 20, 20]);
+        for (cluster, centroid) in result.centroids.iter().enumerate() {
+            let members: Vec<&Vec<f64>> = data.iter().zip(&result.labels).filter(|(_, &label)| label == cluster).map(|(row, _)| row).collect();
+            for (j, &value) in centroid.iter().enumerate() {
+                let mean = members.iter().map(|row| row[j]).sum::<f64>() / members.len() as f64;
+                assert!((value - mean).abs() < 1e-12);
+            }
+        }
+    }
+}
@@ -0,0 +1,703 @@
+// A module to join the sequential parts of a session into one recording
+
+// Written by Amin Alam in 2024
+
+use crate::core::recording::Recording;
+use crate::core::session::SessionInfo;
+use crate::core::timeseries::TimeSeries;
+use crate::processing::artifacts::{self, ArtifactKind, ArtifactRejection, ArtifactSpan};
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+
+/// The largest relative difference between the sampling rates of parts joined together
+const RATE_TOLERANCE: f64 = 1e-9;
+
+/// One part of a session, e.g. one file written between two restarts of the acquisition
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel of the part
+/// * `names` - The name of each channel
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample in seconds, on the clock shared by all parts
+///
+/// # Examples
+///
+/// ```
+/// let part = RecordingPart { channels: &channels, names: &names, sampling_rate: 1000.0, start_time: 3600.0 };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordingPart<'a> {
+    pub channels: &'a [Vec<f64>],
+    pub names: &'a [String],
+    pub sampling_rate: f64,
+    pub start_time: f64,
+}
+
+/// What `concatenate` does where a part does not start one sample after the previous one ends
+///
+/// # Arguments
+///
+/// * `Error` - Returns an error naming the parts
+/// * `FillNan` - Fills a gap with as many NaN samples as it lasts, keeping the times of all parts; an overlap is still an error
+/// * `Stitch` - Joins the parts sample to sample, ignoring the gap or overlap, and records a seam
+///
+/// # Examples
+///
+/// ```
+/// let session = concatenate(&parts, GapPolicy::FillNan)?;
+/// ```
+///
+/// # Note
+///
+/// Parts are contiguous when the start of one is within half a sample of the end of the
+/// previous one, which no policy treats as a gap
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum GapPolicy {
+    #[default]
+    Error,
+    FillNan,
+    Stitch,
+}
+
+/// Where a part was placed in the joined recording
+///
+/// # Arguments
+///
+/// * `start_time` - The time of the first sample of the part on its own clock, in seconds
+/// * `first_sample` - The index of the first sample of the part in the joined recording
+/// * `n_samples` - The number of samples of the part
+/// * `gap` - The time from the end of the previous part to the start of this one in seconds, negative for an overlap, 0 for the first part and parts within half a sample of the previous one
+/// * `n_filled` - The number of NaN samples inserted before the part
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PartPlacement {
+    pub start_time: f64,
+    pub first_sample: usize,
+    pub n_samples: usize,
+    pub gap: f64,
+    pub n_filled: usize,
+}
+
+/// The layout of the parts of a joined session
+///
+/// # Arguments
+///
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample of the joined recording in seconds
+/// * `parts` - The placement of every part, in order
+/// * `n_samples` - The number of samples of the joined recording
+///
+/// # Examples
+///
+/// ```
+/// for part in &session.layout.parts {
+///     println!("part at sample {} had a gap of {} s", part.first_sample, part.gap);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SessionLayout {
+    pub sampling_rate: f64,
+    pub start_time: f64,
+    pub parts: Vec<PartPlacement>,
+    pub n_samples: usize,
+}
+
+/// The parts of a session joined into one recording
+///
+/// # Arguments
+///
+/// * `channels` - The joined samples of each channel, in the channel order of the first part
+/// * `names` - The name of each channel
+/// * `layout` - Where each part was placed, with the seams between them
+///
+/// # Examples
+///
+/// ```
+/// let session = concatenate(&parts, GapPolicy::Stitch)?;
+/// let rejection = session.reject_epochs(&trial_windows);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ConcatenatedRecording {
+    pub channels: Vec<Vec<f64>>,
+    pub names: Vec<String>,
+    pub layout: SessionLayout,
+}
+
+/// The parts of a session read as one recording without copying them
+///
+/// # Examples
+///
+/// ```
+/// let view = ConcatenatedView::new(&parts, GapPolicy::FillNan)?;
+/// let window = view.read_window(view.layout().n_samples - 1000, view.layout().n_samples);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConcatenatedView<'a> {
+    parts: Vec<RecordingPart<'a>>,
+    order: Vec<Vec<usize>>,
+    layout: SessionLayout,
+}
+
+/// Implementation of the SessionLayout struct
+///
+/// # Methods
+///
+/// * `seams` - Returns the times at which the joined samples are not continuous
+/// * `seam_spans` - Returns the seams as artifact spans
+/// * `reject_epochs` - Rejects the epochs that span a seam
+impl SessionLayout {
+    /// Returns the times at which the joined samples are not continuous
+    ///
+    /// # Returns
+    ///
+    /// The times in seconds, on the time axis of the joined recording, of the first sample
+    /// after every seam, in order
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let seams = session.layout.seams();
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A stitched gap or overlap is one seam, at the first sample of the later part. A
+    /// filled gap is two, at the first NaN sample and at the first sample of the later part.
+    /// Contiguous parts have no seam.
+    ///
+    pub fn seams(&self) -> Vec<f64> {
+        let time = |sample: usize| self.start_time + sample as f64 / self.sampling_rate;
+        let mut seams = Vec::new();
+        for part in self.parts.iter().skip(1) {
+            if part.n_filled > 0 {
+                seams.push(time(part.first_sample - part.n_filled));
+                seams.push(time(part.first_sample));
+            } else if part.gap != 0.0 {
+                seams.push(time(part.first_sample));
+            }
+        }
+        seams
+    }
+
+    /// Returns the seams as artifact spans
+    ///
+    /// # Arguments
+    ///
+    /// * `n_channels` - The number of channels marked by every span
+    ///
+    /// # Returns
+    ///
+    /// The spans of kind `ArtifactKind::Seam` on every channel, sorted by start time: a
+    /// filled gap spans its NaN samples and a stitched seam is a span of no duration
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut spans = detect(&session.channels, 1000.0, 0.0, &criteria)?;
+    /// spans.extend(session.layout.seam_spans(session.channels.len()));
+    /// let rejection = reject_epochs(&spans, &windows);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// `reject_epochs` rejects an epoch that shares time with a span, so a span of no
+    /// duration rejects the epochs that start before the seam and end after it
+    ///
+    pub fn seam_spans(&self, n_channels: usize) -> Vec<ArtifactSpan> {
+        let time = |sample: usize| self.start_time + sample as f64 / self.sampling_rate;
+        let mut spans = Vec::new();
+        for part in self.parts.iter().skip(1).filter(|part| part.n_filled > 0 || part.gap != 0.0) {
+            let (start, end) = (time(part.first_sample - part.n_filled), time(part.first_sample));
+            spans.extend((0..n_channels).map(|channel| ArtifactSpan { start, end, kind: ArtifactKind::Seam, channel }));
+        }
+        spans
+    }
+
+    /// Rejects the epochs that span a seam
+    ///
+    /// # Arguments
+    ///
+    /// * `epochs` - The start and end time of each epoch in seconds, on the time axis of the joined recording
+    ///
+    /// # Returns
+    ///
+    /// The kept epochs and the epochs that span a seam or share time with filled samples
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let windows: Vec<(f64, f64)> = onsets.iter().map(|onset| (onset - 0.2, onset + 0.8)).collect();
+    /// let kept = session.layout.reject_epochs(&windows).kept;
+    /// ```
+    ///
+    pub fn reject_epochs(&self, epochs: &[(f64, f64)]) -> ArtifactRejection {
+        artifacts::reject_epochs(&self.seam_spans(1), epochs)
+    }
+}
+
+/// Implementation of the ConcatenatedRecording struct
+///
+/// # Methods
+///
+/// * `seams` - Returns the times at which the joined samples are not continuous
+/// * `reject_epochs` - Rejects the epochs that span a seam
+/// * `into_recording` - Returns the joined channels as a Recording that knows its seams
+impl ConcatenatedRecording {
+    /// Returns the times at which the joined samples are not continuous
+    ///
+    /// # Returns
+    ///
+    /// The times of the seams in seconds, as in `SessionLayout::seams`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// for seam in session.seams() {
+    ///     println!("seam at {} s", seam);
+    /// }
+    /// ```
+    ///
+    pub fn seams(&self) -> Vec<f64> {
+        self.layout.seams()
+    }
+
+    /// Rejects the epochs that span a seam
+    ///
+    /// # Arguments
+    ///
+    /// * `epochs` - The start and end time of each epoch in seconds
+    ///
+    /// # Returns
+    ///
+    /// The kept and rejected epochs, as in `SessionLayout::reject_epochs`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let kept = session.reject_epochs(&windows).kept;
+    /// ```
+    ///
+    pub fn reject_epochs(&self, epochs: &[(f64, f64)]) -> ArtifactRejection {
+        self.layout.reject_epochs(epochs)
+    }
+
+    /// Returns the joined channels as a Recording that knows its seams
+    ///
+    /// # Arguments
+    ///
+    /// * `session` - The metadata of the session
+    ///
+    /// # Returns
+    ///
+    /// The Recording of one signal with the joined channels, whose seams are stored under
+    /// `SEAMS_KEY` in the session, or an error if the joined channels cannot form a signal
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let recording = concatenate(&parts, GapPolicy::Stitch)?.into_recording(SessionInfo::load("session.csv")?)?;
+    /// let epochs = Epochs::from_events(&recording, &stimuli, 0.2, 0.8)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The seams replace any the session held already, and `Epochs::from_events` drops the
+    /// trials that span them
+    ///
+    pub fn into_recording(self, session: SessionInfo) -> Result<Recording, ProcessingError> {
+        let seams = self.layout.seams();
+        let mut recording = Recording::new(session);
+        recording.add_signal(TimeSeries::new(self.channels, self.names, self.layout.sampling_rate, self.layout.start_time)?)?;
+        recording.set_seams(&seams);
+        Ok(recording)
+    }
+}
+
+/// Implementation of the ConcatenatedView struct
+///
+/// # Methods
+///
+/// * `new` - Lays out the parts of a session without copying them
+/// * `layout` - Returns where each part was placed
+/// * `names` - Returns the name of each channel
+/// * `seams` - Returns the times at which the joined samples are not continuous
+/// * `read_window` - Copies a range of samples of the joined recording
+impl<'a> ConcatenatedView<'a> {
+    /// Lays out the parts of a session without copying them
+    ///
+    /// # Arguments
+    ///
+    /// * `parts` - The parts, in time order
+    /// * `policy` - What is done with gaps and overlaps between consecutive parts
+    ///
+    /// # Returns
+    ///
+    /// The ConcatenatedView, or an error as in `concatenate`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let view = ConcatenatedView::new(&parts, GapPolicy::Stitch)?;
+    /// ```
+    ///
+    pub fn new(parts: &[RecordingPart<'a>], policy: GapPolicy) -> Result<Self, ProcessingError> {
+        let order = channel_orders(parts)?;
+        let layout = layout(parts, policy)?;
+        Ok(Self { parts: parts.to_vec(), order, layout })
+    }
+
+    /// Returns where each part was placed
+    ///
+    /// # Returns
+    ///
+    /// The SessionLayout of the parts
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let duration = view.layout().n_samples as f64 / view.layout().sampling_rate;
+    /// ```
+    ///
+    pub fn layout(&self) -> &SessionLayout {
+        &self.layout
+    }
+
+    /// Returns the name of each channel
+    ///
+    /// # Returns
+    ///
+    /// The names, in the channel order of the first part
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let names = view.names();
+    /// ```
+    ///
+    pub fn names(&self) -> &[String] {
+        self.parts[0].names
+    }
+
+    /// Returns the times at which the joined samples are not continuous
+    ///
+    /// # Returns
+    ///
+    /// The times of the seams in seconds, as in `SessionLayout::seams`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let seams = view.seams();
+    /// ```
+    ///
+    pub fn seams(&self) -> Vec<f64> {
+        self.layout.seams()
+    }
+
+    /// Copies a range of samples of the joined recording
+    ///
+    /// # Arguments
+    ///
+    /// * `start` - The index of the first sample, included
+    /// * `end` - The index of the last sample, excluded, clamped to the length of the recording
+    ///
+    /// # Returns
+    ///
+    /// The samples of each channel in the range, NaN where a gap was filled
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let first_minute = view.read_window(0, 60 * 1000);
+    /// ```
+    ///
+    pub fn read_window(&self, start: usize, end: usize) -> Vec<Vec<f64>> {
+        let end = end.min(self.layout.n_samples);
+        let start = start.min(end);
+        let mut window = vec![vec![f64::NAN; end - start]; self.names().len()];
+        for ((part, placement), order) in self.parts.iter().zip(&self.layout.parts).zip(&self.order) {
+            let first = placement.first_sample.max(start);
+            let last = (placement.first_sample + placement.n_samples).min(end);
+            if first >= last {
+                continue;
+            }
+            for (output, &channel) in window.iter_mut().zip(order) {
+                let samples = &part.channels[channel][first - placement.first_sample..last - placement.first_sample];
+                output[first - start..last - start].copy_from_slice(samples);
+            }
+        }
+        window
+    }
+}
+
+/// Joins the sequential parts of a session into one recording
+///
+/// # Arguments
+///
+/// * `parts` - The parts, in time order
+/// * `policy` - What is done with gaps and overlaps between consecutive parts
+///
+/// # Returns
+///
+/// The ConcatenatedRecording, or an error if there is no part, the parts do not have the
+/// same channels or sampling rate, the channels of a part differ in length, a start time
+/// is not finite, or a gap or overlap is not allowed by the policy
+///
+/// # Examples
+///
+/// ```
+/// let parts: Vec<RecordingPart> = files.iter().map(|file| RecordingPart {
+///     channels: &file.channels,
+///     names: &file.names,
+///     sampling_rate: 1000.0,
+///     start_time: file.start_time,
+/// }).collect();
+/// let session = concatenate(&parts, GapPolicy::FillNan)?;
+/// ```
+///
+/// # Note
+///
+/// The parts may list the same channels in different orders; the joined recording follows
+/// the order of the first part. Its time axis starts at the start time of the first part,
+/// so with `GapPolicy::Stitch` the times after a seam are shifted by the gap. Use
+/// `ConcatenatedView` to read parts too large to copy.
+///
+pub fn concatenate(parts: &[RecordingPart], policy: GapPolicy) -> Result<ConcatenatedRecording, ProcessingError> {
+    let view = ConcatenatedView::new(parts, policy)?;
+    Ok(ConcatenatedRecording {
+        channels: view.read_window(0, view.layout.n_samples),
+        names: view.names().to_vec(),
+        layout: view.layout,
+    })
+}
+
+/// Checks the channels of the parts and returns the index of every channel of the first part in each part
+fn channel_orders(parts: &[RecordingPart]) -> Result<Vec<Vec<usize>>, ProcessingError> {
+    let first = parts.first().ok_or_else(|| ProcessingError::InvalidParameter("At least one part is needed to concatenate".to_string()))?;
+    let mut orders = Vec::with_capacity(parts.len());
+    for (index, part) in parts.iter().enumerate() {
+        if part.channels.len() != part.names.len() {
+            return Err(ProcessingError::InvalidParameter(format!("Part {} has {} channels but {} names", index, part.channels.len(), part.names.len())));
+        }
+        if part.channels.iter().any(|channel| channel.len() != part.channels[0].len()) {
+            return Err(ProcessingError::InvalidParameter(format!("The channels of part {} differ in length", index)));
+        }
+        let missing: Vec<&str> = first.names.iter().filter(|name| !part.names.contains(name)).map(|name| name.as_str()).collect();
+        let extra: Vec<&str> = part.names.iter().filter(|name| !first.names.contains(name)).map(|name| name.as_str()).collect();
+        if !missing.is_empty() || !extra.is_empty() || part.names.len() != first.names.len() {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "The channels of part {} differ from those of part 0: missing [{}], extra [{}]",
+                index,
+                missing.join(", "),
+                extra.join(", ")
+            )));
+        }
+        orders.push(first.names.iter().map(|name| part.names.iter().position(|other| other == name).unwrap_or_default()).collect());
+    }
+    Ok(orders)
+}
+
+/// Places the parts one after the other according to the policy
+fn layout(parts: &[RecordingPart], policy: GapPolicy) -> Result<SessionLayout, ProcessingError> {
+    let sampling_rate = parts[0].sampling_rate;
+    validate_sampling_rate(sampling_rate)?;
+    let mut placements: Vec<PartPlacement> = Vec::with_capacity(parts.len());
+    let mut n_samples = 0;
+    for (index, part) in parts.iter().enumerate() {
+        if (part.sampling_rate - sampling_rate).abs() > RATE_TOLERANCE * sampling_rate {
+            return Err(ProcessingError::InvalidParameter(format!("Part {} is sampled at {} Hz but part 0 at {} Hz", index, part.sampling_rate, sampling_rate)));
+        }
+        if !part.start_time.is_finite() {
+            return Err(ProcessingError::InvalidParameter(format!("The start time of part {} must be finite, got {}", index, part.start_time)));
+        }
+        let length = part.channels.first().map_or(0, |channel| channel.len());
+        let (gap, n_filled) = match placements.last() {
+            None => (0.0, 0),
+            Some(previous) => {
+                let gap = part.start_time - (previous.start_time + previous.n_samples as f64 / sampling_rate);
+                let n_gap = (gap * sampling_rate).round();
+                match policy {
+                    _ if n_gap == 0.0 => (0.0, 0),
+                    GapPolicy::Stitch => (gap, 0),
+                    GapPolicy::FillNan if n_gap > 0.0 => (gap, n_gap as usize),
+                    _ if gap > 0.0 => return Err(ProcessingError::InvalidParameter(format!("Part {} starts {} s after part {} ends", index, gap, index - 1))),
+                    _ => return Err(ProcessingError::InvalidParameter(format!("Part {} overlaps part {} by {} s", index, index - 1, -gap))),
+                }
+            }
+        };
+        n_samples += n_filled;
+        placements.push(PartPlacement { start_time: part.start_time, first_sample: n_samples, n_samples: length, gap, n_filled });
+        n_samples += length;
+    }
+    Ok(SessionLayout { sampling_rate, start_time: parts[0].start_time, parts: placements, n_samples })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::epochs::Epochs;
+    use crate::core::events::Events;
+
+    const RATE: f64 = 100.0;
+
+    /// The channels A and B of part `k`, with A at `1000 k + i` and B at its negative, in the order of `names`
+    fn part_channels(k: usize, n: usize, names: &[&str]) -> (Vec<Vec<f64>>, Vec<String>) {
+        let a: Vec<f64> = (0..n).map(|i| (1000 * k + i) as f64).collect();
+        let b: Vec<f64> = a.iter().map(|value| -value).collect();
+        let channels = names.iter().map(|&name| if name == "A" { a.clone() } else { b.clone() }).collect();
+        (channels, names.iter().map(|name| name.to_string()).collect())
+    }
+
+    fn parts<'a>(data: &'a [(Vec<Vec<f64>>, Vec<String>)], starts: &[f64]) -> Vec<RecordingPart<'a>> {
+        data.iter().zip(starts).map(|((channels, names), &start_time)| RecordingPart { channels, names, sampling_rate: RATE, start_time }).collect()
+    }
+
+    fn expected_a(lengths: &[usize], filled: &[usize]) -> Vec<f64> {
+        let mut a = Vec::new();
+        for (k, (&n, &n_filled)) in lengths.iter().zip(filled).enumerate() {
+            a.extend(std::iter::repeat_n(f64::NAN, n_filled));
+            a.extend((0..n).map(|i| (1000 * k + i) as f64));
+        }
+        a
+    }
+
+    fn same(actual: &[f64], expected: &[f64]) -> bool {
+        actual.len() == expected.len() && actual.iter().zip(expected).all(|(a, b)| a == b || (a.is_nan() && b.is_nan()))
+    }
+
+    fn message(result: Result<ConcatenatedRecording, ProcessingError>) -> String {
+        match result {
+            Err(ProcessingError::InvalidParameter(message)) => message,
+            other => panic!("Expected an invalid parameter, got {:?}", other.map(|session| session.layout)),
+        }
+    }
+
+    #[test]
+    fn contiguous_parts_join_without_seams_under_every_policy() {
+        let data = [part_channels(0, 50, &["A", "B"]), part_channels(1, 30, &["B", "A"]), part_channels(2, 20, &["A", "B"])];
+        // The third part starts 0.004 s late, within half a sample
+        let parts = parts(&data, &[10.0, 10.5, 10.804]);
+        for policy in [GapPolicy::Error, GapPolicy::FillNan, GapPolicy::Stitch] {
+            let session = concatenate(&parts, policy).unwrap();
+            assert_eq!(session.names, ["A", "B"]);
+            assert!(same(&session.channels[0], &expected_a(&[50, 30, 20], &[0, 0, 0])));
+            assert!(session.channels[1].iter().zip(&session.channels[0]).all(|(b, a)| *b == -a));
+            assert!(session.seams().is_empty());
+            assert_eq!(session.layout.n_samples, 100);
+            assert_eq!(session.layout.start_time, 10.0);
+            let placed: Vec<(usize, usize, f64, usize)> = session.layout.parts.iter().map(|part| (part.first_sample, part.n_samples, part.gap, part.n_filled)).collect();
+            assert_eq!(placed, [(0, 50, 0.0, 0), (50, 30, 0.0, 0), (80, 20, 0.0, 0)]);
+        }
+    }
+
+    #[test]
+    fn gaps_error_fill_or_stitch() {
+        let data = [part_channels(0, 50, &["A", "B"]), part_channels(1, 40, &["A", "B"])];
+        let parts = parts(&data, &[0.0, 0.75]);
+        assert_eq!(message(concatenate(&parts, GapPolicy::Error)), "Part 1 starts 0.25 s after part 0 ends");
+
+        let filled = concatenate(&parts, GapPolicy::FillNan).unwrap();
+        assert!(same(&filled.channels[0], &expected_a(&[50, 40], &[0, 25])));
+        assert_eq!(filled.layout.parts[1], PartPlacement { start_time: 0.75, first_sample: 75, n_samples: 40, gap: 0.25, n_filled: 25 });
+        // The filled samples keep every part at its own time
+        assert_eq!(filled.seams(), [0.5, 0.75]);
+        assert_eq!(filled.layout.n_samples, 115);
+
+        let stitched = concatenate(&parts, GapPolicy::Stitch).unwrap();
+        assert!(same(&stitched.channels[0], &expected_a(&[50, 40], &[0, 0])));
+        assert_eq!(stitched.layout.parts[1], PartPlacement { start_time: 0.75, first_sample: 50, n_samples: 40, gap: 0.25, n_filled: 0 });
+        assert_eq!(stitched.seams(), [0.5]);
+    }
+
+    #[test]
+    fn overlaps_are_stitched_or_refused() {
+        let data = [part_channels(0, 50, &["A", "B"]), part_channels(1, 40, &["A", "B"]), part_channels(2, 10, &["A", "B"])];
+        let parts = parts(&data, &[0.0, 0.25, 1.0]);
+        assert_eq!(message(concatenate(&parts, GapPolicy::Error)), "Part 1 overlaps part 0 by 0.25 s");
+        assert_eq!(message(concatenate(&parts, GapPolicy::FillNan)), "Part 1 overlaps part 0 by 0.25 s");
+
+        let stitched = concatenate(&parts, GapPolicy::Stitch).unwrap();
+        assert!(same(&stitched.channels[0], &expected_a(&[50, 40, 10], &[0, 0, 0])));
+        assert_eq!(stitched.layout.parts.iter().map(|part| part.gap).collect::<Vec<_>>(), [0.0, -0.25, 0.35]);
+        assert_eq!(stitched.seams(), [0.5, 0.9]);
+        assert_eq!(stitched.layout.seam_spans(2).len(), 4);
+    }
+
+    #[test]
+    fn mismatched_parts_are_rejected() {
+        let data = [part_channels(0, 10, &["A", "B"]), part_channels(1, 10, &["A", "C"])];
+        assert_eq!(message(concatenate(&parts(&data, &[0.0, 0.1]), GapPolicy::Stitch)), "The channels of part 1 differ from those of part 0: missing [B], extra [C]");
+        let data = [part_channels(0, 10, &["A", "B"]), part_channels(1, 10, &["A"])];
+        assert_eq!(message(concatenate(&parts(&data, &[0.0, 0.1]), GapPolicy::Stitch)), "The channels of part 1 differ from those of part 0: missing [B], extra []");
+        let data = [part_channels(0, 10, &["A"]), part_channels(1, 10, &["A", "A"])];
+        assert_eq!(message(concatenate(&parts(&data, &[0.0, 0.1]), GapPolicy::Stitch)), "The channels of part 1 differ from those of part 0: missing [], extra []");
+
+        let data = [part_channels(0, 10, &["A", "B"]), part_channels(1, 10, &["A", "B"])];
+        let mut mixed = parts(&data, &[0.0, 0.1]);
+        mixed[1].sampling_rate = 200.0;
+        assert_eq!(message(concatenate(&mixed, GapPolicy::Stitch)), "Part 1 is sampled at 200 Hz but part 0 at 100 Hz");
+        let mut unanchored = parts(&data, &[0.0, f64::NAN]);
+        assert_eq!(message(concatenate(&unanchored, GapPolicy::Stitch)), "The start time of part 1 must be finite, got NaN");
+        unanchored[1].names = &data[0].1[..1];
+        assert_eq!(message(concatenate(&unanchored, GapPolicy::Stitch)), "Part 1 has 2 channels but 1 names");
+        let ragged = [vec![0.0; 10], vec![0.0; 9]];
+        unanchored[1] = RecordingPart { channels: &ragged, names: &data[0].1, sampling_rate: RATE, start_time: 0.1 };
+        assert_eq!(message(concatenate(&unanchored, GapPolicy::Stitch)), "The channels of part 1 differ in length");
+        assert_eq!(message(concatenate(&[], GapPolicy::Stitch)), "At least one part is needed to concatenate");
+    }
+
+    #[test]
+    fn views_read_the_same_samples_without_copying() {
+        let data = [part_channels(0, 50, &["A", "B"]), part_channels(1, 40, &["B", "A"]), part_channels(2, 30, &["A", "B"])];
+        let parts = parts(&data, &[0.0, 0.75, 1.2]);
+        let session = concatenate(&parts, GapPolicy::FillNan).unwrap();
+        let view = ConcatenatedView::new(&parts, GapPolicy::FillNan).unwrap();
+        assert_eq!((view.layout(), view.names(), view.seams()), (&session.layout, session.names.as_slice(), session.seams()));
+        assert_eq!(session.seams(), [0.5, 0.75, 1.15, 1.2]);
+        let n = session.layout.n_samples;
+        assert_eq!(n, 150);
+        for (start, end) in [(0, 150), (45, 80), (60, 70), (110, 130), (140, 1000), (200, 300), (30, 10)] {
+            let window = view.read_window(start, end);
+            let (first, last) = (start.min(end.min(n)), end.min(n));
+            for (channel, samples) in window.iter().enumerate() {
+                assert!(same(samples, &session.channels[channel][first..last]), "{} {}", start, end);
+            }
+        }
+    }
+
+    #[test]
+    fn epochs_across_seams_are_rejected() {
+        let data = [part_channels(0, 50, &["A", "B"]), part_channels(1, 50, &["A", "B"])];
+        let parts = parts(&data, &[0.0, 0.75]);
+        let filled = concatenate(&parts, GapPolicy::FillNan).unwrap();
+        let windows = [(0.3, 0.45), (0.4, 0.5), (0.45, 0.55), (0.6, 0.7), (0.7, 0.8), (0.75, 0.85), (0.2, 1.0)];
+        let rejection = filled.reject_epochs(&windows);
+        assert_eq!((rejection.kept, rejection.rejected), (vec![0, 1, 5], vec![2, 3, 4, 6]));
+        assert_eq!(rejection.removed_per_kind.get(&ArtifactKind::Seam), Some(&4));
+
+        // A stitched seam only rejects the epochs that have time on both sides of it
+        let stitched = concatenate(&parts, GapPolicy::Stitch).unwrap();
+        let rejection = stitched.reject_epochs(&windows);
+        assert_eq!((rejection.kept, rejection.rejected), (vec![0, 1, 3, 4, 5], vec![2, 6]));
+    }
+
+    #[test]
+    fn recordings_keep_their_seams_for_epoching() {
+        let data = [part_channels(0, 100, &["A", "B"]), part_channels(1, 100, &["A", "B"])];
+        let stitched = concatenate(&parts(&data, &[5.0, 6.5]), GapPolicy::Stitch).unwrap();
+        let recording = stitched.into_recording(SessionInfo::new()).unwrap();
+        assert_eq!(recording.seams().unwrap(), [6.0]);
+        assert_eq!(recording.signals()[0].start_time(), 5.0);
+
+        let events = Events::new(vec![5.5, 5.95, 6.05, 6.5, 6.95], vec!["go".to_string(); 5], None).unwrap();
+        let epochs = Epochs::from_events(&recording, &events, 0.1, 0.2).unwrap();
+        assert_eq!(epochs.dropped(), [1, 2, 4]);
+        assert_eq!(epochs.n_trials(), 2);
+        // The trial after the seam holds the second part from its first samples
+        assert_eq!(epochs.trial(1).unwrap()[0][..3], [1040.0, 1041.0, 1042.0]);
+    }
+}
@@ -0,0 +1,382 @@
+// A module to measure the phase locking between channels
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::{butterworth, FilterKind};
+use crate::processing::hilbert::instantaneous_phase;
+
+/// A measure of the consistency of phase differences
+///
+/// # Arguments
+///
+/// * `Plv` - The phase-locking value, the length of the mean unit vector of the phase differences, biased upwards for few observations
+/// * `Ppc` - The pairwise phase consistency of Vinck et al. (2010), the mean cosine between the phase differences of every pair of observations, unbiased
+///
+/// # Examples
+///
+/// ```
+/// let options = PhaseLockingOptions { measure: PhaseMeasure::Ppc, ..PhaseLockingOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PhaseMeasure {
+    Plv,
+    Ppc,
+}
+
+/// Options of `band_phase_locking` and `plv_matrix`
+///
+/// # Arguments
+///
+/// * `measure` - The measure of phase locking, `Plv` by default
+/// * `filter_order` - The order of the zero-phase Butterworth bandpass filter, 4 by default
+///
+/// # Examples
+///
+/// ```
+/// let options = PhaseLockingOptions { filter_order: 2, ..PhaseLockingOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PhaseLockingOptions {
+    pub measure: PhaseMeasure,
+    pub filter_order: usize,
+}
+
+impl Default for PhaseLockingOptions {
+    fn default() -> Self {
+        Self { measure: PhaseMeasure::Plv, filter_order: 4 }
+    }
+}
+
+/// The band-limited phase locking between every pair of channels
+///
+/// # Arguments
+///
+/// * `names` - The name of each channel
+/// * `band` - The frequency band in Hz the channels were filtered to
+/// * `measure` - The measure of phase locking
+/// * `values` - The square, symmetric matrix of the measure across trials, averaged over time, with ones on the diagonal
+///
+/// # Examples
+///
+/// ```
+/// let matrix = plv_matrix(&trials, &names, 1000.0, (8.0, 12.0), &PhaseLockingOptions::default())?;
+/// matrix.to_csv(&mut csv_io)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PhaseLockingMatrix {
+    pub names: Vec<String>,
+    pub band: (f64, f64),
+    pub measure: PhaseMeasure,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub values: Vec<Vec<f64>>,
+}
+
+/// Implementation of the PhaseLockingMatrix struct
+///
+/// # Methods
+///
+/// * `to_csv` - Writes the matrix with a header row and a leading column of channel names
+impl PhaseLockingMatrix {
+    /// Writes the matrix with a header row and a leading column of channel names
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// matrix.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The header is `channel` followed by the channel names. The rows are not flushed to disk
+    /// until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        let mut header = vec!["channel".to_string()];
+        header.extend(self.names.iter().cloned());
+        csv_io.write_record(StringRecord::from(header))?;
+        for (name, row) in self.names.iter().zip(&self.values) {
+            let mut record = vec![name.clone()];
+            record.extend(row.iter().map(|value| float_format.format(*value)));
+            csv_io.write_record(StringRecord::from(record))?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes the phase-locking value between two phase time series
+///
+/// # Arguments
+///
+/// * `phase_a` - The instantaneous phase of the first signal in radians
+/// * `phase_b` - The instantaneous phase of the second signal in radians, as long as the first
+///
+/// # Returns
+///
+/// The PLV in `[0, 1]`, 1 for a constant phase difference, or an error if the lengths differ or are zero
+///
+/// # Examples
+///
+/// ```
+/// let locking = plv(&instantaneous_phase(&theta_a), &instantaneous_phase(&theta_b))?;
+/// ```
+///
+/// # Note
+///
+/// Over `n` independent phase differences the PLV is about `sqrt(pi / (4 * n))` rather
+/// than 0; use `ppc` to compare recordings of different lengths.
+///
+pub fn plv(phase_a: &[f64], phase_b: &[f64]) -> Result<f64, ProcessingError> {
+    validate_lengths(phase_a, phase_b, 1)?;
+    Ok(locking(phase_a.iter().zip(phase_b).map(|(a, b)| a - b), PhaseMeasure::Plv))
+}
+
+/// Computes the pairwise phase consistency between two phase time series
+///
+/// # Arguments
+///
+/// * `phase_a` - The instantaneous phase of the first signal in radians
+/// * `phase_b` - The instantaneous phase of the second signal in radians, as long as the first
+///
+/// # Returns
+///
+/// The PPC in `[-1 / (n - 1), 1]`, near 0 for independent phases, or an error if the lengths differ or are below two
+///
+/// # Examples
+///
+/// ```
+/// let consistency = ppc(&phase_a, &phase_b)?;
+/// ```
+///
+/// # Note
+///
+/// The PPC equals the square of the PLV with its bias removed, `(n * plv^2 - 1) / (n - 1)`.
+/// Neighbouring samples of a band-limited signal are not independent, so over time it is
+/// less biased than the PLV but not unbiased; across trials it is.
+///
+pub fn ppc(phase_a: &[f64], phase_b: &[f64]) -> Result<f64, ProcessingError> {
+    validate_lengths(phase_a, phase_b, 2)?;
+    Ok(locking(phase_a.iter().zip(phase_b).map(|(a, b)| a - b), PhaseMeasure::Ppc))
+}
+
+/// Computes the phase-locking value across trials at every time point
+///
+/// # Arguments
+///
+/// * `phases_a` - The instantaneous phase of the first signal in each trial, indexed as `phases_a[trial][time]`
+/// * `phases_b` - The instantaneous phase of the second signal in the same trials
+///
+/// # Returns
+///
+/// The PLV at each time point, or an error if the numbers of trials or the lengths of the trials differ
+///
+/// # Examples
+///
+/// ```
+/// let locking = plv_epochs(&phases_a, &phases_b)?;
+/// ```
+///
+pub fn plv_epochs(phases_a: &[Vec<f64>], phases_b: &[Vec<f64>]) -> Result<Vec<f64>, ProcessingError> {
+    locking_epochs(phases_a, phases_b, PhaseMeasure::Plv)
+}
+
+/// Computes the pairwise phase consistency across trials at every time point
+///
+/// # Arguments
+///
+/// * `phases_a` - The instantaneous phase of the first signal in each trial, indexed as `phases_a[trial][time]`
+/// * `phases_b` - The instantaneous phase of the second signal in the same trials
+///
+/// # Returns
+///
+/// The PPC at each time point, or an error if there are fewer than two trials or the
+/// numbers of trials or the lengths of the trials differ
+///
+/// # Examples
+///
+/// ```
+/// let consistency = ppc_epochs(&phases_a, &phases_b)?;
+/// ```
+///
+pub fn ppc_epochs(phases_a: &[Vec<f64>], phases_b: &[Vec<f64>]) -> Result<Vec<f64>, ProcessingError> {
+    locking_epochs(phases_a, phases_b, PhaseMeasure::Ppc)
+}
+
+/// Filters two signals to a band and measures their phase locking across trials at every time point
+///
+/// # Arguments
+///
+/// * `trials_a` - The samples of the first signal in each trial, indexed as `trials_a[trial][time]`
+/// * `trials_b` - The samples of the second signal in the same trials
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `band` - The frequency band in Hz
+/// * `options` - The measure and the filter order
+///
+/// # Returns
+///
+/// The PLV or PPC at each time point, or an error if the trials do not match, the band is
+/// invalid for the filter or a trial is too short to filter
+///
+/// # Examples
+///
+/// ```
+/// let locking = band_phase_locking(&hippocampus, &prefrontal, 1000.0, (6.0, 10.0), &PhaseLockingOptions::default())?;
+/// ```
+///
+/// # Note
+///
+/// Each trial is filtered with a zero-phase Butterworth bandpass filter and its phase is
+/// taken from the analytic signal. The ends of each trial are distorted by the filter and
+/// the Hilbert transform, so epochs should extend a few cycles beyond the window of interest.
+///
+pub fn band_phase_locking(trials_a: &[Vec<f64>], trials_b: &[Vec<f64>], sampling_rate: f64, band: (f64, f64), options: &PhaseLockingOptions) -> Result<Vec<f64>, ProcessingError> {
+    validate_trials(trials_a, trials_b, options.measure)?;
+    let filter = butterworth(options.filter_order, FilterKind::Bandpass(band.0, band.1), sampling_rate)?;
+    let phases = |trials: &[Vec<f64>]| -> Result<Vec<Vec<f64>>, ProcessingError> {
+        trials.iter().map(|trial| Ok(instantaneous_phase(&filter.filtfilt(trial)?))).collect()
+    };
+    locking_epochs(&phases(trials_a)?, &phases(trials_b)?, options.measure)
+}
+
+/// Computes the band-limited phase locking between every pair of channels
+///
+/// # Arguments
+///
+/// * `trials` - The samples of each trial, indexed as `trials[trial][channel][time]`
+/// * `names` - The name of each channel
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `band` - The frequency band in Hz
+/// * `options` - The measure and the filter order
+///
+/// # Returns
+///
+/// The PhaseLockingMatrix, or an error if the trials differ in their number of channels
+/// or samples, there are too few trials for the measure, the band is invalid for the
+/// filter or a trial is too short to filter
+///
+/// # Examples
+///
+/// ```
+/// let options = PhaseLockingOptions { measure: PhaseMeasure::Ppc, ..PhaseLockingOptions::default() };
+/// let matrix = plv_matrix(&trials, &names, 1000.0, (8.0, 12.0), &options)?;
+/// ```
+///
+/// # Note
+///
+/// The measure is taken across trials at every time point, as in `band_phase_locking`,
+/// and then averaged over time. Every channel of every trial is filtered once.
+///
+pub fn plv_matrix(trials: &[Vec<Vec<f64>>], names: &[String], sampling_rate: f64, band: (f64, f64), options: &PhaseLockingOptions) -> Result<PhaseLockingMatrix, ProcessingError> {
+    let min_trials = if options.measure == PhaseMeasure::Ppc { 2 } else { 1 };
+    if trials.len() < min_trials {
+        return Err(ProcessingError::InvalidParameter(format!("Need at least {} trials, got {}", min_trials, trials.len())));
+    }
+    if let Some(trial) = trials.iter().position(|trial| trial.len() != names.len()) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Trial {} has {} channels but {} channel names were given",
+            trial,
+            trials[trial].len(),
+            names.len()
+        )));
+    }
+    let filter = butterworth(options.filter_order, FilterKind::Bandpass(band.0, band.1), sampling_rate)?;
+    // phases[channel][trial][time]
+    let mut phases = vec![Vec::with_capacity(trials.len()); names.len()];
+    for trial in trials {
+        for (channel, samples) in trial.iter().enumerate() {
+            phases[channel].push(instantaneous_phase(&filter.filtfilt(samples)?));
+        }
+    }
+
+    let mut values = vec![vec![1.0; names.len()]; names.len()];
+    for a in 0..names.len() {
+        for b in a + 1..names.len() {
+            let series = locking_epochs(&phases[a], &phases[b], options.measure)?;
+            let mean = series.iter().sum::<f64>() / series.len() as f64;
+            values[a][b] = mean;
+            values[b][a] = mean;
+        }
+    }
+    Ok(PhaseLockingMatrix { names: names.to_vec(), band, measure: options.measure, values })
+}
+
+/// Measures the consistency of a set of phase differences
+fn locking(differences: impl Iterator<Item = f64>, measure: PhaseMeasure) -> f64 {
+    let (mut re, mut im, mut n) = (0.0, 0.0, 0usize);
+    for difference in differences {
+        re += difference.cos();
+        im += difference.sin();
+        n += 1;
+    }
+    let squared = re * re + im * im;
+    match measure {
+        PhaseMeasure::Plv => squared.sqrt() / n as f64,
+        // The sum of the cosines over all ordered pairs j != k is |sum|^2 - n
+        PhaseMeasure::Ppc => (squared - n as f64) / (n as f64 * (n as f64 - 1.0)),
+    }
+}
+
+fn locking_epochs(phases_a: &[Vec<f64>], phases_b: &[Vec<f64>], measure: PhaseMeasure) -> Result<Vec<f64>, ProcessingError> {
+    validate_trials(phases_a, phases_b, measure)?;
+    Ok((0..phases_a[0].len())
+        .map(|t| locking(phases_a.iter().zip(phases_b).map(|(a, b)| a[t] - b[t]), measure))
+        .collect())
+}
+
+fn validate_lengths(phase_a: &[f64], phase_b: &[f64], min_len: usize) -> Result<(), ProcessingError> {
+    if phase_a.len() != phase_b.len() {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Got {} samples of the first signal but {} of the second",
+            phase_a.len(),
+            phase_b.len()
+        )));
+    }
+    if phase_a.len() < min_len {
+        return Err(ProcessingError::InvalidParameter(format!("Need at least {} samples, got {}", min_len, phase_a.len())));
+    }
+    Ok(())
+}
+
+fn validate_trials(trials_a: &[Vec<f64>], trials_b: &[Vec<f64>], measure: PhaseMeasure) -> Result<(), ProcessingError> {
+    if trials_a.len() != trials_b.len() {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Got {} trials of the first signal but {} of the second",
+            trials_a.len(),
+            trials_b.len()
+        )));
+    }
+    let min_trials = if measure == PhaseMeasure::Ppc { 2 } else { 1 };
+    if trials_a.len() < min_trials {
+        return Err(ProcessingError::InvalidParameter(format!("Need at least {} trials, got {}", min_trials, trials_a.len())));
+    }
+    let n_samples = trials_a[0].len();
+    if let Some(trial) = (0..trials_a.len()).find(|&trial| trials_a[trial].len() != n_samples || trials_b[trial].len() != n_samples) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Trial {} has {} and {} samples but the first trial has {}",
+            trial,
+            trials_a[trial].len(),
+            trials_b[trial].len(),
+            n_samples
+        )));
+    }
+    if n_samples == 0 {
+        return Err(ProcessingError::InvalidParameter("The trials are empty".to_string()));
+    }
+    Ok(())
+}
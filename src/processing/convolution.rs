@@ -0,0 +1,193 @@
+// A module to convolve signals with kernels, directly or through the FFT
+
+// Written by Amin Alam in 2024
+
+use num_complex::Complex64;
+use rustfft::FftPlanner;
+
+/// The part of the full convolution that is returned
+///
+/// # Arguments
+///
+/// * `Full` - Every sample where the signal and the kernel overlap, `n + m - 1` samples
+/// * `Same` - The central part with the length of the longer input
+/// * `Valid` - Only the samples where the inputs overlap completely, `max(n, m) - min(n, m) + 1` samples
+///
+/// # Examples
+///
+/// ```
+/// let smoothed = convolve(&samples, &kernel, ConvMode::Same);
+/// ```
+///
+/// # Note
+///
+/// The modes match those of numpy.convolve
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ConvMode {
+    Full,
+    Same,
+    Valid,
+}
+
+/// The number of multiply-adds above which `convolve` switches to the FFT
+const DIRECT_CONVOLUTION_LIMIT: usize = 1 << 20;
+
+/// The kernel length up to which `convolve` always convolves directly
+const DIRECT_KERNEL_LIMIT: usize = 64;
+
+/// Convolves a signal with a kernel
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `kernel` - The kernel, e.g. FIR filter taps or a template for matched filtering
+/// * `mode` - The part of the full convolution to return
+///
+/// # Returns
+///
+/// The convolved samples, or an empty vector if either input is empty
+///
+/// # Examples
+///
+/// ```
+/// let full = convolve(&[1.0, 2.0, 3.0], &[0.0, 1.0, 0.5], ConvMode::Full);
+/// assert_eq!(full, vec![0.0, 1.0, 2.5, 4.0, 1.5]);
+/// ```
+///
+/// # Note
+///
+/// Short kernels are convolved directly; when `samples.len() * kernel.len()` is large the
+/// convolution is computed through the FFT instead, which agrees with the direct result
+/// up to floating-point rounding
+///
+pub fn convolve(samples: &[f64], kernel: &[f64], mode: ConvMode) -> Vec<f64> {
+    if samples.is_empty() || kernel.is_empty() {
+        return Vec::new();
+    }
+
+    let use_fft = kernel.len().min(samples.len()) > DIRECT_KERNEL_LIMIT
+        && samples.len().saturating_mul(kernel.len()) > DIRECT_CONVOLUTION_LIMIT;
+    let full = if use_fft {
+        fft_convolve(samples, kernel)
+    } else {
+        direct_convolve(samples, kernel)
+    };
+    trim_convolution(full, samples.len(), kernel.len(), mode)
+}
+
+/// Convolves two sequences by the definition
+pub(crate) fn direct_convolve(a: &[f64], b: &[f64]) -> Vec<f64> {
+    let mut full = vec![0.0; a.len() + b.len() - 1];
+    for (i, &x) in a.iter().enumerate() {
+        for (j, &h) in b.iter().enumerate() {
+            full[i + j] += x * h;
+        }
+    }
+    full
+}
+
+/// Convolves two sequences through the FFT, returning the full convolution
+pub(crate) fn fft_convolve(a: &[f64], b: &[f64]) -> Vec<f64> {
+    let full_len = a.len() + b.len() - 1;
+    let fft_len = full_len.next_power_of_two();
+    let mut planner = FftPlanner::<f64>::new();
+    let forward = planner.plan_fft_forward(fft_len);
+    let inverse = planner.plan_fft_inverse(fft_len);
+
+    let mut a_spectrum = to_complex_padded(a, fft_len);
+    let mut b_spectrum = to_complex_padded(b, fft_len);
+    forward.process(&mut a_spectrum);
+    forward.process(&mut b_spectrum);
+    for (x, h) in a_spectrum.iter_mut().zip(&b_spectrum) {
+        *x *= h;
+    }
+    inverse.process(&mut a_spectrum);
+
+    let scale = 1.0 / fft_len as f64;
+    a_spectrum.iter().take(full_len).map(|value| value.re * scale).collect()
+}
+
+fn to_complex_padded(values: &[f64], len: usize) -> Vec<Complex64> {
+    let mut buffer: Vec<Complex64> = values.iter().map(|&value| Complex64::new(value, 0.0)).collect();
+    buffer.resize(len, Complex64::new(0.0, 0.0));
+    buffer
+}
+
+fn trim_convolution(full: Vec<f64>, n: usize, m: usize, mode: ConvMode) -> Vec<f64> {
+    let shorter = n.min(m);
+    let longer = n.max(m);
+    let (start, len) = match mode {
+        ConvMode::Full => return full,
+        ConvMode::Same => ((shorter - 1) / 2, longer),
+        ConvMode::Valid => (shorter - 1, longer - shorter + 1),
+    };
+    full[start..start + len].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    /// numpy.convolve written out from its definition
+    fn reference_convolve(a: &[f64], v: &[f64], mode: ConvMode) -> Vec<f64> {
+        let (n, m) = (a.len() as isize, v.len() as isize);
+        let full: Vec<f64> = (0..n + m - 1)
+            .map(|k| ((k - m + 1).max(0)..=k.min(n - 1)).map(|i| a[i as usize] * v[(k - i) as usize]).sum())
+            .collect();
+        let (shorter, longer) = (n.min(m), n.max(m));
+        match mode {
+            ConvMode::Full => full,
+            ConvMode::Same => full[((shorter - 1) / 2) as usize..][..longer as usize].to_vec(),
+            ConvMode::Valid => full[(shorter - 1) as usize..][..(longer - shorter + 1) as usize].to_vec(),
+        }
+    }
+
+    fn random_signal(len: usize, rng: &mut SeededRng) -> Vec<f64> {
+        (0..len).map(|_| rng.next_gaussian()).collect()
+    }
+
+    #[test]
+    fn convolve_matches_the_numpy_docstring() {
+        assert_eq!(convolve(&[1.0, 2.0, 3.0], &[0.0, 1.0, 0.5], ConvMode::Full), vec![0.0, 1.0, 2.5, 4.0, 1.5]);
+        assert_eq!(convolve(&[1.0, 2.0, 3.0], &[0.0, 1.0, 0.5], ConvMode::Same), vec![1.0, 2.5, 4.0]);
+        assert_eq!(convolve(&[1.0, 2.0, 3.0], &[0.0, 1.0, 0.5], ConvMode::Valid), vec![2.5]);
+        assert!(convolve(&[], &[1.0], ConvMode::Full).is_empty() && convolve(&[1.0], &[], ConvMode::Same).is_empty());
+    }
+
+    #[test]
+    fn convolve_matches_numpy_in_every_mode_on_both_sides_of_the_fft_switch() {
+        let mut rng = SeededRng::new(108);
+        // Direct: short kernels, and long kernels below the multiply-add limit; FFT: the rest
+        for (n, m, uses_fft) in [(1, 1, false), (7, 4, false), (4, 7, false), (6, 6, false), (20000, 64, false), (1000, 1000, false), (20000, 101, true), (101, 20000, true), (3000, 2999, true)] {
+            assert_eq!(m.min(n) > DIRECT_KERNEL_LIMIT && n * m > DIRECT_CONVOLUTION_LIMIT, uses_fft);
+            let a = random_signal(n, &mut rng);
+            let v = random_signal(m, &mut rng);
+            for mode in [ConvMode::Full, ConvMode::Same, ConvMode::Valid] {
+                let actual = convolve(&a, &v, mode);
+                let expected = reference_convolve(&a, &v, mode);
+                assert_eq!(actual.len(), expected.len(), "{} x {} {:?}", n, m, mode);
+                let scale = expected.iter().fold(1.0f64, |peak, value| peak.max(value.abs()));
+                for (x, y) in actual.iter().zip(&expected) {
+                    assert!((x - y).abs() < 1e-10 * scale, "{} x {} {:?}: {} vs {}", n, m, mode, x, y);
+                }
+                // numpy.convolve is symmetric in its arguments, and so is every mode here
+                let swapped = convolve(&v, &a, mode);
+                assert!(swapped.iter().zip(&actual).all(|(x, y)| (x - y).abs() < 1e-10 * scale));
+            }
+        }
+    }
+
+    #[test]
+    fn fft_convolution_agrees_with_the_direct_sum() {
+        let mut rng = SeededRng::new(1080);
+        let a = random_signal(513, &mut rng);
+        let b = random_signal(300, &mut rng);
+        let direct = direct_convolve(&a, &b);
+        let fft = fft_convolve(&a, &b);
+        assert_eq!(direct.len(), 812);
+        assert_eq!(fft.len(), 812);
+        assert!(direct.iter().zip(&fft).all(|(x, y)| (x - y).abs() < 1e-11));
+    }
+}
@@ -0,0 +1,416 @@
+// A module to compute cross- and auto-correlograms of spike trains
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::random::SeededRng;
+
+/// The scaling of the values of a Correlogram
+///
+/// # Arguments
+///
+/// * `Counts` - The raw number of spike pairs in each lag bin
+/// * `Rate` - The rate of the second train in spikes per second, conditioned on a spike of the first one
+/// * `JitterCorrected` - The raw counts minus their mean over surrogates in which every spike of the second train is moved uniformly within its jitter window of `window` seconds
+///
+/// # Examples
+///
+/// ```
+/// let normalization = CorrelogramNormalization::JitterCorrected { window: 0.025, n_surrogates: 100, seed: 1 };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum CorrelogramNormalization {
+    Counts,
+    Rate,
+    JitterCorrected { window: f64, n_surrogates: usize, seed: u64 },
+}
+
+/// A histogram of the lags between the spikes of two trains
+///
+/// # Arguments
+///
+/// * `lags` - The center of each lag bin in seconds, the time of the second train minus the time of the first
+/// * `values` - The value of each bin, scaled as requested
+/// * `bin_size` - The width of each lag bin in seconds
+///
+/// # Examples
+///
+/// ```
+/// let correlogram = cross(&unit_a, &unit_b, 0.001, 0.05, CorrelogramNormalization::Counts)?;
+/// correlogram.to_csv(&mut csv_io)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Correlogram {
+    pub lags: Vec<f64>,
+    pub values: Vec<f64>,
+    pub bin_size: f64,
+}
+
+/// Implementation of the Correlogram struct
+///
+/// # Methods
+///
+/// * `peak` - Finds the bin with the highest value
+/// * `to_csv` - Writes the correlogram as `lag,count` rows
+impl Correlogram {
+    /// Finds the bin with the highest value
+    ///
+    /// # Returns
+    ///
+    /// The lag and value of the highest bin, or `(NaN, NaN)` if the correlogram is empty
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (lag, _) = correlogram.peak();
+    /// ```
+    ///
+    pub fn peak(&self) -> (f64, f64) {
+        self.lags
+            .iter()
+            .zip(&self.values)
+            .fold((f64::NAN, f64::NAN), |best, (&lag, &value)| if best.1.is_nan() || value > best.1 { (lag, value) } else { best })
+    }
+
+    /// Writes the correlogram as `lag,count` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// correlogram.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first, and the `count` column holds the normalized values if a
+    /// normalization was requested. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["lag", "count"]))?;
+        for (lag, value) in self.lags.iter().zip(&self.values) {
+            csv_io.write_record(StringRecord::from(vec![float_format.format(*lag), float_format.format(*value)]))?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes the cross-correlogram of two spike trains
+///
+/// # Arguments
+///
+/// * `train_a` - The spike times of the reference train in seconds, in any order
+/// * `train_b` - The spike times of the target train in seconds, in any order
+/// * `bin_size` - The width of each lag bin in seconds
+/// * `max_lag` - The largest lag shown in seconds, rounded to a whole number of bins
+/// * `normalization` - The scaling of the values
+///
+/// # Returns
+///
+/// The Correlogram, or an error if the bin size is not positive, the maximum lag is
+/// negative, or the jitter parameters are invalid
+///
+/// # Examples
+///
+/// ```
+/// // A peak a few ms after zero suggests that unit A excites unit B
+/// let correlogram = cross(&unit_a, &unit_b, 0.0005, 0.02, CorrelogramNormalization::Rate)?;
+/// ```
+///
+/// # Note
+///
+/// The bins are centered on multiples of the bin size, so the zero-lag bin spans
+/// `[-bin_size / 2, bin_size / 2)`. Pairs are found with a two-pointer sweep over the sorted
+/// trains, so the cost grows with the number of spikes and of pairs within the maximum lag
+/// rather than with the product of the train lengths.
+///
+pub fn cross(
+    train_a: &[f64],
+    train_b: &[f64],
+    bin_size: f64,
+    max_lag: f64,
+    normalization: CorrelogramNormalization,
+) -> Result<Correlogram, ProcessingError> {
+    let half_bins = validate(bin_size, max_lag, normalization)?;
+    let a = sorted(train_a);
+    let b = sorted(train_b);
+    let counts = sweep(&a, &b, bin_size, half_bins);
+    Ok(finish(counts, &a, &b, bin_size, half_bins, normalization, false))
+}
+
+/// Computes the auto-correlogram of a spike train
+///
+/// # Arguments
+///
+/// * `train` - The spike times in seconds, in any order
+/// * `bin_size` - The width of each lag bin in seconds
+/// * `max_lag` - The largest lag shown in seconds, rounded to a whole number of bins
+/// * `normalization` - The scaling of the values
+///
+/// # Returns
+///
+/// The Correlogram, or an error if the bin size is not positive, the maximum lag is
+/// negative, or the jitter parameters are invalid
+///
+/// # Examples
+///
+/// ```
+/// // An empty center reflects the refractory period of a well-isolated unit
+/// let correlogram = auto(&unit, 0.0005, 0.02, CorrelogramNormalization::Counts)?;
+/// ```
+///
+/// # Note
+///
+/// The pairing of every spike with itself is removed from the zero-lag bin, so only
+/// distinct spikes that coincide remain there. With jitter correction, the pairing of every
+/// spike with its own jittered copy is likewise removed from the predictor.
+///
+pub fn auto(train: &[f64], bin_size: f64, max_lag: f64, normalization: CorrelogramNormalization) -> Result<Correlogram, ProcessingError> {
+    let half_bins = validate(bin_size, max_lag, normalization)?;
+    let a = sorted(train);
+    let mut counts = sweep(&a, &a, bin_size, half_bins);
+    counts[half_bins] -= a.len() as f64;
+    Ok(finish(counts, &a, &a, bin_size, half_bins, normalization, true))
+}
+
+fn validate(bin_size: f64, max_lag: f64, normalization: CorrelogramNormalization) -> Result<usize, ProcessingError> {
+    if !(bin_size > 0.0 && bin_size.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!("Bin size must be positive, got {}", bin_size)));
+    }
+    if !(max_lag >= 0.0 && max_lag.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!("Maximum lag must be non-negative, got {}", max_lag)));
+    }
+    if let CorrelogramNormalization::JitterCorrected { window, n_surrogates, .. } = normalization {
+        if !(window > 0.0 && window.is_finite()) || n_surrogates == 0 {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Jitter window must be positive and at least one surrogate is needed, got {:?}",
+                normalization
+            )));
+        }
+    }
+    Ok((max_lag / bin_size).round() as usize)
+}
+
+fn sorted(train: &[f64]) -> Vec<f64> {
+    let mut sorted: Vec<f64> = train.iter().copied().filter(|time| time.is_finite()).collect();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    sorted
+}
+
+/// Counts the lags `b - a` of all pairs within `half_bins + 0.5` bins with a two-pointer sweep
+fn sweep(a: &[f64], b: &[f64], bin_size: f64, half_bins: usize) -> Vec<f64> {
+    let reach = (half_bins as f64 + 0.5) * bin_size;
+    let mut counts = vec![0.0; 2 * half_bins + 1];
+    let mut start = 0;
+    for &time in a {
+        while start < b.len() && b[start] < time - reach {
+            start += 1;
+        }
+        for &other in b[start..].iter().take_while(|&&other| other < time + reach) {
+            if let Some(bin) = lag_bin(other - time, bin_size, half_bins) {
+                counts[bin] += 1.0;
+            }
+        }
+    }
+    counts
+}
+
+/// Returns the bin of a lag, or `None` if it is beyond the maximum lag
+fn lag_bin(lag: f64, bin_size: f64, half_bins: usize) -> Option<usize> {
+    let bin = (lag / bin_size + half_bins as f64 + 0.5).floor();
+    if bin >= 0.0 && bin < (2 * half_bins + 1) as f64 {
+        Some(bin as usize)
+    } else {
+        None
+    }
+}
+
+fn finish(
+    counts: Vec<f64>,
+    a: &[f64],
+    b: &[f64],
+    bin_size: f64,
+    half_bins: usize,
+    normalization: CorrelogramNormalization,
+    is_auto: bool,
+) -> Correlogram {
+    let lags = (0..counts.len()).map(|k| (k as f64 - half_bins as f64) * bin_size).collect();
+    let values = match normalization {
+        CorrelogramNormalization::Counts => counts,
+        CorrelogramNormalization::Rate => {
+            let pairs = a.len().max(1) as f64 * bin_size;
+            counts.iter().map(|count| count / pairs).collect()
+        }
+        CorrelogramNormalization::JitterCorrected { window, n_surrogates, seed } => {
+            let mut rng = SeededRng::new(seed);
+            let mut predictor = vec![0.0; counts.len()];
+            for _ in 0..n_surrogates {
+                let mut jittered: Vec<f64> = b
+                    .iter()
+                    .map(|&time| (time / window).floor() * window + rng.next_f64() * window)
+                    .collect();
+                if is_auto {
+                    // Remove the pairing of every spike with its own jittered copy
+                    for (&original, &moved) in a.iter().zip(&jittered) {
+                        if let Some(bin) = lag_bin(moved - original, bin_size, half_bins) {
+                            predictor[bin] -= 1.0;
+                        }
+                    }
+                }
+                jittered.sort_by(|x, y| x.total_cmp(y));
+                let surrogate = sweep(a, &jittered, bin_size, half_bins);
+                for (sum, count) in predictor.iter_mut().zip(surrogate) {
+                    *sum += count;
+                }
+            }
+            counts.iter().zip(&predictor).map(|(count, sum)| count - sum / n_surrogates as f64).collect()
+        }
+    };
+    Correlogram { lags, values, bin_size }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn poisson(rate: f64, duration: f64, seed: u64) -> Vec<f64> {
+        let mut rng = SeededRng::new(seed);
+        let mut times = Vec::new();
+        let mut time = 0.0;
+        loop {
+            time -= (1.0 - rng.next_f64()).ln() / rate;
+            if time > duration {
+                return times;
+            }
+            times.push(time);
+        }
+    }
+
+    /// Counts the lags of all pairs, bin `k` holding the lags in `[(k - h - 1/2) bin_size, (k - h + 1/2) bin_size)`
+    fn reference_counts(a: &[f64], b: &[f64], bin_size: f64, half_bins: usize, skip_self: bool) -> Vec<f64> {
+        let mut counts = vec![0.0; 2 * half_bins + 1];
+        for (i, x) in a.iter().enumerate() {
+            for (j, y) in b.iter().enumerate() {
+                if skip_self && i == j {
+                    continue;
+                }
+                let position = (y - x) / bin_size + half_bins as f64 + 0.5;
+                if position >= 0.0 && position < counts.len() as f64 {
+                    counts[position as usize] += 1.0;
+                }
+            }
+        }
+        counts
+    }
+
+    #[test]
+    fn a_train_shifted_by_5_ms_peaks_in_the_5_ms_bin() {
+        let a = poisson(10.0, 100.0, 1);
+        let b: Vec<f64> = a.iter().map(|time| time + 0.005).collect();
+        let correlogram = cross(&a, &b, 0.001, 0.02, CorrelogramNormalization::Counts).unwrap();
+        assert_eq!(correlogram.lags.len(), 41);
+        assert_eq!(correlogram.bin_size, 0.001);
+        let (lag, count) = correlogram.peak();
+        assert!((lag - 0.005).abs() < 1e-12);
+        // Every spike pairs with its shifted copy, plus about 10 chance pairs per bin
+        assert!(count >= a.len() as f64 && count < a.len() as f64 + 40.0, "{} of {}", count, a.len());
+        let others = correlogram.values.iter().filter(|&&value| value != count).fold(0.0f64, |max, &value| max.max(value));
+        assert!(others < 40.0);
+
+        // The reverse direction peaks at -5 ms, and the rate is conditioned on a reference spike
+        assert!((cross(&b, &a, 0.001, 0.02, CorrelogramNormalization::Counts).unwrap().peak().0 + 0.005).abs() < 1e-12);
+        let rate = cross(&a, &b, 0.001, 0.02, CorrelogramNormalization::Rate).unwrap();
+        assert!(rate.values.iter().zip(&correlogram.values).all(|(r, c)| (r - c / (a.len() as f64 * 0.001)).abs() < 1e-9));
+        assert!(rate.peak().1 > 1000.0);
+    }
+
+    #[test]
+    fn the_sweep_matches_all_pairs_and_the_auto_correlogram_drops_self_pairs() {
+        let a = poisson(40.0, 20.0, 2);
+        let b = poisson(25.0, 20.0, 3);
+        let correlogram = cross(&a, &b, 0.002, 0.05, CorrelogramNormalization::Counts).unwrap();
+        assert_eq!(correlogram.values, reference_counts(&a, &b, 0.002, 25, false));
+
+        let mut shuffled = a.clone();
+        shuffled.reverse();
+        let autocorrelogram = auto(&shuffled, 0.002, 0.05, CorrelogramNormalization::Counts).unwrap();
+        assert_eq!(autocorrelogram.values, reference_counts(&a, &a, 0.002, 25, true));
+        // Symmetric, with no self pairs in the center
+        assert!((0..51).all(|k| autocorrelogram.values[k] == autocorrelogram.values[50 - k]));
+        assert_eq!(auto(&[1.0, 2.0, 3.0], 0.001, 0.01, CorrelogramNormalization::Counts).unwrap().values[10], 0.0);
+        // A duplicated spike pairs with its copy in both orders
+        assert_eq!(auto(&[1.0, 2.0, 2.0], 0.001, 0.01, CorrelogramNormalization::Counts).unwrap().values[10], 2.0);
+
+        // Hundreds of thousands of spikes are swept quickly
+        let long = poisson(50.0, 4000.0, 4);
+        assert!(long.len() > 190_000);
+        let long_auto = auto(&long, 0.0005, 0.02, CorrelogramNormalization::Counts).unwrap();
+        let pairs: f64 = long_auto.values.iter().sum();
+        // Each spike has about 2 * 0.02025 s * 50 Hz neighbours within the maximum lag
+        assert!((pairs / long.len() as f64 - 2.025).abs() < 0.05, "{}", pairs / long.len() as f64);
+    }
+
+    #[test]
+    fn jitter_correction_centers_independent_trains_and_keeps_fine_synchrony() {
+        let a = poisson(10.0, 200.0, 5);
+        let independent = poisson(10.0, 200.0, 6);
+        let jitter = CorrelogramNormalization::JitterCorrected { window: 0.025, n_surrogates: 50, seed: 7 };
+        let corrected = cross(&a, &independent, 0.001, 0.02, jitter).unwrap();
+        let mean = corrected.values.iter().sum::<f64>() / corrected.values.len() as f64;
+        let raw = cross(&a, &independent, 0.001, 0.02, CorrelogramNormalization::Counts).unwrap();
+        assert!(raw.values.iter().sum::<f64>() / 41.0 > 15.0);
+        assert!(mean.abs() < 1.0, "{}", mean);
+
+        let shifted: Vec<f64> = a.iter().map(|time| time + 0.005).collect();
+        let synchrony = cross(&a, &shifted, 0.001, 0.02, jitter).unwrap();
+        let (lag, value) = synchrony.peak();
+        assert!((lag - 0.005).abs() < 1e-12);
+        assert!(value > 0.9 * a.len() as f64, "{} of {}", value, a.len());
+        // The same seed gives the same surrogates
+        assert_eq!(synchrony, cross(&a, &shifted, 0.001, 0.02, jitter).unwrap());
+        let other_seed = CorrelogramNormalization::JitterCorrected { window: 0.025, n_surrogates: 50, seed: 8 };
+        assert_ne!(synchrony, cross(&a, &shifted, 0.001, 0.02, other_seed).unwrap());
+
+        // The jittered copy of each spike is not counted in the auto-correlogram predictor, so
+        // the center is the difference of two counts of about 20 chance pairs, not about -2000
+        let auto_corrected = auto(&a, 0.001, 0.02, jitter).unwrap();
+        assert!(auto_corrected.values[20].abs() < 15.0, "{}", auto_corrected.values[20]);
+    }
+
+    #[test]
+    fn correlograms_export_as_lag_count_rows_and_reject_invalid_options() {
+        let correlogram = cross(&[1.0, 2.0], &[1.001, 2.001], 0.001, 0.002, CorrelogramNormalization::Counts).unwrap();
+        let path = std::env::temp_dir().join(format!("neurorust-correlogram-{}-ccg.csv", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        correlogram.to_csv(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rows: Vec<Vec<f64>> = written.lines().skip(1).map(|line| line.split(',').map(|value| value.parse().unwrap()).collect()).collect();
+        assert_eq!(written.lines().next(), Some("lag,count"));
+        assert_eq!(rows.len(), 5);
+        assert!((rows[3][0] - 0.001).abs() < 1e-12 && rows[3][1] == 2.0);
+
+        let empty = Correlogram { lags: vec![], values: vec![], bin_size: 0.001 };
+        assert!(empty.peak().0.is_nan() && empty.peak().1.is_nan());
+        assert!(cross(&[], &[], 0.0, 0.01, CorrelogramNormalization::Counts).is_err());
+        assert!(cross(&[], &[], 0.001, -0.01, CorrelogramNormalization::Counts).is_err());
+        let no_surrogates = CorrelogramNormalization::JitterCorrected { window: 0.025, n_surrogates: 0, seed: 0 };
+        assert!(auto(&[], 0.001, 0.01, no_surrogates).is_err());
+    }
+}
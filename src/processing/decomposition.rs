@@ -0,0 +1,852 @@
+// A module to decompose data matrices such as spike waveforms or multi-channel recordings
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::map_channels;
+use crate::processing::linalg::jacobi_svd;
+use crate::processing::random::SeededRng;
+
+/// A principal component analysis fitted on a data matrix
+///
+/// # Arguments
+///
+/// * `mean` - The mean of each feature, subtracted before projecting
+/// * `components` - The principal axes, one unit vector of feature loadings per component, by decreasing variance
+/// * `explained_variance` - The variance of the scores along each component, with `n - 1` degrees of freedom
+/// * `explained_variance_ratio` - The fraction of the total variance explained by each component
+/// * `scores` - The projection of each observation onto the components
+///
+/// # Examples
+///
+/// ```
+/// let result = pca(&waveforms, 3)?;
+/// println!("The first three PCs explain {:.1}%", 100.0 * result.explained_variance_ratio.iter().sum::<f64>());
+/// ```
+///
+/// # Note
+///
+/// The sign of each component is fixed so that its largest loading is positive, so results
+/// are reproducible across runs
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PcaResult {
+    pub mean: Vec<f64>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub components: Vec<Vec<f64>>,
+    pub explained_variance: Vec<f64>,
+    pub explained_variance_ratio: Vec<f64>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub scores: Vec<Vec<f64>>,
+}
+
+/// Implementation of the PcaResult struct
+///
+/// # Methods
+///
+/// * `transform` - Projects new observations onto the components
+/// * `inverse_transform` - Reconstructs observations from their scores
+/// * `to_csv` - Writes the scores as `pc1,pc2,...` rows
+impl PcaResult {
+    /// Projects new observations onto the components
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The observations, one row per observation with the features of the fitted data
+    ///
+    /// # Returns
+    ///
+    /// The scores of each observation, or an error if a row has the wrong number of features
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let new_features = result.transform(&new_waveforms)?;
+    /// ```
+    ///
+    pub fn transform(&self, data: &[Vec<f64>]) -> Result<Vec<Vec<f64>>, ProcessingError> {
+        check_row_lengths(data, self.mean.len())?;
+        Ok(data
+            .iter()
+            .map(|row| {
+                self.components
+                    .iter()
+                    .map(|component| component.iter().zip(row).zip(&self.mean).map(|((w, x), m)| w * (x - m)).sum())
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Reconstructs observations from their scores
+    ///
+    /// # Arguments
+    ///
+    /// * `scores` - The scores, one row per observation with one value per component
+    ///
+    /// # Returns
+    ///
+    /// The observations in the original feature space, or an error if a row has the wrong
+    /// number of scores
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let denoised = result.inverse_transform(&result.scores)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// With fewer components than features, this is the best reconstruction in the least-squares sense
+    ///
+    pub fn inverse_transform(&self, scores: &[Vec<f64>]) -> Result<Vec<Vec<f64>>, ProcessingError> {
+        check_row_lengths(scores, self.components.len())?;
+        Ok(scores
+            .iter()
+            .map(|row| {
+                let mut reconstructed = self.mean.clone();
+                for (score, component) in row.iter().zip(&self.components) {
+                    for (value, loading) in reconstructed.iter_mut().zip(component) {
+                        *value += score * loading;
+                    }
+                }
+                reconstructed
+            })
+            .collect())
+    }
+
+    /// Writes the scores as `pc1,pc2,...` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// result.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        let header: Vec<String> = (1..=self.components.len()).map(|k| format!("pc{}", k)).collect();
+        csv_io.write_record(StringRecord::from(header))?;
+        for row in &self.scores {
+            csv_io.write_record(StringRecord::from(row.iter().map(|score| float_format.format(*score)).collect::<Vec<String>>()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes the principal components of a data matrix
+///
+/// # Arguments
+///
+/// * `data` - The observations, one row per observation (e.g. one spike waveform) and one column per feature
+/// * `n_components` - The number of components to keep
+///
+/// # Returns
+///
+/// The fitted PcaResult, or an error if the matrix is empty, its rows differ in length, or
+/// more components than features are requested
+///
+/// # Examples
+///
+/// ```
+/// let result = pca(&detection.waveforms, 3)?;
+/// let features = result.scores;
+/// ```
+///
+/// # Note
+///
+/// The centered matrix is decomposed by a one-sided Jacobi SVD rather than forming its
+/// covariance matrix, which squares the condition number. If `n_components` exceeds the rank
+/// of the centered matrix, the extra components are orthonormal directions of zero explained
+/// variance and the corresponding scores are 0 up to rounding.
+///
+pub fn pca(data: &[Vec<f64>], n_components: usize) -> Result<PcaResult, ProcessingError> {
+    let n_features = data.first().map_or(0, |row| row.len());
+    if data.is_empty() || n_features == 0 {
+        return Err(ProcessingError::InvalidParameter("PCA needs at least one observation and one feature".to_string()));
+    }
+    check_row_lengths(data, n_features)?;
+    if n_components == 0 || n_components > n_features {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Number of components must be between 1 and the number of features {}, got {}",
+            n_features, n_components
+        )));
+    }
+
+    let n = data.len();
+    let mean: Vec<f64> = (0..n_features).map(|j| data.iter().map(|row| row[j]).sum::<f64>() / n as f64).collect();
+    let centered: Vec<Vec<f64>> = (0..n_features).map(|j| data.iter().map(|row| row[j] - mean[j]).collect()).collect();
+    let (singular_values, mut scaled_scores, mut axes) = jacobi_svd(&centered);
+
+    // Make the largest loading of each component positive
+    for (axis, scores) in axes.iter_mut().zip(scaled_scores.iter_mut()) {
+        let largest = axis.iter().copied().fold(0.0f64, |largest, value| if value.abs() > largest.abs() { value } else { largest });
+        if largest < 0.0 {
+            axis.iter_mut().for_each(|value| *value = -*value);
+            scores.iter_mut().for_each(|value| *value = -*value);
+        }
+    }
+
+    let degrees_of_freedom = (n.max(2) - 1) as f64;
+    let variances: Vec<f64> = singular_values.iter().map(|sigma| sigma * sigma / degrees_of_freedom).collect();
+    let total_variance: f64 = variances.iter().sum();
+    let explained_variance: Vec<f64> = variances[..n_components].to_vec();
+    let explained_variance_ratio = explained_variance
+        .iter()
+        .map(|variance| if total_variance > 0.0 { variance / total_variance } else { 0.0 })
+        .collect();
+    let scores = (0..n).map(|i| scaled_scores[..n_components].iter().map(|column| column[i]).collect()).collect();
+    axes.truncate(n_components);
+
+    Ok(PcaResult { mean, components: axes, explained_variance, explained_variance_ratio, scores })
+}
+
+/// Options of `fast_ica`
+///
+/// # Arguments
+///
+/// * `max_iterations` - The maximum number of fixed-point iterations, 200 by default
+/// * `tolerance` - The largest change of the unmixing directions at convergence, 1e-4 by default
+/// * `seed` - The seed of the random initial unmixing matrix, 0 by default
+///
+/// # Examples
+///
+/// ```
+/// let options = IcaOptions { max_iterations: 1000, ..IcaOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IcaOptions {
+    pub max_iterations: usize,
+    pub tolerance: f64,
+    pub seed: u64,
+}
+
+impl Default for IcaOptions {
+    fn default() -> Self {
+        Self { max_iterations: 200, tolerance: 1e-4, seed: 0 }
+    }
+}
+
+/// An independent component analysis fitted on multi-channel data
+///
+/// # Arguments
+///
+/// * `mean` - The mean of each channel, subtracted before unmixing
+/// * `unmixing` - The unmixing matrix, one row of channel weights per component
+/// * `mixing` - The mixing matrix, one row of component weights per channel
+/// * `sources` - The samples of each independent component, with unit variance
+/// * `iterations` - The number of iterations until convergence
+///
+/// # Examples
+///
+/// ```
+/// let ica = fast_ica(&channels, 20, &IcaOptions::default())?;
+/// let cleaned = ica.reconstruct_excluding(&[0, 3])?;
+/// ```
+///
+/// # Note
+///
+/// The order of the components is arbitrary. The sign of each component is fixed so that
+/// its largest mixing weight is positive, so results are reproducible for a given seed.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IcaResult {
+    pub mean: Vec<f64>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub unmixing: Vec<Vec<f64>>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub mixing: Vec<Vec<f64>>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub sources: Vec<Vec<f64>>,
+    pub iterations: usize,
+}
+
+/// Implementation of the IcaResult struct
+///
+/// # Methods
+///
+/// * `transform` - Unmixes new data into the fitted components
+/// * `reconstruct_excluding` - Reconstructs the channels without some components
+/// * `to_csv` - Writes the sources as `ic1,ic2,...` rows
+impl IcaResult {
+    /// Unmixes new data into the fitted components
+    ///
+    /// # Arguments
+    ///
+    /// * `data` - The samples of each channel, with the channels of the fitted data
+    ///
+    /// # Returns
+    ///
+    /// The samples of each component, or an error if the number of channels differs or the
+    /// channels differ in length
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let sources = ica.transform(&next_block)?;
+    /// ```
+    ///
+    pub fn transform(&self, data: &[Vec<f64>]) -> Result<Vec<Vec<f64>>, ProcessingError> {
+        if data.len() != self.mean.len() {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Got {} channels but the ICA was fitted on {}",
+                data.len(),
+                self.mean.len()
+            )));
+        }
+        check_row_lengths(data, data.first().map_or(0, |row| row.len()))?;
+        let centered: Vec<Vec<f64>> = data.iter().zip(&self.mean).map(|(row, m)| row.iter().map(|x| x - m).collect()).collect();
+        Ok(multiply(&self.unmixing, &centered))
+    }
+
+    /// Reconstructs the channels without some components
+    ///
+    /// # Arguments
+    ///
+    /// * `excluded` - The indices of the components to remove, e.g. blink or muscle components
+    ///
+    /// # Returns
+    ///
+    /// The samples of each channel, or an error if an index is not a component
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let blink = 2;
+    /// let cleaned = ica.reconstruct_excluding(&[blink])?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// With fewer components than channels, the variance outside the retained principal
+    /// subspace is lost even if no component is excluded
+    ///
+    pub fn reconstruct_excluding(&self, excluded: &[usize]) -> Result<Vec<Vec<f64>>, ProcessingError> {
+        if let Some(index) = excluded.iter().find(|&&index| index >= self.sources.len()) {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Component {} does not exist, there are {}",
+                index,
+                self.sources.len()
+            )));
+        }
+        let n_samples = self.sources.first().map_or(0, |source| source.len());
+        Ok(self
+            .mixing
+            .iter()
+            .zip(&self.mean)
+            .map(|(weights, mean)| {
+                let mut channel = vec![*mean; n_samples];
+                for (component, (weight, source)) in weights.iter().zip(&self.sources).enumerate() {
+                    if excluded.contains(&component) {
+                        continue;
+                    }
+                    for (value, s) in channel.iter_mut().zip(source) {
+                        *value += weight * s;
+                    }
+                }
+                channel
+            })
+            .collect())
+    }
+
+    /// Writes the sources as `ic1,ic2,...` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// ica.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first, then one row per sample. The rows are not flushed to
+    /// disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        let header: Vec<String> = (1..=self.sources.len()).map(|k| format!("ic{}", k)).collect();
+        csv_io.write_record(StringRecord::from(header))?;
+        let n_samples = self.sources.first().map_or(0, |source| source.len());
+        for t in 0..n_samples {
+            csv_io.write_record(StringRecord::from(self.sources.iter().map(|source| float_format.format(source[t])).collect::<Vec<String>>()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes the independent components of multi-channel data with FastICA
+///
+/// # Arguments
+///
+/// * `data` - The samples of each channel, one row per channel
+/// * `n_components` - The number of components, at most the number of channels
+/// * `options` - The stopping rule and seed
+///
+/// # Returns
+///
+/// The fitted IcaResult, an error if the data is empty, its rows differ in length, the
+/// number of components is invalid or the retained principal subspace is rank deficient,
+/// or `ProcessingError::NotConverged` if the iterations do not converge
+///
+/// # Examples
+///
+/// ```
+/// let ica = fast_ica(&eeg, 15, &IcaOptions { seed: 7, ..IcaOptions::default() })?;
+/// ```
+///
+/// # Note
+///
+/// The data is centered and whitened onto its first `n_components` principal components,
+/// then the symmetric (parallel) FastICA fixed-point iteration with the logcosh contrast
+/// finds an orthogonal unmixing of the whitened data, as in sklearn.decomposition.FastICA.
+/// The components are updated in parallel.
+///
+pub fn fast_ica(data: &[Vec<f64>], n_components: usize, options: &IcaOptions) -> Result<IcaResult, ProcessingError> {
+    let n_samples = data.first().map_or(0, |row| row.len());
+    if data.is_empty() || n_samples < 2 {
+        return Err(ProcessingError::InvalidParameter("ICA needs at least one channel with two samples".to_string()));
+    }
+    check_row_lengths(data, n_samples)?;
+    if n_components == 0 || n_components > data.len() {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Number of components must be between 1 and the number of channels {}, got {}",
+            data.len(),
+            n_components
+        )));
+    }
+
+    // Whiten onto the leading eigenvectors of the covariance matrix
+    let mean: Vec<f64> = data.iter().map(|row| row.iter().sum::<f64>() / n_samples as f64).collect();
+    let centered: Vec<Vec<f64>> = data.iter().zip(&mean).map(|(row, m)| row.iter().map(|x| x - m).collect()).collect();
+    let covariance: Vec<Vec<f64>> = centered
+        .iter()
+        .map(|a| centered.iter().map(|b| a.iter().zip(b).map(|(x, y)| x * y).sum::<f64>() / n_samples as f64).collect())
+        .collect();
+    let (eigenvalues, _, eigenvectors) = jacobi_svd(&covariance);
+    if eigenvalues[n_components - 1].is_nan() || eigenvalues[n_components - 1] <= eigenvalues[0] * 1e-12 {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "The data has fewer than {} linearly independent channels",
+            n_components
+        )));
+    }
+    let whitening: Vec<Vec<f64>> = (0..n_components)
+        .map(|k| eigenvectors[k].iter().map(|v| v / eigenvalues[k].sqrt()).collect())
+        .collect();
+    let whitened = multiply(&whitening, &centered);
+
+    let mut rng = SeededRng::new(options.seed);
+    let initial: Vec<Vec<f64>> = (0..n_components).map(|_| (0..n_components).map(|_| rng.next_gaussian()).collect()).collect();
+    let mut w = decorrelate(&initial);
+    let mut iterations = 0;
+    loop {
+        if iterations == options.max_iterations {
+            return Err(ProcessingError::NotConverged { iterations });
+        }
+        iterations += 1;
+        let updated = decorrelate(&map_channels(&w, |row| logcosh_update(row, &whitened)));
+        let change = updated
+            .iter()
+            .zip(&w)
+            .map(|(new, old)| (new.iter().zip(old).map(|(a, b)| a * b).sum::<f64>().abs() - 1.0).abs())
+            .fold(0.0, f64::max);
+        w = updated;
+        if change < options.tolerance {
+            break;
+        }
+    }
+
+    let mut unmixing = multiply(&w, &whitening);
+    let mut sources = multiply(&w, &whitened);
+    // The inverse of the whitening is `E sqrt(D)`, and `w` is orthogonal
+    let mut mixing: Vec<Vec<f64>> = (0..data.len())
+        .map(|channel| {
+            w.iter()
+                .map(|row| row.iter().enumerate().map(|(k, weight)| eigenvectors[k][channel] * eigenvalues[k].sqrt() * weight).sum())
+                .collect()
+        })
+        .collect();
+    // Make the largest mixing weight of each component positive
+    for component in 0..n_components {
+        let largest = mixing.iter().map(|row| row[component]).fold(0.0f64, |largest, value| if value.abs() > largest.abs() { value } else { largest });
+        if largest < 0.0 {
+            mixing.iter_mut().for_each(|row| row[component] = -row[component]);
+            unmixing[component].iter_mut().for_each(|value| *value = -*value);
+            sources[component].iter_mut().for_each(|value| *value = -*value);
+        }
+    }
+    Ok(IcaResult { mean, unmixing, mixing, sources, iterations })
+}
+
+/// One FastICA fixed-point step of an unmixing direction with the logcosh contrast, `E[z g(wᵀz)] - E[g'(wᵀz)] w`
+fn logcosh_update(row: &[f64], whitened: &[Vec<f64>]) -> Vec<f64> {
+    let n_samples = whitened[0].len();
+    let mut updated = vec![0.0; row.len()];
+    let mut derivative = 0.0;
+    for t in 0..n_samples {
+        let g = row.iter().zip(whitened).map(|(weight, z)| weight * z[t]).sum::<f64>().tanh();
+        derivative += 1.0 - g * g;
+        for (value, z) in updated.iter_mut().zip(whitened) {
+            *value += g * z[t];
+        }
+    }
+    updated
+        .iter()
+        .zip(row)
+        .map(|(value, weight)| (value - derivative * weight) / n_samples as f64)
+        .collect()
+}
+
+/// Symmetrically orthogonalizes the rows of a square matrix, `(W Wᵀ)^(-1/2) W`
+fn decorrelate(w: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let gram: Vec<Vec<f64>> = w.iter().map(|a| w.iter().map(|b| a.iter().zip(b).map(|(x, y)| x * y).sum()).collect()).collect();
+    let (eigenvalues, _, eigenvectors) = jacobi_svd(&gram);
+    let n = w.len();
+    let inverse_sqrt: Vec<Vec<f64>> = (0..n)
+        .map(|i| (0..n).map(|j| (0..n).map(|k| eigenvectors[k][i] * eigenvectors[k][j] / eigenvalues[k].sqrt()).sum()).collect())
+        .collect();
+    multiply(&inverse_sqrt, w)
+}
+
+/// Multiplies two matrices stored as rows
+fn multiply(a: &[Vec<f64>], b: &[Vec<f64>]) -> Vec<Vec<f64>> {
+    let n_columns = b.first().map_or(0, |row| row.len());
+    a.iter()
+        .map(|row| {
+            let mut product = vec![0.0; n_columns];
+            for (weight, b_row) in row.iter().zip(b) {
+                for (value, x) in product.iter_mut().zip(b_row) {
+                    *value += weight * x;
+                }
+            }
+            product
+        })
+        .collect()
+}
+
+/// Checks that every row holds `expected` values
+pub(crate) fn check_row_lengths(rows: &[Vec<f64>], expected: usize) -> Result<(), ProcessingError> {
+    match rows.iter().position(|row| row.len() != expected) {
+        Some(index) => Err(ProcessingError::InvalidParameter(format!(
+            "Row {} has {} values but {} were expected",
+            index,
+            rows[index].len(),
+            expected
+        ))),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::spikes::SpikeDetectionResult;
+
+    fn dot(a: &[f64], b: &[f64]) -> f64 {
+        a.iter().zip(b).map(|(x, y)| x * y).sum()
+    }
+
+    /// Waveforms of 48 samples mixing three templates with well separated variances, plus noise of `noise` standard deviation
+    fn waveforms(n: usize, noise: f64, seed: u64) -> Vec<Vec<f64>> {
+        let mut rng = SeededRng::new(seed);
+        let templates: Vec<Vec<f64>> = (0..3)
+            .map(|k| (0..48).map(|t| (-(t as f64 - 16.0 - 4.0 * k as f64).powi(2) / (4.0 + 6.0 * k as f64)).exp()).collect())
+            .collect();
+        (0..n)
+            .map(|_| {
+                let weights = [5.0 * rng.next_gaussian(), 2.0 * rng.next_gaussian(), 0.8 * rng.next_gaussian()];
+                (0..48)
+                    .map(|t| 1.0 + weights.iter().zip(&templates).map(|(w, template)| w * template[t]).sum::<f64>() + noise * rng.next_gaussian())
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// The leading eigenpairs of the sample covariance matrix by power iteration with deflation
+    fn reference_eigenpairs(data: &[Vec<f64>], k: usize) -> Vec<(f64, Vec<f64>)> {
+        let n = data.len() as f64;
+        let d = data[0].len();
+        let mean: Vec<f64> = (0..d).map(|j| data.iter().map(|row| row[j]).sum::<f64>() / n).collect();
+        let mut covariance: Vec<Vec<f64>> = (0..d)
+            .map(|i| (0..d).map(|j| data.iter().map(|row| (row[i] - mean[i]) * (row[j] - mean[j])).sum::<f64>() / (n - 1.0)).collect())
+            .collect();
+        let mut pairs = Vec::new();
+        for _ in 0..k {
+            let mut vector = vec![1.0; d];
+            let mut value = 0.0;
+            for _ in 0..2000 {
+                let next: Vec<f64> = covariance.iter().map(|row| dot(row, &vector)).collect();
+                let norm = dot(&next, &next).sqrt();
+                value = norm;
+                vector = next.iter().map(|x| x / norm).collect();
+            }
+            for (i, row) in covariance.iter_mut().enumerate() {
+                for (j, entry) in row.iter_mut().enumerate() {
+                    *entry -= value * vector[i] * vector[j];
+                }
+            }
+            pairs.push((value, vector));
+        }
+        pairs
+    }
+
+    #[test]
+    fn pca_matches_the_sklearn_example() {
+        // The example of sklearn.decomposition.PCA
+        let data = vec![vec![-1.0, -1.0], vec![-2.0, -1.0], vec![-3.0, -2.0], vec![1.0, 1.0], vec![2.0, 1.0], vec![3.0, 2.0]];
+        let result = pca(&data, 2).unwrap();
+        assert!((result.explained_variance_ratio[0] - 0.99244289).abs() < 1e-8);
+        assert!((result.explained_variance_ratio[1] - 0.00755711).abs() < 1e-8);
+        let singular_values: Vec<f64> = result.explained_variance.iter().map(|variance| (variance * 5.0).sqrt()).collect();
+        assert!((singular_values[0] - 6.30061232).abs() < 1e-8);
+        assert!((singular_values[1] - 0.54980396).abs() < 1e-8);
+        assert_eq!(result.mean, vec![0.0, 0.0]);
+    }
+
+    #[test]
+    fn pca_of_thousands_of_waveforms_matches_the_covariance_eigenpairs() {
+        let data = waveforms(3000, 0.05, 1);
+        let result = pca(&data, 5).unwrap();
+        assert_eq!(result.scores.len(), 3000);
+        assert!(result.scores.iter().all(|row| row.len() == 5));
+        for ((variance, component), (expected_variance, expected_component)) in
+            result.explained_variance.iter().zip(&result.components).take(3).zip(reference_eigenpairs(&data, 3))
+        {
+            assert!((variance / expected_variance - 1.0).abs() < 1e-9, "{} vs {}", variance, expected_variance);
+            assert!((dot(component, &expected_component).abs() - 1.0).abs() < 1e-9);
+        }
+        // Decreasing variance, ratios of the total, orthonormal and sign-fixed components
+        assert!(result.explained_variance.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert!(result.explained_variance_ratio.iter().take(3).sum::<f64>() > 0.99);
+        for (i, a) in result.components.iter().enumerate() {
+            for (j, b) in result.components.iter().enumerate() {
+                assert!((dot(a, b) - if i == j { 1.0 } else { 0.0 }).abs() < 1e-12);
+            }
+            let largest = a.iter().copied().fold(0.0f64, |largest, value| if value.abs() > largest.abs() { value } else { largest });
+            assert!(largest > 0.0);
+        }
+        // The scores are the centered data projected onto the components
+        assert_eq!(result.transform(&data).unwrap().len(), 3000);
+        for (row, expected) in result.transform(&data).unwrap().iter().zip(&result.scores) {
+            assert!(row.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-10));
+        }
+    }
+
+    #[test]
+    fn reconstruction_error_is_the_discarded_variance() {
+        let data = waveforms(500, 0.05, 2);
+        let full = pca(&data, 48).unwrap();
+        assert!((full.explained_variance_ratio.iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        let exact = full.inverse_transform(&full.scores).unwrap();
+        assert!(exact.iter().flatten().zip(data.iter().flatten()).all(|(a, b)| (a - b).abs() < 1e-10));
+
+        for n_components in [1, 3, 10] {
+            let result = pca(&data, n_components).unwrap();
+            let reconstructed = result.inverse_transform(&result.scores).unwrap();
+            let squared_error: f64 = reconstructed.iter().flatten().zip(data.iter().flatten()).map(|(a, b)| (a - b).powi(2)).sum();
+            let discarded: f64 = full.explained_variance[n_components..].iter().sum();
+            assert!((squared_error / 499.0 / discarded - 1.0).abs() < 1e-8, "{} components", n_components);
+        }
+        // Three templates leave only the 0.05 noise behind
+        let result = pca(&data, 3).unwrap();
+        let reconstructed = result.inverse_transform(&result.scores).unwrap();
+        let rms = (reconstructed.iter().flatten().zip(data.iter().flatten()).map(|(a, b)| (a - b).powi(2)).sum::<f64>() / (500.0 * 48.0)).sqrt();
+        assert!(rms < 0.05, "{}", rms);
+    }
+
+    #[test]
+    fn components_beyond_the_rank_have_zero_variance() {
+        // Five features spanned by two directions around an offset
+        let mut rng = SeededRng::new(3);
+        let data: Vec<Vec<f64>> = (0..40)
+            .map(|_| {
+                let (a, b) = (rng.next_gaussian(), rng.next_gaussian());
+                vec![1.0 + a, 2.0 + a + b, 3.0 - b, 4.0 + 2.0 * a, 5.0]
+            })
+            .collect();
+        let result = pca(&data, 4).unwrap();
+        assert!(result.explained_variance[1] > 0.1);
+        assert!(result.explained_variance[2..].iter().all(|variance| *variance < 1e-20));
+        assert!((result.explained_variance_ratio[..2].iter().sum::<f64>() - 1.0).abs() < 1e-12);
+        assert!(result.scores.iter().all(|row| row[2..].iter().all(|score| score.abs() < 1e-10)));
+        for (i, a) in result.components.iter().enumerate() {
+            for (j, b) in result.components.iter().enumerate() {
+                assert!((dot(a, b) - if i == j { 1.0 } else { 0.0 }).abs() < 1e-12);
+            }
+        }
+
+        // Fewer observations than features
+        let result = pca(&data[..3], 5).unwrap();
+        assert!(result.explained_variance[2..].iter().all(|variance| *variance < 1e-20));
+
+        assert!(pca(&data, 0).is_err());
+        assert!(pca(&data, 6).is_err());
+        assert!(pca(&[], 1).is_err());
+        assert!(pca(&[vec![1.0, 2.0], vec![3.0]], 1).is_err());
+        assert!(result.transform(&[vec![1.0; 4]]).is_err());
+        assert!(result.inverse_transform(&[vec![1.0; 4]]).is_err());
+    }
+
+    #[test]
+    fn spike_pca_features_are_the_waveform_scores_and_export_to_csv() {
+        let detection = SpikeDetectionResult {
+            times: (0..200).map(|i| i as f64 * 0.01).collect(),
+            indices: (0..200).map(|i| i * 300).collect(),
+            waveforms: waveforms(200, 0.05, 4),
+            pre_samples: 16,
+            threshold: 5.0,
+            noise: 1.0,
+            dropped_at_edges: 0,
+        };
+        let features = detection.pca_features(3).unwrap();
+        assert_eq!(features, pca(&detection.waveforms, 3).unwrap());
+
+        let path = std::env::temp_dir().join(format!("neurorust-decomposition-{}-pca.csv", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        features.to_csv(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 201);
+        assert_eq!(lines[0], "pc1,pc2,pc3");
+        let first: Vec<f64> = lines[1].split(',').map(|value| value.parse().unwrap()).collect();
+        assert!(first.iter().zip(&features.scores[0]).all(|(a, b)| (a - b).abs() < 1e-6 * b.abs().max(1.0)));
+    }
+
+    /// A 7 Hz sine, a 3 Hz sine and Laplacian (supergaussian) noise over 10 s at 1 kHz
+    fn independent_sources(seed: u64) -> Vec<Vec<f64>> {
+        let mut rng = SeededRng::new(seed);
+        let time = |k: usize| k as f64 / 1000.0;
+        vec![
+            (0..10_000).map(|k| (2.0 * std::f64::consts::PI * 7.0 * time(k)).sin()).collect(),
+            (0..10_000).map(|k| (2.0 * std::f64::consts::PI * 3.0 * time(k) + 1.0).sin()).collect(),
+            (0..10_000)
+                .map(|_| {
+                    let magnitude = -(1.0 - rng.next_f64()).ln() / 2f64.sqrt();
+                    if rng.next_f64() < 0.5 { -magnitude } else { magnitude }
+                })
+                .collect(),
+        ]
+    }
+
+    const MIXING: [[f64; 3]; 4] = [[1.0, 0.5, 0.8], [0.3, 1.2, -0.4], [-0.7, 0.4, 1.5], [0.6, -0.9, 0.2]];
+
+    fn mix(sources: &[Vec<f64>]) -> Vec<Vec<f64>> {
+        MIXING
+            .iter()
+            .enumerate()
+            .map(|(c, weights)| (0..sources[0].len()).map(|t| 2.0 * c as f64 + weights.iter().zip(sources).map(|(w, s)| w * s[t]).sum::<f64>()).collect())
+            .collect()
+    }
+
+    fn correlation(a: &[f64], b: &[f64]) -> f64 {
+        let n = a.len() as f64;
+        let (mean_a, mean_b) = (a.iter().sum::<f64>() / n, b.iter().sum::<f64>() / n);
+        let covariance: f64 = a.iter().zip(b).map(|(x, y)| (x - mean_a) * (y - mean_b)).sum();
+        let variance = |values: &[f64], mean: f64| values.iter().map(|x| (x - mean).powi(2)).sum::<f64>();
+        covariance / (variance(a, mean_a) * variance(b, mean_b)).sqrt()
+    }
+
+    #[test]
+    fn fast_ica_recovers_independent_sources_up_to_permutation_and_sign() {
+        let sources = independent_sources(5);
+        let data = mix(&sources);
+        let ica = fast_ica(&data, 3, &IcaOptions::default()).unwrap();
+        assert_eq!((ica.sources.len(), ica.unmixing.len(), ica.mixing.len()), (3, 3, 4));
+        assert!(ica.iterations < 200);
+
+        // Each original source matches a distinct recovered one
+        let mut matched = Vec::new();
+        for source in &sources {
+            let (best, r) = (0..3)
+                .map(|k| (k, correlation(source, &ica.sources[k]).abs()))
+                .fold((0, 0.0), |best, item| if item.1 > best.1 { item } else { best });
+            assert!(r > 0.999, "{}", r);
+            matched.push(best);
+        }
+        matched.sort();
+        assert_eq!(matched, vec![0, 1, 2]);
+
+        // Unit-variance, uncorrelated sources, with the largest mixing weight of each positive
+        for (k, source) in ica.sources.iter().enumerate() {
+            let variance = source.iter().map(|x| x * x).sum::<f64>() / source.len() as f64;
+            assert!((variance - 1.0).abs() < 1e-9);
+            let largest = ica.mixing.iter().map(|row| row[k]).fold(0.0f64, |largest, value| if value.abs() > largest.abs() { value } else { largest });
+            assert!(largest > 0.0);
+            for other in &ica.sources[k + 1..] {
+                assert!(correlation(source, other).abs() < 1e-9);
+            }
+        }
+        assert_eq!(ica.mean.iter().map(|mean| mean.round()).collect::<Vec<f64>>(), vec![0.0, 2.0, 4.0, 6.0]);
+        let transformed = ica.transform(&data).unwrap();
+        assert!(transformed.iter().flatten().zip(ica.sources.iter().flatten()).all(|(a, b)| (a - b).abs() < 1e-9));
+
+        // The same seed gives the same result
+        assert_eq!(ica, fast_ica(&data, 3, &IcaOptions::default()).unwrap());
+    }
+
+    #[test]
+    fn excluding_a_component_removes_exactly_its_contribution() {
+        let sources = independent_sources(6);
+        let data = mix(&sources);
+        let ica = fast_ica(&data, 3, &IcaOptions { seed: 3, ..IcaOptions::default() }).unwrap();
+        // Three sources span the four channels, so nothing is lost without exclusions
+        let reconstructed = ica.reconstruct_excluding(&[]).unwrap();
+        assert!(reconstructed.iter().flatten().zip(data.iter().flatten()).all(|(a, b)| (a - b).abs() < 1e-9));
+
+        // Removing the noise component, the "blink", leaves the channels mixing only the sines
+        let noise = (0..3).max_by(|&a, &b| correlation(&sources[2], &ica.sources[a]).abs().total_cmp(&correlation(&sources[2], &ica.sources[b]).abs())).unwrap();
+        let cleaned = ica.reconstruct_excluding(&[noise]).unwrap();
+        let mut without_noise = sources.clone();
+        without_noise[2].iter_mut().for_each(|sample| *sample = 0.0);
+        for (channel, expected) in cleaned.iter().zip(mix(&without_noise)) {
+            let error = channel.iter().zip(&expected).map(|(a, b)| (a - b).powi(2)).sum::<f64>() / expected.len() as f64;
+            assert!(error.sqrt() < 0.05, "{}", error.sqrt());
+        }
+        assert!(ica.reconstruct_excluding(&[3]).is_err());
+    }
+
+    #[test]
+    fn fast_ica_reports_non_convergence_and_invalid_inputs() {
+        let data = mix(&independent_sources(7));
+        let strict = IcaOptions { max_iterations: 2, tolerance: 1e-15, seed: 0 };
+        match fast_ica(&data, 3, &strict) {
+            Err(ProcessingError::NotConverged { iterations }) => assert_eq!(iterations, 2),
+            other => panic!("Expected NotConverged, got {:?}", other.map(|ica| ica.iterations)),
+        }
+        assert!(fast_ica(&data, 0, &IcaOptions::default()).is_err());
+        assert!(fast_ica(&data, 5, &IcaOptions::default()).is_err());
+        assert!(fast_ica(&[vec![1.0]], 1, &IcaOptions::default()).is_err());
+        assert!(fast_ica(&[vec![1.0, 2.0, 3.0], vec![1.0, 2.0]], 1, &IcaOptions::default()).is_err());
+        // Four channels mixing three sources have no fourth independent component
+        assert!(fast_ica(&data, 4, &IcaOptions::default()).is_err());
+        let ica = fast_ica(&data, 2, &IcaOptions::default()).unwrap();
+        assert!(ica.transform(&data[..3]).is_err());
+    }
+}
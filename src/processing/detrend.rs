@@ -0,0 +1,251 @@
+// A module to remove drifts and baselines from signals
+
+// Written by Amin Alam in 2024
+
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+use crate::processing::linalg::{least_squares, legendre_basis};
+
+/// The trend removed by `detrend`
+///
+/// # Arguments
+///
+/// * `Constant` - The mean of the signal
+/// * `Linear` - The least-squares straight line
+/// * `Polynomial` - The least-squares polynomial of the given order
+///
+/// # Examples
+///
+/// ```
+/// let flattened = detrend(&samples, DetrendMethod::Polynomial(3))?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum DetrendMethod {
+    Constant,
+    Linear,
+    Polynomial(usize),
+}
+
+/// Fits a trend to a signal
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `method` - The kind of trend to fit
+///
+/// # Returns
+///
+/// The fitted trend at each sample, or an error if the signal has no more samples than
+/// the polynomial has coefficients
+///
+/// # Examples
+///
+/// ```
+/// let drift = fit_trend(&samples, DetrendMethod::Linear)?;
+/// ```
+///
+/// # Note
+///
+/// Polynomials are fitted in a Legendre basis over the sample positions scaled to [-1, 1]
+/// and solved with Householder QR, so high orders on long signals stay well-conditioned
+/// where the normal equations of a plain power basis would be singular
+///
+pub fn fit_trend(samples: &[f64], method: DetrendMethod) -> Result<Vec<f64>, ProcessingError> {
+    let order = match method {
+        DetrendMethod::Constant => 0,
+        DetrendMethod::Linear => 1,
+        DetrendMethod::Polynomial(order) => order,
+    };
+    let n = samples.len();
+    if n <= order {
+        return Err(ProcessingError::SignalTooShort { length: n, required: order + 1 });
+    }
+    if order == 0 {
+        let mean = samples.iter().sum::<f64>() / n as f64;
+        return Ok(vec![mean; n]);
+    }
+
+    let positions: Vec<f64> = (0..n).map(|i| scaled_position(i, n)).collect();
+    let rows: Vec<Vec<f64>> = positions.iter().map(|&x| legendre_basis(x, order)).collect();
+    let columns: Vec<Vec<f64>> = (0..=order).map(|k| rows.iter().map(|row| row[k]).collect()).collect();
+    let coefficients = least_squares(&columns, samples).ok_or_else(|| {
+        ProcessingError::InvalidParameter(format!("Polynomial trend of order {} could not be fitted", order))
+    })?;
+    Ok(rows
+        .iter()
+        .map(|row| row.iter().zip(&coefficients).map(|(basis, coefficient)| basis * coefficient).sum())
+        .collect())
+}
+
+/// Removes a trend from a signal
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `method` - The kind of trend to remove
+///
+/// # Returns
+///
+/// The samples minus the fitted trend, or an error if the trend cannot be fitted
+///
+/// # Examples
+///
+/// ```
+/// let flattened = detrend(&samples, DetrendMethod::Linear)?;
+/// ```
+///
+/// # See
+///
+/// * `fit_trend` - The fitting method
+///
+pub fn detrend(samples: &[f64], method: DetrendMethod) -> Result<Vec<f64>, ProcessingError> {
+    let trend = fit_trend(samples, method)?;
+    Ok(samples.iter().zip(&trend).map(|(sample, trend)| sample - trend).collect())
+}
+
+/// Subtracts the mean of a baseline time window from a signal
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample in seconds
+/// * `window` - The start and end of the baseline window in seconds, inclusive
+///
+/// # Returns
+///
+/// The baseline-corrected samples, or an error if no sample falls within the window
+///
+/// # Examples
+///
+/// ```
+/// // Epoch from -0.2 s to 0.8 s around a stimulus, corrected to the pre-stimulus mean
+/// let corrected = baseline_correct(&epoch, 1000.0, -0.2, (-0.2, 0.0))?;
+/// ```
+///
+/// # Note
+///
+/// NaN samples within the window are ignored when computing the mean
+///
+pub fn baseline_correct(samples: &[f64], sampling_rate: f64, start_time: f64, window: (f64, f64)) -> Result<Vec<f64>, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    let (sum, count) = samples
+        .iter()
+        .enumerate()
+        .filter(|(i, sample)| {
+            let time = start_time + *i as f64 / sampling_rate;
+            time >= window.0 && time <= window.1 && !sample.is_nan()
+        })
+        .fold((0.0, 0usize), |(sum, count), (_, sample)| (sum + sample, count + 1));
+    if count == 0 {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "No valid sample falls within the baseline window {:?}",
+            window
+        )));
+    }
+    let baseline = sum / count as f64;
+    Ok(samples.iter().map(|sample| sample - baseline).collect())
+}
+
+/// Maps a sample index onto [-1, 1]
+fn scaled_position(index: usize, n: usize) -> f64 {
+    if n == 1 {
+        0.0
+    } else {
+        2.0 * index as f64 / (n - 1) as f64 - 1.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    /// An alpha oscillation with a smaller beta one, the "known signal" the drifts are added to
+    fn known_signal(n: usize, sampling_rate: f64) -> Vec<f64> {
+        (0..n)
+            .map(|k| {
+                let t = k as f64 / sampling_rate;
+                (2.0 * PI * 10.0 * t).cos() + 0.3 * (2.0 * PI * 23.0 * t + 1.0).sin()
+            })
+            .collect()
+    }
+
+    fn max_difference(a: &[f64], b: &[f64]) -> f64 {
+        a.iter().zip(b).map(|(x, y)| (x - y).abs()).fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn linear_and_quadratic_drifts_are_removed_and_the_signal_recovered() {
+        let (n, sampling_rate) = (10_000, 1000.0);
+        let signal = known_signal(n, sampling_rate);
+        for (method, drift) in [
+            (DetrendMethod::Constant, Box::new(|_: f64| 40.0) as Box<dyn Fn(f64) -> f64>),
+            (DetrendMethod::Linear, Box::new(|t: f64| 40.0 - 8.0 * t)),
+            (DetrendMethod::Polynomial(2), Box::new(|t: f64| 40.0 - 8.0 * t + 0.9 * t * t)),
+        ] {
+            let drifted: Vec<f64> = signal.iter().enumerate().map(|(k, value)| value + drift(k as f64 / sampling_rate)).collect();
+            let recovered = detrend(&drifted, method).unwrap();
+            // The drift lies in the fitted space, so only the small trend of the signal itself
+            // is taken with it
+            assert!(max_difference(&recovered, &detrend(&signal, method).unwrap()) < 1e-9, "{:?}", method);
+            assert!(max_difference(&recovered, &signal) < 0.01, "{:?}: {}", method, max_difference(&recovered, &signal));
+            let trend = fit_trend(&drifted, method).unwrap();
+            assert!(trend.iter().enumerate().all(|(k, value)| (value - drift(k as f64 / sampling_rate)).abs() < 0.01));
+        }
+        // A linear fit leaves a quadratic drift behind
+        let bowed: Vec<f64> = signal.iter().enumerate().map(|(k, value)| value + 0.9 * (k as f64 / sampling_rate).powi(2)).collect();
+        assert!(max_difference(&detrend(&bowed, DetrendMethod::Linear).unwrap(), &signal) > 1.0);
+    }
+
+    #[test]
+    fn an_order_5_fit_on_a_long_signal_stays_well_conditioned() {
+        // In sample units the power basis spans 1 to 1e30, where the normal equations are singular
+        let n = 1_000_000;
+        let drift = |k: f64| {
+            let x = k / n as f64;
+            3.0 - 20.0 * x + 150.0 * x.powi(2) - 400.0 * x.powi(3) + 420.0 * x.powi(4) - 150.0 * x.powi(5)
+        };
+        let signal = known_signal(n, 30000.0);
+        let drifted: Vec<f64> = signal.iter().enumerate().map(|(k, value)| value + drift(k as f64)).collect();
+        let recovered = detrend(&drifted, DetrendMethod::Polynomial(5)).unwrap();
+        assert!(max_difference(&recovered, &detrend(&signal, DetrendMethod::Polynomial(5)).unwrap()) < 1e-9);
+        assert!(max_difference(&recovered, &signal) < 0.02, "{}", max_difference(&recovered, &signal));
+        let exact: Vec<f64> = (0..n).map(|k| drift(k as f64)).collect();
+        assert!(max_difference(&detrend(&exact, DetrendMethod::Polynomial(5)).unwrap(), &vec![0.0; n]) < 1e-9);
+    }
+
+    #[test]
+    fn low_orders_agree_and_short_signals_are_errors() {
+        let samples: Vec<f64> = (0..50).map(|k| (k as f64 * 0.7).sin() + 0.1 * k as f64).collect();
+        assert!(max_difference(&detrend(&samples, DetrendMethod::Polynomial(0)).unwrap(), &detrend(&samples, DetrendMethod::Constant).unwrap()) < 1e-12);
+        assert!(max_difference(&detrend(&samples, DetrendMethod::Polynomial(1)).unwrap(), &detrend(&samples, DetrendMethod::Linear).unwrap()) < 1e-12);
+        let mean = samples.iter().sum::<f64>() / 50.0;
+        assert!(fit_trend(&samples, DetrendMethod::Constant).unwrap().iter().all(|value| (value - mean).abs() < 1e-12));
+
+        assert!(detrend(&[1.0, 2.0, 3.0], DetrendMethod::Polynomial(3)).is_err());
+        assert_eq!(detrend(&[1.0, 2.0, 4.0], DetrendMethod::Polynomial(2)).unwrap().iter().map(|value| value.abs() < 1e-12).collect::<Vec<_>>(), vec![true; 3]);
+        assert_eq!(detrend(&[5.0], DetrendMethod::Constant).unwrap(), vec![0.0]);
+        assert!(detrend(&[], DetrendMethod::Constant).is_err());
+    }
+
+    #[test]
+    fn baseline_correct_subtracts_the_mean_of_the_inclusive_window() {
+        // An epoch from -0.2 s at 100 Hz: an offset of 5, then a response after 0 s
+        let mut epoch: Vec<f64> = (0..100).map(|k| 5.0 + if k > 20 { 2.0 } else { 0.0 } + if k % 2 == 0 { 0.1 } else { -0.1 }).collect();
+        let corrected = baseline_correct(&epoch, 100.0, -0.2, (-0.2, -0.01)).unwrap();
+        assert!(corrected[..20].iter().sum::<f64>().abs() < 1e-9);
+        assert!((corrected[50] - 2.1).abs() < 1e-9);
+        // Both ends are included: -0.2 s to 0 s is 21 samples
+        let inclusive = baseline_correct(&epoch, 100.0, -0.2, (-0.2, 0.0)).unwrap();
+        assert!((epoch[0] - inclusive[0] - (5.0 + 0.1 / 21.0)).abs() < 1e-9);
+
+        epoch[3] = f64::NAN;
+        let skipped = baseline_correct(&epoch, 100.0, -0.2, (-0.2, -0.01)).unwrap();
+        assert!(skipped[3].is_nan() && (skipped[50] - (2.1 - 0.1 / 19.0)).abs() < 1e-9);
+        assert!(baseline_correct(&epoch, 100.0, -0.2, (1.0, 2.0)).is_err());
+        assert!(baseline_correct(&[f64::NAN; 10], 100.0, 0.0, (0.0, 1.0)).is_err());
+        assert!(baseline_correct(&epoch, 0.0, -0.2, (-0.2, 0.0)).is_err());
+    }
+}
@@ -0,0 +1,616 @@
+// A module to measure the latency and amplitude of the components of evoked responses
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+use crate::processing::spikes::Polarity;
+
+/// How the latency and amplitude of a component are measured within its window
+///
+/// # Arguments
+///
+/// * `Peak` - The largest local peak of the polarity: its time and value
+/// * `MeanAmplitude` - The mean of the samples of the window, without a latency
+/// * `FractionalArea` - The time at which this fraction of the rectified area of the window is reached, e.g. `0.5`, and the value there
+/// * `FractionalPeak` - The time before the peak at which the signal first reaches this fraction of the peak value, e.g. `0.5`, and the peak value
+///
+/// # Examples
+///
+/// ```
+/// let n1 = erp.measure_component("Cz", (0.08, 0.14), Polarity::Negative, PeakMeasure::FractionalArea(0.5))?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PeakMeasure {
+    Peak,
+    MeanAmplitude,
+    FractionalArea(f64),
+    FractionalPeak(f64),
+}
+
+/// Whether a component could be measured
+///
+/// # Arguments
+///
+/// * `Measured` - The window was within the signal and the measure was found
+/// * `Clipped` - The window extended past the signal and was measured over the part within it
+/// * `NoPeak` - The window holds no local peak of the polarity, or no rectified area, so the values are NaN
+/// * `NoOnset` - The signal stays above the fraction of the peak up to the start of the window, so the latency is NaN
+/// * `OutsideSignal` - The window holds no finite sample of the signal, so the values are NaN
+///
+/// # Examples
+///
+/// ```
+/// if measure.status == ComponentStatus::NoPeak { println!("No N1 on this channel"); }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ComponentStatus {
+    Measured,
+    Clipped,
+    NoPeak,
+    NoOnset,
+    OutsideSignal,
+}
+
+/// The latency and amplitude of a component
+///
+/// # Arguments
+///
+/// * `latency` - The latency in seconds, on the time axis of the signal, NaN if it is not defined
+/// * `amplitude` - The amplitude in the units of the signal, NaN if it is not defined
+/// * `status` - Whether the component could be measured
+///
+/// # Examples
+///
+/// ```
+/// let p2 = measure(&erp.mean[0], 500.0, -0.2, (0.15, 0.25), Polarity::Positive, PeakMeasure::Peak)?;
+/// println!("P2 of {} at {} s", p2.amplitude, p2.latency);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ComponentMeasure {
+    pub latency: f64,
+    pub amplitude: f64,
+    pub status: ComponentStatus,
+}
+
+/// A measure of one channel, and of one trial for trial-level tables
+///
+/// # Arguments
+///
+/// * `trial` - The index of the trial, or None for an evoked response
+/// * `channel` - The name of the channel
+/// * `measure` - The latency and amplitude
+///
+/// # Examples
+///
+/// ```
+/// for row in &table.rows { println!("{}: {} s", row.channel, row.measure.latency); }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ComponentRow {
+    pub trial: Option<usize>,
+    pub channel: String,
+    pub measure: ComponentMeasure,
+}
+
+/// The measures of a component on several channels, and trials
+///
+/// # Arguments
+///
+/// * `rows` - One row per channel, or per trial and channel ordered by trial
+///
+/// # Examples
+///
+/// ```
+/// let table = erp.measure_components((0.08, 0.14), Polarity::Negative, PeakMeasure::Peak)?;
+/// table.to_csv(&mut csv_io)?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ComponentTable {
+    pub rows: Vec<ComponentRow>,
+}
+
+/// Implementation of the ComponentStatus enum
+///
+/// # Methods
+///
+/// * `name` - Returns the name written to csv files
+impl ComponentStatus {
+    /// Returns the name written to csv files
+    ///
+    /// # Returns
+    ///
+    /// The name in snake case, e.g. `no_peak`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(ComponentStatus::OutsideSignal.name(), "outside_signal");
+    /// ```
+    ///
+    pub fn name(&self) -> &'static str {
+        match self {
+            ComponentStatus::Measured => "measured",
+            ComponentStatus::Clipped => "clipped",
+            ComponentStatus::NoPeak => "no_peak",
+            ComponentStatus::NoOnset => "no_onset",
+            ComponentStatus::OutsideSignal => "outside_signal",
+        }
+    }
+}
+
+/// Implementation of the ComponentTable struct
+///
+/// # Methods
+///
+/// * `to_csv` - Writes the table as `channel,latency,amplitude,status` rows, with a leading `trial` column for trial-level tables
+impl ComponentTable {
+    /// Writes the table as `channel,latency,amplitude,status` rows, with a leading `trial` column for trial-level tables
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// table.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. Undefined values are written as NaN. The rows are not
+    /// flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        let by_trial = self.rows.iter().any(|row| row.trial.is_some());
+        let mut header = vec!["channel", "latency", "amplitude", "status"];
+        if by_trial {
+            header.insert(0, "trial");
+        }
+        csv_io.write_record(StringRecord::from(header))?;
+        for row in &self.rows {
+            let mut record = vec![
+                row.channel.clone(),
+                float_format.format(row.measure.latency),
+                float_format.format(row.measure.amplitude),
+                row.measure.status.name().to_string(),
+            ];
+            if by_trial {
+                record.insert(0, row.trial.map_or_else(String::new, |trial| trial.to_string()));
+            }
+            csv_io.write_record(StringRecord::from(record))?;
+        }
+        Ok(())
+    }
+}
+
+/// Measures a component of one signal
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal, e.g. one channel of an evoked response
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample in seconds, e.g. `-0.2` relative to the event
+/// * `window` - The start and end in seconds of the window searched, on the same time axis
+/// * `polarity` - Whether the component is positive, negative, or either for `Polarity::Both`
+/// * `method` - The measure
+///
+/// # Returns
+///
+/// The ComponentMeasure, or an error if the sampling rate is invalid, the window is not
+/// finite or is empty, or the fraction of a fractional measure is outside `(0, 1]`
+///
+/// # Examples
+///
+/// ```
+/// let onset = measure(&erp.mean[0], 1000.0, -0.1, (0.05, 0.2), Polarity::Positive, PeakMeasure::FractionalPeak(0.5))?;
+/// ```
+///
+/// # Note
+///
+/// The window holds the samples whose time is within it, ends included. A window reaching
+/// past the signal is clipped to it and the status is `Clipped`; one with no sample at all
+/// gives NaN values and the status `OutsideSignal`, and never an error. A local peak is a
+/// sample at least as large, after flipping the sign for negative components, as both of
+/// its neighbours in the signal, which may lie outside the window, and of the right sign;
+/// a window whose extreme lies on a slope into its edge therefore has no peak, instead of
+/// reporting the edge. The rectified area is the area of the signal on the side of the
+/// polarity, or of its absolute value for `Polarity::Both`, with each sample covering one
+/// sample period centred on it, so a symmetric component has its 50% area latency at its
+/// centre. Fractional latencies are linearly interpolated between samples. NaN samples are
+/// never peaks and add no area.
+///
+pub fn measure(
+    samples: &[f64],
+    sampling_rate: f64,
+    start_time: f64,
+    window: (f64, f64),
+    polarity: Polarity,
+    method: PeakMeasure,
+) -> Result<ComponentMeasure, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    if !(window.0.is_finite() && window.1.is_finite() && window.0 < window.1) {
+        return Err(ProcessingError::InvalidParameter(format!("The window {:?} must be finite and have its start before its end", window)));
+    }
+    match method {
+        PeakMeasure::FractionalArea(fraction) | PeakMeasure::FractionalPeak(fraction) if !(fraction > 0.0 && fraction <= 1.0) => {
+            return Err(ProcessingError::InvalidParameter(format!("The fraction must be in (0, 1], got {}", fraction)))
+        }
+        _ => {}
+    }
+    let period = 1.0 / sampling_rate;
+    // A small tolerance keeps the samples at the ends of the window despite rounding of the times
+    let tolerance = 1e-9 * period;
+    let first = ((window.0 - start_time) * sampling_rate - tolerance).ceil().max(0.0);
+    let end = (((window.1 - start_time) * sampling_rate + tolerance).floor() + 1.0).min(samples.len() as f64);
+    let clipped = start_time - tolerance > window.0 || start_time + (samples.len() as f64 - 1.0) * period + tolerance < window.1;
+    let undefined = |status| ComponentMeasure { latency: f64::NAN, amplitude: f64::NAN, status };
+    if first >= end {
+        return Ok(undefined(ComponentStatus::OutsideSignal));
+    }
+    let (first, end) = (first as usize, end as usize);
+    if samples[first..end].iter().all(|value| !value.is_finite()) {
+        return Ok(undefined(ComponentStatus::OutsideSignal));
+    }
+    let status = if clipped { ComponentStatus::Clipped } else { ComponentStatus::Measured };
+    let time = |index: f64| start_time + index * period;
+
+    match method {
+        PeakMeasure::MeanAmplitude => {
+            let values: Vec<f64> = samples[first..end].iter().copied().filter(|value| value.is_finite()).collect();
+            Ok(ComponentMeasure { latency: f64::NAN, amplitude: values.iter().sum::<f64>() / values.len() as f64, status })
+        }
+        PeakMeasure::Peak => Ok(match find_peak(samples, first, end, polarity) {
+            Some((index, _)) => ComponentMeasure { latency: time(index as f64), amplitude: samples[index], status },
+            None => undefined(ComponentStatus::NoPeak),
+        }),
+        PeakMeasure::FractionalPeak(fraction) => {
+            let (peak, sign) = match find_peak(samples, first, end, polarity) {
+                Some(peak) => peak,
+                None => return Ok(undefined(ComponentStatus::NoPeak)),
+            };
+            let target = fraction * sign * samples[peak];
+            let onset = (first..peak).rev().find(|&index| samples[index].is_nan() || sign * samples[index] < target);
+            Ok(match onset {
+                Some(index) if samples[index].is_finite() => {
+                    let (below, above) = (sign * samples[index], sign * samples[index + 1]);
+                    let latency = time(index as f64 + (target - below) / (above - below));
+                    ComponentMeasure { latency, amplitude: samples[peak], status }
+                }
+                _ => ComponentMeasure { latency: f64::NAN, amplitude: samples[peak], status: ComponentStatus::NoOnset },
+            })
+        }
+        PeakMeasure::FractionalArea(fraction) => {
+            let rectified = |value: f64| match (value.is_finite(), polarity) {
+                (false, _) => 0.0,
+                (true, Polarity::Positive) => value.max(0.0),
+                (true, Polarity::Negative) => (-value).max(0.0),
+                (true, Polarity::Both) => value.abs(),
+            };
+            let total: f64 = samples[first..end].iter().map(|&value| rectified(value)).sum();
+            if total <= 0.0 {
+                return Ok(undefined(ComponentStatus::NoPeak));
+            }
+            let target = fraction * total;
+            let mut cumulative = 0.0;
+            for index in first..end {
+                let area = rectified(samples[index]);
+                if area > 0.0 && cumulative + area >= target {
+                    // The sample covers half a period on either side of its time
+                    let latency = time(index as f64 - 0.5 + (target - cumulative) / area);
+                    let position = (latency - start_time) * sampling_rate;
+                    return Ok(ComponentMeasure { latency, amplitude: interpolate(samples, position), status });
+                }
+                cumulative += area;
+            }
+            Ok(undefined(ComponentStatus::NoPeak))
+        }
+    }
+}
+
+/// Measures a component on every channel of every trial
+///
+/// # Arguments
+///
+/// * `trials` - The samples of each trial, indexed as `trials[trial][channel][time]`
+/// * `names` - The name of each channel
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample of each trial in seconds relative to the event
+/// * `window` - The start and end in seconds of the window searched, relative to the event
+/// * `polarity` - Whether the component is positive, negative, or either
+/// * `method` - The measure
+///
+/// # Returns
+///
+/// The ComponentTable with one row per trial and channel, or an error if a trial does not
+/// have one row per name or the parameters of `measure` are invalid
+///
+/// # Examples
+///
+/// ```
+/// let table = measure_trials(&trials, &names, 500.0, -0.2, (0.25, 0.5), Polarity::Positive, PeakMeasure::MeanAmplitude)?;
+/// table.to_csv(&mut csv_io)?;
+/// ```
+///
+/// # Note
+///
+/// Single trials are far noisier than their average. Peak measures are biased: the
+/// largest value of noise plus signal is on average larger than the signal peak, and
+/// more so with more noise, so peak amplitudes of trials are inflated and their latencies
+/// scatter across the window, and windows with no local peak become common. The mean
+/// amplitude is unbiased and the fractional area latency is far less sensitive to noise,
+/// so they are the better choices for trial-level statistics. Low-pass filtering the
+/// trials first also helps.
+///
+pub fn measure_trials(
+    trials: &[Vec<Vec<f64>>],
+    names: &[String],
+    sampling_rate: f64,
+    start_time: f64,
+    window: (f64, f64),
+    polarity: Polarity,
+    method: PeakMeasure,
+) -> Result<ComponentTable, ProcessingError> {
+    let mut table = ComponentTable::default();
+    for (trial, channels) in trials.iter().enumerate() {
+        if channels.len() != names.len() {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Trial {} has {} channels but {} names were given",
+                trial,
+                channels.len(),
+                names.len()
+            )));
+        }
+        for (channel, name) in channels.iter().zip(names) {
+            table.rows.push(ComponentRow {
+                trial: Some(trial),
+                channel: name.clone(),
+                measure: measure(channel, sampling_rate, start_time, window, polarity, method)?,
+            });
+        }
+    }
+    Ok(table)
+}
+
+/// Returns the index of the largest local peak of the polarity within `first..end`, and the sign of the peak
+fn find_peak(samples: &[f64], first: usize, end: usize, polarity: Polarity) -> Option<(usize, f64)> {
+    let signs: &[f64] = match polarity {
+        Polarity::Positive => &[1.0],
+        Polarity::Negative => &[-1.0],
+        Polarity::Both => &[1.0, -1.0],
+    };
+    let mut best: Option<(usize, f64)> = None;
+    for &sign in signs {
+        for index in first.max(1)..end.min(samples.len().saturating_sub(1)) {
+            let value = sign * samples[index];
+            let is_peak = value > 0.0 && value >= sign * samples[index - 1] && value >= sign * samples[index + 1];
+            if is_peak && best.is_none_or(|(best_index, best_sign)| value > best_sign * samples[best_index]) {
+                best = Some((index, sign));
+            }
+        }
+    }
+    best
+}
+
+/// Returns the value of the signal at a fractional sample position, by linear interpolation
+fn interpolate(samples: &[f64], position: f64) -> f64 {
+    let position = position.clamp(0.0, (samples.len() - 1) as f64);
+    let below = position.floor() as usize;
+    let above = (below + 1).min(samples.len() - 1);
+    let weight = position - below as f64;
+    match (samples[below].is_finite(), samples[above].is_finite()) {
+        (true, true) => samples[below] + weight * (samples[above] - samples[below]),
+        (true, false) => samples[below],
+        _ => samples[above],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::evoked::average;
+    use crate::processing::random::SeededRng;
+
+    const RATE: f64 = 1000.0;
+    const START: f64 = -0.2;
+
+    fn gaussian(time: f64, centre: f64, width: f64) -> f64 {
+        (-((time - centre) / width).powi(2) / 2.0).exp()
+    }
+
+    /// An N1 of -5 at 100 ms, a P2 of 8 at 250 ms and a plateau of 3 from 450 to 550 ms, from -200 to 800 ms
+    fn erp() -> Vec<f64> {
+        (0..=1000)
+            .map(|index| {
+                let time = START + index as f64 / RATE;
+                let plateau = if (650..=750).contains(&index) { 3.0 } else { 0.0 };
+                -5.0 * gaussian(time, 0.1, 0.015) + 8.0 * gaussian(time, 0.25, 0.02) + plateau
+            })
+            .collect()
+    }
+
+    fn measured(samples: &[f64], window: (f64, f64), polarity: Polarity, method: PeakMeasure) -> ComponentMeasure {
+        measure(samples, RATE, START, window, polarity, method).unwrap()
+    }
+
+    #[test]
+    fn peaks_are_found_at_the_known_latencies() {
+        let erp = erp();
+        let n1 = measured(&erp, (0.05, 0.15), Polarity::Negative, PeakMeasure::Peak);
+        assert_eq!(n1.status, ComponentStatus::Measured);
+        assert!((n1.latency - 0.1).abs() < 1e-9 && (n1.amplitude + 5.0).abs() < 1e-6, "{:?}", n1);
+        let p2 = measured(&erp, (0.2, 0.33), Polarity::Positive, PeakMeasure::Peak);
+        assert!((p2.latency - 0.25).abs() < 1e-9 && (p2.amplitude - 8.0).abs() < 1e-6, "{:?}", p2);
+        let both = measured(&erp, (0.05, 0.33), Polarity::Both, PeakMeasure::Peak);
+        assert!((both.latency - 0.25).abs() < 1e-9, "the larger of N1 and P2 wins: {:?}", both);
+        let no_peak = measured(&erp, (0.05, 0.15), Polarity::Positive, PeakMeasure::Peak);
+        assert_eq!(no_peak.status, ComponentStatus::NoPeak);
+        assert!(no_peak.latency.is_nan() && no_peak.amplitude.is_nan());
+        // A ramp rising to the end of the window has no local peak inside it
+        assert_eq!(measured(&erp, (0.2, 0.24), Polarity::Positive, PeakMeasure::Peak).status, ComponentStatus::NoPeak);
+    }
+
+    #[test]
+    fn mean_amplitudes_average_the_window() {
+        let erp = erp();
+        let plateau = measured(&erp, (0.47, 0.53), Polarity::Positive, PeakMeasure::MeanAmplitude);
+        assert!((plateau.amplitude - 3.0).abs() < 1e-12 && plateau.latency.is_nan(), "{:?}", plateau);
+        // Over one width either side of the P2 the mean is 8 * erf(1 / sqrt 2) * sqrt(pi / 2)
+        let p2 = measured(&erp, (0.23, 0.27), Polarity::Positive, PeakMeasure::MeanAmplitude);
+        let expected = 8.0 * 0.682_689_492 * (std::f64::consts::PI / 2.0).sqrt();
+        assert!((p2.amplitude - expected).abs() < 0.01 * expected, "{} vs {}", p2.amplitude, expected);
+        // The polarity does not matter to the mean
+        assert_eq!(measured(&erp, (0.05, 0.15), Polarity::Positive, PeakMeasure::MeanAmplitude).amplitude, measured(&erp, (0.05, 0.15), Polarity::Negative, PeakMeasure::MeanAmplitude).amplitude);
+    }
+
+    #[test]
+    fn fractional_area_latencies_split_the_rectified_area() {
+        let erp = erp();
+        let p2 = measured(&erp, (0.175, 0.325), Polarity::Positive, PeakMeasure::FractionalArea(0.5));
+        assert!((p2.latency - 0.25).abs() < 1e-6 && (p2.amplitude - 8.0).abs() < 1e-3, "{:?}", p2);
+        let n1 = measured(&erp, (0.04, 0.16), Polarity::Negative, PeakMeasure::FractionalArea(0.5));
+        assert!((n1.latency - 0.1).abs() < 1e-6, "{:?}", n1);
+        // The plateau holds 101 samples, each covering a millisecond centred on its time
+        let plateau = |fraction| measured(&erp, (0.43, 0.57), Polarity::Positive, PeakMeasure::FractionalArea(fraction));
+        assert!((plateau(0.5).latency - 0.5).abs() < 1e-9, "{:?}", plateau(0.5));
+        assert!((plateau(0.25).latency - (0.4495 + 0.101 * 0.25)).abs() < 1e-9, "{:?}", plateau(0.25));
+        assert!((plateau(1.0).latency - 0.5505).abs() < 1e-9, "the whole area ends half a sample after the last: {:?}", plateau(1.0));
+        assert_eq!(plateau(0.5).amplitude, 3.0);
+
+        // Area of the other sign is ignored, and a window with none of the right sign has no latency
+        let mixed = measured(&erp, (0.04, 0.325), Polarity::Positive, PeakMeasure::FractionalArea(0.5));
+        assert!((mixed.latency - 0.25).abs() < 1e-5, "{:?}", mixed);
+        assert_eq!(measured(&erp, (0.05, 0.15), Polarity::Positive, PeakMeasure::FractionalArea(0.5)).status, ComponentStatus::NoPeak);
+        assert_eq!(measured(&vec![0.0; 1001], (0.0, 0.1), Polarity::Both, PeakMeasure::FractionalArea(0.5)).status, ComponentStatus::NoPeak);
+
+        // Missing samples add no area: with 460 to 479 ms gone, half of the 81 samples left is reached 30.5 samples after the gap
+        let mut gapped = erp.clone();
+        gapped[660..680].iter_mut().for_each(|value| *value = f64::NAN);
+        let with_gap = measured(&gapped, (0.43, 0.57), Polarity::Positive, PeakMeasure::FractionalArea(0.5));
+        assert!((with_gap.latency - 0.51).abs() < 1e-9, "{:?}", with_gap);
+    }
+
+    #[test]
+    fn fractional_peak_onsets_interpolate_the_rising_edge() {
+        let erp = erp();
+        // A Gaussian reaches half its peak sqrt(2 ln 2) widths before it
+        let half_width = (2.0 * std::f64::consts::LN_2).sqrt();
+        let p2 = measured(&erp, (0.17, 0.33), Polarity::Positive, PeakMeasure::FractionalPeak(0.5));
+        assert!((p2.latency - (0.25 - 0.02 * half_width)).abs() < 1e-4 && (p2.amplitude - 8.0).abs() < 1e-6, "{:?}", p2);
+        let n1 = measured(&erp, (0.04, 0.15), Polarity::Negative, PeakMeasure::FractionalPeak(0.5));
+        assert!((n1.latency - (0.1 - 0.015 * half_width)).abs() < 1e-4, "{:?}", n1);
+        assert!((measured(&erp, (0.04, 0.15), Polarity::Negative, PeakMeasure::FractionalPeak(1.0)).latency - 0.1).abs() < 1e-9);
+        // When the window starts above the fraction of the peak the onset lies outside it
+        let late = measured(&erp, (0.24, 0.33), Polarity::Positive, PeakMeasure::FractionalPeak(0.5));
+        assert_eq!(late.status, ComponentStatus::NoOnset);
+        assert!(late.latency.is_nan() && (late.amplitude - 8.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn windows_outside_the_epoch_do_not_panic() {
+        let erp = erp();
+        let clipped = measured(&erp, (-0.5, 0.15), Polarity::Negative, PeakMeasure::Peak);
+        assert_eq!(clipped.status, ComponentStatus::Clipped);
+        assert!((clipped.latency - 0.1).abs() < 1e-9);
+        assert_eq!(measured(&erp, (0.7, 1.5), Polarity::Positive, PeakMeasure::MeanAmplitude).status, ComponentStatus::Clipped);
+        for window in [(0.9, 1.2), (-1.0, -0.5)] {
+            for method in [PeakMeasure::Peak, PeakMeasure::MeanAmplitude, PeakMeasure::FractionalArea(0.5), PeakMeasure::FractionalPeak(0.5)] {
+                let outside = measured(&erp, window, Polarity::Both, method);
+                assert_eq!(outside.status, ComponentStatus::OutsideSignal, "{:?} {:?}", window, method);
+                assert!(outside.latency.is_nan() && outside.amplitude.is_nan());
+            }
+        }
+        assert_eq!(measured(&[f64::NAN; 1001], (0.0, 0.1), Polarity::Both, PeakMeasure::MeanAmplitude).status, ComponentStatus::OutsideSignal);
+        assert_eq!(measured(&[], (0.0, 0.1), Polarity::Both, PeakMeasure::Peak).status, ComponentStatus::OutsideSignal);
+        // A window ending on the last sample is not clipped
+        assert_eq!(measured(&erp, (0.6, 0.8), Polarity::Both, PeakMeasure::MeanAmplitude).status, ComponentStatus::Measured);
+    }
+
+    #[test]
+    fn invalid_parameters_are_rejected() {
+        let erp = erp();
+        for window in [(0.2, 0.1), (0.1, 0.1), (f64::NAN, 0.1), (0.0, f64::INFINITY)] {
+            assert!(measure(&erp, RATE, START, window, Polarity::Both, PeakMeasure::Peak).is_err());
+        }
+        for fraction in [0.0, -0.5, 1.5, f64::NAN] {
+            assert!(measure(&erp, RATE, START, (0.1, 0.3), Polarity::Both, PeakMeasure::FractionalArea(fraction)).is_err());
+            assert!(measure(&erp, RATE, START, (0.1, 0.3), Polarity::Both, PeakMeasure::FractionalPeak(fraction)).is_err());
+        }
+        assert!(measure(&erp, 0.0, START, (0.1, 0.3), Polarity::Both, PeakMeasure::Peak).is_err());
+    }
+
+    #[test]
+    fn evoked_responses_give_a_table_per_channel() {
+        let names = vec!["Cz".to_string(), "Pz".to_string()];
+        let erp = erp();
+        let trials = vec![vec![erp.clone(), erp.iter().map(|value| 0.5 * value).collect()]; 3];
+        let evoked = average(&trials, &names, RATE, START).unwrap();
+        let cz = evoked.measure_component("Cz", (0.2, 0.33), Polarity::Positive, PeakMeasure::Peak).unwrap();
+        assert!((cz.latency - 0.25).abs() < 1e-9 && (cz.amplitude - 8.0).abs() < 1e-6);
+        assert!(evoked.measure_component("Oz", (0.2, 0.33), Polarity::Positive, PeakMeasure::Peak).is_err());
+        let table = evoked.measure_components((0.2, 0.33), Polarity::Positive, PeakMeasure::Peak).unwrap();
+        assert_eq!(table.rows.iter().map(|row| (row.trial, row.channel.as_str())).collect::<Vec<_>>(), [(None, "Cz"), (None, "Pz")]);
+        assert!((table.rows[1].measure.amplitude - 4.0).abs() < 1e-6);
+
+        let path = std::env::temp_dir().join(format!("neurorust-erp-measures-{}-channels.csv", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        table.to_csv(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().next().unwrap(), "channel,latency,amplitude,status");
+        let pz: Vec<&str> = text.lines().nth(2).unwrap().split(',').collect();
+        assert_eq!((pz[0], pz[3]), ("Pz", "measured"));
+        assert!((pz[1].parse::<f64>().unwrap() - 0.25).abs() < 1e-9 && (pz[2].parse::<f64>().unwrap() - 4.0).abs() < 1e-6, "{}", text);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn single_trials_recover_their_own_latencies() {
+        let mut rng = SeededRng::new(182);
+        let names = vec!["Cz".to_string()];
+        let jitters: Vec<f64> = (0..20).map(|_| (rng.next_index(41) as f64 - 20.0) / RATE).collect();
+        let trials: Vec<Vec<Vec<f64>>> = jitters
+            .iter()
+            .map(|jitter| {
+                let trial = (0..=1000).map(|index| 8.0 * gaussian(START + index as f64 / RATE, 0.2 + jitter, 0.025) + 0.2 * rng.next_gaussian()).collect();
+                vec![trial]
+            })
+            .collect();
+        let table = measure_trials(&trials, &names, RATE, START, (0.12, 0.28), Polarity::Positive, PeakMeasure::FractionalArea(0.5)).unwrap();
+        assert_eq!(table.rows.len(), 20);
+        for (row, jitter) in table.rows.iter().zip(&jitters) {
+            assert_eq!(row.measure.status, ComponentStatus::Measured);
+            assert!((row.measure.latency - 0.2 - jitter).abs() < 0.004, "trial {:?}: {} vs {}", row.trial, row.measure.latency, 0.2 + jitter);
+        }
+        assert_eq!(table.rows[7].trial, Some(7));
+
+        let path = std::env::temp_dir().join(format!("neurorust-erp-measures-{}-trials.csv", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        table.to_csv(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().next().unwrap(), "trial,channel,latency,amplitude,status");
+        assert!(text.lines().nth(1).unwrap().starts_with("0,Cz,"));
+        std::fs::remove_file(&path).unwrap();
+
+        let ragged = vec![vec![vec![0.0; 10]], vec![vec![0.0; 10], vec![0.0; 10]]];
+        assert!(measure_trials(&ragged, &names, RATE, START, (0.0, 0.1), Polarity::Both, PeakMeasure::Peak).is_err());
+    }
+}
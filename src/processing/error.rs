@@ -0,0 +1,59 @@
+// A module to describe the errors of the processing functions
+
+// Written by Amin Alam in 2024
+
+use std::error::Error;
+use std::fmt;
+
+/// The errors returned by the processing functions
+///
+/// # Arguments
+///
+/// * `InvalidFrequency` - A frequency is not strictly between zero and the Nyquist frequency
+/// * `InvalidParameter` - A parameter is outside of its valid range
+/// * `SignalTooShort` - The signal has fewer samples than the operation needs
+/// * `NotConverged` - An iterative algorithm did not converge within its maximum number of iterations
+/// * `MemoryBudgetExceeded` - The intermediate results would need more memory than the `MemoryBudget`, in bytes
+///
+/// # Examples
+///
+/// ```
+/// match butterworth(4, FilterKind::Lowpass(600.0), 1000.0) {
+///     Err(ProcessingError::InvalidFrequency { frequency, nyquist }) => println!("{} >= {}", frequency, nyquist),
+///     _ => {}
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum ProcessingError {
+    InvalidFrequency { frequency: f64, nyquist: f64 },
+    InvalidParameter(String),
+    SignalTooShort { length: usize, required: usize },
+    NotConverged { iterations: usize },
+    MemoryBudgetExceeded { needed: usize, budget: usize },
+}
+
+impl fmt::Display for ProcessingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProcessingError::InvalidFrequency { frequency, nyquist } => write!(
+                f,
+                "Frequency {} Hz must be strictly between 0 and the Nyquist frequency {} Hz",
+                frequency, nyquist
+            ),
+            ProcessingError::InvalidParameter(message) => write!(f, "Invalid parameter: {}", message),
+            ProcessingError::SignalTooShort { length, required } => write!(
+                f,
+                "Signal has {} samples but at least {} are required",
+                length, required
+            ),
+            ProcessingError::NotConverged { iterations } => write!(f, "Did not converge within {} iterations", iterations),
+            ProcessingError::MemoryBudgetExceeded { needed, budget } => write!(
+                f,
+                "Needs more than {} bytes but the memory budget is {} bytes",
+                needed, budget
+            ),
+        }
+    }
+}
+
+impl Error for ProcessingError {}
@@ -0,0 +1,525 @@
+// A module to average epoched trials into evoked responses
+
+// Written by Amin Alam in 2024
+
+use std::collections::BTreeMap;
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::erp_measures::{measure, ComponentMeasure, ComponentRow, ComponentTable, PeakMeasure};
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+use crate::processing::spikes::Polarity;
+
+/// The average of several trials time-locked to an event
+///
+/// # Arguments
+///
+/// * `times` - The time of each sample in seconds relative to the event
+/// * `names` - The name of each channel
+/// * `mean` - The mean over trials, indexed as `mean[channel][time]`
+/// * `std` - The sample standard deviation over trials, NaN with fewer than two trials
+/// * `sem` - The standard error of the mean, NaN with fewer than two trials
+/// * `n` - The number of trials with a finite value at each channel and time
+///
+/// # Examples
+///
+/// ```
+/// let erp = average(&trials, &names, 500.0, -0.2)?;
+/// erp.to_csv(&mut csv_io)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EvokedResponse {
+    pub times: Vec<f64>,
+    pub names: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub mean: Vec<Vec<f64>>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub std: Vec<Vec<f64>>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub sem: Vec<Vec<f64>>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub n: Vec<Vec<usize>>,
+}
+
+/// Implementation of the EvokedResponse struct
+///
+/// # Methods
+///
+/// * `difference` - Subtracts another evoked response, e.g. for a condition contrast
+/// * `measure_component` - Measures the latency and amplitude of a component on one channel
+/// * `measure_components` - Measures the latency and amplitude of a component on every channel
+/// * `to_csv` - Writes the mean as one row per time and one column per channel
+/// * `to_csv_long` - Writes the response as `time,channel,mean,std,sem,n` rows
+impl EvokedResponse {
+    /// Subtracts another evoked response, e.g. for a condition contrast
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The evoked response to subtract, with the same times and channels
+    ///
+    /// # Returns
+    ///
+    /// The EvokedResponse of `self - other`, or an error if the times or channels differ
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let by_condition = average_by_label(&trials, &labels, &names, 500.0, -0.2)?;
+    /// let mismatch_negativity = by_condition["deviant"].difference(&by_condition["standard"])?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The conditions are treated as independent, so the standard errors and standard
+    /// deviations add in quadrature. `n` holds the smaller of the two trial counts.
+    ///
+    pub fn difference(&self, other: &EvokedResponse) -> Result<EvokedResponse, ProcessingError> {
+        if self.times.len() != other.times.len()
+            || self.times.iter().zip(&other.times).any(|(a, b)| (a - b).abs() > 1e-9)
+            || self.names != other.names
+        {
+            return Err(ProcessingError::InvalidParameter(
+                "Evoked responses must share their times and channels to be subtracted".to_string(),
+            ));
+        }
+        let combine = |a: &[Vec<f64>], b: &[Vec<f64>], f: fn(f64, f64) -> f64| -> Vec<Vec<f64>> {
+            a.iter().zip(b).map(|(a, b)| a.iter().zip(b).map(|(&a, &b)| f(a, b)).collect()).collect()
+        };
+        Ok(EvokedResponse {
+            times: self.times.clone(),
+            names: self.names.clone(),
+            mean: combine(&self.mean, &other.mean, |a, b| a - b),
+            std: combine(&self.std, &other.std, f64::hypot),
+            sem: combine(&self.sem, &other.sem, f64::hypot),
+            n: self.n.iter().zip(&other.n).map(|(a, b)| a.iter().zip(b).map(|(&a, &b)| a.min(b)).collect()).collect(),
+        })
+    }
+
+    /// Measures the latency and amplitude of a component on one channel
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The name of the channel
+    /// * `window` - The start and end in seconds of the window searched, relative to the event, e.g. `(0.08, 0.14)` for an N1
+    /// * `polarity` - Whether the component is positive, negative, or either
+    /// * `method` - The measure
+    ///
+    /// # Returns
+    ///
+    /// The ComponentMeasure of the mean, or an error if the channel is not found, the
+    /// response has fewer than two times, or the parameters of `erp_measures::measure` are invalid
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let n1 = erp.measure_component("Cz", (0.08, 0.14), Polarity::Negative, PeakMeasure::Peak)?;
+    /// println!("N1 of {} at {} s", n1.amplitude, n1.latency);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A window reaching past the epoch is clipped to it, and a window with no sign-consistent
+    /// peak gives NaN values with a status saying why, as described for `erp_measures::measure`
+    ///
+    pub fn measure_component(&self, channel: &str, window: (f64, f64), polarity: Polarity, method: PeakMeasure) -> Result<ComponentMeasure, ProcessingError> {
+        let index = self
+            .names
+            .iter()
+            .position(|name| name == channel)
+            .ok_or_else(|| ProcessingError::InvalidParameter(format!("Channel '{}' not found", channel)))?;
+        measure(&self.mean[index], self.sampling_rate()?, self.times[0], window, polarity, method)
+    }
+
+    /// Measures the latency and amplitude of a component on every channel
+    ///
+    /// # Arguments
+    ///
+    /// * `window` - The start and end in seconds of the window searched, relative to the event
+    /// * `polarity` - Whether the component is positive, negative, or either
+    /// * `method` - The measure
+    ///
+    /// # Returns
+    ///
+    /// The ComponentTable with one row per channel, or an error if the response has fewer
+    /// than two times or the parameters of `erp_measures::measure` are invalid
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let p3 = erp.measure_components((0.3, 0.6), Polarity::Positive, PeakMeasure::FractionalArea(0.5))?;
+    /// p3.to_csv(&mut csv_io)?;
+    /// ```
+    ///
+    pub fn measure_components(&self, window: (f64, f64), polarity: Polarity, method: PeakMeasure) -> Result<ComponentTable, ProcessingError> {
+        let sampling_rate = self.sampling_rate()?;
+        let rows = self
+            .names
+            .iter()
+            .zip(&self.mean)
+            .map(|(name, mean)| {
+                Ok(ComponentRow { trial: None, channel: name.clone(), measure: measure(mean, sampling_rate, self.times[0], window, polarity, method)? })
+            })
+            .collect::<Result<Vec<ComponentRow>, ProcessingError>>()?;
+        Ok(ComponentTable { rows })
+    }
+
+    /// Returns the sampling rate implied by the first two times
+    fn sampling_rate(&self) -> Result<f64, ProcessingError> {
+        match self.times.as_slice() {
+            [first, second, ..] => Ok(1.0 / (second - first)),
+            _ => Err(ProcessingError::SignalTooShort { length: self.times.len(), required: 2 }),
+        }
+    }
+
+    /// Writes the mean as one row per time and one column per channel
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// erp.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row `time,<channel names>` is written first. The rows are not flushed to disk
+    /// until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        let mut header = vec!["time".to_string()];
+        header.extend(self.names.iter().cloned());
+        csv_io.write_record(StringRecord::from(header))?;
+        for (t, time) in self.times.iter().enumerate() {
+            let mut record = vec![float_format.format(*time)];
+            record.extend(self.mean.iter().map(|channel| float_format.format(channel[t])));
+            csv_io.write_record(StringRecord::from(record))?;
+        }
+        Ok(())
+    }
+
+    /// Writes the response as `time,channel,mean,std,sem,n` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// erp.to_csv_long(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first, then one row per time and channel, ordered by time
+    ///
+    pub fn to_csv_long(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["time", "channel", "mean", "std", "sem", "n"]))?;
+        for (t, time) in self.times.iter().enumerate() {
+            for (c, name) in self.names.iter().enumerate() {
+                csv_io.write_record(StringRecord::from(vec![
+                    float_format.format(*time),
+                    name.clone(),
+                    float_format.format(self.mean[c][t]),
+                    float_format.format(self.std[c][t]),
+                    float_format.format(self.sem[c][t]),
+                    self.n[c][t].to_string(),
+                ]))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Averages trials into an evoked response
+///
+/// # Arguments
+///
+/// * `trials` - The samples of each trial, indexed as `trials[trial][channel][time]`
+/// * `names` - The name of each channel
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample of each trial in seconds relative to the event, e.g. `-0.2`
+///
+/// # Returns
+///
+/// The EvokedResponse, or an error if there are no trials or they differ in their number
+/// of channels or samples
+///
+/// # Examples
+///
+/// ```
+/// let erp = average(&trials, &["Cz".to_string()], 500.0, -0.2)?;
+/// ```
+///
+/// # Note
+///
+/// NaN samples, e.g. from rejected artifacts, are left out of the statistics of their
+/// channel and time only, and `n` counts the trials that remain
+///
+pub fn average(trials: &[Vec<Vec<f64>>], names: &[String], sampling_rate: f64, start_time: f64) -> Result<EvokedResponse, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    let n_samples = check_trials(trials, names)?;
+    let trials: Vec<&Vec<Vec<f64>>> = trials.iter().collect();
+    Ok(statistics(&trials, names, n_samples, sampling_rate, start_time))
+}
+
+/// Averages trials into one evoked response per event label
+///
+/// # Arguments
+///
+/// * `trials` - The samples of each trial, indexed as `trials[trial][channel][time]`
+/// * `labels` - The label (condition) of each trial
+/// * `names` - The name of each channel
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample of each trial in seconds relative to the event
+///
+/// # Returns
+///
+/// The EvokedResponse of each label, or an error if the trials and labels differ in
+/// length or the trials differ in their number of channels or samples
+///
+/// # Examples
+///
+/// ```
+/// let by_condition = average_by_label(&trials, &labels, &names, 500.0, -0.2)?;
+/// let target = &by_condition["target"];
+/// ```
+///
+pub fn average_by_label(
+    trials: &[Vec<Vec<f64>>],
+    labels: &[String],
+    names: &[String],
+    sampling_rate: f64,
+    start_time: f64,
+) -> Result<BTreeMap<String, EvokedResponse>, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    if trials.len() != labels.len() {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Got {} trials but {} labels",
+            trials.len(),
+            labels.len()
+        )));
+    }
+    let n_samples = check_trials(trials, names)?;
+    let mut grouped: BTreeMap<String, Vec<&Vec<Vec<f64>>>> = BTreeMap::new();
+    for (trial, label) in trials.iter().zip(labels) {
+        grouped.entry(label.clone()).or_default().push(trial);
+    }
+    Ok(grouped
+        .into_iter()
+        .map(|(label, group)| (label, statistics(&group, names, n_samples, sampling_rate, start_time)))
+        .collect())
+}
+
+/// Checks that every trial has one row per channel of a common length, and returns that length
+fn check_trials(trials: &[Vec<Vec<f64>>], names: &[String]) -> Result<usize, ProcessingError> {
+    let n_samples = match trials.first().and_then(|trial| trial.first()) {
+        Some(channel) => channel.len(),
+        None => return Err(ProcessingError::InvalidParameter("At least one trial with one channel is needed".to_string())),
+    };
+    for (index, trial) in trials.iter().enumerate() {
+        if trial.len() != names.len() || trial.iter().any(|channel| channel.len() != n_samples) {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Trial {} must have {} channels of {} samples",
+                index,
+                names.len(),
+                n_samples
+            )));
+        }
+    }
+    Ok(n_samples)
+}
+
+fn statistics(trials: &[&Vec<Vec<f64>>], names: &[String], n_samples: usize, sampling_rate: f64, start_time: f64) -> EvokedResponse {
+    let n_channels = names.len();
+    let mut mean = vec![vec![f64::NAN; n_samples]; n_channels];
+    let mut std = vec![vec![f64::NAN; n_samples]; n_channels];
+    let mut sem = vec![vec![f64::NAN; n_samples]; n_channels];
+    let mut n = vec![vec![0; n_samples]; n_channels];
+    for c in 0..n_channels {
+        for t in 0..n_samples {
+            let values: Vec<f64> = trials.iter().map(|trial| trial[c][t]).filter(|value| value.is_finite()).collect();
+            n[c][t] = values.len();
+            if values.is_empty() {
+                continue;
+            }
+            let count = values.len() as f64;
+            mean[c][t] = values.iter().sum::<f64>() / count;
+            if values.len() > 1 {
+                let variance = values.iter().map(|value| (value - mean[c][t]).powi(2)).sum::<f64>() / (count - 1.0);
+                std[c][t] = variance.sqrt();
+                sem[c][t] = (variance / count).sqrt();
+            }
+        }
+    }
+    let times = (0..n_samples).map(|t| start_time + t as f64 / sampling_rate).collect();
+    EvokedResponse { times, names: names.to_vec(), mean, std, sem, n }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    const SAMPLING_RATE: f64 = 250.0;
+
+    fn names() -> Vec<String> {
+        vec!["Cz".to_string(), "Pz".to_string()]
+    }
+
+    /// An N1 at 100 ms and a P3 at 350 ms, scaled per channel
+    fn ground_truth(time: f64, channel: usize) -> f64 {
+        let scale = [1.0, 0.6][channel];
+        scale * (-5.0 * (-((time - 0.1) / 0.02).powi(2) / 2.0).exp() + 8.0 * (-((time - 0.35) / 0.06).powi(2) / 2.0).exp())
+    }
+
+    /// Trials of 1 s from -0.2 s holding the ground truth plus white noise of `noise` standard deviation
+    fn noisy_trials(n_trials: usize, noise: f64, seed: u64) -> Vec<Vec<Vec<f64>>> {
+        let mut rng = SeededRng::new(seed);
+        (0..n_trials)
+            .map(|_| (0..2).map(|c| (0..250).map(|t| ground_truth(-0.2 + t as f64 / SAMPLING_RATE, c) + noise * rng.next_gaussian()).collect()).collect())
+            .collect()
+    }
+
+    fn read_back(name: &str, write: impl FnOnce(&mut CsvIO)) -> Vec<String> {
+        let path = std::env::temp_dir().join(format!("neurorust-evoked-{}-{}", std::process::id(), name));
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        write(&mut csv_io);
+        csv_io.save().unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        written.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn the_average_recovers_the_ground_truth_within_its_standard_error() {
+        let trials = noisy_trials(200, 4.0, 1);
+        let erp = average(&trials, &names(), SAMPLING_RATE, -0.2).unwrap();
+        assert_eq!(erp.times.len(), 250);
+        assert!((erp.times[50]).abs() < 1e-12);
+        let mut covered = 0;
+        for c in 0..2 {
+            for t in 0..250 {
+                let error = erp.mean[c][t] - ground_truth(erp.times[t], c);
+                // The standard error is 4 / sqrt(200) = 0.28, and four of them are never exceeded
+                assert!(error.abs() < 4.0 * 4.0 / 200f64.sqrt(), "{} at {}: {}", erp.names[c], erp.times[t], error);
+                assert!((erp.sem[c][t] - erp.std[c][t] / 200f64.sqrt()).abs() < 1e-12);
+                assert_eq!(erp.n[c][t], 200);
+                if error.abs() < 1.96 * erp.sem[c][t] {
+                    covered += 1;
+                }
+            }
+        }
+        let mean_std = erp.std.iter().flatten().sum::<f64>() / 500.0;
+        assert!((mean_std / 4.0 - 1.0).abs() < 0.03, "{}", mean_std);
+        // About 95% of the points lie within 1.96 standard errors of the truth
+        let coverage = covered as f64 / 500.0;
+        assert!((coverage - 0.95).abs() < 0.04, "{}", coverage);
+    }
+
+    #[test]
+    fn nan_samples_are_left_out_per_time_point() {
+        // Three trials holding 1, 2 and 6, whose sample standard deviation is sqrt(7)
+        let trials = vec![vec![vec![1.0, 1.0, f64::NAN]], vec![vec![2.0, f64::NAN, f64::NAN]], vec![vec![6.0, 3.0, f64::NAN]]];
+        let fz = vec!["Fz".to_string()];
+        let erp = average(&trials, &fz, 100.0, 0.0).unwrap();
+        assert_eq!(erp.n[0], vec![3, 2, 0]);
+        assert_eq!(erp.mean[0][0], 3.0);
+        assert!((erp.std[0][0] - 7f64.sqrt()).abs() < 1e-12);
+        assert!((erp.sem[0][0] - (7.0f64 / 3.0).sqrt()).abs() < 1e-12);
+        assert_eq!(erp.mean[0][1], 2.0);
+        assert!((erp.std[0][1] - 2f64.sqrt()).abs() < 1e-12);
+        assert!(erp.mean[0][2].is_nan() && erp.std[0][2].is_nan() && erp.sem[0][2].is_nan());
+        let single = average(&trials[..1], &fz, 100.0, 0.0).unwrap();
+        assert_eq!(single.mean[0][0], 1.0);
+        assert!(single.std[0][0].is_nan() && single.sem[0][0].is_nan());
+
+        // Rejected stretches of a few trials only lower the count where they are
+        let mut trials = noisy_trials(50, 1.0, 2);
+        trials[0][0][10..20].iter_mut().for_each(|sample| *sample = f64::NAN);
+        trials[1][1].iter_mut().for_each(|sample| *sample = f64::NAN);
+        let erp = average(&trials, &names(), SAMPLING_RATE, -0.2).unwrap();
+        assert_eq!((erp.n[0][15], erp.n[0][25], erp.n[1][15]), (49, 50, 49));
+        let expected = trials.iter().skip(1).map(|trial| trial[0][15]).sum::<f64>() / 49.0;
+        assert!((erp.mean[0][15] - expected).abs() < 1e-12);
+    }
+
+    #[test]
+    fn conditions_are_split_and_contrasted_with_propagated_errors() {
+        let mut trials = noisy_trials(120, 2.0, 3);
+        let labels: Vec<String> = (0..120).map(|k| if k % 3 == 0 { "deviant".to_string() } else { "standard".to_string() }).collect();
+        // The deviants carry an extra negativity of -3 on both channels from 150 to 250 ms
+        for (trial, label) in trials.iter_mut().zip(&labels) {
+            if label == "deviant" {
+                for channel in trial.iter_mut() {
+                    channel[87..113].iter_mut().for_each(|sample| *sample -= 3.0);
+                }
+            }
+        }
+        let by_label = average_by_label(&trials, &labels, &names(), SAMPLING_RATE, -0.2).unwrap();
+        assert_eq!(by_label.keys().collect::<Vec<_>>(), vec!["deviant", "standard"]);
+        let deviants: Vec<Vec<Vec<f64>>> = trials.iter().step_by(3).cloned().collect();
+        assert_eq!(by_label["deviant"], average(&deviants, &names(), SAMPLING_RATE, -0.2).unwrap());
+        assert_eq!(by_label["standard"].n[0][0], 80);
+
+        let contrast = by_label["deviant"].difference(&by_label["standard"]).unwrap();
+        for c in 0..2 {
+            for t in 0..250 {
+                let (a, b) = (&by_label["deviant"], &by_label["standard"]);
+                assert_eq!(contrast.mean[c][t], a.mean[c][t] - b.mean[c][t]);
+                assert!((contrast.sem[c][t] - (a.sem[c][t].powi(2) + b.sem[c][t].powi(2)).sqrt()).abs() < 1e-12);
+                assert_eq!(contrast.n[c][t], 40);
+            }
+            let window_mean = contrast.mean[c][87..113].iter().sum::<f64>() / 26.0;
+            assert!((window_mean + 3.0).abs() < 0.3, "{}", window_mean);
+        }
+
+        let other_channels = average(&trials, &["Oz".to_string(), "Pz".to_string()], SAMPLING_RATE, -0.2).unwrap();
+        assert!(contrast.difference(&other_channels).is_err());
+        let other_times = average(&trials, &names(), SAMPLING_RATE, -0.1).unwrap();
+        assert!(contrast.difference(&other_times).is_err());
+        assert!(average_by_label(&trials, &labels[..119], &names(), SAMPLING_RATE, -0.2).is_err());
+        assert!(average(&[], &names(), SAMPLING_RATE, -0.2).is_err());
+        assert!(average(&trials, &names()[..1], SAMPLING_RATE, -0.2).is_err());
+        assert!(average(&trials, &names(), 0.0, -0.2).is_err());
+    }
+
+    #[test]
+    fn evoked_responses_export_in_wide_and_long_formats() {
+        let trials = vec![vec![vec![1.0, 2.0], vec![3.0, 4.0]], vec![vec![3.0, 2.0], vec![5.0, f64::NAN]]];
+        let erp = average(&trials, &names(), 2.0, -0.5).unwrap();
+
+        let wide = read_back("wide.csv", |csv_io| erp.to_csv(csv_io).unwrap());
+        assert_eq!(wide[0], "time,Cz,Pz");
+        assert_eq!(wide.len(), 3);
+        let row: Vec<f64> = wide[1].split(',').map(|value| value.parse().unwrap()).collect();
+        assert_eq!(row, vec![-0.5, 2.0, 4.0]);
+
+        let long = read_back("long.csv", |csv_io| erp.to_csv_long(csv_io).unwrap());
+        assert_eq!(long[0], "time,channel,mean,std,sem,n");
+        assert_eq!(long.len(), 5);
+        let fields: Vec<&str> = long[4].split(',').collect();
+        assert_eq!(&fields[..3], &["0", "Pz", "4"]);
+        assert_eq!(fields[5], "1");
+        let first: Vec<&str> = long[1].split(',').collect();
+        assert!((first[3].parse::<f64>().unwrap() - 2f64.sqrt()).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,505 @@
+// A module to export one analysis-ready row per trial
+
+// Written by Amin Alam in 2024
+
+use std::collections::HashMap;
+use std::io;
+use csv::StringRecord;
+use crate::data_io::csv::{CsvIO, CsvReader};
+use crate::data_io::error::DataIoError;
+use crate::processing::artifacts::{ArtifactKind, ArtifactSpan};
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+use crate::processing::spectral::{band_power_trials, BandPowerMethod, FrequencyBand};
+
+/// The spike trains counted by `trial_table`
+///
+/// # Arguments
+///
+/// * `names` - The name of each unit, used as the prefix of its `<name>_count` column
+/// * `trains` - The spike times of each unit in seconds, in any order
+///
+/// # Examples
+///
+/// ```
+/// let spikes = TrialSpikes { names: &unit_names, trains: &spike_times };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrialSpikes<'a> {
+    pub names: &'a [String],
+    pub trains: &'a [Vec<f64>],
+}
+
+/// The continuous recording whose band powers `trial_table` estimates
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel, all of the same length
+/// * `names` - The name of each channel, used as the prefix of its `<name>_<band>_power` columns
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample in seconds
+/// * `bands` - The frequency bands
+/// * `method` - The band power estimator
+/// * `relative` - Divides every band power by the total power of the trial if true
+///
+/// # Examples
+///
+/// ```
+/// let recording = TrialRecording {
+///     channels: &lfp,
+///     names: &channel_names,
+///     sampling_rate: 1000.0,
+///     start_time: 0.0,
+///     bands: &FrequencyBand::canonical(),
+///     method: BandPowerMethod::FilterHilbert { order: 4 },
+///     relative: false,
+/// };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrialRecording<'a> {
+    pub channels: &'a [Vec<f64>],
+    pub names: &'a [String],
+    pub sampling_rate: f64,
+    pub start_time: f64,
+    pub bands: &'a [FrequencyBand],
+    pub method: BandPowerMethod,
+    pub relative: bool,
+}
+
+/// The behavioral table joined by `trial_table`
+///
+/// # Arguments
+///
+/// * `headers` - The names of the columns
+/// * `rows` - The fields of each row
+///
+/// # Examples
+///
+/// ```
+/// let behavior = Behavior::read(&mut CsvReader::open("behavior.csv")?)?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Behavior {
+    pub headers: Vec<String>,
+    pub rows: Vec<Vec<String>>,
+}
+
+/// Implementation of the Behavior struct
+///
+/// # Methods
+///
+/// * `read` - Reads the remaining records of a CsvReader
+/// * `load` - Reads the remaining records of a CsvIO object
+impl Behavior {
+    /// Reads the remaining records of a CsvReader
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The reader of the behavioral csv file
+    ///
+    /// # Returns
+    ///
+    /// The Behavior, or an error if a record cannot be read
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let behavior = Behavior::read(&mut CsvReader::open("behavior.csv")?)?;
+    /// ```
+    ///
+    pub fn read(reader: &mut CsvReader) -> io::Result<Self> {
+        let headers = reader.headers().iter().map(String::from).collect();
+        let rows = reader.read_records()?.iter().map(|record| record.iter().map(String::from).collect()).collect();
+        Ok(Self { headers, rows })
+    }
+
+    /// Reads the remaining records of a CsvIO object
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object of the behavioral csv file
+    ///
+    /// # Returns
+    ///
+    /// The Behavior, or an error if a record cannot be read
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let behavior = Behavior::load(&mut csv_io)?;
+    /// ```
+    ///
+    pub fn load(csv_io: &mut CsvIO) -> io::Result<Self> {
+        Self::read(csv_io.reader_mut()?)
+    }
+}
+
+/// How `trial_table` matches behavioral rows to trials
+///
+/// # Arguments
+///
+/// * `Row` - Row `i` of the behavioral table belongs to trial `i`
+/// * `Key` - The row whose `column` equals `keys[i]` belongs to trial `i`, given one key per trial
+///
+/// # Examples
+///
+/// ```
+/// let trial_numbers: Vec<String> = (1..=onsets.len()).map(|n| n.to_string()).collect();
+/// let options = TrialTableOptions { join: TrialJoin::Key { column: "trial_id", keys: &trial_numbers }, ..TrialTableOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TrialJoin<'a> {
+    Row,
+    Key { column: &'a str, keys: &'a [String] },
+}
+
+/// The options of `trial_table`
+///
+/// # Arguments
+///
+/// * `join` - How behavioral rows are matched to trials, by row order by default
+/// * `artifacts` - The artifact spans; trials whose window overlaps one are dropped, none by default
+/// * `extent` - The start and end of the recording in seconds, outside of which trials are dropped; None uses the span of the TrialRecording, if any
+///
+/// # Examples
+///
+/// ```
+/// let options = TrialTableOptions { artifacts: &spans, extent: Some((0.0, duration)), ..TrialTableOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrialTableOptions<'a> {
+    pub join: TrialJoin<'a>,
+    pub artifacts: &'a [ArtifactSpan],
+    pub extent: Option<(f64, f64)>,
+}
+
+impl Default for TrialTableOptions<'_> {
+    fn default() -> Self {
+        Self { join: TrialJoin::Row, artifacts: &[], extent: None }
+    }
+}
+
+/// What `trial_table` wrote
+///
+/// # Arguments
+///
+/// * `columns` - The names of the columns, in order
+/// * `n_trials` - The number of rows, one per trial
+/// * `dropped` - The index and drop reason of every dropped trial
+/// * `unmatched` - The indices of the trials without a behavioral row under `TrialJoin::Key`
+///
+/// # Examples
+///
+/// ```
+/// let summary = trial_table(&onsets, (0.0, 0.5), Some(&spikes), Some(&recording), Some(&behavior), &options, &mut csv_io)?;
+/// println!("{} of {} trials dropped", summary.dropped.len(), summary.n_trials);
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TrialTableSummary {
+    pub columns: Vec<String>,
+    pub n_trials: usize,
+    pub dropped: Vec<(usize, String)>,
+    pub unmatched: Vec<usize>,
+}
+
+/// Writes one row per trial with behavioral columns, spike counts and band powers
+///
+/// # Arguments
+///
+/// * `event_times` - The time of each event (trial onset) in seconds
+/// * `window` - The start and end of each trial in seconds relative to its event, e.g. `(0.1, 0.6)`
+/// * `spikes` - The spike trains to count in each window, if any
+/// * `recording` - The recording whose band powers to estimate in each window, if any
+/// * `behavior` - The behavioral table to join, if any
+/// * `options` - The join of the behavioral rows, the artifact spans and the extent of the recording
+/// * `output` - The CsvIO object to write to
+///
+/// # Returns
+///
+/// The TrialTableSummary, or an error if the window is empty, the names and data do not
+/// match, the recording is invalid, two columns would have the same name, the behavioral
+/// rows cannot be matched to the trials, a band power estimate fails, or a row cannot be written
+///
+/// # Examples
+///
+/// ```
+/// let spikes = TrialSpikes { names: &unit_names, trains: &spike_times };
+/// let behavior = Behavior::read(&mut CsvReader::open("behavior.csv")?)?;
+/// let summary = trial_table(&onsets, (0.0, 0.5), Some(&spikes), Some(&recording), Some(&behavior), &TrialTableOptions::default(), &mut csv_io)?;
+/// csv_io.save()?;
+/// ```
+///
+/// # Note
+///
+/// The columns are `trial` (the index of the event), `time` (the event time), the
+/// behavioral columns, `<unit>_count` for every unit, `<channel>_<band>_power` for every
+/// channel and band, and `drop_reason`. Spikes are counted in the half-open window
+/// `[event + window.0, event + window.1)`. The band powers of a trial are estimated on the
+/// `round((window.1 - window.0) * sampling_rate)` samples from the one nearest to
+/// `event + window.0`, so every trial has the same length. Trials whose window leaves the
+/// recording or overlaps an artifact keep their row, with empty spike and power cells and
+/// the reason in `drop_reason`. Under `TrialJoin::Row` the behavioral table must have one
+/// row per trial; under `TrialJoin::Key` trials without a row get empty behavioral cells and
+/// are listed in `unmatched`. A header row is written first. The rows are not flushed to
+/// disk until `save` is called.
+///
+pub fn trial_table(
+    event_times: &[f64],
+    window: (f64, f64),
+    spikes: Option<&TrialSpikes>,
+    recording: Option<&TrialRecording>,
+    behavior: Option<&Behavior>,
+    options: &TrialTableOptions,
+    output: &mut CsvIO,
+) -> Result<TrialTableSummary, DataIoError> {
+    if window.0.is_nan() || window.1.is_nan() || window.1 <= window.0 {
+        return Err(ProcessingError::InvalidParameter(format!("Trial window ({}, {}) is empty", window.0, window.1)).into());
+    }
+    let n_trials = event_times.len();
+    let mut columns: Vec<String> = vec!["trial".to_string(), "time".to_string()];
+
+    let (behavior_rows, unmatched) = match behavior {
+        Some(behavior) => {
+            columns.extend(behavior.headers.iter().cloned());
+            match_behavior(behavior, n_trials, &options.join)?
+        }
+        None => (vec![None; n_trials], Vec::new()),
+    };
+
+    let mut spike_counts: Vec<Vec<usize>> = Vec::new();
+    if let Some(spikes) = spikes {
+        if spikes.names.len() != spikes.trains.len() {
+            return Err(ProcessingError::InvalidParameter(format!("Got {} spike trains but {} unit names", spikes.trains.len(), spikes.names.len())).into());
+        }
+        columns.extend(spikes.names.iter().map(|name| format!("{}_count", name)));
+        for train in spikes.trains {
+            let mut sorted = train.clone();
+            sorted.sort_by(f64::total_cmp);
+            let count = |&event: &f64| sorted.partition_point(|&time| time < event + window.1) - sorted.partition_point(|&time| time < event + window.0);
+            spike_counts.push(event_times.iter().map(count).collect());
+        }
+    }
+
+    let mut extent = options.extent;
+    let mut length = 0;
+    if let Some(recording) = recording {
+        validate_sampling_rate(recording.sampling_rate)?;
+        if recording.names.len() != recording.channels.len() {
+            return Err(ProcessingError::InvalidParameter(format!("Got {} channels but {} channel names", recording.channels.len(), recording.names.len())).into());
+        }
+        let n_samples = recording.channels.first().map_or(0, |channel| channel.len());
+        if recording.channels.iter().any(|channel| channel.len() != n_samples) {
+            return Err(ProcessingError::InvalidParameter("Channels must have the same length".to_string()).into());
+        }
+        extent = extent.or(Some((recording.start_time, recording.start_time + n_samples as f64 / recording.sampling_rate)));
+        length = ((window.1 - window.0) * recording.sampling_rate).round().max(1.0) as usize;
+        for name in recording.names {
+            columns.extend(recording.bands.iter().map(|band| format!("{}_{}_power", name, band.name)));
+        }
+    }
+    columns.push("drop_reason".to_string());
+    for (index, column) in columns.iter().enumerate() {
+        if columns[..index].contains(column) {
+            return Err(ProcessingError::InvalidParameter(format!("Column {} would appear twice in the trial table", column)).into());
+        }
+    }
+
+    let mut dropped: Vec<(usize, String)> = Vec::new();
+    let mut first_samples: Vec<Option<usize>> = Vec::with_capacity(n_trials);
+    for (trial, &event) in event_times.iter().enumerate() {
+        let (start, end) = (event + window.0, event + window.1);
+        let mut reasons: Vec<String> = Vec::new();
+        let mut first_sample = None;
+        if let Some(extent) = extent.filter(|extent| !(start >= extent.0 && end <= extent.1)) {
+            reasons.push(format!("window outside the recording ({} to {} s)", extent.0, extent.1));
+        } else if let Some(recording) = recording {
+            let first = ((start - recording.start_time) * recording.sampling_rate).round();
+            let n_samples = recording.channels.first().map_or(0, |channel| channel.len());
+            if first < 0.0 || first as usize + length > n_samples {
+                reasons.push("window outside the recording".to_string());
+            } else {
+                first_sample = Some(first as usize);
+            }
+        }
+        let mut kinds: Vec<ArtifactKind> = options.artifacts.iter().filter(|span| span.start < end && span.end > start).map(|span| span.kind).collect();
+        if !kinds.is_empty() {
+            kinds.sort_unstable();
+            kinds.dedup();
+            let names: Vec<&str> = kinds.iter().map(|kind| artifact_name(*kind)).collect();
+            reasons.push(format!("artifact ({})", names.join(", ")));
+        }
+        if !reasons.is_empty() {
+            dropped.push((trial, reasons.join("; ")));
+            first_sample = None;
+        }
+        first_samples.push(first_sample);
+    }
+    let is_dropped = |trial: usize| dropped.iter().any(|(index, _)| *index == trial);
+
+    // The powers of each channel, for the kept trials in order
+    let mut powers: Vec<Vec<Vec<f64>>> = Vec::new();
+    if let Some(recording) = recording {
+        for channel in recording.channels {
+            let trials: Vec<Vec<f64>> = first_samples.iter().flatten().map(|&first| channel[first..first + length].to_vec()).collect();
+            powers.push(band_power_trials(&trials, recording.sampling_rate, recording.bands, recording.method, recording.relative)?);
+        }
+    }
+
+    let float_format = output.float_format();
+    output.write_record(StringRecord::from(columns.clone()))?;
+    let n_behavior = behavior.map_or(0, |behavior| behavior.headers.len());
+    let n_powers = recording.map_or(0, |recording| recording.channels.len() * recording.bands.len());
+    let mut kept = 0;
+    for (trial, &event) in event_times.iter().enumerate() {
+        let mut record = vec![trial.to_string(), float_format.format(event)];
+        match (behavior, behavior_rows[trial]) {
+            (Some(behavior), Some(row)) => record.extend((0..n_behavior).map(|column| behavior.rows[row].get(column).cloned().unwrap_or_default())),
+            _ => record.extend(std::iter::repeat_n(String::new(), n_behavior)),
+        }
+        if is_dropped(trial) {
+            record.extend(std::iter::repeat_n(String::new(), spike_counts.len() + n_powers));
+            record.push(dropped.iter().find(|(index, _)| *index == trial).map_or(String::new(), |(_, reason)| reason.clone()));
+        } else {
+            record.extend(spike_counts.iter().map(|counts| counts[trial].to_string()));
+            if first_samples[trial].is_some() {
+                record.extend(powers.iter().flat_map(|channel| channel[kept].iter().map(|power| float_format.format(*power))));
+                kept += 1;
+            }
+            record.push(String::new());
+        }
+        output.write_record(StringRecord::from(record))?;
+    }
+    Ok(TrialTableSummary { columns, n_trials, dropped, unmatched })
+}
+
+/// Returns the behavioral row of every trial and the trials without one
+fn match_behavior(behavior: &Behavior, n_trials: usize, join: &TrialJoin) -> Result<(Vec<Option<usize>>, Vec<usize>), ProcessingError> {
+    match join {
+        TrialJoin::Row => {
+            if behavior.rows.len() != n_trials {
+                return Err(ProcessingError::InvalidParameter(format!(
+                    "The behavioral table has {} rows but there are {} trials; join by a key column if they do not match one to one",
+                    behavior.rows.len(),
+                    n_trials
+                )));
+            }
+            Ok(((0..n_trials).map(Some).collect(), Vec::new()))
+        }
+        TrialJoin::Key { column, keys } => {
+            if keys.len() != n_trials {
+                return Err(ProcessingError::InvalidParameter(format!("Expected {} trial keys, one per trial, got {}", n_trials, keys.len())));
+            }
+            let key_column = behavior
+                .headers
+                .iter()
+                .position(|header| header == column)
+                .ok_or_else(|| ProcessingError::InvalidParameter(format!("The behavioral table has no column {}", column)))?;
+            let mut rows: HashMap<&str, usize> = HashMap::new();
+            for (index, row) in behavior.rows.iter().enumerate() {
+                let key = row.get(key_column).map_or("", String::as_str);
+                if rows.insert(key, index).is_some() {
+                    return Err(ProcessingError::InvalidParameter(format!("Key {} appears twice in column {} of the behavioral table", key, column)));
+                }
+            }
+            let matched: Vec<Option<usize>> = keys.iter().map(|key| rows.get(key.as_str()).copied()).collect();
+            let unmatched = (0..n_trials).filter(|&trial| matched[trial].is_none()).collect();
+            Ok((matched, unmatched))
+        }
+    }
+}
+
+/// Returns the name of a kind of artifact in a drop reason
+fn artifact_name(kind: ArtifactKind) -> &'static str {
+    match kind {
+        ArtifactKind::Amplitude => "amplitude",
+        ArtifactKind::PeakToPeak => "peak_to_peak",
+        ArtifactKind::Gradient => "gradient",
+        ArtifactKind::Flatline => "flatline",
+        ArtifactKind::Stimulation => "stimulation",
+        ArtifactKind::Seam => "seam",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use crate::processing::window::Window;
+
+    fn temp_path(name: &str) -> String {
+        std::env::temp_dir().join(format!("neurorust-export-{}-{}", std::process::id(), name)).to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn two_trials_match_hand_computed_counts_and_powers() {
+        // A 6 Hz sine of amplitude 1 for the first 3 s and 2 afterwards, whose mean square in a
+        // whole number of cycles is 0.5 and then 2
+        let sampling_rate = 100.0;
+        let channel: Vec<f64> = (0..1000)
+            .map(|i| {
+                let time = i as f64 / sampling_rate;
+                let amplitude = if time < 3.0 { 1.0 } else { 2.0 };
+                amplitude * (2.0 * PI * 6.0 * time).sin()
+            })
+            .collect();
+        let channels = vec![channel];
+        let channel_names = vec!["ch12".to_string()];
+        let bands = vec![FrequencyBand::new("theta", 4.0, 8.0)];
+        let recording = TrialRecording {
+            channels: &channels,
+            names: &channel_names,
+            sampling_rate,
+            start_time: 0.0,
+            bands: &bands,
+            method: BandPowerMethod::Welch { segment_len: 100, overlap_fraction: 0.5, window: Window::Rectangular },
+            relative: false,
+        };
+        let unit_names = vec!["unit7".to_string(), "unit9".to_string()];
+        // The window is half-open, so the spikes at 2.0 and 5.0 belong to no trial
+        let trains = vec![vec![1.1, 1.0, 1.5, 1.99, 2.0, 4.2, 4.0, 5.0], vec![4.5]];
+        let spikes = TrialSpikes { names: &unit_names, trains: &trains };
+
+        let behavior_path = temp_path("behavior.csv");
+        std::fs::write(&behavior_path, "trial_id,choice,rt\n2,left,0.4\n1,right,0.35\n4,left,0.5\n").unwrap();
+        let behavior = Behavior::read(&mut CsvReader::open(&behavior_path).unwrap()).unwrap();
+        let keys: Vec<String> = (1..=4).map(|n| n.to_string()).collect();
+        let artifacts = vec![ArtifactSpan { start: 6.2, end: 6.3, kind: ArtifactKind::Amplitude, channel: 0 }];
+        let options = TrialTableOptions { join: TrialJoin::Key { column: "trial_id", keys: &keys }, artifacts: &artifacts, extent: None };
+
+        let output_path = temp_path("table.csv");
+        std::fs::write(&output_path, "").unwrap();
+        let mut output = CsvIO::open_write(&output_path).unwrap();
+        let events = [1.0, 4.0, 9.8, 6.0];
+        let summary = trial_table(&events, (0.0, 1.0), Some(&spikes), Some(&recording), Some(&behavior), &options, &mut output).unwrap();
+        output.save().unwrap();
+        let text = std::fs::read_to_string(&output_path).unwrap();
+        [behavior_path, output_path].iter().for_each(|path| std::fs::remove_file(path).unwrap());
+
+        let rows: Vec<Vec<&str>> = text.lines().map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows[0], ["trial", "time", "trial_id", "choice", "rt", "unit7_count", "unit9_count", "ch12_theta_power", "drop_reason"]);
+        assert_eq!(rows[1][..7], ["0", "1", "1", "right", "0.35", "4", "0"]);
+        assert!((rows[1][7].parse::<f64>().unwrap() - 0.5).abs() < 1e-9, "{}", rows[1][7]);
+        assert_eq!(rows[1][8], "");
+        assert_eq!(rows[2][..7], ["1", "4", "2", "left", "0.4", "2", "1"]);
+        assert!((rows[2][7].parse::<f64>().unwrap() - 2.0).abs() < 1e-9, "{}", rows[2][7]);
+        // Dropped trials keep their row and behavior, with empty cells and the reason
+        assert_eq!(rows[3][..8], ["2", "9.8", "", "", "", "", "", ""]);
+        assert!(rows[3][8].starts_with("window outside the recording"));
+        assert_eq!(rows[4], ["3", "6", "4", "left", "0.5", "", "", "", "artifact (amplitude)"]);
+        assert_eq!(summary.n_trials, 4);
+        assert_eq!(summary.unmatched, vec![2]);
+        assert_eq!(summary.dropped.iter().map(|(trial, _)| *trial).collect::<Vec<_>>(), vec![2, 3]);
+    }
+
+    #[test]
+    fn row_join_needs_one_behavioral_row_per_trial() {
+        let behavior = Behavior { headers: vec!["choice".to_string()], rows: vec![vec!["left".to_string()], vec!["right".to_string()]] };
+        let output_path = temp_path("row-join.csv");
+        std::fs::write(&output_path, "").unwrap();
+        let mut output = CsvIO::open_write(&output_path).unwrap();
+        assert!(trial_table(&[1.0, 2.0, 3.0], (0.0, 0.5), None, None, Some(&behavior), &TrialTableOptions::default(), &mut output).is_err());
+        assert!(trial_table(&[1.0, 2.0], (0.0, 0.5), None, None, Some(&behavior), &TrialTableOptions::default(), &mut output).is_ok());
+        assert!(trial_table(&[1.0, 2.0], (0.5, 0.5), None, None, Some(&behavior), &TrialTableOptions::default(), &mut output).is_err());
+        std::fs::remove_file(&output_path).unwrap();
+    }
+}
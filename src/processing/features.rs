@@ -0,0 +1,628 @@
+// A module to extract features of signals over sliding windows
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+use crate::processing::parallel::{try_map_tasks, worker_count};
+use crate::processing::spectral::SegmentPeriodogram;
+use crate::processing::window::Window;
+
+/// A feature computed over each window
+///
+/// # Arguments
+///
+/// * `Rms` - The root mean square `sqrt(mean(x²))`, without removing the mean
+/// * `Variance` - The population variance, with `n` degrees of freedom
+/// * `LineLength` - The sum of the absolute differences between consecutive samples
+/// * `ZeroCrossings` - The number of consecutive samples on opposite sides of zero, counting 0 as positive
+/// * `PeakToPeak` - The largest sample minus the smallest sample
+/// * `SpectralEdge` - The lowest frequency in Hz below which the given fraction of the power of the mean-removed, Hann-windowed periodogram lies, e.g. `0.9` for SEF90
+///
+/// # Examples
+///
+/// ```
+/// let features = [Feature::LineLength, Feature::Rms, Feature::SpectralEdge(0.95)];
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Feature {
+    Rms,
+    Variance,
+    LineLength,
+    ZeroCrossings,
+    PeakToPeak,
+    SpectralEdge(f64),
+}
+
+/// Implementation of the Feature enum
+///
+/// # Methods
+///
+/// * `name` - Returns the column name of the feature
+impl Feature {
+    /// Returns the column name of the feature
+    ///
+    /// # Returns
+    ///
+    /// `rms`, `variance`, `line_length`, `zero_crossings`, `peak_to_peak` or `sef` followed by the percentage, e.g. `sef90`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(Feature::SpectralEdge(0.95).name(), "sef95");
+    /// ```
+    ///
+    pub fn name(&self) -> String {
+        match self {
+            Feature::Rms => "rms".to_string(),
+            Feature::Variance => "variance".to_string(),
+            Feature::LineLength => "line_length".to_string(),
+            Feature::ZeroCrossings => "zero_crossings".to_string(),
+            Feature::PeakToPeak => "peak_to_peak".to_string(),
+            Feature::SpectralEdge(fraction) => format!("sef{}", (fraction * 1000.0).round() / 10.0),
+        }
+    }
+}
+
+/// What to do with the windows that extend past the end of the signal
+///
+/// # Arguments
+///
+/// * `Drop` - Leaves them out of the table
+/// * `Flag` - Computes their features over the samples they hold and marks them as partial
+///
+/// # Examples
+///
+/// ```
+/// let table = sliding(&channels, &names, 256.0, 2.0, 1.0, &[Feature::LineLength], PartialWindow::Flag)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum PartialWindow {
+    Drop,
+    Flag,
+}
+
+/// The features of every window, one row per window
+///
+/// # Arguments
+///
+/// * `times` - The center of each window in seconds, relative to the first sample
+/// * `columns` - The name of each feature column, `<channel>_<feature>`
+/// * `values` - The feature values of each window, one per column
+/// * `partial` - Whether each window extends past the end of the signal
+///
+/// # Examples
+///
+/// ```
+/// let table = sliding(&channels, &names, 512.0, 1.0, 0.5, &[Feature::Rms], PartialWindow::Drop)?;
+/// table.to_csv(&mut csv_io)?;
+/// ```
+///
+/// # Note
+///
+/// The time of a partial window is the center it would have if it were complete
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FeatureTable {
+    pub times: Vec<f64>,
+    pub columns: Vec<String>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub values: Vec<Vec<f64>>,
+    pub partial: Vec<bool>,
+}
+
+/// Implementation of the FeatureTable struct
+///
+/// # Methods
+///
+/// * `column` - Returns the values of one column
+/// * `to_csv` - Writes the table as `time,<columns>,partial` rows
+impl FeatureTable {
+    /// Returns the values of one column
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the column, e.g. `Fz_line_length`
+    ///
+    /// # Returns
+    ///
+    /// The value of the column in each window, or `None` if there is no such column
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let line_length = table.column("Fz_line_length").unwrap();
+    /// ```
+    ///
+    pub fn column(&self, name: &str) -> Option<Vec<f64>> {
+        let index = self.columns.iter().position(|column| column == name)?;
+        Some(self.values.iter().map(|row| row[index]).collect())
+    }
+
+    /// Writes the table as `time,<columns>,partial` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// table.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        let mut header = vec!["time".to_string()];
+        header.extend(self.columns.iter().cloned());
+        header.push("partial".to_string());
+        csv_io.write_record(StringRecord::from(header))?;
+        for ((time, row), partial) in self.times.iter().zip(&self.values).zip(&self.partial) {
+            let mut record = vec![float_format.format(*time)];
+            record.extend(row.iter().map(|value| float_format.format(*value)));
+            record.push(partial.to_string());
+            csv_io.write_record(StringRecord::from(record))?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes sliding-window features chunk by chunk, keeping only one window of samples in memory
+///
+/// # Examples
+///
+/// ```
+/// let mut builder = SlidingFeatures::new(&names, 256.0, 2.0, 1.0, &[Feature::LineLength, Feature::Rms], PartialWindow::Drop)?;
+/// for chunk in chunks {
+///     builder.push(&chunk)?;
+/// }
+/// let table = builder.finish();
+/// ```
+pub struct SlidingFeatures {
+    names: Vec<String>,
+    sampling_rate: f64,
+    window_len: usize,
+    step: usize,
+    features: Vec<Feature>,
+    partial: PartialWindow,
+    periodogram: Option<SegmentPeriodogram>,
+    pending: Vec<Vec<f64>>,
+    pending_start: usize,
+    skip: usize,
+    table: FeatureTable,
+}
+
+/// Implementation of the SlidingFeatures struct
+///
+/// # Methods
+///
+/// * `new` - Creates an empty SlidingFeatures
+/// * `push` - Adds the next chunk of samples of every channel
+/// * `finish` - Returns the FeatureTable of all windows
+impl SlidingFeatures {
+    /// Creates an empty SlidingFeatures
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - The name of each channel, used as the prefix of its columns
+    /// * `sampling_rate` - The sampling rate in Hz
+    /// * `window` - The length of each window in seconds, rounded to a whole number of samples
+    /// * `step` - The time between the starts of consecutive windows in seconds, rounded to a whole number of samples
+    /// * `features` - The features computed over each window
+    /// * `partial` - What to do with the windows that extend past the end of the signal
+    ///
+    /// # Returns
+    ///
+    /// The SlidingFeatures, or an error if the sampling rate is not positive, the window or
+    /// step is shorter than one sample, no feature is requested or a spectral edge fraction
+    /// is not within (0, 1]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let builder = SlidingFeatures::new(&["EMG".to_string()], 2000.0, 0.25, 0.125, &[Feature::Rms], PartialWindow::Flag)?;
+    /// ```
+    ///
+    pub fn new(
+        names: &[String],
+        sampling_rate: f64,
+        window: f64,
+        step: f64,
+        features: &[Feature],
+        partial: PartialWindow,
+    ) -> Result<Self, ProcessingError> {
+        validate_sampling_rate(sampling_rate)?;
+        let window_len = (window * sampling_rate).round();
+        let step_len = (step * sampling_rate).round();
+        if !(window_len >= 1.0 && step_len >= 1.0 && window_len.is_finite() && step_len.is_finite()) {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Window and step must be at least one sample long, got {} s and {} s",
+                window, step
+            )));
+        }
+        if features.is_empty() {
+            return Err(ProcessingError::InvalidParameter("At least one feature is needed".to_string()));
+        }
+        if let Some(feature) = features
+            .iter()
+            .find(|feature| matches!(feature, Feature::SpectralEdge(fraction) if !(*fraction > 0.0 && *fraction <= 1.0)))
+        {
+            return Err(ProcessingError::InvalidParameter(format!("Spectral edge fraction must be within (0, 1], got {:?}", feature)));
+        }
+        let window_len = window_len as usize;
+        let periodogram = features
+            .iter()
+            .any(|feature| matches!(feature, Feature::SpectralEdge(_)))
+            .then(|| SegmentPeriodogram::new(window_len, sampling_rate, Window::Hann));
+        let columns = names
+            .iter()
+            .flat_map(|name| features.iter().map(move |feature| format!("{}_{}", name, feature.name())))
+            .collect();
+        Ok(Self {
+            names: names.to_vec(),
+            sampling_rate,
+            window_len,
+            step: step_len as usize,
+            features: features.to_vec(),
+            partial,
+            periodogram,
+            pending: vec![Vec::with_capacity(window_len); names.len()],
+            pending_start: 0,
+            skip: 0,
+            table: FeatureTable { columns, ..FeatureTable::default() },
+        })
+    }
+
+    /// Adds the next chunk of samples of every channel
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk` - The samples following the previously pushed ones, one row per channel
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if the chunk does not have one row per channel or its rows differ in length
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// builder.push(&chunk)?;
+    /// ```
+    ///
+    pub fn push(&mut self, chunk: &[Vec<f64>]) -> Result<(), ProcessingError> {
+        let length = chunk.first().map_or(0, |row| row.len());
+        if chunk.len() != self.names.len() || chunk.iter().any(|row| row.len() != length) {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Chunk must have {} channels of equal length",
+                self.names.len()
+            )));
+        }
+        let skipped = self.skip.min(length);
+        self.skip -= skipped;
+        self.pending_start += skipped;
+        for (pending, row) in self.pending.iter_mut().zip(chunk) {
+            pending.extend_from_slice(&row[skipped..]);
+        }
+
+        while self.pending[0].len() >= self.window_len {
+            self.add_window(self.window_len, false);
+            let dropped = self.step.min(self.pending[0].len());
+            self.pending.iter_mut().for_each(|pending| {
+                pending.drain(..dropped);
+            });
+            self.pending_start += dropped;
+            self.skip = self.step - dropped;
+            if self.skip > 0 {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the FeatureTable of all windows
+    ///
+    /// # Returns
+    ///
+    /// The FeatureTable, with the windows that extend past the end of the signal flagged or
+    /// dropped as requested
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let table = builder.finish();
+    /// ```
+    ///
+    pub fn finish(mut self) -> FeatureTable {
+        if self.partial == PartialWindow::Flag && self.skip == 0 {
+            while !self.pending.is_empty() && !self.pending[0].is_empty() {
+                self.add_window(self.pending[0].len(), true);
+                if self.step >= self.pending[0].len() {
+                    break;
+                }
+                let step = self.step;
+                self.pending.iter_mut().for_each(|pending| {
+                    pending.drain(..step);
+                });
+                self.pending_start += step;
+            }
+        }
+        self.table
+    }
+
+    /// Computes the features of the first `len` pending samples of every channel
+    fn add_window(&mut self, len: usize, partial: bool) {
+        let mut row = Vec::with_capacity(self.table.columns.len());
+        for channel in 0..self.pending.len() {
+            let window = &self.pending[channel][..len];
+            for &feature in &self.features {
+                let value = match feature {
+                    Feature::SpectralEdge(fraction) if len == self.window_len => {
+                        spectral_edge(self.periodogram.as_mut().expect("Periodogram of the spectral edge"), window, fraction)
+                    }
+                    Feature::SpectralEdge(fraction) => {
+                        spectral_edge(&mut SegmentPeriodogram::new(len, self.sampling_rate, Window::Hann), window, fraction)
+                    }
+                    _ => feature_value(feature, window),
+                };
+                row.push(value);
+            }
+        }
+        let center = self.pending_start as f64 + self.window_len as f64 / 2.0;
+        self.table.times.push(center / self.sampling_rate);
+        self.table.values.push(row);
+        self.table.partial.push(partial);
+    }
+}
+
+/// Computes features of a multi-channel signal over sliding windows
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel
+/// * `names` - The name of each channel, used as the prefix of its columns
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `window` - The length of each window in seconds
+/// * `step` - The time between the starts of consecutive windows in seconds
+/// * `features` - The features computed over each window
+/// * `partial` - What to do with the windows that extend past the end of the signal
+///
+/// # Returns
+///
+/// The FeatureTable, or an error if the parameters are invalid or the channels and names
+/// do not match
+///
+/// # Examples
+///
+/// ```
+/// let features = [Feature::LineLength, Feature::Variance, Feature::SpectralEdge(0.9)];
+/// let table = sliding(&channels, &names, 256.0, 2.0, 1.0, &features, PartialWindow::Drop)?;
+/// let line_length = table.column("T3_line_length");
+/// ```
+///
+/// # Note
+///
+/// Use `SlidingFeatures` to process a recording that does not fit in memory. The windows
+/// are computed in parallel and give the same table as `SlidingFeatures`.
+///
+pub fn sliding(
+    channels: &[Vec<f64>],
+    names: &[String],
+    sampling_rate: f64,
+    window: f64,
+    step: f64,
+    features: &[Feature],
+    partial: PartialWindow,
+) -> Result<FeatureTable, ProcessingError> {
+    let mut builder = SlidingFeatures::new(names, sampling_rate, window, step, features, partial)?;
+    let length = channels.first().map_or(0, |channel| channel.len());
+    if channels.len() != names.len() || channels.iter().any(|channel| channel.len() != length) || channels.is_empty() || length < builder.window_len {
+        builder.push(channels)?;
+        return Ok(builder.finish());
+    }
+
+    let n_full = (length - builder.window_len) / builder.step + 1;
+    let group_len = n_full.div_ceil(worker_count(n_full) * 4);
+    let n_groups = n_full.div_ceil(group_len);
+    let groups = try_map_tasks(vec![(); worker_count(n_groups)], n_groups, |_, group| {
+        let first = group * group_len;
+        let last = n_groups - 1 == group;
+        let partial = if last { partial } else { PartialWindow::Drop };
+        let mut builder = SlidingFeatures::new(names, sampling_rate, window, step, features, partial)?;
+        let start = first * builder.step;
+        let end = if last { length } else { (first + group_len - 1) * builder.step + builder.window_len };
+        builder.pending_start = start;
+        builder.push(&channels.iter().map(|channel| channel[start..end].to_vec()).collect::<Vec<_>>())?;
+        Ok(builder.finish())
+    })
+    .map_err(|(_, error)| error)?;
+
+    for group in groups {
+        builder.table.times.extend(group.times);
+        builder.table.values.extend(group.values);
+        builder.table.partial.extend(group.partial);
+    }
+    Ok(builder.table)
+}
+
+/// Computes a time-domain feature of a window
+fn feature_value(feature: Feature, window: &[f64]) -> f64 {
+    let n = window.len() as f64;
+    match feature {
+        Feature::Rms => (window.iter().map(|x| x * x).sum::<f64>() / n).sqrt(),
+        Feature::Variance => {
+            let mean = window.iter().sum::<f64>() / n;
+            window.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n
+        }
+        Feature::LineLength => window.windows(2).fold(0.0, |total, pair| total + (pair[1] - pair[0]).abs()),
+        Feature::ZeroCrossings => window.windows(2).filter(|pair| (pair[0] >= 0.0) != (pair[1] >= 0.0)).count() as f64,
+        Feature::PeakToPeak => {
+            let (low, high) = window.iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(low, high), &x| (low.min(x), high.max(x)));
+            high - low
+        }
+        Feature::SpectralEdge(_) => unreachable!("The spectral edge is computed from a periodogram"),
+    }
+}
+
+/// Returns the lowest frequency below which `fraction` of the power of the window lies, or NaN for a constant window
+fn spectral_edge(periodogram: &mut SegmentPeriodogram, window: &[f64], fraction: f64) -> f64 {
+    let mut power = vec![0.0; periodogram.n_bins()];
+    periodogram.accumulate(window, &mut power);
+    let total: f64 = power.iter().sum();
+    if total.is_nan() || total <= 0.0 {
+        return f64::NAN;
+    }
+    let frequencies = periodogram.frequencies();
+    let mut cumulative = 0.0;
+    for (frequency, value) in frequencies.iter().zip(&power) {
+        cumulative += value;
+        if cumulative >= fraction * total * (1.0 - 1e-12) {
+            return *frequency;
+        }
+    }
+    frequencies[frequencies.len() - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    const TIME_FEATURES: [Feature; 5] = [Feature::Rms, Feature::Variance, Feature::LineLength, Feature::ZeroCrossings, Feature::PeakToPeak];
+
+    fn names(n: usize) -> Vec<String> {
+        (1..=n).map(|k| format!("ch{}", k)).collect()
+    }
+
+    #[test]
+    fn features_of_a_tiny_signal_match_the_hand_computed_values() {
+        // Windows of 4 samples every 2 samples at 4 Hz
+        let samples = vec![vec![1.0, -1.0, 2.0, -2.0, 3.0, 0.0, -3.0, 1.0]];
+        let table = sliding(&samples, &names(1), 4.0, 1.0, 0.5, &TIME_FEATURES, PartialWindow::Flag).unwrap();
+        assert_eq!(table.columns, vec!["ch1_rms", "ch1_variance", "ch1_line_length", "ch1_zero_crossings", "ch1_peak_to_peak"]);
+        assert_eq!(table.times, vec![0.5, 1.0, 1.5, 2.0]);
+        assert_eq!(table.partial, vec![false, false, false, true]);
+        let expected = [
+            // [1, -1, 2, -2]
+            [2.5f64.sqrt(), 2.5, 9.0, 3.0, 4.0],
+            // [2, -2, 3, 0], where 3 -> 0 does not cross since 0 counts as positive
+            [4.25f64.sqrt(), 3.6875, 12.0, 2.0, 5.0],
+            // [3, 0, -3, 1]
+            [4.75f64.sqrt(), 4.6875, 10.0, 2.0, 6.0],
+            // The partial window [-3, 1]
+            [5f64.sqrt(), 4.0, 4.0, 1.0, 4.0],
+        ];
+        for (row, expected) in table.values.iter().zip(expected) {
+            assert!(row.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-12), "{:?} vs {:?}", row, expected);
+        }
+
+        let dropped = sliding(&samples, &names(1), 4.0, 1.0, 0.5, &TIME_FEATURES, PartialWindow::Drop).unwrap();
+        assert_eq!(dropped.values, table.values[..3].to_vec());
+        assert_eq!(dropped.partial, vec![false; 3]);
+        // A signal shorter than one window is dropped entirely, or flagged as the partial
+        // windows starting at samples 0 and 2
+        let short = vec![samples[0][..3].to_vec()];
+        assert!(sliding(&short, &names(1), 4.0, 1.0, 0.5, &TIME_FEATURES, PartialWindow::Drop).unwrap().values.is_empty());
+        let flagged = sliding(&short, &names(1), 4.0, 1.0, 0.5, &TIME_FEATURES, PartialWindow::Flag).unwrap();
+        assert_eq!((flagged.times.clone(), flagged.partial.clone()), (vec![0.5, 1.0], vec![true, true]));
+        assert_eq!(flagged.values[1], vec![2.0, 0.0, 0.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn the_spectral_edge_frequency_follows_the_power_distribution() {
+        assert_eq!(Feature::SpectralEdge(0.9).name(), "sef90");
+        assert_eq!(Feature::SpectralEdge(0.525).name(), "sef52.5");
+        let sine: Vec<f64> = (0..2560).map(|k| (2.0 * std::f64::consts::PI * 10.0 * k as f64 / 256.0).sin()).collect();
+        let features = [Feature::SpectralEdge(0.5), Feature::SpectralEdge(0.9)];
+        let table = sliding(&[sine], &names(1), 256.0, 1.0, 1.0, &features, PartialWindow::Drop).unwrap();
+        // A Hann window spreads a bin-centered sine over 9, 10 and 11 Hz with powers 1/4, 1 and 1/4
+        assert!(table.column("ch1_sef50").unwrap().iter().all(|&edge| edge == 10.0));
+        assert!(table.column("ch1_sef90").unwrap().iter().all(|&edge| edge == 11.0));
+
+        // White noise has half of its power below a quarter of the sampling rate
+        let mut rng = SeededRng::new(1);
+        let noise: Vec<f64> = (0..256 * 60).map(|_| rng.next_gaussian()).collect();
+        let table = sliding(&[noise], &names(1), 256.0, 4.0, 4.0, &[Feature::SpectralEdge(0.5)], PartialWindow::Drop).unwrap();
+        let edges = table.column("ch1_sef50").unwrap();
+        let mean = edges.iter().sum::<f64>() / edges.len() as f64;
+        assert!((mean - 64.0).abs() < 3.0, "{}", mean);
+
+        let constant = vec![vec![2.0; 512]];
+        assert!(sliding(&constant, &names(1), 256.0, 1.0, 1.0, &[Feature::SpectralEdge(0.9)], PartialWindow::Drop).unwrap().values[0][0].is_nan());
+    }
+
+    #[test]
+    fn chunked_streaming_matches_the_parallel_table_on_several_channels() {
+        let mut rng = SeededRng::new(2);
+        let channels: Vec<Vec<f64>> = (0..3).map(|_| (0..10_037).map(|_| rng.next_gaussian()).collect()).collect();
+        let features = [Feature::Rms, Feature::LineLength, Feature::ZeroCrossings, Feature::SpectralEdge(0.9)];
+        // Overlapping windows, adjacent windows, and steps longer than the windows
+        for (window, step) in [(1.0, 0.25), (0.5, 0.5), (0.2, 0.35)] {
+            for partial in [PartialWindow::Drop, PartialWindow::Flag] {
+                let table = sliding(&channels, &names(3), 200.0, window, step, &features, partial).unwrap();
+                assert_eq!(table.columns.len(), 12);
+                assert_eq!(table.columns[4], "ch2_rms");
+                let mut builder = SlidingFeatures::new(&names(3), 200.0, window, step, &features, partial).unwrap();
+                let mut start = 0;
+                while start < 10_037 {
+                    let end = (start + 1 + rng.next_index(150)).min(10_037);
+                    builder.push(&channels.iter().map(|channel| channel[start..end].to_vec()).collect::<Vec<_>>()).unwrap();
+                    start = end;
+                }
+                let streamed = builder.finish();
+                assert_eq!(streamed.times, table.times, "{} {} {:?}", window, step, partial);
+                assert_eq!(streamed.partial, table.partial);
+                for (a, b) in streamed.values.iter().flatten().zip(table.values.iter().flatten()) {
+                    assert!((a - b).abs() < 1e-9 * b.abs().max(1.0));
+                }
+                let step_samples = (step * 200.0f64).round() as usize;
+                let window_samples = (window * 200.0f64).round() as usize;
+                let n_full = (10_037 - window_samples) / step_samples + 1;
+                assert_eq!(table.partial.iter().filter(|partial| !**partial).count(), n_full);
+                assert!((table.times[1] - table.times[0] - step).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn tables_export_to_csv_and_invalid_options_are_errors() {
+        let samples = vec![vec![0.0, 1.0, 0.0, -1.0, 0.0], vec![1.0, 1.0, 1.0, 1.0, 1.0]];
+        let table = sliding(&samples, &["Fz".to_string(), "Cz".to_string()], 2.0, 1.0, 1.0, &[Feature::PeakToPeak], PartialWindow::Flag).unwrap();
+        assert_eq!(table.column("Cz_peak_to_peak"), Some(vec![0.0, 0.0, 0.0]));
+        assert_eq!(table.column("Oz_peak_to_peak"), None);
+
+        let path = std::env::temp_dir().join(format!("neurorust-features-{}-table.csv", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        table.to_csv(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines, vec!["time,Fz_peak_to_peak,Cz_peak_to_peak,partial", "0.5,1,0,false", "1.5,1,0,false", "2.5,0,0,true"]);
+
+        let names = names(2);
+        assert!(SlidingFeatures::new(&names, 2.0, 0.1, 1.0, &[Feature::Rms], PartialWindow::Drop).is_err());
+        assert!(SlidingFeatures::new(&names, 2.0, 1.0, 0.1, &[Feature::Rms], PartialWindow::Drop).is_err());
+        assert!(SlidingFeatures::new(&names, 2.0, 1.0, 1.0, &[], PartialWindow::Drop).is_err());
+        assert!(SlidingFeatures::new(&names, 2.0, 1.0, 1.0, &[Feature::SpectralEdge(0.0)], PartialWindow::Drop).is_err());
+        assert!(SlidingFeatures::new(&names, 2.0, 1.0, 1.0, &[Feature::SpectralEdge(1.5)], PartialWindow::Drop).is_err());
+        assert!(SlidingFeatures::new(&names, 0.0, 1.0, 1.0, &[Feature::Rms], PartialWindow::Drop).is_err());
+        let mut builder = SlidingFeatures::new(&names, 2.0, 1.0, 1.0, &[Feature::Rms], PartialWindow::Drop).unwrap();
+        assert!(builder.push(&samples[..1]).is_err());
+        assert!(builder.push(&[vec![1.0, 2.0], vec![1.0]]).is_err());
+    }
+}
@@ -0,0 +1,1472 @@
+// A module to design and apply digital filters
+
+// Written by Amin Alam in 2024
+
+use std::f64::consts::PI;
+use num_complex::Complex64;
+use crate::processing::convolution::{convolve, ConvMode};
+use crate::processing::error::ProcessingError;
+use crate::processing::window::Window;
+
+/// The frequency band that a filter passes
+///
+/// # Arguments
+///
+/// * `Lowpass` - Passes frequencies below the cutoff in Hz
+/// * `Highpass` - Passes frequencies above the cutoff in Hz
+/// * `Bandpass` - Passes frequencies between the low and high cutoffs in Hz
+///
+/// # Examples
+///
+/// ```
+/// let kind = FilterKind::Bandpass(4.0, 8.0);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum FilterKind {
+    Lowpass(f64),
+    Highpass(f64),
+    Bandpass(f64, f64),
+}
+
+/// An IIR filter stored as a cascade of second-order sections
+///
+/// # Arguments
+///
+/// * `sections` - The second-order sections, each laid out as `[b0, b1, b2, a0, a1, a2]`
+/// * `sampling_rate` - The sampling rate in Hz that the filter was designed for
+///
+/// # Examples
+///
+/// ```
+/// let filter = butterworth(4, FilterKind::Lowpass(40.0), 1000.0)?;
+/// let filtered = filter.apply(&samples);
+/// ```
+///
+/// # Note
+///
+/// The section layout matches the `sos` arrays of scipy.signal, so coefficients can be
+/// compared directly. The filter state is kept in f64 regardless of the input precision.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct IirFilter {
+    sections: Vec<[f64; 6]>,
+    sampling_rate: f64,
+}
+
+/// Implementation of the IirFilter struct
+///
+/// # Methods
+///
+/// * `new` - Creates an IirFilter from second-order sections
+/// * `cascade` - Chains two filters into one
+/// * `sections` - Returns the second-order sections
+/// * `sampling_rate` - Returns the sampling rate the filter was designed for
+/// * `order` - Returns the order of the filter
+/// * `frequency_response` - Evaluates the complex response at a frequency
+/// * `magnitude_response` - Evaluates the gain at a frequency
+/// * `apply` - Filters a signal
+/// * `apply_in_place` - Filters a signal in place
+/// * `apply_channels` - Filters several channels in parallel
+/// * `padding_length` - Returns the edge padding used by `filtfilt`
+/// * `impulse_response_length` - Estimates how long the impulse response lasts from the poles
+/// * `filtfilt` - Filters a signal forwards and backwards for zero phase
+/// * `filtfilt_channels` - Filters several channels forwards and backwards in parallel
+impl IirFilter {
+    /// Creates an IirFilter from second-order sections
+    ///
+    /// # Arguments
+    ///
+    /// * `sections` - The second-order sections, each laid out as `[b0, b1, b2, a0, a1, a2]`
+    /// * `sampling_rate` - The sampling rate in Hz that the filter was designed for
+    ///
+    /// # Returns
+    ///
+    /// The IirFilter, or an error if a section has `a0 == 0` or the sampling rate is not positive
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let filter = IirFilter::new(vec![[0.5, 0.5, 0.0, 1.0, 0.0, 0.0]], 1000.0)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Sections are normalized so that `a0 == 1`
+    ///
+    pub fn new(sections: Vec<[f64; 6]>, sampling_rate: f64) -> Result<Self, ProcessingError> {
+        validate_sampling_rate(sampling_rate)?;
+        let mut normalized = Vec::with_capacity(sections.len());
+        for section in sections {
+            let a0 = section[3];
+            if a0 == 0.0 || !a0.is_finite() {
+                return Err(ProcessingError::InvalidParameter(format!(
+                    "Second-order section {:?} has an invalid a0 coefficient",
+                    section
+                )));
+            }
+            normalized.push(section.map(|coefficient| coefficient / a0));
+        }
+        Ok(Self { sections: normalized, sampling_rate })
+    }
+
+    /// Chains two filters into one
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The filter applied after this one
+    ///
+    /// # Returns
+    ///
+    /// An IirFilter with the sections of both filters, or an error if their sampling rates differ
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let band = highpass.cascade(&lowpass)?;
+    /// ```
+    ///
+    pub fn cascade(&self, other: &IirFilter) -> Result<IirFilter, ProcessingError> {
+        if self.sampling_rate != other.sampling_rate {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Cannot cascade filters designed for {} Hz and {} Hz",
+                self.sampling_rate, other.sampling_rate
+            )));
+        }
+        let mut sections = self.sections.clone();
+        sections.extend_from_slice(&other.sections);
+        Ok(IirFilter { sections, sampling_rate: self.sampling_rate })
+    }
+
+    /// Returns the second-order sections
+    ///
+    /// # Returns
+    ///
+    /// The sections, each laid out as `[b0, b1, b2, a0, a1, a2]`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let sos = filter.sections();
+    /// ```
+    ///
+    pub fn sections(&self) -> &[[f64; 6]] {
+        &self.sections
+    }
+
+    /// Returns the sampling rate the filter was designed for
+    ///
+    /// # Returns
+    ///
+    /// The sampling rate in Hz
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let fs = filter.sampling_rate();
+    /// ```
+    ///
+    pub fn sampling_rate(&self) -> f64 {
+        self.sampling_rate
+    }
+
+    /// Returns the order of the filter
+    ///
+    /// # Returns
+    ///
+    /// The number of poles of the cascade
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(butterworth(4, FilterKind::Bandpass(4.0, 8.0), 250.0)?.order(), 8);
+    /// ```
+    ///
+    pub fn order(&self) -> usize {
+        self.sections
+            .iter()
+            .map(|section| if section[5] != 0.0 || section[2] != 0.0 { 2 } else { 1 })
+            .sum()
+    }
+
+    /// Evaluates the complex frequency response at a frequency
+    ///
+    /// # Arguments
+    ///
+    /// * `frequency` - The frequency in Hz
+    ///
+    /// # Returns
+    ///
+    /// The complex response of the cascade at the frequency
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let phase = filter.frequency_response(10.0).arg();
+    /// ```
+    ///
+    pub fn frequency_response(&self, frequency: f64) -> Complex64 {
+        let z_inv = Complex64::from_polar(1.0, -2.0 * PI * frequency / self.sampling_rate);
+        let z_inv2 = z_inv * z_inv;
+        self.sections.iter().fold(Complex64::new(1.0, 0.0), |response, s| {
+            let numerator = s[0] + z_inv * s[1] + z_inv2 * s[2];
+            let denominator = s[3] + z_inv * s[4] + z_inv2 * s[5];
+            response * numerator / denominator
+        })
+    }
+
+    /// Evaluates the gain at a frequency
+    ///
+    /// # Arguments
+    ///
+    /// * `frequency` - The frequency in Hz
+    ///
+    /// # Returns
+    ///
+    /// The magnitude of the frequency response (1.0 means unchanged amplitude)
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let attenuation_db = 20.0 * filter.magnitude_response(100.0).log10();
+    /// ```
+    ///
+    pub fn magnitude_response(&self, frequency: f64) -> f64 {
+        self.frequency_response(frequency).norm()
+    }
+
+    /// Filters a signal
+    ///
+    /// # Arguments
+    ///
+    /// * `samples` - The samples of the signal
+    ///
+    /// # Returns
+    ///
+    /// The filtered samples, starting from a zero filter state
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let filtered = filter.apply(&samples);
+    /// ```
+    ///
+    pub fn apply(&self, samples: &[f64]) -> Vec<f64> {
+        let mut filtered = samples.to_vec();
+        self.apply_in_place(&mut filtered);
+        filtered
+    }
+
+    /// Filters a signal in place
+    ///
+    /// # Arguments
+    ///
+    /// * `samples` - The samples of the signal, overwritten with the filtered samples
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// filter.apply_in_place(&mut samples);
+    /// ```
+    ///
+    pub fn apply_in_place(&self, samples: &mut [f64]) {
+        for section in &self.sections {
+            let mut state = [0.0; 2];
+            filter_section(section, &mut state, samples);
+        }
+    }
+
+    /// Filters several channels in parallel
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - The samples of each channel
+    ///
+    /// # Returns
+    ///
+    /// The filtered samples of each channel, in the input order
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let filtered = filter.apply_channels(&channels);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The channels are split across the available CPU cores
+    ///
+    pub fn apply_channels(&self, channels: &[Vec<f64>]) -> Vec<Vec<f64>> {
+        map_channels(channels, |channel| self.apply(channel))
+    }
+
+    /// Returns the edge padding used by `filtfilt`
+    ///
+    /// # Returns
+    ///
+    /// The number of samples reflected at each end, about three times the filter order
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let padding = filter.padding_length();
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The length matches the default `padlen` of scipy.signal.sosfiltfilt
+    ///
+    pub fn padding_length(&self) -> usize {
+        let zero_b2 = self.sections.iter().filter(|section| section[2] == 0.0).count();
+        let zero_a2 = self.sections.iter().filter(|section| section[5] == 0.0).count();
+        3 * (2 * self.sections.len() + 1 - zero_b2.min(zero_a2))
+    }
+
+    /// Estimates how long the impulse response lasts from the poles
+    ///
+    /// # Arguments
+    ///
+    /// * `tolerance` - The fraction of its initial size below which a pole's response counts as gone, e.g. `1e-12`
+    ///
+    /// # Returns
+    ///
+    /// The number of samples, or None if a pole lies on or outside the unit circle and the
+    /// response never dies out
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let filter = butterworth(4, FilterKind::Highpass(0.5), 1000.0)?;
+    /// let overlap = filter.impulse_response_length(1e-12);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The response of a section with poles of radius `r` falls below `tolerance` after
+    /// `ln(tolerance) / ln(r)` samples, plus the two samples of its zeros. The lengths of the
+    /// sections are added, as the response of a cascade is the convolution of theirs, which
+    /// also covers the slower decay of repeated poles.
+    ///
+    pub fn impulse_response_length(&self, tolerance: f64) -> Option<usize> {
+        let mut length = 1.0;
+        for &[_, _, _, _, a1, a2] in &self.sections {
+            // The poles are the roots of z² + a1 z + a2
+            let discriminant = a1 * a1 - 4.0 * a2;
+            let radius = if discriminant < 0.0 { a2.sqrt() } else { (a1.abs() + discriminant.sqrt()) / 2.0 };
+            if radius.is_nan() || radius >= 1.0 {
+                return None;
+            }
+            length += if radius > 0.0 { (tolerance.ln() / radius.ln()).ceil() } else { 0.0 } + 2.0;
+        }
+        Some(length as usize)
+    }
+
+    /// Filters a signal forwards and backwards for zero phase
+    ///
+    /// # Arguments
+    ///
+    /// * `samples` - The samples of the signal
+    ///
+    /// # Returns
+    ///
+    /// The filtered samples with no phase shift, or an error if the signal is not longer
+    /// than the edge padding
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let filter = butterworth(4, FilterKind::Bandpass(1.0, 30.0), 500.0)?;
+    /// let erp = filter.filtfilt(&samples)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The signal is extended at both ends by odd reflection over `padding_length` samples,
+    /// and each pass starts from the steady-state filter state scaled by its first sample,
+    /// as in scipy.signal.sosfiltfilt. The magnitude response is squared by the two passes.
+    ///
+    pub fn filtfilt(&self, samples: &[f64]) -> Result<Vec<f64>, ProcessingError> {
+        self.filtfilt_window(samples, true, true)
+    }
+
+    /// Filters several channels forwards and backwards in parallel
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - The samples of each channel
+    ///
+    /// # Returns
+    ///
+    /// The zero-phase filtered samples of each channel, or the first error encountered
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let filtered = filter.filtfilt_channels(&channels)?;
+    /// ```
+    ///
+    pub fn filtfilt_channels(&self, channels: &[Vec<f64>]) -> Result<Vec<Vec<f64>>, ProcessingError> {
+        map_channels(channels, |channel| self.filtfilt(channel)).into_iter().collect()
+    }
+
+    /// Computes the per-section filter state reached after a long run of unit input
+    fn steady_state(&self) -> Vec<[f64; 2]> {
+        let mut scale = 1.0;
+        self.sections
+            .iter()
+            .map(|&[b0, b1, b2, _, a1, a2]| {
+                let gain = (b0 + b1 + b2) / (1.0 + a1 + a2);
+                let state = [scale * (gain - b0), scale * (b2 - a2 * gain)];
+                scale *= gain;
+                state
+            })
+            .collect()
+    }
+
+    /// Filters forwards and backwards like `filtfilt`, padding only the ends that are ends of the whole signal
+    ///
+    /// An end that is not padded is a cut inside a longer signal, where each pass starts from
+    /// the steady state of the first sample it meets. The output then differs from `filtfilt`
+    /// of the whole signal only within `impulse_response_length` samples of that end.
+    pub(crate) fn filtfilt_window(&self, samples: &[f64], pad_start: bool, pad_end: bool) -> Result<Vec<f64>, ProcessingError> {
+        let padding = self.padding_length();
+        if (pad_start || pad_end) && samples.len() <= padding {
+            return Err(ProcessingError::SignalTooShort { length: samples.len(), required: padding + 1 });
+        }
+        if samples.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let n = samples.len();
+        let first = samples[0];
+        let last = samples[n - 1];
+        let (before, after) = (if pad_start { padding } else { 0 }, if pad_end { padding } else { 0 });
+        let mut extended = Vec::with_capacity(n + before + after);
+        extended.extend((1..=before).rev().map(|i| 2.0 * first - samples[i]));
+        extended.extend_from_slice(samples);
+        extended.extend((1..=after).map(|i| 2.0 * last - samples[n - 1 - i]));
+
+        let steady_state = self.steady_state();
+        let start = extended[0];
+        self.apply_from_state(&mut extended, &steady_state, start);
+        extended.reverse();
+        let start = extended[0];
+        self.apply_from_state(&mut extended, &steady_state, start);
+        extended.reverse();
+
+        Ok(extended[before..before + n].to_vec())
+    }
+
+    /// Filters in place starting from the steady state scaled by `level`
+    fn apply_from_state(&self, samples: &mut [f64], steady_state: &[[f64; 2]], level: f64) {
+        for (section, state) in self.sections.iter().zip(steady_state) {
+            let mut state = [state[0] * level, state[1] * level];
+            filter_section(section, &mut state, samples);
+        }
+    }
+}
+
+/// Designs a Butterworth filter
+///
+/// # Arguments
+///
+/// * `order` - The order of the lowpass prototype (a bandpass has twice as many poles)
+/// * `kind` - The band that the filter passes, with cutoffs in Hz
+/// * `sampling_rate` - The sampling rate in Hz
+///
+/// # Returns
+///
+/// An IirFilter made of second-order sections, or an error if the order is zero or a
+/// cutoff is not strictly between zero and the Nyquist frequency
+///
+/// # Examples
+///
+/// ```
+/// let theta = butterworth(4, FilterKind::Bandpass(4.0, 8.0), 250.0)?;
+/// let filtered = theta.apply(&samples);
+/// ```
+///
+/// # Note
+///
+/// The design follows scipy.signal.butter: an analog prototype is frequency-transformed
+/// with pre-warped cutoffs and discretized with the bilinear transform. The gain is
+/// stored in the first section.
+///
+pub fn butterworth(order: usize, kind: FilterKind, sampling_rate: f64) -> Result<IirFilter, ProcessingError> {
+    if order == 0 {
+        return Err(ProcessingError::InvalidParameter("Filter order must be at least 1".to_string()));
+    }
+    validate_sampling_rate(sampling_rate)?;
+
+    // Poles of the analog lowpass prototype with a cutoff of 1 rad/s
+    let prototype: Vec<Complex64> = (0..order)
+        .map(|k| {
+            let m = 2 * k as i64 - order as i64 + 1;
+            if m == 0 {
+                Complex64::new(-1.0, 0.0)
+            } else {
+                -Complex64::from_polar(1.0, PI * m as f64 / (2 * order) as f64)
+            }
+        })
+        .collect();
+
+    let (zeros, poles, gain) = match kind {
+        FilterKind::Lowpass(cutoff) => {
+            let warped = prewarp(cutoff, sampling_rate)?;
+            let poles: Vec<Complex64> = prototype.iter().map(|p| p * warped).collect();
+            (Vec::new(), poles, warped.powi(order as i32))
+        }
+        FilterKind::Highpass(cutoff) => {
+            let warped = prewarp(cutoff, sampling_rate)?;
+            let poles: Vec<Complex64> = prototype.iter().map(|p| warped / p).collect();
+            let gain = (Complex64::new(1.0, 0.0) / prototype.iter().map(|p| -p).product::<Complex64>()).re;
+            (vec![Complex64::new(0.0, 0.0); order], poles, gain)
+        }
+        FilterKind::Bandpass(low, high) => {
+            if low >= high {
+                return Err(ProcessingError::InvalidParameter(format!(
+                    "Bandpass low cutoff {} Hz must be below the high cutoff {} Hz",
+                    low, high
+                )));
+            }
+            let warped_low = prewarp(low, sampling_rate)?;
+            let warped_high = prewarp(high, sampling_rate)?;
+            let bandwidth = warped_high - warped_low;
+            let center = (warped_low * warped_high).sqrt();
+            let mut poles = Vec::with_capacity(2 * order);
+            for p in &prototype {
+                let scaled = p * bandwidth / 2.0;
+                let offset = (scaled * scaled - center * center).sqrt();
+                poles.push(scaled + offset);
+                poles.push(scaled - offset);
+            }
+            (vec![Complex64::new(0.0, 0.0); order], poles, bandwidth.powi(order as i32))
+        }
+    };
+
+    let (zeros, poles, gain) = bilinear(&zeros, &poles, gain, sampling_rate);
+    IirFilter::new(zpk_to_sections(&zeros, &poles, gain), sampling_rate)
+}
+
+/// A linear-phase FIR filter
+///
+/// # Arguments
+///
+/// * `taps` - The filter coefficients (the impulse response)
+/// * `sampling_rate` - The sampling rate in Hz that the filter was designed for
+///
+/// # Examples
+///
+/// ```
+/// let filter = fir_design(101, FilterKind::Lowpass(40.0), Window::Hamming, 1000.0)?;
+/// let delay = filter.group_delay_seconds();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FirFilter {
+    taps: Vec<f64>,
+    sampling_rate: f64,
+}
+
+/// Implementation of the FirFilter struct
+///
+/// # Methods
+///
+/// * `new` - Creates an FirFilter from arbitrary taps
+/// * `taps` - Returns the filter coefficients
+/// * `sampling_rate` - Returns the sampling rate the filter was designed for
+/// * `group_delay` - Returns the delay of a linear-phase filter in samples
+/// * `group_delay_seconds` - Returns the delay of a linear-phase filter in seconds
+/// * `magnitude_response` - Evaluates the gain at a frequency
+/// * `apply` - Filters a signal causally
+/// * `apply_zero_delay` - Filters a signal and removes the group delay
+impl FirFilter {
+    /// Creates an FirFilter from arbitrary taps
+    ///
+    /// # Arguments
+    ///
+    /// * `taps` - The filter coefficients, e.g. a stimulus artifact template for matched filtering
+    /// * `sampling_rate` - The sampling rate in Hz
+    ///
+    /// # Returns
+    ///
+    /// The FirFilter, or an error if there are no taps or the sampling rate is not positive
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let matched = FirFilter::new(template.iter().rev().copied().collect(), 30000.0)?;
+    /// ```
+    ///
+    pub fn new(taps: Vec<f64>, sampling_rate: f64) -> Result<Self, ProcessingError> {
+        validate_sampling_rate(sampling_rate)?;
+        if taps.is_empty() {
+            return Err(ProcessingError::InvalidParameter("An FIR filter needs at least one tap".to_string()));
+        }
+        Ok(Self { taps, sampling_rate })
+    }
+
+    /// Returns the filter coefficients
+    ///
+    /// # Returns
+    ///
+    /// The taps of the filter
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let taps = filter.taps();
+    /// ```
+    ///
+    pub fn taps(&self) -> &[f64] {
+        &self.taps
+    }
+
+    /// Returns the sampling rate the filter was designed for
+    ///
+    /// # Returns
+    ///
+    /// The sampling rate in Hz
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let fs = filter.sampling_rate();
+    /// ```
+    ///
+    pub fn sampling_rate(&self) -> f64 {
+        self.sampling_rate
+    }
+
+    /// Returns the group delay of the filter in samples
+    ///
+    /// # Returns
+    ///
+    /// `(n_taps - 1) / 2`, which is exact for the symmetric taps of `fir_design`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let shift = filter.group_delay();
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Custom taps that are not symmetric do not have a constant group delay
+    ///
+    pub fn group_delay(&self) -> f64 {
+        (self.taps.len() - 1) as f64 / 2.0
+    }
+
+    /// Returns the group delay of the filter in seconds
+    ///
+    /// # Returns
+    ///
+    /// The group delay divided by the sampling rate, to be subtracted from output timestamps
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let corrected_times: Vec<f64> = times.iter().map(|t| t - filter.group_delay_seconds()).collect();
+    /// ```
+    ///
+    pub fn group_delay_seconds(&self) -> f64 {
+        self.group_delay() / self.sampling_rate
+    }
+
+    /// Evaluates the gain at a frequency
+    ///
+    /// # Arguments
+    ///
+    /// * `frequency` - The frequency in Hz
+    ///
+    /// # Returns
+    ///
+    /// The magnitude of the frequency response
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let gain = filter.magnitude_response(10.0);
+    /// ```
+    ///
+    pub fn magnitude_response(&self, frequency: f64) -> f64 {
+        let omega = -2.0 * PI * frequency / self.sampling_rate;
+        self.taps
+            .iter()
+            .enumerate()
+            .map(|(n, &tap)| Complex64::from_polar(tap, omega * n as f64))
+            .sum::<Complex64>()
+            .norm()
+    }
+
+    /// Filters a signal causally
+    ///
+    /// # Arguments
+    ///
+    /// * `samples` - The samples of the signal
+    ///
+    /// # Returns
+    ///
+    /// The filtered samples, delayed by `group_delay` samples and of the same length as the input
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let filtered = filter.apply(&samples);
+    /// ```
+    ///
+    pub fn apply(&self, samples: &[f64]) -> Vec<f64> {
+        let mut filtered = convolve(samples, &self.taps, ConvMode::Full);
+        filtered.truncate(samples.len());
+        filtered
+    }
+
+    /// Filters a signal and removes the group delay
+    ///
+    /// # Arguments
+    ///
+    /// * `samples` - The samples of the signal
+    ///
+    /// # Returns
+    ///
+    /// The filtered samples aligned with the input, of the same length as the input
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let filtered = filter.apply_zero_delay(&samples);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// For an even number of taps the delay is half a sample more than what is removed
+    ///
+    pub fn apply_zero_delay(&self, samples: &[f64]) -> Vec<f64> {
+        let full = convolve(samples, &self.taps, ConvMode::Full);
+        let shift = (self.taps.len() - 1) / 2;
+        full.into_iter().skip(shift).take(samples.len()).collect()
+    }
+}
+
+/// Designs a linear-phase FIR filter with the windowed-sinc method
+///
+/// # Arguments
+///
+/// * `n_taps` - The number of coefficients of the filter
+/// * `kind` - The band that the filter passes, with cutoffs in Hz
+/// * `window` - The window that tapers the ideal sinc response
+/// * `sampling_rate` - The sampling rate in Hz
+///
+/// # Returns
+///
+/// The FirFilter, or an error if a cutoff is not strictly between zero and the Nyquist
+/// frequency, or if a highpass filter is requested with an even number of taps
+///
+/// # Examples
+///
+/// ```
+/// let filter = fir_design(255, FilterKind::Bandpass(300.0, 3000.0), Window::Hamming, 30000.0)?;
+/// ```
+///
+/// # Note
+///
+/// The taps are scaled to unit gain at DC for lowpass filters, at the Nyquist frequency for
+/// highpass filters and at the band center for bandpass filters, which matches
+/// scipy.signal.firwin with `scale=True`
+///
+pub fn fir_design(n_taps: usize, kind: FilterKind, window: Window, sampling_rate: f64) -> Result<FirFilter, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    if n_taps == 0 {
+        return Err(ProcessingError::InvalidParameter("An FIR filter needs at least one tap".to_string()));
+    }
+    let nyquist = sampling_rate / 2.0;
+    let (left, right, scale_frequency) = match kind {
+        FilterKind::Lowpass(cutoff) => {
+            validate_frequency(cutoff, sampling_rate)?;
+            (0.0, cutoff / nyquist, 0.0)
+        }
+        FilterKind::Highpass(cutoff) => {
+            validate_frequency(cutoff, sampling_rate)?;
+            if n_taps.is_multiple_of(2) {
+                return Err(ProcessingError::InvalidParameter(format!(
+                    "A highpass FIR filter needs an odd number of taps, got {}",
+                    n_taps
+                )));
+            }
+            (cutoff / nyquist, 1.0, 1.0)
+        }
+        FilterKind::Bandpass(low, high) => {
+            validate_frequency(low, sampling_rate)?;
+            validate_frequency(high, sampling_rate)?;
+            if low >= high {
+                return Err(ProcessingError::InvalidParameter(format!(
+                    "Bandpass low cutoff {} Hz must be below the high cutoff {} Hz",
+                    low, high
+                )));
+            }
+            let (left, right) = (low / nyquist, high / nyquist);
+            (left, right, (left + right) / 2.0)
+        }
+    };
+
+    let center = (n_taps - 1) as f64 / 2.0;
+    let taper = window.symmetric(n_taps);
+    let mut taps: Vec<f64> = (0..n_taps)
+        .map(|n| {
+            let m = n as f64 - center;
+            (right * sinc(right * m) - left * sinc(left * m)) * taper[n]
+        })
+        .collect();
+
+    let gain: f64 = taps
+        .iter()
+        .enumerate()
+        .map(|(n, tap)| tap * (PI * (n as f64 - center) * scale_frequency).cos())
+        .sum();
+    for tap in taps.iter_mut() {
+        *tap /= gain;
+    }
+    FirFilter::new(taps, sampling_rate)
+}
+
+/// Returns the normalized sinc function, sin(pi x) / (pi x)
+pub(crate) fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// The quality factor used by `remove_line_noise`
+pub const DEFAULT_NOTCH_Q: f64 = 30.0;
+
+/// Designs a second-order notch filter
+///
+/// # Arguments
+///
+/// * `frequency` - The frequency to remove in Hz
+/// * `q_factor` - The quality factor, i.e. the notch frequency divided by the -3 dB bandwidth
+/// * `sampling_rate` - The sampling rate in Hz
+///
+/// # Returns
+///
+/// An IirFilter with a single section, or an error if the frequency is not strictly between
+/// zero and the Nyquist frequency or the quality factor is not positive
+///
+/// # Examples
+///
+/// ```
+/// // Removes 50 Hz with a -3 dB bandwidth of 50 / 30 = 1.67 Hz
+/// let notch = notch(50.0, 30.0, 1000.0)?;
+/// ```
+///
+/// # Note
+///
+/// The quality factor translates to a bandwidth in Hz as `frequency / q_factor`: a notch at
+/// 50 Hz with `q_factor = 30` attenuates 49.17 Hz to 50.83 Hz by at least 3 dB. The design
+/// matches scipy.signal.iirnotch.
+///
+pub fn notch(frequency: f64, q_factor: f64, sampling_rate: f64) -> Result<IirFilter, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    validate_frequency(frequency, sampling_rate)?;
+    if !(q_factor > 0.0 && q_factor.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Notch quality factor must be positive, got {}",
+            q_factor
+        )));
+    }
+
+    let center = 2.0 * PI * frequency / sampling_rate;
+    let bandwidth = center / q_factor;
+    let gain = 1.0 / (1.0 + (bandwidth / 2.0).tan());
+    let cos_center = center.cos();
+    IirFilter::new(
+        vec![[gain, -2.0 * gain * cos_center, gain, 1.0, -2.0 * gain * cos_center, 2.0 * gain - 1.0]],
+        sampling_rate,
+    )
+}
+
+/// Designs a cascade of notch filters at a line frequency and its harmonics
+///
+/// # Arguments
+///
+/// * `base_frequency` - The line frequency in Hz (usually 50 or 60)
+/// * `n_harmonics` - The number of multiples of the line frequency to remove, including the line frequency itself
+/// * `q_factor` - The quality factor of each notch
+/// * `sampling_rate` - The sampling rate in Hz
+///
+/// # Returns
+///
+/// An IirFilter with one notch per multiple below the Nyquist frequency
+///
+/// # Examples
+///
+/// ```
+/// // Notches at 50, 100 and 150 Hz
+/// let filter = line_noise_filter(50.0, 3, 30.0, 1000.0)?;
+/// ```
+///
+/// # Note
+///
+/// Every notch has the same quality factor, so the bandwidth in Hz grows with the harmonic
+///
+pub fn line_noise_filter(base_frequency: f64, n_harmonics: usize, q_factor: f64, sampling_rate: f64) -> Result<IirFilter, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    validate_frequency(base_frequency, sampling_rate)?;
+    if n_harmonics == 0 {
+        return Err(ProcessingError::InvalidParameter("At least one harmonic must be removed".to_string()));
+    }
+
+    let nyquist = sampling_rate / 2.0;
+    let mut sections = Vec::new();
+    for harmonic in 1..=n_harmonics {
+        let frequency = base_frequency * harmonic as f64;
+        if frequency >= nyquist {
+            break;
+        }
+        sections.extend_from_slice(notch(frequency, q_factor, sampling_rate)?.sections());
+    }
+    IirFilter::new(sections, sampling_rate)
+}
+
+/// Removes line noise and its harmonics with zero-phase notch filtering
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `base_frequency` - The line frequency in Hz (usually 50 or 60)
+/// * `n_harmonics` - The number of multiples of the line frequency to remove, including the line frequency itself
+///
+/// # Returns
+///
+/// The cleaned samples, or an error if the frequencies are invalid or the signal is too short
+///
+/// # Examples
+///
+/// ```
+/// let cleaned = remove_line_noise(&samples, 1000.0, 50.0, 3)?;
+/// ```
+///
+/// # Note
+///
+/// The notches use `DEFAULT_NOTCH_Q` (a -3 dB bandwidth of `base_frequency / 30` Hz for the
+/// fundamental) and are applied with `filtfilt`, which squares the response: the
+/// frequencies at the edges of that bandwidth are attenuated by 6 dB instead of 3 dB.
+/// Use `line_noise_filter` directly for another quality factor or causal filtering, and
+/// `remove_line_noise_clean` to leave the phase of the frequencies near the line intact.
+///
+pub fn remove_line_noise(samples: &[f64], sampling_rate: f64, base_frequency: f64, n_harmonics: usize) -> Result<Vec<f64>, ProcessingError> {
+    line_noise_filter(base_frequency, n_harmonics, DEFAULT_NOTCH_Q, sampling_rate)?.filtfilt(samples)
+}
+
+pub(crate) fn validate_sampling_rate(sampling_rate: f64) -> Result<(), ProcessingError> {
+    if sampling_rate > 0.0 && sampling_rate.is_finite() {
+        Ok(())
+    } else {
+        Err(ProcessingError::InvalidParameter(format!(
+            "Sampling rate must be positive, got {}",
+            sampling_rate
+        )))
+    }
+}
+
+pub(crate) fn validate_frequency(frequency: f64, sampling_rate: f64) -> Result<(), ProcessingError> {
+    let nyquist = sampling_rate / 2.0;
+    if frequency > 0.0 && frequency < nyquist {
+        Ok(())
+    } else {
+        Err(ProcessingError::InvalidFrequency { frequency, nyquist })
+    }
+}
+
+/// Runs one second-order section over the samples in transposed direct form II
+pub(crate) fn filter_section(section: &[f64; 6], state: &mut [f64; 2], samples: &mut [f64]) {
+    let [b0, b1, b2, _, a1, a2] = *section;
+    for sample in samples.iter_mut() {
+        let x = *sample;
+        let y = b0 * x + state[0];
+        state[0] = b1 * x - a1 * y + state[1];
+        state[1] = b2 * x - a2 * y;
+        *sample = y;
+    }
+}
+
+/// Applies `f` to every channel, splitting the channels across the available CPU cores
+pub(crate) fn map_channels<T, F>(channels: &[Vec<f64>], f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(&[f64]) -> T + Sync,
+{
+    let n_threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+    let chunk_size = channels.len().div_ceil(n_threads).max(1);
+    let f = &f;
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = channels
+            .chunks(chunk_size)
+            .map(|chunk| scope.spawn(move || chunk.iter().map(|channel| f(channel)).collect::<Vec<T>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("Channel worker thread panicked"))
+            .collect()
+    })
+}
+
+/// Maps a cutoff in Hz to the analog frequency that the bilinear transform maps back onto it
+fn prewarp(cutoff: f64, sampling_rate: f64) -> Result<f64, ProcessingError> {
+    validate_frequency(cutoff, sampling_rate)?;
+    Ok(2.0 * sampling_rate * (PI * cutoff / sampling_rate).tan())
+}
+
+fn bilinear(zeros: &[Complex64], poles: &[Complex64], gain: f64, sampling_rate: f64) -> (Vec<Complex64>, Vec<Complex64>, f64) {
+    let fs2 = 2.0 * sampling_rate;
+    let mut digital_zeros: Vec<Complex64> = zeros.iter().map(|z| (fs2 + z) / (fs2 - z)).collect();
+    let digital_poles: Vec<Complex64> = poles.iter().map(|p| (fs2 + p) / (fs2 - p)).collect();
+    digital_zeros.resize(digital_poles.len(), Complex64::new(-1.0, 0.0));
+
+    let numerator: Complex64 = zeros.iter().map(|z| fs2 - z).product();
+    let denominator: Complex64 = poles.iter().map(|p| fs2 - p).product();
+    (digital_zeros, digital_poles, gain * (numerator / denominator).re)
+}
+
+/// Groups real zeros and conjugate pole pairs into second-order sections
+///
+/// The sections are ordered with the poles closest to the unit circle last, and the gain
+/// is put into the first section.
+fn zpk_to_sections(zeros: &[Complex64], poles: &[Complex64], gain: f64) -> Vec<[f64; 6]> {
+    let tolerance = 1e-10;
+    let mut complex_poles: Vec<Complex64> = poles.iter().copied().filter(|p| p.im > tolerance).collect();
+    let mut real_poles: Vec<f64> = poles.iter().filter(|p| p.im.abs() <= tolerance).map(|p| p.re).collect();
+    complex_poles.sort_by(|a, b| a.norm().partial_cmp(&b.norm()).unwrap());
+    real_poles.sort_by(|a, b| a.abs().partial_cmp(&b.abs()).unwrap());
+
+    // Each entry holds the denominator polynomial, the number of poles and the largest pole radius
+    let mut denominators: Vec<([f64; 3], usize, f64)> = Vec::new();
+    for chunk in real_poles.chunks(2) {
+        match chunk {
+            [p1, p2] => denominators.push(([1.0, -(p1 + p2), p1 * p2], 2, p1.abs().max(p2.abs()))),
+            [p] => denominators.push(([1.0, -p, 0.0], 1, p.abs())),
+            _ => unreachable!(),
+        }
+    }
+    for p in &complex_poles {
+        denominators.push(([1.0, -2.0 * p.re, p.norm_sqr()], 2, p.norm()));
+    }
+    denominators.sort_by(|a, b| a.2.partial_cmp(&b.2).unwrap());
+
+    let mut real_zeros: Vec<f64> = zeros.iter().map(|z| z.re).collect();
+    real_zeros.sort_by(|a, b| a.partial_cmp(b).unwrap());
+    let mut zeros_iter = real_zeros.into_iter();
+
+    let mut sections = Vec::with_capacity(denominators.len());
+    for (denominator, n_poles, _) in denominators {
+        let numerator = if n_poles == 1 {
+            match zeros_iter.next() {
+                Some(z) => [1.0, -z, 0.0],
+                None => [1.0, 0.0, 0.0],
+            }
+        } else {
+            match (zeros_iter.next(), zeros_iter.next_back()) {
+                (Some(z1), Some(z2)) => [1.0, -(z1 + z2), z1 * z2],
+                (Some(z), None) | (None, Some(z)) => [1.0, -z, 0.0],
+                (None, None) => [1.0, 0.0, 0.0],
+            }
+        };
+        sections.push([numerator[0], numerator[1], numerator[2], denominator[0], denominator[1], denominator[2]]);
+    }
+
+    if let Some(first) = sections.first_mut() {
+        for coefficient in first.iter_mut().take(3) {
+            *coefficient *= gain;
+        }
+    }
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Multiplies the sections of a filter into the numerator and denominator of its transfer function
+    fn transfer_function(filter: &IirFilter) -> (Vec<f64>, Vec<f64>) {
+        let multiply = |p: &[f64], q: &[f64]| {
+            let mut product = vec![0.0; p.len() + q.len() - 1];
+            for (i, a) in p.iter().enumerate() {
+                for (j, b) in q.iter().enumerate() {
+                    product[i + j] += a * b;
+                }
+            }
+            product
+        };
+        filter.sections().iter().fold((vec![1.0], vec![1.0]), |(b, a), section| (multiply(&b, &section[..3]), multiply(&a, &section[3..])))
+    }
+
+    /// The magnitude of a digital Butterworth filter designed with pre-warped cutoffs and the bilinear transform
+    fn bilinear_butterworth_magnitude(order: usize, kind: FilterKind, sampling_rate: f64, frequency: f64) -> f64 {
+        let warp = |f: f64| (PI * f / sampling_rate).tan();
+        let omega = warp(frequency);
+        let ratio = match kind {
+            FilterKind::Lowpass(cutoff) => omega / warp(cutoff),
+            FilterKind::Highpass(cutoff) => warp(cutoff) / omega,
+            FilterKind::Bandpass(low, high) => (omega * omega - warp(low) * warp(high)) / (omega * (warp(high) - warp(low))),
+        };
+        1.0 / (1.0 + ratio.powi(2 * order as i32)).sqrt()
+    }
+
+    #[test]
+    fn butterworth_matches_the_bilinear_magnitude_at_every_order() {
+        for order in 1..=10 {
+            for kind in [FilterKind::Lowpass(40.0), FilterKind::Highpass(5.0), FilterKind::Bandpass(8.0, 12.0), FilterKind::Bandpass(300.0, 450.0)] {
+                let filter = butterworth(order, kind, 1000.0).unwrap();
+                assert!(filter.impulse_response_length(1e-12).is_some(), "order {} {:?} is unstable", order, kind);
+                for step in 1..1000 {
+                    let frequency = step as f64 * 0.4999;
+                    let expected = bilinear_butterworth_magnitude(order, kind, 1000.0, frequency);
+                    let actual = filter.magnitude_response(frequency);
+                    assert!((actual - expected).abs() < 1e-9, "order {} {:?} at {} Hz: {} vs {}", order, kind, frequency, actual, expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn butterworth_coefficients_match_scipy() {
+        // scipy.signal.butter(2, 0.5) and butter(4, 0.2), as scipy prints them to 8 digits;
+        // the first also follows in closed form from tan(pi / 4) = 1
+        let references: [(usize, f64, &[f64], &[f64]); 2] = [
+            (2, 250.0, &[0.29289322, 0.58578644, 0.29289322], &[1.0, 0.0, 0.17157288]),
+            (4, 100.0, &[0.00482434, 0.01929737, 0.02894606, 0.01929737, 0.00482434], &[1.0, -2.36951301, 2.31398841, -1.05466541, 0.18737949]),
+        ];
+        for (order, cutoff, b_reference, a_reference) in references {
+            let (b, a) = transfer_function(&butterworth(order, FilterKind::Lowpass(cutoff), 1000.0).unwrap());
+            for (actual, expected) in b.iter().zip(b_reference).chain(a.iter().zip(a_reference)) {
+                assert!((actual - expected).abs() < 5e-9, "order {}: {} vs {}", order, actual, expected);
+            }
+        }
+        let (b, a) = transfer_function(&butterworth(2, FilterKind::Lowpass(250.0), 1000.0).unwrap());
+        assert!((b[0] - 1.0 / (2.0 + 2f64.sqrt())).abs() < 1e-15 && (a[2] - (2.0 - 2f64.sqrt()) / (2.0 + 2f64.sqrt())).abs() < 1e-15);
+        // A highpass passes Nyquist and a bandpass of order 2 has four poles and four zeros
+        assert!((butterworth(3, FilterKind::Highpass(100.0), 1000.0).unwrap().magnitude_response(500.0) - 1.0).abs() < 1e-12);
+        let (b, a) = transfer_function(&butterworth(2, FilterKind::Bandpass(10.0, 20.0), 1000.0).unwrap());
+        assert_eq!((b.len(), a.len()), (5, 5));
+    }
+
+    #[test]
+    fn butterworth_rejects_cutoffs_at_or_above_nyquist() {
+        for kind in [FilterKind::Lowpass(500.0), FilterKind::Highpass(600.0), FilterKind::Bandpass(10.0, 500.0), FilterKind::Lowpass(0.0)] {
+            assert!(matches!(butterworth(4, kind, 1000.0), Err(ProcessingError::InvalidFrequency { nyquist, .. }) if nyquist == 500.0), "{:?}", kind);
+        }
+        assert!(matches!(butterworth(0, FilterKind::Lowpass(40.0), 1000.0), Err(ProcessingError::InvalidParameter(_))));
+        assert!(matches!(butterworth(2, FilterKind::Bandpass(20.0, 10.0), 1000.0), Err(ProcessingError::InvalidParameter(_))));
+        assert!(butterworth(2, FilterKind::Lowpass(40.0), 0.0).is_err());
+    }
+
+    #[test]
+    fn in_place_and_channel_parallel_filtering_match_apply() {
+        let mut rng = crate::processing::random::SeededRng::new(105);
+        let channels: Vec<Vec<f64>> = (0..5).map(|_| (0..3000).map(|_| rng.next_gaussian()).collect()).collect();
+        let filter = butterworth(6, FilterKind::Bandpass(30.0, 80.0), 1000.0).unwrap();
+        let parallel = filter.apply_channels(&channels);
+        for (channel, filtered) in channels.iter().zip(&parallel) {
+            let mut in_place = channel.clone();
+            filter.apply_in_place(&mut in_place);
+            assert_eq!(&in_place, filtered);
+            assert_eq!(&filter.apply(channel), filtered);
+        }
+        // A 50 Hz sine passes a 30-80 Hz band and a 5 Hz sine does not, once the transient is over
+        let sine = |frequency: f64| (0..3000).map(|k| (2.0 * PI * frequency * k as f64 / 1000.0).sin()).collect::<Vec<f64>>();
+        let rms = |samples: &[f64]| (samples[1000..].iter().map(|x| x * x).sum::<f64>() / 2000.0).sqrt();
+        assert!((rms(&filter.apply(&sine(50.0))) - 0.5f64.sqrt()).abs() < 1e-3);
+        assert!(rms(&filter.apply(&sine(5.0))) < 1e-5);
+    }
+
+    /// scipy.signal.sosfiltfilt with its default odd padding, written from its definition
+    ///
+    /// The initial state of each section solves the linear system of scipy.signal.lfilter_zi
+    /// instead of the closed form of `IirFilter::steady_state`.
+    fn reference_sosfiltfilt(sections: &[[f64; 6]], samples: &[f64], padding: usize) -> Vec<f64> {
+        let mut zi = Vec::new();
+        let mut scale = 1.0;
+        for &[b0, b1, b2, _, a1, a2] in sections {
+            // (I - companion(a)^T) zi = b[1..] - a[1..] b0, solved by Cramer's rule
+            let (m00, m01, m10, m11) = (1.0 + a1, -1.0, a2, 1.0);
+            let (r0, r1) = (b1 - a1 * b0, b2 - a2 * b0);
+            let determinant = m00 * m11 - m01 * m10;
+            zi.push([scale * (r0 * m11 - m01 * r1) / determinant, scale * (m00 * r1 - m10 * r0) / determinant]);
+            scale *= (b0 + b1 + b2) / (1.0 + a1 + a2);
+        }
+        let sosfilt = |x: &[f64], level: f64| {
+            let mut y = x.to_vec();
+            for (&[b0, b1, b2, _, a1, a2], z) in sections.iter().zip(&zi) {
+                let (mut z0, mut z1) = (z[0] * level, z[1] * level);
+                for value in y.iter_mut() {
+                    let input = *value;
+                    let output = b0 * input + z0;
+                    z0 = b1 * input - a1 * output + z1;
+                    z1 = b2 * input - a2 * output;
+                    *value = output;
+                }
+            }
+            y
+        };
+        let n = samples.len();
+        let mut extended: Vec<f64> = (0..padding).map(|i| 2.0 * samples[0] - samples[padding - i]).collect();
+        extended.extend_from_slice(samples);
+        extended.extend((0..padding).map(|i| 2.0 * samples[n - 1] - samples[n - 2 - i]));
+        let forward = sosfilt(&extended, extended[0]);
+        let mut backward: Vec<f64> = forward.into_iter().rev().collect();
+        backward = sosfilt(&backward, backward[0]);
+        backward.reverse();
+        backward[padding..padding + n].to_vec()
+    }
+
+    fn noise_and_sine(n: usize, seed: u64) -> Vec<f64> {
+        let mut rng = crate::processing::random::SeededRng::new(seed);
+        (0..n).map(|k| rng.next_gaussian() + 2.0 * (2.0 * PI * 10.0 * k as f64 / 250.0).sin()).collect()
+    }
+
+    #[test]
+    fn filtfilt_matches_scipy_sosfiltfilt_within_1e_8() {
+        let samples = noise_and_sine(2000, 106);
+        let filters = [
+            butterworth(4, FilterKind::Bandpass(1.0, 40.0), 250.0).unwrap(),
+            butterworth(8, FilterKind::Lowpass(30.0), 250.0).unwrap(),
+            butterworth(3, FilterKind::Highpass(0.5), 250.0).unwrap(),
+            notch(50.0, 30.0, 250.0).unwrap(),
+        ];
+        for filter in &filters {
+            let expected = reference_sosfiltfilt(filter.sections(), &samples, filter.padding_length());
+            let actual = filter.filtfilt(&samples).unwrap();
+            let error = actual.iter().zip(&expected).map(|(a, b)| (a - b).abs()).fold(0.0, f64::max);
+            assert!(error < 1e-8, "{} sections: largest difference {:e}", filter.sections().len(), error);
+        }
+        // scipy's default padlen for 4 sections with no zero coefficient is 3 * (2 * 4 + 1)
+        assert_eq!(filters[0].padding_length(), 27);
+        assert_eq!(butterworth(3, FilterKind::Lowpass(30.0), 250.0).unwrap().padding_length(), 3 * (2 * 2 + 1 - 1));
+    }
+
+    #[test]
+    fn filtfilt_has_no_phase_delay_and_keeps_constants() {
+        let sine: Vec<f64> = (0..2500).map(|k| (2.0 * PI * 10.0 * k as f64 / 250.0).sin()).collect();
+        let filter = butterworth(4, FilterKind::Bandpass(5.0, 20.0), 250.0).unwrap();
+        let gain = filter.magnitude_response(10.0).powi(2);
+        let filtered = filter.filtfilt(&sine).unwrap();
+        // The middle 4 s are far from the transients of the padding at both ends
+        let error = (750..1750).map(|k| (filtered[k] - gain * sine[k]).abs()).fold(0.0, f64::max);
+        assert!(error < 1e-6, "largest difference {:e}", error);
+        let constant = butterworth(2, FilterKind::Lowpass(30.0), 250.0).unwrap().filtfilt(&[3.25; 100]).unwrap();
+        assert!(constant.iter().all(|value| (value - 3.25).abs() < 1e-12));
+    }
+
+    #[test]
+    fn filtfilt_refuses_signals_not_longer_than_the_padding() {
+        let filter = butterworth(4, FilterKind::Bandpass(1.0, 40.0), 250.0).unwrap();
+        let padding = filter.padding_length();
+        let samples = noise_and_sine(padding + 1, 1);
+        assert!(matches!(filter.filtfilt(&samples[..padding]), Err(ProcessingError::SignalTooShort { length, required }) if length == padding && required == padding + 1));
+        assert!(matches!(filter.filtfilt(&[]), Err(ProcessingError::SignalTooShort { .. })));
+        assert_eq!(filter.filtfilt(&samples).unwrap().len(), padding + 1);
+        let channels = vec![noise_and_sine(500, 2), noise_and_sine(500, 3), samples[..padding].to_vec()];
+        assert!(filter.filtfilt_channels(&channels).is_err());
+        let channels = &channels[..2];
+        let filtered = filter.filtfilt_channels(channels).unwrap();
+        assert!(channels.iter().zip(&filtered).all(|(channel, filtered)| &filter.filtfilt(channel).unwrap() == filtered));
+    }
+
+    /// Pink noise from white noise, with the filter of Paul Kellett
+    fn pink_noise(n: usize, seed: u64) -> Vec<f64> {
+        let mut rng = crate::processing::random::SeededRng::new(seed);
+        let mut b = [0.0; 7];
+        (0..n)
+            .map(|_| {
+                let white = rng.next_gaussian();
+                b[0] = 0.99886 * b[0] + white * 0.0555179;
+                b[1] = 0.99332 * b[1] + white * 0.0750759;
+                b[2] = 0.96900 * b[2] + white * 0.1538520;
+                b[3] = 0.86650 * b[3] + white * 0.3104856;
+                b[4] = 0.55000 * b[4] + white * 0.5329522;
+                b[5] = -0.7616 * b[5] - white * 0.0168980;
+                let pink = b.iter().sum::<f64>() + white * 0.5362;
+                b[6] = white * 0.115926;
+                pink
+            })
+            .collect()
+    }
+
+    /// The amplitude of a frequency over samples holding a whole number of its cycles
+    fn tone_amplitude(samples: &[f64], frequency: f64, sampling_rate: f64) -> f64 {
+        let sum: Complex64 = samples.iter().enumerate().map(|(k, &x)| Complex64::from_polar(x, -2.0 * PI * frequency * k as f64 / sampling_rate)).sum();
+        2.0 * sum.norm() / samples.len() as f64
+    }
+
+    fn band_power_db(samples: &[f64], sampling_rate: f64, low: f64, high: f64) -> f64 {
+        let spectrum = crate::processing::spectral::welch(samples, sampling_rate, sampling_rate as usize, 0.5, Window::Hann).unwrap();
+        10.0 * spectrum.band_power(low, high).log10()
+    }
+
+    #[test]
+    fn line_noise_in_pink_noise_drops_by_40_db_and_bands_away_from_it_keep_their_power() {
+        // The neighbouring bands are at least four notch bandwidths (harmonic / Q) away from every
+        // harmonic, where the squared response of filtfilt of each notch loses at most 0.14 dB,
+        // so a narrow band between two notches loses up to about 0.4 dB
+        for (base, sampling_rate, n_harmonics, amplitudes, neighbours) in [
+            (50.0, 1000.0, 3, vec![5.0, 2.0, 1.0], vec![(10.0, 43.0), (57.0, 86.0), (114.0, 130.0), (170.0, 400.0)]),
+            // 300 Hz is above the Nyquist frequency and is left alone
+            (60.0, 500.0, 5, vec![4.0, 2.0, 1.5, 1.0], vec![(10.0, 52.0), (68.0, 104.0), (136.0, 156.0), (204.0, 208.0)]),
+        ] {
+            let n = 20 * sampling_rate as usize;
+            let noise = pink_noise(n, 107);
+            let line: Vec<f64> = (0..n)
+                .map(|k| amplitudes.iter().enumerate().map(|(h, a)| a * (2.0 * PI * base * (h + 1) as f64 * k as f64 / sampling_rate + h as f64).sin()).sum())
+                .collect();
+            let noisy: Vec<f64> = noise.iter().zip(&line).map(|(a, b)| a + b).collect();
+            let cleaned = remove_line_noise(&noisy, sampling_rate, base, n_harmonics).unwrap();
+            let middle = 2 * sampling_rate as usize..18 * sampling_rate as usize;
+            for (h, amplitude) in amplitudes.iter().enumerate() {
+                let frequency = base * (h + 1) as f64;
+                let before = tone_amplitude(&noisy[middle.clone()], frequency, sampling_rate);
+                let after = tone_amplitude(&cleaned[middle.clone()], frequency, sampling_rate);
+                assert!((before - amplitude).abs() < 0.05 * amplitude, "{} Hz: {} vs {}", frequency, before, amplitude);
+                let attenuation = 20.0 * (before / after).log10();
+                assert!(attenuation > 40.0, "{} Hz is attenuated by only {:.1} dB", frequency, attenuation);
+            }
+            // The filter is linear, so the noise alone shows what happens next to the notches
+            let filtered_noise = remove_line_noise(&noise, sampling_rate, base, n_harmonics).unwrap();
+            for (low, high) in neighbours {
+                let change = band_power_db(&noise, sampling_rate, low, high) - band_power_db(&filtered_noise, sampling_rate, low, high);
+                assert!(change.abs() < 0.5, "{}-{} Hz changed by {:.3} dB", low, high, change);
+            }
+        }
+        assert_eq!(line_noise_filter(60.0, 5, 30.0, 500.0).unwrap().sections().len(), 4);
+    }
+
+    #[test]
+    fn notch_bandwidth_in_hz_is_the_frequency_over_q() {
+        for (frequency, q_factor) in [(50.0, 30.0), (60.0, 10.0), (120.0, 35.0)] {
+            let filter = notch(frequency, q_factor, 1000.0).unwrap();
+            let half_width = frequency / q_factor / 2.0;
+            assert!(filter.magnitude_response(frequency) < 1e-9);
+            // The bilinear transform moves the -3 dB points by about 0.1 dB at a Q of 10
+            for edge in [frequency - half_width, frequency + half_width] {
+                let db = 20.0 * filter.magnitude_response(edge).log10();
+                assert!((db + 3.0103).abs() < 0.15, "{} Hz is at {:.3} dB", edge, db);
+            }
+            assert!(filter.magnitude_response(0.0) > 1.0 - 1e-12 && filter.magnitude_response(500.0) > 1.0 - 1e-12);
+        }
+        assert!(notch(50.0, 0.0, 1000.0).is_err() && notch(500.0, 30.0, 1000.0).is_err());
+        assert!(line_noise_filter(50.0, 0, 30.0, 1000.0).is_err());
+    }
+
+    /// firwin written out from its definition: a windowed difference of sincs scaled to unit gain at `scale_frequency`
+    fn reference_firwin(n_taps: usize, left: f64, right: f64, scale_frequency: f64, coefficients: &[f64]) -> Vec<f64> {
+        let center = (n_taps - 1) as f64 / 2.0;
+        let ideal = |cutoff: f64, m: f64| if m == 0.0 { cutoff } else { (PI * cutoff * m).sin() / (PI * m) };
+        let taps: Vec<f64> = (0..n_taps)
+            .map(|n| {
+                let m = n as f64 - center;
+                let taper: f64 = coefficients
+                    .iter()
+                    .enumerate()
+                    .map(|(k, a)| (-1.0f64).powi(k as i32) * a * (2.0 * PI * k as f64 * n as f64 / (n_taps - 1) as f64).cos())
+                    .sum();
+                (ideal(right, m) - ideal(left, m)) * taper
+            })
+            .collect();
+        let gain: f64 = taps.iter().enumerate().map(|(n, tap)| tap * (PI * (n as f64 - center) * scale_frequency).cos()).sum();
+        taps.iter().map(|tap| tap / gain).collect()
+    }
+
+    #[test]
+    fn fir_design_matches_scipy_firwin() {
+        // The outputs printed in the scipy.signal.firwin docstring for numtaps = 3, with fs = 2
+        // so that the cutoffs are the normalized frequencies of the docstring
+        for (kind, expected) in [
+            (FilterKind::Lowpass(0.1), [0.06799017, 0.86401967, 0.06799017]),
+            (FilterKind::Highpass(0.1), [-0.00859313, 0.98281375, -0.00859313]),
+            (FilterKind::Bandpass(0.1, 0.2), [0.06301614, 0.88770441, 0.06301614]),
+        ] {
+            let filter = fir_design(3, kind, Window::Hamming, 2.0).unwrap();
+            for (tap, want) in filter.taps().iter().zip(expected) {
+                assert!((tap - want).abs() < 1e-8, "{:?}: {:?} vs {:?}", kind, filter.taps(), expected);
+            }
+        }
+
+        for (window, coefficients) in [(Window::Hann, &[0.5, 0.5][..]), (Window::Hamming, &[0.54, 0.46][..]), (Window::Blackman, &[0.42, 0.5, 0.08][..])] {
+            for n_taps in [101, 255] {
+                for (kind, left, right, scale_frequency) in [
+                    (FilterKind::Lowpass(40.0), 0.0, 0.08, 0.0),
+                    (FilterKind::Highpass(300.0), 0.6, 1.0, 1.0),
+                    (FilterKind::Bandpass(8.0, 12.0), 0.016, 0.024, 0.02),
+                ] {
+                    let taps = fir_design(n_taps, kind, window, 1000.0).unwrap().taps().to_vec();
+                    let expected = reference_firwin(n_taps, left, right, scale_frequency, coefficients);
+                    for (tap, want) in taps.iter().zip(&expected) {
+                        assert!((tap - want).abs() < 1e-12, "{:?} {:?} with {} taps", window, kind, n_taps);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fir_design_is_linear_phase_with_the_window_stopband() {
+        // The transition width in cycles per sample times the number of taps of each window, and
+        // the stopband attenuation measured at 201 taps, just under the textbook 44, 53 and 74 dB
+        for (window, transition, attenuation) in [(Window::Hann, 3.1, 42.0), (Window::Hamming, 3.3, 51.0), (Window::Blackman, 5.5, 72.0)] {
+            let n_taps = 201;
+            let filter = fir_design(n_taps, FilterKind::Lowpass(100.0), window, 1000.0).unwrap();
+            let taps = filter.taps();
+            assert!(taps.iter().zip(taps.iter().rev()).all(|(a, b)| (a - b).abs() < 1e-15));
+            assert_eq!(filter.group_delay(), 100.0);
+            assert!((filter.group_delay_seconds() - 0.1).abs() < 1e-15);
+            assert!((filter.magnitude_response(0.0) - 1.0).abs() < 1e-12);
+            assert!((20.0 * filter.magnitude_response(100.0).log10() + 6.02).abs() < 0.1);
+
+            let stopband = 100.0 + transition * 1000.0 / n_taps as f64 / 2.0;
+            let worst = (0..=1000)
+                .map(|step| stopband + (500.0 - stopband) * step as f64 / 1000.0)
+                .map(|frequency| -20.0 * filter.magnitude_response(frequency).log10())
+                .fold(f64::INFINITY, f64::min);
+            assert!(worst > attenuation, "{:?} stops only {:.1} dB", window, worst);
+        }
+
+        let highpass = fir_design(101, FilterKind::Highpass(100.0), Window::Hamming, 1000.0).unwrap();
+        assert!((highpass.magnitude_response(500.0) - 1.0).abs() < 1e-12 && highpass.magnitude_response(0.0) < 1e-2);
+        let bandpass = fir_design(401, FilterKind::Bandpass(40.0, 60.0), Window::Hamming, 1000.0).unwrap();
+        assert!((bandpass.magnitude_response(50.0) - 1.0).abs() < 1e-12 && bandpass.magnitude_response(0.0) < 1e-2);
+
+        assert!(fir_design(100, FilterKind::Highpass(100.0), Window::Hamming, 1000.0).is_err());
+        assert!(fir_design(0, FilterKind::Lowpass(100.0), Window::Hamming, 1000.0).is_err());
+        assert!(fir_design(101, FilterKind::Lowpass(500.0), Window::Hamming, 1000.0).is_err());
+        assert!(fir_design(101, FilterKind::Bandpass(60.0, 40.0), Window::Hamming, 1000.0).is_err());
+    }
+
+    #[test]
+    fn fir_apply_delays_by_the_group_delay_and_apply_zero_delay_removes_it() {
+        let filter = fir_design(51, FilterKind::Lowpass(50.0), Window::Blackman, 1000.0).unwrap();
+        let mut impulse = vec![0.0; 400];
+        impulse[150] = 1.0;
+        let peak = |values: &[f64]| values.iter().enumerate().fold(0, |best, (i, v)| if *v > values[best] { i } else { best });
+
+        let delayed = filter.apply(&impulse);
+        assert_eq!(delayed.len(), impulse.len());
+        assert_eq!(peak(&delayed), 175);
+        assert_eq!(&delayed[150..201], filter.taps());
+        let aligned = filter.apply_zero_delay(&impulse);
+        assert_eq!(aligned.len(), impulse.len());
+        assert_eq!(peak(&aligned), 150);
+
+        // Matched filtering with a custom kernel: the reversed template peaks where the artifact ends
+        let template = [0.0, 1.0, -2.0, 3.0, -1.0, 0.5];
+        let mut signal: Vec<f64> = (0..500).map(|k| 0.1 * (k as f64 * 0.37).sin()).collect();
+        for (offset, value) in template.iter().enumerate() {
+            signal[300 + offset] += value;
+        }
+        let matched = FirFilter::new(template.iter().rev().copied().collect(), 30000.0).unwrap();
+        assert_eq!(peak(&matched.apply(&signal)), 300 + template.len() - 1);
+        assert!(FirFilter::new(Vec::new(), 30000.0).is_err() && FirFilter::new(vec![1.0], 0.0).is_err());
+    }
+}
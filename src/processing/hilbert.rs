@@ -0,0 +1,311 @@
+// A module to compute the analytic signal, envelope and instantaneous phase of signals
+
+// Written by Amin Alam in 2024
+
+use std::f64::consts::PI;
+use num_complex::Complex64;
+use rustfft::FftPlanner;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+
+/// Computes the analytic signal with the FFT method
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+///
+/// # Returns
+///
+/// The complex analytic signal, whose real part is the input and whose imaginary part is
+/// its Hilbert transform
+///
+/// # Examples
+///
+/// ```
+/// let analytic = analytic_signal(&samples);
+/// ```
+///
+/// # Note
+///
+/// The negative frequencies of the spectrum are zeroed and the positive ones doubled, as in
+/// scipy.signal.hilbert. The FFT treats the signal as periodic, so the first and last few
+/// cycles of the lowest frequency of interest are distorted when the signal does not start
+/// and end smoothly; see `trim_edges`.
+///
+pub fn analytic_signal(samples: &[f64]) -> Vec<Complex64> {
+    let n = samples.len();
+    if n == 0 {
+        return Vec::new();
+    }
+    let mut planner = FftPlanner::<f64>::new();
+    let mut buffer: Vec<Complex64> = samples.iter().map(|&sample| Complex64::new(sample, 0.0)).collect();
+    planner.plan_fft_forward(n).process(&mut buffer);
+
+    let positive_end = n.div_ceil(2);
+    for (k, value) in buffer.iter_mut().enumerate() {
+        let gain = if k == 0 || (n.is_multiple_of(2) && k == n / 2) {
+            1.0
+        } else if k < positive_end {
+            2.0
+        } else {
+            0.0
+        };
+        *value *= gain / n as f64;
+    }
+    planner.plan_fft_inverse(n).process(&mut buffer);
+    buffer
+}
+
+/// Computes the amplitude envelope and instantaneous phase of a signal
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal, usually bandpass filtered first
+///
+/// # Returns
+///
+/// The envelope (magnitude of the analytic signal) and the instantaneous phase in radians
+/// within (-pi, pi], each with one value per sample
+///
+/// # Examples
+///
+/// ```
+/// // Theta phase in two lines
+/// let theta = butterworth(4, FilterKind::Bandpass(4.0, 8.0), fs)?.filtfilt(&samples)?;
+/// let (theta_envelope, theta_phase) = analytic(&theta);
+/// ```
+///
+/// # See
+///
+/// * `analytic_signal` - The edge effects of the FFT method
+///
+pub fn analytic(samples: &[f64]) -> (Vec<f64>, Vec<f64>) {
+    analytic_signal(samples).iter().map(|value| (value.norm(), value.arg())).unzip()
+}
+
+/// Computes the amplitude envelope of a signal
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+///
+/// # Returns
+///
+/// The magnitude of the analytic signal at each sample
+///
+/// # Examples
+///
+/// ```
+/// let envelope = envelope(&gamma);
+/// ```
+///
+pub fn envelope(samples: &[f64]) -> Vec<f64> {
+    analytic_signal(samples).iter().map(|value| value.norm()).collect()
+}
+
+/// Computes the instantaneous phase of a signal
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+///
+/// # Returns
+///
+/// The phase of the analytic signal at each sample in radians, within (-pi, pi]
+///
+/// # Examples
+///
+/// ```
+/// let phase = instantaneous_phase(&theta);
+/// ```
+///
+pub fn instantaneous_phase(samples: &[f64]) -> Vec<f64> {
+    analytic_signal(samples).iter().map(|value| value.arg()).collect()
+}
+
+/// Computes the instantaneous frequency of a signal
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+///
+/// # Returns
+///
+/// The derivative of the unwrapped phase in Hz, one value between each pair of consecutive
+/// samples (`n - 1` values), or an error if the sampling rate is not positive
+///
+/// # Examples
+///
+/// ```
+/// let frequency = instantaneous_frequency(&chirp, 1000.0)?;
+/// ```
+///
+pub fn instantaneous_frequency(samples: &[f64], sampling_rate: f64) -> Result<Vec<f64>, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    let phase = unwrap_phase(&instantaneous_phase(samples));
+    Ok(phase.windows(2).map(|pair| (pair[1] - pair[0]) * sampling_rate / (2.0 * PI)).collect())
+}
+
+/// Removes the jumps of 2 pi from a wrapped phase
+///
+/// # Arguments
+///
+/// * `phase` - The wrapped phase in radians
+///
+/// # Returns
+///
+/// The phase with every step between consecutive samples brought into [-pi, pi)
+///
+/// # Examples
+///
+/// ```
+/// let continuous = unwrap_phase(&instantaneous_phase(&samples));
+/// ```
+///
+pub fn unwrap_phase(phase: &[f64]) -> Vec<f64> {
+    let mut unwrapped = Vec::with_capacity(phase.len());
+    let mut offset = 0.0;
+    for (i, &value) in phase.iter().enumerate() {
+        if i > 0 {
+            let step = value - phase[i - 1];
+            let wrapped_step = (step + PI).rem_euclid(2.0 * PI) - PI;
+            offset += wrapped_step - step;
+        }
+        unwrapped.push(value + offset);
+    }
+    unwrapped
+}
+
+/// Drops the samples most affected by edge effects
+///
+/// # Arguments
+///
+/// * `values` - The values computed from a signal, e.g. its envelope
+/// * `n_samples` - The number of samples to drop at each end
+///
+/// # Returns
+///
+/// The central part of the values, empty if fewer than `2 * n_samples` values are given
+///
+/// # Examples
+///
+/// ```
+/// // Drop one cycle of 4 Hz at 1 kHz at each end
+/// let trimmed = trim_edges(&theta_phase, 250);
+/// ```
+///
+pub fn trim_edges(values: &[f64], n_samples: usize) -> &[f64] {
+    if values.len() <= 2 * n_samples {
+        &[]
+    } else {
+        &values[n_samples..values.len() - n_samples]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::filter::{butterworth, FilterKind};
+
+    /// scipy.signal.chirp with `method="linear"`, sweeping `f0` to `f1` Hz over `duration` seconds
+    fn chirp(sampling_rate: f64, duration: f64, f0: f64, f1: f64) -> Vec<f64> {
+        let n = (sampling_rate * duration) as usize;
+        (0..n)
+            .map(|k| {
+                let t = k as f64 / sampling_rate;
+                (2.0 * PI * (f0 * t + (f1 - f0) / (2.0 * duration) * t * t)).cos()
+            })
+            .collect()
+    }
+
+    /// scipy.signal.hilbert written out from its definition, with DFTs by the sum
+    fn reference_hilbert(samples: &[f64]) -> Vec<Complex64> {
+        let n = samples.len();
+        let angle = |k: usize, m: usize| 2.0 * PI * ((k * m) % n) as f64 / n as f64;
+        let spectrum: Vec<Complex64> = (0..n).map(|k| samples.iter().enumerate().map(|(m, x)| Complex64::from_polar(*x, -angle(k, m))).sum()).collect();
+        let mut h = vec![0.0; n];
+        h[0] = 1.0;
+        if n.is_multiple_of(2) {
+            h[n / 2] = 1.0;
+            h[1..n / 2].iter_mut().for_each(|value| *value = 2.0);
+        } else {
+            h[1..n.div_ceil(2)].iter_mut().for_each(|value| *value = 2.0);
+        }
+        (0..n).map(|m| (0..n).map(|k| spectrum[k] * h[k] * Complex64::from_polar(1.0, angle(k, m))).sum::<Complex64>() / n as f64).collect()
+    }
+
+    #[test]
+    fn analytic_signal_matches_scipy_hilbert_on_a_chirp() {
+        for (sampling_rate, duration) in [(500.0, 2.0), (501.0, 2.0)] {
+            let samples = chirp(sampling_rate, duration, 5.0, 50.0);
+            let analytic = analytic_signal(&samples);
+            let expected = reference_hilbert(&samples);
+            assert_eq!(analytic.len(), samples.len());
+            for (k, (actual, want)) in analytic.iter().zip(&expected).enumerate() {
+                assert!((actual - want).norm() < 1e-10, "sample {} of {}: {} vs {}", k, samples.len(), actual, want);
+                assert!((actual.re - samples[k]).abs() < 1e-12);
+            }
+        }
+        assert!(analytic_signal(&[]).is_empty());
+        assert_eq!(analytic_signal(&[2.0]), vec![Complex64::new(2.0, 0.0)]);
+    }
+
+    #[test]
+    fn a_chirp_has_a_unit_envelope_and_its_sweep_as_instantaneous_frequency_away_from_the_edges() {
+        let (sampling_rate, duration, f0, f1) = (1000.0, 4.0, 5.0, 50.0);
+        let samples = chirp(sampling_rate, duration, f0, f1);
+        let (amplitude, phase) = analytic(&samples);
+        assert_eq!(amplitude, envelope(&samples));
+        assert_eq!(phase, instantaneous_phase(&samples));
+        assert!(phase.iter().all(|value| (-PI..=PI).contains(value)));
+
+        // Two cycles of the lowest frequency are trimmed at each end
+        let edge = 400;
+        assert!(trim_edges(&amplitude, edge).iter().all(|value| (value - 1.0).abs() < 0.02));
+        assert!(amplitude[..20].iter().chain(&amplitude[amplitude.len() - 20..]).any(|value| (value - 1.0).abs() > 0.05));
+
+        let frequency = instantaneous_frequency(&samples, sampling_rate).unwrap();
+        assert_eq!(frequency.len(), samples.len() - 1);
+        for (k, value) in frequency.iter().enumerate().skip(edge).take(frequency.len() - 2 * edge) {
+            // Between samples k and k + 1
+            let t = (k as f64 + 0.5) / sampling_rate;
+            let expected = f0 + (f1 - f0) * t / duration;
+            assert!((value - expected).abs() < 0.5, "{} Hz at {} s, expected {}", value, t, expected);
+        }
+        assert!(instantaneous_frequency(&samples, 0.0).is_err());
+    }
+
+    #[test]
+    fn theta_phase_after_a_bandpass_filter_follows_the_theta_oscillation() {
+        // 6 Hz theta under a larger 40 Hz gamma, filtered into the theta band
+        let sampling_rate = 1000.0;
+        let theta: Vec<f64> = (0..5000).map(|k| (2.0 * PI * 6.0 * k as f64 / sampling_rate).cos()).collect();
+        let samples: Vec<f64> = theta.iter().enumerate().map(|(k, value)| value + 2.0 * (2.0 * PI * 40.0 * k as f64 / sampling_rate).sin()).collect();
+        let filtered = butterworth(4, FilterKind::Bandpass(4.0, 8.0), sampling_rate).unwrap().filtfilt(&samples).unwrap();
+        let (amplitude, phase) = analytic(&filtered);
+        for k in 1000..4000 {
+            let expected = (2.0 * PI * 6.0 * k as f64 / sampling_rate + PI).rem_euclid(2.0 * PI) - PI;
+            let difference = (phase[k] - expected + PI).rem_euclid(2.0 * PI) - PI;
+            assert!(difference.abs() < 0.05, "sample {}: {} vs {}", k, phase[k], expected);
+            assert!((amplitude[k] - 1.0).abs() < 0.05);
+        }
+    }
+
+    #[test]
+    fn unwrap_phase_removes_the_jumps_and_trim_edges_drops_both_ends() {
+        let ramp: Vec<f64> = (0..100).map(|k| 0.9 * k as f64 - 3.0).collect();
+        let wrapped: Vec<f64> = ramp.iter().map(|value| (value + PI).rem_euclid(2.0 * PI) - PI).collect();
+        let unwrapped = unwrap_phase(&wrapped);
+        assert!(unwrapped.iter().zip(&ramp).all(|(a, b)| (a - b).abs() < 1e-9));
+        let falling: Vec<f64> = ramp.iter().map(|value| -value).collect();
+        let wrapped: Vec<f64> = falling.iter().map(|value| (value + PI).rem_euclid(2.0 * PI) - PI).collect();
+        assert!(unwrap_phase(&wrapped).iter().zip(&falling).all(|(a, b)| (a - b - wrapped[0] + falling[0]).abs() < 1e-9));
+        assert!(unwrap_phase(&[]).is_empty());
+
+        let values = [1.0, 2.0, 3.0, 4.0, 5.0];
+        assert_eq!(trim_edges(&values, 0), &values);
+        assert_eq!(trim_edges(&values, 2), &[3.0]);
+        assert!(trim_edges(&values, 3).is_empty() && trim_edges(&values[..4], 2).is_empty());
+    }
+}
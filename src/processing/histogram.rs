@@ -0,0 +1,242 @@
+// A module to count values in regular bins
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+
+/// A histogram of values in regular bins
+///
+/// # Arguments
+///
+/// * `bin_edges` - The edges of the bins, one more than the number of bins
+/// * `counts` - The number of values in each half-open bin `[edge, next edge)`
+///
+/// # Examples
+///
+/// ```
+/// let histogram = Histogram::from_values(&isis, 0.001, 0.0, 0.1)?;
+/// histogram.to_csv(&mut csv_io)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Histogram {
+    pub bin_edges: Vec<f64>,
+    pub counts: Vec<usize>,
+}
+
+/// Implementation of the Histogram struct
+///
+/// # Methods
+///
+/// * `from_values` - Counts values in regular bins
+/// * `add_values` - Counts more values in the existing bins
+/// * `bin_centers` - Returns the center of each bin
+/// * `total` - Returns the number of counted values
+/// * `density` - Returns the counts normalized to unit area
+/// * `to_csv` - Writes the histogram as `bin_start,bin_end,count` rows
+impl Histogram {
+    /// Counts values in regular bins
+    ///
+    /// # Arguments
+    ///
+    /// * `values` - The values to count
+    /// * `bin_size` - The width of each bin
+    /// * `low` - The lower edge of the first bin
+    /// * `high` - The upper edge of the last bin, rounded up to a whole number of bins
+    ///
+    /// # Returns
+    ///
+    /// The Histogram, or an error if the bin size is not positive or the range is empty
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let histogram = Histogram::from_values(&amplitudes, 5.0, -200.0, 0.0)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Values outside `[low, high)` and NaN values are not counted
+    ///
+    pub fn from_values(values: &[f64], bin_size: f64, low: f64, high: f64) -> Result<Self, ProcessingError> {
+        if !(bin_size > 0.0 && bin_size.is_finite()) {
+            return Err(ProcessingError::InvalidParameter(format!("Bin size must be positive, got {}", bin_size)));
+        }
+        if !(high > low && low.is_finite() && high.is_finite()) {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Histogram range must end after it starts, got {} to {}",
+                low, high
+            )));
+        }
+        // Tolerate rounding so that e.g. a range of 0.1 holds exactly 100 bins of 0.001
+        let n_bins = ((high - low) / bin_size - 1e-9).ceil().max(1.0) as usize;
+        let bin_edges: Vec<f64> = (0..=n_bins).map(|k| low + k as f64 * bin_size).collect();
+        let mut counts = vec![0; n_bins];
+        count_values(&mut counts, values, low, bin_size, bin_edges[n_bins]);
+        Ok(Self { bin_edges, counts })
+    }
+
+    /// Counts more values in the existing bins
+    ///
+    /// # Arguments
+    ///
+    /// * `values` - The values to count
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut histogram = Histogram::from_values(&[], 5.0, -200.0, 0.0)?;
+    /// for chunk in chunks {
+    ///     histogram.add_values(&chunk);
+    /// }
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Values outside the bins and NaN values are not counted
+    ///
+    pub fn add_values(&mut self, values: &[f64]) {
+        let n_bins = self.counts.len();
+        let (low, high) = (self.bin_edges[0], self.bin_edges[n_bins]);
+        count_values(&mut self.counts, values, low, (high - low) / n_bins as f64, high);
+    }
+
+    /// Returns the center of each bin
+    ///
+    /// # Returns
+    ///
+    /// The midpoints between consecutive edges
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let centers = histogram.bin_centers();
+    /// ```
+    ///
+    pub fn bin_centers(&self) -> Vec<f64> {
+        self.bin_edges.windows(2).map(|pair| (pair[0] + pair[1]) / 2.0).collect()
+    }
+
+    /// Returns the number of counted values
+    ///
+    /// # Returns
+    ///
+    /// The sum of the counts
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let n = histogram.total();
+    /// ```
+    ///
+    pub fn total(&self) -> usize {
+        self.counts.iter().sum()
+    }
+
+    /// Returns the counts normalized to unit area
+    ///
+    /// # Returns
+    ///
+    /// The count of each bin divided by the total count and the bin width, all zeros if no
+    /// value was counted
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let probability_density = histogram.density();
+    /// ```
+    ///
+    pub fn density(&self) -> Vec<f64> {
+        let total = self.total();
+        self.bin_edges
+            .windows(2)
+            .zip(&self.counts)
+            .map(|(pair, &count)| if total == 0 { 0.0 } else { count as f64 / (total as f64 * (pair[1] - pair[0])) })
+            .collect()
+    }
+
+    /// Writes the histogram as `bin_start,bin_end,count` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// histogram.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["bin_start", "bin_end", "count"]))?;
+        for (pair, count) in self.bin_edges.windows(2).zip(&self.counts) {
+            csv_io.write_record(StringRecord::from(vec![float_format.format(pair[0]), float_format.format(pair[1]), count.to_string()]))?;
+        }
+        Ok(())
+    }
+}
+
+/// Adds the values within `[low, high)` to the counts of their bins
+fn count_values(counts: &mut [usize], values: &[f64], low: f64, bin_size: f64, high: f64) {
+    let n_bins = counts.len();
+    for &value in values {
+        if value >= low && value < high {
+            counts[(((value - low) / bin_size) as usize).min(n_bins - 1)] += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn values_are_counted_in_half_open_bins() {
+        let mut histogram = Histogram::from_values(&[0.0, 0.05, 0.099, 0.1, 0.35, -0.1, f64::NAN, 0.4], 0.1, 0.0, 0.4).unwrap();
+        assert_eq!(histogram.counts, vec![3, 1, 0, 1]);
+        assert!((histogram.bin_centers()[1] - 0.15).abs() < 1e-12);
+        assert_eq!(histogram.total(), 5);
+        let density = histogram.density();
+        assert!((density[0] - 3.0 / (5.0 * 0.1)).abs() < 1e-9);
+        assert!((density.iter().sum::<f64>() * 0.1 - 1.0).abs() < 1e-9);
+        histogram.add_values(&[0.25, 0.39999, 1.0]);
+        assert_eq!(histogram.counts, vec![3, 1, 1, 2]);
+
+        // A range of 0.1 holds exactly 100 bins of 0.001, a range of 0.25 three bins of 0.1
+        assert_eq!(Histogram::from_values(&[], 0.001, 0.0, 0.1).unwrap().counts.len(), 100);
+        let rounded_up = Histogram::from_values(&[0.29], 0.1, 0.0, 0.25).unwrap();
+        assert_eq!(rounded_up.counts, vec![0, 0, 1]);
+        assert!(Histogram::from_values(&[], 0.1, 1.0, 1.0).is_err());
+        assert!(Histogram::from_values(&[], -0.1, 0.0, 1.0).is_err());
+    }
+
+    #[test]
+    fn a_histogram_exports_one_row_per_bin() {
+        let histogram = Histogram::from_values(&[0.5, 1.5, 1.7], 1.0, 0.0, 2.0).unwrap();
+        let path = std::env::temp_dir().join(format!("neurorust-histogram-{}-histogram.csv", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        histogram.to_csv(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines[0], "bin_start,bin_end,count");
+        assert_eq!(lines.len(), 3);
+        let row: Vec<f64> = lines[2].split(',').map(|value| value.parse().unwrap()).collect();
+        assert_eq!(row, vec![1.0, 2.0, 2.0]);
+    }
+}
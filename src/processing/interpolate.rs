@@ -0,0 +1,391 @@
+// A module to fill short gaps of missing samples by interpolation
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+
+/// The number of valid samples on each side of a gap used by the cubic methods
+const GAP_NEIGHBOURS: usize = 3;
+
+/// The interpolant used by `interpolate_gaps`
+///
+/// # Arguments
+///
+/// * `Linear` - A straight line between the valid samples on either side of the gap
+/// * `Cubic` - A monotone piecewise cubic (PCHIP), which never overshoots the neighbouring samples
+/// * `Spline` - An Akima spline, smoother than `Cubic` and with little overshoot at steep edges
+///
+/// # Examples
+///
+/// ```
+/// let (repaired, report) = interpolate_gaps(&samples, 500.0, 0.0, &[], InterpMethod::Cubic, 0.05, false)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum InterpMethod {
+    Linear,
+    Cubic,
+    Spline,
+}
+
+/// The gaps found by `interpolate_gaps`
+///
+/// # Arguments
+///
+/// * `filled` - The start and end in seconds of each filled gap
+/// * `unfilled` - The start and end in seconds of each gap left as NaN
+/// * `filled_samples` - The number of filled samples
+///
+/// # Examples
+///
+/// ```
+/// let (repaired, report) = interpolate_gaps(&samples, 500.0, 0.0, &artifact_spans, InterpMethod::Linear, 0.02, true)?;
+/// println!("Filled {} gaps, {} too long", report.filled.len(), report.unfilled.len());
+/// ```
+///
+/// # Note
+///
+/// A gap spans from the time of its first missing sample to the time of the sample after its last one
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GapReport {
+    pub filled: Vec<(f64, f64)>,
+    pub unfilled: Vec<(f64, f64)>,
+    pub filled_samples: usize,
+}
+
+/// Implementation of the GapReport struct
+///
+/// # Methods
+///
+/// * `to_csv` - Writes the gaps as `start,end,filled` rows
+impl GapReport {
+    /// Writes the gaps as `start,end,filled` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// report.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first, then the filled and the unfilled gaps in time order. The
+    /// rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["start", "end", "filled"]))?;
+        let mut gaps: Vec<((f64, f64), bool)> = self
+            .filled
+            .iter()
+            .map(|&gap| (gap, true))
+            .chain(self.unfilled.iter().map(|&gap| (gap, false)))
+            .collect();
+        gaps.sort_by(|a, b| a.0 .0.total_cmp(&b.0 .0));
+        for ((start, end), filled) in gaps {
+            csv_io.write_record(StringRecord::from(vec![float_format.format(start), float_format.format(end), filled.to_string()]))?;
+        }
+        Ok(())
+    }
+}
+
+/// Fills the short gaps of missing samples of a signal
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal, with NaN or infinite values marking missing samples
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample in seconds
+/// * `spans` - The start and end in seconds of extra spans to treat as missing, e.g. from `merge_spans`
+/// * `method` - The interpolant
+/// * `max_gap` - The longest gap filled in seconds
+/// * `extend_edges` - Fills gaps at the start or end of the signal with the nearest valid sample if true, otherwise leaves them as NaN
+///
+/// # Returns
+///
+/// The repaired samples, with the samples of `spans` and of gaps that were not filled set to
+/// NaN, and the GapReport, or an error if the sampling rate or the maximum gap is invalid
+///
+/// # Examples
+///
+/// ```
+/// let spans = merge_spans(&detect(&channels, 1000.0, 0.0, &criteria)?, 0.01);
+/// let (repaired, report) = interpolate_gaps(&channels[0], 1000.0, 0.0, &spans, InterpMethod::Spline, 0.05, false)?;
+/// ```
+///
+/// # Note
+///
+/// Gaps are interpolated from the valid samples of the input only, never from filled
+/// samples of a neighbouring gap. The cubic methods use up to three valid samples on each
+/// side and fall back to fewer near other gaps or the ends of the signal. Gaps at the edges
+/// are also limited to `max_gap`.
+///
+pub fn interpolate_gaps(
+    samples: &[f64],
+    sampling_rate: f64,
+    start_time: f64,
+    spans: &[(f64, f64)],
+    method: InterpMethod,
+    max_gap: f64,
+    extend_edges: bool,
+) -> Result<(Vec<f64>, GapReport), ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    if max_gap.is_nan() || max_gap < 0.0 {
+        return Err(ProcessingError::InvalidParameter(format!("Maximum gap must be non-negative, got {}", max_gap)));
+    }
+    let n = samples.len();
+    let mut marked = samples.to_vec();
+    for &(start, end) in spans {
+        // The samples whose time lies within [start, end), tolerating rounding of the span edges
+        let first = (((start - start_time) * sampling_rate - 1e-9).ceil().max(0.0) as usize).min(n);
+        let last = (((end - start_time) * sampling_rate - 1e-9).ceil().max(0.0) as usize).min(n);
+        for sample in marked.iter_mut().take(last).skip(first) {
+            *sample = f64::NAN;
+        }
+    }
+    let valid: Vec<usize> = (0..n).filter(|&i| marked[i].is_finite()).collect();
+    let time_at = |i: usize| start_time + i as f64 / sampling_rate;
+
+    let mut repaired: Vec<f64> = marked.iter().map(|&sample| if sample.is_finite() { sample } else { f64::NAN }).collect();
+    let mut report = GapReport::default();
+    let mut i = 0;
+    while i < n {
+        if marked[i].is_finite() {
+            i += 1;
+            continue;
+        }
+        let mut j = i;
+        while j < n && !marked[j].is_finite() {
+            j += 1;
+        }
+        let gap = (time_at(i), time_at(j));
+        let before = if i > 0 { Some(i - 1) } else { None };
+        let after = if j < n { Some(j) } else { None };
+        let short = (j - i) as f64 <= max_gap * sampling_rate + 1e-9;
+        let fill = match (before, after) {
+            (Some(left), Some(_)) if short => {
+                let position = valid.partition_point(|&index| index < left);
+                let knots = &valid[position.saturating_sub(GAP_NEIGHBOURS - 1)..(position + 1 + GAP_NEIGHBOURS).min(valid.len())];
+                let xs: Vec<f64> = knots.iter().map(|&index| index as f64).collect();
+                let ys: Vec<f64> = knots.iter().map(|&index| marked[index]).collect();
+                let interval = position - position.saturating_sub(GAP_NEIGHBOURS - 1);
+                for (k, sample) in repaired.iter_mut().enumerate().take(j).skip(i) {
+                    *sample = interpolate(&xs, &ys, interval, k as f64, method);
+                }
+                true
+            }
+            (Some(edge), None) | (None, Some(edge)) if short && extend_edges => {
+                repaired[i..j].iter_mut().for_each(|sample| *sample = marked[edge]);
+                true
+            }
+            _ => false,
+        };
+        if fill {
+            report.filled.push(gap);
+            report.filled_samples += j - i;
+        } else {
+            report.unfilled.push(gap);
+        }
+        i = j;
+    }
+    Ok((repaired, report))
+}
+
+/// Evaluates the interpolant through the knots at `x`, which lies between knots `interval` and `interval + 1`
+fn interpolate(xs: &[f64], ys: &[f64], interval: usize, x: f64, method: InterpMethod) -> f64 {
+    let (x0, x1, y0, y1) = (xs[interval], xs[interval + 1], ys[interval], ys[interval + 1]);
+    let secants: Vec<f64> = xs.windows(2).zip(ys.windows(2)).map(|(x, y)| (y[1] - y[0]) / (x[1] - x[0])).collect();
+    let (d0, d1) = match method {
+        InterpMethod::Linear => return y0 + (y1 - y0) * (x - x0) / (x1 - x0),
+        InterpMethod::Cubic => (pchip_slope(xs, &secants, interval), pchip_slope(xs, &secants, interval + 1)),
+        InterpMethod::Spline => akima_slopes(&secants, interval),
+    };
+    let h = x1 - x0;
+    let s = (x - x0) / h;
+    let (s2, s3) = (s * s, s * s * s);
+    (2.0 * s3 - 3.0 * s2 + 1.0) * y0 + (s3 - 2.0 * s2 + s) * h * d0 + (-2.0 * s3 + 3.0 * s2) * y1 + (s3 - s2) * h * d1
+}
+
+/// The Fritsch-Carlson slope at knot `i`, zero at local extrema so the curve stays monotone between knots
+fn pchip_slope(xs: &[f64], secants: &[f64], i: usize) -> f64 {
+    if i == 0 {
+        return secants[0];
+    }
+    if i == secants.len() {
+        return secants[i - 1];
+    }
+    let (m0, m1) = (secants[i - 1], secants[i]);
+    if m0 * m1 <= 0.0 {
+        return 0.0;
+    }
+    let (h0, h1) = (xs[i] - xs[i - 1], xs[i + 1] - xs[i]);
+    let (w0, w1) = (2.0 * h1 + h0, h1 + 2.0 * h0);
+    (w0 + w1) / (w0 / m0 + w1 / m1)
+}
+
+/// The Akima slopes at both ends of an interval, extrapolating the secants past the outermost knots
+fn akima_slopes(secants: &[f64], interval: usize) -> (f64, f64) {
+    // Two secants are needed on each side of the interval
+    let mut extended = secants.to_vec();
+    let mut offset = 2 - interval.min(2);
+    for _ in 0..offset {
+        let next = extended.get(1).copied().unwrap_or(extended[0]);
+        extended.insert(0, 2.0 * extended[0] - next);
+    }
+    while extended.len() < offset + interval + 3 {
+        let last = extended.len() - 1;
+        let previous = if last > 0 { extended[last - 1] } else { extended[last] };
+        extended.push(2.0 * extended[last] - previous);
+    }
+    offset += interval;
+    let slope = |k: usize| {
+        let (a, b) = ((extended[k + 1] - extended[k]).abs(), (extended[k - 1] - extended[k - 2]).abs());
+        if a + b > 0.0 {
+            (a * extended[k - 1] + b * extended[k]) / (a + b)
+        } else {
+            (extended[k - 1] + extended[k]) / 2.0
+        }
+    };
+    (slope(offset), slope(offset + 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const METHODS: [InterpMethod; 3] = [InterpMethod::Linear, InterpMethod::Cubic, InterpMethod::Spline];
+
+    fn with_gap(samples: &[f64], gap: std::ops::Range<usize>) -> Vec<f64> {
+        let mut gapped = samples.to_vec();
+        gapped[gap].iter_mut().for_each(|sample| *sample = f64::NAN);
+        gapped
+    }
+
+    #[test]
+    fn lines_are_filled_exactly_and_sines_closely() {
+        let line: Vec<f64> = (0..50).map(|k| 2.0 - 0.3 * k as f64).collect();
+        for method in METHODS {
+            let (repaired, report) = interpolate_gaps(&with_gap(&line, 20..25), 1000.0, 0.0, &[], method, 0.01, false).unwrap();
+            assert!(repaired.iter().zip(&line).all(|(a, b)| (a - b).abs() < 1e-12), "{:?}", method);
+            assert_eq!(report.filled_samples, 5);
+            assert!(report.unfilled.is_empty());
+        }
+
+        // A 10 Hz sine sampled at 1 kHz with a 5 ms gap
+        let sine: Vec<f64> = (0..500).map(|k| (2.0 * std::f64::consts::PI * 10.0 * k as f64 / 1000.0).sin()).collect();
+        let gapped = with_gap(&sine, 200..205);
+        let error = |method| {
+            let (repaired, _) = interpolate_gaps(&gapped, 1000.0, 0.0, &[], method, 0.01, false).unwrap();
+            repaired.iter().zip(&sine).map(|(a, b)| (a - b).abs()).fold(0.0, f64::max)
+        };
+        let (linear, cubic, spline) = (error(InterpMethod::Linear), error(InterpMethod::Cubic), error(InterpMethod::Spline));
+        assert!(linear < 3e-3 && cubic < 1e-3 && spline < 2e-3, "{} {} {}", linear, cubic, spline);
+        assert!(cubic < linear && spline < linear);
+    }
+
+    #[test]
+    fn a_gap_across_a_step_does_not_overshoot() {
+        // Flat on both sides, and sloped on both sides of a steep rise
+        let flat = [0.0, 0.0, 0.0, f64::NAN, f64::NAN, f64::NAN, f64::NAN, 1.0, 1.0, 1.0];
+        let sloped = [0.0, 0.1, 0.2, f64::NAN, f64::NAN, f64::NAN, f64::NAN, 5.0, 5.1, 5.2];
+        for (samples, low, high) in [(&flat, 0.0, 1.0), (&sloped, 0.2, 5.0)] {
+            for method in [InterpMethod::Cubic, InterpMethod::Spline] {
+                let (repaired, report) = interpolate_gaps(samples, 100.0, 0.0, &[], method, 0.04, false).unwrap();
+                assert_eq!(report.filled, vec![(0.03, 0.07)]);
+                let filled = &repaired[2..8];
+                assert!(filled.iter().all(|&value| (low..=high).contains(&value)), "{:?}: {:?}", method, filled);
+                assert!(filled.windows(2).all(|pair| pair[1] >= pair[0]), "{:?}: {:?}", method, filled);
+            }
+        }
+        // Flat neighbours give zero end slopes, so the fill is the smoothstep 3s² - 2s³
+        let (repaired, _) = interpolate_gaps(&flat, 100.0, 0.0, &[], InterpMethod::Cubic, 0.04, false).unwrap();
+        for (k, value) in repaired[3..7].iter().enumerate() {
+            let s = (k + 1) as f64 / 5.0;
+            assert!((value - (3.0 * s * s - 2.0 * s * s * s)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn only_gaps_up_to_the_limit_are_filled_and_edges_follow_the_flag() {
+        let mut samples: Vec<f64> = (0..40).map(|k| k as f64).collect();
+        for k in [0, 1, 10, 11, 12, 20, 21, 22, 23, 38, 39] {
+            samples[k] = f64::NAN;
+        }
+        samples[30] = f64::INFINITY;
+        let (repaired, report) = interpolate_gaps(&samples, 100.0, 1.0, &[], InterpMethod::Linear, 0.03, false).unwrap();
+        assert_eq!(report.filled, vec![(1.1, 1.13), (1.3, 1.31)]);
+        assert_eq!(report.unfilled.len(), 3);
+        assert_eq!(report.unfilled[1], (1.2, 1.24));
+        assert_eq!(report.filled_samples, 4);
+        assert_eq!(&repaired[10..13], &[10.0, 11.0, 12.0]);
+        assert_eq!(repaired[30], 30.0);
+        assert!(repaired[20..24].iter().all(|value| value.is_nan()));
+        assert!(repaired[0].is_nan() && repaired[39].is_nan());
+
+        let (extended, report) = interpolate_gaps(&samples, 100.0, 1.0, &[], InterpMethod::Linear, 0.03, true).unwrap();
+        assert_eq!(&extended[..2], &[2.0, 2.0]);
+        assert_eq!(&extended[38..], &[37.0, 37.0]);
+        assert_eq!((report.filled.len(), report.unfilled.len(), report.filled_samples), (4, 1, 8));
+        // Edge gaps are limited to the maximum gap as well
+        let (_, report) = interpolate_gaps(&samples, 100.0, 1.0, &[], InterpMethod::Linear, 0.01, true).unwrap();
+        assert_eq!(report.filled, vec![(1.3, 1.31)]);
+    }
+
+    #[test]
+    fn marked_spans_are_removed_and_gaps_use_only_valid_input() {
+        let samples: Vec<f64> = (0..30).map(|k| (k as f64 * 0.3).sin()).collect();
+        // [5, 8) is filled, [15, 25) is too long and set to NaN
+        let (repaired, report) = interpolate_gaps(&samples, 10.0, 0.0, &[(0.5, 0.8), (1.5, 2.5)], InterpMethod::Spline, 0.5, false).unwrap();
+        assert_eq!(report.filled, vec![(0.5, 0.8)]);
+        assert_eq!(report.unfilled, vec![(1.5, 2.5)]);
+        assert!(repaired[5..8].iter().zip(&samples[5..8]).all(|(a, b)| (a - b).abs() < 0.05));
+        assert!(repaired[15..25].iter().all(|value| value.is_nan()) && repaired[25] == samples[25]);
+
+        // Two gaps separated by one valid sample are both interpolated from it, not from each other
+        let line: Vec<f64> = (0..20).map(|k| k as f64).collect();
+        let mut gapped = line.clone();
+        for k in [5, 6, 8, 9] {
+            gapped[k] = f64::NAN;
+        }
+        for method in METHODS {
+            let (repaired, report) = interpolate_gaps(&gapped, 1.0, 0.0, &[], method, 2.0, false).unwrap();
+            assert_eq!(report.filled.len(), 2);
+            assert!(repaired.iter().zip(&line).all(|(a, b)| (a - b).abs() < 1e-12), "{:?}", method);
+        }
+
+        assert!(interpolate_gaps(&samples, 0.0, 0.0, &[], InterpMethod::Linear, 0.1, false).is_err());
+        assert!(interpolate_gaps(&samples, 10.0, 0.0, &[], InterpMethod::Linear, -0.1, false).is_err());
+        assert!(interpolate_gaps(&samples, 10.0, 0.0, &[], InterpMethod::Linear, f64::NAN, false).is_err());
+        assert_eq!(interpolate_gaps(&[], 10.0, 0.0, &[], InterpMethod::Cubic, 0.1, false).unwrap(), (vec![], GapReport::default()));
+    }
+
+    #[test]
+    fn the_report_lists_the_gaps_in_time_order() {
+        let report = GapReport { filled: vec![(0.5, 0.6), (2.0, 2.1)], unfilled: vec![(1.0, 1.5)], filled_samples: 20 };
+        let path = std::env::temp_dir().join(format!("neurorust-interpolate-{}-gaps.csv", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        report.to_csv(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rows: Vec<Vec<&str>> = written.lines().map(|line| line.split(',').collect()).collect();
+        assert_eq!(rows[0], vec!["start", "end", "filled"]);
+        assert_eq!(rows.iter().skip(1).map(|row| row[2]).collect::<Vec<_>>(), vec!["true", "false", "true"]);
+        assert_eq!(rows[2][0].parse::<f64>().unwrap(), 1.0);
+    }
+}
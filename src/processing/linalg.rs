@@ -0,0 +1,199 @@
+// A module with the small dense linear algebra routines used by the processing functions
+
+// Written by Amin Alam in 2024
+
+/// Solves the linear least-squares problem `min ||A x - b||` with Householder QR
+///
+/// `columns` holds the columns of `A`, each with one entry per row of `b`. Returns `None`
+/// if `A` is rank deficient or has more columns than rows.
+pub(crate) fn least_squares(columns: &[Vec<f64>], b: &[f64]) -> Option<Vec<f64>> {
+    let n_rows = b.len();
+    let n_cols = columns.len();
+    if n_cols == 0 || n_cols > n_rows || columns.iter().any(|column| column.len() != n_rows) {
+        return None;
+    }
+
+    let mut r: Vec<Vec<f64>> = columns.to_vec();
+    let mut rhs = b.to_vec();
+    let scale = r.iter().flat_map(|column| column.iter()).fold(0.0f64, |max, value| max.max(value.abs()));
+    let tolerance = 1e-12 * scale.max(f64::MIN_POSITIVE) * n_rows as f64;
+
+    for k in 0..n_cols {
+        let norm = r[k][k..].iter().map(|value| value * value).sum::<f64>().sqrt();
+        if norm <= tolerance {
+            return None;
+        }
+        let alpha = if r[k][k] > 0.0 { -norm } else { norm };
+        let mut v: Vec<f64> = r[k][k..].to_vec();
+        v[0] -= alpha;
+        let v_norm_sqr: f64 = v.iter().map(|value| value * value).sum();
+        if v_norm_sqr > 0.0 {
+            for column in r.iter_mut().skip(k) {
+                reflect(&v, v_norm_sqr, &mut column[k..]);
+            }
+            reflect(&v, v_norm_sqr, &mut rhs[k..]);
+        }
+    }
+
+    let mut x = vec![0.0; n_cols];
+    for k in (0..n_cols).rev() {
+        let mut sum = rhs[k];
+        for j in k + 1..n_cols {
+            sum -= r[j][k] * x[j];
+        }
+        if r[k][k].abs() <= tolerance {
+            return None;
+        }
+        x[k] = sum / r[k][k];
+    }
+    Some(x)
+}
+
+/// Minimizes `||y - model(p)||` over box-bounded parameters with Levenberg-Marquardt
+///
+/// `evaluate` returns the residuals `y - model(p)` and the columns of the Jacobian of the
+/// model, one per parameter. Steps are solved as damped least-squares problems, so no
+/// normal equations are formed, and projected onto `[lower, upper]`. Returns whether the
+/// fit converged, i.e. the cost stopped decreasing, within `max_iterations` steps; `params`
+/// holds the best parameters found either way.
+pub(crate) fn levenberg_marquardt<F>(params: &mut [f64], lower: &[f64], upper: &[f64], max_iterations: usize, evaluate: F) -> bool
+where
+    F: Fn(&[f64]) -> (Vec<f64>, Vec<Vec<f64>>),
+{
+    let cost_of = |residuals: &[f64]| residuals.iter().map(|residual| residual * residual).sum::<f64>();
+    let (mut residuals, mut jacobian) = evaluate(params);
+    let mut cost = cost_of(&residuals);
+    let mut damping: f64 = 1e-3;
+    for _ in 0..max_iterations {
+        // Parameters on a bound that the gradient pushes against are held there
+        let free: Vec<usize> = (0..params.len())
+            .filter(|&k| {
+                let gradient: f64 = jacobian[k].iter().zip(&residuals).map(|(derivative, residual)| derivative * residual).sum();
+                !(params[k] <= lower[k] && gradient < 0.0 || params[k] >= upper[k] && gradient > 0.0)
+            })
+            .collect();
+        if free.is_empty() {
+            return true;
+        }
+        // The damped step solves [J; sqrt(damping) D] step = [r; 0], with D the column norms of J
+        let columns: Vec<Vec<f64>> = free
+            .iter()
+            .enumerate()
+            .map(|(position, &k)| {
+                let norm = jacobian[k].iter().map(|value| value * value).sum::<f64>().sqrt().max(1e-12);
+                let mut augmented = jacobian[k].clone();
+                augmented.extend((0..free.len()).map(|other| if other == position { damping.sqrt() * norm } else { 0.0 }));
+                augmented
+            })
+            .collect();
+        let mut target = residuals.clone();
+        target.resize(residuals.len() + free.len(), 0.0);
+        let Some(free_step) = least_squares(&columns, &target) else {
+            return true;
+        };
+        let mut candidate = params.to_vec();
+        for (&k, step) in free.iter().zip(&free_step) {
+            candidate[k] = (params[k] + step).clamp(lower[k], upper[k]);
+        }
+        let (candidate_residuals, candidate_jacobian) = evaluate(&candidate);
+        let candidate_cost = cost_of(&candidate_residuals);
+        if candidate_cost.is_finite() && candidate_cost < cost {
+            let decrease = cost - candidate_cost;
+            params.copy_from_slice(&candidate);
+            residuals = candidate_residuals;
+            jacobian = candidate_jacobian;
+            cost = candidate_cost;
+            damping = (damping / 10.0).max(1e-12);
+            if cost == 0.0 || decrease <= 1e-8 * cost {
+                return true;
+            }
+        } else {
+            damping *= 10.0;
+            // No step, however short, decreases the cost: a minimum on the bounds or within them
+            if damping > 1e12 {
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Applies the Householder reflection `I - 2 v vᵀ / (vᵀ v)` to `target`
+fn reflect(v: &[f64], v_norm_sqr: f64, target: &mut [f64]) {
+    let dot: f64 = v.iter().zip(target.iter()).map(|(a, b)| a * b).sum();
+    let factor = 2.0 * dot / v_norm_sqr;
+    for (value, component) in target.iter_mut().zip(v) {
+        *value -= factor * component;
+    }
+}
+
+/// Evaluates the Legendre polynomials of degree `0..=order` at `x`
+pub(crate) fn legendre_basis(x: f64, order: usize) -> Vec<f64> {
+    let mut basis = Vec::with_capacity(order + 1);
+    basis.push(1.0);
+    if order >= 1 {
+        basis.push(x);
+    }
+    for n in 2..=order {
+        let n_f = n as f64;
+        let next = ((2.0 * n_f - 1.0) * x * basis[n - 1] - (n_f - 1.0) * basis[n - 2]) / n_f;
+        basis.push(next);
+    }
+    basis
+}
+
+/// Computes the singular value decomposition `A = U Σ Vᵀ` with one-sided Jacobi rotations
+///
+/// `columns` holds the columns of `A`. Returns the singular values in decreasing order,
+/// the matching columns of `U Σ` and the matching columns of the orthogonal matrix `V`.
+/// Jacobi rotations keep the relative accuracy of small singular values, unlike an
+/// eigendecomposition of `Aᵀ A`.
+pub(crate) fn jacobi_svd(columns: &[Vec<f64>]) -> (Vec<f64>, Vec<Vec<f64>>, Vec<Vec<f64>>) {
+    const MAX_SWEEPS: usize = 60;
+    let n_cols = columns.len();
+    let mut a: Vec<Vec<f64>> = columns.to_vec();
+    let mut v: Vec<Vec<f64>> = (0..n_cols).map(|j| (0..n_cols).map(|i| if i == j { 1.0 } else { 0.0 }).collect()).collect();
+
+    for _ in 0..MAX_SWEEPS {
+        let mut rotated = false;
+        for p in 0..n_cols {
+            for q in p + 1..n_cols {
+                let alpha: f64 = a[p].iter().map(|value| value * value).sum();
+                let beta: f64 = a[q].iter().map(|value| value * value).sum();
+                let gamma: f64 = a[p].iter().zip(&a[q]).map(|(x, y)| x * y).sum();
+                if gamma == 0.0 || gamma.abs() <= f64::EPSILON * (alpha * beta).sqrt() {
+                    continue;
+                }
+                rotated = true;
+                let zeta = (beta - alpha) / (2.0 * gamma);
+                let t = zeta.signum() / (zeta.abs() + (1.0 + zeta * zeta).sqrt());
+                let c = 1.0 / (1.0 + t * t).sqrt();
+                let s = c * t;
+                rotate(&mut a, p, q, c, s);
+                rotate(&mut v, p, q, c, s);
+            }
+        }
+        if !rotated {
+            break;
+        }
+    }
+
+    let norms: Vec<f64> = a.iter().map(|column| column.iter().map(|value| value * value).sum::<f64>().sqrt()).collect();
+    let mut order: Vec<usize> = (0..n_cols).collect();
+    order.sort_by(|&i, &j| norms[j].total_cmp(&norms[i]));
+    (
+        order.iter().map(|&j| norms[j]).collect(),
+        order.iter().map(|&j| a[j].clone()).collect(),
+        order.iter().map(|&j| v[j].clone()).collect(),
+    )
+}
+
+/// Rotates columns `p` and `q` by the Jacobi rotation with cosine `c` and sine `s`
+fn rotate(columns: &mut [Vec<f64>], p: usize, q: usize, c: f64, s: f64) {
+    let (left, right) = columns.split_at_mut(q);
+    for (x, y) in left[p].iter_mut().zip(right[0].iter_mut()) {
+        let (old_x, old_y) = (*x, *y);
+        *x = c * old_x - s * old_y;
+        *y = s * old_x + c * old_y;
+    }
+}
@@ -0,0 +1,54 @@
+pub mod artifacts;
+pub mod autoreject;
+pub mod bad_channels;
+pub mod bursts;
+pub mod channel_interpolation;
+pub mod channel_math;
+pub mod cleanline;
+pub mod checkpoint;
+pub mod cluster;
+pub mod concat;
+pub mod connectivity;
+pub mod convolution;
+pub mod correlogram;
+pub mod decomposition;
+pub mod detrend;
+pub mod erp_measures;
+pub mod error;
+pub mod evoked;
+pub mod export;
+pub mod features;
+pub mod filter;
+pub mod hilbert;
+pub mod histogram;
+pub mod interpolate;
+pub mod linalg;
+pub mod normalize;
+pub mod pac;
+pub mod parallel;
+pub mod peaks;
+pub mod pipeline;
+pub mod psth;
+pub mod qc;
+pub mod random;
+pub mod rate;
+pub mod reference;
+pub mod resample;
+pub mod robust;
+pub mod sleep;
+pub mod smooth;
+pub mod spectral;
+pub mod spike_stats;
+pub mod spikes;
+pub mod sta;
+pub mod stability;
+pub mod stim_artifact;
+pub mod stimulus;
+pub mod streaming;
+pub mod sync;
+pub mod synth;
+pub mod timing;
+pub mod triggers;
+pub mod wavelet;
+pub mod window;
+pub mod xcorr;
\ No newline at end of file
@@ -0,0 +1,594 @@
+// A module to normalize signals with z-score, robust and min-max scaling
+
+// Written by Amin Alam in 2024
+
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::map_channels;
+use crate::processing::robust::{mad, median};
+
+/// The scaling applied by a Normalizer
+///
+/// # Arguments
+///
+/// * `ZScore` - Subtracts the mean and divides by the standard deviation
+/// * `Robust` - Subtracts the median and divides by the median absolute deviation, scaled to match the standard deviation of normal data
+/// * `MinMax` - Maps the minimum and maximum onto the given low and high values
+///
+/// # Examples
+///
+/// ```
+/// let normalizer = Normalizer::fit(&train, NormalizationMethod::MinMax(-1.0, 1.0), true, ZeroVariance::Center)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum NormalizationMethod {
+    ZScore,
+    Robust,
+    MinMax(f64, f64),
+}
+
+/// The handling of channels whose spread is zero, e.g. flat or disconnected electrodes
+///
+/// # Arguments
+///
+/// * `Center` - Only subtracts the center, so a constant channel becomes all zeros (or the low value for `MinMax`)
+/// * `Error` - Returns an error from `Normalizer::fit`
+///
+/// # Examples
+///
+/// ```
+/// let (scaled, normalizer) = zscore(&samples, ZeroVariance::Error)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ZeroVariance {
+    Center,
+    Error,
+}
+
+/// The parameters of the affine map `(x - center) / scale + offset` of one channel
+///
+/// # Arguments
+///
+/// * `center` - The mean, median or minimum of the fitted data
+/// * `scale` - The standard deviation, scaled MAD or range divided by the target range, 1 for zero-spread channels
+/// * `offset` - The value that the center maps onto, the low value for `MinMax` and 0 otherwise
+///
+/// # Examples
+///
+/// ```
+/// let ChannelScaling { center, scale, .. } = normalizer.scalings()[0];
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChannelScaling {
+    pub center: f64,
+    pub scale: f64,
+    pub offset: f64,
+}
+
+/// Implementation of the ChannelScaling struct
+///
+/// # Methods
+///
+/// * `apply` - Scales a sample
+/// * `invert` - Recovers a sample from its scaled value
+impl ChannelScaling {
+    /// Scales a sample
+    ///
+    /// # Arguments
+    ///
+    /// * `sample` - The sample in the original units
+    ///
+    /// # Returns
+    ///
+    /// The scaled sample, NaN if the sample is NaN
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let scaled = scaling.apply(42.0);
+    /// ```
+    ///
+    pub fn apply(&self, sample: f64) -> f64 {
+        (sample - self.center) / self.scale + self.offset
+    }
+
+    /// Recovers a sample from its scaled value
+    ///
+    /// # Arguments
+    ///
+    /// * `scaled` - The scaled sample
+    ///
+    /// # Returns
+    ///
+    /// The sample in the original units
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let original = scaling.invert(scaling.apply(42.0));
+    /// ```
+    ///
+    pub fn invert(&self, scaled: f64) -> f64 {
+        (scaled - self.offset) * self.scale + self.center
+    }
+}
+
+/// A normalization fitted on one set of channels and applicable to others
+///
+/// # Arguments
+///
+/// * `method` - The scaling that was fitted
+/// * `per_channel` - Whether every channel has its own parameters or all share one set
+/// * `scalings` - The fitted parameters, one per channel or a single shared one
+///
+/// # Examples
+///
+/// ```
+/// let normalizer = Normalizer::fit(&train, NormalizationMethod::ZScore, true, ZeroVariance::Center)?;
+/// let train_features = normalizer.transform(&train)?;
+/// let test_features = normalizer.transform(&test)?;
+/// ```
+///
+/// # Note
+///
+/// Non-finite samples are ignored when fitting and stay NaN (or infinite) after scaling.
+/// Standard deviations are population standard deviations, as in scikit-learn.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Normalizer {
+    method: NormalizationMethod,
+    per_channel: bool,
+    scalings: Vec<ChannelScaling>,
+}
+
+/// Implementation of the Normalizer struct
+///
+/// # Methods
+///
+/// * `fit` - Fits the scaling parameters to a set of channels
+/// * `from_scalings` - Creates a Normalizer from known parameters
+/// * `method` - Returns the fitted scaling
+/// * `per_channel` - Returns whether every channel has its own parameters
+/// * `scalings` - Returns the fitted parameters
+/// * `transform` - Scales a set of channels
+/// * `inverse_transform` - Recovers the original units of a set of scaled channels
+impl Normalizer {
+    /// Fits the scaling parameters to a set of channels
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - The samples of each channel
+    /// * `method` - The scaling to fit
+    /// * `per_channel` - Fits each channel separately if true, or all samples together if false
+    /// * `zero_variance` - The handling of channels with zero spread
+    ///
+    /// # Returns
+    ///
+    /// The Normalizer, or an error if a channel (or all channels together) has no finite
+    /// sample, the `MinMax` range is empty, or a spread is zero with `ZeroVariance::Error`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let normalizer = Normalizer::fit(&channels, NormalizationMethod::Robust, false, ZeroVariance::Center)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The channels are fitted in parallel
+    ///
+    pub fn fit(
+        channels: &[Vec<f64>],
+        method: NormalizationMethod,
+        per_channel: bool,
+        zero_variance: ZeroVariance,
+    ) -> Result<Self, ProcessingError> {
+        if let NormalizationMethod::MinMax(low, high) = method {
+            if !(low.is_finite() && high.is_finite() && high > low) {
+                return Err(ProcessingError::InvalidParameter(format!(
+                    "Min-max range ({}, {}) must be finite with the high value above the low one",
+                    low, high
+                )));
+            }
+        }
+
+        let scalings = if per_channel {
+            map_channels(channels, |channel| fit_scaling(channel, method, zero_variance))
+                .into_iter()
+                .enumerate()
+                .map(|(index, scaling)| {
+                    scaling.map_err(|message| ProcessingError::InvalidParameter(format!("Channel {} {}", index, message)))
+                })
+                .collect::<Result<Vec<ChannelScaling>, ProcessingError>>()?
+        } else {
+            let pooled: Vec<f64> = channels.iter().flatten().copied().collect();
+            let scaling = fit_scaling(&pooled, method, zero_variance)
+                .map_err(|message| ProcessingError::InvalidParameter(format!("Recording {}", message)))?;
+            vec![scaling]
+        };
+        Ok(Self { method, per_channel, scalings })
+    }
+
+    /// Creates a Normalizer from known parameters
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - The scaling that the parameters describe
+    /// * `per_channel` - Whether every channel has its own parameters
+    /// * `scalings` - The parameters, one per channel or a single shared one
+    ///
+    /// # Returns
+    ///
+    /// The Normalizer, or an error if a shared normalizer is given other than one scaling or
+    /// a scale is not a finite nonzero number
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // Parameters saved from a previous session
+    /// let normalizer = Normalizer::from_scalings(NormalizationMethod::ZScore, true, saved)?;
+    /// ```
+    ///
+    pub fn from_scalings(method: NormalizationMethod, per_channel: bool, scalings: Vec<ChannelScaling>) -> Result<Self, ProcessingError> {
+        if !per_channel && scalings.len() != 1 {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "A shared normalizer needs exactly one scaling, got {}",
+                scalings.len()
+            )));
+        }
+        if let Some(scaling) = scalings.iter().find(|scaling| scaling.scale == 0.0 || !scaling.scale.is_finite()) {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Scale must be a finite nonzero number, got {}",
+                scaling.scale
+            )));
+        }
+        Ok(Self { method, per_channel, scalings })
+    }
+
+    /// Returns the fitted scaling
+    ///
+    /// # Returns
+    ///
+    /// The normalization method
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let method = normalizer.method();
+    /// ```
+    ///
+    pub fn method(&self) -> NormalizationMethod {
+        self.method
+    }
+
+    /// Returns whether every channel has its own parameters
+    ///
+    /// # Returns
+    ///
+    /// True if the parameters were fitted per channel
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let separate = normalizer.per_channel();
+    /// ```
+    ///
+    pub fn per_channel(&self) -> bool {
+        self.per_channel
+    }
+
+    /// Returns the fitted parameters
+    ///
+    /// # Returns
+    ///
+    /// One scaling per channel, or a single one shared by all channels
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let means: Vec<f64> = normalizer.scalings().iter().map(|scaling| scaling.center).collect();
+    /// ```
+    ///
+    pub fn scalings(&self) -> &[ChannelScaling] {
+        &self.scalings
+    }
+
+    /// Scales a set of channels
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - The samples of each channel, in the order the Normalizer was fitted on
+    ///
+    /// # Returns
+    ///
+    /// The scaled channels, or an error if the Normalizer was fitted per channel on a
+    /// different number of channels
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let test_features = normalizer.transform(&test)?;
+    /// ```
+    ///
+    pub fn transform(&self, channels: &[Vec<f64>]) -> Result<Vec<Vec<f64>>, ProcessingError> {
+        self.map(channels, ChannelScaling::apply)
+    }
+
+    /// Recovers the original units of a set of scaled channels
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - The scaled samples of each channel
+    ///
+    /// # Returns
+    ///
+    /// The channels in their original units, or an error if the Normalizer was fitted per
+    /// channel on a different number of channels
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let denoised_volts = normalizer.inverse_transform(&denoised)?;
+    /// ```
+    ///
+    pub fn inverse_transform(&self, channels: &[Vec<f64>]) -> Result<Vec<Vec<f64>>, ProcessingError> {
+        self.map(channels, ChannelScaling::invert)
+    }
+
+    fn map(&self, channels: &[Vec<f64>], f: fn(&ChannelScaling, f64) -> f64) -> Result<Vec<Vec<f64>>, ProcessingError> {
+        if self.per_channel && channels.len() != self.scalings.len() {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Normalizer was fitted on {} channels but {} were given",
+                self.scalings.len(),
+                channels.len()
+            )));
+        }
+        Ok(channels
+            .iter()
+            .enumerate()
+            .map(|(index, channel)| {
+                let scaling = &self.scalings[if self.per_channel { index } else { 0 }];
+                channel.iter().map(|&sample| f(scaling, sample)).collect()
+            })
+            .collect())
+    }
+}
+
+/// Scales a signal to zero mean and unit standard deviation
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `zero_variance` - The handling of a signal with zero spread
+///
+/// # Returns
+///
+/// The scaled samples and the Normalizer that produced them, or an error if the signal
+/// has no finite sample or zero spread with `ZeroVariance::Error`
+///
+/// # Examples
+///
+/// ```
+/// let (scaled, normalizer) = zscore(&train, ZeroVariance::Center)?;
+/// let held_out = normalizer.transform(&[test])?;
+/// ```
+///
+pub fn zscore(samples: &[f64], zero_variance: ZeroVariance) -> Result<(Vec<f64>, Normalizer), ProcessingError> {
+    normalize(samples, NormalizationMethod::ZScore, zero_variance)
+}
+
+/// Scales a signal to zero median and unit scaled median absolute deviation
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `zero_variance` - The handling of a signal with zero spread
+///
+/// # Returns
+///
+/// The scaled samples and the Normalizer that produced them, or an error if the signal
+/// has no finite sample or zero spread with `ZeroVariance::Error`
+///
+/// # Examples
+///
+/// ```
+/// let (scaled, normalizer) = robust_scale(&samples, ZeroVariance::Center)?;
+/// ```
+///
+/// # Note
+///
+/// Large artifacts barely move the median and MAD, unlike the mean and standard deviation
+///
+pub fn robust_scale(samples: &[f64], zero_variance: ZeroVariance) -> Result<(Vec<f64>, Normalizer), ProcessingError> {
+    normalize(samples, NormalizationMethod::Robust, zero_variance)
+}
+
+/// Scales a signal so that its minimum and maximum map onto a range
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `range` - The low and high values of the scaled signal
+/// * `zero_variance` - The handling of a constant signal
+///
+/// # Returns
+///
+/// The scaled samples and the Normalizer that produced them, or an error if the signal
+/// has no finite sample, the range is empty, or the signal is constant with `ZeroVariance::Error`
+///
+/// # Examples
+///
+/// ```
+/// let (scaled, normalizer) = minmax_scale(&samples, (0.0, 1.0), ZeroVariance::Center)?;
+/// ```
+///
+pub fn minmax_scale(samples: &[f64], range: (f64, f64), zero_variance: ZeroVariance) -> Result<(Vec<f64>, Normalizer), ProcessingError> {
+    normalize(samples, NormalizationMethod::MinMax(range.0, range.1), zero_variance)
+}
+
+fn normalize(samples: &[f64], method: NormalizationMethod, zero_variance: ZeroVariance) -> Result<(Vec<f64>, Normalizer), ProcessingError> {
+    let normalizer = Normalizer::fit(&[samples.to_vec()], method, true, zero_variance)?;
+    let scaling = normalizer.scalings[0];
+    Ok((samples.iter().map(|&sample| scaling.apply(sample)).collect(), normalizer))
+}
+
+/// Fits the scaling of one channel, returning the end of the error message on failure
+fn fit_scaling(samples: &[f64], method: NormalizationMethod, zero_variance: ZeroVariance) -> Result<ChannelScaling, String> {
+    let finite: Vec<f64> = samples.iter().copied().filter(|sample| sample.is_finite()).collect();
+    if finite.is_empty() {
+        return Err("has no finite samples to fit".to_string());
+    }
+    let n = finite.len() as f64;
+    let (center, spread, offset) = match method {
+        NormalizationMethod::ZScore => {
+            let mean = finite.iter().sum::<f64>() / n;
+            let variance = finite.iter().map(|sample| (sample - mean).powi(2)).sum::<f64>() / n;
+            (mean, variance.sqrt(), 0.0)
+        }
+        NormalizationMethod::Robust => {
+            (median(&finite), mad(&finite, true), 0.0)
+        }
+        NormalizationMethod::MinMax(low, high) => {
+            let min = finite.iter().copied().fold(f64::INFINITY, f64::min);
+            let max = finite.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+            (min, (max - min) / (high - low), low)
+        }
+    };
+    if spread > 0.0 && spread.is_finite() {
+        Ok(ChannelScaling { center, scale: spread, offset })
+    } else {
+        match zero_variance {
+            ZeroVariance::Center => Ok(ChannelScaling { center, scale: 1.0, offset }),
+            ZeroVariance::Error => Err("has zero spread".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    fn channels() -> Vec<Vec<f64>> {
+        let mut rng = SeededRng::new(116);
+        vec![
+            (0..1000).map(|_| 5.0 + 2.0 * rng.next_gaussian()).collect(),
+            (0..1000).map(|_| -40.0 + 0.5 * rng.next_gaussian()).collect(),
+        ]
+    }
+
+    fn mean_and_std(values: &[f64]) -> (f64, f64) {
+        let finite: Vec<f64> = values.iter().copied().filter(|value| value.is_finite()).collect();
+        let mean = finite.iter().sum::<f64>() / finite.len() as f64;
+        (mean, (finite.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / finite.len() as f64).sqrt())
+    }
+
+    #[test]
+    fn each_method_scales_the_fitted_data_and_reports_its_parameters() {
+        let train = channels();
+        let (scaled, normalizer) = zscore(&train[0], ZeroVariance::Error).unwrap();
+        let (mean, std) = mean_and_std(&scaled);
+        assert!(mean.abs() < 1e-12 && (std - 1.0).abs() < 1e-12);
+        let (raw_mean, raw_std) = mean_and_std(&train[0]);
+        assert_eq!(normalizer.scalings(), &[ChannelScaling { center: raw_mean, scale: raw_std, offset: 0.0 }]);
+        assert_eq!(normalizer.method(), NormalizationMethod::ZScore);
+
+        let (scaled, normalizer) = robust_scale(&train[1], ZeroVariance::Error).unwrap();
+        assert!(median(&scaled).abs() < 1e-12 && (mad(&scaled, true) - 1.0).abs() < 1e-12);
+        assert!((normalizer.scalings()[0].center + 40.0).abs() < 0.1 && (normalizer.scalings()[0].scale - 0.5).abs() < 0.05);
+
+        let (scaled, normalizer) = minmax_scale(&train[0], (-1.0, 1.0), ZeroVariance::Error).unwrap();
+        assert_eq!(scaled.iter().copied().fold(f64::INFINITY, f64::min), -1.0);
+        assert!((scaled.iter().copied().fold(f64::NEG_INFINITY, f64::max) - 1.0).abs() < 1e-12);
+        assert_eq!(normalizer.scalings()[0].offset, -1.0);
+    }
+
+    #[test]
+    fn a_fitted_normalizer_applies_the_identical_transform_to_held_out_data() {
+        let train = channels();
+        let test: Vec<Vec<f64>> = train.iter().map(|channel| channel.iter().map(|value| value * 1.5 + 3.0).collect()).collect();
+        for method in [NormalizationMethod::ZScore, NormalizationMethod::Robust, NormalizationMethod::MinMax(0.0, 10.0)] {
+            let normalizer = Normalizer::fit(&train, method, true, ZeroVariance::Error).unwrap();
+            assert!(normalizer.per_channel() && normalizer.scalings().len() == 2);
+            let transformed = normalizer.transform(&test).unwrap();
+            for (channel, (scaling, values)) in normalizer.scalings().iter().zip(&test).enumerate() {
+                assert!(transformed[channel].iter().zip(values).all(|(scaled, value)| *scaled == scaling.apply(*value)));
+            }
+            // Every channel has its own parameters, so fitting the first channel alone changes nothing for it
+            let alone = Normalizer::fit(&train[..1], method, true, ZeroVariance::Error).unwrap();
+            assert_eq!(alone.scalings()[0], normalizer.scalings()[0]);
+            let recovered = normalizer.inverse_transform(&transformed).unwrap();
+            for (a, b) in recovered.iter().flatten().zip(test.iter().flatten()) {
+                assert!((a - b).abs() < 1e-9);
+            }
+            assert!(normalizer.transform(&test[..1]).is_err());
+        }
+
+        // Shared parameters are fitted on all samples together and apply to any number of channels
+        let shared = Normalizer::fit(&train, NormalizationMethod::ZScore, false, ZeroVariance::Error).unwrap();
+        let pooled: Vec<f64> = train.iter().flatten().copied().collect();
+        let (mean, std) = mean_and_std(&pooled);
+        assert_eq!(shared.scalings().len(), 1);
+        assert!((shared.scalings()[0].center - mean).abs() < 1e-9 && (shared.scalings()[0].scale - std).abs() < 1e-9);
+        assert_eq!(shared.transform(&train[..1]).unwrap().len(), 1);
+
+        let restored = Normalizer::from_scalings(NormalizationMethod::ZScore, true, shared.scalings().to_vec()).unwrap();
+        assert_eq!(restored.transform(&train[..1]).unwrap(), shared.transform(&train[..1]).unwrap());
+    }
+
+    #[test]
+    fn zero_variance_channels_are_centered_or_rejected_but_never_nan() {
+        let flat = vec![vec![3.0; 100], vec![1.0, 2.0, 3.0]];
+        for method in [NormalizationMethod::ZScore, NormalizationMethod::Robust, NormalizationMethod::MinMax(-1.0, 1.0)] {
+            let normalizer = Normalizer::fit(&flat, method, true, ZeroVariance::Center).unwrap();
+            let scaled = normalizer.transform(&flat).unwrap();
+            let expected = if let NormalizationMethod::MinMax(low, _) = method { low } else { 0.0 };
+            assert!(scaled[0].iter().all(|value| *value == expected), "{:?}: {:?}", method, &scaled[0][..3]);
+            assert!(scaled.iter().flatten().all(|value| value.is_finite()));
+            match Normalizer::fit(&flat, method, true, ZeroVariance::Error) {
+                Err(ProcessingError::InvalidParameter(message)) => assert_eq!(message, "Channel 0 has zero spread"),
+                other => panic!("{:?}: expected an error, got {:?}", method, other),
+            }
+        }
+        // Half the samples equal: the MAD is zero even though the variance is not
+        let mostly_flat = [0.0, 0.0, 0.0, 1.0, 5.0];
+        assert!(robust_scale(&mostly_flat, ZeroVariance::Error).is_err());
+        assert!(zscore(&mostly_flat, ZeroVariance::Error).is_ok());
+    }
+
+    #[test]
+    fn nan_samples_are_ignored_when_fitting_and_stay_nan() {
+        let mut samples = channels().swap_remove(0);
+        let clean = zscore(&samples, ZeroVariance::Error).unwrap().1;
+        let mut with_gaps = samples.clone();
+        with_gaps.extend([f64::NAN, f64::INFINITY, f64::NAN]);
+        for method in [NormalizationMethod::ZScore, NormalizationMethod::Robust, NormalizationMethod::MinMax(0.0, 1.0)] {
+            let a = Normalizer::fit(&[samples.clone()], method, true, ZeroVariance::Error).unwrap();
+            let b = Normalizer::fit(&[with_gaps.clone()], method, true, ZeroVariance::Error).unwrap();
+            assert_eq!(a.scalings(), b.scalings(), "{:?}", method);
+        }
+        let (scaled, normalizer) = zscore(&with_gaps, ZeroVariance::Error).unwrap();
+        assert_eq!(normalizer.scalings(), clean.scalings());
+        assert!(scaled[1000].is_nan() && scaled[1001] == f64::INFINITY);
+
+        samples.iter_mut().for_each(|value| *value = f64::NAN);
+        assert!(zscore(&samples, ZeroVariance::Center).is_err());
+        assert!(minmax_scale(&[1.0, 2.0], (1.0, 1.0), ZeroVariance::Center).is_err());
+        assert!(Normalizer::from_scalings(NormalizationMethod::ZScore, true, vec![ChannelScaling { center: 0.0, scale: 0.0, offset: 0.0 }]).is_err());
+        assert!(Normalizer::from_scalings(NormalizationMethod::ZScore, false, Vec::new()).is_err());
+    }
+
+    #[test]
+    fn robust_scaling_barely_moves_with_an_artifact() {
+        let mut samples = channels().swap_remove(0);
+        let before_robust = robust_scale(&samples, ZeroVariance::Error).unwrap().1.scalings()[0];
+        let before_zscore = zscore(&samples, ZeroVariance::Error).unwrap().1.scalings()[0];
+        samples[500] = 1e6;
+        let after_robust = robust_scale(&samples, ZeroVariance::Error).unwrap().1.scalings()[0];
+        let after_zscore = zscore(&samples, ZeroVariance::Error).unwrap().1.scalings()[0];
+        assert!((after_robust.scale / before_robust.scale - 1.0).abs() < 0.01 && (after_robust.center - before_robust.center).abs() < 0.01);
+        assert!(after_zscore.scale > 100.0 * before_zscore.scale);
+    }
+}
@@ -0,0 +1,404 @@
+// A module to measure phase-amplitude coupling between frequency bands
+
+// Written by Amin Alam in 2024
+
+use std::f64::consts::PI;
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::{butterworth, FilterKind};
+use crate::processing::hilbert::{envelope, instantaneous_phase};
+use crate::processing::random::SeededRng;
+
+/// Options of `pac`
+///
+/// # Arguments
+///
+/// * `n_phase_bins` - The number of phase bins, 18 (20° each) by default as in Tort et al. (2010)
+/// * `filter_order` - The order of the zero-phase Butterworth bandpass filters, 4 by default
+/// * `n_surrogates` - The number of time-shifted surrogates of the significance test, 200 by default, 0 to skip the test
+/// * `seed` - The seed of the surrogate time shifts, 0 by default
+///
+/// # Examples
+///
+/// ```
+/// let options = PacOptions { n_surrogates: 1000, seed: 3, ..PacOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PacOptions {
+    pub n_phase_bins: usize,
+    pub filter_order: usize,
+    pub n_surrogates: usize,
+    pub seed: u64,
+}
+
+impl Default for PacOptions {
+    fn default() -> Self {
+        Self { n_phase_bins: 18, filter_order: 4, n_surrogates: 200, seed: 0 }
+    }
+}
+
+/// The significance of a modulation index against time-shifted surrogates
+///
+/// # Arguments
+///
+/// * `mean` - The mean modulation index of the surrogates
+/// * `std` - The standard deviation of the modulation index of the surrogates
+/// * `z_score` - The distance of the observed modulation index from the surrogate mean, in surrogate standard deviations
+/// * `p_value` - The fraction of surrogates at least as large as the observed index, counting the observation itself
+/// * `n_surrogates` - The number of surrogates
+///
+/// # Examples
+///
+/// ```
+/// let test = surrogate_test(&phase, &amplitude, 18, 500, 0)?;
+/// if test.p_value < 0.01 { println!("Significant coupling, z = {:.1}", test.z_score); }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SurrogateTest {
+    pub mean: f64,
+    pub std: f64,
+    pub z_score: f64,
+    pub p_value: f64,
+    pub n_surrogates: usize,
+}
+
+/// The phase-amplitude coupling of two signals
+///
+/// # Arguments
+///
+/// * `bin_centers` - The center of each phase bin in radians, from -π to π
+/// * `mean_amplitude` - The mean amplitude in each phase bin, 0 for an empty bin
+/// * `modulation_index` - The Tort modulation index, from 0 (no coupling) to 1
+/// * `surrogates` - The surrogate test of the modulation index, if requested
+///
+/// # Examples
+///
+/// ```
+/// let coupling = pac(&lfp, 1000.0, (4.0, 8.0), (30.0, 80.0), &PacOptions::default())?;
+/// coupling.to_csv(&mut csv_io)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PacResult {
+    pub bin_centers: Vec<f64>,
+    pub mean_amplitude: Vec<f64>,
+    pub modulation_index: f64,
+    pub surrogates: Option<SurrogateTest>,
+}
+
+/// Implementation of the PacResult struct
+///
+/// # Methods
+///
+/// * `amplitude_distribution` - Returns the mean amplitude of each phase bin normalized to sum to 1
+/// * `preferred_phase` - Returns the center of the phase bin with the highest mean amplitude
+/// * `to_csv` - Writes the phase-amplitude profile as `phase,mean_amplitude,probability` rows
+impl PacResult {
+    /// Returns the mean amplitude of each phase bin normalized to sum to 1
+    ///
+    /// # Returns
+    ///
+    /// The amplitude distribution over the phase bins, uniform without coupling, or zeros if
+    /// every amplitude is 0
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let distribution = coupling.amplitude_distribution();
+    /// ```
+    ///
+    pub fn amplitude_distribution(&self) -> Vec<f64> {
+        let total: f64 = self.mean_amplitude.iter().sum();
+        self.mean_amplitude.iter().map(|amplitude| if total > 0.0 { amplitude / total } else { 0.0 }).collect()
+    }
+
+    /// Returns the center of the phase bin with the highest mean amplitude
+    ///
+    /// # Returns
+    ///
+    /// The preferred phase in radians, or NaN if there are no bins
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let phase = coupling.preferred_phase();
+    /// ```
+    ///
+    pub fn preferred_phase(&self) -> f64 {
+        self.bin_centers
+            .iter()
+            .zip(&self.mean_amplitude)
+            .fold((f64::NAN, f64::NEG_INFINITY), |best, (&phase, &amplitude)| if amplitude > best.1 { (phase, amplitude) } else { best })
+            .0
+    }
+
+    /// Writes the phase-amplitude profile as `phase,mean_amplitude,probability` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// coupling.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["phase", "mean_amplitude", "probability"]))?;
+        let distribution = self.amplitude_distribution();
+        for ((phase, amplitude), probability) in self.bin_centers.iter().zip(&self.mean_amplitude).zip(&distribution) {
+            csv_io.write_record(StringRecord::from(vec![float_format.format(*phase), float_format.format(*amplitude), float_format.format(*probability)]))?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes the Tort modulation index of an amplitude by a phase
+///
+/// # Arguments
+///
+/// * `phase` - The instantaneous phase of the low-frequency signal in radians, e.g. from `instantaneous_phase`
+/// * `amplitude` - The amplitude envelope of the high-frequency signal, e.g. from `envelope`
+/// * `n_phase_bins` - The number of phase bins
+///
+/// # Returns
+///
+/// The PacResult without surrogate test, or an error if the signals differ in length or
+/// fewer than two bins are requested
+///
+/// # Examples
+///
+/// ```
+/// let coupling = modulation_index(&theta_phase, &gamma_envelope, 18)?;
+/// ```
+///
+/// # Note
+///
+/// The index is the Kullback-Leibler divergence of the amplitude distribution over the
+/// phase bins from the uniform distribution, divided by `ln(n_phase_bins)` (Tort et al.,
+/// 2010). Samples where the phase or the amplitude is not finite are left out.
+///
+pub fn modulation_index(phase: &[f64], amplitude: &[f64], n_phase_bins: usize) -> Result<PacResult, ProcessingError> {
+    validate(phase, amplitude, n_phase_bins)?;
+    let bins = phase_bins(phase, n_phase_bins);
+    let mean_amplitude = binned_means(&bins, amplitude, 0, n_phase_bins);
+    let bin_centers = (0..n_phase_bins).map(|j| -PI + (j as f64 + 0.5) * 2.0 * PI / n_phase_bins as f64).collect();
+    Ok(PacResult { bin_centers, modulation_index: tort_index(&mean_amplitude), mean_amplitude, surrogates: None })
+}
+
+/// Tests a modulation index against surrogates with circularly time-shifted amplitudes
+///
+/// # Arguments
+///
+/// * `phase` - The instantaneous phase of the low-frequency signal in radians
+/// * `amplitude` - The amplitude envelope of the high-frequency signal
+/// * `n_phase_bins` - The number of phase bins
+/// * `n_surrogates` - The number of surrogates
+/// * `seed` - The seed of the time shifts
+///
+/// # Returns
+///
+/// The SurrogateTest, or an error if the signals differ in length or have fewer than ten
+/// samples, fewer than two bins are requested or no surrogate is requested
+///
+/// # Examples
+///
+/// ```
+/// let test = surrogate_test(&theta_phase, &gamma_envelope, 18, 200, 42)?;
+/// ```
+///
+/// # Note
+///
+/// Each surrogate shifts the amplitude by a random lag within the middle 80% of the signal
+/// length, which keeps the spectra of both signals but breaks their temporal relation. For a
+/// strictly periodic phase, as in some synthetic signals, a shifted amplitude is as coupled
+/// as the original one, so the test is only meaningful for rhythms whose cycles vary.
+///
+pub fn surrogate_test(phase: &[f64], amplitude: &[f64], n_phase_bins: usize, n_surrogates: usize, seed: u64) -> Result<SurrogateTest, ProcessingError> {
+    validate(phase, amplitude, n_phase_bins)?;
+    let n = phase.len();
+    if n_surrogates == 0 || n < 10 {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "The surrogate test needs at least one surrogate and ten samples, got {} and {}",
+            n_surrogates, n
+        )));
+    }
+    let bins = phase_bins(phase, n_phase_bins);
+    let observed = tort_index(&binned_means(&bins, amplitude, 0, n_phase_bins));
+    let mut rng = SeededRng::new(seed);
+    let min_shift = n / 10;
+    let surrogates: Vec<f64> = (0..n_surrogates)
+        .map(|_| {
+            let shift = min_shift + rng.next_index(n - 2 * min_shift);
+            tort_index(&binned_means(&bins, amplitude, shift, n_phase_bins))
+        })
+        .collect();
+    let mean = surrogates.iter().sum::<f64>() / n_surrogates as f64;
+    let std = (surrogates.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / n_surrogates as f64).sqrt();
+    let exceeding = surrogates.iter().filter(|&&value| value >= observed).count();
+    Ok(SurrogateTest {
+        mean,
+        std,
+        z_score: (observed - mean) / std,
+        p_value: (exceeding + 1) as f64 / (n_surrogates + 1) as f64,
+        n_surrogates,
+    })
+}
+
+/// Computes the phase-amplitude coupling between two frequency bands of a signal
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `phase_band` - The low and high edges in Hz of the band providing the phase, e.g. theta `(4.0, 8.0)`
+/// * `amplitude_band` - The low and high edges in Hz of the band providing the amplitude, e.g. gamma `(30.0, 80.0)`
+/// * `options` - The binning, filters and surrogate test
+///
+/// # Returns
+///
+/// The PacResult, with a surrogate test unless `options.n_surrogates` is 0, or an error if
+/// a band is invalid for the filter or the signal is too short
+///
+/// # Examples
+///
+/// ```
+/// let coupling = pac(&ca1, 1250.0, (6.0, 10.0), (60.0, 120.0), &PacOptions::default())?;
+/// println!("MI = {:.2e}, p = {}", coupling.modulation_index, coupling.surrogates.unwrap().p_value);
+/// ```
+///
+/// # Note
+///
+/// Both bands are extracted with zero-phase Butterworth filters, then the phase and the
+/// envelope are taken from the analytic signal. The amplitude band should be at least twice
+/// as wide as the phase frequency so that the filter keeps the modulation sidebands.
+///
+pub fn pac(samples: &[f64], sampling_rate: f64, phase_band: (f64, f64), amplitude_band: (f64, f64), options: &PacOptions) -> Result<PacResult, ProcessingError> {
+    let phase_filter = butterworth(options.filter_order, FilterKind::Bandpass(phase_band.0, phase_band.1), sampling_rate)?;
+    let amplitude_filter = butterworth(options.filter_order, FilterKind::Bandpass(amplitude_band.0, amplitude_band.1), sampling_rate)?;
+    let phase = instantaneous_phase(&phase_filter.filtfilt(samples)?);
+    let amplitude = envelope(&amplitude_filter.filtfilt(samples)?);
+    let mut result = modulation_index(&phase, &amplitude, options.n_phase_bins)?;
+    if options.n_surrogates > 0 {
+        result.surrogates = Some(surrogate_test(&phase, &amplitude, options.n_phase_bins, options.n_surrogates, options.seed)?);
+    }
+    Ok(result)
+}
+
+fn validate(phase: &[f64], amplitude: &[f64], n_phase_bins: usize) -> Result<(), ProcessingError> {
+    if phase.len() != amplitude.len() {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Got {} phase samples but {} amplitude samples",
+            phase.len(),
+            amplitude.len()
+        )));
+    }
+    if n_phase_bins < 2 {
+        return Err(ProcessingError::InvalidParameter(format!("At least two phase bins are needed, got {}", n_phase_bins)));
+    }
+    Ok(())
+}
+
+/// Returns the phase bin of every sample, or `None` for a non-finite phase
+fn phase_bins(phase: &[f64], n_phase_bins: usize) -> Vec<Option<usize>> {
+    phase
+        .iter()
+        .map(|&value| {
+            if !value.is_finite() {
+                return None;
+            }
+            let position = (value + PI).rem_euclid(2.0 * PI) / (2.0 * PI);
+            Some(((position * n_phase_bins as f64) as usize).min(n_phase_bins - 1))
+        })
+        .collect()
+}
+
+/// Averages the amplitude, circularly shifted by `shift` samples, within each phase bin
+fn binned_means(bins: &[Option<usize>], amplitude: &[f64], shift: usize, n_phase_bins: usize) -> Vec<f64> {
+    let n = amplitude.len();
+    let mut sums = vec![0.0; n_phase_bins];
+    let mut counts = vec![0usize; n_phase_bins];
+    for (i, bin) in bins.iter().enumerate() {
+        let value = amplitude[(i + shift) % n];
+        if let (Some(bin), true) = (bin, value.is_finite()) {
+            sums[*bin] += value;
+            counts[*bin] += 1;
+        }
+    }
+    sums.iter().zip(&counts).map(|(sum, &count)| if count > 0 { sum / count as f64 } else { 0.0 }).collect()
+}
+
+/// The normalized Kullback-Leibler divergence of the amplitude distribution from the uniform one
+fn tort_index(mean_amplitude: &[f64]) -> f64 {
+    let total: f64 = mean_amplitude.iter().sum();
+    if total.is_nan() || total <= 0.0 {
+        return 0.0;
+    }
+    let max_entropy = (mean_amplitude.len() as f64).ln();
+    let entropy: f64 = mean_amplitude
+        .iter()
+        .map(|amplitude| amplitude / total)
+        .filter(|&p| p > 0.0)
+        .map(|p| -p * p.ln())
+        .sum();
+    (max_entropy - entropy) / max_entropy
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    /// A theta rhythm with a wandering frequency and a 60 Hz gamma, whose amplitude follows the theta phase if `coupling` is not zero
+    fn theta_gamma(coupling: f64, seed: u64) -> Vec<f64> {
+        let sampling_rate = 1000.0;
+        let mut rng = SeededRng::new(seed);
+        let mut phase = 0.0;
+        let mut frequency = 6.0;
+        (0..20000)
+            .map(|k| {
+                frequency = (frequency + 0.05 * rng.next_gaussian()).clamp(4.5, 7.5);
+                phase += 2.0 * PI * frequency / sampling_rate;
+                let theta = phase.sin();
+                let gamma = (2.0 * PI * 60.0 * k as f64 / sampling_rate).sin();
+                theta + 0.3 * (1.0 + coupling * theta) * gamma + 0.2 * rng.next_gaussian()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn coupled_gamma_stands_out_from_its_surrogates() {
+        let coupled = pac(&theta_gamma(0.8, 3), 1000.0, (4.0, 8.0), (40.0, 80.0), &PacOptions::default()).unwrap();
+        let uncoupled = pac(&theta_gamma(0.0, 3), 1000.0, (4.0, 8.0), (40.0, 80.0), &PacOptions::default()).unwrap();
+        let z = |result: &PacResult| result.surrogates.as_ref().unwrap().z_score;
+        assert!(z(&coupled) > 20.0, "{}", z(&coupled));
+        assert!(z(&uncoupled).abs() < 3.0, "{}", z(&uncoupled));
+        assert!(coupled.modulation_index > 100.0 * uncoupled.modulation_index);
+        // The gamma is largest at the theta peaks, where the analytic phase of a sine is 0
+        assert!(coupled.preferred_phase().abs() < 0.6, "{}", coupled.preferred_phase());
+    }
+
+    #[test]
+    fn uniform_amplitude_has_no_modulation() {
+        let phase: Vec<f64> = (0..3600).map(|k| -PI + (k as f64 + 0.5) * 2.0 * PI / 3600.0).collect();
+        let result = modulation_index(&phase, &vec![2.0; 3600], 18).unwrap();
+        assert!(result.modulation_index.abs() < 1e-12);
+        assert_eq!(result.bin_centers.len(), 18);
+        assert!(result.mean_amplitude.iter().all(|&amplitude| (amplitude - 2.0).abs() < 1e-12));
+    }
+}
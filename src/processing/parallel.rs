@@ -0,0 +1,444 @@
+// A module to map functions over chunks of long signals and csv files in parallel
+
+// Written by Amin Alam in 2024
+
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use csv::StringRecord;
+use crate::data_io::csv::CsvReader;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+
+/// A chunk of a multi-channel signal handed to the function of `chunk_map`
+///
+/// # Arguments
+///
+/// * `index` - The position of the chunk in the output
+/// * `channels` - The samples of every channel in the chunk, including the overlap on both sides
+/// * `offset` - The index in the signal of the first sample of `channels`
+/// * `core` - The range of `channels` that belongs to this chunk alone, without the overlap
+/// * `sampling_rate` - The sampling rate in Hz
+///
+/// # Examples
+///
+/// ```
+/// let filtered = chunk_map(&channels, 30000.0, 10.0, 0.5, |view| -> Result<Vec<f64>, ProcessingError> {
+///     let output = filter.filtfilt(view.channels[0])?;
+///     Ok(view.trim(&output).to_vec())
+/// })?;
+/// let filtered: Vec<f64> = filtered.concat();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChunkView<'a> {
+    pub index: usize,
+    pub channels: Vec<&'a [f64]>,
+    pub offset: usize,
+    pub core: Range<usize>,
+    pub sampling_rate: f64,
+}
+
+/// Implementation of the ChunkView struct
+///
+/// # Methods
+///
+/// * `start_time` - Returns the time of the first sample of the core
+/// * `end_time` - Returns the time just after the last sample of the core
+/// * `core_channels` - Returns the samples of the core of every channel
+/// * `trim` - Cuts an output aligned with the chunk down to its core
+impl<'a> ChunkView<'a> {
+    /// Returns the time of the first sample of the core
+    ///
+    /// # Returns
+    ///
+    /// The time in seconds, counted from the first sample of the signal
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let start = view.start_time();
+    /// ```
+    ///
+    pub fn start_time(&self) -> f64 {
+        (self.offset + self.core.start) as f64 / self.sampling_rate
+    }
+
+    /// Returns the time just after the last sample of the core
+    ///
+    /// # Returns
+    ///
+    /// The time in seconds, one sample period after the last sample of the core
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let duration = view.end_time() - view.start_time();
+    /// ```
+    ///
+    pub fn end_time(&self) -> f64 {
+        (self.offset + self.core.end) as f64 / self.sampling_rate
+    }
+
+    /// Returns the samples of the core of every channel
+    ///
+    /// # Returns
+    ///
+    /// One slice per channel, without the overlap
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let rms: Vec<f64> = view.core_channels().iter().map(|channel| rms(channel)).collect();
+    /// ```
+    ///
+    pub fn core_channels(&self) -> Vec<&'a [f64]> {
+        self.channels.iter().map(|channel| &channel[self.core.clone()]).collect()
+    }
+
+    /// Cuts an output aligned with the chunk down to its core
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - Values with one entry per sample of `channels`, e.g. a filtered channel
+    ///
+    /// # Returns
+    ///
+    /// The entries of the core, so that concatenating the trimmed outputs of all chunks
+    /// gives one entry per sample of the signal
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let kept = view.trim(&smoothed);
+    /// ```
+    ///
+    pub fn trim<'o, T>(&self, output: &'o [T]) -> &'o [T] {
+        &output[self.core.clone()]
+    }
+}
+
+/// A chunk of rows of a csv file handed to the function of `chunk_map_records`
+///
+/// # Arguments
+///
+/// * `index` - The position of the chunk in the output
+/// * `records` - The rows of the chunk, including the overlap on both sides
+/// * `offset` - The index in the file of the first row of `records`, counted from 0 after the header row
+/// * `core` - The range of `records` that belongs to this chunk alone, without the overlap
+///
+/// # Examples
+///
+/// ```
+/// let counts = chunk_map_records(&mut reader, 100000, 0, |chunk| -> Result<usize, ProcessingError> {
+///     Ok(chunk.core_records().iter().filter(|record| &record[2] == "1").count())
+/// })?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordChunk {
+    pub index: usize,
+    pub records: Vec<StringRecord>,
+    pub offset: usize,
+    pub core: Range<usize>,
+}
+
+/// Implementation of the RecordChunk struct
+///
+/// # Methods
+///
+/// * `core_records` - Returns the rows of the core
+/// * `trim` - Cuts an output aligned with the chunk down to its core
+impl RecordChunk {
+    /// Returns the rows of the core
+    ///
+    /// # Returns
+    ///
+    /// The rows that belong to this chunk alone
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let own_rows = chunk.core_records();
+    /// ```
+    ///
+    pub fn core_records(&self) -> &[StringRecord] {
+        &self.records[self.core.clone()]
+    }
+
+    /// Cuts an output aligned with the chunk down to its core
+    ///
+    /// # Arguments
+    ///
+    /// * `output` - Values with one entry per row of `records`
+    ///
+    /// # Returns
+    ///
+    /// The entries of the core
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let kept = chunk.trim(&parsed);
+    /// ```
+    ///
+    pub fn trim<'o, T>(&self, output: &'o [T]) -> &'o [T] {
+        &output[self.core.clone()]
+    }
+}
+
+/// The errors returned by `chunk_map` and `chunk_map_records`
+///
+/// # Arguments
+///
+/// * `Setup` - The chunking parameters are invalid
+/// * `Io` - The csv file could not be indexed, reopened or read
+/// * `Chunk` - The function failed on a chunk of `chunk_map`, with the start and end of the chunk's core in seconds
+/// * `Rows` - The function failed on a chunk of `chunk_map_records`, with the first row of the chunk's core and the row after it
+///
+/// # Examples
+///
+/// ```
+/// match chunk_map(&channels, 1000.0, 60.0, 1.0, detect_events) {
+///     Err(ChunkError::Chunk { start, end, error, .. }) => println!("Failed between {} and {} s: {}", start, end, error),
+///     Err(error) => println!("{}", error),
+///     Ok(events) => println!("{} chunks", events.len()),
+/// }
+/// ```
+#[derive(Debug)]
+pub enum ChunkError<E> {
+    Setup(ProcessingError),
+    Io(io::Error),
+    Chunk { index: usize, start: f64, end: f64, error: E },
+    Rows { index: usize, first_row: usize, end_row: usize, error: E },
+}
+
+impl<E: fmt::Display> fmt::Display for ChunkError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ChunkError::Setup(error) => write!(f, "{}", error),
+            ChunkError::Io(error) => write!(f, "I/O error: {}", error),
+            ChunkError::Chunk { index, start, end, error } => write!(f, "Chunk {} ({} to {} s): {}", index, start, end, error),
+            ChunkError::Rows { index, first_row, end_row, error } => write!(f, "Chunk {} (rows {} to {}): {}", index, first_row, end_row, error),
+        }
+    }
+}
+
+impl<E: Error + 'static> Error for ChunkError<E> {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ChunkError::Setup(error) => Some(error),
+            ChunkError::Io(error) => Some(error),
+            ChunkError::Chunk { error, .. } | ChunkError::Rows { error, .. } => Some(error),
+        }
+    }
+}
+
+/// Maps a function over consecutive chunks of a multi-channel signal in parallel
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel, all of the same length
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `chunk_s` - The length of the core of each chunk in seconds, rounded to a whole number of samples
+/// * `overlap_s` - The context added on both sides of each core in seconds, rounded to a whole number of samples
+/// * `f` - The function called on every chunk
+///
+/// # Returns
+///
+/// The results of the chunks in signal order, or the error of the earliest failed chunk
+///
+/// # Examples
+///
+/// ```
+/// let spikes: Vec<Vec<usize>> = chunk_map(&channels, 30000.0, 10.0, 0.002, |view| detect_in_core(&view))?;
+/// ```
+///
+/// # Note
+///
+/// The cores partition the signal, so a function that only reports what lies in
+/// `view.core` counts every sample exactly once; the overlap, cut short at the ends of the
+/// signal, only gives it context. The chunks are handed out to the available CPU cores as
+/// they become free, but the output keeps the order of the chunks. Once a chunk fails, no
+/// new chunk is started.
+///
+pub fn chunk_map<'a, T, E, F>(channels: &'a [Vec<f64>], sampling_rate: f64, chunk_s: f64, overlap_s: f64, f: F) -> Result<Vec<T>, ChunkError<E>>
+where
+    T: Send,
+    E: Send,
+    F: Fn(ChunkView<'a>) -> Result<T, E> + Sync,
+{
+    validate_sampling_rate(sampling_rate).map_err(ChunkError::Setup)?;
+    let chunk_len = (chunk_s * sampling_rate).round();
+    let overlap_len = (overlap_s * sampling_rate).round();
+    if chunk_len.is_nan() || chunk_len < 1.0 || chunk_len.is_infinite() || overlap_len.is_nan() || overlap_len < 0.0 || overlap_len.is_infinite() {
+        return Err(ChunkError::Setup(ProcessingError::InvalidParameter(format!(
+            "Chunks must be at least one sample long and the overlap not negative, got {} s and {} s",
+            chunk_s, overlap_s
+        ))));
+    }
+    let (chunk_len, overlap_len) = (chunk_len as usize, overlap_len as usize);
+    let length = channels.first().map_or(0, |channel| channel.len());
+    if channels.iter().any(|channel| channel.len() != length) {
+        return Err(ChunkError::Setup(ProcessingError::InvalidParameter("Channels must have the same length".to_string())));
+    }
+
+    let n_chunks = length.div_ceil(chunk_len);
+    let core_of = |index: usize| index * chunk_len..((index + 1) * chunk_len).min(length);
+    let states = vec![(); worker_count(n_chunks)];
+    try_map_tasks(states, n_chunks, |_, index| {
+        let core = core_of(index);
+        let view = core.start.saturating_sub(overlap_len)..(core.end + overlap_len).min(length);
+        f(ChunkView {
+            index,
+            channels: channels.iter().map(|channel| &channel[view.clone()]).collect(),
+            offset: view.start,
+            core: core.start - view.start..core.end - view.start,
+            sampling_rate,
+        })
+    })
+    .map_err(|(index, error)| {
+        let core = core_of(index);
+        ChunkError::Chunk { index, start: core.start as f64 / sampling_rate, end: core.end as f64 / sampling_rate, error }
+    })
+}
+
+/// Maps a function over consecutive chunks of rows of a csv file in parallel
+///
+/// # Arguments
+///
+/// * `reader` - The reader of the csv file, indexed first if it has no RowIndex yet
+/// * `chunk_rows` - The number of rows of the core of each chunk
+/// * `overlap_rows` - The number of rows of context added on both sides of each core
+/// * `f` - The function called on every chunk
+///
+/// # Returns
+///
+/// The results of the chunks in file order, or the error of the earliest failed chunk
+///
+/// # Examples
+///
+/// ```
+/// let mut reader = CsvReader::open("events.csv")?;
+/// let per_chunk = chunk_map_records(&mut reader, 50000, 0, |chunk| parse_events(chunk.core_records()))?;
+/// ```
+///
+/// # Note
+///
+/// Every worker thread reads its chunks through its own clone of the reader, so only the
+/// chunks being processed are held in memory. The position of `reader` is not changed.
+///
+pub fn chunk_map_records<T, E, F>(reader: &mut CsvReader, chunk_rows: usize, overlap_rows: usize, f: F) -> Result<Vec<T>, ChunkError<E>>
+where
+    T: Send,
+    E: Send,
+    F: Fn(RecordChunk) -> Result<T, E> + Sync,
+{
+    if chunk_rows == 0 {
+        return Err(ChunkError::Setup(ProcessingError::InvalidParameter("Chunks must hold at least one row".to_string())));
+    }
+    let n_rows = reader.build_index().map_err(ChunkError::Io)?.len();
+    let n_chunks = n_rows.div_ceil(chunk_rows);
+    let readers = (0..worker_count(n_chunks)).map(|_| reader.try_clone()).collect::<io::Result<Vec<_>>>().map_err(ChunkError::Io)?;
+    let core_of = |index: usize| index * chunk_rows..((index + 1) * chunk_rows).min(n_rows);
+    try_map_tasks(readers, n_chunks, |reader, index| {
+        let core = core_of(index);
+        let rows = core.start.saturating_sub(overlap_rows)..(core.end + overlap_rows).min(n_rows);
+        let records = reader.read_rows(rows.clone()).map_err(ChunkError::Io)?;
+        let chunk = RecordChunk { index, records, offset: rows.start, core: core.start - rows.start..core.end - rows.start };
+        f(chunk).map_err(|error| ChunkError::Rows { index, first_row: core.start, end_row: core.end, error })
+    })
+    .map_err(|(_, error)| error)
+}
+
+/// Returns the number of worker threads used for `n_tasks` tasks
+pub(crate) fn worker_count(n_tasks: usize) -> usize {
+    std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(n_tasks).max(1)
+}
+
+/// Runs the tasks `0..n_tasks` on one thread per worker state and returns their results in task order
+///
+/// Each thread takes the next task as soon as it is free and owns one of `states`, e.g. its
+/// own file handle. Once a task fails no new task is started, and the error of the failed
+/// task with the lowest index is returned with that index.
+pub(crate) fn try_map_tasks<S, T, E, F>(states: Vec<S>, n_tasks: usize, f: F) -> Result<Vec<T>, (usize, E)>
+where
+    S: Send,
+    T: Send,
+    E: Send,
+    F: Fn(&mut S, usize) -> Result<T, E> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let cancelled = AtomicBool::new(false);
+    let (next, cancelled, f) = (&next, &cancelled, &f);
+    let mut results: Vec<(usize, Result<T, E>)> = std::thread::scope(|scope| {
+        let handles: Vec<_> = states
+            .into_iter()
+            .map(|mut state| {
+                scope.spawn(move || {
+                    let mut done = Vec::new();
+                    while !cancelled.load(Ordering::Relaxed) {
+                        let task = next.fetch_add(1, Ordering::Relaxed);
+                        if task >= n_tasks {
+                            break;
+                        }
+                        let result = f(&mut state, task);
+                        if result.is_err() {
+                            cancelled.store(true, Ordering::Relaxed);
+                        }
+                        done.push((task, result));
+                    }
+                    done
+                })
+            })
+            .collect();
+        handles.into_iter().flat_map(|handle| handle.join().expect("Chunk worker thread panicked")).collect()
+    });
+    results.sort_by_key(|(task, _)| *task);
+    results.into_iter().map(|(task, result)| result.map_err(|error| (task, error))).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The sum of each sample and its two neighbours on both sides
+    fn window_sums(samples: &[f64]) -> Vec<f64> {
+        (0..samples.len()).map(|i| samples[i.saturating_sub(2)..(i + 3).min(samples.len())].iter().sum()).collect()
+    }
+
+    #[test]
+    fn chunks_with_context_give_the_output_of_the_whole_signal() {
+        let channels: Vec<Vec<f64>> = (0..3).map(|c| (0..1001).map(|i| ((i * 7 + c * 13) % 17) as f64).collect()).collect();
+        let per_chunk = chunk_map(&channels, 100.0, 0.37, 0.02, |view| {
+            Ok::<_, ProcessingError>(view.channels.iter().map(|samples| view.trim(&window_sums(samples)).to_vec()).collect::<Vec<_>>())
+        })
+        .unwrap();
+        assert_eq!(per_chunk.len(), 28);
+        for (channel, samples) in channels.iter().enumerate() {
+            let joined: Vec<f64> = per_chunk.iter().flat_map(|chunk| chunk[channel].clone()).collect();
+            assert_eq!(joined, window_sums(samples));
+        }
+    }
+
+    #[test]
+    fn the_earliest_failed_chunk_is_reported_in_seconds_or_rows() {
+        let channels = vec![vec![0.0; 1000]];
+        let result = chunk_map(&channels, 100.0, 1.0, 0.0, |view| if view.index >= 3 { Err(view.index) } else { Ok(()) });
+        match result {
+            Err(ChunkError::Chunk { index, start, end, error }) => assert_eq!((index, start, end, error), (3, 3.0, 4.0, 3)),
+            _ => panic!("chunk 3 should fail"),
+        }
+
+        let path = std::env::temp_dir().join(format!("neurorust-parallel-{}-rows.csv", std::process::id()));
+        let rows: String = (0..25).map(|i| format!("{}\n", i)).collect();
+        std::fs::write(&path, format!("value\n{}", rows)).unwrap();
+        let mut reader = CsvReader::open(&path).unwrap();
+        let result = chunk_map_records(&mut reader, 10, 2, |chunk| if chunk.index == 2 { Err(chunk.records.len()) } else { Ok(()) });
+        std::fs::remove_file(&path).unwrap();
+        match result {
+            Err(ChunkError::Rows { index, first_row, end_row, error }) => assert_eq!((index, first_row, end_row, error), (2, 20, 25, 7)),
+            _ => panic!("chunk 2 should fail"),
+        }
+    }
+}
@@ -0,0 +1,341 @@
+// A module to find peaks in signals and measure their prominence and width
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+use crate::processing::spikes::Polarity;
+
+/// Options of `find_peaks`
+///
+/// # Arguments
+///
+/// * `min_height` - The smallest peak value kept, none by default; for negative peaks it applies to the negated signal, so `Some(5.0)` keeps troughs below -5
+/// * `min_prominence` - The smallest prominence kept, none by default
+/// * `min_distance` - The smallest time in seconds between kept peaks, none by default; the highest peaks are kept first
+/// * `min_width` - The smallest width in seconds kept, none by default
+/// * `rel_height` - The height at which widths are measured, as a fraction of the prominence below the peak, 0.5 (half prominence) by default
+/// * `polarity` - Whether maxima, minima or both are found, maxima by default
+///
+/// # Examples
+///
+/// ```
+/// let options = PeakOptions { min_prominence: Some(0.2), min_distance: Some(1.5), ..PeakOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct PeakOptions {
+    pub min_height: Option<f64>,
+    pub min_prominence: Option<f64>,
+    pub min_distance: Option<f64>,
+    pub min_width: Option<f64>,
+    pub rel_height: f64,
+    pub polarity: Polarity,
+}
+
+impl Default for PeakOptions {
+    fn default() -> Self {
+        Self {
+            min_height: None,
+            min_prominence: None,
+            min_distance: None,
+            min_width: None,
+            rel_height: 0.5,
+            polarity: Polarity::Positive,
+        }
+    }
+}
+
+/// A peak of a signal
+///
+/// # Arguments
+///
+/// * `index` - The sample index of the peak, the center of a plateau
+/// * `time` - The time of the peak in seconds
+/// * `height` - The value of the signal at the peak
+/// * `prominence` - How far the peak stands out from the higher of its two bases, always positive
+/// * `left_base` - The sample index of the lowest point between the peak and the nearest higher sample to its left
+/// * `right_base` - The sample index of the lowest point between the peak and the nearest higher sample to its right
+/// * `width` - The width of the peak in seconds at `rel_height` of its prominence, interpolated between samples
+///
+/// # Examples
+///
+/// ```
+/// let peaks = find_peaks(&samples, 250.0, 0.0, &PeakOptions::default())?;
+/// let widest = peaks.iter().fold(0.0, |widest: f64, peak| widest.max(peak.width));
+/// ```
+///
+/// # Note
+///
+/// For negative peaks, the bases are the highest points and the prominence is measured downwards
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Peak {
+    pub index: usize,
+    pub time: f64,
+    pub height: f64,
+    pub prominence: f64,
+    pub left_base: usize,
+    pub right_base: usize,
+    pub width: f64,
+}
+
+/// Finds the peaks of a signal
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample in seconds
+/// * `options` - The constraints on the peaks
+///
+/// # Returns
+///
+/// The peaks that satisfy every constraint, ordered by index, or an error if the sampling
+/// rate is not positive, `rel_height` is negative or a constraint is negative
+///
+/// # Examples
+///
+/// ```
+/// // One peak per breath, at least 1.5 s apart
+/// let breaths = find_peaks(&respiration, 25.0, 0.0, &PeakOptions { min_distance: Some(1.5), ..PeakOptions::default() })?;
+/// // The N1 trough of an ERP
+/// let options = PeakOptions { polarity: Polarity::Negative, min_prominence: Some(2e-6), ..PeakOptions::default() };
+/// let troughs = find_peaks(&erp, 500.0, -0.2, &options)?;
+/// ```
+///
+/// # Note
+///
+/// The constraints are applied in the order of scipy.signal.find_peaks (height, distance,
+/// prominence, width), and peaks, prominences and widths match it without `wlen`. A peak is
+/// a sample, or a plateau of equal samples, higher than both of its neighbours, so the first
+/// and last samples are never peaks and NaN samples never form one. With `Polarity::Both`,
+/// maxima and minima are found separately and merged, so `min_distance` only separates
+/// peaks of the same sign.
+///
+pub fn find_peaks(samples: &[f64], sampling_rate: f64, start_time: f64, options: &PeakOptions) -> Result<Vec<Peak>, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    let constraints = [options.min_prominence, options.min_distance, options.min_width];
+    let is_invalid = |value: f64| value.is_nan() || value < 0.0;
+    if is_invalid(options.rel_height) || constraints.iter().flatten().any(|&value| is_invalid(value)) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Peak constraints and relative height must be non-negative, got {:?}",
+            options
+        )));
+    }
+    let mut peaks = match options.polarity {
+        Polarity::Positive => find_maxima(samples, sampling_rate, start_time, options, 1.0),
+        Polarity::Negative => find_maxima(samples, sampling_rate, start_time, options, -1.0),
+        Polarity::Both => {
+            let mut peaks = find_maxima(samples, sampling_rate, start_time, options, 1.0);
+            peaks.extend(find_maxima(samples, sampling_rate, start_time, options, -1.0));
+            peaks
+        }
+    };
+    peaks.sort_by_key(|peak| peak.index);
+    Ok(peaks)
+}
+
+/// Writes peaks as `index,time,height,prominence,width` rows
+///
+/// # Arguments
+///
+/// * `peaks` - The peaks to write
+/// * `csv_io` - The CsvIO object to write to
+///
+/// # Returns
+///
+/// Nothing, or an error if a row cannot be written
+///
+/// # Examples
+///
+/// ```
+/// to_csv(&peaks, &mut csv_io)?;
+/// csv_io.save()?;
+/// ```
+///
+/// # Note
+///
+/// A header row is written first. The rows are not flushed to disk until `save` is called.
+///
+pub fn to_csv(peaks: &[Peak], csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+    let float_format = csv_io.float_format();
+    csv_io.write_record(StringRecord::from(vec!["index", "time", "height", "prominence", "width"]))?;
+    for peak in peaks {
+        csv_io.write_record(StringRecord::from(vec![
+            peak.index.to_string(),
+            float_format.format(peak.time),
+            float_format.format(peak.height),
+            float_format.format(peak.prominence),
+            float_format.format(peak.width),
+        ]))?;
+    }
+    Ok(())
+}
+
+/// Finds the maxima of `sign * samples` that satisfy the options
+fn find_maxima(samples: &[f64], sampling_rate: f64, start_time: f64, options: &PeakOptions, sign: f64) -> Vec<Peak> {
+    let x: Vec<f64> = samples.iter().map(|sample| sign * sample).collect();
+    let mut indices = local_maxima(&x);
+    if let Some(min_height) = options.min_height {
+        indices.retain(|&index| x[index] >= min_height);
+    }
+    if let Some(min_distance) = options.min_distance {
+        // Tolerate rounding so that e.g. 0.01 s at 1 kHz is exactly 10 samples
+        let distance = (min_distance * sampling_rate - 1e-9).ceil().max(1.0) as usize;
+        indices = select_by_distance(&x, &indices, distance);
+    }
+
+    let mut peaks = Vec::with_capacity(indices.len());
+    for index in indices {
+        let (prominence, left_base, right_base) = prominence(&x, index);
+        if options.min_prominence.is_some_and(|min_prominence| prominence < min_prominence) {
+            continue;
+        }
+        let width = width(&x, index, prominence, left_base, right_base, options.rel_height) / sampling_rate;
+        if options.min_width.is_some_and(|min_width| width < min_width) {
+            continue;
+        }
+        peaks.push(Peak {
+            index,
+            time: start_time + index as f64 / sampling_rate,
+            height: samples[index],
+            prominence,
+            left_base,
+            right_base,
+            width,
+        });
+    }
+    peaks
+}
+
+/// Finds the samples higher than both neighbours, taking the center (rounded down) of plateaus
+fn local_maxima(x: &[f64]) -> Vec<usize> {
+    let mut maxima = Vec::new();
+    let mut i = 1;
+    while i + 1 < x.len() {
+        if x[i - 1] < x[i] {
+            let mut ahead = i + 1;
+            while ahead + 1 < x.len() && x[ahead] == x[i] {
+                ahead += 1;
+            }
+            if x[ahead] < x[i] {
+                maxima.push((i + ahead - 1) / 2);
+                i = ahead;
+            }
+        }
+        i += 1;
+    }
+    maxima
+}
+
+/// Keeps the highest peaks and removes every lower peak closer than `distance` samples to a kept one
+fn select_by_distance(x: &[f64], indices: &[usize], distance: usize) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..indices.len()).collect();
+    order.sort_by(|&a, &b| x[indices[a]].total_cmp(&x[indices[b]]));
+    let mut keep = vec![true; indices.len()];
+    for &position in order.iter().rev() {
+        if !keep[position] {
+            continue;
+        }
+        for other in (0..position).rev().take_while(|&other| indices[position] - indices[other] < distance) {
+            keep[other] = false;
+        }
+        for other in (position + 1..indices.len()).take_while(|&other| indices[other] - indices[position] < distance) {
+            keep[other] = false;
+        }
+    }
+    indices.iter().zip(&keep).filter(|(_, &kept)| kept).map(|(&index, _)| index).collect()
+}
+
+/// Computes the prominence and the left and right bases of a peak
+fn prominence(x: &[f64], peak: usize) -> (f64, usize, usize) {
+    let mut left_base = peak;
+    let mut left_min = x[peak];
+    for i in (0..peak).rev().take_while(|&i| x[i] <= x[peak]) {
+        if x[i] < left_min {
+            left_min = x[i];
+            left_base = i;
+        }
+    }
+    let mut right_base = peak;
+    let mut right_min = x[peak];
+    for i in (peak + 1..x.len()).take_while(|&i| x[i] <= x[peak]) {
+        if x[i] < right_min {
+            right_min = x[i];
+            right_base = i;
+        }
+    }
+    (x[peak] - left_min.max(right_min), left_base, right_base)
+}
+
+/// Computes the width of a peak in samples at `rel_height` of its prominence below it
+fn width(x: &[f64], peak: usize, prominence: f64, left_base: usize, right_base: usize, rel_height: f64) -> f64 {
+    let height = x[peak] - prominence * rel_height;
+    let mut i = peak;
+    while left_base < i && height < x[i] {
+        i -= 1;
+    }
+    let mut left = i as f64;
+    if x[i] < height {
+        left += (height - x[i]) / (x[i + 1] - x[i]);
+    }
+    let mut i = peak;
+    while i < right_base && height < x[i] {
+        i += 1;
+    }
+    let mut right = i as f64;
+    if x[i] < height {
+        right -= (height - x[i]) / (x[i - 1] - x[i]);
+    }
+    right - left
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The example signal of the scipy.signal.peak_prominences documentation
+    fn scipy_example() -> Vec<f64> {
+        (0..1000)
+            .map(|k| {
+                let x = 6.0 * std::f64::consts::PI * k as f64 / 999.0;
+                x.sin() + 0.6 * (2.6 * x).sin()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn prominences_match_scipy() {
+        let peaks = find_peaks(&scipy_example(), 1.0, 0.0, &PeakOptions::default()).unwrap();
+        let indices: Vec<usize> = peaks.iter().map(|peak| peak.index).collect();
+        assert_eq!(indices, vec![42, 147, 299, 416, 533, 685, 791, 933]);
+        // The prominences printed by scipy for this signal
+        let expected = [1.24159486, 0.47840168, 0.28470524, 3.10716793, 0.284603, 0.47822491, 2.48340261, 0.47822491];
+        for (peak, expected) in peaks.iter().zip(expected) {
+            assert!((peak.prominence - expected).abs() < 5e-9, "{} at {}", peak.prominence, peak.index);
+        }
+    }
+
+    #[test]
+    fn distance_keeps_the_highest_peaks() {
+        let options = PeakOptions { min_distance: Some(200.0), ..PeakOptions::default() };
+        let peaks = find_peaks(&scipy_example(), 1.0, 0.0, &options).unwrap();
+        assert_eq!(peaks.iter().map(|peak| peak.index).collect::<Vec<_>>(), vec![42, 416, 791]);
+    }
+
+    #[test]
+    fn plateaus_take_their_middle_and_widths_interpolate() {
+        let samples = [0.0, 1.0, 3.0, 3.0, 3.0, 3.0, 1.0, 0.0, 2.0, 0.5];
+        let peaks = find_peaks(&samples, 1.0, 0.0, &PeakOptions::default()).unwrap();
+        assert_eq!(peaks.len(), 2);
+        assert_eq!((peaks[0].index, peaks[0].prominence, peaks[0].left_base, peaks[0].right_base), (3, 3.0, 0, 7));
+        assert_eq!(peaks[0].width, 4.5);
+        assert_eq!((peaks[1].index, peaks[1].prominence, peaks[1].width), (8, 1.5, 0.875));
+        let wide = find_peaks(&samples, 1.0, 0.0, &PeakOptions { min_width: Some(2.0), ..PeakOptions::default() }).unwrap();
+        assert_eq!(wide.iter().map(|peak| peak.index).collect::<Vec<_>>(), vec![3]);
+    }
+}
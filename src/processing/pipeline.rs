@@ -0,0 +1,2211 @@
+// A module to run preprocessing stages over multi-channel signals chunk by chunk
+
+// Written by Amin Alam in 2024
+
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use csv::{Reader, ReaderBuilder, StringRecord};
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::data_io::float_format::FloatFormat;
+use crate::processing::checkpoint::{Checkpoint, Fnv, InputIdentity};
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::{butterworth, filter_section, validate_sampling_rate, FilterKind, FirFilter, IirFilter};
+use crate::processing::resample::{decimation_stages, DECIMATION_CUTOFF_FRACTION, DECIMATION_FILTER_ORDER};
+
+/// The number of samples per channel read from the source at a time by default
+pub const DEFAULT_CHUNK_SIZE: usize = 65536;
+
+/// The size, relative to the signal, below which the transient of a block cut counts as gone when sizing the overlap of `Pipeline::zero_phase`
+const OVERLAP_TOLERANCE: f64 = 1e-12;
+
+/// The errors returned when running a Pipeline
+///
+/// # Arguments
+///
+/// * `Processing` - A stage was misconfigured or failed on a chunk
+/// * `Io` - The source could not be read or the sink could not be written
+/// * `Interrupted` - The run failed after output was emitted, with the number of samples per channel already written to the sink, counted row by row for the sinks of this module
+///
+/// # Examples
+///
+/// ```
+/// match pipeline.collect() {
+///     Err(PipelineError::Io(error)) => println!("Could not read the recording: {}", error),
+///     _ => {}
+/// }
+/// ```
+#[derive(Debug)]
+pub enum PipelineError {
+    Processing(ProcessingError),
+    Io(io::Error),
+    Interrupted { samples_written: usize, error: Box<PipelineError> },
+}
+
+impl fmt::Display for PipelineError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PipelineError::Processing(error) => write!(f, "{}", error),
+            PipelineError::Io(error) => write!(f, "I/O error: {}", error),
+            PipelineError::Interrupted { samples_written, error } => {
+                write!(f, "{} (after {} samples per channel were written)", error, samples_written)
+            }
+        }
+    }
+}
+
+impl Error for PipelineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PipelineError::Processing(error) => Some(error),
+            PipelineError::Io(error) => Some(error),
+            PipelineError::Interrupted { error, .. } => Some(error.as_ref()),
+        }
+    }
+}
+
+impl From<ProcessingError> for PipelineError {
+    fn from(error: ProcessingError) -> Self {
+        PipelineError::Processing(error)
+    }
+}
+
+impl From<io::Error> for PipelineError {
+    fn from(error: io::Error) -> Self {
+        PipelineError::Io(error)
+    }
+}
+
+impl From<csv::Error> for PipelineError {
+    fn from(error: csv::Error) -> Self {
+        PipelineError::Io(error.into())
+    }
+}
+
+impl From<DataIoError> for PipelineError {
+    fn from(error: DataIoError) -> Self {
+        match error {
+            DataIoError::Processing(error) => PipelineError::Processing(error),
+            error => PipelineError::Io(error.into()),
+        }
+    }
+}
+
+/// The progress of a running Pipeline, passed to the callback set with `on_progress`
+///
+/// # Arguments
+///
+/// * `chunks` - The number of chunks processed so far
+/// * `samples_read` - The number of samples per channel read from the source so far
+/// * `total_samples` - The number of samples per channel in the source, if known in advance
+///
+/// # Examples
+///
+/// ```
+/// let pipeline = pipeline.on_progress(|progress: &Progress| {
+///     if let Some(fraction) = progress.fraction() { println!("{:.0}%", 100.0 * fraction); }
+/// });
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub chunks: usize,
+    pub samples_read: usize,
+    pub total_samples: Option<usize>,
+}
+
+/// Implementation of the Progress struct
+///
+/// # Methods
+///
+/// * `fraction` - Returns the fraction of the source processed so far
+impl Progress {
+    /// Returns the fraction of the source processed so far
+    ///
+    /// # Returns
+    ///
+    /// The fraction between 0 and 1, or None if the length of the source is not known
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let percent = progress.fraction().map(|fraction| 100.0 * fraction);
+    /// ```
+    ///
+    pub fn fraction(&self) -> Option<f64> {
+        self.total_samples.map(|total| if total == 0 { 1.0 } else { self.samples_read as f64 / total as f64 })
+    }
+}
+
+/// The signal that a Pipeline reads chunk by chunk
+///
+/// # Examples
+///
+/// ```
+/// let source = SignalSource::from_csv("wideband.csv", 30000.0, Some("time"))?;
+/// let source = SignalSource::from_channels(&channels, &names, 30000.0)?;
+/// ```
+///
+/// # Note
+///
+/// A CSV source holds one sample per row and one channel per column, and only the current
+/// chunk is kept in memory
+pub struct SignalSource<'a> {
+    names: Vec<String>,
+    sampling_rate: f64,
+    kind: SourceKind<'a>,
+}
+
+enum SourceKind<'a> {
+    Channels { channels: &'a [Vec<f64>], position: usize },
+    Csv {
+        reader: Reader<Box<dyn Read + 'a>>,
+        columns: Vec<usize>,
+        record: StringRecord,
+        path: Option<PathBuf>,
+        byte_offset: u64,
+        line_offset: u64,
+    },
+}
+
+/// Implementation of the SignalSource struct
+///
+/// # Methods
+///
+/// * `from_channels` - Creates a SignalSource over channels held in memory
+/// * `from_csv` - Creates a SignalSource that streams the columns of a CSV file
+/// * `from_reader` - Creates a SignalSource that streams CSV data from any reader
+/// * `from_stdin` - Creates a SignalSource that streams CSV data from the standard input
+/// * `names` - Returns the channel names
+/// * `sampling_rate` - Returns the sampling rate
+impl<'a> SignalSource<'a> {
+    /// Creates a SignalSource over channels held in memory
+    ///
+    /// # Arguments
+    ///
+    /// * `channels` - The samples of each channel
+    /// * `names` - The name of each channel
+    /// * `sampling_rate` - The sampling rate in Hz
+    ///
+    /// # Returns
+    ///
+    /// The SignalSource, or an error if the sampling rate is not positive, the number of names
+    /// differs from the number of channels or the channels differ in length
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let source = SignalSource::from_channels(&channels, &["CA1".to_string(), "CA3".to_string()], 30000.0)?;
+    /// ```
+    ///
+    pub fn from_channels(channels: &'a [Vec<f64>], names: &[String], sampling_rate: f64) -> Result<Self, ProcessingError> {
+        validate_sampling_rate(sampling_rate)?;
+        if names.len() != channels.len() {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Got {} names for {} channels",
+                names.len(),
+                channels.len()
+            )));
+        }
+        let length = channels.first().map_or(0, |channel| channel.len());
+        if channels.iter().any(|channel| channel.len() != length) {
+            return Err(ProcessingError::InvalidParameter("Channels must have the same length".to_string()));
+        }
+        Ok(Self { names: names.to_vec(), sampling_rate, kind: SourceKind::Channels { channels, position: 0 } })
+    }
+
+    /// Creates a SignalSource that streams the columns of a CSV file
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path to the CSV file, with a header row naming the channels
+    /// * `sampling_rate` - The sampling rate in Hz
+    /// * `time_column` - The name of a column that is left out of the channels, e.g. a timestamp column
+    ///
+    /// # Returns
+    ///
+    /// The SignalSource, or an error if the sampling rate is not positive, the file cannot be
+    /// opened, the time column is missing or no other column is left
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let source = SignalSource::from_csv("wideband.csv", 30000.0, Some("time"))?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The file is only opened for reading. A field that is not a number
+    /// fails the run with an `InvalidData` I/O error naming its row.
+    ///
+    pub fn from_csv(file_path: &str, sampling_rate: f64, time_column: Option<&str>) -> Result<Self, PipelineError> {
+        validate_sampling_rate(sampling_rate)?;
+        let mut source = Self::from_reader(BufReader::new(File::open(file_path)?), sampling_rate, time_column)?;
+        if let SourceKind::Csv { path, .. } = &mut source.kind {
+            *path = Some(PathBuf::from(file_path));
+        }
+        Ok(source)
+    }
+
+    /// Creates a SignalSource that streams CSV data from any reader
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The CSV data, with a header row naming the channels
+    /// * `sampling_rate` - The sampling rate in Hz
+    /// * `time_column` - The name of a column that is left out of the channels
+    ///
+    /// # Returns
+    ///
+    /// The SignalSource, or an error if the sampling rate is not positive, the header row
+    /// cannot be read, the time column is missing or no other column is left
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let socket = TcpStream::connect("acquisition:9000")?;
+    /// let source = SignalSource::from_reader(socket, 1000.0, None)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The reader is only read forwards, so pipes and sockets work as well as files
+    ///
+    pub fn from_reader<R: Read + 'a>(reader: R, sampling_rate: f64, time_column: Option<&str>) -> Result<Self, PipelineError> {
+        validate_sampling_rate(sampling_rate)?;
+        let mut reader = Reader::from_reader(Box::new(reader) as Box<dyn Read + 'a>);
+        let headers = reader.headers()?.clone();
+        if let Some(time_column) = time_column {
+            if !headers.iter().any(|header| header == time_column) {
+                return Err(ProcessingError::InvalidParameter(format!("No column named {}", time_column)).into());
+            }
+        }
+        let (columns, names): (Vec<usize>, Vec<String>) = headers
+            .iter()
+            .enumerate()
+            .filter(|(_, header)| Some(*header) != time_column)
+            .map(|(i, header)| (i, header.to_string()))
+            .unzip();
+        if columns.is_empty() {
+            return Err(ProcessingError::InvalidParameter("The CSV data has no channel columns".to_string()).into());
+        }
+        let kind = SourceKind::Csv { reader, columns, record: StringRecord::new(), path: None, byte_offset: 0, line_offset: 0 };
+        Ok(Self { names, sampling_rate, kind })
+    }
+
+    /// Creates a SignalSource that streams CSV data from the standard input
+    ///
+    /// # Arguments
+    ///
+    /// * `sampling_rate` - The sampling rate in Hz
+    /// * `time_column` - The name of a column that is left out of the channels
+    ///
+    /// # Returns
+    ///
+    /// The SignalSource, or an error as in `from_reader`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // decimate_filter < wideband.csv > lfp.csv
+    /// let summary = Pipeline::source(SignalSource::from_stdin(30000.0, Some("time"))?).decimate(30).to_stdout()?;
+    /// ```
+    ///
+    pub fn from_stdin(sampling_rate: f64, time_column: Option<&str>) -> Result<SignalSource<'static>, PipelineError> {
+        SignalSource::from_reader(io::stdin().lock(), sampling_rate, time_column)
+    }
+
+    /// Returns the channel names
+    ///
+    /// # Returns
+    ///
+    /// The name of each channel, in source order
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let names = source.names();
+    /// ```
+    ///
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+
+    /// Returns the sampling rate
+    ///
+    /// # Returns
+    ///
+    /// The sampling rate in Hz
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let fs = source.sampling_rate();
+    /// ```
+    ///
+    pub fn sampling_rate(&self) -> f64 {
+        self.sampling_rate
+    }
+
+    /// Returns the number of samples per channel, if known without reading the source
+    fn total_samples(&self) -> Option<usize> {
+        match &self.kind {
+            SourceKind::Channels { channels, .. } => Some(channels.first().map_or(0, |channel| channel.len())),
+            SourceKind::Csv { .. } => None,
+        }
+    }
+
+    /// Returns the identity of the source, or an error if it cannot be read again on resume
+    fn identity(&self) -> Result<InputIdentity, PipelineError> {
+        match &self.kind {
+            SourceKind::Channels { channels, .. } => Ok(InputIdentity::of_channels(channels)),
+            SourceKind::Csv { path: Some(path), .. } => Ok(InputIdentity::of_file(path)?),
+            SourceKind::Csv { path: None, .. } => {
+                Err(io::Error::new(io::ErrorKind::Unsupported, "Only file and in-memory sources can be checkpointed").into())
+            }
+        }
+    }
+
+    /// Returns the offset and line number of the next sample, as stored in a Checkpoint
+    fn offset(&self) -> (u64, u64) {
+        match &self.kind {
+            SourceKind::Channels { position, .. } => (*position as u64, 0),
+            SourceKind::Csv { reader, byte_offset, line_offset, .. } => {
+                (byte_offset + reader.position().byte(), line_offset + reader.position().line())
+            }
+        }
+    }
+
+    /// Moves the source to an offset and line number returned by `offset`
+    fn seek(&mut self, offset: u64, line: u64) -> Result<(), PipelineError> {
+        match &mut self.kind {
+            SourceKind::Channels { channels, position } => {
+                if offset as usize > channels.first().map_or(0, |channel| channel.len()) {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, "Checkpoint offset is past the end of the channels").into());
+                }
+                *position = offset as usize;
+            }
+            SourceKind::Csv { reader, path, byte_offset, line_offset, .. } => {
+                let mut file = File::open(path.as_ref().ok_or(io::Error::from(io::ErrorKind::Unsupported))?)?;
+                file.seek(SeekFrom::Start(offset))?;
+                // The header row lies before the offset, so every row read from here on is data
+                *reader = ReaderBuilder::new().has_headers(false).from_reader(Box::new(BufReader::new(file)) as Box<dyn Read + 'a>);
+                *byte_offset = offset;
+                *line_offset = line.saturating_sub(1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads up to `chunk_size` samples of every channel, or None once the source is exhausted
+    fn next_chunk(&mut self, chunk_size: usize) -> Result<Option<Vec<Vec<f64>>>, PipelineError> {
+        match &mut self.kind {
+            SourceKind::Channels { channels, position } => {
+                let length = channels.first().map_or(0, |channel| channel.len());
+                if *position >= length {
+                    return Ok(None);
+                }
+                let end = (*position + chunk_size).min(length);
+                let chunk = channels.iter().map(|channel| channel[*position..end].to_vec()).collect();
+                *position = end;
+                Ok(Some(chunk))
+            }
+            SourceKind::Csv { reader, columns, record, line_offset, .. } => {
+                let mut chunk = vec![Vec::with_capacity(chunk_size); columns.len()];
+                while chunk[0].len() < chunk_size && reader.read_record(record)? {
+                    for (channel, &column) in chunk.iter_mut().zip(columns.iter()) {
+                        let field = record.get(column).unwrap_or("");
+                        let value = field.trim().parse::<f64>().map_err(|_| {
+                            let line = *line_offset + record.position().map_or(0, |position| position.line());
+                            io::Error::new(io::ErrorKind::InvalidData, format!("Line {}: {:?} is not a number", line, field))
+                        })?;
+                        channel.push(value);
+                    }
+                }
+                Ok(if chunk[0].is_empty() { None } else { Some(chunk) })
+            }
+        }
+    }
+}
+
+/// A processing step of a Pipeline that keeps its own state between chunks
+///
+/// # Methods
+///
+/// * `configure` - Prepares the stage for the channels and sampling rate it receives
+/// * `process` - Processes the next chunk
+/// * `finish` - Returns the samples still held back once the source is exhausted
+/// * `describe` - Describes the settings of the stage
+/// * `state` - Returns the state carried between chunks
+/// * `restore` - Restores a state returned by `state`
+///
+/// # Examples
+///
+/// ```
+/// struct Rectify;
+///
+/// impl Stage for Rectify {
+///     fn configure(&mut self, names: &[String], sampling_rate: f64) -> Result<(Vec<String>, f64), ProcessingError> {
+///         Ok((names.to_vec(), sampling_rate))
+///     }
+///
+///     fn process(&mut self, mut chunk: Vec<Vec<f64>>) -> Result<Vec<Vec<f64>>, ProcessingError> {
+///         chunk.iter_mut().flatten().for_each(|sample| *sample = sample.abs());
+///         Ok(chunk)
+///     }
+/// }
+///
+/// let pipeline = pipeline.stage(Rectify);
+/// ```
+///
+/// # Note
+///
+/// For the chunked output to equal the output of a single chunk, `process` must carry over
+/// whatever it needs from earlier samples, such as filter state or the phase of a decimation
+pub trait Stage {
+    /// Prepares the stage for the channels and sampling rate it receives
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - The names of the incoming channels
+    /// * `sampling_rate` - The incoming sampling rate in Hz
+    ///
+    /// # Returns
+    ///
+    /// The names of the outgoing channels and the outgoing sampling rate, or an error if the
+    /// stage cannot handle its input
+    ///
+    fn configure(&mut self, names: &[String], sampling_rate: f64) -> Result<(Vec<String>, f64), ProcessingError>;
+
+    /// Processes the next chunk
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk` - The samples following the previous chunk, one row per incoming channel
+    ///
+    /// # Returns
+    ///
+    /// The outgoing samples, one row per outgoing channel and possibly with no samples, or an error
+    ///
+    fn process(&mut self, chunk: Vec<Vec<f64>>) -> Result<Vec<Vec<f64>>, ProcessingError>;
+
+    /// Returns the samples still held back once the source is exhausted
+    ///
+    /// # Returns
+    ///
+    /// The remaining outgoing samples, or None if the stage holds nothing back
+    ///
+    fn finish(&mut self) -> Result<Option<Vec<Vec<f64>>>, ProcessingError> {
+        Ok(None)
+    }
+
+    /// Describes the settings of the stage
+    ///
+    /// # Returns
+    ///
+    /// A description hashed into checkpoints, so that a run is not resumed with other settings
+    ///
+    fn describe(&self) -> String {
+        String::new()
+    }
+
+    /// Returns the state carried between chunks
+    ///
+    /// # Returns
+    ///
+    /// The state as a flat vector, empty for a stateless stage, or None if the stage cannot be
+    /// checkpointed, which is the default
+    ///
+    fn state(&self) -> Option<Vec<f64>> {
+        None
+    }
+
+    /// Restores a state returned by `state`, after `configure` was called with the same input
+    ///
+    /// # Arguments
+    ///
+    /// * `state` - The state stored in a checkpoint
+    ///
+    fn restore(&mut self, state: &[f64]) -> Result<(), ProcessingError> {
+        let _ = state;
+        Err(ProcessingError::InvalidParameter("Stage cannot be restored from a checkpoint".to_string()))
+    }
+}
+
+/// The destination of the samples produced by a Pipeline
+///
+/// # Methods
+///
+/// * `start` - Prepares the sink for the channels it receives
+/// * `write` - Writes the next chunk
+/// * `finish` - Completes the output once every chunk is written
+/// * `is_closed` - Returns whether the reader of the output has gone away
+/// * `flush_position` - Flushes the output and returns its length in bytes
+/// * `samples_written` - Returns the number of samples per channel the sink has written
+///
+/// # Examples
+///
+/// ```
+/// let summary = pipeline.run(&mut my_sink)?;
+/// ```
+pub trait Sink {
+    /// Prepares the sink for the channels it receives
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - The names of the channels
+    /// * `sampling_rate` - The sampling rate in Hz
+    ///
+    fn start(&mut self, names: &[String], sampling_rate: f64) -> Result<(), PipelineError>;
+
+    /// Writes the next chunk
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk` - The samples following the previous chunk, one row per channel
+    ///
+    fn write(&mut self, chunk: &[Vec<f64>]) -> Result<(), PipelineError>;
+
+    /// Completes the output once every chunk is written
+    fn finish(&mut self) -> Result<(), PipelineError> {
+        Ok(())
+    }
+
+    /// Returns whether the reader of the output has gone away, which ends the run early without an error
+    fn is_closed(&self) -> bool {
+        false
+    }
+
+    /// Flushes the output and returns its length in bytes, or None if the sink cannot be checkpointed
+    fn flush_position(&mut self) -> Result<Option<u64>, PipelineError> {
+        Ok(None)
+    }
+
+    /// Returns the number of samples per channel the sink has written since it was created,
+    /// or None if it writes every chunk whole or not at all
+    ///
+    /// A sink that writes a chunk row by row counts the rows it wrote, so that a run that
+    /// stops midway through a chunk, on an error or a closed reader, reports exactly them
+    fn samples_written(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// The outcome of running a Pipeline
+///
+/// # Arguments
+///
+/// * `names` - The names of the output channels
+/// * `sampling_rate` - The sampling rate of the output in Hz
+/// * `samples_read` - The number of samples per channel read from the source
+/// * `samples_written` - The number of samples per channel written to the sink
+///
+/// # Examples
+///
+/// ```
+/// let summary = pipeline.to_binary("lfp.bin")?;
+/// println!("{} samples at {} Hz", summary.samples_written, summary.sampling_rate);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct PipelineSummary {
+    pub names: Vec<String>,
+    pub sampling_rate: f64,
+    pub samples_read: usize,
+    pub samples_written: usize,
+}
+
+/// The output of a Pipeline collected in memory
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each output channel
+/// * `names` - The names of the output channels
+/// * `sampling_rate` - The sampling rate of the output in Hz
+///
+/// # Examples
+///
+/// ```
+/// let output = pipeline.collect()?;
+/// let lfp = &output.channels[0];
+/// ```
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PipelineOutput {
+    pub channels: Vec<Vec<f64>>,
+    pub names: Vec<String>,
+    pub sampling_rate: f64,
+}
+
+/// A filter applied without phase shift by `Pipeline::zero_phase`
+///
+/// # Arguments
+///
+/// * `Iir` - An IIR filter run forwards and backwards, as by `IirFilter::filtfilt`
+/// * `Fir` - A linear-phase FIR filter with its delay removed, as by `FirFilter::apply_zero_delay`
+///
+/// # Examples
+///
+/// ```
+/// let filter = ZeroPhaseFilter::from(butterworth(4, FilterKind::Bandpass(1.0, 40.0), 1000.0)?);
+/// let overlap = filter.required_overlap()?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum ZeroPhaseFilter {
+    Iir(IirFilter),
+    Fir(FirFilter),
+}
+
+impl From<IirFilter> for ZeroPhaseFilter {
+    fn from(filter: IirFilter) -> Self {
+        ZeroPhaseFilter::Iir(filter)
+    }
+}
+
+impl From<FirFilter> for ZeroPhaseFilter {
+    fn from(filter: FirFilter) -> Self {
+        ZeroPhaseFilter::Fir(filter)
+    }
+}
+
+/// Implementation of the ZeroPhaseFilter enum
+///
+/// # Methods
+///
+/// * `sampling_rate` - Returns the sampling rate the filter was designed for
+/// * `required_overlap` - Returns the number of samples of context needed on each side of a block
+impl ZeroPhaseFilter {
+    /// Returns the sampling rate the filter was designed for
+    ///
+    /// # Returns
+    ///
+    /// The sampling rate in Hz
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let rate = filter.sampling_rate();
+    /// ```
+    ///
+    pub fn sampling_rate(&self) -> f64 {
+        match self {
+            ZeroPhaseFilter::Iir(filter) => filter.sampling_rate(),
+            ZeroPhaseFilter::Fir(filter) => filter.sampling_rate(),
+        }
+    }
+
+    /// Returns the number of samples of context needed on each side of a block
+    ///
+    /// # Returns
+    ///
+    /// The overlap in samples, or an error if the IIR filter is unstable
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let seconds = filter.required_overlap()? as f64 / filter.sampling_rate();
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// For an IIR filter it is the `impulse_response_length` to a tolerance of 1e-12, and at
+    /// least one more than the `padding_length` of `filtfilt`. For an FIR filter it is the
+    /// number of taps, beyond which a block does not depend on the cut at all.
+    ///
+    pub fn required_overlap(&self) -> Result<usize, ProcessingError> {
+        match self {
+            ZeroPhaseFilter::Iir(filter) => filter
+                .impulse_response_length(OVERLAP_TOLERANCE)
+                .map(|length| length.max(filter.padding_length() + 1))
+                .ok_or_else(|| ProcessingError::InvalidParameter("Filter has a pole on or outside the unit circle and cannot be applied in blocks".to_string())),
+            ZeroPhaseFilter::Fir(filter) => Ok(filter.taps().len()),
+        }
+    }
+
+    /// Filters a window of the signal, padding only the ends that are ends of the whole signal
+    fn apply(&self, samples: &[f64], at_start: bool, at_end: bool) -> Result<Vec<f64>, ProcessingError> {
+        match self {
+            ZeroPhaseFilter::Iir(filter) => filter.filtfilt_window(samples, at_start, at_end),
+            ZeroPhaseFilter::Fir(filter) => Ok(filter.apply_zero_delay(samples)),
+        }
+    }
+}
+
+type ProgressCallback<'a> = Box<dyn FnMut(&Progress) + 'a>;
+
+/// A chain of processing stages from a SignalSource to a sink, run chunk by chunk
+///
+/// # Examples
+///
+/// ```
+/// let highpass = butterworth(4, FilterKind::Highpass(0.5), 30000.0)?;
+/// let summary = Pipeline::source(SignalSource::from_csv("wideband.csv", 30000.0, Some("time"))?)
+///     .select_channels(&["CA1", "CA3"])
+///     .filter(highpass)
+///     .decimate(30)
+///     .map(|sample| sample * 1e6)
+///     .on_progress(|progress| eprintln!("{} samples", progress.samples_read))
+///     .to_binary("lfp.bin")?;
+/// ```
+///
+/// # Note
+///
+/// Every built-in stage is causal and carries its state across chunk boundaries, so the
+/// output does not depend on the chunk size: it is identical, sample for sample, to the
+/// output of running the whole signal as a single chunk. The exception is `zero_phase`,
+/// which looks ahead and matches filtering the whole signal to within 1e-10 of its scale.
+pub struct Pipeline<'a> {
+    source: SignalSource<'a>,
+    stages: Vec<Box<dyn Stage + 'a>>,
+    chunk_size: usize,
+    strict_output: bool,
+    float_format: FloatFormat,
+    checkpoint: Option<(PathBuf, usize)>,
+    resume: Option<Checkpoint>,
+    progress: Option<ProgressCallback<'a>>,
+}
+
+/// Implementation of the Pipeline struct
+///
+/// # Methods
+///
+/// * `source` - Starts a Pipeline reading from a SignalSource
+/// * `select_channels` - Keeps only the named channels, in the given order
+/// * `filter` - Filters every channel causally with an IIR filter
+/// * `decimate` - Lowpass filters causally and keeps every Nth sample
+/// * `zero_phase` - Filters every channel without phase shift, block by block with overlap
+/// * `map` - Transforms every sample with a function
+/// * `stage` - Appends a custom stage
+/// * `chunk_size` - Sets the number of samples per channel read at a time
+/// * `strict_output` - Sets whether output files are only written if the whole run succeeds
+/// * `float_format` - Sets the format of the numbers written by `to_csv_file` and `to_stdout`
+/// * `checkpoint` - Saves a Checkpoint at a regular interval so that a failed run can resume
+/// * `resume_from` - Continues a run from a Checkpoint
+/// * `on_progress` - Sets a callback called after every chunk
+/// * `run` - Runs the Pipeline into a sink
+/// * `to_csv` - Runs the Pipeline into a CsvIO object
+/// * `to_csv_file` - Runs the Pipeline into a CSV file
+/// * `filter_to_file` - Filters every channel without phase shift into a CSV file
+/// * `to_stdout` - Runs the Pipeline into the standard output as CSV
+/// * `to_binary` - Runs the Pipeline into a raw binary file
+/// * `collect` - Runs the Pipeline and keeps the output in memory
+impl<'a> Pipeline<'a> {
+    /// Starts a Pipeline reading from a SignalSource
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The signal to process
+    ///
+    /// # Returns
+    ///
+    /// A Pipeline with no stages that reads `DEFAULT_CHUNK_SIZE` samples at a time
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let pipeline = Pipeline::source(SignalSource::from_channels(&channels, &names, 1000.0)?);
+    /// ```
+    ///
+    pub fn source(source: SignalSource<'a>) -> Self {
+        Self { source, stages: Vec::new(), chunk_size: DEFAULT_CHUNK_SIZE, strict_output: false, float_format: FloatFormat::default(), checkpoint: None, resume: None, progress: None }
+    }
+
+    /// Keeps only the named channels, in the given order
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - The names of the channels to keep
+    ///
+    /// # Returns
+    ///
+    /// The Pipeline with the stage appended. The run fails before reading any sample if a
+    /// name is not an incoming channel or is given twice.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let pipeline = pipeline.select_channels(&["CA1", "CA3"]);
+    /// ```
+    ///
+    pub fn select_channels(self, names: &[&str]) -> Self {
+        self.stage(SelectChannels { names: names.iter().map(|name| name.to_string()).collect(), indices: Vec::new() })
+    }
+
+    /// Filters every channel causally with an IIR filter
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The filter, designed for the incoming sampling rate
+    ///
+    /// # Returns
+    ///
+    /// The Pipeline with the stage appended. The run fails if the filter was designed for a
+    /// different sampling rate.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let pipeline = pipeline.filter(notch(50.0, 30.0, 1000.0)?);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The output equals `IirFilter::apply` on each whole channel. Use `zero_phase` for the
+    /// output of `filtfilt`.
+    ///
+    pub fn filter(self, filter: IirFilter) -> Self {
+        self.stage(FilterStage::new(filter))
+    }
+
+    /// Lowpass filters causally and keeps every Nth sample
+    ///
+    /// # Arguments
+    ///
+    /// * `factor` - The ratio between the incoming and the outgoing sampling rate
+    ///
+    /// # Returns
+    ///
+    /// The Pipeline with the stage appended. The run fails if the factor is zero or too large
+    /// for the anti-aliasing filter.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let pipeline = pipeline.decimate(30);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The stages and anti-aliasing filters are those of `decimate`, but each filter is
+    /// applied forwards only, so the output is delayed by the group delay of the filters
+    /// instead of being zero-phase, and the frequencies that would alias below the cutoff are
+    /// attenuated by at least 35 dB instead of 70 dB. The first sample is kept.
+    ///
+    pub fn decimate(self, factor: usize) -> Self {
+        self.stage(Decimate { factor, stages: Vec::new() })
+    }
+
+    /// Filters every channel without phase shift, block by block with overlap
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The IIR or FIR filter, designed for the incoming sampling rate
+    /// * `block_duration` - The duration in seconds of the blocks filtered at a time, at least the `required_overlap` of the filter
+    ///
+    /// # Returns
+    ///
+    /// The Pipeline with the stage appended. The run fails if the filter was designed for a
+    /// different sampling rate, or if the blocks are shorter than the overlap, with the
+    /// shortest valid duration in the error.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let pipeline = pipeline.zero_phase(butterworth(4, FilterKind::Bandpass(1.0, 40.0), 1000.0)?, 60.0);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Each block is filtered together with `required_overlap` samples on each side, which
+    /// absorb the transients of the cuts and are then discarded. The ends of the whole
+    /// signal are padded exactly as `IirFilter::filtfilt` pads them, so the output matches
+    /// `filtfilt`, or `FirFilter::apply_zero_delay`, of each whole channel to within 1e-10
+    /// of the signal's scale, and to rounding for an FIR filter. The stage holds back up to a
+    /// block and an overlap of samples, which it releases once the source is exhausted, and
+    /// it cannot be checkpointed.
+    ///
+    pub fn zero_phase<F: Into<ZeroPhaseFilter>>(self, filter: F, block_duration: f64) -> Self {
+        self.stage(ZeroPhase { filter: filter.into(), block_duration, block_len: 0, overlap: 0, buffers: Vec::new(), buffer_start: 0, next: 0 })
+    }
+
+    /// Transforms every sample with a function
+    ///
+    /// # Arguments
+    ///
+    /// * `f` - The function applied to every sample of every channel
+    ///
+    /// # Returns
+    ///
+    /// The Pipeline with the stage appended
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // Raw ADC counts to microvolts
+    /// let pipeline = pipeline.map(|count| count * 0.195);
+    /// ```
+    ///
+    pub fn map<F: FnMut(f64) -> f64 + 'a>(self, f: F) -> Self {
+        self.stage(Map(f))
+    }
+
+    /// Appends a custom stage
+    ///
+    /// # Arguments
+    ///
+    /// * `stage` - The stage, run on the output of the previous stages
+    ///
+    /// # Returns
+    ///
+    /// The Pipeline with the stage appended
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let pipeline = pipeline.stage(Rectify);
+    /// ```
+    ///
+    pub fn stage<S: Stage + 'a>(mut self, stage: S) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    /// Sets the number of samples per channel read at a time
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk_size` - The number of samples per chunk, at least 1
+    ///
+    /// # Returns
+    ///
+    /// The Pipeline with the new chunk size
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let pipeline = pipeline.chunk_size(30000);
+    /// ```
+    ///
+    pub fn chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size.max(1);
+        self
+    }
+
+    /// Sets whether output files are only written if the whole run succeeds
+    ///
+    /// # Arguments
+    ///
+    /// * `strict` - Writes `to_csv_file` and `to_binary` output to a temporary file that replaces the target only on success if true
+    ///
+    /// # Returns
+    ///
+    /// The Pipeline with the new setting
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let pipeline = pipeline.strict_output(true);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// By default the output is streamed: if the run fails midway, the rows written so far
+    /// stay in the file and the error is `PipelineError::Interrupted` with their exact count.
+    /// In strict mode the temporary file sits next to the target, named after it with a
+    /// `.partial` suffix, and is removed on failure, so the target is either complete or
+    /// untouched. Sinks that are not files are always streamed.
+    ///
+    pub fn strict_output(mut self, strict: bool) -> Self {
+        self.strict_output = strict;
+        self
+    }
+
+    /// Sets the format of the numbers written by `to_csv_file` and `to_stdout`
+    ///
+    /// # Arguments
+    ///
+    /// * `float_format` - The format of the samples
+    ///
+    /// # Returns
+    ///
+    /// The Pipeline with the new format
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let summary = pipeline.float_format(FloatFormat::significant(7)).to_csv_file("lfp.csv")?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// `to_csv` uses the format of its CsvIO object instead. A checkpoint only resumes a run
+    /// with the same format, so that the output file does not mix two formats.
+    ///
+    pub fn float_format(mut self, float_format: FloatFormat) -> Self {
+        self.float_format = float_format;
+        self
+    }
+
+    /// Saves a Checkpoint at a regular interval so that a failed run can resume
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the checkpoint file, replaced atomically at every checkpoint
+    /// * `interval` - The number of chunks between checkpoints
+    ///
+    /// # Returns
+    ///
+    /// The Pipeline with checkpointing enabled. The run fails before reading any sample if the
+    /// source is not a file or in memory, a stage returns no `state` or the sink is not
+    /// `to_csv_file` or `to_binary`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let summary = Pipeline::source(SignalSource::from_csv("wideband.csv", 30000.0, Some("time"))?)
+    ///     .decimate(30)
+    ///     .checkpoint("lfp.csv.checkpoint", 100)
+    ///     .to_csv_file("lfp.csv")?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The output is flushed at every checkpoint, so the checkpoint always points at output
+    /// that is on disk. The checkpoint file is removed once the run succeeds.
+    ///
+    pub fn checkpoint<P: AsRef<Path>>(mut self, path: P, interval: usize) -> Self {
+        self.checkpoint = Some((path.as_ref().to_path_buf(), interval.max(1)));
+        self
+    }
+
+    /// Continues a run from a Checkpoint
+    ///
+    /// # Arguments
+    ///
+    /// * `checkpoint` - The last Checkpoint saved by the failed run
+    ///
+    /// # Returns
+    ///
+    /// The Pipeline set to resume. The run fails if the input, the stages, the chunk size or
+    /// the output differ from the failed run.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut pipeline = build_pipeline()?.checkpoint("lfp.csv.checkpoint", 100);
+    /// if Path::new("lfp.csv.checkpoint").exists() {
+    ///     pipeline = pipeline.resume_from(Checkpoint::load("lfp.csv.checkpoint")?);
+    /// }
+    /// let summary = pipeline.to_csv_file("lfp.csv")?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The output file is truncated to the length stored in the checkpoint, dropping anything
+    /// written after it, and the run continues from the next input sample with the stage
+    /// states restored, so the final output is byte-identical to an uninterrupted run
+    ///
+    pub fn resume_from(mut self, checkpoint: Checkpoint) -> Self {
+        self.resume = Some(checkpoint);
+        self
+    }
+
+    /// Sets a callback called after every chunk
+    ///
+    /// # Arguments
+    ///
+    /// * `callback` - The function called with the Progress once each chunk is written
+    ///
+    /// # Returns
+    ///
+    /// The Pipeline with the callback
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let pipeline = pipeline.on_progress(|progress| eprintln!("{} chunks", progress.chunks));
+    /// ```
+    ///
+    pub fn on_progress<F: FnMut(&Progress) + 'a>(mut self, callback: F) -> Self {
+        self.progress = Some(Box::new(callback));
+        self
+    }
+
+    /// Runs the Pipeline into a sink
+    ///
+    /// # Arguments
+    ///
+    /// * `sink` - The destination of the output
+    ///
+    /// # Returns
+    ///
+    /// The PipelineSummary, or the first error of a stage, the source or the sink. An error
+    /// raised after the sink was started is wrapped in `PipelineError::Interrupted`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let summary = pipeline.run(&mut my_sink)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The run stops early, without an error, once `Sink::is_closed` returns true
+    ///
+    pub fn run(mut self, sink: &mut dyn Sink) -> Result<PipelineSummary, PipelineError> {
+        let mut names = self.source.names.clone();
+        let mut sampling_rate = self.source.sampling_rate;
+        for stage in self.stages.iter_mut() {
+            (names, sampling_rate) = stage.configure(&names, sampling_rate)?;
+        }
+        let mut progress = Progress { chunks: 0, samples_read: 0, total_samples: self.source.total_samples() };
+        let mut samples_written = 0;
+        let checkpointing = match self.checkpoint.is_some() || self.resume.is_some() {
+            true => Some((self.source.identity()?, self.config_hash(&names, sampling_rate))),
+            false => None,
+        };
+        if let Some(checkpoint) = self.resume.take() {
+            let (identity, config_hash) = checkpointing.as_ref().expect("resuming implies checkpointing");
+            self.restore(&checkpoint, identity, *config_hash)?;
+            progress.samples_read = checkpoint.samples_read;
+            samples_written = checkpoint.samples_written;
+        } else {
+            sink.start(&names, sampling_rate)?;
+        }
+        if self.checkpoint.is_some() {
+            if let Some(i) = self.stages.iter().position(|stage| stage.state().is_none()) {
+                return Err(ProcessingError::InvalidParameter(format!("Stage {} cannot be checkpointed", i)).into());
+            }
+            if sink.flush_position()?.is_none() {
+                return Err(io::Error::new(io::ErrorKind::Unsupported, "Sink cannot be checkpointed").into());
+            }
+        }
+
+        let resumed_at = samples_written;
+        let result = self.stream(sink, &mut progress, &mut samples_written, checkpointing.as_ref());
+        // Chunks are counted whole, the sinks that write row by row know how far they got
+        let samples_written = sink.samples_written().map_or(samples_written, |rows| resumed_at + rows);
+        if result.is_ok() {
+            if let Some((path, _)) = &self.checkpoint {
+                match fs::remove_file(path) {
+                    Err(error) if error.kind() != io::ErrorKind::NotFound => return Err(error.into()),
+                    _ => {}
+                }
+            }
+        }
+        match result {
+            Ok(()) => Ok(PipelineSummary { names, sampling_rate, samples_read: progress.samples_read, samples_written }),
+            Err(error) => Err(PipelineError::Interrupted { samples_written, error: Box::new(error) }),
+        }
+    }
+
+    /// Hashes everything of the Pipeline that a resumed run must share with the failed one
+    fn config_hash(&self, names: &[String], sampling_rate: f64) -> u64 {
+        let mut hasher = Fnv::new();
+        let mut write = |text: &str| {
+            hasher.write(text.as_bytes());
+            hasher.write(&[0]);
+        };
+        write(&self.chunk_size.to_string());
+        self.source.names.iter().for_each(|name| write(name));
+        write(&self.source.sampling_rate.to_bits().to_string());
+        for stage in self.stages.iter() {
+            write(&stage.describe());
+        }
+        names.iter().for_each(|name| write(name));
+        write(&sampling_rate.to_bits().to_string());
+        if self.float_format != FloatFormat::default() {
+            write(&format!("{:?}", self.float_format));
+        }
+        hasher.finish()
+    }
+
+    /// Checks a Checkpoint against the run and moves the source and the stages to it
+    fn restore(&mut self, checkpoint: &Checkpoint, identity: &InputIdentity, config_hash: u64) -> Result<(), PipelineError> {
+        if checkpoint.input != *identity {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Checkpoint was saved for another or a modified input").into());
+        }
+        if checkpoint.config_hash != config_hash || checkpoint.states.len() != self.stages.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Checkpoint was saved for a pipeline with other settings").into());
+        }
+        for (stage, state) in self.stages.iter_mut().zip(checkpoint.states.iter()) {
+            stage.restore(state)?;
+        }
+        self.source.seek(checkpoint.input_offset, checkpoint.input_line)
+    }
+
+    /// Flushes the sink and saves a Checkpoint of the run after the last chunk
+    fn save_checkpoint(
+        &self,
+        sink: &mut dyn Sink,
+        (identity, config_hash): &(InputIdentity, u64),
+        progress: &Progress,
+        samples_written: usize,
+    ) -> Result<(), PipelineError> {
+        let Some((path, _)) = &self.checkpoint else {
+            return Ok(());
+        };
+        let output_offset = sink.flush_position()?.ok_or(io::Error::from(io::ErrorKind::Unsupported))?;
+        let (input_offset, input_line) = self.source.offset();
+        let checkpoint = Checkpoint {
+            input: identity.clone(),
+            config_hash: *config_hash,
+            input_offset,
+            input_line,
+            samples_read: progress.samples_read,
+            samples_written,
+            output_offset,
+            states: self.stages.iter().map(|stage| stage.state().unwrap_or_default()).collect(),
+        };
+        Ok(checkpoint.save(path)?)
+    }
+
+    /// Runs every chunk of the source through the stages into a started sink
+    fn stream(
+        &mut self,
+        sink: &mut dyn Sink,
+        progress: &mut Progress,
+        samples_written: &mut usize,
+        checkpointing: Option<&(InputIdentity, u64)>,
+    ) -> Result<(), PipelineError> {
+        while let Some(chunk) = self.source.next_chunk(self.chunk_size)? {
+            progress.samples_read += chunk.first().map_or(0, |channel| channel.len());
+            let mut chunk = chunk;
+            for stage in self.stages.iter_mut() {
+                chunk = stage.process(chunk)?;
+            }
+            *samples_written += write_chunk(sink, &chunk)?;
+            if sink.is_closed() {
+                return Ok(());
+            }
+            progress.chunks += 1;
+            if let (Some((_, interval)), Some(checkpointing)) = (&self.checkpoint, checkpointing) {
+                if progress.chunks.is_multiple_of(*interval) {
+                    self.save_checkpoint(sink, checkpointing, progress, *samples_written)?;
+                }
+            }
+            if let Some(callback) = self.progress.as_mut() {
+                callback(progress);
+            }
+        }
+
+        // Whatever a stage still holds back goes through the stages after it
+        for i in 0..self.stages.len() {
+            if let Some(mut chunk) = self.stages[i].finish()? {
+                for stage in self.stages[i + 1..].iter_mut() {
+                    chunk = stage.process(chunk)?;
+                }
+                *samples_written += write_chunk(sink, &chunk)?;
+                if sink.is_closed() {
+                    return Ok(());
+                }
+            }
+        }
+        sink.finish()
+    }
+
+    /// Runs the Pipeline into a CsvIO object
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// The PipelineSummary, or the first error of a stage or the source
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// pipeline.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row with the channel names is written first, then one row per sample. The
+    /// rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(self, csv_io: &mut CsvIO) -> Result<PipelineSummary, PipelineError> {
+        self.run(&mut CsvSink { csv_io, rows: 0 })
+    }
+
+    /// Runs the Pipeline into a raw binary file
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path to the file, created or truncated
+    ///
+    /// # Returns
+    ///
+    /// The PipelineSummary, or the first error of a stage, the source or the file
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let summary = pipeline.to_binary("lfp.bin")?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The samples are written as little-endian f64, interleaved one sample of every channel
+    /// after the other, so the file can be read with `numpy.fromfile(path, "<f8").reshape(-1, n_channels)`.
+    /// The channel names and sampling rate are not stored; they are in the returned summary.
+    ///
+    pub fn to_binary(self, file_path: &str) -> Result<PipelineSummary, PipelineError> {
+        self.run_to_file(Path::new(file_path), |file, _| Box::new(BinarySink { writer: BufWriter::new(file), rows: 0 }))
+    }
+
+    /// Runs the Pipeline into a CSV file
+    ///
+    /// # Arguments
+    ///
+    /// * `file_path` - The path to the file, created or truncated
+    ///
+    /// # Returns
+    ///
+    /// The PipelineSummary, or the first error of a stage, the source or the file
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let summary = pipeline.strict_output(true).to_csv_file("lfp.csv")?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row with the channel names is written first, then one row per sample
+    ///
+    pub fn to_csv_file(self, file_path: &str) -> Result<PipelineSummary, PipelineError> {
+        let float_format = self.float_format;
+        self.run_to_file(Path::new(file_path), |file, offset| Box::new(WriterSink::new(BufWriter::new(file), Some(offset), float_format)))
+    }
+
+    /// Filters every channel without phase shift into a CSV file
+    ///
+    /// # Arguments
+    ///
+    /// * `filter` - The IIR or FIR filter, designed for the sampling rate after the previous stages
+    /// * `file_path` - The path to the file, created or truncated
+    /// * `block_duration` - The duration in seconds of the blocks filtered at a time, at least the `required_overlap` of the filter
+    ///
+    /// # Returns
+    ///
+    /// The PipelineSummary, or the first error of a stage, the source or the file
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let bandpass = butterworth(4, FilterKind::Bandpass(0.5, 40.0), 1000.0)?;
+    /// let summary = Pipeline::source(SignalSource::from_csv("overnight.csv", 1000.0, Some("time"))?)
+    ///     .strict_output(true)
+    ///     .filter_to_file(bandpass, "overnight_filtered.csv", 60.0)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// This is `zero_phase` followed by `to_csv_file`, so the file matches filtering each
+    /// whole channel in memory while only a block and its overlap are held at a time
+    ///
+    pub fn filter_to_file<F: Into<ZeroPhaseFilter>>(self, filter: F, file_path: &str, block_duration: f64) -> Result<PipelineSummary, PipelineError> {
+        self.zero_phase(filter, block_duration).to_csv_file(file_path)
+    }
+
+    /// Runs the Pipeline into the standard output as CSV
+    ///
+    /// # Returns
+    ///
+    /// The PipelineSummary, or the first error of a stage, the source or the standard output
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// fn main() -> ExitCode {
+    ///     let result = SignalSource::from_stdin(1000.0, None).and_then(|source| Pipeline::source(source).map(f64::abs).to_stdout());
+    ///     match result {
+    ///         Ok(_) => ExitCode::SUCCESS,
+    ///         Err(error) => { eprintln!("{}", error); ExitCode::FAILURE }
+    ///     }
+    /// }
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The rows are streamed as they are produced. If the reader of the output closes it,
+    /// e.g. `head` in a shell pipeline, the run stops quietly and returns the summary of the
+    /// rows written so far instead of an error.
+    ///
+    pub fn to_stdout(self) -> Result<PipelineSummary, PipelineError> {
+        let float_format = self.float_format;
+        self.run(&mut WriterSink::new(io::stdout().lock(), None, float_format))
+    }
+
+    /// Runs the Pipeline into a file, through a temporary file in strict mode and after the
+    /// checkpointed length when resuming
+    fn run_to_file<F>(self, path: &Path, make_sink: F) -> Result<PipelineSummary, PipelineError>
+    where
+        F: FnOnce(File, u64) -> Box<dyn Sink>,
+    {
+        if self.strict_output && (self.checkpoint.is_some() || self.resume.is_some()) {
+            return Err(ProcessingError::InvalidParameter("Strict output cannot be combined with checkpoints".to_string()).into());
+        }
+        if let Some(checkpoint) = &self.resume {
+            let mut file = fs::OpenOptions::new().write(true).open(path)?;
+            if file.metadata()?.len() < checkpoint.output_offset {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "Output is shorter than the checkpoint").into());
+            }
+            file.set_len(checkpoint.output_offset)?;
+            file.seek(SeekFrom::End(0))?;
+            let offset = checkpoint.output_offset;
+            return self.run(make_sink(file, offset).as_mut());
+        }
+        if !self.strict_output {
+            return self.run(make_sink(File::create(path)?, 0).as_mut());
+        }
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let partial = PathBuf::from(partial);
+        let mut sink = make_sink(File::create(&partial)?, 0);
+        let result = self.run(sink.as_mut()).map_err(|error| match error {
+            PipelineError::Interrupted { error, .. } => *error,
+            error => error,
+        });
+        drop(sink);
+        match result {
+            Ok(summary) => {
+                fs::rename(&partial, path)?;
+                Ok(summary)
+            }
+            Err(error) => {
+                let _ = fs::remove_file(&partial);
+                Err(error)
+            }
+        }
+    }
+
+    /// Runs the Pipeline and keeps the output in memory
+    ///
+    /// # Returns
+    ///
+    /// The PipelineOutput, or the first error of a stage or the source
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let output = pipeline.collect()?;
+    /// ```
+    ///
+    pub fn collect(self) -> Result<PipelineOutput, PipelineError> {
+        let mut output = PipelineOutput::default();
+        self.run(&mut output)?;
+        Ok(output)
+    }
+}
+
+impl Sink for PipelineOutput {
+    fn start(&mut self, names: &[String], sampling_rate: f64) -> Result<(), PipelineError> {
+        self.names = names.to_vec();
+        self.sampling_rate = sampling_rate;
+        self.channels = vec![Vec::new(); names.len()];
+        Ok(())
+    }
+
+    fn write(&mut self, chunk: &[Vec<f64>]) -> Result<(), PipelineError> {
+        for (channel, samples) in self.channels.iter_mut().zip(chunk) {
+            channel.extend_from_slice(samples);
+        }
+        Ok(())
+    }
+}
+
+/// Writes a chunk that holds samples to the sink and returns its length
+fn write_chunk(sink: &mut dyn Sink, chunk: &[Vec<f64>]) -> Result<usize, PipelineError> {
+    let length = chunk.first().map_or(0, |channel| channel.len());
+    if chunk.iter().any(|channel| channel.len() != length) {
+        return Err(ProcessingError::InvalidParameter("A stage returned channels of different lengths".to_string()).into());
+    }
+    if length > 0 {
+        sink.write(chunk)?;
+    }
+    Ok(length)
+}
+
+struct CsvSink<'c> {
+    csv_io: &'c mut CsvIO,
+    rows: usize,
+}
+
+impl Sink for CsvSink<'_> {
+    fn start(&mut self, names: &[String], _sampling_rate: f64) -> Result<(), PipelineError> {
+        self.csv_io.write_record(StringRecord::from(names.to_vec()))?;
+        Ok(())
+    }
+
+    fn write(&mut self, chunk: &[Vec<f64>]) -> Result<(), PipelineError> {
+        for i in 0..chunk[0].len() {
+            let float_format = self.csv_io.float_format();
+            self.csv_io.write_record(chunk.iter().map(|channel| float_format.format(channel[i])).collect())?;
+            self.rows += 1;
+        }
+        Ok(())
+    }
+
+    fn samples_written(&self) -> Option<usize> {
+        Some(self.rows)
+    }
+}
+
+/// Writes CSV rows to any writer, noting when the reader of the output has gone away
+struct WriterSink<W: Write> {
+    writer: csv::Writer<Counted<W>>,
+    rows: usize,
+    closed: bool,
+    start: Option<u64>,
+    float_format: FloatFormat,
+}
+
+impl<W: Write> WriterSink<W> {
+    /// Creates a sink whose output already holds `start` bytes, or None if its length does not matter
+    fn new(writer: W, start: Option<u64>, float_format: FloatFormat) -> Self {
+        Self { writer: csv::Writer::from_writer(Counted { inner: writer, bytes: 0 }), rows: 0, closed: false, start, float_format }
+    }
+
+    fn check(&mut self, result: csv::Result<()>) -> Result<(), PipelineError> {
+        match result {
+            Err(error) if matches!(error.kind(), csv::ErrorKind::Io(io_error) if io_error.kind() == io::ErrorKind::BrokenPipe) => {
+                self.closed = true;
+                Ok(())
+            }
+            result => Ok(result?),
+        }
+    }
+}
+
+impl<W: Write> Sink for WriterSink<W> {
+    fn start(&mut self, names: &[String], _sampling_rate: f64) -> Result<(), PipelineError> {
+        let result = self.writer.write_record(names);
+        self.check(result)
+    }
+
+    fn write(&mut self, chunk: &[Vec<f64>]) -> Result<(), PipelineError> {
+        for i in 0..chunk[0].len() {
+            let float_format = self.float_format;
+            let result = self.writer.write_record(chunk.iter().map(|channel| float_format.format(channel[i])));
+            self.check(result)?;
+            if self.closed {
+                return Ok(());
+            }
+            self.rows += 1;
+        }
+        // Each chunk is pushed out so that a closed reader is noticed while the run goes on
+        let result = self.writer.flush().map_err(csv::Error::from);
+        self.check(result)
+    }
+
+    fn finish(&mut self) -> Result<(), PipelineError> {
+        let result = self.writer.flush().map_err(csv::Error::from);
+        self.check(result)
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed
+    }
+
+    fn flush_position(&mut self) -> Result<Option<u64>, PipelineError> {
+        self.writer.flush()?;
+        Ok(self.start.map(|start| start + self.writer.get_ref().bytes))
+    }
+
+    fn samples_written(&self) -> Option<usize> {
+        Some(self.rows)
+    }
+}
+
+/// Counts the bytes written through a writer
+struct Counted<W: Write> {
+    inner: W,
+    bytes: u64,
+}
+
+impl<W: Write> Write for Counted<W> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buffer)?;
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+struct BinarySink {
+    writer: BufWriter<File>,
+    rows: usize,
+}
+
+impl Sink for BinarySink {
+    fn start(&mut self, _names: &[String], _sampling_rate: f64) -> Result<(), PipelineError> {
+        Ok(())
+    }
+
+    fn write(&mut self, chunk: &[Vec<f64>]) -> Result<(), PipelineError> {
+        for i in 0..chunk[0].len() {
+            for channel in chunk {
+                self.writer.write_all(&channel[i].to_le_bytes())?;
+            }
+            self.rows += 1;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<(), PipelineError> {
+        Ok(self.writer.flush()?)
+    }
+
+    fn flush_position(&mut self) -> Result<Option<u64>, PipelineError> {
+        self.writer.flush()?;
+        Ok(Some(self.writer.get_mut().stream_position()?))
+    }
+
+    fn samples_written(&self) -> Option<usize> {
+        Some(self.rows)
+    }
+}
+
+struct SelectChannels {
+    names: Vec<String>,
+    indices: Vec<usize>,
+}
+
+impl Stage for SelectChannels {
+    fn configure(&mut self, names: &[String], sampling_rate: f64) -> Result<(Vec<String>, f64), ProcessingError> {
+        for (i, name) in self.names.iter().enumerate() {
+            if let Some(first) = self.names[..i].iter().position(|other| other == name) {
+                return Err(ProcessingError::InvalidParameter(format!("Channel {} is selected at positions {} and {}", name, first, i)));
+            }
+        }
+        self.indices = self
+            .names
+            .iter()
+            .map(|wanted| {
+                names
+                    .iter()
+                    .position(|name| name == wanted)
+                    .ok_or_else(|| ProcessingError::InvalidParameter(format!("No channel named {}", wanted)))
+            })
+            .collect::<Result<_, _>>()?;
+        Ok((self.names.clone(), sampling_rate))
+    }
+
+    fn process(&mut self, mut chunk: Vec<Vec<f64>>) -> Result<Vec<Vec<f64>>, ProcessingError> {
+        Ok(self.indices.iter().map(|&i| std::mem::take(&mut chunk[i])).collect())
+    }
+
+    fn describe(&self) -> String {
+        format!("select {:?}", self.names)
+    }
+
+    fn state(&self) -> Option<Vec<f64>> {
+        Some(Vec::new())
+    }
+
+    fn restore(&mut self, state: &[f64]) -> Result<(), ProcessingError> {
+        restore_length(state, 0)
+    }
+}
+
+/// An IIR filter with one set of section states per channel
+struct FilterStage {
+    filter: IirFilter,
+    states: Vec<Vec<[f64; 2]>>,
+}
+
+impl FilterStage {
+    fn new(filter: IirFilter) -> Self {
+        Self { filter, states: Vec::new() }
+    }
+
+    fn check_rate(&self, sampling_rate: f64) -> Result<(), ProcessingError> {
+        if self.filter.sampling_rate() != sampling_rate {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Filter designed for {} Hz cannot run at {} Hz",
+                self.filter.sampling_rate(),
+                sampling_rate
+            )));
+        }
+        Ok(())
+    }
+
+    fn reset(&mut self, n_channels: usize) {
+        self.states = vec![vec![[0.0; 2]; self.filter.sections().len()]; n_channels];
+    }
+
+    fn apply(&mut self, chunk: &mut [Vec<f64>]) {
+        for (channel, states) in chunk.iter_mut().zip(self.states.iter_mut()) {
+            for (section, state) in self.filter.sections().iter().zip(states.iter_mut()) {
+                filter_section(section, state, channel);
+            }
+        }
+    }
+
+    fn flat_states(&self) -> impl Iterator<Item = f64> + '_ {
+        self.states.iter().flatten().flatten().copied()
+    }
+
+    /// Restores the section states from the front of `state` and returns how many values were used
+    fn restore_states(&mut self, state: &[f64]) -> Result<usize, ProcessingError> {
+        let length = self.states.iter().map(|states| states.len() * 2).sum();
+        restore_length(state.get(..length).unwrap_or(state), length)?;
+        for (value, stored) in self.states.iter_mut().flatten().flatten().zip(state) {
+            *value = *stored;
+        }
+        Ok(length)
+    }
+}
+
+impl Stage for FilterStage {
+    fn configure(&mut self, names: &[String], sampling_rate: f64) -> Result<(Vec<String>, f64), ProcessingError> {
+        self.check_rate(sampling_rate)?;
+        self.reset(names.len());
+        Ok((names.to_vec(), sampling_rate))
+    }
+
+    fn process(&mut self, mut chunk: Vec<Vec<f64>>) -> Result<Vec<Vec<f64>>, ProcessingError> {
+        self.apply(&mut chunk);
+        Ok(chunk)
+    }
+
+    fn describe(&self) -> String {
+        format!("filter {:?}", self.filter)
+    }
+
+    fn state(&self) -> Option<Vec<f64>> {
+        Some(self.flat_states().collect())
+    }
+
+    fn restore(&mut self, state: &[f64]) -> Result<(), ProcessingError> {
+        let used = self.restore_states(state)?;
+        restore_length(state, used)
+    }
+}
+
+/// One decimation stage: its anti-aliasing filter and the samples to drop before the next kept one
+struct DecimationStep {
+    filter: FilterStage,
+    factor: usize,
+    skip: usize,
+}
+
+struct Decimate {
+    factor: usize,
+    stages: Vec<DecimationStep>,
+}
+
+impl Stage for Decimate {
+    fn configure(&mut self, names: &[String], sampling_rate: f64) -> Result<(Vec<String>, f64), ProcessingError> {
+        if self.factor == 0 {
+            return Err(ProcessingError::InvalidParameter("Decimation factor must be at least 1".to_string()));
+        }
+        let mut rate = sampling_rate;
+        self.stages.clear();
+        for factor in decimation_stages(self.factor) {
+            let new_rate = rate / factor as f64;
+            let cutoff = DECIMATION_CUTOFF_FRACTION * new_rate / 2.0;
+            let mut filter = FilterStage::new(butterworth(DECIMATION_FILTER_ORDER, FilterKind::Lowpass(cutoff), rate)?);
+            filter.reset(names.len());
+            self.stages.push(DecimationStep { filter, factor, skip: 0 });
+            rate = new_rate;
+        }
+        Ok((names.to_vec(), rate))
+    }
+
+    fn process(&mut self, mut chunk: Vec<Vec<f64>>) -> Result<Vec<Vec<f64>>, ProcessingError> {
+        for step in self.stages.iter_mut() {
+            step.filter.apply(&mut chunk);
+            let length = chunk.first().map_or(0, |channel| channel.len());
+            for channel in chunk.iter_mut() {
+                *channel = channel.iter().skip(step.skip).step_by(step.factor).copied().collect();
+            }
+            // The kept sample positions continue the grid of the previous chunk
+            step.skip = if length > step.skip { (step.factor - (length - step.skip) % step.factor) % step.factor } else { step.skip - length };
+        }
+        Ok(chunk)
+    }
+
+    fn describe(&self) -> String {
+        format!("decimate {}", self.factor)
+    }
+
+    // Every step stores its skip count followed by its filter states
+    fn state(&self) -> Option<Vec<f64>> {
+        Some(self.stages.iter().flat_map(|step| std::iter::once(step.skip as f64).chain(step.filter.flat_states())).collect())
+    }
+
+    fn restore(&mut self, state: &[f64]) -> Result<(), ProcessingError> {
+        let mut used = 0;
+        for step in self.stages.iter_mut() {
+            let Some(&skip) = state.get(used) else {
+                return restore_length(state, used + 1);
+            };
+            step.skip = skip as usize;
+            used += 1 + step.filter.restore_states(&state[used + 1..])?;
+        }
+        restore_length(state, used)
+    }
+}
+
+/// A zero-phase filter run over blocks of `block_len` samples that overlap by `overlap` samples on each side
+struct ZeroPhase {
+    filter: ZeroPhaseFilter,
+    block_duration: f64,
+    block_len: usize,
+    overlap: usize,
+    buffers: Vec<Vec<f64>>,
+    buffer_start: usize,
+    next: usize,
+}
+
+impl ZeroPhase {
+    /// Returns the filtered samples from `start` to `stop`, the buffered samples ending the whole signal if `at_end`
+    fn filter_block(&self, start: usize, stop: usize, at_end: bool) -> Result<Vec<Vec<f64>>, ProcessingError> {
+        let window_start = start.saturating_sub(self.overlap);
+        let window_stop = if at_end { self.buffer_start + self.buffers.first().map_or(0, |buffer| buffer.len()) } else { stop + self.overlap };
+        self.buffers
+            .iter()
+            .map(|buffer| {
+                let window = &buffer[window_start - self.buffer_start..window_stop - self.buffer_start];
+                let filtered = self.filter.apply(window, window_start == 0, at_end)?;
+                Ok(filtered[start - window_start..stop - window_start].to_vec())
+            })
+            .collect()
+    }
+}
+
+impl Stage for ZeroPhase {
+    fn configure(&mut self, names: &[String], sampling_rate: f64) -> Result<(Vec<String>, f64), ProcessingError> {
+        if self.filter.sampling_rate() != sampling_rate {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Filter designed for {} Hz cannot run at {} Hz",
+                self.filter.sampling_rate(),
+                sampling_rate
+            )));
+        }
+        let block_len = (self.block_duration * sampling_rate).round();
+        if !(block_len.is_finite() && block_len >= 1.0) {
+            return Err(ProcessingError::InvalidParameter(format!("Block duration must be positive, got {} s", self.block_duration)));
+        }
+        self.overlap = self.filter.required_overlap()?;
+        self.block_len = block_len as usize;
+        if self.block_len < self.overlap {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Blocks of {} samples are shorter than the {} samples of overlap the filter needs, use blocks of at least {} s",
+                self.block_len,
+                self.overlap,
+                self.overlap as f64 / sampling_rate
+            )));
+        }
+        self.buffers = vec![Vec::new(); names.len()];
+        self.buffer_start = 0;
+        self.next = 0;
+        Ok((names.to_vec(), sampling_rate))
+    }
+
+    fn process(&mut self, chunk: Vec<Vec<f64>>) -> Result<Vec<Vec<f64>>, ProcessingError> {
+        for (buffer, channel) in self.buffers.iter_mut().zip(chunk) {
+            buffer.extend(channel);
+        }
+        let end = self.buffer_start + self.buffers.first().map_or(0, |buffer| buffer.len());
+        let mut output = vec![Vec::new(); self.buffers.len()];
+        // A block is only released once samples follow its overlap, so that the last block is never taken for a cut
+        while self.next + self.block_len + self.overlap < end {
+            let block = self.filter_block(self.next, self.next + self.block_len, false)?;
+            for (channel, filtered) in output.iter_mut().zip(block) {
+                channel.extend(filtered);
+            }
+            self.next += self.block_len;
+        }
+        let keep = self.next.saturating_sub(self.overlap);
+        for buffer in self.buffers.iter_mut() {
+            buffer.drain(..keep - self.buffer_start);
+        }
+        self.buffer_start = keep;
+        Ok(output)
+    }
+
+    fn finish(&mut self) -> Result<Option<Vec<Vec<f64>>>, ProcessingError> {
+        let end = self.buffer_start + self.buffers.first().map_or(0, |buffer| buffer.len());
+        if self.next >= end {
+            return Ok(None);
+        }
+        let block = self.filter_block(self.next, end, true)?;
+        self.next = end;
+        Ok(Some(block))
+    }
+
+    fn describe(&self) -> String {
+        format!("zero_phase {:?} {}", self.filter, self.block_duration)
+    }
+}
+
+struct Map<F>(F);
+
+impl<F: FnMut(f64) -> f64> Stage for Map<F> {
+    fn configure(&mut self, names: &[String], sampling_rate: f64) -> Result<(Vec<String>, f64), ProcessingError> {
+        Ok((names.to_vec(), sampling_rate))
+    }
+
+    fn process(&mut self, mut chunk: Vec<Vec<f64>>) -> Result<Vec<Vec<f64>>, ProcessingError> {
+        chunk.iter_mut().flatten().for_each(|sample| *sample = (self.0)(*sample));
+        Ok(chunk)
+    }
+
+    // The function is assumed to be pure, as it cannot be inspected
+    fn describe(&self) -> String {
+        "map".to_string()
+    }
+
+    fn state(&self) -> Option<Vec<f64>> {
+        Some(Vec::new())
+    }
+
+    fn restore(&mut self, state: &[f64]) -> Result<(), ProcessingError> {
+        restore_length(state, 0)
+    }
+}
+
+/// Checks that a stored stage state has the length the configured stage expects
+fn restore_length(state: &[f64], expected: usize) -> Result<(), ProcessingError> {
+    if state.len() != expected {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Checkpointed stage state has {} values but {} are expected",
+            state.len(),
+            expected
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::filter::fir_design;
+    use crate::processing::window::Window;
+
+    /// A few seconds of three channels mixing slow and fast sines with a step
+    fn fixture(n: usize) -> (Vec<Vec<f64>>, Vec<String>) {
+        let channels = (0..3)
+            .map(|c| {
+                (0..n)
+                    .map(|i| {
+                        let t = i as f64 / 1000.0;
+                        (2.0 * std::f64::consts::PI * (3.0 + c as f64) * t).sin()
+                            + 0.3 * (2.0 * std::f64::consts::PI * 180.0 * t).sin()
+                            + if i > n / 3 { 0.5 * c as f64 } else { 0.0 }
+                    })
+                    .collect()
+            })
+            .collect();
+        (channels, vec!["a".to_string(), "b".to_string(), "c".to_string()])
+    }
+
+    fn run_chunked(channels: &[Vec<f64>], names: &[String], chunk_size: usize) -> PipelineOutput {
+        let highpass = butterworth(4, FilterKind::Highpass(1.0), 1000.0).unwrap();
+        Pipeline::source(SignalSource::from_channels(channels, names, 1000.0).unwrap())
+            .select_channels(&["c", "a"])
+            .filter(highpass)
+            .decimate(6)
+            .map(|sample| sample * 2.0)
+            .chunk_size(chunk_size)
+            .collect()
+            .unwrap()
+    }
+
+    #[test]
+    fn chunked_output_is_identical_to_a_single_chunk() {
+        let (channels, names) = fixture(5003);
+        let whole = run_chunked(&channels, &names, channels[0].len());
+        assert_eq!(whole.names, vec!["c".to_string(), "a".to_string()]);
+        assert_eq!(whole.sampling_rate, 1000.0 / 6.0);
+        assert_eq!(whole.channels[0].len(), 5003usize.div_ceil(6));
+        for chunk_size in [1, 5, 6, 7, 64, 999, 4096] {
+            let chunked = run_chunked(&channels, &names, chunk_size);
+            assert_eq!(chunked.channels, whole.channels, "chunk size {}", chunk_size);
+        }
+    }
+
+    #[test]
+    fn chunked_filter_matches_the_whole_signal() {
+        let (channels, names) = fixture(2500);
+        let filter = butterworth(3, FilterKind::Bandpass(2.0, 40.0), 1000.0).unwrap();
+        for chunk_size in [1, 13, 1024] {
+            let output = Pipeline::source(SignalSource::from_channels(&channels, &names, 1000.0).unwrap())
+                .filter(filter.clone())
+                .chunk_size(chunk_size)
+                .collect()
+                .unwrap();
+            for (filtered, channel) in output.channels.iter().zip(&channels) {
+                assert_eq!(filtered, &filter.apply(channel), "chunk size {}", chunk_size);
+            }
+        }
+    }
+
+    /// The largest difference between two sets of channels, relative to the largest sample of the first
+    fn relative_error(expected: &[Vec<f64>], actual: &[Vec<f64>]) -> f64 {
+        let scale = expected.iter().flatten().fold(0.0f64, |max, value| max.max(value.abs()));
+        expected
+            .iter()
+            .zip(actual)
+            .flat_map(|(expected, actual)| {
+                assert_eq!(expected.len(), actual.len());
+                expected.iter().zip(actual).map(|(a, b)| (a - b).abs())
+            })
+            .fold(0.0, f64::max)
+            / scale
+    }
+
+    fn zero_phase_blocks(channels: &[Vec<f64>], names: &[String], filter: ZeroPhaseFilter, block_duration: f64, chunk_size: usize) -> Result<PipelineOutput, PipelineError> {
+        Pipeline::source(SignalSource::from_channels(channels, names, 1000.0).unwrap())
+            .zero_phase(filter, block_duration)
+            .chunk_size(chunk_size)
+            .collect()
+    }
+
+    #[test]
+    fn zero_phase_iir_blocks_match_filtfilt() {
+        let (channels, names) = fixture(6007);
+        let filter = butterworth(2, FilterKind::Bandpass(5.0, 40.0), 1000.0).unwrap();
+        let expected = filter.filtfilt_channels(&channels).unwrap();
+        let overlap = ZeroPhaseFilter::from(filter.clone()).required_overlap().unwrap();
+        assert!(overlap > filter.padding_length());
+        let shortest = overlap as f64 / 1000.0;
+        for block_duration in [shortest, 1.5 * shortest, 2.0, 10.0] {
+            for chunk_size in [1, 97, 1000, 6007] {
+                let output = zero_phase_blocks(&channels, &names, filter.clone().into(), block_duration, chunk_size).unwrap();
+                let error = relative_error(&expected, &output.channels);
+                assert!(error <= 1e-10, "blocks of {} s, chunks of {}: {}", block_duration, chunk_size, error);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_phase_fir_blocks_match_the_whole_signal() {
+        let (channels, names) = fixture(4001);
+        let filter = fir_design(101, FilterKind::Lowpass(40.0), Window::Hamming, 1000.0).unwrap();
+        let expected: Vec<Vec<f64>> = channels.iter().map(|channel| filter.apply_zero_delay(channel)).collect();
+        assert_eq!(ZeroPhaseFilter::from(filter.clone()).required_overlap().unwrap(), 101);
+        for block_duration in [0.101, 0.25, 3.0] {
+            for chunk_size in [1, 64, 4001] {
+                let output = zero_phase_blocks(&channels, &names, filter.clone().into(), block_duration, chunk_size).unwrap();
+                let error = relative_error(&expected, &output.channels);
+                assert!(error <= 1e-12, "blocks of {} s, chunks of {}: {}", block_duration, chunk_size, error);
+            }
+        }
+    }
+
+    #[test]
+    fn zero_phase_rejects_blocks_shorter_than_the_overlap() {
+        let (channels, names) = fixture(1000);
+        let filter = fir_design(101, FilterKind::Lowpass(40.0), Window::Hamming, 1000.0).unwrap();
+        let error = zero_phase_blocks(&channels, &names, filter.into(), 0.05, 64).unwrap_err();
+        assert!(matches!(error, PipelineError::Processing(ProcessingError::InvalidParameter(_))), "{}", error);
+        assert!(error.to_string().contains("use blocks of at least 0.101 s"), "{}", error);
+    }
+
+    #[test]
+    fn filter_to_file_writes_the_filtfilt_output() {
+        let (channels, names) = fixture(3001);
+        let filter = butterworth(2, FilterKind::Highpass(20.0), 1000.0).unwrap();
+        let expected = filter.filtfilt_channels(&channels).unwrap();
+        let path = std::env::temp_dir().join(format!("neurorust-filter-to-file-{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let summary = Pipeline::source(SignalSource::from_channels(&channels, &names, 1000.0).unwrap())
+            .chunk_size(500)
+            .filter_to_file(filter, path, 0.7)
+            .unwrap();
+        assert_eq!(summary.samples_written, 3001);
+        let mut reader = ReaderBuilder::new().from_path(path).unwrap();
+        let mut written = vec![Vec::new(); 3];
+        for record in reader.records() {
+            for (channel, field) in written.iter_mut().zip(record.unwrap().iter()) {
+                channel.push(field.parse::<f64>().unwrap());
+            }
+        }
+        fs::remove_file(path).unwrap();
+        assert!(relative_error(&expected, &written) <= 1e-10);
+    }
+
+    /// Fails on the chunk that holds a sample, after passing the ones before it
+    struct FailAt(usize, usize);
+
+    impl Stage for FailAt {
+        fn configure(&mut self, names: &[String], sampling_rate: f64) -> Result<(Vec<String>, f64), ProcessingError> {
+            Ok((names.to_vec(), sampling_rate))
+        }
+
+        fn process(&mut self, chunk: Vec<Vec<f64>>) -> Result<Vec<Vec<f64>>, ProcessingError> {
+            self.1 += chunk[0].len();
+            if self.1 > self.0 {
+                return Err(ProcessingError::InvalidParameter("planted failure".to_string()));
+            }
+            Ok(chunk)
+        }
+    }
+
+    /// Keeps rows until its output fails at a row, counting them if `counts` is true
+    struct Failing {
+        rows: usize,
+        fail_at: usize,
+        counts: bool,
+    }
+
+    impl Sink for Failing {
+        fn start(&mut self, _names: &[String], _sampling_rate: f64) -> Result<(), PipelineError> {
+            Ok(())
+        }
+
+        fn write(&mut self, chunk: &[Vec<f64>]) -> Result<(), PipelineError> {
+            for _ in 0..chunk[0].len() {
+                if self.rows == self.fail_at {
+                    return Err(io::Error::other("disk full").into());
+                }
+                self.rows += 1;
+            }
+            Ok(())
+        }
+
+        fn samples_written(&self) -> Option<usize> {
+            self.counts.then_some(self.rows)
+        }
+    }
+
+    #[test]
+    fn interrupted_runs_count_the_rows_that_reached_the_sink() {
+        let (channels, names) = fixture(5000);
+        let run = |sink: &mut Failing| Pipeline::source(SignalSource::from_channels(&channels, &names, 1000.0).unwrap()).chunk_size(1000).run(sink);
+        match run(&mut Failing { rows: 0, fail_at: 3456, counts: true }) {
+            Err(PipelineError::Interrupted { samples_written, .. }) => assert_eq!(samples_written, 3456),
+            other => panic!("{:?}", other.map(|summary| summary.samples_written)),
+        }
+        // A sink that does not count its rows is credited with the chunks it took whole
+        match run(&mut Failing { rows: 0, fail_at: 3456, counts: false }) {
+            Err(PipelineError::Interrupted { samples_written, .. }) => assert_eq!(samples_written, 3000),
+            other => panic!("{:?}", other.map(|summary| summary.samples_written)),
+        }
+
+        let path = std::env::temp_dir().join(format!("neurorust-interrupted-{}.csv", std::process::id()));
+        let path = path.to_str().unwrap();
+        let error = Pipeline::source(SignalSource::from_channels(&channels, &names, 1000.0).unwrap())
+            .chunk_size(700)
+            .stage(FailAt(3000, 0))
+            .to_csv_file(path)
+            .unwrap_err();
+        let PipelineError::Interrupted { samples_written, error } = error else { panic!("{}", error) };
+        assert_eq!(samples_written, 2800);
+        assert!(error.to_string().contains("planted failure"));
+        let rows = ReaderBuilder::new().from_path(path).unwrap().records().count();
+        assert_eq!(rows, samples_written);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn selecting_a_channel_twice_fails_before_reading() {
+        let (channels, names) = fixture(100);
+        let error = Pipeline::source(SignalSource::from_channels(&channels, &names, 1000.0).unwrap())
+            .select_channels(&["a", "b", "a"])
+            .collect()
+            .unwrap_err();
+        assert!(matches!(error, PipelineError::Processing(ProcessingError::InvalidParameter(_))), "{}", error);
+        assert!(error.to_string().contains("positions 0 and 2"), "{}", error);
+    }
+}
@@ -0,0 +1,362 @@
+// A module to compute peri-stimulus time histograms
+
+// Written by Amin Alam in 2024
+
+use std::collections::BTreeMap;
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+
+/// A peri-stimulus time histogram averaged over trials
+///
+/// # Arguments
+///
+/// * `bin_centers` - The center of each bin in seconds relative to the events
+/// * `rates` - The mean firing rate of each bin in spikes per second, NaN if no trial was used
+/// * `sem` - The standard error of the mean rate of each bin, NaN with fewer than two trials
+/// * `counts` - The spike count of each used trial in each bin, one row per trial
+/// * `n_trials` - The number of trials used
+/// * `dropped_trials` - The number of events dropped because their window extends past the recording
+///
+/// # Examples
+///
+/// ```
+/// let psth = compute(&spike_times, &stimulus_onsets, (-0.2, 0.5), 0.01, (0.0, 3600.0))?;
+/// psth.to_csv(&mut csv_io)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Psth {
+    pub bin_centers: Vec<f64>,
+    pub rates: Vec<f64>,
+    pub sem: Vec<f64>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub counts: Vec<Vec<usize>>,
+    pub n_trials: usize,
+    pub dropped_trials: usize,
+}
+
+/// Implementation of the Psth struct
+///
+/// # Methods
+///
+/// * `bin_size` - Returns the width of the bins
+/// * `to_csv` - Writes the histogram as `bin_center,rate,sem,n_trials` rows
+impl Psth {
+    /// Returns the width of the bins
+    ///
+    /// # Returns
+    ///
+    /// The bin width in seconds, or NaN if the histogram has fewer than two bins
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let bin_size = psth.bin_size();
+    /// ```
+    ///
+    pub fn bin_size(&self) -> f64 {
+        match self.bin_centers.as_slice() {
+            [first, second, ..] => second - first,
+            _ => f64::NAN,
+        }
+    }
+
+    /// Writes the histogram as `bin_center,rate,sem,n_trials` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// psth.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["bin_center", "rate", "sem", "n_trials"]))?;
+        for ((center, rate), sem) in self.bin_centers.iter().zip(&self.rates).zip(&self.sem) {
+            csv_io.write_record(StringRecord::from(vec![
+                float_format.format(*center),
+                float_format.format(*rate),
+                float_format.format(*sem),
+                self.n_trials.to_string(),
+            ]))?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes the peri-stimulus time histogram of a spike train
+///
+/// # Arguments
+///
+/// * `spike_times` - The spike times in seconds, in any order
+/// * `event_times` - The time of each event (trial onset) in seconds
+/// * `window` - The start and end of each trial in seconds relative to its event, e.g. `(-0.2, 0.5)`
+/// * `bin_size` - The width of each bin in seconds
+/// * `recording` - The start and end of the recording in seconds
+///
+/// # Returns
+///
+/// The Psth, or an error if the window is empty or the bin size is not positive
+///
+/// # Examples
+///
+/// ```
+/// let psth = compute(&unit, &onsets, (-0.1, 0.3), 0.005, (0.0, duration))?;
+/// let (peak_bin, _) = psth.rates.iter().enumerate().fold((0, f64::MIN), |best, (i, &r)| if r > best.1 { (i, r) } else { best });
+/// ```
+///
+/// # Note
+///
+/// Bins are half-open `[start, start + bin_size)` and span the window, the last one extending
+/// past its end if the window is not a multiple of the bin size. Events whose window is not
+/// entirely within the recording are dropped and counted in `dropped_trials`, so edge trials
+/// do not pull the rates down.
+///
+pub fn compute(
+    spike_times: &[f64],
+    event_times: &[f64],
+    window: (f64, f64),
+    bin_size: f64,
+    recording: (f64, f64),
+) -> Result<Psth, ProcessingError> {
+    let n_bins = bin_count(window, bin_size)?;
+    let mut sorted = spike_times.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    Ok(histogram(&sorted, event_times, window, bin_size, n_bins, recording))
+}
+
+/// Computes one peri-stimulus time histogram per event label
+///
+/// # Arguments
+///
+/// * `spike_times` - The spike times in seconds, in any order
+/// * `event_times` - The time of each event in seconds
+/// * `labels` - The label (condition) of each event
+/// * `window` - The start and end of each trial in seconds relative to its event
+/// * `bin_size` - The width of each bin in seconds
+/// * `recording` - The start and end of the recording in seconds
+///
+/// # Returns
+///
+/// The Psth of each label, or an error if the events and labels differ in length, the
+/// window is empty or the bin size is not positive
+///
+/// # Examples
+///
+/// ```
+/// let by_condition = compute_by_label(&unit, &onsets, &conditions, (-0.1, 0.3), 0.005, (0.0, duration))?;
+/// let preferred = &by_condition["grating_90"];
+/// ```
+///
+pub fn compute_by_label(
+    spike_times: &[f64],
+    event_times: &[f64],
+    labels: &[String],
+    window: (f64, f64),
+    bin_size: f64,
+    recording: (f64, f64),
+) -> Result<BTreeMap<String, Psth>, ProcessingError> {
+    if event_times.len() != labels.len() {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Got {} events but {} labels",
+            event_times.len(),
+            labels.len()
+        )));
+    }
+    let n_bins = bin_count(window, bin_size)?;
+    let mut sorted = spike_times.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+
+    let mut grouped: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+    for (&time, label) in event_times.iter().zip(labels) {
+        grouped.entry(label.clone()).or_default().push(time);
+    }
+    Ok(grouped
+        .into_iter()
+        .map(|(label, events)| (label, histogram(&sorted, &events, window, bin_size, n_bins, recording)))
+        .collect())
+}
+
+fn bin_count(window: (f64, f64), bin_size: f64) -> Result<usize, ProcessingError> {
+    if !(bin_size > 0.0 && bin_size.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!("Bin size must be positive, got {}", bin_size)));
+    }
+    if !(window.1 > window.0 && window.0.is_finite() && window.1.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!("Window {:?} must end after it starts", window)));
+    }
+    // Tolerate rounding so that e.g. a 0.7 s window holds exactly 70 bins of 10 ms
+    Ok(((window.1 - window.0) / bin_size - 1e-9).ceil().max(1.0) as usize)
+}
+
+fn histogram(sorted_spikes: &[f64], event_times: &[f64], window: (f64, f64), bin_size: f64, n_bins: usize, recording: (f64, f64)) -> Psth {
+    let span = n_bins as f64 * bin_size;
+    let mut counts = Vec::with_capacity(event_times.len());
+    let mut dropped_trials = 0;
+    for &event in event_times {
+        let start = event + window.0;
+        if start < recording.0 || event + window.1 > recording.1 {
+            dropped_trials += 1;
+            continue;
+        }
+        let mut trial = vec![0usize; n_bins];
+        let first = sorted_spikes.partition_point(|&time| time < start);
+        for &time in sorted_spikes[first..].iter().take_while(|&&time| time < start + span) {
+            let bin = (((time - start) / bin_size) as usize).min(n_bins - 1);
+            trial[bin] += 1;
+        }
+        counts.push(trial);
+    }
+
+    let n_trials = counts.len();
+    let mut rates = vec![f64::NAN; n_bins];
+    let mut sem = vec![f64::NAN; n_bins];
+    for bin in 0..n_bins {
+        if n_trials == 0 {
+            break;
+        }
+        let trial_rates: Vec<f64> = counts.iter().map(|trial| trial[bin] as f64 / bin_size).collect();
+        let mean = trial_rates.iter().sum::<f64>() / n_trials as f64;
+        rates[bin] = mean;
+        if n_trials > 1 {
+            let variance = trial_rates.iter().map(|rate| (rate - mean).powi(2)).sum::<f64>() / (n_trials - 1) as f64;
+            sem[bin] = (variance / n_trials as f64).sqrt();
+        }
+    }
+    let bin_centers = (0..n_bins).map(|bin| window.0 + (bin as f64 + 0.5) * bin_size).collect();
+    Psth { bin_centers, rates, sem, counts, n_trials, dropped_trials }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    /// A 5 Hz Poisson neuron over `[0, duration]` that fires `response_spikes` extra spikes
+    /// within `[latency + 1 ms, latency + 9 ms]` after each event
+    fn responding_neuron(events: &[f64], latency: f64, response_spikes: usize, duration: f64, rng: &mut SeededRng) -> Vec<f64> {
+        let mut spikes = Vec::new();
+        let mut time = 0.0;
+        loop {
+            time -= (1.0 - rng.next_f64()).ln() / 5.0;
+            if time > duration {
+                break;
+            }
+            spikes.push(time);
+        }
+        for &event in events {
+            for _ in 0..response_spikes {
+                spikes.push(event + latency + 0.001 + 0.008 * rng.next_f64());
+            }
+        }
+        spikes
+    }
+
+    #[test]
+    fn a_50_ms_latency_response_peaks_in_the_right_bin_with_the_right_height() {
+        let mut rng = SeededRng::new(1);
+        let events: Vec<f64> = (0..202).map(|k| 0.1 + k as f64).collect();
+        let spikes = responding_neuron(&events, 0.05, 2, 201.5, &mut rng);
+        let psth = compute(&spikes, &events, (-0.2, 0.5), 0.01, (0.0, 201.5)).unwrap();
+
+        assert_eq!(psth.bin_centers.len(), 70);
+        assert!((psth.bin_size() - 0.01).abs() < 1e-12);
+        // The first event starts before the recording and the last one ends after it
+        assert_eq!(psth.dropped_trials, 2);
+        assert_eq!(psth.n_trials, 200);
+        assert_eq!(psth.counts.len(), 200);
+
+        let peak = (0..70).max_by(|&a, &b| psth.rates[a].total_cmp(&psth.rates[b])).unwrap();
+        assert!((psth.bin_centers[peak] - 0.055).abs() < 1e-12);
+        // Two spikes per 10 ms bin are 200 spikes/s on top of the 5 Hz baseline, whose mean
+        // in one bin over 200 trials has a standard error of 1.6 spikes/s
+        assert!((psth.rates[peak] - 205.0).abs() < 6.5, "{}", psth.rates[peak]);
+        assert!(psth.counts.iter().all(|trial| trial[peak] >= 2));
+        let baseline: f64 = (0..70).filter(|&bin| bin != peak).map(|bin| psth.rates[bin]).sum::<f64>() / 69.0;
+        assert!((baseline - 5.0).abs() < 1.0, "{}", baseline);
+
+        // The rates are the mean counts over the trials divided by the bin width
+        for bin in [0, peak, 69] {
+            let mean_count = psth.counts.iter().map(|trial| trial[bin]).sum::<usize>() as f64 / 200.0;
+            assert!((psth.rates[bin] - mean_count / 0.01).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn counts_bins_and_the_standard_error_follow_the_trials() {
+        // Three trials with 1, 2 and 3 spikes in the second bin and one spike at a window edge
+        let events = [1.0, 2.0, 3.0];
+        let spikes = [1.15, 2.12, 2.18, 3.11, 3.13, 3.17, 3.0, 3.3];
+        let psth = compute(&spikes, &events, (0.0, 0.25), 0.1, (0.0, 10.0)).unwrap();
+        // A 0.25 s window in 0.1 s bins has a last bin reaching past its end
+        assert_eq!(psth.bin_centers.len(), 3);
+        assert!((psth.bin_centers[2] - 0.25).abs() < 1e-12);
+        assert_eq!(psth.counts, vec![vec![0, 1, 0], vec![0, 2, 0], vec![1, 3, 0]]);
+        assert!((psth.rates[1] - 20.0).abs() < 1e-9);
+        // The trial rates 10, 20 and 30 have a standard deviation of 10
+        assert!((psth.sem[1] - 10.0 / 3f64.sqrt()).abs() < 1e-9);
+        assert!((psth.sem[2]).abs() < 1e-12);
+
+        // A 0.7 s window holds exactly 70 bins of 10 ms
+        assert_eq!(compute(&spikes, &events, (-0.2, 0.5), 0.01, (0.0, 10.0)).unwrap().rates.len(), 70);
+        // One trial has no standard error and no trial has no rate
+        let single = compute(&spikes, &[1.0], (0.0, 0.25), 0.1, (0.0, 10.0)).unwrap();
+        assert!(single.sem.iter().all(|sem| sem.is_nan()));
+        let none = compute(&spikes, &[9.9], (0.0, 0.25), 0.1, (0.0, 10.0)).unwrap();
+        assert_eq!((none.n_trials, none.dropped_trials), (0, 1));
+        assert!(none.rates.iter().all(|rate| rate.is_nan()));
+
+        assert!(compute(&spikes, &events, (0.5, 0.5), 0.1, (0.0, 10.0)).is_err());
+        assert!(compute(&spikes, &events, (0.0, 0.5), 0.0, (0.0, 10.0)).is_err());
+    }
+
+    #[test]
+    fn conditions_are_compared_in_one_call_and_exported_to_csv() {
+        let mut rng = SeededRng::new(2);
+        let events: Vec<f64> = (0..100).map(|k| 1.0 + k as f64).collect();
+        let labels: Vec<String> = (0..100).map(|k| if k % 2 == 0 { "strong".to_string() } else { "weak".to_string() }).collect();
+        let strong: Vec<f64> = events.iter().step_by(2).copied().collect();
+        let weak: Vec<f64> = events.iter().skip(1).step_by(2).copied().collect();
+        let mut spikes = responding_neuron(&strong, 0.05, 3, 102.0, &mut rng);
+        spikes.extend(weak.iter().map(|event| event + 0.055));
+
+        let by_label = compute_by_label(&spikes, &events, &labels, (-0.1, 0.2), 0.01, (0.0, 102.0)).unwrap();
+        assert_eq!(by_label.keys().collect::<Vec<_>>(), vec!["strong", "weak"]);
+        assert_eq!(by_label["strong"], compute(&spikes, &strong, (-0.1, 0.2), 0.01, (0.0, 102.0)).unwrap());
+        assert_eq!(by_label["weak"], compute(&spikes, &weak, (-0.1, 0.2), 0.01, (0.0, 102.0)).unwrap());
+        assert_eq!(by_label["strong"].n_trials, 50);
+        assert!(by_label["strong"].rates[15] > 300.0);
+        assert!(by_label["weak"].rates[15] >= 100.0 && by_label["weak"].rates[15] < 300.0);
+        assert!(compute_by_label(&spikes, &events, &labels[..99], (-0.1, 0.2), 0.01, (0.0, 102.0)).is_err());
+
+        let psth = &by_label["weak"];
+        let path = std::env::temp_dir().join(format!("neurorust-psth-{}-psth.csv", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        psth.to_csv(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines[0], "bin_center,rate,sem,n_trials");
+        assert_eq!(lines.len(), 31);
+        let row: Vec<f64> = lines[16].split(',').map(|value| value.parse().unwrap()).collect();
+        assert!((row[0] - 0.055).abs() < 1e-9);
+        assert!((row[1] - psth.rates[15]).abs() < 1e-6 * psth.rates[15]);
+        assert_eq!(row[3], 50.0);
+    }
+}
@@ -0,0 +1,1096 @@
+// A module to check the quality of a whole directory of recordings before analysis
+
+// Written by Amin Alam in 2024
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+use csv::{ReaderBuilder, StringRecord};
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+use crate::processing::pipeline::DEFAULT_CHUNK_SIZE;
+use crate::processing::spectral::welch;
+use crate::processing::streaming::StreamingStats;
+use crate::processing::timing::validate_timing;
+use crate::processing::window::Window;
+
+/// The half-width in Hz of the band searched for the line-noise peak
+const LINE_PEAK_HALF_WIDTH: f64 = 1.0;
+
+/// The half-width in Hz of the band whose median power is the reference of the line-noise peak
+const LINE_REFERENCE_HALF_WIDTH: f64 = 10.0;
+
+/// The outcome of a quality check, ordered from best to worst
+///
+/// # Arguments
+///
+/// * `Pass` - The data is within all thresholds
+/// * `Warn` - A value exceeds its warning threshold, or a check could not be run
+/// * `Fail` - A value exceeds its failure threshold, or the file could not be read
+///
+/// # Examples
+///
+/// ```
+/// if report.status == QcStatus::Fail {
+///     println!("{}", report.files.iter().filter(|file| file.status == QcStatus::Fail).count());
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum QcStatus {
+    #[default]
+    Pass,
+    Warn,
+    Fail,
+}
+
+impl fmt::Display for QcStatus {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            QcStatus::Pass => write!(f, "pass"),
+            QcStatus::Warn => write!(f, "warn"),
+            QcStatus::Fail => write!(f, "fail"),
+        }
+    }
+}
+
+/// The warning and failure thresholds of one quality measure
+///
+/// # Arguments
+///
+/// * `warn` - A value above it gives `QcStatus::Warn`
+/// * `fail` - A value above it gives `QcStatus::Fail`
+///
+/// # Examples
+///
+/// ```
+/// let options = QcOptions { saturated_fraction: QcThreshold { warn: 0.0, fail: 0.001 }, ..Default::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QcThreshold {
+    pub warn: f64,
+    pub fail: f64,
+}
+
+/// Implementation of the QcThreshold struct
+///
+/// # Methods
+///
+/// * `status` - Grades a value against the thresholds
+impl QcThreshold {
+    /// Grades a value against the thresholds
+    ///
+    /// # Arguments
+    ///
+    /// * `value` - The measured value
+    ///
+    /// # Returns
+    ///
+    /// `Fail` above `fail`, `Warn` above `warn` and `Pass` otherwise, including for NaN
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(QcThreshold { warn: 0.1, fail: 0.5 }.status(0.2), QcStatus::Warn);
+    /// ```
+    ///
+    pub fn status(&self, value: f64) -> QcStatus {
+        if value > self.fail {
+            QcStatus::Fail
+        } else if value > self.warn {
+            QcStatus::Warn
+        } else {
+            QcStatus::Pass
+        }
+    }
+}
+
+/// The options of `generate_report`
+///
+/// # Arguments
+///
+/// * `sampling_rate` - The sampling rate in Hz, or None to estimate it from the time column of CSV files
+/// * `time_column` - The name of the CSV column holding the timestamps in seconds, skipped if a file has no such column
+/// * `binary_channels` - The number of channels of raw binary files, which hold interleaved little-endian f64 samples
+/// * `timing_tolerance` - The tolerated deviation of a timestamp step from the nominal interval, as a fraction of it
+/// * `saturation_limits` - The lowest and highest value the acquisition can record, or None to count the samples stuck at the minimum or maximum of each channel
+/// * `flatline_tolerance` - The largest difference between consecutive samples of a flat stretch
+/// * `flatline_duration` - The shortest flat stretch in seconds that is counted
+/// * `line_frequency` - The mains frequency in Hz
+/// * `missing_fraction` - The thresholds of the fraction of missing (NaN or unparsable) samples of a channel
+/// * `saturated_fraction` - The thresholds of the fraction of saturated samples of a channel
+/// * `flatline_fraction` - The thresholds of the fraction of samples of a channel in flat stretches
+/// * `line_noise_db` - The thresholds of the line-noise peak above the neighbouring spectrum in dB
+/// * `dropped_fraction` - The thresholds of the fraction of samples of a file dropped according to its timestamps
+/// * `chunk_size` - The number of samples per channel read at a time
+/// * `max_parallel_files` - The largest number of files checked at the same time
+///
+/// # Examples
+///
+/// ```
+/// let options = QcOptions { line_frequency: 60.0, binary_channels: Some(64), sampling_rate: Some(30000.0), ..Default::default() };
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct QcOptions {
+    pub sampling_rate: Option<f64>,
+    pub time_column: Option<String>,
+    pub binary_channels: Option<usize>,
+    pub timing_tolerance: f64,
+    pub saturation_limits: Option<(f64, f64)>,
+    pub flatline_tolerance: f64,
+    pub flatline_duration: f64,
+    pub line_frequency: f64,
+    pub missing_fraction: QcThreshold,
+    pub saturated_fraction: QcThreshold,
+    pub flatline_fraction: QcThreshold,
+    pub line_noise_db: QcThreshold,
+    pub dropped_fraction: QcThreshold,
+    pub chunk_size: usize,
+    pub max_parallel_files: usize,
+}
+
+impl Default for QcOptions {
+    fn default() -> Self {
+        Self {
+            sampling_rate: None,
+            time_column: Some("time".to_string()),
+            binary_channels: None,
+            timing_tolerance: 0.1,
+            saturation_limits: None,
+            flatline_tolerance: 1e-12,
+            flatline_duration: 1.0,
+            line_frequency: 50.0,
+            missing_fraction: QcThreshold { warn: 0.001, fail: 0.05 },
+            saturated_fraction: QcThreshold { warn: 0.001, fail: 0.01 },
+            flatline_fraction: QcThreshold { warn: 0.01, fail: 0.1 },
+            line_noise_db: QcThreshold { warn: 10.0, fail: 20.0 },
+            dropped_fraction: QcThreshold { warn: 0.0, fail: 0.01 },
+            chunk_size: DEFAULT_CHUNK_SIZE,
+            max_parallel_files: std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        }
+    }
+}
+
+/// The format of an input file, chosen from its extension
+///
+/// # Arguments
+///
+/// * `Csv` - A `.csv` file with a header row and one channel per column
+/// * `Binary` - A `.bin`, `.dat` or `.raw` file of interleaved little-endian f64 samples
+/// * `Unsupported` - Any other file, e.g. EDF, reported as failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum QcFormat {
+    Csv,
+    Binary,
+    Unsupported,
+}
+
+/// Implementation of the QcFormat enum
+///
+/// # Methods
+///
+/// * `of_path` - Chooses the format of a file from its extension
+impl QcFormat {
+    /// Chooses the format of a file from its extension
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the file
+    ///
+    /// # Returns
+    ///
+    /// The QcFormat, matching the extension case-insensitively
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(QcFormat::of_path(Path::new("m12/lfp.CSV")), QcFormat::Csv);
+    /// ```
+    ///
+    pub fn of_path(path: &Path) -> Self {
+        match path.extension().map(|extension| extension.to_string_lossy().to_lowercase()).as_deref() {
+            Some("csv") => QcFormat::Csv,
+            Some("bin" | "dat" | "raw") => QcFormat::Binary,
+            _ => QcFormat::Unsupported,
+        }
+    }
+}
+
+/// The regularity of the timestamps of a file, checked in one pass
+///
+/// # Arguments
+///
+/// * `n_samples` - The number of timestamps that were checked
+/// * `nominal_rate` - The sampling rate implied by the nominal interval
+/// * `gaps` - The number of steps longer than tolerated
+/// * `overlaps` - The number of steps shorter than tolerated, including backwards steps
+/// * `missing_samples` - The number of samples that would fit into the gaps at the nominal rate
+/// * `is_monotonic` - Whether the timestamps are strictly increasing
+///
+/// # Note
+///
+/// Steps are graded as in `validate_timing`, with the nominal interval being the inverse of
+/// `QcOptions::sampling_rate` or else the median interval of the first chunk
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QcTiming {
+    pub n_samples: usize,
+    pub nominal_rate: f64,
+    pub gaps: usize,
+    pub overlaps: usize,
+    pub missing_samples: usize,
+    pub is_monotonic: bool,
+}
+
+/// The quality of one channel
+///
+/// # Arguments
+///
+/// * `name` - The name of the channel
+/// * `stats` - The streaming statistics of the channel, with missing samples counted separately
+/// * `missing_fraction` - The fraction of samples that are NaN or could not be parsed
+/// * `saturated_fraction` - The fraction of valid samples at or beyond the saturation limits
+/// * `flatline_fraction` - The fraction of valid samples in flat stretches, or None if the sampling rate is unknown
+/// * `line_noise_db` - The line-noise peak above the neighbouring spectrum in dB, or None if it could not be estimated
+/// * `status` - The worst status of the measures
+/// * `reasons` - A description of every measure above its warning threshold
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChannelQc {
+    pub name: String,
+    pub stats: StreamingStats,
+    pub missing_fraction: f64,
+    pub saturated_fraction: f64,
+    pub flatline_fraction: Option<f64>,
+    pub line_noise_db: Option<f64>,
+    pub status: QcStatus,
+    pub reasons: Vec<String>,
+}
+
+/// The quality of one file
+///
+/// # Arguments
+///
+/// * `path` - The path of the file
+/// * `format` - The format the file was read as
+/// * `n_samples` - The number of samples per channel
+/// * `sampling_rate` - The sampling rate given in the options or estimated, if known
+/// * `malformed_rows` - The number of CSV rows with the wrong number of fields, or 1 for a binary file with trailing bytes
+/// * `timing` - The regularity of the timestamps, if the file has a time column
+/// * `dropped_fraction` - The fraction of samples dropped according to the timestamps, 0 without a time column
+/// * `channels` - The quality of each channel
+/// * `status` - The worst status of the file-level measures and the channels
+/// * `reasons` - A description of every file-level problem
+/// * `error` - The error that stopped the file from being read, if any
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FileQc {
+    pub path: PathBuf,
+    pub format: QcFormat,
+    pub n_samples: usize,
+    pub sampling_rate: Option<f64>,
+    pub malformed_rows: usize,
+    pub timing: Option<QcTiming>,
+    pub dropped_fraction: f64,
+    pub channels: Vec<ChannelQc>,
+    pub status: QcStatus,
+    pub reasons: Vec<String>,
+    pub error: Option<String>,
+}
+
+/// The quality of a set of files
+///
+/// # Arguments
+///
+/// * `files` - The quality of each file, in input order
+/// * `status` - The worst status of the files
+///
+/// # Examples
+///
+/// ```
+/// let report = generate_report(&inputs, &QcOptions::default())?;
+/// report.save_json("qc.json")?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct QcReport {
+    pub files: Vec<FileQc>,
+    pub status: QcStatus,
+}
+
+/// Implementation of the QcReport struct
+///
+/// # Methods
+///
+/// * `to_csv` - Writes one summary row per channel
+impl QcReport {
+    /// Writes one summary row per channel
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// report.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The columns are `file,file_status,channel,status,count,missing_fraction,
+    /// saturated_fraction,flatline_fraction,line_noise_db,mean,std,min,max,reasons`, with
+    /// unknown values left empty and the file and channel reasons joined by `; `. A file
+    /// without channels, e.g. one that could not be read, gets a single row with an empty
+    /// channel. A header row is written first. The rows are not flushed to disk until `save`
+    /// is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        let header = [
+            "file",
+            "file_status",
+            "channel",
+            "status",
+            "count",
+            "missing_fraction",
+            "saturated_fraction",
+            "flatline_fraction",
+            "line_noise_db",
+            "mean",
+            "std",
+            "min",
+            "max",
+            "reasons",
+        ];
+        csv_io.write_record(StringRecord::from(header.to_vec()))?;
+        let optional = |value: Option<f64>| value.map_or(String::new(), |value| float_format.format(value));
+        for file in self.files.iter() {
+            let path = file.path.to_string_lossy().into_owned();
+            if file.channels.is_empty() {
+                let mut record = vec![path.clone(), file.status.to_string(), String::new(), file.status.to_string()];
+                record.extend(std::iter::repeat_n(String::new(), 9));
+                record.push(file.reasons.join("; "));
+                csv_io.write_record(StringRecord::from(record))?;
+            }
+            for channel in file.channels.iter() {
+                let reasons: Vec<&str> = file.reasons.iter().chain(channel.reasons.iter()).map(|reason| reason.as_str()).collect();
+                let record = vec![
+                    path.clone(),
+                    file.status.to_string(),
+                    channel.name.clone(),
+                    channel.status.to_string(),
+                    channel.stats.count().to_string(),
+                    float_format.format(channel.missing_fraction),
+                    float_format.format(channel.saturated_fraction),
+                    optional(channel.flatline_fraction),
+                    optional(channel.line_noise_db),
+                    float_format.format(channel.stats.mean()),
+                    float_format.format(channel.stats.std()),
+                    float_format.format(channel.stats.min()),
+                    float_format.format(channel.stats.max()),
+                    reasons.join("; "),
+                ];
+                csv_io.write_record(StringRecord::from(record))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Checks the quality of a set of files
+///
+/// # Arguments
+///
+/// * `inputs` - The paths of the files, dispatched by extension as in `QcFormat::of_path`
+/// * `options` - The settings of the checks and their thresholds
+///
+/// # Returns
+///
+/// The QcReport, or an error if an option is invalid. A file that cannot be read does not
+/// stop the report: it is listed as failed with its error.
+///
+/// # Examples
+///
+/// ```
+/// let mut inputs: Vec<PathBuf> = std::fs::read_dir("session_12")?.map(|entry| entry.map(|entry| entry.path())).collect::<Result<_, _>>()?;
+/// inputs.sort();
+/// let report = generate_report(&inputs, &QcOptions { sampling_rate: Some(1000.0), ..Default::default() })?;
+/// let mut csv_io = CsvIO::open_write("session_12_qc.csv")?;
+/// report.to_csv(&mut csv_io)?;
+/// csv_io.save()?;
+/// ```
+///
+/// # Note
+///
+/// Every file is read once, chunk by chunk, so the memory used per file is bounded by the
+/// chunk size, the number of channels and one line-noise segment per channel, whatever the
+/// length of the file. Up to `max_parallel_files` files are checked at the same time. The
+/// line noise is estimated from the average Hann-windowed periodogram of consecutive
+/// segments of about one second; segments holding a missing sample are left out.
+///
+pub fn generate_report(inputs: &[PathBuf], options: &QcOptions) -> Result<QcReport, ProcessingError> {
+    if let Some(sampling_rate) = options.sampling_rate {
+        validate_sampling_rate(sampling_rate)?;
+    }
+    if options.chunk_size == 0 || options.max_parallel_files == 0 {
+        return Err(ProcessingError::InvalidParameter("The chunk size and the number of parallel files must be positive".to_string()));
+    }
+    if options.binary_channels == Some(0) {
+        return Err(ProcessingError::InvalidParameter("Binary files must have at least one channel".to_string()));
+    }
+    if options.saturation_limits.is_some_and(|(low, high)| low.is_nan() || high.is_nan() || low >= high) {
+        return Err(ProcessingError::InvalidParameter("The low saturation limit must be below the high one".to_string()));
+    }
+
+    let group = inputs.len().div_ceil(options.max_parallel_files).max(1);
+    let files: Vec<FileQc> = std::thread::scope(|scope| {
+        let handles: Vec<_> = inputs
+            .chunks(group)
+            .map(|paths| scope.spawn(move || paths.iter().map(|path| check_file(path, options)).collect::<Vec<FileQc>>()))
+            .collect();
+        handles.into_iter().flat_map(|handle| handle.join().expect("File worker thread panicked")).collect()
+    });
+    let status = files.iter().map(|file| file.status).max().unwrap_or_default();
+    Ok(QcReport { files, status })
+}
+
+/// Checks one file, turning an error into a failed FileQc
+fn check_file(path: &Path, options: &QcOptions) -> FileQc {
+    let format = QcFormat::of_path(path);
+    let mut file = FileQc {
+        path: path.to_path_buf(),
+        format,
+        n_samples: 0,
+        sampling_rate: options.sampling_rate,
+        malformed_rows: 0,
+        timing: None,
+        dropped_fraction: 0.0,
+        channels: Vec::new(),
+        status: QcStatus::Pass,
+        reasons: Vec::new(),
+        error: None,
+    };
+    let result = match format {
+        QcFormat::Csv => check_csv(&mut file, options),
+        QcFormat::Binary => check_binary(&mut file, options),
+        QcFormat::Unsupported => Err(io::Error::new(io::ErrorKind::Unsupported, "Unsupported file format, only CSV and raw binary files can be checked")),
+    };
+    if let Err(error) = result {
+        file.channels.clear();
+        file.status = QcStatus::Fail;
+        file.reasons.push(error.to_string());
+        file.error = Some(error.to_string());
+    }
+    file
+}
+
+fn check_csv(file: &mut FileQc, options: &QcOptions) -> io::Result<()> {
+    let mut reader = ReaderBuilder::new().flexible(true).from_path(&file.path).map_err(io::Error::from)?;
+    let headers = reader.headers().map_err(io::Error::from)?.clone();
+    let time_column = options.time_column.as_ref().and_then(|name| headers.iter().position(|header| header == name));
+    let (columns, names): (Vec<usize>, Vec<String>) =
+        headers.iter().enumerate().filter(|(i, _)| Some(*i) != time_column).map(|(i, header)| (i, header.to_string())).unzip();
+    if columns.is_empty() {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "The file has no channel columns"));
+    }
+
+    let parse = |field: Option<&str>| field.and_then(|field| field.trim().parse::<f64>().ok()).unwrap_or(f64::NAN);
+    let mut checker: Option<FileChecker> = None;
+    let mut record = StringRecord::new();
+    loop {
+        let mut chunk = vec![Vec::with_capacity(options.chunk_size); columns.len()];
+        let mut times = Vec::new();
+        while chunk[0].len() < options.chunk_size && reader.read_record(&mut record).map_err(io::Error::from)? {
+            if record.len() != headers.len() {
+                file.malformed_rows += 1;
+            }
+            for (channel, &column) in chunk.iter_mut().zip(columns.iter()) {
+                channel.push(parse(record.get(column)));
+            }
+            if let Some(time_column) = time_column {
+                times.push(parse(record.get(time_column)));
+            }
+        }
+        if chunk[0].is_empty() {
+            break;
+        }
+        let checker = checker.get_or_insert_with(|| FileChecker::new(&names, time_column.map(|_| times.as_slice()), options));
+        checker.update(&chunk, &times);
+    }
+    match checker {
+        Some(checker) => checker.finish(file, options),
+        None => FileChecker::new(&names, None, options).finish(file, options),
+    }
+    if file.malformed_rows > 0 {
+        file.reasons.push(format!("{} rows have the wrong number of fields", file.malformed_rows));
+        file.status = file.status.max(QcStatus::Warn);
+    }
+    Ok(())
+}
+
+fn check_binary(file: &mut FileQc, options: &QcOptions) -> io::Result<()> {
+    let n_channels = options
+        .binary_channels
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "The number of channels of binary files is not set"))?;
+    let names: Vec<String> = (0..n_channels).map(|i| format!("ch{}", i)).collect();
+    let mut checker = FileChecker::new(&names, None, options);
+    let mut reader = BufReader::new(File::open(&file.path)?);
+    let frame = n_channels * 8;
+    let mut buffer = vec![0u8; options.chunk_size * frame];
+    loop {
+        let mut filled = 0;
+        while filled < buffer.len() {
+            match reader.read(&mut buffer[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            }
+        }
+        if filled % frame != 0 {
+            file.malformed_rows = 1;
+        }
+        let n_frames = filled / frame;
+        if n_frames == 0 {
+            break;
+        }
+        let mut chunk = vec![Vec::with_capacity(n_frames); n_channels];
+        for frame_bytes in buffer[..n_frames * frame].chunks_exact(frame) {
+            for (channel, sample) in chunk.iter_mut().zip(frame_bytes.chunks_exact(8)) {
+                channel.push(f64::from_le_bytes(sample.try_into().expect("chunks_exact yields 8 bytes")));
+            }
+        }
+        checker.update(&chunk, &[]);
+        if filled < buffer.len() {
+            break;
+        }
+    }
+    checker.finish(file, options);
+    if file.malformed_rows > 0 {
+        file.reasons.push(format!("The file ends with a partial frame of {} channels", n_channels));
+        file.status = file.status.max(QcStatus::Warn);
+    }
+    Ok(())
+}
+
+/// The running checks of one file
+struct FileChecker {
+    sampling_rate: Option<f64>,
+    timing: Option<TimingChecker>,
+    channels: Vec<ChannelChecker>,
+    n_samples: usize,
+}
+
+impl FileChecker {
+    /// Sets up the checks, estimating the sampling rate from the first timestamps if it is not given
+    fn new(names: &[String], first_times: Option<&[f64]>, options: &QcOptions) -> Self {
+        let timing = first_times.and_then(|times| {
+            let interval = match options.sampling_rate {
+                Some(sampling_rate) => 1.0 / sampling_rate,
+                None => validate_timing(times, options.timing_tolerance).nominal_interval,
+            };
+            (interval.is_finite() && interval > 0.0).then(|| TimingChecker::new(interval, options.timing_tolerance))
+        });
+        let sampling_rate = options.sampling_rate.or(timing.as_ref().map(|timing| 1.0 / timing.interval));
+        let channels = names.iter().map(|name| ChannelChecker::new(name, sampling_rate, options)).collect();
+        Self { sampling_rate, timing, channels, n_samples: 0 }
+    }
+
+    fn update(&mut self, chunk: &[Vec<f64>], times: &[f64]) {
+        self.n_samples += chunk[0].len();
+        if let Some(timing) = self.timing.as_mut() {
+            times.iter().for_each(|&time| timing.update(time));
+        }
+        for (checker, samples) in self.channels.iter_mut().zip(chunk) {
+            checker.update(samples);
+        }
+    }
+
+    /// Grades the measures and stores them in the FileQc
+    fn finish(self, file: &mut FileQc, options: &QcOptions) {
+        file.n_samples = self.n_samples;
+        file.sampling_rate = self.sampling_rate;
+        if self.sampling_rate.is_none() {
+            file.reasons.push("The sampling rate is unknown, so flatlines and line noise were not checked".to_string());
+            file.status = QcStatus::Warn;
+        }
+        if self.n_samples == 0 {
+            file.reasons.push("The file holds no samples".to_string());
+            file.status = QcStatus::Fail;
+        }
+        if let Some(timing) = self.timing {
+            let timing = timing.finish();
+            file.dropped_fraction = timing.missing_samples as f64 / (timing.n_samples + timing.missing_samples).max(1) as f64;
+            let status = options.dropped_fraction.status(file.dropped_fraction);
+            if status > QcStatus::Pass {
+                file.reasons.push(format!("{} samples dropped in {} gaps", timing.missing_samples, timing.gaps));
+            }
+            file.status = file.status.max(status);
+            if !timing.is_monotonic || timing.overlaps > 0 {
+                file.reasons.push(format!("{} timestamp steps are too short or go backwards", timing.overlaps));
+                file.status = file.status.max(QcStatus::Warn);
+            }
+            file.timing = Some(timing);
+        }
+        file.channels = self.channels.into_iter().map(|checker| checker.finish(options)).collect();
+        file.status = file.channels.iter().map(|channel| channel.status).fold(file.status, QcStatus::max);
+    }
+}
+
+/// The streaming counterpart of `validate_timing`
+struct TimingChecker {
+    interval: f64,
+    tolerance: f64,
+    previous: Option<f64>,
+    timing: QcTiming,
+}
+
+impl TimingChecker {
+    fn new(interval: f64, tolerance_fraction: f64) -> Self {
+        let timing = QcTiming { n_samples: 0, nominal_rate: 1.0 / interval, gaps: 0, overlaps: 0, missing_samples: 0, is_monotonic: true };
+        Self { interval, tolerance: tolerance_fraction.abs() * interval, previous: None, timing }
+    }
+
+    fn update(&mut self, time: f64) {
+        self.timing.n_samples += 1;
+        if let Some(previous) = self.previous.replace(time) {
+            let step = time - previous;
+            let deviation = step - self.interval;
+            if deviation > self.tolerance {
+                self.timing.gaps += 1;
+                self.timing.missing_samples += ((step / self.interval).round() as usize).saturating_sub(1);
+            } else if deviation < -self.tolerance || step <= 0.0 {
+                self.timing.overlaps += 1;
+            }
+            // A NaN timestamp also breaks monotonicity
+            if step.is_nan() || step <= 0.0 {
+                self.timing.is_monotonic = false;
+            }
+        }
+    }
+
+    fn finish(self) -> QcTiming {
+        self.timing
+    }
+}
+
+/// The running checks of one channel
+struct ChannelChecker {
+    name: String,
+    stats: StreamingStats,
+    limits: Option<(f64, f64)>,
+    at_limits: usize,
+    at_min: (f64, usize),
+    at_max: (f64, usize),
+    flatline: Option<Flatline>,
+    line_noise: Option<LineNoise>,
+}
+
+/// The running count of samples in flat stretches
+struct Flatline {
+    tolerance: f64,
+    min_run: usize,
+    previous: f64,
+    run: usize,
+    flat_samples: usize,
+}
+
+/// The running average periodogram used to estimate the line noise
+struct LineNoise {
+    sampling_rate: f64,
+    segment: Vec<f64>,
+    segment_len: usize,
+    frequencies: Vec<f64>,
+    power: Vec<f64>,
+    n_segments: usize,
+}
+
+impl ChannelChecker {
+    fn new(name: &str, sampling_rate: Option<f64>, options: &QcOptions) -> Self {
+        let flatline = sampling_rate.map(|sampling_rate| Flatline {
+            tolerance: options.flatline_tolerance,
+            min_run: ((options.flatline_duration * sampling_rate).ceil() as usize).max(2),
+            previous: f64::NAN,
+            run: 0,
+            flat_samples: 0,
+        });
+        let line_noise = sampling_rate.filter(|&sampling_rate| options.line_frequency + LINE_REFERENCE_HALF_WIDTH < sampling_rate / 2.0).map(
+            |sampling_rate| {
+                let segment_len = (sampling_rate.round() as usize).next_power_of_two().max(64);
+                LineNoise { sampling_rate, segment: Vec::with_capacity(segment_len), segment_len, frequencies: Vec::new(), power: Vec::new(), n_segments: 0 }
+            },
+        );
+        Self {
+            name: name.to_string(),
+            stats: StreamingStats::new(),
+            limits: options.saturation_limits,
+            at_limits: 0,
+            at_min: (f64::INFINITY, 0),
+            at_max: (f64::NEG_INFINITY, 0),
+            flatline,
+            line_noise,
+        }
+    }
+
+    fn update(&mut self, samples: &[f64]) {
+        self.stats.update(samples);
+        for &sample in samples.iter().filter(|sample| sample.is_finite()) {
+            if let Some((low, high)) = self.limits {
+                self.at_limits += (sample <= low || sample >= high) as usize;
+            }
+            self.at_min = match sample {
+                sample if sample < self.at_min.0 => (sample, 1),
+                sample if sample == self.at_min.0 => (sample, self.at_min.1 + 1),
+                _ => self.at_min,
+            };
+            self.at_max = match sample {
+                sample if sample > self.at_max.0 => (sample, 1),
+                sample if sample == self.at_max.0 => (sample, self.at_max.1 + 1),
+                _ => self.at_max,
+            };
+        }
+        if let Some(flatline) = self.flatline.as_mut() {
+            samples.iter().for_each(|&sample| flatline.update(sample));
+        }
+        if let Some(line_noise) = self.line_noise.as_mut() {
+            line_noise.update(samples);
+        }
+    }
+
+    fn finish(self, options: &QcOptions) -> ChannelQc {
+        let total = (self.stats.count() + self.stats.missing()).max(1) as f64;
+        let valid = self.stats.count().max(1) as f64;
+        let missing_fraction = self.stats.missing() as f64 / total;
+        // Without limits, a sample is saturated if it repeats the extreme value of the channel,
+        // which a constant channel does everywhere but is reported as a flatline instead
+        let saturated = match self.limits {
+            Some(_) => self.at_limits,
+            None if self.at_max.0 > self.at_min.0 => self.at_min.1 - 1 + self.at_max.1 - 1,
+            None => 0,
+        };
+        let saturated_fraction = saturated as f64 / valid;
+        let flatline_fraction = self.flatline.map(|flatline| flatline.finish() as f64 / valid);
+        let line_noise_db = self.line_noise.and_then(|line_noise| line_noise.finish(options.line_frequency));
+
+        let mut status = QcStatus::Pass;
+        let mut reasons = Vec::new();
+        let mut grade = |measure: &str, value: f64, threshold: &QcThreshold| {
+            let graded = threshold.status(value);
+            if graded > QcStatus::Pass {
+                reasons.push(format!("{} {} {} is above {}", self.name, measure, value, if graded == QcStatus::Fail { threshold.fail } else { threshold.warn }));
+            }
+            status = status.max(graded);
+        };
+        grade("missing_fraction", missing_fraction, &options.missing_fraction);
+        grade("saturated_fraction", saturated_fraction, &options.saturated_fraction);
+        if let Some(flatline_fraction) = flatline_fraction {
+            grade("flatline_fraction", flatline_fraction, &options.flatline_fraction);
+        }
+        if let Some(line_noise_db) = line_noise_db {
+            grade("line_noise_db", line_noise_db, &options.line_noise_db);
+        }
+        if self.stats.count() == 0 {
+            reasons.push(format!("{} has no valid samples", self.name));
+            status = QcStatus::Fail;
+        }
+        ChannelQc { name: self.name, stats: self.stats, missing_fraction, saturated_fraction, flatline_fraction, line_noise_db, status, reasons }
+    }
+}
+
+impl Flatline {
+    fn update(&mut self, sample: f64) {
+        if (sample - self.previous).abs() <= self.tolerance {
+            self.run += 1;
+        } else {
+            self.close_run();
+            self.run = if sample.is_nan() { 0 } else { 1 };
+        }
+        self.previous = sample;
+    }
+
+    fn close_run(&mut self) {
+        if self.run >= self.min_run {
+            self.flat_samples += self.run;
+        }
+    }
+
+    /// Returns the number of samples in flat stretches
+    fn finish(mut self) -> usize {
+        self.close_run();
+        self.flat_samples
+    }
+}
+
+impl LineNoise {
+    fn update(&mut self, samples: &[f64]) {
+        for &sample in samples {
+            self.segment.push(sample);
+            if self.segment.len() == self.segment_len {
+                if self.segment.iter().all(|sample| sample.is_finite()) {
+                    self.add_segment();
+                }
+                self.segment.clear();
+            }
+        }
+    }
+
+    fn add_segment(&mut self) {
+        let Ok(spectrum) = welch(&self.segment, self.sampling_rate, self.segment_len, 0.0, Window::Hann) else {
+            return;
+        };
+        if self.power.is_empty() {
+            self.frequencies = spectrum.frequencies;
+            self.power = spectrum.power;
+        } else {
+            self.power.iter_mut().zip(spectrum.power.iter()).for_each(|(total, power)| *total += power);
+        }
+        self.n_segments += 1;
+    }
+
+    /// Returns the peak power near the line frequency over the median power around it, in dB
+    fn finish(self, line_frequency: f64) -> Option<f64> {
+        if self.n_segments == 0 {
+            return None;
+        }
+        let offsets = self.frequencies.iter().map(|frequency| (frequency - line_frequency).abs());
+        let peak = offsets
+            .clone()
+            .zip(self.power.iter())
+            .filter(|(offset, _)| *offset <= LINE_PEAK_HALF_WIDTH)
+            .map(|(_, &power)| power)
+            .fold(f64::NAN, f64::max);
+        let mut reference: Vec<f64> = offsets
+            .zip(self.power.iter())
+            .filter(|(offset, _)| *offset > 2.0 * LINE_PEAK_HALF_WIDTH && *offset <= LINE_REFERENCE_HALF_WIDTH)
+            .map(|(_, &power)| power)
+            .collect();
+        if peak.is_nan() || reference.is_empty() {
+            return None;
+        }
+        reference.sort_by(|a, b| a.total_cmp(b));
+        let median = reference[reference.len() / 2];
+        match median > 0.0 {
+            true => Some(10.0 * (peak / median).log10()),
+            false => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    const SAMPLING_RATE: f64 = 250.0;
+    const N_SAMPLES: usize = 5000;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("neurorust-qc-{}-{}", std::process::id(), name))
+    }
+
+    /// Writes a CSV file with a time column and one column per channel, skipping the samples in `dropped`
+    fn write_csv(name: &str, channels: &[(&str, Vec<f64>)], dropped: &[std::ops::Range<usize>]) -> PathBuf {
+        let path = temp_path(name);
+        let mut text = String::from("time");
+        for (channel, _) in channels {
+            text.push_str(&format!(",{}", channel));
+        }
+        text.push('\n');
+        for k in (0..channels[0].1.len()).filter(|k| !dropped.iter().any(|range| range.contains(k))) {
+            text.push_str(&format!("{}", k as f64 / SAMPLING_RATE));
+            for (_, samples) in channels {
+                text.push_str(&format!(",{}", samples[k]));
+            }
+            text.push('\n');
+        }
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    fn noise(seed: u64) -> Vec<f64> {
+        let mut rng = SeededRng::new(seed);
+        (0..N_SAMPLES).map(|_| rng.next_gaussian()).collect()
+    }
+
+    fn statuses(file: &FileQc) -> Vec<QcStatus> {
+        file.channels.iter().map(|channel| channel.status).collect()
+    }
+
+    #[test]
+    fn the_fixture_set_gets_the_expected_statuses() {
+        let clean = write_csv("clean.csv", &[("a", noise(1)), ("b", noise(2))], &[]);
+        let clipped: Vec<f64> = noise(4).iter().map(|sample| sample.clamp(-1.5, 1.5)).collect();
+        let saturated = write_csv("saturated.csv", &[("a", noise(3)), ("b", clipped)], &[]);
+        let dropped = write_csv("dropped.csv", &[("a", noise(5))], &[1000..1040, 3000..3060]);
+        let inputs = vec![clean.clone(), saturated.clone(), dropped.clone()];
+        let report = generate_report(&inputs, &QcOptions { max_parallel_files: 2, ..Default::default() }).unwrap();
+        inputs.iter().for_each(|path| std::fs::remove_file(path).unwrap());
+
+        assert_eq!(report.files.iter().map(|file| file.path.clone()).collect::<Vec<_>>(), inputs);
+        assert_eq!(report.files.iter().map(|file| file.status).collect::<Vec<_>>(), vec![QcStatus::Pass, QcStatus::Fail, QcStatus::Fail]);
+        assert_eq!(report.status, QcStatus::Fail);
+
+        let clean = &report.files[0];
+        assert_eq!((clean.format, clean.n_samples, clean.malformed_rows), (QcFormat::Csv, N_SAMPLES, 0));
+        assert!((clean.sampling_rate.unwrap() - SAMPLING_RATE).abs() < 1e-6);
+        assert!(clean.reasons.is_empty() && clean.dropped_fraction == 0.0);
+        for channel in &clean.channels {
+            assert_eq!((channel.missing_fraction, channel.saturated_fraction, channel.flatline_fraction), (0.0, 0.0, Some(0.0)));
+            assert!(channel.line_noise_db.unwrap() < 10.0, "{:?}", channel.line_noise_db);
+            assert_eq!(channel.stats.count(), N_SAMPLES);
+        }
+
+        // Only the clipped channel fails, on the samples repeating its extremes
+        let saturated = &report.files[1];
+        assert_eq!(statuses(saturated), vec![QcStatus::Pass, QcStatus::Fail]);
+        let clipped_fraction = saturated.channels[1].saturated_fraction;
+        assert!(clipped_fraction > 0.1 && clipped_fraction < 0.16, "{}", clipped_fraction);
+        assert!(saturated.channels[1].reasons[0].starts_with("b saturated_fraction"));
+
+        let dropped = &report.files[2];
+        let timing = dropped.timing.as_ref().unwrap();
+        assert_eq!((timing.n_samples, timing.gaps, timing.missing_samples, timing.overlaps, timing.is_monotonic), (4900, 2, 100, 0, true));
+        assert_eq!(dropped.dropped_fraction, 0.02);
+        assert_eq!(statuses(dropped), vec![QcStatus::Pass]);
+        assert_eq!(dropped.reasons, vec!["100 samples dropped in 2 gaps".to_string()]);
+    }
+
+    #[test]
+    fn line_noise_flatlines_and_missing_samples_are_graded() {
+        let hum: Vec<f64> = noise(6).iter().enumerate().map(|(k, sample)| sample + 3.0 * (2.0 * std::f64::consts::PI * 50.0 * k as f64 / SAMPLING_RATE).sin()).collect();
+        let mut flat = noise(7);
+        flat[1000..1750].iter_mut().for_each(|sample| *sample = 0.25);
+        let mut gappy = noise(8);
+        gappy.iter_mut().step_by(50).for_each(|sample| *sample = f64::NAN);
+        let path = write_csv("artifacts.csv", &[("hum", hum), ("flat", flat), ("gappy", gappy)], &[]);
+        let report = generate_report(std::slice::from_ref(&path), &QcOptions::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let file = &report.files[0];
+        assert_eq!(statuses(file), vec![QcStatus::Fail, QcStatus::Fail, QcStatus::Warn]);
+        let [hum, flat, gappy] = &file.channels[..] else { unreachable!() };
+        assert!(hum.line_noise_db.unwrap() > 20.0, "{:?}", hum.line_noise_db);
+        assert_eq!(flat.flatline_fraction, Some(750.0 / N_SAMPLES as f64));
+        assert_eq!(gappy.missing_fraction, 0.02);
+        assert_eq!((gappy.stats.count(), gappy.stats.missing()), (4900, 100));
+        // Every segment of the gappy channel holds a NaN, so its line noise is unknown
+        assert_eq!(gappy.line_noise_db, None);
+
+        // Raising the thresholds passes the same file
+        let lenient = QcOptions {
+            line_noise_db: QcThreshold { warn: f64::INFINITY, fail: f64::INFINITY },
+            flatline_fraction: QcThreshold { warn: 0.2, fail: 0.5 },
+            missing_fraction: QcThreshold { warn: 0.05, fail: 0.1 },
+            ..Default::default()
+        };
+        let path = temp_path("artifacts-lenient.csv");
+        std::fs::write(&path, "time,a\n0,1\n0.004,2\n0.008,3\n").unwrap();
+        assert_eq!(generate_report(std::slice::from_ref(&path), &lenient).unwrap().status, QcStatus::Pass);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(QcThreshold { warn: 0.1, fail: 0.5 }.status(f64::NAN), QcStatus::Pass);
+    }
+
+    #[test]
+    fn binary_files_unreadable_files_and_options_are_handled() {
+        let path = temp_path("raw.bin");
+        let (a, b) = (noise(9), noise(10));
+        let mut bytes: Vec<u8> = a.iter().zip(&b).flat_map(|(x, y)| x.to_le_bytes().into_iter().chain(y.to_le_bytes())).collect();
+        // A partial frame at the end
+        bytes.extend_from_slice(&[0; 8]);
+        std::fs::write(&path, &bytes).unwrap();
+        let missing = temp_path("absent.csv");
+        let edf = temp_path("night.edf");
+        std::fs::write(&edf, "0       ").unwrap();
+        let inputs = vec![path.clone(), missing, edf.clone()];
+        let options = QcOptions { sampling_rate: Some(SAMPLING_RATE), binary_channels: Some(2), chunk_size: 1000, ..Default::default() };
+        let report = generate_report(&inputs, &options).unwrap();
+
+        let raw = &report.files[0];
+        assert_eq!((raw.format, raw.n_samples, raw.malformed_rows, raw.status), (QcFormat::Binary, N_SAMPLES, 1, QcStatus::Warn));
+        assert_eq!(raw.channels.iter().map(|channel| channel.name.as_str()).collect::<Vec<_>>(), vec!["ch0", "ch1"]);
+        assert!((raw.channels[1].stats.mean() - b.iter().sum::<f64>() / N_SAMPLES as f64).abs() < 1e-12);
+        assert_eq!(statuses(raw), vec![QcStatus::Pass, QcStatus::Pass]);
+        for file in &report.files[1..] {
+            assert_eq!(file.status, QcStatus::Fail);
+            assert!(file.error.is_some() && file.channels.is_empty());
+        }
+        assert_eq!(report.files[2].format, QcFormat::Unsupported);
+
+        // Without the number of channels a binary file cannot be read
+        let unset = generate_report(std::slice::from_ref(&path), &QcOptions { binary_channels: None, ..options.clone() }).unwrap();
+        assert_eq!(unset.files[0].status, QcStatus::Fail);
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&edf).unwrap();
+
+        assert_eq!(QcFormat::of_path(Path::new("m12/lfp.CSV")), QcFormat::Csv);
+        assert_eq!(QcFormat::of_path(Path::new("m12/ap.dat")), QcFormat::Binary);
+        assert!(generate_report(&[], &QcOptions { chunk_size: 0, ..Default::default() }).is_err());
+        assert!(generate_report(&[], &QcOptions { binary_channels: Some(0), ..Default::default() }).is_err());
+        assert!(generate_report(&[], &QcOptions { saturation_limits: Some((1.0, -1.0)), ..Default::default() }).is_err());
+        assert!(generate_report(&[], &QcOptions { sampling_rate: Some(0.0), ..Default::default() }).is_err());
+        assert_eq!(generate_report(&[], &QcOptions::default()).unwrap(), QcReport::default());
+    }
+
+    #[test]
+    fn saturation_limits_count_samples_at_or_beyond_them() {
+        let path = temp_path("limits.csv");
+        std::fs::write(&path, "time,a\n0,0\n1,5\n2,-5\n3,1\n4,7\n5,2\n6,3\n7,4\n8,1\n9,0\n").unwrap();
+        let options = QcOptions { saturation_limits: Some((-5.0, 5.0)), ..Default::default() };
+        let report = generate_report(std::slice::from_ref(&path), &options).unwrap();
+        let without = generate_report(std::slice::from_ref(&path), &QcOptions::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(report.files[0].channels[0].saturated_fraction, 0.3);
+        // At the channel extremes, -5 and 7 appear once each, so nothing repeats them
+        assert_eq!(without.files[0].channels[0].saturated_fraction, 0.0);
+        assert_eq!(report.files[0].sampling_rate, Some(1.0));
+    }
+
+    #[test]
+    fn reports_export_to_csv() {
+        let path = write_csv("export.csv", &[("a", noise(11))], &[]);
+        let absent = temp_path("absent.bin");
+        let report = generate_report(&[path.clone(), absent.clone()], &QcOptions::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let csv_path = temp_path("summary.csv");
+        std::fs::write(&csv_path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(csv_path.to_str().unwrap()).unwrap();
+        report.to_csv(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let written = std::fs::read_to_string(&csv_path).unwrap();
+        std::fs::remove_file(&csv_path).unwrap();
+
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "file,file_status,channel,status,count,missing_fraction,saturated_fraction,flatline_fraction,line_noise_db,mean,std,min,max,reasons");
+        let fields: Vec<&str> = lines[1].split(',').collect();
+        assert_eq!(&fields[..8], &[path.to_str().unwrap(), "pass", "a", "pass", "5000", "0", "0", "0"]);
+        assert!(lines[2].starts_with(&format!("{},fail,,fail,,,,,,,,,,", absent.to_str().unwrap())));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn reports_round_trip_through_json() {
+        use crate::data_io::cache::Persist;
+        let path = write_csv("json.csv", &[("a", noise(12))], &[]);
+        let report = generate_report(std::slice::from_ref(&path), &QcOptions::default()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let json = temp_path("report.json");
+        report.save_json(&json).unwrap();
+        let loaded = QcReport::load_json(&json).unwrap();
+        std::fs::remove_file(&json).unwrap();
+        assert_eq!(loaded.status, report.status);
+        assert!((loaded.files[0].channels[0].line_noise_db.unwrap() - report.files[0].channels[0].line_noise_db.unwrap()).abs() < 1e-12);
+        assert_eq!(loaded.files[0].channels[0].stats.count(), N_SAMPLES);
+    }
+}
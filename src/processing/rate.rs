@@ -0,0 +1,393 @@
+// A module to estimate firing rates from spike times
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+
+/// The number of kernel widths beyond which a kernel is treated as zero
+const KERNEL_SUPPORT_WIDTHS: f64 = 10.0;
+
+/// The smoothing kernel of `kernel_rate`
+///
+/// # Arguments
+///
+/// * `Gaussian` - A Gaussian with the given standard deviation in seconds
+/// * `Exponential` - A causal exponential decay with the given time constant in seconds, so the rate only depends on past spikes
+///
+/// # Examples
+///
+/// ```
+/// let rate = kernel_rate(&times, 0.0, 60.0, Kernel::Gaussian(0.05), 0.001)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Kernel {
+    Gaussian(f64),
+    Exponential(f64),
+}
+
+/// A firing rate sampled at regular times
+///
+/// # Arguments
+///
+/// * `start_time` - The time of the first rate sample in seconds
+/// * `sampling_rate` - The number of rate samples per second
+/// * `rates` - The firing rate of each sample in spikes per second
+///
+/// # Examples
+///
+/// ```
+/// let rate = binned_rate(&times, 0.0, 60.0, 0.01)?;
+/// let (decimated, new_rate) = decimate(&rate.rates, rate.sampling_rate, 10)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FiringRate {
+    pub start_time: f64,
+    pub sampling_rate: f64,
+    pub rates: Vec<f64>,
+}
+
+/// Implementation of the FiringRate struct
+///
+/// # Methods
+///
+/// * `times` - Returns the time of each rate sample
+/// * `to_csv` - Writes the rate as `time,rate` rows
+impl FiringRate {
+    /// Returns the time of each rate sample
+    ///
+    /// # Returns
+    ///
+    /// The times in seconds, one per rate sample
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let times = rate.times();
+    /// ```
+    ///
+    pub fn times(&self) -> Vec<f64> {
+        (0..self.rates.len()).map(|k| self.start_time + k as f64 / self.sampling_rate).collect()
+    }
+
+    /// Writes the rate as `time,rate` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// rate.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["time", "rate"]))?;
+        for (time, rate) in self.times().iter().zip(&self.rates) {
+            csv_io.write_record(StringRecord::from(vec![float_format.format(*time), float_format.format(*rate)]))?;
+        }
+        Ok(())
+    }
+}
+
+/// Estimates a firing rate by counting spikes in bins
+///
+/// # Arguments
+///
+/// * `spike_times` - The spike times in seconds, in any order
+/// * `t_start` - The start of the recording in seconds, the start of the first bin
+/// * `t_stop` - The end of the recording in seconds
+/// * `bin_size` - The width of each bin in seconds
+///
+/// # Returns
+///
+/// The spike count of each bin divided by the bin width, sampled at the bin starts, or an
+/// error if the bin size is not positive or the recording ends before it starts
+///
+/// # Examples
+///
+/// ```
+/// let rate = binned_rate(&times, 0.0, 600.0, 0.05)?;
+/// ```
+///
+/// # Note
+///
+/// The last bin is as wide as the others and may extend past `t_stop`. Every spike within
+/// `[t_start, t_stop]` falls into exactly one bin, so the counts add up to the number of
+/// spikes in the recording.
+///
+pub fn binned_rate(spike_times: &[f64], t_start: f64, t_stop: f64, bin_size: f64) -> Result<FiringRate, ProcessingError> {
+    let rates = bin_counts(spike_times, t_start, t_stop, bin_size)?.into_iter().map(|count| count as f64 / bin_size).collect();
+    Ok(FiringRate { start_time: t_start, sampling_rate: 1.0 / bin_size, rates })
+}
+
+/// Counts the spikes in consecutive bins from `t_start`, as described for `binned_rate`
+pub(crate) fn bin_counts(spike_times: &[f64], t_start: f64, t_stop: f64, bin_size: f64) -> Result<Vec<usize>, ProcessingError> {
+    validate_interval(t_start, t_stop, bin_size)?;
+    let n_bins = (((t_stop - t_start) / bin_size).ceil() as usize).max(1);
+    let mut counts = vec![0; n_bins];
+    for &time in spike_times.iter().filter(|&&time| time >= t_start && time <= t_stop) {
+        let bin = (((time - t_start) / bin_size) as usize).min(n_bins - 1);
+        counts[bin] += 1;
+    }
+    Ok(counts)
+}
+
+/// Estimates a firing rate by smoothing the spike train with a kernel
+///
+/// # Arguments
+///
+/// * `spike_times` - The spike times in seconds, in any order
+/// * `t_start` - The start of the recording in seconds
+/// * `t_stop` - The end of the recording in seconds
+/// * `kernel` - The smoothing kernel
+/// * `resolution` - The spacing of the rate samples in seconds
+///
+/// # Returns
+///
+/// The smoothed rate at the centers of consecutive intervals of `resolution` seconds, or an
+/// error if the resolution or kernel width is not positive or the recording ends before it
+/// starts
+///
+/// # Examples
+///
+/// ```
+/// let psth_like = kernel_rate(&times, 0.0, 600.0, Kernel::Gaussian(0.02), 0.001)?;
+/// let online = kernel_rate(&times, 0.0, 600.0, Kernel::Exponential(0.1), 0.001)?;
+/// ```
+///
+/// # Note
+///
+/// Near the edges of the recording part of the kernel falls outside of it, which would
+/// depress the rate. The rate at each time is therefore divided by the fraction of the kernel
+/// mass that lies within `[t_start, t_stop]`. Spikes outside the recording are ignored and
+/// kernels are truncated at ten widths.
+///
+pub fn kernel_rate(spike_times: &[f64], t_start: f64, t_stop: f64, kernel: Kernel, resolution: f64) -> Result<FiringRate, ProcessingError> {
+    validate_interval(t_start, t_stop, resolution)?;
+    let width = match kernel {
+        Kernel::Gaussian(sigma) => sigma,
+        Kernel::Exponential(tau) => tau,
+    };
+    if !(width > 0.0 && width.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!("Kernel width must be positive, got {:?}", kernel)));
+    }
+
+    let n = (((t_stop - t_start) / resolution).ceil() as usize).max(1);
+    let first_time = t_start + resolution / 2.0;
+    let time_at = |k: usize| first_time + k as f64 * resolution;
+    let mut rates = vec![0.0; n];
+    for &spike in spike_times.iter().filter(|&&time| time >= t_start && time <= t_stop) {
+        // The rate samples whose kernel covers this spike
+        let (earliest, latest) = match kernel {
+            Kernel::Gaussian(sigma) => (spike - KERNEL_SUPPORT_WIDTHS * sigma, spike + KERNEL_SUPPORT_WIDTHS * sigma),
+            Kernel::Exponential(tau) => (spike, spike + KERNEL_SUPPORT_WIDTHS * tau),
+        };
+        let first = ((earliest - first_time) / resolution).ceil().max(0.0) as usize;
+        let last = ((latest - first_time) / resolution).floor();
+        if last < 0.0 {
+            continue;
+        }
+        for (k, rate) in rates.iter_mut().enumerate().take((last as usize).min(n - 1) + 1).skip(first) {
+            *rate += kernel_value(kernel, time_at(k) - spike);
+        }
+    }
+    for (k, rate) in rates.iter_mut().enumerate() {
+        let mass = kernel_mass(kernel, t_start - time_at(k), t_stop - time_at(k));
+        if mass > 0.0 {
+            *rate /= mass;
+        }
+    }
+    Ok(FiringRate { start_time: first_time, sampling_rate: 1.0 / resolution, rates })
+}
+
+fn validate_interval(t_start: f64, t_stop: f64, step: f64) -> Result<(), ProcessingError> {
+    if !(step > 0.0 && step.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!("Bin size must be positive, got {}", step)));
+    }
+    if !(t_stop > t_start && t_start.is_finite() && t_stop.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Recording must end after it starts, got {} to {} s",
+            t_start, t_stop
+        )));
+    }
+    Ok(())
+}
+
+/// Evaluates a kernel at `lag = t - spike` seconds
+fn kernel_value(kernel: Kernel, lag: f64) -> f64 {
+    match kernel {
+        Kernel::Gaussian(sigma) => (-0.5 * (lag / sigma).powi(2)).exp() / (sigma * (2.0 * std::f64::consts::PI).sqrt()),
+        Kernel::Exponential(tau) if lag >= 0.0 => (-lag / tau).exp() / tau,
+        Kernel::Exponential(_) => 0.0,
+    }
+}
+
+/// Integrates a kernel centered on a rate sample over the spike positions `[low, high]` relative to it
+fn kernel_mass(kernel: Kernel, low: f64, high: f64) -> f64 {
+    match kernel {
+        Kernel::Gaussian(sigma) => {
+            let cdf = |x: f64| 0.5 * erfc(-x / (sigma * std::f64::consts::SQRT_2));
+            cdf(high) - cdf(low)
+        }
+        Kernel::Exponential(tau) => {
+            // Only spikes before the rate sample contribute, at lags from -high to -low
+            let lag_min = (-high).max(0.0);
+            let lag_max = (-low).max(0.0);
+            (-lag_min / tau).exp() - (-lag_max / tau).exp()
+        }
+    }
+}
+
+/// The complementary error function, with a fractional error below 1.2e-7
+fn erfc(x: f64) -> f64 {
+    let z = x.abs();
+    let t = 1.0 / (1.0 + 0.5 * z);
+    let polynomial = -z * z - 1.26551223
+        + t * (1.00002368
+            + t * (0.37409196
+                + t * (0.09678418
+                    + t * (-0.18628806
+                        + t * (0.27886807 + t * (-1.13520398 + t * (1.48851587 + t * (-0.82215223 + t * 0.17087277))))))));
+    let value = t * polynomial.exp();
+    if x >= 0.0 {
+        value
+    } else {
+        2.0 - value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::spike_train::SpikeTrain;
+    use crate::processing::random::SeededRng;
+
+    /// A homogeneous Poisson spike train from exponential inter-spike intervals
+    fn poisson(rate: f64, t_start: f64, t_stop: f64, rng: &mut SeededRng) -> Vec<f64> {
+        let mut times = Vec::new();
+        let mut time = t_start;
+        loop {
+            time -= (1.0 - rng.next_f64()).ln() / rate;
+            if time > t_stop {
+                return times;
+            }
+            times.push(time);
+        }
+    }
+
+    #[test]
+    fn binned_rate_of_a_poisson_train_is_within_tolerance_and_conserves_the_count() {
+        let mut rng = SeededRng::new(1);
+        let times = poisson(20.0, 0.0, 200.37, &mut rng);
+        let rate = binned_rate(&times, 0.0, 200.37, 1.0).unwrap();
+        assert_eq!(rate.rates.len(), 201);
+        assert_eq!(rate.start_time, 0.0);
+        assert_eq!(rate.times()[3], 3.0);
+        let counts: f64 = rate.rates.iter().map(|r| r * 1.0).sum();
+        assert_eq!(counts as usize, times.len());
+
+        // The mean count of the full bins is 20 with a standard error of 0.32, and their variance is 20 too
+        let full = &rate.rates[..200];
+        let mean = full.iter().sum::<f64>() / 200.0;
+        let variance = full.iter().map(|r| (r - mean).powi(2)).sum::<f64>() / 199.0;
+        assert!((mean - 20.0).abs() < 1.3, "{}", mean);
+        assert!((variance / 20.0 - 1.0).abs() < 0.3, "{}", variance);
+
+        // Spikes on the interval bounds are counted once, those outside are ignored
+        let counts = bin_counts(&[-0.5, 0.0, 0.25, 0.5, 0.999, 1.0, 1.5, 2.0], 0.0, 1.0, 0.25).unwrap();
+        assert_eq!(counts, vec![1, 1, 1, 2]);
+        assert_eq!(counts.iter().sum::<usize>(), 5);
+        let rates = binned_rate(&[0.1, 0.2, 0.3], 0.0, 1.0, 0.5).unwrap();
+        assert_eq!(rates.rates, vec![6.0, 0.0]);
+        assert_eq!(rates.sampling_rate, 2.0);
+    }
+
+    #[test]
+    fn kernel_rates_are_not_depressed_at_the_edges() {
+        let mut rng = SeededRng::new(2);
+        let trials = 1000;
+        let (mut gaussian_edges, mut gaussian_middle, mut exponential_start, mut exponential_end) = (0.0, 0.0, 0.0, 0.0);
+        for _ in 0..trials {
+            let times = poisson(20.0, 0.0, 1.0, &mut rng);
+            let gaussian = kernel_rate(&times, 0.0, 1.0, Kernel::Gaussian(0.1), 0.01).unwrap();
+            assert_eq!(gaussian.rates.len(), 100);
+            gaussian_edges += (gaussian.rates[0] + gaussian.rates[99]) / 2.0;
+            gaussian_middle += gaussian.rates[50];
+            let exponential = kernel_rate(&times, 0.0, 1.0, Kernel::Exponential(0.1), 0.01).unwrap();
+            // 0.055 s, half a time constant into the recording
+            exponential_start += exponential.rates[5];
+            exponential_end += exponential.rates[99];
+        }
+        let trials = trials as f64;
+        // The standard errors are about 0.4, 0.25, 0.65 and 0.3 spikes/s, while dropping the
+        // normalization would halve the Gaussian edges and cut the exponential start to 40%
+        assert!((gaussian_edges / trials - 20.0).abs() < 1.5, "{}", gaussian_edges / trials);
+        assert!((gaussian_middle / trials - 20.0).abs() < 1.0, "{}", gaussian_middle / trials);
+        assert!((exponential_start / trials - 20.0).abs() < 2.5, "{}", exponential_start / trials);
+        assert!((exponential_end / trials - 20.0).abs() < 1.2, "{}", exponential_end / trials);
+    }
+
+    #[test]
+    fn a_single_spike_is_smoothed_into_the_kernel_shape() {
+        let gaussian = kernel_rate(&[5.0], 0.0, 10.0, Kernel::Gaussian(0.2), 0.001).unwrap();
+        assert_eq!(gaussian.start_time, 0.0005);
+        assert_eq!(gaussian.sampling_rate, 1000.0);
+        assert!((gaussian.rates.iter().sum::<f64>() * 0.001 - 1.0).abs() < 1e-9);
+        let peak = gaussian.rates.iter().copied().fold(0.0, f64::max);
+        assert!((peak - 1.0 / (0.2 * (2.0 * std::f64::consts::PI).sqrt())).abs() < 1e-4);
+
+        // The causal kernel starts at the spike and decays with the time constant, divided by
+        // the kernel mass after the start of the recording
+        let exponential = kernel_rate(&[5.0], 0.0, 10.0, Kernel::Exponential(0.5), 0.001).unwrap();
+        let times = exponential.times();
+        for (time, rate) in times.iter().zip(&exponential.rates) {
+            let mass = 1.0 - (-time / 0.5).exp();
+            let expected = if *time < 5.0 { 0.0 } else { (-(time - 5.0) / 0.5).exp() / 0.5 / mass };
+            assert!((rate - expected).abs() < 1e-9, "{}: {} vs {}", time, rate, expected);
+        }
+
+        assert!(kernel_rate(&[], 0.0, 1.0, Kernel::Gaussian(0.1), 0.01).unwrap().rates.iter().all(|rate| *rate == 0.0));
+        assert!(kernel_rate(&[0.5], 0.0, 1.0, Kernel::Gaussian(0.0), 0.01).is_err());
+        assert!(kernel_rate(&[0.5], 0.0, 1.0, Kernel::Exponential(-1.0), 0.01).is_err());
+        assert!(kernel_rate(&[0.5], 0.0, 1.0, Kernel::Gaussian(0.1), 0.0).is_err());
+        assert!(binned_rate(&[0.5], 1.0, 1.0, 0.1).is_err());
+        assert!(binned_rate(&[0.5], 0.0, 1.0, f64::NAN).is_err());
+    }
+
+    #[test]
+    fn spike_train_rates_match_and_export_to_csv() {
+        let mut rng = SeededRng::new(3);
+        let times = poisson(5.0, 2.0, 12.0, &mut rng);
+        let train = SpikeTrain::new(times.clone(), None, 2.0, 12.0).unwrap();
+        assert_eq!(train.binned_rate(0.5).unwrap(), binned_rate(&times, 2.0, 12.0, 0.5).unwrap());
+        assert_eq!(train.binned_counts(0.5).unwrap().iter().sum::<usize>(), times.len());
+
+        let rate = FiringRate { start_time: 1.0, sampling_rate: 4.0, rates: vec![0.0, 8.0, 4.0] };
+        let path = std::env::temp_dir().join(format!("neurorust-rate-{}-rate.csv", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        rate.to_csv(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let rows: Vec<Vec<f64>> = written.lines().skip(1).map(|line| line.split(',').map(|value| value.parse().unwrap()).collect()).collect();
+        assert_eq!(written.lines().next(), Some("time,rate"));
+        assert_eq!(rows, vec![vec![1.0, 0.0], vec![1.25, 8.0], vec![1.5, 4.0]]);
+    }
+}
@@ -0,0 +1,278 @@
+// A module to re-reference multichannel recordings
+
+// Written by Amin Alam in 2024
+
+use crate::processing::error::ProcessingError;
+
+/// The name of the retained common average reference channel
+pub const COMMON_AVERAGE_NAME: &str = "CAR";
+
+/// The name of the retained reference channel of `Reference::Channel` and `Reference::Mean`
+pub const REFERENCE_NAME: &str = "REF";
+
+/// The referencing scheme applied by `rereference`
+///
+/// # Arguments
+///
+/// * `CommonAverage` - Subtracts the mean of all good channels from every channel
+/// * `Channel` - Subtracts the named channel from every channel
+/// * `Mean` - Subtracts the mean of the named channels from every channel, e.g. linked mastoids
+/// * `Bipolar` - Replaces the channels by the differences of the given anode and cathode pairs
+///
+/// # Examples
+///
+/// ```
+/// let reference = Reference::Bipolar(vec![("C3".to_string(), "C4".to_string())]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Reference {
+    CommonAverage,
+    Channel(String),
+    Mean(Vec<String>),
+    Bipolar(Vec<(String, String)>),
+}
+
+/// Re-references a set of channels
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel, all of the same length
+/// * `names` - The name of each channel
+/// * `bad_channels` - The names of the channels left out of the common average
+/// * `reference` - The referencing scheme
+/// * `keep_reference` - Appends the subtracted reference signal as an extra channel if true
+///
+/// # Returns
+///
+/// The re-referenced channels and their names, or an error if the channels and names do not
+/// match, a channel name is unknown, or no good channel is left for the common average
+///
+/// # Examples
+///
+/// ```
+/// let (car, car_names) = rereference(&channels, &names, &["T7".to_string()], &Reference::CommonAverage, false)?;
+/// let (bipolar, bipolar_names) = rereference(&channels, &names, &[], &Reference::Bipolar(pairs), false)?;
+/// ```
+///
+/// # Note
+///
+/// Bad channels are still re-referenced, they are only excluded from the average. The
+/// retained reference is named `CAR` for the common average and `REF` for the channel and
+/// mean references; bipolar derivations have no single reference to retain. Bipolar
+/// channels are named `anode-cathode`, e.g. `C3-C4`.
+///
+pub fn rereference(
+    channels: &[Vec<f64>],
+    names: &[String],
+    bad_channels: &[String],
+    reference: &Reference,
+    keep_reference: bool,
+) -> Result<(Vec<Vec<f64>>, Vec<String>), ProcessingError> {
+    if channels.len() != names.len() {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Got {} channels but {} channel names",
+            channels.len(),
+            names.len()
+        )));
+    }
+    let length = channels.first().map_or(0, |channel| channel.len());
+    if let Some(index) = channels.iter().position(|channel| channel.len() != length) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Channel {} has {} samples but channel {} has {}",
+            names[index],
+            channels[index].len(),
+            names[0],
+            length
+        )));
+    }
+    let index_of = |name: &str| {
+        names
+            .iter()
+            .position(|candidate| candidate == name)
+            .ok_or_else(|| ProcessingError::InvalidParameter(format!("Unknown channel {}", name)))
+    };
+
+    let (signal, reference_name) = match reference {
+        Reference::CommonAverage => {
+            let good: Vec<usize> = (0..channels.len()).filter(|&index| !bad_channels.contains(&names[index])).collect();
+            if good.is_empty() {
+                return Err(ProcessingError::InvalidParameter(
+                    "No good channel is left for the common average reference".to_string(),
+                ));
+            }
+            (mean_of(channels, &good, length), COMMON_AVERAGE_NAME)
+        }
+        Reference::Channel(name) => (channels[index_of(name)?].clone(), REFERENCE_NAME),
+        Reference::Mean(reference_names) => {
+            if reference_names.is_empty() {
+                return Err(ProcessingError::InvalidParameter("Mean reference needs at least one channel".to_string()));
+            }
+            let indices = reference_names.iter().map(|name| index_of(name)).collect::<Result<Vec<usize>, ProcessingError>>()?;
+            (mean_of(channels, &indices, length), REFERENCE_NAME)
+        }
+        Reference::Bipolar(pairs) => {
+            let mut derived = Vec::with_capacity(pairs.len());
+            let mut derived_names = Vec::with_capacity(pairs.len());
+            for (anode, cathode) in pairs {
+                let (anode_index, cathode_index) = (index_of(anode)?, index_of(cathode)?);
+                derived.push(
+                    channels[anode_index]
+                        .iter()
+                        .zip(&channels[cathode_index])
+                        .map(|(a, c)| a - c)
+                        .collect(),
+                );
+                derived_names.push(format!("{}-{}", anode, cathode));
+            }
+            return Ok((derived, derived_names));
+        }
+    };
+
+    let mut referenced: Vec<Vec<f64>> = channels
+        .iter()
+        .map(|channel| channel.iter().zip(&signal).map(|(sample, reference)| sample - reference).collect())
+        .collect();
+    let mut referenced_names = names.to_vec();
+    if keep_reference {
+        referenced.push(signal);
+        referenced_names.push(reference_name.to_string());
+    }
+    Ok((referenced, referenced_names))
+}
+
+/// Removes the bad channels of a set of channels
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel
+/// * `names` - The name of each channel
+/// * `bad_channels` - The names of the channels to remove
+///
+/// # Returns
+///
+/// The remaining channels and their names, or an error if the channels and names do not
+/// match or a bad channel is unknown
+///
+/// # Examples
+///
+/// ```
+/// let report = detect_bad_channels(&channels, &names, 1000.0, &BadChannelOptions::default())?;
+/// let (good, good_names) = drop_bad_channels(&channels, &names, &report.names())?;
+/// ```
+///
+pub fn drop_bad_channels(channels: &[Vec<f64>], names: &[String], bad_channels: &[String]) -> Result<(Vec<Vec<f64>>, Vec<String>), ProcessingError> {
+    if channels.len() != names.len() {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Got {} channels but {} channel names",
+            channels.len(),
+            names.len()
+        )));
+    }
+    if let Some(unknown) = bad_channels.iter().find(|name| !names.contains(name)) {
+        return Err(ProcessingError::InvalidParameter(format!("Unknown channel {}", unknown)));
+    }
+    Ok(channels
+        .iter()
+        .zip(names)
+        .filter(|(_, name)| !bad_channels.contains(name))
+        .map(|(channel, name)| (channel.clone(), name.clone()))
+        .unzip())
+}
+
+/// Averages the given channels sample by sample
+fn mean_of(channels: &[Vec<f64>], indices: &[usize], length: usize) -> Vec<f64> {
+    let mut mean = vec![0.0; length];
+    for &index in indices {
+        for (sum, sample) in mean.iter_mut().zip(&channels[index]) {
+            *sum += sample;
+        }
+    }
+    let count = indices.len() as f64;
+    mean.iter_mut().for_each(|sum| *sum /= count);
+    mean
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    fn montage() -> (Vec<Vec<f64>>, Vec<String>) {
+        let mut rng = SeededRng::new(117);
+        // Every channel shares a common drift, as through a noisy reference electrode
+        let common: Vec<f64> = (0..500).map(|k| 50.0 + (k as f64 * 0.01).sin() * 20.0).collect();
+        let channels = (0..5).map(|c| common.iter().map(|value| value + c as f64 + rng.next_gaussian()).collect()).collect();
+        let names = ["Fz", "C3", "C4", "T7", "Pz"].iter().map(|name| name.to_string()).collect();
+        (channels, names)
+    }
+
+    fn names(list: &[&str]) -> Vec<String> {
+        list.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn after_car_the_mean_of_the_good_channels_is_zero_at_every_sample() {
+        let (channels, channel_names) = montage();
+        let bad = names(&["T7"]);
+        let (referenced, referenced_names) = rereference(&channels, &channel_names, &bad, &Reference::CommonAverage, false).unwrap();
+        assert_eq!(referenced_names, channel_names);
+        let good_mean = |k: usize| [0, 1, 2, 4].iter().map(|&c| referenced[c][k]).sum::<f64>() / 4.0;
+        assert!((0..500).all(|k| good_mean(k).abs() < 1e-12));
+        // The bad channel is re-referenced too, just not averaged in
+        let average = [0, 1, 2, 4].iter().map(|&c| channels[c][7]).sum::<f64>() / 4.0;
+        assert!((referenced[3][7] - (channels[3][7] - average)).abs() < 1e-12);
+
+        let (kept, kept_names) = rereference(&channels, &channel_names, &bad, &Reference::CommonAverage, true).unwrap();
+        assert_eq!(kept_names.last().unwrap(), COMMON_AVERAGE_NAME);
+        assert!((kept[5][7] - average).abs() < 1e-12);
+        assert_eq!(&kept[..5], &referenced[..]);
+        assert!(rereference(&channels, &channel_names, &channel_names, &Reference::CommonAverage, false).is_err());
+    }
+
+    #[test]
+    fn bipolar_of_a_channel_with_itself_is_exactly_zero() {
+        let (channels, channel_names) = montage();
+        let pairs = vec![("C3".to_string(), "C3".to_string()), ("C3".to_string(), "C4".to_string()), ("Fz".to_string(), "Pz".to_string())];
+        let (bipolar, bipolar_names) = rereference(&channels, &channel_names, &[], &Reference::Bipolar(pairs.clone()), true).unwrap();
+        assert_eq!(bipolar_names, names(&["C3-C3", "C3-C4", "Fz-Pz"]));
+        assert!(bipolar[0].iter().all(|value| *value == 0.0));
+        assert!(bipolar[1].iter().zip(channels[1].iter().zip(&channels[2])).all(|(d, (a, c))| *d == a - c));
+        // There is no single reference to retain
+        assert_eq!(bipolar.len(), 3);
+        let unknown = Reference::Bipolar(vec![("C3".to_string(), "Cz".to_string())]);
+        assert!(matches!(rereference(&channels, &channel_names, &[], &unknown, false), Err(ProcessingError::InvalidParameter(message)) if message == "Unknown channel Cz"));
+    }
+
+    #[test]
+    fn channel_and_mean_references_subtract_the_reference_from_every_channel() {
+        let (channels, channel_names) = montage();
+        let (single, single_names) = rereference(&channels, &channel_names, &[], &Reference::Channel("Pz".to_string()), true).unwrap();
+        assert_eq!(single_names.last().unwrap(), REFERENCE_NAME);
+        assert_eq!(single[5], channels[4]);
+        assert!(single[4].iter().all(|value| *value == 0.0));
+        assert!(single[0].iter().zip(channels[0].iter().zip(&channels[4])).all(|(r, (a, p))| *r == a - p));
+
+        // Linked mastoids-style: the mean of two channels
+        let (linked, _) = rereference(&channels, &channel_names, &[], &Reference::Mean(names(&["C3", "C4"])), false).unwrap();
+        for k in [0, 250, 499] {
+            assert!((linked[1][k] + linked[2][k]).abs() < 1e-12);
+            assert!((linked[0][k] - (channels[0][k] - (channels[1][k] + channels[2][k]) / 2.0)).abs() < 1e-12);
+        }
+        assert!(rereference(&channels, &channel_names, &[], &Reference::Mean(Vec::new()), false).is_err());
+        assert!(rereference(&channels, &channel_names, &[], &Reference::Channel("Oz".to_string()), false).is_err());
+    }
+
+    #[test]
+    fn mismatched_inputs_are_errors_and_bad_channels_can_be_dropped() {
+        let (mut channels, channel_names) = montage();
+        assert!(rereference(&channels, &channel_names[..4], &[], &Reference::CommonAverage, false).is_err());
+        let (good, good_names) = drop_bad_channels(&channels, &channel_names, &names(&["C4", "T7"])).unwrap();
+        assert_eq!(good_names, names(&["Fz", "C3", "Pz"]));
+        assert_eq!(good[2], channels[4]);
+        assert!(drop_bad_channels(&channels, &channel_names, &names(&["Oz"])).is_err());
+        assert!(drop_bad_channels(&channels, &channel_names[..2], &[]).is_err());
+        channels[2].pop();
+        assert!(matches!(rereference(&channels, &channel_names, &[], &Reference::CommonAverage, false), Err(ProcessingError::InvalidParameter(message)) if message == "Channel C4 has 499 samples but channel Fz has 500"));
+    }
+}
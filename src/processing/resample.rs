@@ -0,0 +1,352 @@
+// A module to change the sampling rate of signals
+
+// Written by Amin Alam in 2024
+
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::{butterworth, fir_design, map_channels, sinc, validate_sampling_rate, FilterKind};
+use crate::processing::window::Window;
+
+/// The order of the Butterworth anti-aliasing filter used by `decimate`
+pub(crate) const DECIMATION_FILTER_ORDER: usize = 10;
+
+/// The anti-aliasing cutoff of `decimate`, as a fraction of the new Nyquist frequency
+pub(crate) const DECIMATION_CUTOFF_FRACTION: f64 = 0.8;
+
+/// The largest factor decimated in a single stage
+const MAX_STAGE_FACTOR: usize = 13;
+
+/// The largest numerator or denominator of the rational ratio used by polyphase resampling
+const MAX_RATIO_TERM: usize = 1000;
+
+/// The number of zero crossings on each side of the interpolation kernel of sinc resampling
+const SINC_ZERO_CROSSINGS: usize = 16;
+
+/// The method used by `resample`
+///
+/// # Arguments
+///
+/// * `Polyphase` - Upsampling, FIR lowpass filtering and downsampling with a rational approximation of the ratio
+/// * `Sinc` - Band-limited interpolation with a Blackman-windowed sinc kernel at the exact new rate
+/// * `Linear` - Linear interpolation between neighboring samples, fast but neither anti-aliased nor band-limited
+///
+/// # Examples
+///
+/// ```
+/// let (eye_aligned, rate) = resample(&neural, 30000.0, 1100.0, ResampleMethod::Polyphase)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ResampleMethod {
+    Polyphase,
+    Sinc,
+    Linear,
+}
+
+/// Decimates a signal by an integer factor after anti-aliasing
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `factor` - The ratio between the old and the new sampling rate
+///
+/// # Returns
+///
+/// The decimated samples and the new sampling rate, or an error if the factor is zero or
+/// the signal is too short for the anti-aliasing filter
+///
+/// # Examples
+///
+/// ```
+/// // 30 kHz wideband data to 1 kHz LFP, decimated in stages of 5 and 6
+/// let (lfp, lfp_rate) = decimate(&wideband, 30000.0, 30)?;
+/// ```
+///
+/// # Note
+///
+/// Every stage applies an order-10 Butterworth lowpass at 0.8 times the new Nyquist frequency
+/// with zero phase (`filtfilt`) and keeps every Nth sample starting with the first. The first
+/// output sample is the first input sample, so the start time of the signal is unchanged.
+/// Frequencies from 1.2 times the new Nyquist frequency up, the ones that would alias below
+/// the cutoff, are attenuated by at least 70 dB. The prime factors of the factor are grouped
+/// into stages of at most 13 to keep the filters well-conditioned, but a prime factor above
+/// 13, such as 17, cannot be split and is decimated in one stage.
+///
+pub fn decimate(samples: &[f64], sampling_rate: f64, factor: usize) -> Result<(Vec<f64>, f64), ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    if factor == 0 {
+        return Err(ProcessingError::InvalidParameter("Decimation factor must be at least 1".to_string()));
+    }
+
+    let mut decimated = samples.to_vec();
+    let mut rate = sampling_rate;
+    for stage in decimation_stages(factor) {
+        let new_rate = rate / stage as f64;
+        let cutoff = DECIMATION_CUTOFF_FRACTION * new_rate / 2.0;
+        let filter = butterworth(DECIMATION_FILTER_ORDER, FilterKind::Lowpass(cutoff), rate)?;
+        decimated = filter.filtfilt(&decimated)?.into_iter().step_by(stage).collect();
+        rate = new_rate;
+    }
+    Ok((decimated, rate))
+}
+
+/// Decimates several channels by an integer factor after anti-aliasing
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `factor` - The ratio between the old and the new sampling rate
+///
+/// # Returns
+///
+/// The decimated samples of each channel and the new sampling rate, or the first error encountered
+///
+/// # Examples
+///
+/// ```
+/// let (lfp, lfp_rate) = decimate_channels(&channels, 30000.0, 30)?;
+/// ```
+///
+/// # Note
+///
+/// The channels are processed in parallel
+///
+pub fn decimate_channels(channels: &[Vec<f64>], sampling_rate: f64, factor: usize) -> Result<(Vec<Vec<f64>>, f64), ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    if factor == 0 {
+        return Err(ProcessingError::InvalidParameter("Decimation factor must be at least 1".to_string()));
+    }
+    let decimated = map_channels(channels, |channel| decimate(channel, sampling_rate, factor).map(|(samples, _)| samples))
+        .into_iter()
+        .collect::<Result<Vec<Vec<f64>>, ProcessingError>>()?;
+    Ok((decimated, sampling_rate / factor as f64))
+}
+
+/// Resamples a signal to an arbitrary sampling rate
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `new_rate` - The requested sampling rate in Hz
+/// * `method` - The resampling method
+///
+/// # Returns
+///
+/// The resampled samples and their sampling rate, or an error if a rate is not positive
+///
+/// # Examples
+///
+/// ```
+/// let (resampled, rate) = resample(&samples, 30000.0, 1100.0, ResampleMethod::Sinc)?;
+/// let times: Vec<f64> = (0..resampled.len()).map(|k| start_time + k as f64 / rate).collect();
+/// ```
+///
+/// # Note
+///
+/// The output holds `ceil(n * new_rate / sampling_rate)` samples, the first one at the time of
+/// the first input sample, so the duration is preserved to within one output sample.
+/// `Polyphase` approximates the ratio by a fraction with terms of at most 1000, so the
+/// returned rate can differ marginally from `new_rate`; `Sinc` and `Linear` are exact. Both
+/// band-limited methods lowpass at the lower of the two Nyquist frequencies and treat the
+/// signal as zero outside of its samples, which attenuates the first and last few samples.
+///
+pub fn resample(samples: &[f64], sampling_rate: f64, new_rate: f64, method: ResampleMethod) -> Result<(Vec<f64>, f64), ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    validate_sampling_rate(new_rate)?;
+    if samples.is_empty() {
+        return Ok((Vec::new(), new_rate));
+    }
+
+    match method {
+        ResampleMethod::Polyphase => {
+            let (up, down) = rational_approximation(new_rate / sampling_rate, MAX_RATIO_TERM);
+            Ok((resample_polyphase(samples, sampling_rate, up, down)?, sampling_rate * up as f64 / down as f64))
+        }
+        ResampleMethod::Sinc => Ok((resample_sinc(samples, sampling_rate / new_rate), new_rate)),
+        ResampleMethod::Linear => Ok((resample_linear(samples, sampling_rate / new_rate), new_rate)),
+    }
+}
+
+/// Resamples by `up / down` with a windowed-sinc FIR lowpass applied at the upsampled rate
+fn resample_polyphase(samples: &[f64], sampling_rate: f64, up: usize, down: usize) -> Result<Vec<f64>, ProcessingError> {
+    if up == down {
+        return Ok(samples.to_vec());
+    }
+    let max_factor = up.max(down);
+    let upsampled_rate = sampling_rate * up as f64;
+    let cutoff = upsampled_rate / 2.0 / max_factor as f64;
+    let half_len = SINC_ZERO_CROSSINGS * max_factor;
+    let filter = fir_design(2 * half_len + 1, FilterKind::Lowpass(cutoff), Window::Blackman, upsampled_rate)?;
+    let taps: Vec<f64> = filter.taps().iter().map(|tap| tap * up as f64).collect();
+
+    let n_out = (samples.len() * up).div_ceil(down);
+    let output = (0..n_out)
+        .map(|m| {
+            // Index into the zero-stuffed signal, shifted by the group delay of the filter
+            let center = m * down + half_len;
+            let mut sum = 0.0;
+            let mut tap = center % up;
+            while tap < taps.len() && tap <= center {
+                let index = (center - tap) / up;
+                if index < samples.len() {
+                    sum += taps[tap] * samples[index];
+                }
+                tap += up;
+            }
+            sum
+        })
+        .collect();
+    Ok(output)
+}
+
+/// Interpolates at every `step` input samples with a Blackman-windowed sinc kernel
+fn resample_sinc(samples: &[f64], step: f64) -> Vec<f64> {
+    let bandwidth = (1.0 / step).min(1.0);
+    let half_width = (SINC_ZERO_CROSSINGS as f64 / bandwidth).ceil() as i64;
+    let n_out = output_length(samples.len(), step);
+    (0..n_out)
+        .map(|k| {
+            let position = k as f64 * step;
+            let nearest = position.floor() as i64;
+            let first = (nearest - half_width + 1).max(0);
+            let last = (nearest + half_width).min(samples.len() as i64 - 1);
+            (first..=last)
+                .map(|i| {
+                    let offset = position - i as f64;
+                    let taper = Window::Blackman.value_at(0.5 + offset / (2.0 * half_width as f64));
+                    samples[i as usize] * bandwidth * sinc(bandwidth * offset) * taper
+                })
+                .sum()
+        })
+        .collect()
+}
+
+/// Interpolates linearly at every `step` input samples
+fn resample_linear(samples: &[f64], step: f64) -> Vec<f64> {
+    let last = samples.len() - 1;
+    (0..output_length(samples.len(), step))
+        .map(|k| {
+            let position = k as f64 * step;
+            let i = (position.floor() as usize).min(last);
+            if i == last {
+                samples[last]
+            } else {
+                let fraction = position - i as f64;
+                samples[i] + fraction * (samples[i + 1] - samples[i])
+            }
+        })
+        .collect()
+}
+
+fn output_length(n_samples: usize, step: f64) -> usize {
+    (n_samples as f64 / step).ceil() as usize
+}
+
+/// Finds the fraction `up / down` closest to `ratio` with both terms at most `max_term`
+pub(crate) fn rational_approximation(ratio: f64, max_term: usize) -> (usize, usize) {
+    // Convergents of the continued fraction expansion
+    let (mut p0, mut q0, mut p1, mut q1) = (0usize, 1usize, 1usize, 0usize);
+    let mut remainder = ratio;
+    let mut best = (ratio.round().max(1.0) as usize, 1);
+    for _ in 0..64 {
+        let term = remainder.floor();
+        if term > max_term as f64 {
+            break;
+        }
+        let a = term as usize;
+        let (p2, q2) = (a * p1 + p0, a * q1 + q0);
+        if p2 > max_term || q2 > max_term {
+            break;
+        }
+        if p2 > 0 {
+            best = (p2, q2);
+        }
+        (p0, q0, p1, q1) = (p1, q1, p2, q2);
+        let fraction = remainder - term;
+        if fraction.abs() < 1e-12 || (p2 as f64 / q2 as f64 - ratio).abs() <= 1e-12 * ratio {
+            break;
+        }
+        remainder = 1.0 / fraction;
+    }
+    best
+}
+
+/// Splits a decimation factor into stages of at most `MAX_STAGE_FACTOR`, or of one larger prime
+///
+/// The prime factors are grouped greedily from the largest, so 30 becomes `[5, 6]` and 34
+/// becomes `[17, 2]`.
+pub(crate) fn decimation_stages(factor: usize) -> Vec<usize> {
+    let mut primes = Vec::new();
+    let mut remaining = factor;
+    let mut divisor = 2;
+    while divisor * divisor <= remaining {
+        while remaining.is_multiple_of(divisor) {
+            primes.push(divisor);
+            remaining /= divisor;
+        }
+        divisor += 1;
+    }
+    if remaining > 1 {
+        primes.push(remaining);
+    }
+    primes.sort_unstable_by(|a, b| b.cmp(a));
+
+    let mut stages: Vec<usize> = Vec::new();
+    for prime in primes {
+        match stages.last_mut() {
+            Some(stage) if *stage * prime <= MAX_STAGE_FACTOR => *stage *= prime,
+            _ => stages.push(prime),
+        }
+    }
+    stages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    /// The amplitude of the sine of a frequency in the middle half of a signal, by projection
+    fn amplitude(samples: &[f64], sampling_rate: f64, frequency: f64) -> f64 {
+        let middle = &samples[samples.len() / 4..3 * samples.len() / 4];
+        let (mut cosine, mut sine) = (0.0, 0.0);
+        for (k, sample) in middle.iter().enumerate() {
+            let phase = 2.0 * PI * frequency * k as f64 / sampling_rate;
+            cosine += sample * phase.cos();
+            sine += sample * phase.sin();
+        }
+        2.0 * cosine.hypot(sine) / middle.len() as f64
+    }
+
+    fn sine(frequency: f64, sampling_rate: f64, n: usize) -> Vec<f64> {
+        (0..n).map(|k| (2.0 * PI * frequency * k as f64 / sampling_rate).sin()).collect()
+    }
+
+    #[test]
+    fn stages_group_primes_up_to_13_and_keep_larger_primes_whole() {
+        assert_eq!(decimation_stages(30), vec![5, 6]);
+        assert_eq!(decimation_stages(17), vec![17]);
+        assert_eq!(decimation_stages(34), vec![17, 2]);
+        assert_eq!(decimation_stages(1), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn aliasing_frequencies_lose_60_db_and_the_passband_is_kept() {
+        for (factor, sampling_rate) in [(30, 30000.0), (17, 17000.0)] {
+            let new_rate = sampling_rate / factor as f64;
+            let nyquist = new_rate / 2.0;
+            let n = 2 * sampling_rate as usize;
+            // 1.2 times the new Nyquist frequency aliases onto the cutoff, 0.8 times it
+            let (aliased, rate) = decimate(&sine(1.2 * nyquist, sampling_rate, n), sampling_rate, factor).unwrap();
+            assert_eq!(rate, new_rate);
+            let alias = amplitude(&aliased, rate, new_rate - 1.2 * nyquist);
+            assert!(20.0 * alias.log10() < -60.0, "factor {}: {} dB", factor, 20.0 * alias.log10());
+
+            let (kept, _) = decimate(&sine(0.5 * nyquist, sampling_rate, n), sampling_rate, factor).unwrap();
+            let kept = amplitude(&kept, rate, 0.5 * nyquist);
+            assert!((kept - 1.0).abs() < 0.01, "factor {}: amplitude {}", factor, kept);
+        }
+    }
+}
@@ -0,0 +1,667 @@
+// A module to compute robust statistics: medians, median absolute deviations, quantiles and trimmed means
+
+// Written by Amin Alam in 2024
+
+use std::cmp::Ordering;
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+
+/// The factor that makes the median absolute deviation of normal data equal its standard deviation
+pub const MAD_CONSISTENCY: f64 = 1.482602218505602;
+
+/// The robust summary of one channel
+///
+/// # Arguments
+///
+/// * `count` - The number of values that are not NaN
+/// * `missing` - The number of NaN values left out
+/// * `median` - The median
+/// * `mad` - The median absolute deviation, scaled by `MAD_CONSISTENCY` to estimate the standard deviation of normal data
+/// * `q25` - The first quartile
+/// * `q75` - The third quartile
+/// * `trimmed_mean` - The mean after dropping the `trim_fraction` smallest and largest values
+///
+/// # Examples
+///
+/// ```
+/// let table = robust_stats(&channels, &names, 0.1)?;
+/// let iqr = table.channels[0].q75 - table.channels[0].q25;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RobustSummary {
+    pub count: usize,
+    pub missing: usize,
+    pub median: f64,
+    pub mad: f64,
+    pub q25: f64,
+    pub q75: f64,
+    pub trimmed_mean: f64,
+}
+
+/// The robust summaries of several channels
+///
+/// # Arguments
+///
+/// * `names` - The name of each channel
+/// * `trim_fraction` - The fraction trimmed from each end for the trimmed means
+/// * `channels` - The RobustSummary of each channel
+///
+/// # Examples
+///
+/// ```
+/// let table = robust_stats(&channels, &names, 0.1)?;
+/// table.to_csv(&mut csv_io)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RobustStatsTable {
+    pub names: Vec<String>,
+    pub trim_fraction: f64,
+    pub channels: Vec<RobustSummary>,
+}
+
+/// Implementation of the RobustStatsTable struct
+///
+/// # Methods
+///
+/// * `to_csv` - Writes one row of robust statistics per channel
+impl RobustStatsTable {
+    /// Writes one row of robust statistics per channel
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// table.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The header is `channel,count,missing,median,mad,q25,q75,trimmed_mean`. The rows are
+    /// not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["channel", "count", "missing", "median", "mad", "q25", "q75", "trimmed_mean"]))?;
+        for (name, summary) in self.names.iter().zip(&self.channels) {
+            csv_io.write_record(StringRecord::from(vec![
+                name.clone(),
+                summary.count.to_string(),
+                summary.missing.to_string(),
+                float_format.format(summary.median),
+                float_format.format(summary.mad),
+                float_format.format(summary.q25),
+                float_format.format(summary.q75),
+                float_format.format(summary.trimmed_mean),
+            ]))?;
+        }
+        Ok(())
+    }
+}
+
+/// Computes the median of a set of values
+///
+/// # Arguments
+///
+/// * `values` - The values
+///
+/// # Returns
+///
+/// The median, the mean of the two middle values for an even count, or NaN if there are
+/// no values or any of them is NaN
+///
+/// # Examples
+///
+/// ```
+/// let centre = median(&[3.0, 1.0, 100.0, 2.0]);  // 2.5
+/// ```
+///
+/// # Note
+///
+/// The middle values are found with a selection instead of a sort, so the cost is linear
+/// in the number of values. Use `nan_median` to leave NaN values out.
+///
+pub fn median(values: &[f64]) -> f64 {
+    if values.iter().any(|value| value.is_nan()) {
+        return f64::NAN;
+    }
+    select_quantile(&mut values.to_vec(), 0.5)
+}
+
+/// Computes the median of the values that are not NaN
+///
+/// # Arguments
+///
+/// * `values` - The values, NaN for missing ones
+///
+/// # Returns
+///
+/// The median, or NaN if every value is NaN
+///
+/// # Examples
+///
+/// ```
+/// let centre = nan_median(&[3.0, f64::NAN, 1.0]);  // 2.0
+/// ```
+///
+pub fn nan_median(values: &[f64]) -> f64 {
+    select_quantile(&mut present(values), 0.5)
+}
+
+/// Computes the median absolute deviation from the median
+///
+/// # Arguments
+///
+/// * `values` - The values
+/// * `scaled` - Multiplies the deviation by `MAD_CONSISTENCY` so that it estimates the standard deviation of normal data
+///
+/// # Returns
+///
+/// The median absolute deviation, or NaN if there are no values or any of them is NaN
+///
+/// # Examples
+///
+/// ```
+/// let noise = mad(&samples, true);
+/// ```
+///
+pub fn mad(values: &[f64], scaled: bool) -> f64 {
+    if values.iter().any(|value| value.is_nan()) {
+        return f64::NAN;
+    }
+    deviation(values.to_vec(), scaled)
+}
+
+/// Computes the median absolute deviation of the values that are not NaN
+///
+/// # Arguments
+///
+/// * `values` - The values, NaN for missing ones
+/// * `scaled` - Multiplies the deviation by `MAD_CONSISTENCY`
+///
+/// # Returns
+///
+/// The median absolute deviation, or NaN if every value is NaN
+///
+/// # Examples
+///
+/// ```
+/// let noise = nan_mad(&samples, true);
+/// ```
+///
+pub fn nan_mad(values: &[f64], scaled: bool) -> f64 {
+    deviation(present(values), scaled)
+}
+
+/// Computes a quantile of a set of values
+///
+/// # Arguments
+///
+/// * `values` - The values
+/// * `q` - The quantile in `[0, 1]`, e.g. 0.95
+///
+/// # Returns
+///
+/// The quantile, or NaN if there are no values or any of them is NaN, or an error if `q`
+/// is outside `[0, 1]`
+///
+/// # Examples
+///
+/// ```
+/// let upper = quantile(&samples, 0.975)?;
+/// ```
+///
+/// # Note
+///
+/// The quantile interpolates linearly between the two closest order statistics, at rank
+/// `q * (n - 1)` counted from 0, as NumPy does by default. It is exact, not estimated
+/// like `StreamingStats::quantile`, and found by selection in linear time.
+///
+pub fn quantile(values: &[f64], q: f64) -> Result<f64, ProcessingError> {
+    validate_quantile(q)?;
+    if values.iter().any(|value| value.is_nan()) {
+        return Ok(f64::NAN);
+    }
+    Ok(select_quantile(&mut values.to_vec(), q))
+}
+
+/// Computes a quantile of the values that are not NaN
+///
+/// # Arguments
+///
+/// * `values` - The values, NaN for missing ones
+/// * `q` - The quantile in `[0, 1]`
+///
+/// # Returns
+///
+/// The quantile, or NaN if every value is NaN, or an error if `q` is outside `[0, 1]`
+///
+/// # Examples
+///
+/// ```
+/// let upper = nan_quantile(&samples, 0.975)?;
+/// ```
+///
+pub fn nan_quantile(values: &[f64], q: f64) -> Result<f64, ProcessingError> {
+    validate_quantile(q)?;
+    Ok(select_quantile(&mut present(values), q))
+}
+
+/// Computes the mean after dropping a fraction of the smallest and of the largest values
+///
+/// # Arguments
+///
+/// * `values` - The values
+/// * `fraction` - The fraction dropped from each end, in `[0, 0.5)`
+///
+/// # Returns
+///
+/// The trimmed mean, or NaN if there are no values or any of them is NaN, or an error if
+/// the fraction is outside `[0, 0.5)`
+///
+/// # Examples
+///
+/// ```
+/// // The mean of the central 80% of the values
+/// let centre = trimmed_mean(&samples, 0.1)?;
+/// ```
+///
+/// # Note
+///
+/// `floor(fraction * n)` values are dropped from each end, so a trim of 0 gives the mean.
+///
+pub fn trimmed_mean(values: &[f64], fraction: f64) -> Result<f64, ProcessingError> {
+    validate_fraction(fraction)?;
+    if values.iter().any(|value| value.is_nan()) {
+        return Ok(f64::NAN);
+    }
+    Ok(trim(values.to_vec(), fraction))
+}
+
+/// Computes the trimmed mean of the values that are not NaN
+///
+/// # Arguments
+///
+/// * `values` - The values, NaN for missing ones
+/// * `fraction` - The fraction dropped from each end, in `[0, 0.5)`
+///
+/// # Returns
+///
+/// The trimmed mean, or NaN if every value is NaN, or an error if the fraction is outside `[0, 0.5)`
+///
+/// # Examples
+///
+/// ```
+/// let centre = nan_trimmed_mean(&samples, 0.1)?;
+/// ```
+///
+pub fn nan_trimmed_mean(values: &[f64], fraction: f64) -> Result<f64, ProcessingError> {
+    validate_fraction(fraction)?;
+    Ok(trim(present(values), fraction))
+}
+
+/// Clamps a fraction of the smallest and of the largest values to the nearest value kept
+///
+/// # Arguments
+///
+/// * `values` - The values
+/// * `fraction` - The fraction clamped at each end, in `[0, 0.5)`
+///
+/// # Returns
+///
+/// The winsorized values in their original order, or an error if any value is NaN or
+/// the fraction is outside `[0, 0.5)`
+///
+/// # Examples
+///
+/// ```
+/// let clamped = winsorize(&rates, 0.05)?;
+/// ```
+///
+/// # Note
+///
+/// The `floor(fraction * n)` smallest values are raised to the next order statistic and
+/// as many largest values lowered to the one before them.
+///
+pub fn winsorize(values: &[f64], fraction: f64) -> Result<Vec<f64>, ProcessingError> {
+    validate_fraction(fraction)?;
+    if values.iter().any(|value| value.is_nan()) {
+        return Err(ProcessingError::InvalidParameter("Cannot winsorize NaN values; use nan_winsorize".to_string()));
+    }
+    Ok(clamp_tails(values, values.to_vec(), fraction))
+}
+
+/// Winsorizes the values that are not NaN, leaving the NaN values in place
+///
+/// # Arguments
+///
+/// * `values` - The values, NaN for missing ones
+/// * `fraction` - The fraction clamped at each end of the values present, in `[0, 0.5)`
+///
+/// # Returns
+///
+/// The winsorized values in their original order, or an error if the fraction is outside `[0, 0.5)`
+///
+/// # Examples
+///
+/// ```
+/// let clamped = nan_winsorize(&rates, 0.05)?;
+/// ```
+///
+pub fn nan_winsorize(values: &[f64], fraction: f64) -> Result<Vec<f64>, ProcessingError> {
+    validate_fraction(fraction)?;
+    Ok(clamp_tails(values, present(values), fraction))
+}
+
+/// Computes the robust statistics of every channel
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel, NaN for missing ones
+/// * `names` - The name of each channel
+/// * `trim_fraction` - The fraction trimmed from each end for the trimmed means, in `[0, 0.5)`
+///
+/// # Returns
+///
+/// The RobustStatsTable, or an error if the numbers of channels and names differ or the
+/// fraction is outside `[0, 0.5)`
+///
+/// # Examples
+///
+/// ```
+/// let table = robust_stats(&channels, &names, 0.1)?;
+/// ```
+///
+/// # Note
+///
+/// NaN values are left out and counted as missing; a channel without values gets NaN
+/// statistics. Unlike a StatsTable, every value of a channel is held in memory at once.
+///
+pub fn robust_stats(channels: &[Vec<f64>], names: &[String], trim_fraction: f64) -> Result<RobustStatsTable, ProcessingError> {
+    if channels.len() != names.len() {
+        return Err(ProcessingError::InvalidParameter(format!("Got {} channels but {} channel names", channels.len(), names.len())));
+    }
+    validate_fraction(trim_fraction)?;
+    Ok(RobustStatsTable {
+        names: names.to_vec(),
+        trim_fraction,
+        channels: channels.iter().map(|channel| summarize(channel, trim_fraction)).collect(),
+    })
+}
+
+/// Computes the RobustSummary of one channel, leaving NaN values out
+pub(crate) fn summarize(values: &[f64], trim_fraction: f64) -> RobustSummary {
+    let mut kept = present(values);
+    RobustSummary {
+        count: kept.len(),
+        missing: values.len() - kept.len(),
+        median: select_quantile(&mut kept, 0.5),
+        mad: deviation(kept.clone(), true),
+        q25: select_quantile(&mut kept, 0.25),
+        q75: select_quantile(&mut kept, 0.75),
+        trimmed_mean: trim(kept, trim_fraction),
+    }
+}
+
+fn present(values: &[f64]) -> Vec<f64> {
+    values.iter().copied().filter(|value| !value.is_nan()).collect()
+}
+
+fn by_value(a: &f64, b: &f64) -> Ordering {
+    a.total_cmp(b)
+}
+
+/// Returns the interpolated order statistic at rank `q * (n - 1)`, reordering the values
+pub(crate) fn select_quantile(values: &mut [f64], q: f64) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    let rank = q * (values.len() - 1) as f64;
+    let below = rank.floor() as usize;
+    let (_, &mut low, above) = values.select_nth_unstable_by(below, by_value);
+    let weight = rank - below as f64;
+    if weight == 0.0 {
+        return low;
+    }
+    // The next order statistic is the smallest of the values above the selected one
+    let high = above.iter().copied().fold(f64::INFINITY, f64::min);
+    low + weight * (high - low)
+}
+
+fn deviation(mut values: Vec<f64>, scaled: bool) -> f64 {
+    let centre = select_quantile(&mut values, 0.5);
+    values.iter_mut().for_each(|value| *value = (*value - centre).abs());
+    let spread = select_quantile(&mut values, 0.5);
+    if scaled {
+        MAD_CONSISTENCY * spread
+    } else {
+        spread
+    }
+}
+
+fn trim(mut values: Vec<f64>, fraction: f64) -> f64 {
+    if values.is_empty() {
+        return f64::NAN;
+    }
+    let n_trimmed = (fraction * values.len() as f64).floor() as usize;
+    if n_trimmed > 0 {
+        values.select_nth_unstable_by(n_trimmed, by_value);
+        let len = values.len();
+        values[n_trimmed..].select_nth_unstable_by(len - 2 * n_trimmed - 1, by_value);
+    }
+    let kept = &values[n_trimmed..values.len() - n_trimmed];
+    kept.iter().sum::<f64>() / kept.len() as f64
+}
+
+/// Clamps `values` to the order statistics of `present` that bound its central part
+fn clamp_tails(values: &[f64], mut present: Vec<f64>, fraction: f64) -> Vec<f64> {
+    let n_clamped = (fraction * present.len() as f64).floor() as usize;
+    if n_clamped == 0 {
+        return values.to_vec();
+    }
+    let len = present.len();
+    let low = *present.select_nth_unstable_by(n_clamped, by_value).1;
+    let high = *present.select_nth_unstable_by(len - 1 - n_clamped, by_value).1;
+    values.iter().map(|&value| if value.is_nan() { value } else { value.clamp(low, high) }).collect()
+}
+
+fn validate_quantile(q: f64) -> Result<(), ProcessingError> {
+    if !(0.0..=1.0).contains(&q) {
+        return Err(ProcessingError::InvalidParameter(format!("A quantile must be in [0, 1], got {}", q)));
+    }
+    Ok(())
+}
+
+fn validate_fraction(fraction: f64) -> Result<(), ProcessingError> {
+    if !(0.0..0.5).contains(&fraction) {
+        return Err(ProcessingError::InvalidParameter(format!("A trim fraction must be in [0, 0.5), got {}", fraction)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    fn sorted(values: &[f64]) -> Vec<f64> {
+        let mut sorted: Vec<f64> = values.iter().copied().filter(|value| !value.is_nan()).collect();
+        sorted.sort_by(f64::total_cmp);
+        sorted
+    }
+
+    /// The linearly interpolated quantile of a sorted array
+    fn reference_quantile(sorted: &[f64], q: f64) -> f64 {
+        if sorted.is_empty() {
+            return f64::NAN;
+        }
+        let rank = q * (sorted.len() - 1) as f64;
+        let (below, above) = (rank.floor() as usize, rank.ceil() as usize);
+        sorted[below] + (rank - below as f64) * (sorted[above] - sorted[below])
+    }
+
+    fn reference_mad(sorted: &[f64]) -> f64 {
+        let centre = reference_quantile(sorted, 0.5);
+        reference_quantile(&self::sorted(&sorted.iter().map(|value| (value - centre).abs()).collect::<Vec<f64>>()), 0.5)
+    }
+
+    fn reference_trimmed_mean(sorted: &[f64], fraction: f64) -> f64 {
+        let n_trimmed = (fraction * sorted.len() as f64).floor() as usize;
+        let kept = &sorted[n_trimmed..sorted.len() - n_trimmed];
+        kept.iter().sum::<f64>() / kept.len() as f64
+    }
+
+    fn reference_winsorize(values: &[f64], fraction: f64) -> Vec<f64> {
+        let sorted = sorted(values);
+        let n_clamped = (fraction * sorted.len() as f64).floor() as usize;
+        let (low, high) = (sorted[n_clamped], sorted[sorted.len() - 1 - n_clamped]);
+        values.iter().map(|&value| if value.is_nan() { value } else { value.max(low).min(high) }).collect()
+    }
+
+    /// Draws samples with repeated values, so ties are exercised
+    fn sample(rng: &mut SeededRng, n: usize) -> Vec<f64> {
+        (0..n).map(|_| if rng.next_f64() < 0.2 { rng.next_index(4) as f64 } else { 10.0 * rng.next_gaussian() }).collect()
+    }
+
+    #[test]
+    fn matches_sorted_references_for_odd_and_even_lengths() {
+        let mut rng = SeededRng::new(173);
+        for n in 1..=60 {
+            for _ in 0..5 {
+                let values = sample(&mut rng, n);
+                let reference = sorted(&values);
+                assert_eq!(median(&values), reference_quantile(&reference, 0.5), "n = {}", n);
+                assert_eq!(mad(&values, false), reference_mad(&reference), "n = {}", n);
+                assert_eq!(mad(&values, true), MAD_CONSISTENCY * reference_mad(&reference));
+                for q in [0.0, 0.05, 0.25, 0.5, 0.9, 0.975, 1.0] {
+                    assert_eq!(quantile(&values, q).unwrap(), reference_quantile(&reference, q), "n = {}, q = {}", n, q);
+                }
+                for fraction in [0.0, 0.1, 0.25, 0.49] {
+                    let expected = reference_trimmed_mean(&reference, fraction);
+                    let actual = trimmed_mean(&values, fraction).unwrap();
+                    assert!((actual - expected).abs() <= 1e-12 * expected.abs().max(1.0), "n = {}: {} vs {}", n, actual, expected);
+                    assert_eq!(winsorize(&values, fraction).unwrap(), reference_winsorize(&values, fraction), "n = {}", n);
+                }
+            }
+        }
+        assert_eq!(median(&[3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(median(&[4.0, 1.0, 3.0, 2.0]), 2.5);
+        assert_eq!(mad(&[1.0, 1.0, 2.0, 2.0, 4.0, 6.0, 9.0], false), 1.0);
+        assert_eq!(trimmed_mean(&[1.0, 2.0, 3.0, 4.0, 100.0], 0.2).unwrap(), 3.0);
+        assert_eq!(winsorize(&[1.0, 2.0, 3.0, 4.0, 100.0], 0.2).unwrap(), [2.0, 2.0, 3.0, 4.0, 4.0]);
+    }
+
+    #[test]
+    fn nan_variants_skip_missing_values() {
+        let mut rng = SeededRng::new(1730);
+        for n in 1..=40 {
+            let mut values = sample(&mut rng, n);
+            for value in values.iter_mut() {
+                if rng.next_f64() < 0.3 {
+                    *value = f64::NAN;
+                }
+            }
+            let reference = sorted(&values);
+            let has_nan = values.iter().any(|value| value.is_nan());
+            assert_eq!(nan_median(&values).to_bits(), reference_quantile(&reference, 0.5).to_bits(), "n = {}", n);
+            assert_eq!(nan_quantile(&values, 0.8).unwrap().to_bits(), reference_quantile(&reference, 0.8).to_bits());
+            assert_eq!(median(&values).is_nan(), has_nan || reference.is_empty());
+            assert_eq!(quantile(&values, 0.8).unwrap().is_nan(), has_nan || reference.is_empty());
+            assert_eq!(mad(&values, true).is_nan(), has_nan || reference.is_empty());
+            assert_eq!(trimmed_mean(&values, 0.1).unwrap().is_nan(), has_nan || reference.is_empty());
+            if !reference.is_empty() {
+                assert_eq!(nan_mad(&values, false), reference_mad(&reference));
+                let expected = reference_trimmed_mean(&reference, 0.2);
+                assert!((nan_trimmed_mean(&values, 0.2).unwrap() - expected).abs() <= 1e-12 * expected.abs().max(1.0));
+                let winsorized = nan_winsorize(&values, 0.2).unwrap();
+                let expected = reference_winsorize(&values, 0.2);
+                assert!(winsorized.iter().zip(&expected).all(|(a, b)| a.to_bits() == b.to_bits()), "{:?} vs {:?}", winsorized, expected);
+            }
+            assert_eq!(winsorize(&values, 0.1).is_err(), has_nan);
+        }
+    }
+
+    #[test]
+    fn all_nan_and_empty_inputs_give_nan() {
+        let all_nan = [f64::NAN; 5];
+        for values in [&all_nan[..], &[]] {
+            assert!(nan_median(values).is_nan());
+            assert!(nan_mad(values, true).is_nan());
+            assert!(nan_quantile(values, 0.3).unwrap().is_nan());
+            assert!(nan_trimmed_mean(values, 0.1).unwrap().is_nan());
+            assert!(median(values).is_nan());
+        }
+        assert!(nan_winsorize(&all_nan, 0.1).unwrap().iter().all(|value| value.is_nan()));
+        let summary = summarize(&all_nan, 0.1);
+        assert_eq!((summary.count, summary.missing), (0, 5));
+        assert!(summary.median.is_nan() && summary.mad.is_nan() && summary.q25.is_nan() && summary.trimmed_mean.is_nan());
+    }
+
+    #[test]
+    fn resists_heavy_outlier_contamination() {
+        let mut rng = SeededRng::new(17);
+        let n = 10_000;
+        let mut values: Vec<f64> = (0..n).map(|_| 5.0 + 2.0 * rng.next_gaussian()).collect();
+        for value in values.iter_mut().step_by(5) {
+            *value = 1e6 * (1.0 + rng.next_f64());
+        }
+        let mean = values.iter().sum::<f64>() / n as f64;
+        assert!(mean > 1e5, "{}", mean);
+        // 20% of the samples are outliers, so the median moves by about 0.25 standard deviations
+        assert!((median(&values) - 5.0).abs() < 1.0, "{}", median(&values));
+        assert!((mad(&values, true) - 2.0).abs() < 1.0, "{}", mad(&values, true));
+        assert!((trimmed_mean(&values, 0.25).unwrap() - 5.0).abs() < 1.0);
+        assert!(winsorize(&values, 0.25).unwrap().iter().all(|value| *value < 100.0));
+        assert!(quantile(&values, 0.5).unwrap() < quantile(&values, 0.75).unwrap());
+        assert!(quantile(&values, 0.95).unwrap() > 1e6);
+    }
+
+    #[test]
+    fn rejects_invalid_parameters() {
+        for q in [-0.1, 1.1, f64::NAN] {
+            assert!(quantile(&[1.0], q).is_err());
+            assert!(nan_quantile(&[1.0], q).is_err());
+        }
+        for fraction in [-0.1, 0.5, 0.7, f64::NAN] {
+            assert!(trimmed_mean(&[1.0], fraction).is_err());
+            assert!(winsorize(&[1.0], fraction).is_err());
+            assert!(robust_stats(&[vec![1.0]], &["a".to_string()], fraction).is_err());
+        }
+        assert!(robust_stats(&[vec![1.0]], &[], 0.1).is_err());
+    }
+
+    #[test]
+    fn tables_are_written_per_channel() {
+        let channels = vec![vec![1.0, 2.0, 3.0, 4.0, 100.0], vec![f64::NAN, 2.0, 2.0, 4.0, f64::NAN]];
+        let table = robust_stats(&channels, &["Cz".to_string(), "Pz".to_string()], 0.2).unwrap();
+        assert_eq!(table.channels[0], RobustSummary { count: 5, missing: 0, median: 3.0, mad: MAD_CONSISTENCY, q25: 2.0, q75: 4.0, trimmed_mean: 3.0 });
+        assert_eq!((table.channels[1].count, table.channels[1].missing, table.channels[1].median, table.channels[1].mad), (3, 2, 2.0, 0.0));
+
+        let path = std::env::temp_dir().join(format!("neurorust-robust-{}-table.csv", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        table.to_csv(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(text.lines().next().unwrap(), "channel,count,missing,median,mad,q25,q75,trimmed_mean");
+        assert_eq!(text.lines().nth(1).unwrap(), format!("Cz,5,0,3,{},2,4,3", MAD_CONSISTENCY));
+        assert!(text.lines().nth(2).unwrap().starts_with("Pz,3,2,2,0,"));
+
+        std::fs::write(&path, "Cz,Pz,label\n1,,a\n2,2,b\n3,2,c\n4,4,d\n100,,e\n").unwrap();
+        let from_file = CsvIO::open_read(path.to_str().unwrap()).unwrap().robust_column_stats(0.2).unwrap();
+        assert_eq!(from_file.channels[..2], table.channels[..]);
+        assert_eq!((from_file.channels[2].count, from_file.channels[2].missing), (0, 5));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
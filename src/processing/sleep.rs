@@ -0,0 +1,749 @@
+// A module to compute epoch-wise band-power features for sleep and state scoring and to handle hypnograms
+
+// Written by Amin Alam in 2024
+
+use std::collections::BTreeMap;
+use std::io;
+use csv::StringRecord;
+use crate::data_io::csv::{CsvIO, CsvReader};
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::features::FeatureTable;
+use crate::processing::filter::validate_sampling_rate;
+use crate::processing::spectral::{welch, FrequencyBand, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW};
+
+/// The band ratios added by `epoch_features` when both of their bands are given, as `(numerator, denominator)`
+pub const STANDARD_RATIOS: [(&str, &str); 2] = [("delta", "beta"), ("theta", "alpha")];
+
+/// The longest Welch segment of `epoch_features`, in seconds
+const SEGMENT_DURATION: f64 = 4.0;
+
+/// Computes epoch-wise band powers chunk by chunk, keeping only one epoch of samples in memory
+///
+/// # Examples
+///
+/// ```
+/// let mut builder = EpochFeatures::new(&names, 256.0, 30.0, &FrequencyBand::canonical())?;
+/// for chunk in chunks {
+///     builder.push(&chunk)?;
+/// }
+/// let table = builder.finish();
+/// ```
+pub struct EpochFeatures {
+    names: Vec<String>,
+    sampling_rate: f64,
+    epoch_len: usize,
+    segment_len: usize,
+    bands: Vec<FrequencyBand>,
+    ratios: Vec<(usize, usize)>,
+    pending: Vec<Vec<f64>>,
+    table: FeatureTable,
+}
+
+/// A scored sequence of epochs, one stage label per epoch
+///
+/// # Arguments
+///
+/// * `epoch_duration` - The duration of each epoch in seconds
+/// * `start_time` - The time of the start of the first epoch in seconds
+/// * `stages` - The stage label of each epoch, e.g. `W`, `N1`, `N2`, `N3` and `REM`
+///
+/// # Examples
+///
+/// ```
+/// let hypnogram = Hypnogram::from_csv(&mut CsvReader::open("s01_scores.csv")?, 30.0)?;
+/// let rem = hypnogram.select_epochs_by_stage("REM");
+/// ```
+///
+/// # Note
+///
+/// Epoch `i` covers the times from `start_time + i * epoch_duration`, included, to
+/// `start_time + (i + 1) * epoch_duration`, excluded, which are the epochs of
+/// `epoch_features` when `start_time` is 0
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Hypnogram {
+    pub epoch_duration: f64,
+    pub start_time: f64,
+    pub stages: Vec<String>,
+}
+
+/// The numbers of transitions between the stages of consecutive epochs
+///
+/// # Arguments
+///
+/// * `stages` - The stage labels, sorted
+/// * `counts` - The number of transitions from each stage to each stage, indexed as `counts[from][to]`, with epochs staying in a stage on the diagonal
+///
+/// # Examples
+///
+/// ```
+/// let transitions = hypnogram.transitions_matrix();
+/// transitions.to_csv(&mut csv_io)?;
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StageTransitions {
+    pub stages: Vec<String>,
+    pub counts: Vec<Vec<usize>>,
+}
+
+/// Implementation of the EpochFeatures struct
+///
+/// # Methods
+///
+/// * `new` - Creates an empty EpochFeatures
+/// * `push` - Adds the next chunk of samples of every channel
+/// * `finish` - Returns the FeatureTable of all complete epochs
+impl EpochFeatures {
+    /// Creates an empty EpochFeatures
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - The name of each channel, used as the prefix of its columns
+    /// * `sampling_rate` - The sampling rate in Hz
+    /// * `epoch_duration` - The duration of each epoch in seconds, rounded to a whole number of samples, e.g. `30.0`
+    /// * `bands` - The frequency bands
+    ///
+    /// # Returns
+    ///
+    /// The EpochFeatures, or an error if the sampling rate is not positive, the epoch is
+    /// shorter than two samples, no band is given or a band is not within `[0, Nyquist]`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let builder = EpochFeatures::new(&["C3".to_string(), "C4".to_string()], 200.0, 30.0, &FrequencyBand::canonical())?;
+    /// ```
+    ///
+    pub fn new(names: &[String], sampling_rate: f64, epoch_duration: f64, bands: &[FrequencyBand]) -> Result<Self, ProcessingError> {
+        validate_sampling_rate(sampling_rate)?;
+        let epoch_len = (epoch_duration * sampling_rate).round();
+        if !(epoch_len.is_finite() && epoch_len >= 2.0) {
+            return Err(ProcessingError::InvalidParameter(format!("The epochs must be at least two samples long, got {} s", epoch_duration)));
+        }
+        if bands.is_empty() {
+            return Err(ProcessingError::InvalidParameter("At least one band is needed".to_string()));
+        }
+        if let Some(band) = bands.iter().find(|band| !(band.f_low >= 0.0 && band.f_low < band.f_high && band.f_high <= sampling_rate / 2.0)) {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Band {} of {} to {} Hz must lie within 0 and the Nyquist frequency of {} Hz",
+                band.name,
+                band.f_low,
+                band.f_high,
+                sampling_rate / 2.0
+            )));
+        }
+        let epoch_len = epoch_len as usize;
+        let position = |name: &str| bands.iter().position(|band| band.name == name);
+        let ratios: Vec<(usize, usize)> = STANDARD_RATIOS.iter().filter_map(|(numerator, denominator)| Some((position(numerator)?, position(denominator)?))).collect();
+        let columns = names
+            .iter()
+            .flat_map(|name| {
+                bands
+                    .iter()
+                    .map(move |band| format!("{}_{}", name, band.name))
+                    .chain(ratios.iter().map(move |&(numerator, denominator)| format!("{}_{}_{}", name, bands[numerator].name, bands[denominator].name)))
+            })
+            .collect();
+        Ok(Self {
+            names: names.to_vec(),
+            sampling_rate,
+            epoch_len,
+            segment_len: ((SEGMENT_DURATION * sampling_rate).round() as usize).clamp(2, epoch_len),
+            bands: bands.to_vec(),
+            ratios,
+            pending: vec![Vec::with_capacity(epoch_len); names.len()],
+            table: FeatureTable { columns, ..FeatureTable::default() },
+        })
+    }
+
+    /// Adds the next chunk of samples of every channel
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk` - The samples following the previously pushed ones, one row per channel
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if the chunk does not have one row per channel or its rows differ in length
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// builder.push(&chunk)?;
+    /// ```
+    ///
+    pub fn push(&mut self, chunk: &[Vec<f64>]) -> Result<(), ProcessingError> {
+        let length = chunk.first().map_or(0, |row| row.len());
+        if chunk.len() != self.names.len() || chunk.iter().any(|row| row.len() != length) {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Chunk must have {} channels of equal length",
+                self.names.len()
+            )));
+        }
+        let mut offset = 0;
+        while offset < length {
+            let taken = (self.epoch_len - self.pending.first().map_or(0, |pending| pending.len())).min(length - offset);
+            for (pending, row) in self.pending.iter_mut().zip(chunk) {
+                pending.extend_from_slice(&row[offset..offset + taken]);
+            }
+            offset += taken;
+            if self.pending.first().is_some_and(|pending| pending.len() == self.epoch_len) {
+                self.add_epoch()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns the FeatureTable of all complete epochs
+    ///
+    /// # Returns
+    ///
+    /// The FeatureTable, with one row per epoch at its center, and the samples after the
+    /// last complete epoch left out
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let table = builder.finish();
+    /// ```
+    ///
+    pub fn finish(self) -> FeatureTable {
+        self.table
+    }
+
+    /// Computes the band powers and ratios of the pending epoch and clears it
+    fn add_epoch(&mut self) -> Result<(), ProcessingError> {
+        let mut row = Vec::with_capacity(self.table.columns.len());
+        for pending in &mut self.pending {
+            let psd = welch(pending, self.sampling_rate, self.segment_len, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW)?;
+            let powers: Vec<f64> = self.bands.iter().map(|band| psd.band_power(band.f_low, band.f_high)).collect();
+            row.extend_from_slice(&powers);
+            row.extend(self.ratios.iter().map(|&(numerator, denominator)| powers[numerator] / powers[denominator]));
+            pending.clear();
+        }
+        let index = self.table.times.len();
+        self.table.times.push((index as f64 + 0.5) * self.epoch_len as f64 / self.sampling_rate);
+        self.table.values.push(row);
+        self.table.partial.push(false);
+        Ok(())
+    }
+}
+
+/// Computes the band powers of every channel in consecutive epochs, e.g. of 30 seconds for sleep scoring
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel
+/// * `names` - The name of each channel, used as the prefix of its columns
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `epoch_duration` - The duration of each epoch in seconds
+/// * `bands` - The frequency bands, e.g. `FrequencyBand::canonical()`
+///
+/// # Returns
+///
+/// The FeatureTable with one row per complete epoch and the columns `<channel>_<band>` in
+/// units² followed by `<channel>_delta_beta` and `<channel>_theta_alpha` for the
+/// `STANDARD_RATIOS` whose bands are given, or an error if the parameters are invalid or
+/// the channels and names do not match
+///
+/// # Examples
+///
+/// ```
+/// let table = epoch_features(&channels, &names, 256.0, 30.0, &FrequencyBand::canonical())?;
+/// let delta_beta = table.column("C3_delta_beta");
+/// ```
+///
+/// # Note
+///
+/// The power of each band is the integral over the band of the Welch spectrum of the
+/// epoch, with Hann-windowed segments of 4 seconds, or of the whole epoch if it is
+/// shorter, overlapping by half. A ratio is NaN, or infinite, when the power of its
+/// denominator is 0. The epochs start at the first sample and a trailing incomplete epoch is
+/// left out. Use `EpochFeatures` to process a recording that does not fit in memory; it
+/// gives the same table.
+///
+pub fn epoch_features(channels: &[Vec<f64>], names: &[String], sampling_rate: f64, epoch_duration: f64, bands: &[FrequencyBand]) -> Result<FeatureTable, ProcessingError> {
+    let mut builder = EpochFeatures::new(names, sampling_rate, epoch_duration, bands)?;
+    builder.push(channels)?;
+    Ok(builder.finish())
+}
+
+/// Implementation of the Hypnogram struct
+///
+/// # Methods
+///
+/// * `from_csv` - Reads the scores of a scorer from `epoch,stage` rows
+/// * `to_csv` - Writes the hypnogram as `epoch,start,end,stage` rows
+/// * `len` - Returns the number of epochs
+/// * `is_empty` - Checks whether there are no epochs
+/// * `align` - Pairs the stage of every epoch with its row of a feature table
+/// * `stage_durations` - Returns the total time spent in each stage
+/// * `transitions_matrix` - Counts the transitions between the stages of consecutive epochs
+/// * `select_epochs_by_stage` - Returns the time ranges scored as a stage
+impl Hypnogram {
+    /// Reads the scores of a scorer from `epoch,stage` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `reader` - The reader of the csv file of scores, at its first record
+    /// * `epoch_duration` - The duration of each epoch in seconds
+    ///
+    /// # Returns
+    ///
+    /// The Hypnogram, starting at time 0, or an error if the `epoch` or `stage` column is
+    /// missing, the epoch numbers are not consecutive integers, or the records cannot be read
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let hypnogram = Hypnogram::from_csv(&mut CsvReader::open("s01_scores.csv")?, 30.0)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The first epoch number of the file, usually 0 or 1, is taken as the first epoch, so
+    /// files numbered from either align with `epoch_features`. Other columns are ignored.
+    ///
+    pub fn from_csv(reader: &mut CsvReader, epoch_duration: f64) -> io::Result<Self> {
+        let headers = reader.headers().clone();
+        let find = |name: &str| {
+            headers
+                .iter()
+                .position(|header| header == name)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, format!("Column '{}' not found", name)))
+        };
+        let (epoch_column, stage_column) = (find("epoch")?, find("stage")?);
+        let mut stages = Vec::new();
+        let mut first_epoch: Option<i64> = None;
+        for record in reader.records() {
+            let record = record?;
+            let line = record.position().map_or(0, |position| position.line());
+            let field = record.get(epoch_column).unwrap_or("");
+            let epoch: i64 = field
+                .trim()
+                .parse()
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("Epoch '{}' at line {} is not an integer", field, line)))?;
+            let expected = *first_epoch.get_or_insert(epoch) + stages.len() as i64;
+            if epoch != expected {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, format!("Expected epoch {} at line {} but found {}", expected, line, epoch)));
+            }
+            stages.push(record.get(stage_column).unwrap_or("").trim().to_string());
+        }
+        Ok(Self { epoch_duration, start_time: 0.0, stages })
+    }
+
+    /// Writes the hypnogram as `epoch,start,end,stage` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// hypnogram.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first, and the epochs are numbered from 0. The file can be
+    /// read back with `from_csv`. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["epoch", "start", "end", "stage"]))?;
+        for (epoch, stage) in self.stages.iter().enumerate() {
+            let (start, end) = self.epoch_range(epoch);
+            csv_io.write_record(StringRecord::from(vec![epoch.to_string(), float_format.format(start), float_format.format(end), stage.clone()]))?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of epochs
+    ///
+    /// # Returns
+    ///
+    /// The number of scored epochs
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let hours = hypnogram.len() as f64 * hypnogram.epoch_duration / 3600.0;
+    /// ```
+    ///
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    /// Checks whether there are no epochs
+    ///
+    /// # Returns
+    ///
+    /// `true` if no epoch is scored
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// if hypnogram.is_empty() { return Ok(()); }
+    /// ```
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Pairs the stage of every epoch with its row of a feature table
+    ///
+    /// # Arguments
+    ///
+    /// * `table` - The features of the same epochs, e.g. from `epoch_features`
+    ///
+    /// # Returns
+    ///
+    /// The stage and feature values of each epoch, or an error with both counts if the
+    /// table does not have one row per epoch
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let rem_delta: Vec<f64> = hypnogram.align(&table)?.iter().filter(|(stage, _)| *stage == "REM").map(|(_, row)| row[0]).collect();
+    /// ```
+    ///
+    pub fn align<'a>(&'a self, table: &'a FeatureTable) -> Result<Vec<(&'a str, &'a [f64])>, ProcessingError> {
+        if table.values.len() != self.stages.len() {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "The hypnogram has {} epochs but the feature table has {}",
+                self.stages.len(),
+                table.values.len()
+            )));
+        }
+        Ok(self.stages.iter().map(String::as_str).zip(table.values.iter().map(Vec::as_slice)).collect())
+    }
+
+    /// Returns the total time spent in each stage
+    ///
+    /// # Returns
+    ///
+    /// The duration in seconds of each stage, keyed by stage label
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let durations = hypnogram.stage_durations();
+    /// let rem_minutes = durations.get("REM").copied().unwrap_or(0.0) / 60.0;
+    /// ```
+    ///
+    pub fn stage_durations(&self) -> BTreeMap<String, f64> {
+        let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+        for stage in &self.stages {
+            *counts.entry(stage.clone()).or_insert(0) += 1;
+        }
+        counts.into_iter().map(|(stage, count)| (stage, count as f64 * self.epoch_duration)).collect()
+    }
+
+    /// Counts the transitions between the stages of consecutive epochs
+    ///
+    /// # Returns
+    ///
+    /// The StageTransitions, over the `len() - 1` pairs of consecutive epochs
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let transitions = hypnogram.transitions_matrix();
+    /// ```
+    ///
+    pub fn transitions_matrix(&self) -> StageTransitions {
+        let mut stages: Vec<String> = self.stages.clone();
+        stages.sort();
+        stages.dedup();
+        let mut counts = vec![vec![0; stages.len()]; stages.len()];
+        for pair in self.stages.windows(2) {
+            let from = stages.binary_search(&pair[0]).unwrap_or_default();
+            let to = stages.binary_search(&pair[1]).unwrap_or_default();
+            counts[from][to] += 1;
+        }
+        StageTransitions { stages, counts }
+    }
+
+    /// Returns the time ranges scored as a stage
+    ///
+    /// # Arguments
+    ///
+    /// * `stage` - The stage label, e.g. `N3`
+    ///
+    /// # Returns
+    ///
+    /// The start and end times in seconds of each run of consecutive epochs of the stage,
+    /// in time order, empty if the stage was never scored
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let bouts = hypnogram.select_epochs_by_stage("N3");
+    /// let n3_seconds: f64 = bouts.iter().map(|(start, end)| end - start).sum();
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Consecutive epochs of the stage are merged into one range, the start being included
+    /// and the end excluded
+    ///
+    pub fn select_epochs_by_stage(&self, stage: &str) -> Vec<(f64, f64)> {
+        let mut ranges: Vec<(f64, f64)> = Vec::new();
+        let mut previous = false;
+        for (epoch, label) in self.stages.iter().enumerate() {
+            let matches = label == stage;
+            if matches {
+                let (start, end) = self.epoch_range(epoch);
+                match ranges.last_mut() {
+                    Some(last) if previous => last.1 = end,
+                    _ => ranges.push((start, end)),
+                }
+            }
+            previous = matches;
+        }
+        ranges
+    }
+
+    /// Returns the start and end time of an epoch
+    fn epoch_range(&self, epoch: usize) -> (f64, f64) {
+        (self.start_time + epoch as f64 * self.epoch_duration, self.start_time + (epoch + 1) as f64 * self.epoch_duration)
+    }
+}
+
+/// Implementation of the StageTransitions struct
+///
+/// # Methods
+///
+/// * `to_csv` - Writes the counts as `from,<stages>` rows
+impl StageTransitions {
+    /// Writes the counts as `from,<stages>` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// hypnogram.transitions_matrix().to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row `from,<stages>` is written first, then one row per stage of the first
+    /// epoch of a pair. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let mut header = vec!["from".to_string()];
+        header.extend(self.stages.iter().cloned());
+        csv_io.write_record(StringRecord::from(header))?;
+        for (stage, row) in self.stages.iter().zip(&self.counts) {
+            let mut record = vec![stage.clone()];
+            record.extend(row.iter().map(|count| count.to_string()));
+            csv_io.write_record(StringRecord::from(record))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use crate::processing::random::SeededRng;
+    use crate::processing::spectral::FrequencyBand;
+
+    const RATE: f64 = 100.0;
+
+    fn bands() -> Vec<FrequencyBand> {
+        vec![FrequencyBand::new("delta", 0.5, 4.0), FrequencyBand::new("theta", 4.0, 8.0), FrequencyBand::new("alpha", 8.0, 12.0), FrequencyBand::new("beta", 12.0, 30.0)]
+    }
+
+    fn sine(frequency: f64, amplitude: f64, n: usize) -> impl Iterator<Item = f64> {
+        (0..n).map(move |index| amplitude * (2.0 * PI * frequency * index as f64 / RATE).sin())
+    }
+
+    /// Three 30 s epochs: delta with a little beta, beta alone, and theta with alpha, plus light noise on a second channel
+    fn fixture() -> (Vec<Vec<f64>>, Vec<String>) {
+        let n = 3000;
+        let mut first: Vec<f64> = sine(2.0, 2.0, n).zip(sine(20.0, 1.0, n)).map(|(a, b)| a + b).collect();
+        first.extend(sine(20.0, 1.0, n));
+        first.extend(sine(6.0, 3.0, n).zip(sine(10.0, 1.0, n)).map(|(a, b)| a + b));
+        let mut rng = SeededRng::new(184);
+        let second: Vec<f64> = first.iter().map(|value| 0.5 * value + 0.01 * rng.next_gaussian()).collect();
+        (vec![first, second], vec!["C3".to_string(), "O1".to_string()])
+    }
+
+    fn column(table: &FeatureTable, name: &str) -> Vec<f64> {
+        let index = table.columns.iter().position(|column| column == name).unwrap_or_else(|| panic!("No column {}", name));
+        table.values.iter().map(|row| row[index]).collect()
+    }
+
+    fn close(actual: f64, expected: f64, relative: f64) -> bool {
+        (actual - expected).abs() <= relative * expected.abs()
+    }
+
+    #[test]
+    fn band_powers_and_ratios_follow_the_sines_of_each_epoch() {
+        let (channels, names) = fixture();
+        let table = epoch_features(&channels, &names, RATE, 30.0, &bands()).unwrap();
+        assert_eq!(table.columns[..6], ["C3_delta", "C3_theta", "C3_alpha", "C3_beta", "C3_delta_beta", "C3_theta_alpha"]);
+        assert_eq!(table.columns.len(), 12);
+        assert_eq!(table.times, [15.0, 45.0, 75.0]);
+        assert_eq!(table.partial, [false; 3]);
+
+        // Every sine falls on a Welch bin, so a sine of amplitude a has a power of a^2 / 2
+        let (delta, beta) = (column(&table, "C3_delta"), column(&table, "C3_beta"));
+        assert!(close(delta[0], 2.0, 1e-9) && close(beta[0], 0.5, 1e-9) && close(beta[1], 0.5, 1e-9), "{:?} {:?}", delta, beta);
+        assert!(delta[1] < 1e-3 * beta[1]);
+        let (theta, alpha) = (column(&table, "C3_theta"), column(&table, "C3_alpha"));
+        assert!(close(theta[2], 4.5, 1e-9) && close(alpha[2], 0.5, 1e-9), "{:?} {:?}", theta, alpha);
+
+        let delta_beta = column(&table, "C3_delta_beta");
+        let theta_alpha = column(&table, "C3_theta_alpha");
+        for epoch in 0..3 {
+            assert_eq!(delta_beta[epoch], delta[epoch] / beta[epoch]);
+            assert_eq!(theta_alpha[epoch], theta[epoch] / alpha[epoch]);
+        }
+        assert!(close(delta_beta[0], 4.0, 1e-9) && close(theta_alpha[2], 9.0, 1e-9), "{:?} {:?}", delta_beta, theta_alpha);
+
+        // Halving the signal quarters the powers but leaves the ratios
+        let o1 = column(&table, "O1_delta_beta");
+        assert!(close(column(&table, "O1_delta")[0], 0.5, 0.03) && close(o1[0], delta_beta[0], 0.01), "{:?}", o1);
+    }
+
+    #[test]
+    fn ratios_need_both_bands() {
+        let (channels, names) = fixture();
+        let bands = [FrequencyBand::new("delta", 0.5, 4.0), FrequencyBand::new("beta", 12.0, 30.0), FrequencyBand::new("sigma", 11.0, 16.0)];
+        let table = epoch_features(&channels, &names, RATE, 30.0, &bands).unwrap();
+        assert_eq!(table.columns[..4], ["C3_delta", "C3_beta", "C3_sigma", "C3_delta_beta"]);
+        assert!(!table.columns.iter().any(|name| name.contains("theta")));
+    }
+
+    #[test]
+    fn chunked_input_matches_the_whole_recording() {
+        let (channels, names) = fixture();
+        let whole = epoch_features(&channels, &names, RATE, 30.0, &bands()).unwrap();
+        let mut builder = EpochFeatures::new(&names, RATE, 30.0, &bands()).unwrap();
+        let mut start = 0;
+        for size in [1, 700, 2299, 3000, 1234, 1766].iter().cycle() {
+            let end = (start + size).min(9000);
+            builder.push(&channels.iter().map(|channel| channel[start..end].to_vec()).collect::<Vec<_>>()).unwrap();
+            start = end;
+            if start == 9000 {
+                break;
+            }
+        }
+        assert_eq!(builder.finish(), whole);
+
+        // A trailing partial epoch is left out
+        let short: Vec<Vec<f64>> = channels.iter().map(|channel| channel[..7500].to_vec()).collect();
+        assert_eq!(epoch_features(&short, &names, RATE, 30.0, &bands()).unwrap().values.len(), 2);
+        let mut builder = EpochFeatures::new(&names, RATE, 30.0, &bands()).unwrap();
+        assert!(builder.push(&channels[..1]).is_err());
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        let names = vec!["C3".to_string()];
+        assert!(EpochFeatures::new(&names, 0.0, 30.0, &bands()).is_err());
+        assert!(EpochFeatures::new(&names, RATE, 0.01, &bands()).is_err());
+        assert!(EpochFeatures::new(&names, RATE, 30.0, &[]).is_err());
+        assert!(EpochFeatures::new(&names, RATE, 30.0, &[FrequencyBand::new("gamma", 30.0, 80.0)]).is_err());
+        assert!(EpochFeatures::new(&names, RATE, 30.0, &[FrequencyBand::new("odd", 8.0, 4.0)]).is_err());
+    }
+
+    fn scored() -> Hypnogram {
+        let path = std::env::temp_dir().join(format!("neurorust-sleep-{}-scored.csv", std::process::id()));
+        std::fs::write(&path, "epoch,stage,scorer\n1,W,ab\n2,N1,ab\n3,N2,ab\n4,N2,ab\n5,N3,ab\n6,N2,ab\n7,R,ab\n8,R,ab\n9,W,ab\n").unwrap();
+        let hypnogram = Hypnogram::from_csv(&mut CsvReader::open(&path).unwrap(), 30.0).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        hypnogram
+    }
+
+    #[test]
+    fn hypnograms_load_and_summarize() {
+        let hypnogram = scored();
+        assert_eq!(hypnogram.stages, ["W", "N1", "N2", "N2", "N3", "N2", "R", "R", "W"]);
+        assert_eq!((hypnogram.len(), hypnogram.is_empty(), hypnogram.start_time), (9, false, 0.0));
+        let durations = hypnogram.stage_durations();
+        assert_eq!(durations.into_iter().collect::<Vec<_>>(), [("N1".to_string(), 30.0), ("N2".to_string(), 90.0), ("N3".to_string(), 30.0), ("R".to_string(), 60.0), ("W".to_string(), 60.0)]);
+
+        let transitions = hypnogram.transitions_matrix();
+        assert_eq!(transitions.stages, ["N1", "N2", "N3", "R", "W"]);
+        assert_eq!(transitions.counts, [[0, 1, 0, 0, 0], [0, 1, 1, 1, 0], [0, 1, 0, 0, 0], [0, 0, 0, 1, 1], [1, 0, 0, 0, 0]]);
+        assert_eq!(transitions.counts.iter().flatten().sum::<usize>(), 8);
+    }
+
+    #[test]
+    fn stage_selection_gives_merged_time_ranges() {
+        let mut hypnogram = scored();
+        assert_eq!(hypnogram.select_epochs_by_stage("N2"), [(60.0, 120.0), (150.0, 180.0)]);
+        assert_eq!(hypnogram.select_epochs_by_stage("R"), [(180.0, 240.0)]);
+        assert_eq!(hypnogram.select_epochs_by_stage("W"), [(0.0, 30.0), (240.0, 270.0)]);
+        assert!(hypnogram.select_epochs_by_stage("N4").is_empty());
+        hypnogram.start_time = 3600.0;
+        assert_eq!(hypnogram.select_epochs_by_stage("N3"), [(3720.0, 3750.0)]);
+    }
+
+    #[test]
+    fn hypnograms_align_to_features_and_write_back() {
+        let (channels, names) = fixture();
+        let table = epoch_features(&channels, &names, RATE, 30.0, &bands()).unwrap();
+        let hypnogram = Hypnogram { epoch_duration: 30.0, start_time: 0.0, stages: vec!["N3".to_string(), "W".to_string(), "R".to_string()] };
+        let aligned = hypnogram.align(&table).unwrap();
+        assert_eq!(aligned.iter().map(|(stage, _)| *stage).collect::<Vec<_>>(), ["N3", "W", "R"]);
+        assert_eq!(aligned[1].1, table.values[1].as_slice());
+        match scored().align(&table) {
+            Err(ProcessingError::InvalidParameter(message)) => assert_eq!(message, "The hypnogram has 9 epochs but the feature table has 3"),
+            other => panic!("Expected a mismatch, got {:?}", other),
+        }
+
+        let path = std::env::temp_dir().join(format!("neurorust-sleep-{}-hypnogram.csv", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        scored().to_csv(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        assert!(text.starts_with("epoch,start,end,stage\n0,0,30,W\n1,30,60,N1\n"), "{}", text);
+        let reloaded = Hypnogram::from_csv(&mut CsvReader::open(&path).unwrap(), 30.0).unwrap();
+        assert_eq!(reloaded, scored());
+
+        let transitions = scored().transitions_matrix();
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        transitions.to_csv(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        assert!(std::fs::read_to_string(&path).unwrap().starts_with("from,N1,N2,N3,R,W\nN1,0,1,0,0,0\nN2,0,1,1,1,0\n"));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn malformed_hypnograms_are_rejected() {
+        let path = std::env::temp_dir().join(format!("neurorust-sleep-{}-malformed.csv", std::process::id()));
+        for (contents, message) in [
+            ("epoch,stage\n0,W\n2,N1\n", "Expected epoch 1 at line 3 but found 2"),
+            ("epoch,stage\n0,W\none,N1\n", "Epoch 'one' at line 3 is not an integer"),
+            ("epoch,label\n0,W\n", "Column 'stage' not found"),
+        ] {
+            std::fs::write(&path, contents).unwrap();
+            let error = Hypnogram::from_csv(&mut CsvReader::open(&path).unwrap(), 30.0).unwrap_err();
+            assert_eq!(error.to_string(), message);
+        }
+        std::fs::remove_file(&path).unwrap();
+    }
+}
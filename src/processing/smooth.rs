@@ -0,0 +1,419 @@
+// A module to smooth signals with moving-average, Gaussian and Savitzky-Golay filters
+
+// Written by Amin Alam in 2024
+
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::{map_channels, validate_sampling_rate};
+use crate::processing::linalg::least_squares;
+
+/// The number of standard deviations beyond which the Gaussian kernel is truncated, as in scipy.ndimage
+const GAUSSIAN_TRUNCATE_SIGMAS: f64 = 4.0;
+
+/// The smoothing filter of `smooth`
+///
+/// # Arguments
+///
+/// * `MovingAverage` - The mean over a centered window of the given odd number of samples
+/// * `Gaussian` - A Gaussian kernel with the given standard deviation in seconds, truncated at 4 standard deviations
+/// * `SavitzkyGolay` - A least-squares polynomial of order `polyorder` fitted to a centered window of `window` samples, an odd number larger than the order
+///
+/// # Examples
+///
+/// ```
+/// let method = SmoothMethod::SavitzkyGolay { window: 11, polyorder: 3 };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SmoothMethod {
+    MovingAverage(usize),
+    Gaussian(f64),
+    SavitzkyGolay { window: usize, polyorder: usize },
+}
+
+/// The values assumed beyond the ends of the signal
+///
+/// # Arguments
+///
+/// * `Reflect` - The signal mirrored about its ends, repeating the edge sample (`d c b a | a b c d`)
+/// * `Nearest` - The edge sample repeated (`a a a a | a b c d`)
+/// * `NanPad` - NaN, so the edges are NaN unless NaNs are ignored, in which case the window shrinks at the edges
+///
+/// # Examples
+///
+/// ```
+/// let smoothed = smooth(&pupil, 60.0, SmoothMethod::Gaussian(0.05), EdgeMode::Nearest, true)?;
+/// ```
+///
+/// # Note
+///
+/// `Reflect` and `Nearest` match the modes of the same name in scipy.ndimage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum EdgeMode {
+    Reflect,
+    Nearest,
+    NanPad,
+}
+
+/// Smooths a signal
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `method` - The smoothing filter
+/// * `edge` - The values assumed beyond the ends of the signal
+/// * `ignore_nan` - Leaves NaN samples out of each window if true, otherwise any NaN in a window makes its output NaN
+///
+/// # Returns
+///
+/// The smoothed samples, as many as the input, or an error if the window is even or too
+/// short for the polynomial order, or the Gaussian width is not positive
+///
+/// # Examples
+///
+/// ```
+/// let rate_curve = smooth(&rate.rates, rate.sampling_rate, SmoothMethod::MovingAverage(5), EdgeMode::Reflect, false)?;
+/// let force = smooth(&force, 1000.0, SmoothMethod::SavitzkyGolay { window: 31, polyorder: 2 }, EdgeMode::Reflect, true)?;
+/// ```
+///
+/// # Note
+///
+/// The moving average is computed with a running sum, so its cost does not depend on the
+/// window length. With `ignore_nan`, the moving average and Gaussian filters average the
+/// finite samples of each window with renormalized weights, and the Savitzky-Golay filter
+/// fits its polynomial to the finite samples only; a window without enough finite samples
+/// gives NaN.
+///
+pub fn smooth(samples: &[f64], sampling_rate: f64, method: SmoothMethod, edge: EdgeMode, ignore_nan: bool) -> Result<Vec<f64>, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    match method {
+        SmoothMethod::MovingAverage(window) => {
+            if window % 2 != 1 {
+                return Err(ProcessingError::InvalidParameter(format!("Moving average window must be odd, got {}", window)));
+            }
+            let padded = pad(samples, window / 2, edge);
+            Ok(moving_average(&padded, samples.len(), window, ignore_nan))
+        }
+        SmoothMethod::Gaussian(sigma) => {
+            if !(sigma > 0.0 && sigma.is_finite()) {
+                return Err(ProcessingError::InvalidParameter(format!("Gaussian width must be positive, got {}", sigma)));
+            }
+            let sigma_samples = sigma * sampling_rate;
+            let half = (GAUSSIAN_TRUNCATE_SIGMAS * sigma_samples + 0.5) as usize;
+            let kernel: Vec<f64> = (0..=2 * half)
+                .map(|k| (-0.5 * ((k as f64 - half as f64) / sigma_samples).powi(2)).exp())
+                .collect();
+            let total: f64 = kernel.iter().sum();
+            let kernel: Vec<f64> = kernel.iter().map(|weight| weight / total).collect();
+            let padded = pad(samples, half, edge);
+            Ok(weighted_average(&padded, samples.len(), &kernel, ignore_nan))
+        }
+        SmoothMethod::SavitzkyGolay { window, polyorder } => {
+            let coefficients = savgol_coefficients(window, polyorder)?;
+            let padded = pad(samples, window / 2, edge);
+            Ok(savitzky_golay(&padded, samples.len(), &coefficients, polyorder, ignore_nan))
+        }
+    }
+}
+
+/// Smooths every channel of a multi-channel signal
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `method` - The smoothing filter
+/// * `edge` - The values assumed beyond the ends of each channel
+/// * `ignore_nan` - Leaves NaN samples out of each window if true
+///
+/// # Returns
+///
+/// The smoothed channels, or the first error encountered
+///
+/// # Examples
+///
+/// ```
+/// let smoothed = smooth_channels(&channels, 100.0, SmoothMethod::Gaussian(0.1), EdgeMode::Reflect, false)?;
+/// ```
+///
+/// # Note
+///
+/// The channels are processed in parallel
+///
+pub fn smooth_channels(
+    channels: &[Vec<f64>],
+    sampling_rate: f64,
+    method: SmoothMethod,
+    edge: EdgeMode,
+    ignore_nan: bool,
+) -> Result<Vec<Vec<f64>>, ProcessingError> {
+    map_channels(channels, |channel| smooth(channel, sampling_rate, method, edge, ignore_nan))
+        .into_iter()
+        .collect()
+}
+
+/// Computes the Savitzky-Golay smoothing coefficients
+///
+/// # Arguments
+///
+/// * `window` - The odd number of samples of the window
+/// * `polyorder` - The order of the fitted polynomial, smaller than the window
+///
+/// # Returns
+///
+/// The weights that evaluate the least-squares polynomial at the center of the window, or
+/// an error if the window is even or not larger than the order
+///
+/// # Examples
+///
+/// ```
+/// let coefficients = savgol_coefficients(5, 2)?;
+/// // [-3, 12, 17, 12, -3] / 35
+/// ```
+///
+pub fn savgol_coefficients(window: usize, polyorder: usize) -> Result<Vec<f64>, ProcessingError> {
+    if window % 2 != 1 || window <= polyorder {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Savitzky-Golay window must be odd and larger than the polynomial order, got {} and {}",
+            window, polyorder
+        )));
+    }
+    let positions = window_positions(window);
+    let columns = polynomial_columns(&positions, polyorder);
+    // The fit to a unit impulse at position j evaluates to the weight of sample j at the center
+    let mut coefficients = Vec::with_capacity(window);
+    for j in 0..window {
+        let mut impulse = vec![0.0; window];
+        impulse[j] = 1.0;
+        let fit = least_squares(&columns, &impulse).ok_or_else(|| {
+            ProcessingError::InvalidParameter(format!("Savitzky-Golay fit of order {} is singular", polyorder))
+        })?;
+        coefficients.push(fit[0]);
+    }
+    Ok(coefficients)
+}
+
+/// Extends the signal by `half` samples on each side
+fn pad(samples: &[f64], half: usize, edge: EdgeMode) -> Vec<f64> {
+    let n = samples.len() as isize;
+    if n == 0 {
+        return Vec::new();
+    }
+    (-(half as isize)..n + half as isize)
+        .map(|i| match edge {
+            _ if (0..n).contains(&i) => samples[i as usize],
+            EdgeMode::Reflect => {
+                let m = i.rem_euclid(2 * n);
+                samples[if m < n { m } else { 2 * n - 1 - m } as usize]
+            }
+            EdgeMode::Nearest => samples[i.clamp(0, n - 1) as usize],
+            EdgeMode::NanPad => f64::NAN,
+        })
+        .collect()
+}
+
+/// Averages every window of the padded signal with a running sum of its finite samples
+fn moving_average(padded: &[f64], n: usize, window: usize, ignore_nan: bool) -> Vec<f64> {
+    let mut sum = 0.0;
+    let mut finite = 0usize;
+    let mut smoothed = Vec::with_capacity(n);
+    for (i, &value) in padded.iter().enumerate() {
+        if value.is_finite() {
+            sum += value;
+            finite += 1;
+        }
+        if i + 1 < window {
+            continue;
+        }
+        smoothed.push(if finite == 0 || (!ignore_nan && finite < window) { f64::NAN } else { sum / finite as f64 });
+        let leaving = padded[i + 1 - window];
+        if leaving.is_finite() {
+            sum -= leaving;
+            finite -= 1;
+        }
+    }
+    smoothed
+}
+
+/// Convolves the padded signal with a normalized symmetric kernel
+fn weighted_average(padded: &[f64], n: usize, kernel: &[f64], ignore_nan: bool) -> Vec<f64> {
+    (0..n)
+        .map(|i| {
+            let window = &padded[i..i + kernel.len()];
+            if !ignore_nan {
+                return window.iter().zip(kernel).map(|(value, weight)| value * weight).sum();
+            }
+            let (sum, total) = window
+                .iter()
+                .zip(kernel)
+                .filter(|(value, _)| value.is_finite())
+                .fold((0.0, 0.0), |(sum, total), (value, weight)| (sum + value * weight, total + weight));
+            if total > 0.0 { sum / total } else { f64::NAN }
+        })
+        .collect()
+}
+
+/// Applies the Savitzky-Golay coefficients, refitting windows with NaN samples if they are ignored
+fn savitzky_golay(padded: &[f64], n: usize, coefficients: &[f64], polyorder: usize, ignore_nan: bool) -> Vec<f64> {
+    let positions = window_positions(coefficients.len());
+    (0..n)
+        .map(|i| {
+            let window = &padded[i..i + coefficients.len()];
+            if !ignore_nan || window.iter().all(|value| value.is_finite()) {
+                return window.iter().zip(coefficients).map(|(value, weight)| value * weight).sum();
+            }
+            let (kept_positions, values): (Vec<f64>, Vec<f64>) = positions
+                .iter()
+                .zip(window)
+                .filter(|(_, value)| value.is_finite())
+                .map(|(&position, &value)| (position, value))
+                .unzip();
+            if values.len() <= polyorder {
+                return f64::NAN;
+            }
+            least_squares(&polynomial_columns(&kept_positions, polyorder), &values).map_or(f64::NAN, |fit| fit[0])
+        })
+        .collect()
+}
+
+/// The sample positions of a centered window, scaled to [-1, 1] to keep the fits well conditioned
+fn window_positions(window: usize) -> Vec<f64> {
+    let half = (window / 2).max(1) as f64;
+    (0..window).map(|j| (j as f64 - (window / 2) as f64) / half).collect()
+}
+
+/// The columns `x^0, ..., x^order` of the polynomial design matrix
+fn polynomial_columns(positions: &[f64], order: usize) -> Vec<Vec<f64>> {
+    (0..=order).map(|k| positions.iter().map(|x| x.powi(k as i32)).collect()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    fn assert_close(actual: &[f64], expected: &[f64], tolerance: f64) {
+        assert_eq!(actual.len(), expected.len());
+        for (k, (a, e)) in actual.iter().zip(expected).enumerate() {
+            assert!((a - e).abs() < tolerance, "sample {}: {} vs {}", k, a, e);
+        }
+    }
+
+    #[test]
+    fn savitzky_golay_coefficients_match_the_published_tables() {
+        // Savitzky and Golay (1964), as corrected by Steinier et al. (1972)
+        let tables: [(usize, usize, &[f64], f64); 7] = [
+            (5, 2, &[-3.0, 12.0, 17.0, 12.0, -3.0], 35.0),
+            (5, 3, &[-3.0, 12.0, 17.0, 12.0, -3.0], 35.0),
+            (7, 2, &[-2.0, 3.0, 6.0, 7.0, 6.0, 3.0, -2.0], 21.0),
+            (9, 2, &[-21.0, 14.0, 39.0, 54.0, 59.0, 54.0, 39.0, 14.0, -21.0], 231.0),
+            (11, 2, &[-36.0, 9.0, 44.0, 69.0, 84.0, 89.0, 84.0, 69.0, 44.0, 9.0, -36.0], 429.0),
+            (7, 4, &[5.0, -30.0, 75.0, 131.0, 75.0, -30.0, 5.0], 231.0),
+            (9, 4, &[15.0, -55.0, 30.0, 135.0, 179.0, 135.0, 30.0, -55.0, 15.0], 429.0),
+        ];
+        for (window, polyorder, numerators, normalization) in tables {
+            let expected: Vec<f64> = numerators.iter().map(|value| value / normalization).collect();
+            assert_close(&savgol_coefficients(window, polyorder).unwrap(), &expected, 1e-12);
+        }
+        // scipy.signal.savgol_coeffs(5, 2) from its docstring, and a moving average at order 0
+        assert_close(&savgol_coefficients(5, 2).unwrap(), &[-0.08571429, 0.34285714, 0.48571429, 0.34285714, -0.08571429], 1e-8);
+        assert_close(&savgol_coefficients(7, 0).unwrap(), &[1.0 / 7.0; 7], 1e-12);
+        assert_eq!(savgol_coefficients(1, 0).unwrap(), vec![1.0]);
+        // Long windows with high orders stay accurate and sum to one
+        let long = savgol_coefficients(101, 8).unwrap();
+        assert!((long.iter().sum::<f64>() - 1.0).abs() < 1e-10);
+        assert!((0..101).all(|k| (long[k] - long[100 - k]).abs() < 1e-10));
+
+        assert!(savgol_coefficients(5, 5).is_err());
+        assert!(savgol_coefficients(5, 7).is_err());
+        assert!(savgol_coefficients(6, 2).is_err());
+        assert!(smooth(&[1.0; 10], 1.0, SmoothMethod::SavitzkyGolay { window: 3, polyorder: 3 }, EdgeMode::Reflect, false).is_err());
+    }
+
+    #[test]
+    fn savitzky_golay_preserves_polynomials_up_to_its_order_even_around_nans() {
+        let cubic: Vec<f64> = (0..100)
+            .map(|k| {
+                let x = k as f64 / 10.0;
+                1.0 - 2.0 * x + 0.5 * x * x - 0.03 * x * x * x
+            })
+            .collect();
+        let method = SmoothMethod::SavitzkyGolay { window: 11, polyorder: 3 };
+        let smoothed = smooth(&cubic, 10.0, method, EdgeMode::Reflect, false).unwrap();
+        assert_close(&smoothed[5..95], &cubic[5..95], 1e-10);
+
+        let mut gapped = cubic.clone();
+        gapped[50] = f64::NAN;
+        gapped[53] = f64::NAN;
+        let refitted = smooth(&gapped, 10.0, method, EdgeMode::Reflect, true).unwrap();
+        assert_close(&refitted[5..95], &cubic[5..95], 1e-10);
+        let propagated = smooth(&gapped, 10.0, method, EdgeMode::Reflect, false).unwrap();
+        assert!((45..=58).all(|k| propagated[k].is_nan()));
+        assert!(propagated[44].is_finite() && propagated[59].is_finite());
+    }
+
+    #[test]
+    fn moving_average_and_gaussian_match_scipy_ndimage() {
+        // The docstring example of scipy.ndimage.uniform_filter1d, whose integer output truncates these means
+        let samples = [2.0, 8.0, 0.0, 4.0, 1.0, 9.0, 9.0, 0.0];
+        let averaged = smooth(&samples, 1.0, SmoothMethod::MovingAverage(3), EdgeMode::Reflect, false).unwrap();
+        assert_close(&averaged, &[4.0, 10.0 / 3.0, 4.0, 5.0 / 3.0, 14.0 / 3.0, 19.0 / 3.0, 6.0, 3.0], 1e-12);
+        assert_eq!(averaged.iter().map(|value| value.floor()).collect::<Vec<f64>>(), vec![4.0, 3.0, 4.0, 1.0, 4.0, 6.0, 6.0, 3.0]);
+
+        // The docstring examples of scipy.ndimage.gaussian_filter1d, with one sample per second
+        let ramp = [1.0, 2.0, 3.0, 4.0, 5.0];
+        let narrow = smooth(&ramp, 1.0, SmoothMethod::Gaussian(1.0), EdgeMode::Reflect, false).unwrap();
+        assert_close(&narrow, &[1.42704095, 2.06782203, 3.0, 3.93217797, 4.57295905], 1e-8);
+        let wide = smooth(&ramp, 1.0, SmoothMethod::Gaussian(4.0), EdgeMode::Reflect, false).unwrap();
+        assert_close(&wide, &[2.91948343, 2.95023502, 3.0, 3.04976498, 3.08051657], 1e-8);
+
+        // The running sum matches a direct mean over long random signals with either edge
+        let mut rng = SeededRng::new(1);
+        let noise: Vec<f64> = (0..2000).map(|_| 100.0 + rng.next_gaussian()).collect();
+        for edge in [EdgeMode::Reflect, EdgeMode::Nearest] {
+            let padded = pad(&noise, 25, edge);
+            let direct: Vec<f64> = (0..2000).map(|i| padded[i..i + 51].iter().sum::<f64>() / 51.0).collect();
+            assert_close(&smooth(&noise, 1.0, SmoothMethod::MovingAverage(51), edge, false).unwrap(), &direct, 1e-9);
+        }
+        assert!(smooth(&noise, 1.0, SmoothMethod::MovingAverage(4), EdgeMode::Reflect, false).is_err());
+        assert!(smooth(&noise, 1.0, SmoothMethod::Gaussian(0.0), EdgeMode::Reflect, false).is_err());
+        assert!(smooth(&noise, 0.0, SmoothMethod::MovingAverage(3), EdgeMode::Reflect, false).is_err());
+    }
+
+    #[test]
+    fn edge_modes_and_nan_handling_follow_their_definitions() {
+        assert_eq!(pad(&[1.0, 2.0, 3.0, 4.0], 3, EdgeMode::Reflect), vec![3.0, 2.0, 1.0, 1.0, 2.0, 3.0, 4.0, 4.0, 3.0, 2.0]);
+        assert_eq!(pad(&[1.0, 2.0, 3.0, 4.0], 2, EdgeMode::Nearest), vec![1.0, 1.0, 1.0, 2.0, 3.0, 4.0, 4.0, 4.0]);
+        assert!(smooth(&[], 1.0, SmoothMethod::MovingAverage(3), EdgeMode::Reflect, false).unwrap().is_empty());
+
+        let samples = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0];
+        let nearest = smooth(&samples, 1.0, SmoothMethod::MovingAverage(5), EdgeMode::Nearest, false).unwrap();
+        assert_close(&nearest, &[1.6, 2.2, 3.0, 4.0, 4.8, 5.4], 1e-12);
+        // NaN padding makes the edges NaN, or shrinks the window if NaNs are ignored
+        let padded = smooth(&samples, 1.0, SmoothMethod::MovingAverage(5), EdgeMode::NanPad, false).unwrap();
+        assert!(padded[0].is_nan() && padded[1].is_nan() && padded[4].is_nan() && padded[5].is_nan());
+        assert_close(&padded[2..4], &[3.0, 4.0], 1e-12);
+        let shrunk = smooth(&samples, 1.0, SmoothMethod::MovingAverage(5), EdgeMode::NanPad, true).unwrap();
+        assert_close(&shrunk, &[2.0, 2.5, 3.0, 4.0, 4.5, 5.0], 1e-12);
+
+        // A NaN spreads over its window unless it is ignored
+        let gapped = [1.0, 2.0, f64::NAN, 4.0, 5.0, 6.0, 7.0];
+        let propagated = smooth(&gapped, 1.0, SmoothMethod::MovingAverage(3), EdgeMode::Reflect, false).unwrap();
+        assert!(propagated[1..4].iter().all(|value| value.is_nan()) && propagated[4] == 5.0);
+        let ignored = smooth(&gapped, 1.0, SmoothMethod::MovingAverage(3), EdgeMode::Reflect, true).unwrap();
+        assert_close(&ignored[1..4], &[1.5, 3.0, 4.5], 1e-12);
+        let gaussian = smooth(&gapped, 1.0, SmoothMethod::Gaussian(1.0), EdgeMode::Reflect, true).unwrap();
+        assert!(gaussian.iter().all(|value| value.is_finite()));
+        assert!(smooth(&gapped, 1.0, SmoothMethod::Gaussian(1.0), EdgeMode::Reflect, false).unwrap()[2].is_nan());
+        // A window without enough finite samples for the polynomial is NaN
+        let sparse = [1.0, f64::NAN, f64::NAN, f64::NAN, f64::NAN, 2.0, 3.0];
+        let method = SmoothMethod::SavitzkyGolay { window: 5, polyorder: 2 };
+        assert!(smooth(&sparse, 1.0, method, EdgeMode::NanPad, true).unwrap()[2].is_nan());
+
+        let channels = vec![samples.to_vec(), gapped.to_vec()];
+        let smoothed = smooth_channels(&channels, 1.0, SmoothMethod::MovingAverage(3), EdgeMode::Reflect, true).unwrap();
+        assert_eq!(smoothed[1], ignored);
+        assert!(smooth_channels(&channels, 1.0, SmoothMethod::MovingAverage(2), EdgeMode::Reflect, true).is_err());
+    }
+}
@@ -0,0 +1,2360 @@
+// A module to estimate the frequency content of signals
+
+// Written by Amin Alam in 2024
+
+use std::error::Error;
+use std::fmt;
+use csv::StringRecord;
+use std::sync::Arc;
+use num_complex::Complex64;
+use rustfft::{Fft, FftPlanner};
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::data_io::float_format::FloatFormat;
+#[cfg(feature = "plot")]
+use crate::data_io::plot::{plot_spectrogram_png, plot_spectrum_svg, PlotOptions};
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::{butterworth, map_channels, validate_sampling_rate, FilterKind};
+use crate::processing::hilbert::envelope;
+use crate::processing::linalg::{least_squares, levenberg_marquardt};
+use crate::processing::parallel::{try_map_tasks, worker_count};
+use crate::processing::window::Window;
+
+/// A one-sided spectrum of a real signal
+///
+/// # Arguments
+///
+/// * `frequencies` - The frequency of each bin in Hz, from 0 up to the Nyquist frequency
+/// * `power` - The power spectral density of each bin in units²/Hz
+/// * `amplitude` - The amplitude of each bin, scaled so that a sine of amplitude `A` peaks at `A`, if available
+/// * `phase` - The phase of each bin in radians, if available
+///
+/// # Examples
+///
+/// ```
+/// let spectrum = fft_spectrum(&samples, 1000.0, Some(Window::Hann), &SpectrumOptions::default())?;
+/// let (peak_frequency, _) = spectrum.peak();
+/// ```
+///
+/// # Note
+///
+/// Amplitude and phase are only available for spectra of a single FFT, not for averaged estimates
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Spectrum {
+    pub frequencies: Vec<f64>,
+    pub power: Vec<f64>,
+    pub amplitude: Option<Vec<f64>>,
+    pub phase: Option<Vec<f64>>,
+}
+
+/// Options of `fft_spectrum`
+///
+/// # Arguments
+///
+/// * `detrend` - Subtracts the mean of the signal before the FFT
+/// * `pad_to_power_of_two` - Zero-pads the signal to the next power of two
+///
+/// # Examples
+///
+/// ```
+/// let options = SpectrumOptions { detrend: true, ..SpectrumOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpectrumOptions {
+    pub detrend: bool,
+    pub pad_to_power_of_two: bool,
+}
+
+/// Implementation of the Spectrum struct
+///
+/// # Methods
+///
+/// * `resolution` - Returns the spacing of the frequency bins
+/// * `total_power` - Integrates the power over all frequencies
+/// * `peak` - Finds the bin with the highest power
+/// * `band_power` - Integrates the power within a frequency band
+/// * `to_csv` - Writes the spectrum as `frequency,power` rows
+impl Spectrum {
+    /// Returns the spacing of the frequency bins
+    ///
+    /// # Returns
+    ///
+    /// The bin spacing in Hz, or NaN if the spectrum has fewer than two bins
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let df = spectrum.resolution();
+    /// ```
+    ///
+    pub fn resolution(&self) -> f64 {
+        if self.frequencies.len() < 2 {
+            f64::NAN
+        } else {
+            self.frequencies[1] - self.frequencies[0]
+        }
+    }
+
+    /// Integrates the power over all frequencies
+    ///
+    /// # Returns
+    ///
+    /// The sum of the power spectral density times the bin spacing, in units²
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // Parseval: equals the mean square of the signal for a rectangular window
+    /// let mean_square = spectrum.total_power();
+    /// ```
+    ///
+    pub fn total_power(&self) -> f64 {
+        self.power.iter().sum::<f64>() * self.resolution()
+    }
+
+    /// Finds the bin with the highest power
+    ///
+    /// # Returns
+    ///
+    /// The frequency in Hz and the power of the highest bin, or NaNs for an empty spectrum
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (frequency, power) = spectrum.peak();
+    /// ```
+    ///
+    pub fn peak(&self) -> (f64, f64) {
+        self.power
+            .iter()
+            .enumerate()
+            .filter(|(_, power)| !power.is_nan())
+            .max_by(|a, b| a.1.partial_cmp(b.1).unwrap())
+            .map(|(i, &power)| (self.frequencies[i], power))
+            .unwrap_or((f64::NAN, f64::NAN))
+    }
+
+    /// Integrates the power within a frequency band
+    ///
+    /// # Arguments
+    ///
+    /// * `f_low` - The lower edge of the band in Hz
+    /// * `f_high` - The upper edge of the band in Hz
+    ///
+    /// # Returns
+    ///
+    /// The trapezoidal integral of the power spectral density over the bins within
+    /// `[f_low, f_high]`, in units², or 0.0 if fewer than two bins fall in the band
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let alpha = psd.band_power(8.0, 12.0);
+    /// ```
+    ///
+    pub fn band_power(&self, f_low: f64, f_high: f64) -> f64 {
+        let in_band: Vec<(f64, f64)> = self
+            .frequencies
+            .iter()
+            .zip(&self.power)
+            .filter(|(frequency, _)| **frequency >= f_low && **frequency <= f_high)
+            .map(|(&frequency, &power)| (frequency, power))
+            .collect();
+        in_band
+            .windows(2)
+            .map(|pair| (pair[1].0 - pair[0].0) * (pair[0].1 + pair[1].1) / 2.0)
+            .sum()
+    }
+
+    /// Writes the spectrum as `frequency,power` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// spectrum.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["frequency", "power"]))?;
+        for (frequency, power) in self.frequencies.iter().zip(&self.power) {
+            csv_io.write_record(StringRecord::from(vec![float_format.format(*frequency), float_format.format(*power)]))?;
+        }
+        Ok(())
+    }
+}
+
+/// Implementation of the rendering of the Spectrum struct
+///
+/// # Methods
+///
+/// * `plot_svg` - Renders the power spectral density as an SVG file
+#[cfg(feature = "plot")]
+impl Spectrum {
+    /// Renders the power spectral density as an SVG file
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the SVG file, created or truncated
+    /// * `options` - The size, title, y-limits and logarithmic axes of the figure
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if no bin can be plotted or the file cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let options = PlotOptions { log_x: true, log_y: true, ..PlotOptions::default() };
+    /// welch(&samples, 1000.0, 1024, 0.5, Window::Hann)?.plot_svg("psd.svg", &options)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// On a logarithmic axis the zero-frequency bin and zero power are left out
+    ///
+    pub fn plot_svg<P: AsRef<std::path::Path>>(&self, path: P, options: &PlotOptions) -> std::io::Result<()> {
+        plot_spectrum_svg(self, path.as_ref(), options)
+    }
+}
+
+/// Computes the spectrum of a signal with a single FFT
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `window` - The window applied before the FFT, or `None` for a rectangular window
+/// * `options` - Detrending and zero-padding options
+///
+/// # Returns
+///
+/// A Spectrum with power, amplitude and phase, or an error if the signal is empty
+///
+/// # Examples
+///
+/// ```
+/// let spectrum = fft_spectrum(&samples, 1000.0, None, &SpectrumOptions::default())?;
+/// ```
+///
+/// # Note
+///
+/// The power is a density: with a rectangular window and no padding `total_power` equals
+/// the mean square of the signal (Parseval's theorem). The amplitude is corrected for the
+/// coherent gain of the window, so a sine of amplitude `A` on a bin center reads `A`.
+///
+pub fn fft_spectrum(samples: &[f64], sampling_rate: f64, window: Option<Window>, options: &SpectrumOptions) -> Result<Spectrum, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    if samples.is_empty() {
+        return Err(ProcessingError::SignalTooShort { length: 0, required: 1 });
+    }
+
+    let n = samples.len();
+    let taper = window.unwrap_or(Window::Rectangular).periodic(n);
+    let mean = if options.detrend { samples.iter().sum::<f64>() / n as f64 } else { 0.0 };
+    let fft_len = if options.pad_to_power_of_two { n.next_power_of_two() } else { n };
+
+    let mut buffer: Vec<Complex64> = samples
+        .iter()
+        .zip(&taper)
+        .map(|(sample, w)| Complex64::new((sample - mean) * w, 0.0))
+        .collect();
+    buffer.resize(fft_len, Complex64::new(0.0, 0.0));
+    FftPlanner::<f64>::new().plan_fft_forward(fft_len).process(&mut buffer);
+
+    let coherent_gain: f64 = taper.iter().sum();
+    let power_gain: f64 = taper.iter().map(|w| w * w).sum();
+    let n_bins = fft_len / 2 + 1;
+    let mut frequencies = Vec::with_capacity(n_bins);
+    let mut power = Vec::with_capacity(n_bins);
+    let mut amplitude = Vec::with_capacity(n_bins);
+    let mut phase = Vec::with_capacity(n_bins);
+    for (k, value) in buffer.iter().take(n_bins).enumerate() {
+        let one_sided = one_sided_factor(k, fft_len);
+        frequencies.push(k as f64 * sampling_rate / fft_len as f64);
+        power.push(one_sided * value.norm_sqr() / (sampling_rate * power_gain));
+        amplitude.push(one_sided * value.norm() / coherent_gain);
+        phase.push(value.arg());
+    }
+
+    Ok(Spectrum { frequencies, power, amplitude: Some(amplitude), phase: Some(phase) })
+}
+
+/// The segment overlap of scipy.signal.welch
+pub const DEFAULT_WELCH_OVERLAP: f64 = 0.5;
+
+/// The window of scipy.signal.welch
+pub const DEFAULT_WELCH_WINDOW: Window = Window::Hann;
+
+/// Estimates the power spectral density with Welch's method
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `segment_len` - The number of samples of each segment
+/// * `overlap_fraction` - The fraction of each segment shared with the next, in `[0, 1)`
+/// * `window` - The window applied to each segment
+///
+/// # Returns
+///
+/// A Spectrum holding the averaged power spectral density in units²/Hz, or an error if
+/// the signal is shorter than one segment or the overlap is out of range
+///
+/// # Examples
+///
+/// ```
+/// let psd = welch(&samples, 1000.0, 1024, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW)?;
+/// let alpha = psd.band_power(8.0, 12.0);
+/// ```
+///
+/// # Note
+///
+/// The estimate follows scipy.signal.welch with `scaling="density"` and
+/// `detrend="constant"`: the mean of every segment is removed, the overlap is rounded down
+/// to whole samples, and a final partial segment is dropped
+///
+pub fn welch(samples: &[f64], sampling_rate: f64, segment_len: usize, overlap_fraction: f64, window: Window) -> Result<Spectrum, ProcessingError> {
+    let (n_segments, step) = welch_segments(samples.len(), sampling_rate, segment_len, overlap_fraction)?;
+    let mut periodogram = SegmentPeriodogram::new(segment_len, sampling_rate, window);
+    let mut power = vec![0.0; periodogram.n_bins()];
+    for segment in 0..n_segments {
+        periodogram.accumulate(&samples[segment * step..segment * step + segment_len], &mut power);
+    }
+    for value in power.iter_mut() {
+        *value /= n_segments as f64;
+    }
+    Ok(Spectrum { frequencies: periodogram.frequencies(), power, amplitude: None, phase: None })
+}
+
+/// Estimates the power spectral density of several channels with Welch's method
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `segment_len` - The number of samples of each segment
+/// * `overlap_fraction` - The fraction of each segment shared with the next, in `[0, 1)`
+/// * `window` - The window applied to each segment
+///
+/// # Returns
+///
+/// One Spectrum per channel, in the input order, or the first error encountered
+///
+/// # Examples
+///
+/// ```
+/// let psds = welch_channels(&channels, 1000.0, 1024, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW)?;
+/// ```
+///
+/// # Note
+///
+/// The channels are processed in parallel
+///
+pub fn welch_channels(channels: &[Vec<f64>], sampling_rate: f64, segment_len: usize, overlap_fraction: f64, window: Window) -> Result<Vec<Spectrum>, ProcessingError> {
+    map_channels(channels, |channel| welch(channel, sampling_rate, segment_len, overlap_fraction, window))
+        .into_iter()
+        .collect()
+}
+
+/// Validates the Welch parameters and returns the number of segments and the step between them
+pub(crate) fn welch_segments(n_samples: usize, sampling_rate: f64, segment_len: usize, overlap_fraction: f64) -> Result<(usize, usize), ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    if segment_len == 0 {
+        return Err(ProcessingError::InvalidParameter("Segment length must be at least 1".to_string()));
+    }
+    if !(0.0..1.0).contains(&overlap_fraction) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Overlap fraction must be in [0, 1), got {}",
+            overlap_fraction
+        )));
+    }
+    if n_samples < segment_len {
+        return Err(ProcessingError::SignalTooShort { length: n_samples, required: segment_len });
+    }
+    let overlap = (segment_len as f64 * overlap_fraction).floor() as usize;
+    let step = segment_len - overlap;
+    Ok(((n_samples - segment_len) / step + 1, step))
+}
+
+/// A short-time power spectrum on a grid of window positions
+///
+/// # Arguments
+///
+/// * `times` - The center of each window in seconds, relative to the first sample
+/// * `frequencies` - The frequency of each bin in Hz
+/// * `power` - The power spectral density in units²/Hz, indexed as `power[frequency][time]`
+///
+/// # Examples
+///
+/// ```
+/// let tf = spectrogram(&samples, 1000.0, 256, 64, Window::Hann)?;
+/// let db = tf.to_db(1.0);
+/// ```
+///
+/// # Note
+///
+/// The time of a window is the time of its center, `(start + window_len / 2) / sampling_rate`,
+/// as in scipy.signal.spectrogram
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Spectrogram {
+    pub times: Vec<f64>,
+    pub frequencies: Vec<f64>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub power: Vec<Vec<f64>>,
+}
+
+/// Implementation of the Spectrogram struct
+///
+/// # Methods
+///
+/// * `to_db` - Converts the power to decibels
+/// * `normalize_to_baseline` - Divides every frequency by its mean power in a baseline window
+/// * `to_csv_long` - Writes the spectrogram as `time,frequency,power` rows
+impl Spectrogram {
+    /// Converts the power to decibels
+    ///
+    /// # Arguments
+    ///
+    /// * `reference` - The power that maps to 0 dB
+    ///
+    /// # Returns
+    ///
+    /// A Spectrogram holding `10 * log10(power / reference)`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let db = tf.to_db(1.0);
+    /// ```
+    ///
+    pub fn to_db(&self, reference: f64) -> Spectrogram {
+        self.map_power(|row| row.iter().map(|power| 10.0 * (power / reference).log10()).collect())
+    }
+
+    /// Divides every frequency by its mean power in a baseline window
+    ///
+    /// # Arguments
+    ///
+    /// * `baseline` - The start and end of the baseline window in seconds, matched against the window centers
+    ///
+    /// # Returns
+    ///
+    /// A Spectrogram holding the power relative to the baseline (1.0 means unchanged), or an
+    /// error if no window center falls within the baseline
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let change_db = tf.normalize_to_baseline((0.0, 0.5))?.to_db(1.0);
+    /// ```
+    ///
+    pub fn normalize_to_baseline(&self, baseline: (f64, f64)) -> Result<Spectrogram, ProcessingError> {
+        let columns: Vec<usize> = (0..self.times.len())
+            .filter(|&t| self.times[t] >= baseline.0 && self.times[t] <= baseline.1)
+            .collect();
+        if columns.is_empty() {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "No spectrogram window is centered within the baseline {:?}",
+                baseline
+            )));
+        }
+        Ok(self.map_power(|row| {
+            let mean = columns.iter().map(|&t| row[t]).sum::<f64>() / columns.len() as f64;
+            row.iter().map(|power| power / mean).collect()
+        }))
+    }
+
+    /// Writes the spectrogram as `time,frequency,power` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// tf.to_csv_long(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first, then one row per time and frequency, ordered by time
+    ///
+    pub fn to_csv_long(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["time", "frequency", "power"]))?;
+        for (t, time) in self.times.iter().enumerate() {
+            for (f, frequency) in self.frequencies.iter().enumerate() {
+                csv_io.write_record(StringRecord::from(vec![
+                    float_format.format(*time),
+                    float_format.format(*frequency),
+                    float_format.format(self.power[f][t]),
+                ]))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn map_power<F: Fn(&[f64]) -> Vec<f64>>(&self, f: F) -> Spectrogram {
+        Spectrogram {
+            times: self.times.clone(),
+            frequencies: self.frequencies.clone(),
+            power: self.power.iter().map(|row| f(row)).collect(),
+        }
+    }
+}
+
+/// Implementation of the rendering of the Spectrogram struct
+///
+/// # Methods
+///
+/// * `plot_png` - Renders the spectrogram in decibels as a PNG image
+#[cfg(feature = "plot")]
+impl Spectrogram {
+    /// Renders the spectrogram in decibels as a PNG image
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - The path of the PNG file, created or truncated
+    /// * `options` - The size, title, time range, frequency limits, colormap and color limits of the figure
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if no window is within the time range or the file cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let options = PlotOptions { y_limits: Some((0.0, 100.0)), color_limits: Some((-40.0, 10.0)), ..PlotOptions::default() };
+    /// tf.plot_png("spectrogram.png", &options)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Each window and frequency bin is drawn as a cell centred on its time and frequency.
+    /// The colors map `10 * log10(power)`, so `color_limits` are in dB.
+    ///
+    pub fn plot_png<P: AsRef<std::path::Path>>(&self, path: P, options: &PlotOptions) -> std::io::Result<()> {
+        plot_spectrogram_png(self, path.as_ref(), options)
+    }
+}
+
+/// Computes a spectrogram chunk by chunk, keeping only one window of samples in memory
+///
+/// # Examples
+///
+/// ```
+/// let mut builder = StreamingSpectrogram::new(30000.0, 4096, 1024, Window::Hann)?;
+/// for chunk in chunks {
+///     builder.push(&chunk);
+/// }
+/// let tf = builder.finish();
+/// ```
+///
+/// # Note
+///
+/// Samples that do not fill a last complete window are dropped by `finish`
+pub struct StreamingSpectrogram {
+    periodogram: SegmentPeriodogram,
+    hop: usize,
+    pending: Vec<f64>,
+    pending_start: usize,
+    skip: usize,
+    times: Vec<f64>,
+    columns: Vec<Vec<f64>>,
+}
+
+/// Implementation of the StreamingSpectrogram struct
+///
+/// # Methods
+///
+/// * `new` - Creates an empty StreamingSpectrogram
+/// * `push` - Adds the next chunk of samples
+/// * `finish` - Returns the Spectrogram of all complete windows
+impl StreamingSpectrogram {
+    /// Creates an empty StreamingSpectrogram
+    ///
+    /// # Arguments
+    ///
+    /// * `sampling_rate` - The sampling rate in Hz
+    /// * `window_len` - The number of samples of each window
+    /// * `hop` - The number of samples between the starts of consecutive windows
+    /// * `window` - The window applied to each segment
+    ///
+    /// # Returns
+    ///
+    /// The StreamingSpectrogram, or an error if a length is zero or the sampling rate is not positive
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let builder = StreamingSpectrogram::new(1000.0, 256, 64, Window::Hann)?;
+    /// ```
+    ///
+    pub fn new(sampling_rate: f64, window_len: usize, hop: usize, window: Window) -> Result<Self, ProcessingError> {
+        validate_sampling_rate(sampling_rate)?;
+        if window_len == 0 || hop == 0 {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Window length and hop must be at least 1, got {} and {}",
+                window_len, hop
+            )));
+        }
+        Ok(Self {
+            periodogram: SegmentPeriodogram::new(window_len, sampling_rate, window),
+            hop,
+            pending: Vec::with_capacity(window_len),
+            pending_start: 0,
+            skip: 0,
+            times: Vec::new(),
+            columns: Vec::new(),
+        })
+    }
+
+    /// Adds the next chunk of samples
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk` - The samples following the previously pushed ones
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// builder.push(&chunk);
+    /// ```
+    ///
+    pub fn push(&mut self, chunk: &[f64]) {
+        let skipped = self.skip.min(chunk.len());
+        self.skip -= skipped;
+        self.pending_start += skipped;
+        self.pending.extend_from_slice(&chunk[skipped..]);
+
+        let window_len = self.periodogram.len();
+        while self.pending.len() >= window_len {
+            let mut column = vec![0.0; self.periodogram.n_bins()];
+            self.periodogram.accumulate(&self.pending[..window_len], &mut column);
+            self.columns.push(column);
+            let center = self.pending_start as f64 + window_len as f64 / 2.0;
+            self.times.push(center / self.periodogram.sampling_rate);
+
+            let dropped = self.hop.min(self.pending.len());
+            self.pending.drain(..dropped);
+            self.pending_start += dropped;
+            self.skip = self.hop - dropped;
+            if self.skip > 0 {
+                break;
+            }
+        }
+    }
+
+    /// Returns the Spectrogram of all complete windows
+    ///
+    /// # Returns
+    ///
+    /// The Spectrogram, with no time bins if not a single window was completed
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let tf = builder.finish();
+    /// ```
+    ///
+    pub fn finish(self) -> Spectrogram {
+        let frequencies = self.periodogram.frequencies();
+        let power = (0..frequencies.len())
+            .map(|f| self.columns.iter().map(|column| column[f]).collect())
+            .collect();
+        Spectrogram { times: self.times, frequencies, power }
+    }
+}
+
+/// Computes the spectrogram of a signal with a short-time Fourier transform
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `window_len` - The number of samples of each window
+/// * `hop` - The number of samples between the starts of consecutive windows
+/// * `window` - The window applied to each segment
+///
+/// # Returns
+///
+/// The Spectrogram, or an error if the signal is shorter than one window
+///
+/// # Examples
+///
+/// ```
+/// let tf = spectrogram(&samples, 1000.0, 256, 64, Window::Hann)?;
+/// tf.to_csv_long(&mut csv_io)?;
+/// ```
+///
+/// # Note
+///
+/// Each window is detrended by its mean and scaled as a power spectral density, as in
+/// scipy.signal.spectrogram. A final partial window is dropped. Use `StreamingSpectrogram`
+/// to compute the spectrogram of a signal that does not fit in memory. The windows are
+/// computed in parallel and give the same result as `StreamingSpectrogram`.
+///
+pub fn spectrogram(samples: &[f64], sampling_rate: f64, window_len: usize, hop: usize, window: Window) -> Result<Spectrogram, ProcessingError> {
+    if samples.len() < window_len {
+        return Err(ProcessingError::SignalTooShort { length: samples.len(), required: window_len });
+    }
+    let frequencies = StreamingSpectrogram::new(sampling_rate, window_len, hop, window)?.periodogram.frequencies();
+    let n_frames = (samples.len() - window_len) / hop + 1;
+    let group_len = n_frames.div_ceil(worker_count(n_frames) * 4);
+    let n_groups = n_frames.div_ceil(group_len);
+    let groups = try_map_tasks(vec![(); worker_count(n_groups)], n_groups, |_, group| {
+        let frames = group * group_len..((group + 1) * group_len).min(n_frames);
+        let mut builder = StreamingSpectrogram::new(sampling_rate, window_len, hop, window)?;
+        builder.pending_start = frames.start * hop;
+        builder.push(&samples[frames.start * hop..(frames.end - 1) * hop + window_len]);
+        Ok::<_, ProcessingError>((builder.times, builder.columns))
+    })
+    .map_err(|(_, error)| error)?;
+
+    let (times, columns): (Vec<Vec<f64>>, Vec<Vec<Vec<f64>>>) = groups.into_iter().unzip();
+    let columns: Vec<Vec<f64>> = columns.concat();
+    let power = (0..frequencies.len())
+        .map(|f| columns.iter().map(|column| column[f]).collect())
+        .collect();
+    Ok(Spectrogram { times: times.concat(), frequencies, power })
+}
+
+/// The magnitude-squared coherence between two signals
+///
+/// # Arguments
+///
+/// * `frequencies` - The frequency of each bin in Hz, from 0 up to the Nyquist frequency
+/// * `coherence` - The coherence of each bin, in [0, 1]
+///
+/// # Examples
+///
+/// ```
+/// let coupling = coherence(&hippocampus, &prefrontal, 1000.0, 1024, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW, false)?;
+/// let theta = coupling.band_mean(4.0, 8.0)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Coherence {
+    pub frequencies: Vec<f64>,
+    pub coherence: Vec<f64>,
+}
+
+/// Implementation of the Coherence struct
+///
+/// # Methods
+///
+/// * `band_mean` - Averages the coherence over a frequency band
+/// * `to_csv` - Writes the coherence as `frequency,coherence` rows
+impl Coherence {
+    /// Averages the coherence over a frequency band
+    ///
+    /// # Arguments
+    ///
+    /// * `f_low` - The lower edge of the band in Hz
+    /// * `f_high` - The upper edge of the band in Hz
+    ///
+    /// # Returns
+    ///
+    /// The mean coherence of the bins within `[f_low, f_high]`, or an error if no bin falls
+    /// within the band
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let gamma = coupling.band_mean(30.0, 80.0)?;
+    /// ```
+    ///
+    pub fn band_mean(&self, f_low: f64, f_high: f64) -> Result<f64, ProcessingError> {
+        let values: Vec<f64> = self
+            .frequencies
+            .iter()
+            .zip(&self.coherence)
+            .filter(|(&frequency, _)| frequency >= f_low && frequency <= f_high)
+            .map(|(_, &value)| value)
+            .collect();
+        if values.is_empty() {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "No frequency bin falls within {} to {} Hz",
+                f_low, f_high
+            )));
+        }
+        Ok(values.iter().sum::<f64>() / values.len() as f64)
+    }
+
+    /// Writes the coherence as `frequency,coherence` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// coupling.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["frequency", "coherence"]))?;
+        for (frequency, value) in self.frequencies.iter().zip(&self.coherence) {
+            csv_io.write_record(StringRecord::from(vec![float_format.format(*frequency), float_format.format(*value)]))?;
+        }
+        Ok(())
+    }
+}
+
+/// The band-averaged coherence between every pair of channels
+///
+/// # Arguments
+///
+/// * `names` - The name of each channel
+/// * `band` - The frequency band in Hz that the coherence was averaged over
+/// * `values` - The square, symmetric matrix of coherences, with ones on the diagonal
+///
+/// # Examples
+///
+/// ```
+/// let matrix = coherence_matrix(&channels, &names, 1000.0, 1024, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW, (4.0, 8.0))?;
+/// matrix.to_csv(&mut csv_io)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CoherenceMatrix {
+    pub names: Vec<String>,
+    pub band: (f64, f64),
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub values: Vec<Vec<f64>>,
+}
+
+/// Implementation of the CoherenceMatrix struct
+///
+/// # Methods
+///
+/// * `to_csv` - Writes the matrix with a header row and a leading column of channel names
+impl CoherenceMatrix {
+    /// Writes the matrix with a header row and a leading column of channel names
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// matrix.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The header is `channel` followed by the channel names. The rows are not flushed to disk
+    /// until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        let mut header = vec!["channel".to_string()];
+        header.extend(self.names.iter().cloned());
+        csv_io.write_record(StringRecord::from(header))?;
+        for (name, row) in self.names.iter().zip(&self.values) {
+            let mut record = vec![name.clone()];
+            record.extend(row.iter().map(|value| float_format.format(*value)));
+            csv_io.write_record(StringRecord::from(record))?;
+        }
+        Ok(())
+    }
+}
+
+/// Estimates the magnitude-squared coherence between two signals with Welch's method
+///
+/// # Arguments
+///
+/// * `samples_a` - The samples of the first signal
+/// * `samples_b` - The samples of the second signal, recorded at the same sampling rate
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `segment_len` - The number of samples of each segment
+/// * `overlap_fraction` - The fraction of each segment shared with the next, in `[0, 1)`
+/// * `window` - The window applied to each segment
+/// * `truncate` - Truncates the longer signal to the length of the shorter one if true
+///
+/// # Returns
+///
+/// The Coherence, or an error if the signals differ in length and `truncate` is false, or
+/// they are shorter than one segment
+///
+/// # Examples
+///
+/// ```
+/// let coupling = coherence(&lfp_a, &lfp_b, 1000.0, 512, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW, false)?;
+/// ```
+///
+/// # Note
+///
+/// The coherence is `|Pxy|² / (Pxx Pyy)` with cross- and auto-spectra averaged over the same
+/// detrended, windowed segments as `welch`, as in scipy.signal.coherence. Bins where either
+/// signal has no power get a coherence of 0. The estimate is biased upwards by roughly
+/// `1 / n_segments`, so unrelated signals need many segments to come out near 0.
+///
+pub fn coherence(
+    samples_a: &[f64],
+    samples_b: &[f64],
+    sampling_rate: f64,
+    segment_len: usize,
+    overlap_fraction: f64,
+    window: Window,
+    truncate: bool,
+) -> Result<Coherence, ProcessingError> {
+    let length = matched_length(&[samples_a, samples_b], truncate)?;
+    let (n_segments, step) = welch_segments(length, sampling_rate, segment_len, overlap_fraction)?;
+    let mut periodogram = SegmentPeriodogram::new(segment_len, sampling_rate, window);
+    let n_bins = periodogram.n_bins();
+    let mut power_a = vec![0.0; n_bins];
+    let mut power_b = vec![0.0; n_bins];
+    let mut cross = vec![Complex64::new(0.0, 0.0); n_bins];
+    for segment in 0..n_segments {
+        let range = segment * step..segment * step + segment_len;
+        let spectrum_a: Vec<Complex64> = periodogram.transform(&samples_a[range.clone()])[..n_bins].to_vec();
+        let spectrum_b = periodogram.transform(&samples_b[range]);
+        for k in 0..n_bins {
+            power_a[k] += spectrum_a[k].norm_sqr();
+            power_b[k] += spectrum_b[k].norm_sqr();
+            cross[k] += spectrum_a[k].conj() * spectrum_b[k];
+        }
+    }
+    let coherence = (0..n_bins).map(|k| magnitude_squared_coherence(cross[k], power_a[k], power_b[k])).collect();
+    Ok(Coherence { frequencies: periodogram.frequencies(), coherence })
+}
+
+/// Estimates the band-averaged coherence between every pair of channels
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel, all of the same length
+/// * `names` - The name of each channel
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `segment_len` - The number of samples of each segment
+/// * `overlap_fraction` - The fraction of each segment shared with the next, in `[0, 1)`
+/// * `window` - The window applied to each segment
+/// * `band` - The frequency band in Hz to average over
+///
+/// # Returns
+///
+/// The CoherenceMatrix, or an error if the channels and names do not match, the channels
+/// differ in length or are shorter than one segment, or no bin falls within the band
+///
+/// # Examples
+///
+/// ```
+/// let theta = coherence_matrix(&channels, &names, 1000.0, 1024, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW, (4.0, 8.0))?;
+/// ```
+///
+/// # Note
+///
+/// Every segment of every channel is transformed once and only the bins within the band
+/// are kept, so the cost grows with the number of pairs times the band width rather than
+/// with the full spectrum of every pair
+///
+pub fn coherence_matrix(
+    channels: &[Vec<f64>],
+    names: &[String],
+    sampling_rate: f64,
+    segment_len: usize,
+    overlap_fraction: f64,
+    window: Window,
+    band: (f64, f64),
+) -> Result<CoherenceMatrix, ProcessingError> {
+    if channels.len() != names.len() {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Got {} channels but {} channel names",
+            channels.len(),
+            names.len()
+        )));
+    }
+    let slices: Vec<&[f64]> = channels.iter().map(|channel| channel.as_slice()).collect();
+    let length = matched_length(&slices, false)?;
+    let (n_segments, step) = welch_segments(length, sampling_rate, segment_len, overlap_fraction)?;
+    let mut periodogram = SegmentPeriodogram::new(segment_len, sampling_rate, window);
+    let bins: Vec<usize> = periodogram
+        .frequencies()
+        .iter()
+        .enumerate()
+        .filter(|(_, &frequency)| frequency >= band.0 && frequency <= band.1)
+        .map(|(k, _)| k)
+        .collect();
+    if bins.is_empty() {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "No frequency bin falls within {} to {} Hz",
+            band.0, band.1
+        )));
+    }
+
+    let n_channels = channels.len();
+    // cross[a][b][bin] for b >= a, with the auto-spectra on the diagonal
+    let mut cross = vec![vec![vec![Complex64::new(0.0, 0.0); bins.len()]; n_channels]; n_channels];
+    for segment in 0..n_segments {
+        let range = segment * step..segment * step + segment_len;
+        let spectra: Vec<Vec<Complex64>> = channels
+            .iter()
+            .map(|channel| {
+                let spectrum = periodogram.transform(&channel[range.clone()]);
+                bins.iter().map(|&k| spectrum[k]).collect()
+            })
+            .collect();
+        for a in 0..n_channels {
+            for b in a..n_channels {
+                for (sum, (x, y)) in cross[a][b].iter_mut().zip(spectra[a].iter().zip(&spectra[b])) {
+                    *sum += x.conj() * y;
+                }
+            }
+        }
+    }
+
+    let mut values = vec![vec![1.0; n_channels]; n_channels];
+    for a in 0..n_channels {
+        for b in a + 1..n_channels {
+            let mean = (0..bins.len())
+                .map(|i| magnitude_squared_coherence(cross[a][b][i], cross[a][a][i].re, cross[b][b][i].re))
+                .sum::<f64>()
+                / bins.len() as f64;
+            values[a][b] = mean;
+            values[b][a] = mean;
+        }
+    }
+    Ok(CoherenceMatrix { names: names.to_vec(), band, values })
+}
+
+/// Returns the common length of the signals, or an error if they differ and truncation is off
+fn matched_length(signals: &[&[f64]], truncate: bool) -> Result<usize, ProcessingError> {
+    let shortest = signals.iter().map(|signal| signal.len()).min().unwrap_or(0);
+    let longest = signals.iter().map(|signal| signal.len()).max().unwrap_or(0);
+    if shortest != longest && !truncate {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Signals must have the same length, got {} and {} samples",
+            shortest, longest
+        )));
+    }
+    Ok(shortest)
+}
+
+fn magnitude_squared_coherence(cross: Complex64, power_a: f64, power_b: f64) -> f64 {
+    if power_a > 0.0 && power_b > 0.0 {
+        (cross.norm_sqr() / (power_a * power_b)).min(1.0)
+    } else {
+        0.0
+    }
+}
+
+/// A named frequency band
+///
+/// # Arguments
+///
+/// * `name` - The name of the band, used as a column header
+/// * `f_low` - The lower edge of the band in Hz
+/// * `f_high` - The upper edge of the band in Hz
+///
+/// # Examples
+///
+/// ```
+/// let spindle = FrequencyBand::new("sigma", 11.0, 16.0);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct FrequencyBand {
+    pub name: String,
+    pub f_low: f64,
+    pub f_high: f64,
+}
+
+/// Implementation of the FrequencyBand struct
+///
+/// # Methods
+///
+/// * `new` - Creates a FrequencyBand
+/// * `canonical` - Returns the delta, theta, alpha, beta and gamma bands of EEG
+impl FrequencyBand {
+    /// Creates a FrequencyBand
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - The name of the band
+    /// * `f_low` - The lower edge of the band in Hz
+    /// * `f_high` - The upper edge of the band in Hz
+    ///
+    /// # Returns
+    ///
+    /// The FrequencyBand
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let high_gamma = FrequencyBand::new("high_gamma", 80.0, 150.0);
+    /// ```
+    ///
+    pub fn new(name: &str, f_low: f64, f_high: f64) -> Self {
+        Self { name: name.to_string(), f_low, f_high }
+    }
+
+    /// Returns the delta, theta, alpha, beta and gamma bands of EEG
+    ///
+    /// # Returns
+    ///
+    /// The bands 1-4, 4-8, 8-13, 13-30 and 30-80 Hz, in that order
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// // Narrow gamma for a 200 Hz recording
+    /// let mut bands = FrequencyBand::canonical();
+    /// bands[4].f_high = 45.0;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Band edges vary across the literature; edit the returned bands to match your convention
+    ///
+    pub fn canonical() -> Vec<FrequencyBand> {
+        vec![
+            FrequencyBand::new("delta", 1.0, 4.0),
+            FrequencyBand::new("theta", 4.0, 8.0),
+            FrequencyBand::new("alpha", 8.0, 13.0),
+            FrequencyBand::new("beta", 13.0, 30.0),
+            FrequencyBand::new("gamma", 30.0, 80.0),
+        ]
+    }
+}
+
+/// The estimator used by `band_power`
+///
+/// # Arguments
+///
+/// * `Welch` - Integrates the Welch power spectral density over each band
+/// * `FilterHilbert` - Bandpass filters the signal with a zero-phase Butterworth filter of the given order and averages the squared Hilbert envelope
+///
+/// # Examples
+///
+/// ```
+/// let method = BandPowerMethod::Welch { segment_len: 1024, overlap_fraction: DEFAULT_WELCH_OVERLAP, window: DEFAULT_WELCH_WINDOW };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BandPowerMethod {
+    Welch { segment_len: usize, overlap_fraction: f64, window: Window },
+    FilterHilbert { order: usize },
+}
+
+/// Estimates the power of a signal within several frequency bands
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `bands` - The frequency bands
+/// * `method` - The estimator
+/// * `relative` - Divides every band power by the total power of the signal if true
+///
+/// # Returns
+///
+/// The power of each band in units², or as a fraction of the total power, or an error if
+/// the signal is too short for the estimator or a band edge is invalid for the filter
+///
+/// # Examples
+///
+/// ```
+/// let powers = band_power(&samples, 250.0, &FrequencyBand::canonical(), method, true)?;
+/// ```
+///
+/// # Note
+///
+/// With `Welch`, the total power is the integral of the whole spectrum. With
+/// `FilterHilbert`, the band power is half the mean squared envelope, i.e. the mean square
+/// of the filtered signal, and the total power is the variance of the signal; the bands must
+/// lie strictly between 0 and the Nyquist frequency.
+///
+pub fn band_power(samples: &[f64], sampling_rate: f64, bands: &[FrequencyBand], method: BandPowerMethod, relative: bool) -> Result<Vec<f64>, ProcessingError> {
+    let (powers, total) = match method {
+        BandPowerMethod::Welch { segment_len, overlap_fraction, window } => {
+            let psd = welch(samples, sampling_rate, segment_len, overlap_fraction, window)?;
+            let powers = bands.iter().map(|band| psd.band_power(band.f_low, band.f_high)).collect();
+            (powers, psd.total_power())
+        }
+        BandPowerMethod::FilterHilbert { order } => {
+            validate_sampling_rate(sampling_rate)?;
+            let mut powers = Vec::with_capacity(bands.len());
+            for band in bands {
+                let filter = butterworth(order, FilterKind::Bandpass(band.f_low, band.f_high), sampling_rate)?;
+                let band_envelope = envelope(&filter.filtfilt(samples)?);
+                powers.push(band_envelope.iter().map(|value| value * value).sum::<f64>() / (2.0 * samples.len() as f64));
+            }
+            let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+            let variance = samples.iter().map(|sample| (sample - mean).powi(2)).sum::<f64>() / samples.len() as f64;
+            (powers, variance)
+        }
+    };
+    if relative {
+        Ok(powers.iter().map(|power| if total > 0.0 { power / total } else { 0.0 }).collect())
+    } else {
+        Ok(powers)
+    }
+}
+
+/// Estimates the band powers of every trial of an epoched signal
+///
+/// # Arguments
+///
+/// * `trials` - The samples of each trial
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `bands` - The frequency bands
+/// * `method` - The estimator
+/// * `relative` - Divides every band power by the total power of the trial if true
+///
+/// # Returns
+///
+/// One row of band powers per trial, or the first error encountered
+///
+/// # Examples
+///
+/// ```
+/// let theta = FrequencyBand::new("theta", 4.0, 8.0);
+/// let per_trial = band_power_trials(&trials, 500.0, &[theta], method, false)?;
+/// ```
+///
+/// # Note
+///
+/// The trials are processed in parallel
+///
+pub fn band_power_trials(trials: &[Vec<f64>], sampling_rate: f64, bands: &[FrequencyBand], method: BandPowerMethod, relative: bool) -> Result<Vec<Vec<f64>>, ProcessingError> {
+    map_channels(trials, |trial| band_power(trial, sampling_rate, bands, method, relative))
+        .into_iter()
+        .collect()
+}
+
+/// Tabulates the band powers of every channel as CSV records
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel
+/// * `names` - The name of each channel
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `bands` - The frequency bands
+/// * `method` - The estimator
+/// * `relative` - Divides every band power by the total power of the channel if true
+/// * `float_format` - The format of the band powers, usually the one of the CsvIO object the table is written to
+///
+/// # Returns
+///
+/// A header record `channel,<band names>` followed by one record per channel, or an error
+/// if the channels and names do not match or an estimate fails
+///
+/// # Examples
+///
+/// ```
+/// let table = band_power_table(&channels, &names, 250.0, &FrequencyBand::canonical(), method, true, &csv_io.float_format())?;
+/// csv_io.write_records(table)?;
+/// csv_io.save()?;
+/// ```
+///
+/// # Note
+///
+/// The channels are processed in parallel
+///
+pub fn band_power_table(
+    channels: &[Vec<f64>],
+    names: &[String],
+    sampling_rate: f64,
+    bands: &[FrequencyBand],
+    method: BandPowerMethod,
+    relative: bool,
+    float_format: &FloatFormat,
+) -> Result<Vec<StringRecord>, ProcessingError> {
+    if channels.len() != names.len() {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Got {} channels but {} channel names",
+            channels.len(),
+            names.len()
+        )));
+    }
+    let powers = band_power_trials(channels, sampling_rate, bands, method, relative)?;
+    let mut header = vec!["channel".to_string()];
+    header.extend(bands.iter().map(|band| band.name.clone()));
+    let mut table = vec![StringRecord::from(header)];
+    for (name, row) in names.iter().zip(&powers) {
+        let mut record = vec![name.clone()];
+        record.extend(row.iter().map(|power| float_format.format(*power)));
+        table.push(StringRecord::from(record));
+    }
+    Ok(table)
+}
+
+/// The shape of the aperiodic component fitted by `parameterize`
+///
+/// # Arguments
+///
+/// * `Fixed` - A straight line in log-log space, `offset - log10(f^exponent)`
+/// * `Knee` - A line that bends flat below a knee frequency, `offset - log10(knee + f^exponent)`
+///
+/// # Examples
+///
+/// ```
+/// let options = ParameterizeOptions { aperiodic_mode: AperiodicMode::Knee, f_high: 150.0, ..ParameterizeOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum AperiodicMode {
+    Fixed,
+    Knee,
+}
+
+/// Options of `parameterize`
+///
+/// # Arguments
+///
+/// * `f_low` - The lowest frequency fitted in Hz, 1 by default
+/// * `f_high` - The highest frequency fitted in Hz, 40 by default
+/// * `aperiodic_mode` - The shape of the aperiodic component, `Fixed` by default
+/// * `peak_width_limits` - The smallest and largest bandwidth of a peak in Hz, (0.5, 12) by default
+/// * `max_n_peaks` - The largest number of peaks fitted, 6 by default
+/// * `min_peak_height` - The smallest height of a peak above the aperiodic component in log10 power, 0 by default
+/// * `peak_threshold` - The smallest height of a peak in standard deviations of the flattened spectrum, 2 by default
+/// * `max_iterations` - The largest number of steps of each nonlinear fit, 200 by default
+///
+/// # Examples
+///
+/// ```
+/// let options = ParameterizeOptions { max_n_peaks: 3, min_peak_height: 0.1, ..ParameterizeOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ParameterizeOptions {
+    pub f_low: f64,
+    pub f_high: f64,
+    pub aperiodic_mode: AperiodicMode,
+    pub peak_width_limits: (f64, f64),
+    pub max_n_peaks: usize,
+    pub min_peak_height: f64,
+    pub peak_threshold: f64,
+    pub max_iterations: usize,
+}
+
+impl Default for ParameterizeOptions {
+    fn default() -> Self {
+        Self {
+            f_low: 1.0,
+            f_high: 40.0,
+            aperiodic_mode: AperiodicMode::Fixed,
+            peak_width_limits: (0.5, 12.0),
+            max_n_peaks: 6,
+            min_peak_height: 0.0,
+            peak_threshold: 2.0,
+            max_iterations: 200,
+        }
+    }
+}
+
+/// An oscillatory peak of a SpectralModel
+///
+/// # Arguments
+///
+/// * `frequency` - The center frequency in Hz
+/// * `power` - The height of the peak above the aperiodic component in log10 power
+/// * `bandwidth` - The width of the peak in Hz, twice the standard deviation of its Gaussian
+///
+/// # Examples
+///
+/// ```
+/// let alpha = model.peaks.iter().find(|peak| (8.0..=12.0).contains(&peak.frequency));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpectralPeak {
+    pub frequency: f64,
+    pub power: f64,
+    pub bandwidth: f64,
+}
+
+/// A spectrum decomposed into an aperiodic component and Gaussian peaks in log10 power
+///
+/// # Arguments
+///
+/// * `frequencies` - The frequencies fitted in Hz
+/// * `log_power` - The log10 power of the spectrum at each frequency
+/// * `offset` - The offset of the aperiodic component in log10 power
+/// * `knee` - The knee of the aperiodic component, 0 under `AperiodicMode::Fixed`
+/// * `exponent` - The exponent of the aperiodic component, positive for power falling with frequency
+/// * `peaks` - The peaks, ordered by frequency
+/// * `aperiodic_fit` - The aperiodic component at each frequency in log10 power
+/// * `model` - The aperiodic component plus the peaks at each frequency in log10 power
+/// * `r_squared` - The squared correlation between `log_power` and `model`
+/// * `error` - The mean absolute difference between `log_power` and `model`
+///
+/// # Examples
+///
+/// ```
+/// let model = parameterize(&welch(&eeg, 250.0, 500, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW)?, &ParameterizeOptions::default())?;
+/// println!("exponent {}, {} peaks, r² {}", model.exponent, model.peaks.len(), model.r_squared);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpectralModel {
+    pub frequencies: Vec<f64>,
+    pub log_power: Vec<f64>,
+    pub offset: f64,
+    pub knee: f64,
+    pub exponent: f64,
+    pub peaks: Vec<SpectralPeak>,
+    pub aperiodic_fit: Vec<f64>,
+    pub model: Vec<f64>,
+    pub r_squared: f64,
+    pub error: f64,
+}
+
+/// Implementation of the SpectralModel struct
+///
+/// # Methods
+///
+/// * `to_csv` - Writes one row per peak with the aperiodic parameters and the goodness of fit
+impl SpectralModel {
+    /// Writes one row per peak with the aperiodic parameters and the goodness of fit
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// model.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row `offset,knee,exponent,r_squared,error,frequency,power,bandwidth` is
+    /// written first. A model without peaks is written as one row with empty peak fields.
+    /// The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["offset", "knee", "exponent", "r_squared", "error", "frequency", "power", "bandwidth"]))?;
+        let aperiodic: Vec<String> = [self.offset, self.knee, self.exponent, self.r_squared, self.error].iter().map(|value| float_format.format(*value)).collect();
+        if self.peaks.is_empty() {
+            let mut record = aperiodic.clone();
+            record.extend(vec![String::new(); 3]);
+            csv_io.write_record(StringRecord::from(record))?;
+        }
+        for peak in &self.peaks {
+            let mut record = aperiodic.clone();
+            record.extend([peak.frequency, peak.power, peak.bandwidth].iter().map(|value| float_format.format(*value)));
+            csv_io.write_record(StringRecord::from(record))?;
+        }
+        Ok(())
+    }
+}
+
+/// The errors returned by `parameterize`
+///
+/// # Arguments
+///
+/// * `Invalid` - The spectrum or the options are invalid
+/// * `NotConverged` - A nonlinear fit did not converge within `max_iterations` steps, with the fit reached so far
+///
+/// # Examples
+///
+/// ```
+/// match parameterize(&spectrum, &options) {
+///     Err(ParameterizeError::NotConverged { partial, .. }) => println!("Unconverged exponent {}", partial.exponent),
+///     _ => {}
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParameterizeError {
+    Invalid(ProcessingError),
+    NotConverged { iterations: usize, partial: Box<SpectralModel> },
+}
+
+impl fmt::Display for ParameterizeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParameterizeError::Invalid(error) => write!(f, "{}", error),
+            ParameterizeError::NotConverged { iterations, .. } => write!(f, "The spectral fit did not converge within {} iterations", iterations),
+        }
+    }
+}
+
+impl Error for ParameterizeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParameterizeError::Invalid(error) => Some(error),
+            ParameterizeError::NotConverged { .. } => None,
+        }
+    }
+}
+
+impl From<ProcessingError> for ParameterizeError {
+    fn from(error: ProcessingError) -> Self {
+        ParameterizeError::Invalid(error)
+    }
+}
+
+/// Separates the oscillatory peaks of a spectrum from its aperiodic (1/f) background
+///
+/// # Arguments
+///
+/// * `spectrum` - The power spectrum, e.g. from `welch`
+/// * `options` - The frequency range, aperiodic shape, peak constraints and iteration limit
+///
+/// # Returns
+///
+/// The SpectralModel, or an error if the options are invalid, fewer than eight bins with
+/// positive power fall in the frequency range, or a nonlinear fit does not converge
+///
+/// # Examples
+///
+/// ```
+/// let psd = welch(&eeg, 250.0, 500, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW)?;
+/// let model = parameterize(&psd, &ParameterizeOptions::default())?;
+/// model.to_csv(&mut csv_io)?;
+/// ```
+///
+/// # Note
+///
+/// The algorithm follows FOOOF (Donoghue et al., 2020) in log10 power. The aperiodic
+/// component is fitted to the whole spectrum, then refitted to the bins below that first
+/// fit so that peaks do not pull it up. Peaks are found one at a time in the flattened
+/// spectrum, as its highest point above both thresholds, with a width guessed from the
+/// half-height of its steeper side; each guess is subtracted before the next is sought.
+/// Guesses within one standard deviation of the edges of the range are dropped, as is the
+/// lower of two guesses that overlap. All Gaussians are then fitted together, with each
+/// center kept within three guessed standard deviations and each bandwidth within the
+/// limits; peaks that end up no higher than `min_peak_height`, or zero, are dropped, and
+/// the aperiodic component is refitted to the spectrum without the rest. The fixed
+/// aperiodic fit is linear; the nonlinear fits are bounded Levenberg-Marquardt solves of at
+/// most `max_iterations` steps, so the result is deterministic.
+///
+pub fn parameterize(spectrum: &Spectrum, options: &ParameterizeOptions) -> Result<SpectralModel, ParameterizeError> {
+    let (min_width, max_width) = options.peak_width_limits;
+    if !(options.f_low > 0.0 && options.f_high > options.f_low && options.f_high.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "The frequency range must be positive and increasing, got {} to {} Hz",
+            options.f_low, options.f_high
+        ))
+        .into());
+    }
+    if !(min_width > 0.0 && max_width >= min_width && max_width.is_finite()) || options.peak_threshold.is_nan() || options.min_peak_height.is_nan() {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "The peak width limits must be positive and increasing and the thresholds numbers, got {:?}, {} and {}",
+            options.peak_width_limits, options.peak_threshold, options.min_peak_height
+        ))
+        .into());
+    }
+    let (frequencies, log_power): (Vec<f64>, Vec<f64>) = spectrum
+        .frequencies
+        .iter()
+        .zip(&spectrum.power)
+        .filter(|(&frequency, _)| frequency >= options.f_low && frequency <= options.f_high)
+        .map(|(&frequency, &power)| (frequency, power.log10()))
+        .unzip();
+    if frequencies.len() < 8 || log_power.iter().any(|power| !power.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "At least 8 bins with positive power are needed between {} and {} Hz, got {} bins",
+            options.f_low,
+            options.f_high,
+            frequencies.len()
+        ))
+        .into());
+    }
+    let mut converged = true;
+
+    // A first aperiodic fit, refitted to the bins below it
+    let (initial, initial_converged) = fit_aperiodic(&frequencies, &log_power, options.aperiodic_mode, None, options.max_iterations);
+    converged &= initial_converged;
+    let below: Vec<usize> = (0..frequencies.len()).filter(|&k| log_power[k] <= aperiodic_value(frequencies[k], &initial)).collect();
+    let robust = if below.len() >= 4 {
+        let sub_frequencies: Vec<f64> = below.iter().map(|&k| frequencies[k]).collect();
+        let sub_power: Vec<f64> = below.iter().map(|&k| log_power[k]).collect();
+        let (robust, robust_converged) = fit_aperiodic(&sub_frequencies, &sub_power, options.aperiodic_mode, Some(initial), options.max_iterations);
+        converged &= robust_converged;
+        robust
+    } else {
+        initial
+    };
+    let flat: Vec<f64> = frequencies.iter().zip(&log_power).map(|(&frequency, power)| power - aperiodic_value(frequency, &robust)).collect();
+
+    let resolution = frequencies[1] - frequencies[0];
+    let guesses = guess_peaks(&frequencies, &flat, resolution, options);
+    let (gaussians, peaks_converged) = fit_peaks(&frequencies, &flat, &guesses, options);
+    converged &= peaks_converged;
+    // The joint fit can flatten a guess that a neighbour explains better
+    let gaussians: Vec<f64> = gaussians
+        .chunks(3)
+        .filter(|gaussian| gaussian[1] > options.min_peak_height.max(0.0))
+        .flatten()
+        .copied()
+        .collect();
+
+    let periodic: Vec<f64> = frequencies.iter().map(|&frequency| gaussians_value(frequency, &gaussians)).collect();
+    let without_peaks: Vec<f64> = log_power.iter().zip(&periodic).map(|(power, peak)| power - peak).collect();
+    let (aperiodic, final_converged) = fit_aperiodic(&frequencies, &without_peaks, options.aperiodic_mode, Some(robust), options.max_iterations);
+    converged &= final_converged;
+
+    let aperiodic_fit: Vec<f64> = frequencies.iter().map(|&frequency| aperiodic_value(frequency, &aperiodic)).collect();
+    let model: Vec<f64> = aperiodic_fit.iter().zip(&periodic).map(|(aperiodic, peak)| aperiodic + peak).collect();
+    let n = frequencies.len() as f64;
+    let error = log_power.iter().zip(&model).map(|(power, fit)| (power - fit).abs()).sum::<f64>() / n;
+    let (mean_power, mean_model) = (log_power.iter().sum::<f64>() / n, model.iter().sum::<f64>() / n);
+    let (covariance, power_variance, model_variance) = log_power.iter().zip(&model).fold((0.0, 0.0, 0.0), |(c, p, m), (power, fit)| {
+        (c + (power - mean_power) * (fit - mean_model), p + (power - mean_power).powi(2), m + (fit - mean_model).powi(2))
+    });
+    let mut peaks: Vec<SpectralPeak> = gaussians
+        .chunks(3)
+        .map(|gaussian| SpectralPeak { frequency: gaussian[0], power: gaussian[1], bandwidth: 2.0 * gaussian[2] })
+        .collect();
+    peaks.sort_by(|a, b| a.frequency.total_cmp(&b.frequency));
+    let result = SpectralModel {
+        frequencies,
+        log_power,
+        offset: aperiodic[0],
+        knee: aperiodic[1],
+        exponent: aperiodic[2],
+        peaks,
+        aperiodic_fit,
+        model,
+        r_squared: covariance * covariance / (power_variance * model_variance),
+        error,
+    };
+    if converged {
+        Ok(result)
+    } else {
+        Err(ParameterizeError::NotConverged { iterations: options.max_iterations, partial: Box::new(result) })
+    }
+}
+
+/// Evaluates the aperiodic component `[offset, knee, exponent]` at a frequency
+fn aperiodic_value(frequency: f64, params: &[f64; 3]) -> f64 {
+    params[0] - (params[1] + frequency.powf(params[2])).log10()
+}
+
+/// Evaluates a sum of Gaussians laid out as `[center, height, std, ...]` at a frequency
+fn gaussians_value(frequency: f64, gaussians: &[f64]) -> f64 {
+    gaussians
+        .chunks(3)
+        .map(|gaussian| gaussian[1] * (-(frequency - gaussian[0]).powi(2) / (2.0 * gaussian[2] * gaussian[2])).exp())
+        .sum()
+}
+
+/// Fits the aperiodic component `[offset, knee, exponent]` and returns whether the fit converged
+fn fit_aperiodic(frequencies: &[f64], log_power: &[f64], mode: AperiodicMode, start: Option<[f64; 3]>, max_iterations: usize) -> ([f64; 3], bool) {
+    // The fixed component is linear in log10 frequency
+    let log_frequencies: Vec<f64> = frequencies.iter().map(|frequency| -frequency.log10()).collect();
+    let line = least_squares(&[vec![1.0; frequencies.len()], log_frequencies], log_power).unwrap_or_else(|| vec![log_power[0], 0.0]);
+    if mode == AperiodicMode::Fixed {
+        return ([line[0], 0.0, line[1]], true);
+    }
+    let mut params = start.unwrap_or([line[0], 0.0, line[1]]);
+    let converged = levenberg_marquardt(&mut params, &[f64::NEG_INFINITY, 0.0, f64::NEG_INFINITY], &[f64::INFINITY; 3], max_iterations, |params| {
+        let mut residuals = Vec::with_capacity(frequencies.len());
+        let mut jacobian = vec![Vec::new(), Vec::new(), Vec::new()];
+        for (&frequency, &power) in frequencies.iter().zip(log_power) {
+            let term = frequency.powf(params[2]);
+            let denominator = (params[1] + term) * std::f64::consts::LN_10;
+            residuals.push(power - (params[0] - (params[1] + term).log10()));
+            jacobian[0].push(1.0);
+            jacobian[1].push(-1.0 / denominator);
+            jacobian[2].push(-term * frequency.ln() / denominator);
+        }
+        (residuals, jacobian)
+    });
+    (params, converged)
+}
+
+/// Finds the initial guesses `[center, height, std, ...]` of the peaks of a flattened spectrum
+fn guess_peaks(frequencies: &[f64], flat: &[f64], resolution: f64, options: &ParameterizeOptions) -> Vec<[f64; 3]> {
+    let (min_std, max_std) = (options.peak_width_limits.0 / 2.0, options.peak_width_limits.1 / 2.0);
+    let mut residual = flat.to_vec();
+    let mut guesses: Vec<[f64; 3]> = Vec::new();
+    while guesses.len() < options.max_n_peaks {
+        let (index, &height) = residual.iter().enumerate().max_by(|a, b| a.1.total_cmp(b.1)).unwrap();
+        let mean = residual.iter().sum::<f64>() / residual.len() as f64;
+        let std = (residual.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / residual.len() as f64).sqrt();
+        if height <= options.peak_threshold * std || height <= options.min_peak_height {
+            break;
+        }
+        // The full width at half maximum of the steeper side
+        let half = height / 2.0;
+        let left = (0..index).rev().find(|&k| residual[k] <= half).map(|k| index - k);
+        let right = (index + 1..residual.len()).find(|&k| residual[k] <= half).map(|k| k - index);
+        let half_width = left.into_iter().chain(right).min().map_or(max_std, |side| side as f64 * resolution);
+        let guess_std = (half_width * 2.0 / (2.0 * (2.0 * 2f64.ln()).sqrt())).clamp(min_std, max_std);
+        let guess = [frequencies[index], height, guess_std];
+        for (value, &frequency) in residual.iter_mut().zip(frequencies) {
+            *value -= gaussians_value(frequency, &guess);
+        }
+        guesses.push(guess);
+    }
+
+    let (first, last) = (frequencies[0], frequencies[frequencies.len() - 1]);
+    guesses.retain(|guess| guess[0] - guess[2] >= first && guess[0] + guess[2] <= last);
+    guesses.sort_by(|a, b| a[0].total_cmp(&b[0]));
+    // Of two overlapping guesses, only the higher one is kept
+    let mut kept: Vec<[f64; 3]> = Vec::new();
+    for guess in guesses {
+        match kept.last_mut() {
+            Some(previous) if previous[0] + 0.75 * previous[2] >= guess[0] - 0.75 * guess[2] => {
+                if guess[1] > previous[1] {
+                    *previous = guess;
+                }
+            }
+            _ => kept.push(guess),
+        }
+    }
+    kept
+}
+
+/// Fits all peaks together to the flattened spectrum and returns whether the fit converged
+fn fit_peaks(frequencies: &[f64], flat: &[f64], guesses: &[[f64; 3]], options: &ParameterizeOptions) -> (Vec<f64>, bool) {
+    if guesses.is_empty() {
+        return (Vec::new(), true);
+    }
+    let (min_std, max_std) = (options.peak_width_limits.0 / 2.0, options.peak_width_limits.1 / 2.0);
+    let mut params: Vec<f64> = guesses.iter().flatten().copied().collect();
+    let lower: Vec<f64> = guesses.iter().flat_map(|guess| [guess[0] - 3.0 * guess[2], 0.0, min_std]).collect();
+    let upper: Vec<f64> = guesses.iter().flat_map(|guess| [guess[0] + 3.0 * guess[2], f64::INFINITY, max_std]).collect();
+    let converged = levenberg_marquardt(&mut params, &lower, &upper, options.max_iterations, |params| {
+        let residuals = frequencies.iter().zip(flat).map(|(&frequency, value)| value - gaussians_value(frequency, params)).collect();
+        let jacobian = params
+            .chunks(3)
+            .flat_map(|gaussian| {
+                let (center, height, std) = (gaussian[0], gaussian[1], gaussian[2]);
+                let shape: Vec<f64> = frequencies.iter().map(|&frequency| (-(frequency - center).powi(2) / (2.0 * std * std)).exp()).collect();
+                let d_center = frequencies.iter().zip(&shape).map(|(&frequency, value)| height * value * (frequency - center) / (std * std)).collect();
+                let d_std = frequencies.iter().zip(&shape).map(|(&frequency, value)| height * value * (frequency - center).powi(2) / std.powi(3)).collect();
+                [d_center, shape, d_std]
+            })
+            .collect();
+        (residuals, jacobian)
+    });
+    (params, converged)
+}
+
+/// Computes mean-detrended, windowed, density-scaled periodograms of fixed-length segments
+pub(crate) struct SegmentPeriodogram {
+    taper: Vec<f64>,
+    fft: Arc<dyn Fft<f64>>,
+    buffer: Vec<Complex64>,
+    sampling_rate: f64,
+    scale: f64,
+}
+
+impl SegmentPeriodogram {
+    pub(crate) fn new(len: usize, sampling_rate: f64, window: Window) -> Self {
+        let taper = window.periodic(len);
+        let power_gain: f64 = taper.iter().map(|w| w * w).sum();
+        Self {
+            taper,
+            fft: FftPlanner::<f64>::new().plan_fft_forward(len),
+            buffer: vec![Complex64::new(0.0, 0.0); len],
+            sampling_rate,
+            scale: 1.0 / (sampling_rate * power_gain),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.taper.len()
+    }
+
+    pub(crate) fn n_bins(&self) -> usize {
+        self.len() / 2 + 1
+    }
+
+    pub(crate) fn frequencies(&self) -> Vec<f64> {
+        let len = self.len() as f64;
+        (0..self.n_bins()).map(|k| k as f64 * self.sampling_rate / len).collect()
+    }
+
+    /// Adds the one-sided power spectral density of `segment` to `power`
+    pub(crate) fn accumulate(&mut self, segment: &[f64], power: &mut [f64]) {
+        let len = self.len();
+        let scale = self.scale;
+        let spectrum = self.transform(segment);
+        for (k, value) in power.iter_mut().enumerate() {
+            *value += scale * one_sided_factor(k, len) * spectrum[k].norm_sqr();
+        }
+    }
+
+    /// Returns the unscaled FFT of the mean-detrended, windowed `segment`
+    fn transform(&mut self, segment: &[f64]) -> &[Complex64] {
+        let mean = segment.iter().sum::<f64>() / self.len() as f64;
+        for ((value, sample), w) in self.buffer.iter_mut().zip(segment).zip(&self.taper) {
+            *value = Complex64::new((sample - mean) * w, 0.0);
+        }
+        self.fft.process(&mut self.buffer);
+        &self.buffer
+    }
+}
+
+/// Returns 2 for bins whose negative-frequency twin is folded in, and 1 for DC and Nyquist
+pub(crate) fn one_sided_factor(bin: usize, fft_len: usize) -> f64 {
+    if bin == 0 || (fft_len.is_multiple_of(2) && bin == fft_len / 2) {
+        1.0
+    } else {
+        2.0
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+    use crate::processing::random::SeededRng;
+
+    /// Four channels sharing one unit-variance source, scaled by 1, 1, 2 and 2, each with independent unit-variance noise
+    fn shared_source(n: usize) -> Vec<Vec<f64>> {
+        let mut rng = SeededRng::new(8);
+        let source: Vec<f64> = (0..n).map(|_| rng.next_gaussian()).collect();
+        [1.0, 1.0, 2.0, 2.0].iter().map(|&gain| source.iter().map(|&s| gain * s + rng.next_gaussian()).collect()).collect()
+    }
+
+    fn gaussian_noise(n: usize, std: f64, seed: u64) -> Vec<f64> {
+        let mut rng = SeededRng::new(seed);
+        (0..n).map(|_| std * rng.next_gaussian()).collect()
+    }
+
+    fn sine(n: usize, sampling_rate: f64, frequency: f64, amplitude: f64) -> Vec<f64> {
+        (0..n).map(|k| amplitude * (2.0 * PI * frequency * k as f64 / sampling_rate).sin()).collect()
+    }
+
+    /// Writes through a CsvIO object and returns the lines of the file
+    fn written_lines<F: FnOnce(&mut CsvIO) -> Result<(), DataIoError>>(name: &str, write: F) -> Vec<String> {
+        let path = std::env::temp_dir().join(format!("neurorust-spectral-{}-{}", std::process::id(), name)).to_string_lossy().into_owned();
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(&path).unwrap();
+        write(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let lines = std::fs::read_to_string(&path).unwrap().lines().map(str::to_string).collect();
+        std::fs::remove_file(&path).unwrap();
+        lines
+    }
+
+    #[test]
+    fn coherence_recovers_the_shared_fraction_of_the_power() {
+        let channels = shared_source(200_000);
+        let mean = |a: usize, b: usize| {
+            let result = coherence(&channels[a], &channels[b], 1000.0, 256, 0.5, Window::Hann, false).unwrap();
+            result.band_mean(10.0, 400.0).unwrap()
+        };
+        // |Pxy|^2 / (Pxx Pyy) for gains g and h is g^2 h^2 / ((g^2 + 1)(h^2 + 1))
+        assert!((mean(0, 1) - 0.25).abs() < 0.005, "{}", mean(0, 1));
+        assert!((mean(0, 2) - 0.4).abs() < 0.005, "{}", mean(0, 2));
+        assert!((mean(2, 3) - 0.64).abs() < 0.005, "{}", mean(2, 3));
+    }
+
+    #[test]
+    fn coherence_matrix_matches_the_pairwise_coherence() {
+        let channels = shared_source(50_000);
+        let names: Vec<String> = ["a", "b", "c", "d"].iter().map(|name| name.to_string()).collect();
+        let matrix = coherence_matrix(&channels, &names, 1000.0, 256, 0.5, Window::Hann, (10.0, 100.0)).unwrap();
+        for a in 0..4 {
+            assert!((matrix.values[a][a] - 1.0).abs() < 1e-12);
+            for b in 0..4 {
+                assert_eq!(matrix.values[a][b], matrix.values[b][a]);
+            }
+        }
+        let pairwise = coherence(&channels[0], &channels[2], 1000.0, 256, 0.5, Window::Hann, false).unwrap();
+        assert!((matrix.values[0][2] - pairwise.band_mean(10.0, 100.0).unwrap()).abs() < 0.01);
+    }
+
+    #[test]
+    fn coherence_rejects_signals_of_different_lengths_unless_truncating() {
+        let channels = shared_source(4096);
+        assert!(coherence(&channels[0], &channels[1][..3000], 1000.0, 256, 0.5, Window::Hann, false).is_err());
+        let truncated = coherence(&channels[0], &channels[1][..3000], 1000.0, 256, 0.5, Window::Hann, true).unwrap();
+        assert_eq!(truncated.frequencies.len(), 129);
+    }
+
+    #[test]
+    fn fft_spectrum_total_power_equals_the_mean_square_by_parseval() {
+        for (n, seed) in [(1000, 1), (999, 2), (1, 3)] {
+            let samples: Vec<f64> = gaussian_noise(n, 1.5, seed).iter().map(|value| value + 0.7).collect();
+            let mean_square = samples.iter().map(|value| value * value).sum::<f64>() / n as f64;
+            let relative = |a: f64, b: f64| (a - b).abs() / b;
+
+            if n > 1 {
+                let plain = fft_spectrum(&samples, 250.0, None, &SpectrumOptions::default()).unwrap();
+                assert!(relative(plain.total_power(), mean_square) < 1e-12, "{} samples", n);
+                // Zero-padding interpolates the spectrum without changing its total
+                let padded = fft_spectrum(&samples, 250.0, None, &SpectrumOptions { pad_to_power_of_two: true, ..SpectrumOptions::default() }).unwrap();
+                assert_eq!(padded.frequencies.len(), n.next_power_of_two() / 2 + 1);
+                assert!(relative(padded.total_power(), mean_square) < 1e-12, "{} samples padded", n);
+
+                let mean = samples.iter().sum::<f64>() / n as f64;
+                let variance = samples.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / n as f64;
+                let detrended = fft_spectrum(&samples, 250.0, None, &SpectrumOptions { detrend: true, ..SpectrumOptions::default() }).unwrap();
+                assert!(relative(detrended.total_power(), variance) < 1e-12 && detrended.power[0] < 1e-20);
+
+                // With a window, Parseval holds for the windowed signal over the window power
+                let taper = Window::Hann.periodic(n);
+                let windowed = taper.iter().zip(&samples).map(|(w, x)| (w * x).powi(2)).sum::<f64>() / taper.iter().map(|w| w * w).sum::<f64>();
+                let hann = fft_spectrum(&samples, 250.0, Some(Window::Hann), &SpectrumOptions::default()).unwrap();
+                assert!(relative(hann.total_power(), windowed) < 1e-12);
+            } else {
+                let single = fft_spectrum(&samples, 250.0, None, &SpectrumOptions::default()).unwrap();
+                assert_eq!(single.frequencies, vec![0.0]);
+                assert!(relative(single.power[0] * 250.0, mean_square) < 1e-12);
+            }
+        }
+        assert!(fft_spectrum(&[], 250.0, None, &SpectrumOptions::default()).is_err());
+        assert!(fft_spectrum(&[1.0, 2.0], 0.0, None, &SpectrumOptions::default()).is_err());
+    }
+
+    #[test]
+    fn fft_spectrum_puts_a_sine_at_its_frequency_with_its_amplitude() {
+        let sampling_rate = 1000.0;
+        for n in [1000, 1001] {
+            let samples = sine(n, sampling_rate, 37.0, 3.0);
+            let spectrum = fft_spectrum(&samples, sampling_rate, None, &SpectrumOptions::default()).unwrap();
+            assert_eq!(spectrum.frequencies.len(), n / 2 + 1);
+            assert!(spectrum.frequencies.iter().enumerate().all(|(k, f)| (f - k as f64 * sampling_rate / n as f64).abs() < 1e-9));
+            assert!((spectrum.resolution() - sampling_rate / n as f64).abs() < 1e-12);
+            let last = *spectrum.frequencies.last().unwrap();
+            assert!(if n % 2 == 0 { last == 500.0 } else { last < 500.0 });
+
+            let (frequency, _) = spectrum.peak();
+            assert!((frequency - 37.0).abs() <= spectrum.resolution() / 2.0, "{} samples peak at {} Hz", n, frequency);
+            if n == 1000 {
+                // On a bin center: the amplitude reads 3, a sine has phase -pi/2, and the
+                // power of the bin times the bin width is the mean square A^2 / 2
+                let bin = 37;
+                let amplitude = spectrum.amplitude.as_ref().unwrap();
+                assert!((amplitude[bin] - 3.0).abs() < 1e-9);
+                assert!((spectrum.phase.as_ref().unwrap()[bin] + PI / 2.0).abs() < 1e-9);
+                assert!((spectrum.power[bin] * spectrum.resolution() - 4.5).abs() < 1e-9);
+                let hann = fft_spectrum(&samples, sampling_rate, Some(Window::Hann), &SpectrumOptions::default()).unwrap();
+                assert!((hann.amplitude.as_ref().unwrap()[bin] - 3.0).abs() < 1e-9);
+                assert_eq!(hann.peak().0, 37.0);
+            }
+        }
+
+        // Between bins, padding to a power of two still finds the peak within the finer spacing
+        let samples = sine(1000, sampling_rate, 123.3, 1.0);
+        let padded = fft_spectrum(&samples, sampling_rate, Some(Window::Hann), &SpectrumOptions { pad_to_power_of_two: true, ..SpectrumOptions::default() }).unwrap();
+        assert!((padded.resolution() - 1000.0 / 1024.0).abs() < 1e-12);
+        assert!((padded.peak().0 - 123.3).abs() <= padded.resolution() / 2.0);
+    }
+
+    #[test]
+    fn spectrum_to_csv_writes_frequency_and_power() {
+        let spectrum = fft_spectrum(&sine(8, 8.0, 1.0, 1.0), 8.0, None, &SpectrumOptions::default()).unwrap();
+        let lines = written_lines("spectrum.csv", |csv_io| spectrum.to_csv(csv_io));
+        assert_eq!(lines.len(), 6);
+        assert_eq!(lines[0], "frequency,power");
+        let row: Vec<f64> = lines[2].split(',').map(|field| field.parse().unwrap()).collect();
+        assert_eq!(row[0], 1.0);
+        assert!((row[1] - spectrum.power[1]).abs() <= 1e-9 * spectrum.power[1]);
+    }
+
+    /// scipy.signal.welch with its defaults written out from its definition, with a DFT by the sum
+    fn reference_welch(samples: &[f64], sampling_rate: f64, nperseg: usize) -> Vec<f64> {
+        let noverlap = nperseg / 2;
+        let step = nperseg - noverlap;
+        let window: Vec<f64> = (0..nperseg).map(|n| 0.5 - 0.5 * (2.0 * PI * n as f64 / nperseg as f64).cos()).collect();
+        let scale = 1.0 / (sampling_rate * window.iter().map(|w| w * w).sum::<f64>());
+        let n_segments = (samples.len() - noverlap) / step;
+        let mut psd = vec![0.0; nperseg / 2 + 1];
+        for segment in 0..n_segments {
+            let values = &samples[segment * step..segment * step + nperseg];
+            let mean = values.iter().sum::<f64>() / nperseg as f64;
+            for (k, bin) in psd.iter_mut().enumerate() {
+                let (re, im) = values.iter().zip(&window).enumerate().fold((0.0, 0.0), |(re, im), (n, (x, w))| {
+                    let angle = -2.0 * PI * (k * n) as f64 / nperseg as f64;
+                    (re + (x - mean) * w * angle.cos(), im + (x - mean) * w * angle.sin())
+                });
+                let doubled = if k == 0 || (nperseg.is_multiple_of(2) && k == nperseg / 2) { 1.0 } else { 2.0 };
+                *bin += doubled * scale * (re * re + im * im) / n_segments as f64;
+            }
+        }
+        psd
+    }
+
+    #[test]
+    fn welch_matches_the_scipy_definition_for_white_noise_and_a_sine_in_noise() {
+        let sampling_rate = 500.0;
+        let noise = gaussian_noise(5000, 2.0, 110);
+        let tone: Vec<f64> = sine(5000, sampling_rate, 62.5, 1.5).iter().zip(&noise).map(|(a, b)| a + b).collect();
+        for (samples, nperseg) in [(&noise, 256), (&tone, 256), (&tone, 255)] {
+            let psd = welch(samples, sampling_rate, nperseg, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW).unwrap();
+            let expected = reference_welch(samples, sampling_rate, nperseg);
+            assert_eq!(psd.power.len(), expected.len());
+            assert!(psd.frequencies.iter().enumerate().all(|(k, f)| (f - k as f64 * sampling_rate / nperseg as f64).abs() < 1e-12));
+            for (actual, want) in psd.power.iter().zip(&expected) {
+                assert!((actual - want).abs() <= 1e-10 * want.abs().max(1e-6), "{} vs {} with {} samples per segment", actual, want, nperseg);
+            }
+            assert!(psd.amplitude.is_none() && psd.phase.is_none());
+        }
+    }
+
+    #[test]
+    fn welch_reports_the_density_in_units_squared_per_hz() {
+        // White noise of variance 4 sampled at 500 Hz spreads 4 units² over 250 Hz
+        let sampling_rate = 500.0;
+        let noise = gaussian_noise(200_000, 2.0, 1100);
+        let psd = welch(&noise, sampling_rate, 512, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW).unwrap();
+        let interior = &psd.power[5..psd.power.len() - 5];
+        let level = interior.iter().sum::<f64>() / interior.len() as f64;
+        assert!((level - 4.0 / 250.0).abs() < 0.01 * 4.0 / 250.0, "{}", level);
+        assert!((psd.total_power() - 4.0).abs() < 0.04);
+
+        // A sine of amplitude 1.5 adds 1.125 units² around its frequency
+        let tone: Vec<f64> = sine(200_000, sampling_rate, 60.0, 1.5).iter().zip(&noise).map(|(a, b)| a + b).collect();
+        let with_tone = welch(&tone, sampling_rate, 512, DEFAULT_WELCH_OVERLAP, DEFAULT_WELCH_WINDOW).unwrap();
+        let excess = with_tone.band_power(55.0, 65.0) - psd.band_power(55.0, 65.0);
+        assert!((excess - 1.125).abs() < 0.01 * 1.125, "{}", excess);
+        assert!((with_tone.peak().0 - 60.0).abs() <= with_tone.resolution() / 2.0);
+    }
+
+    #[test]
+    fn welch_drops_the_final_partial_segment() {
+        // 256-sample segments every 128 samples fit six times in 1000 samples, covering 896
+        let samples = gaussian_noise(1000, 1.0, 111);
+        let all = welch(&samples, 1000.0, 256, 0.5, Window::Hann).unwrap();
+        assert_eq!(all, welch(&samples[..896], 1000.0, 256, 0.5, Window::Hann).unwrap());
+        assert_ne!(all, welch(&samples[..895], 1000.0, 256, 0.5, Window::Hann).unwrap());
+        assert_eq!(welch_segments(1000, 1000.0, 256, 0.5).unwrap(), (6, 128));
+        // The overlap is rounded down to whole samples
+        assert_eq!(welch_segments(1000, 1000.0, 255, 0.5).unwrap(), (6, 128));
+        assert_eq!(welch_segments(256, 1000.0, 256, 0.0).unwrap(), (1, 256));
+
+        assert!(welch(&samples, 1000.0, 1001, 0.5, Window::Hann).is_err());
+        assert!(welch(&samples, 1000.0, 0, 0.5, Window::Hann).is_err());
+        assert!(welch(&samples, 1000.0, 256, 1.0, Window::Hann).is_err());
+        assert!(welch(&samples, 1000.0, 256, -0.1, Window::Hann).is_err());
+    }
+
+    #[test]
+    fn welch_channels_and_band_power_integrate_each_channel() {
+        let channels = shared_source(4096);
+        let psds = welch_channels(&channels, 1000.0, 256, 0.5, Window::Hamming).unwrap();
+        assert_eq!(psds.len(), 4);
+        for (channel, psd) in channels.iter().zip(&psds) {
+            assert_eq!(psd, &welch(channel, 1000.0, 256, 0.5, Window::Hamming).unwrap());
+        }
+        assert!(welch_channels(&[channels[0].clone(), vec![0.0; 10]], 1000.0, 256, 0.5, Window::Hann).is_err());
+
+        // The trapezoidal rule over the bins within the band, edges included
+        let ramp = Spectrum { frequencies: (0..11).map(f64::from).collect(), power: (0..11).map(f64::from).collect(), amplitude: None, phase: None };
+        assert_eq!(ramp.band_power(2.0, 5.0), 10.5);
+        assert_eq!(ramp.band_power(1.5, 5.5), 10.5);
+        assert_eq!(ramp.band_power(0.0, 10.0), 50.0);
+        assert_eq!(ramp.band_power(3.2, 3.8), 0.0);
+        assert_eq!(ramp.band_power(3.0, 3.5), 0.0);
+    }
+
+    #[test]
+    fn spectrogram_times_are_window_centers_and_the_partial_window_is_dropped() {
+        // 256-sample windows every 100 samples fit eight times in 1000 samples, the last
+        // starting at 700; the 44 samples after 956 do not fill a window
+        let samples = gaussian_noise(1000, 1.0, 1110);
+        let tf = spectrogram(&samples, 1000.0, 256, 100, Window::Hann).unwrap();
+        assert_eq!(tf.times.len(), 8);
+        for (k, time) in tf.times.iter().enumerate() {
+            assert!((time - (k as f64 * 100.0 + 128.0) / 1000.0).abs() < 1e-12, "window {} at {}", k, time);
+        }
+        assert_eq!(tf.frequencies.len(), 129);
+        assert!(tf.power.iter().all(|row| row.len() == 8));
+        assert_eq!(tf, spectrogram(&samples[..956], 1000.0, 256, 100, Window::Hann).unwrap());
+        assert_eq!(spectrogram(&samples[..955], 1000.0, 256, 100, Window::Hann).unwrap().times.len(), 7);
+        // An odd window is centered half a sample after its middle sample
+        assert!((spectrogram(&samples, 1000.0, 255, 100, Window::Hann).unwrap().times[0] - 0.1275).abs() < 1e-12);
+
+        // Each column is the density-scaled periodogram of its window
+        for (k, start) in [(0, 0), (3, 300), (7, 700)] {
+            let column = welch(&samples[start..start + 256], 1000.0, 256, 0.0, Window::Hann).unwrap();
+            for (f, power) in column.power.iter().enumerate() {
+                assert!((tf.power[f][k] - power).abs() <= 1e-12 * power.max(1e-9));
+            }
+        }
+
+        assert!(spectrogram(&samples[..255], 1000.0, 256, 100, Window::Hann).is_err());
+        assert!(spectrogram(&samples, 1000.0, 256, 0, Window::Hann).is_err());
+        assert!(spectrogram(&samples, 1000.0, 0, 100, Window::Hann).is_err());
+        assert!(spectrogram(&samples, -1.0, 256, 100, Window::Hann).is_err());
+    }
+
+    #[test]
+    fn streaming_spectrogram_matches_the_batch_spectrogram_for_any_chunking() {
+        let samples = gaussian_noise(5000, 1.0, 1111);
+        // Hops longer than the window skip samples between windows
+        for (window_len, hop) in [(256, 64), (256, 256), (100, 333), (1, 1)] {
+            let batch = spectrogram(&samples, 500.0, window_len, hop, Window::Hamming).unwrap();
+            for chunk_len in [1, 7, 333, 5000] {
+                let mut builder = StreamingSpectrogram::new(500.0, window_len, hop, Window::Hamming).unwrap();
+                for chunk in samples.chunks(chunk_len) {
+                    builder.push(chunk);
+                }
+                let streamed = builder.finish();
+                assert_eq!(streamed.times, batch.times, "window {} hop {} chunks {}", window_len, hop, chunk_len);
+                assert_eq!(streamed.power, batch.power, "window {} hop {} chunks {}", window_len, hop, chunk_len);
+            }
+        }
+        let empty = StreamingSpectrogram::new(500.0, 256, 64, Window::Hann).unwrap().finish();
+        assert!(empty.times.is_empty() && empty.frequencies.len() == 129 && empty.power.iter().all(Vec::is_empty));
+        assert!(StreamingSpectrogram::new(500.0, 256, 0, Window::Hann).is_err());
+    }
+
+    #[test]
+    fn spectrogram_follows_a_change_of_frequency_and_normalizes_to_a_baseline() {
+        // 2 s at 50 Hz then 2 s at 200 Hz
+        let sampling_rate = 1000.0;
+        let samples: Vec<f64> = (0..4000)
+            .map(|k| {
+                let frequency = if k < 2000 { 50.0 } else { 200.0 };
+                (2.0 * PI * frequency * k as f64 / sampling_rate).sin()
+            })
+            .collect();
+        let tf = spectrogram(&samples, sampling_rate, 200, 100, Window::Hann).unwrap();
+        for (t, time) in tf.times.iter().enumerate() {
+            let column: Vec<f64> = tf.power.iter().map(|row| row[t]).collect();
+            let peak = tf.frequencies[(0..column.len()).max_by(|&a, &b| column[a].total_cmp(&column[b])).unwrap()];
+            if *time < 1.9 {
+                assert_eq!(peak, 50.0, "at {} s", time);
+            } else if *time > 2.1 {
+                assert_eq!(peak, 200.0, "at {} s", time);
+            }
+        }
+
+        let db = tf.to_db(2.0);
+        assert!((db.power[10][3] - 10.0 * (tf.power[10][3] / 2.0).log10()).abs() < 1e-12);
+        let relative = tf.normalize_to_baseline((0.0, 1.0)).unwrap();
+        let baseline: Vec<usize> = (0..tf.times.len()).filter(|&t| tf.times[t] <= 1.0).collect();
+        assert_eq!(baseline.len(), 10);
+        for row in &relative.power {
+            let mean = baseline.iter().map(|&t| row[t]).sum::<f64>() / baseline.len() as f64;
+            assert!((mean - 1.0).abs() < 1e-9);
+        }
+        // After the switch, 200 Hz rises far above its baseline and 50 Hz falls far below
+        let last = tf.times.len() - 1;
+        assert!(relative.power[40][last] > 1e3 && relative.power[10][last] < 1e-3);
+        assert!(tf.normalize_to_baseline((0.0, 0.05)).is_err());
+    }
+
+    #[test]
+    fn spectrogram_to_csv_long_writes_one_row_per_time_and_frequency() {
+        let tf = spectrogram(&gaussian_noise(64, 1.0, 1112), 100.0, 16, 16, Window::Hann).unwrap();
+        let lines = written_lines("spectrogram.csv", |csv_io| tf.to_csv_long(csv_io));
+        assert_eq!(lines.len(), 1 + 4 * 9);
+        assert_eq!(lines[0], "time,frequency,power");
+        let row: Vec<f64> = lines[1 + 9 + 2].split(',').map(|field| field.parse().unwrap()).collect();
+        assert_eq!(&row[..2], &[0.24, 12.5]);
+        assert!((row[2] - tf.power[2][1]).abs() <= 1e-9 * tf.power[2][1]);
+    }
+
+    #[test]
+    fn a_sine_in_each_canonical_band_puts_at_least_95_percent_of_its_power_there() {
+        let sampling_rate = 250.0;
+        let bands = FrequencyBand::canonical();
+        let methods = [
+            BandPowerMethod::Welch { segment_len: 500, overlap_fraction: DEFAULT_WELCH_OVERLAP, window: DEFAULT_WELCH_WINDOW },
+            BandPowerMethod::FilterHilbert { order: 4 },
+        ];
+        for (band, frequency) in [2.5, 6.0, 10.5, 20.0, 50.0].iter().enumerate() {
+            let samples = sine(20 * sampling_rate as usize, sampling_rate, *frequency, 3.0);
+            for method in methods {
+                let relative = band_power(&samples, sampling_rate, &bands, method, true).unwrap();
+                assert!(relative[band] >= 0.95, "{} Hz in {} with {:?}: {:?}", frequency, bands[band].name, method, relative);
+                assert!(relative.iter().enumerate().all(|(other, power)| other == band || *power < 0.05));
+                // The absolute power of a sine of amplitude 3 is its mean square, 4.5
+                let absolute = band_power(&samples, sampling_rate, &bands, method, false).unwrap();
+                assert!((absolute[band] - 4.5).abs() < 0.05 * 4.5, "{} Hz with {:?}: {}", frequency, method, absolute[band]);
+            }
+        }
+
+        // Custom bands override the canonical ones
+        let samples = sine(5000, sampling_rate, 14.0, 1.0);
+        let sigma = [FrequencyBand::new("sigma", 11.0, 16.0)];
+        assert!(band_power(&samples, sampling_rate, &sigma, methods[0], true).unwrap()[0] >= 0.95);
+        assert!(band_power(&samples, sampling_rate, &[FrequencyBand::new("high", 100.0, 125.0)], methods[1], true).is_err());
+        assert!(band_power(&samples[..100], sampling_rate, &sigma, methods[0], true).is_err());
+    }
+
+    #[test]
+    fn band_power_per_trial_and_per_channel_table() {
+        let sampling_rate = 250.0;
+        let trials: Vec<Vec<f64>> = [6.0, 10.5, 6.0].iter().map(|&frequency| sine(1000, sampling_rate, frequency, 1.0)).collect();
+        let bands = FrequencyBand::canonical();
+        let method = BandPowerMethod::Welch { segment_len: 250, overlap_fraction: 0.5, window: Window::Hann };
+        let per_trial = band_power_trials(&trials, sampling_rate, &bands, method, true).unwrap();
+        assert_eq!(per_trial.len(), 3);
+        for (trial, row) in trials.iter().zip(&per_trial) {
+            assert_eq!(row, &band_power(trial, sampling_rate, &bands, method, true).unwrap());
+        }
+        assert!(per_trial[0][1] > 0.95 && per_trial[1][2] > 0.95);
+
+        let names: Vec<String> = ["Fz", "Cz", "Pz"].iter().map(|name| name.to_string()).collect();
+        let table = band_power_table(&trials, &names, sampling_rate, &bands, method, true, &FloatFormat::default()).unwrap();
+        assert_eq!(table.len(), 4);
+        assert_eq!(table[0], StringRecord::from(vec!["channel", "delta", "theta", "alpha", "beta", "gamma"]));
+        assert_eq!(&table[2][0], "Cz");
+        let alpha: f64 = table[2][3].parse().unwrap();
+        assert!((alpha - per_trial[1][2]).abs() < 1e-9);
+        let lines = written_lines("band-power.csv", |csv_io| csv_io.write_records(table));
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].starts_with("Fz,"));
+        assert!(band_power_table(&trials, &names[..2], sampling_rate, &bands, method, true, &FloatFormat::default()).is_err());
+    }
+
+    /// A spectrum built from an aperiodic component `[offset, knee, exponent]` and Gaussian peaks
+    /// `(center, height, std)` in log10 power, with Gaussian noise of `noise` in log10 power
+    fn synthetic_spectrum(f_high: f64, aperiodic: [f64; 3], peaks: &[(f64, f64, f64)], noise: f64, seed: u64) -> Spectrum {
+        let mut rng = SeededRng::new(seed);
+        let frequencies: Vec<f64> = (0..=(f_high * 4.0) as usize).map(|k| k as f64 * 0.25).collect();
+        let power = frequencies
+            .iter()
+            .map(|&frequency| {
+                let periodic: f64 = peaks.iter().map(|(center, height, std)| height * (-(frequency - center).powi(2) / (2.0 * std * std)).exp()).sum();
+                10f64.powf(aperiodic_value(frequency, &aperiodic) + periodic + noise * rng.next_gaussian())
+            })
+            .collect();
+        Spectrum { frequencies, power, amplitude: None, phase: None }
+    }
+
+    fn assert_peaks(model: &SpectralModel, expected: &[(f64, f64, f64)], center: f64, height: f64, bandwidth: f64) {
+        assert_eq!(model.peaks.len(), expected.len(), "{:?}", model.peaks);
+        for (peak, (want_center, want_height, want_std)) in model.peaks.iter().zip(expected) {
+            assert!((peak.frequency - want_center).abs() < center, "{:?} vs center {}", peak, want_center);
+            assert!((peak.power - want_height).abs() < height, "{:?} vs height {}", peak, want_height);
+            assert!((peak.bandwidth / (2.0 * want_std) - 1.0).abs() < bandwidth, "{:?} vs bandwidth {}", peak, 2.0 * want_std);
+        }
+    }
+
+    #[test]
+    fn parameterize_recovers_a_fixed_aperiodic_component_and_its_peaks() {
+        let peaks = [(10.0, 0.8, 1.0), (22.0, 0.4, 2.0)];
+        // Noise of 0.03 in log10 power makes bumps of up to about 0.1 that pass the standard
+        // deviation threshold, and without noise rounding errors do, so a minimum height is
+        // set as in FOOOF
+        let options = ParameterizeOptions { min_peak_height: 0.15, ..ParameterizeOptions::default() };
+
+        // Noiseless: offset and exponent within 0.02, centers within 0.1 Hz, heights within
+        // 0.02 and bandwidths within 5%
+        let exact = parameterize(&synthetic_spectrum(50.0, [1.5, 0.0, 1.8], &peaks, 0.0, 167), &options).unwrap();
+        assert!((exact.offset - 1.5).abs() < 0.02 && (exact.exponent - 1.8).abs() < 0.02, "{} {}", exact.offset, exact.exponent);
+        assert_eq!(exact.knee, 0.0);
+        assert_peaks(&exact, &peaks, 0.1, 0.02, 0.05);
+        assert!(exact.r_squared > 0.999 && exact.error < 0.01, "{} {}", exact.r_squared, exact.error);
+        assert_eq!(exact.frequencies.len(), 157);
+        assert!(exact.frequencies[0] >= 1.0 && *exact.frequencies.last().unwrap() <= 40.0);
+
+        // Noise of 0.03 in log10 power: offset and exponent within 0.05, centers within 0.3 Hz,
+        // heights within 0.06 and bandwidths within 20%
+        for seed in 0..5 {
+            let noisy = parameterize(&synthetic_spectrum(50.0, [1.5, 0.0, 1.8], &peaks, 0.03, 1670 + seed), &options).unwrap();
+            assert!((noisy.offset - 1.5).abs() < 0.05 && (noisy.exponent - 1.8).abs() < 0.05, "seed {}: {} {}", seed, noisy.offset, noisy.exponent);
+            assert_peaks(&noisy, &peaks, 0.3, 0.06, 0.2);
+            assert!(noisy.r_squared > 0.98, "seed {}: {}", seed, noisy.r_squared);
+        }
+
+        // A purely aperiodic spectrum has no peaks
+        let flat = parameterize(&synthetic_spectrum(50.0, [0.5, 0.0, 1.0], &[], 0.0, 1671), &options).unwrap();
+        assert!(flat.peaks.is_empty() && (flat.exponent - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parameterize_recovers_a_knee_and_is_deterministic() {
+        let peaks = [(40.0, 0.6, 3.0)];
+        let options = ParameterizeOptions { aperiodic_mode: AperiodicMode::Knee, f_high: 100.0, ..ParameterizeOptions::default() };
+        let spectrum = synthetic_spectrum(120.0, [2.0, 20.0, 2.5], &peaks, 0.0, 1672);
+        let model = parameterize(&spectrum, &options).unwrap();
+        assert!((model.offset - 2.0).abs() < 0.05, "{}", model.offset);
+        assert!((model.knee / 20.0 - 1.0).abs() < 0.1, "{}", model.knee);
+        assert!((model.exponent - 2.5).abs() < 0.05, "{}", model.exponent);
+        assert_peaks(&model, &peaks, 0.2, 0.03, 0.1);
+        assert_eq!(model, parameterize(&spectrum, &options).unwrap());
+
+        // A limit too low to converge returns the fit reached so far
+        match parameterize(&spectrum, &ParameterizeOptions { max_iterations: 1, ..options }) {
+            Err(ParameterizeError::NotConverged { iterations, partial }) => {
+                assert_eq!(iterations, 1);
+                assert_eq!(partial.frequencies, model.frequencies);
+                assert_eq!(partial.model.len(), partial.frequencies.len());
+            }
+            other => panic!("expected NotConverged, got {:?}", other.map(|model| model.exponent)),
+        }
+    }
+
+    #[test]
+    fn parameterize_limits_peaks_and_rejects_invalid_options() {
+        let peaks = [(6.0, 0.5, 1.0), (10.0, 0.9, 1.0), (20.0, 0.3, 1.5), (30.0, 0.6, 1.0)];
+        let spectrum = synthetic_spectrum(50.0, [1.0, 0.0, 1.5], &peaks, 0.0, 1673);
+        let two = parameterize(&spectrum, &ParameterizeOptions { max_n_peaks: 2, ..ParameterizeOptions::default() }).unwrap();
+        // The highest peaks are found first, and are reported by frequency
+        assert_eq!(two.peaks.len(), 2);
+        assert!((two.peaks[0].frequency - 10.0).abs() < 0.2 && (two.peaks[1].frequency - 30.0).abs() < 0.2, "{:?}", two.peaks);
+        let tall = parameterize(&spectrum, &ParameterizeOptions { min_peak_height: 0.4, ..ParameterizeOptions::default() }).unwrap();
+        assert!(tall.peaks.iter().all(|peak| peak.power > 0.4) && tall.peaks.len() == 3, "{:?}", tall.peaks);
+
+        let invalid = |options: ParameterizeOptions| matches!(parameterize(&spectrum, &options), Err(ParameterizeError::Invalid(_)));
+        assert!(invalid(ParameterizeOptions { f_low: 0.0, ..ParameterizeOptions::default() }));
+        assert!(invalid(ParameterizeOptions { f_high: 0.5, ..ParameterizeOptions::default() }));
+        assert!(invalid(ParameterizeOptions { peak_width_limits: (2.0, 1.0), ..ParameterizeOptions::default() }));
+        assert!(invalid(ParameterizeOptions { peak_threshold: f64::NAN, ..ParameterizeOptions::default() }));
+        assert!(invalid(ParameterizeOptions { f_low: 1.0, f_high: 2.0, ..ParameterizeOptions::default() }));
+        let mut silent = spectrum.clone();
+        silent.power[20] = 0.0;
+        assert!(matches!(parameterize(&silent, &ParameterizeOptions::default()), Err(ParameterizeError::Invalid(_))));
+    }
+
+    #[test]
+    fn spectral_model_to_csv_writes_one_row_per_peak() {
+        let peaks = [(10.0, 0.8, 1.0), (22.0, 0.4, 2.0)];
+        let options = ParameterizeOptions { min_peak_height: 0.15, ..ParameterizeOptions::default() };
+        let model = parameterize(&synthetic_spectrum(50.0, [1.5, 0.0, 1.8], &peaks, 0.0, 1674), &options).unwrap();
+        let lines = written_lines("model.csv", |csv_io| model.to_csv(csv_io));
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], "offset,knee,exponent,r_squared,error,frequency,power,bandwidth");
+        let row: Vec<f64> = lines[2].split(',').map(|field| field.parse().unwrap()).collect();
+        assert!((row[2] - model.exponent).abs() < 1e-9 && (row[5] - model.peaks[1].frequency).abs() < 1e-9);
+
+        let flat = parameterize(&synthetic_spectrum(50.0, [0.5, 0.0, 1.0], &[], 0.0, 1675), &options).unwrap();
+        let lines = written_lines("flat-model.csv", |csv_io| flat.to_csv(csv_io));
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].ends_with(",,,"));
+    }
+}
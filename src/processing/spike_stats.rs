@@ -0,0 +1,262 @@
+// A module to compute inter-spike interval and spike count statistics
+
+// Written by Amin Alam in 2024
+
+use crate::processing::error::ProcessingError;
+use crate::processing::histogram::Histogram;
+
+/// The refractory period commonly used to judge the isolation of a unit, in seconds
+pub const DEFAULT_REFRACTORY_PERIOD: f64 = 0.002;
+
+/// Computes the inter-spike intervals of a spike train
+///
+/// # Arguments
+///
+/// * `spike_times` - The spike times in seconds, in any order
+///
+/// # Returns
+///
+/// The intervals between consecutive spikes in seconds, empty for fewer than two spikes
+///
+/// # Examples
+///
+/// ```
+/// let intervals = isi(&unit);
+/// ```
+///
+pub fn isi(spike_times: &[f64]) -> Vec<f64> {
+    let mut sorted = spike_times.to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    sorted.windows(2).map(|pair| pair[1] - pair[0]).collect()
+}
+
+/// Computes the histogram of the inter-spike intervals of a spike train
+///
+/// # Arguments
+///
+/// * `spike_times` - The spike times in seconds, in any order
+/// * `bin_size` - The width of each bin in seconds
+/// * `max_isi` - The longest interval shown in seconds
+///
+/// # Returns
+///
+/// The Histogram of the intervals from 0 to `max_isi`, or an error if the bin size or the
+/// maximum interval is not positive
+///
+/// # Examples
+///
+/// ```
+/// let histogram = isi_histogram(&unit, 0.0005, 0.05)?;
+/// ```
+///
+pub fn isi_histogram(spike_times: &[f64], bin_size: f64, max_isi: f64) -> Result<Histogram, ProcessingError> {
+    Histogram::from_values(&isi(spike_times), bin_size, 0.0, max_isi)
+}
+
+/// Computes the coefficient of variation of the inter-spike intervals
+///
+/// # Arguments
+///
+/// * `spike_times` - The spike times in seconds, in any order
+///
+/// # Returns
+///
+/// The standard deviation of the intervals divided by their mean, about 1 for a Poisson
+/// process, or NaN for fewer than three spikes
+///
+/// # Examples
+///
+/// ```
+/// let regularity = cv_isi(&unit);
+/// ```
+///
+/// # Note
+///
+/// The standard deviation is the population one, as in elephant.statistics.cv
+///
+pub fn cv_isi(spike_times: &[f64]) -> f64 {
+    let intervals = isi(spike_times);
+    if intervals.len() < 2 {
+        return f64::NAN;
+    }
+    let (mean, variance) = mean_and_variance(&intervals);
+    variance.sqrt() / mean
+}
+
+/// Computes the Fano factor of the spike counts in consecutive windows
+///
+/// # Arguments
+///
+/// * `spike_times` - The spike times in seconds, in any order
+/// * `window_size` - The width of each counting window in seconds
+/// * `recording` - The start and end of the recording in seconds
+///
+/// # Returns
+///
+/// The variance of the counts divided by their mean, about 1 for a Poisson process, NaN if
+/// fewer than two full windows fit in the recording or no spike was counted, or an error if
+/// the window size is not positive
+///
+/// # Examples
+///
+/// ```
+/// let fano = fano_factor(&unit, 1.0, (0.0, 600.0))?;
+/// ```
+///
+/// # Note
+///
+/// The windows tile the recording from its start and a final partial window is left out,
+/// since its count is not comparable to the others. The variance is the population one.
+///
+pub fn fano_factor(spike_times: &[f64], window_size: f64, recording: (f64, f64)) -> Result<f64, ProcessingError> {
+    if !(window_size > 0.0 && window_size.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!("Window size must be positive, got {}", window_size)));
+    }
+    // Tolerate rounding so that e.g. 600 s hold exactly 6000 windows of 0.1 s
+    let n_windows = ((recording.1 - recording.0) / window_size + 1e-9).floor().max(0.0) as usize;
+    if n_windows < 2 {
+        return Ok(f64::NAN);
+    }
+    let mut counts = vec![0.0; n_windows];
+    for &time in spike_times {
+        let position = (time - recording.0) / window_size;
+        if position >= 0.0 && (position as usize) < n_windows {
+            counts[position as usize] += 1.0;
+        }
+    }
+    let (mean, variance) = mean_and_variance(&counts);
+    Ok(if mean > 0.0 { variance / mean } else { f64::NAN })
+}
+
+/// Counts the inter-spike intervals shorter than a refractory period
+///
+/// # Arguments
+///
+/// * `spike_times` - The spike times in seconds, in any order
+/// * `refractory_period` - The refractory period in seconds, see `DEFAULT_REFRACTORY_PERIOD`
+///
+/// # Returns
+///
+/// The number of violating intervals and their fraction of all intervals, NaN for fewer
+/// than two spikes
+///
+/// # Examples
+///
+/// ```
+/// let (violations, fraction) = refractory_violations(&unit, DEFAULT_REFRACTORY_PERIOD);
+/// if fraction > 0.01 { println!("Poorly isolated unit"); }
+/// ```
+///
+pub fn refractory_violations(spike_times: &[f64], refractory_period: f64) -> (usize, f64) {
+    let intervals = isi(spike_times);
+    if intervals.is_empty() {
+        return (0, f64::NAN);
+    }
+    let violations = intervals.iter().filter(|&&interval| interval < refractory_period).count();
+    (violations, violations as f64 / intervals.len() as f64)
+}
+
+fn mean_and_variance(values: &[f64]) -> (f64, f64) {
+    let n = values.len() as f64;
+    let mean = values.iter().sum::<f64>() / n;
+    let variance = values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / n;
+    (mean, variance)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    /// The tiny train of the hand-computed values, out of order
+    const TINY: [f64; 5] = [0.35, 0.1, 0.5, 0.101, 0.2];
+
+    fn poisson(rate: f64, duration: f64, seed: u64) -> Vec<f64> {
+        let mut rng = SeededRng::new(seed);
+        let mut times = Vec::new();
+        let mut time = 0.0;
+        loop {
+            time -= (1.0 - rng.next_f64()).ln() / rate;
+            if time > duration {
+                return times;
+            }
+            times.push(time);
+        }
+    }
+
+    #[test]
+    fn statistics_of_a_tiny_train_match_the_hand_computed_values() {
+        let intervals = isi(&TINY);
+        let expected = [0.001, 0.099, 0.15, 0.15];
+        assert_eq!(intervals.len(), 4);
+        assert!(intervals.iter().zip(expected).all(|(a, b)| (a - b).abs() < 1e-12));
+        // The intervals have mean 0.1 and population standard deviation sqrt(0.0037005)
+        assert!((cv_isi(&TINY) - 0.0037005f64.sqrt() / 0.1).abs() < 1e-9);
+        assert_eq!(refractory_violations(&TINY, DEFAULT_REFRACTORY_PERIOD), (1, 0.25));
+        assert_eq!(refractory_violations(&TINY, 0.2), (4, 1.0));
+
+        // Windows of 0.1 s hold 0, 2, 1, 1 and 0 spikes with mean 0.8 and variance 0.56; the
+        // spike at 0.5 s is in the final partial window, which is left out
+        let fano = fano_factor(&TINY, 0.1, (0.0, 0.55)).unwrap();
+        assert!((fano - 0.7).abs() < 1e-12, "{}", fano);
+        assert_eq!(fano_factor(&TINY, 0.1, (0.0, 0.5)).unwrap(), fano);
+        assert_eq!(fano_factor(&TINY, 0.1, (0.0, 0.59)).unwrap(), fano);
+
+        let histogram = isi_histogram(&TINY, 0.04, 0.2).unwrap();
+        assert_eq!(histogram.counts, vec![1, 0, 1, 2, 0]);
+        assert_eq!(histogram.bin_edges.len(), 6);
+    }
+
+    #[test]
+    fn trains_with_fewer_than_two_spikes_give_empty_or_nan_results() {
+        for train in [&[][..], &[1.0][..]] {
+            assert!(isi(train).is_empty());
+            assert!(cv_isi(train).is_nan());
+            let (violations, fraction) = refractory_violations(train, DEFAULT_REFRACTORY_PERIOD);
+            assert_eq!(violations, 0);
+            assert!(fraction.is_nan());
+            let histogram = isi_histogram(train, 0.001, 0.05).unwrap();
+            assert_eq!(histogram.total(), 0);
+            assert!(histogram.density().iter().all(|density| *density == 0.0));
+        }
+        assert!(fano_factor(&[], 1.0, (0.0, 10.0)).unwrap().is_nan());
+        assert!(fano_factor(&[1.0], 1.0, (0.0, 10.0)).unwrap() > 0.0);
+        // Two spikes have one interval and no spread
+        assert!(cv_isi(&[0.0, 1.0]).is_nan());
+        // Fewer than two full windows
+        assert!(fano_factor(&TINY, 0.3, (0.0, 0.55)).unwrap().is_nan());
+        assert!(fano_factor(&TINY, 0.0, (0.0, 0.55)).is_err());
+        assert!(isi_histogram(&TINY, 0.0, 0.05).is_err());
+        assert!(isi_histogram(&TINY, 0.001, 0.0).is_err());
+    }
+
+    #[test]
+    fn poisson_and_regular_trains_have_the_expected_statistics() {
+        let train = poisson(50.0, 2000.0, 1);
+        let n = isi(&train).len() as f64;
+        // The standard errors are about 0.0045 for the CV, 0.03 for the Fano factor of 2000
+        // one-second windows and 0.001 for the violation fraction
+        let cv = cv_isi(&train);
+        assert!((cv - 1.0).abs() < 0.02, "{}", cv);
+        let fano = fano_factor(&train, 1.0, (0.0, 2000.0)).unwrap();
+        assert!((fano - 1.0).abs() < 0.12, "{}", fano);
+        let (violations, fraction) = refractory_violations(&train, DEFAULT_REFRACTORY_PERIOD);
+        let expected = 1.0 - (-50.0f64 * 0.002).exp();
+        assert!((fraction - expected).abs() < 0.004, "{} vs {}", fraction, expected);
+        assert_eq!(violations as f64 / n, fraction);
+
+        // The interval density is exponential, 50 exp(-50 t)
+        let histogram = isi_histogram(&train, 0.002, 0.1).unwrap();
+        assert_eq!(histogram.counts.len(), 50);
+        for (center, density) in histogram.bin_centers().iter().zip(histogram.density()).take(20) {
+            let expected = 50.0 * (-50.0 * center).exp() / (1.0 - (-50.0f64 * 0.1).exp());
+            assert!((density / expected - 1.0).abs() < 0.1, "{}: {} vs {}", center, density, expected);
+        }
+
+        // A clock-like train is perfectly regular
+        let regular: Vec<f64> = (0..1000).map(|k| 0.005 + k as f64 * 0.01).collect();
+        assert!(cv_isi(&regular) < 1e-9);
+        assert!(fano_factor(&regular, 0.1, (0.0, 10.0)).unwrap() < 1e-12);
+        assert_eq!(refractory_violations(&regular, DEFAULT_REFRACTORY_PERIOD).0, 0);
+    }
+}
@@ -0,0 +1,546 @@
+// A module to detect spikes in extracellular recordings and extract their waveforms
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::decomposition::{pca, PcaResult};
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+use crate::processing::timing::median;
+
+/// The ratio between the median absolute value and the standard deviation of Gaussian noise
+const NOISE_MEDIAN_RATIO: f64 = 0.6745;
+
+/// The detection threshold of `detect`
+///
+/// # Arguments
+///
+/// * `Absolute` - A threshold in the units of the signal
+/// * `NoiseMultiple` - A multiple of the robust noise estimate `median(|x|) / 0.6745`
+///
+/// # Examples
+///
+/// ```
+/// let threshold = Threshold::NoiseMultiple(4.5);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Threshold {
+    Absolute(f64),
+    NoiseMultiple(f64),
+}
+
+/// The direction of the threshold crossings detected as spikes
+///
+/// # Arguments
+///
+/// * `Negative` - Samples below minus the threshold, as for most extracellular spikes
+/// * `Positive` - Samples above the threshold
+/// * `Both` - Samples whose absolute value exceeds the threshold
+///
+/// # Examples
+///
+/// ```
+/// let options = SpikeDetectionOptions { polarity: Polarity::Both, ..SpikeDetectionOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Polarity {
+    Negative,
+    Positive,
+    Both,
+}
+
+/// Options of `detect`
+///
+/// # Arguments
+///
+/// * `threshold` - The detection threshold, 5 times the noise estimate by default
+/// * `polarity` - The crossing direction, negative by default
+/// * `dead_time` - The time in seconds after a spike peak during which crossings are merged into it, 1 ms by default
+/// * `pre_samples` - The number of samples before the peak in each waveform, 10 by default
+/// * `post_samples` - The number of samples after the peak in each waveform, 21 by default
+///
+/// # Examples
+///
+/// ```
+/// let options = SpikeDetectionOptions { threshold: Threshold::Absolute(-60e-6), ..SpikeDetectionOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpikeDetectionOptions {
+    pub threshold: Threshold,
+    pub polarity: Polarity,
+    pub dead_time: f64,
+    pub pre_samples: usize,
+    pub post_samples: usize,
+}
+
+impl Default for SpikeDetectionOptions {
+    fn default() -> Self {
+        Self {
+            threshold: Threshold::NoiseMultiple(5.0),
+            polarity: Polarity::Negative,
+            dead_time: 1e-3,
+            pre_samples: 10,
+            post_samples: 21,
+        }
+    }
+}
+
+/// The spikes detected in a signal
+///
+/// # Arguments
+///
+/// * `times` - The time of each spike peak in seconds
+/// * `indices` - The sample index of each spike peak
+/// * `waveforms` - The peak-aligned snippet of each spike, `pre_samples + 1 + post_samples` samples long
+/// * `pre_samples` - The number of samples before the peak in each waveform
+/// * `threshold` - The absolute threshold that was applied
+/// * `noise` - The robust noise estimate of the signal
+/// * `dropped_at_edges` - The number of spikes dropped for lack of a full snippet
+///
+/// # Examples
+///
+/// ```
+/// let result = detect(&filtered, 30000.0, 0.0, &SpikeDetectionOptions::default())?;
+/// println!("{} spikes, {} dropped at the edges", result.times.len(), result.dropped_at_edges);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SpikeDetectionResult {
+    pub times: Vec<f64>,
+    pub indices: Vec<usize>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub waveforms: Vec<Vec<f64>>,
+    pub pre_samples: usize,
+    pub threshold: f64,
+    pub noise: f64,
+    pub dropped_at_edges: usize,
+}
+
+/// Implementation of the SpikeDetectionResult struct
+///
+/// # Methods
+///
+/// * `len` - Returns the number of spikes
+/// * `is_empty` - Returns whether no spike was detected
+/// * `times_to_csv` - Writes the spike times as `time` rows
+/// * `waveforms_to_csv` - Writes one row per spike with its time and waveform
+/// * `pca_features` - Computes the principal component scores of the waveforms
+impl SpikeDetectionResult {
+    /// Returns the number of spikes
+    ///
+    /// # Returns
+    ///
+    /// The number of detected spikes with a full waveform
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let rate = result.len() as f64 / duration;
+    /// ```
+    ///
+    pub fn len(&self) -> usize {
+        self.times.len()
+    }
+
+    /// Returns whether no spike was detected
+    ///
+    /// # Returns
+    ///
+    /// True if the result holds no spike
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// if result.is_empty() { println!("Silent channel"); }
+    /// ```
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.times.is_empty()
+    }
+
+    /// Writes the spike times as `time` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// result.times_to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn times_to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["time"]))?;
+        for time in &self.times {
+            csv_io.write_record(StringRecord::from(vec![float_format.format(*time)]))?;
+        }
+        Ok(())
+    }
+
+    /// Writes one row per spike with its time and waveform
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// result.waveforms_to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The header is `time` followed by the sample offsets relative to the peak, e.g.
+    /// `-10,...,0,...,21`. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn waveforms_to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        let length = self.waveforms.first().map_or(self.pre_samples + 1, |waveform| waveform.len());
+        let mut header = vec!["time".to_string()];
+        header.extend((0..length).map(|k| (k as i64 - self.pre_samples as i64).to_string()));
+        csv_io.write_record(StringRecord::from(header))?;
+        for (time, waveform) in self.times.iter().zip(&self.waveforms) {
+            let mut row = vec![float_format.format(*time)];
+            row.extend(waveform.iter().map(|sample| float_format.format(*sample)));
+            csv_io.write_record(StringRecord::from(row))?;
+        }
+        Ok(())
+    }
+
+    /// Computes the principal component scores of the waveforms
+    ///
+    /// # Arguments
+    ///
+    /// * `n_components` - The number of components to keep
+    ///
+    /// # Returns
+    ///
+    /// The PcaResult whose `scores` hold one feature row per spike, or an error if no spike
+    /// was detected or more components than waveform samples are requested
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let features = result.pca_features(3)?;
+    /// features.to_csv(&mut csv_io)?;
+    /// ```
+    ///
+    pub fn pca_features(&self, n_components: usize) -> Result<PcaResult, ProcessingError> {
+        pca(&self.waveforms, n_components)
+    }
+}
+
+/// Estimates the noise level of a signal robustly
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal, usually highpass filtered
+///
+/// # Returns
+///
+/// `median(|x|) / 0.6745`, the standard deviation of Gaussian noise that is not inflated by
+/// the spikes themselves, or NaN if the signal has no finite sample
+///
+/// # Examples
+///
+/// ```
+/// let sigma = noise_estimate(&filtered);
+/// ```
+///
+/// # Note
+///
+/// See Quiroga, Nadasdy and Ben-Shaul (2004), Neural Computation 16(8)
+///
+pub fn noise_estimate(samples: &[f64]) -> f64 {
+    let magnitudes: Vec<f64> = samples.iter().map(|sample| sample.abs()).collect();
+    median(&magnitudes) / NOISE_MEDIAN_RATIO
+}
+
+/// Detects spikes by threshold crossing and extracts their waveforms
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal, usually bandpass filtered around 300-6000 Hz
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample in seconds
+/// * `options` - The threshold, polarity, dead time and waveform length
+///
+/// # Returns
+///
+/// The detected spikes, or an error if the sampling rate, threshold or dead time is invalid
+///
+/// # Examples
+///
+/// ```
+/// let filtered = butterworth(4, FilterKind::Bandpass(300.0, 6000.0), 30000.0)?.filtfilt(&raw)?;
+/// let result = detect(&filtered, 30000.0, 0.0, &SpikeDetectionOptions::default())?;
+/// ```
+///
+/// # Note
+///
+/// When a sample crosses the threshold, the spike peak is the extreme sample within the dead
+/// time after the crossing, and crossings within the dead time after the peak belong to the
+/// same spike. Waveforms are aligned on the peak, and spikes too close to the edges for a
+/// full snippet are dropped and counted in `dropped_at_edges`. The sign of an absolute
+/// threshold is ignored; the polarity decides the direction.
+///
+pub fn detect(samples: &[f64], sampling_rate: f64, start_time: f64, options: &SpikeDetectionOptions) -> Result<SpikeDetectionResult, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    if !(options.dead_time >= 0.0 && options.dead_time.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Dead time must be a non-negative number of seconds, got {}",
+            options.dead_time
+        )));
+    }
+    let noise = noise_estimate(samples);
+    let threshold = match options.threshold {
+        Threshold::Absolute(threshold) => threshold.abs(),
+        Threshold::NoiseMultiple(multiple) => multiple * noise,
+    };
+    if !(threshold > 0.0 && threshold.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Threshold must be a positive number, got {} (noise estimate {})",
+            threshold, noise
+        )));
+    }
+
+    // The signed amplitude in the direction of the polarity, NaN never exceeds the threshold
+    let amplitude = |sample: f64| match options.polarity {
+        Polarity::Negative => -sample,
+        Polarity::Positive => sample,
+        Polarity::Both => sample.abs(),
+    };
+    let dead_samples = ((options.dead_time * sampling_rate).round() as usize).max(1);
+    let n = samples.len();
+
+    let mut result = SpikeDetectionResult {
+        times: Vec::new(),
+        indices: Vec::new(),
+        waveforms: Vec::new(),
+        pre_samples: options.pre_samples,
+        threshold,
+        noise,
+        dropped_at_edges: 0,
+    };
+    let mut i = 0;
+    while i < n {
+        if amplitude(samples[i]).is_nan() || amplitude(samples[i]) <= threshold {
+            i += 1;
+            continue;
+        }
+        let search_end = (i + dead_samples).min(n);
+        let peak = (i..search_end)
+            .filter(|&k| !samples[k].is_nan())
+            .max_by(|&a, &b| amplitude(samples[a]).total_cmp(&amplitude(samples[b])))
+            .unwrap_or(i);
+
+        if peak >= options.pre_samples && peak + options.post_samples < n {
+            result.indices.push(peak);
+            result.times.push(start_time + peak as f64 / sampling_rate);
+            result.waveforms.push(samples[peak - options.pre_samples..=peak + options.post_samples].to_vec());
+        } else {
+            result.dropped_at_edges += 1;
+        }
+        i = peak + dead_samples;
+    }
+    Ok(result)
+}
+
+/// Splits spike times into one train per unit
+///
+/// # Arguments
+///
+/// * `times` - The time of each spike
+/// * `labels` - The unit of each spike, e.g. the labels of `kmeans`
+///
+/// # Returns
+///
+/// The spike times of each unit, indexed by label and in their original order, or an error
+/// if the times and labels differ in length
+///
+/// # Examples
+///
+/// ```
+/// let sorted = kmeans(&detection.pca_features(3)?.scores, 3, &KMeansOptions::default())?;
+/// let units = split_by_labels(&detection.times, &sorted.labels)?;
+/// ```
+///
+pub fn split_by_labels(times: &[f64], labels: &[usize]) -> Result<Vec<Vec<f64>>, ProcessingError> {
+    if times.len() != labels.len() {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Got {} spike times but {} labels",
+            times.len(),
+            labels.len()
+        )));
+    }
+    let n_units = labels.iter().max().map_or(0, |label| label + 1);
+    let mut units = vec![Vec::new(); n_units];
+    for (&time, &label) in times.iter().zip(labels) {
+        units[label].push(time);
+    }
+    Ok(units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::spike_train::SpikeTrain;
+    use crate::processing::random::SeededRng;
+
+    const SAMPLING_RATE: f64 = 30000.0;
+
+    /// A biphasic extracellular spike whose negative peak of `-amplitude` is at offset 0, with a
+    /// trough of 1.5 samples (50 us) standard deviation
+    fn spike_shape(offset: f64, amplitude: f64) -> f64 {
+        -amplitude * (-offset * offset / 4.5).exp() + 0.3 * amplitude * (-(offset - 10.0).powi(2) / 50.0).exp()
+    }
+
+    /// Unit-variance noise with spikes injected at known, at least 3 ms apart, sample indices
+    fn spikes_in_noise(n: usize, amplitude: f64, seed: u64) -> (Vec<f64>, Vec<usize>) {
+        let mut rng = SeededRng::new(seed);
+        let mut samples: Vec<f64> = (0..n).map(|_| rng.next_gaussian()).collect();
+        let mut indices = Vec::new();
+        let mut next = 200 + rng.next_index(100);
+        while next + 200 < n {
+            indices.push(next);
+            next += 600 + rng.next_index(900);
+        }
+        for &index in &indices {
+            for offset in -20i64..=40 {
+                samples[(index as i64 + offset) as usize] += spike_shape(offset as f64, amplitude);
+            }
+        }
+        (samples, indices)
+    }
+
+    #[test]
+    fn injected_spikes_are_recovered_within_one_sample_at_several_snrs() {
+        for (amplitude, seed) in [(10.0, 1), (15.0, 2), (25.0, 3)] {
+            let (samples, injected) = spikes_in_noise(3 * SAMPLING_RATE as usize, amplitude, seed);
+            let result = detect(&samples, SAMPLING_RATE, 0.0, &SpikeDetectionOptions::default()).unwrap();
+            assert_eq!(result.len(), injected.len(), "SNR {}", amplitude);
+            for (found, expected) in result.indices.iter().zip(&injected) {
+                assert!(found.abs_diff(*expected) <= 1, "SNR {}: {} vs {}", amplitude, found, expected);
+            }
+            assert!(result.times.iter().zip(&result.indices).all(|(time, index)| *time == *index as f64 / SAMPLING_RATE));
+            // At about 30 Hz the spikes barely move the median-based estimate off the unit noise
+            assert!((result.noise - 1.0).abs() < 0.05, "SNR {}: noise {}", amplitude, result.noise);
+            assert_eq!(result.threshold, 5.0 * result.noise);
+            assert_eq!(result.dropped_at_edges, 0);
+        }
+        // Below the threshold nothing is found
+        let (samples, _) = spikes_in_noise(3 * SAMPLING_RATE as usize, 3.0, 4);
+        assert!(detect(&samples, SAMPLING_RATE, 0.0, &SpikeDetectionOptions::default()).unwrap().len() < 3);
+    }
+
+    #[test]
+    fn waveforms_are_peak_aligned_and_spikes_at_the_edges_are_dropped() {
+        let mut samples = vec![0.0; 1000];
+        for (index, amplitude) in [(5, 10.0), (300, 10.0), (600, 14.0), (990, 10.0)] {
+            for offset in -20i64..=40 {
+                let position = index as i64 + offset;
+                if (0..1000).contains(&position) {
+                    samples[position as usize] += spike_shape(offset as f64, amplitude);
+                }
+            }
+        }
+        let options = SpikeDetectionOptions { threshold: Threshold::Absolute(-5.0), ..SpikeDetectionOptions::default() };
+        let result = detect(&samples, SAMPLING_RATE, 2.0, &options).unwrap();
+        assert_eq!(result.indices, vec![300, 600]);
+        assert_eq!(result.dropped_at_edges, 2);
+        assert_eq!(result.times, vec![2.0 + 300.0 / SAMPLING_RATE, 2.0 + 600.0 / SAMPLING_RATE]);
+        for (waveform, &index) in result.waveforms.iter().zip(&result.indices) {
+            assert_eq!(waveform.len(), 32);
+            assert_eq!(waveform.as_slice(), &samples[index - 10..=index + 21]);
+            assert_eq!(waveform.iter().copied().fold(f64::INFINITY, f64::min), waveform[result.pre_samples]);
+        }
+
+        let train = SpikeTrain::from_detection(&result, 2.0, 2.0 + 1000.0 / SAMPLING_RATE).unwrap();
+        assert_eq!(train.times(), result.times.as_slice());
+        assert_eq!(train.waveforms().unwrap(), result.waveforms.as_slice());
+    }
+
+    #[test]
+    fn crossings_within_the_dead_time_are_one_spike_and_polarity_selects_the_direction() {
+        // Two troughs 0.5 ms apart, then a positive spike
+        let mut samples = vec![0.0; 3000];
+        samples[1000] = -8.0;
+        samples[1015] = -9.0;
+        samples[2000] = 12.0;
+        let detect_with = |polarity: Polarity, dead_time: f64| {
+            let options = SpikeDetectionOptions { threshold: Threshold::Absolute(5.0), polarity, dead_time, ..SpikeDetectionOptions::default() };
+            detect(&samples, SAMPLING_RATE, 0.0, &options).unwrap().indices
+        };
+        // The peak is the extreme sample within the dead time after the crossing
+        assert_eq!(detect_with(Polarity::Negative, 1e-3), vec![1015]);
+        assert_eq!(detect_with(Polarity::Negative, 0.2e-3), vec![1000, 1015]);
+        assert_eq!(detect_with(Polarity::Positive, 1e-3), vec![2000]);
+        assert_eq!(detect_with(Polarity::Both, 1e-3), vec![1015, 2000]);
+    }
+
+    #[test]
+    fn results_export_to_csv_and_invalid_options_are_errors() {
+        let (samples, _) = spikes_in_noise(30000, 15.0, 5);
+        let result = detect(&samples, SAMPLING_RATE, 0.0, &SpikeDetectionOptions::default()).unwrap();
+        let path = std::env::temp_dir().join(format!("neurorust-spikes-{}.csv", std::process::id())).to_string_lossy().into_owned();
+        for waveforms in [false, true] {
+            std::fs::write(&path, "").unwrap();
+            let mut csv_io = CsvIO::open_write(&path).unwrap();
+            if waveforms { result.waveforms_to_csv(&mut csv_io) } else { result.times_to_csv(&mut csv_io) }.unwrap();
+            csv_io.save().unwrap();
+            let text = std::fs::read_to_string(&path).unwrap();
+            let lines: Vec<&str> = text.lines().collect();
+            assert_eq!(lines.len(), 1 + result.len());
+            if waveforms {
+                assert!(lines[0].starts_with("time,-10,-9,") && lines[0].ends_with(",0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21"));
+                assert_eq!(lines[1].split(',').count(), 33);
+            } else {
+                assert_eq!(lines[0], "time");
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+
+        let invalid = |options: SpikeDetectionOptions| detect(&samples, SAMPLING_RATE, 0.0, &options).is_err();
+        assert!(invalid(SpikeDetectionOptions { dead_time: -1e-3, ..SpikeDetectionOptions::default() }));
+        assert!(invalid(SpikeDetectionOptions { threshold: Threshold::Absolute(0.0), ..SpikeDetectionOptions::default() }));
+        assert!(detect(&[0.0; 100], SAMPLING_RATE, 0.0, &SpikeDetectionOptions::default()).is_err());
+        assert!(detect(&samples, 0.0, 0.0, &SpikeDetectionOptions::default()).is_err());
+    }
+
+    #[test]
+    fn noise_is_estimated_robustly_and_times_split_by_label() {
+        let mut rng = SeededRng::new(6);
+        let mut noise: Vec<f64> = (0..100_000).map(|_| 3.0 * rng.next_gaussian()).collect();
+        assert!((noise_estimate(&noise) / 3.0 - 1.0).abs() < 0.02);
+        // Large spikes on 2% of the samples barely move it
+        noise.iter_mut().step_by(50).for_each(|sample| *sample -= 100.0);
+        assert!((noise_estimate(&noise) / 3.0 - 1.0).abs() < 0.05);
+
+        let units = split_by_labels(&[0.1, 0.2, 0.3, 0.4], &[1, 0, 1, 2]).unwrap();
+        assert_eq!(units, vec![vec![0.2], vec![0.1, 0.3], vec![0.4]]);
+        assert!(split_by_labels(&[0.1], &[]).is_err());
+        assert!(split_by_labels(&[], &[]).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,453 @@
+// A module to compute event-triggered averages of continuous signals, e.g. spike-triggered averages
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::evoked::EvokedResponse;
+use crate::processing::filter::validate_sampling_rate;
+use crate::processing::random::SeededRng;
+
+/// The surrogate events used to estimate the chance-level average
+///
+/// # Arguments
+///
+/// * `Jitter` - Every event is moved by a uniform offset within plus or minus this many seconds
+/// * `ShuffleIntervals` - The intervals between successive events are shuffled, keeping the first event and the rate
+///
+/// # Examples
+///
+/// ```
+/// let options = TriggeredOptions { control: Some(ChanceControl::Jitter(0.5)), ..TriggeredOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ChanceControl {
+    Jitter(f64),
+    ShuffleIntervals,
+}
+
+/// The options of `compute`
+///
+/// # Arguments
+///
+/// * `artifacts` - The start and end times in seconds of the stretches to avoid, e.g. from `merge_spans`
+/// * `control` - The surrogate events of the chance-level average, or None to skip it
+/// * `n_controls` - The number of surrogate event sets averaged together
+/// * `seed` - The seed of the surrogate events
+///
+/// # Examples
+///
+/// ```
+/// let options = TriggeredOptions { artifacts: merge_spans(&spans, 0.05), ..TriggeredOptions::default() };
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TriggeredOptions {
+    pub artifacts: Vec<(f64, f64)>,
+    pub control: Option<ChanceControl>,
+    pub n_controls: usize,
+    pub seed: u64,
+}
+
+impl Default for TriggeredOptions {
+    fn default() -> Self {
+        Self { artifacts: Vec::new(), control: None, n_controls: 20, seed: 0 }
+    }
+}
+
+/// The average of a continuous signal around many events
+///
+/// # Arguments
+///
+/// * `response` - The mean, standard deviation and standard error at each time relative to the events
+/// * `n_events` - The number of events averaged
+/// * `dropped_edge` - The number of events dropped because their window extends past the signal
+/// * `dropped_artifact` - The number of events dropped because their window overlaps an artifact
+/// * `chance` - The average around the surrogate events, if a control was requested
+///
+/// # Examples
+///
+/// ```
+/// let sta = compute(&[stimulus], &["stimulus".to_string()], 1000.0, 0.0, &spike_times, (-0.3, 0.05), &TriggeredOptions::default())?;
+/// println!("{} spikes used, {} at the edges", sta.n_events, sta.dropped_edge);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TriggeredAverage {
+    pub response: EvokedResponse,
+    pub n_events: usize,
+    pub dropped_edge: usize,
+    pub dropped_artifact: usize,
+    pub chance: Option<EvokedResponse>,
+}
+
+/// Implementation of the TriggeredAverage struct
+///
+/// # Methods
+///
+/// * `to_csv` - Writes the average as `time,channel,mean,std,sem,n,chance_mean,chance_sem` rows
+impl TriggeredAverage {
+    /// Writes the average as `time,channel,mean,std,sem,n,chance_mean,chance_sem` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// sta.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first, then one row per time and channel, ordered by time.
+    /// The chance columns are empty without a control.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["time", "channel", "mean", "std", "sem", "n", "chance_mean", "chance_sem"]))?;
+        let response = &self.response;
+        for (t, time) in response.times.iter().enumerate() {
+            for (c, name) in response.names.iter().enumerate() {
+                let (chance_mean, chance_sem) = match &self.chance {
+                    Some(chance) => (float_format.format(chance.mean[c][t]), float_format.format(chance.sem[c][t])),
+                    None => (String::new(), String::new()),
+                };
+                csv_io.write_record(StringRecord::from(vec![
+                    float_format.format(*time),
+                    name.clone(),
+                    float_format.format(response.mean[c][t]),
+                    float_format.format(response.std[c][t]),
+                    float_format.format(response.sem[c][t]),
+                    response.n[c][t].to_string(),
+                    chance_mean,
+                    chance_sem,
+                ]))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Computes the average of a continuous signal around each event
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel; pass a single channel for one signal
+/// * `names` - The name of each channel
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample in seconds
+/// * `events` - The event (e.g. spike) times in seconds, in any order
+/// * `window` - The start and end of the segment in seconds relative to each event, e.g. `(-0.3, 0.05)`
+/// * `options` - The artifacts to avoid and the chance-level control
+///
+/// # Returns
+///
+/// The TriggeredAverage, or an error if the sampling rate, the window, an event time or the
+/// control is invalid, the channels and names differ in number, or the channels differ in length
+///
+/// # Examples
+///
+/// ```
+/// let options = TriggeredOptions { control: Some(ChanceControl::Jitter(1.0)), seed: 3, ..TriggeredOptions::default() };
+/// let sta = compute(&lfp, &names, 1000.0, 0.0, &spike_times, (-0.1, 0.1), &options)?;
+/// sta.to_csv(&mut csv_io)?;
+/// ```
+///
+/// # Note
+///
+/// The segments are never stored: running means and variances are updated in place, so
+/// the memory used depends on the window and not on the number of events. The segment
+/// starts at the sample nearest to each event plus the window start and spans the window
+/// length rounded to samples. An event overlapping an artifact, touching ends excepted,
+/// is dropped; one also past an edge counts as an edge drop. The surrogate events go
+/// through the same drops, and the `n_controls` sets are averaged into one response, which
+/// is the same for the same seed.
+///
+pub fn compute(
+    channels: &[Vec<f64>],
+    names: &[String],
+    sampling_rate: f64,
+    start_time: f64,
+    events: &[f64],
+    window: (f64, f64),
+    options: &TriggeredOptions,
+) -> Result<TriggeredAverage, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    if channels.len() != names.len() {
+        return Err(ProcessingError::InvalidParameter(format!("Got {} channels but {} names", channels.len(), names.len())));
+    }
+    let n_samples = channels.first().map_or(0, |channel| channel.len());
+    if let Some(index) = channels.iter().position(|channel| channel.len() != n_samples) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Channel {} has {} samples but channel 0 has {}",
+            index,
+            channels[index].len(),
+            n_samples
+        )));
+    }
+    let length = ((window.1 - window.0) * sampling_rate).round();
+    if !(window.0.is_finite() && window.1.is_finite() && length >= 1.0) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "The window {:?} must be finite and span at least one sample",
+            window
+        )));
+    }
+    if let Some(event) = events.iter().find(|event| !event.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!("Event time {} is not finite", event)));
+    }
+    match options.control {
+        Some(ChanceControl::Jitter(width)) if !(width.is_finite() && width > 0.0) => {
+            return Err(ProcessingError::InvalidParameter(format!("The jitter width must be positive, got {}", width)))
+        }
+        Some(_) if options.n_controls == 0 => {
+            return Err(ProcessingError::InvalidParameter("At least one control set is needed".to_string()))
+        }
+        _ => {}
+    }
+
+    let mut artifacts: Vec<(f64, f64)> = options.artifacts.iter().copied().filter(|(start, end)| start < end).collect();
+    artifacts.sort_by(|a, b| a.0.total_cmp(&b.0));
+    let mut merged: Vec<(f64, f64)> = Vec::with_capacity(artifacts.len());
+    for (start, end) in artifacts {
+        match merged.last_mut() {
+            Some(last) if start < last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    let segments = Segments {
+        n_samples,
+        sampling_rate,
+        start_time,
+        offset: (window.0 * sampling_rate).round() as i64,
+        length: length as usize,
+        artifacts: merged,
+    };
+
+    let mut accumulator = Accumulator::new(channels.len(), segments.length);
+    let (dropped_edge, dropped_artifact) = accumulator.add_all(channels, &segments, events);
+    let n_events = events.len() - dropped_edge - dropped_artifact;
+    let chance = options.control.map(|control| {
+        let mut rng = SeededRng::new(options.seed);
+        let mut chance = Accumulator::new(channels.len(), segments.length);
+        let mut sorted = events.to_vec();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        for _ in 0..options.n_controls {
+            let surrogate = surrogate_events(&sorted, control, &mut rng);
+            chance.add_all(channels, &segments, &surrogate);
+        }
+        chance.finish(names, &segments)
+    });
+    Ok(TriggeredAverage {
+        response: accumulator.finish(names, &segments),
+        n_events,
+        dropped_edge,
+        dropped_artifact,
+        chance,
+    })
+}
+
+/// Where the segments of the events are in the signal
+struct Segments {
+    n_samples: usize,
+    sampling_rate: f64,
+    start_time: f64,
+    offset: i64,
+    length: usize,
+    /// Sorted, disjoint artifact stretches, for a binary search per event
+    artifacts: Vec<(f64, f64)>,
+}
+
+/// The running means and sums of squared deviations of the segments, by Welford's method
+struct Accumulator {
+    mean: Vec<Vec<f64>>,
+    m2: Vec<Vec<f64>>,
+    n: Vec<Vec<usize>>,
+}
+
+impl Accumulator {
+    fn new(n_channels: usize, length: usize) -> Self {
+        Self { mean: vec![vec![0.0; length]; n_channels], m2: vec![vec![0.0; length]; n_channels], n: vec![vec![0; length]; n_channels] }
+    }
+
+    /// Adds the segment of every event that fits, and returns the numbers dropped at the edges and for artifacts
+    fn add_all(&mut self, channels: &[Vec<f64>], segments: &Segments, events: &[f64]) -> (usize, usize) {
+        let (mut dropped_edge, mut dropped_artifact) = (0, 0);
+        for event in events {
+            let first = ((event - segments.start_time) * segments.sampling_rate).round() as i64 + segments.offset;
+            let end = first + segments.length as i64;
+            if first < 0 || end > segments.n_samples as i64 {
+                dropped_edge += 1;
+                continue;
+            }
+            let segment_start = segments.start_time + first as f64 / segments.sampling_rate;
+            let segment_end = segments.start_time + end as f64 / segments.sampling_rate;
+            let next = segments.artifacts.partition_point(|&(_, artifact_end)| artifact_end <= segment_start);
+            if segments.artifacts.get(next).is_some_and(|&(artifact_start, _)| artifact_start < segment_end) {
+                dropped_artifact += 1;
+                continue;
+            }
+            let first = first as usize;
+            for (c, channel) in channels.iter().enumerate() {
+                let (mean, m2, n) = (&mut self.mean[c], &mut self.m2[c], &mut self.n[c]);
+                for (t, &value) in channel[first..first + segments.length].iter().enumerate() {
+                    if !value.is_finite() {
+                        continue;
+                    }
+                    n[t] += 1;
+                    let delta = value - mean[t];
+                    mean[t] += delta / n[t] as f64;
+                    m2[t] += delta * (value - mean[t]);
+                }
+            }
+        }
+        (dropped_edge, dropped_artifact)
+    }
+
+    fn finish(self, names: &[String], segments: &Segments) -> EvokedResponse {
+        let statistic = |f: &dyn Fn(f64, f64) -> f64| -> Vec<Vec<f64>> {
+            self.m2
+                .iter()
+                .zip(&self.n)
+                .map(|(m2, n)| m2.iter().zip(n).map(|(&m2, &n)| if n > 1 { f(m2 / (n as f64 - 1.0), n as f64) } else { f64::NAN }).collect())
+                .collect()
+        };
+        let std = statistic(&|variance, _| variance.sqrt());
+        let sem = statistic(&|variance, count| (variance / count).sqrt());
+        let mean = self
+            .mean
+            .iter()
+            .zip(&self.n)
+            .map(|(mean, n)| mean.iter().zip(n).map(|(&mean, &n)| if n > 0 { mean } else { f64::NAN }).collect())
+            .collect();
+        let times = (0..segments.length).map(|t| (segments.offset + t as i64) as f64 / segments.sampling_rate).collect();
+        EvokedResponse { times, names: names.to_vec(), mean, std, sem, n: self.n }
+    }
+}
+
+/// Draws one set of surrogate events from the sorted events
+fn surrogate_events(sorted: &[f64], control: ChanceControl, rng: &mut SeededRng) -> Vec<f64> {
+    match control {
+        ChanceControl::Jitter(width) => sorted.iter().map(|event| event + width * (2.0 * rng.next_f64() - 1.0)).collect(),
+        ChanceControl::ShuffleIntervals => {
+            let mut intervals: Vec<f64> = sorted.windows(2).map(|pair| pair[1] - pair[0]).collect();
+            for i in (1..intervals.len()).rev() {
+                intervals.swap(i, rng.next_index(i + 1));
+            }
+            let mut time = sorted.first().copied().unwrap_or(0.0);
+            std::iter::once(time)
+                .take(sorted.len())
+                .chain(intervals.into_iter().map(|interval| {
+                    time += interval;
+                    time
+                }))
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    const SAMPLING_RATE: f64 = 1000.0;
+
+    /// 200 s of uniform noise with a 40 ms sine cycle of amplitude 3 starting 10 ms before
+    /// every event, and events before, at and past the edges
+    fn fixture() -> (Vec<f64>, Vec<f64>, Vec<f64>) {
+        let mut rng = SeededRng::new(99);
+        let mut signal: Vec<f64> = (0..200_000).map(|_| 2.0 * (rng.next_f64() - 0.5)).collect();
+        let waveform: Vec<f64> = (0..40).map(|i| 3.0 * (2.0 * PI * i as f64 / 40.0).sin()).collect();
+        let mut events = Vec::new();
+        let mut time = 0.5;
+        while time < 199.0 {
+            events.push(time);
+            time += 0.05 + 0.1 * rng.next_f64();
+        }
+        for &event in &events {
+            let first = (event * SAMPLING_RATE).round() as usize - 10;
+            signal[first..first + 40].iter_mut().zip(&waveform).for_each(|(sample, value)| *sample += value);
+        }
+        events.extend([-1.0, 0.005, 199.99]);
+        (signal, events, waveform)
+    }
+
+    fn largest_difference(a: &[f64], b: &[f64]) -> f64 {
+        a.iter().zip(b).map(|(a, b)| (a - b).abs()).fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn recovers_the_waveform_inserted_at_every_event() {
+        let (signal, events, waveform) = fixture();
+        let names = vec!["lfp".to_string()];
+        let options = TriggeredOptions { control: Some(ChanceControl::Jitter(0.5)), n_controls: 10, seed: 5, ..TriggeredOptions::default() };
+        let sta = compute(&[signal], &names, SAMPLING_RATE, 0.0, &events, (-0.01, 0.03), &options).unwrap();
+        assert_eq!(sta.dropped_edge, 3);
+        assert_eq!(sta.n_events, events.len() - 3);
+        assert_eq!(sta.response.times.len(), 40);
+        assert!(sta.response.times[10].abs() < 1e-12);
+        assert!(sta.response.n[0].iter().all(|&n| n == sta.n_events));
+        // The noise has a standard deviation of 0.58, averaged over about 2000 events
+        assert!(largest_difference(&sta.response.mean[0], &waveform) < 0.1);
+        let chance = sta.chance.unwrap();
+        assert!(chance.mean[0].iter().all(|value| value.abs() < 0.1), "{:?}", chance.mean[0]);
+    }
+
+    #[test]
+    fn events_overlapping_artifacts_are_dropped_and_counted() {
+        let (signal, events, _) = fixture();
+        let window = (-0.01, 0.03);
+        let options = TriggeredOptions { artifacts: vec![(50.0, 60.0)], ..TriggeredOptions::default() };
+        let sta = compute(&[signal], &["lfp".to_string()], SAMPLING_RATE, 0.0, &events, window, &options).unwrap();
+        let overlapping = events[..events.len() - 3].iter().filter(|&&event| event + window.1 > 50.0 && event + window.0 < 60.0).count();
+        assert!(overlapping > 50);
+        assert_eq!(sta.dropped_artifact, overlapping);
+        assert_eq!(sta.n_events + sta.dropped_artifact + sta.dropped_edge, events.len());
+    }
+
+    #[test]
+    fn the_average_equals_the_mean_of_the_segments() {
+        let channels = vec![(0..100).map(|i| (i * i % 17) as f64).collect::<Vec<f64>>(), (0..100).map(|i| i as f64).collect()];
+        let names = vec!["a".to_string(), "b".to_string()];
+        let events = [0.02, 0.05, 0.031];
+        let sta = compute(&channels, &names, 100.0, 0.0, &events, (-0.01, 0.02), &TriggeredOptions::default()).unwrap();
+        for (channel, samples) in channels.iter().enumerate() {
+            for t in 0..3 {
+                let mean = [1, 4, 2].iter().map(|&first| samples[first + t]).sum::<f64>() / 3.0;
+                assert!((sta.response.mean[channel][t] - mean).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn shuffled_controls_are_reproducible_and_export_to_csv() {
+        let (signal, events, _) = fixture();
+        let names = vec!["lfp".to_string()];
+        let options = TriggeredOptions { control: Some(ChanceControl::ShuffleIntervals), n_controls: 3, seed: 8, ..TriggeredOptions::default() };
+        let sta = compute(std::slice::from_ref(&signal), &names, SAMPLING_RATE, 0.0, &events, (-0.01, 0.03), &options).unwrap();
+        assert_eq!(sta, compute(&[signal], &names, SAMPLING_RATE, 0.0, &events, (-0.01, 0.03), &options).unwrap());
+
+        let path = std::env::temp_dir().join(format!("neurorust-sta-{}.csv", std::process::id())).to_string_lossy().into_owned();
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(&path).unwrap();
+        sta.to_csv(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0], "time,channel,mean,std,sem,n,chance_mean,chance_sem");
+        assert_eq!(lines.len(), 41);
+        assert!(lines[11].starts_with("0,lfp,"));
+        assert_eq!(lines[11].split(',').nth(5), Some(sta.n_events.to_string().as_str()));
+    }
+}
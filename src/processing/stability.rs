@@ -0,0 +1,604 @@
+// A module to diagnose drift and non-stationarity over long recordings
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::detrend::{fit_trend, DetrendMethod};
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::{butterworth, map_channels, validate_sampling_rate, FilterKind};
+use crate::processing::spikes::noise_estimate;
+
+/// The RMS, offset and noise floor of each window of a channel
+type WindowMetrics = (Vec<f64>, Vec<f64>, Vec<f64>);
+
+/// Options of `assess`
+///
+/// # Arguments
+///
+/// * `max_rms_change` - The largest change of the fitted RMS trend over the session, as a fraction of the mean RMS, 0.5 by default
+/// * `max_offset_drift` - The largest change of the fitted baseline trend over the session, as a multiple of the mean RMS, 1.0 by default
+/// * `max_noise_change` - The largest change of the fitted noise floor trend over the session, as a fraction of the mean noise floor, 0.5 by default
+/// * `noise_band` - The band in Hz of the zero-phase Butterworth bandpass filter applied before estimating the noise floor, none by default
+/// * `filter_order` - The order of the noise band filter, 4 by default
+///
+/// # Examples
+///
+/// ```
+/// let options = StabilityOptions { noise_band: Some((300.0, 3000.0)), ..StabilityOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StabilityOptions {
+    pub max_rms_change: f64,
+    pub max_offset_drift: f64,
+    pub max_noise_change: f64,
+    pub noise_band: Option<(f64, f64)>,
+    pub filter_order: usize,
+}
+
+impl Default for StabilityOptions {
+    fn default() -> Self {
+        Self { max_rms_change: 0.5, max_offset_drift: 1.0, max_noise_change: 0.5, noise_band: None, filter_order: 4 }
+    }
+}
+
+/// The stability metrics of one channel
+///
+/// # Arguments
+///
+/// * `name` - The name of the channel
+/// * `rms` - The RMS about the mean of each window
+/// * `offset` - The mean of each window
+/// * `noise` - The noise floor of each window, `median(|x|) / 0.6745` of the band-limited samples
+/// * `rms_change` - The change of the fitted linear RMS trend from the first to the last window, as a fraction of the mean RMS
+/// * `offset_drift` - The change of the fitted linear baseline trend, as a multiple of the mean RMS
+/// * `noise_change` - The change of the fitted linear noise floor trend, as a fraction of the mean noise floor
+/// * `passed` - Whether every change is within its threshold
+///
+/// # Examples
+///
+/// ```
+/// let report = assess(&channels, &names, 30000.0, 60.0, &StabilityOptions::default())?;
+/// let drifting: Vec<&str> = report.channels.iter().filter(|channel| !channel.passed).map(|channel| channel.name.as_str()).collect();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ChannelStability {
+    pub name: String,
+    pub rms: Vec<f64>,
+    pub offset: Vec<f64>,
+    pub noise: Vec<f64>,
+    pub rms_change: f64,
+    pub offset_drift: f64,
+    pub noise_change: f64,
+    pub passed: bool,
+}
+
+/// The stability metrics of every channel of a recording
+///
+/// # Arguments
+///
+/// * `times` - The center of each window in seconds, relative to the first sample
+/// * `channels` - The metrics of each channel
+///
+/// # Examples
+///
+/// ```
+/// let report = assess(&channels, &names, 1000.0, 60.0, &StabilityOptions::default())?;
+/// report.summary_to_csv(&mut csv_io)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StabilityReport {
+    pub times: Vec<f64>,
+    pub channels: Vec<ChannelStability>,
+}
+
+/// Implementation of the StabilityReport struct
+///
+/// # Methods
+///
+/// * `passed` - Returns whether every channel passed
+/// * `to_csv` - Writes the metrics as `time,channel,rms,offset,noise` rows
+/// * `summary_to_csv` - Writes one `channel,rms_change,offset_drift,noise_change,passed` row per channel
+impl StabilityReport {
+    /// Returns whether every channel passed
+    ///
+    /// # Returns
+    ///
+    /// True if no channel exceeds a threshold
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// if !report.passed() { println!("Check the electrodes"); }
+    /// ```
+    ///
+    pub fn passed(&self) -> bool {
+        self.channels.iter().all(|channel| channel.passed)
+    }
+
+    /// Writes the metrics as `time,channel,rms,offset,noise` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// report.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first, then one row per window and channel, ordered by time.
+    /// The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["time", "channel", "rms", "offset", "noise"]))?;
+        for (w, time) in self.times.iter().enumerate() {
+            for channel in &self.channels {
+                csv_io.write_record(StringRecord::from(vec![
+                    float_format.format(*time),
+                    channel.name.clone(),
+                    float_format.format(channel.rms[w]),
+                    float_format.format(channel.offset[w]),
+                    float_format.format(channel.noise[w]),
+                ]))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes one `channel,rms_change,offset_drift,noise_change,passed` row per channel
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// report.summary_to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn summary_to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["channel", "rms_change", "offset_drift", "noise_change", "passed"]))?;
+        for channel in &self.channels {
+            csv_io.write_record(StringRecord::from(vec![
+                channel.name.clone(),
+                float_format.format(channel.rms_change),
+                float_format.format(channel.offset_drift),
+                float_format.format(channel.noise_change),
+                channel.passed.to_string(),
+            ]))?;
+        }
+        Ok(())
+    }
+}
+
+/// Options of `rate_stability`
+///
+/// # Arguments
+///
+/// * `max_rate_change` - The largest change of the fitted firing rate trend over the session, as a fraction of the mean rate, 0.5 by default
+/// * `max_silence` - The longest time without spikes, as a fraction of the session, 0.2 by default
+///
+/// # Examples
+///
+/// ```
+/// let options = RateStabilityOptions { max_silence: 0.1, ..RateStabilityOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct RateStabilityOptions {
+    pub max_rate_change: f64,
+    pub max_silence: f64,
+}
+
+impl Default for RateStabilityOptions {
+    fn default() -> Self {
+        Self { max_rate_change: 0.5, max_silence: 0.2 }
+    }
+}
+
+/// The firing rate stability of one unit
+///
+/// # Arguments
+///
+/// * `name` - The name of the unit
+/// * `rates` - The firing rate of each window in spikes per second
+/// * `rate_change` - The change of the fitted linear rate trend from the first to the last window, as a fraction of the mean rate
+/// * `longest_silence` - The longest time without spikes, including before the first and after the last spike, as a fraction of the session
+/// * `passed` - Whether the rate change and the longest silence are within their thresholds
+///
+/// # Examples
+///
+/// ```
+/// let units = rate_stability(&trains, &names, 0.0, 7200.0, 300.0, &RateStabilityOptions::default())?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct UnitStability {
+    pub name: String,
+    pub rates: Vec<f64>,
+    pub rate_change: f64,
+    pub longest_silence: f64,
+    pub passed: bool,
+}
+
+/// Writes one `unit,rate_change,longest_silence,passed` row per unit
+///
+/// # Arguments
+///
+/// * `units` - The stability of each unit, e.g. from `rate_stability`
+/// * `csv_io` - The CsvIO object to write to
+///
+/// # Returns
+///
+/// Nothing, or an error if a row cannot be written
+///
+/// # Examples
+///
+/// ```
+/// units_to_csv(&units, &mut csv_io)?;
+/// csv_io.save()?;
+/// ```
+///
+/// # Note
+///
+/// A header row is written first. The rows are not flushed to disk until `save` is called.
+///
+pub fn units_to_csv(units: &[UnitStability], csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+    let float_format = csv_io.float_format();
+    csv_io.write_record(StringRecord::from(vec!["unit", "rate_change", "longest_silence", "passed"]))?;
+    for unit in units {
+        csv_io.write_record(StringRecord::from(vec![
+            unit.name.clone(),
+            float_format.format(unit.rate_change),
+            float_format.format(unit.longest_silence),
+            unit.passed.to_string(),
+        ]))?;
+    }
+    Ok(())
+}
+
+/// Assesses the stability of the channels of a recording over consecutive windows
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel
+/// * `names` - The name of each channel
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `window` - The length of each window in seconds, rounded to a whole number of samples
+/// * `options` - The thresholds and the noise band
+///
+/// # Returns
+///
+/// The StabilityReport, or an error if the channels and names differ in number or length,
+/// fewer than two windows fit in the recording, or the noise band is invalid
+///
+/// # Examples
+///
+/// ```
+/// let report = assess(&channels, &names, 30000.0, 60.0, &StabilityOptions { noise_band: Some((300.0, 3000.0)), ..StabilityOptions::default() })?;
+/// if !report.passed() { report.summary_to_csv(&mut csv_io)?; }
+/// ```
+///
+/// # Note
+///
+/// The windows tile the recording from its start and a final partial window is left out.
+/// Changes are read from a least-squares line through the window metrics, so single
+/// outlying windows, e.g. from an artifact, move them little. A channel whose mean RMS or
+/// noise floor is zero has a NaN change, which does not fail the channel. The channels are
+/// processed in parallel.
+///
+pub fn assess(channels: &[Vec<f64>], names: &[String], sampling_rate: f64, window: f64, options: &StabilityOptions) -> Result<StabilityReport, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    let length = channels.first().map_or(0, |channel| channel.len());
+    if channels.len() != names.len() || channels.iter().any(|channel| channel.len() != length) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Expected {} channels of equal length",
+            names.len()
+        )));
+    }
+    let window_len = (window * sampling_rate).round();
+    if !(window_len >= 1.0 && window_len.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!("Window must be at least one sample long, got {} s", window)));
+    }
+    let window_len = window_len as usize;
+    let n_windows = length / window_len;
+    if n_windows < 2 {
+        return Err(ProcessingError::SignalTooShort { length, required: 2 * window_len });
+    }
+    let filter = match options.noise_band {
+        Some((f_low, f_high)) => Some(butterworth(options.filter_order, FilterKind::Bandpass(f_low, f_high), sampling_rate)?),
+        None => None,
+    };
+
+    let metrics = map_channels(channels, |channel| -> Result<WindowMetrics, ProcessingError> {
+        let band = match &filter {
+            Some(filter) => Some(filter.filtfilt(channel)?),
+            None => None,
+        };
+        let (mut rms, mut offset, mut noise) = (Vec::with_capacity(n_windows), Vec::with_capacity(n_windows), Vec::with_capacity(n_windows));
+        for w in 0..n_windows {
+            let samples = &channel[w * window_len..(w + 1) * window_len];
+            let mean = samples.iter().sum::<f64>() / window_len as f64;
+            let centered: Vec<f64> = samples.iter().map(|sample| sample - mean).collect();
+            rms.push((centered.iter().map(|x| x * x).sum::<f64>() / window_len as f64).sqrt());
+            offset.push(mean);
+            noise.push(match &band {
+                Some(band) => noise_estimate(&band[w * window_len..(w + 1) * window_len]),
+                None => noise_estimate(&centered),
+            });
+        }
+        Ok((rms, offset, noise))
+    });
+
+    let mut stabilities = Vec::with_capacity(channels.len());
+    for (name, metric) in names.iter().zip(metrics) {
+        let (rms, offset, noise) = metric?;
+        let mean_rms = rms.iter().sum::<f64>() / n_windows as f64;
+        let mean_noise = noise.iter().sum::<f64>() / n_windows as f64;
+        let rms_change = trend_change(&rms)? / mean_rms;
+        let offset_drift = trend_change(&offset)? / mean_rms;
+        let noise_change = trend_change(&noise)? / mean_noise;
+        let passed = within(rms_change, options.max_rms_change)
+            && within(offset_drift, options.max_offset_drift)
+            && within(noise_change, options.max_noise_change);
+        stabilities.push(ChannelStability { name: name.clone(), rms, offset, noise, rms_change, offset_drift, noise_change, passed });
+    }
+    let times = (0..n_windows).map(|w| (w as f64 + 0.5) * window_len as f64 / sampling_rate).collect();
+    Ok(StabilityReport { times, channels: stabilities })
+}
+
+/// Assesses the stability of the firing rates of several units over consecutive windows
+///
+/// # Arguments
+///
+/// * `trains` - The spike times of each unit in seconds, in any order
+/// * `names` - The name of each unit
+/// * `t_start` - The start of the session in seconds
+/// * `t_stop` - The end of the session in seconds
+/// * `window` - The width of each counting window in seconds
+/// * `options` - The thresholds
+///
+/// # Returns
+///
+/// The UnitStability of each unit, or an error if the trains and names differ in number
+/// or fewer than two windows fit in the session
+///
+/// # Examples
+///
+/// ```
+/// let units = rate_stability(&trains, &names, 0.0, 7200.0, 300.0, &RateStabilityOptions::default())?;
+/// units_to_csv(&units, &mut csv_io)?;
+/// ```
+///
+/// # Note
+///
+/// The windows tile the session from its start and a final partial window is left out.
+/// A unit that stops firing fails on its silence even if its mean rate is low, and a
+/// unit without spikes has a NaN rate change and always fails.
+///
+pub fn rate_stability(
+    trains: &[Vec<f64>],
+    names: &[String],
+    t_start: f64,
+    t_stop: f64,
+    window: f64,
+    options: &RateStabilityOptions,
+) -> Result<Vec<UnitStability>, ProcessingError> {
+    if trains.len() != names.len() {
+        return Err(ProcessingError::InvalidParameter(format!("Got {} trains but {} names", trains.len(), names.len())));
+    }
+    if !(window > 0.0 && window.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!("Window must be positive, got {}", window)));
+    }
+    // Tolerate rounding so that e.g. 600 s hold exactly 6000 windows of 0.1 s
+    let n_windows = ((t_stop - t_start) / window + 1e-9).floor().max(0.0) as usize;
+    if n_windows < 2 {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "At least two windows of {} s must fit within {} to {}",
+            window, t_start, t_stop
+        )));
+    }
+    let duration = t_stop - t_start;
+
+    let mut units = Vec::with_capacity(trains.len());
+    for (train, name) in trains.iter().zip(names) {
+        let mut spikes: Vec<f64> = train.iter().copied().filter(|&time| time >= t_start && time <= t_stop).collect();
+        spikes.sort_by(|a, b| a.total_cmp(b));
+        let mut rates = vec![0.0; n_windows];
+        for &time in &spikes {
+            let position = ((time - t_start) / window) as usize;
+            if position < n_windows {
+                rates[position] += 1.0 / window;
+            }
+        }
+        let mean_rate = rates.iter().sum::<f64>() / n_windows as f64;
+        let rate_change = trend_change(&rates)? / mean_rate;
+        let edges = std::iter::once(t_start).chain(spikes.iter().copied()).chain(std::iter::once(t_stop));
+        let longest_silence = edges.clone().zip(edges.skip(1)).fold(0.0, |longest: f64, (a, b)| longest.max(b - a)) / duration;
+        let passed = !spikes.is_empty() && within(rate_change, options.max_rate_change) && longest_silence <= options.max_silence;
+        units.push(UnitStability { name: name.clone(), rates, rate_change, longest_silence, passed });
+    }
+    Ok(units)
+}
+
+/// The change of the least-squares line through the values from the first to the last value
+fn trend_change(values: &[f64]) -> Result<f64, ProcessingError> {
+    let trend = fit_trend(values, DetrendMethod::Linear)?;
+    Ok(trend[trend.len() - 1] - trend[0])
+}
+
+/// Whether a relative change is within a threshold, treating NaN as within
+fn within(change: f64, threshold: f64) -> bool {
+    change.is_nan() || change.abs() <= threshold
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    const SAMPLING_RATE: f64 = 500.0;
+    const DURATION: f64 = 240.0;
+
+    fn names(count: usize) -> Vec<String> {
+        (0..count).map(|k| format!("ch{}", k)).collect()
+    }
+
+    /// A stable channel, one whose amplitude triples, one whose baseline drifts by 3 RMS, and one
+    /// whose broadband noise triples under a 2 Hz sine that dominates its RMS
+    fn fixture() -> Vec<Vec<f64>> {
+        let mut rng = SeededRng::new(1);
+        let n = (DURATION * SAMPLING_RATE) as usize;
+        let mut channels: Vec<Vec<f64>> = (0..4).map(|_| Vec::with_capacity(n)).collect();
+        for k in 0..n {
+            let t = k as f64 / SAMPLING_RATE;
+            let ramp = t / DURATION;
+            channels[0].push(rng.next_gaussian());
+            channels[1].push(rng.next_gaussian() * (1.0 + 2.0 * ramp));
+            channels[2].push(rng.next_gaussian() + 3.0 * ramp);
+            channels[3].push(5.0 * (2.0 * std::f64::consts::PI * 2.0 * t).sin() + 0.3 * rng.next_gaussian() * (1.0 + 2.0 * ramp));
+        }
+        channels
+    }
+
+    #[test]
+    fn an_amplitude_ramp_and_a_baseline_drift_fail_while_the_stable_channel_passes() {
+        let report = assess(&fixture(), &names(4), SAMPLING_RATE, 10.0, &StabilityOptions::default()).unwrap();
+        assert_eq!(report.times.len(), 24);
+        assert_eq!((report.times[0], report.times[23]), (5.0, 235.0));
+        let passed: Vec<bool> = report.channels.iter().map(|channel| channel.passed).collect();
+        // Without a noise band the sine hides the growing noise of the last channel
+        assert_eq!(passed, vec![true, false, false, true]);
+        assert!(!report.passed());
+
+        let [stable, ramped, drifting, _] = &report.channels[..] else { unreachable!() };
+        assert!(stable.rms_change.abs() < 0.05 && stable.offset_drift.abs() < 0.1 && stable.noise_change.abs() < 0.05);
+        // The RMS grows from 1 to 3 about a mean of 2, so the fitted line rises by close to 23/24 of the mean
+        assert!((ramped.rms_change - 23.0 / 24.0).abs() < 0.05, "{}", ramped.rms_change);
+        assert!(ramped.offset_drift.abs() < 0.1);
+        assert!((drifting.offset_drift - 3.0 * 23.0 / 24.0).abs() < 0.1, "{}", drifting.offset_drift);
+        assert!(drifting.rms_change.abs() < 0.05);
+        assert!(stable.rms.iter().all(|rms| (rms - 1.0).abs() < 0.05));
+    }
+
+    #[test]
+    fn a_noise_band_exposes_noise_growing_under_a_large_slow_signal() {
+        let channels = fixture();
+        let options = StabilityOptions { noise_band: Some((20.0, 200.0)), ..StabilityOptions::default() };
+        let report = assess(&channels, &names(4), SAMPLING_RATE, 10.0, &options).unwrap();
+        let sine = &report.channels[3];
+        assert!(!sine.passed);
+        assert!(sine.rms_change.abs() < 0.05, "{}", sine.rms_change);
+        assert!(sine.noise_change > 0.8, "{}", sine.noise_change);
+        assert!(report.channels[0].passed);
+    }
+
+    #[test]
+    fn window_metrics_match_hand_values() {
+        // Windows of 4 samples: [1, 3, 1, 3] and [2, 2, 2, 2]
+        let channels = vec![vec![1.0, 3.0, 1.0, 3.0, 2.0, 2.0, 2.0, 2.0, 9.0], vec![0.0; 9]];
+        let report = assess(&channels, &names(2), 1.0, 4.0, &StabilityOptions::default()).unwrap();
+        let steady = &report.channels[0];
+        assert_eq!((steady.rms.clone(), steady.offset.clone()), (vec![1.0, 0.0], vec![2.0, 2.0]));
+        assert!((steady.noise[0] - 1.0 / 0.6745).abs() < 1e-12);
+        assert!((steady.rms_change + 2.0).abs() < 1e-12);
+        assert_eq!(steady.offset_drift, 0.0);
+        assert!(!steady.passed);
+        // A flat channel has no RMS to compare with, so its changes are NaN and it passes
+        let flat = &report.channels[1];
+        assert!(flat.rms_change.is_nan() && flat.offset_drift.is_nan() && flat.noise_change.is_nan());
+        assert!(flat.passed);
+
+        assert!(assess(&channels, &names(1), 1.0, 4.0, &StabilityOptions::default()).is_err());
+        assert!(assess(&channels, &names(2), 1.0, 5.0, &StabilityOptions::default()).is_err());
+        assert!(assess(&channels, &names(2), 1.0, 0.1, &StabilityOptions::default()).is_err());
+        assert!(assess(&channels, &names(2), 0.0, 4.0, &StabilityOptions::default()).is_err());
+    }
+
+    #[test]
+    fn units_that_stop_firing_or_drift_fail_their_rate_stability() {
+        let mut rng = SeededRng::new(2);
+        let poisson = |rng: &mut SeededRng, rate: &dyn Fn(f64) -> f64, end: f64| -> Vec<f64> {
+            // Thinning of a 20 Hz process
+            let (mut time, mut spikes) = (0.0, Vec::new());
+            loop {
+                time -= (1.0 - rng.next_f64()).ln() / 20.0;
+                if time >= end {
+                    return spikes;
+                }
+                if rng.next_f64() < rate(time) / 20.0 {
+                    spikes.push(time);
+                }
+            }
+        };
+        let trains = vec![
+            poisson(&mut rng, &|_| 10.0, 600.0),
+            poisson(&mut rng, &|_| 10.0, 300.0),
+            poisson(&mut rng, &|time| 5.0 + 10.0 * time / 600.0, 600.0),
+            Vec::new(),
+        ];
+        let units = rate_stability(&trains, &names(4), 0.0, 600.0, 30.0, &RateStabilityOptions::default()).unwrap();
+        assert_eq!(units.iter().map(|unit| unit.passed).collect::<Vec<_>>(), vec![true, false, false, false]);
+        let [stable, stopped, ramped, silent] = &units[..] else { unreachable!() };
+        assert_eq!(stable.rates.len(), 20);
+        assert!(stable.rate_change.abs() < 0.1 && stable.longest_silence < 0.01);
+        assert!(stopped.rate_change < -1.0 && (stopped.longest_silence - 0.5).abs() < 0.01);
+        assert!((ramped.rate_change - 0.95).abs() < 0.15 && ramped.longest_silence < 0.01, "{}", ramped.rate_change);
+        assert!(silent.rate_change.is_nan() && silent.longest_silence == 1.0);
+
+        // Rounding of the session length does not drop the last window
+        assert_eq!(rate_stability(&[vec![0.05]], &names(1), 0.0, 0.3, 0.1, &RateStabilityOptions::default()).unwrap()[0].rates.len(), 3);
+        assert!(rate_stability(&trains, &names(3), 0.0, 600.0, 30.0, &RateStabilityOptions::default()).is_err());
+        assert!(rate_stability(&trains, &names(4), 0.0, 50.0, 30.0, &RateStabilityOptions::default()).is_err());
+        assert!(rate_stability(&trains, &names(4), 0.0, 600.0, 0.0, &RateStabilityOptions::default()).is_err());
+    }
+
+    #[test]
+    fn reports_export_to_csv() {
+        let channels = vec![vec![1.0, 3.0, 1.0, 3.0, 2.0, 2.0, 2.0, 2.0]];
+        let report = assess(&channels, &names(1), 1.0, 4.0, &StabilityOptions::default()).unwrap();
+        let units = vec![UnitStability { name: "u1".to_string(), rates: vec![1.0, 2.0], rate_change: 0.25, longest_silence: 0.5, passed: false }];
+        let path = std::env::temp_dir().join(format!("neurorust-stability-{}-report.csv", std::process::id()));
+        let mut lines = Vec::new();
+        for write in [0, 1, 2] {
+            std::fs::write(&path, "").unwrap();
+            let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+            match write {
+                0 => report.to_csv(&mut csv_io).unwrap(),
+                1 => report.summary_to_csv(&mut csv_io).unwrap(),
+                _ => units_to_csv(&units, &mut csv_io).unwrap(),
+            }
+            csv_io.save().unwrap();
+            lines.push(std::fs::read_to_string(&path).unwrap().lines().map(str::to_string).collect::<Vec<_>>());
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(lines[0], vec!["time,channel,rms,offset,noise", "2,ch0,1,2,1.4825796886582654", "6,ch0,0,2,0"]);
+        assert_eq!(lines[1], vec!["channel,rms_change,offset_drift,noise_change,passed", "ch0,-2,0,-2,false"]);
+        assert_eq!(lines[2], vec!["unit,rate_change,longest_silence,passed", "u1,0.25,0.5,false"]);
+    }
+}
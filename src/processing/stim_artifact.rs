@@ -0,0 +1,560 @@
+// A module to remove electrical stimulation artifacts by blanking or template subtraction
+
+// Written by Amin Alam in 2024
+
+use crate::processing::artifacts::{ArtifactKind, ArtifactSpan};
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::{map_channels, validate_sampling_rate};
+use crate::processing::linalg::least_squares;
+
+/// The values written over the samples blanked by `blank`
+///
+/// # Arguments
+///
+/// * `Nan` - NaN, to mark the samples as missing
+/// * `Zero` - 0
+/// * `Linear` - A straight line between the samples just before and just after the blanked samples
+///
+/// # Examples
+///
+/// ```
+/// let repair = blank(&channels, 30000.0, 0.0, &stim_times, (-0.0002, 0.0015), BlankFill::Linear)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum BlankFill {
+    Nan,
+    Zero,
+    Linear,
+}
+
+/// Options of `template_subtract`
+///
+/// # Arguments
+///
+/// * `n_clusters` - The number of templates, for events grouped by the peak-to-peak amplitude of their artifact, 1 by default
+/// * `fit_scale` - Fits the amplitude of the template to every event if true, by default, or subtracts it unscaled
+/// * `fit_offset` - Fits a constant offset alongside the template, so slow activity under the artifact does not bias the scale, true by default
+///
+/// # Examples
+///
+/// ```
+/// // Two stimulation intensities in the same session
+/// let options = TemplateOptions { n_clusters: 2, ..TemplateOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TemplateOptions {
+    pub n_clusters: usize,
+    pub fit_scale: bool,
+    pub fit_offset: bool,
+}
+
+impl Default for TemplateOptions {
+    fn default() -> Self {
+        Self { n_clusters: 1, fit_scale: true, fit_offset: true }
+    }
+}
+
+/// The repaired channels and what was repaired
+///
+/// # Arguments
+///
+/// * `channels` - The repaired samples of each channel
+/// * `spans` - One `Stimulation` span per repaired event and channel, sorted by start time
+/// * `overlapping` - The indices of the events whose window overlaps the window of another event
+/// * `skipped` - The indices of the events left unrepaired by `template_subtract` because their window runs past an end of the signal
+/// * `scales` - The fitted scale of the template of every event on every channel, `[channel][event]`, NaN for skipped events; empty for `blank`
+/// * `clusters` - The index of the template of every event on every channel, `[channel][event]`; empty for `blank`
+///
+/// # Examples
+///
+/// ```
+/// let repair = template_subtract(&channels, 30000.0, 0.0, &stim_times, (-0.0002, 0.004), &TemplateOptions::default())?;
+/// let rejection = reject_epochs(&repair.spans, &epoch_windows);
+/// ```
+///
+/// # Note
+///
+/// The spans can be passed with the detected artifacts to `reject_epochs` or `merge_spans`,
+/// so that epochs overlapping a repaired stretch can be handled downstream
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StimRepair {
+    pub channels: Vec<Vec<f64>>,
+    pub spans: Vec<ArtifactSpan>,
+    pub overlapping: Vec<usize>,
+    pub skipped: Vec<usize>,
+    pub scales: Vec<Vec<f64>>,
+    pub clusters: Vec<Vec<usize>>,
+}
+
+/// Replaces a window around every stimulation event
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel; pass a single channel for one signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample in seconds
+/// * `events` - The times of the stimulation events in seconds
+/// * `window` - The start and end of the window relative to each event in seconds, e.g. `(-0.0002, 0.0015)`
+/// * `fill` - The values written over the window
+///
+/// # Returns
+///
+/// The StimRepair, or an error if the sampling rate, the window or an event time is invalid
+///
+/// # Examples
+///
+/// ```
+/// let repair = blank(&channels, 30000.0, 0.0, &stim_times, (-0.0002, 0.0015), BlankFill::Nan)?;
+/// ```
+///
+/// # Note
+///
+/// Every window is `round((end - start) * sampling_rate)` samples long, starting at the
+/// sample nearest to the event plus `round(start * sampling_rate)`, and is only shortened
+/// where it runs past an end of the signal. Overlapping windows are blanked as one
+/// stretch, so the linear fill runs from before the first to after the last of them; at
+/// an end of the signal the one neighbouring sample is held.
+///
+pub fn blank(channels: &[Vec<f64>], sampling_rate: f64, start_time: f64, events: &[f64], window: (f64, f64), fill: BlankFill) -> Result<StimRepair, ProcessingError> {
+    let windows = event_windows(sampling_rate, start_time, events, window)?;
+    let n_samples = channels.first().map_or(0, Vec::len);
+    check_lengths(channels, n_samples)?;
+    let clipped: Vec<(usize, usize)> = windows.iter().map(|&(first, end)| clip(first, end, n_samples)).collect();
+
+    // Blank the union of the windows, so overlapping ones are filled as one stretch
+    let mut stretches: Vec<(usize, usize)> = clipped.iter().copied().filter(|(first, end)| first < end).collect();
+    stretches.sort_unstable();
+    let mut merged: Vec<(usize, usize)> = Vec::with_capacity(stretches.len());
+    for (first, end) in stretches {
+        match merged.last_mut() {
+            Some(last) if first <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((first, end)),
+        }
+    }
+    let repaired = map_channels(channels, |samples| {
+        let mut samples = samples.to_vec();
+        for &(first, end) in &merged {
+            let before = first.checked_sub(1).map(|index| samples[index]);
+            let after = samples.get(end).copied();
+            for (offset, sample) in samples[first..end].iter_mut().enumerate() {
+                *sample = match (fill, before, after) {
+                    (BlankFill::Nan, _, _) => f64::NAN,
+                    (BlankFill::Zero, _, _) => 0.0,
+                    (BlankFill::Linear, Some(a), Some(b)) => a + (b - a) * (offset + 1) as f64 / (end + 1 - first) as f64,
+                    (BlankFill::Linear, Some(held), None) | (BlankFill::Linear, None, Some(held)) => held,
+                    (BlankFill::Linear, None, None) => f64::NAN,
+                };
+            }
+        }
+        samples
+    });
+    let repaired_events: Vec<usize> = (0..events.len()).filter(|&event| clipped[event].0 < clipped[event].1).collect();
+    Ok(StimRepair {
+        spans: spans(channels.len(), sampling_rate, start_time, &clipped, &repaired_events),
+        channels: repaired,
+        overlapping: overlapping(&windows),
+        ..StimRepair::default()
+    })
+}
+
+/// Subtracts the average artifact from the window around every stimulation event
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel; pass a single channel for one signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample in seconds
+/// * `events` - The times of the stimulation events in seconds
+/// * `window` - The start and end of the window relative to each event in seconds, long enough to hold the whole artifact
+/// * `options` - The number of templates and what is fitted to each event
+///
+/// # Returns
+///
+/// The StimRepair, or an error if the sampling rate, the window, an event time or the
+/// number of clusters is invalid, or fewer events than clusters fit in the signal
+///
+/// # Examples
+///
+/// ```
+/// let repair = template_subtract(&channels, 30000.0, 0.0, &stim_times, (-0.0002, 0.004), &TemplateOptions::default())?;
+/// let residual_spans = detect(&repair.channels, 30000.0, 0.0, &[ArtifactCriterion::Amplitude(500e-6)])?;
+/// ```
+///
+/// # Note
+///
+/// On every channel the events are ranked by the peak-to-peak amplitude within their
+/// window and split into `n_clusters` groups of equal size, and the template of a group
+/// is the average of its windows, using only the events that overlap no other event when
+/// there are any. Every event then gets the least-squares scale of its template, with an
+/// offset if `fit_offset` is true, and the scaled template is subtracted. Events whose
+/// windows overlap are fitted jointly, with one scale per event over their combined
+/// stretch, and are listed in `overlapping`. Events whose window runs past an end of the
+/// signal are left unrepaired and listed in `skipped`. The windows are those of `blank`.
+///
+pub fn template_subtract(
+    channels: &[Vec<f64>],
+    sampling_rate: f64,
+    start_time: f64,
+    events: &[f64],
+    window: (f64, f64),
+    options: &TemplateOptions,
+) -> Result<StimRepair, ProcessingError> {
+    let windows = event_windows(sampling_rate, start_time, events, window)?;
+    let n_samples = channels.first().map_or(0, Vec::len);
+    check_lengths(channels, n_samples)?;
+    let inside: Vec<usize> = (0..events.len()).filter(|&event| windows[event].0 >= 0 && windows[event].1 <= n_samples as i64).collect();
+    if options.n_clusters == 0 || (!channels.is_empty() && inside.len() < options.n_clusters) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Need at least one cluster and one event per cluster, got {} clusters and {} events within the signal",
+            options.n_clusters,
+            inside.len()
+        )));
+    }
+    let overlapping = overlapping(&windows);
+    let bounds: Vec<(usize, usize)> = windows.iter().map(|&(first, end)| clip(first, end, n_samples)).collect();
+
+    // Events whose windows overlap are fitted together
+    let mut groups: Vec<Vec<usize>> = Vec::new();
+    let mut sorted = inside.clone();
+    sorted.sort_by_key(|&event| bounds[event].0);
+    for event in sorted {
+        match groups.last_mut() {
+            Some(group) if group.iter().any(|&other| bounds[other].1 > bounds[event].0) => group.push(event),
+            _ => groups.push(vec![event]),
+        }
+    }
+
+    let length = windows.first().map_or(0, |&(first, end)| (end - first) as usize);
+    let results = map_channels(channels, |samples| {
+        let segment = |event: usize| &samples[bounds[event].0..bounds[event].1];
+
+        // Rank the events by artifact amplitude and split them into equal clusters
+        let mut ranked = inside.clone();
+        let peak_to_peak = |event: usize| {
+            let (min, max) = segment(event).iter().fold((f64::INFINITY, f64::NEG_INFINITY), |(min, max), &x| (min.min(x), max.max(x)));
+            max - min
+        };
+        ranked.sort_by(|&a, &b| peak_to_peak(a).total_cmp(&peak_to_peak(b)));
+        let mut clusters = vec![0; events.len()];
+        for (rank, &event) in ranked.iter().enumerate() {
+            clusters[event] = rank * options.n_clusters / ranked.len();
+        }
+
+        let templates: Vec<Vec<f64>> = (0..options.n_clusters)
+            .map(|cluster| {
+                let members: Vec<usize> = inside.iter().copied().filter(|&event| clusters[event] == cluster).collect();
+                let isolated: Vec<usize> = members.iter().copied().filter(|event| !overlapping.contains(event)).collect();
+                let used = if isolated.is_empty() { &members } else { &isolated };
+                let mut template = vec![0.0; length];
+                for &event in used {
+                    template.iter_mut().zip(segment(event)).for_each(|(sum, x)| *sum += x / used.len() as f64);
+                }
+                template
+            })
+            .collect();
+
+        let mut repaired = samples.to_vec();
+        let mut scales = vec![f64::NAN; events.len()];
+        for group in &groups {
+            let first = group.iter().map(|&event| bounds[event].0).min().unwrap_or(0);
+            let end = group.iter().map(|&event| bounds[event].1).max().unwrap_or(0);
+            let mut columns: Vec<Vec<f64>> = group
+                .iter()
+                .map(|&event| {
+                    let mut column = vec![0.0; end - first];
+                    column[bounds[event].0 - first..bounds[event].1 - first].copy_from_slice(&templates[clusters[event]]);
+                    column
+                })
+                .collect();
+            let fitted = match options.fit_scale {
+                true => {
+                    if options.fit_offset {
+                        columns.push(vec![1.0; end - first]);
+                    }
+                    least_squares(&columns, &samples[first..end])
+                }
+                false => None,
+            };
+            for (index, &event) in group.iter().enumerate() {
+                let scale = fitted.as_ref().map_or(1.0, |fitted| fitted[index]);
+                scales[event] = scale;
+                repaired[first..end].iter_mut().zip(&columns[index]).for_each(|(x, t)| *x -= scale * t);
+            }
+        }
+        (repaired, scales, clusters)
+    });
+
+    let mut repair = StimRepair {
+        spans: spans(channels.len(), sampling_rate, start_time, &bounds, &inside),
+        overlapping,
+        skipped: (0..events.len()).filter(|event| !inside.contains(event)).collect(),
+        ..StimRepair::default()
+    };
+    for (repaired, scales, clusters) in results {
+        repair.channels.push(repaired);
+        repair.scales.push(scales);
+        repair.clusters.push(clusters);
+    }
+    Ok(repair)
+}
+
+/// Checks the parameters and returns the first sample and the end sample of every window, unclipped
+fn event_windows(sampling_rate: f64, start_time: f64, events: &[f64], window: (f64, f64)) -> Result<Vec<(i64, i64)>, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    let length = ((window.1 - window.0) * sampling_rate).round();
+    if !(window.0.is_finite() && window.1.is_finite() && length >= 1.0) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "The window {:?} must be finite and span at least one sample",
+            window
+        )));
+    }
+    if let Some(event) = events.iter().find(|event| !event.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!("Event time {} is not finite", event)));
+    }
+    let offset = (window.0 * sampling_rate).round() as i64;
+    Ok(events
+        .iter()
+        .map(|event| {
+            let first = ((event - start_time) * sampling_rate).round() as i64 + offset;
+            (first, first + length as i64)
+        })
+        .collect())
+}
+
+fn check_lengths(channels: &[Vec<f64>], n_samples: usize) -> Result<(), ProcessingError> {
+    match channels.iter().position(|channel| channel.len() != n_samples) {
+        Some(index) => Err(ProcessingError::InvalidParameter(format!(
+            "Channel {} has {} samples but channel 0 has {}",
+            index,
+            channels[index].len(),
+            n_samples
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Clips a window to the samples of the signal
+fn clip(first: i64, end: i64, n_samples: usize) -> (usize, usize) {
+    let first = first.clamp(0, n_samples as i64) as usize;
+    (first, (end.clamp(0, n_samples as i64) as usize).max(first))
+}
+
+/// Returns the indices of the events whose window overlaps the window of another event
+fn overlapping(windows: &[(i64, i64)]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..windows.len()).collect();
+    order.sort_by_key(|&event| windows[event]);
+    let mut flagged = vec![false; windows.len()];
+    let mut furthest: Option<usize> = None;
+    for &event in &order {
+        if let Some(previous) = furthest {
+            if windows[previous].1 > windows[event].0 {
+                flagged[previous] = true;
+                flagged[event] = true;
+            }
+        }
+        if furthest.is_none_or(|previous| windows[event].1 > windows[previous].1) {
+            furthest = Some(event);
+        }
+    }
+    (0..windows.len()).filter(|&event| flagged[event]).collect()
+}
+
+/// Returns one span per repaired event and channel
+fn spans(n_channels: usize, sampling_rate: f64, start_time: f64, bounds: &[(usize, usize)], events: &[usize]) -> Vec<ArtifactSpan> {
+    let mut spans: Vec<ArtifactSpan> = events
+        .iter()
+        .flat_map(|&event| {
+            (0..n_channels).map(move |channel| ArtifactSpan {
+                start: start_time + bounds[event].0 as f64 / sampling_rate,
+                end: start_time + bounds[event].1 as f64 / sampling_rate,
+                kind: ArtifactKind::Stimulation,
+                channel,
+            })
+        })
+        .collect();
+    spans.sort_by(|a, b| a.start.total_cmp(&b.start).then(a.channel.cmp(&b.channel)));
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::artifacts::{merge_spans, reject_epochs};
+    use crate::processing::random::SeededRng;
+
+    const RATE: f64 = 1000.0;
+
+    /// A known background of two sines and a little noise, 20 s at 1 kHz
+    fn background(rng: &mut SeededRng) -> Vec<f64> {
+        (0..20_000)
+            .map(|index| {
+                let t = index as f64 / RATE;
+                (2.0 * std::f64::consts::PI * 3.0 * t).sin() + 0.5 * (2.0 * std::f64::consts::PI * 11.0 * t + 1.0).sin() + 0.05 * rng.next_gaussian()
+            })
+            .collect()
+    }
+
+    /// Event samples 400 to 600 ms apart from 0.5 s on
+    fn event_samples(rng: &mut SeededRng) -> Vec<usize> {
+        let mut events = vec![500];
+        while *events.last().unwrap() < 19_000 {
+            events.push(events.last().unwrap() + 400 + rng.next_index(200));
+        }
+        events
+    }
+
+    /// Adds an exponentially decaying artifact of the given amplitude and time constant at a sample
+    fn inject(samples: &mut [f64], at: usize, amplitude: f64, tau: f64) {
+        for (offset, sample) in samples[at..].iter_mut().take(60).enumerate() {
+            *sample += amplitude * (-(offset as f64) / (tau * RATE)).exp();
+        }
+    }
+
+    fn rms_in_windows(a: &[f64], b: &[f64], events: &[usize], length: usize) -> f64 {
+        let squares: Vec<f64> = events.iter().flat_map(|&at| (at..at + length).map(|index| (a[index] - b[index]).powi(2))).collect();
+        (squares.iter().sum::<f64>() / squares.len() as f64).sqrt()
+    }
+
+    fn times(events: &[usize]) -> Vec<f64> {
+        events.iter().map(|&at| at as f64 / RATE).collect()
+    }
+
+    #[test]
+    fn template_subtraction_recovers_the_background() {
+        let mut rng = SeededRng::new(178);
+        let clean = background(&mut rng);
+        let events = event_samples(&mut rng);
+        let mut contaminated = clean.clone();
+        for &at in &events {
+            inject(&mut contaminated, at, 100.0 * (0.8 + 0.4 * rng.next_f64()), 0.005);
+        }
+        let before = rms_in_windows(&contaminated, &clean, &events, 50);
+        let repair = template_subtract(&[contaminated.clone()], RATE, 0.0, &times(&events), (0.0, 0.05), &TemplateOptions::default()).unwrap();
+        let after = rms_in_windows(&repair.channels[0], &clean, &events, 50);
+        assert!(before > 20.0, "{}", before);
+        // The background is 0.79 RMS; what is left is mostly the background averaged into the template
+        assert!(after < 0.35, "residual {} against {} before", after, before);
+        assert!(repair.overlapping.is_empty() && repair.skipped.is_empty());
+        assert!(repair.scales[0].iter().all(|scale| (0.75..1.25).contains(scale)), "{:?}", repair.scales[0]);
+        // Outside the windows the signal is untouched
+        let outside = (0..clean.len()).filter(|index| !events.iter().any(|&at| (at..at + 50).contains(index)));
+        assert!(outside.into_iter().all(|index| repair.channels[0][index] == contaminated[index]));
+    }
+
+    #[test]
+    fn clusters_separate_artifacts_of_different_shapes() {
+        let mut rng = SeededRng::new(1780);
+        let clean = background(&mut rng);
+        let events = event_samples(&mut rng);
+        let mut contaminated = clean.clone();
+        for (index, &at) in events.iter().enumerate() {
+            match index % 2 {
+                0 => inject(&mut contaminated, at, 40.0 * (0.9 + 0.2 * rng.next_f64()), 0.003),
+                _ => inject(&mut contaminated, at, 200.0 * (0.9 + 0.2 * rng.next_f64()), 0.008),
+            }
+        }
+        let residual = |n_clusters: usize| {
+            let options = TemplateOptions { n_clusters, ..TemplateOptions::default() };
+            let repair = template_subtract(&[contaminated.clone()], RATE, 0.0, &times(&events), (0.0, 0.06), &options).unwrap();
+            (rms_in_windows(&repair.channels[0], &clean, &events, 60), repair.clusters[0].clone())
+        };
+        let (one, _) = residual(1);
+        let (two, clusters) = residual(2);
+        assert!(two < 0.45 && two * 5.0 < one, "one cluster {}, two clusters {}", one, two);
+        assert!(clusters.iter().enumerate().all(|(index, &cluster)| cluster == index % 2), "{:?}", clusters);
+    }
+
+    #[test]
+    fn overlapping_events_are_flagged_and_fitted_jointly() {
+        let mut rng = SeededRng::new(17800);
+        let clean = background(&mut rng);
+        let mut events = event_samples(&mut rng);
+        events.insert(11, events[10] + 20);
+        let mut contaminated = clean.clone();
+        for &at in &events {
+            inject(&mut contaminated, at, 100.0, 0.005);
+        }
+        let repair = template_subtract(&[contaminated.clone()], RATE, 0.0, &times(&events), (0.0, 0.05), &TemplateOptions::default()).unwrap();
+        assert_eq!(repair.overlapping, [10, 11]);
+        let pair = rms_in_windows(&repair.channels[0], &clean, &events[10..12], 50);
+        assert!(pair < 0.3, "{}", pair);
+        assert!((repair.scales[0][10] - 1.0).abs() < 0.05 && (repair.scales[0][11] - 1.0).abs() < 0.05, "{:?}", &repair.scales[0][9..13]);
+
+        // Without the joint fit the second artifact would sit on the tail of the first
+        let blanked = blank(&[contaminated], RATE, 0.0, &times(&events), (0.0, 0.05), BlankFill::Nan).unwrap();
+        assert_eq!(blanked.overlapping, [10, 11]);
+        assert_eq!(blanked.channels[0][events[10]..events[11] + 50].iter().filter(|x| x.is_nan()).count(), 70);
+    }
+
+    #[test]
+    fn events_without_a_full_window_are_skipped() {
+        let mut rng = SeededRng::new(3);
+        let clean = background(&mut rng);
+        let events = [1.0, 2.0, 3.0, 19.98, -0.01];
+        let repair = template_subtract(std::slice::from_ref(&clean), RATE, 0.0, &events, (0.0, 0.05), &TemplateOptions::default()).unwrap();
+        assert_eq!(repair.skipped, [3, 4]);
+        assert!(repair.scales[0][3].is_nan() && repair.scales[0][4].is_nan());
+        assert_eq!(repair.channels[0][19_980..], clean[19_980..]);
+        assert_eq!(repair.spans.len(), 3);
+    }
+
+    #[test]
+    fn blanked_spans_have_exactly_the_requested_width() {
+        let signal: Vec<f64> = (0..1000).map(|index| index as f64).collect();
+        let events = [0.1, 0.5004, 0.0, 0.995];
+        let window = (-0.002, 0.01);
+        for fill in [BlankFill::Nan, BlankFill::Zero, BlankFill::Linear] {
+            let repair = blank(&[signal.clone(), signal.clone()], RATE, 0.0, &events, window, fill).unwrap();
+            let changed: Vec<usize> = (0..1000).filter(|&index| repair.channels[1][index].to_bits() != signal[index].to_bits()).collect();
+            let expected: Vec<usize> = (0..10).chain(98..110).chain(498..510).chain(993..1000).collect();
+            match fill {
+                BlankFill::Nan => assert_eq!((0..1000).filter(|&index| repair.channels[0][index].is_nan()).collect::<Vec<_>>(), expected),
+                BlankFill::Zero => assert!(expected.iter().all(|&index| repair.channels[0][index] == 0.0)),
+                // A ramp is its own linear interpolation; the spans at the edges hold the nearest sample
+                BlankFill::Linear => {
+                    assert!(changed.iter().all(|&index| !(10..993).contains(&index)), "{:?}", changed);
+                    assert!((0..10).all(|index| repair.channels[0][index] == 10.0));
+                    assert!((993..1000).all(|index| repair.channels[0][index] == 992.0));
+                }
+            }
+            match fill {
+                BlankFill::Nan => assert_eq!(changed, expected),
+                // The first sample of the ramp is zero already
+                BlankFill::Zero => assert_eq!(changed, expected[1..]),
+                BlankFill::Linear => {}
+            }
+            let span_widths: Vec<(f64, f64)> = repair.spans.iter().filter(|span| span.channel == 0).map(|span| (span.start, span.end)).collect();
+            assert_eq!(span_widths, [(0.0, 0.01), (0.098, 0.11), (0.498, 0.51), (0.993, 1.0)]);
+            assert!(repair.spans.iter().all(|span| span.kind == ArtifactKind::Stimulation));
+        }
+
+        let wiggly: Vec<f64> = (0..100).map(|index| if index % 2 == 0 { 1.0 } else { -1.0 }).collect();
+        let repair = blank(&[wiggly], 100.0, 0.0, &[0.5], (0.0, 0.04), BlankFill::Linear).unwrap();
+        let expected = [-1.0, -0.6, -0.2, 0.2, 0.6, 1.0];
+        assert!(repair.channels[0][49..55].iter().zip(expected).all(|(x, y)| (x - y).abs() < 1e-12), "{:?}", &repair.channels[0][49..55]);
+    }
+
+    #[test]
+    fn repaired_spans_reject_the_epochs_they_touch() {
+        let signal = vec![0.0; 5000];
+        let repair = blank(&[signal], RATE, 10.0, &[11.0, 11.02, 13.0], (0.0, 0.05), BlankFill::Zero).unwrap();
+        assert_eq!(merge_spans(&repair.spans, 0.0), [(11.0, 11.07), (13.0, 13.05)]);
+        let rejection = reject_epochs(&repair.spans, &[(10.5, 10.9), (10.9, 11.1), (12.9, 13.2), (13.5, 14.0)]);
+        assert_eq!(rejection.rejected, [1, 2]);
+    }
+
+    #[test]
+    fn invalid_inputs_are_rejected() {
+        let channels = vec![vec![0.0; 1000]];
+        assert!(blank(&channels, 0.0, 0.0, &[0.5], (0.0, 0.05), BlankFill::Nan).is_err());
+        assert!(blank(&channels, RATE, 0.0, &[0.5], (0.0, 0.0), BlankFill::Nan).is_err());
+        assert!(blank(&channels, RATE, 0.0, &[f64::NAN], (0.0, 0.05), BlankFill::Nan).is_err());
+        assert!(blank(&[vec![0.0; 10], vec![0.0; 9]], RATE, 0.0, &[0.001], (0.0, 0.005), BlankFill::Nan).is_err());
+        let options = |n_clusters| TemplateOptions { n_clusters, ..TemplateOptions::default() };
+        assert!(template_subtract(&channels, RATE, 0.0, &[0.5], (0.0, 0.05), &options(0)).is_err());
+        assert!(template_subtract(&channels, RATE, 0.0, &[0.5, 0.99], (0.0, 0.05), &options(2)).is_err());
+    }
+}
@@ -0,0 +1,274 @@
+// A module to turn discrete events into continuous stimulus signals
+
+// Written by Amin Alam in 2024
+
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+
+/// The shape of the signal built by `event_signal`
+///
+/// # Arguments
+///
+/// * `Delta` - 1 at the sample of each event and 0 elsewhere
+/// * `Boxcar` - 1 over the samples covered by each event, given the duration of each event in seconds
+/// * `WeightedDelta` - The value of each event at its sample and 0 elsewhere
+/// * `WeightedBoxcar` - The value of each event over the samples it covers, given the duration and value of each event
+///
+/// # Examples
+///
+/// ```
+/// let onsets = event_signal(&tone_times, 1000.0, 600.0, EventSignalKind::Delta, &EventSignalOptions::default())?;
+/// let loudness = event_signal(&tone_times, 1000.0, 600.0, EventSignalKind::WeightedBoxcar { durations: &tone_lengths, values: &tone_db }, &EventSignalOptions::default())?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EventSignalKind<'a> {
+    Delta,
+    Boxcar { durations: &'a [f64] },
+    WeightedDelta { values: &'a [f64] },
+    WeightedBoxcar { durations: &'a [f64], values: &'a [f64] },
+}
+
+/// The rule that maps a time to a sample
+///
+/// # Arguments
+///
+/// * `Nearest` - The nearest sample, the later one for a time exactly halfway between two samples
+/// * `Floor` - The last sample at or before the time
+/// * `Ceil` - The first sample at or after the time
+///
+/// # Examples
+///
+/// ```
+/// let options = EventSignalOptions { rounding: SampleRounding::Floor, ..EventSignalOptions::default() };
+/// ```
+///
+/// # Note
+///
+/// The time is first multiplied by the sampling rate in double precision, so a time is only
+/// exactly halfway between two samples if that product is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SampleRounding {
+    Nearest,
+    Floor,
+    Ceil,
+}
+
+/// What `event_signal` does with events that do not fit in the signal
+///
+/// # Arguments
+///
+/// * `Error` - Returns an error naming the first such event
+/// * `Clip` - Drops the deltas outside the signal and cuts the boxcars to the signal
+///
+/// # Examples
+///
+/// ```
+/// let options = EventSignalOptions { out_of_range: OutOfRange::Clip, ..EventSignalOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OutOfRange {
+    Error,
+    Clip,
+}
+
+/// The options of `event_signal`
+///
+/// # Arguments
+///
+/// * `rounding` - The rule that maps event times to samples, `Nearest` by default
+/// * `out_of_range` - What to do with events that do not fit in the signal, `Error` by default
+///
+/// # Examples
+///
+/// ```
+/// let options = EventSignalOptions { rounding: SampleRounding::Ceil, out_of_range: OutOfRange::Clip };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct EventSignalOptions {
+    pub rounding: SampleRounding,
+    pub out_of_range: OutOfRange,
+}
+
+impl Default for EventSignalOptions {
+    fn default() -> Self {
+        Self { rounding: SampleRounding::Nearest, out_of_range: OutOfRange::Error }
+    }
+}
+
+/// Builds a continuous signal from the times of discrete events
+///
+/// # Arguments
+///
+/// * `times` - The time of each event in seconds from the first sample of the signal
+/// * `sampling_rate` - The sampling rate of the signal in Hz
+/// * `duration` - The duration of the signal in seconds
+/// * `kind` - The shape of the signal, with the durations and values of the events if needed
+/// * `options` - The rounding of times to samples and the handling of events outside the signal
+///
+/// # Returns
+///
+/// The samples of the signal, or an error if the sampling rate or duration is invalid, a
+/// time, duration or value is not finite, a duration is negative, the durations or values
+/// do not have one entry per event, or an event falls outside the signal under `OutOfRange::Error`
+///
+/// # Examples
+///
+/// ```
+/// let regressor = event_signal(&word_onsets, 128.0, eeg_duration, EventSignalKind::WeightedDelta { values: &surprisal }, &EventSignalOptions::default())?;
+/// let correlation = cross_correlate(&regressor, &eeg[0], 128.0, 0.5, true)?;
+/// ```
+///
+/// # Note
+///
+/// The signal has `round(duration * sampling_rate)` samples, and sample `k` is at time
+/// `k / sampling_rate`. An event at time `t` starts at the sample `t * sampling_rate` rounds
+/// to, and a boxcar ends before the sample `(t + duration) * sampling_rate` rounds to, but
+/// always covers at least one sample. An event falls outside the signal if one of its samples
+/// does, which includes an event placed on the sample just after the last one. Overlapping
+/// events add up.
+///
+pub fn event_signal(
+    times: &[f64],
+    sampling_rate: f64,
+    duration: f64,
+    kind: EventSignalKind,
+    options: &EventSignalOptions,
+) -> Result<Vec<f64>, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    let n_samples = (duration * sampling_rate).round();
+    if !(n_samples >= 0.0 && n_samples.is_finite()) {
+        return Err(ProcessingError::InvalidParameter(format!("Duration must be finite and not negative, got {}", duration)));
+    }
+    let (durations, values) = match kind {
+        EventSignalKind::Delta => (None, None),
+        EventSignalKind::Boxcar { durations } => (Some(durations), None),
+        EventSignalKind::WeightedDelta { values } => (None, Some(values)),
+        EventSignalKind::WeightedBoxcar { durations, values } => (Some(durations), Some(values)),
+    };
+    for (name, entries) in [("durations", durations), ("values", values)] {
+        if let Some(entries) = entries.filter(|entries| entries.len() != times.len()) {
+            return Err(ProcessingError::InvalidParameter(format!("Expected {} {}, one per event, got {}", times.len(), name, entries.len())));
+        }
+    }
+
+    let to_sample = |time: f64| {
+        let position = time * sampling_rate;
+        match options.rounding {
+            SampleRounding::Nearest => position.round(),
+            SampleRounding::Floor => position.floor(),
+            SampleRounding::Ceil => position.ceil(),
+        }
+    };
+    let mut signal = vec![0.0; n_samples as usize];
+    for (event, &time) in times.iter().enumerate() {
+        let length = durations.map_or(0.0, |durations| durations[event]);
+        let value = values.map_or(1.0, |values| values[event]);
+        if !(time.is_finite() && length.is_finite() && length >= 0.0 && value.is_finite()) {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Event {} must have a finite time, value and non-negative duration, got {} s, {} and {} s",
+                event, time, value, length
+            )));
+        }
+        let start = to_sample(time);
+        let end = if durations.is_some() { to_sample(time + length).max(start + 1.0) } else { start + 1.0 };
+        if (start < 0.0 || end > n_samples) && options.out_of_range == OutOfRange::Error {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Event {} at {} s does not fit in the {} s of the signal",
+                event, time, duration
+            )));
+        }
+        let (start, end) = (start.clamp(0.0, n_samples) as usize, end.clamp(0.0, n_samples) as usize);
+        signal[start..end.max(start)].iter_mut().for_each(|sample| *sample += value);
+    }
+    Ok(signal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::xcorr::cross_correlate;
+
+    fn nonzero(signal: &[f64]) -> Vec<(usize, f64)> {
+        signal.iter().copied().enumerate().filter(|(_, sample)| *sample != 0.0).collect()
+    }
+
+    #[test]
+    fn events_halfway_between_samples_follow_each_rounding_rule() {
+        // At 4 Hz, 0.125 s is exactly sample 0.5, 0.375 s sample 1.5 and 0.625 s sample 2.5
+        let times = [0.125, 0.375, 0.625];
+        let expected = [(SampleRounding::Nearest, [1, 2, 3]), (SampleRounding::Floor, [0, 1, 2]), (SampleRounding::Ceil, [1, 2, 3])];
+        for (rounding, samples) in expected {
+            let options = EventSignalOptions { rounding, ..EventSignalOptions::default() };
+            let signal = event_signal(&times, 4.0, 1.0, EventSignalKind::Delta, &options).unwrap();
+            assert_eq!(signal.len(), 4);
+            assert_eq!(nonzero(&signal), samples.iter().map(|&k| (k, 1.0)).collect::<Vec<_>>(), "{:?}", rounding);
+        }
+        // Times on a sample land on it under every rule, and just off it only Nearest keeps it
+        for rounding in [SampleRounding::Nearest, SampleRounding::Floor, SampleRounding::Ceil] {
+            let options = EventSignalOptions { rounding, ..EventSignalOptions::default() };
+            assert_eq!(nonzero(&event_signal(&[0.5], 4.0, 1.0, EventSignalKind::Delta, &options).unwrap()), vec![(2, 1.0)]);
+        }
+        let late = event_signal(&[0.51], 4.0, 1.0, EventSignalKind::Delta, &EventSignalOptions { rounding: SampleRounding::Ceil, ..EventSignalOptions::default() }).unwrap();
+        assert_eq!(nonzero(&late), vec![(3, 1.0)]);
+    }
+
+    #[test]
+    fn boxcars_and_weights_cover_the_expected_samples() {
+        let options = EventSignalOptions::default();
+        // 0.2 s for 0.3 s covers samples 2, 3 and 4 at 10 Hz, and a zero duration covers one sample
+        let boxcar = event_signal(&[0.2, 0.8], 10.0, 1.0, EventSignalKind::Boxcar { durations: &[0.3, 0.0] }, &options).unwrap();
+        assert_eq!(boxcar, vec![0.0, 0.0, 1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+        // The boxcar end follows the rounding rule too: 0.25 s to 0.45 s is samples 2.5 to 4.5
+        let floor = EventSignalOptions { rounding: SampleRounding::Floor, ..options };
+        let halfway = event_signal(&[0.25], 10.0, 1.0, EventSignalKind::Boxcar { durations: &[0.2] }, &floor).unwrap();
+        assert_eq!(nonzero(&halfway).iter().map(|(k, _)| *k).collect::<Vec<_>>(), vec![2, 3]);
+
+        let weighted = event_signal(&[0.1, 0.1, 0.5], 10.0, 1.0, EventSignalKind::WeightedDelta { values: &[2.0, 0.5, -3.0] }, &options).unwrap();
+        assert_eq!(nonzero(&weighted), vec![(1, 2.5), (5, -3.0)]);
+        let overlapping = EventSignalKind::WeightedBoxcar { durations: &[0.4, 0.2], values: &[1.5, 2.0] };
+        let weighted_boxcar = event_signal(&[0.1, 0.3], 10.0, 1.0, overlapping, &options).unwrap();
+        assert_eq!(nonzero(&weighted_boxcar), vec![(1, 1.5), (2, 1.5), (3, 3.5), (4, 3.5)]);
+    }
+
+    #[test]
+    fn events_outside_the_signal_are_refused_or_clipped() {
+        let refuse = EventSignalOptions::default();
+        let clip = EventSignalOptions { out_of_range: OutOfRange::Clip, ..refuse };
+        // The sample at the duration itself is one past the last sample
+        for (times, kind) in [
+            (vec![-0.1], EventSignalKind::Delta),
+            (vec![1.0], EventSignalKind::Delta),
+            (vec![0.8], EventSignalKind::Boxcar { durations: &[0.5] }),
+            (vec![-0.2], EventSignalKind::Boxcar { durations: &[0.5] }),
+        ] {
+            assert!(event_signal(&times, 10.0, 1.0, kind, &refuse).is_err(), "{:?}", times);
+        }
+        assert_eq!(event_signal(&[0.94], 10.0, 1.0, EventSignalKind::Delta, &refuse).unwrap()[9], 1.0);
+        assert!(nonzero(&event_signal(&[-0.1, 1.0], 10.0, 1.0, EventSignalKind::Delta, &clip).unwrap()).is_empty());
+        let cut = event_signal(&[0.8, -0.2], 10.0, 1.0, EventSignalKind::Boxcar { durations: &[0.5, 0.5] }, &clip).unwrap();
+        assert_eq!(cut, vec![1.0, 1.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 1.0, 1.0]);
+
+        assert!(event_signal(&[0.1], 10.0, 1.0, EventSignalKind::Boxcar { durations: &[] }, &refuse).is_err());
+        assert!(event_signal(&[0.1], 10.0, 1.0, EventSignalKind::WeightedDelta { values: &[1.0, 2.0] }, &refuse).is_err());
+        assert!(event_signal(&[f64::NAN], 10.0, 1.0, EventSignalKind::Delta, &clip).is_err());
+        assert!(event_signal(&[0.1], 10.0, 1.0, EventSignalKind::Boxcar { durations: &[-0.1] }, &clip).is_err());
+        assert!(event_signal(&[0.1], 10.0, 1.0, EventSignalKind::WeightedDelta { values: &[f64::INFINITY] }, &clip).is_err());
+        assert!(event_signal(&[0.1], 0.0, 1.0, EventSignalKind::Delta, &refuse).is_err());
+        assert!(event_signal(&[], 10.0, -1.0, EventSignalKind::Delta, &refuse).is_err());
+        assert!(event_signal(&[], 10.0, 0.0, EventSignalKind::Delta, &refuse).unwrap().is_empty());
+    }
+
+    #[test]
+    fn the_signal_feeds_a_cross_correlation_with_the_recording() {
+        // A recording that answers each event 40 ms later is found at a 40 ms lag
+        let times = [0.3, 1.1, 1.75, 2.6];
+        let regressor = event_signal(&times, 1000.0, 3.0, EventSignalKind::Delta, &EventSignalOptions::default()).unwrap();
+        let shifted: Vec<f64> = times.iter().map(|time| time + 0.04).collect();
+        let response = event_signal(&shifted, 1000.0, 3.0, EventSignalKind::WeightedDelta { values: &[0.5, 1.0, 2.0, 1.5] }, &EventSignalOptions::default()).unwrap();
+        let (lag, _) = cross_correlate(&regressor, &response, 1000.0, 0.1, false).unwrap().best_lag(false);
+        assert!((lag - 0.04).abs() < 1e-12, "{}", lag);
+    }
+}
@@ -0,0 +1,871 @@
+// A module to compute statistics of signals in one bounded-memory pass
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::histogram::Histogram;
+use crate::processing::pipeline::{Pipeline, PipelineError, SignalSource, Sink};
+
+/// The compression of the quantile sketch used by default: larger values keep more centroids and give more accurate quantiles
+pub const DEFAULT_COMPRESSION: f64 = 100.0;
+
+/// The quantiles written by `StatsTable::to_csv`
+pub const TABLE_QUANTILES: [f64; 5] = [0.05, 0.25, 0.5, 0.75, 0.95];
+
+/// The number of values buffered per unit of compression before the sketch is compressed
+const BUFFER_FACTOR: f64 = 5.0;
+
+/// The fewest values of a chunk given to each thread by `StatsTable::update`, below which spawning costs more than it saves
+const MIN_VALUES_PER_THREAD: usize = 1 << 15;
+
+/// A cluster of nearby values in the quantile sketch
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+struct Centroid {
+    mean: f64,
+    weight: f64,
+}
+
+/// The running statistics of a stream of values
+///
+/// # Examples
+///
+/// ```
+/// let mut stats = StreamingStats::new().with_histogram(10.0, -500.0, 500.0)?;
+/// for chunk in chunks {
+///     stats.update(&chunk);
+/// }
+/// println!("{} +- {}, median {}", stats.mean(), stats.std(), stats.quantile(0.5));
+/// ```
+///
+/// # Note
+///
+/// The moments are accumulated with Welford's algorithm and the quantiles with a merging
+/// t-digest, so the memory used does not grow with the number of values. Statistics of
+/// separate chunks or threads can be combined with `merge`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StreamingStats {
+    count: usize,
+    missing: usize,
+    mean: f64,
+    m2: f64,
+    min: f64,
+    max: f64,
+    compression: f64,
+    centroids: Vec<Centroid>,
+    buffer: Vec<Centroid>,
+    histogram: Option<Histogram>,
+}
+
+impl Default for StreamingStats {
+    fn default() -> Self {
+        Self {
+            count: 0,
+            missing: 0,
+            mean: 0.0,
+            m2: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            compression: DEFAULT_COMPRESSION,
+            centroids: Vec::new(),
+            buffer: Vec::new(),
+            histogram: None,
+        }
+    }
+}
+
+/// Implementation of the StreamingStats struct
+///
+/// # Methods
+///
+/// * `new` - Creates empty StreamingStats
+/// * `with_compression` - Sets the compression of the quantile sketch
+/// * `with_histogram` - Also counts the values in regular bins
+/// * `update` - Adds the next chunk of values
+/// * `merge` - Adds the values summarized by other StreamingStats
+/// * `count` - Returns the number of values
+/// * `missing` - Returns the number of values left out
+/// * `mean` - Returns the mean
+/// * `std` - Returns the standard deviation
+/// * `min` - Returns the smallest value
+/// * `max` - Returns the largest value
+/// * `quantile` - Estimates a quantile
+/// * `histogram` - Returns the histogram
+impl StreamingStats {
+    /// Creates empty StreamingStats
+    ///
+    /// # Returns
+    ///
+    /// StreamingStats with no values, a sketch of `DEFAULT_COMPRESSION` and no histogram
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let stats = StreamingStats::new();
+    /// ```
+    ///
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the compression of the quantile sketch
+    ///
+    /// # Arguments
+    ///
+    /// * `compression` - About the number of centroids kept, at least 10
+    ///
+    /// # Returns
+    ///
+    /// The StreamingStats with the new compression, or an error if it is below 10 or values were already added
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let stats = StreamingStats::new().with_compression(500.0)?;
+    /// ```
+    ///
+    pub fn with_compression(mut self, compression: f64) -> Result<Self, ProcessingError> {
+        if compression.is_nan() || compression < 10.0 || compression.is_infinite() {
+            return Err(ProcessingError::InvalidParameter(format!("Compression must be at least 10, got {}", compression)));
+        }
+        if self.count + self.missing > 0 {
+            return Err(ProcessingError::InvalidParameter("Compression cannot change once values are added".to_string()));
+        }
+        self.compression = compression;
+        Ok(self)
+    }
+
+    /// Also counts the values in regular bins
+    ///
+    /// # Arguments
+    ///
+    /// * `bin_size` - The width of each bin
+    /// * `low` - The lower edge of the first bin
+    /// * `high` - The upper edge of the last bin, rounded up to a whole number of bins
+    ///
+    /// # Returns
+    ///
+    /// The StreamingStats with an empty histogram, or an error if the bins are invalid as in
+    /// `Histogram::from_values` or values were already added
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let stats = StreamingStats::new().with_histogram(10.0, -500.0, 500.0)?;
+    /// ```
+    ///
+    pub fn with_histogram(mut self, bin_size: f64, low: f64, high: f64) -> Result<Self, ProcessingError> {
+        if self.count + self.missing > 0 {
+            return Err(ProcessingError::InvalidParameter("A histogram cannot be added once values are added".to_string()));
+        }
+        self.histogram = Some(Histogram::from_values(&[], bin_size, low, high)?);
+        Ok(self)
+    }
+
+    /// Adds the next chunk of values
+    ///
+    /// # Arguments
+    ///
+    /// * `values` - The values, NaN and infinite values are counted as missing and left out
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// stats.update(&chunk);
+    /// ```
+    ///
+    pub fn update(&mut self, values: &[f64]) {
+        for &value in values {
+            if !value.is_finite() {
+                self.missing += 1;
+                continue;
+            }
+            self.count += 1;
+            let delta = value - self.mean;
+            self.mean += delta / self.count as f64;
+            self.m2 += delta * (value - self.mean);
+            self.min = self.min.min(value);
+            self.max = self.max.max(value);
+            self.buffer.push(Centroid { mean: value, weight: 1.0 });
+            if self.buffer.len() as f64 >= BUFFER_FACTOR * self.compression {
+                self.compress();
+            }
+        }
+        if let Some(histogram) = self.histogram.as_mut() {
+            histogram.add_values(values);
+        }
+    }
+
+    /// Adds the values summarized by other StreamingStats
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The statistics of other values, e.g. of another chunk or thread
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if exactly one of the two has a histogram or their histogram bins differ
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut total = StreamingStats::new();
+    /// for part in &per_thread {
+    ///     total.merge(part)?;
+    /// }
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The moments, extremes and histogram are the same as if all values were added to one
+    /// StreamingStats, up to floating-point rounding. The quantile sketch depends slightly on
+    /// the order of the merges, within its usual accuracy.
+    ///
+    pub fn merge(&mut self, other: &StreamingStats) -> Result<(), ProcessingError> {
+        match (self.histogram.as_mut(), &other.histogram) {
+            (None, None) => {}
+            (Some(histogram), Some(other)) if histogram.bin_edges == other.bin_edges => {
+                histogram.counts.iter_mut().zip(&other.counts).for_each(|(count, other)| *count += other);
+            }
+            _ => return Err(ProcessingError::InvalidParameter("Cannot merge statistics with different histogram bins".to_string())),
+        }
+        self.missing += other.missing;
+        if other.count == 0 {
+            return Ok(());
+        }
+        let count = self.count + other.count;
+        let delta = other.mean - self.mean;
+        self.m2 += other.m2 + delta * delta * self.count as f64 * other.count as f64 / count as f64;
+        self.mean += delta * other.count as f64 / count as f64;
+        self.count = count;
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.buffer.extend_from_slice(&other.centroids);
+        self.buffer.extend_from_slice(&other.buffer);
+        if self.buffer.len() as f64 >= BUFFER_FACTOR * self.compression {
+            self.compress();
+        }
+        Ok(())
+    }
+
+    /// Returns the number of values
+    ///
+    /// # Returns
+    ///
+    /// The number of finite values added
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let n = stats.count();
+    /// ```
+    ///
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the number of values left out
+    ///
+    /// # Returns
+    ///
+    /// The number of NaN and infinite values added
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let dropouts = stats.missing();
+    /// ```
+    ///
+    pub fn missing(&self) -> usize {
+        self.missing
+    }
+
+    /// Returns the mean
+    ///
+    /// # Returns
+    ///
+    /// The mean of the values, or NaN if there is none
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let offset = stats.mean();
+    /// ```
+    ///
+    pub fn mean(&self) -> f64 {
+        if self.count == 0 { f64::NAN } else { self.mean }
+    }
+
+    /// Returns the standard deviation
+    ///
+    /// # Returns
+    ///
+    /// The sample standard deviation (divided by `n - 1`), or NaN for fewer than two values
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let noise = stats.std();
+    /// ```
+    ///
+    pub fn std(&self) -> f64 {
+        if self.count < 2 { f64::NAN } else { (self.m2 / (self.count - 1) as f64).sqrt() }
+    }
+
+    /// Returns the smallest value
+    ///
+    /// # Returns
+    ///
+    /// The smallest value, or NaN if there is none
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let floor = stats.min();
+    /// ```
+    ///
+    pub fn min(&self) -> f64 {
+        if self.count == 0 { f64::NAN } else { self.min }
+    }
+
+    /// Returns the largest value
+    ///
+    /// # Returns
+    ///
+    /// The largest value, or NaN if there is none
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let clipped = stats.max() >= 32767.0;
+    /// ```
+    ///
+    pub fn max(&self) -> f64 {
+        if self.count == 0 { f64::NAN } else { self.max }
+    }
+
+    /// Estimates a quantile
+    ///
+    /// # Arguments
+    ///
+    /// * `q` - The quantile, between 0 and 1
+    ///
+    /// # Returns
+    ///
+    /// The estimated quantile, or NaN if there is no value or `q` is outside [0, 1]
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let median = stats.quantile(0.5);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Until the sketch is first compressed, i.e. for fewer than `5 * compression` values,
+    /// the quantile is exact and interpolated linearly between the sorted values as in
+    /// numpy.quantile. Beyond that it is interpolated between the centroids of the t-digest,
+    /// whose error is smallest near the extremes and largest around the median.
+    ///
+    pub fn quantile(&self, q: f64) -> f64 {
+        if self.count == 0 || !(0.0..=1.0).contains(&q) {
+            return f64::NAN;
+        }
+        if self.centroids.is_empty() && self.buffer.iter().all(|centroid| centroid.weight == 1.0) {
+            let mut sorted: Vec<f64> = self.buffer.iter().map(|centroid| centroid.mean).collect();
+            sorted.sort_by(|a, b| a.total_cmp(b));
+            let position = q * (sorted.len() - 1) as f64;
+            let below = position.floor() as usize;
+            let above = (below + 1).min(sorted.len() - 1);
+            return sorted[below] + (position - below as f64) * (sorted[above] - sorted[below]);
+        }
+        let mut digest = self.clone();
+        digest.compress();
+        digest.interpolate(q)
+    }
+
+    /// Returns the histogram
+    ///
+    /// # Returns
+    ///
+    /// The Histogram of the values, or None if it was not requested with `with_histogram`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// stats.histogram().expect("No histogram requested").to_csv(&mut csv_io)?;
+    /// ```
+    ///
+    pub fn histogram(&self) -> Option<&Histogram> {
+        self.histogram.as_ref()
+    }
+
+    /// Merges the buffered values into the centroids with the arcsine scale function
+    fn compress(&mut self) {
+        let mut all = std::mem::take(&mut self.centroids);
+        all.append(&mut self.buffer);
+        if all.is_empty() {
+            return;
+        }
+        all.sort_by(|a, b| a.mean.total_cmp(&b.mean));
+        let total: f64 = all.iter().map(|centroid| centroid.weight).sum();
+        let scale = |q: f64| self.compression / (2.0 * std::f64::consts::PI) * (2.0 * q - 1.0).asin();
+        let inverse = |k: f64| ((2.0 * std::f64::consts::PI * k / self.compression).sin() + 1.0) / 2.0;
+        let limit = |before: f64| if scale(before / total) + 1.0 >= self.compression / 4.0 { total } else { total * inverse(scale(before / total) + 1.0) };
+
+        let mut merged = Vec::with_capacity(self.compression as usize);
+        let mut current = all[0];
+        let mut before = 0.0;
+        let mut bound = limit(before);
+        for &centroid in &all[1..] {
+            if before + current.weight + centroid.weight <= bound {
+                current.weight += centroid.weight;
+                current.mean += (centroid.mean - current.mean) * centroid.weight / current.weight;
+            } else {
+                before += current.weight;
+                merged.push(current);
+                bound = limit(before);
+                current = centroid;
+            }
+        }
+        merged.push(current);
+        self.centroids = merged;
+    }
+
+    /// Interpolates a quantile between the centres of the compressed centroids
+    fn interpolate(&self, q: f64) -> f64 {
+        let centroids = &self.centroids;
+        let target = q * self.count as f64;
+        let first = centroids[0];
+        let last = centroids[centroids.len() - 1];
+        if target <= first.weight / 2.0 {
+            return if first.weight <= 1.0 { first.mean } else { self.min + (first.mean - self.min) * target / (first.weight / 2.0) };
+        }
+        let total = self.count as f64;
+        if target >= total - last.weight / 2.0 {
+            return if last.weight <= 1.0 { last.mean } else { self.max - (self.max - last.mean) * (total - target) / (last.weight / 2.0) };
+        }
+        let mut before = 0.0;
+        for pair in centroids.windows(2) {
+            let left = before + pair[0].weight / 2.0;
+            let right = before + pair[0].weight + pair[1].weight / 2.0;
+            if target <= right {
+                return pair[0].mean + (target - left) / (right - left) * (pair[1].mean - pair[0].mean);
+            }
+            before += pair[0].weight;
+        }
+        last.mean
+    }
+}
+
+/// The running statistics of several channels
+///
+/// # Arguments
+///
+/// * `names` - The name of each channel
+/// * `channels` - The StreamingStats of each channel
+///
+/// # Examples
+///
+/// ```
+/// let table = StatsTable::from_source(SignalSource::from_csv("wideband.csv", 30000.0, Some("time"))?, &StreamingStats::new())?;
+/// table.to_csv(&mut csv_io)?;
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct StatsTable {
+    pub names: Vec<String>,
+    pub channels: Vec<StreamingStats>,
+}
+
+/// Implementation of the StatsTable struct
+///
+/// # Methods
+///
+/// * `new` - Creates a StatsTable with no values
+/// * `from_source` - Computes the statistics of every channel of a SignalSource
+/// * `update` - Adds the next chunk of every channel
+/// * `merge` - Adds the values summarized by another StatsTable
+/// * `to_csv` - Writes one row of statistics per channel
+impl StatsTable {
+    /// Creates a StatsTable with no values
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - The name of each channel
+    /// * `template` - The empty StreamingStats copied for every channel, with its compression and histogram bins
+    ///
+    /// # Returns
+    ///
+    /// The StatsTable, or an error if the template already holds values
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let table = StatsTable::new(&names, &StreamingStats::new().with_histogram(1.0, -100.0, 100.0)?)?;
+    /// ```
+    ///
+    pub fn new(names: &[String], template: &StreamingStats) -> Result<Self, ProcessingError> {
+        if template.count + template.missing > 0 {
+            return Err(ProcessingError::InvalidParameter("The template statistics must be empty".to_string()));
+        }
+        Ok(Self { names: names.to_vec(), channels: vec![template.clone(); names.len()] })
+    }
+
+    /// Computes the statistics of every channel of a SignalSource
+    ///
+    /// # Arguments
+    ///
+    /// * `source` - The signal, read chunk by chunk
+    /// * `template` - The empty StreamingStats copied for every channel
+    ///
+    /// # Returns
+    ///
+    /// The StatsTable, or an error if the template holds values or the source cannot be read
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let table = StatsTable::from_source(SignalSource::from_channels(&channels, &names, 1000.0)?, &StreamingStats::new())?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// To compute the statistics after filtering or decimating, pass the StatsTable of the
+    /// output channels to `Pipeline::run` instead
+    ///
+    pub fn from_source(source: SignalSource, template: &StreamingStats) -> Result<Self, PipelineError> {
+        let mut table = Self::new(source.names(), template)?;
+        Pipeline::source(source).run(&mut table)?;
+        Ok(table)
+    }
+
+    /// Adds the next chunk of every channel
+    ///
+    /// # Arguments
+    ///
+    /// * `chunk` - The next values of each channel, one row per channel
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if the chunk does not have one row per channel
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// table.update(&chunk)?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The channels are split across the available CPU cores when the chunk is large enough
+    /// to give every thread at least 32768 values, and updated on the calling thread otherwise
+    ///
+    pub fn update(&mut self, chunk: &[Vec<f64>]) -> Result<(), ProcessingError> {
+        if chunk.len() != self.channels.len() {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Chunk has {} channels but the table has {}",
+                chunk.len(),
+                self.channels.len()
+            )));
+        }
+        let n_values: usize = chunk.iter().map(Vec::len).sum();
+        let n_threads = std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1).min(n_values / MIN_VALUES_PER_THREAD);
+        if n_threads <= 1 || chunk.len() == 1 {
+            self.channels.iter_mut().zip(chunk).for_each(|(stats, row)| stats.update(row));
+            return Ok(());
+        }
+        let group = self.channels.len().div_ceil(n_threads).max(1);
+        std::thread::scope(|scope| {
+            for (stats, rows) in self.channels.chunks_mut(group).zip(chunk.chunks(group)) {
+                scope.spawn(move || stats.iter_mut().zip(rows).for_each(|(stats, row)| stats.update(row)));
+            }
+        });
+        Ok(())
+    }
+
+    /// Adds the values summarized by another StatsTable
+    ///
+    /// # Arguments
+    ///
+    /// * `other` - The statistics of other values of the same channels
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if the channel names or histogram bins differ
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let mut total = first_session;
+    /// total.merge(&second_session)?;
+    /// ```
+    ///
+    pub fn merge(&mut self, other: &StatsTable) -> Result<(), ProcessingError> {
+        if self.names != other.names {
+            return Err(ProcessingError::InvalidParameter("Cannot merge tables of different channels".to_string()));
+        }
+        for (stats, other) in self.channels.iter_mut().zip(&other.channels) {
+            stats.merge(other)?;
+        }
+        Ok(())
+    }
+
+    /// Writes one row of statistics per channel
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// table.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The columns are `channel,count,missing,mean,std,min,max` followed by the
+    /// `TABLE_QUANTILES` as `q05` to `q95`. A header row is written first. The rows are not
+    /// flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        let mut header: Vec<String> = ["channel", "count", "missing", "mean", "std", "min", "max"].iter().map(|name| name.to_string()).collect();
+        header.extend(TABLE_QUANTILES.iter().map(|q| format!("q{:02}", (q * 100.0).round())));
+        csv_io.write_record(StringRecord::from(header))?;
+        for (name, stats) in self.names.iter().zip(&self.channels) {
+            let mut record = vec![
+                name.clone(),
+                stats.count().to_string(),
+                stats.missing().to_string(),
+                float_format.format(stats.mean()),
+                float_format.format(stats.std()),
+                float_format.format(stats.min()),
+                float_format.format(stats.max()),
+            ];
+            record.extend(TABLE_QUANTILES.iter().map(|&q| float_format.format(stats.quantile(q))));
+            csv_io.write_record(StringRecord::from(record))?;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for StatsTable {
+    fn start(&mut self, names: &[String], _sampling_rate: f64) -> Result<(), PipelineError> {
+        if names != self.names.as_slice() {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Table has channels {:?} but the pipeline produces {:?}",
+                self.names, names
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, chunk: &[Vec<f64>]) -> Result<(), PipelineError> {
+        Ok(self.update(chunk)?)
+    }
+}
+
+/// The running statistics of the values sharing a combination of key values
+///
+/// # Arguments
+///
+/// * `keys` - The value of each key column, e.g. `["s01", "rest", "Cz"]`
+/// * `stats` - The StreamingStats of the values of the group
+///
+/// # Examples
+///
+/// ```
+/// let groups = csv_io.grouped_stats(&["subject", "condition", "channel"], "amplitude", &StreamingStats::new(), 100_000)?;
+/// for group in &groups {
+///     println!("{:?}: {} +- {}", group.keys, group.stats.mean(), group.stats.std());
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct GroupStats {
+    pub keys: Vec<String>,
+    pub stats: StreamingStats,
+}
+
+/// Writes one row of statistics per group
+///
+/// # Arguments
+///
+/// * `groups` - The statistics of each group
+/// * `key_names` - The names of the key columns, one per key of the groups
+/// * `csv_io` - The CsvIO object to write to
+///
+/// # Returns
+///
+/// Nothing, or an error if a row cannot be written
+///
+/// # Examples
+///
+/// ```
+/// let groups = input.grouped_stats(&["subject", "channel"], "value", &StreamingStats::new(), 10_000)?;
+/// groups_to_csv(&groups, &["subject", "channel"], &mut output)?;
+/// output.save();
+/// ```
+///
+/// # Note
+///
+/// The columns are the key columns followed by `count,missing,mean,std,min,max` and the
+/// `TABLE_QUANTILES`, as in `StatsTable::to_csv`. A header row is written first. The rows
+/// are not flushed to disk until `save` is called.
+///
+pub fn groups_to_csv(groups: &[GroupStats], key_names: &[&str], csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+    let float_format = csv_io.float_format();
+    let mut header: Vec<String> = key_names.iter().map(|name| name.to_string()).collect();
+    header.extend(["count", "missing", "mean", "std", "min", "max"].iter().map(|name| name.to_string()));
+    header.extend(TABLE_QUANTILES.iter().map(|q| format!("q{:02}", (q * 100.0).round())));
+    csv_io.write_record(StringRecord::from(header))?;
+    for group in groups {
+        let stats = &group.stats;
+        let mut record = group.keys.clone();
+        record.extend([
+            stats.count().to_string(),
+            stats.missing().to_string(),
+            float_format.format(stats.mean()),
+            float_format.format(stats.std()),
+            float_format.format(stats.min()),
+            float_format.format(stats.max()),
+        ]);
+        record.extend(TABLE_QUANTILES.iter().map(|&q| float_format.format(stats.quantile(q))));
+        csv_io.write_record(StringRecord::from(record))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    /// The linearly interpolated quantile of the sorted values, as numpy computes it
+    fn exact_quantile(sorted: &[f64], q: f64) -> f64 {
+        let position = q * (sorted.len() - 1) as f64;
+        let below = position.floor() as usize;
+        let above = (below + 1).min(sorted.len() - 1);
+        sorted[below] + (position - below as f64) * (sorted[above] - sorted[below])
+    }
+
+    fn lognormal(n: usize, seed: u64) -> Vec<f64> {
+        let mut rng = SeededRng::new(seed);
+        (0..n).map(|_| rng.next_gaussian().exp()).collect()
+    }
+
+    fn sorted(values: &[f64]) -> Vec<f64> {
+        let mut sorted = values.to_vec();
+        sorted.sort_by(f64::total_cmp);
+        sorted
+    }
+
+    #[test]
+    fn tables_match_their_channels_updated_alone_on_small_and_large_chunks() {
+        let names: Vec<String> = (0..8).map(|channel| format!("ch{}", channel)).collect();
+        let mut table = StatsTable::new(&names, &StreamingStats::new()).unwrap();
+        let mut alone = vec![StreamingStats::new(); names.len()];
+        // 800 values stay on this thread, 160000 are split across threads
+        for (seed, length) in [(1, 100), (2, 20000), (3, 7)] {
+            let chunk: Vec<Vec<f64>> = (0..names.len()).map(|channel| lognormal(length, seed * 10 + channel as u64)).collect();
+            table.update(&chunk).unwrap();
+            alone.iter_mut().zip(&chunk).for_each(|(stats, row)| stats.update(row));
+        }
+        assert_eq!(table.channels, alone);
+        assert!(table.update(&[vec![1.0]]).is_err());
+    }
+
+    #[test]
+    fn small_samples_have_exact_quantiles() {
+        let values = lognormal(137, 1);
+        let mut stats = StreamingStats::new();
+        stats.update(&values);
+        let sorted = sorted(&values);
+        for q in [0.0, 0.1, 0.25, 0.5, 0.9, 1.0] {
+            assert_eq!(stats.quantile(q), exact_quantile(&sorted, q), "q = {}", q);
+        }
+    }
+
+    #[test]
+    fn large_streams_have_a_small_rank_error() {
+        let n = 200_000;
+        let values = lognormal(n, 7);
+        let mut stats = StreamingStats::new();
+        for chunk in values.chunks(999) {
+            stats.update(chunk);
+        }
+        let sorted = sorted(&values);
+        for q in [0.001, 0.01, 0.05, 0.25, 0.5, 0.75, 0.95, 0.99, 0.999] {
+            let estimate = stats.quantile(q);
+            let rank = sorted.partition_point(|&value| value < estimate) as f64 / n as f64;
+            assert!((rank - q).abs() < 1e-3, "q = {}: rank {}", q, rank);
+        }
+        let mean = values.iter().sum::<f64>() / n as f64;
+        let std = (values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / (n - 1) as f64).sqrt();
+        assert!((stats.mean() - mean).abs() < 1e-10);
+        assert!((stats.std() - std).abs() < 1e-10);
+        assert_eq!((stats.min(), stats.max()), (sorted[0], sorted[n - 1]));
+    }
+
+    #[test]
+    fn merge_order_does_not_change_the_moments() {
+        let values = lognormal(120_000, 9);
+        let part = |values: &[f64]| {
+            let mut stats = StreamingStats::new().with_histogram(0.5, 0.0, 10.0).unwrap();
+            stats.update(values);
+            stats
+        };
+        let whole = part(&values);
+        let parts: Vec<StreamingStats> = values.chunks(30_000).map(part).collect();
+        let mut left = parts[0].clone();
+        parts[1..].iter().for_each(|other| left.merge(other).unwrap());
+        let mut right = parts[3].clone();
+        parts[..3].iter().rev().for_each(|other| right.merge(other).unwrap());
+        let mut tree = parts[0].clone();
+        tree.merge(&parts[1]).unwrap();
+        let mut branch = parts[2].clone();
+        branch.merge(&parts[3]).unwrap();
+        tree.merge(&branch).unwrap();
+        for merged in [&left, &right, &tree] {
+            assert_eq!(merged.count(), whole.count());
+            assert!((merged.mean() - whole.mean()).abs() < 1e-12);
+            assert!((merged.std() - whole.std()).abs() < 1e-12);
+            assert_eq!((merged.min(), merged.max()), (whole.min(), whole.max()));
+            assert_eq!(merged.histogram().unwrap().counts, whole.histogram().unwrap().counts);
+        }
+    }
+
+    #[test]
+    fn non_finite_values_are_counted_as_missing() {
+        let mut stats = StreamingStats::new();
+        stats.update(&[1.0, f64::NAN, 3.0, f64::INFINITY]);
+        assert_eq!((stats.count(), stats.missing()), (2, 2));
+        assert_eq!(stats.mean(), 2.0);
+    }
+
+    #[test]
+    fn tables_match_the_statistics_of_each_channel() {
+        let channels = vec![lognormal(5000, 2), lognormal(5000, 3), lognormal(5000, 4)];
+        let names = vec!["a".to_string(), "b".to_string(), "c".to_string()];
+        let table = StatsTable::from_source(SignalSource::from_channels(&channels, &names, 1000.0).unwrap(), &StreamingStats::new()).unwrap();
+        for (stats, channel) in table.channels.iter().zip(&channels) {
+            let mut expected = StreamingStats::new();
+            expected.update(channel);
+            assert_eq!(stats.count(), 5000);
+            assert!((stats.mean() - expected.mean()).abs() < 1e-12);
+            assert_eq!(stats.min(), expected.min());
+        }
+    }
+}
@@ -0,0 +1,481 @@
+// A module to align the clocks of several recordings by their shared sync pulses
+
+// Written by Amin Alam in 2024
+
+use crate::processing::error::ProcessingError;
+
+/// The relation fitted between a target clock and the reference clock
+///
+/// # Arguments
+///
+/// * `Offset` - The clocks run at the same rate and differ by a constant offset
+/// * `OffsetDrift` - The clocks differ by an offset and a constant rate, e.g. crystals a few ppm apart
+///
+/// # Examples
+///
+/// ```
+/// let options = SyncOptions { model: ClockModel::Offset, ..SyncOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum ClockModel {
+    Offset,
+    OffsetDrift,
+}
+
+/// The options of `align`
+///
+/// # Arguments
+///
+/// * `model` - The relation fitted between the clocks, `OffsetDrift` by default
+/// * `tolerance` - The largest difference in seconds between a target pulse, once mapped, and the reference pulse it is matched to, 10 ms by default
+/// * `max_offset` - The largest offset in seconds between the clocks, none by default; set it when the pulses are evenly spaced
+/// * `min_matches` - The number of matched pulses below which no mapping is fitted, 10 by default
+/// * `max_residual` - The residual in seconds above which a mapping is flagged, 1 ms by default
+///
+/// # Examples
+///
+/// ```
+/// // Video frames are timestamped to within a frame at 30 fps
+/// let options = SyncOptions { tolerance: 0.04, max_residual: 0.02, ..SyncOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SyncOptions {
+    pub model: ClockModel,
+    pub tolerance: f64,
+    pub max_offset: Option<f64>,
+    pub min_matches: usize,
+    pub max_residual: f64,
+}
+
+impl Default for SyncOptions {
+    fn default() -> Self {
+        Self {
+            model: ClockModel::OffsetDrift,
+            tolerance: 0.01,
+            max_offset: None,
+            min_matches: 10,
+            max_residual: 0.001,
+        }
+    }
+}
+
+/// A mapping from the times of a target clock to the times of the reference clock
+///
+/// # Arguments
+///
+/// * `offset` - The reference time of target time zero
+/// * `rate` - The reference seconds per target second, 1 under `ClockModel::Offset`
+/// * `matches` - The indices of the matched reference and target pulses, in order
+/// * `residuals` - The reference time of each matched pulse minus its mapped target time
+/// * `rms_residual` - The root mean square of the residuals
+/// * `max_residual` - The largest absolute residual
+/// * `n_unmatched_reference` - The number of reference pulses without a target pulse
+/// * `n_unmatched_target` - The number of target pulses without a reference pulse
+/// * `flagged` - Whether `max_residual` exceeds `SyncOptions::max_residual`
+///
+/// # Examples
+///
+/// ```
+/// let mapping = align(&ephys_sync, &video_sync, &SyncOptions::default())?;
+/// if mapping.flagged {
+///     return Err(format!("Video alignment residual of {} s", mapping.max_residual).into());
+/// }
+/// let frame_times = mapping.map_times(&video_frame_times);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ClockMapping {
+    pub offset: f64,
+    pub rate: f64,
+    pub matches: Vec<(usize, usize)>,
+    pub residuals: Vec<f64>,
+    pub rms_residual: f64,
+    pub max_residual: f64,
+    pub n_unmatched_reference: usize,
+    pub n_unmatched_target: usize,
+    pub flagged: bool,
+}
+
+/// Implementation of the ClockMapping struct
+///
+/// # Methods
+///
+/// * `map_time` - Maps a target time to the reference clock
+/// * `map_times` - Maps target times, such as events, to the reference clock
+/// * `map_signal` - Maps the start time and sampling rate of a signal to the reference clock
+/// * `drift_ppm` - Returns how much faster the reference clock runs, in parts per million
+/// * `n_matched` - Returns the number of matched pulses
+impl ClockMapping {
+    /// Maps a target time to the reference clock
+    ///
+    /// # Arguments
+    ///
+    /// * `time` - The time on the target clock in seconds
+    ///
+    /// # Returns
+    ///
+    /// The time on the reference clock in seconds
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let onset = mapping.map_time(stimulus_log_onset);
+    /// ```
+    ///
+    pub fn map_time(&self, time: f64) -> f64 {
+        self.offset + self.rate * time
+    }
+
+    /// Maps target times, such as events, to the reference clock
+    ///
+    /// # Arguments
+    ///
+    /// * `times` - The times on the target clock in seconds
+    ///
+    /// # Returns
+    ///
+    /// The times on the reference clock in seconds
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let onsets = mapping.map_times(&stimulus_onsets);
+    /// ```
+    ///
+    pub fn map_times(&self, times: &[f64]) -> Vec<f64> {
+        times.iter().map(|&time| self.map_time(time)).collect()
+    }
+
+    /// Maps the start time and sampling rate of a signal to the reference clock
+    ///
+    /// # Arguments
+    ///
+    /// * `start_time` - The time of the first sample on the target clock in seconds
+    /// * `sampling_rate` - The sampling rate on the target clock in Hz
+    ///
+    /// # Returns
+    ///
+    /// The start time and sampling rate on the reference clock
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (start_time, sampling_rate) = mapping.map_signal(0.0, 30.0);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// With drift, a signal sampled at a nominal rate on one clock is sampled at a slightly
+    /// different rate on the other, so both are needed to place every sample
+    ///
+    pub fn map_signal(&self, start_time: f64, sampling_rate: f64) -> (f64, f64) {
+        (self.map_time(start_time), sampling_rate / self.rate)
+    }
+
+    /// Returns how much faster the reference clock runs, in parts per million
+    ///
+    /// # Returns
+    ///
+    /// `(rate - 1) * 1e6`, positive if a target second lasts longer than a reference second
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// println!("{:.1} ppm", mapping.drift_ppm());
+    /// ```
+    ///
+    pub fn drift_ppm(&self) -> f64 {
+        (self.rate - 1.0) * 1e6
+    }
+
+    /// Returns the number of matched pulses
+    ///
+    /// # Returns
+    ///
+    /// The number of pulses the mapping was fitted to
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// println!("{} pulses matched", mapping.n_matched());
+    /// ```
+    ///
+    pub fn n_matched(&self) -> usize {
+        self.matches.len()
+    }
+}
+
+/// Aligns a target clock to the reference clock by the sync pulses recorded on both
+///
+/// # Arguments
+///
+/// * `reference` - The times of the sync pulses on the reference clock in seconds, strictly increasing
+/// * `target` - The times of the same sync pulses on the target clock in seconds, strictly increasing
+/// * `options` - The clock model, matching tolerance, largest offset, minimum number of matches and residual threshold
+///
+/// # Returns
+///
+/// The mapping from target to reference times, or an error if the times are not finite and
+/// strictly increasing, an option is invalid, the offset is ambiguous or fewer than
+/// `min_matches` pulses match
+///
+/// # Examples
+///
+/// ```
+/// let mapping = align(&ephys_sync, &video_sync, &SyncOptions::default())?;
+/// println!("{} matched, {} ppm, {} s rms", mapping.n_matched(), mapping.drift_ppm(), mapping.rms_residual);
+/// ```
+///
+/// # Note
+///
+/// The pulses are matched in two steps. First, the offset is found by a vote: every pairing
+/// of a reference pulse with one of a few target pulses spread over the recording proposes
+/// an offset, scored by how many of the next target pulses it places within `tolerance`
+/// of a reference pulse. The pulses are then matched outwards from the winning pair, each
+/// mapped with the offset of the last match so that drift is followed, and one to one in
+/// order, so missed and spurious pulses on either side are left unmatched. Of two target
+/// pulses that fall on the same reference pulse, the closer one is matched. The model is
+/// fitted to the matches by least squares. Evenly spaced pulses fit any whole number of
+/// periods equally well, which is reported as an ambiguous offset unless `max_offset`
+/// rules out all but one; pulses at random intervals need no `max_offset`.
+///
+pub fn align(reference: &[f64], target: &[f64], options: &SyncOptions) -> Result<ClockMapping, ProcessingError> {
+    for (name, times) in [("reference", reference), ("target", target)] {
+        if times.iter().any(|time| !time.is_finite()) || times.windows(2).any(|pair| pair[1] <= pair[0]) {
+            return Err(ProcessingError::InvalidParameter(format!("The {} pulse times must be finite and strictly increasing", name)));
+        }
+    }
+    if !(options.tolerance > 0.0 && options.tolerance.is_finite()) || options.max_offset.is_some_and(|max_offset| max_offset.is_nan() || max_offset < 0.0) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "The tolerance must be positive and the largest offset non-negative, got {} and {:?}",
+            options.tolerance, options.max_offset
+        )));
+    }
+    if options.min_matches < 2 {
+        return Err(ProcessingError::InvalidParameter(format!("At least two pulses must match, got {}", options.min_matches)));
+    }
+    let too_few = |n_matched: usize| {
+        ProcessingError::InvalidParameter(format!("Only {} sync pulses match, fewer than the {} required", n_matched, options.min_matches))
+    };
+    if reference.len().min(target.len()) < options.min_matches {
+        return Err(too_few(reference.len().min(target.len())));
+    }
+
+    let (reference_anchor, target_anchor) = find_anchor(reference, target, options)?;
+    let matches = track(reference, target, reference_anchor, target_anchor, options.tolerance);
+    if matches.len() < options.min_matches {
+        return Err(too_few(matches.len()));
+    }
+
+    let (offset, rate) = fit(reference, target, &matches, options.model);
+    let residuals: Vec<f64> = matches.iter().map(|&(i, j)| reference[i] - (offset + rate * target[j])).collect();
+    let rms_residual = (residuals.iter().map(|residual| residual * residual).sum::<f64>() / residuals.len() as f64).sqrt();
+    let max_residual = residuals.iter().fold(0.0, |max: f64, residual| max.max(residual.abs()));
+    Ok(ClockMapping {
+        offset,
+        rate,
+        n_unmatched_reference: reference.len() - matches.len(),
+        n_unmatched_target: target.len() - matches.len(),
+        matches,
+        residuals,
+        rms_residual,
+        max_residual,
+        flagged: max_residual > options.max_residual,
+    })
+}
+
+/// The number of target pulses that propose offsets
+const N_ANCHORS: usize = 16;
+/// The number of target pulses after an anchor that score its offsets
+const N_SCORED: usize = 16;
+
+/// The score, offset and reference pulse of a pairing with a target anchor
+type Proposal = (usize, f64, usize);
+
+/// Returns the index of the reference pulse closest to a time
+fn nearest(times: &[f64], time: f64) -> usize {
+    let after = times.partition_point(|&other| other < time);
+    if after == times.len() || (after > 0 && time - times[after - 1] < times[after] - time) {
+        after - 1
+    } else {
+        after
+    }
+}
+
+/// Finds a matching pair of pulses by voting on the offset between the clocks
+fn find_anchor(reference: &[f64], target: &[f64], options: &SyncOptions) -> Result<(usize, usize), ProcessingError> {
+    let n_anchors = N_ANCHORS.min(target.len());
+    // The score, index and proposals of the best target anchor so far
+    let mut best: Option<(usize, usize, Vec<Proposal>)> = None;
+    for anchor in 0..n_anchors {
+        let j = anchor * (target.len() - 1) / (n_anchors - 1).max(1);
+        let proposals: Vec<Proposal> = reference
+            .iter()
+            .enumerate()
+            .map(|(i, &time)| (time - target[j], i))
+            .filter(|&(offset, _)| options.max_offset.is_none_or(|max_offset| offset.abs() <= max_offset))
+            .map(|(offset, i)| {
+                let score = target[j + 1..(j + 1 + N_SCORED).min(target.len())]
+                    .iter()
+                    .filter(|&&other| (reference[nearest(reference, other + offset)] - (other + offset)).abs() <= options.tolerance)
+                    .count();
+                (score, offset, i)
+            })
+            .collect();
+        let score = proposals.iter().map(|proposal| proposal.0).max().unwrap_or(0);
+        if best.as_ref().is_none_or(|&(best_score, _, _)| best_score < score) {
+            best = Some((score, j, proposals));
+        }
+    }
+    let Some((_, j, proposals)) = best else {
+        return Err(ProcessingError::InvalidParameter("No target pulse to match".to_string()));
+    };
+    let Some(&(score, offset, i)) = proposals.iter().max_by_key(|proposal| proposal.0).filter(|proposal| proposal.0 > 0) else {
+        return Err(ProcessingError::InvalidParameter(format!("No offset within {:?} s pairs more than one sync pulse", options.max_offset)));
+    };
+    // Another offset of the same anchor as good as the best one makes the matching a guess
+    if let Some(rival) = proposals.iter().find(|proposal| proposal.0 >= score && (proposal.1 - offset).abs() > options.tolerance) {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "The offset between the clocks is ambiguous, {} s and {} s fit equally well; set max_offset",
+            offset, rival.1
+        )));
+    }
+    Ok((i, j))
+}
+
+/// Matches the pulses in order outwards from an anchor pair, following the drift
+fn track(reference: &[f64], target: &[f64], reference_anchor: usize, target_anchor: usize, tolerance: f64) -> Vec<(usize, usize)> {
+    let anchor = (reference_anchor, target_anchor);
+    let mut matches = follow(reference, target, anchor, target_anchor + 1..target.len(), tolerance);
+    matches.extend(follow(reference, target, anchor, (0..target_anchor).rev(), tolerance));
+    matches.push(anchor);
+    matches.sort_unstable();
+    matches
+}
+
+/// Matches the target pulses in the given order, one way from an anchor pair
+///
+/// A target pulse that maps onto the reference pulse of the last match replaces it if it
+/// is closer, so a spurious pulse just before a real one does not take its match
+fn follow(reference: &[f64], target: &[f64], anchor: (usize, usize), order: impl Iterator<Item = usize>, tolerance: f64) -> Vec<(usize, usize)> {
+    let mut matches: Vec<(usize, usize)> = Vec::new();
+    // The offset that predicted the last match, and the one following from it
+    let mut predicting = reference[anchor.0] - target[anchor.1];
+    let mut offset = predicting;
+    let (mut last_i, mut last_j) = anchor;
+    for j in order {
+        let i = nearest(reference, target[j] + offset);
+        let error = (reference[i] - (target[j] + offset)).abs();
+        let beyond = if j > anchor.1 { i > last_i } else { i < last_i };
+        if beyond && error <= tolerance {
+            matches.push((i, j));
+            predicting = offset;
+        } else if i == last_i
+            && !matches.is_empty()
+            && (reference[i] - (target[j] + predicting)).abs() < (reference[i] - (target[last_j] + predicting)).abs()
+        {
+            *matches.last_mut().expect("A match was made") = (i, j);
+        } else {
+            continue;
+        }
+        offset = reference[i] - target[j];
+        (last_i, last_j) = (i, j);
+    }
+    matches
+}
+
+/// Fits the offset and rate of the mapping to the matched pulses by least squares
+fn fit(reference: &[f64], target: &[f64], matches: &[(usize, usize)], model: ClockModel) -> (f64, f64) {
+    let n = matches.len() as f64;
+    let mean_reference = matches.iter().map(|&(i, _)| reference[i]).sum::<f64>() / n;
+    let mean_target = matches.iter().map(|&(_, j)| target[j]).sum::<f64>() / n;
+    let rate = match model {
+        ClockModel::Offset => 1.0,
+        ClockModel::OffsetDrift => {
+            // Centered sums keep the precision of hour-long recordings
+            let (covariance, variance) = matches.iter().fold((0.0, 0.0), |(covariance, variance), &(i, j)| {
+                let dt = target[j] - mean_target;
+                (covariance + dt * (reference[i] - mean_reference), variance + dt * dt)
+            });
+            covariance / variance
+        }
+    };
+    (mean_reference - rate * mean_target, rate)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    /// An hour of pulses at random intervals on the reference clock, and on a target clock
+    /// 12.345 s behind and 50 ppm slow that misses 5% of them and logs 40 spurious ones
+    fn clocks() -> (Vec<f64>, Vec<f64>, impl Fn(f64) -> f64) {
+        let mut rng = SeededRng::new(12345);
+        let to_target = |time: f64| (time - 12.345) / (1.0 + 50e-6);
+        let mut truth = Vec::new();
+        let mut time = 0.0;
+        while time < 3600.0 {
+            time += 0.5 + rng.next_f64();
+            truth.push(time);
+        }
+        let jitter = |rng: &mut SeededRng| (rng.next_f64() - 0.5) * 1e-4;
+        let reference: Vec<f64> = truth.iter().map(|&time| time + jitter(&mut rng)).collect();
+        let mut target: Vec<f64> = Vec::new();
+        for &time in &truth {
+            if rng.next_f64() > 0.05 {
+                target.push(to_target(time) + jitter(&mut rng));
+            }
+        }
+        target.extend((0..40).map(|_| rng.next_f64() * 3600.0 - 12.0));
+        target.sort_by(f64::total_cmp);
+        (reference, target, to_target)
+    }
+
+    #[test]
+    fn recovers_a_drifting_clock_despite_missed_and_spurious_pulses() {
+        let (reference, target, to_target) = clocks();
+        let mapping = align(&reference, &target, &SyncOptions::default()).unwrap();
+        assert!((mapping.drift_ppm() - 50.0).abs() < 0.5, "{} ppm", mapping.drift_ppm());
+        assert!(!mapping.flagged);
+        // Each clock jitters by up to 50 us
+        assert!(mapping.max_residual < 2e-4);
+        // Spurious pulses within the tolerance of a real one can be matched in its place
+        let expected_matches = (reference.len() as f64 * 0.95) as usize;
+        assert!(mapping.n_matched().abs_diff(expected_matches) < 40, "{} matched", mapping.n_matched());
+        assert_eq!(mapping.n_unmatched_reference, reference.len() - mapping.n_matched());
+        assert_eq!(mapping.n_unmatched_target, target.len() - mapping.n_matched());
+        let worst = (0..=3600).map(|second| (mapping.map_time(to_target(second as f64)) - second as f64).abs()).fold(0.0, f64::max);
+        assert!(worst < 1e-5, "Mapping error of {} s", worst);
+    }
+
+    #[test]
+    fn an_offset_fit_to_a_drifting_clock_is_not_silent() {
+        // 50 ppm over an hour is 180 ms, far beyond the 1 ms residual threshold
+        let (reference, target, _) = clocks();
+        let options = SyncOptions { model: ClockModel::Offset, ..SyncOptions::default() };
+        if let Ok(mapping) = align(&reference, &target, &options) {
+            assert!(mapping.flagged);
+            assert_eq!(mapping.rate, 1.0);
+        }
+    }
+
+    #[test]
+    fn refuses_to_fit_too_few_matches() {
+        let (reference, target, _) = clocks();
+        assert!(align(&reference[..5], &target, &SyncOptions::default()).is_err());
+        assert!(align(&reference, &[3.0, 2.0], &SyncOptions::default()).is_err());
+    }
+
+    #[test]
+    fn evenly_spaced_pulses_need_a_largest_offset() {
+        let periodic: Vec<f64> = (0..100).map(|k| k as f64).collect();
+        let shifted: Vec<f64> = periodic.iter().map(|time| time + 0.3).collect();
+        assert!(align(&periodic, &shifted, &SyncOptions::default()).is_err());
+        let mapping = align(&periodic, &shifted, &SyncOptions { max_offset: Some(0.5), ..SyncOptions::default() }).unwrap();
+        assert!((mapping.offset + 0.3).abs() < 1e-9);
+        assert_eq!(mapping.n_matched(), 100);
+    }
+}
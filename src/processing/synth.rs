@@ -0,0 +1,693 @@
+// A module to generate seeded synthetic signals, spike trains and events for testing and benchmarking
+
+// Written by Amin Alam in 2024
+
+use std::f64::consts::PI;
+use std::io;
+use csv::StringRecord;
+use num_complex::Complex64;
+use rustfft::FftPlanner;
+use crate::data_io::csv::CsvWriter;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+use crate::processing::random::SeededRng;
+
+/// The number of samples an autoregressive process runs before its first returned sample, so that it starts from its stationary state
+const AR_BURN_IN: usize = 1000;
+
+/// A component summed into the channels of a SyntheticRecording
+///
+/// # Arguments
+///
+/// * `Sine` - A sinusoid, as from `sine`, with the same phase on every channel
+/// * `WhiteNoise` - Independent Gaussian white noise on every channel, as from `white_noise`
+/// * `PinkNoise` - Independent 1/f noise on every channel, as from `pink_noise`
+/// * `Ar` - An independent autoregressive process on every channel, as from `ar_process`
+/// * `Spikes` - Poisson spikes of a fixed amplitude and width in samples, as from `poisson_spike_train`, independent on every channel
+///
+/// # Examples
+///
+/// ```
+/// let alpha = Component::Sine { frequency: 10.0, amplitude: 20.0, phase: 0.0 };
+/// let background = Component::PinkNoise { std: 5.0 };
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Component {
+    Sine { frequency: f64, amplitude: f64, phase: f64 },
+    WhiteNoise { std: f64 },
+    PinkNoise { std: f64 },
+    Ar { coefficients: Vec<f64>, noise_std: f64 },
+    Spikes { rate: f64, amplitude: f64, width: usize },
+}
+
+/// A builder of multi-channel synthetic signals made of summed components
+///
+/// # Examples
+///
+/// ```
+/// let channels = SyntheticRecording::new(4, 60.0, 1000.0)
+///     .seed(7)
+///     .component(Component::PinkNoise { std: 5.0 })
+///     .channel_component(2, Component::Sine { frequency: 10.0, amplitude: 20.0, phase: 0.0 })
+///     .build()?;
+/// ```
+///
+/// # Note
+///
+/// Every random component of every channel draws from its own stream, derived from the
+/// seed and the order of the components, so the same builder always gives the same
+/// channels and adding a component does not change the noise of the earlier ones
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntheticRecording {
+    n_channels: usize,
+    duration: f64,
+    sampling_rate: f64,
+    seed: u64,
+    components: Vec<(Option<usize>, Component)>,
+}
+
+/// Implementation of the SyntheticRecording struct
+///
+/// # Methods
+///
+/// * `new` - Starts a recording with no components
+/// * `seed` - Sets the seed of the random components
+/// * `component` - Adds a component to every channel
+/// * `channel_component` - Adds a component to one channel
+/// * `names` - Returns the channel names `ch0`, `ch1`, ...
+/// * `build` - Generates the channels
+impl SyntheticRecording {
+    /// Starts a recording with no components
+    ///
+    /// # Arguments
+    ///
+    /// * `n_channels` - The number of channels
+    /// * `duration` - The duration in seconds
+    /// * `sampling_rate` - The sampling rate in Hz
+    ///
+    /// # Returns
+    ///
+    /// The SyntheticRecording, with seed 0, whose channels are all zero until components are added
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let recording = SyntheticRecording::new(8, 10.0, 30000.0);
+    /// ```
+    ///
+    pub fn new(n_channels: usize, duration: f64, sampling_rate: f64) -> Self {
+        Self { n_channels, duration, sampling_rate, seed: 0, components: Vec::new() }
+    }
+
+    /// Sets the seed of the random components
+    ///
+    /// # Arguments
+    ///
+    /// * `seed` - The seed
+    ///
+    /// # Returns
+    ///
+    /// The SyntheticRecording with the new seed
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let recording = recording.seed(42);
+    /// ```
+    ///
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Adds a component to every channel
+    ///
+    /// # Arguments
+    ///
+    /// * `component` - The component
+    ///
+    /// # Returns
+    ///
+    /// The SyntheticRecording with the component added
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let recording = recording.component(Component::WhiteNoise { std: 1.0 });
+    /// ```
+    ///
+    pub fn component(mut self, component: Component) -> Self {
+        self.components.push((None, component));
+        self
+    }
+
+    /// Adds a component to one channel
+    ///
+    /// # Arguments
+    ///
+    /// * `channel` - The index of the channel
+    /// * `component` - The component
+    ///
+    /// # Returns
+    ///
+    /// The SyntheticRecording with the component added. `build` fails if the channel does not exist.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let recording = recording.channel_component(0, Component::Sine { frequency: 50.0, amplitude: 3.0, phase: 0.0 });
+    /// ```
+    ///
+    pub fn channel_component(mut self, channel: usize, component: Component) -> Self {
+        self.components.push((Some(channel), component));
+        self
+    }
+
+    /// Returns the channel names `ch0`, `ch1`, ...
+    ///
+    /// # Returns
+    ///
+    /// The name of each channel
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// write_channels(&channels, &recording.names(), 1000.0, &mut writer)?;
+    /// ```
+    ///
+    pub fn names(&self) -> Vec<String> {
+        (0..self.n_channels).map(|channel| format!("ch{}", channel)).collect()
+    }
+
+    /// Generates the channels
+    ///
+    /// # Returns
+    ///
+    /// The samples of each channel, or an error if the duration or the sampling rate is
+    /// invalid, a component has invalid parameters or targets a missing channel
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let channels = recording.build()?;
+    /// ```
+    ///
+    pub fn build(&self) -> Result<Vec<Vec<f64>>, ProcessingError> {
+        let n = n_samples(self.duration, self.sampling_rate)?;
+        let mut channels = vec![vec![0.0; n]; self.n_channels];
+        let mut seeds = SeededRng::new(self.seed);
+        for (target, component) in &self.components {
+            if let Some(channel) = target.filter(|&channel| channel >= self.n_channels) {
+                return Err(ProcessingError::InvalidParameter(format!("Channel {} does not exist in {} channels", channel, self.n_channels)));
+            }
+            for (index, samples) in channels.iter_mut().enumerate() {
+                // Every channel takes a seed even when skipped, so a stream depends only on the component's position
+                let seed = seeds.next_u64();
+                if target.is_some_and(|channel| channel != index) {
+                    continue;
+                }
+                let values = self.generate(component, seed)?;
+                samples.iter_mut().zip(values).for_each(|(sample, value)| *sample += value);
+            }
+        }
+        Ok(channels)
+    }
+
+    /// Generates one component for one channel
+    fn generate(&self, component: &Component, seed: u64) -> Result<Vec<f64>, ProcessingError> {
+        let (duration, sampling_rate) = (self.duration, self.sampling_rate);
+        match component {
+            Component::Sine { frequency, amplitude, phase } => sine(*frequency, *amplitude, *phase, duration, sampling_rate),
+            Component::WhiteNoise { std } => white_noise(*std, duration, sampling_rate, seed),
+            Component::PinkNoise { std } => pink_noise(*std, duration, sampling_rate, seed),
+            Component::Ar { coefficients, noise_std } => ar_process(coefficients, *noise_std, duration, sampling_rate, seed),
+            Component::Spikes { rate, amplitude, width } => {
+                let mut samples = vec![0.0; n_samples(duration, sampling_rate)?];
+                for time in poisson_spike_train(*rate, duration, seed)? {
+                    let start = (time * sampling_rate) as usize;
+                    let end = (start + (*width).max(1)).min(samples.len());
+                    samples[start.min(end)..end].iter_mut().for_each(|sample| *sample += amplitude);
+                }
+                Ok(samples)
+            }
+        }
+    }
+}
+
+/// Generates a sinusoid
+///
+/// # Arguments
+///
+/// * `frequency` - The frequency in Hz
+/// * `amplitude` - The peak amplitude
+/// * `phase` - The phase at time 0 in radians
+/// * `duration` - The duration in seconds
+/// * `sampling_rate` - The sampling rate in Hz
+///
+/// # Returns
+///
+/// The `round(duration * sampling_rate)` samples of `amplitude * sin(2π f t + phase)`, or
+/// an error if the duration or the sampling rate is invalid
+///
+/// # Examples
+///
+/// ```
+/// let alpha = sine(10.0, 20.0, 0.0, 5.0, 250.0)?;
+/// ```
+///
+pub fn sine(frequency: f64, amplitude: f64, phase: f64, duration: f64, sampling_rate: f64) -> Result<Vec<f64>, ProcessingError> {
+    let n = n_samples(duration, sampling_rate)?;
+    Ok((0..n).map(|i| amplitude * (2.0 * PI * frequency * i as f64 / sampling_rate + phase).sin()).collect())
+}
+
+/// Generates Gaussian white noise
+///
+/// # Arguments
+///
+/// * `std` - The standard deviation
+/// * `duration` - The duration in seconds
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `seed` - The seed of the generator
+///
+/// # Returns
+///
+/// The zero-mean samples, or an error if the standard deviation is negative or the
+/// duration or the sampling rate is invalid
+///
+/// # Examples
+///
+/// ```
+/// let noise = white_noise(1.0, 10.0, 1000.0, 42)?;
+/// ```
+///
+pub fn white_noise(std: f64, duration: f64, sampling_rate: f64, seed: u64) -> Result<Vec<f64>, ProcessingError> {
+    validate_std(std)?;
+    let n = n_samples(duration, sampling_rate)?;
+    let mut rng = SeededRng::new(seed);
+    Ok((0..n).map(|_| std * rng.next_gaussian()).collect())
+}
+
+/// Generates pink noise, whose power falls as 1/f
+///
+/// # Arguments
+///
+/// * `std` - The standard deviation
+/// * `duration` - The duration in seconds
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `seed` - The seed of the generator
+///
+/// # Returns
+///
+/// The zero-mean samples, scaled to exactly `std`, or an error if the standard deviation
+/// is negative or the duration or the sampling rate is invalid
+///
+/// # Examples
+///
+/// ```
+/// let background = pink_noise(5.0, 60.0, 1000.0, 42)?;
+/// ```
+///
+/// # Note
+///
+/// White Gaussian noise is filtered in the frequency domain by `1 / sqrt(f)`, which gives a
+/// spectral slope of -1 in log-log over the whole band from `1 / duration` to the Nyquist
+/// frequency, with the mean removed. The noise is periodic over the duration.
+///
+pub fn pink_noise(std: f64, duration: f64, sampling_rate: f64, seed: u64) -> Result<Vec<f64>, ProcessingError> {
+    let white = white_noise(1.0, duration, sampling_rate, seed)?;
+    let n = white.len();
+    if n < 2 {
+        return Ok(vec![0.0; n]);
+    }
+    let mut planner = FftPlanner::<f64>::new();
+    let mut spectrum: Vec<Complex64> = white.iter().map(|&value| Complex64::new(value, 0.0)).collect();
+    planner.plan_fft_forward(n).process(&mut spectrum);
+    spectrum[0] = Complex64::new(0.0, 0.0);
+    for (k, value) in spectrum.iter_mut().enumerate().skip(1) {
+        *value /= (k.min(n - k) as f64).sqrt();
+    }
+    planner.plan_fft_inverse(n).process(&mut spectrum);
+
+    let mut samples: Vec<f64> = spectrum.iter().map(|value| value.re).collect();
+    let mean = samples.iter().sum::<f64>() / n as f64;
+    let spread = (samples.iter().map(|sample| (sample - mean).powi(2)).sum::<f64>() / n as f64).sqrt();
+    let scale = if spread > 0.0 { std / spread } else { 0.0 };
+    samples.iter_mut().for_each(|sample| *sample = (*sample - mean) * scale);
+    Ok(samples)
+}
+
+/// Generates the spike times of a homogeneous Poisson process
+///
+/// # Arguments
+///
+/// * `rate` - The firing rate in Hz
+/// * `duration` - The duration in seconds
+/// * `seed` - The seed of the generator
+///
+/// # Returns
+///
+/// The increasing spike times in `[0, duration)`, or an error if the rate or the duration
+/// is negative or not finite
+///
+/// # Examples
+///
+/// ```
+/// let spikes = poisson_spike_train(8.0, 600.0, 42)?;
+/// ```
+///
+/// # Note
+///
+/// The intervals are independent and exponentially distributed with mean `1 / rate`, so
+/// the number of spikes is Poisson distributed with mean `rate * duration`
+///
+pub fn poisson_spike_train(rate: f64, duration: f64, seed: u64) -> Result<Vec<f64>, ProcessingError> {
+    if !(rate.is_finite() && rate >= 0.0) {
+        return Err(ProcessingError::InvalidParameter(format!("Rate must be non-negative, got {}", rate)));
+    }
+    validate_duration(duration)?;
+    let mut rng = SeededRng::new(seed);
+    let mut spikes = Vec::new();
+    if rate == 0.0 {
+        return Ok(spikes);
+    }
+    let mut time = 0.0;
+    loop {
+        time -= (1.0 - rng.next_f64()).ln() / rate;
+        if time >= duration {
+            return Ok(spikes);
+        }
+        spikes.push(time);
+    }
+}
+
+/// Generates an autoregressive process
+///
+/// # Arguments
+///
+/// * `coefficients` - The coefficients `a_1, ..., a_p` of `x[t] = a_1 x[t - 1] + ... + a_p x[t - p] + e[t]`
+/// * `noise_std` - The standard deviation of the Gaussian innovations `e[t]`
+/// * `duration` - The duration in seconds
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `seed` - The seed of the generator
+///
+/// # Returns
+///
+/// The samples, or an error if the noise standard deviation is negative or the duration or
+/// the sampling rate is invalid
+///
+/// # Examples
+///
+/// ```
+/// // A damped 10 Hz oscillation at 250 Hz
+/// let radius: f64 = 0.95;
+/// let angle = 2.0 * std::f64::consts::PI * 10.0 / 250.0;
+/// let lfp = ar_process(&[2.0 * radius * angle.cos(), -radius * radius], 1.0, 30.0, 250.0, 42)?;
+/// ```
+///
+/// # Note
+///
+/// The process runs for 1000 samples before the first returned one so that it starts near
+/// its stationary state. The coefficients are not checked for stationarity: for an
+/// explosive process the samples grow without bound.
+///
+pub fn ar_process(coefficients: &[f64], noise_std: f64, duration: f64, sampling_rate: f64, seed: u64) -> Result<Vec<f64>, ProcessingError> {
+    validate_std(noise_std)?;
+    let n = n_samples(duration, sampling_rate)?;
+    let mut rng = SeededRng::new(seed);
+    let mut samples: Vec<f64> = Vec::with_capacity(AR_BURN_IN + n);
+    for t in 0..AR_BURN_IN + n {
+        let past: f64 = coefficients.iter().zip(samples[..t].iter().rev()).map(|(a, x)| a * x).sum();
+        samples.push(past + noise_std * rng.next_gaussian());
+    }
+    Ok(samples.split_off(AR_BURN_IN))
+}
+
+/// Generates random event times at least a minimum spacing apart
+///
+/// # Arguments
+///
+/// * `n` - The number of events
+/// * `duration` - The duration in seconds
+/// * `min_spacing` - The minimum interval between consecutive events in seconds
+/// * `seed` - The seed of the generator
+///
+/// # Returns
+///
+/// The `n` increasing event times in `[0, duration)`, or an error if the events cannot fit
+/// in the duration at that spacing
+///
+/// # Examples
+///
+/// ```
+/// let stimuli = synthetic_events(100, 600.0, 2.0, 42)?;
+/// ```
+///
+/// # Note
+///
+/// The times are uniform over every arrangement that respects the spacing: `n` uniform
+/// times in `[0, duration - (n - 1) * min_spacing)` are sorted and the i-th is shifted by
+/// `i * min_spacing`
+///
+pub fn synthetic_events(n: usize, duration: f64, min_spacing: f64, seed: u64) -> Result<Vec<f64>, ProcessingError> {
+    validate_duration(duration)?;
+    if !(min_spacing.is_finite() && min_spacing >= 0.0) {
+        return Err(ProcessingError::InvalidParameter(format!("Minimum spacing must be non-negative, got {}", min_spacing)));
+    }
+    if n == 0 {
+        return Ok(Vec::new());
+    }
+    let free = duration - (n - 1) as f64 * min_spacing;
+    if free <= 0.0 {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "{} events {} s apart do not fit in {} s",
+            n, min_spacing, duration
+        )));
+    }
+    let mut rng = SeededRng::new(seed);
+    let mut times: Vec<f64> = (0..n).map(|_| rng.next_f64() * free).collect();
+    times.sort_by(f64::total_cmp);
+    Ok(times.iter().enumerate().map(|(i, time)| time + i as f64 * min_spacing).collect())
+}
+
+/// Writes channels as `time,<names>` rows, e.g. to create a fixture for `SignalSource::from_csv`
+///
+/// # Arguments
+///
+/// * `channels` - The samples of each channel
+/// * `names` - The name of each channel
+/// * `sampling_rate` - The sampling rate in Hz, giving the time of sample `i` as `i / sampling_rate`
+/// * `output` - The writer of the csv file
+///
+/// # Returns
+///
+/// Nothing, or an error if the names do not match the channels, the channels differ in
+/// length or the file cannot be written
+///
+/// # Examples
+///
+/// ```
+/// let recording = SyntheticRecording::new(2, 10.0, 1000.0).component(Component::WhiteNoise { std: 1.0 });
+/// write_channels(&recording.build()?, &recording.names(), 1000.0, &mut CsvWriter::create("fixture.csv")?)?;
+/// ```
+///
+/// # Note
+///
+/// The numbers use the float format of the writer, which round-trips exactly by default
+///
+pub fn write_channels(channels: &[Vec<f64>], names: &[String], sampling_rate: f64, output: &mut CsvWriter) -> io::Result<()> {
+    let length = channels.first().map_or(0, |channel| channel.len());
+    if channels.len() != names.len() || channels.iter().any(|channel| channel.len() != length) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "Channels must match the names and have equal lengths"));
+    }
+    let mut header = vec!["time".to_string()];
+    header.extend(names.iter().cloned());
+    output.write_record(&StringRecord::from(header))?;
+    let float_format = output.float_format();
+    for i in 0..length {
+        let mut record = vec![float_format.format(i as f64 / sampling_rate)];
+        record.extend(channels.iter().map(|channel| float_format.format(channel[i])));
+        output.write_record(&StringRecord::from(record))?;
+    }
+    output.flush()
+}
+
+/// Returns the number of samples in a duration, checking both parameters
+fn n_samples(duration: f64, sampling_rate: f64) -> Result<usize, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    validate_duration(duration)?;
+    Ok((duration * sampling_rate).round() as usize)
+}
+
+fn validate_duration(duration: f64) -> Result<(), ProcessingError> {
+    if !(duration.is_finite() && duration >= 0.0) {
+        return Err(ProcessingError::InvalidParameter(format!("Duration must be non-negative, got {} s", duration)));
+    }
+    Ok(())
+}
+
+fn validate_std(std: f64) -> Result<(), ProcessingError> {
+    if !(std.is_finite() && std >= 0.0) {
+        return Err(ProcessingError::InvalidParameter(format!("Standard deviation must be non-negative, got {}", std)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mean_and_variance(values: &[f64]) -> (f64, f64) {
+        let mean = values.iter().sum::<f64>() / values.len() as f64;
+        (mean, values.iter().map(|value| (value - mean).powi(2)).sum::<f64>() / values.len() as f64)
+    }
+
+    #[test]
+    fn sines_have_the_requested_samples() {
+        let samples = sine(5.0, 2.0, PI / 2.0, 1.0 / 3.0, 100.0).unwrap();
+        assert_eq!(samples.len(), 33);
+        assert_eq!(samples[0], 2.0);
+        assert!(samples[5].abs() < 1e-12 && (samples[10] + 2.0).abs() < 1e-12 && (samples[20] - 2.0).abs() < 1e-12);
+        assert!(sine(5.0, 1.0, 0.0, 0.0, 100.0).unwrap().is_empty());
+        assert!(sine(5.0, 1.0, 0.0, 1.0, 0.0).is_err());
+        assert!(sine(5.0, 1.0, 0.0, f64::NAN, 100.0).is_err());
+    }
+
+    #[test]
+    fn white_noise_is_seeded_with_the_requested_spread() {
+        let noise = white_noise(3.0, 100.0, 1000.0, 7).unwrap();
+        assert_eq!(noise, white_noise(3.0, 100.0, 1000.0, 7).unwrap());
+        assert_ne!(noise, white_noise(3.0, 100.0, 1000.0, 8).unwrap());
+        let (mean, variance) = mean_and_variance(&noise);
+        assert!(mean.abs() < 0.03 && (variance.sqrt() - 3.0).abs() < 0.03, "{} {}", mean, variance);
+        assert!(white_noise(-1.0, 1.0, 1000.0, 7).is_err());
+    }
+
+    #[test]
+    fn pink_noise_has_a_spectral_slope_of_minus_one() {
+        let noise = pink_noise(5.0, 65.536, 1000.0, 186).unwrap();
+        assert_eq!(noise, pink_noise(5.0, 65.536, 1000.0, 186).unwrap());
+        let (mean, variance) = mean_and_variance(&noise);
+        assert!(mean.abs() < 1e-12 && (variance.sqrt() - 5.0).abs() < 1e-9);
+
+        // A least-squares line through the log periodogram over every bin up to Nyquist
+        let n = noise.len();
+        let mut spectrum: Vec<Complex64> = noise.iter().map(|&value| Complex64::new(value, 0.0)).collect();
+        FftPlanner::<f64>::new().plan_fft_forward(n).process(&mut spectrum);
+        let points: Vec<(f64, f64)> = (1..n / 2).map(|k| ((k as f64).ln(), spectrum[k].norm_sqr().ln())).collect();
+        let (mean_x, mean_y) = (points.iter().map(|p| p.0).sum::<f64>() / points.len() as f64, points.iter().map(|p| p.1).sum::<f64>() / points.len() as f64);
+        let slope = points.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum::<f64>() / points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum::<f64>();
+        assert!((slope + 1.0).abs() < 0.02, "{}", slope);
+        assert_eq!(pink_noise(1.0, 0.001, 1000.0, 1).unwrap(), [0.0]);
+    }
+
+    #[test]
+    fn poisson_counts_follow_the_poisson_distribution() {
+        let (rate, duration, trains) = (5.0, 2.0, 4000);
+        let counts: Vec<usize> = (0..trains as u64).map(|seed| {
+            let spikes = poisson_spike_train(rate, duration, seed).unwrap();
+            assert!(spikes.windows(2).all(|pair| pair[0] < pair[1]) && spikes.iter().all(|&time| (0.0..duration).contains(&time)));
+            spikes.len()
+        }).collect();
+        let (mean, variance) = mean_and_variance(&counts.iter().map(|&count| count as f64).collect::<Vec<_>>());
+        assert!((mean - 10.0).abs() < 0.15 && (variance / mean - 1.0).abs() < 0.08, "{} {}", mean, variance);
+
+        // The frequency of every count stays near the probability mass e^-10 10^k / k!
+        let mut probability = (-10.0f64).exp();
+        for k in 0..30 {
+            let frequency = counts.iter().filter(|&&count| count == k).count() as f64 / trains as f64;
+            assert!((frequency - probability).abs() < 0.015, "{} {} {}", k, frequency, probability);
+            probability *= 10.0 / (k + 1) as f64;
+        }
+
+        let long = poisson_spike_train(rate, 2000.0, 1).unwrap();
+        let intervals: Vec<f64> = long.windows(2).map(|pair| pair[1] - pair[0]).collect();
+        let (mean, variance) = mean_and_variance(&intervals);
+        assert!((mean - 0.2).abs() < 0.006 && (variance.sqrt() / mean - 1.0).abs() < 0.03, "{} {}", mean, variance);
+        assert!(poisson_spike_train(0.0, 10.0, 1).unwrap().is_empty());
+        assert!(poisson_spike_train(-1.0, 10.0, 1).is_err() && poisson_spike_train(1.0, -10.0, 1).is_err());
+    }
+
+    #[test]
+    fn autoregressive_processes_reach_their_stationary_moments() {
+        let samples = ar_process(&[0.9], 1.0, 100.0, 1000.0, 3).unwrap();
+        assert_eq!(samples.len(), 100_000);
+        assert_eq!(samples, ar_process(&[0.9], 1.0, 100.0, 1000.0, 3).unwrap());
+        // x[t] = 0.9 x[t - 1] + e[t] has a variance of 1 / (1 - 0.81) and a lag-one correlation of 0.9
+        let (mean, variance) = mean_and_variance(&samples);
+        let lag_one = samples.windows(2).map(|pair| (pair[0] - mean) * (pair[1] - mean)).sum::<f64>() / (samples.len() - 1) as f64 / variance;
+        assert!((variance - 1.0 / 0.19).abs() < 0.3 && (lag_one - 0.9).abs() < 0.01, "{} {}", variance, lag_one);
+        // Without coefficients the process is its innovations
+        assert_eq!(ar_process(&[], 2.0, 1.0, 100.0, 3).unwrap().len(), 100);
+        assert!(ar_process(&[0.5], -1.0, 1.0, 100.0, 3).is_err());
+    }
+
+    #[test]
+    fn events_keep_their_spacing_inside_the_duration() {
+        let events = synthetic_events(100, 600.0, 2.0, 42).unwrap();
+        assert_eq!(events, synthetic_events(100, 600.0, 2.0, 42).unwrap());
+        assert_eq!(events.len(), 100);
+        assert!(events.windows(2).all(|pair| pair[1] - pair[0] >= 2.0 - 1e-9));
+        assert!(events[0] >= 0.0 && events[99] < 600.0);
+        // With almost no room to spare the events sit nearly on a 2 s grid
+        let packed = synthetic_events(10, 18.01, 2.0, 1).unwrap();
+        assert!(packed.iter().enumerate().all(|(i, time)| (time - 2.0 * i as f64).abs() < 0.01), "{:?}", packed);
+        assert!(synthetic_events(0, 1.0, 5.0, 1).unwrap().is_empty());
+        assert_eq!(synthetic_events(11, 20.0, 2.0, 1).unwrap_err().to_string(), "Invalid parameter: 11 events 2 s apart do not fit in 20 s");
+        assert!(synthetic_events(2, 10.0, -1.0, 1).is_err());
+    }
+
+    #[test]
+    fn recordings_sum_components_from_stable_streams() {
+        let noisy = SyntheticRecording::new(3, 2.0, 250.0).seed(9).component(Component::WhiteNoise { std: 1.0 });
+        let channels = noisy.build().unwrap();
+        assert_eq!(noisy.names(), ["ch0", "ch1", "ch2"]);
+        assert_eq!(channels, noisy.build().unwrap());
+        assert_ne!(channels, noisy.clone().seed(10).build().unwrap());
+
+        // Each channel draws its own stream, in channel order, from the seed
+        let mut seeds = SeededRng::new(9);
+        for channel in &channels {
+            assert_eq!(*channel, white_noise(1.0, 2.0, 250.0, seeds.next_u64()).unwrap());
+        }
+
+        // A later component leaves the earlier noise alone and lands on its channel only
+        let alpha = Component::Sine { frequency: 10.0, amplitude: 4.0, phase: 0.0 };
+        let layered = noisy.clone().channel_component(1, alpha).component(Component::PinkNoise { std: 0.0 }).build().unwrap();
+        let wave = sine(10.0, 4.0, 0.0, 2.0, 250.0).unwrap();
+        assert_eq!(layered[0], channels[0]);
+        assert_eq!(layered[2], channels[2]);
+        assert!(layered[1].iter().zip(&channels[1]).zip(&wave).all(|((sum, noise), wave)| (sum - noise - wave).abs() < 1e-12));
+
+        let spikes = SyntheticRecording::new(1, 10.0, 1000.0).component(Component::Spikes { rate: 20.0, amplitude: 50.0, width: 3 }).build().unwrap();
+        let times = poisson_spike_train(20.0, 10.0, SeededRng::new(0).next_u64()).unwrap();
+        assert!(spikes[0].iter().all(|&value| value % 50.0 == 0.0));
+        for time in times {
+            assert!(spikes[0][(time * 1000.0) as usize] >= 50.0);
+        }
+
+        let error = SyntheticRecording::new(2, 1.0, 100.0).channel_component(2, Component::WhiteNoise { std: 1.0 }).build().unwrap_err();
+        assert_eq!(error.to_string(), "Invalid parameter: Channel 2 does not exist in 2 channels");
+        assert!(SyntheticRecording::new(2, 1.0, 100.0).component(Component::WhiteNoise { std: -1.0 }).build().is_err());
+        assert_eq!(SyntheticRecording::new(2, 1.0, 100.0).build().unwrap(), [vec![0.0; 100], vec![0.0; 100]]);
+    }
+
+    #[test]
+    fn channels_write_out_as_an_exact_fixture() {
+        let recording = SyntheticRecording::new(2, 0.1, 100.0).seed(4).component(Component::PinkNoise { std: 1.0 });
+        let channels = recording.build().unwrap();
+        let path = std::env::temp_dir().join(format!("neurorust-synth-{}-fixture.csv", std::process::id()));
+        write_channels(&channels, &recording.names(), 100.0, &mut CsvWriter::create(path.to_str().unwrap()).unwrap()).unwrap();
+        let text = std::fs::read_to_string(&path).unwrap();
+        let mut lines = text.lines();
+        assert_eq!(lines.next(), Some("time,ch0,ch1"));
+        let rows: Vec<Vec<f64>> = lines.map(|line| line.split(',').map(|field| field.parse().unwrap()).collect()).collect();
+        assert_eq!(rows.len(), 10);
+        for (i, row) in rows.iter().enumerate() {
+            assert_eq!(*row, [i as f64 / 100.0, channels[0][i], channels[1][i]]);
+        }
+        let mismatched = write_channels(&channels, &recording.names()[..1], 100.0, &mut CsvWriter::create(path.to_str().unwrap()).unwrap());
+        assert_eq!(mismatched.unwrap_err().kind(), io::ErrorKind::InvalidInput);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,289 @@
+// A module to validate the timestamps of sampled data
+
+// Written by Amin Alam in 2024
+
+use crate::processing::robust;
+
+/// A single irregular step between two consecutive timestamps
+///
+/// # Arguments
+///
+/// * `index` - The index of the first sample after the irregular step
+/// * `time` - The timestamp of that sample
+/// * `interval` - The measured interval between the sample and its predecessor
+/// * `missing_samples` - The number of samples that would fit into the step at the nominal rate (zero for overlaps)
+/// * `missing_duration` - The duration in excess of the nominal interval (zero for overlaps)
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimingIrregularity {
+    pub index: usize,
+    pub time: f64,
+    pub interval: f64,
+    pub missing_samples: usize,
+    pub missing_duration: f64,
+}
+
+/// A report on the regularity of a series of timestamps
+///
+/// # Arguments
+///
+/// * `n_samples` - The number of timestamps that were checked
+/// * `nominal_interval` - The median interval between consecutive timestamps
+/// * `nominal_rate` - The sampling rate implied by the nominal interval
+/// * `tolerance_fraction` - The tolerated deviation from the nominal interval, as a fraction of it
+/// * `gaps` - The steps that are longer than tolerated (e.g. dropped samples)
+/// * `overlaps` - The steps that are shorter than tolerated, including backwards steps
+/// * `total_missing_duration` - The summed `missing_duration` of all gaps
+/// * `is_monotonic` - Whether the timestamps are strictly increasing
+///
+/// # Examples
+///
+/// ```
+/// let report = validate_timing(&times, 0.1);
+/// if !report.is_regular() {
+///     println!("{} gaps, {} s missing", report.gaps.len(), report.total_missing_duration);
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimingReport {
+    pub n_samples: usize,
+    pub nominal_interval: f64,
+    pub nominal_rate: f64,
+    pub tolerance_fraction: f64,
+    pub gaps: Vec<TimingIrregularity>,
+    pub overlaps: Vec<TimingIrregularity>,
+    pub total_missing_duration: f64,
+    pub is_monotonic: bool,
+}
+
+/// What `Recording::from_csv_with_time` does when the timestamps of a file are not regular
+///
+/// # Arguments
+///
+/// * `Error` - Returns an error describing the first gap or overlap
+/// * `Warn` - Keeps the samples as they are, at the nominal rate, and writes a warning to the standard error
+/// * `FillNan` - Inserts as many NaN samples as fit into each gap, so the samples are on a regular time base; an overlap is still an error
+///
+/// # Examples
+///
+/// ```
+/// let (recording, report) = Recording::<f64>::from_csv_with_time(&mut csv_io, "time", 0.1, TimingPolicy::FillNan)?;
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TimingPolicy {
+    #[default]
+    Error,
+    Warn,
+    FillNan,
+}
+
+/// Implementation of the TimingReport struct
+///
+/// # Methods
+///
+/// * `is_regular` - Checks that there are no gaps or overlaps
+/// * `has_nominal_interval` - Checks that the nominal interval is usable for grading the steps
+/// * `total_missing_samples` - Counts the samples missing in all gaps
+impl TimingReport {
+    /// Checks that the timestamps are monotonic and free of gaps and overlaps
+    ///
+    /// # Returns
+    ///
+    /// `true` if the timestamps are regular within the tolerance
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert!(validate_timing(&[0.0, 0.001, 0.002], 0.1).is_regular());
+    /// ```
+    ///
+    pub fn is_regular(&self) -> bool {
+        self.is_monotonic && self.gaps.is_empty() && self.overlaps.is_empty()
+    }
+
+    /// Checks that the nominal interval is finite and positive, so the steps could be graded
+    ///
+    /// # Returns
+    ///
+    /// `false` if there were fewer than two timestamps, or most steps repeat a timestamp or go backwards
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert!(!validate_timing(&[0.0, 0.0, 0.0, 0.1], 0.1).has_nominal_interval());
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// Without a nominal interval no gaps are reported, and only the steps that do not
+    /// increase are listed as overlaps
+    ///
+    pub fn has_nominal_interval(&self) -> bool {
+        self.nominal_interval.is_finite() && self.nominal_interval > 0.0
+    }
+
+    /// Counts the samples missing in all gaps
+    ///
+    /// # Returns
+    ///
+    /// The summed `missing_samples` of all gaps
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let missing = report.total_missing_samples();
+    /// ```
+    ///
+    pub fn total_missing_samples(&self) -> usize {
+        self.gaps.iter().map(|gap| gap.missing_samples).sum()
+    }
+}
+
+/// Validates the regularity of a series of timestamps
+///
+/// # Arguments
+///
+/// * `times` - The timestamps in seconds, one per sample
+/// * `tolerance_fraction` - The tolerated deviation from the nominal interval, as a fraction of it (e.g. `0.1`)
+///
+/// # Returns
+///
+/// A TimingReport describing the nominal rate and every irregular step
+///
+/// # Examples
+///
+/// ```
+/// let report = validate_timing(&[0.0, 0.001, 0.002, 0.053, 0.054], 0.1);
+/// assert_eq!(report.gaps[0].index, 3);
+/// assert_eq!(report.gaps[0].missing_samples, 50);
+/// ```
+///
+/// # Note
+///
+/// The nominal interval is the median of the intervals, so isolated gaps do not bias it.
+/// With fewer than two timestamps the nominal interval and rate are NaN. If the nominal
+/// interval is not positive, e.g. because most timestamps repeat, no gaps can be measured
+/// and `has_nominal_interval` is false.
+///
+pub fn validate_timing(times: &[f64], tolerance_fraction: f64) -> TimingReport {
+    let intervals: Vec<f64> = times.windows(2).map(|pair| pair[1] - pair[0]).collect();
+    let nominal_interval = median(&intervals);
+    let gradable = nominal_interval.is_finite() && nominal_interval > 0.0;
+    let tolerance = tolerance_fraction.abs() * nominal_interval;
+
+    let mut gaps = Vec::new();
+    let mut overlaps = Vec::new();
+    for (i, &interval) in intervals.iter().enumerate() {
+        let index = i + 1;
+        let deviation = interval - nominal_interval;
+        if gradable && deviation > tolerance {
+            let missing_samples = ((interval / nominal_interval).round() as usize).saturating_sub(1);
+            gaps.push(TimingIrregularity {
+                index,
+                time: times[index],
+                interval,
+                missing_samples,
+                missing_duration: deviation,
+            });
+        } else if (gradable && deviation < -tolerance) || interval <= 0.0 {
+            overlaps.push(TimingIrregularity {
+                index,
+                time: times[index],
+                interval,
+                missing_samples: 0,
+                missing_duration: 0.0,
+            });
+        }
+    }
+
+    TimingReport {
+        n_samples: times.len(),
+        nominal_interval,
+        nominal_rate: 1.0 / nominal_interval,
+        tolerance_fraction,
+        total_missing_duration: gaps.iter().map(|gap| gap.missing_duration).sum(),
+        is_monotonic: intervals.iter().all(|&interval| interval > 0.0),
+        gaps,
+        overlaps,
+    }
+}
+
+/// Fills the gaps found by `validate_timing` with NaN samples
+///
+/// # Arguments
+///
+/// * `samples` - The samples belonging to the validated timestamps
+/// * `report` - The TimingReport of the timestamps
+///
+/// # Returns
+///
+/// A vector of samples on a regular time base, with `missing_samples` NaNs inserted before every gap
+///
+/// # Examples
+///
+/// ```
+/// let report = validate_timing(&times, 0.1);
+/// let regular = fill_gaps_with_nan(&samples, &report);
+/// ```
+///
+/// # Note
+///
+/// Overlaps are left untouched, so the result is only regular if the report has none
+///
+pub fn fill_gaps_with_nan(samples: &[f64], report: &TimingReport) -> Vec<f64> {
+    let mut filled = Vec::with_capacity(samples.len() + report.total_missing_samples());
+    let mut start = 0;
+    for gap in report.gaps.iter().filter(|gap| gap.index <= samples.len()) {
+        filled.extend_from_slice(&samples[start..gap.index]);
+        filled.extend(std::iter::repeat_n(f64::NAN, gap.missing_samples));
+        start = gap.index;
+    }
+    filled.extend_from_slice(&samples[start..]);
+    filled
+}
+
+/// Computes the median of the finite values, NaN if there are none
+pub(crate) fn median(values: &[f64]) -> f64 {
+    let finite: Vec<f64> = values.iter().copied().filter(|value| value.is_finite()).collect();
+    robust::median(&finite)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_the_samples_missing_in_a_gap() {
+        let report = validate_timing(&[0.0, 0.001, 0.002, 0.053, 0.054], 0.1);
+        assert!(report.has_nominal_interval());
+        assert_eq!(report.gaps.len(), 1);
+        assert_eq!(report.gaps[0].index, 3);
+        assert_eq!(report.gaps[0].missing_samples, 50);
+        let filled = fill_gaps_with_nan(&[1.0, 2.0, 3.0, 4.0, 5.0], &report);
+        assert_eq!(filled.len(), 55);
+        assert!(filled[3..53].iter().all(|value| value.is_nan()));
+    }
+
+    #[test]
+    fn repeated_timestamps_have_no_nominal_interval() {
+        let times = [0.0, 0.0, 0.0, 0.0, 1.0, 1.0, 1.0, 2.0];
+        let report = validate_timing(&times, 0.1);
+        assert_eq!(report.nominal_interval, 0.0);
+        assert!(!report.has_nominal_interval());
+        assert!(!report.is_regular());
+        assert!(report.gaps.is_empty());
+        assert_eq!(report.total_missing_samples(), 0);
+        assert_eq!(report.overlaps.len(), 5);
+        assert_eq!(fill_gaps_with_nan(&times, &report).len(), times.len());
+    }
+
+    #[test]
+    fn backward_timestamps_have_no_nominal_interval() {
+        let report = validate_timing(&[3.0, 2.0, 1.0, 0.0, 5.0], 0.1);
+        assert!(!report.has_nominal_interval());
+        assert!(report.gaps.is_empty());
+        assert_eq!(report.overlaps.len(), 3);
+    }
+}
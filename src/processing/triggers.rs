@@ -0,0 +1,443 @@
+// A module to decode digital trigger words into labeled events
+
+// Written by Amin Alam in 2024
+
+use std::collections::BTreeMap;
+use std::io;
+use csv::StringRecord;
+use crate::data_io::csv::{CsvIO, CsvReader};
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+
+/// The level of a digital line while a pulse is on
+///
+/// # Arguments
+///
+/// * `ActiveHigh` - A pulse sets the bits of its code, and the idle word is 0
+/// * `ActiveLow` - A pulse clears the bits of its code, and the idle word has all bits of the mask set
+///
+/// # Examples
+///
+/// ```
+/// let options = TriggerOptions { polarity: TriggerPolarity::ActiveLow, ..TriggerOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TriggerPolarity {
+    ActiveHigh,
+    ActiveLow,
+}
+
+/// How `decode` turns the words into events
+///
+/// # Arguments
+///
+/// * `Word` - Every change of the whole word to a nonzero code starts an event with that code
+/// * `Bits` - Every pulse on each line is an event with the code `1 << line`, and pulses on different lines may overlap
+///
+/// # Examples
+///
+/// ```
+/// let options = TriggerOptions { mode: TriggerMode::Bits, ..TriggerOptions::default() };
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TriggerMode {
+    Word,
+    Bits,
+}
+
+/// The options of `decode`
+///
+/// # Arguments
+///
+/// * `polarity` - The level of the lines while a pulse is on, `ActiveHigh` by default
+/// * `mode` - Whether whole-word codes or individual lines are decoded, `Word` by default
+/// * `mask` - The bits of the word that carry triggers, the lower 16 by default
+/// * `debounce` - The number of samples a new value must hold to count as a change, 1 (no debouncing) by default
+/// * `min_width` - The number of samples below which completed pulses are dropped, 1 by default
+/// * `labels` - The label of each code; codes without one are labeled with their number
+///
+/// # Examples
+///
+/// ```
+/// let labels = read_trigger_labels(&mut CsvReader::open("codes.csv")?)?;
+/// let options = TriggerOptions { debounce: 3, min_width: 5, labels, ..TriggerOptions::default() };
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TriggerOptions {
+    pub polarity: TriggerPolarity,
+    pub mode: TriggerMode,
+    pub mask: u64,
+    pub debounce: usize,
+    pub min_width: usize,
+    pub labels: BTreeMap<u64, String>,
+}
+
+impl Default for TriggerOptions {
+    fn default() -> Self {
+        Self {
+            polarity: TriggerPolarity::ActiveHigh,
+            mode: TriggerMode::Word,
+            mask: 0xFFFF,
+            debounce: 1,
+            min_width: 1,
+            labels: BTreeMap::new(),
+        }
+    }
+}
+
+/// An event decoded from a trigger word
+///
+/// # Arguments
+///
+/// * `sample` - The index of the first sample of the pulse
+/// * `time` - The time of the first sample of the pulse in seconds
+/// * `duration` - The duration of the pulse in seconds, or None if it is still on at the last sample
+/// * `code` - The code of the pulse, with the bits of the mask only
+/// * `label` - The label of the code
+/// * `line` - The bit the pulse was on under `TriggerMode::Bits`, None under `TriggerMode::Word`
+///
+/// # Examples
+///
+/// ```
+/// let onsets: Vec<f64> = events.iter().filter(|event| event.label == "stimulus").map(|event| event.time).collect();
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TriggerEvent {
+    pub sample: usize,
+    pub time: f64,
+    pub duration: Option<f64>,
+    pub code: u64,
+    pub label: String,
+    pub line: Option<u32>,
+}
+
+/// Decodes a stream of digital trigger words into labeled events
+///
+/// # Arguments
+///
+/// * `words` - The digital word at every sample
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `start_time` - The time of the first sample in seconds
+/// * `options` - The polarity, mode, mask, debouncing, minimum width and labels
+///
+/// # Returns
+///
+/// The events sorted by onset, then by line, or an error if the sampling rate is invalid,
+/// the mask is empty or the debounce interval or minimum width is zero
+///
+/// # Examples
+///
+/// ```
+/// let words = words_from_samples(&dig_in)?;
+/// let events = decode(&words, 30000.0, 0.0, &TriggerOptions { debounce: 10, ..TriggerOptions::default() })?;
+/// ```
+///
+/// # Note
+///
+/// After masking, and inverting under `ActiveLow`, a change of value only counts if the new
+/// value holds for at least `debounce` samples; shorter runs, such as contact bounce or the
+/// intermediate codes seen while the lines of a word settle, are merged into the value
+/// before them. A pulse starts at the first sample of its stable run, so a bouncy edge is
+/// timed at its last bounce. An event needs an onset edge: a code already on at the first
+/// sample is skipped. A pulse still on at the last sample is kept with a duration of None,
+/// whatever its width. Under `Word`, a change from one nonzero code to another ends the
+/// first event and starts the second.
+///
+pub fn decode(words: &[u64], sampling_rate: f64, start_time: f64, options: &TriggerOptions) -> Result<Vec<TriggerEvent>, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    if options.mask == 0 {
+        return Err(ProcessingError::InvalidParameter("The trigger mask has no bit set".to_string()));
+    }
+    if options.debounce == 0 || options.min_width == 0 {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "The debounce interval and minimum width must be at least one sample, got {} and {}",
+            options.debounce, options.min_width
+        )));
+    }
+    let active = |word: u64| match options.polarity {
+        TriggerPolarity::ActiveHigh => word & options.mask,
+        TriggerPolarity::ActiveLow => !word & options.mask,
+    };
+    let codes: Vec<u64> = words.iter().map(|&word| active(word)).collect();
+
+    let mut pulses: Vec<(usize, Option<usize>, u64, Option<u32>)> = Vec::new();
+    match options.mode {
+        TriggerMode::Word => pulses.extend(pulses_of(&codes, options.debounce).into_iter().map(|(start, end, code)| (start, end, code, None))),
+        TriggerMode::Bits => {
+            for line in (0..u64::BITS).filter(|line| options.mask >> line & 1 == 1) {
+                let bits: Vec<u64> = codes.iter().map(|code| code >> line & 1).collect();
+                pulses.extend(pulses_of(&bits, options.debounce).into_iter().map(|(start, end, _)| (start, end, 1 << line, Some(line))));
+            }
+        }
+    }
+    pulses.retain(|&(start, end, _, _)| end.is_none_or(|end| end - start >= options.min_width));
+    pulses.sort_by_key(|&(start, _, _, line)| (start, line));
+
+    Ok(pulses
+        .into_iter()
+        .map(|(start, end, code, line)| TriggerEvent {
+            sample: start,
+            time: start_time + start as f64 / sampling_rate,
+            duration: end.map(|end| (end - start) as f64 / sampling_rate),
+            code,
+            label: options.labels.get(&code).cloned().unwrap_or_else(|| code.to_string()),
+            line,
+        })
+        .collect())
+}
+
+/// Returns the start, end (None if on at the last sample) and code of every debounced nonzero run
+fn pulses_of(codes: &[u64], debounce: usize) -> Vec<(usize, Option<usize>, u64)> {
+    let mut runs: Vec<(usize, u64)> = Vec::new();
+    let mut index = 0;
+    while index < codes.len() {
+        let code = codes[index];
+        let length = codes[index..].iter().take_while(|&&other| other == code).count();
+        match runs.last() {
+            Some(&(_, stable)) if stable == code => {}
+            Some(_) if length < debounce => {}
+            _ => runs.push((index, code)),
+        }
+        index += length;
+    }
+    // The first run has no onset edge, whatever its code
+    (1..runs.len())
+        .filter(|&run| runs[run].1 != 0)
+        .map(|run| (runs[run].0, runs.get(run + 1).map(|next| next.0), runs[run].1))
+        .collect()
+}
+
+/// Converts the samples of a digital channel to trigger words
+///
+/// # Arguments
+///
+/// * `samples` - The samples, e.g. a `dig_in` column read as floats
+///
+/// # Returns
+///
+/// The words, or an error naming the first sample that is not a non-negative integer
+///
+/// # Examples
+///
+/// ```
+/// let words = words_from_samples(&channels[dig_in])?;
+/// ```
+///
+pub fn words_from_samples(samples: &[f64]) -> Result<Vec<u64>, ProcessingError> {
+    samples
+        .iter()
+        .enumerate()
+        .map(|(index, &sample)| {
+            if sample >= 0.0 && sample.fract() == 0.0 && sample < u64::MAX as f64 {
+                Ok(sample as u64)
+            } else {
+                Err(ProcessingError::InvalidParameter(format!("Sample {} is {}, which is not a digital word", index, sample)))
+            }
+        })
+        .collect()
+}
+
+/// Reads the labels of trigger codes from a two-column csv file
+///
+/// # Arguments
+///
+/// * `reader` - The reader of a file whose first column holds the codes, in decimal or with a `0x` prefix in hexadecimal, and whose second column holds the labels
+///
+/// # Returns
+///
+/// The label of each code, or an error if a record cannot be read, has fewer than two
+/// fields or an invalid code, or a code appears twice
+///
+/// # Examples
+///
+/// ```
+/// let labels = read_trigger_labels(&mut CsvReader::open("codes.csv")?)?;
+/// ```
+///
+pub fn read_trigger_labels(reader: &mut CsvReader) -> io::Result<BTreeMap<u64, String>> {
+    let mut labels = BTreeMap::new();
+    while let Some(record) = reader.read_record()? {
+        let line = record.position().map_or(0, |position| position.line());
+        let invalid = |message: String| io::Error::new(io::ErrorKind::InvalidData, format!("Line {}: {}", line, message));
+        let (Some(code), Some(label)) = (record.get(0), record.get(1)) else {
+            return Err(invalid("Expected a code and a label".to_string()));
+        };
+        let code = code.trim();
+        let parsed = match code.strip_prefix("0x").or_else(|| code.strip_prefix("0X")) {
+            Some(hex) => u64::from_str_radix(hex, 16),
+            None => code.parse(),
+        };
+        let code = parsed.map_err(|_| invalid(format!("{} is not a trigger code", code)))?;
+        if labels.insert(code, label.trim().to_string()).is_some() {
+            return Err(invalid(format!("Code {} has two labels", code)));
+        }
+    }
+    Ok(labels)
+}
+
+/// Writes trigger events as `time,duration,code,label,line` rows
+///
+/// # Arguments
+///
+/// * `events` - The events to write
+/// * `csv_io` - The CsvIO object to write to
+///
+/// # Returns
+///
+/// Nothing, or an error if a row cannot be written
+///
+/// # Examples
+///
+/// ```
+/// to_csv(&events, &mut csv_io)?;
+/// csv_io.save()?;
+/// ```
+///
+/// # Note
+///
+/// A header row is written first. The rows are not flushed to disk until `save` is called.
+/// The duration of a pulse on at the last sample and the line under `TriggerMode::Word` are
+/// written as empty fields.
+///
+pub fn to_csv(events: &[TriggerEvent], csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+    let float_format = csv_io.float_format();
+    csv_io.write_record(StringRecord::from(vec!["time", "duration", "code", "label", "line"]))?;
+    for event in events {
+        csv_io.write_record(StringRecord::from(vec![
+            float_format.format(event.time),
+            event.duration.map_or(String::new(), |duration| float_format.format(duration)),
+            event.code.to_string(),
+            event.label.clone(),
+            event.line.map_or(String::new(), |line| line.to_string()),
+        ]))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLING_RATE: f64 = 1000.0;
+    const START: f64 = 10.0;
+
+    /// A 100-sample word stream:
+    ///
+    /// * line 0 rises with bounces at 5 to 8 and settles high at 9
+    /// * line 2 rises at 20 while line 0 is still high
+    /// * line 0 falls at 30, bounces back for one sample and settles low at 32
+    /// * line 2 falls at 40
+    /// * line 3 glitches for one sample at 50
+    /// * line 1 gives a 4-sample pulse at 60
+    /// * lines 0 and 1 rise together at 90 and are still high at the end
+    fn stream() -> Vec<u64> {
+        let mut words = vec![0; 100];
+        words[5] = 1;
+        words[7] = 1;
+        words[9..20].iter_mut().for_each(|word| *word = 1);
+        words[20..30].iter_mut().for_each(|word| *word = 5);
+        words[30] = 4;
+        words[31] = 5;
+        words[32..40].iter_mut().for_each(|word| *word = 4);
+        words[50] = 8;
+        words[60..64].iter_mut().for_each(|word| *word = 2);
+        words[90..].iter_mut().for_each(|word| *word = 3);
+        words
+    }
+
+    fn options(mode: TriggerMode) -> TriggerOptions {
+        TriggerOptions { mode, debounce: 3, min_width: 5, ..TriggerOptions::default() }
+    }
+
+    fn event(sample: usize, duration: Option<usize>, code: u64, label: &str, line: Option<u32>) -> TriggerEvent {
+        TriggerEvent {
+            sample,
+            time: START + sample as f64 / SAMPLING_RATE,
+            duration: duration.map(|samples| samples as f64 / SAMPLING_RATE),
+            code,
+            label: label.to_string(),
+            line,
+        }
+    }
+
+    #[test]
+    fn whole_words_decode_into_the_expected_events() {
+        let events = decode(&stream(), SAMPLING_RATE, START, &options(TriggerMode::Word)).unwrap();
+        // Bounces time the edges at 9 and 32, the glitch is merged and the 4-sample pulse is too short
+        let expected = vec![event(9, Some(11), 1, "1", None), event(20, Some(12), 5, "5", None), event(32, Some(8), 4, "4", None), event(90, None, 3, "3", None)];
+        assert_eq!(events, expected);
+
+        // Without debouncing every bounce and the glitch is a change
+        let raw = decode(&stream(), SAMPLING_RATE, START, &TriggerOptions::default()).unwrap();
+        let onsets: Vec<(usize, u64)> = raw.iter().map(|event| (event.sample, event.code)).collect();
+        assert_eq!(onsets, vec![(5, 1), (7, 1), (9, 1), (20, 5), (30, 4), (31, 5), (32, 4), (50, 8), (60, 2), (90, 3)]);
+    }
+
+    #[test]
+    fn individual_lines_decode_into_overlapping_pulses() {
+        let events = decode(&stream(), SAMPLING_RATE, START, &options(TriggerMode::Bits)).unwrap();
+        let expected = vec![event(9, Some(23), 1, "1", Some(0)), event(20, Some(20), 4, "4", Some(2)), event(90, None, 1, "1", Some(0)), event(90, None, 2, "2", Some(1))];
+        assert_eq!(events, expected);
+
+        // The mask leaves out line 2, and a minimum width of 1 keeps the short pulse on line 1
+        let masked = TriggerOptions { mask: 0b11, min_width: 1, ..options(TriggerMode::Bits) };
+        let events = decode(&stream(), SAMPLING_RATE, START, &masked).unwrap();
+        assert_eq!(events.iter().map(|event| (event.sample, event.line)).collect::<Vec<_>>(), vec![(9, Some(0)), (60, Some(1)), (90, Some(0)), (90, Some(1))]);
+    }
+
+    #[test]
+    fn active_low_lines_and_codes_on_at_the_first_sample() {
+        // Inverted lines, with the unused upper bits of a 16-bit port floating high
+        let inverted: Vec<u64> = stream().iter().map(|word| !word & 0xFFFF | 0xF00).collect();
+        let active_low = TriggerOptions { polarity: TriggerPolarity::ActiveLow, mask: 0xFF, ..options(TriggerMode::Word) };
+        let high = decode(&stream(), SAMPLING_RATE, START, &options(TriggerMode::Word)).unwrap();
+        assert_eq!(decode(&inverted, SAMPLING_RATE, START, &active_low).unwrap(), high);
+
+        // A code already on at the first sample has no onset edge and is skipped
+        let words = [6, 6, 6, 0, 0, 0, 6, 6, 6, 6, 6, 0, 0];
+        let events = decode(&words, SAMPLING_RATE, 0.0, &TriggerOptions { debounce: 2, ..TriggerOptions::default() }).unwrap();
+        assert_eq!(events.iter().map(|event| (event.sample, event.duration)).collect::<Vec<_>>(), vec![(6, Some(0.005))]);
+        // A pulse running to the end is kept whatever the minimum width
+        let events = decode(&[0, 0, 1], SAMPLING_RATE, 0.0, &TriggerOptions { min_width: 10, ..TriggerOptions::default() }).unwrap();
+        assert_eq!(events.iter().map(|event| (event.sample, event.duration)).collect::<Vec<_>>(), vec![(2, None)]);
+
+        assert!(decode(&words, 0.0, 0.0, &TriggerOptions::default()).is_err());
+        assert!(decode(&words, SAMPLING_RATE, 0.0, &TriggerOptions { mask: 0, ..TriggerOptions::default() }).is_err());
+        assert!(decode(&words, SAMPLING_RATE, 0.0, &TriggerOptions { debounce: 0, ..TriggerOptions::default() }).is_err());
+        assert!(decode(&words, SAMPLING_RATE, 0.0, &TriggerOptions { min_width: 0, ..TriggerOptions::default() }).is_err());
+    }
+
+    #[test]
+    fn labels_load_from_csv_and_events_export_to_csv() {
+        let path = std::env::temp_dir().join(format!("neurorust-triggers-{}-codes.csv", std::process::id()));
+        std::fs::write(&path, "code,label\n1, stimulus\n0x4,reward\n5,both\n").unwrap();
+        let labels = read_trigger_labels(&mut CsvReader::open(&path).unwrap()).unwrap();
+        assert_eq!(labels, BTreeMap::from([(1, "stimulus".to_string()), (4, "reward".to_string()), (5, "both".to_string())]));
+        let events = decode(&stream(), SAMPLING_RATE, START, &TriggerOptions { labels, ..options(TriggerMode::Word) }).unwrap();
+        assert_eq!(events.iter().map(|event| event.label.as_str()).collect::<Vec<_>>(), vec!["stimulus", "both", "reward", "3"]);
+
+        for (text, message) in [("code,label\n1,a\n1,b\n", "Line 3: Code 1 has two labels"), ("code,label\nten,a\n", "Line 2: ten is not a trigger code"), ("code\n1\n", "Line 2: Expected a code and a label")] {
+            std::fs::write(&path, text).unwrap();
+            let error = read_trigger_labels(&mut CsvReader::open(&path).unwrap()).unwrap_err();
+            assert_eq!(error.to_string(), message);
+        }
+
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        to_csv(&events[2..], &mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written.lines().collect::<Vec<_>>(), vec!["time,duration,code,label,line", "10.032,0.008,4,reward,", "10.09,,3,3,"]);
+
+        assert_eq!(words_from_samples(&[0.0, 3.0, 65535.0]).unwrap(), vec![0, 3, 65535]);
+        assert!(words_from_samples(&[0.0, 2.5]).is_err());
+        assert!(words_from_samples(&[-1.0]).is_err());
+        assert!(words_from_samples(&[f64::NAN]).is_err());
+    }
+}
@@ -0,0 +1,443 @@
+// A module to compute time-frequency representations with complex Morlet wavelets
+
+// Written by Amin Alam in 2024
+
+use std::f64::consts::PI;
+use csv::StringRecord;
+use num_complex::Complex64;
+use rustfft::FftPlanner;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::{validate_frequency, validate_sampling_rate};
+
+/// The number of Gaussian standard deviations kept on each side of a wavelet
+const WAVELET_SUPPORT_SIGMAS: f64 = 5.0;
+
+/// The number of cycles of each wavelet, which trades time resolution for frequency resolution
+///
+/// # Arguments
+///
+/// * `Fixed` - The same number of cycles at every frequency
+/// * `Proportional` - A number of cycles equal to the frequency times the given factor, e.g. `0.5` for `f / 2` as is common in MNE
+/// * `PerFrequency` - One number of cycles per frequency
+///
+/// # Examples
+///
+/// ```
+/// let cycles = Cycles::Proportional(0.5);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Cycles {
+    Fixed(f64),
+    Proportional(f64),
+    PerFrequency(Vec<f64>),
+}
+
+/// Limits the part of the signal for which `cwt` keeps coefficients
+///
+/// # Arguments
+///
+/// * `time_range` - The first and one-past-last sample to keep, or `None` for the whole signal
+/// * `decimation` - Keeps every `decimation`-th sample of the time range
+///
+/// # Examples
+///
+/// ```
+/// // Keep every 10th sample of the first second of a 1 kHz recording
+/// let options = CwtOptions { time_range: Some((0, 1000)), decimation: 10 };
+/// ```
+///
+/// # Note
+///
+/// The convolution always runs over the whole signal, so a time slice has the same values
+/// as the corresponding part of the full transform
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CwtOptions {
+    pub time_range: Option<(usize, usize)>,
+    pub decimation: usize,
+}
+
+impl Default for CwtOptions {
+    fn default() -> Self {
+        Self { time_range: None, decimation: 1 }
+    }
+}
+
+/// A wavelet time-frequency representation
+///
+/// # Arguments
+///
+/// * `times` - The time of each column in seconds, relative to the first sample of the signal
+/// * `frequencies` - The center frequency of each wavelet in Hz
+/// * `power` - The squared magnitude of the coefficients, indexed as `power[frequency][time]`
+/// * `phase` - The phase of the coefficients in radians, indexed as `phase[frequency][time]`
+///
+/// # Examples
+///
+/// ```
+/// let tf = cwt(&samples, 1000.0, &frequencies, &Cycles::Fixed(7.0), &CwtOptions::default())?;
+/// let relative = tf.normalize_to_baseline((0.0, 0.5))?;
+/// ```
+///
+/// # Note
+///
+/// The wavelets are normalized so that a sine of amplitude `A` at the center frequency of a
+/// wavelet has a power of `A²` at every frequency, independently of the number of cycles
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimeFrequency {
+    pub times: Vec<f64>,
+    pub frequencies: Vec<f64>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub power: Vec<Vec<f64>>,
+    #[cfg_attr(feature = "serde", serde(with = "crate::data_io::cache::matrix"))]
+    pub phase: Vec<Vec<f64>>,
+}
+
+/// Implementation of the TimeFrequency struct
+///
+/// # Methods
+///
+/// * `normalize_to_baseline` - Divides every frequency by its mean power in a baseline window
+/// * `to_csv_long` - Writes the representation as `time,frequency,power,phase` rows
+impl TimeFrequency {
+    /// Divides every frequency by its mean power in a baseline window
+    ///
+    /// # Arguments
+    ///
+    /// * `baseline` - The start and end of the baseline window in seconds
+    ///
+    /// # Returns
+    ///
+    /// A TimeFrequency holding the power relative to the baseline (1.0 means unchanged) and
+    /// the unchanged phase, or an error if no column falls within the baseline
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let change = tf.normalize_to_baseline((0.0, 0.2))?;
+    /// ```
+    ///
+    pub fn normalize_to_baseline(&self, baseline: (f64, f64)) -> Result<TimeFrequency, ProcessingError> {
+        let columns: Vec<usize> = (0..self.times.len())
+            .filter(|&t| self.times[t] >= baseline.0 && self.times[t] <= baseline.1)
+            .collect();
+        if columns.is_empty() {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "No time-frequency column falls within the baseline {:?}",
+                baseline
+            )));
+        }
+        let power = self
+            .power
+            .iter()
+            .map(|row| {
+                let mean = columns.iter().map(|&t| row[t]).sum::<f64>() / columns.len() as f64;
+                row.iter().map(|power| power / mean).collect()
+            })
+            .collect();
+        Ok(TimeFrequency {
+            times: self.times.clone(),
+            frequencies: self.frequencies.clone(),
+            power,
+            phase: self.phase.clone(),
+        })
+    }
+
+    /// Writes the representation as `time,frequency,power,phase` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// tf.to_csv_long(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first, then one row per time and frequency, ordered by time
+    ///
+    pub fn to_csv_long(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["time", "frequency", "power", "phase"]))?;
+        for (t, time) in self.times.iter().enumerate() {
+            for (f, frequency) in self.frequencies.iter().enumerate() {
+                csv_io.write_record(StringRecord::from(vec![
+                    float_format.format(*time),
+                    float_format.format(*frequency),
+                    float_format.format(self.power[f][t]),
+                    float_format.format(self.phase[f][t]),
+                ]))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Computes the continuous wavelet transform of a signal with complex Morlet wavelets
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal
+/// * `sampling_rate` - The sampling rate in Hz
+/// * `frequencies` - The center frequency of each wavelet in Hz
+/// * `cycles` - The number of cycles of each wavelet
+/// * `options` - The time range and decimation of the output
+///
+/// # Returns
+///
+/// The TimeFrequency, or an error if a frequency is not between 0 and the Nyquist
+/// frequency, a number of cycles is not positive, or the options do not fit the signal
+///
+/// # Examples
+///
+/// ```
+/// let frequencies: Vec<f64> = (0..60).map(|k| 60.0 + 2.0 * k as f64).collect();
+/// let high_gamma = cwt(&samples, 1000.0, &frequencies, &Cycles::Proportional(0.1), &CwtOptions::default())?;
+/// ```
+///
+/// # Note
+///
+/// Each wavelet is `exp(2πift) exp(-t² / 2σ²)` with `σ = cycles / (2πf)`, truncated at 5σ,
+/// and is convolved with the signal through the FFT. The signal is zero-padded, so
+/// coefficients within about 3σ of either end are attenuated. Memory grows with the number
+/// of frequencies times the number of kept samples; use `options` to limit the latter.
+///
+pub fn cwt(
+    samples: &[f64],
+    sampling_rate: f64,
+    frequencies: &[f64],
+    cycles: &Cycles,
+    options: &CwtOptions,
+) -> Result<TimeFrequency, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    let n_cycles = cycles_per_frequency(frequencies, cycles)?;
+    for &frequency in frequencies {
+        validate_frequency(frequency, sampling_rate)?;
+    }
+    let (start, end) = options.time_range.unwrap_or((0, samples.len()));
+    if start >= end || end > samples.len() || options.decimation == 0 {
+        return Err(ProcessingError::InvalidParameter(format!(
+            "Time range {:?} with decimation {} does not fit a signal of {} samples",
+            options.time_range,
+            options.decimation,
+            samples.len()
+        )));
+    }
+
+    let wavelets: Vec<Vec<Complex64>> = frequencies
+        .iter()
+        .zip(&n_cycles)
+        .map(|(&frequency, &n)| morlet(frequency, n, sampling_rate))
+        .collect();
+    let longest = wavelets.iter().map(|wavelet| wavelet.len()).max().unwrap_or(1);
+    let fft_len = (samples.len() + longest - 1).next_power_of_two();
+    let mut planner = FftPlanner::<f64>::new();
+    let forward = planner.plan_fft_forward(fft_len);
+    let inverse = planner.plan_fft_inverse(fft_len);
+
+    let mut signal_spectrum: Vec<Complex64> = samples.iter().map(|&sample| Complex64::new(sample, 0.0)).collect();
+    signal_spectrum.resize(fft_len, Complex64::new(0.0, 0.0));
+    forward.process(&mut signal_spectrum);
+
+    let kept: Vec<usize> = (start..end).step_by(options.decimation).collect();
+    let mut power = Vec::with_capacity(frequencies.len());
+    let mut phase = Vec::with_capacity(frequencies.len());
+    let mut buffer = vec![Complex64::new(0.0, 0.0); fft_len];
+    for wavelet in &wavelets {
+        buffer.iter_mut().for_each(|value| *value = Complex64::new(0.0, 0.0));
+        buffer[..wavelet.len()].copy_from_slice(wavelet);
+        forward.process(&mut buffer);
+        for (value, signal) in buffer.iter_mut().zip(&signal_spectrum) {
+            *value *= signal / fft_len as f64;
+        }
+        inverse.process(&mut buffer);
+        // The wavelets have odd length, so the full convolution is centered half a wavelet in
+        let offset = wavelet.len() / 2;
+        power.push(kept.iter().map(|&k| buffer[k + offset].norm_sqr()).collect());
+        phase.push(kept.iter().map(|&k| buffer[k + offset].arg()).collect());
+    }
+    let times = kept.iter().map(|&k| k as f64 / sampling_rate).collect();
+    Ok(TimeFrequency { times, frequencies: frequencies.to_vec(), power, phase })
+}
+
+/// Resolves the number of cycles of the wavelet at each frequency
+fn cycles_per_frequency(frequencies: &[f64], cycles: &Cycles) -> Result<Vec<f64>, ProcessingError> {
+    let n_cycles: Vec<f64> = match cycles {
+        Cycles::Fixed(n) => vec![*n; frequencies.len()],
+        Cycles::Proportional(factor) => frequencies.iter().map(|frequency| frequency * factor).collect(),
+        Cycles::PerFrequency(n) if n.len() == frequencies.len() => n.clone(),
+        Cycles::PerFrequency(n) => {
+            return Err(ProcessingError::InvalidParameter(format!(
+                "Got {} frequencies but {} numbers of cycles",
+                frequencies.len(),
+                n.len()
+            )));
+        }
+    };
+    match n_cycles.iter().find(|&&n| !(n > 0.0 && n.is_finite())) {
+        Some(n) => Err(ProcessingError::InvalidParameter(format!("Number of cycles must be positive, got {}", n))),
+        None => Ok(n_cycles),
+    }
+}
+
+/// Samples a complex Morlet wavelet, scaled so that a sine of amplitude `A` at its frequency gives coefficients of magnitude `A`
+fn morlet(frequency: f64, n_cycles: f64, sampling_rate: f64) -> Vec<Complex64> {
+    let sigma = n_cycles / (2.0 * PI * frequency);
+    let half = (WAVELET_SUPPORT_SIGMAS * sigma * sampling_rate).ceil() as usize;
+    let gaussian: Vec<f64> = (0..=2 * half)
+        .map(|k| {
+            let t = (k as f64 - half as f64) / sampling_rate;
+            (-0.5 * (t / sigma).powi(2)).exp()
+        })
+        .collect();
+    // A real sine is half positive and half negative frequency, hence the factor of 2
+    let scale = 2.0 / gaussian.iter().sum::<f64>();
+    gaussian
+        .iter()
+        .enumerate()
+        .map(|(k, &weight)| {
+            let t = (k as f64 - half as f64) / sampling_rate;
+            Complex64::from_polar(scale * weight, 2.0 * PI * frequency * t)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    const SAMPLING_RATE: f64 = 1000.0;
+
+    /// Returns the frequency of the highest power at a column
+    fn peak_frequency(tf: &TimeFrequency, column: usize) -> f64 {
+        let best = (0..tf.frequencies.len()).max_by(|&a, &b| tf.power[a][column].total_cmp(&tf.power[b][column])).unwrap();
+        tf.frequencies[best]
+    }
+
+    #[test]
+    fn a_sine_has_its_squared_amplitude_as_power_and_its_phase_at_every_cycle_count() {
+        let samples: Vec<f64> = (0..4000).map(|k| 2.0 * (2.0 * PI * 40.0 * k as f64 / SAMPLING_RATE).cos()).collect();
+        for cycles in [3.0, 7.0, 15.0] {
+            let tf = cwt(&samples, SAMPLING_RATE, &[40.0], &Cycles::Fixed(cycles), &CwtOptions::default()).unwrap();
+            for column in (1000..3000).step_by(97) {
+                assert!((tf.power[0][column] / 4.0 - 1.0).abs() < 1e-3, "{} cycles: {}", cycles, tf.power[0][column]);
+                let expected = (2.0 * PI * 40.0 * tf.times[column] + PI).rem_euclid(2.0 * PI) - PI;
+                let error = (tf.phase[0][column] - expected + PI).rem_euclid(2.0 * PI) - PI;
+                assert!(error.abs() < 1e-3, "{} cycles at {}: {}", cycles, column, error);
+            }
+            // Zero padding attenuates the first and last coefficients
+            assert!(tf.power[0][0] < 3.0);
+        }
+    }
+
+    #[test]
+    fn the_peak_follows_a_chirp_and_localizes_a_burst() {
+        // A linear chirp from 20 to 120 Hz over 4 s
+        let chirp: Vec<f64> = (0..4000)
+            .map(|k| {
+                let t = k as f64 / SAMPLING_RATE;
+                (2.0 * PI * (20.0 * t + 12.5 * t * t)).sin()
+            })
+            .collect();
+        let frequencies: Vec<f64> = (0..71).map(|k| 10.0 + 2.0 * k as f64).collect();
+        let tf = cwt(&chirp, SAMPLING_RATE, &frequencies, &Cycles::Fixed(7.0), &CwtOptions::default()).unwrap();
+        assert_eq!((tf.power.len(), tf.power[0].len()), (71, 4000));
+        for column in (500..3500).step_by(250) {
+            let instantaneous = 20.0 + 25.0 * tf.times[column];
+            let found = peak_frequency(&tf, column);
+            assert!((found - instantaneous).abs() <= 3.0, "{} s: {} vs {}", tf.times[column], found, instantaneous);
+        }
+
+        // A 100 Hz burst from 1.0 to 1.2 s over a 10 Hz rhythm and weak noise
+        let mut rng = SeededRng::new(1);
+        let burst: Vec<f64> = (0..3000)
+            .map(|k| {
+                let t = k as f64 / SAMPLING_RATE;
+                let gamma = if (1.0..1.2).contains(&t) { (2.0 * PI * 100.0 * t).sin() } else { 0.0 };
+                gamma + (2.0 * PI * 10.0 * t).sin() + 0.1 * rng.next_gaussian()
+            })
+            .collect();
+        let frequencies: Vec<f64> = (0..50).map(|k| 52.0 + 2.0 * k as f64).collect();
+        let tf = cwt(&burst, SAMPLING_RATE, &frequencies, &Cycles::Proportional(0.1), &CwtOptions::default()).unwrap();
+        assert_eq!(peak_frequency(&tf, 1100), 100.0);
+        let row = &tf.power[frequencies.iter().position(|&f| f == 100.0).unwrap()];
+        // The amplitude is above half of its plateau from the start to the end of the burst
+        let above: Vec<f64> = (0..3000).filter(|&k| row[k] > 0.25).map(|k| tf.times[k]).collect();
+        assert!((above[0] - 1.0).abs() < 0.005 && (above[above.len() - 1] - 1.2).abs() < 0.005, "{:?}", (above[0], above[above.len() - 1]));
+        assert!((row[1100] - 1.0).abs() < 0.1, "{}", row[1100]);
+        assert!(row[500] < 0.01 && row[2000] < 0.01, "{} {}", row[500], row[2000]);
+
+        let relative = tf.normalize_to_baseline((0.3, 0.8)).unwrap();
+        let baseline: Vec<usize> = (300..=800).collect();
+        for power in &relative.power {
+            assert!((baseline.iter().map(|&k| power[k]).sum::<f64>() / baseline.len() as f64 - 1.0).abs() < 1e-9);
+        }
+        assert!(relative.power[24][1100] > 100.0);
+        assert_eq!(relative.phase, tf.phase);
+        assert!(tf.normalize_to_baseline((5.0, 6.0)).is_err());
+    }
+
+    #[test]
+    fn cycles_per_frequency_time_slices_and_decimation_match_the_full_transform() {
+        let mut rng = SeededRng::new(2);
+        let samples: Vec<f64> = (0..2000).map(|_| rng.next_gaussian()).collect();
+        let frequencies = [8.0, 20.0, 40.0];
+        let proportional = cwt(&samples, SAMPLING_RATE, &frequencies, &Cycles::Proportional(0.25), &CwtOptions::default()).unwrap();
+        let per_frequency = cwt(&samples, SAMPLING_RATE, &frequencies, &Cycles::PerFrequency(vec![2.0, 5.0, 10.0]), &CwtOptions::default()).unwrap();
+        assert_eq!(proportional, per_frequency);
+        let fixed = cwt(&samples, SAMPLING_RATE, &[20.0], &Cycles::Fixed(5.0), &CwtOptions::default()).unwrap();
+        assert_eq!(fixed.power[0], proportional.power[1]);
+
+        let options = CwtOptions { time_range: Some((500, 1500)), decimation: 10 };
+        let sliced = cwt(&samples, SAMPLING_RATE, &frequencies, &Cycles::Proportional(0.25), &options).unwrap();
+        assert_eq!(sliced.times.len(), 100);
+        assert!((sliced.times[1] - 0.51).abs() < 1e-12);
+        for (f, row) in sliced.power.iter().enumerate() {
+            for (k, power) in row.iter().enumerate() {
+                assert_eq!(*power, proportional.power[f][500 + 10 * k]);
+                assert_eq!(sliced.phase[f][k], proportional.phase[f][500 + 10 * k]);
+            }
+        }
+
+        assert!(cwt(&samples, SAMPLING_RATE, &[600.0], &Cycles::Fixed(7.0), &CwtOptions::default()).is_err());
+        assert!(cwt(&samples, SAMPLING_RATE, &[0.0], &Cycles::Fixed(7.0), &CwtOptions::default()).is_err());
+        assert!(cwt(&samples, SAMPLING_RATE, &[20.0], &Cycles::Fixed(0.0), &CwtOptions::default()).is_err());
+        assert!(cwt(&samples, SAMPLING_RATE, &frequencies, &Cycles::PerFrequency(vec![3.0]), &CwtOptions::default()).is_err());
+        assert!(cwt(&samples, SAMPLING_RATE, &[20.0], &Cycles::Fixed(7.0), &CwtOptions { time_range: Some((100, 100)), decimation: 1 }).is_err());
+        assert!(cwt(&samples, SAMPLING_RATE, &[20.0], &Cycles::Fixed(7.0), &CwtOptions { time_range: Some((0, 2001)), decimation: 1 }).is_err());
+        assert!(cwt(&samples, SAMPLING_RATE, &[20.0], &Cycles::Fixed(7.0), &CwtOptions { time_range: None, decimation: 0 }).is_err());
+    }
+
+    #[test]
+    fn the_long_table_has_one_row_per_time_and_frequency() {
+        let samples: Vec<f64> = (0..200).map(|k| (2.0 * PI * 50.0 * k as f64 / SAMPLING_RATE).sin()).collect();
+        let options = CwtOptions { time_range: Some((50, 150)), decimation: 25 };
+        let tf = cwt(&samples, SAMPLING_RATE, &[40.0, 50.0], &Cycles::Fixed(3.0), &options).unwrap();
+        let path = std::env::temp_dir().join(format!("neurorust-wavelet-{}-tf.csv", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        tf.to_csv_long(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let lines: Vec<&str> = written.lines().collect();
+        assert_eq!(lines[0], "time,frequency,power,phase");
+        assert_eq!(lines.len(), 1 + 4 * 2);
+        let row: Vec<f64> = lines[4].split(',').map(|value| value.parse().unwrap()).collect();
+        assert!((row[0] - 0.075).abs() < 1e-9 && row[1] == 50.0);
+        assert!((row[2] - tf.power[1][1]).abs() < 1e-6);
+    }
+}
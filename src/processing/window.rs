@@ -0,0 +1,128 @@
+// A module to generate window functions for filter design and spectral analysis
+
+// Written by Amin Alam in 2024
+
+use std::f64::consts::PI;
+
+/// A tapering window function
+///
+/// # Arguments
+///
+/// * `Rectangular` - No tapering
+/// * `Hann` - The raised cosine window
+/// * `Hamming` - The raised cosine window with non-zero end points
+/// * `Blackman` - The three-term cosine window with low side lobes
+///
+/// # Examples
+///
+/// ```
+/// let taper = Window::Hann.symmetric(64);
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Window {
+    Rectangular,
+    Hann,
+    Hamming,
+    Blackman,
+}
+
+/// Implementation of the Window enum
+///
+/// # Methods
+///
+/// * `symmetric` - Generates a symmetric window, as used for filter design
+/// * `periodic` - Generates a periodic window, as used for spectral analysis
+/// * `value_at` - Evaluates the continuous window function
+impl Window {
+    /// Generates a symmetric window
+    ///
+    /// # Arguments
+    ///
+    /// * `len` - The number of samples of the window
+    ///
+    /// # Returns
+    ///
+    /// The window coefficients, with the peak in the middle and equal end points
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let taps = Window::Hamming.symmetric(101);
+    /// ```
+    ///
+    pub fn symmetric(&self, len: usize) -> Vec<f64> {
+        if len <= 1 {
+            return vec![1.0; len];
+        }
+        self.generate(len, (len - 1) as f64)
+    }
+
+    /// Generates a periodic window
+    ///
+    /// # Arguments
+    ///
+    /// * `len` - The number of samples of the window
+    ///
+    /// # Returns
+    ///
+    /// The first `len` samples of a symmetric window of length `len + 1`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let taper = Window::Hann.periodic(256);
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// This matches the default `fftbins=True` windows of scipy.signal.get_window
+    ///
+    pub fn periodic(&self, len: usize) -> Vec<f64> {
+        if len <= 1 {
+            return vec![1.0; len];
+        }
+        self.generate(len, len as f64)
+    }
+
+    /// Evaluates the continuous window function
+    ///
+    /// # Arguments
+    ///
+    /// * `position` - The position across the window, from 0.0 (start) through 0.5 (center) to 1.0 (end)
+    ///
+    /// # Returns
+    ///
+    /// The window value, or 0.0 outside of `[0, 1]`
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// assert_eq!(Window::Hann.value_at(0.5), 1.0);
+    /// ```
+    ///
+    pub fn value_at(&self, position: f64) -> f64 {
+        if !(0.0..=1.0).contains(&position) {
+            return 0.0;
+        }
+        let coefficients: &[f64] = match self {
+            Window::Rectangular => &[1.0],
+            Window::Hann => &[0.5, 0.5],
+            Window::Hamming => &[0.54, 0.46],
+            Window::Blackman => &[0.42, 0.5, 0.08],
+        };
+        let phase = 2.0 * PI * position;
+        coefficients
+            .iter()
+            .enumerate()
+            .map(|(k, a)| {
+                let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                sign * a * (k as f64 * phase).cos()
+            })
+            .sum()
+    }
+
+    fn generate(&self, len: usize, denominator: f64) -> Vec<f64> {
+        (0..len).map(|n| self.value_at(n as f64 / denominator)).collect()
+    }
+}
@@ -0,0 +1,363 @@
+// A module to cross-correlate continuous signals and estimate the lag between them
+
+// Written by Amin Alam in 2024
+
+use csv::StringRecord;
+use crate::data_io::csv::CsvIO;
+use crate::data_io::error::DataIoError;
+use crate::processing::convolution::{convolve, ConvMode};
+use crate::processing::error::ProcessingError;
+use crate::processing::filter::validate_sampling_rate;
+
+/// The cross-correlation of two signals as a function of lag
+///
+/// # Arguments
+///
+/// * `lags` - The lag of each value in seconds, positive when the second signal lags behind the first
+/// * `values` - The correlation at each lag
+/// * `sampling_rate` - The sampling rate of the signals in Hz
+///
+/// # Examples
+///
+/// ```
+/// let correlation = cross_correlate(&stimulus, &photodiode, 1000.0, 0.1, true)?;
+/// let (delay, peak) = correlation.best_lag(true);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CorrelationResult {
+    pub lags: Vec<f64>,
+    pub values: Vec<f64>,
+    pub sampling_rate: f64,
+}
+
+/// Implementation of the CorrelationResult struct
+///
+/// # Methods
+///
+/// * `best_lag` - Finds the lag of the highest correlation
+/// * `to_csv` - Writes the correlation as `lag,correlation` rows
+impl CorrelationResult {
+    /// Finds the lag of the highest correlation
+    ///
+    /// # Arguments
+    ///
+    /// * `interpolate` - Refines the lag to a fraction of a sample by fitting a parabola through the peak and its two neighbours if true
+    ///
+    /// # Returns
+    ///
+    /// The lag in seconds and the correlation at that lag, or `(NaN, NaN)` if there is no finite value
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// let (lag, peak) = correlation.best_lag(true);
+    /// let lag_samples = lag * correlation.sampling_rate;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// The peak is the largest value, not the largest magnitude, so an inverted copy of a
+    /// signal is not matched. Peaks at the ends of the lag range are not interpolated.
+    ///
+    pub fn best_lag(&self, interpolate: bool) -> (f64, f64) {
+        let best = self
+            .values
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| value.is_finite())
+            .fold(None, |best: Option<(usize, f64)>, (i, &value)| match best {
+                Some((_, top)) if top >= value => best,
+                _ => Some((i, value)),
+            });
+        let (i, peak) = match best {
+            Some(best) => best,
+            None => return (f64::NAN, f64::NAN),
+        };
+        if !interpolate || i == 0 || i + 1 == self.values.len() {
+            return (self.lags[i], peak);
+        }
+        let (left, right) = (self.values[i - 1], self.values[i + 1]);
+        let curvature = left - 2.0 * peak + right;
+        if !curvature.is_finite() || curvature >= 0.0 {
+            return (self.lags[i], peak);
+        }
+        let offset = 0.5 * (left - right) / curvature;
+        (self.lags[i] + offset / self.sampling_rate, peak - 0.25 * (left - right) * offset)
+    }
+
+    /// Writes the correlation as `lag,correlation` rows
+    ///
+    /// # Arguments
+    ///
+    /// * `csv_io` - The CsvIO object to write to
+    ///
+    /// # Returns
+    ///
+    /// Nothing, or an error if a row cannot be written
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// correlation.to_csv(&mut csv_io)?;
+    /// csv_io.save()?;
+    /// ```
+    ///
+    /// # Note
+    ///
+    /// A header row is written first. The rows are not flushed to disk until `save` is called.
+    ///
+    pub fn to_csv(&self, csv_io: &mut CsvIO) -> Result<(), DataIoError> {
+        let float_format = csv_io.float_format();
+        csv_io.write_record(StringRecord::from(vec!["lag", "correlation"]))?;
+        for (lag, value) in self.lags.iter().zip(&self.values) {
+            csv_io.write_record(StringRecord::from(vec![float_format.format(*lag), float_format.format(*value)]))?;
+        }
+        Ok(())
+    }
+}
+
+/// Cross-correlates two signals sampled at the same rate
+///
+/// # Arguments
+///
+/// * `a` - The samples of the reference signal
+/// * `b` - The samples of the signal compared with the reference
+/// * `sampling_rate` - The sampling rate of both signals in Hz
+/// * `max_lag` - The largest lag computed in seconds, rounded to a whole number of samples
+/// * `normalize` - Removes the mean of each signal and scales the correlation to [-1, 1] if true
+///
+/// # Returns
+///
+/// The CorrelationResult with the value `sum(a[n] * b[n + k])` at each lag `k`, or an error if
+/// either signal is empty, the sampling rate is not positive or the maximum lag is negative
+///
+/// # Examples
+///
+/// ```
+/// // The photodiode trace lags behind the stimulus channel by the display latency
+/// let correlation = cross_correlate(&stimulus, &photodiode, 1000.0, 0.1, true)?;
+/// let (latency, _) = correlation.best_lag(true);
+/// ```
+///
+/// # Note
+///
+/// Signals of unequal length are zero-padded, so each lag sums over the samples where
+/// both signals exist. The normalized correlation divides by the norms of the whole
+/// mean-removed signals, which is the biased estimate: it shrinks towards zero at large
+/// lags and is NaN if either signal is constant. Lags beyond the signal lengths are left
+/// out. The FFT is used when the signals are long.
+///
+pub fn cross_correlate(a: &[f64], b: &[f64], sampling_rate: f64, max_lag: f64, normalize: bool) -> Result<CorrelationResult, ProcessingError> {
+    validate_sampling_rate(sampling_rate)?;
+    if max_lag.is_nan() || max_lag < 0.0 {
+        return Err(ProcessingError::InvalidParameter(format!("Maximum lag must be non-negative, got {}", max_lag)));
+    }
+    if a.is_empty() || b.is_empty() {
+        return Err(ProcessingError::SignalTooShort { length: a.len().min(b.len()), required: 1 });
+    }
+    let centered = |samples: &[f64]| -> Vec<f64> {
+        let mean = samples.iter().sum::<f64>() / samples.len() as f64;
+        samples.iter().map(|x| x - mean).collect()
+    };
+    let (a, b) = if normalize { (centered(a), centered(b)) } else { (a.to_vec(), b.to_vec()) };
+
+    // c[k] = sum(a[n] b[n + k]) is the convolution of b with the reversed a, offset by len(a) - 1
+    let reversed: Vec<f64> = a.iter().rev().copied().collect();
+    let full = convolve(&b, &reversed, ConvMode::Full);
+    let offset = a.len() as i64 - 1;
+    let max_lag = (max_lag * sampling_rate).round() as i64;
+    let first = (-max_lag).max(-offset);
+    let last = max_lag.min(b.len() as i64 - 1);
+    let scale = if normalize {
+        1.0 / (a.iter().map(|x| x * x).sum::<f64>() * b.iter().map(|x| x * x).sum::<f64>()).sqrt()
+    } else {
+        1.0
+    };
+
+    let lags = (first..=last).map(|k| k as f64 / sampling_rate).collect();
+    let values = (first..=last)
+        .map(|k| {
+            let value = full[(k + offset) as usize] * scale;
+            if scale.is_finite() { value } else { f64::NAN }
+        })
+        .collect();
+    Ok(CorrelationResult { lags, values, sampling_rate })
+}
+
+/// Shifts a signal so that it lines up with a reference signal
+///
+/// # Arguments
+///
+/// * `samples` - The samples of the signal to shift
+/// * `reference` - The samples of the reference signal, at the same sampling rate
+/// * `sampling_rate` - The sampling rate of both signals in Hz
+/// * `max_lag` - The largest shift considered in seconds
+///
+/// # Returns
+///
+/// The shifted samples, with as many samples as the input and NaN where the shift leaves
+/// no sample, and the lag removed in seconds, or an error if `cross_correlate` fails
+///
+/// # Examples
+///
+/// ```
+/// let (aligned, latency) = align(&photodiode, &stimulus, 1000.0, 0.1)?;
+/// ```
+///
+/// # Note
+///
+/// The lag is estimated from the normalized cross-correlation and rounded to a whole number
+/// of samples, so `aligned[n]` is `samples[n + lag]`
+///
+pub fn align(samples: &[f64], reference: &[f64], sampling_rate: f64, max_lag: f64) -> Result<(Vec<f64>, f64), ProcessingError> {
+    let correlation = cross_correlate(reference, samples, sampling_rate, max_lag, true)?;
+    let (lag, _) = correlation.best_lag(false);
+    if lag.is_nan() {
+        return Ok((samples.to_vec(), 0.0));
+    }
+    let shift = (lag * sampling_rate).round() as i64;
+    let aligned = (0..samples.len() as i64)
+        .map(|n| usize::try_from(n + shift).ok().and_then(|index| samples.get(index)).copied().unwrap_or(f64::NAN))
+        .collect();
+    Ok((aligned, shift as f64 / sampling_rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::processing::random::SeededRng;
+
+    const SAMPLING_RATE: f64 = 1000.0;
+
+    fn noise(n: usize, seed: u64) -> Vec<f64> {
+        let mut rng = SeededRng::new(seed);
+        (0..n).map(|_| rng.next_gaussian()).collect()
+    }
+
+    fn delayed(samples: &[f64], delay: usize) -> Vec<f64> {
+        let mut shifted = vec![0.0; delay];
+        shifted.extend_from_slice(&samples[..samples.len() - delay]);
+        shifted
+    }
+
+    /// A sum of sines below 40 Hz with random phases, evaluated `delay` samples late
+    fn band_limited(n: usize, delay: f64, seed: u64) -> Vec<f64> {
+        let mut rng = SeededRng::new(seed);
+        let components: Vec<(f64, f64)> = (0..12).map(|_| (2.0 + 38.0 * rng.next_f64(), 2.0 * std::f64::consts::PI * rng.next_f64())).collect();
+        (0..n)
+            .map(|k| {
+                let t = (k as f64 - delay) / SAMPLING_RATE;
+                components.iter().map(|(frequency, phase)| (2.0 * std::f64::consts::PI * frequency * t + phase).sin()).sum()
+            })
+            .collect()
+    }
+
+    /// The definition `sum(a[n] * b[n + k])` over the samples where both signals exist
+    fn reference_correlation(a: &[f64], b: &[f64], lag: i64) -> f64 {
+        (0..a.len() as i64).filter_map(|n| usize::try_from(n + lag).ok().and_then(|m| b.get(m)).map(|y| a[n as usize] * y)).sum()
+    }
+
+    #[test]
+    fn a_copy_delayed_by_37_samples_has_a_lag_of_37() {
+        let a = noise(5000, 1);
+        let b = delayed(&a, 37);
+        let correlation = cross_correlate(&a, &b, SAMPLING_RATE, 0.1, true).unwrap();
+        assert_eq!(correlation.lags.len(), 201);
+        let (lag, peak) = correlation.best_lag(false);
+        assert!((lag * SAMPLING_RATE - 37.0).abs() < 1e-9);
+        assert!(peak > 0.99 && peak <= 1.0, "{}", peak);
+        // The reverse direction is a lead of 37 samples, and an inverted copy is not matched
+        assert!((cross_correlate(&b, &a, SAMPLING_RATE, 0.1, true).unwrap().best_lag(false).0 * SAMPLING_RATE + 37.0).abs() < 1e-9);
+        let inverted: Vec<f64> = b.iter().map(|x| -x).collect();
+        let (_, inverted_peak) = cross_correlate(&a, &inverted, SAMPLING_RATE, 0.1, true).unwrap().best_lag(false);
+        assert!(inverted_peak < 0.1);
+
+        // A band-limited signal delayed by a whole or a fractional number of samples
+        for delay in [37.0, 37.3, 36.75] {
+            let reference = band_limited(4000, 0.0, 2);
+            let late = band_limited(4000, delay, 2);
+            let (lag, _) = cross_correlate(&reference, &late, SAMPLING_RATE, 0.1, true).unwrap().best_lag(true);
+            assert!((lag * SAMPLING_RATE - delay).abs() < 0.02, "{}: {}", delay, lag * SAMPLING_RATE);
+        }
+    }
+
+    #[test]
+    fn the_correlation_matches_its_definition_for_unequal_lengths_and_long_signals() {
+        let a = noise(50, 3);
+        let b = noise(80, 4);
+        let raw = cross_correlate(&a, &b, 10.0, 100.0, false).unwrap();
+        // Lags reach from -(len(a) - 1) to len(b) - 1
+        assert_eq!((raw.lags[0], raw.lags[raw.lags.len() - 1]), (-4.9, 7.9));
+        for (lag, value) in raw.lags.iter().zip(&raw.values) {
+            let k = (lag * 10.0).round() as i64;
+            assert!((value - reference_correlation(&a, &b, k)).abs() < 1e-9, "lag {}", k);
+        }
+        let limited = cross_correlate(&a, &b, 10.0, 0.3, false).unwrap();
+        assert_eq!(limited.lags.len(), 7);
+        assert!((limited.values[3] - reference_correlation(&a, &b, 0)).abs() < 1e-9);
+
+        // The normalized correlation removes the means and divides by the norms of the whole signals
+        let (offset_a, offset_b): (Vec<f64>, Vec<f64>) = (a.iter().map(|x| x + 5.0).collect(), b.iter().map(|x| 2.0 * x - 3.0).collect());
+        let normalized = cross_correlate(&offset_a, &offset_b, 10.0, 100.0, true).unwrap();
+        let center = |x: &[f64]| -> Vec<f64> { x.iter().map(|v| v - x.iter().sum::<f64>() / x.len() as f64).collect() };
+        let (ca, cb) = (center(&a), center(&b));
+        let norm = (ca.iter().map(|x| x * x).sum::<f64>() * cb.iter().map(|x| x * x).sum::<f64>()).sqrt();
+        for (lag, value) in normalized.lags.iter().zip(&normalized.values) {
+            let k = (lag * 10.0).round() as i64;
+            assert!((value - reference_correlation(&ca, &cb, k) / norm).abs() < 1e-12);
+        }
+
+        // Long signals go through the FFT
+        let long_a = noise(20_000, 5);
+        let long_b = noise(30_000, 6);
+        let long = cross_correlate(&long_a, &long_b, SAMPLING_RATE, 0.01, false).unwrap();
+        for (lag, value) in long.lags.iter().zip(&long.values).step_by(5) {
+            let k = (lag * SAMPLING_RATE).round() as i64;
+            assert!((value - reference_correlation(&long_a, &long_b, k)).abs() < 1e-8, "lag {}", k);
+        }
+    }
+
+    #[test]
+    fn align_applies_the_estimated_shift() {
+        let stimulus = noise(2000, 7);
+        let photodiode = delayed(&stimulus, 37);
+        let (aligned, lag) = align(&photodiode, &stimulus, SAMPLING_RATE, 0.1).unwrap();
+        assert!((lag - 0.037).abs() < 1e-12);
+        assert_eq!(aligned.len(), 2000);
+        assert_eq!(&aligned[..1963], &stimulus[..1963]);
+        assert!(aligned[1963..].iter().all(|sample| sample.is_nan()));
+
+        // A constant signal has no correlation and is returned unchanged
+        let (unchanged, lag) = align(&[1.0; 100], &stimulus[..100], SAMPLING_RATE, 0.01).unwrap();
+        assert_eq!((unchanged, lag), (vec![1.0; 100], 0.0));
+        let flat = cross_correlate(&[1.0; 100], &stimulus[..100], SAMPLING_RATE, 0.01, true).unwrap();
+        assert!(flat.values.iter().all(|value| value.is_nan()));
+        let (lag, peak) = flat.best_lag(true);
+        assert!(lag.is_nan() && peak.is_nan());
+
+        assert!(cross_correlate(&[], &stimulus, SAMPLING_RATE, 0.1, true).is_err());
+        assert!(cross_correlate(&stimulus, &stimulus, SAMPLING_RATE, -0.1, true).is_err());
+        assert!(cross_correlate(&stimulus, &stimulus, 0.0, 0.1, true).is_err());
+    }
+
+    #[test]
+    fn peaks_at_the_lag_range_ends_are_not_interpolated_and_export_to_csv() {
+        let correlation = CorrelationResult { lags: vec![-0.1, 0.0, 0.1], values: vec![0.2, 0.5, 0.9], sampling_rate: 10.0 };
+        assert_eq!(correlation.best_lag(true), (0.1, 0.9));
+        // A parabola through (-1, 0.5), (0, 1) and (1, 0.75) peaks at +1/6 sample
+        let peaked = CorrelationResult { lags: vec![-0.1, 0.0, 0.1], values: vec![0.5, 1.0, 0.75], sampling_rate: 10.0 };
+        let (lag, peak) = peaked.best_lag(true);
+        assert!((lag - 1.0 / 60.0).abs() < 1e-12);
+        assert!((peak - (1.0 + 0.25 * 0.25 / 6.0)).abs() < 1e-12);
+
+        let path = std::env::temp_dir().join(format!("neurorust-xcorr-{}-xcorr.csv", std::process::id()));
+        std::fs::write(&path, "").unwrap();
+        let mut csv_io = CsvIO::open_write(path.to_str().unwrap()).unwrap();
+        peaked.to_csv(&mut csv_io).unwrap();
+        csv_io.save().unwrap();
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written.lines().collect::<Vec<_>>(), vec!["lag,correlation", "-0.1,0.5", "0,1", "0.1,0.75"]);
+    }
+}
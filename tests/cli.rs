@@ -0,0 +1,147 @@
+// Tests of the neurorust command-line tool, run as a separate process
+
+// Written by Amin Alam in 2024
+
+#![cfg(feature = "cli")]
+
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// A small recording with a time column, one missing value and a quoted label
+const CSV: &str = "time,Fz,Cz,label\n0,1.5,-2,rest\n0.5,2.5,,\"go, now\"\n1,3.5,4,rest\n1.5,4.5,6,stop\n";
+
+/// Returns a path in the temporary directory that no other test uses
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("neurorust-cli-{}-{}", std::process::id(), name))
+}
+
+/// Runs the tool with some arguments, writing `stdin` to its standard input
+fn run(arguments: &[&str], stdin: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_neurorust"))
+        .args(arguments)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // The tool may exit on an argument error before reading its input, which closes the pipe
+    let _ = child.stdin.take().unwrap().write_all(stdin);
+    child.wait_with_output().unwrap()
+}
+
+/// Runs the tool and returns its standard output, failing the test if it did not succeed
+fn run_ok(arguments: &[&str], stdin: &[u8]) -> String {
+    let output = run(arguments, stdin);
+    assert!(output.status.success(), "{:?} failed: {}", arguments, String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn info_reports_the_columns_rows_and_sampling_rate() {
+    let out = run_ok(&["info", "-"], CSV.as_bytes());
+    assert_eq!(out, "format: csv\ncolumns: 4\n  time\n  Fz\n  Cz\n  label\nrows: 4\nstart: 0\nduration: 1.5\nsampling_rate: 2\n");
+}
+
+#[test]
+fn head_and_select_stream_the_requested_rows_and_columns() {
+    assert_eq!(run_ok(&["head", "-n", "1", "-"], CSV.as_bytes()), "time,Fz,Cz,label\n0,1.5,-2,rest\n");
+    let out = run_ok(&["select", "-", "--columns", "label,Fz", "--rows", "1..3"], CSV.as_bytes());
+    assert_eq!(out, "label,Fz\n\"go, now\",2.5\nrest,3.5\n");
+    assert_eq!(run_ok(&["select", "-", "--rows", "3.."], CSV.as_bytes()), "time,Fz,Cz,label\n1.5,4.5,6,stop\n");
+}
+
+#[test]
+fn stats_skip_missing_and_non_numeric_fields() {
+    let out = run_ok(&["stats", "-"], CSV.as_bytes());
+    let lines: Vec<&str> = out.lines().collect();
+    assert_eq!(lines[0], "column,count,missing,mean,std,min,max");
+    assert_eq!(lines[2], format!("Fz,4,0,3,{},1.5,4.5", (5.0f64 / 3.0).sqrt()));
+    assert!(lines[3].starts_with("Cz,3,1,2.66666666666666"), "{}", lines[3]);
+    assert!(lines[3].ends_with(",-2,6"), "{}", lines[3]);
+    assert_eq!(lines[4], "label,0,4,NaN,NaN,NaN,NaN");
+}
+
+#[test]
+fn conversions_round_trip_through_every_format() {
+    let csv = temp_path("round-trip.csv");
+    fs::write(&csv, CSV).unwrap();
+    for extension in ["tsv", "jsonl"] {
+        let converted = temp_path(&format!("round-trip.{}", extension));
+        let back = temp_path(&format!("round-trip-{}.csv", extension));
+        run_ok(&["convert", csv.to_str().unwrap(), converted.to_str().unwrap()], b"");
+        run_ok(&["convert", converted.to_str().unwrap(), back.to_str().unwrap()], b"");
+        assert_eq!(fs::read_to_string(&back).unwrap(), CSV, "through {}", extension);
+        fs::remove_file(converted).unwrap();
+        fs::remove_file(back).unwrap();
+    }
+    let jsonl = run_ok(&["convert", csv.to_str().unwrap(), "--to", "jsonl"], b"");
+    assert_eq!(jsonl.lines().nth(1).unwrap(), r#"{"time":0.5,"Fz":2.5,"Cz":"","label":"go, now"}"#);
+    fs::remove_file(csv).unwrap();
+}
+
+#[test]
+fn jsonl_inputs_map_keys_to_the_columns_of_the_first_object() {
+    let jsonl = temp_path("keys.jsonl");
+    fs::write(&jsonl, "{\"t\":0,\"x\":1.25,\"note\":\"a\\nb\"}\n\n{\"x\":2,\"t\":1,\"note\":null}\n{\"t\":2}\n").unwrap();
+    let out = run_ok(&["convert", jsonl.to_str().unwrap()], b"");
+    assert_eq!(out, "t,x,note\n0,1.25,\"a\nb\"\n1,2,\n2,,\n");
+
+    fs::write(&jsonl, "{\"t\":0}\n{\"t\":1,\"y\":2}\n").unwrap();
+    let output = run(&["stats", jsonl.to_str().unwrap()], b"");
+    assert_eq!(output.status.code(), Some(65));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Line 2"));
+
+    fs::write(&jsonl, "{\"t\":0}\n{\"t\":[1]}\n").unwrap();
+    let output = run(&["stats", jsonl.to_str().unwrap()], b"");
+    assert_eq!(output.status.code(), Some(65));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("line 2"));
+    fs::remove_file(jsonl).unwrap();
+}
+
+#[test]
+fn binary_files_hold_interleaved_little_endian_samples() {
+    let bin = temp_path("samples.bin");
+    run_ok(&["convert", "-", bin.to_str().unwrap()], b"a,b\n1,-0.5\n,3e10\n");
+    let bytes = fs::read(&bin).unwrap();
+    let values: Vec<f64> = bytes.chunks_exact(8).map(|value| f64::from_le_bytes(value.try_into().unwrap())).collect();
+    assert_eq!(values.len(), 4);
+    assert_eq!((values[0], values[1], values[3]), (1.0, -0.5, 3e10));
+    assert!(values[2].is_nan());
+
+    let out = run_ok(&["convert", bin.to_str().unwrap(), "--channels", "2"], b"");
+    assert_eq!(out, "field_1,field_2\n1,-0.5\nNaN,30000000000\n");
+    assert_eq!(run_ok(&["info", "--channels", "4", bin.to_str().unwrap()], b"").lines().last(), Some("rows: 1"));
+
+    let output = run(&["info", bin.to_str().unwrap()], b"");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--channels"));
+    let output = run(&["info", "--channels", "3", bin.to_str().unwrap()], b"");
+    assert_eq!(output.status.code(), Some(65));
+    fs::remove_file(bin).unwrap();
+
+    let output = run(&["convert", "-", "--to", "bin"], CSV.as_bytes());
+    assert_eq!(output.status.code(), Some(65));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("`rest`"));
+}
+
+#[test]
+fn user_errors_exit_with_2_and_data_errors_with_65() {
+    let missing = temp_path("missing.csv");
+    for arguments in [
+        vec!["info", missing.to_str().unwrap()],
+        vec!["info", "recording.parquet"],
+        vec!["info", "recording"],
+        vec!["select", "-", "--columns", "Oz"],
+        vec!["select", "-", "--rows", "5..2"],
+        vec!["convert", "-", "--to", "xlsx"],
+        vec!["info"],
+    ] {
+        let output = run(&arguments, CSV.as_bytes());
+        assert_eq!(output.status.code(), Some(2), "{:?}", arguments);
+        assert!(output.stdout.is_empty(), "{:?}", arguments);
+    }
+    let output = run(&["stats", "-"], b"a,b\n1,2\n3\n");
+    assert_eq!(output.status.code(), Some(65));
+}